@@ -39,7 +39,29 @@ pub trait Database {
     /// Count those keys that start with the given prefix.
     fn count_prefix(&self, prefix: &str) -> Result<usize, Error>;
 
+    /// Visit the key/value pairs for those keys that start with the given
+    /// prefix, in key order, without holding them all in memory. The prefix
+    /// is stripped from the keys before being passed to the visitor, which
+    /// returns `false` to stop the scan early.
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        visitor: &mut dyn FnMut(&str, &[u8]) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Fetch the key/value pairs for those keys that start with the given
     /// prefix. The prefix is stripped from the keys before being returned.
-    fn fetch_prefix(&self, prefix: &str) -> Result<HashMap<String, Box<[u8]>>, Error>;
+    ///
+    /// Use `scan_prefix()` for large sets of records, such as chunks and packs.
+    fn fetch_prefix(&self, prefix: &str) -> Result<HashMap<String, Box<[u8]>>, Error> {
+        let mut results: HashMap<String, Box<[u8]>> = HashMap::new();
+        self.scan_prefix(prefix, &mut |key, value| {
+            results.insert(key.to_owned(), Box::from(value));
+            Ok(true)
+        })?;
+        Ok(results)
+    }
+
+    /// Reclaim the space occupied by deleted and overwritten records.
+    fn compact(&self) -> Result<(), Error>;
 }
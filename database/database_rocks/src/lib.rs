@@ -178,13 +178,17 @@ impl database_core::Database for Database {
     //         Ok(results)
     //     }
 
-    /// Fetch the key/value pairs for those keys that start with the given
-    /// prefix. The prefix is stripped from the keys before being returned.
-    fn fetch_prefix(&self, prefix: &str) -> Result<HashMap<String, Box<[u8]>>, Error> {
+    /// Visit the key/value pairs for those keys that start with the given
+    /// prefix, in key order. The prefix is stripped from the keys before being
+    /// passed to the visitor, which returns `false` to stop the scan early.
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        visitor: &mut dyn FnMut(&str, &[u8]) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
         let pre_bytes = prefix.as_bytes();
         // this only gets us started, we then have to check for the end of the range
         let iter = self.db.prefix_iterator(pre_bytes);
-        let mut results: HashMap<String, Box<[u8]>> = HashMap::new();
         for item in iter {
             let (key, value) = item?;
             let pre = &key[..pre_bytes.len()];
@@ -192,8 +196,16 @@ impl database_core::Database for Database {
                 break;
             }
             let key_str = std::str::from_utf8(&key[pre_bytes.len()..])?;
-            results.insert(key_str.to_owned(), value);
+            if !visitor(key_str, &value)? {
+                break;
+            }
         }
-        Ok(results)
+        Ok(())
+    }
+
+    /// Reclaim the space occupied by deleted and overwritten records.
+    fn compact(&self) -> Result<(), Error> {
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        Ok(())
     }
 }
@@ -180,6 +180,7 @@ class DataSetModel extends DataSet {
     required super.status,
     required Option<BackupStateModel> super.backupState,
     required super.errorMsg,
+    super.etag,
   });
 
   factory DataSetModel.from(DataSet dataset) {
@@ -200,6 +201,7 @@ class DataSetModel extends DataSet {
       status: dataset.status,
       backupState: state,
       errorMsg: dataset.errorMsg,
+      etag: dataset.etag,
     );
   }
 
@@ -237,6 +239,7 @@ class DataSetModel extends DataSet {
       status: decodeStatus(json['status']),
       backupState: backupState,
       errorMsg: Option.from(json['errorMessage']),
+      etag: Option.from(json['etag']),
     );
   }
 
@@ -254,6 +257,9 @@ class DataSetModel extends DataSet {
       'stores': stores,
       'excludes': excludes,
     };
+    if (etag is Some) {
+      result['etag'] = etag.unwrap();
+    }
     if (!input) {
       result['computerId'] = computerId;
       result['status'] = encodeStatus(status);
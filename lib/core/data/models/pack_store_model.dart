@@ -1,6 +1,7 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
+import 'package:oxidized/oxidized.dart';
 import 'package:zorigami/core/domain/entities/pack_store.dart';
 
 class PackStoreModel extends PackStore {
@@ -9,6 +10,7 @@ class PackStoreModel extends PackStore {
     required super.label,
     required super.kind,
     required super.options,
+    super.etag,
   });
 
   factory PackStoreModel.fromStore(PackStore store) {
@@ -17,6 +19,7 @@ class PackStoreModel extends PackStore {
       label: store.label,
       kind: store.kind,
       options: store.options,
+      etag: store.etag,
     );
   }
 
@@ -28,18 +31,23 @@ class PackStoreModel extends PackStore {
       kind: kind,
       label: json['label'],
       options: options,
+      etag: Option.from(json['etag']),
     );
   }
 
   Map<String, dynamic> toJson() {
     final kind = encodeKind(this.kind);
     final options = encodeOptions(this.options);
-    return {
+    final Map<String, dynamic> result = {
       'id': key,
       'storeType': kind,
       'label': label,
       'properties': options,
     };
+    if (etag is Some) {
+      result['etag'] = etag.unwrap();
+    }
+    return result;
   }
 }
 
@@ -48,12 +56,16 @@ StoreKind decodeKind(String kind) {
     return StoreKind.amazon;
   } else if (kind == 'azure') {
     return StoreKind.azure;
+  } else if (kind == 'dropbox') {
+    return StoreKind.dropbox;
   } else if (kind == 'google') {
     return StoreKind.google;
   } else if (kind == 'local') {
     return StoreKind.local;
   } else if (kind == 'minio') {
     return StoreKind.minio;
+  } else if (kind == 'rclone') {
+    return StoreKind.rclone;
   } else if (kind == 'sftp') {
     return StoreKind.sftp;
   } else {
@@ -67,12 +79,16 @@ String encodeKind(StoreKind kind) {
       return 'amazon';
     case StoreKind.azure:
       return 'azure';
+    case StoreKind.dropbox:
+      return 'dropbox';
     case StoreKind.google:
       return 'google';
     case StoreKind.local:
       return 'local';
     case StoreKind.minio:
       return 'minio';
+    case StoreKind.rclone:
+      return 'rclone';
     case StoreKind.sftp:
       return 'sftp';
     default:
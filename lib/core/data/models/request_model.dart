@@ -13,6 +13,7 @@ class RequestModel extends Request {
     required super.finished,
     required super.filesRestored,
     required super.errorMessage,
+    required super.mergedInto,
   });
 
   factory RequestModel.from(Request request) {
@@ -24,6 +25,7 @@ class RequestModel extends Request {
       finished: request.finished,
       filesRestored: request.filesRestored,
       errorMessage: request.errorMessage,
+      mergedInto: request.mergedInto,
     );
   }
 
@@ -40,6 +42,7 @@ class RequestModel extends Request {
       // limiting file count to 2^53 (in JavaScript) is acceptable
       filesRestored: json['filesRestored'],
       errorMessage: Option.from(json['errorMessage']),
+      mergedInto: Option.from(json['mergedInto']),
     );
   }
 
@@ -52,6 +55,7 @@ class RequestModel extends Request {
       'finished': finished.mapOr((v) => v.toIso8601String(), null),
       'filesRestored': filesRestored,
       'errorMessage': errorMessage.toNullable(),
+      'mergedInto': mergedInto.toNullable(),
     };
   }
 }
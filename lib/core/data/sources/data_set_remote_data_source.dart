@@ -17,6 +17,7 @@ abstract class DataSetRemoteDataSource {
 
 const String dataSetFields = '''
   id
+  etag
   computerId
   basepath
   schedules {
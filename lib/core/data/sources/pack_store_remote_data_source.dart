@@ -27,6 +27,7 @@ class PackStoreRemoteDataSourceImpl extends PackStoreRemoteDataSource {
           id
           storeType
           label
+          etag
           properties {
             name
             value
@@ -56,7 +57,9 @@ class PackStoreRemoteDataSourceImpl extends PackStoreRemoteDataSource {
   Future<String> testPackStore(PackStore input) async {
     const testStore = r'''
       mutation TestStore($input: StoreInput!) {
-        testStore(input: $input)
+        testStore(input: $input) {
+          status
+        }
       }
     ''';
     final storeModel = PackStoreModel.fromStore(input);
@@ -71,7 +74,7 @@ class PackStoreRemoteDataSourceImpl extends PackStoreRemoteDataSource {
     if (result.hasException) {
       throw err.ServerException(result.exception.toString());
     }
-    return (result.data?['testStore'] ?? 'ng') as String;
+    return (result.data?['testStore']?['status'] ?? 'ng') as String;
   }
 
   @override
@@ -103,6 +106,7 @@ class PackStoreRemoteDataSourceImpl extends PackStoreRemoteDataSource {
           id
           storeType
           label
+          etag
           properties {
             name
             value
@@ -137,6 +141,7 @@ class PackStoreRemoteDataSourceImpl extends PackStoreRemoteDataSource {
           id
           storeType
           label
+          etag
           properties {
             name
             value
@@ -116,6 +116,7 @@ class SnapshotRemoteDataSourceImpl extends SnapshotRemoteDataSource {
           finished
           filesRestored
           errorMessage
+          mergedInto
         }
       }
     ''';
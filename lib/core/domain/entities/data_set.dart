@@ -211,6 +211,9 @@ class DataSet extends Equatable {
   final Option<BackupState> backupState;
   final Option<String> errorMsg;
 
+  /// Entity tag from the server, required when updating the data set.
+  final Option<String> etag;
+
   const DataSet({
     required this.key,
     required this.computerId,
@@ -223,11 +226,30 @@ class DataSet extends Equatable {
     required this.status,
     required this.backupState,
     required this.errorMsg,
+    this.etag = const None(),
   });
 
   @override
   List<Object> get props => [key, computerId, basepath];
 
+  /// Return a copy of this data set with the given entity tag.
+  DataSet withEtag(Option<String> etag) {
+    return DataSet(
+      key: key,
+      computerId: computerId,
+      basepath: basepath,
+      schedules: schedules,
+      packSize: packSize,
+      stores: stores,
+      excludes: excludes,
+      snapshot: snapshot,
+      status: status,
+      backupState: backupState,
+      errorMsg: errorMsg,
+      etag: etag,
+    );
+  }
+
   @override
   bool get stringify => true;
 
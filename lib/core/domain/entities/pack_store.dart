@@ -2,8 +2,9 @@
 // Copyright (c) 2023 Nathan Fiedler
 //
 import 'package:equatable/equatable.dart';
+import 'package:oxidized/oxidized.dart';
 
-enum StoreKind { amazon, azure, google, local, minio, sftp }
+enum StoreKind { amazon, azure, dropbox, google, local, minio, rclone, sftp }
 
 class PackStore extends Equatable {
   /// The `key` is unique among all pack stores.
@@ -18,13 +19,28 @@ class PackStore extends Equatable {
   /// Map of names and values for configuring this pack store.
   final Map<String, dynamic> options;
 
+  /// Entity tag from the server, required when updating the store.
+  final Option<String> etag;
+
   const PackStore({
     required this.key,
     required this.label,
     required this.kind,
     required this.options,
+    this.etag = const None(),
   });
 
+  /// Return a copy of this store with the given entity tag.
+  PackStore withEtag(Option<String> etag) {
+    return PackStore(
+      key: key,
+      label: label,
+      kind: kind,
+      options: options,
+      etag: etag,
+    );
+  }
+
   @override
   List<Object> get props => [key, label, kind, options];
 
@@ -42,12 +58,16 @@ String packStoreSubtitle(PackStore store) {
       return store.options['region'];
     case StoreKind.azure:
       return store.options['account'];
+    case StoreKind.dropbox:
+      return store.options['basepath'];
     case StoreKind.google:
       return store.options['project'];
     case StoreKind.local:
       return store.options['basepath'];
     case StoreKind.minio:
       return store.options['endpoint'];
+    case StoreKind.rclone:
+      return store.options['remote'];
     case StoreKind.sftp:
       return store.options['remote_addr'];
     default:
@@ -61,12 +81,16 @@ String prettyKind(StoreKind kind) {
       return 'remote amazon';
     case StoreKind.azure:
       return 'remote azure';
+    case StoreKind.dropbox:
+      return 'remote dropbox';
     case StoreKind.google:
       return 'remote google';
     case StoreKind.local:
       return 'local disk';
     case StoreKind.minio:
       return 'remote minio';
+    case StoreKind.rclone:
+      return 'remote rclone';
     case StoreKind.sftp:
       return 'remote SFTP';
     default:
@@ -20,6 +20,8 @@ class Request extends Equatable {
   final int filesRestored;
   // Error message if request processing failed.
   final Option<String> errorMessage;
+  // Destination of the overlapping request that restored this one.
+  final Option<String> mergedInto;
 
   const Request({
     required this.tree,
@@ -29,6 +31,7 @@ class Request extends Equatable {
     required this.finished,
     required this.filesRestored,
     required this.errorMessage,
+    required this.mergedInto,
   });
 
   @override
@@ -16,11 +16,19 @@ class AmazonStoreForm extends PackStoreForm {
   Map<String, dynamic> initialValuesFrom(PackStore store) {
     final region = store.options['region'] ?? '';
     final storage = store.options['storage'] ?? '';
+    final endpoint = store.options['endpoint'] ?? '';
+    final renames = store.options['renames'] ?? '';
+    final sse = store.options['sse'] ?? '';
+    final sseKmsKeyId = store.options['sse_kms_key_id'] ?? '';
     return {
       'key': store.key,
       'label': store.label,
       'region': region,
       'storage': storage,
+      'endpoint': endpoint,
+      'renames': renames,
+      'sse': sse,
+      'sse_kms_key_id': sseKmsKeyId,
       'access_key': store.options['access_key'],
       'secret_key': store.options['secret_key'],
     };
@@ -35,6 +43,10 @@ class AmazonStoreForm extends PackStoreForm {
       options: {
         'region': state.value['region'],
         'storage': state.value['storage'],
+        'endpoint': state.value['endpoint'],
+        'renames': state.value['renames'],
+        'sse': state.value['sse'],
+        'sse_kms_key_id': state.value['sse_kms_key_id'],
         'access_key': state.value['access_key'],
         'secret_key': state.value['secret_key'],
       },
@@ -85,6 +97,54 @@ class AmazonStoreForm extends PackStoreForm {
               )
               .toList(),
         ),
+        FormBuilderTextField(
+          name: 'endpoint',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.dns),
+            labelText: 'Endpoint',
+            hintText: 'Leave blank for Amazon, or https://s3.example.com',
+          ),
+        ),
+        FormBuilderDropdown(
+          name: 'renames',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.drive_file_rename_outline),
+            labelText: 'Bucket Renames',
+            hintText: 'Select where renamed buckets are recorded',
+          ),
+          items: ['dynamodb', 'object', 'none']
+              .map(
+                (backend) => DropdownMenuItem(
+                  value: backend,
+                  child: Text(backend),
+                ),
+              )
+              .toList(),
+        ),
+        FormBuilderDropdown(
+          name: 'sse',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.enhanced_encryption),
+            labelText: 'Server-side Encryption',
+            hintText: 'Select encryption algorithm',
+          ),
+          items: ['AES256', 'aws:kms']
+              .map(
+                (algorithm) => DropdownMenuItem(
+                  value: algorithm,
+                  child: Text(algorithm),
+                ),
+              )
+              .toList(),
+        ),
+        FormBuilderTextField(
+          name: 'sse_kms_key_id',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.key),
+            labelText: 'KMS Key ID',
+            hintText: 'Key ID or ARN, leave blank for the default key',
+          ),
+        ),
         FormBuilderTextField(
           name: 'access_key',
           decoration: const InputDecoration(
@@ -15,11 +15,15 @@ class AzureStoreForm extends PackStoreForm {
   @override
   Map<String, dynamic> initialValuesFrom(PackStore store) {
     final customUri = store.options['custom_uri'] ?? '';
+    final accessKey = store.options['access_key'] ?? '';
+    final sasToken = store.options['sas_token'] ?? '';
     return {
       'key': store.key,
       'label': store.label,
       'account': store.options['account'],
-      'access_key': store.options['access_key'],
+      'access_key': accessKey,
+      'sas_token': sasToken,
+      'managed_identity': store.options['credential'] == 'default',
       'custom_uri': customUri,
       'access_tier': store.options['access_tier'],
     };
@@ -29,9 +33,19 @@ class AzureStoreForm extends PackStoreForm {
   PackStore storeFromState(FormBuilderState state) {
     final Map<String, dynamic> options = {
       'account': state.value['account'],
-      'access_key': state.value['access_key'],
       'access_tier': state.value['access_tier'],
     };
+    final String accessKey = state.value['access_key'];
+    if (accessKey.isNotEmpty) {
+      options['access_key'] = accessKey;
+    }
+    final String sasToken = state.value['sas_token'];
+    if (sasToken.isNotEmpty) {
+      options['sas_token'] = sasToken;
+    }
+    if (state.value['managed_identity'] == true) {
+      options['credential'] = 'default';
+    }
     final String customUri = state.value['custom_uri'];
     if (customUri.isNotEmpty) {
       options['custom_uri'] = customUri;
@@ -78,7 +92,19 @@ class AzureStoreForm extends PackStoreForm {
             icon: Icon(Icons.folder_open),
             labelText: 'Access Key',
           ),
-          validator: FormBuilderValidators.required(),
+        ),
+        FormBuilderTextField(
+          name: 'sas_token',
+          obscureText: true,
+          maxLines: 1,
+          decoration: const InputDecoration(
+            icon: Icon(Icons.key),
+            labelText: 'SAS Token',
+          ),
+        ),
+        FormBuilderCheckbox(
+          name: 'managed_identity',
+          title: const Text('Use managed identity if no key or token'),
         ),
         FormBuilderDropdown(
           name: 'access_tier',
@@ -165,12 +165,13 @@ class DataSetListDetails extends StatelessWidget {
 
   void saveDataSet(BuildContext context, DataSetForm setForm) {
     if (formKey.currentState!.saveAndValidate()) {
-      final dataset = DataSetForm.datasetFromState(
+      final edited = DataSetForm.datasetFromState(
         formKey.currentState!,
         stores,
       );
+      // send the original entity tag so the server can detect conflicts
       BlocProvider.of<edsb.EditDataSetsBloc>(context).add(
-        edsb.UpdateDataSet(dataset: dataset),
+        edsb.UpdateDataSet(dataset: edited.withEtag(dataset.etag)),
       );
     }
   }
@@ -0,0 +1,99 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+import 'package:flutter/material.dart';
+import 'package:form_builder_validators/form_builder_validators.dart';
+import 'package:flutter_form_builder/flutter_form_builder.dart';
+import 'package:zorigami/core/domain/entities/pack_store.dart';
+import 'package:zorigami/features/backup/preso/widgets/pack_store_form.dart';
+
+class DropboxStoreForm extends PackStoreForm {
+  final PackStore store;
+
+  const DropboxStoreForm({super.key, required this.store});
+
+  @override
+  Map<String, dynamic> initialValuesFrom(PackStore store) {
+    return {
+      'key': store.key,
+      'label': store.label,
+      'basepath': store.options['basepath'],
+      'app_key': store.options['app_key'],
+      'app_secret': store.options['app_secret'],
+      'refresh_token': store.options['refresh_token'],
+    };
+  }
+
+  @override
+  PackStore storeFromState(FormBuilderState state) {
+    return PackStore(
+      key: state.value['key'],
+      label: state.value['label'],
+      kind: StoreKind.dropbox,
+      options: {
+        'basepath': state.value['basepath'],
+        'app_key': state.value['app_key'],
+        'app_secret': state.value['app_secret'],
+        'refresh_token': state.value['refresh_token'],
+      },
+    );
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return Column(
+      children: <Widget>[
+        FormBuilderTextField(
+          name: 'key',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.vpn_key),
+            labelText: 'Store Key',
+          ),
+          readOnly: true,
+        ),
+        FormBuilderTextField(
+          name: 'label',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.label),
+            labelText: 'Label',
+          ),
+          validator: FormBuilderValidators.required(),
+        ),
+        FormBuilderTextField(
+          name: 'basepath',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'Base Folder',
+          ),
+        ),
+        FormBuilderTextField(
+          name: 'app_key',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'App Key',
+          ),
+          validator: FormBuilderValidators.required(),
+        ),
+        FormBuilderTextField(
+          name: 'app_secret',
+          obscureText: true,
+          maxLines: 1,
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'App Secret',
+          ),
+        ),
+        FormBuilderTextField(
+          name: 'refresh_token',
+          obscureText: true,
+          maxLines: 1,
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'Refresh Token',
+          ),
+          validator: FormBuilderValidators.required(),
+        ),
+      ],
+    );
+  }
+}
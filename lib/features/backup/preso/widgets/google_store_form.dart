@@ -16,6 +16,8 @@ class GoogleStoreForm extends PackStoreForm {
   Map<String, dynamic> initialValuesFrom(PackStore store) {
     final region = store.options['region'] ?? '';
     final storage = store.options['storage'] ?? '';
+    final kmsKey = store.options['kms_key'] ?? '';
+    final renames = store.options['renames'] ?? '';
     return {
       'key': store.key,
       'label': store.label,
@@ -23,6 +25,8 @@ class GoogleStoreForm extends PackStoreForm {
       'project': store.options['project'],
       'region': region,
       'storage': storage,
+      'kms_key': kmsKey,
+      'renames': renames,
     };
   }
 
@@ -37,6 +41,8 @@ class GoogleStoreForm extends PackStoreForm {
         'project': state.value['project'],
         'region': state.value['region'],
         'storage': state.value['storage'],
+        'kms_key': state.value['kms_key'],
+        'renames': state.value['renames'],
       },
     );
   }
@@ -100,6 +106,30 @@ class GoogleStoreForm extends PackStoreForm {
               )
               .toList(),
         ),
+        FormBuilderTextField(
+          name: 'kms_key',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.enhanced_encryption),
+            labelText: 'KMS Key',
+            hintText: 'projects/P/locations/L/keyRings/R/cryptoKeys/K',
+          ),
+        ),
+        FormBuilderDropdown(
+          name: 'renames',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.drive_file_rename_outline),
+            labelText: 'Bucket Renames',
+            hintText: 'Select where renamed buckets are recorded',
+          ),
+          items: ['firestore', 'object', 'none']
+              .map(
+                (backend) => DropdownMenuItem(
+                  value: backend,
+                  child: Text(backend),
+                ),
+              )
+              .toList(),
+        ),
       ],
     );
   }
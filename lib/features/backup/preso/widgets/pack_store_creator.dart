@@ -17,8 +17,10 @@ final List<NewStoreItem> storeItems = [
   NewStoreItem(title: 'Local', kind: StoreKind.local),
   NewStoreItem(title: 'Amazon', kind: StoreKind.amazon),
   NewStoreItem(title: 'Azure', kind: StoreKind.azure),
+  NewStoreItem(title: 'Dropbox', kind: StoreKind.dropbox),
   NewStoreItem(title: 'Google', kind: StoreKind.google),
   NewStoreItem(title: 'Minio', kind: StoreKind.minio),
+  NewStoreItem(title: 'rclone', kind: StoreKind.rclone),
   NewStoreItem(title: 'SFTP', kind: StoreKind.sftp),
 ];
 
@@ -145,6 +147,18 @@ PackStore defaultPackStore(StoreKind kind) {
           'custom_uri': '',
         },
       );
+    case StoreKind.dropbox:
+      return const PackStore(
+        kind: StoreKind.dropbox,
+        key: 'auto-generated',
+        label: 'dropbox',
+        options: <String, dynamic>{
+          'basepath': '/zorigami',
+          'app_key': 'abcdefghijklmno',
+          'app_secret': 'pqrstuvwxyz1234',
+          'refresh_token': '',
+        },
+      );
     case StoreKind.google:
       return const PackStore(
         kind: StoreKind.google,
@@ -169,6 +183,17 @@ PackStore defaultPackStore(StoreKind kind) {
           'secret_key': 'wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY',
         },
       );
+    case StoreKind.rclone:
+      return const PackStore(
+        kind: StoreKind.rclone,
+        key: 'auto-generated',
+        label: 'rclone',
+        options: <String, dynamic>{
+          'remote': 'b2:',
+          'rclone_path': '',
+          'config_file': '',
+        },
+      );
     case StoreKind.sftp:
       return const PackStore(
         kind: StoreKind.sftp,
@@ -12,10 +12,12 @@ import 'package:zorigami/features/backup/preso/bloc/pack_stores_bloc.dart';
 import 'package:zorigami/features/backup/preso/bloc/providers.dart';
 import 'package:zorigami/features/backup/preso/widgets/amazon_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/azure_store_form.dart';
+import 'package:zorigami/features/backup/preso/widgets/dropbox_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/google_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/local_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/minio_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/pack_store_form.dart';
+import 'package:zorigami/features/backup/preso/widgets/rclone_store_form.dart';
 import 'package:zorigami/features/backup/preso/widgets/sftp_store_form.dart';
 
 class PackStoresList extends ConsumerStatefulWidget {
@@ -110,11 +112,12 @@ class PackStoreListDetails extends StatelessWidget {
 
   void savePack(BuildContext context, PackStoreForm storeForm) {
     if (formKey.currentState!.saveAndValidate()) {
-      final store = storeForm.storeFromState(
+      final edited = storeForm.storeFromState(
         formKey.currentState!,
       );
+      // send the original entity tag so the server can detect conflicts
       BlocProvider.of<epsb.EditPackStoresBloc>(context).add(
-        epsb.UpdatePackStore(store: store),
+        epsb.UpdatePackStore(store: edited.withEtag(store.etag)),
       );
     }
   }
@@ -210,12 +213,18 @@ PackStoreForm? buildStoreForm(PackStore store) {
   if (store.kind == StoreKind.azure) {
     return AzureStoreForm(store: store);
   }
+  if (store.kind == StoreKind.dropbox) {
+    return DropboxStoreForm(store: store);
+  }
   if (store.kind == StoreKind.google) {
     return GoogleStoreForm(store: store);
   }
   if (store.kind == StoreKind.minio) {
     return MinioStoreForm(store: store);
   }
+  if (store.kind == StoreKind.rclone) {
+    return RcloneStoreForm(store: store);
+  }
   if (store.kind == StoreKind.sftp) {
     return SftpStoreForm(store: store);
   }
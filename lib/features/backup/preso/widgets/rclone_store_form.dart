@@ -0,0 +1,85 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+import 'package:flutter/material.dart';
+import 'package:form_builder_validators/form_builder_validators.dart';
+import 'package:flutter_form_builder/flutter_form_builder.dart';
+import 'package:zorigami/core/domain/entities/pack_store.dart';
+import 'package:zorigami/features/backup/preso/widgets/pack_store_form.dart';
+
+class RcloneStoreForm extends PackStoreForm {
+  final PackStore store;
+
+  const RcloneStoreForm({super.key, required this.store});
+
+  @override
+  Map<String, dynamic> initialValuesFrom(PackStore store) {
+    return {
+      'key': store.key,
+      'label': store.label,
+      'remote': store.options['remote'],
+      'rclone_path': store.options['rclone_path'],
+      'config_file': store.options['config_file'],
+    };
+  }
+
+  @override
+  PackStore storeFromState(FormBuilderState state) {
+    return PackStore(
+      key: state.value['key'],
+      label: state.value['label'],
+      kind: StoreKind.rclone,
+      options: {
+        'remote': state.value['remote'],
+        'rclone_path': state.value['rclone_path'],
+        'config_file': state.value['config_file'],
+      },
+    );
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return Column(
+      children: <Widget>[
+        FormBuilderTextField(
+          name: 'key',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.vpn_key),
+            labelText: 'Store Key',
+          ),
+          readOnly: true,
+        ),
+        FormBuilderTextField(
+          name: 'label',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.label),
+            labelText: 'Label',
+          ),
+          validator: FormBuilderValidators.required(),
+        ),
+        FormBuilderTextField(
+          name: 'remote',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.cloud),
+            labelText: 'Remote',
+          ),
+          validator: FormBuilderValidators.required(),
+        ),
+        FormBuilderTextField(
+          name: 'rclone_path',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'Path to rclone (optional)',
+          ),
+        ),
+        FormBuilderTextField(
+          name: 'config_file',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.folder_open),
+            labelText: 'Config File (optional)',
+          ),
+        ),
+      ],
+    );
+  }
+}
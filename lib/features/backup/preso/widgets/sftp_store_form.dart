@@ -16,12 +16,18 @@ class SftpStoreForm extends PackStoreForm {
   Map<String, dynamic> initialValuesFrom(PackStore store) {
     final password = store.options['password'] ?? '';
     final basepath = store.options['basepath'] ?? '';
+    final privateKey = store.options['private_key'] ?? '';
+    final passphrase = store.options['passphrase'] ?? '';
+    final knownHosts = store.options['known_hosts'] ?? '';
     return {
       'key': store.key,
       'label': store.label,
       'remote_addr': store.options['remote_addr'],
       'username': store.options['username'],
       'password': password,
+      'private_key': privateKey,
+      'passphrase': passphrase,
+      'known_hosts': knownHosts,
       'basepath': basepath,
     };
   }
@@ -36,6 +42,18 @@ class SftpStoreForm extends PackStoreForm {
     if (password.isNotEmpty) {
       options['password'] = password;
     }
+    final String privateKey = state.value['private_key'];
+    if (privateKey.isNotEmpty) {
+      options['private_key'] = privateKey;
+    }
+    final String passphrase = state.value['passphrase'];
+    if (passphrase.isNotEmpty) {
+      options['passphrase'] = passphrase;
+    }
+    final String knownHosts = state.value['known_hosts'];
+    if (knownHosts.isNotEmpty) {
+      options['known_hosts'] = knownHosts;
+    }
     final String basepath = state.value['basepath'];
     if (basepath.isNotEmpty) {
       options['basepath'] = basepath;
@@ -93,6 +111,31 @@ class SftpStoreForm extends PackStoreForm {
             labelText: 'Password',
           ),
         ),
+        FormBuilderTextField(
+          name: 'private_key',
+          minLines: 1,
+          maxLines: 4,
+          decoration: const InputDecoration(
+            icon: Icon(Icons.key),
+            labelText: 'Private Key (path or PEM)',
+          ),
+        ),
+        FormBuilderTextField(
+          name: 'passphrase',
+          obscureText: true,
+          maxLines: 1,
+          decoration: const InputDecoration(
+            icon: Icon(Icons.password),
+            labelText: 'Key Passphrase',
+          ),
+        ),
+        FormBuilderTextField(
+          name: 'known_hosts',
+          decoration: const InputDecoration(
+            icon: Icon(Icons.verified_user),
+            labelText: 'Known Hosts File',
+          ),
+        ),
         FormBuilderTextField(
           name: 'basepath',
           decoration: const InputDecoration(
@@ -150,12 +150,15 @@ class RestoreListEntry extends StatelessWidget {
 String requestSubtitle(Request request) {
   return request.errorMessage.mapOrElse(
     (err) => 'Restore error: $err',
-    () => request.finished.mapOrElse(
-      (e) {
-        var fin = DateFormat.yMd().add_jm().format(e.toLocal());
-        return 'finished at $fin';
-      },
-      () => '${request.filesRestored} files restored so far...',
+    () => request.mergedInto.mapOrElse(
+      (path) => 'merged with restore of $path',
+      () => request.finished.mapOrElse(
+        (e) {
+          var fin = DateFormat.yMd().add_jm().format(e.toLocal());
+          return 'finished at $fin';
+        },
+        () => '${request.filesRestored} files restored so far...',
+      ),
     ),
   );
 }
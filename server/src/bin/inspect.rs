@@ -0,0 +1,121 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Command-line tool for examining a downloaded database snapshot without
+//! touching the live database. The archive is extracted and restored to a
+//! temporary location that is removed when the tool exits.
+//!
+//! The passphrase for the archive is taken from the configured secrets
+//! provider, the same as the server.
+
+use anyhow::{anyhow, Error};
+use server::data::repositories::RecordRepositoryImpl;
+use server::domain::helpers::crypto;
+use server::domain::managers::settings;
+use server::domain::repositories::RecordRepository;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str =
+    "Usage: zorigami-inspect <archive> [counts | datasets | stores | snapshots <dataset-id>]";
+
+fn main() {
+    settings::init_logging();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(err) = run(&args) {
+        eprintln!("error: {:#}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Error> {
+    let archive = PathBuf::from(&args[0]);
+    if !archive.is_file() {
+        return Err(anyhow!(format!("no such file: {}", archive.display())));
+    }
+    let workdir = tempfile::tempdir()?;
+    let passphrase = crypto::get_passphrase()?;
+    let repo = RecordRepositoryImpl::open_archive(&archive, passphrase.expose(), workdir.path())?;
+    match args.get(1).map(|s| s.as_str()) {
+        None => {
+            print_counts(&repo)?;
+            println!();
+            print_datasets(&repo)
+        }
+        Some("counts") => print_counts(&repo),
+        Some("datasets") => print_datasets(&repo),
+        Some("stores") => print_stores(&repo),
+        Some("snapshots") => {
+            let dataset_id = args
+                .get(2)
+                .ok_or_else(|| anyhow!(format!("missing dataset identifier\n{}", USAGE)))?;
+            print_snapshots(&repo, dataset_id)
+        }
+        Some(other) => Err(anyhow!(format!("unknown command: {}\n{}", other, USAGE))),
+    }
+}
+
+fn print_counts(repo: &dyn RecordRepository) -> Result<(), Error> {
+    let counts = repo.get_entity_counts()?;
+    println!("chunks:    {}", counts.chunk);
+    println!("datasets:  {}", counts.dataset);
+    println!("files:     {}", counts.file);
+    println!("packs:     {}", counts.pack);
+    println!("snapshots: {}", counts.snapshot);
+    println!("stores:    {}", counts.store);
+    println!("trees:     {}", counts.tree);
+    println!("xattrs:    {}", counts.xattr);
+    Ok(())
+}
+
+fn print_datasets(repo: &dyn RecordRepository) -> Result<(), Error> {
+    for dataset in repo.get_datasets()? {
+        let latest = repo.get_latest_snapshot(&dataset.id)?;
+        println!("{}  {}", dataset.id, dataset.basepath.display());
+        println!("    stores: {}", dataset.stores.join(", "));
+        match latest {
+            Some(digest) => println!("    latest: {}", digest),
+            None => println!("    latest: (none)"),
+        }
+    }
+    Ok(())
+}
+
+fn print_stores(repo: &dyn RecordRepository) -> Result<(), Error> {
+    for store in repo.get_stores()? {
+        let store_type = store.store_type.to_string();
+        println!("{}  {}  {}", store.id, store_type, store.label);
+    }
+    Ok(())
+}
+
+fn print_snapshots(repo: &dyn RecordRepository, dataset_id: &str) -> Result<(), Error> {
+    let mut next = repo.get_latest_snapshot(dataset_id)?;
+    if next.is_none() {
+        println!("no snapshots for dataset {}", dataset_id);
+    }
+    while let Some(digest) = next {
+        let snapshot = repo
+            .get_snapshot(&digest)?
+            .ok_or_else(|| anyhow!(format!("missing snapshot: {}", digest)))?;
+        let end_time = snapshot
+            .end_time
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_else(|| "(incomplete)".into());
+        println!(
+            "{}  {}  {}  files: {}",
+            snapshot.digest,
+            snapshot.start_time.to_rfc3339(),
+            end_time,
+            snapshot.file_counts.total_files()
+        );
+        next = snapshot.parent;
+    }
+    Ok(())
+}
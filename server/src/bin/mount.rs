@@ -0,0 +1,99 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Command-line tool for mounting the snapshots of a dataset as a read-only
+//! FUSE file system, with each snapshot appearing as a directory named for the
+//! time it was started. File content is fetched from the pack stores as it is
+//! read. Runs until the file system is unmounted.
+//!
+//! The database is opened from the path in the `DB_PATH` environment variable,
+//! the same as the server, which must not be running at the same time. With
+//! `--archive`, a downloaded database snapshot is opened instead, leaving the
+//! live database untouched. The passphrase is taken from the same secrets
+//! provider as the server.
+
+use anyhow::{anyhow, Error};
+use server::data::repositories::RecordRepositoryImpl;
+use server::data::sources::EntityDataSourceImpl;
+use server::domain::helpers::crypto;
+use server::domain::managers::{mount, settings};
+use server::domain::repositories::RecordRepository;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+const USAGE: &str =
+    "Usage: zorigami-mount [--archive <file>] [--cache <packs>] <dataset-id> <mountpoint>";
+
+// Number of packs whose chunks are kept on disk when not specified.
+const DEFAULT_CACHE_PACKS: usize = 16;
+
+fn main() {
+    settings::init_logging();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(err) = run(&args) {
+        eprintln!("error: {:#}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Error> {
+    let mut archive: Option<PathBuf> = None;
+    let mut cache_packs = DEFAULT_CACHE_PACKS;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--archive" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!(format!("missing archive file\n{}", USAGE)))?;
+                archive = Some(PathBuf::from(value));
+            }
+            "--cache" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!(format!("missing number of packs\n{}", USAGE)))?;
+                cache_packs = value
+                    .parse()
+                    .map_err(|_| anyhow!(format!("invalid number of packs: {}", value)))?;
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 2 {
+        return Err(anyhow!(USAGE));
+    }
+    let dataset_id = positional[0];
+    let mountpoint = PathBuf::from(positional[1]);
+    if !mountpoint.is_dir() {
+        return Err(anyhow!(format!(
+            "no such directory: {}",
+            mountpoint.display()
+        )));
+    }
+    let passphrase = crypto::get_passphrase()?;
+    // keep the working directory until the file system is unmounted
+    let workdir = tempfile::tempdir()?;
+    let repo = match archive {
+        Some(archive) => {
+            if !archive.is_file() {
+                return Err(anyhow!(format!("no such file: {}", archive.display())));
+            }
+            RecordRepositoryImpl::open_archive(&archive, passphrase.expose(), workdir.path())?
+        }
+        None => {
+            let db_path = env::var("DB_PATH").unwrap_or_else(|_| "./tmp/database".to_owned());
+            let source = EntityDataSourceImpl::new(db_path)?;
+            RecordRepositoryImpl::new(Arc::new(source))
+        }
+    };
+    let repo: Arc<dyn RecordRepository> = Arc::new(repo);
+    mount::mount(repo, dataset_id, &mountpoint, passphrase, cache_packs)
+}
@@ -0,0 +1,139 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Command-line tool for recovering the most important files of a dataset
+//! using only the emergency index kept in the pack store, without the database
+//! or any of its backups. The store is described entirely on the command line,
+//! by its type and properties, such as the credentials and region of a cloud
+//! store or the base path of a local one.
+//!
+//! The computer identifier defaults to the one generated for the current user
+//! and host, the same as the server would use. The passphrase is taken from
+//! the same secrets provider as the server.
+//!
+//! With `--list` and no dataset, the emergency indices found in the store are
+//! listed, whose names include the identifier of the dataset; with a dataset,
+//! the files in its latest index are listed instead.
+
+use anyhow::{anyhow, Error};
+use server::data::repositories::RecordRepositoryImpl;
+use server::data::sources::EntityDataSourceImpl;
+use server::domain::entities::{Configuration, Store, StoreType};
+use server::domain::helpers::crypto;
+use server::domain::managers::{emergency, settings};
+use server::domain::repositories::RecordRepository;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const USAGE: &str = "Usage: zorigami-rescue --store <type> [--property <name>=<value>]... \
+    [--computer <id>] (--list [<dataset-id>] | <dataset-id> <outdir> [<path>...])";
+
+// Identifier given to the store described on the command line.
+const RESCUE_STORE_ID: &str = "rescue";
+
+fn main() {
+    settings::init_logging();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(err) = run(&args) {
+        eprintln!("error: {:#}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Error> {
+    let mut store_type: Option<StoreType> = None;
+    let mut properties: HashMap<String, String> = HashMap::new();
+    let mut computer_id: Option<String> = None;
+    let mut list = false;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--store" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!(format!("missing store type\n{}", USAGE)))?;
+                store_type = Some(StoreType::from_str(value)?);
+            }
+            "--property" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!(format!("missing store property\n{}", USAGE)))?;
+                let (name, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!(format!("invalid store property: {}", value)))?;
+                properties.insert(name.to_owned(), value.to_owned());
+            }
+            "--computer" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| anyhow!(format!("missing computer identifier\n{}", USAGE)))?;
+                computer_id = Some(value.to_owned());
+            }
+            "--list" => list = true,
+            _ => positional.push(arg),
+        }
+    }
+    let store_type = store_type.ok_or_else(|| anyhow!(USAGE))?;
+    if !list && positional.len() < 2 {
+        return Err(anyhow!(USAGE));
+    }
+    let computer_id = computer_id.unwrap_or_else(|| Configuration::default().computer_id);
+    let store = Store {
+        id: RESCUE_STORE_ID.to_owned(),
+        store_type,
+        label: "rescue".to_owned(),
+        properties,
+    };
+    // an empty database serves only to build the pack repository
+    let workdir = tempfile::tempdir()?;
+    let source = EntityDataSourceImpl::new(workdir.path().join("database"))?;
+    let repo = RecordRepositoryImpl::new(Arc::new(source));
+    let stores = repo.build_pack_repo(&store)?;
+    let Some(dataset_id) = positional.first() else {
+        for name in stores.list_emergency(&computer_id)? {
+            println!("{}", name);
+        }
+        return Ok(());
+    };
+    let passphrase = crypto::get_passphrase()?;
+    let archive = workdir.path().join("emergency");
+    if !stores.retrieve_latest_emergency(&computer_id, dataset_id, &archive)? {
+        return Err(anyhow!(format!(
+            "no emergency index for dataset {} of computer {}",
+            dataset_id, computer_id
+        )));
+    }
+    let mut index = repo.unpack_emergency(&archive, passphrase.expose())?;
+    if list {
+        println!("snapshot {} of {}", index.snapshot, index.created);
+        for file in index.files.iter() {
+            println!("{:>12}  {}", file.length, file.path);
+        }
+        return Ok(());
+    }
+    let outdir = PathBuf::from(positional[1]);
+    let selection: Vec<String> = positional[2..].iter().map(|s| s.to_string()).collect();
+    emergency::relocate(&mut index, RESCUE_STORE_ID);
+    let workspace = workdir.path().join("packs");
+    std::fs::create_dir_all(&workspace)?;
+    let count = emergency::recover(
+        &index,
+        stores.as_ref(),
+        &selection,
+        &outdir,
+        &workspace,
+        passphrase.expose(),
+    )?;
+    println!("recovered {} files to {}", count, outdir.display());
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Catalog, CatalogFile, CatalogPack, Checksum, PackLocation};
+use anyhow::Error;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct FileRecord {
+    #[serde(rename = "pa")]
+    path: String,
+    #[serde(rename = "ch")]
+    chunks: Vec<Checksum>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackRecord {
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "lo")]
+    locations: Vec<PackLocation>,
+    #[serde(rename = "ch")]
+    chunks: Vec<Checksum>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CatalogRecord {
+    #[serde(rename = "cr")]
+    created: DateTime<Utc>,
+    #[serde(rename = "fi")]
+    files: Vec<FileRecord>,
+    #[serde(rename = "pk")]
+    packs: Vec<PackRecord>,
+}
+
+///
+/// Encode the catalog into a CBOR-formatted byte vector.
+///
+pub fn encode_catalog(catalog: &Catalog) -> Result<Vec<u8>, Error> {
+    let record = CatalogRecord {
+        created: catalog.created,
+        files: catalog
+            .files
+            .iter()
+            .map(|f| FileRecord {
+                path: f.path.clone(),
+                chunks: f.chunks.clone(),
+            })
+            .collect(),
+        packs: catalog
+            .packs
+            .iter()
+            .map(|p| PackRecord {
+                digest: p.digest.clone(),
+                locations: p.locations.clone(),
+                chunks: p.chunks.clone(),
+            })
+            .collect(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the catalog from the CBOR-formatted bytes.
+///
+pub fn decode_catalog(encoded: &[u8]) -> Result<Catalog, Error> {
+    let record: CatalogRecord = serde_cbor::from_slice(encoded)?;
+    Ok(Catalog {
+        created: record.created,
+        files: record
+            .files
+            .into_iter()
+            .map(|f| CatalogFile {
+                path: f.path,
+                chunks: f.chunks,
+            })
+            .collect(),
+        packs: record
+            .packs
+            .into_iter()
+            .map(|p| CatalogPack {
+                digest: p.digest,
+                locations: p.locations,
+                chunks: p.chunks,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_round_trip() -> Result<(), Error> {
+        let chunk1 = Checksum::BLAKE3(
+            "261930e84e14c240210ae8c459acc4bb85dd52f1b91c868f2106dbc1ceb3acca".to_owned(),
+        );
+        let chunk2 = Checksum::BLAKE3(
+            "1dd8ae20bf4fc1ed0ac58c4b2e45ec6a96ea1b94fb3b2bf9bf7e3ee0ad3ba6d4".to_owned(),
+        );
+        let pack = Checksum::BLAKE3(
+            "5f3e3b7a1d2e6f0a9c8b7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c9d8e7f6a".to_owned(),
+        );
+        let mut catalog = Catalog::new();
+        catalog.files.push(CatalogFile {
+            path: "shared/000009.sst".to_owned(),
+            chunks: vec![chunk1.clone(), chunk2.clone()],
+        });
+        catalog.files.push(CatalogFile {
+            path: "meta/1".to_owned(),
+            chunks: vec![],
+        });
+        catalog.packs.push(CatalogPack {
+            digest: pack.clone(),
+            locations: vec![PackLocation::new("store1", "bucket1", "object1")],
+            chunks: vec![chunk1, chunk2.clone()],
+        });
+        let encoded = encode_catalog(&catalog)?;
+        let actual = decode_catalog(&encoded)?;
+        assert_eq!(actual, catalog);
+        let chunk_packs = actual.chunk_packs();
+        assert_eq!(chunk_packs.len(), 2);
+        assert_eq!(chunk_packs.get(&chunk2), Some(&pack));
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Checksum, DedupStats, SnapshotDedup};
+use anyhow::Error;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotRecord {
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "st")]
+    start_time: DateTime<Utc>,
+    #[serde(rename = "ls")]
+    logical_size: u64,
+    #[serde(rename = "ub")]
+    unique_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StatsRecord {
+    #[serde(rename = "ds")]
+    dataset_id: String,
+    #[serde(rename = "co")]
+    computed: DateTime<Utc>,
+    #[serde(rename = "lb")]
+    logical_bytes: u64,
+    #[serde(rename = "pb")]
+    physical_bytes: u64,
+    #[serde(rename = "sn")]
+    snapshots: Vec<SnapshotRecord>,
+}
+
+///
+/// Encode the deduplication statistics into a CBOR-formatted byte vector.
+///
+pub fn encode_dedup(stats: &DedupStats) -> Result<Vec<u8>, Error> {
+    let record = StatsRecord {
+        dataset_id: stats.dataset_id.clone(),
+        computed: stats.computed,
+        logical_bytes: stats.logical_bytes,
+        physical_bytes: stats.physical_bytes,
+        snapshots: stats
+            .snapshots
+            .iter()
+            .map(|s| SnapshotRecord {
+                digest: s.digest.clone(),
+                start_time: s.start_time,
+                logical_size: s.logical_size,
+                unique_bytes: s.unique_bytes,
+            })
+            .collect(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the deduplication statistics from the CBOR-formatted bytes.
+///
+pub fn decode_dedup(encoded: &[u8]) -> Result<DedupStats, Error> {
+    let record: StatsRecord = serde_cbor::from_slice(encoded)?;
+    Ok(DedupStats {
+        dataset_id: record.dataset_id,
+        computed: record.computed,
+        logical_bytes: record.logical_bytes,
+        physical_bytes: record.physical_bytes,
+        snapshots: record
+            .snapshots
+            .into_iter()
+            .map(|s| SnapshotDedup {
+                digest: s.digest,
+                start_time: s.start_time,
+                logical_size: s.logical_size,
+                unique_bytes: s.unique_bytes,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_round_trip() -> Result<(), Error> {
+        let stats = DedupStats {
+            dataset_id: "cafebabe".to_owned(),
+            computed: Utc::now(),
+            logical_bytes: 4000,
+            physical_bytes: 3000,
+            snapshots: vec![SnapshotDedup {
+                digest: Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned()),
+                start_time: Utc::now(),
+                logical_size: 3000,
+                unique_bytes: 2000,
+            }],
+        };
+        let encoded = encode_dedup(&stats)?;
+        let actual = decode_dedup(&encoded)?;
+        assert_eq!(actual, stats);
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Compression, EmergencyChunk, EmergencyFile, EmergencyIndex, EmergencyPack,
+    PackLocation,
+};
+use anyhow::Error;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize)]
+struct ChunkRecord {
+    #[serde(rename = "of")]
+    offset: u64,
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "pk")]
+    pack: Checksum,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileRecord {
+    #[serde(rename = "pa")]
+    path: String,
+    #[serde(rename = "le")]
+    length: u64,
+    #[serde(rename = "co")]
+    content: Option<Vec<u8>>,
+    #[serde(rename = "ch")]
+    chunks: Vec<ChunkRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackRecord {
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "lo")]
+    locations: Vec<PackLocation>,
+    #[serde(rename = "z")]
+    compression: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexRecord {
+    #[serde(rename = "ds")]
+    dataset: String,
+    #[serde(rename = "sn")]
+    snapshot: Checksum,
+    #[serde(rename = "cr")]
+    created: DateTime<Utc>,
+    #[serde(rename = "fi")]
+    files: Vec<FileRecord>,
+    #[serde(rename = "pk")]
+    packs: Vec<PackRecord>,
+}
+
+///
+/// Encode the emergency index into a CBOR-formatted byte vector.
+///
+pub fn encode_emergency(index: &EmergencyIndex) -> Result<Vec<u8>, Error> {
+    let record = IndexRecord {
+        dataset: index.dataset.clone(),
+        snapshot: index.snapshot.clone(),
+        created: index.created,
+        files: index
+            .files
+            .iter()
+            .map(|f| FileRecord {
+                path: f.path.clone(),
+                length: f.length,
+                content: f.content.clone(),
+                chunks: f
+                    .chunks
+                    .iter()
+                    .map(|c| ChunkRecord {
+                        offset: c.offset,
+                        digest: c.digest.clone(),
+                        pack: c.pack.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        packs: index
+            .packs
+            .iter()
+            .map(|p| PackRecord {
+                digest: p.digest.clone(),
+                locations: p.locations.clone(),
+                compression: p.compression.to_string(),
+            })
+            .collect(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the emergency index from the CBOR-formatted bytes.
+///
+pub fn decode_emergency(encoded: &[u8]) -> Result<EmergencyIndex, Error> {
+    let record: IndexRecord = serde_cbor::from_slice(encoded)?;
+    let mut packs: Vec<EmergencyPack> = Vec::with_capacity(record.packs.len());
+    for p in record.packs.into_iter() {
+        packs.push(EmergencyPack {
+            digest: p.digest,
+            locations: p.locations,
+            compression: Compression::from_str(&p.compression)?,
+        });
+    }
+    Ok(EmergencyIndex {
+        dataset: record.dataset,
+        snapshot: record.snapshot,
+        created: record.created,
+        files: record
+            .files
+            .into_iter()
+            .map(|f| EmergencyFile {
+                path: f.path,
+                length: f.length,
+                content: f.content,
+                chunks: f
+                    .chunks
+                    .into_iter()
+                    .map(|c| EmergencyChunk {
+                        offset: c.offset,
+                        digest: c.digest,
+                        pack: c.pack,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        packs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emergency_round_trip() -> Result<(), Error> {
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let chunk = Checksum::BLAKE3(
+            "261930e84e14c240210ae8c459acc4bb85dd52f1b91c868f2106dbc1ceb3acca".to_owned(),
+        );
+        let pack = Checksum::BLAKE3(
+            "5f3e3b7a1d2e6f0a9c8b7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c9d8e7f6a".to_owned(),
+        );
+        let mut index = EmergencyIndex::new("cafebabe", snapshot);
+        index.files.push(EmergencyFile {
+            path: "etc/passwords.kdbx".to_owned(),
+            length: 3129,
+            content: None,
+            chunks: vec![EmergencyChunk {
+                offset: 0,
+                digest: chunk,
+                pack: pack.clone(),
+            }],
+        });
+        index.files.push(EmergencyFile {
+            path: "notes.txt".to_owned(),
+            length: 5,
+            content: Some(b"hello".to_vec()),
+            chunks: vec![],
+        });
+        index.packs.push(EmergencyPack {
+            digest: pack,
+            locations: vec![PackLocation::new("store1", "bucket1", "object1")],
+            compression: Compression::Zstd(9),
+        });
+        let encoded = encode_emergency(&index)?;
+        let actual = decode_emergency(&encoded)?;
+        assert_eq!(actual, index);
+        Ok(())
+    }
+}
@@ -11,8 +11,33 @@
 // 6. Copy the results here
 // 7. Remove the redundant type definitions
 //
-use crate::domain::entities::FileCounts;
+use crate::domain::entities::{CategoryCount, FileCategory, FileCounts};
 use std::collections::HashMap;
+use std::str::FromStr;
+
+// The file categories are stored by name, with the count and total size, to
+// allow for adding categories later.
+fn categories_to_names(
+    categories: &HashMap<FileCategory, CategoryCount>,
+) -> HashMap<String, (u32, u64)> {
+    categories
+        .iter()
+        .map(|(k, v)| (k.to_string(), (v.files, v.bytes)))
+        .collect()
+}
+
+// Convert the stored category names back to categories, ignoring any that are
+// not recognized.
+fn names_to_categories(names: HashMap<String, (u32, u64)>) -> HashMap<FileCategory, CategoryCount> {
+    names
+        .into_iter()
+        .filter_map(|(k, (files, bytes))| {
+            FileCategory::from_str(&k)
+                .ok()
+                .map(|c| (c, CategoryCount { files, bytes }))
+        })
+        .collect()
+}
 
 #[doc(hidden)]
 #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
@@ -31,7 +56,7 @@ const _: () = {
             let mut __serde_state = match _serde::Serializer::serialize_struct(
                 __serializer,
                 "FileCounts",
-                false as usize + 1 + 1 + 1 + 1 + 1,
+                false as usize + 1 + 1 + 1 + 1 + 1 + 1,
             ) {
                 _serde::__private::Ok(__val) => __val,
                 _serde::__private::Err(__err) => {
@@ -88,6 +113,16 @@ const _: () = {
                     return _serde::__private::Err(__err);
                 }
             };
+            match _serde::ser::SerializeStruct::serialize_field(
+                &mut __serde_state,
+                "c",
+                &categories_to_names(&self.categories),
+            ) {
+                _serde::__private::Ok(__val) => __val,
+                _serde::__private::Err(__err) => {
+                    return _serde::__private::Err(__err);
+                }
+            };
             _serde::ser::SerializeStruct::end(__serde_state)
         }
     }
@@ -110,6 +145,7 @@ const _: () = {
                 __field2,
                 __field3,
                 __field4,
+                __field5,
                 __ignore,
             }
             struct __FieldVisitor;
@@ -131,6 +167,7 @@ const _: () = {
                         2u64 => _serde::__private::Ok(__Field::__field2),
                         3u64 => _serde::__private::Ok(__Field::__field3),
                         4u64 => _serde::__private::Ok(__Field::__field4),
+                        5u64 => _serde::__private::Ok(__Field::__field5),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -147,6 +184,7 @@ const _: () = {
                         "vs" => _serde::__private::Ok(__Field::__field2),
                         "vl" => _serde::__private::Ok(__Field::__field3),
                         "m" => _serde::__private::Ok(__Field::__field4),
+                        "c" => _serde::__private::Ok(__Field::__field5),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -163,6 +201,7 @@ const _: () = {
                         b"vs" => _serde::__private::Ok(__Field::__field2),
                         b"vl" => _serde::__private::Ok(__Field::__field3),
                         b"m" => _serde::__private::Ok(__Field::__field4),
+                        b"c" => _serde::__private::Ok(__Field::__field5),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -209,7 +248,7 @@ const _: () = {
                             _serde::__private::None => {
                                 return _serde::__private::Err(_serde::de::Error::invalid_length(
                                     0usize,
-                                    &"struct FileCounts with 5 elements",
+                                    &"struct FileCounts with 6 elements",
                                 ));
                             }
                         };
@@ -224,7 +263,7 @@ const _: () = {
                             _serde::__private::None => {
                                 return _serde::__private::Err(_serde::de::Error::invalid_length(
                                     1usize,
-                                    &"struct FileCounts with 5 elements",
+                                    &"struct FileCounts with 6 elements",
                                 ));
                             }
                         };
@@ -239,7 +278,7 @@ const _: () = {
                             _serde::__private::None => {
                                 return _serde::__private::Err(_serde::de::Error::invalid_length(
                                     2usize,
-                                    &"struct FileCounts with 5 elements",
+                                    &"struct FileCounts with 6 elements",
                                 ));
                             }
                         };
@@ -254,7 +293,7 @@ const _: () = {
                             _serde::__private::None => {
                                 return _serde::__private::Err(_serde::de::Error::invalid_length(
                                     3usize,
-                                    &"struct FileCounts with 5 elements",
+                                    &"struct FileCounts with 6 elements",
                                 ));
                             }
                         };
@@ -270,16 +309,30 @@ const _: () = {
                         _serde::__private::None => {
                             return _serde::__private::Err(_serde::de::Error::invalid_length(
                                 4usize,
-                                &"struct FileCounts with 5 elements",
+                                &"struct FileCounts with 6 elements",
                             ));
                         }
                     };
+                    // categories were added later and may be missing
+                    let __field5 = match match _serde::de::SeqAccess::next_element::<
+                        HashMap<String, (u32, u64)>,
+                    >(&mut __seq)
+                    {
+                        _serde::__private::Ok(__val) => __val,
+                        _serde::__private::Err(__err) => {
+                            return _serde::__private::Err(__err);
+                        }
+                    } {
+                        _serde::__private::Some(__value) => __value,
+                        _serde::__private::None => _serde::__private::Default::default(),
+                    };
                     _serde::__private::Ok(FileCounts {
                         directories: __field0,
                         symlinks: __field1,
                         very_small_files: __field2,
                         very_large_files: __field3,
                         file_sizes: __field4,
+                        categories: names_to_categories(__field5),
                     })
                 }
                 #[inline]
@@ -296,6 +349,8 @@ const _: () = {
                     let mut __field3: _serde::__private::Option<u32> = _serde::__private::None;
                     let mut __field4: _serde::__private::Option<HashMap<u8, u32>> =
                         _serde::__private::None;
+                    let mut __field5: _serde::__private::Option<HashMap<String, (u32, u64)>> =
+                        _serde::__private::None;
                     while let _serde::__private::Some(__key) =
                         match _serde::de::MapAccess::next_key::<__Field>(&mut __map) {
                             _serde::__private::Ok(__val) => __val,
@@ -382,6 +437,24 @@ const _: () = {
                                     },
                                 );
                             }
+                            __Field::__field5 => {
+                                if _serde::__private::Option::is_some(&__field5) {
+                                    return _serde::__private::Err(
+                                        <__A::Error as _serde::de::Error>::duplicate_field("c"),
+                                    );
+                                }
+                                __field5 = _serde::__private::Some(
+                                    match _serde::de::MapAccess::next_value::<
+                                        HashMap<String, (u32, u64)>,
+                                    >(&mut __map)
+                                    {
+                                        _serde::__private::Ok(__val) => __val,
+                                        _serde::__private::Err(__err) => {
+                                            return _serde::__private::Err(__err);
+                                        }
+                                    },
+                                );
+                            }
                             _ => {
                                 let _ = match _serde::de::MapAccess::next_value::<
                                     _serde::de::IgnoredAny,
@@ -448,16 +521,19 @@ const _: () = {
                             }
                         }
                     };
+                    // categories were added later and may be missing
+                    let __field5 = __field5.unwrap_or_default();
                     _serde::__private::Ok(FileCounts {
                         directories: __field0,
                         symlinks: __field1,
                         very_small_files: __field2,
                         very_large_files: __field3,
                         file_sizes: __field4,
+                        categories: names_to_categories(__field5),
                     })
                 }
             }
-            const FIELDS: &[&str] = &["d", "s", "vs", "vl", "m"];
+            const FIELDS: &[&str] = &["d", "s", "vs", "vl", "m", "c"];
             _serde::Deserializer::deserialize_struct(
                 __deserializer,
                 "FileCounts",
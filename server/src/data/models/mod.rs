@@ -1,15 +1,18 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::schedule::Schedule;
+use crate::domain::entities::schedule::{Schedule, TimeRange};
 use crate::domain::entities::{
-    Checksum, Chunk, Configuration, Dataset, File, FileCounts, Pack, PackLocation, Snapshot, Store,
-    StoreType,
+    ChainEntry, Checksum, Chunk, Compression, Configuration, Dataset, Device, DeviceScope, Event,
+    EventKind, File, FileCounts, MaintenanceResult, MaintenanceTask, Migration, Pack, PackLocation,
+    Snapshot, Store, StoreType, Verification, VerifyDepth, Webhook,
 };
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::str::FromStr;
+use store_core::Checkpoint;
 
 //
 // serde_derive has trouble with the combination of remote derivations and
@@ -142,9 +145,12 @@ pub struct ChunkDef {
 pub enum StoreTypeDef {
     AMAZON,
     AZURE,
+    DROPBOX,
     GOOGLE,
     LOCAL,
+    MEMORY,
     MINIO,
+    RCLONE,
     SFTP,
 }
 
@@ -179,6 +185,10 @@ pub struct DatasetDef {
     pub stores: Vec<String>,
     #[serde(rename = "ex")]
     pub excludes: Vec<String>,
+    #[serde(rename = "pp")]
+    pub properties: HashMap<String, String>,
+    #[serde(rename = "ar", default, skip_serializing_if = "Option::is_none")]
+    pub archived: Option<DateTime<Utc>>,
 }
 
 impl Default for DatasetDef {
@@ -191,6 +201,8 @@ impl Default for DatasetDef {
             pack_size: 0,
             stores: vec![],
             excludes: vec![],
+            properties: HashMap::new(),
+            archived: None,
         }
     }
 }
@@ -204,6 +216,10 @@ pub struct FileDef {
     pub length: u64,
     #[serde(rename = "c")]
     pub chunks: Vec<(u64, Checksum)>,
+    #[serde(rename = "tk", default, skip_serializing_if = "Option::is_none")]
+    pub taken: Option<DateTime<Utc>>,
+    #[serde(rename = "su", default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
 }
 
 mod file_counts;
@@ -246,6 +262,8 @@ pub struct SnapshotDef {
     pub file_counts: FileCounts,
     #[serde(rename = "tr")]
     pub tree: Checksum,
+    #[serde(rename = "pz", default)]
+    pub paused: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -255,6 +273,25 @@ pub struct PackDef {
     pub digest: Checksum,
     #[serde(rename = "l")]
     pub locations: Vec<PackLocation>,
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(rename = "z", default, with = "CompressionDef")]
+    pub compression: Compression,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Checkpoint")]
+pub struct CheckpointDef {
+    #[serde(rename = "fp")]
+    pub fingerprint: String,
+    #[serde(rename = "se")]
+    pub session: String,
+    #[serde(rename = "pa")]
+    pub parts: Vec<String>,
+    #[serde(rename = "of")]
+    pub offset: u64,
+    #[serde(rename = "up")]
+    pub updated: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -268,11 +305,224 @@ pub struct ConfigurationDef {
     pub computer_id: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "DeviceScope")]
+pub enum DeviceScopeDef {
+    Restore,
+    Full,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Device")]
+pub struct DeviceDef {
+    #[serde(skip)]
+    pub id: String,
+    #[serde(rename = "na")]
+    pub name: String,
+    #[serde(rename = "sc", with = "DeviceScopeDef")]
+    pub scope: DeviceScope,
+    #[serde(rename = "th")]
+    pub token_hash: String,
+    #[serde(rename = "cr")]
+    pub created: DateTime<Utc>,
+    #[serde(rename = "lu")]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Migration")]
+pub struct MigrationDef {
+    #[serde(skip)]
+    pub version: u32,
+    #[serde(rename = "na")]
+    pub name: String,
+    #[serde(rename = "ap")]
+    pub applied: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Event")]
+pub struct EventDef {
+    #[serde(skip)]
+    pub id: String,
+    #[serde(rename = "ki", with = "EventKindDef")]
+    pub kind: EventKind,
+    #[serde(rename = "ti")]
+    pub time: DateTime<Utc>,
+    #[serde(rename = "su")]
+    pub subject: String,
+    #[serde(rename = "de")]
+    pub details: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Verification")]
+pub struct VerificationDef {
+    #[serde(skip)]
+    pub id: String,
+    #[serde(skip)]
+    pub dataset_id: String,
+    #[serde(rename = "sn")]
+    pub snapshot: Checksum,
+    #[serde(rename = "de", with = "VerifyDepthDef")]
+    pub depth: VerifyDepth,
+    #[serde(rename = "st")]
+    pub started: DateTime<Utc>,
+    #[serde(rename = "fi")]
+    pub finished: DateTime<Utc>,
+    #[serde(rename = "nf")]
+    pub files: u64,
+    #[serde(rename = "np")]
+    pub packs: u64,
+    #[serde(rename = "nr")]
+    pub restored: u64,
+    #[serde(rename = "is")]
+    pub issues: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "MaintenanceResult")]
+pub struct MaintenanceResultDef {
+    #[serde(rename = "ta", with = "MaintenanceTaskDef")]
+    pub task: MaintenanceTask,
+    #[serde(skip)]
+    pub dataset_id: Option<String>,
+    #[serde(rename = "st")]
+    pub started: DateTime<Utc>,
+    #[serde(rename = "fi")]
+    pub finished: DateTime<Utc>,
+    #[serde(rename = "su")]
+    pub summary: String,
+    #[serde(rename = "er")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ChainEntry")]
+pub struct ChainEntryDef {
+    #[serde(skip)]
+    pub store: String,
+    #[serde(rename = "sq")]
+    pub sequence: u64,
+    #[serde(rename = "sn")]
+    pub snapshot: Checksum,
+    #[serde(rename = "ti")]
+    pub time: DateTime<Utc>,
+    #[serde(rename = "pr")]
+    pub previous: Checksum,
+    #[serde(rename = "di")]
+    pub digest: Checksum,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Webhook")]
+pub struct WebhookDef {
+    #[serde(skip)]
+    pub id: String,
+    #[serde(rename = "ur")]
+    pub url: String,
+    #[serde(rename = "ds")]
+    pub dataset: Option<String>,
+    #[serde(rename = "sh")]
+    pub stale_hours: Option<u32>,
+    #[serde(rename = "qh", default)]
+    pub quiet_hours: Option<TimeRange>,
+    #[serde(rename = "dg", default)]
+    pub digest: bool,
+}
+
+// The event kind is saved as text so that adding kinds in the future will not
+// disturb the existing records.
+struct EventKindDef;
+
+impl EventKindDef {
+    fn serialize<S>(kind: &EventKind, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&kind.to_string())
+    }
+
+    fn deserialize<'de, D>(deserializer: D) -> Result<EventKind, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        EventKind::from_str(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+// The verify depth is saved as text, the same as the event kind.
+struct VerifyDepthDef;
+
+impl VerifyDepthDef {
+    fn serialize<S>(depth: &VerifyDepth, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&depth.to_string())
+    }
+
+    fn deserialize<'de, D>(deserializer: D) -> Result<VerifyDepth, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        VerifyDepth::from_str(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+// The maintenance task is saved as text, the same as the event kind.
+struct MaintenanceTaskDef;
+
+impl MaintenanceTaskDef {
+    fn serialize<S>(task: &MaintenanceTask, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&task.to_string())
+    }
+
+    fn deserialize<'de, D>(deserializer: D) -> Result<MaintenanceTask, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        MaintenanceTask::from_str(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+// The compression is saved as text, and is absent from the records of packs
+// made before the compression could be chosen.
+struct CompressionDef;
+
+impl CompressionDef {
+    fn serialize<S>(compression: &Compression, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&compression.to_string())
+    }
+
+    fn deserialize<'de, D>(deserializer: D) -> Result<Compression, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        Compression::from_str(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+pub mod catalog;
+pub mod dedup;
+pub mod emergency;
+pub mod plan;
+pub mod replica;
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::entities::schedule::TimeRange;
-    use crate::domain::entities::{Tree, TreeEntry, TreeReference};
+    use crate::domain::entities::{RawName, Tree, TreeEntry, TreeReference};
     use anyhow::Error;
     use std::path::Path;
 
@@ -348,6 +598,9 @@ mod tests {
         let range = TimeRange::new(12, 0, 18, 0);
         let schedule = Schedule::Daily(Some(range));
         dataset.schedules.push(schedule.clone());
+        dataset
+            .properties
+            .insert("trigger_files".to_owned(), "100".to_owned());
         // act
         let mut buffer: Vec<u8> = Vec::new();
         let mut ser = serde_json::Serializer::new(&mut buffer);
@@ -360,6 +613,18 @@ mod tests {
         assert_eq!(actual.pack_size, dataset.pack_size);
         assert_eq!(actual.schedules.len(), 1);
         assert_eq!(actual.schedules[0], schedule);
+        assert_eq!(actual.properties, dataset.properties);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_counts_without_categories() -> Result<(), Error> {
+        // records written before categories were introduced
+        let as_text = r#"{"d":3,"s":1,"vs":2,"vl":0,"m":{"10":4}}"#;
+        let actual: FileCounts = serde_json::from_str(as_text)?;
+        assert_eq!(actual.directories, 3);
+        assert_eq!(actual.total_files(), 6);
+        assert!(actual.categories.is_empty());
         Ok(())
     }
 
@@ -374,6 +639,8 @@ mod tests {
         file_counts.register_file(16384);
         file_counts.register_file(16777216);
         file_counts.register_file(1048576);
+        file_counts.register_category(Path::new("photo.jpg"), 16384);
+        file_counts.register_category(Path::new("movie.mp4"), 16777216);
         let mut snapshot = Snapshot::new(Some(parent), tree, file_counts);
         snapshot.set_end_time(Utc::now());
         // act
@@ -391,6 +658,7 @@ mod tests {
         assert_eq!(actual.end_time, snapshot.end_time);
         assert_eq!(actual.file_counts, snapshot.file_counts);
         assert_eq!(actual.tree, snapshot.tree);
+        assert_eq!(actual.paused, snapshot.paused);
         Ok(())
     }
 
@@ -399,7 +667,7 @@ mod tests {
         // arrange
         let digest = Checksum::SHA1(String::from("65ace06cc7f835c497811ea7199968a119eeba4b"));
         let coords = vec![PackLocation::new("store1", "bucket1", "object1")];
-        let pack = Pack::new(digest, coords);
+        let pack = Pack::new(digest, coords).compression(Compression::Zstd(19));
         // act
         let mut buffer: Vec<u8> = Vec::new();
         let mut ser = serde_json::Serializer::new(&mut buffer);
@@ -411,6 +679,28 @@ mod tests {
         assert_eq!(actual.locations.len(), pack.locations.len());
         assert_eq!(actual.locations.len(), 1);
         assert_eq!(actual.locations[0], pack.locations[0]);
+        assert_eq!(actual.compression, Compression::Zstd(19));
+        // records from before the compression could be chosen
+        let as_text = r#"{"l":[]}"#;
+        let mut de = serde_json::Deserializer::from_str(as_text);
+        let actual = PackDef::deserialize(&mut de)?;
+        assert_eq!(actual.compression, Compression::Archive);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_serde() -> Result<(), Error> {
+        // arrange
+        let mut checkpoint = Checkpoint::new("3129-cafebabe", "upload-1");
+        checkpoint.add_part("etag-1", 8388608);
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut buffer);
+        CheckpointDef::serialize(&checkpoint, &mut ser)?;
+        let mut de = serde_cbor::Deserializer::from_slice(&buffer);
+        let actual = CheckpointDef::deserialize(&mut de)?;
+        // assert
+        assert_eq!(actual, checkpoint);
         Ok(())
     }
 
@@ -432,6 +722,173 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_device_serde() -> Result<(), Error> {
+        // arrange
+        let device = Device::new("phone", DeviceScope::Restore, "cafebabe");
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        DeviceDef::serialize(&device, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = DeviceDef::deserialize(&mut de)?;
+        // assert
+        // id is not serialized in the record itself
+        assert_eq!(actual.id, "");
+        assert_eq!(actual.name, "phone");
+        assert_eq!(actual.scope, DeviceScope::Restore);
+        assert_eq!(actual.token_hash, "cafebabe");
+        assert_eq!(actual.created, device.created);
+        assert!(actual.last_used.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migration_serde() -> Result<(), Error> {
+        // arrange
+        let migration = Migration::new(2, "rewrite dataset records");
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        MigrationDef::serialize(&migration, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = MigrationDef::deserialize(&mut de)?;
+        // assert
+        // version is not serialized in the record itself
+        assert_eq!(actual.version, 0);
+        assert_eq!(actual.name, "rewrite dataset records");
+        assert_eq!(actual.applied, migration.applied);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_entry_serde() -> Result<(), Error> {
+        // arrange
+        let snapshot = Checksum::SHA1(String::from("b14c4909c3fce2483cd54b328ada88f5ef5e8f96"));
+        let first = ChainEntry::new("store1", None, snapshot.clone());
+        let entry = ChainEntry::new("store1", Some(&first), snapshot);
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        ChainEntryDef::serialize(&entry, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = ChainEntryDef::deserialize(&mut de)?;
+        // assert
+        // store is not serialized in the record itself
+        assert!(actual.store.is_empty());
+        assert_eq!(actual.sequence, 2);
+        assert_eq!(actual.snapshot, entry.snapshot);
+        assert_eq!(actual.time, entry.time);
+        assert_eq!(actual.previous, first.digest);
+        assert_eq!(actual.digest, entry.digest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_webhook_serde() -> Result<(), Error> {
+        // arrange
+        let mut webhook = Webhook::new("https://example.com/hook");
+        webhook.dataset = Some("cafebabe".to_owned());
+        webhook.stale_hours = Some(36);
+        webhook.quiet_hours = Some(TimeRange::new(22, 0, 6, 0));
+        webhook.digest = true;
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        WebhookDef::serialize(&webhook, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = WebhookDef::deserialize(&mut de)?;
+        // assert
+        // identifier is not serialized in the record itself
+        assert!(actual.id.is_empty());
+        assert_eq!(actual.url, webhook.url);
+        assert_eq!(actual.dataset, webhook.dataset);
+        assert_eq!(actual.stale_hours, Some(36));
+        assert_eq!(actual.quiet_hours, webhook.quiet_hours);
+        assert!(actual.digest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_serde() -> Result<(), Error> {
+        // arrange
+        let event = Event::new(EventKind::StoreUpdated, "cafebabe").detail("label", "usb disk");
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        EventDef::serialize(&event, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = EventDef::deserialize(&mut de)?;
+        // assert
+        // identifier is not serialized in the record itself
+        assert!(actual.id.is_empty());
+        assert_eq!(actual.kind, EventKind::StoreUpdated);
+        assert_eq!(actual.time, event.time);
+        assert_eq!(actual.subject, "cafebabe");
+        assert_eq!(actual.details["label"], "usb disk");
+        Ok(())
+    }
+
+    #[test]
+    fn test_verification_serde() -> Result<(), Error> {
+        // arrange
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut verification = Verification::new("cafebabe", digest, VerifyDepth::Packs);
+        verification.files = 101;
+        verification.packs = 7;
+        verification
+            .issues
+            .push("missing chunk record sha1-cafebabe".to_owned());
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        VerificationDef::serialize(&verification, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = VerificationDef::deserialize(&mut de)?;
+        // assert
+        // identifiers are not serialized in the record itself
+        assert!(actual.id.is_empty());
+        assert!(actual.dataset_id.is_empty());
+        assert_eq!(actual.snapshot, verification.snapshot);
+        assert_eq!(actual.depth, VerifyDepth::Packs);
+        assert_eq!(actual.started, verification.started);
+        assert_eq!(actual.files, 101);
+        assert_eq!(actual.packs, 7);
+        assert_eq!(actual.restored, 0);
+        assert_eq!(actual.issues, verification.issues);
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintenance_result_serde() -> Result<(), Error> {
+        // arrange
+        let mut run = MaintenanceResult::new(MaintenanceTask::UploadDatabase).dataset("cafebabe");
+        run.summary = "uploaded database backup".to_owned();
+        run.error = Some("store unreachable".to_owned());
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_json::Serializer::new(&mut buffer);
+        MaintenanceResultDef::serialize(&run, &mut ser)?;
+        let as_text = String::from_utf8(buffer)?;
+        let mut de = serde_json::Deserializer::from_str(&as_text);
+        let actual = MaintenanceResultDef::deserialize(&mut de)?;
+        // assert
+        // dataset identifier is not serialized in the record itself
+        assert!(actual.dataset_id.is_none());
+        assert_eq!(actual.task, MaintenanceTask::UploadDatabase);
+        assert_eq!(actual.started, run.started);
+        assert_eq!(actual.finished, run.finished);
+        assert_eq!(actual.summary, run.summary);
+        assert_eq!(actual.error, run.error);
+        Ok(())
+    }
+
     #[test]
     fn test_tree_serde() -> Result<(), Error> {
         // arrange
@@ -454,6 +911,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_tree_entry_raw_name_serde() -> Result<(), Error> {
+        // arrange
+        let b3sum = "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128";
+        let file_digest = Checksum::BLAKE3(String::from(b3sum));
+        let reference = TreeReference::FILE(file_digest);
+        let plain = TreeEntry::new(Path::new("/nowhere/plain.txt"), reference.clone());
+        let decomposed = TreeEntry::new(Path::new("/nowhere/cafe\u{301}.txt"), reference);
+        let tree = Tree::new(vec![plain, decomposed], 2);
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut buffer);
+        Tree::serialize(&tree, &mut ser)?;
+        let mut de = serde_cbor::Deserializer::from_slice(&buffer);
+        let actual = Tree::deserialize(&mut de)?;
+        // assert
+        assert_eq!(actual.entries.len(), 2);
+        assert_eq!(actual.entries[0].name, "caf\u{e9}.txt");
+        assert_eq!(
+            actual.entries[0].raw_name,
+            Some(RawName::UTF8("cafe\u{301}.txt".to_owned()))
+        );
+        assert_eq!(actual.entries[1].name, "plain.txt");
+        assert!(actual.entries[1].raw_name.is_none());
+        // the raw name field is written only when needed
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut buffer);
+        TreeEntry::serialize(&actual.entries[1], &mut ser)?;
+        assert!(!buffer.windows(2).any(|w| w == b"rn"));
+        Ok(())
+    }
+
     #[test]
     fn test_file_serde() -> Result<(), Error> {
         // arrange
@@ -475,6 +964,23 @@ mod tests {
         assert_eq!(actual.chunks.len(), 1);
         assert_eq!(actual.chunks[0].0, 0);
         assert_eq!(actual.chunks[0].1, file_digest);
+        assert!(actual.taken.is_none());
+        assert!(actual.subject.is_none());
+
+        // arrange
+        let mut file = File::new(file_digest.clone(), 3129, vec![(0, file_digest)]);
+        let taken = Utc.with_ymd_and_hms(2019, 7, 4, 12, 30, 0).unwrap();
+        file.taken = Some(taken);
+        file.subject = Some(String::from("Trip photos"));
+        // act
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut buffer);
+        FileDef::serialize(&file, &mut ser)?;
+        let mut de = serde_cbor::Deserializer::from_slice(&buffer);
+        let actual = FileDef::deserialize(&mut de)?;
+        // assert
+        assert_eq!(actual.taken, Some(taken));
+        assert_eq!(actual.subject.unwrap(), "Trip photos");
         Ok(())
     }
 }
@@ -0,0 +1,204 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Checksum, Chunk, PendingUpload, PlannedFile, UploadPlan};
+use anyhow::Error;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+struct ChunkRecord {
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "of")]
+    offset: usize,
+    #[serde(rename = "le")]
+    length: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileRecord {
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "pa")]
+    path: PathBuf,
+    // plans saved by older versions lack these, and their files are read again
+    #[serde(rename = "le", default)]
+    length: u64,
+    #[serde(rename = "mt", default)]
+    modified: Option<DateTime<Utc>>,
+    #[serde(rename = "ch")]
+    chunks: Vec<ChunkRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanRecord {
+    #[serde(rename = "sn")]
+    snapshot: Checksum,
+    #[serde(rename = "cr")]
+    created: DateTime<Utc>,
+    #[serde(rename = "fi")]
+    files: Vec<FileRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingRecord {
+    #[serde(rename = "sn")]
+    snapshot: Checksum,
+    #[serde(rename = "di")]
+    digest: Checksum,
+    #[serde(rename = "bu")]
+    bucket: String,
+    #[serde(rename = "ob")]
+    object: String,
+}
+
+///
+/// Encode the upload plan into a CBOR-formatted byte vector.
+///
+pub fn encode_plan(plan: &UploadPlan) -> Result<Vec<u8>, Error> {
+    let record = PlanRecord {
+        snapshot: plan.snapshot.clone(),
+        created: plan.created,
+        files: plan
+            .files
+            .iter()
+            .map(|f| FileRecord {
+                digest: f.digest.clone(),
+                path: f.path.clone(),
+                length: f.length,
+                modified: f.modified,
+                chunks: f
+                    .chunks
+                    .iter()
+                    .map(|c| ChunkRecord {
+                        digest: c.digest.clone(),
+                        offset: c.offset,
+                        length: c.length,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the upload plan from the CBOR-formatted bytes, with the path of
+/// each file set in each of its chunks.
+///
+pub fn decode_plan(encoded: &[u8]) -> Result<UploadPlan, Error> {
+    let record: PlanRecord = serde_cbor::from_slice(encoded)?;
+    Ok(UploadPlan {
+        snapshot: record.snapshot,
+        created: record.created,
+        files: record
+            .files
+            .into_iter()
+            .map(|f| PlannedFile {
+                chunks: f
+                    .chunks
+                    .into_iter()
+                    .map(|c| Chunk::new(c.digest, c.offset, c.length).filepath(&f.path))
+                    .collect(),
+                digest: f.digest,
+                path: f.path,
+                length: f.length,
+                modified: f.modified,
+            })
+            .collect(),
+    })
+}
+
+///
+/// Encode the pending upload into a CBOR-formatted byte vector.
+///
+pub fn encode_pending(pending: &PendingUpload) -> Result<Vec<u8>, Error> {
+    let record = PendingRecord {
+        snapshot: pending.snapshot.clone(),
+        digest: pending.digest.clone(),
+        bucket: pending.bucket.clone(),
+        object: pending.object.clone(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the pending upload from the CBOR-formatted bytes.
+///
+pub fn decode_pending(encoded: &[u8]) -> Result<PendingUpload, Error> {
+    let record: PendingRecord = serde_cbor::from_slice(encoded)?;
+    Ok(PendingUpload {
+        snapshot: record.snapshot,
+        digest: record.digest,
+        bucket: record.bucket,
+        object: record.object,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_plan_round_trip() -> Result<(), Error> {
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let file1 = Checksum::BLAKE3(
+            "261930e84e14c240210ae8c459acc4bb85dd52f1b91c868f2106dbc1ceb3acca".to_owned(),
+        );
+        let chunk1 = Checksum::BLAKE3(
+            "1dd8ae20bf4fc1ed0ac58c4b2e45ec6a96ea1b94fb3b2bf9bf7e3ee0ad3ba6d4".to_owned(),
+        );
+        let chunk2 = Checksum::BLAKE3(
+            "5f3e3b7a1d2e6f0a9c8b7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c9d8e7f6a".to_owned(),
+        );
+        let path = Path::new("/home/planet/photos/IMG_0001.jpg");
+        let mut plan = UploadPlan::new(snapshot.clone());
+        plan.files.push(PlannedFile {
+            digest: file1.clone(),
+            path: path.to_path_buf(),
+            length: 66560,
+            modified: Some(Utc::now()),
+            chunks: vec![
+                Chunk::new(chunk1.clone(), 0, 65536).filepath(path),
+                Chunk::new(chunk2.clone(), 65536, 1024).filepath(path),
+            ],
+        });
+        let encoded = encode_plan(&plan)?;
+        let actual = decode_plan(&encoded)?;
+        assert_eq!(actual.snapshot, snapshot);
+        assert_eq!(actual.created, plan.created);
+        assert_eq!(actual.files.len(), 1);
+        assert_eq!(actual.files[0].digest, file1);
+        assert_eq!(actual.files[0].path, path);
+        assert_eq!(actual.files[0].length, 66560);
+        assert_eq!(actual.files[0].modified, plan.files[0].modified);
+        let chunks = &actual.files[0].chunks;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].digest, chunk2);
+        assert_eq!(chunks[1].offset, 65536);
+        assert_eq!(chunks[1].length, 1024);
+        assert_eq!(chunks[1].filepath.as_deref(), Some(path));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_round_trip() -> Result<(), Error> {
+        let pending = PendingUpload {
+            snapshot: Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned()),
+            digest: Checksum::BLAKE3(
+                "261930e84e14c240210ae8c459acc4bb85dd52f1b91c868f2106dbc1ceb3acca".to_owned(),
+            ),
+            bucket: "bucket1".to_owned(),
+            object: "object1".to_owned(),
+        };
+        let encoded = encode_pending(&pending)?;
+        let actual = decode_pending(&encoded)?;
+        assert_eq!(actual, pending);
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use super::{ChunkDef, DatasetDef, FileDef, PackDef, SnapshotDef, StoreDef};
+use crate::domain::entities::{
+    Checksum, Chunk, Dataset, File, Pack, ReplicaBatch, Snapshot, Store, Tree,
+};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+//
+// The identifiers and digests of the entities are normally not serialized
+// since they form the database key. For replication the key must travel
+// alongside the value, hence these wrapper types.
+//
+
+#[derive(Serialize, Deserialize)]
+struct KeyedStore {
+    #[serde(rename = "k")]
+    key: String,
+    #[serde(rename = "v", with = "StoreDef")]
+    value: Store,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedDataset {
+    #[serde(rename = "k")]
+    key: String,
+    #[serde(rename = "v", with = "DatasetDef")]
+    value: Dataset,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedSnapshot {
+    #[serde(rename = "k")]
+    key: Checksum,
+    #[serde(rename = "v", with = "SnapshotDef")]
+    value: Snapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedTree {
+    #[serde(rename = "k")]
+    key: Checksum,
+    #[serde(rename = "v")]
+    value: Tree,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedFile {
+    #[serde(rename = "k")]
+    key: Checksum,
+    #[serde(rename = "v", with = "FileDef")]
+    value: File,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedChunk {
+    #[serde(rename = "k")]
+    key: Checksum,
+    #[serde(rename = "v", with = "ChunkDef")]
+    value: Chunk,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedPack {
+    #[serde(rename = "k")]
+    key: Checksum,
+    #[serde(rename = "v", with = "PackDef")]
+    value: Pack,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BatchRecord {
+    #[serde(rename = "ds")]
+    dataset: KeyedDataset,
+    #[serde(rename = "st")]
+    stores: Vec<KeyedStore>,
+    #[serde(rename = "sn")]
+    snapshot: KeyedSnapshot,
+    #[serde(rename = "tr")]
+    trees: Vec<KeyedTree>,
+    #[serde(rename = "fi")]
+    files: Vec<KeyedFile>,
+    #[serde(rename = "ch")]
+    chunks: Vec<KeyedChunk>,
+    #[serde(rename = "pa")]
+    packs: Vec<KeyedPack>,
+    #[serde(rename = "xa")]
+    xattrs: Vec<(Checksum, Vec<u8>)>,
+}
+
+///
+/// Encode the replica batch into a CBOR-formatted byte vector.
+///
+pub fn encode_batch(batch: &ReplicaBatch) -> Result<Vec<u8>, Error> {
+    let record = BatchRecord {
+        dataset: KeyedDataset {
+            key: batch.dataset.id.clone(),
+            value: batch.dataset.clone(),
+        },
+        stores: batch
+            .stores
+            .iter()
+            .map(|s| KeyedStore {
+                key: s.id.clone(),
+                value: s.clone(),
+            })
+            .collect(),
+        snapshot: KeyedSnapshot {
+            key: batch.snapshot.digest.clone(),
+            value: batch.snapshot.clone(),
+        },
+        trees: batch
+            .trees
+            .iter()
+            .map(|t| KeyedTree {
+                key: t.digest.clone(),
+                value: t.clone(),
+            })
+            .collect(),
+        files: batch
+            .files
+            .iter()
+            .map(|f| KeyedFile {
+                key: f.digest.clone(),
+                value: f.clone(),
+            })
+            .collect(),
+        chunks: batch
+            .chunks
+            .iter()
+            .map(|c| KeyedChunk {
+                key: c.digest.clone(),
+                value: c.clone(),
+            })
+            .collect(),
+        packs: batch
+            .packs
+            .iter()
+            .map(|p| KeyedPack {
+                key: p.digest.clone(),
+                value: p.clone(),
+            })
+            .collect(),
+        xattrs: batch.xattrs.clone(),
+    };
+    let encoded: Vec<u8> = serde_cbor::to_vec(&record)?;
+    Ok(encoded)
+}
+
+///
+/// Decode the replica batch from the CBOR-formatted bytes, restoring the
+/// identifiers and digests of each of the entities.
+///
+pub fn decode_batch(encoded: &[u8]) -> Result<ReplicaBatch, Error> {
+    let record: BatchRecord = serde_cbor::from_slice(encoded)?;
+    let mut dataset = record.dataset.value;
+    dataset.id = record.dataset.key;
+    let mut snapshot = record.snapshot.value;
+    snapshot.digest = record.snapshot.key;
+    Ok(ReplicaBatch {
+        dataset,
+        stores: record
+            .stores
+            .into_iter()
+            .map(|mut s| {
+                s.value.id = s.key;
+                s.value
+            })
+            .collect(),
+        snapshot,
+        trees: record
+            .trees
+            .into_iter()
+            .map(|mut t| {
+                t.value.digest = t.key;
+                t.value
+            })
+            .collect(),
+        files: record
+            .files
+            .into_iter()
+            .map(|mut f| {
+                f.value.digest = f.key;
+                f.value
+            })
+            .collect(),
+        chunks: record
+            .chunks
+            .into_iter()
+            .map(|mut c| {
+                c.value.digest = c.key;
+                c.value
+            })
+            .collect(),
+        packs: record
+            .packs
+            .into_iter()
+            .map(|mut p| {
+                p.value.digest = p.key;
+                p.value
+            })
+            .collect(),
+        xattrs: record.xattrs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{FileCounts, PackLocation, StoreType, TreeEntry, TreeReference};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_batch_round_trip() -> Result<(), Error> {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".into();
+        let store = Store {
+            id: "localstore".into(),
+            store_type: StoreType::LOCAL,
+            label: "my local".into(),
+            properties: HashMap::new(),
+        };
+        let file_digest = Checksum::BLAKE3("1111".into());
+        let entry = TreeEntry::new(
+            Path::new("lorem-ipsum.txt"),
+            TreeReference::FILE(file_digest.clone()),
+        );
+        let tree = Tree::new(vec![entry], 1);
+        let snapshot = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        let pack_digest = Checksum::SHA1("2222".into());
+        let mut chunk = Chunk::new(Checksum::BLAKE3("3333".into()), 0, 100);
+        chunk.packfile = Some(pack_digest.clone());
+        let file = File::new(file_digest.clone(), 100, vec![(0, chunk.digest.clone())]);
+        let location = PackLocation::new("localstore", "bucket1", "object1");
+        let pack = Pack::new(pack_digest.clone(), vec![location]);
+        let batch = ReplicaBatch {
+            dataset,
+            stores: vec![store],
+            snapshot: snapshot.clone(),
+            trees: vec![tree.clone()],
+            files: vec![file],
+            chunks: vec![chunk.clone()],
+            packs: vec![pack],
+            xattrs: vec![(Checksum::SHA1("4444".into()), vec![1, 2, 3])],
+        };
+        // act
+        let encoded = encode_batch(&batch)?;
+        let actual = decode_batch(&encoded)?;
+        // assert
+        assert_eq!(actual.dataset.id, "cafebabe");
+        assert_eq!(actual.stores.len(), 1);
+        assert_eq!(actual.stores[0].id, "localstore");
+        assert_eq!(actual.snapshot.digest, snapshot.digest);
+        assert_eq!(actual.trees[0].digest, tree.digest);
+        assert_eq!(actual.files[0].digest, file_digest);
+        assert_eq!(actual.chunks[0].digest, chunk.digest);
+        assert_eq!(actual.chunks[0].packfile, Some(pack_digest.clone()));
+        assert_eq!(actual.packs[0].digest, pack_digest);
+        assert_eq!(actual.packs[0].locations[0].bucket, "bucket1");
+        assert_eq!(actual.xattrs[0].1, vec![1, 2, 3]);
+        Ok(())
+    }
+}
@@ -11,10 +11,47 @@
 // 6. Copy the output here
 // 7. Remove the redundant type definitions
 //
-use crate::domain::entities::{Checksum, Tree, TreeEntry, TreeReference};
+use crate::domain::entities::{Checksum, RawName, Tree, TreeEntry, TreeReference};
 use chrono::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 
+//
+// The raw name of a tree entry was added later, and is written only when
+// present, such that existing tree records remain unchanged.
+//
+
+#[derive(Serialize, Deserialize)]
+enum RawNameModel {
+    #[serde(rename = "u")]
+    Utf8(String),
+    #[serde(rename = "b")]
+    Bytes(Vec<u8>),
+    #[serde(rename = "w")]
+    Wide(Vec<u16>),
+}
+
+impl Serialize for RawName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let model = match self {
+            RawName::UTF8(value) => RawNameModel::Utf8(value.clone()),
+            RawName::BYTES(value) => RawNameModel::Bytes(value.clone()),
+            RawName::WIDE(value) => RawNameModel::Wide(value.clone()),
+        };
+        model.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match RawNameModel::deserialize(deserializer)? {
+            RawNameModel::Utf8(value) => RawName::UTF8(value),
+            RawNameModel::Bytes(value) => RawName::BYTES(value),
+            RawNameModel::Wide(value) => RawName::WIDE(value),
+        })
+    }
+}
+
 #[doc(hidden)]
 #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
 const _: () = {
@@ -230,7 +267,22 @@ const _: () = {
             let mut __serde_state = match _serde::Serializer::serialize_struct(
                 __serializer,
                 "TreeEntry",
-                false as usize + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1,
+                false as usize
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + 1
+                    + if _serde::__private::Option::is_none(&self.raw_name) {
+                        0
+                    } else {
+                        1
+                    },
             ) {
                 _serde::__private::Ok(__val) => __val,
                 _serde::__private::Err(__err) => {
@@ -331,6 +383,25 @@ const _: () = {
                     return _serde::__private::Err(__err);
                 }
             };
+            if !_serde::__private::Option::is_none(&self.raw_name) {
+                match _serde::ser::SerializeStruct::serialize_field(
+                    &mut __serde_state,
+                    "rn",
+                    &self.raw_name,
+                ) {
+                    _serde::__private::Ok(__val) => __val,
+                    _serde::__private::Err(__err) => {
+                        return _serde::__private::Err(__err);
+                    }
+                };
+            } else {
+                match _serde::ser::SerializeStruct::skip_field(&mut __serde_state, "rn") {
+                    _serde::__private::Ok(__val) => __val,
+                    _serde::__private::Err(__err) => {
+                        return _serde::__private::Err(__err);
+                    }
+                };
+            }
             _serde::ser::SerializeStruct::end(__serde_state)
         }
     }
@@ -358,6 +429,7 @@ const _: () = {
                 __field7,
                 __field8,
                 __field9,
+                __field10,
                 __ignore,
             }
             struct __FieldVisitor;
@@ -384,6 +456,7 @@ const _: () = {
                         7u64 => _serde::__private::Ok(__Field::__field7),
                         8u64 => _serde::__private::Ok(__Field::__field8),
                         9u64 => _serde::__private::Ok(__Field::__field9),
+                        10u64 => _serde::__private::Ok(__Field::__field10),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -405,6 +478,7 @@ const _: () = {
                         "mt" => _serde::__private::Ok(__Field::__field7),
                         "tr" => _serde::__private::Ok(__Field::__field8),
                         "xa" => _serde::__private::Ok(__Field::__field9),
+                        "rn" => _serde::__private::Ok(__Field::__field10),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -426,6 +500,7 @@ const _: () = {
                         b"mt" => _serde::__private::Ok(__Field::__field7),
                         b"tr" => _serde::__private::Ok(__Field::__field8),
                         b"xa" => _serde::__private::Ok(__Field::__field9),
+                        b"rn" => _serde::__private::Ok(__Field::__field10),
                         _ => _serde::__private::Ok(__Field::__ignore),
                     }
                 }
@@ -621,8 +696,20 @@ const _: () = {
                             ));
                         }
                     };
+                    let __field10 = match match _serde::de::SeqAccess::next_element::<Option<RawName>>(
+                        &mut __seq,
+                    ) {
+                        _serde::__private::Ok(__val) => __val,
+                        _serde::__private::Err(__err) => {
+                            return _serde::__private::Err(__err);
+                        }
+                    } {
+                        _serde::__private::Some(__value) => __value,
+                        _serde::__private::None => _serde::__private::Default::default(),
+                    };
                     _serde::__private::Ok(TreeEntry {
                         name: __field0,
+                        raw_name: __field10,
                         mode: __field1,
                         uid: __field2,
                         user: __field3,
@@ -661,6 +748,8 @@ const _: () = {
                         _serde::__private::None;
                     let mut __field9: _serde::__private::Option<HashMap<String, Checksum>> =
                         _serde::__private::None;
+                    let mut __field10: _serde::__private::Option<Option<RawName>> =
+                        _serde::__private::None;
                     while let _serde::__private::Some(__key) =
                         match _serde::de::MapAccess::next_key::<__Field>(&mut __map) {
                             _serde::__private::Ok(__val) => __val,
@@ -839,6 +928,23 @@ const _: () = {
                                     },
                                 );
                             }
+                            __Field::__field10 => {
+                                if _serde::__private::Option::is_some(&__field10) {
+                                    return _serde::__private::Err(
+                                        <__A::Error as _serde::de::Error>::duplicate_field("rn"),
+                                    );
+                                }
+                                __field10 = _serde::__private::Some(
+                                    match _serde::de::MapAccess::next_value::<Option<RawName>>(
+                                        &mut __map,
+                                    ) {
+                                        _serde::__private::Ok(__val) => __val,
+                                        _serde::__private::Err(__err) => {
+                                            return _serde::__private::Err(__err);
+                                        }
+                                    },
+                                );
+                            }
                             _ => {
                                 let _ = match _serde::de::MapAccess::next_value::<
                                     _serde::de::IgnoredAny,
@@ -952,8 +1058,13 @@ const _: () = {
                             }
                         },
                     };
+                    let __field10 = match __field10 {
+                        _serde::__private::Some(__field10) => __field10,
+                        _serde::__private::None => _serde::__private::Default::default(),
+                    };
                     _serde::__private::Ok(TreeEntry {
                         name: __field0,
+                        raw_name: __field10,
                         mode: __field1,
                         uid: __field2,
                         user: __field3,
@@ -966,7 +1077,9 @@ const _: () = {
                     })
                 }
             }
-            const FIELDS: &[&str] = &["nm", "mo", "ui", "us", "gi", "gr", "ct", "mt", "tr", "xa"];
+            const FIELDS: &[&str] = &[
+                "nm", "mo", "ui", "us", "gi", "gr", "ct", "mt", "tr", "xa", "rn",
+            ];
             _serde::Deserializer::deserialize_struct(
                 __deserializer,
                 "TreeEntry",
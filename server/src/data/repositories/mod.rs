@@ -1,21 +1,28 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
+use crate::data::models::catalog::{decode_catalog, encode_catalog};
+use crate::data::models::emergency::{decode_emergency, encode_emergency};
 use crate::data::sources::{
-    EntityDataSource, PackDataSource, PackSourceBuilder, PackSourceBuilderImpl,
+    EntityDataSource, EntityDataSourceImpl, PackDataSource, PackSourceBuilder,
+    PackSourceBuilderImpl,
 };
 use crate::domain::entities::{
-    Checksum, Chunk, Configuration, Dataset, File, Pack, PackLocation, RecordCounts, Snapshot,
-    Store, Tree,
+    BandwidthUsage, Catalog, ChainEntry, Checksum, Chunk, Configuration, Dataset, DedupStats,
+    Device, EmergencyIndex, Event, File, MaintenanceResult, Message, MessageCode, Migration, Pack,
+    PackLocation, RecordCounts, RetrievalFailures, Snapshot, Store, StoreTestStep, StoreUsage,
+    TrashedDataset, Tree, Verification, Webhook,
 };
-use crate::domain::repositories::{PackRepository, RecordRepository};
+use crate::domain::managers::checkpoint::TransferCheckpoints;
+use crate::domain::repositories::{IntegrityError, PackRepository, RecordRepository};
 use anyhow::{anyhow, Context, Error, Result};
 use lazy_static::lazy_static;
 use log::{error, info, warn};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use store_core::CollisionError;
+use std::time::{Duration, Instant};
+use store_core::{Checkpoint, CollisionError, LifecycleRule, TimeoutError};
 
 lazy_static! {
     // Name that will be returned by get_bucket_name(), unless of course it is
@@ -24,6 +31,38 @@ lazy_static! {
     // Number of times get_bucket_name() has been called and returned the same
     // bucket name. When the value is zero, a new name is generated.
     static ref NAME_COUNT: Mutex<usize> = Mutex::new(0);
+    // Bucket and object listings for each store, keyed by store identifier,
+    // shared by all pack repositories so that consecutive maintenance jobs can
+    // avoid listing the same store repeatedly.
+    static ref LISTINGS: Mutex<HashMap<String, StoreListing>> = Mutex::new(HashMap::new());
+    // Held while updating the bandwidth usage records, which may be changed by
+    // several pack repositories at once.
+    static ref BANDWIDTH: Mutex<()> = Mutex::new(());
+    // Held while updating the store usage records, for the same reason.
+    static ref USAGE: Mutex<()> = Mutex::new(());
+    // Pack retrieval failures of each store, keyed by store identifier.
+    static ref RETRIEVAL_FAILURES: Mutex<HashMap<String, RetrievalFailures>> =
+        Mutex::new(HashMap::new());
+}
+
+// Cached bucket and object listings for a single store.
+struct StoreListing {
+    // When the listing was first populated.
+    created: Instant,
+    // Names of the buckets in the store, if they have been listed.
+    buckets: Option<Vec<String>>,
+    // Names of the objects within those buckets that have been listed.
+    objects: HashMap<String, Vec<String>>,
+}
+
+impl StoreListing {
+    fn new() -> Self {
+        Self {
+            created: Instant::now(),
+            buckets: None,
+            objects: HashMap::new(),
+        }
+    }
 }
 
 // Use an `Arc` to hold the data source to make cloning easy for the caller. If
@@ -38,6 +77,27 @@ impl RecordRepositoryImpl {
     pub fn new(datasource: Arc<dyn EntityDataSource>) -> Self {
         Self { datasource }
     }
+
+    /// Open a copy of the database contained in the given archive, as
+    /// produced by `create_backup()`, for the purpose of inspecting it.
+    ///
+    /// The archive is extracted and the database restored within `workdir`,
+    /// leaving the live database untouched.
+    pub fn open_archive(archive: &Path, password: &str, workdir: &Path) -> Result<Self, Error> {
+        let backup_path = workdir.join("backup");
+        extract_archive(archive, &backup_path, password)
+            .with_context(|| format!("extracting database archive {}", archive.display()))?;
+        let db_path = workdir.join("database");
+        let source = EntityDataSourceImpl::from_backup(&backup_path, &db_path)?;
+        Ok(Self::new(Arc::new(source)))
+    }
+
+    // Builder for pack sources that record upload checkpoints in this database.
+    fn source_builder(&self) -> PackSourceBuilderImpl {
+        let repo: Arc<dyn RecordRepository> =
+            Arc::new(RecordRepositoryImpl::new(self.datasource.clone()));
+        PackSourceBuilderImpl::with_checkpoints(Arc::new(TransferCheckpoints::new(repo)))
+    }
 }
 
 impl RecordRepository for RecordRepositoryImpl {
@@ -50,6 +110,22 @@ impl RecordRepository for RecordRepositoryImpl {
         Ok(config)
     }
 
+    fn get_schema_version(&self) -> Result<Option<u32>, Error> {
+        self.datasource.get_schema_version()
+    }
+
+    fn put_schema_version(&self, version: u32) -> Result<(), Error> {
+        self.datasource.put_schema_version(version)
+    }
+
+    fn put_migration(&self, migration: &Migration) -> Result<(), Error> {
+        self.datasource.put_migration(migration)
+    }
+
+    fn get_migrations(&self) -> Result<Vec<Migration>, Error> {
+        self.datasource.get_migrations()
+    }
+
     fn get_excludes(&self) -> Vec<PathBuf> {
         let path = self.datasource.get_db_path();
         vec![path]
@@ -87,6 +163,21 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.get_chunk(digest)
     }
 
+    fn put_chunk(&self, chunk: &Chunk) -> Result<(), Error> {
+        self.datasource.put_chunk(chunk)
+    }
+
+    fn delete_chunk(&self, digest: &Checksum) -> Result<(), Error> {
+        self.datasource.delete_chunk(digest)
+    }
+
+    fn visit_chunks(
+        &self,
+        visitor: &mut dyn FnMut(Chunk) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        self.datasource.visit_chunks(visitor)
+    }
+
     fn insert_pack(&self, pack: &Pack) -> Result<(), Error> {
         self.datasource.insert_pack(pack)
     }
@@ -99,6 +190,10 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.get_pack(digest)
     }
 
+    fn delete_pack(&self, digest: &Checksum) -> Result<(), Error> {
+        self.datasource.delete_pack(digest)
+    }
+
     fn get_packs(&self, store_id: &str) -> Result<Vec<Pack>, Error> {
         self.datasource.get_packs(store_id)
     }
@@ -107,6 +202,13 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.get_all_packs()
     }
 
+    fn visit_packs(
+        &self,
+        visitor: &mut dyn FnMut(Pack) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        self.datasource.visit_packs(visitor)
+    }
+
     fn insert_database(&self, pack: &Pack) -> Result<(), Error> {
         self.datasource.insert_database(pack)
     }
@@ -135,6 +237,21 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.get_file(digest)
     }
 
+    fn put_file(&self, file: &File) -> Result<(), Error> {
+        self.datasource.put_file(file)
+    }
+
+    fn delete_file(&self, digest: &Checksum) -> Result<(), Error> {
+        self.datasource.delete_file(digest)
+    }
+
+    fn visit_files(
+        &self,
+        visitor: &mut dyn FnMut(File) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        self.datasource.visit_files(visitor)
+    }
+
     fn insert_tree(&self, tree: &Tree) -> Result<(), Error> {
         self.datasource.insert_tree(tree)
     }
@@ -145,7 +262,7 @@ impl RecordRepository for RecordRepositoryImpl {
 
     fn put_store(&self, store: &Store) -> Result<(), Error> {
         // validate the store configuration
-        let builder = PackSourceBuilderImpl {};
+        let builder = PackSourceBuilderImpl::default();
         builder.build_source(store)?;
         self.datasource.put_store(store)
     }
@@ -162,6 +279,37 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.delete_store(id)
     }
 
+    fn get_bandwidth_usage(&self, store_id: &str) -> Result<BandwidthUsage, Error> {
+        let month = BandwidthUsage::month_of(chrono::Utc::now());
+        let usage = self.datasource.get_bandwidth(store_id, &month)?;
+        Ok(usage.unwrap_or_else(|| BandwidthUsage::new(month)))
+    }
+
+    fn get_store_usage(&self, store_id: &str) -> Result<StoreUsage, Error> {
+        let usage = self.datasource.get_store_usage(store_id)?;
+        Ok(usage.unwrap_or_default())
+    }
+
+    fn put_checkpoint(
+        &self,
+        location: &PackLocation,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), Error> {
+        self.datasource.put_checkpoint(location, checkpoint)
+    }
+
+    fn get_checkpoint(&self, location: &PackLocation) -> Result<Option<Checkpoint>, Error> {
+        self.datasource.get_checkpoint(location)
+    }
+
+    fn delete_checkpoint(&self, location: &PackLocation) -> Result<(), Error> {
+        self.datasource.delete_checkpoint(location)
+    }
+
+    fn get_checkpoints(&self) -> Result<Vec<(PackLocation, Checkpoint)>, Error> {
+        self.datasource.get_checkpoints()
+    }
+
     fn load_dataset_stores(&self, dataset: &Dataset) -> Result<Box<dyn PackRepository>, Error> {
         let stores: Vec<Store> = dataset
             .stores
@@ -176,17 +324,19 @@ impl RecordRepository for RecordRepositoryImpl {
                 dataset.id
             )));
         }
-        let store_builder = Box::new(PackSourceBuilderImpl {});
-        let packs: Box<dyn PackRepository> =
-            Box::new(PackRepositoryImpl::new(stores, store_builder)?);
+        let store_builder = Box::new(self.source_builder());
+        let packs: Box<dyn PackRepository> = Box::new(
+            PackRepositoryImpl::new(stores, store_builder)?.accounting(self.datasource.clone()),
+        );
         Ok(packs)
     }
 
     fn build_pack_repo(&self, store: &Store) -> Result<Box<dyn PackRepository>, Error> {
         let stores: Vec<Store> = vec![store.to_owned()];
-        let store_builder = Box::new(PackSourceBuilderImpl {});
-        let pack: Box<dyn PackRepository> =
-            Box::new(PackRepositoryImpl::new(stores, store_builder)?);
+        let store_builder = Box::new(self.source_builder());
+        let pack: Box<dyn PackRepository> = Box::new(
+            PackRepositoryImpl::new(stores, store_builder)?.accounting(self.datasource.clone()),
+        );
         Ok(pack)
     }
 
@@ -206,6 +356,115 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.delete_dataset(id)
     }
 
+    fn put_trashed_dataset(&self, trashed: &TrashedDataset) -> Result<(), Error> {
+        self.datasource.put_trashed_dataset(trashed)
+    }
+
+    fn get_trashed_datasets(&self) -> Result<Vec<TrashedDataset>, Error> {
+        self.datasource.get_trashed_datasets()
+    }
+
+    fn delete_trashed_dataset(&self, id: &str) -> Result<(), Error> {
+        self.datasource.delete_trashed_dataset(id)
+    }
+
+    fn put_device(&self, device: &Device) -> Result<(), Error> {
+        self.datasource.put_device(device)
+    }
+
+    fn get_devices(&self) -> Result<Vec<Device>, Error> {
+        self.datasource.get_devices()
+    }
+
+    fn delete_device(&self, id: &str) -> Result<(), Error> {
+        self.datasource.delete_device(id)
+    }
+
+    fn put_webhook(&self, webhook: &Webhook) -> Result<(), Error> {
+        self.datasource.put_webhook(webhook)
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        self.datasource.get_webhooks()
+    }
+
+    fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        self.datasource.delete_webhook(id)
+    }
+
+    fn put_event(&self, event: &Event) -> Result<(), Error> {
+        self.datasource.put_event(event)
+    }
+
+    fn get_events(&self) -> Result<Vec<Event>, Error> {
+        let mut events = self.datasource.get_events()?;
+        // identifiers generated within the same millisecond are not ordered
+        events.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(events)
+    }
+
+    fn delete_event(&self, id: &str) -> Result<(), Error> {
+        self.datasource.delete_event(id)
+    }
+
+    fn put_verification(&self, verification: &Verification) -> Result<(), Error> {
+        self.datasource.put_verification(verification)
+    }
+
+    fn get_verifications(&self, dataset: &str) -> Result<Vec<Verification>, Error> {
+        let mut verifications = self.datasource.get_verifications(dataset)?;
+        verifications.sort_by(|a, b| a.started.cmp(&b.started).then_with(|| a.id.cmp(&b.id)));
+        Ok(verifications)
+    }
+
+    fn delete_verification(&self, dataset: &str, id: &str) -> Result<(), Error> {
+        self.datasource.delete_verification(dataset, id)
+    }
+
+    fn put_task_run(&self, run: &MaintenanceResult) -> Result<(), Error> {
+        self.datasource.put_task_run(run)
+    }
+
+    fn get_task_runs(&self, dataset: &str) -> Result<Vec<MaintenanceResult>, Error> {
+        let mut runs = self.datasource.get_task_runs(dataset)?;
+        runs.sort_by_key(|r| r.task.to_string());
+        Ok(runs)
+    }
+
+    fn put_chain_entry(&self, entry: &ChainEntry) -> Result<(), Error> {
+        self.datasource.put_chain_entry(entry)
+    }
+
+    fn get_chain_entries(&self, store_id: &str) -> Result<Vec<ChainEntry>, Error> {
+        let mut entries = self.datasource.get_chain_entries(store_id)?;
+        entries.sort_by_key(|e| e.sequence);
+        Ok(entries)
+    }
+
+    fn put_chain_location(&self, location: &PackLocation) -> Result<(), Error> {
+        self.datasource.put_chain_location(location)
+    }
+
+    fn get_chain_locations(&self) -> Result<Vec<PackLocation>, Error> {
+        self.datasource.get_chain_locations()
+    }
+
+    fn put_catalog(&self, store_id: &str, catalog: &Catalog) -> Result<(), Error> {
+        self.datasource.put_catalog(store_id, catalog)
+    }
+
+    fn get_catalog(&self, store_id: &str) -> Result<Option<Catalog>, Error> {
+        self.datasource.get_catalog(store_id)
+    }
+
+    fn put_dedup_stats(&self, stats: &DedupStats) -> Result<(), Error> {
+        self.datasource.put_dedup_stats(stats)
+    }
+
+    fn get_dedup_stats(&self, dataset: &str) -> Result<Option<DedupStats>, Error> {
+        self.datasource.get_dedup_stats(dataset)
+    }
+
     fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Error> {
         self.datasource.put_snapshot(snapshot)
     }
@@ -214,6 +473,10 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.get_snapshot(digest)
     }
 
+    fn delete_snapshot(&self, digest: &Checksum) -> Result<(), Error> {
+        self.datasource.delete_snapshot(digest)
+    }
+
     fn create_backup(&self, password: &str) -> Result<tempfile::TempPath, Error> {
         let backup_path = self.datasource.create_backup(None)?;
         let file = tempfile::NamedTempFile::new()?;
@@ -229,9 +492,60 @@ impl RecordRepository for RecordRepositoryImpl {
         self.datasource.restore_from_backup(Some(temppath))
     }
 
+    fn create_backup_dir(&self) -> Result<PathBuf, Error> {
+        self.datasource.create_backup(None)
+    }
+
+    fn restore_from_dir(&self, path: &Path) -> Result<(), Error> {
+        self.datasource
+            .restore_from_backup(Some(path.to_path_buf()))
+    }
+
+    fn pack_catalog(&self, catalog: &Catalog, password: &str) -> Result<tempfile::TempPath, Error> {
+        let tempdir = tempfile::tempdir()?;
+        let encoded = encode_catalog(catalog)?;
+        std::fs::write(tempdir.path().join(CATALOG_ENTRY), encoded)?;
+        let file = tempfile::NamedTempFile::new()?;
+        let path = file.into_temp_path();
+        create_archive(tempdir.path(), &path, password)?;
+        Ok(path)
+    }
+
+    fn unpack_catalog(&self, path: &Path, password: &str) -> Result<Catalog, Error> {
+        let tempdir = tempfile::tempdir()?;
+        extract_archive(path, tempdir.path(), password)?;
+        let encoded = std::fs::read(tempdir.path().join(CATALOG_ENTRY))?;
+        decode_catalog(&encoded)
+    }
+
+    fn pack_emergency(
+        &self,
+        index: &EmergencyIndex,
+        password: &str,
+    ) -> Result<tempfile::TempPath, Error> {
+        let tempdir = tempfile::tempdir()?;
+        let encoded = encode_emergency(index)?;
+        std::fs::write(tempdir.path().join(EMERGENCY_ENTRY), encoded)?;
+        let file = tempfile::NamedTempFile::new()?;
+        let path = file.into_temp_path();
+        create_archive(tempdir.path(), &path, password)?;
+        Ok(path)
+    }
+
+    fn unpack_emergency(&self, path: &Path, password: &str) -> Result<EmergencyIndex, Error> {
+        let tempdir = tempfile::tempdir()?;
+        extract_archive(path, tempdir.path(), password)?;
+        let encoded = std::fs::read(tempdir.path().join(EMERGENCY_ENTRY))?;
+        decode_emergency(&encoded)
+    }
+
     fn get_entity_counts(&self) -> Result<RecordCounts, Error> {
         self.datasource.get_entity_counts()
     }
+
+    fn compact_database(&self) -> Result<(), Error> {
+        self.datasource.compact_database()
+    }
 }
 
 ///
@@ -278,6 +592,8 @@ fn extract_archive(infile: &Path, outdir: &Path, password: &str) -> Result<(), E
 
 pub struct PackRepositoryImpl {
     sources: HashMap<Store, Box<dyn PackDataSource>>,
+    // Data source in which to record the bandwidth usage of each store.
+    datasource: Option<Arc<dyn EntityDataSource>>,
 }
 
 impl PackRepositoryImpl {
@@ -290,7 +606,87 @@ impl PackRepositoryImpl {
             let source = builder.build_source(&store)?;
             sources.insert(store, source);
         }
-        Ok(Self { sources })
+        Ok(Self {
+            sources,
+            datasource: None,
+        })
+    }
+
+    /// Record the number of bytes transferred to and from each store, as well
+    /// as the number and size of the objects in each store, and enforce the
+    /// monthly transfer cap and quota of the stores, if any.
+    pub fn accounting(mut self, datasource: Arc<dyn EntityDataSource>) -> Self {
+        self.datasource = Some(datasource);
+        self
+    }
+
+    // Return an error if the store has reached its monthly transfer cap.
+    fn check_cap(&self, store: &Store) -> Result<(), Error> {
+        if let (Some(datasource), Some(cap)) = (self.datasource.as_ref(), store.monthly_cap()) {
+            let month = BandwidthUsage::month_of(chrono::Utc::now());
+            if let Some(usage) = datasource.get_bandwidth(&store.id, &month)? {
+                if usage.total() >= cap {
+                    return Err(anyhow!(format!(
+                        "store {} ({}) has reached its monthly transfer cap",
+                        store.id, store.label
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Return false if adding the file to the store would exceed its quota.
+    fn check_quota(&self, store: &Store, path: &Path) -> Result<bool, Error> {
+        if let (Some(datasource), Some(quota)) = (self.datasource.as_ref(), store.quota()) {
+            let length = std::fs::metadata(path)?.len();
+            let usage = datasource.get_store_usage(&store.id)?.unwrap_or_default();
+            return Ok(!quota.would_exceed(&usage, length));
+        }
+        Ok(true)
+    }
+
+    // Apply the change to the usage record of the store, logging rather than
+    // returning any errors since the store operation itself succeeded.
+    fn record_usage<F>(&self, store_id: &str, update: F)
+    where
+        F: FnOnce(&mut StoreUsage),
+    {
+        if let Some(datasource) = self.datasource.as_ref() {
+            let _guard = USAGE.lock().unwrap();
+            let result = datasource.get_store_usage(store_id).and_then(|usage| {
+                let mut usage = usage.unwrap_or_default();
+                update(&mut usage);
+                datasource.put_store_usage(store_id, &usage)
+            });
+            if let Err(err) = result {
+                warn!("could not record usage for store {}: {}", store_id, err);
+            }
+        }
+    }
+
+    // Add the size of the file to the bandwidth usage of the store, logging
+    // rather than returning any errors since the transfer itself succeeded.
+    fn record_transfer(&self, store_id: &str, path: &Path, upload: bool) {
+        if let Some(datasource) = self.datasource.as_ref() {
+            let length = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            let _guard = BANDWIDTH.lock().unwrap();
+            let month = BandwidthUsage::month_of(chrono::Utc::now());
+            let result = datasource
+                .get_bandwidth(store_id, &month)
+                .and_then(|usage| {
+                    let mut usage = usage.unwrap_or_else(|| BandwidthUsage::new(&month));
+                    if upload {
+                        usage.uploaded += length;
+                    } else {
+                        usage.downloaded += length;
+                    }
+                    datasource.put_bandwidth(store_id, &usage)
+                });
+            if let Err(err) = result {
+                warn!("could not record bandwidth for store {}: {}", store_id, err);
+            }
+        }
     }
 
     // Use the old bucket name to generate a new one.
@@ -302,6 +698,64 @@ impl PackRepositoryImpl {
         name.clone()
     }
 
+    // Retrieve the pack from the most suitable store, trying the others in
+    // turn, and verifying the digest of the retrieved file, if given.
+    fn retrieve_best(
+        &self,
+        locations: &[PackLocation],
+        digest: Option<&Checksum>,
+        outfile: &Path,
+    ) -> Result<(), Error> {
+        // prefer a local store, then one that is not slow, then any store,
+        // otherwise keeping the order of the locations; each is tried once
+        let mut candidates: Vec<(u8, &Store, &dyn PackDataSource, &PackLocation)> = Vec::new();
+        for loc in locations.iter() {
+            for (store, source) in self.sources.iter() {
+                if loc.store == store.id {
+                    let rank = if source.is_local() {
+                        0
+                    } else if !source.is_slow() {
+                        1
+                    } else {
+                        2
+                    };
+                    candidates.push((rank, store, source.as_ref(), loc));
+                }
+            }
+        }
+        candidates.sort_by_key(|c| c.0);
+        let mut mismatch: Option<IntegrityError> = None;
+        for (_, store, source, loc) in candidates.into_iter() {
+            let result = source
+                .retrieve_pack(loc, outfile)
+                .and_then(|_| match digest {
+                    Some(digest) => verify_retrieved(&store.id, digest, outfile),
+                    None => Ok(()),
+                });
+            match result {
+                Ok(()) => {
+                    record_retrieval(&store.id, None);
+                    self.record_transfer(&store.id, outfile, false);
+                    return Ok(());
+                }
+                Err(err) => {
+                    warn!(
+                        "pack retrieval from {} failed, will try another source: {:?}",
+                        store.id, err
+                    );
+                    record_retrieval(&store.id, Some(&err));
+                    if let Ok(integrity) = err.downcast::<IntegrityError>() {
+                        mismatch = Some(integrity);
+                    }
+                }
+            }
+        }
+        if let Some(integrity) = mismatch {
+            return Err(Error::from(integrity));
+        }
+        Err(anyhow!("unable to retrieve pack file: {:?}", locations))
+    }
+
     // Try to store the pack file up to three times before giving up.
     fn store_pack_retry(
         &self,
@@ -328,12 +782,45 @@ impl PackRepositoryImpl {
                         if retries == 0 {
                             return Err(e);
                         }
-                        warn!("pack store failed, will retry: {:?}", e);
+                        if is_timeout(&e) {
+                            warn!("pack store timed out, will retry: {}", e);
+                        } else {
+                            warn!("pack store failed, will retry: {:?}", e);
+                        }
                     }
                 },
             }
         }
     }
+
+    // Store the file in the named bucket of every pack store, in the manner of
+    // the database archives, which are given no lifecycle rule when the bucket
+    // is created and thus remain in the standard storage class.
+    fn store_warm(
+        &self,
+        infile: &Path,
+        bucket: &str,
+        object: &str,
+        kind: &str,
+    ) -> Result<Vec<PackLocation>, Error> {
+        let length = std::fs::metadata(infile).map(|m| m.len()).unwrap_or(0);
+        let mut results: Vec<PackLocation> = Vec::new();
+        for (store, source) in self.sources.iter() {
+            let ctx = format!(
+                "{} store {} ({}) failed for {}/{}",
+                kind, store.id, store.label, bucket, object
+            );
+            let loc = store_database_retry(source, infile, bucket, object).context(ctx)?;
+            self.invalidate_listings(&store.id);
+            self.record_transfer(&store.id, infile, true);
+            self.record_usage(&store.id, |usage| {
+                usage.objects += 1;
+                usage.bytes += length;
+            });
+            results.push(loc)
+        }
+        Ok(results)
+    }
 }
 
 impl PackRepository for PackRepositoryImpl {
@@ -357,70 +844,121 @@ impl PackRepository for PackRepositoryImpl {
         bucket: &str,
         object: &str,
     ) -> Result<Vec<PackLocation>, Error> {
+        let length = std::fs::metadata(packfile).map(|m| m.len()).unwrap_or(0);
         let mut results: Vec<PackLocation> = Vec::new();
+        let mut exceeded: Vec<String> = Vec::new();
         for (store, source) in self.sources.iter() {
             let ctx = format!(
                 "pack store {} ({}) failed for {}/{}",
                 store.id, store.label, bucket, object
             );
+            self.check_cap(store)?;
+            // a full store is left out while the others continue
+            if !self.check_quota(store, packfile)? {
+                warn!(
+                    "store {} ({}) has reached its quota, skipping {}/{}",
+                    store.id, store.label, bucket, object
+                );
+                exceeded.push(store.id.clone());
+                continue;
+            }
             let loc = self
                 .store_pack_retry(source, packfile, bucket, object)
                 .context(ctx)?;
+            self.invalidate_listings(&store.id);
+            self.record_transfer(&store.id, packfile, true);
+            self.record_usage(&store.id, |usage| {
+                usage.objects += 1;
+                usage.bytes += length;
+            });
             results.push(loc)
         }
+        if results.is_empty() && !exceeded.is_empty() {
+            return Err(anyhow!(format!(
+                "every store has reached its quota: {}",
+                exceeded.join(", ")
+            )));
+        }
         Ok(results)
     }
 
-    fn retrieve_pack(&self, locations: &[PackLocation], outfile: &Path) -> Result<(), Error> {
-        // find a local store, if available
-        for loc in locations.iter() {
-            for (store, source) in self.sources.iter() {
-                if loc.store == store.id && source.is_local() {
-                    let result = source.retrieve_pack(loc, outfile);
-                    if result.is_ok() {
-                        return result;
-                    }
-                    warn!(
-                        "pack retrieval failed, will try another source: {:?}",
-                        result
-                    );
-                }
+    fn retrieve_pack(
+        &self,
+        locations: &[PackLocation],
+        digest: &Checksum,
+        outfile: &Path,
+    ) -> Result<(), Error> {
+        self.retrieve_best(locations, Some(digest), outfile)
+    }
+
+    fn retrieve_pack_unverified(
+        &self,
+        locations: &[PackLocation],
+        outfile: &Path,
+    ) -> Result<(), Error> {
+        self.retrieve_best(locations, None, outfile)
+    }
+
+    fn retrieve_object(&self, location: &PackLocation, outfile: &Path) -> Result<(), Error> {
+        for (store, source) in self.sources.iter() {
+            if location.store == store.id {
+                source.retrieve_pack(location, outfile)?;
+                self.record_transfer(&store.id, outfile, false);
+                return Ok(());
             }
         }
+        Err(Message::new(MessageCode::NoSuchStore)
+            .with("id", &location.store)
+            .into())
+    }
 
-        // find a store that is not slow, if available
+    fn check_pack(&self, locations: &[PackLocation], md5: &str) -> Result<Option<bool>, Error> {
+        let mut result: Option<bool> = None;
         for loc in locations.iter() {
             for (store, source) in self.sources.iter() {
-                if loc.store == store.id && !source.is_slow() {
-                    let result = source.retrieve_pack(loc, outfile);
-                    if result.is_ok() {
-                        return result;
+                if loc.store == store.id {
+                    // a store that cannot be asked is the same as one that has
+                    // no digest, the pack will be verified once retrieved
+                    match source.object_md5(loc) {
+                        Ok(Some(digest)) => {
+                            if digest.eq_ignore_ascii_case(md5) {
+                                return Ok(Some(true));
+                            }
+                            warn!(
+                                "pack {}/{} in store {} has digest {}, expected {}",
+                                loc.bucket, loc.object, store.id, digest, md5
+                            );
+                            result = Some(false);
+                        }
+                        Ok(None) => (),
+                        Err(err) => warn!("could not check pack in {}: {:?}", store.id, err),
                     }
-                    warn!(
-                        "pack retrieval failed, will try another source: {:?}",
-                        result
-                    );
                 }
             }
         }
+        Ok(result)
+    }
 
-        // find any matching store
-        for loc in locations.iter() {
-            for (store, source) in self.sources.iter() {
-                if loc.store == store.id {
-                    let result = source.retrieve_pack(loc, outfile);
-                    if result.is_ok() {
-                        return result;
-                    }
-                    warn!(
-                        "pack retrieval failed, will try another source: {:?}",
-                        result
-                    );
-                }
+    fn object_size(&self, location: &PackLocation) -> Result<Option<u64>, Error> {
+        for (store, source) in self.sources.iter() {
+            if location.store == store.id {
+                return source.object_size(location);
             }
         }
+        Err(Message::new(MessageCode::NoSuchStore)
+            .with("id", &location.store)
+            .into())
+    }
 
-        Err(anyhow!("unable to retrieve pack file: {:?}", locations))
+    fn abort_upload(&self, location: &PackLocation, checkpoint: &Checkpoint) -> Result<(), Error> {
+        for (store, source) in self.sources.iter() {
+            if location.store == store.id {
+                return source.abort_upload(location, checkpoint);
+            }
+        }
+        Err(Message::new(MessageCode::NoSuchStore)
+            .with("id", &location.store)
+            .into())
     }
 
     fn test_store(&self, store_id: &str) -> Result<(), Error> {
@@ -433,6 +971,15 @@ impl PackRepository for PackRepositoryImpl {
         Ok(())
     }
 
+    fn test_store_deep(&self, store_id: &str) -> Result<Vec<StoreTestStep>, Error> {
+        for (store, source) in self.sources.iter() {
+            if store_id == store.id {
+                return Ok(probe_store(source));
+            }
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
     fn store_database(&self, computer_id: &str, infile: &Path) -> Result<Vec<PackLocation>, Error> {
         // Use a ULID as the object name so they sort by time which will make
         // it easier to find the latest database archive later.
@@ -446,6 +993,19 @@ impl PackRepository for PackRepositoryImpl {
                 store.id, store.label, bucket, object
             );
             let loc = store_database_retry(source, infile, &bucket, &object).context(ctx)?;
+            self.invalidate_listings(&store.id);
+            self.record_transfer(&store.id, infile, true);
+            // the database is always uploaded, but it does take up space
+            let length = std::fs::metadata(infile).map(|m| m.len()).unwrap_or(0);
+            self.record_usage(&store.id, |usage| {
+                usage.objects += 1;
+                usage.bytes += length;
+            });
+            match expire_databases(source, store, &bucket, &loc.bucket) {
+                Ok(0) => (),
+                Ok(count) => info!("removed {} old database archives", count),
+                Err(err) => warn!("could not remove old database archives: {}", err),
+            }
             results.push(loc)
         }
         Ok(results)
@@ -462,53 +1022,219 @@ impl PackRepository for PackRepositoryImpl {
                 source
                     .retrieve_database(&loc, outfile)
                     .context("database archive retrieval")?;
+                self.record_transfer(&store.id, outfile, false);
                 return Ok(());
             } else {
                 return Err(anyhow!("no database archives available"));
             }
         }
-        Err(anyhow!("no matching store found"))
+        Err(Message::new(MessageCode::NoMatchingStore).into())
     }
 
-    fn find_missing(&self, store_id: &str, packs: &[Pack]) -> Result<Vec<Checksum>, Error> {
+    fn store_chain(
+        &self,
+        store_id: &str,
+        computer_id: &str,
+        infile: &Path,
+    ) -> Result<PackLocation, Error> {
+        let bucket = chain_bucket_name(computer_id);
         for (store, source) in self.sources.iter() {
             if store.id == store_id {
-                // Rather than trying to make this fast by using up a lot of
-                // memory (i.e. making sets of store/bucket/object tuples), just
-                // scan through the entire pack list B*O times. In the average
-                // case, the list of packs will not get any smaller so it will
-                // end up being an O(B*O*P) operation, which is regretable.
-                let mut missing_packs = packs.to_owned();
-                let buckets = source.list_buckets()?;
-                for bucket in buckets.iter() {
-                    info!("find_missing scanning bucket {}", bucket);
-                    let objects = source.list_objects(bucket.as_str())?;
-                    for object in objects.iter() {
-                        // remove any packs that have a location that matches
-                        // the current store/bucket/object tuple
-                        let not_yet_missing = missing_packs.into_iter().filter(|p| {
-                            p.locations.iter().all(|l| {
-                                l.store != store_id
-                                    || l.bucket != bucket.as_str()
-                                    || l.object != object.as_str()
-                            })
-                        });
-                        missing_packs = not_yet_missing.collect();
-                    }
-                }
-                let digests = missing_packs.into_iter().map(|p| p.digest).collect();
-                return Ok(digests);
+                let ctx = format!(
+                    "snapshot log store {} ({}) failed for {}/{}",
+                    store.id, store.label, bucket, CHAIN_OBJECT
+                );
+                let loc = self
+                    .store_pack_retry(source, infile, &bucket, CHAIN_OBJECT)
+                    .context(ctx)?;
+                self.invalidate_listings(&store.id);
+                self.record_transfer(&store.id, infile, true);
+                return Ok(loc);
             }
         }
-        Err(anyhow!("no matching store found"))
+        Err(Message::new(MessageCode::NoMatchingStore).into())
     }
 
-    fn prune_extra(&self, store_id: &str, packs: &[Pack]) -> Result<u32, Error> {
-        for (store, source) in self.sources.iter() {
-            if store.id == store_id {
-                let mut count: u32 = 0;
-                let buckets = source.list_buckets()?;
-                for bucket in buckets.iter() {
+    fn get_catalog_bucket(&self, computer_id: &str) -> String {
+        catalog_bucket_name(computer_id)
+    }
+
+    fn store_catalog(&self, computer_id: &str, infile: &Path) -> Result<Vec<PackLocation>, Error> {
+        // like the database archives, a ULID makes the names sort by time
+        let object = format!("{}{}", MANIFEST_PREFIX, ulid::Ulid::new());
+        self.store_catalog_pack(computer_id, infile, &object)
+    }
+
+    fn store_catalog_pack(
+        &self,
+        computer_id: &str,
+        infile: &Path,
+        object: &str,
+    ) -> Result<Vec<PackLocation>, Error> {
+        let bucket = catalog_bucket_name(computer_id);
+        self.store_warm(infile, &bucket, object, "catalog")
+    }
+
+    fn retrieve_latest_catalog(&self, computer_id: &str, outfile: &Path) -> Result<bool, Error> {
+        let bucket_name = catalog_bucket_name(computer_id);
+        // use the first store returned by the iterator, probably only one anyway
+        if let Some((store, source)) = self.sources.iter().next() {
+            // the bucket may have been renamed, which listing the databases
+            // will take into account
+            let objects = match source.list_databases(&bucket_name) {
+                Ok(objects) => objects,
+                // databases saved by older versions have no catalog bucket
+                Err(_) if !source.list_buckets()?.contains(&bucket_name) => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            let mut objects: Vec<String> = objects
+                .into_iter()
+                .filter(|o| o.starts_with(MANIFEST_PREFIX))
+                .collect();
+            objects.sort();
+            if let Some(latest) = objects.last() {
+                let loc = PackLocation::new(&store.id, &bucket_name, latest);
+                source
+                    .retrieve_database(&loc, outfile)
+                    .context("catalog manifest retrieval")?;
+                self.record_transfer(&store.id, outfile, false);
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn store_emergency(
+        &self,
+        computer_id: &str,
+        dataset_id: &str,
+        infile: &Path,
+    ) -> Result<Vec<PackLocation>, Error> {
+        let object = format!("{}{}", emergency_prefix(dataset_id), ulid::Ulid::new());
+        let bucket = rescue_bucket_name(computer_id);
+        self.store_warm(infile, &bucket, &object, "emergency index")
+    }
+
+    fn retrieve_latest_emergency(
+        &self,
+        computer_id: &str,
+        dataset_id: &str,
+        outfile: &Path,
+    ) -> Result<bool, Error> {
+        let prefix = emergency_prefix(dataset_id);
+        // use the first store returned by the iterator, probably only one anyway
+        if let Some((store, source)) = self.sources.iter().next() {
+            // indices saved by older versions are in the catalog bucket
+            let buckets = source.list_buckets()?;
+            for bucket_name in [
+                rescue_bucket_name(computer_id),
+                catalog_bucket_name(computer_id),
+            ] {
+                if !buckets.contains(&bucket_name) {
+                    continue;
+                }
+                let mut objects: Vec<String> = source
+                    .list_databases(&bucket_name)?
+                    .into_iter()
+                    .filter(|o| o.starts_with(&prefix))
+                    .collect();
+                objects.sort();
+                if let Some(latest) = objects.last() {
+                    let loc = PackLocation::new(&store.id, &bucket_name, latest);
+                    source
+                        .retrieve_database(&loc, outfile)
+                        .context("emergency index retrieval")?;
+                    self.record_transfer(&store.id, outfile, false);
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn list_emergency(&self, computer_id: &str) -> Result<Vec<String>, Error> {
+        // use the first store returned by the iterator, probably only one anyway
+        if let Some((_, source)) = self.sources.iter().next() {
+            let buckets = source.list_buckets()?;
+            let mut objects: Vec<String> = Vec::new();
+            for bucket_name in [
+                rescue_bucket_name(computer_id),
+                catalog_bucket_name(computer_id),
+            ] {
+                if buckets.contains(&bucket_name) {
+                    objects.extend(
+                        source
+                            .list_databases(&bucket_name)?
+                            .into_iter()
+                            .filter(|o| o.starts_with(EMERGENCY_PREFIX)),
+                    );
+                }
+            }
+            objects.sort();
+            objects.dedup();
+            return Ok(objects);
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn list_locations(&self, store_id: &str) -> Result<Vec<PackLocation>, Error> {
+        for (store, source) in self.sources.iter() {
+            if store.id == store_id {
+                let mut locations: Vec<PackLocation> = Vec::new();
+                for bucket in cached_buckets(store, source)?.iter() {
+                    for object in cached_objects(store, source, bucket)?.iter() {
+                        locations.push(PackLocation::new(store_id, bucket, object));
+                    }
+                }
+                return Ok(locations);
+            }
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn find_missing(&self, store_id: &str, packs: &[Pack]) -> Result<Vec<Checksum>, Error> {
+        for (store, source) in self.sources.iter() {
+            if store.id == store_id {
+                // Rather than trying to make this fast by using up a lot of
+                // memory (i.e. making sets of store/bucket/object tuples), just
+                // scan through the entire pack list B*O times. In the average
+                // case, the list of packs will not get any smaller so it will
+                // end up being an O(B*O*P) operation, which is regretable.
+                let mut missing_packs = packs.to_owned();
+                let buckets = cached_buckets(store, source)?;
+                for bucket in buckets.iter() {
+                    info!("find_missing scanning bucket {}", bucket);
+                    let objects = cached_objects(store, source, bucket)?;
+                    for object in objects.iter() {
+                        // remove any packs that have a location that matches
+                        // the current store/bucket/object tuple
+                        let not_yet_missing = missing_packs.into_iter().filter(|p| {
+                            p.locations.iter().all(|l| {
+                                l.store != store_id
+                                    || l.bucket != bucket.as_str()
+                                    || l.object != object.as_str()
+                            })
+                        });
+                        missing_packs = not_yet_missing.collect();
+                    }
+                }
+                let digests = missing_packs.into_iter().map(|p| p.digest).collect();
+                return Ok(digests);
+            }
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn prune_extra(&self, store_id: &str, packs: &[Pack]) -> Result<u32, Error> {
+        for (store, source) in self.sources.iter() {
+            if store.id == store_id {
+                let mut count: u32 = 0;
+                let buckets = cached_buckets(store, source)?;
+                // objects are about to be removed, the listings will be stale
+                self.invalidate_listings(store_id);
+                for bucket in buckets.iter() {
                     info!("prune_extra scanning bucket {}", bucket);
                     if is_bucket_referenced(store_id, bucket, packs) {
                         count += remove_objects(store_id, bucket, source, packs)?;
@@ -516,10 +1242,203 @@ impl PackRepository for PackRepositoryImpl {
                         count += remove_bucket(bucket, source)?;
                     }
                 }
+                self.record_usage(store_id, |usage| usage.remove_objects(count as u64));
                 return Ok(count);
             }
         }
-        Err(anyhow!("no matching store found"))
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn delete_pack(&self, pack: &Pack) -> Result<u32, Error> {
+        let mut count: u32 = 0;
+        for (store, source) in self.sources.iter() {
+            let mut removed: u32 = 0;
+            for location in pack.locations.iter().filter(|l| l.store == store.id) {
+                source.delete_object(&location.bucket, &location.object)?;
+                removed += 1;
+            }
+            if removed > 0 {
+                self.invalidate_listings(&store.id);
+                self.record_usage(&store.id, |usage| usage.remove_objects(removed as u64));
+                count += removed;
+            }
+        }
+        Ok(count)
+    }
+
+    fn invalidate_listings(&self, store_id: &str) {
+        let mut listings = LISTINGS.lock().unwrap();
+        listings.remove(store_id);
+    }
+
+    fn set_storage_class(&self, store_id: &str, pack: &Pack, class: &str) -> Result<bool, Error> {
+        for (store, source) in self.sources.iter() {
+            if store.id == store_id {
+                let mut changed = false;
+                for location in pack.locations.iter().filter(|l| l.store == store_id) {
+                    if source.set_storage_class(&location.bucket, &location.object, class)? {
+                        changed = true;
+                    }
+                }
+                return Ok(changed);
+            }
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+
+    fn configure_lifecycle(
+        &self,
+        store_id: &str,
+        computer_id: &str,
+        pack_buckets: &[String],
+    ) -> Result<u32, Error> {
+        for (store, source) in self.sources.iter() {
+            if store.id == store_id {
+                let pack_rule = LifecycleRule::for_packs(&store.properties)?;
+                let database_bucket = computer_bucket_name(computer_id);
+                let catalog_bucket = catalog_bucket_name(computer_id);
+                let existing: HashSet<String> = source.list_buckets()?.into_iter().collect();
+                let mut count: u32 = 0;
+                // the database archives are expired by the server, so that the
+                // newest is always kept; clear any rule set by older versions
+                if existing.contains(&database_bucket) {
+                    info!("configure_lifecycle for bucket {}", database_bucket);
+                    source.set_lifecycle(&database_bucket, &LifecycleRule::default())?;
+                    count += 1;
+                }
+                let mut seen: HashSet<&String> = HashSet::new();
+                for bucket in pack_buckets.iter() {
+                    // the catalog manifests must remain readable at all times
+                    if bucket == &catalog_bucket || !existing.contains(bucket) {
+                        continue;
+                    }
+                    if seen.insert(bucket) {
+                        info!("configure_lifecycle for bucket {}", bucket);
+                        source.set_lifecycle(bucket, &pack_rule)?;
+                        count += 1;
+                    }
+                }
+                return Ok(count);
+            }
+        }
+        Err(Message::new(MessageCode::NoMatchingStore).into())
+    }
+}
+
+// Remove the database archives in the named bucket that are older than the
+// number of days given by the `lifecycle_database_days` property of the store.
+// The newest archive is always kept, no matter its age, as it is the only way
+// to recover the computer after losing the database.
+//
+// The archives are listed by the name of the computer bucket, while they are
+// removed from the bucket that actually holds them, which may have been renamed.
+//
+// Returns the number of archives that were removed.
+fn expire_databases(
+    source: &Box<dyn PackDataSource>,
+    store: &Store,
+    bucket: &str,
+    actual: &str,
+) -> Result<u32, Error> {
+    let Some(days) = LifecycleRule::for_databases(&store.properties)?.expiration_days else {
+        return Ok(0);
+    };
+    let mut archives: Vec<(ulid::Ulid, String)> = source
+        .list_databases(bucket)?
+        .into_iter()
+        .filter_map(|name| ulid::Ulid::from_string(&name).ok().map(|id| (id, name)))
+        .collect();
+    archives.sort();
+    archives.pop();
+    let cutoff = std::time::SystemTime::now() - Duration::from_secs(u64::from(days) * 86_400);
+    let mut count: u32 = 0;
+    for (id, name) in archives.iter() {
+        if id.datetime() < cutoff {
+            source.delete_object(actual, name)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// Return the length of time for which listings for the given store are
+// considered valid, as given by the `listing_ttl` property (in seconds). If
+// the property is missing or invalid, listings are not cached.
+fn listing_ttl(store: &Store) -> Option<Duration> {
+    store
+        .properties
+        .get("listing_ttl")
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .map(Duration::from_secs)
+}
+
+// Retrieve the cached listing for the store, discarding it if it has expired.
+fn fresh_listing<'a>(
+    listings: &'a mut HashMap<String, StoreListing>,
+    store: &Store,
+    ttl: Duration,
+) -> &'a mut StoreListing {
+    if let Some(listing) = listings.get(&store.id) {
+        if listing.created.elapsed() > ttl {
+            listings.remove(&store.id);
+        }
+    }
+    listings
+        .entry(store.id.clone())
+        .or_insert_with(StoreListing::new)
+}
+
+// List the buckets in the store, using the cached listing if still valid. The
+// lock is released while the store is being listed, which may take a while.
+fn cached_buckets(store: &Store, source: &Box<dyn PackDataSource>) -> Result<Vec<String>, Error> {
+    if let Some(ttl) = listing_ttl(store) {
+        let created = {
+            let mut listings = LISTINGS.lock().unwrap();
+            let listing = fresh_listing(&mut listings, store, ttl);
+            if let Some(buckets) = listing.buckets.as_ref() {
+                return Ok(buckets.to_owned());
+            }
+            listing.created
+        };
+        let buckets = source.list_buckets()?;
+        let mut listings = LISTINGS.lock().unwrap();
+        let listing = fresh_listing(&mut listings, store, ttl);
+        // the listing may have been forgotten while the lock was released
+        if listing.created == created {
+            listing.buckets = Some(buckets.clone());
+        }
+        Ok(buckets)
+    } else {
+        source.list_buckets()
+    }
+}
+
+// List the objects in the bucket, using the cached listing if still valid. As
+// with the buckets, the lock is not held while listing the objects.
+fn cached_objects(
+    store: &Store,
+    source: &Box<dyn PackDataSource>,
+    bucket: &str,
+) -> Result<Vec<String>, Error> {
+    if let Some(ttl) = listing_ttl(store) {
+        let created = {
+            let mut listings = LISTINGS.lock().unwrap();
+            let listing = fresh_listing(&mut listings, store, ttl);
+            if let Some(objects) = listing.objects.get(bucket) {
+                return Ok(objects.to_owned());
+            }
+            listing.created
+        };
+        let objects = source.list_objects(bucket)?;
+        let mut listings = LISTINGS.lock().unwrap();
+        let listing = fresh_listing(&mut listings, store, ttl);
+        if listing.created == created {
+            listing.objects.insert(bucket.to_owned(), objects.clone());
+        }
+        Ok(objects)
+    } else {
+        source.list_objects(bucket)
     }
 }
 
@@ -536,6 +1455,53 @@ pub fn computer_bucket_name(unique_id: &str) -> String {
     }
 }
 
+// Name of the object that holds the log of snapshots uploaded to a store.
+const CHAIN_OBJECT: &str = "snapshots.log";
+
+// Return the name of the bucket that holds the snapshot log for the computer,
+// which is kept apart from the database snapshots so as not to be mistaken
+// for one of them.
+fn chain_bucket_name(unique_id: &str) -> String {
+    let mut name = computer_bucket_name(unique_id);
+    name.push_str("log");
+    name
+}
+
+// Name of the entry within a catalog archive that holds the encoded catalog.
+const CATALOG_ENTRY: &str = "catalog";
+
+// Prefix of the names of the catalog manifests, which sets them apart from
+// the packs in the same bucket, which are named by their digest.
+const MANIFEST_PREFIX: &str = "manifest-";
+
+// Name of the entry within an emergency archive that holds the encoded index.
+const EMERGENCY_ENTRY: &str = "emergency";
+
+// Prefix of the names of the emergency indices.
+const EMERGENCY_PREFIX: &str = "emergency-";
+
+// Return the prefix of the names of the emergency indices of the dataset.
+fn emergency_prefix(dataset_id: &str) -> String {
+    format!("{}{}-", EMERGENCY_PREFIX, dataset_id)
+}
+
+// Return the name of the bucket that holds the emergency indices for the
+// computer, which like the catalog bucket is never given a lifecycle rule, but
+// unlike it holds nothing else, such that the indices are easily found.
+fn rescue_bucket_name(unique_id: &str) -> String {
+    let mut name = computer_bucket_name(unique_id);
+    name.push_str("rescue");
+    name
+}
+
+// Return the name of the bucket that holds the chunked database backups for
+// the computer, which is kept apart from the database archives.
+fn catalog_bucket_name(unique_id: &str) -> String {
+    let mut name = computer_bucket_name(unique_id);
+    name.push_str("catalog");
+    name
+}
+
 // Generate a suitable bucket name, using a ULID and the given unique ID.
 //
 // The unique ID is assumed to be a shorted version of the UUID returned from
@@ -631,6 +1597,78 @@ fn remove_bucket(bucket: &str, source: &Box<dyn PackDataSource>) -> Result<u32,
     Ok(objects.len() as u32)
 }
 
+// Perform a round trip of operations against the store using a small probe
+// pack, recording the outcome and latency of each step.
+fn probe_store(source: &Box<dyn PackDataSource>) -> Vec<StoreTestStep> {
+    let mut steps: Vec<StoreTestStep> = Vec::new();
+    let started = Instant::now();
+    let result = source.list_buckets();
+    steps.push(StoreTestStep::new("list", started, &result));
+    if result.is_err() {
+        return steps;
+    }
+    // write a probe file with unique content to be uploaded
+    let started = Instant::now();
+    let result = tempfile::NamedTempFile::new().and_then(|tmp| {
+        let content = format!("zorigami store probe {}", ulid::Ulid::new());
+        std::fs::write(tmp.path(), content)?;
+        let digest = Checksum::blake3_from_file(tmp.path())?;
+        Ok((tmp.into_temp_path(), digest))
+    });
+    let (probe, digest) = match result {
+        Ok(pair) => pair,
+        Err(err) => {
+            let result: Result<(), Error> = Err(Error::from(err));
+            steps.push(StoreTestStep::new("prepare", started, &result));
+            return steps;
+        }
+    };
+    let bucket = ulid::Ulid::new().to_string().to_lowercase();
+    let object = digest.to_string();
+    let started = Instant::now();
+    let result = source.store_pack(&probe, &bucket, &object);
+    steps.push(StoreTestStep::new("upload", started, &result));
+    let location = match result {
+        Ok(location) => location,
+        Err(_) => return steps,
+    };
+    // read the probe back and verify the contents are unchanged
+    let started = Instant::now();
+    let result = tempfile::NamedTempFile::new()
+        .map_err(Error::from)
+        .and_then(|tmp| {
+            let outfile = tmp.into_temp_path();
+            source.retrieve_pack(&location, &outfile)?;
+            Ok(outfile)
+        });
+    steps.push(StoreTestStep::new("download", started, &result));
+    if let Ok(outfile) = result {
+        let started = Instant::now();
+        let result = Checksum::blake3_from_file(&outfile)
+            .map_err(Error::from)
+            .and_then(|actual| {
+                if actual == digest {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "probe digest does not match: {} != {}",
+                        actual,
+                        digest
+                    ))
+                }
+            });
+        steps.push(StoreTestStep::new("verify", started, &result));
+    }
+    // always try to clean up after a successful upload
+    let started = Instant::now();
+    let result = source.delete_object(&location.bucket, &location.object);
+    steps.push(StoreTestStep::new("delete_object", started, &result));
+    let started = Instant::now();
+    let result = source.delete_bucket(&location.bucket);
+    steps.push(StoreTestStep::new("delete_bucket", started, &result));
+    steps
+}
+
 // Try to store the database archive up to three times before giving up.
 fn store_database_retry(
     source: &Box<dyn PackDataSource>,
@@ -648,8 +1686,69 @@ fn store_database_retry(
         if retries == 0 {
             return result;
         }
-        warn!("database store failed, will retry: {:?}", result);
+        match result {
+            Err(ref err) if is_timeout(err) => {
+                warn!("database store timed out, will retry: {}", err)
+            }
+            _ => warn!("database store failed, will retry: {:?}", result),
+        }
+    }
+}
+
+// Return true if the store gave up on the operation due to one of the time
+// limits defined in the store properties.
+fn is_timeout(err: &Error) -> bool {
+    err.chain().any(|cause| cause.is::<TimeoutError>())
+}
+
+// Update the retrieval failures of the store, either counting another failure
+// or resetting the consecutive count after a success.
+fn record_retrieval(store_id: &str, error: Option<&Error>) {
+    let mut failures = RETRIEVAL_FAILURES.lock().unwrap();
+    match error {
+        Some(err) => {
+            let entry = failures
+                .entry(store_id.to_owned())
+                .or_insert_with(|| RetrievalFailures::new(store_id));
+            entry.failures += 1;
+            entry.consecutive += 1;
+            entry.last_error = Some(err.to_string());
+            entry.last_failure = Some(chrono::Utc::now());
+        }
+        None => {
+            if let Some(entry) = failures.get_mut(store_id) {
+                entry.consecutive = 0;
+            }
+        }
+    }
+}
+
+///
+/// Return the pack retrieval failures of each store since the server started,
+/// ordered by store identifier. Stores that have never failed are omitted.
+///
+pub fn retrieval_failures() -> Vec<RetrievalFailures> {
+    let failures = RETRIEVAL_FAILURES.lock().unwrap();
+    let mut results: Vec<RetrievalFailures> = failures.values().cloned().collect();
+    results.sort_by(|a, b| a.store.cmp(&b.store));
+    results
+}
+
+// Ensure the retrieved pack file matches the digest from the pack record. Only
+// the BLAKE3 digests are computed from the pack file itself, so anything else
+// is accepted as-is.
+fn verify_retrieved(store_id: &str, expected: &Checksum, outfile: &Path) -> Result<(), Error> {
+    if expected.is_blake3() {
+        let actual = Checksum::blake3_from_file(outfile)?;
+        if &actual != expected {
+            return Err(Error::from(IntegrityError {
+                store: store_id.to_owned(),
+                expected: expected.to_owned(),
+                actual,
+            }));
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -881,6 +1980,35 @@ mod tests {
         assert_eq!(&location.bucket[26..], &bucket_name[26..]);
     }
 
+    #[test]
+    fn test_store_pack_timeout() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source.expect_store_pack().times(3).returning(|_, _, _| {
+                let limit = Some(Duration::from_secs(60));
+                Err(Error::from(TimeoutError::new("store_pack", limit)))
+            });
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let repo = PackRepositoryImpl::new(stores, Box::new(builder)).unwrap();
+        let input_file = PathBuf::from("/home/planet/important.txt");
+        let result = repo.store_pack(&input_file, "bucket1", "object1");
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert!(is_timeout(&err));
+        assert!(format!("{:#}", err).contains("store_pack timed out after 60s"));
+    }
+
     #[test]
     fn test_store_pack_single_source() {
         // arrange
@@ -911,6 +2039,132 @@ mod tests {
         assert_eq!(locations.len(), 1);
     }
 
+    #[test]
+    fn test_store_pack_accounting() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_store_pack()
+                .returning(|_, bucket, object| Ok(PackLocation::new("store", bucket, object)));
+            Ok(Box::new(source))
+        });
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("monthly_cap".to_owned(), "5000".to_owned());
+        let stores = vec![Store {
+            id: "metered".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "metered".to_owned(),
+            properties,
+        }];
+        let used: Arc<Mutex<u64>> = Arc::new(Mutex::new(0));
+        let mut datasource = MockEntityDataSource::new();
+        let used_get = used.clone();
+        datasource
+            .expect_get_bandwidth()
+            .with(eq("metered"), always())
+            .returning(move |_, month| {
+                let mut usage = BandwidthUsage::new(month);
+                usage.uploaded = *used_get.lock().unwrap();
+                Ok(Some(usage))
+            });
+        let used_put = used.clone();
+        datasource
+            .expect_put_bandwidth()
+            .returning(move |_, usage| {
+                *used_put.lock().unwrap() = usage.uploaded;
+                Ok(())
+            });
+        datasource.expect_get_store_usage().returning(|_| Ok(None));
+        datasource.expect_put_store_usage().returning(|_, _| Ok(()));
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap().accounting(Arc::new(datasource));
+        let input_file = PathBuf::from("../test/fixtures/lorem-ipsum.txt");
+        let result = repo.store_pack(&input_file, "bucket1", "object1");
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(*used.lock().unwrap(), 3129);
+        let result = repo.store_pack(&input_file, "bucket1", "object2");
+        assert!(result.is_ok());
+        assert_eq!(*used.lock().unwrap(), 6258);
+        // the cap has been reached and the store refuses any more uploads
+        let result = repo.store_pack(&input_file, "bucket1", "object3");
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("monthly transfer cap"));
+    }
+
+    #[test]
+    fn test_store_pack_quota() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|store| {
+            let mut source = MockPackDataSource::new();
+            let store_id = store.id.clone();
+            source
+                .expect_store_pack()
+                .returning(move |_, bucket, object| {
+                    Ok(PackLocation::new(&store_id, bucket, object))
+                });
+            Ok(Box::new(source))
+        });
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("quota_bytes".to_owned(), "5000".to_owned());
+        let stores = vec![
+            Store {
+                id: "usbdisk".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "usb disk".to_owned(),
+                properties,
+            },
+            Store {
+                id: "minio".to_owned(),
+                store_type: StoreType::MINIO,
+                label: "server".to_owned(),
+                properties: HashMap::new(),
+            },
+        ];
+        let usage: Arc<Mutex<HashMap<String, StoreUsage>>> = Arc::new(Mutex::new(HashMap::new()));
+        let mut datasource = MockEntityDataSource::new();
+        datasource.expect_get_bandwidth().returning(|_, _| Ok(None));
+        datasource.expect_put_bandwidth().returning(|_, _| Ok(()));
+        let usage_get = usage.clone();
+        datasource
+            .expect_get_store_usage()
+            .returning(move |id| Ok(usage_get.lock().unwrap().get(id).cloned()));
+        let usage_put = usage.clone();
+        datasource
+            .expect_put_store_usage()
+            .returning(move |id, value| {
+                let mut usage = usage_put.lock().unwrap();
+                usage.insert(id.to_owned(), value.to_owned());
+                Ok(())
+            });
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap().accounting(Arc::new(datasource));
+        let input_file = PathBuf::from("../test/fixtures/lorem-ipsum.txt");
+        let result = repo.store_pack(&input_file, "bucket1", "object1");
+        // assert
+        assert_eq!(result.unwrap().len(), 2);
+        // the second pack would not fit within the quota of the usb disk
+        let result = repo.store_pack(&input_file, "bucket1", "object2");
+        let locations = result.unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].store, "minio");
+        let usage = usage.lock().unwrap();
+        let expected = StoreUsage {
+            objects: 1,
+            bytes: 3129,
+        };
+        assert_eq!(usage.get("usbdisk"), Some(&expected));
+        assert_eq!(usage.get("minio").unwrap().objects, 2);
+    }
+
     #[test]
     fn test_store_pack_multiple_sources() {
         // arrange
@@ -955,6 +2209,61 @@ mod tests {
         assert_eq!(locations.len(), 3);
     }
 
+    #[test]
+    fn test_check_pack() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().times(2).returning(|store| {
+            let mut source = MockPackDataSource::new();
+            if store.store_type == StoreType::LOCAL {
+                source
+                    .expect_object_md5()
+                    .returning(|_| Ok(Some("bad9a5cbe5b4ee4be5f1ed7d5e4c8a31".to_owned())));
+            } else {
+                source.expect_object_md5().returning(|location| {
+                    if location.object == "object1" {
+                        Ok(Some("40756E6058736E2485119410C2014380".to_owned()))
+                    } else {
+                        Ok(None)
+                    }
+                });
+            }
+            Ok(Box::new(source))
+        });
+        let stores = vec![
+            Store {
+                id: "local123".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "temporary".to_owned(),
+                properties: HashMap::new(),
+            },
+            Store {
+                id: "minio123".to_owned(),
+                store_type: StoreType::MINIO,
+                label: "server".to_owned(),
+                properties: HashMap::new(),
+            },
+        ];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let md5 = "40756e6058736e2485119410c2014380";
+        // assert
+        let locations = vec![
+            PackLocation::new("local123", "bucket1", "object1"),
+            PackLocation::new("minio123", "bucket1", "object1"),
+        ];
+        assert_eq!(repo.check_pack(&locations, md5).unwrap(), Some(true));
+        let locations = vec![
+            PackLocation::new("local123", "bucket1", "object2"),
+            PackLocation::new("minio123", "bucket1", "object2"),
+        ];
+        assert_eq!(repo.check_pack(&locations, md5).unwrap(), Some(false));
+        let locations = vec![PackLocation::new("minio123", "bucket1", "object2")];
+        assert_eq!(repo.check_pack(&locations, md5).unwrap(), None);
+    }
+
     #[test]
     fn test_retrieve_pack_multiple_local() {
         // arrange
@@ -1011,7 +2320,9 @@ mod tests {
             PackLocation::new("sftp123", "bucket1", "object1"),
         ];
         let output_file = PathBuf::from("/home/planet/restored.txt");
-        let result = repo.retrieve_pack(&locations, &output_file);
+        // legacy SHA1 digests are not verified
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
         // assert
         assert!(result.is_ok());
     }
@@ -1063,7 +2374,9 @@ mod tests {
             PackLocation::new("sftp123", "bucket1", "object1"),
         ];
         let output_file = PathBuf::from("/home/planet/restored.txt");
-        let result = repo.retrieve_pack(&locations, &output_file);
+        // legacy SHA1 digests are not verified
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
         // assert
         assert!(result.is_ok());
     }
@@ -1099,7 +2412,9 @@ mod tests {
             PackLocation::new("sftp123", "bucket1", "object1"),
         ];
         let output_file = PathBuf::from("/home/planet/restored.txt");
-        let result = repo.retrieve_pack(&locations, &output_file);
+        // legacy SHA1 digests are not verified
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
         // assert
         assert!(result.is_ok());
     }
@@ -1128,20 +2443,275 @@ mod tests {
             PackLocation::new("minio123", "bucket1", "object1"),
         ];
         let output_file = PathBuf::from("/home/planet/restored.txt");
-        let result = repo.retrieve_pack(&locations, &output_file);
+        // legacy SHA1 digests are not verified
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("unable to retrieve pack file"));
+    }
+
+    #[test]
+    fn test_retrieve_pack_integrity() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().times(2).returning(|store| {
+            let mut source = MockPackDataSource::new();
+            source.expect_is_local().returning(|| false);
+            source.expect_is_slow().returning(|| false);
+            if store.id == "minio123" {
+                // this store has a damaged copy of the pack
+                source.expect_retrieve_pack().returning(|_, outfile| {
+                    std::fs::write(outfile, b"not the pack you are looking for")?;
+                    Ok(())
+                });
+            } else {
+                source.expect_retrieve_pack().returning(|_, outfile| {
+                    std::fs::copy("../test/fixtures/lorem-ipsum.txt", outfile)?;
+                    Ok(())
+                });
+            }
+            Ok(Box::new(source))
+        });
+        let stores = vec![
+            Store {
+                id: "minio123".to_owned(),
+                store_type: StoreType::MINIO,
+                label: "server".to_owned(),
+                properties: HashMap::new(),
+            },
+            Store {
+                id: "sftp123".to_owned(),
+                store_type: StoreType::SFTP,
+                label: "other_server".to_owned(),
+                properties: HashMap::new(),
+            },
+        ];
+        let repo = PackRepositoryImpl::new(stores, Box::new(builder)).unwrap();
+        let infile = Path::new("../test/fixtures/lorem-ipsum.txt");
+        let digest = Checksum::blake3_from_file(infile).unwrap();
+        let outdir = tempfile::tempdir().unwrap();
+        let output_file = outdir.path().join("restored.txt");
+        // act
+        let locations = vec![
+            PackLocation::new("minio123", "bucket1", "object1"),
+            PackLocation::new("sftp123", "bucket1", "object1"),
+        ];
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(Checksum::blake3_from_file(&output_file).unwrap(), digest);
+
+        // act
+        let locations = vec![PackLocation::new("minio123", "bucket1", "object1")];
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        let integrity = err.downcast_ref::<IntegrityError>().unwrap();
+        assert_eq!(integrity.store, "minio123");
+        assert_eq!(integrity.expected, digest);
+    }
+
+    #[test]
+    fn test_retrieve_pack_failover() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().times(3).returning(|store| {
+            let mut source = MockPackDataSource::new();
+            if store.id == "failing123" {
+                source.expect_is_local().returning(|| true);
+                source
+                    .expect_retrieve_pack()
+                    .times(1)
+                    .returning(|_, _| Err(anyhow!("disk not mounted")));
+            } else if store.id == "minio456" {
+                source.expect_is_local().returning(|| false);
+                source.expect_is_slow().returning(|| false);
+                source
+                    .expect_retrieve_pack()
+                    .times(1)
+                    .returning(|_, _| Ok(()));
+            } else {
+                // slow store is not tried once another store has succeeded
+                source.expect_is_local().returning(|| false);
+                source.expect_is_slow().returning(|| true);
+                source.expect_retrieve_pack().never();
+            }
+            Ok(Box::new(source))
+        });
+        let stores = vec![
+            Store {
+                id: "glacier456".to_owned(),
+                store_type: StoreType::AMAZON,
+                label: "archive".to_owned(),
+                properties: HashMap::new(),
+            },
+            Store {
+                id: "failing123".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "external".to_owned(),
+                properties: HashMap::new(),
+            },
+            Store {
+                id: "minio456".to_owned(),
+                store_type: StoreType::MINIO,
+                label: "server".to_owned(),
+                properties: HashMap::new(),
+            },
+        ];
+        let repo = PackRepositoryImpl::new(stores, Box::new(builder)).unwrap();
+        let locations = vec![
+            PackLocation::new("glacier456", "bucket1", "object1"),
+            PackLocation::new("minio456", "bucket1", "object1"),
+            PackLocation::new("failing123", "bucket1", "object1"),
+        ];
+        let output_file = PathBuf::from("/home/planet/restored.txt");
+        // legacy SHA1 digests are not verified
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        // act
+        let result = repo.retrieve_pack(&locations, &digest, &output_file);
+        // assert
+        assert!(result.is_ok());
+        let failures = retrieval_failures();
+        let failing = failures.iter().find(|f| f.store == "failing123").unwrap();
+        assert_eq!(failing.failures, 1);
+        assert_eq!(failing.consecutive, 1);
+        assert!(failing
+            .last_error
+            .as_ref()
+            .unwrap()
+            .contains("disk not mounted"));
+        assert!(failing.last_failure.is_some());
+        assert!(!failures.iter().any(|f| f.store == "minio456"));
+    }
+
+    #[test]
+    fn test_test_store() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source.expect_list_buckets().returning(|| Ok(Vec::new()));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let result = repo.test_store("localtmp");
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_test_store_deep_upload_denied() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source.expect_list_buckets().returning(|| Ok(Vec::new()));
+            source
+                .expect_store_pack()
+                .returning(|_, _, _| Err(anyhow!("access denied")));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let result = repo.test_store_deep("localtmp");
+        // assert
+        assert!(result.is_ok());
+        let steps = result.unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].name, "list");
+        assert!(steps[0].error.is_none());
+        assert_eq!(steps[1].name, "upload");
+        assert!(steps[1].error.as_ref().unwrap().contains("access denied"));
+    }
+
+    #[test]
+    fn test_store_database() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_store_database()
+                .returning(|_, bucket, object| Ok(PackLocation::new("store", bucket, object)));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let input_file = PathBuf::from("/home/planet/important.txt");
+        let result = repo.store_database("hal9000", &input_file);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_retrieve_latest_database_none() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source.expect_list_databases().returning(|_| Ok(Vec::new()));
+            source.expect_retrieve_database().returning(|_, _| Ok(()));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let input_file = PathBuf::from("/home/planet/important.txt");
+        let result = repo.retrieve_latest_database("hal9000", &input_file);
         // assert
         assert!(result.is_err());
-        let err_string = result.unwrap_err().to_string();
-        assert!(err_string.contains("unable to retrieve pack file"));
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("no database archives available"));
     }
 
     #[test]
-    fn test_test_store() {
+    fn test_retrieve_latest_database_single() {
         // arrange
         let mut builder = MockPackSourceBuilder::new();
         builder.expect_build_source().returning(|_| {
             let mut source = MockPackDataSource::new();
-            source.expect_list_buckets().returning(|| Ok(Vec::new()));
+            source
+                .expect_list_databases()
+                .returning(|_| Ok(vec!["007".to_owned()]));
+            source
+                .expect_retrieve_database()
+                .withf(|location, _| location.object == "007")
+                .returning(|_, _| Ok(()));
             Ok(Box::new(source))
         });
         let stores = vec![Store {
@@ -1154,20 +2724,30 @@ mod tests {
         let result = PackRepositoryImpl::new(stores, Box::new(builder));
         assert!(result.is_ok());
         let repo = result.unwrap();
-        let result = repo.test_store("localtmp");
+        let input_file = PathBuf::from("/home/planet/important.txt");
+        let result = repo.retrieve_latest_database("hal9000", &input_file);
         // assert
         assert!(result.is_ok());
     }
 
     #[test]
-    fn test_store_database() {
+    fn test_retrieve_latest_database_multiple() {
         // arrange
         let mut builder = MockPackSourceBuilder::new();
         builder.expect_build_source().returning(|_| {
             let mut source = MockPackDataSource::new();
+            source.expect_list_databases().returning(|_| {
+                let objects = vec![
+                    "01arz3ndektsv4rrffq69g5fav".to_owned(),
+                    "01edn29q3m3n7ccpd2sfh4244b".to_owned(),
+                    "01ce0d526z6cyzgm02ap0jv281".to_owned(),
+                ];
+                Ok(objects)
+            });
             source
-                .expect_store_database()
-                .returning(|_, bucket, object| Ok(PackLocation::new("store", bucket, object)));
+                .expect_retrieve_database()
+                .withf(|loc, _| loc.object == "01edn29q3m3n7ccpd2sfh4244b")
+                .returning(|_, _| Ok(()));
             Ok(Box::new(source))
         });
         let stores = vec![Store {
@@ -1181,19 +2761,24 @@ mod tests {
         assert!(result.is_ok());
         let repo = result.unwrap();
         let input_file = PathBuf::from("/home/planet/important.txt");
-        let result = repo.store_database("hal9000", &input_file);
+        let result = repo.retrieve_latest_database("hal9000", &input_file);
         // assert
         assert!(result.is_ok());
     }
 
     #[test]
-    fn test_retrieve_latest_database_none() {
+    fn test_retrieve_latest_catalog_none() {
         // arrange
         let mut builder = MockPackSourceBuilder::new();
         builder.expect_build_source().returning(|_| {
             let mut source = MockPackDataSource::new();
-            source.expect_list_databases().returning(|_| Ok(Vec::new()));
-            source.expect_retrieve_database().returning(|_, _| Ok(()));
+            source
+                .expect_list_databases()
+                .returning(|_| Err(anyhow!("no such bucket")));
+            source
+                .expect_list_buckets()
+                .returning(|| Ok(vec!["pack0001".to_owned()]));
+            source.expect_retrieve_database().never();
             Ok(Box::new(source))
         });
         let stores = vec![Store {
@@ -1206,26 +2791,37 @@ mod tests {
         let result = PackRepositoryImpl::new(stores, Box::new(builder));
         assert!(result.is_ok());
         let repo = result.unwrap();
+        let computer_id = Configuration::generate_unique_id("charlie", "localhost");
         let input_file = PathBuf::from("/home/planet/important.txt");
-        let result = repo.retrieve_latest_database("hal9000", &input_file);
+        let result = repo.retrieve_latest_catalog(&computer_id, &input_file);
         // assert
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err().to_string();
-        assert!(err_msg.contains("no database archives available"));
+        assert!(result.is_ok());
+        assert!(!result.unwrap());
     }
 
     #[test]
-    fn test_retrieve_latest_database_single() {
+    fn test_retrieve_latest_catalog_multiple() {
         // arrange
+        let computer_id = Configuration::generate_unique_id("charlie", "localhost");
+        let bucket = catalog_bucket_name(&computer_id);
         let mut builder = MockPackSourceBuilder::new();
-        builder.expect_build_source().returning(|_| {
+        builder.expect_build_source().returning(move |_| {
+            let bucket = bucket.clone();
             let mut source = MockPackDataSource::new();
             source
                 .expect_list_databases()
-                .returning(|_| Ok(vec!["007".to_owned()]));
+                .withf(move |name| name == bucket)
+                .returning(|_| {
+                    Ok(vec![
+                        "manifest-01ARZ3NDEKTSV4RRFFQ69G5FAV".to_owned(),
+                        "blake3-0123456789abcdef".to_owned(),
+                        "manifest-01BX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                        "manifest-01ARZ3NDEKTSV4RRFFQ69G5FAX".to_owned(),
+                    ])
+                });
             source
                 .expect_retrieve_database()
-                .withf(|location, _| location.object == "007")
+                .withf(|location, _| location.object == "manifest-01BX5ZZKBKACTAV9WEVGEMMVRZ")
                 .returning(|_, _| Ok(()));
             Ok(Box::new(source))
         });
@@ -1240,28 +2836,34 @@ mod tests {
         assert!(result.is_ok());
         let repo = result.unwrap();
         let input_file = PathBuf::from("/home/planet/important.txt");
-        let result = repo.retrieve_latest_database("hal9000", &input_file);
+        let result = repo.retrieve_latest_catalog(&computer_id, &input_file);
         // assert
         assert!(result.is_ok());
+        assert!(result.unwrap());
     }
 
     #[test]
-    fn test_retrieve_latest_database_multiple() {
+    fn test_retrieve_latest_emergency() {
         // arrange
+        let computer_id = Configuration::generate_unique_id("charlie", "localhost");
+        let bucket = rescue_bucket_name(&computer_id);
         let mut builder = MockPackSourceBuilder::new();
-        builder.expect_build_source().returning(|_| {
+        builder.expect_build_source().returning(move |_| {
+            let bucket = bucket.clone();
             let mut source = MockPackDataSource::new();
+            source
+                .expect_list_buckets()
+                .returning(move || Ok(vec![bucket.clone()]));
             source.expect_list_databases().returning(|_| {
-                let objects = vec![
-                    "01arz3ndektsv4rrffq69g5fav".to_owned(),
-                    "01edn29q3m3n7ccpd2sfh4244b".to_owned(),
-                    "01ce0d526z6cyzgm02ap0jv281".to_owned(),
-                ];
-                Ok(objects)
+                Ok(vec![
+                    "emergency-cafe-01ARZ3NDEKTSV4RRFFQ69G5FAV".to_owned(),
+                    "emergency-cafebabe-01CX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                    "emergency-cafe-01BX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                ])
             });
             source
                 .expect_retrieve_database()
-                .withf(|loc, _| loc.object == "01edn29q3m3n7ccpd2sfh4244b")
+                .withf(|location, _| location.object == "emergency-cafe-01BX5ZZKBKACTAV9WEVGEMMVRZ")
                 .returning(|_, _| Ok(()));
             Ok(Box::new(source))
         });
@@ -1276,9 +2878,61 @@ mod tests {
         assert!(result.is_ok());
         let repo = result.unwrap();
         let input_file = PathBuf::from("/home/planet/important.txt");
-        let result = repo.retrieve_latest_database("hal9000", &input_file);
+        let result = repo.retrieve_latest_emergency(&computer_id, "cafe", &input_file);
         // assert
         assert!(result.is_ok());
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn test_list_emergency() {
+        // arrange
+        let computer_id = Configuration::generate_unique_id("charlie", "localhost");
+        let rescue = rescue_bucket_name(&computer_id);
+        let catalog = catalog_bucket_name(&computer_id);
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(move |_| {
+            let buckets = vec![rescue.clone(), catalog.clone()];
+            let rescue = rescue.clone();
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_list_buckets()
+                .returning(move || Ok(buckets.clone()));
+            source.expect_list_databases().returning(move |bucket| {
+                if bucket == rescue {
+                    Ok(vec![
+                        "emergency-cafe-01CX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                        "emergency-babe-01BX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                    ])
+                } else {
+                    // indices saved by older versions are in the catalog bucket
+                    Ok(vec![
+                        "emergency-cafe-01ARZ3NDEKTSV4RRFFQ69G5FAV".to_owned(),
+                        "manifest-01CX5ZZKBKACTAV9WEVGEMMVRZ".to_owned(),
+                        "blake3-8f37a1b2".to_owned(),
+                    ])
+                }
+            });
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let repo = PackRepositoryImpl::new(stores, Box::new(builder)).unwrap();
+        let result = repo.list_emergency(&computer_id);
+        // assert
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                "emergency-babe-01BX5ZZKBKACTAV9WEVGEMMVRZ",
+                "emergency-cafe-01ARZ3NDEKTSV4RRFFQ69G5FAV",
+                "emergency-cafe-01CX5ZZKBKACTAV9WEVGEMMVRZ",
+            ]
+        );
     }
 
     #[test]
@@ -1512,6 +3166,235 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_find_missing_cached_listings() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source.expect_list_buckets().times(2).returning(|| {
+                let buckets = vec!["bucket1".to_owned()];
+                Ok(buckets)
+            });
+            source
+                .expect_list_objects()
+                .with(eq("bucket1"))
+                .times(2)
+                .returning(|_| {
+                    let objects = vec!["object1".to_owned()];
+                    Ok(objects)
+                });
+            Ok(Box::new(source))
+        });
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("listing_ttl".to_owned(), "300".to_owned());
+        let stores = vec![Store {
+            id: "cachedtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties,
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let digest = Checksum::SHA1(String::from("ed841695851abdcfe6a50ce3d01d770eb053356b"));
+        let coords = vec![PackLocation::new("cachedtmp", "bucket1", "object1")];
+        let pack1 = Pack::new(digest.clone(), coords);
+        let digest = Checksum::SHA1(String::from("ad9fec27d7e071f5380af0c1499b651e9fadfb48"));
+        let coords = vec![PackLocation::new("cachedtmp", "bucket1", "object2")];
+        let pack2 = Pack::new(digest.clone(), coords);
+        let packs: Vec<Pack> = vec![pack1, pack2];
+        // the second and third calls use the cached listings
+        for _ in 0..3 {
+            let result = repo.find_missing("cachedtmp", &packs);
+            assert!(result.is_ok());
+            assert_eq!(result.unwrap().len(), 1);
+        }
+        // after invalidation the store is listed again
+        repo.invalidate_listings("cachedtmp");
+        let result = repo.find_missing("cachedtmp", &packs);
+        // assert
+        assert!(result.is_ok());
+        let missing_packs = result.unwrap();
+        assert_eq!(missing_packs.len(), 1);
+        assert_eq!(
+            missing_packs[0].to_string(),
+            "sha1-ad9fec27d7e071f5380af0c1499b651e9fadfb48"
+        );
+    }
+
+    #[test]
+    fn test_set_storage_class() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_set_storage_class()
+                .with(eq("bucket1"), eq("object1"), eq("GLACIER_IR"))
+                .times(1)
+                .returning(|_, _, _| Ok(true));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "coldtmp".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let digest = Checksum::SHA1(String::from("ed841695851abdcfe6a50ce3d01d770eb053356b"));
+        let coords = vec![
+            PackLocation::new("coldtmp", "bucket1", "object1"),
+            PackLocation::new("othertmp", "bucket1", "object1"),
+        ];
+        let pack = Pack::new(digest, coords);
+        let result = repo.set_storage_class("coldtmp", &pack, "GLACIER_IR");
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap());
+        let result = repo.set_storage_class("nosuchstore", &pack, "GLACIER_IR");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_delete_pack() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_delete_object()
+                .with(eq("bucket1"), eq("object1"))
+                .times(1)
+                .returning(|_, _| Ok(()));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let digest = Checksum::SHA1(String::from("ed841695851abdcfe6a50ce3d01d770eb053356b"));
+        let coords = vec![
+            PackLocation::new("localtmp", "bucket1", "object1"),
+            PackLocation::new("othertmp", "bucket1", "object1"),
+        ];
+        let pack = Pack::new(digest, coords);
+        let result = repo.delete_pack(&pack);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_configure_lifecycle() {
+        // arrange
+        let computer_id = "uUeOJWUbR4KTTa9nKyxmQr";
+        let database_bucket = computer_bucket_name(computer_id);
+        let catalog_bucket = catalog_bucket_name(computer_id);
+        let expected_bucket = database_bucket.clone();
+        let listed_catalog = catalog_bucket.clone();
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(move |_| {
+            let mut source = MockPackDataSource::new();
+            let buckets = vec![
+                expected_bucket.clone(),
+                listed_catalog.clone(),
+                "bucket1".to_owned(),
+                "someone-else".to_owned(),
+            ];
+            source
+                .expect_list_buckets()
+                .returning(move || Ok(buckets.clone()));
+            source
+                .expect_set_lifecycle()
+                .withf(|bucket, rule| bucket == "bucket1" && rule.transition_days == Some(60))
+                .times(1)
+                .returning(|_, _| Ok(()));
+            let database_bucket = expected_bucket.clone();
+            source
+                .expect_set_lifecycle()
+                .withf(move |bucket, rule| bucket == database_bucket && rule.is_empty())
+                .times(1)
+                .returning(|_, _| Ok(()));
+            Ok(Box::new(source))
+        });
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("lifecycle_days".to_owned(), "60".to_owned());
+        properties.insert("lifecycle_class".to_owned(), "GLACIER".to_owned());
+        properties.insert("lifecycle_database_days".to_owned(), "30".to_owned());
+        let stores = vec![Store {
+            id: "coldtmp".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "temporary".to_owned(),
+            properties,
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        // the catalog bucket and missing buckets are passed over
+        let pack_buckets = vec![
+            "bucket1".to_owned(),
+            catalog_bucket,
+            "bucket1".to_owned(),
+            "gone".to_owned(),
+        ];
+        let result = repo.configure_lifecycle("coldtmp", computer_id, &pack_buckets);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+        let result = repo.configure_lifecycle("nosuchstore", computer_id, &pack_buckets);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_expire_databases() {
+        // arrange
+        let now = std::time::SystemTime::now();
+        let day = Duration::from_secs(86_400);
+        let newest = ulid::Ulid::from_datetime(now - day * 60).to_string();
+        let older = ulid::Ulid::from_datetime(now - day * 90).to_string();
+        let listed = vec![older.clone(), newest.clone(), "renamed".to_owned()];
+        let mut source = MockPackDataSource::new();
+        source
+            .expect_list_databases()
+            .with(eq("dbbucket"))
+            .returning(move |_| Ok(listed.clone()));
+        source
+            .expect_delete_object()
+            .withf(move |bucket, object| bucket == "actual" && object == older)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let source: Box<dyn PackDataSource> = Box::new(source);
+        let mut properties: HashMap<String, String> = HashMap::new();
+        let mut store = Store {
+            id: "coldtmp".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "temporary".to_owned(),
+            properties: properties.clone(),
+        };
+        // act
+        let result = expire_databases(&source, &store, "dbbucket", "actual");
+        // assert
+        assert_eq!(result.unwrap(), 0);
+        // the newest archive is kept even though it is past the limit
+        properties.insert("lifecycle_database_days".to_owned(), "30".to_owned());
+        store.properties = properties;
+        let result = expire_databases(&source, &store, "dbbucket", "actual");
+        assert_eq!(result.unwrap(), 1);
+    }
+
     #[test]
     fn test_prune_extra_no_store() {
         // arrange
@@ -1538,6 +3421,41 @@ mod tests {
         assert!(err_string.contains("no matching store found"));
     }
 
+    #[test]
+    fn test_store_chain() {
+        // arrange
+        let mut builder = MockPackSourceBuilder::new();
+        builder.expect_build_source().returning(|_| {
+            let mut source = MockPackDataSource::new();
+            source
+                .expect_store_pack()
+                .withf(|_, bucket, object| bucket.ends_with("log") && object == CHAIN_OBJECT)
+                .returning(|_, bucket, object| Ok(PackLocation::new("localtmp", bucket, object)));
+            Ok(Box::new(source))
+        });
+        let stores = vec![Store {
+            id: "localtmp".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "temporary".to_owned(),
+            properties: HashMap::new(),
+        }];
+        // act
+        let result = PackRepositoryImpl::new(stores, Box::new(builder));
+        assert!(result.is_ok());
+        let repo = result.unwrap();
+        let infile = Path::new("../test/fixtures/lorem-ipsum.txt");
+        let computer_id = "Xx1zSxqOQcSIqM96qr6PHQ";
+        let result = repo.store_chain("localtmp", computer_id, infile);
+        // assert
+        assert!(result.is_ok());
+        let location = result.unwrap();
+        assert_eq!(location.store, "localtmp");
+        assert_eq!(location.bucket, chain_bucket_name(computer_id));
+        assert_eq!(location.object, "snapshots.log");
+        let result = repo.store_chain("nostore", computer_id, infile);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_prune_extra_no_buckets() {
         // arrange
@@ -1777,29 +3695,16 @@ mod tests {
         );
         let file = outdir.path().join("lorem-ipsum.txt");
         let chksum = Checksum::blake3_from_file(&file)?;
-        #[cfg(target_family = "unix")]
         assert_eq!(
             chksum.to_string(),
             "blake3-deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128"
         );
-        // line endings differ
-        #[cfg(target_family = "windows")]
-        assert_eq!(
-            chksum.to_string(),
-            "blake3-2720a91db93dae2a92ed9f74b0f7a135cfdf4d32dd069477cda457002ffc9e7a"
-        );
         let file = outdir.path().join("washington-journal.txt");
         let chksum = Checksum::blake3_from_file(&file)?;
-        #[cfg(target_family = "unix")]
         assert_eq!(
             chksum.to_string(),
             "blake3-540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60"
         );
-        #[cfg(target_family = "windows")]
-        assert_eq!(
-            chksum.to_string(),
-            "blake3-183d52ff928be3e77cccf1b78b12b31910d5079195a637a9a2b499059f99b781"
-        );
 
         Ok(())
     }
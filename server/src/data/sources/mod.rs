@@ -4,14 +4,20 @@
 
 //! Performs serde on entities and stores them in a database.
 
+use crate::data::models::catalog::{decode_catalog, encode_catalog};
+use crate::data::models::dedup::{decode_dedup, encode_dedup};
 use crate::data::models::{
-    ChunkDef, ConfigurationDef, DatasetDef, FileDef, PackDef, SnapshotDef, StoreDef,
+    ChainEntryDef, CheckpointDef, ChunkDef, ConfigurationDef, DatasetDef, DeviceDef, EventDef,
+    FileDef, MaintenanceResultDef, MigrationDef, PackDef, SnapshotDef, StoreDef, VerificationDef,
+    WebhookDef,
 };
 use crate::domain::entities::{
-    Checksum, Chunk, Configuration, Dataset, File, Pack, PackLocation, RecordCounts, Snapshot,
-    Store, StoreType, Tree,
+    BandwidthUsage, Catalog, ChainEntry, Checksum, Chunk, Configuration, Dataset, DedupStats,
+    Device, Event, File, MaintenanceResult, Migration, Pack, PackLocation, RecordCounts, Snapshot,
+    Store, StoreType, StoreUsage, TrashedDataset, Tree, Verification, Webhook,
 };
-use anyhow::Error;
+use crate::domain::managers::migrate;
+use anyhow::{anyhow, Error};
 use database_core::Database;
 use database_rocks;
 use log::debug;
@@ -20,15 +26,12 @@ use mockall::automock;
 use std::str::FromStr;
 use std::{
     path::{Path, PathBuf},
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
+use store::StorePackSource;
+use store_core::{Checkpoint, CheckpointStore, LifecycleRule};
 
-mod amazon;
-mod azure;
-mod google;
-mod local;
-mod minio;
-mod sftp;
+mod store;
 
 /// Data source for entity objects.
 #[cfg_attr(test, automock)]
@@ -39,6 +42,18 @@ pub trait EntityDataSource: Send + Sync {
     /// Retrieve the configuration from the data source.
     fn get_configuration(&self) -> Result<Option<Configuration>, Error>;
 
+    /// Save the version of the schema of the database records.
+    fn put_schema_version(&self, version: u32) -> Result<(), Error>;
+
+    /// Retrieve the version of the schema of the database records, if any.
+    fn get_schema_version(&self) -> Result<Option<u32>, Error>;
+
+    /// Save the record of a migration that was applied to the database.
+    fn put_migration(&self, migration: &Migration) -> Result<(), Error>;
+
+    /// Retrieve the records of the migrations applied to the database.
+    fn get_migrations(&self) -> Result<Vec<Migration>, Error>;
+
     /// Save the computer identifier for the dataset with the given key.
     fn put_computer_id(&self, dataset: &str, computer_id: &str) -> Result<(), Error>;
 
@@ -57,6 +72,31 @@ pub trait EntityDataSource: Send + Sync {
     /// Remvoe the digest of the latest snapshot for the dataset with the given key.
     fn delete_latest_snapshot(&self, dataset: &str) -> Result<(), Error>;
 
+    /// Save the bandwidth usage of the store for the month given in the record.
+    fn put_bandwidth(&self, store: &str, usage: &BandwidthUsage) -> Result<(), Error>;
+
+    /// Retrieve the bandwidth usage of the store for the given month.
+    fn get_bandwidth(&self, store: &str, month: &str) -> Result<Option<BandwidthUsage>, Error>;
+
+    /// Save the number and total size of the objects added to the store.
+    fn put_store_usage(&self, store: &str, usage: &StoreUsage) -> Result<(), Error>;
+
+    /// Retrieve the number and total size of the objects added to the store.
+    fn get_store_usage(&self, store: &str) -> Result<Option<StoreUsage>, Error>;
+
+    /// Save the checkpoint of a partially completed upload to the location.
+    fn put_checkpoint(&self, location: &PackLocation, checkpoint: &Checkpoint)
+        -> Result<(), Error>;
+
+    /// Retrieve the checkpoint of an upload to the location, if any.
+    fn get_checkpoint(&self, location: &PackLocation) -> Result<Option<Checkpoint>, Error>;
+
+    /// Remove the checkpoint of an upload to the location.
+    fn delete_checkpoint(&self, location: &PackLocation) -> Result<(), Error>;
+
+    /// Retrieve all of the upload checkpoints along with their locations.
+    fn get_checkpoints(&self) -> Result<Vec<(PackLocation, Checkpoint)>, Error>;
+
     /// Insert the given chunk into the data source, if one with the same digest does
     /// not already exist. Chunks with the same digest are assumed to be identical.
     fn insert_chunk(&self, chunk: &Chunk) -> Result<(), Error>;
@@ -64,6 +104,19 @@ pub trait EntityDataSource: Send + Sync {
     /// Retrieve the chunk by the given digest, returning `None` if not found.
     fn get_chunk(&self, digest: &Checksum) -> Result<Option<Chunk>, Error>;
 
+    /// Save the given chunk to the data source, overwriting any existing entry.
+    fn put_chunk(&self, chunk: &Chunk) -> Result<(), Error>;
+
+    /// Remove the chunk record with the given digest.
+    fn delete_chunk(&self, digest: &Checksum) -> Result<(), Error>;
+
+    /// Pass each chunk record to the visitor, one at a time, until the visitor
+    /// returns `false`. The visitor must not access the data source.
+    fn visit_chunks(
+        &self,
+        visitor: &mut dyn FnMut(Chunk) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given pack into the data source, if one with the same digest
     /// does not already exist. Packs with the same digest are assumed to be
     /// identical.
@@ -75,12 +128,23 @@ pub trait EntityDataSource: Send + Sync {
     /// Retrieve the pack by the given digest, returning `None` if not found.
     fn get_pack(&self, digest: &Checksum) -> Result<Option<Pack>, Error>;
 
+    /// Remove the pack record with the given digest.
+    fn delete_pack(&self, digest: &Checksum) -> Result<(), Error>;
+
     /// Retrieve all pack records that should be in the given store.
     fn get_packs(&self, store_id: &str) -> Result<Vec<Pack>, Error>;
 
     /// Retrieve all pack records in the system regardless of store.
     fn get_all_packs(&self) -> Result<Vec<Pack>, Error>;
 
+    /// Pass each pack record in the system to the visitor, one at a time,
+    /// until the visitor returns `false`. The visitor must not access the data
+    /// source, which remains locked for the duration of the scan.
+    fn visit_packs(
+        &self,
+        visitor: &mut dyn FnMut(Pack) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given psedo-pack for the database snapshot, if one with the
     /// same digest does not already exist. Packs with the same digest are
     /// assumed to be identical.
@@ -110,6 +174,19 @@ pub trait EntityDataSource: Send + Sync {
     /// Retrieve the file by the given digest, returning `None` if not found.
     fn get_file(&self, digest: &Checksum) -> Result<Option<File>, Error>;
 
+    /// Save the given file to the data source, overwriting any existing entry.
+    fn put_file(&self, file: &File) -> Result<(), Error>;
+
+    /// Remove the file record with the given digest.
+    fn delete_file(&self, digest: &Checksum) -> Result<(), Error>;
+
+    /// Pass each file record to the visitor, one at a time, until the visitor
+    /// returns `false`. The visitor must not access the data source.
+    fn visit_files(
+        &self,
+        visitor: &mut dyn FnMut(File) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given tree into the data source, if one with the same digest
     /// does not already exist. Trees with the same digest are assumed to be
     /// identical.
@@ -142,12 +219,94 @@ pub trait EntityDataSource: Send + Sync {
     /// Remove the dataset by the given identifier.
     fn delete_dataset(&self, id: &str) -> Result<(), Error>;
 
+    /// Save the deleted dataset to the trash.
+    fn put_trashed_dataset(&self, trashed: &TrashedDataset) -> Result<(), Error>;
+
+    /// Retrieve all of the datasets in the trash.
+    fn get_trashed_datasets(&self) -> Result<Vec<TrashedDataset>, Error>;
+
+    /// Remove the dataset with the given identifier from the trash.
+    fn delete_trashed_dataset(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given paired device to the data source.
+    fn put_device(&self, device: &Device) -> Result<(), Error>;
+
+    /// Retrieve all of the paired devices.
+    fn get_devices(&self) -> Result<Vec<Device>, Error>;
+
+    /// Remove the paired device with the given identifier.
+    fn delete_device(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given webhook, replacing any with the same identifier.
+    fn put_webhook(&self, webhook: &Webhook) -> Result<(), Error>;
+
+    /// Retrieve all of the defined webhooks.
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, Error>;
+
+    /// Remove the webhook with the given identifier.
+    fn delete_webhook(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given event to the event log.
+    fn put_event(&self, event: &Event) -> Result<(), Error>;
+
+    /// Retrieve all of the events in the event log, in no particular order.
+    fn get_events(&self) -> Result<Vec<Event>, Error>;
+
+    /// Remove the event with the given identifier from the event log.
+    fn delete_event(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the outcome of verifying a snapshot of a dataset.
+    fn put_verification(&self, verification: &Verification) -> Result<(), Error>;
+
+    /// Retrieve the verifications of the snapshots of the given dataset, in
+    /// no particular order.
+    fn get_verifications(&self, dataset: &str) -> Result<Vec<Verification>, Error>;
+
+    /// Remove the verification with the given identifier.
+    fn delete_verification(&self, dataset: &str, id: &str) -> Result<(), Error>;
+
+    /// Save the outcome of running a scheduled task for a dataset, replacing
+    /// the previous outcome of the same task.
+    fn put_task_run(&self, run: &MaintenanceResult) -> Result<(), Error>;
+
+    /// Retrieve the most recent run of each scheduled task of the dataset.
+    fn get_task_runs(&self, dataset: &str) -> Result<Vec<MaintenanceResult>, Error>;
+
+    /// Save the given entry of the snapshot log of a store.
+    fn put_chain_entry(&self, entry: &ChainEntry) -> Result<(), Error>;
+
+    /// Retrieve the entries of the snapshot log of the given store, in order.
+    fn get_chain_entries(&self, store_id: &str) -> Result<Vec<ChainEntry>, Error>;
+
+    /// Save the location of the snapshot log within its store.
+    fn put_chain_location(&self, location: &PackLocation) -> Result<(), Error>;
+
+    /// Retrieve the locations of the snapshot logs of every store.
+    fn get_chain_locations(&self) -> Result<Vec<PackLocation>, Error>;
+
+    /// Save the catalog of the most recent chunked database backup to the
+    /// given store.
+    fn put_catalog(&self, store_id: &str, catalog: &Catalog) -> Result<(), Error>;
+
+    /// Retrieve the catalog of the most recent chunked database backup to the
+    /// given store.
+    fn get_catalog(&self, store_id: &str) -> Result<Option<Catalog>, Error>;
+
+    /// Save the deduplication statistics of a dataset.
+    fn put_dedup_stats(&self, stats: &DedupStats) -> Result<(), Error>;
+
+    /// Retrieve the deduplication statistics of the dataset, if any.
+    fn get_dedup_stats(&self, dataset: &str) -> Result<Option<DedupStats>, Error>;
+
     /// Save the given snapshot to the data source.
     fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Error>;
 
     /// Retrieve a snapshot by its digest, returning `None` if not found.
     fn get_snapshot(&self, digest: &Checksum) -> Result<Option<Snapshot>, Error>;
 
+    /// Remove the snapshot with the given digest.
+    fn delete_snapshot(&self, digest: &Checksum) -> Result<(), Error>;
+
     /// Retrieve the path to the database files.
     fn get_db_path(&self) -> PathBuf;
 
@@ -159,6 +318,9 @@ pub trait EntityDataSource: Send + Sync {
 
     /// Retrieve the counts of the various record types in the data source.
     fn get_entity_counts(&self) -> Result<RecordCounts, Error>;
+
+    /// Reclaim the space occupied by deleted and overwritten records.
+    fn compact_database(&self) -> Result<(), Error>;
 }
 
 /// Implementation of the entity data source backed by RocksDB.
@@ -178,6 +340,17 @@ impl EntityDataSourceImpl {
         let database = Mutex::new(database_rocks::Database::new(db_path)?);
         Ok(Self { database })
     }
+
+    /// Restore the database backup found at `backup_path` into `db_path`, a
+    /// location other than that of the live database, and open the result.
+    pub fn from_backup<P: AsRef<Path>>(backup_path: P, db_path: P) -> Result<Self, Error> {
+        std::fs::create_dir_all(&db_path)?;
+        database_rocks::Database::restore_from_backup(
+            Some(backup_path.as_ref().to_path_buf()),
+            db_path.as_ref(),
+        )?;
+        Self::new(db_path)
+    }
 }
 
 impl EntityDataSource for EntityDataSourceImpl {
@@ -204,6 +377,50 @@ impl EntityDataSource for EntityDataSourceImpl {
         db.put_document(key.as_bytes(), &encoded)
     }
 
+    fn put_schema_version(&self, version: u32) -> Result<(), Error> {
+        let key = "schema_version";
+        let as_string = version.to_string();
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), as_string.as_bytes())
+    }
+
+    fn get_schema_version(&self) -> Result<Option<u32>, Error> {
+        let key = "schema_version";
+        let db = self.database.lock().unwrap();
+        let option = db.get_document(key.as_bytes())?;
+        match option {
+            Some(value) => {
+                let as_string = String::from_utf8(value)?;
+                Ok(Some(as_string.parse::<u32>()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_migration(&self, migration: &Migration) -> Result<(), Error> {
+        // zero padded such that the records are sorted by version
+        let key = format!("migration/{:08}", migration.version);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        MigrationDef::serialize(migration, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_migrations(&self) -> Result<Vec<Migration>, Error> {
+        let db = self.database.lock().unwrap();
+        let migrations = db.fetch_prefix("migration/")?;
+        let mut results: Vec<Migration> = Vec::new();
+        for (key, value) in migrations {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = MigrationDef::deserialize(&mut de)?;
+            result.version = key.parse::<u32>()?;
+            results.push(result);
+        }
+        results.sort_by_key(|m| m.version);
+        Ok(results)
+    }
+
     fn put_computer_id(&self, dataset: &str, computer_id: &str) -> Result<(), Error> {
         let key = format!("computer/{}", dataset);
         let db = self.database.lock().unwrap();
@@ -257,6 +474,109 @@ impl EntityDataSource for EntityDataSourceImpl {
         db.delete_document(key.as_bytes())
     }
 
+    fn put_bandwidth(&self, store: &str, usage: &BandwidthUsage) -> Result<(), Error> {
+        let key = format!("bandwidth/{}/{}", store, usage.month);
+        // use simple approach as serde can be tricky to compile
+        let as_string = format!("{}:{}", usage.uploaded, usage.downloaded);
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), as_string.as_bytes())
+    }
+
+    fn get_bandwidth(&self, store: &str, month: &str) -> Result<Option<BandwidthUsage>, Error> {
+        let key = format!("bandwidth/{}/{}", store, month);
+        let db = self.database.lock().unwrap();
+        let option = db.get_document(key.as_bytes())?;
+        match option {
+            Some(value) => {
+                let as_string = String::from_utf8(value)?;
+                let (up, down) = as_string
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!(format!("invalid bandwidth record: {}", as_string)))?;
+                let mut usage = BandwidthUsage::new(month);
+                usage.uploaded = up.parse::<u64>()?;
+                usage.downloaded = down.parse::<u64>()?;
+                Ok(Some(usage))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_store_usage(&self, store: &str, usage: &StoreUsage) -> Result<(), Error> {
+        let key = format!("usage/{}", store);
+        let as_string = format!("{}:{}", usage.objects, usage.bytes);
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), as_string.as_bytes())
+    }
+
+    fn get_store_usage(&self, store: &str) -> Result<Option<StoreUsage>, Error> {
+        let key = format!("usage/{}", store);
+        let db = self.database.lock().unwrap();
+        let option = db.get_document(key.as_bytes())?;
+        match option {
+            Some(value) => {
+                let as_string = String::from_utf8(value)?;
+                let (objects, bytes) = as_string
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!(format!("invalid usage record: {}", as_string)))?;
+                Ok(Some(StoreUsage {
+                    objects: objects.parse::<u64>()?,
+                    bytes: bytes.parse::<u64>()?,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_checkpoint(
+        &self,
+        location: &PackLocation,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), Error> {
+        let key = checkpoint_key(location);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        CheckpointDef::serialize(checkpoint, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_checkpoint(&self, location: &PackLocation) -> Result<Option<Checkpoint>, Error> {
+        let key = checkpoint_key(location);
+        let db = self.database.lock().unwrap();
+        let encoded = db.get_document(key.as_bytes())?;
+        match encoded {
+            Some(value) => {
+                let mut de = serde_cbor::Deserializer::from_slice(&value);
+                let result = CheckpointDef::deserialize(&mut de)?;
+                Ok(Some(result))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn delete_checkpoint(&self, location: &PackLocation) -> Result<(), Error> {
+        let key = checkpoint_key(location);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn get_checkpoints(&self) -> Result<Vec<(PackLocation, Checkpoint)>, Error> {
+        let mut results: Vec<(PackLocation, Checkpoint)> = Vec::new();
+        let db = self.database.lock().unwrap();
+        db.scan_prefix("checkpoint/", &mut |key, value| {
+            // remainder of the key is the store, bucket, and object
+            let parts: Vec<&str> = key.splitn(3, '/').collect();
+            if parts.len() == 3 {
+                let mut de = serde_cbor::Deserializer::from_slice(value);
+                let checkpoint = CheckpointDef::deserialize(&mut de)?;
+                let location = PackLocation::new(parts[0], parts[1], parts[2]);
+                results.push((location, checkpoint));
+            }
+            Ok(true)
+        })?;
+        Ok(results)
+    }
+
     fn insert_chunk(&self, chunk: &Chunk) -> Result<(), Error> {
         let key = format!("chunk/{}", chunk.digest);
         let mut encoded: Vec<u8> = Vec::new();
@@ -281,6 +601,40 @@ impl EntityDataSource for EntityDataSourceImpl {
         }
     }
 
+    fn put_chunk(&self, chunk: &Chunk) -> Result<(), Error> {
+        let key = format!("chunk/{}", chunk.digest);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        ChunkDef::serialize(chunk, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn delete_chunk(&self, digest: &Checksum) -> Result<(), Error> {
+        let key = format!("chunk/{}", digest);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn visit_chunks(
+        &self,
+        visitor: &mut dyn FnMut(Chunk) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let db = self.database.lock().unwrap();
+        db.scan_prefix("chunk/", &mut |key, value| {
+            let mut de = serde_cbor::Deserializer::from_slice(value);
+            let mut result = ChunkDef::deserialize(&mut de)?;
+            let digest: Result<Checksum, Error> = FromStr::from_str(key);
+            match digest {
+                Ok(value) => {
+                    result.digest = value;
+                    visitor(result)
+                }
+                Err(_) => Ok(true),
+            }
+        })
+    }
+
     fn insert_pack(&self, pack: &Pack) -> Result<(), Error> {
         let key = format!("pack/{}", pack.digest);
         let mut encoded: Vec<u8> = Vec::new();
@@ -314,42 +668,52 @@ impl EntityDataSource for EntityDataSourceImpl {
         }
     }
 
-    fn get_packs(&self, store_id: &str) -> Result<Vec<Pack>, Error> {
+    fn delete_pack(&self, digest: &Checksum) -> Result<(), Error> {
+        let key = format!("pack/{}", digest);
         let db = self.database.lock().unwrap();
-        let packs = db.fetch_prefix("pack/")?;
+        db.delete_document(key.as_bytes())
+    }
+
+    fn get_packs(&self, store_id: &str) -> Result<Vec<Pack>, Error> {
         let mut results: Vec<Pack> = Vec::new();
-        for (key, value) in packs {
-            let mut de = serde_cbor::Deserializer::from_slice(&value);
-            let mut result = PackDef::deserialize(&mut de)?;
-            // pack must have at least one pack location whose store identifier
-            // matches the one given
-            if result.locations.iter().any(|l| l.store == store_id) {
-                // strip leading "pack/" from 'key' and convert to a Checksum
-                let digest: Result<Checksum, Error> = FromStr::from_str(&key);
-                if let Ok(value) = digest {
-                    result.digest = value;
-                    results.push(result);
-                }
+        // pack must have at least one pack location whose store identifier
+        // matches the one given
+        self.visit_packs(&mut |pack| {
+            if pack.locations.iter().any(|l| l.store == store_id) {
+                results.push(pack);
             }
-        }
+            Ok(true)
+        })?;
         Ok(results)
     }
 
     fn get_all_packs(&self) -> Result<Vec<Pack>, Error> {
-        let db = self.database.lock().unwrap();
-        let packs = db.fetch_prefix("pack/")?;
         let mut results: Vec<Pack> = Vec::new();
-        for (key, value) in packs {
-            let mut de = serde_cbor::Deserializer::from_slice(&value);
+        self.visit_packs(&mut |pack| {
+            results.push(pack);
+            Ok(true)
+        })?;
+        Ok(results)
+    }
+
+    fn visit_packs(
+        &self,
+        visitor: &mut dyn FnMut(Pack) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let db = self.database.lock().unwrap();
+        db.scan_prefix("pack/", &mut |key, value| {
+            let mut de = serde_cbor::Deserializer::from_slice(value);
             let mut result = PackDef::deserialize(&mut de)?;
             // strip leading "pack/" from 'key' and convert to a Checksum
-            let digest: Result<Checksum, Error> = FromStr::from_str(&key);
-            if let Ok(value) = digest {
-                result.digest = value;
-                results.push(result);
+            let digest: Result<Checksum, Error> = FromStr::from_str(key);
+            match digest {
+                Ok(value) => {
+                    result.digest = value;
+                    visitor(result)
+                }
+                Err(_) => Ok(true),
             }
-        }
-        Ok(results)
+        })
     }
 
     fn insert_database(&self, pack: &Pack) -> Result<(), Error> {
@@ -429,6 +793,40 @@ impl EntityDataSource for EntityDataSourceImpl {
         }
     }
 
+    fn put_file(&self, file: &File) -> Result<(), Error> {
+        let key = format!("file/{}", file.digest);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        FileDef::serialize(file, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn delete_file(&self, digest: &Checksum) -> Result<(), Error> {
+        let key = format!("file/{}", digest);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn visit_files(
+        &self,
+        visitor: &mut dyn FnMut(File) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let db = self.database.lock().unwrap();
+        db.scan_prefix("file/", &mut |key, value| {
+            let mut de = serde_cbor::Deserializer::from_slice(value);
+            let mut result = FileDef::deserialize(&mut de)?;
+            let digest: Result<Checksum, Error> = FromStr::from_str(key);
+            match digest {
+                Ok(value) => {
+                    result.digest = value;
+                    visitor(result)
+                }
+                Err(_) => Ok(true),
+            }
+        })
+    }
+
     fn insert_tree(&self, tree: &Tree) -> Result<(), Error> {
         let key = format!("tree/{}", tree.digest);
         let encoded: Vec<u8> = serde_cbor::to_vec(&tree)?;
@@ -536,6 +934,257 @@ impl EntityDataSource for EntityDataSourceImpl {
         db.delete_document(key.as_bytes())
     }
 
+    fn put_trashed_dataset(&self, trashed: &TrashedDataset) -> Result<(), Error> {
+        let key = format!("trash/{}", trashed.dataset.id);
+        // deletion time in milliseconds followed by the dataset itself
+        let mut encoded: Vec<u8> = Vec::new();
+        encoded.extend_from_slice(&trashed.deleted.timestamp_millis().to_be_bytes());
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        DatasetDef::serialize(&trashed.dataset, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_trashed_datasets(&self) -> Result<Vec<TrashedDataset>, Error> {
+        let db = self.database.lock().unwrap();
+        let datasets = db.fetch_prefix("trash/")?;
+        let mut results: Vec<TrashedDataset> = Vec::new();
+        for (key, value) in datasets {
+            if value.len() < 8 {
+                return Err(anyhow!(format!("invalid trash record: {}", key)));
+            }
+            let (millis, value) = value.split_at(8);
+            let millis = i64::from_be_bytes(millis.try_into()?);
+            let deleted = chrono::DateTime::from_timestamp_millis(millis)
+                .ok_or_else(|| anyhow!(format!("invalid trash record: {}", key)))?;
+            let mut de = serde_cbor::Deserializer::from_slice(value);
+            let mut dataset = DatasetDef::deserialize(&mut de)?;
+            dataset.id = key;
+            results.push(TrashedDataset { dataset, deleted });
+        }
+        Ok(results)
+    }
+
+    fn delete_trashed_dataset(&self, id: &str) -> Result<(), Error> {
+        let key = format!("trash/{}", id);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn put_device(&self, device: &Device) -> Result<(), Error> {
+        let key = format!("device/{}", device.id);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        DeviceDef::serialize(device, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_devices(&self) -> Result<Vec<Device>, Error> {
+        let db = self.database.lock().unwrap();
+        let devices = db.fetch_prefix("device/")?;
+        let mut results: Vec<Device> = Vec::new();
+        for (key, value) in devices {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = DeviceDef::deserialize(&mut de)?;
+            result.id = key;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn delete_device(&self, id: &str) -> Result<(), Error> {
+        let key = format!("device/{}", id);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn put_webhook(&self, webhook: &Webhook) -> Result<(), Error> {
+        let key = format!("webhook/{}", webhook.id);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        WebhookDef::serialize(webhook, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        let db = self.database.lock().unwrap();
+        let webhooks = db.fetch_prefix("webhook/")?;
+        let mut results: Vec<Webhook> = Vec::new();
+        for (key, value) in webhooks {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = WebhookDef::deserialize(&mut de)?;
+            result.id = key;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn delete_webhook(&self, id: &str) -> Result<(), Error> {
+        let key = format!("webhook/{}", id);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn put_event(&self, event: &Event) -> Result<(), Error> {
+        let key = format!("event/{}", event.id);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        EventDef::serialize(event, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_events(&self) -> Result<Vec<Event>, Error> {
+        let db = self.database.lock().unwrap();
+        let events = db.fetch_prefix("event/")?;
+        let mut results: Vec<Event> = Vec::new();
+        for (key, value) in events {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = EventDef::deserialize(&mut de)?;
+            result.id = key;
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn delete_event(&self, id: &str) -> Result<(), Error> {
+        let key = format!("event/{}", id);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn put_verification(&self, verification: &Verification) -> Result<(), Error> {
+        let key = format!("verify/{}/{}", verification.dataset_id, verification.id);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        VerificationDef::serialize(verification, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_verifications(&self, dataset: &str) -> Result<Vec<Verification>, Error> {
+        let prefix = format!("verify/{}/", dataset);
+        let db = self.database.lock().unwrap();
+        let verifications = db.fetch_prefix(&prefix)?;
+        let mut results: Vec<Verification> = Vec::new();
+        for (key, value) in verifications {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = VerificationDef::deserialize(&mut de)?;
+            result.id = key;
+            result.dataset_id = dataset.to_owned();
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn delete_verification(&self, dataset: &str, id: &str) -> Result<(), Error> {
+        let key = format!("verify/{}/{}", dataset, id);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
+    fn put_task_run(&self, run: &MaintenanceResult) -> Result<(), Error> {
+        let dataset = run
+            .dataset_id
+            .as_ref()
+            .ok_or_else(|| anyhow!("task {} was not run for a dataset", run.task))?;
+        let key = format!("taskrun/{}/{}", dataset, run.task);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        MaintenanceResultDef::serialize(run, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_task_runs(&self, dataset: &str) -> Result<Vec<MaintenanceResult>, Error> {
+        let prefix = format!("taskrun/{}/", dataset);
+        let db = self.database.lock().unwrap();
+        let runs = db.fetch_prefix(&prefix)?;
+        let mut results: Vec<MaintenanceResult> = Vec::new();
+        for (_, value) in runs {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = MaintenanceResultDef::deserialize(&mut de)?;
+            result.dataset_id = Some(dataset.to_owned());
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn put_chain_entry(&self, entry: &ChainEntry) -> Result<(), Error> {
+        // pad the sequence so the entries are fetched in order
+        let key = format!("chain/{}/{:020}", entry.store, entry.sequence);
+        let mut encoded: Vec<u8> = Vec::new();
+        let mut ser = serde_cbor::Serializer::new(&mut encoded);
+        ChainEntryDef::serialize(entry, &mut ser)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_chain_entries(&self, store_id: &str) -> Result<Vec<ChainEntry>, Error> {
+        let prefix = format!("chain/{}/", store_id);
+        let db = self.database.lock().unwrap();
+        let entries = db.fetch_prefix(&prefix)?;
+        let mut results: Vec<ChainEntry> = Vec::new();
+        for (_key, value) in entries {
+            let mut de = serde_cbor::Deserializer::from_slice(&value);
+            let mut result = ChainEntryDef::deserialize(&mut de)?;
+            result.store = store_id.to_owned();
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    fn put_chain_location(&self, location: &PackLocation) -> Result<(), Error> {
+        let key = format!("chainlog/{}", location.store);
+        let encoded = serde_cbor::to_vec(location)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_chain_locations(&self) -> Result<Vec<PackLocation>, Error> {
+        let db = self.database.lock().unwrap();
+        let locations = db.fetch_prefix("chainlog/")?;
+        let mut results: Vec<PackLocation> = Vec::new();
+        for (_key, value) in locations {
+            results.push(serde_cbor::from_slice(&value)?);
+        }
+        Ok(results)
+    }
+
+    fn put_catalog(&self, store_id: &str, catalog: &Catalog) -> Result<(), Error> {
+        let key = format!("catalog/{}", store_id);
+        let encoded = encode_catalog(catalog)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_catalog(&self, store_id: &str) -> Result<Option<Catalog>, Error> {
+        let key = format!("catalog/{}", store_id);
+        let db = self.database.lock().unwrap();
+        match db.get_document(key.as_bytes())? {
+            Some(value) => Ok(Some(decode_catalog(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put_dedup_stats(&self, stats: &DedupStats) -> Result<(), Error> {
+        let key = format!("dedup/{}", stats.dataset_id);
+        let encoded = encode_dedup(stats)?;
+        let db = self.database.lock().unwrap();
+        db.put_document(key.as_bytes(), &encoded)
+    }
+
+    fn get_dedup_stats(&self, dataset: &str) -> Result<Option<DedupStats>, Error> {
+        let key = format!("dedup/{}", dataset);
+        let db = self.database.lock().unwrap();
+        match db.get_document(key.as_bytes())? {
+            Some(value) => Ok(Some(decode_dedup(&value)?)),
+            None => Ok(None),
+        }
+    }
+
     fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Error> {
         let key = format!("snapshot/{}", snapshot.digest);
         let mut encoded: Vec<u8> = Vec::new();
@@ -560,6 +1209,12 @@ impl EntityDataSource for EntityDataSourceImpl {
         }
     }
 
+    fn delete_snapshot(&self, digest: &Checksum) -> Result<(), Error> {
+        let key = format!("snapshot/{}", digest);
+        let db = self.database.lock().unwrap();
+        db.delete_document(key.as_bytes())
+    }
+
     fn get_db_path(&self) -> PathBuf {
         let db = self.database.lock().unwrap();
         db.get_path().to_path_buf()
@@ -571,17 +1226,26 @@ impl EntityDataSource for EntityDataSourceImpl {
     }
 
     fn restore_from_backup(&self, path: Option<PathBuf>) -> Result<(), Error> {
+        let db_path = self.database.lock().unwrap().get_path().to_path_buf();
+        let backup_path = path.unwrap_or_else(|| db_path.with_extension("backup"));
+        // Restore a scratch copy first and check that this build can read it,
+        // before the current database is replaced.
+        let outdir = tempfile::tempdir()?;
+        let checkdb = outdir.path().join("zoricheck");
+        database_rocks::Database::restore_from_backup(Some(backup_path.clone()), &checkdb)?;
+        let restored = EntityDataSourceImpl::new(&checkdb)?;
+        let version = restored.get_schema_version()?.unwrap_or(0);
+        drop(restored);
+        migrate::check_version(version)?;
         // Create a temporary database in order to release the lock on the
         // current database, then restore from the backup, and finish by
         // creating a new database instance using the restored data.
-        let outdir = tempfile::tempdir()?;
         let tmpdb = outdir.path().join("zoritempura");
         let mut db = self.database.lock().unwrap();
-        let db_path = db.get_path().to_path_buf();
         debug!("restore_from_backup opening tmp db in {:?}", tmpdb);
         *db = database_rocks::Database::new(tmpdb)?;
         drop(db);
-        database_rocks::Database::restore_from_backup(path, &db_path)?;
+        database_rocks::Database::restore_from_backup(Some(backup_path), &db_path)?;
         let mut db = self.database.lock().unwrap();
         *db = database_rocks::Database::new(&db_path)?;
         debug!("restore_from_backup open new db in {:?}", db_path);
@@ -609,6 +1273,11 @@ impl EntityDataSource for EntityDataSourceImpl {
             xattr: xattrs,
         })
     }
+
+    fn compact_database(&self) -> Result<(), Error> {
+        let db = self.database.lock().unwrap();
+        db.compact()
+    }
 }
 
 ///
@@ -636,6 +1305,19 @@ pub trait PackDataSource: Send + Sync {
     /// given path.
     fn retrieve_pack(&self, location: &PackLocation, outfile: &Path) -> Result<(), Error>;
 
+    /// Return the hex-encoded MD5 digest that the store keeps for the object
+    /// at the given location, without retrieving the object, or `None` if the
+    /// store does not have a usable digest.
+    fn object_md5(&self, location: &PackLocation) -> Result<Option<String>, Error>;
+
+    /// Return the size in bytes of the object at the given location, without
+    /// retrieving the object, or `None` if the store cannot report the size.
+    fn object_size(&self, location: &PackLocation) -> Result<Option<u64>, Error>;
+
+    /// Discard the partial upload to the location recorded in the checkpoint,
+    /// for those stores that would otherwise retain the parts sent so far.
+    fn abort_upload(&self, location: &PackLocation, checkpoint: &Checkpoint) -> Result<(), Error>;
+
     /// List the known buckets in the repository.
     fn list_buckets(&self) -> Result<Vec<String>, Error>;
 
@@ -649,6 +1331,16 @@ pub trait PackDataSource: Send + Sync {
     /// use `list_objects()` and `delete_object()` to remove the objects.
     fn delete_bucket(&self, bucket: &str) -> Result<(), Error>;
 
+    /// Move the named object to the given storage class (or access tier),
+    /// returning `false` if the object was already in that class. Stores that
+    /// do not have storage classes will return an error.
+    fn set_storage_class(&self, bucket: &str, object: &str, class: &str) -> Result<bool, Error>;
+
+    /// Replace the lifecycle rules of the named bucket with the given rule, or
+    /// remove them if the rule is empty. Stores that do not support lifecycle
+    /// rules will return an error.
+    fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error>;
+
     /// Store the database archive under the named bucket and referenced by the
     /// object name. Returns the remote location of the pack, in case it was
     /// assigned new values by the backing store.
@@ -674,33 +1366,156 @@ pub trait PackSourceBuilder {
     fn build_source(&self, store: &Store) -> Result<Box<dyn PackDataSource>, Error>;
 }
 
-pub struct PackSourceBuilderImpl {}
+#[derive(Default)]
+pub struct PackSourceBuilderImpl {
+    // where stores that can resume an upload will record their progress
+    #[cfg_attr(
+        not(any(
+            feature = "amazon",
+            feature = "azure",
+            feature = "google",
+            feature = "minio"
+        )),
+        allow(dead_code)
+    )]
+    checkpoints: Option<Arc<dyn CheckpointStore>>,
+}
+
+impl PackSourceBuilderImpl {
+    /// Construct a builder whose stores will record the progress of uploads
+    /// in the given checkpoint store, if they are capable of resuming.
+    pub fn with_checkpoints(checkpoints: Arc<dyn CheckpointStore>) -> Self {
+        Self {
+            checkpoints: Some(checkpoints),
+        }
+    }
+}
 
 impl PackSourceBuilder for PackSourceBuilderImpl {
     fn build_source(&self, store: &Store) -> Result<Box<dyn PackDataSource>, Error> {
         // If it helps any, could cache the pack source by the store id to avoid
         // repeatedly constructing the same thing. The lru crate would be perfect
         // for managing the cache.
-        let source: Box<dyn PackDataSource> = match store.store_type {
-            StoreType::AMAZON => Box::new(amazon::AmazonPackSource::new(store)?),
-            StoreType::AZURE => Box::new(azure::AzurePackSource::new(store)?),
-            StoreType::LOCAL => Box::new(local::LocalPackSource::new(store)?),
-            StoreType::GOOGLE => Box::new(google::GooglePackSource::new(store)?),
-            StoreType::MINIO => Box::new(minio::MinioPackSource::new(store)?),
-            StoreType::SFTP => Box::new(sftp::SftpPackSource::new(store)?),
+        let props = &store.properties;
+        let source = match store.store_type {
+            #[cfg(feature = "amazon")]
+            StoreType::AMAZON => {
+                let mut amazon = store_s3::S3Store::new(&store.id, props)?;
+                if let Some(checkpoints) = self.checkpoints.as_ref() {
+                    amazon = amazon.with_checkpoints(checkpoints.clone());
+                }
+                StorePackSource::detached(Box::new(amazon))
+            }
+            #[cfg(feature = "azure")]
+            StoreType::AZURE => {
+                let mut azure = store_azure::AzureStore::new(&store.id, props)?;
+                if let Some(checkpoints) = self.checkpoints.as_ref() {
+                    azure = azure.with_checkpoints(checkpoints.clone());
+                }
+                StorePackSource::detached(Box::new(azure))
+            }
+            #[cfg(feature = "dropbox")]
+            StoreType::DROPBOX => {
+                let dropbox = store_dropbox::DropboxStore::new(&store.id, props)?;
+                StorePackSource::new(Box::new(dropbox))
+            }
+            #[cfg(feature = "local")]
+            StoreType::LOCAL => {
+                StorePackSource::new(Box::new(store_local::LocalStore::new(&store.id, props)?))
+            }
+            #[cfg(feature = "google")]
+            StoreType::GOOGLE => {
+                let mut google = store_google::GoogleStore::new(&store.id, props)?;
+                if let Some(checkpoints) = self.checkpoints.as_ref() {
+                    google = google.with_checkpoints(checkpoints.clone());
+                }
+                StorePackSource::detached(Box::new(google))
+            }
+            #[cfg(feature = "memory")]
+            StoreType::MEMORY => StorePackSource::new(Box::new(
+                store_core::memory::MemoryStore::new(&store.id, props)?,
+            )),
+            #[cfg(feature = "minio")]
+            StoreType::MINIO => {
+                let mut minio = store_s3::S3Store::new_compatible(&store.id, props)?;
+                if let Some(checkpoints) = self.checkpoints.as_ref() {
+                    minio = minio.with_checkpoints(checkpoints.clone());
+                }
+                StorePackSource::detached(Box::new(minio))
+            }
+            #[cfg(feature = "rclone")]
+            StoreType::RCLONE => {
+                StorePackSource::new(Box::new(store_rclone::RcloneStore::new(&store.id, props)?))
+            }
+            #[cfg(feature = "sftp")]
+            StoreType::SFTP => {
+                StorePackSource::new(Box::new(store_sftp::SftpStore::new(&store.id, props)?))
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(anyhow!(
+                    "store type {} not supported by this build",
+                    store.store_type.to_string()
+                ))
+            }
         };
-        Ok(source)
+        Ok(Box::new(source))
     }
 }
 
+// Database key of the upload checkpoint for the given location.
+fn checkpoint_key(location: &PackLocation) -> String {
+    format!(
+        "checkpoint/{}/{}/{}",
+        location.store, location.bucket, location.object
+    )
+}
+
+///
+/// Return the types of stores that were compiled into this build, as selected
+/// by the cargo features of the same names.
+///
+pub fn supported_store_types() -> Vec<StoreType> {
+    #[allow(unused_mut)]
+    let mut types: Vec<StoreType> = Vec::new();
+    #[cfg(feature = "amazon")]
+    types.push(StoreType::AMAZON);
+    #[cfg(feature = "azure")]
+    types.push(StoreType::AZURE);
+    #[cfg(feature = "dropbox")]
+    types.push(StoreType::DROPBOX);
+    #[cfg(feature = "google")]
+    types.push(StoreType::GOOGLE);
+    #[cfg(feature = "local")]
+    types.push(StoreType::LOCAL);
+    #[cfg(feature = "memory")]
+    types.push(StoreType::MEMORY);
+    #[cfg(feature = "minio")]
+    types.push(StoreType::MINIO);
+    #[cfg(feature = "rclone")]
+    types.push(StoreType::RCLONE);
+    #[cfg(feature = "sftp")]
+    types.push(StoreType::SFTP);
+    types
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_supported_store_types() {
+        let types = supported_store_types();
+        assert_eq!(types.contains(&StoreType::LOCAL), cfg!(feature = "local"));
+        assert_eq!(types.contains(&StoreType::RCLONE), cfg!(feature = "rclone"));
+        assert_eq!(types.contains(&StoreType::SFTP), cfg!(feature = "sftp"));
+    }
+
+    #[cfg(feature = "local")]
     #[test]
     fn test_build_source_local() {
-        let builder = PackSourceBuilderImpl {};
+        let builder = PackSourceBuilderImpl::default();
         let mut properties: HashMap<String, String> = HashMap::new();
         properties.insert("basepath".to_owned(), "/tmp".to_owned());
         let store = Store {
@@ -714,9 +1529,62 @@ mod tests {
         assert!(!source.is_slow());
     }
 
+    #[cfg(feature = "memory")]
+    #[test]
+    fn test_build_source_memory() {
+        let builder = PackSourceBuilderImpl::default();
+        let store = Store {
+            id: "memory123".to_owned(),
+            store_type: StoreType::MEMORY,
+            label: "ephemeral".to_owned(),
+            properties: HashMap::new(),
+        };
+        let source = builder.build_source(&store).unwrap();
+        assert!(source.is_local());
+        assert!(!source.is_slow());
+    }
+
+    #[cfg(feature = "amazon")]
+    #[test]
+    fn test_build_source_amazon_endpoint() {
+        let builder = PackSourceBuilderImpl::default();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-east-1".to_owned());
+        properties.insert("endpoint".to_owned(), "https://s3.wasabisys.com".to_owned());
+        properties.insert("access_key".to_owned(), "wasabi".to_owned());
+        properties.insert("secret_key".to_owned(), "shwasabi".to_owned());
+        let store = Store {
+            id: "wasabi123".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "s3compatible".to_owned(),
+            properties,
+        };
+        let source = builder.build_source(&store).unwrap();
+        assert!(!source.is_local());
+        assert!(!source.is_slow());
+    }
+
+    #[cfg(feature = "dropbox")]
+    #[test]
+    fn test_build_source_dropbox() {
+        let builder = PackSourceBuilderImpl::default();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("access_token".to_owned(), "sl.t0k3n".to_owned());
+        let store = Store {
+            id: "dropbox123".to_owned(),
+            store_type: StoreType::DROPBOX,
+            label: "dropbox".to_owned(),
+            properties,
+        };
+        let source = builder.build_source(&store).unwrap();
+        assert!(!source.is_local());
+        assert!(!source.is_slow());
+    }
+
+    #[cfg(feature = "minio")]
     #[test]
     fn test_build_source_minio() {
-        let builder = PackSourceBuilderImpl {};
+        let builder = PackSourceBuilderImpl::default();
         let mut properties: HashMap<String, String> = HashMap::new();
         properties.insert("region".to_owned(), "us-west2".to_owned());
         properties.insert("endpoint".to_owned(), "localhost:9000".to_owned());
@@ -733,9 +1601,27 @@ mod tests {
         assert!(!source.is_slow());
     }
 
+    #[cfg(feature = "rclone")]
+    #[test]
+    fn test_build_source_rclone() {
+        let builder = PackSourceBuilderImpl::default();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        let store = Store {
+            id: "rclone123".to_owned(),
+            store_type: StoreType::RCLONE,
+            label: "backblaze".to_owned(),
+            properties,
+        };
+        let source = builder.build_source(&store).unwrap();
+        assert!(!source.is_local());
+        assert!(!source.is_slow());
+    }
+
+    #[cfg(feature = "sftp")]
     #[test]
     fn test_build_source_sftp() {
-        let builder = PackSourceBuilderImpl {};
+        let builder = PackSourceBuilderImpl::default();
         let mut properties: HashMap<String, String> = HashMap::new();
         properties.insert("remote_addr".to_owned(), "localhost:22".to_owned());
         properties.insert("username".to_owned(), "charlie".to_owned());
@@ -0,0 +1,140 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::data::sources::PackDataSource;
+use crate::domain::entities::PackLocation;
+use anyhow::{anyhow, Error};
+use std::path::Path;
+use std::thread;
+use store_core::{Checkpoint, Coordinates, LifecycleRule};
+
+///
+/// A `PackDataSource` implementation that dispatches to any of the pack store
+/// backends by way of the common trait defined in `store_core`.
+///
+pub struct StorePackSource {
+    store: Box<dyn store_core::PackDataSource>,
+    // true if the store runs its own async runtime to complete each operation
+    detached: bool,
+}
+
+impl StorePackSource {
+    /// Construct a pack source for a store with blocking operations.
+    pub fn new(store: Box<dyn store_core::PackDataSource>) -> Self {
+        Self {
+            store,
+            detached: false,
+        }
+    }
+
+    /// Construct a pack source for a store that is built on an asynchronous
+    /// client, whose operations will be invoked on a separate thread.
+    pub fn detached(store: Box<dyn store_core::PackDataSource>) -> Self {
+        Self {
+            store,
+            detached: true,
+        }
+    }
+
+    // Invoke the operation on the store, using a separate thread if needed.
+    fn invoke<T, F>(&self, op: F) -> Result<T, Error>
+    where
+        T: Send,
+        F: FnOnce(&dyn store_core::PackDataSource) -> Result<T, Error> + Send,
+    {
+        let store = self.store.as_ref();
+        if self.detached {
+            // work-around for async runtime not allowing block_on call
+            thread::scope(|s| {
+                s.spawn(move || op(store))
+                    .join()
+                    .map_err(|_| anyhow!("pack store operation panicked"))?
+            })
+        } else {
+            op(store)
+        }
+    }
+}
+
+impl PackDataSource for StorePackSource {
+    fn is_local(&self) -> bool {
+        self.store.is_local()
+    }
+
+    fn is_slow(&self) -> bool {
+        self.store.is_slow()
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<PackLocation, Error> {
+        let coords = self.invoke(|s| s.store_pack(packfile, bucket, object))?;
+        Ok(PackLocation::from(coords))
+    }
+
+    fn retrieve_pack(&self, location: &PackLocation, outfile: &Path) -> Result<(), Error> {
+        let coords: Coordinates = location.to_owned().into();
+        self.invoke(|s| s.retrieve_pack(&coords, outfile))
+    }
+
+    fn object_md5(&self, location: &PackLocation) -> Result<Option<String>, Error> {
+        let coords: Coordinates = location.to_owned().into();
+        self.invoke(|s| s.object_md5(&coords))
+    }
+
+    fn object_size(&self, location: &PackLocation) -> Result<Option<u64>, Error> {
+        let coords: Coordinates = location.to_owned().into();
+        self.invoke(|s| s.object_size(&coords))
+    }
+
+    fn abort_upload(&self, location: &PackLocation, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let coords: Coordinates = location.to_owned().into();
+        self.invoke(|s| s.abort_upload(&coords, checkpoint))
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.invoke(|s| s.list_buckets())
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.invoke(|s| s.list_objects(bucket))
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        self.invoke(|s| s.delete_object(bucket, object))
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        self.invoke(|s| s.delete_bucket(bucket))
+    }
+
+    fn set_storage_class(&self, bucket: &str, object: &str, class: &str) -> Result<bool, Error> {
+        self.invoke(|s| s.set_storage_class(bucket, object, class))
+    }
+
+    fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        self.invoke(|s| s.set_lifecycle(bucket, rule))
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<PackLocation, Error> {
+        let coords = self.invoke(|s| s.store_database(packfile, bucket, object))?;
+        Ok(PackLocation::from(coords))
+    }
+
+    fn retrieve_database(&self, location: &PackLocation, outfile: &Path) -> Result<(), Error> {
+        let coords: Coordinates = location.to_owned().into();
+        self.invoke(|s| s.retrieve_database(&coords, outfile))
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.invoke(|s| s.list_databases(bucket))
+    }
+}
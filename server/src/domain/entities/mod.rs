@@ -4,14 +4,16 @@
 use anyhow::{anyhow, Error};
 use base64::{engine::general_purpose, Engine as _};
 use chrono::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use store_core::Coordinates;
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
 pub mod schedule;
@@ -53,8 +55,15 @@ impl Checksum {
     ///
     pub fn blake3_from_file(infile: &Path) -> io::Result<Checksum> {
         let mut file = fs::File::open(infile)?;
+        Checksum::blake3_from_reader(&mut file)
+    }
+
+    ///
+    /// Compute the BLAKE3 hash digest of the data from the given reader.
+    ///
+    pub fn blake3_from_reader<R: io::Read>(reader: &mut R) -> io::Result<Checksum> {
         let mut hasher = blake3::Hasher::new();
-        io::copy(&mut file, &mut hasher)?;
+        io::copy(reader, &mut hasher)?;
         let digest = hasher.finalize();
         Ok(Checksum::BLAKE3(format!("{}", digest)))
     }
@@ -97,193 +106,2312 @@ impl fmt::Display for Checksum {
     }
 }
 
-impl FromStr for Checksum {
-    type Err = Error;
+impl FromStr for Checksum {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hash) = s.strip_prefix("sha1-") {
+            Ok(Checksum::SHA1(hash.to_owned()))
+        } else if let Some(hash) = s.strip_prefix("blake3-") {
+            Ok(Checksum::BLAKE3(hash.to_owned()))
+        } else {
+            Err(anyhow!(format!("not a recognized algorithm: {}", s)))
+        }
+    }
+}
+
+/// Parameters of the content-defined chunking of files, in bytes, and the
+/// level of normalization that narrows the spread of the chunk sizes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChunkerParams {
+    /// Smallest size of a chunk, other than the last chunk of a file.
+    pub min_size: u32,
+    /// Desired average size of the chunks.
+    pub avg_size: u32,
+    /// Largest size of a chunk.
+    pub max_size: u32,
+    /// Normalization level of FastCDC, from 0 (none) to 3.
+    pub normalization: u8,
+}
+
+impl ChunkerParams {
+    /// Construct parameters for the given average size, with the min/max
+    /// sizes being 0.25/4 times that size, and normalization level 1.
+    pub fn new(avg_size: u32) -> Self {
+        Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size.saturating_mul(4),
+            normalization: 1,
+        }
+    }
+}
+
+/// Represents a piece of a file, and possibly an entire file.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    /// The hash digest of the chunk, with algorithm prefix.
+    pub digest: Checksum,
+    /// The byte offset of this chunk within the file.
+    pub offset: usize,
+    /// The byte length of this chunk.
+    pub length: usize,
+    /// Path of the file from which the chunk is taken.
+    pub filepath: Option<PathBuf>,
+    /// Digest of packfile this chunk is stored within.
+    pub packfile: Option<Checksum>,
+}
+
+impl Chunk {
+    /// Construct a `Chunk` from the given values.
+    pub fn new(digest: Checksum, offset: usize, length: usize) -> Self {
+        Self {
+            digest,
+            offset,
+            length,
+            filepath: None,
+            packfile: None,
+        }
+    }
+
+    /// Add the filepath property.
+    pub fn filepath(mut self, filepath: &Path) -> Self {
+        self.filepath = Some(filepath.to_owned());
+        self
+    }
+
+    /// Add the packfile property.
+    pub fn packfile(mut self, packfile: Checksum) -> Self {
+        self.packfile = Some(packfile);
+        self
+    }
+}
+
+/// StoreType identifies a kind of store.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StoreType {
+    AMAZON,
+    AZURE,
+    DROPBOX,
+    GOOGLE,
+    LOCAL,
+    MEMORY,
+    MINIO,
+    RCLONE,
+    SFTP,
+}
+
+impl ToString for StoreType {
+    fn to_string(&self) -> String {
+        match self {
+            StoreType::AMAZON => String::from("amazon"),
+            StoreType::AZURE => String::from("azure"),
+            StoreType::DROPBOX => String::from("dropbox"),
+            StoreType::GOOGLE => String::from("google"),
+            StoreType::LOCAL => String::from("local"),
+            StoreType::MEMORY => String::from("memory"),
+            StoreType::MINIO => String::from("minio"),
+            StoreType::RCLONE => String::from("rclone"),
+            StoreType::SFTP => String::from("sftp"),
+        }
+    }
+}
+
+impl FromStr for StoreType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amazon" => Ok(StoreType::AMAZON),
+            "azure" => Ok(StoreType::AZURE),
+            "dropbox" => Ok(StoreType::DROPBOX),
+            "google" => Ok(StoreType::GOOGLE),
+            "local" => Ok(StoreType::LOCAL),
+            "memory" => Ok(StoreType::MEMORY),
+            "minio" => Ok(StoreType::MINIO),
+            "rclone" => Ok(StoreType::RCLONE),
+            "sftp" => Ok(StoreType::SFTP),
+            _ => Err(anyhow!(format!("not a recognized store type: {}", s))),
+        }
+    }
+}
+
+// Names of the store properties that hold credentials.
+const SECRET_PROPERTIES: &[&str] = &[
+    "access_key",
+    "access_token",
+    "app_secret",
+    "passphrase",
+    "password",
+    "private_key",
+    "refresh_token",
+    "sas_token",
+    "secret_key",
+];
+
+///
+/// Value given to clients in place of a store credential. When a store is
+/// updated with this value, the saved credential is kept.
+///
+pub const REDACTED_PROPERTY: &str = "[REDACTED]";
+
+/// Store defines a location where packs will be saved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Store {
+    /// Unique identifier for this store.
+    pub id: String,
+    /// Type of this store.
+    pub store_type: StoreType,
+    /// User-defined label for this store.
+    pub label: String,
+    /// Name/value pairs that make up this store configuration.
+    pub properties: HashMap<String, String>,
+}
+
+impl Store {
+    /// Return `true` if the named property holds a credential, which is never
+    /// given to clients.
+    pub fn is_secret_property(name: &str) -> bool {
+        SECRET_PROPERTIES.contains(&name)
+    }
+
+    /// Return the properties with each credential replaced by a placeholder.
+    pub fn redacted_properties(&self) -> HashMap<String, String> {
+        let mut properties = self.properties.clone();
+        for (name, value) in properties.iter_mut() {
+            if Store::is_secret_property(name) && !value.is_empty() {
+                *value = REDACTED_PROPERTY.to_owned();
+            }
+        }
+        properties
+    }
+
+    /// Replace each credential placeholder with the value of the same property
+    /// in the saved store, or remove it if the saved store has none.
+    pub fn restore_secrets(&mut self, saved: &Store) {
+        self.properties.retain(|name, value| {
+            if value == REDACTED_PROPERTY && Store::is_secret_property(name) {
+                match saved.properties.get(name) {
+                    Some(secret) => {
+                        *value = secret.to_owned();
+                        true
+                    }
+                    None => false,
+                }
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Return an entity tag that changes whenever the store configuration
+    /// changes, for detecting concurrent modification.
+    pub fn etag(&self) -> String {
+        let fields = [
+            self.id.clone(),
+            self.store_type.to_string(),
+            self.label.clone(),
+        ];
+        compute_etag(&fields, &self.properties)
+    }
+
+    /// Return the tiering policy given by the `tiering_days` and
+    /// `tiering_class` properties, if both are defined and valid.
+    pub fn tiering_policy(&self) -> Option<TieringPolicy> {
+        let days = self.properties.get("tiering_days")?.parse::<u32>().ok()?;
+        let storage_class = self.properties.get("tiering_class")?;
+        if days == 0 || storage_class.is_empty() {
+            return None;
+        }
+        Some(TieringPolicy {
+            days,
+            storage_class: storage_class.to_owned(),
+        })
+    }
+
+    /// Return the maximum number of bytes that may be transferred to and from
+    /// the store in a calendar month, as given by the `monthly_cap` property.
+    pub fn monthly_cap(&self) -> Option<u64> {
+        let cap = self.properties.get("monthly_cap")?.parse::<u64>().ok()?;
+        if cap == 0 {
+            None
+        } else {
+            Some(cap)
+        }
+    }
+
+    /// Return the limits on the total size and number of objects in the store,
+    /// as given by the `quota_bytes` and `quota_objects` properties.
+    pub fn quota(&self) -> Option<StoreQuota> {
+        let read = |name: &str| {
+            let value = self.properties.get(name)?.parse::<u64>().ok()?;
+            if value == 0 {
+                None
+            } else {
+                Some(value)
+            }
+        };
+        let quota = StoreQuota {
+            max_bytes: read("quota_bytes"),
+            max_objects: read("quota_objects"),
+        };
+        if quota.max_bytes.is_none() && quota.max_objects.is_none() {
+            None
+        } else {
+            Some(quota)
+        }
+    }
+
+    /// Return the object size limits imposed by the provider behind this store,
+    /// taking into account the storage classes named in the `storage`,
+    /// `tiering_class`, and `lifecycle_class` properties (or `access_tier` for
+    /// Azure).
+    pub fn object_size_limits(&self) -> ObjectSizeLimits {
+        let classes: Vec<String> = ["storage", "tiering_class", "lifecycle_class", "access_tier"]
+            .iter()
+            .filter_map(|name| self.properties.get(*name))
+            .map(|c| c.to_uppercase())
+            .collect();
+        // objects in the colder classes removed before the minimum duration
+        // are billed as if they had been kept that long
+        let min_days = |durations: &[(&str, u32)]| {
+            durations
+                .iter()
+                .filter(|(class, _)| classes.iter().any(|c| c == class))
+                .map(|(_, days)| *days)
+                .max()
+                .unwrap_or(0)
+        };
+        match self.store_type {
+            StoreType::AMAZON => {
+                // infrequent access and instant retrieval classes bill every
+                // object as if it were at least 128 KiB
+                let infrequent = classes
+                    .iter()
+                    .any(|c| matches!(c.as_str(), "STANDARD_IA" | "ONEZONE_IA" | "GLACIER_IR"));
+                let min_billable = if infrequent { 131_072 } else { 0 };
+                ObjectSizeLimits {
+                    // largest object that can be uploaded in a single request
+                    max_object: Some(5_368_709_120),
+                    min_billable,
+                    min_days: min_days(&[
+                        ("STANDARD_IA", 30),
+                        ("ONEZONE_IA", 30),
+                        ("GLACIER_IR", 90),
+                        ("GLACIER", 90),
+                        ("DEEP_ARCHIVE", 180),
+                    ]),
+                }
+            }
+            StoreType::AZURE => ObjectSizeLimits {
+                // largest block blob that can be uploaded in a single request
+                max_object: Some(5_242_880_000),
+                min_billable: 0,
+                min_days: min_days(&[("COOL", 30), ("COLD", 90), ("ARCHIVE", 180)]),
+            },
+            StoreType::DROPBOX => ObjectSizeLimits {
+                // largest file that can be uploaded via an upload session
+                max_object: Some(375_809_638_400),
+                min_billable: 0,
+                min_days: 0,
+            },
+            StoreType::GOOGLE => ObjectSizeLimits {
+                max_object: Some(5_497_558_138_880),
+                min_billable: 0,
+                min_days: min_days(&[("NEARLINE", 30), ("COLDLINE", 90), ("ARCHIVE", 365)]),
+            },
+            StoreType::MINIO => ObjectSizeLimits {
+                max_object: Some(5_368_709_120),
+                min_billable: 0,
+                min_days: 0,
+            },
+            // the limits of the provider behind rclone are not known
+            StoreType::LOCAL | StoreType::MEMORY | StoreType::RCLONE | StoreType::SFTP => {
+                Default::default()
+            }
+        }
+    }
+}
+
+/// Limits on the size of objects imposed by the provider behind a store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjectSizeLimits {
+    /// Largest object the store will accept, if there is a limit.
+    pub max_object: Option<u64>,
+    /// Objects smaller than this are billed as if they were this size.
+    pub min_billable: u64,
+    /// Objects removed sooner than this many days after being uploaded are
+    /// billed as if they had been kept this long.
+    pub min_days: u32,
+}
+
+impl ObjectSizeLimits {
+    /// Combine the limits of all the given stores, such that a pack size that
+    /// satisfies the result will satisfy every store.
+    pub fn for_stores(stores: &[Store]) -> Self {
+        let mut limits: ObjectSizeLimits = Default::default();
+        for store in stores.iter() {
+            let other = store.object_size_limits();
+            limits.max_object = match (limits.max_object, other.max_object) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            limits.min_billable = limits.min_billable.max(other.min_billable);
+            limits.min_days = limits.min_days.max(other.min_days);
+        }
+        limits
+    }
+
+    /// Return an explanation if the given pack size would produce packs that
+    /// are rejected by, or uneconomical for, the store(s).
+    pub fn check(&self, pack_size: u64) -> Option<String> {
+        if let Some(max) = self.max_object {
+            if pack_size > max {
+                return Some(format!(
+                    "pack size {} exceeds the store limit of {} bytes",
+                    pack_size, max
+                ));
+            }
+        }
+        if pack_size < self.min_billable {
+            return Some(format!(
+                "pack size {} is below the minimum billable object size of {} bytes",
+                pack_size, self.min_billable
+            ));
+        }
+        None
+    }
+
+    /// Return the given pack size adjusted to lie within the limits.
+    pub fn clamp(&self, pack_size: u64) -> u64 {
+        let size = pack_size.max(self.min_billable);
+        match self.max_object {
+            Some(max) => size.min(max),
+            None => size,
+        }
+    }
+}
+
+/// Number of bytes transferred to and from a store within a calendar month.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BandwidthUsage {
+    /// Calendar month in the form `YYYY-MM` (UTC).
+    pub month: String,
+    /// Number of bytes uploaded to the store.
+    pub uploaded: u64,
+    /// Number of bytes downloaded from the store.
+    pub downloaded: u64,
+}
+
+impl BandwidthUsage {
+    /// Construct an empty usage record for the given month.
+    pub fn new<T: Into<String>>(month: T) -> Self {
+        Self {
+            month: month.into(),
+            uploaded: 0,
+            downloaded: 0,
+        }
+    }
+
+    /// Return the calendar month in which the given time falls.
+    pub fn month_of(time: DateTime<Utc>) -> String {
+        time.format("%Y-%m").to_string()
+    }
+
+    /// Return the total number of bytes transferred.
+    pub fn total(&self) -> u64 {
+        self.uploaded + self.downloaded
+    }
+}
+
+/// Limits on the contents of a store, beyond which no more packs are added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreQuota {
+    /// Maximum total size of the objects in the store, in bytes.
+    pub max_bytes: Option<u64>,
+    /// Maximum number of objects in the store.
+    pub max_objects: Option<u64>,
+}
+
+impl StoreQuota {
+    /// Return true if adding an object of the given size would exceed the quota.
+    pub fn would_exceed(&self, usage: &StoreUsage, length: u64) -> bool {
+        let bytes = self.max_bytes.map(|m| usage.bytes + length > m);
+        let objects = self.max_objects.map(|m| usage.objects + 1 > m);
+        bytes.unwrap_or(false) || objects.unwrap_or(false)
+    }
+
+    /// Return true if the usage has reached the quota.
+    pub fn is_reached(&self, usage: &StoreUsage) -> bool {
+        let bytes = self.max_bytes.map(|m| usage.bytes >= m);
+        let objects = self.max_objects.map(|m| usage.objects >= m);
+        bytes.unwrap_or(false) || objects.unwrap_or(false)
+    }
+}
+
+/// Number and total size of the objects that have been added to a store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreUsage {
+    /// Number of objects in the store.
+    pub objects: u64,
+    /// Total size of the objects in the store, in bytes.
+    pub bytes: u64,
+}
+
+impl StoreUsage {
+    /// Account for the removal of the given number of objects, whose sizes are
+    /// not known, by assuming they are of average size.
+    pub fn remove_objects(&mut self, count: u64) {
+        if count >= self.objects {
+            self.objects = 0;
+            self.bytes = 0;
+        } else {
+            let average = self.bytes / self.objects;
+            self.objects -= count;
+            self.bytes = self.bytes.saturating_sub(average * count);
+        }
+    }
+}
+
+///
+/// Failed attempts to retrieve packs from a store, counted since the server
+/// started. A failure may be an error from the store or a pack whose digest
+/// does not match.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetrievalFailures {
+    /// Identifier of the store.
+    pub store: String,
+    /// Total number of failed retrievals.
+    pub failures: u64,
+    /// Number of failed retrievals since the last successful one.
+    pub consecutive: u64,
+    /// Message of the most recent failure.
+    pub last_error: Option<String>,
+    /// Date/time of the most recent failure.
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+impl RetrievalFailures {
+    /// Construct a record of no failures for the given store.
+    pub fn new(store: &str) -> Self {
+        Self {
+            store: store.to_owned(),
+            failures: 0,
+            consecutive: 0,
+            last_error: None,
+            last_failure: None,
+        }
+    }
+}
+
+impl std::hash::Hash for Store {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// Policy for moving packs that are no longer referenced by recent snapshots
+/// to a colder, less expensive, storage class.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TieringPolicy {
+    /// Packs whose most recent referencing snapshot is older than this many
+    /// days will be moved to the storage class.
+    pub days: u32,
+    /// Name of the storage class as understood by the store (e.g. `GLACIER_IR`
+    /// for Amazon, `COLDLINE` for Google, `Cool` for Azure).
+    pub storage_class: String,
+}
+
+/// Outcome of applying the tiering policy of a store.
+#[derive(Clone, Debug)]
+pub struct TieringResult {
+    /// Identifier of the store.
+    pub store_id: String,
+    /// Date/time when the policy was last applied.
+    pub finished: DateTime<Utc>,
+    /// Number of packs moved to the colder storage class.
+    pub transitioned: u64,
+    /// Number of eligible packs that were already in the storage class.
+    pub unchanged: u64,
+    /// Number of packs that were left as-is due to being recently referenced.
+    pub retained: u64,
+    /// Error message if the policy could not be applied completely.
+    pub error: Option<String>,
+}
+
+impl TieringResult {
+    /// Construct an empty result for the given store.
+    pub fn new(store_id: &str) -> Self {
+        Self {
+            store_id: store_id.to_owned(),
+            finished: Utc::now(),
+            transitioned: 0,
+            unchanged: 0,
+            retained: 0,
+            error: None,
+        }
+    }
+}
+
+/// Task that is performed automatically, either for the system as a whole
+/// during the maintenance window, or for a single dataset according to its own
+/// schedule, as given by the dataset properties.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MaintenanceTask {
+    /// Retrieve a sample of packs and verify their checksums.
+    Verify,
+    /// Remove objects from the stores that are not referenced by any pack.
+    Prune,
+    /// Reclaim space in the database from deleted and overwritten records.
+    Compact,
+    /// Test the connectivity of each of the stores.
+    Health,
+    /// Remove the snapshots of a dataset according to its retention policy.
+    PruneSnapshots,
+    /// Verify the latest snapshot of a dataset.
+    VerifySnapshot,
+    /// Upload a backup of the database to the stores of a dataset.
+    UploadDatabase,
+}
+
+impl MaintenanceTask {
+    /// The tasks that are scheduled for each dataset, in the order in which
+    /// they are performed.
+    pub const DATASET_TASKS: [MaintenanceTask; 3] = [
+        MaintenanceTask::PruneSnapshots,
+        MaintenanceTask::VerifySnapshot,
+        MaintenanceTask::UploadDatabase,
+    ];
+
+    /// Name of the dataset property that holds the schedule of this task, or
+    /// `None` if the task is not performed for individual datasets.
+    pub fn schedule_property(&self) -> Option<&'static str> {
+        match self {
+            MaintenanceTask::PruneSnapshots => Some("prune_schedule"),
+            MaintenanceTask::VerifySnapshot => Some("verify_schedule"),
+            MaintenanceTask::UploadDatabase => Some("database_schedule"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MaintenanceTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MaintenanceTask::Verify => write!(f, "verify"),
+            MaintenanceTask::Prune => write!(f, "prune"),
+            MaintenanceTask::Compact => write!(f, "compact"),
+            MaintenanceTask::Health => write!(f, "health"),
+            MaintenanceTask::PruneSnapshots => write!(f, "prune-snapshots"),
+            MaintenanceTask::VerifySnapshot => write!(f, "verify-snapshot"),
+            MaintenanceTask::UploadDatabase => write!(f, "upload-database"),
+        }
+    }
+}
+
+impl FromStr for MaintenanceTask {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "verify" => Ok(MaintenanceTask::Verify),
+            "prune" => Ok(MaintenanceTask::Prune),
+            "compact" => Ok(MaintenanceTask::Compact),
+            "health" => Ok(MaintenanceTask::Health),
+            "prune-snapshots" => Ok(MaintenanceTask::PruneSnapshots),
+            "verify-snapshot" => Ok(MaintenanceTask::VerifySnapshot),
+            "upload-database" => Ok(MaintenanceTask::UploadDatabase),
+            _ => Err(anyhow!(format!("not a recognized maintenance task: {}", s))),
+        }
+    }
+}
+
+/// Outcome of running a maintenance task.
+#[derive(Clone, Debug)]
+pub struct MaintenanceResult {
+    /// The task that was performed.
+    pub task: MaintenanceTask,
+    /// Identifier of the dataset for which the task was performed, if any.
+    pub dataset_id: Option<String>,
+    /// Date/time when the task started.
+    pub started: DateTime<Utc>,
+    /// Date/time when the task finished.
+    pub finished: DateTime<Utc>,
+    /// Brief description of what the task accomplished.
+    pub summary: String,
+    /// Error message if the task could not be completed.
+    pub error: Option<String>,
+}
+
+impl MaintenanceResult {
+    /// Construct an empty result for the given task.
+    pub fn new(task: MaintenanceTask) -> Self {
+        let now = Utc::now();
+        Self {
+            task,
+            dataset_id: None,
+            started: now,
+            finished: now,
+            summary: String::new(),
+            error: None,
+        }
+    }
+
+    /// Set the dataset for which the task is performed.
+    pub fn dataset(mut self, dataset_id: &str) -> Self {
+        self.dataset_id = Some(dataset_id.to_owned());
+        self
+    }
+}
+
+/// How thoroughly a snapshot is to be verified, each depth including the
+/// checks of those before it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum VerifyDepth {
+    /// Ensure the records referenced by the snapshot are in the database.
+    Metadata,
+    /// Retrieve each referenced pack and compare its checksum.
+    Packs,
+    /// Restore files to a temporary location and compare their digests.
+    Full,
+}
+
+impl fmt::Display for VerifyDepth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyDepth::Metadata => write!(f, "metadata"),
+            VerifyDepth::Packs => write!(f, "packs"),
+            VerifyDepth::Full => write!(f, "full"),
+        }
+    }
+}
+
+impl FromStr for VerifyDepth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "metadata" => Ok(VerifyDepth::Metadata),
+            "packs" => Ok(VerifyDepth::Packs),
+            "full" => Ok(VerifyDepth::Full),
+            _ => Err(anyhow!(format!("not a recognized verify depth: {}", s))),
+        }
+    }
+}
+
+/// How thoroughly the data is checked while restoring files, each level
+/// including the checks of those before it. Checking less makes restoring
+/// faster on slow hardware, at the risk of restoring corrupted data unnoticed.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RestoreVerify {
+    /// Trust the retrieved packs without computing any digests.
+    None,
+    /// Compare the digest of each retrieved pack with the one recorded.
+    #[default]
+    Pack,
+    /// Also compare the digest of each extracted chunk with its name.
+    Chunk,
+    /// Also compare the digest of each restored file with its record.
+    File,
+}
+
+impl fmt::Display for RestoreVerify {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreVerify::None => write!(f, "none"),
+            RestoreVerify::Pack => write!(f, "pack"),
+            RestoreVerify::Chunk => write!(f, "chunk"),
+            RestoreVerify::File => write!(f, "file"),
+        }
+    }
+}
+
+impl FromStr for RestoreVerify {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(RestoreVerify::None),
+            "pack" => Ok(RestoreVerify::Pack),
+            "chunk" => Ok(RestoreVerify::Chunk),
+            "file" => Ok(RestoreVerify::File),
+            _ => Err(anyhow!(format!("not a recognized verify level: {}", s))),
+        }
+    }
+}
+
+/// Outcome of verifying a snapshot, retained so that the results of periodic
+/// verifications can be reviewed later.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Verification {
+    /// Unique identifier of the verification.
+    pub id: String,
+    /// Identifier of the dataset to which the snapshot belongs.
+    pub dataset_id: String,
+    /// Digest of the snapshot that was verified.
+    pub snapshot: Checksum,
+    /// How thoroughly the snapshot was verified.
+    pub depth: VerifyDepth,
+    /// Date/time when the verification started.
+    pub started: DateTime<Utc>,
+    /// Date/time when the verification finished.
+    pub finished: DateTime<Utc>,
+    /// Number of distinct file records that were checked.
+    pub files: u64,
+    /// Number of packs that were retrieved and checked.
+    pub packs: u64,
+    /// Number of files that were restored and checked.
+    pub restored: u64,
+    /// Description of each problem that was found.
+    pub issues: Vec<String>,
+}
+
+impl Verification {
+    /// Construct an empty verification of the snapshot that starts now.
+    pub fn new(dataset_id: &str, snapshot: Checksum, depth: VerifyDepth) -> Self {
+        let now = Utc::now();
+        Self {
+            id: ulid::Ulid::new().to_string(),
+            dataset_id: dataset_id.to_owned(),
+            snapshot,
+            depth,
+            started: now,
+            finished: now,
+            files: 0,
+            packs: 0,
+            restored: 0,
+            issues: Vec::new(),
+        }
+    }
+
+    /// Return `true` if no problems were found.
+    pub fn passed(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Rules for which snapshots of a dataset are retained when pruning. A
+/// snapshot is retained if any rule calls for it, and the latest snapshot is
+/// always retained. The daily, weekly, monthly, and yearly rules keep the
+/// newest snapshot in each of that many of the most recent periods (in UTC)
+/// that have any snapshots, in the manner of grandfather-father-son rotation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RetentionPolicy {
+    /// Keep this many of the most recent snapshots.
+    pub count: Option<u32>,
+    /// Keep every snapshot that started within this many days.
+    pub days: Option<u32>,
+    /// Keep the newest snapshot of each of this many days.
+    pub daily: Option<u32>,
+    /// Keep the newest snapshot of each of this many (ISO) weeks.
+    pub weekly: Option<u32>,
+    /// Keep the newest snapshot of each of this many months.
+    pub monthly: Option<u32>,
+    /// Keep the newest snapshot of each of this many years.
+    pub yearly: Option<u32>,
+}
+
+impl RetentionPolicy {
+    /// Names of the dataset properties that define the policy, in the order
+    /// of the fields of this structure.
+    pub const PROPERTIES: [&'static str; 6] = [
+        "retain_count",
+        "retain_days",
+        "retain_daily",
+        "retain_weekly",
+        "retain_monthly",
+        "retain_yearly",
+    ];
+
+    /// Return `true` if the policy has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules().iter().all(|r| r.is_none())
+    }
+
+    /// Return the rules in the same order as `PROPERTIES`.
+    pub fn rules(&self) -> [Option<u32>; 6] {
+        [
+            self.count,
+            self.days,
+            self.daily,
+            self.weekly,
+            self.monthly,
+            self.yearly,
+        ]
+    }
+}
+
+/// Importance of a maintenance recommendation, most important first.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Data may be at risk if nothing is done.
+    High,
+    /// The system would benefit from attention soon.
+    Medium,
+    /// Worth doing when convenient.
+    Low,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Priority::High => write!(f, "high"),
+            Priority::Medium => write!(f, "medium"),
+            Priority::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Identifies a user-facing message such that clients can present it in the
+/// language of the user, rather than showing the English text.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MessageCode {
+    /// No dataset with the given `id`.
+    NoSuchDataset,
+    /// No store with the given `id`.
+    NoSuchStore,
+    /// No snapshot record with the given `digest`.
+    MissingSnapshot,
+    /// No tree record with the given `digest`.
+    MissingTree,
+    /// No file record with the given `digest`.
+    MissingFile,
+    /// No chunk record with the given `digest`.
+    MissingChunk,
+    /// No pack record with the given `digest`.
+    MissingPack,
+    /// None of the stores holding a pack are available.
+    NoMatchingStore,
+    /// Dataset at `path` is archived and will not be backed up.
+    DatasetArchived,
+    /// Sentinel file of dataset at `path` was corrupted in snapshot `digest`.
+    SentinelMismatch,
+    /// Stores report that the `packs` needed for the `files` are corrupted.
+    CorruptPacks,
+    /// Dataset at `path` has never completed a backup.
+    NeverBackedUp,
+    /// Dataset at `path` has not completed a backup since `date`.
+    BackupOverdue,
+    /// Dataset at `path` has at least `count` snapshots.
+    ManySnapshots,
+    /// Packs have not been verified since the server started.
+    PacksNotVerified,
+    /// Database has not been compacted since the server started.
+    DatabaseNotCompacted,
+    /// Chunk `digest` extracted from pack `pack` does not match its digest.
+    CorruptChunk,
+    /// Restored file at `path` does not match its digest `digest`.
+    CorruptFile,
+}
+
+impl MessageCode {
+    // English text of the message, with `{name}` for each parameter.
+    fn template(&self) -> &'static str {
+        match self {
+            MessageCode::NoSuchDataset => "no such dataset: {id}",
+            MessageCode::NoSuchStore => "no such store: {id}",
+            MessageCode::MissingSnapshot => "missing snapshot: {digest}",
+            MessageCode::MissingTree => "missing tree: {digest}",
+            MessageCode::MissingFile => "missing file: {digest}",
+            MessageCode::MissingChunk => "missing chunk: {digest}",
+            MessageCode::MissingPack => "missing pack record: {digest}",
+            MessageCode::NoMatchingStore => "no matching store found",
+            MessageCode::DatasetArchived => "dataset {path} is archived",
+            MessageCode::SentinelMismatch => {
+                "sentinel file of dataset {path} was corrupted in snapshot {digest}"
+            }
+            MessageCode::CorruptPacks => "corrupted packs {packs} needed to restore {files}",
+            MessageCode::NeverBackedUp => "dataset {path} has never completed a backup",
+            MessageCode::BackupOverdue => "dataset {path} has not completed a backup since {date}",
+            MessageCode::ManySnapshots => {
+                "dataset {path} has at least {count} snapshots, pruning them may allow removing packs"
+            }
+            MessageCode::PacksNotVerified => "the packs in the stores have not been verified",
+            MessageCode::DatabaseNotCompacted => "the database has not been compacted",
+            MessageCode::CorruptChunk => "chunk {digest} in pack {pack} is corrupted",
+            MessageCode::CorruptFile => "restored file {path} does not match digest {digest}",
+        }
+    }
+}
+
+impl fmt::Display for MessageCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageCode::NoSuchDataset => write!(f, "NO_SUCH_DATASET"),
+            MessageCode::NoSuchStore => write!(f, "NO_SUCH_STORE"),
+            MessageCode::MissingSnapshot => write!(f, "MISSING_SNAPSHOT"),
+            MessageCode::MissingTree => write!(f, "MISSING_TREE"),
+            MessageCode::MissingFile => write!(f, "MISSING_FILE"),
+            MessageCode::MissingChunk => write!(f, "MISSING_CHUNK"),
+            MessageCode::MissingPack => write!(f, "MISSING_PACK"),
+            MessageCode::NoMatchingStore => write!(f, "NO_MATCHING_STORE"),
+            MessageCode::DatasetArchived => write!(f, "DATASET_ARCHIVED"),
+            MessageCode::SentinelMismatch => write!(f, "SENTINEL_MISMATCH"),
+            MessageCode::CorruptPacks => write!(f, "CORRUPT_PACKS"),
+            MessageCode::NeverBackedUp => write!(f, "NEVER_BACKED_UP"),
+            MessageCode::BackupOverdue => write!(f, "BACKUP_OVERDUE"),
+            MessageCode::ManySnapshots => write!(f, "MANY_SNAPSHOTS"),
+            MessageCode::PacksNotVerified => write!(f, "PACKS_NOT_VERIFIED"),
+            MessageCode::DatabaseNotCompacted => write!(f, "DATABASE_NOT_COMPACTED"),
+            MessageCode::CorruptChunk => write!(f, "CORRUPT_CHUNK"),
+            MessageCode::CorruptFile => write!(f, "CORRUPT_FILE"),
+        }
+    }
+}
+
+///
+/// User-facing message given by a code and named parameters, which displays
+/// as English text. Also serves as an error, such that clients receive the
+/// code and parameters along with the text.
+///
+#[derive(thiserror::Error, Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    /// Identifies the message.
+    pub code: MessageCode,
+    /// Values to be inserted into the message, by name.
+    pub params: BTreeMap<String, String>,
+}
+
+impl Message {
+    /// Construct a message without any parameters.
+    pub fn new(code: MessageCode) -> Self {
+        Self {
+            code,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Add the named parameter to the message.
+    pub fn with<T: fmt::Display + ?Sized>(mut self, name: &str, value: &T) -> Self {
+        self.params.insert(name.to_owned(), value.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut text = self.code.template().to_owned();
+        for (name, value) in self.params.iter() {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        write!(f, "{}", text)
+    }
+}
+
+/// Suggested action for keeping the backups in good order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recommendation {
+    /// How soon the suggestion ought to be acted upon.
+    pub priority: Priority,
+    /// Identifier of the dataset or store concerned, if any.
+    pub subject: Option<String>,
+    /// Description of the problem and the suggested remedy.
+    pub message: Message,
+    /// Name of the GraphQL mutation that carries out the suggestion.
+    pub mutation: String,
+}
+
+impl Recommendation {
+    /// Construct a recommendation to invoke the named mutation.
+    pub fn new(priority: Priority, message: Message, mutation: &str) -> Self {
+        Self {
+            priority,
+            subject: None,
+            message,
+            mutation: mutation.to_owned(),
+        }
+    }
+
+    /// Set the identifier of the dataset or store concerned.
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_owned());
+        self
+    }
+}
+
+/// State of the backups of a dataset, as shown on the status page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DatasetStatus {
+    /// Identifier of the dataset.
+    pub id: String,
+    /// Local base path of the dataset.
+    pub basepath: PathBuf,
+    /// State of the most recent backup since the server started: one of
+    /// `none`, `running`, `paused`, `failed`, or `finished`.
+    pub backup: String,
+    /// Date/time when the most recent completed snapshot finished.
+    pub last_success: Option<DateTime<Utc>>,
+    /// Error message of the most recent backup, if it failed.
+    pub error: Option<String>,
+}
+
+/// Health of a pack store, as shown on the status page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreStatus {
+    /// Identifier of the store.
+    pub id: String,
+    /// User-defined label for the store.
+    pub label: String,
+    /// Type of the store.
+    pub store_type: StoreType,
+    /// True unless the most recent retrieval from the store failed.
+    pub healthy: bool,
+    /// Message of the most recent failure, if any.
+    pub last_error: Option<String>,
+}
+
+/// Summary of the backups, stores, and restores, suitable for a dashboard.
+#[derive(Clone, Debug)]
+pub struct StatusReport {
+    /// Date/time when the report was produced.
+    pub generated: DateTime<Utc>,
+    /// State of each dataset that has not been archived.
+    pub datasets: Vec<DatasetStatus>,
+    /// Health of each pack store.
+    pub stores: Vec<StoreStatus>,
+    /// Number of restore requests that have not yet finished.
+    pub pending_restores: u64,
+    /// Number of restore requests that finished with an error.
+    pub failed_restores: u64,
+    /// Outcome of the most recent store health check, if any has run since
+    /// the server started.
+    pub health_check: Option<MaintenanceResult>,
+}
+
+/// Outcome of a single operation performed while testing a store.
+#[derive(Clone, Debug)]
+pub struct StoreTestStep {
+    /// Name of the operation (e.g. "upload", "download").
+    pub name: String,
+    /// Time in milliseconds that the operation took to complete.
+    pub elapsed: u64,
+    /// Error message if the operation failed.
+    pub error: Option<String>,
+}
+
+impl StoreTestStep {
+    /// Construct a step with the given name, duration, and result.
+    pub fn new<T>(name: &str, started: std::time::Instant, result: &Result<T, Error>) -> Self {
+        Self {
+            name: name.to_owned(),
+            elapsed: started.elapsed().as_millis() as u64,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Represents a directory tree that will be backed up according to a schedule,
+/// with pack files saved to a particular local or remote store.
+#[derive(Clone, Debug)]
+pub struct Dataset {
+    /// Unique identifier of this dataset.
+    pub id: String,
+    /// Local base path of dataset to be saved.
+    pub basepath: PathBuf,
+    /// Set of schedules for when to run the backup.
+    pub schedules: Vec<schedule::Schedule>,
+    /// Path for temporary pack building.
+    pub workspace: PathBuf,
+    /// Target size in bytes for pack files.
+    pub pack_size: u64,
+    /// Identifiers of the stores to contain pack files.
+    pub stores: Vec<String>,
+    /// List of file/directory exclusion patterns.
+    pub excludes: Vec<String>,
+    /// Name/value pairs for optional dataset settings.
+    pub properties: HashMap<String, String>,
+    /// When the dataset was archived, if it has been. Archived datasets are
+    /// no longer backed up, while their snapshots remain available.
+    pub archived: Option<DateTime<Utc>>,
+}
+
+// Default pack size is 64mb just because. With a typical ADSL home broadband
+// connection a 64mb pack file should take about 5 minutes to upload.
+const DEFAULT_PACK_SIZE: u64 = 67_108_864;
+
+impl Dataset {
+    /// Construct a Dataset with the path of the directory tree to be saved.
+    pub fn new(basepath: &Path) -> Dataset {
+        Dataset::with_pack_size(basepath, DEFAULT_PACK_SIZE)
+    }
+
+    /// Construct a `Dataset` with the given path and pack size.
+    pub fn with_pack_size(basepath: &Path, pack_size: u64) -> Self {
+        let id = xid::new().to_string();
+        let mut workspace = basepath.to_owned();
+        workspace.push(".tmp");
+        Self {
+            id,
+            basepath: basepath.to_owned(),
+            schedules: vec![],
+            workspace,
+            pack_size,
+            stores: vec![],
+            excludes: vec![],
+            properties: HashMap::new(),
+            archived: None,
+        }
+    }
+
+    /// Return an entity tag that changes whenever the dataset configuration
+    /// changes, for detecting concurrent modification.
+    pub fn etag(&self) -> String {
+        let fields = [
+            self.id.clone(),
+            self.basepath.to_string_lossy().into_owned(),
+            format!("{:?}", self.schedules),
+            self.workspace.to_string_lossy().into_owned(),
+            self.pack_size.to_string(),
+            self.stores.join("\n"),
+            self.excludes.join("\n"),
+            self.archived.map(|a| a.to_rfc3339()).unwrap_or_default(),
+        ];
+        compute_etag(&fields, &self.properties)
+    }
+
+    /// Add the given store identifier to the dataset.
+    pub fn add_store(&mut self, store: &str) {
+        self.stores.push(store.to_owned());
+    }
+
+    /// Add the given schedule to the dataset.
+    pub fn add_schedule(&mut self, schedule: schedule::Schedule) {
+        self.schedules.push(schedule);
+    }
+
+    /// Return the change trigger settings for this dataset, if the
+    /// `trigger_files` or `trigger_bytes` property is defined and valid.
+    pub fn change_trigger(&self) -> Option<ChangeTrigger> {
+        let files = self
+            .properties
+            .get("trigger_files")
+            .and_then(|v| v.parse::<u64>().ok());
+        let bytes = self
+            .properties
+            .get("trigger_bytes")
+            .and_then(|v| v.parse::<u64>().ok());
+        if files.is_none() && bytes.is_none() {
+            return None;
+        }
+        let cooldown = self
+            .properties
+            .get("trigger_cooldown")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_TRIGGER_COOLDOWN);
+        let quiet = self
+            .properties
+            .get("trigger_quiet")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_TRIGGER_QUIET);
+        Some(ChangeTrigger {
+            files,
+            bytes,
+            cooldown: Duration::from_secs(cooldown),
+            quiet: Duration::from_secs(quiet),
+        })
+    }
+
+    /// Return `true` if the `extract_metadata` property is set, in which case
+    /// application metadata, such as the date a photo was taken, is saved with
+    /// the records of new files.
+    pub fn extract_metadata(&self) -> bool {
+        self.properties
+            .get("extract_metadata")
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    }
+
+    /// Return the chunking parameters for the files of this dataset, starting
+    /// with the given average chunk size. The `chunk_size` property overrides
+    /// the average size, `chunk_min_size` and `chunk_max_size` override the
+    /// bounds that are otherwise derived from the average, and
+    /// `chunk_normalization` sets the normalization level (0 to 3).
+    pub fn chunker_params(&self, avg_size: u32) -> ChunkerParams {
+        let size = |name: &str| -> Option<u32> {
+            let value = self.properties.get(name)?.parse::<u32>().ok()?;
+            if value == 0 {
+                None
+            } else {
+                Some(value)
+            }
+        };
+        let mut params = ChunkerParams::new(size("chunk_size").unwrap_or(avg_size));
+        if let Some(min_size) = size("chunk_min_size") {
+            params.min_size = min_size;
+        }
+        if let Some(max_size) = size("chunk_max_size") {
+            params.max_size = max_size;
+        }
+        if let Some(level) = self
+            .properties
+            .get("chunk_normalization")
+            .and_then(|v| v.parse::<u8>().ok())
+            .filter(|l| *l <= 3)
+        {
+            params.normalization = level;
+        }
+        params
+    }
+
+    /// Return the compression for the chunks in the packs of this dataset, as
+    /// given by the `compression` property, one of `zstd` (the default),
+    /// `zstd:<level>`, `lz4`, or `none`.
+    pub fn compression(&self) -> Compression {
+        self.properties
+            .get("compression")
+            .and_then(|v| v.parse::<Compression>().ok())
+            .unwrap_or_default()
+    }
+
+    /// Return the most file system operations, such as examining or reading a
+    /// file, that the backup may have in progress at once, as given by the
+    /// `file_concurrency` property, for file systems that cannot cope with
+    /// many concurrent requests. Without that property there is no limit
+    /// beyond the number of threads.
+    pub fn file_concurrency(&self) -> Option<usize> {
+        let value = self
+            .properties
+            .get("file_concurrency")?
+            .parse::<usize>()
+            .ok()?;
+        if value == 0 {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Return the limits on the resources used by each backup, as given by the
+    /// `upload_rate` (kilobytes per second), `hash_threads`, and `niceness`
+    /// properties. If the `limits_window` property (`HH:MM-HH:MM`, in UTC) is
+    /// also set, the limits apply only within that time of day.
+    pub fn resource_limits(&self) -> schedule::ResourceLimits {
+        let limit = |name: &str| {
+            self.properties
+                .get(name)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|v| *v > 0)
+        };
+        schedule::ResourceLimits {
+            upload_rate: limit("upload_rate").map(|r| r * 1024),
+            hash_threads: limit("hash_threads").map(|t| t as usize),
+            niceness: limit("niceness").map(|n| n.min(19) as i32),
+            window: self
+                .properties
+                .get("limits_window")
+                .and_then(|v| v.parse::<schedule::TimeRange>().ok()),
+        }
+    }
+
+    /// Return `true` if the `sentinel` property is set, in which case a small
+    /// file of known content is kept in the base path of the dataset and
+    /// verified after each backup to detect corruption anywhere in the backup
+    /// pipeline.
+    pub fn sentinel(&self) -> bool {
+        self.properties
+            .get("sentinel")
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    }
+
+    /// Return the longest that a backup started by the given schedule may run
+    /// before it is paused, to resume the next time that schedule comes due,
+    /// as given by the `max_runtime` property in minutes. The property may
+    /// hold a single value for all schedules, or a comma-separated list with
+    /// one value for each schedule, in order, where an empty value means the
+    /// backup may run until it is finished.
+    pub fn max_runtime(&self, schedule: &schedule::Schedule) -> Option<Duration> {
+        let value = self.properties.get("max_runtime")?;
+        let values: Vec<&str> = value.split(',').map(|v| v.trim()).collect();
+        let minutes = if values.len() == 1 {
+            values[0]
+        } else {
+            let index = self.schedules.iter().position(|s| s == schedule)?;
+            values.get(index)?
+        };
+        minutes
+            .parse::<u64>()
+            .ok()
+            .filter(|m| *m > 0)
+            .map(|m| Duration::from_secs(m * 60))
+    }
+
+    /// Return the patterns of the files that are copied to the workspace before
+    /// being read, as given by the comma-separated `copy_patterns` property.
+    /// Nothing is copied without that property, while the value `default`
+    /// selects the databases commonly kept open by browsers and similar
+    /// applications.
+    pub fn copy_patterns(&self) -> Vec<String> {
+        match self.properties.get("copy_patterns").map(|v| v.trim()) {
+            None => vec![],
+            Some("default") => DEFAULT_COPY_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            Some(value) => value
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_owned())
+                .collect(),
+        }
+    }
+
+    /// Return the patterns of the critical paths of the dataset, relative to
+    /// the base path, as given by the comma-separated `critical_paths`
+    /// property. The packs needed to restore these paths are kept in a fast
+    /// store after each backup.
+    pub fn critical_paths(&self) -> Vec<String> {
+        self.properties
+            .get("critical_paths")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|p| p.trim())
+                    .filter(|p| !p.is_empty())
+                    .map(|p| p.to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Return the schedule for the given task, as given by the corresponding
+    /// property (such as `prune_schedule`), one of `hourly`, `daily`,
+    /// `weekly`, or `monthly`. Without that property the task is not run.
+    pub fn task_schedule(&self, task: MaintenanceTask) -> Option<schedule::Schedule> {
+        match self
+            .properties
+            .get(task.schedule_property()?)?
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "hourly" => Some(schedule::Schedule::Hourly),
+            "daily" => Some(schedule::Schedule::Daily(None)),
+            "weekly" => Some(schedule::Schedule::Weekly(None)),
+            "monthly" => Some(schedule::Schedule::Monthly(None)),
+            _ => None,
+        }
+    }
+
+    /// Return the depth to which the scheduled verification checks the latest
+    /// snapshot, as given by the `verify_depth` property, defaulting to `packs`.
+    pub fn verify_depth(&self) -> VerifyDepth {
+        self.properties
+            .get("verify_depth")
+            .and_then(|v| v.parse::<VerifyDepth>().ok())
+            .unwrap_or(VerifyDepth::Packs)
+    }
+
+    /// Return the number of files restored by the scheduled verification at
+    /// the `full` depth, as given by the `verify_sample` property. Without that
+    /// property every file is restored.
+    pub fn verify_sample(&self) -> Option<usize> {
+        self.properties
+            .get("verify_sample")
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+    }
+
+    /// Return the retention policy given by the `retain_count`, `retain_days`,
+    /// `retain_daily`, `retain_weekly`, `retain_monthly`, and `retain_yearly`
+    /// properties. Rules that are not positive numbers are ignored.
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        let rule = |name: &str| {
+            self.properties
+                .get(name)
+                .and_then(|v| v.trim().parse::<u32>().ok())
+                .filter(|v| *v > 0)
+        };
+        let [count, days, daily, weekly, monthly, yearly] = RetentionPolicy::PROPERTIES.map(rule);
+        RetentionPolicy {
+            count,
+            days,
+            daily,
+            weekly,
+            monthly,
+            yearly,
+        }
+    }
+
+    /// Return the URL to be pinged when a backup of the dataset starts,
+    /// finishes, or fails, as given by the `healthcheck_url` property, for use
+    /// with a monitoring service that reports backups that fail to run.
+    pub fn healthcheck_url(&self) -> Option<String> {
+        self.properties
+            .get("healthcheck_url")
+            .map(|v| v.trim())
+            .filter(|v| v.starts_with("http://") || v.starts_with("https://"))
+            .map(|v| v.to_owned())
+    }
+}
+
+// Files that are likely to be modified while being read, such as the SQLite
+// databases of Firefox and the LevelDB stores of Chrome and Electron apps.
+const DEFAULT_COPY_PATTERNS: &[&str] = &[
+    "*.sqlite",
+    "*.sqlite-wal",
+    "*.db",
+    "*.db-wal",
+    "*.ldb",
+    "*/Chrome/*/History",
+    "*/Chrome/*/Cookies",
+    "*/Chrome/*/Web Data",
+    "*/Chrome/*/Login Data",
+];
+
+// Hash the fields and properties of an entity in a consistent order, producing
+// a short hexadecimal tag.
+fn compute_etag(fields: &[String], properties: &HashMap<String, String>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for field in fields {
+        hasher.update(field.as_bytes());
+        hasher.update(&[0]);
+    }
+    let mut names: Vec<&String> = properties.keys().collect();
+    names.sort();
+    for name in names {
+        hasher.update(name.as_bytes());
+        hasher.update(&[1]);
+        hasher.update(properties[name].as_bytes());
+        hasher.update(&[0]);
+    }
+    let digest = hasher.finalize();
+    digest.to_hex()[..16].to_owned()
+}
+
+impl Default for Dataset {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            basepath: PathBuf::new(),
+            schedules: vec![],
+            workspace: PathBuf::new(),
+            pack_size: 0,
+            stores: vec![],
+            excludes: vec![],
+            properties: HashMap::new(),
+        }
+    }
+}
+
+// Default minimum time in seconds between triggered backups.
+const DEFAULT_TRIGGER_COOLDOWN: u64 = 3_600;
+
+// Default time in seconds without changes before a backup is triggered.
+const DEFAULT_TRIGGER_QUIET: u64 = 300;
+
+///
+/// Thresholds for starting a backup when enough changes have accumulated in the
+/// dataset, rather than waiting for the schedule.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeTrigger {
+    /// Number of changed files that will trigger a backup.
+    pub files: Option<u64>,
+    /// Number of changed bytes that will trigger a backup.
+    pub bytes: Option<u64>,
+    /// Minimum time since the previous backup finished.
+    pub cooldown: Duration,
+    /// Time that must pass without further changes.
+    pub quiet: Duration,
+}
+
+impl ChangeTrigger {
+    /// Return `true` if the given changes meet either threshold.
+    pub fn exceeded(&self, files: u64, bytes: u64) -> bool {
+        self.files.map(|f| files >= f).unwrap_or(false)
+            || self.bytes.map(|b| bytes >= b).unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Dataset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dataset-{}:{:?}", self.id, self.basepath)
+    }
+}
+
+/// Access granted to a device that was paired with the server.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DeviceScope {
+    /// Browse the snapshots and restore files, but change nothing else.
+    Restore,
+    /// Everything that the server offers.
+    Full,
+}
+
+impl fmt::Display for DeviceScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceScope::Restore => write!(f, "restore"),
+            DeviceScope::Full => write!(f, "full"),
+        }
+    }
+}
+
+impl FromStr for DeviceScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "restore" => Ok(DeviceScope::Restore),
+            "full" => Ok(DeviceScope::Full),
+            _ => Err(anyhow!(format!("not a recognized device scope: {}", s))),
+        }
+    }
+}
+
+/// Device that was paired with the server and given an access token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Device {
+    /// Unique identifier of the device.
+    pub id: String,
+    /// Name given to the device when it was paired.
+    pub name: String,
+    /// Access granted to the device.
+    pub scope: DeviceScope,
+    /// Hash digest of the access token; the token itself is never saved.
+    pub token_hash: String,
+    /// Date/time when the device was paired.
+    pub created: DateTime<Utc>,
+    /// Date/time when the device last presented its token, if ever.
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl Device {
+    /// Construct a device record for the token with the given hash digest.
+    pub fn new(name: &str, scope: DeviceScope, token_hash: &str) -> Self {
+        Self {
+            id: xid::new().to_string(),
+            name: name.to_owned(),
+            scope,
+            token_hash: token_hash.to_owned(),
+            created: Utc::now(),
+            last_used: None,
+        }
+    }
+}
+
+/// Migration of the database records that has been applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Migration {
+    /// Schema version that the migration produced.
+    pub version: u32,
+    /// Brief description of the changes made by the migration.
+    pub name: String,
+    /// Date/time when the migration was applied.
+    pub applied: DateTime<Utc>,
+}
+
+impl Migration {
+    /// Construct a record of the migration as having just been applied.
+    pub fn new(version: u32, name: &str) -> Self {
+        Self {
+            version,
+            name: name.to_owned(),
+            applied: Utc::now(),
+        }
+    }
+}
+
+/// Kind of action recorded in the event log.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventKind {
+    /// Backup of a dataset has started.
+    BackupStarted,
+    /// Backup of a dataset has finished successfully.
+    BackupFinished,
+    /// Backup of a dataset has failed.
+    BackupFailed,
+    /// Pack file was uploaded to the stores of a dataset.
+    PackUploaded,
+    /// Snapshots of a dataset were removed.
+    SnapshotsPruned,
+    /// Request to restore files was received.
+    RestoreRequested,
+    /// Pack store was added.
+    StoreCreated,
+    /// Pack store was modified.
+    StoreUpdated,
+    /// Pack store was removed.
+    StoreDeleted,
+    /// Sentinel file of a dataset did not survive the round trip intact.
+    SentinelFailed,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventKind::BackupStarted => write!(f, "backup_started"),
+            EventKind::BackupFinished => write!(f, "backup_finished"),
+            EventKind::BackupFailed => write!(f, "backup_failed"),
+            EventKind::PackUploaded => write!(f, "pack_uploaded"),
+            EventKind::SnapshotsPruned => write!(f, "snapshots_pruned"),
+            EventKind::RestoreRequested => write!(f, "restore_requested"),
+            EventKind::StoreCreated => write!(f, "store_created"),
+            EventKind::StoreUpdated => write!(f, "store_updated"),
+            EventKind::StoreDeleted => write!(f, "store_deleted"),
+            EventKind::SentinelFailed => write!(f, "sentinel_failed"),
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "backup_started" => Ok(EventKind::BackupStarted),
+            "backup_finished" => Ok(EventKind::BackupFinished),
+            "backup_failed" => Ok(EventKind::BackupFailed),
+            "pack_uploaded" => Ok(EventKind::PackUploaded),
+            "snapshots_pruned" => Ok(EventKind::SnapshotsPruned),
+            "restore_requested" => Ok(EventKind::RestoreRequested),
+            "store_created" => Ok(EventKind::StoreCreated),
+            "store_updated" => Ok(EventKind::StoreUpdated),
+            "store_deleted" => Ok(EventKind::StoreDeleted),
+            "sentinel_failed" => Ok(EventKind::SentinelFailed),
+            _ => Err(anyhow!(format!("not a recognized event type: {}", s))),
+        }
+    }
+}
+
+/// Action taken by the application, as recorded in the event log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// Unique identifier of the event.
+    pub id: String,
+    /// What sort of action this was.
+    pub kind: EventKind,
+    /// Date/time when the action took place.
+    pub time: DateTime<Utc>,
+    /// What the action concerned, such as a dataset or store identifier.
+    pub subject: String,
+    /// Further details of the action, as name/value pairs.
+    pub details: HashMap<String, String>,
+}
+
+impl Event {
+    /// Construct an event for an action that just took place.
+    pub fn new(kind: EventKind, subject: &str) -> Self {
+        Self {
+            id: ulid::Ulid::new().to_string(),
+            kind,
+            time: Utc::now(),
+            subject: subject.to_owned(),
+            details: HashMap::new(),
+        }
+    }
+
+    /// Add a detail of the action to the event.
+    pub fn detail<S: Into<String>>(mut self, name: &str, value: S) -> Self {
+        self.details.insert(name.to_owned(), value.into());
+        self
+    }
+}
+
+/// Entry in the tamper-evident log of the snapshots uploaded to a store, in
+/// which each entry includes the digest of the entry before it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChainEntry {
+    /// Identifier of the store to which the snapshot was uploaded.
+    pub store: String,
+    /// Position of the entry within the log, starting at 1.
+    pub sequence: u64,
+    /// Digest of the snapshot that was uploaded.
+    pub snapshot: Checksum,
+    /// Date/time when the entry was appended, to the second.
+    pub time: DateTime<Utc>,
+    /// Digest of the previous entry, or all zeroes for the first entry.
+    pub previous: Checksum,
+    /// Digest of this entry, covering all of the other fields.
+    pub digest: Checksum,
+}
+
+impl ChainEntry {
+    /// Construct the entry that follows the given entry, if any.
+    pub fn new(store: &str, previous: Option<&ChainEntry>, snapshot: Checksum) -> Self {
+        let time = Utc::now().with_nanosecond(0).unwrap();
+        let (sequence, previous) = match previous {
+            Some(entry) => (entry.sequence + 1, entry.digest.clone()),
+            None => (1, Checksum::from_str(NULL_SHA1).unwrap()),
+        };
+        let digest = ChainEntry::compute_digest(sequence, time, &snapshot, &previous);
+        Self {
+            store: store.to_owned(),
+            sequence,
+            snapshot,
+            time,
+            previous,
+            digest,
+        }
+    }
+
+    /// Compute the digest of an entry with the given values.
+    pub fn compute_digest(
+        sequence: u64,
+        time: DateTime<Utc>,
+        snapshot: &Checksum,
+        previous: &Checksum,
+    ) -> Checksum {
+        let formed = format!(
+            "{} {} {} {}",
+            sequence,
+            time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            snapshot,
+            previous
+        );
+        Checksum::blake3_from_bytes(formed.as_bytes())
+    }
+
+    /// Parse an entry from a line of the log object, as produced by the
+    /// `Display` implementation, without checking its digest.
+    pub fn parse(store: &str, line: &str) -> Result<Self, Error> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("malformed chain entry: {}", line));
+        }
+        Ok(Self {
+            store: store.to_owned(),
+            sequence: fields[0].parse::<u64>()?,
+            time: DateTime::parse_from_rfc3339(fields[1])?.with_timezone(&Utc),
+            snapshot: Checksum::from_str(fields[2])?,
+            previous: Checksum::from_str(fields[3])?,
+            digest: Checksum::from_str(fields[4])?,
+        })
+    }
+
+    /// Return `true` if the digest of the entry matches its other fields.
+    pub fn is_intact(&self) -> bool {
+        let expected =
+            ChainEntry::compute_digest(self.sequence, self.time, &self.snapshot, &self.previous);
+        expected == self.digest
+    }
+}
+
+impl fmt::Display for ChainEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.sequence,
+            self.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.snapshot,
+            self.previous,
+            self.digest
+        )
+    }
+}
+
+/// Outcome of comparing the snapshot log held by a store with the entries
+/// recorded in the database.
+#[derive(Clone, Debug)]
+pub struct ChainReport {
+    /// Identifier of the store that was checked.
+    pub store: String,
+    /// Number of entries recorded in the database.
+    pub expected: u64,
+    /// Number of entries found in the log held by the store.
+    pub found: u64,
+    /// Descriptions of each discrepancy, empty if the log is intact.
+    pub problems: Vec<String>,
+}
+
+impl ChainReport {
+    /// Construct an empty report for the given store.
+    pub fn new(store: &str) -> Self {
+        Self {
+            store: store.to_owned(),
+            expected: 0,
+            found: 0,
+            problems: vec![],
+        }
+    }
+
+    /// Return `true` if no discrepancies were found.
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Outcome of rebuilding the statistics of a dataset from the records of its
+/// snapshots, trees, and files.
+#[derive(Clone, Debug, Default)]
+pub struct StatisticsReport {
+    /// Identifier of the dataset whose statistics were recomputed.
+    pub dataset_id: String,
+    /// Number of snapshots that were examined.
+    pub snapshots: u64,
+    /// Number of snapshots whose file counts were corrected.
+    pub repaired: u64,
+    /// Number of files in the latest snapshot.
+    pub files: u64,
+    /// Combined size in bytes of the files in the latest snapshot.
+    pub bytes: u64,
+    /// Number of records of each kind in the database.
+    pub records: RecordCounts,
+    /// Descriptions of each discrepancy that was found and corrected.
+    pub problems: Vec<String>,
+}
+
+/// Outcome of rewriting the packs of a dataset that hold mostly dead chunks.
+#[derive(Clone, Debug, Default)]
+pub struct CompactionReport {
+    /// Identifier of the dataset whose packs were compacted.
+    pub dataset_id: String,
+    /// Number of packs that were considered for compaction.
+    pub packs_examined: u64,
+    /// Number of packs whose live chunks were moved to new packs.
+    pub packs_rewritten: u64,
+    /// Number of packs that were removed for having no live chunks.
+    pub packs_removed: u64,
+    /// Number of new packs that were uploaded.
+    pub packs_created: u64,
+    /// Combined size of the dead chunks that were discarded, before
+    /// compression.
+    pub bytes_reclaimed: u64,
+}
+
+/// Object found in a store that is not known to the database.
+#[derive(Clone, Debug)]
+pub struct RemoteObject {
+    /// Location of the object within the store.
+    pub location: PackLocation,
+    /// Digest of the object content, if it could be retrieved.
+    pub digest: Option<Checksum>,
+    /// Digests and lengths of the chunks within the object, if it is a pack
+    /// file.
+    pub chunks: Vec<(Checksum, usize)>,
+    /// Reason the object is not a usable pack file, if any.
+    pub error: Option<String>,
+    /// True if a pack record was made for the object.
+    pub adopted: bool,
+}
+
+impl RemoteObject {
+    /// Construct a record of an unknown object at the given location.
+    pub fn new(location: PackLocation) -> Self {
+        Self {
+            location,
+            digest: None,
+            chunks: vec![],
+            error: None,
+            adopted: false,
+        }
+    }
+
+    /// Return `true` if the object was read successfully as a pack file.
+    pub fn is_pack(&self) -> bool {
+        self.digest.is_some() && self.error.is_none()
+    }
+}
+
+/// Outcome of looking for objects in a store that are not known to the
+/// database, such as after the database was lost or restored from an older
+/// archive.
+#[derive(Clone, Debug)]
+pub struct DiscoveryReport {
+    /// Identifier of the store that was examined.
+    pub store: String,
+    /// Number of objects found in the store.
+    pub objects: u64,
+    /// Number of those objects that are known to the database.
+    pub known: u64,
+    /// Objects that are not known to the database.
+    pub unknown: Vec<RemoteObject>,
+    /// Number of objects removed from the store, if cleanup was requested.
+    pub removed: u32,
+}
+
+impl DiscoveryReport {
+    /// Construct an empty report for the given store.
+    pub fn new(store: &str) -> Self {
+        Self {
+            store: store.to_owned(),
+            objects: 0,
+            known: 0,
+            unknown: vec![],
+            removed: 0,
+        }
+    }
+}
+
+/// Kind of record that expects an object to exist in a pack store.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordKind {
+    /// Pack file holding the chunks of backed up files.
+    Pack,
+    /// Archive of the database.
+    Database,
+    /// Log of the snapshots uploaded to the store.
+    Chain,
+}
+
+impl fmt::Display for RecordKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordKind::Pack => write!(f, "pack"),
+            RecordKind::Database => write!(f, "database"),
+            RecordKind::Chain => write!(f, "chain"),
+        }
+    }
+}
+
+/// Object in a store that is not referenced by any record in the database.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrphanedObject {
+    /// Location of the object within the store.
+    pub location: PackLocation,
+    /// Size of the object in bytes, if the store was able to report it.
+    pub size: Option<u64>,
+}
+
+/// Record in the database whose object was not found in the store.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MissingObject {
+    /// Digest of the pack or archive, or `NULL_SHA1` for the snapshot log.
+    pub digest: Checksum,
+    /// Kind of record that references the object.
+    pub kind: RecordKind,
+    /// Location at which the object was expected to be.
+    pub location: PackLocation,
+}
+
+/// Comparison of the objects in a store with the records in the database,
+/// made without changing or retrieving anything.
+#[derive(Clone, Debug)]
+pub struct ReconciliationReport {
+    /// Identifier of the store that was examined.
+    pub store: String,
+    /// Number of objects found in the store.
+    pub objects: u64,
+    /// Number of those objects that are referenced by a record.
+    pub matched: u64,
+    /// Objects in the store that no record references.
+    pub orphaned: Vec<OrphanedObject>,
+    /// Records whose object is not in the store.
+    pub missing: Vec<MissingObject>,
+}
+
+impl ReconciliationReport {
+    /// Construct an empty report for the given store.
+    pub fn new(store: &str) -> Self {
+        Self {
+            store: store.to_owned(),
+            objects: 0,
+            matched: 0,
+            orphaned: vec![],
+            missing: vec![],
+        }
+    }
+
+    /// Total size of the orphaned objects whose size is known.
+    pub fn orphaned_bytes(&self) -> u64 {
+        self.orphaned.iter().filter_map(|o| o.size).sum()
+    }
+}
+
+/// A backup that is expected to run according to the schedules of a dataset.
+#[derive(Clone, Debug)]
+pub struct ForecastRun {
+    /// Identifier of the dataset.
+    pub dataset_id: String,
+    /// When the backup is expected to start.
+    pub start: DateTime<Utc>,
+    /// When the backup is expected to finish, or to be paused.
+    pub end: DateTime<Utc>,
+    /// Number of previous backups from which the duration was estimated, zero
+    /// if there were none and the default was assumed.
+    pub history: usize,
+    /// True if the backup is not expected to finish before the end of the
+    /// time range of the schedule, or its maximum runtime, and will be paused.
+    pub overrun: bool,
+    /// Identifiers of the stores that are left out of the backup, having
+    /// reached their monthly transfer cap or their quota.
+    pub deferred_stores: Vec<String>,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some(hash) = s.strip_prefix("sha1-") {
-            Ok(Checksum::SHA1(hash.to_owned()))
-        } else if let Some(hash) = s.strip_prefix("blake3-") {
-            Ok(Checksum::BLAKE3(hash.to_owned()))
-        } else {
-            Err(anyhow!(format!("not a recognized algorithm: {}", s)))
-        }
-    }
+/// Two forecast backups that are expected to run at the same time.
+#[derive(Clone, Debug)]
+pub struct ScheduleConflict {
+    /// Identifier of the dataset whose backup starts first.
+    pub first: String,
+    /// Identifier of the dataset whose backup starts second.
+    pub second: String,
+    /// When the backups start running at the same time.
+    pub start: DateTime<Utc>,
+    /// When one of the backups is expected to finish.
+    pub end: DateTime<Utc>,
+    /// Identifiers of the stores to which both datasets upload.
+    pub shared_stores: Vec<String>,
 }
 
-/// Represents a piece of a file, and possibly an entire file.
+/// Backups expected to run over the coming days, and the conflicts among them.
 #[derive(Clone, Debug)]
-pub struct Chunk {
-    /// The hash digest of the chunk, with algorithm prefix.
+pub struct ScheduleForecast {
+    /// Beginning of the forecast period.
+    pub start: DateTime<Utc>,
+    /// End of the forecast period.
+    pub end: DateTime<Utc>,
+    /// Expected backups, ordered by start time.
+    pub runs: Vec<ForecastRun>,
+    /// Overlapping backups, ordered by start time.
+    pub conflicts: Vec<ScheduleConflict>,
+}
+
+/// A file within a backup of the database, along with the digests of the
+/// chunks that make up its content, in order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CatalogFile {
+    /// Path of the file relative to the backup directory.
+    pub path: String,
+    /// Digests of the chunks of the file content.
+    pub chunks: Vec<Checksum>,
+}
+
+/// A pack of database chunks held in the catalog bucket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CatalogPack {
+    /// Digest of the pack file.
     pub digest: Checksum,
-    /// The byte offset of this chunk within the file.
-    pub offset: usize,
-    /// The byte length of this chunk.
-    pub length: usize,
-    /// Path of the file from which the chunk is taken.
-    pub filepath: Option<PathBuf>,
-    /// Digest of packfile this chunk is stored within.
-    pub packfile: Option<Checksum>,
+    /// Locations of the pack file when it was uploaded.
+    pub locations: Vec<PackLocation>,
+    /// Digests of the chunks contained in the pack.
+    pub chunks: Vec<Checksum>,
 }
 
-impl Chunk {
-    /// Construct a `Chunk` from the given values.
-    pub fn new(digest: Checksum, offset: usize, length: usize) -> Self {
+/// Manifest of a database backup that has been split into chunks, such that
+/// the chunks left unchanged since the previous backup need not be uploaded
+/// again.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Catalog {
+    /// Date-time when the backup was made.
+    pub created: DateTime<Utc>,
+    /// Files that make up the database backup.
+    pub files: Vec<CatalogFile>,
+    /// Packs that hold all of the chunks of the files.
+    pub packs: Vec<CatalogPack>,
+}
+
+impl Catalog {
+    /// Construct an empty catalog.
+    pub fn new() -> Self {
         Self {
-            digest,
-            offset,
-            length,
-            filepath: None,
-            packfile: None,
+            created: Utc::now(),
+            files: vec![],
+            packs: vec![],
         }
     }
 
-    /// Add the filepath property.
-    pub fn filepath(mut self, filepath: &Path) -> Self {
-        self.filepath = Some(filepath.to_owned());
-        self
+    /// Map the digest of every chunk to that of the pack which holds it.
+    pub fn chunk_packs(&self) -> HashMap<Checksum, Checksum> {
+        let mut results: HashMap<Checksum, Checksum> = HashMap::new();
+        for pack in self.packs.iter() {
+            for chunk in pack.chunks.iter() {
+                results.insert(chunk.clone(), pack.digest.clone());
+            }
+        }
+        results
     }
+}
 
-    /// Add the packfile property.
-    pub fn packfile(mut self, packfile: Checksum) -> Self {
-        self.packfile = Some(packfile);
-        self
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// StoreType identifies a kind of store.
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
-pub enum StoreType {
-    AMAZON,
-    AZURE,
-    GOOGLE,
-    LOCAL,
-    MINIO,
-    SFTP,
+/// Changed file whose chunks were identified, but not yet uploaded, when the
+/// uploads of a backup were stopped.
+#[derive(Clone, Debug)]
+pub struct PlannedFile {
+    /// Digest of the file content.
+    pub digest: Checksum,
+    /// Path from which the content is read, which may be a copy within the
+    /// workspace of the dataset.
+    pub path: PathBuf,
+    /// Size of the file when its chunks were found.
+    pub length: u64,
+    /// Modification time of the file when its chunks were found, if known.
+    pub modified: Option<DateTime<Utc>>,
+    /// Chunks of the file, in order of their offset.
+    pub chunks: Vec<Chunk>,
 }
 
-impl ToString for StoreType {
-    fn to_string(&self) -> String {
-        match self {
-            StoreType::AMAZON => String::from("amazon"),
-            StoreType::AZURE => String::from("azure"),
-            StoreType::GOOGLE => String::from("google"),
-            StoreType::LOCAL => String::from("local"),
-            StoreType::MINIO => String::from("minio"),
-            StoreType::SFTP => String::from("sftp"),
-        }
-    }
+/// Changed files of a snapshot that remain to be uploaded, saved when the
+/// uploads of a backup were stopped, such that the next run can upload them
+/// without finding and chunking them again.
+#[derive(Clone, Debug)]
+pub struct UploadPlan {
+    /// Digest of the snapshot being backed up.
+    pub snapshot: Checksum,
+    /// Date-time when the plan was made.
+    pub created: DateTime<Utc>,
+    /// Files yet to be uploaded.
+    pub files: Vec<PlannedFile>,
 }
 
-impl FromStr for StoreType {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "amazon" => Ok(StoreType::AMAZON),
-            "azure" => Ok(StoreType::AZURE),
-            "google" => Ok(StoreType::GOOGLE),
-            "local" => Ok(StoreType::LOCAL),
-            "minio" => Ok(StoreType::MINIO),
-            "sftp" => Ok(StoreType::SFTP),
-            _ => Err(anyhow!(format!("not a recognized store type: {}", s))),
+impl UploadPlan {
+    /// Construct an empty plan for the given snapshot.
+    pub fn new(snapshot: Checksum) -> Self {
+        Self {
+            snapshot,
+            created: Utc::now(),
+            files: vec![],
         }
     }
 }
 
-/// Store defines a location where packs will be saved.
+/// Pack whose upload to the stores was interrupted, kept in the workspace such
+/// that the next run uploads the very same file to the same bucket and object,
+/// allowing the stores to resume from their upload checkpoints.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Store {
-    /// Unique identifier for this store.
-    pub id: String,
-    /// Type of this store.
-    pub store_type: StoreType,
-    /// User-defined label for this store.
-    pub label: String,
-    /// Name/value pairs that make up this store configuration.
-    pub properties: HashMap<String, String>,
+pub struct PendingUpload {
+    /// Digest of the snapshot being backed up.
+    pub snapshot: Checksum,
+    /// Digest of the pack file.
+    pub digest: Checksum,
+    /// Name of the bucket to which the pack is uploaded.
+    pub bucket: String,
+    /// Name of the object within the bucket.
+    pub object: String,
 }
 
-impl std::hash::Hash for Store {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
-    }
+/// Chunk of a file listed in an emergency index.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyChunk {
+    /// Byte offset of the chunk within the file.
+    pub offset: u64,
+    /// Digest of the chunk, which is also its name within the pack.
+    pub digest: Checksum,
+    /// Digest of the pack that holds the chunk.
+    pub pack: Checksum,
 }
 
-/// Represents a directory tree that will be backed up according to a schedule,
-/// with pack files saved to a particular local or remote store.
-#[derive(Clone, Debug)]
-pub struct Dataset {
-    /// Unique identifier of this dataset.
-    pub id: String,
-    /// Local base path of dataset to be saved.
-    pub basepath: PathBuf,
-    /// Set of schedules for when to run the backup.
-    pub schedules: Vec<schedule::Schedule>,
-    /// Path for temporary pack building.
-    pub workspace: PathBuf,
-    /// Target size in bytes for pack files.
-    pub pack_size: u64,
-    /// Identifiers of the stores to contain pack files.
-    pub stores: Vec<String>,
-    /// List of file/directory exclusion patterns.
-    pub excludes: Vec<String>,
+/// File listed in an emergency index, with either its content or the
+/// coordinates of its chunks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyFile {
+    /// Path of the file relative to the base path of the dataset, with `/`
+    /// as the separator.
+    pub path: String,
+    /// Length of the file in bytes.
+    pub length: u64,
+    /// Content of very small files that are kept in the tree itself.
+    pub content: Option<Vec<u8>>,
+    /// Chunks of the file content, in order.
+    pub chunks: Vec<EmergencyChunk>,
 }
 
-// Default pack size is 64mb just because. With a typical ADSL home broadband
-// connection a 64mb pack file should take about 5 minutes to upload.
-const DEFAULT_PACK_SIZE: u64 = 67_108_864;
+/// Pack named in an emergency index, along with everything needed to fetch
+/// and unpack it without the database.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyPack {
+    /// Digest of the pack file.
+    pub digest: Checksum,
+    /// Locations of the pack file within the pack stores.
+    pub locations: Vec<PackLocation>,
+    /// Compression applied to the chunks of the pack.
+    pub compression: Compression,
+}
 
-impl Dataset {
-    /// Construct a Dataset with the path of the directory tree to be saved.
-    pub fn new(basepath: &Path) -> Dataset {
-        Dataset::with_pack_size(basepath, DEFAULT_PACK_SIZE)
+/// Small, self-describing listing of the important files of a snapshot, which
+/// is uploaded separately from the database such that those files can be
+/// recovered using only the pack store and the passphrase.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmergencyIndex {
+    /// Identifier of the dataset.
+    pub dataset: String,
+    /// Digest of the snapshot.
+    pub snapshot: Checksum,
+    /// Date-time when the index was made.
+    pub created: DateTime<Utc>,
+    /// Files that can be recovered from the index.
+    pub files: Vec<EmergencyFile>,
+    /// Packs that hold the chunks of the files.
+    pub packs: Vec<EmergencyPack>,
+}
+
+impl EmergencyIndex {
+    /// Construct an empty index for the given snapshot.
+    pub fn new(dataset: &str, snapshot: Checksum) -> Self {
+        Self {
+            dataset: dataset.to_owned(),
+            snapshot,
+            created: Utc::now(),
+            files: vec![],
+            packs: vec![],
+        }
     }
+}
 
-    /// Construct a `Dataset` with the given path and pack size.
-    pub fn with_pack_size(basepath: &Path, pack_size: u64) -> Self {
-        let id = xid::new().to_string();
-        let mut workspace = basepath.to_owned();
-        workspace.push(".tmp");
+/// Destination to which notifications about backups are posted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Webhook {
+    /// Unique identifier of the webhook.
+    pub id: String,
+    /// URL to which the JSON payload is posted.
+    pub url: String,
+    /// Dataset whose backups are reported, or `None` for every dataset.
+    pub dataset: Option<String>,
+    /// Hours without a completed backup after which a dataset is reported as
+    /// being stale, or `None` to never report stale datasets.
+    pub stale_hours: Option<u32>,
+    /// Time of day (local time) during which notifications are held until
+    /// later, like the quiet hours of email and the hour of the digest.
+    pub quiet_hours: Option<schedule::TimeRange>,
+    /// If `true`, finished and failed backups are reported once a day in a
+    /// digest rather than one at a time.
+    pub digest: bool,
+}
+
+impl Webhook {
+    /// Construct a webhook that posts to the given URL.
+    pub fn new(url: &str) -> Self {
         Self {
-            id,
-            basepath: basepath.to_owned(),
-            schedules: vec![],
-            workspace,
-            pack_size,
-            stores: vec![],
-            excludes: vec![],
+            id: xid::new().to_string(),
+            url: url.to_owned(),
+            dataset: None,
+            stale_hours: None,
+            quiet_hours: None,
+            digest: false,
         }
     }
 
-    /// Add the given store identifier to the dataset.
-    pub fn add_store(&mut self, store: &str) {
-        self.stores.push(store.to_owned());
+    /// Return `true` if the webhook reports on the given dataset.
+    pub fn applies_to(&self, dataset_id: &str) -> bool {
+        self.dataset.as_ref().map_or(true, |d| d == dataset_id)
     }
 
-    /// Add the given schedule to the dataset.
-    pub fn add_schedule(&mut self, schedule: schedule::Schedule) {
-        self.schedules.push(schedule);
+    /// Return `true` if the given time falls within the quiet hours, which
+    /// are compared with the local time of day, unlike backup schedules.
+    pub fn is_quiet(&self, datetime: DateTime<Utc>) -> bool {
+        // the time range considers only the time of day, so present the local
+        // time as if it were UTC
+        let local = datetime.with_timezone(&Local).naive_local().and_utc();
+        self.quiet_hours
+            .as_ref()
+            .map_or(false, |r| r.is_within(local))
     }
 }
 
-impl Default for Dataset {
-    fn default() -> Self {
-        Self {
-            id: String::new(),
-            basepath: PathBuf::new(),
-            schedules: vec![],
-            workspace: PathBuf::new(),
-            pack_size: 0,
-            stores: vec![],
-            excludes: vec![],
-        }
-    }
+/// Dataset that has been deleted, but may yet be restored until it is purged.
+#[derive(Clone, Debug)]
+pub struct TrashedDataset {
+    /// The dataset as it was when it was deleted.
+    pub dataset: Dataset,
+    /// Date/time when the dataset was deleted.
+    pub deleted: DateTime<Utc>,
 }
 
-impl fmt::Display for Dataset {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "dataset-{}:{:?}", self.id, self.basepath)
+impl TrashedDataset {
+    /// Construct a trash record for the dataset deleted at the current time.
+    pub fn new(dataset: Dataset) -> Self {
+        Self {
+            dataset,
+            deleted: Utc::now(),
+        }
     }
 }
 
@@ -383,25 +2511,107 @@ impl FromStr for TreeReference {
 }
 
 ///
-/// Return the last part of the path, converting to a String.
+/// Return the last part of the path as a String in composed (NFC) form,
+/// suitable for display, along with the original form of the name if it
+/// differs in any way.
 ///
-fn get_file_name(path: &Path) -> String {
+fn get_file_name(path: &Path) -> (String, Option<RawName>) {
     // ignore any paths that end in '..'
     if let Some(p) = path.file_name() {
-        // ignore any paths that failed UTF-8 translation
         if let Some(pp) = p.to_str() {
-            return pp.to_owned();
+            let composed: String = pp.nfc().collect();
+            if composed == pp {
+                return (composed, None);
+            }
+            return (composed, Some(RawName::UTF8(pp.to_owned())));
         }
+        // the name is not valid Unicode, keep the original for restoring
+        let lossy: String = p.to_string_lossy().nfc().collect();
+        return (lossy, RawName::from_os_str(p));
     }
     // normal conversion failed, return whatever garbage is there
-    path.to_string_lossy().into_owned()
+    (path.to_string_lossy().into_owned(), None)
+}
+
+///
+/// Original form of a tree entry name, recorded when the name of the entry
+/// cannot represent it exactly. The entry name is meant for display and
+/// searching, while the raw name is used to restore the entry.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RawName {
+    /// Valid Unicode that is not in composed form, such as the decomposed
+    /// (NFD) names produced by some macOS file systems.
+    UTF8(String),
+    /// Bytes of a Unix file name that is not valid UTF-8.
+    BYTES(Vec<u8>),
+    /// UTF-16 code units of a Windows file name that is not valid Unicode.
+    WIDE(Vec<u16>),
+}
+
+impl RawName {
+    /// Capture the name as represented by the operating system.
+    #[cfg(target_family = "unix")]
+    fn from_os_str(name: &OsStr) -> Option<RawName> {
+        use std::os::unix::ffi::OsStrExt;
+        Some(RawName::BYTES(name.as_bytes().to_vec()))
+    }
+
+    /// Capture the name as represented by the operating system.
+    #[cfg(target_family = "windows")]
+    fn from_os_str(name: &OsStr) -> Option<RawName> {
+        use std::os::windows::ffi::OsStrExt;
+        Some(RawName::WIDE(name.encode_wide().collect()))
+    }
+
+    /// Return the name in the form used by this operating system, or `None`
+    /// if the name came from a system with an incompatible encoding.
+    pub fn to_os_string(&self) -> Option<OsString> {
+        match self {
+            RawName::UTF8(value) => Some(OsString::from(value)),
+            #[cfg(target_family = "unix")]
+            RawName::BYTES(value) => {
+                use std::os::unix::ffi::OsStringExt;
+                Some(OsString::from_vec(value.clone()))
+            }
+            #[cfg(target_family = "windows")]
+            RawName::WIDE(value) => {
+                use std::os::windows::ffi::OsStringExt;
+                Some(OsString::from_wide(value))
+            }
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RawName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawName::UTF8(value) => {
+                let encoded = general_purpose::STANDARD.encode(value.as_bytes());
+                write!(f, "utf8-{}", encoded)
+            }
+            RawName::BYTES(value) => {
+                let encoded = general_purpose::STANDARD.encode(value);
+                write!(f, "bytes-{}", encoded)
+            }
+            RawName::WIDE(value) => {
+                let bytes: Vec<u8> = value.iter().flat_map(|u| u.to_be_bytes()).collect();
+                let encoded = general_purpose::STANDARD.encode(bytes);
+                write!(f, "wide-{}", encoded)
+            }
+        }
+    }
 }
 
 /// A file, directory, or symbolic link within a tree.
 #[derive(Clone, Debug)]
 pub struct TreeEntry {
-    /// Name of the file, directory, or symbolic link.
+    /// Name of the file, directory, or symbolic link, in composed form.
     pub name: String,
+    /// Original form of the name, if it differs from `name`.
+    pub raw_name: Option<RawName>,
     /// Unix file mode of the entry.
     pub mode: Option<u32>,
     /// Unix user identifier
@@ -429,7 +2639,7 @@ impl TreeEntry {
     /// Create an instance of `TreeEntry` based on the given path.
     ///
     pub fn new(path: &Path, reference: TreeReference) -> Self {
-        let name = get_file_name(path);
+        let (name, raw_name) = get_file_name(path);
         // Lot of error handling built-in so we can safely process any path
         // entry and not blow up the backup process.
         let metadata = fs::symlink_metadata(path);
@@ -445,6 +2655,7 @@ impl TreeEntry {
         };
         Self {
             name,
+            raw_name,
             mode: None,
             uid: None,
             gid: None,
@@ -457,6 +2668,15 @@ impl TreeEntry {
         }
     }
 
+    /// Return the name of the entry as it appeared in the file system, which
+    /// should be used when restoring the entry.
+    pub fn file_name(&self) -> OsString {
+        self.raw_name
+            .as_ref()
+            .and_then(|raw| raw.to_os_string())
+            .unwrap_or_else(|| OsString::from(&self.name))
+    }
+
     ///
     /// Set the `mode` property to either the Unix file mode or the
     /// Windows attributes value, both of which are u32 values.
@@ -562,7 +2782,13 @@ impl fmt::Display for TreeEntry {
             mtime,
             self.reference,
             self.name
-        )
+        )?;
+        // names that differ only in their original form must yield a
+        // different digest, otherwise the tree would appear unchanged
+        if let Some(raw) = self.raw_name.as_ref() {
+            write!(f, " {}", raw)?;
+        }
+        Ok(())
     }
 }
 
@@ -605,6 +2831,26 @@ impl fmt::Display for Tree {
     }
 }
 
+/// Entry found while walking the trees of a snapshot, along with its path
+/// relative to the root of the snapshot.
+#[derive(Clone, Debug)]
+pub struct WalkedEntry {
+    /// Path of the entry, with forward slashes as separators.
+    pub path: String,
+    /// The tree entry itself.
+    pub entry: TreeEntry,
+}
+
+/// Portion of the entries of a snapshot, in depth-first order, and the cursor
+/// from which the walk may continue.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotWalk {
+    /// Entries found in this portion of the walk.
+    pub entries: Vec<WalkedEntry>,
+    /// Opaque position of the next entry, or `None` if the walk is complete.
+    pub cursor: Option<String>,
+}
+
 ///
 /// `File` records the chunks associated with a saved file.
 ///
@@ -621,6 +2867,12 @@ pub struct File {
     /// If the list contains only a single entry, then the checksum is that of
     /// the pack record, avoiding the need for a chunk record.
     pub chunks: Vec<(u64, Checksum)>,
+    /// Date/time the photo was taken, if the file is an image with EXIF data
+    /// and the dataset extracts metadata.
+    pub taken: Option<DateTime<Utc>>,
+    /// Subject of the email message, if the file is a message and the dataset
+    /// extracts metadata.
+    pub subject: Option<String>,
 }
 
 impl File {
@@ -630,6 +2882,8 @@ impl File {
             digest,
             length,
             chunks,
+            taken: None,
+            subject: None,
         }
     }
 }
@@ -647,6 +2901,8 @@ pub struct FileCounts {
     pub very_large_files: u32,
     /// Mapping of file counts by log2 of their file size.
     pub file_sizes: HashMap<u8, u32>,
+    /// Number and size of files in each broad content category.
+    pub categories: HashMap<FileCategory, CategoryCount>,
 }
 
 // Size of a file that is smaller than the corresponding FileDef record. As
@@ -676,6 +2932,14 @@ impl FileCounts {
         }
     }
 
+    /// Update the category counts to track the file at the given path.
+    pub fn register_category(&mut self, path: &Path, size: u64) {
+        let category = FileCategory::from_path(path);
+        let entry = self.categories.entry(category).or_default();
+        entry.files += 1;
+        entry.bytes += size;
+    }
+
     /// Return the total number of files tracked by this record.
     pub fn total_files(&self) -> u64 {
         let mut count: u64 = self.very_small_files.into();
@@ -683,10 +2947,97 @@ impl FileCounts {
         for value in self.file_sizes.values() {
             count += *value as u64;
         }
-        count
+        count
+    }
+}
+
+///
+/// Broad category of file content, as determined by the file extension.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FileCategory {
+    Archive,
+    Audio,
+    Code,
+    Document,
+    Image,
+    Video,
+    Other,
+}
+
+impl FileCategory {
+    /// Determine the category of the file based on its extension.
+    pub fn from_path(path: &Path) -> Self {
+        let ext = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_ascii_lowercase(),
+            None => return FileCategory::Other,
+        };
+        match ext.as_str() {
+            "7z" | "bz2" | "dmg" | "gz" | "iso" | "jar" | "rar" | "tar" | "tgz" | "xz" | "zip"
+            | "zst" => FileCategory::Archive,
+            "aac" | "aiff" | "flac" | "m4a" | "mp3" | "ogg" | "opus" | "wav" | "wma" => {
+                FileCategory::Audio
+            }
+            "c" | "cc" | "cpp" | "cs" | "css" | "dart" | "erl" | "ex" | "exs" | "go" | "h"
+            | "hpp" | "hs" | "html" | "java" | "js" | "json" | "jsx" | "kt" | "lua" | "php"
+            | "pl" | "py" | "rb" | "rs" | "scala" | "sh" | "sql" | "swift" | "toml" | "ts"
+            | "tsx" | "xml" | "yaml" | "yml" => FileCategory::Code,
+            "csv" | "doc" | "docx" | "epub" | "key" | "md" | "numbers" | "odp" | "ods" | "odt"
+            | "org" | "pages" | "pdf" | "ppt" | "pptx" | "rtf" | "tex" | "txt" | "xls" | "xlsx" => {
+                FileCategory::Document
+            }
+            "arw" | "bmp" | "cr2" | "dng" | "gif" | "heic" | "heif" | "jpeg" | "jpg" | "nef"
+            | "png" | "psd" | "svg" | "tif" | "tiff" | "webp" => FileCategory::Image,
+            "3gp" | "avi" | "m4v" | "mkv" | "mov" | "mp4" | "mpeg" | "mpg" | "webm" | "wmv" => {
+                FileCategory::Video
+            }
+            _ => FileCategory::Other,
+        }
+    }
+}
+
+impl fmt::Display for FileCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileCategory::Archive => write!(f, "archive"),
+            FileCategory::Audio => write!(f, "audio"),
+            FileCategory::Code => write!(f, "code"),
+            FileCategory::Document => write!(f, "document"),
+            FileCategory::Image => write!(f, "image"),
+            FileCategory::Video => write!(f, "video"),
+            FileCategory::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl FromStr for FileCategory {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "archive" => Ok(FileCategory::Archive),
+            "audio" => Ok(FileCategory::Audio),
+            "code" => Ok(FileCategory::Code),
+            "document" => Ok(FileCategory::Document),
+            "image" => Ok(FileCategory::Image),
+            "video" => Ok(FileCategory::Video),
+            "other" => Ok(FileCategory::Other),
+            _ => Err(anyhow!(format!("{} is not a recognized file category", s))),
+        }
     }
 }
 
+///
+/// Number of files, and their combined size, within a file category.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CategoryCount {
+    /// Number of files in this category.
+    pub files: u32,
+    /// Combined size of the files in bytes.
+    pub bytes: u64,
+}
+
 ///
 /// A `Snapshot` represents a single backup, either in progress or completed.
 /// It references a possible parent snapshot, and a tree representing the files
@@ -707,6 +3058,9 @@ pub struct Snapshot {
     pub file_counts: FileCounts,
     /// Digest of the root tree for this snapshot.
     pub tree: Checksum,
+    /// Time when the backup was last paused before completing, having run
+    /// out of time, such that it resumes when the schedule next comes due.
+    pub paused: Option<DateTime<Utc>>,
 }
 
 impl Snapshot {
@@ -720,6 +3074,7 @@ impl Snapshot {
             end_time: None,
             file_counts,
             tree,
+            paused: None,
         };
         // Need to compute a checksum and save that as the "key" for this
         // snapshot, cannot compute the checksum later because the object is
@@ -738,6 +3093,16 @@ impl Snapshot {
     pub fn set_start_time(&mut self, start_time: DateTime<Utc>) {
         self.start_time = start_time;
     }
+
+    /// Produce a copy of this snapshot with a different parent, computing the
+    /// new digest accordingly.
+    pub fn with_parent(&self, parent: Option<Checksum>) -> Self {
+        let mut snapshot = self.clone();
+        snapshot.parent = parent;
+        let formed = snapshot.to_string();
+        snapshot.digest = Checksum::sha1_from_bytes(formed.as_bytes());
+        snapshot
+    }
 }
 
 /// A SHA1 of all zeroes.
@@ -807,6 +3172,63 @@ impl From<PackLocation> for Coordinates {
     }
 }
 
+///
+/// Compression applied to the chunks in a pack file.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Compressed by the archive format itself, using Zstandard at its default
+    /// level, as all packs were before the algorithm could be chosen.
+    #[default]
+    Archive,
+    /// Chunks are stored as-is, such as for media that is already compressed.
+    None,
+    /// Each chunk is compressed with Zstandard at the given level.
+    Zstd(i32),
+    /// Each chunk is compressed with LZ4, trading size for speed.
+    Lz4,
+}
+
+impl Compression {
+    /// Returns `true` if the chunks are compressed individually, rather than
+    /// by the archive, and must be decoded after they are extracted.
+    pub fn per_chunk(&self) -> bool {
+        matches!(self, Compression::Zstd(_) | Compression::Lz4)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::Archive => write!(f, "zstd"),
+            Compression::None => write!(f, "none"),
+            Compression::Zstd(level) => write!(f, "zstd:{}", level),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim().to_lowercase();
+        match value.as_str() {
+            "zstd" => Ok(Compression::Archive),
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            _ => {
+                let level = value
+                    .strip_prefix("zstd:")
+                    .and_then(|l| l.parse::<i32>().ok())
+                    .filter(|l| (1..=22).contains(l))
+                    .ok_or_else(|| anyhow!(format!("not a recognized compression: {}", s)))?;
+                Ok(Compression::Zstd(level))
+            }
+        }
+    }
+}
+
 /// Type for database record of saved packs.
 #[derive(Clone, Debug)]
 pub struct Pack {
@@ -814,6 +3236,11 @@ pub struct Pack {
     pub digest: Checksum,
     /// List of pack locations.
     pub locations: Vec<PackLocation>,
+    /// Hex-encoded MD5 digest of the pack file, as uploaded, which can be
+    /// compared to the digest that some stores keep for each object.
+    pub md5: Option<String>,
+    /// Compression applied to the chunks within the pack file.
+    pub compression: Compression,
 }
 
 impl Pack {
@@ -823,8 +3250,22 @@ impl Pack {
         Self {
             digest,
             locations: coords,
+            md5: None,
+            compression: Compression::Archive,
         }
     }
+
+    /// Set the MD5 digest of the pack file.
+    pub fn md5(mut self, md5: String) -> Self {
+        self.md5 = Some(md5);
+        self
+    }
+
+    /// Set the compression applied to the chunks within the pack file.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
 }
 
 /// Information about an entry in a pack file.
@@ -952,6 +3393,237 @@ pub struct RecordCounts {
     pub xattr: usize,
 }
 
+/// Projected monthly cost of keeping the packs of a dataset in a store.
+#[derive(Clone, Debug, Default)]
+pub struct CostEstimate {
+    /// Identifier of the store.
+    pub store_id: String,
+    /// User-defined label of the store.
+    pub label: String,
+    /// Number of packs saved to the store.
+    pub pack_count: u64,
+    /// Approximate number of bytes occupied by the packs.
+    pub stored_bytes: u64,
+    /// Average number of packs uploaded each month.
+    pub monthly_uploads: f64,
+    /// Cost of storing the packs for one month.
+    pub storage_cost: f64,
+    /// Cost of the requests to upload a month's worth of packs.
+    pub upload_cost: f64,
+    /// One-time cost of downloading every pack, as for a full restore.
+    pub restore_cost: f64,
+}
+
+/// Storage consumed by a dataset, as for attributing usage to its owner.
+#[derive(Clone, Debug, Default)]
+pub struct DatasetUsage {
+    /// Identifier of the dataset.
+    pub dataset_id: String,
+    /// Combined size of the files in the latest snapshot.
+    pub logical_size: u64,
+    /// Combined size of the distinct file content across all snapshots of
+    /// the dataset, before compression.
+    pub stored_bytes: u64,
+    /// Growth of the most recent snapshots, newest first.
+    pub growth: Vec<SnapshotGrowth>,
+    /// Portion of the stored bytes held by each store.
+    pub stores: Vec<StoreBytes>,
+}
+
+/// Size of a snapshot and the content it added to the dataset.
+#[derive(Clone, Debug)]
+pub struct SnapshotGrowth {
+    /// Digest of the snapshot.
+    pub digest: Checksum,
+    /// Time when the snapshot was started.
+    pub start_time: DateTime<Utc>,
+    /// Combined size of the files in the snapshot.
+    pub logical_size: u64,
+    /// Size of the content that no earlier snapshot contained.
+    pub added_bytes: u64,
+}
+
+/// Number of bytes of a dataset that are held by a store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreBytes {
+    /// Identifier of the store.
+    pub store_id: String,
+    /// Combined size of the content in the store, before compression.
+    pub bytes: u64,
+}
+
+/// Deduplication achieved across the snapshots of a dataset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Identifier of the dataset.
+    pub dataset_id: String,
+    /// Date-time when the statistics were computed.
+    pub computed: DateTime<Utc>,
+    /// Combined size of the files of every snapshot, as if each snapshot
+    /// were stored in full.
+    pub logical_bytes: u64,
+    /// Combined size of the distinct chunks referenced by any snapshot,
+    /// before compression.
+    pub physical_bytes: u64,
+    /// Statistics of each snapshot, newest first.
+    pub snapshots: Vec<SnapshotDedup>,
+}
+
+impl DedupStats {
+    /// Return the ratio of the logical bytes to the physical bytes, which is
+    /// 1.0 when nothing is stored.
+    pub fn ratio(&self) -> f64 {
+        if self.physical_bytes == 0 {
+            1.0
+        } else {
+            self.logical_bytes as f64 / self.physical_bytes as f64
+        }
+    }
+}
+
+/// Deduplication statistics of a single snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotDedup {
+    /// Digest of the snapshot.
+    pub digest: Checksum,
+    /// Time when the snapshot was started.
+    pub start_time: DateTime<Utc>,
+    /// Combined size of the files in the snapshot.
+    pub logical_size: u64,
+    /// Size of the chunks that no other snapshot references, which is the
+    /// space that would be freed by removing only this snapshot.
+    pub unique_bytes: u64,
+}
+
+/// Entry within the latest snapshot of a dataset that matched a search.
+///
+/// The `tree`, `entry`, `filepath`, and `dataset_id` values are suitable for
+/// requesting the entry be restored.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    /// Identifier of the dataset.
+    pub dataset_id: String,
+    /// Digest of the snapshot containing the entry.
+    pub snapshot: Checksum,
+    /// Digest of the tree containing the entry.
+    pub tree: Checksum,
+    /// Name of the entry within the tree.
+    pub entry: String,
+    /// Path of the entry relative to the dataset base path.
+    pub filepath: PathBuf,
+    /// Modification time of the entry.
+    pub modified: DateTime<Utc>,
+    /// True if the entry is a directory.
+    pub directory: bool,
+    /// Date/time the photo was taken, if known.
+    pub taken: Option<DateTime<Utc>>,
+    /// Subject of the email message, if known.
+    pub subject: Option<String>,
+}
+
+/// Distinct version of a file found by walking the snapshots of a dataset.
+///
+/// The `tree` and `entry` values are suitable for requesting that this
+/// version of the file be restored.
+#[derive(Clone, Debug)]
+pub struct FileVersion {
+    /// Digest of the earliest snapshot containing this version.
+    pub snapshot: Checksum,
+    /// Start time of the earliest snapshot containing this version.
+    pub snapshot_time: DateTime<Utc>,
+    /// Digest of the tree containing the entry.
+    pub tree: Checksum,
+    /// Name of the entry within the tree.
+    pub entry: String,
+    /// Modification time of the entry.
+    pub modified: DateTime<Utc>,
+    /// Length of the file in bytes.
+    pub size: u64,
+    /// Digest of the file content, if it is not a very small file.
+    pub digest: Option<Checksum>,
+    /// True if the content differs from the previous version, as opposed to
+    /// only the modification time.
+    pub changed: bool,
+}
+
+/// Entries of a directory within a snapshot, found by resolving a path.
+///
+/// The `tree` digest and the name of an entry are suitable for requesting
+/// that the entry be restored.
+#[derive(Clone, Debug)]
+pub struct DirectoryListing {
+    /// Digest of the snapshot containing the directory.
+    pub snapshot: Checksum,
+    /// Digest of the tree of the directory.
+    pub tree: Checksum,
+    /// Path of the directory relative to the dataset base path.
+    pub path: PathBuf,
+    /// Entries of the directory, in the order recorded in the tree.
+    pub entries: Vec<ListingEntry>,
+}
+
+/// Entry within a directory listing.
+#[derive(Clone, Debug)]
+pub struct ListingEntry {
+    /// Name of the file, directory, or symbolic link.
+    pub name: String,
+    /// Reference to the entry itself.
+    pub reference: TreeReference,
+    /// Unix file mode of the entry.
+    pub mode: Option<u32>,
+    /// Name of the owning user.
+    pub user: Option<String>,
+    /// Name of the owning group.
+    pub group: Option<String>,
+    /// Modification time of the entry.
+    pub modified: DateTime<Utc>,
+    /// Length of the file in bytes, if the entry is a file.
+    pub size: Option<u64>,
+}
+
+impl From<&TreeEntry> for ListingEntry {
+    fn from(entry: &TreeEntry) -> Self {
+        let size = match &entry.reference {
+            TreeReference::SMALL(contents) => Some(contents.len() as u64),
+            _ => None,
+        };
+        Self {
+            name: entry.name.clone(),
+            reference: entry.reference.clone(),
+            mode: entry.mode,
+            user: entry.user.clone(),
+            group: entry.group.clone(),
+            modified: entry.mtime,
+            size,
+        }
+    }
+}
+
+/// Records that describe a single snapshot, sent by one server to another for
+/// the purpose of replicating the catalog.
+///
+/// Only those trees, files, chunks, packs, and extended attributes that are
+/// not already referenced by the parent snapshot are included.
+#[derive(Clone, Debug)]
+pub struct ReplicaBatch {
+    /// Dataset to which the snapshot belongs.
+    pub dataset: Dataset,
+    /// Stores of the dataset, needed to retrieve the packs.
+    pub stores: Vec<Store>,
+    /// The snapshot being replicated.
+    pub snapshot: Snapshot,
+    /// Trees that are new with this snapshot.
+    pub trees: Vec<Tree>,
+    /// Files that are new with this snapshot.
+    pub files: Vec<File>,
+    /// Chunks of the new files.
+    pub chunks: Vec<Chunk>,
+    /// Packs containing the new files and chunks.
+    pub packs: Vec<Pack>,
+    /// Extended attributes of the new entries, keyed by digest.
+    pub xattrs: Vec<(Checksum, Vec<u8>)>,
+}
+
 impl fmt::Display for RecordCounts {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -1048,6 +3720,662 @@ mod tests {
         assert_eq!(uuid, "dHJn1W5wVxGKmqQMJMFzDw");
     }
 
+    #[test]
+    fn test_store_tiering_policy() {
+        let mut store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "cold storage".to_owned(),
+            properties: HashMap::new(),
+        };
+        assert!(store.tiering_policy().is_none());
+        store
+            .properties
+            .insert("tiering_class".to_owned(), "GLACIER_IR".to_owned());
+        assert!(store.tiering_policy().is_none());
+        store
+            .properties
+            .insert("tiering_days".to_owned(), "notanumber".to_owned());
+        assert!(store.tiering_policy().is_none());
+        store
+            .properties
+            .insert("tiering_days".to_owned(), "90".to_owned());
+        let policy = store.tiering_policy().unwrap();
+        assert_eq!(policy.days, 90);
+        assert_eq!(policy.storage_class, "GLACIER_IR");
+    }
+
+    #[test]
+    fn test_store_object_size_limits() {
+        let mut amazon = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "infrequent".to_owned(),
+            properties: HashMap::new(),
+        };
+        amazon
+            .properties
+            .insert("storage".to_owned(), "STANDARD".to_owned());
+        let limits = amazon.object_size_limits();
+        assert_eq!(limits.max_object, Some(5_368_709_120));
+        assert_eq!(limits.min_billable, 0);
+        amazon
+            .properties
+            .insert("tiering_class".to_owned(), "GLACIER_IR".to_owned());
+        let limits = amazon.object_size_limits();
+        assert_eq!(limits.min_billable, 131_072);
+        assert_eq!(limits.min_days, 90);
+        amazon
+            .properties
+            .insert("lifecycle_class".to_owned(), "DEEP_ARCHIVE".to_owned());
+        assert_eq!(amazon.object_size_limits().min_days, 180);
+        let local = Store {
+            id: "deadbeef".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "nearby".to_owned(),
+            properties: HashMap::new(),
+        };
+        assert_eq!(local.object_size_limits(), Default::default());
+        let mut azure = Store {
+            id: "abadcafe".to_owned(),
+            store_type: StoreType::AZURE,
+            label: "blobs".to_owned(),
+            properties: HashMap::new(),
+        };
+        assert_eq!(azure.object_size_limits().min_days, 0);
+        azure
+            .properties
+            .insert("access_tier".to_owned(), "archive".to_owned());
+        assert_eq!(azure.object_size_limits().min_days, 180);
+        let mut google = Store {
+            id: "feedface".to_owned(),
+            store_type: StoreType::GOOGLE,
+            label: "buckets".to_owned(),
+            properties: HashMap::new(),
+        };
+        google
+            .properties
+            .insert("storage".to_owned(), "ARCHIVE".to_owned());
+        assert_eq!(google.object_size_limits().min_days, 365);
+        let limits = ObjectSizeLimits::for_stores(&[amazon, local, azure]);
+        assert_eq!(limits.max_object, Some(5_242_880_000));
+        assert_eq!(limits.min_billable, 131_072);
+        assert_eq!(limits.min_days, 180);
+        assert!(limits.check(67_108_864).is_none());
+        let msg = limits.check(65_536).unwrap();
+        assert!(msg.contains("minimum billable"));
+        let msg = limits.check(6_000_000_000).unwrap();
+        assert!(msg.contains("exceeds the store limit"));
+        assert_eq!(limits.clamp(65_536), 131_072);
+        assert_eq!(limits.clamp(6_000_000_000), 5_242_880_000);
+        assert_eq!(limits.clamp(67_108_864), 67_108_864);
+        let limits = ObjectSizeLimits::for_stores(&[]);
+        assert!(limits.check(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_store_monthly_cap() {
+        let mut store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "metered".to_owned(),
+            properties: HashMap::new(),
+        };
+        assert!(store.monthly_cap().is_none());
+        store
+            .properties
+            .insert("monthly_cap".to_owned(), "0".to_owned());
+        assert!(store.monthly_cap().is_none());
+        store
+            .properties
+            .insert("monthly_cap".to_owned(), "1073741824".to_owned());
+        assert_eq!(store.monthly_cap(), Some(1073741824));
+        let time = Utc.with_ymd_and_hms(2024, 3, 31, 23, 59, 59).unwrap();
+        assert_eq!(BandwidthUsage::month_of(time), "2024-03");
+    }
+
+    #[test]
+    fn test_dataset_change_trigger() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.change_trigger().is_none());
+        dataset
+            .properties
+            .insert("trigger_files".to_owned(), "many".to_owned());
+        assert!(dataset.change_trigger().is_none());
+        dataset
+            .properties
+            .insert("trigger_files".to_owned(), "100".to_owned());
+        let trigger = dataset.change_trigger().unwrap();
+        assert_eq!(trigger.files, Some(100));
+        assert_eq!(trigger.bytes, None);
+        assert_eq!(trigger.cooldown, Duration::from_secs(3_600));
+        assert_eq!(trigger.quiet, Duration::from_secs(300));
+        assert!(!trigger.exceeded(99, 1_000_000_000));
+        assert!(trigger.exceeded(100, 0));
+        dataset
+            .properties
+            .insert("trigger_bytes".to_owned(), "1048576".to_owned());
+        dataset
+            .properties
+            .insert("trigger_cooldown".to_owned(), "600".to_owned());
+        dataset
+            .properties
+            .insert("trigger_quiet".to_owned(), "60".to_owned());
+        let trigger = dataset.change_trigger().unwrap();
+        assert_eq!(trigger.cooldown, Duration::from_secs(600));
+        assert_eq!(trigger.quiet, Duration::from_secs(60));
+        assert!(trigger.exceeded(1, 1_048_576));
+        assert!(!trigger.exceeded(1, 1_048_575));
+    }
+
+    #[test]
+    fn test_dataset_copy_patterns() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.copy_patterns().is_empty());
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "default".to_owned());
+        assert!(dataset.copy_patterns().contains(&"*.sqlite".to_owned()));
+        dataset.properties.insert(
+            "copy_patterns".to_owned(),
+            "*.kdbx, places.sqlite,".to_owned(),
+        );
+        assert_eq!(dataset.copy_patterns(), vec!["*.kdbx", "places.sqlite"]);
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "".to_owned());
+        assert!(dataset.copy_patterns().is_empty());
+    }
+
+    #[test]
+    fn test_dataset_healthcheck_url() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.healthcheck_url().is_none());
+        dataset
+            .properties
+            .insert("healthcheck_url".to_owned(), "ftp://example.com".to_owned());
+        assert!(dataset.healthcheck_url().is_none());
+        dataset.properties.insert(
+            "healthcheck_url".to_owned(),
+            " https://hc-ping.com/abc123 ".to_owned(),
+        );
+        assert_eq!(
+            dataset.healthcheck_url(),
+            Some("https://hc-ping.com/abc123".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_dataset_max_runtime() {
+        use schedule::{Schedule, TimeRange};
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        let hourly = Schedule::Hourly;
+        let nightly = Schedule::Daily(Some(TimeRange::new(22, 0, 6, 0)));
+        dataset.add_schedule(hourly.clone());
+        dataset.add_schedule(nightly.clone());
+        assert!(dataset.max_runtime(&hourly).is_none());
+        dataset
+            .properties
+            .insert("max_runtime".to_owned(), "90".to_owned());
+        assert_eq!(
+            dataset.max_runtime(&hourly),
+            Some(Duration::from_secs(5400))
+        );
+        assert_eq!(
+            dataset.max_runtime(&nightly),
+            Some(Duration::from_secs(5400))
+        );
+        dataset
+            .properties
+            .insert("max_runtime".to_owned(), ", 240".to_owned());
+        assert!(dataset.max_runtime(&hourly).is_none());
+        assert_eq!(
+            dataset.max_runtime(&nightly),
+            Some(Duration::from_secs(14400))
+        );
+        // schedule not belonging to the dataset, such as for a manual start
+        assert!(dataset.max_runtime(&Schedule::Weekly(None)).is_none());
+    }
+
+    #[test]
+    fn test_chain_entry() {
+        let snapshot = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let first = ChainEntry::new("store1", None, snapshot.clone());
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.previous.to_string(), NULL_SHA1);
+        assert!(first.is_intact());
+        let second = ChainEntry::new("store1", Some(&first), snapshot);
+        assert_eq!(second.sequence, 2);
+        assert_eq!(second.previous, first.digest);
+        assert!(second.is_intact());
+        // round trip through the text form
+        let parsed = ChainEntry::parse("store1", &second.to_string()).unwrap();
+        assert_eq!(parsed, second);
+        assert!(ChainEntry::parse("store1", "1 2 3").is_err());
+        // tampering with any field breaks the digest
+        let mut altered = parsed.clone();
+        altered.sequence = 3;
+        assert!(!altered.is_intact());
+    }
+
+    #[test]
+    fn test_webhook_applies_to() {
+        let mut webhook = Webhook::new("https://example.com/hook");
+        assert!(webhook.applies_to("dataset1"));
+        webhook.dataset = Some("dataset2".to_owned());
+        assert!(!webhook.applies_to("dataset1"));
+        assert!(webhook.applies_to("dataset2"));
+    }
+
+    #[test]
+    fn test_webhook_is_quiet() {
+        let mut webhook = Webhook::new("https://example.com/hook");
+        let now = Utc::now();
+        assert!(!webhook.is_quiet(now));
+        // quiet hours are in local time, starting at the current local hour
+        let hour = now.with_timezone(&Local).hour();
+        webhook.quiet_hours = Some(schedule::TimeRange::new(hour, 0, (hour + 1) % 24, 0));
+        assert!(webhook.is_quiet(now));
+        assert!(!webhook.is_quiet(now + chrono::TimeDelta::hours(2)));
+    }
+
+    #[test]
+    fn test_compression_fromstr() {
+        for compression in [
+            Compression::Archive,
+            Compression::None,
+            Compression::Zstd(19),
+            Compression::Lz4,
+        ] {
+            let actual = Compression::from_str(&compression.to_string()).unwrap();
+            assert_eq!(actual, compression);
+        }
+        assert_eq!(Compression::from_str(" LZ4 ").unwrap(), Compression::Lz4);
+        assert!(Compression::from_str("zstd:0").is_err());
+        assert!(Compression::from_str("zstd:23").is_err());
+        assert!(Compression::from_str("brotli").is_err());
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert_eq!(dataset.compression(), Compression::Archive);
+        dataset
+            .properties
+            .insert("compression".into(), "none".into());
+        assert_eq!(dataset.compression(), Compression::None);
+        dataset
+            .properties
+            .insert("compression".into(), "gzip".into());
+        assert_eq!(dataset.compression(), Compression::Archive);
+    }
+
+    #[test]
+    fn test_dataset_chunker_params() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        let params = dataset.chunker_params(65_536);
+        assert_eq!(params, ChunkerParams::new(65_536));
+        assert_eq!(params.min_size, 16_384);
+        assert_eq!(params.max_size, 262_144);
+        assert_eq!(params.normalization, 1);
+        dataset
+            .properties
+            .insert("chunk_size".into(), "1048576".into());
+        dataset
+            .properties
+            .insert("chunk_max_size".into(), "8388608".into());
+        dataset
+            .properties
+            .insert("chunk_normalization".into(), "2".into());
+        let params = dataset.chunker_params(65_536);
+        assert_eq!(params.min_size, 262_144);
+        assert_eq!(params.avg_size, 1_048_576);
+        assert_eq!(params.max_size, 8_388_608);
+        assert_eq!(params.normalization, 2);
+        // invalid values are ignored
+        dataset.properties.insert("chunk_size".into(), "big".into());
+        dataset
+            .properties
+            .insert("chunk_min_size".into(), "0".into());
+        dataset
+            .properties
+            .insert("chunk_normalization".into(), "7".into());
+        let params = dataset.chunker_params(65_536);
+        assert_eq!(params.min_size, 16_384);
+        assert_eq!(params.avg_size, 65_536);
+        assert_eq!(params.normalization, 1);
+    }
+
+    #[test]
+    fn test_dataset_file_concurrency() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.file_concurrency().is_none());
+        dataset
+            .properties
+            .insert("file_concurrency".into(), "2".into());
+        assert_eq!(dataset.file_concurrency(), Some(2));
+        dataset
+            .properties
+            .insert("file_concurrency".into(), "0".into());
+        assert!(dataset.file_concurrency().is_none());
+        dataset
+            .properties
+            .insert("file_concurrency".into(), "few".into());
+        assert!(dataset.file_concurrency().is_none());
+    }
+
+    #[test]
+    fn test_dataset_resource_limits() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.resource_limits().is_empty());
+        dataset
+            .properties
+            .insert("upload_rate".into(), "512".into());
+        dataset.properties.insert("hash_threads".into(), "0".into());
+        dataset.properties.insert("niceness".into(), "40".into());
+        dataset
+            .properties
+            .insert("limits_window".into(), "13:00-21:00".into());
+        let limits = dataset.resource_limits();
+        assert_eq!(limits.upload_rate, Some(524_288));
+        assert!(limits.hash_threads.is_none());
+        assert_eq!(limits.niceness, Some(19));
+        assert_eq!(limits.window, Some(schedule::TimeRange::new(13, 0, 21, 0)));
+    }
+
+    #[test]
+    fn test_maintenance_task_fromstr() {
+        for task in [
+            MaintenanceTask::Verify,
+            MaintenanceTask::Prune,
+            MaintenanceTask::Compact,
+            MaintenanceTask::Health,
+        ] {
+            let actual = MaintenanceTask::from_str(&task.to_string()).unwrap();
+            assert_eq!(actual, task);
+        }
+        assert_eq!(
+            MaintenanceTask::from_str(" Verify ").unwrap(),
+            MaintenanceTask::Verify
+        );
+        assert!(MaintenanceTask::from_str("defrag").is_err());
+    }
+
+    #[test]
+    fn test_verify_depth_fromstr() {
+        for depth in [VerifyDepth::Metadata, VerifyDepth::Packs, VerifyDepth::Full] {
+            let actual = VerifyDepth::from_str(&depth.to_string()).unwrap();
+            assert_eq!(actual, depth);
+        }
+        assert_eq!(
+            VerifyDepth::from_str(" PACKS ").unwrap(),
+            VerifyDepth::Packs
+        );
+        assert!(VerifyDepth::from_str("deep").is_err());
+    }
+
+    #[test]
+    fn test_restore_verify_fromstr() {
+        for level in [
+            RestoreVerify::None,
+            RestoreVerify::Pack,
+            RestoreVerify::Chunk,
+            RestoreVerify::File,
+        ] {
+            let actual = RestoreVerify::from_str(&level.to_string()).unwrap();
+            assert_eq!(actual, level);
+        }
+        assert_eq!(RestoreVerify::default(), RestoreVerify::Pack);
+        assert_eq!(
+            RestoreVerify::from_str(" Chunk ").unwrap(),
+            RestoreVerify::Chunk
+        );
+        assert!(RestoreVerify::None < RestoreVerify::Pack);
+        assert!(RestoreVerify::Chunk < RestoreVerify::File);
+        assert!(RestoreVerify::from_str("paranoid").is_err());
+    }
+
+    #[test]
+    fn test_dataset_task_schedule() {
+        for task in MaintenanceTask::DATASET_TASKS {
+            let actual = MaintenanceTask::from_str(&task.to_string()).unwrap();
+            assert_eq!(actual, task);
+        }
+        assert!(MaintenanceTask::from_str("backup").is_err());
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset
+            .task_schedule(MaintenanceTask::PruneSnapshots)
+            .is_none());
+        assert_eq!(dataset.verify_depth(), VerifyDepth::Packs);
+        assert!(dataset.verify_sample().is_none());
+        dataset
+            .properties
+            .insert("prune_schedule".into(), " Weekly ".into());
+        dataset
+            .properties
+            .insert("verify_schedule".into(), "fortnightly".into());
+        dataset
+            .properties
+            .insert("verify_depth".into(), "full".into());
+        dataset
+            .properties
+            .insert("verify_sample".into(), "20".into());
+        assert_eq!(
+            dataset.task_schedule(MaintenanceTask::PruneSnapshots),
+            Some(schedule::Schedule::Weekly(None))
+        );
+        assert!(dataset
+            .task_schedule(MaintenanceTask::VerifySnapshot)
+            .is_none());
+        assert!(dataset
+            .task_schedule(MaintenanceTask::UploadDatabase)
+            .is_none());
+        // system-wide tasks are never scheduled for a dataset
+        dataset
+            .properties
+            .insert("compact_schedule".into(), "daily".into());
+        assert!(dataset.task_schedule(MaintenanceTask::Compact).is_none());
+        assert_eq!(dataset.verify_depth(), VerifyDepth::Full);
+        assert_eq!(dataset.verify_sample(), Some(20));
+    }
+
+    #[test]
+    fn test_dataset_retention_policy() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(dataset.retention_policy().is_empty());
+        dataset
+            .properties
+            .insert("retain_days".into(), " 14 ".into());
+        dataset
+            .properties
+            .insert("retain_weekly".into(), "8".into());
+        dataset
+            .properties
+            .insert("retain_monthly".into(), "0".into());
+        dataset
+            .properties
+            .insert("retain_yearly".into(), "forever".into());
+        let policy = dataset.retention_policy();
+        assert!(!policy.is_empty());
+        assert_eq!(
+            policy,
+            RetentionPolicy {
+                days: Some(14),
+                weekly: Some(8),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_display() {
+        let message = Message::new(MessageCode::NoSuchStore).with("id", "cafebabe");
+        assert_eq!(message.code.to_string(), "NO_SUCH_STORE");
+        assert_eq!(message.params.get("id"), Some(&String::from("cafebabe")));
+        assert_eq!(message.to_string(), "no such store: cafebabe");
+        let message = Message::new(MessageCode::ManySnapshots)
+            .with("path", "/home/moon")
+            .with("count", &100);
+        assert_eq!(
+            message.to_string(),
+            "dataset /home/moon has at least 100 snapshots, pruning them may allow removing packs"
+        );
+        let message = Message::new(MessageCode::NoMatchingStore);
+        assert_eq!(message.to_string(), "no matching store found");
+        // an error may be recovered as a message
+        let err: Error = Message::new(MessageCode::MissingTree)
+            .with("digest", "sha1-cafe")
+            .into();
+        let message = err.downcast::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::MissingTree);
+    }
+
+    #[test]
+    fn test_event_kind_fromstr() {
+        for kind in [
+            EventKind::BackupStarted,
+            EventKind::BackupFinished,
+            EventKind::BackupFailed,
+            EventKind::PackUploaded,
+            EventKind::SnapshotsPruned,
+            EventKind::RestoreRequested,
+            EventKind::StoreCreated,
+            EventKind::StoreUpdated,
+            EventKind::StoreDeleted,
+            EventKind::SentinelFailed,
+        ] {
+            let actual = EventKind::from_str(&kind.to_string()).unwrap();
+            assert_eq!(actual, kind);
+        }
+        assert!(EventKind::from_str("backup_paused").is_err());
+    }
+
+    #[test]
+    fn test_store_quota() {
+        let mut store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "usb disk".to_owned(),
+            properties: HashMap::new(),
+        };
+        assert!(store.quota().is_none());
+        store
+            .properties
+            .insert("quota_bytes".to_owned(), "0".to_owned());
+        assert!(store.quota().is_none());
+        store
+            .properties
+            .insert("quota_bytes".to_owned(), "1000".to_owned());
+        store
+            .properties
+            .insert("quota_objects".to_owned(), "10".to_owned());
+        let quota = store.quota().unwrap();
+        assert_eq!(quota.max_bytes, Some(1000));
+        assert_eq!(quota.max_objects, Some(10));
+
+        let mut usage = StoreUsage {
+            objects: 9,
+            bytes: 900,
+        };
+        assert!(!quota.is_reached(&usage));
+        assert!(!quota.would_exceed(&usage, 100));
+        assert!(quota.would_exceed(&usage, 101));
+        usage.objects = 10;
+        assert!(quota.is_reached(&usage));
+        assert!(quota.would_exceed(&usage, 1));
+
+        usage.remove_objects(5);
+        assert_eq!(usage.objects, 5);
+        assert_eq!(usage.bytes, 450);
+        usage.remove_objects(8);
+        assert_eq!(usage, StoreUsage::default());
+    }
+
+    #[test]
+    fn test_store_etag() {
+        let mut store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "my local".to_owned(),
+            properties: HashMap::new(),
+        };
+        store
+            .properties
+            .insert("basepath".to_owned(), "/home/planet".to_owned());
+        store
+            .properties
+            .insert("sync".to_owned(), "true".to_owned());
+        let etag = store.etag();
+        assert_eq!(etag.len(), 16);
+        // insertion order of the properties does not matter
+        let mut copy = store.clone();
+        copy.properties.clear();
+        copy.properties.insert("sync".to_owned(), "true".to_owned());
+        copy.properties
+            .insert("basepath".to_owned(), "/home/planet".to_owned());
+        assert_eq!(copy.etag(), etag);
+        store.label = "your local".to_owned();
+        assert_ne!(store.etag(), etag);
+        store.label = "my local".to_owned();
+        assert_eq!(store.etag(), etag);
+        store
+            .properties
+            .insert("sync".to_owned(), "false".to_owned());
+        assert_ne!(store.etag(), etag);
+    }
+
+    #[test]
+    fn test_store_secrets() {
+        let mut saved = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::MINIO,
+            label: "my minio".to_owned(),
+            properties: HashMap::new(),
+        };
+        saved
+            .properties
+            .insert("access_key".to_owned(), "AKIAEXAMPLE".to_owned());
+        saved
+            .properties
+            .insert("secret_key".to_owned(), "wJalrXUtnFEMI".to_owned());
+        saved
+            .properties
+            .insert("endpoint".to_owned(), "localhost:9000".to_owned());
+        let redacted = saved.redacted_properties();
+        assert_eq!(redacted["access_key"], REDACTED_PROPERTY);
+        assert_eq!(redacted["secret_key"], REDACTED_PROPERTY);
+        assert_eq!(redacted["endpoint"], "localhost:9000");
+        // placeholders are replaced while new values are taken as given
+        let mut updated = saved.clone();
+        updated.properties = redacted;
+        updated
+            .properties
+            .insert("access_key".to_owned(), "AKIANEW".to_owned());
+        updated
+            .properties
+            .insert("password".to_owned(), REDACTED_PROPERTY.to_owned());
+        updated.restore_secrets(&saved);
+        assert_eq!(updated.properties["access_key"], "AKIANEW");
+        assert_eq!(updated.properties["secret_key"], "wJalrXUtnFEMI");
+        assert_eq!(updated.properties["endpoint"], "localhost:9000");
+        assert!(!updated.properties.contains_key("password"));
+    }
+
+    #[test]
+    fn test_dataset_etag() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        let etag = dataset.etag();
+        assert_eq!(dataset.etag(), etag);
+        dataset.add_store("cafebabe");
+        let with_store = dataset.etag();
+        assert_ne!(with_store, etag);
+        dataset.excludes.push("*.tmp".to_owned());
+        assert_ne!(dataset.etag(), with_store);
+        dataset.excludes.clear();
+        assert_eq!(dataset.etag(), with_store);
+        dataset.pack_size = 1_048_576;
+        let resized = dataset.etag();
+        assert_ne!(resized, with_store);
+        dataset.archived = Some(Utc::now());
+        assert_ne!(dataset.etag(), resized);
+    }
+
     #[test]
     fn test_storetype_fromstr() {
         // amazon
@@ -1068,18 +4396,36 @@ mod tests {
         let stype = result.unwrap();
         assert_eq!(stype, StoreType::LOCAL);
         assert_eq!(stype.to_string(), "local");
+        // dropbox
+        let result = StoreType::from_str("dropbox");
+        assert!(result.is_ok());
+        let stype = result.unwrap();
+        assert_eq!(stype, StoreType::DROPBOX);
+        assert_eq!(stype.to_string(), "dropbox");
         // google
         let result = StoreType::from_str("google");
         assert!(result.is_ok());
         let stype = result.unwrap();
         assert_eq!(stype, StoreType::GOOGLE);
         assert_eq!(stype.to_string(), "google");
+        // memory
+        let result = StoreType::from_str("memory");
+        assert!(result.is_ok());
+        let stype = result.unwrap();
+        assert_eq!(stype, StoreType::MEMORY);
+        assert_eq!(stype.to_string(), "memory");
         // minio
         let result = StoreType::from_str("minio");
         assert!(result.is_ok());
         let stype = result.unwrap();
         assert_eq!(stype, StoreType::MINIO);
         assert_eq!(stype.to_string(), "minio");
+        // rclone
+        let result = StoreType::from_str("rclone");
+        assert!(result.is_ok());
+        let stype = result.unwrap();
+        assert_eq!(stype, StoreType::RCLONE);
+        assert_eq!(stype.to_string(), "rclone");
         // sftp
         let result = StoreType::from_str("sftp");
         assert!(result.is_ok());
@@ -1124,6 +4470,39 @@ mod tests {
         assert!(formed.contains("lorem-ipsum.txt"));
     }
 
+    #[test]
+    fn test_tree_entry_raw_name() {
+        let tref = TreeReference::TREE(Checksum::SHA1("cafebabe".to_owned()));
+        // composed names are recorded as-is
+        let entry = TreeEntry::new(Path::new("/nowhere/caf\u{e9}.txt"), tref.clone());
+        assert_eq!(entry.name, "caf\u{e9}.txt");
+        assert!(entry.raw_name.is_none());
+        assert_eq!(entry.file_name(), OsString::from("caf\u{e9}.txt"));
+        // decomposed names are composed for display only
+        let entry = TreeEntry::new(Path::new("/nowhere/cafe\u{301}.txt"), tref.clone());
+        assert_eq!(entry.name, "caf\u{e9}.txt");
+        assert_eq!(
+            entry.raw_name,
+            Some(RawName::UTF8("cafe\u{301}.txt".to_owned()))
+        );
+        assert_eq!(entry.file_name(), OsString::from("cafe\u{301}.txt"));
+        assert!(entry
+            .to_string()
+            .ends_with(" caf\u{e9}.txt utf8-Y2FmZcyBLnR4dA=="));
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let name = OsStr::from_bytes(b"caf\xe9.txt");
+            let entry = TreeEntry::new(&Path::new("/nowhere").join(name), tref);
+            assert_eq!(entry.name, "caf\u{fffd}.txt");
+            assert_eq!(
+                entry.raw_name,
+                Some(RawName::BYTES(b"caf\xe9.txt".to_vec()))
+            );
+            assert_eq!(entry.file_name(), name);
+        }
+    }
+
     #[test]
     fn test_tree() {
         let path = Path::new("../test/fixtures/lorem-ipsum.txt");
@@ -1188,6 +4567,7 @@ mod tests {
         let tref1 = TreeReference::FILE(Checksum::SHA1("cafebabe".to_owned()));
         let entry1 = TreeEntry {
             name: String::from("madoka.kaname"),
+            raw_name: None,
             mode: Some(0o644),
             uid: Some(100),
             gid: Some(100),
@@ -1201,6 +4581,7 @@ mod tests {
         let tref2 = TreeReference::FILE(Checksum::SHA1("babecafe".to_owned()));
         let entry2 = TreeEntry {
             name: String::from("homura.akemi"),
+            raw_name: None,
             mode: Some(0o644),
             uid: Some(100),
             gid: Some(100),
@@ -1214,6 +4595,7 @@ mod tests {
         let tref3 = TreeReference::FILE(Checksum::SHA1("babebabe".to_owned()));
         let entry3 = TreeEntry {
             name: String::from("sayaka.miki"),
+            raw_name: None,
             mode: Some(0o644),
             uid: Some(100),
             gid: Some(100),
@@ -1248,6 +4630,7 @@ mod tests {
             very_small_files: 1,
             very_large_files: 8,
             file_sizes: HashMap::new(),
+            categories: HashMap::new(),
         };
         counts.register_file(83864);
         counts.register_file(11273);
@@ -1259,4 +4642,49 @@ mod tests {
         let actual = counts.total_files();
         assert_eq!(actual, 16);
     }
+
+    #[test]
+    fn test_file_categories() {
+        assert_eq!(
+            FileCategory::from_path(Path::new("notes/report.PDF")),
+            FileCategory::Document
+        );
+        assert_eq!(
+            FileCategory::from_path(Path::new("IMG_0001.jpg")),
+            FileCategory::Image
+        );
+        assert_eq!(
+            FileCategory::from_path(Path::new("src/main.rs")),
+            FileCategory::Code
+        );
+        assert_eq!(
+            FileCategory::from_path(Path::new("backup.tar.gz")),
+            FileCategory::Archive
+        );
+        assert_eq!(
+            FileCategory::from_path(Path::new("Makefile")),
+            FileCategory::Other
+        );
+        for category in [
+            FileCategory::Archive,
+            FileCategory::Audio,
+            FileCategory::Code,
+            FileCategory::Document,
+            FileCategory::Image,
+            FileCategory::Video,
+            FileCategory::Other,
+        ] {
+            let actual = FileCategory::from_str(&category.to_string()).unwrap();
+            assert_eq!(actual, category);
+        }
+        let mut counts: FileCounts = Default::default();
+        counts.register_category(Path::new("a.mp4"), 1_000_000);
+        counts.register_category(Path::new("b.MOV"), 2_000_000);
+        counts.register_category(Path::new("c.txt"), 100);
+        let video = counts.categories.get(&FileCategory::Video).unwrap();
+        assert_eq!(video.files, 2);
+        assert_eq!(video.bytes, 3_000_000);
+        let docs = counts.categories.get(&FileCategory::Document).unwrap();
+        assert_eq!(docs.files, 1);
+    }
 }
@@ -1,7 +1,10 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
+use anyhow::{anyhow, Error};
 use chrono::prelude::*;
+use std::str::FromStr;
+use std::time::Duration;
 
 /// The day of the week, for weekly and monthly schedules.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -107,6 +110,25 @@ impl TimeRange {
     }
 }
 
+impl FromStr for TimeRange {
+    type Err = Error;
+
+    /// Parse the range in the form `HH:MM-HH:MM`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        fn parse_time(time: &str) -> Option<(u32, u32)> {
+            let (hour, min) = time.trim().split_once(':')?;
+            let hour = hour.parse::<u32>().ok().filter(|h| *h < 24)?;
+            let min = min.parse::<u32>().ok().filter(|m| *m < 60)?;
+            Some((hour, min))
+        }
+        let err = || anyhow!(format!("expected HH:MM-HH:MM, got {}", value));
+        let (start, stop) = value.split_once('-').ok_or_else(err)?;
+        let (start_hour, start_min) = parse_time(start).ok_or_else(err)?;
+        let (stop_hour, stop_min) = parse_time(stop).ok_or_else(err)?;
+        Ok(TimeRange::new(start_hour, start_min, stop_hour, stop_min))
+    }
+}
+
 /// The day of the month, for monthly schedules.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum DayOfMonth {
@@ -154,7 +176,13 @@ impl Schedule {
     /// up with a call to the `within_range()` function with the current time to
     /// be sure that the backup should be run now.
     pub fn past_due(&self, then: DateTime<Utc>) -> bool {
-        let elapsed = Utc::now() - then;
+        self.past_due_at(then, Utc::now())
+    }
+
+    /// Determine if enough time has elapsed between `then` and `now` such that
+    /// the backup would be due at that moment, as with `past_due()`.
+    pub fn past_due_at(&self, then: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let elapsed = now - then;
         let as_secs = elapsed.num_seconds();
         if as_secs >= 0 {
             //
@@ -226,6 +254,50 @@ impl Schedule {
     }
 }
 
+///
+/// Limits on the resources used by a backup while it runs, which apply either
+/// all day or only within a time range, such as working hours.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResourceLimits {
+    /// Most bytes per second at which to upload the pack files.
+    pub upload_rate: Option<u64>,
+    /// Most threads with which to compute the digests of the files.
+    pub hash_threads: Option<usize>,
+    /// Scheduling priority of the backup, from 1 (slightly below normal) to
+    /// 19 (only when nothing else wants to run).
+    pub niceness: Option<i32>,
+    /// Time of day during which the limits apply, or `None` for all day.
+    pub window: Option<TimeRange>,
+}
+
+impl ResourceLimits {
+    /// Return `true` if there are no limits.
+    pub fn is_empty(&self) -> bool {
+        self.upload_rate.is_none() && self.hash_threads.is_none() && self.niceness.is_none()
+    }
+
+    /// Return the limits that are in effect at the given time, which are none
+    /// at all outside of the window.
+    pub fn at(&self, datetime: DateTime<Utc>) -> ResourceLimits {
+        match self.window.as_ref() {
+            Some(range) if !range.is_within(datetime) => ResourceLimits::default(),
+            _ => self.clone(),
+        }
+    }
+
+    /// Return how long to wait after uploading the given number of bytes in
+    /// the elapsed time, such that the average rate is within the limit.
+    pub fn upload_delay(&self, bytes: u64, elapsed: Duration) -> Duration {
+        match self.upload_rate.filter(|r| *r > 0) {
+            Some(rate) => {
+                Duration::from_secs_f64(bytes as f64 / rate as f64).saturating_sub(elapsed)
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -273,6 +345,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_time_range_fromstr() {
+        let range: TimeRange = "01:30-05:00".parse().unwrap();
+        assert_eq!(range, TimeRange::new(1, 30, 5, 0));
+        let range: TimeRange = " 22:00 - 02:15 ".parse().unwrap();
+        assert_eq!(range, TimeRange::new(22, 0, 2, 15));
+        assert!("01:30".parse::<TimeRange>().is_err());
+        assert!("25:00-05:00".parse::<TimeRange>().is_err());
+        assert!("01:60-05:00".parse::<TimeRange>().is_err());
+        assert!("one-five".parse::<TimeRange>().is_err());
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let mut limits = ResourceLimits::default();
+        assert!(limits.is_empty());
+        assert_eq!(
+            limits.upload_delay(1_000_000, std::time::Duration::ZERO),
+            std::time::Duration::ZERO
+        );
+        limits.upload_rate = Some(100_000);
+        limits.hash_threads = Some(2);
+        assert!(!limits.is_empty());
+        // one megabyte at 100 KB/s takes ten seconds
+        assert_eq!(
+            limits.upload_delay(1_000_000, std::time::Duration::from_secs(4)),
+            std::time::Duration::from_secs(6)
+        );
+        assert_eq!(
+            limits.upload_delay(1_000_000, std::time::Duration::from_secs(12)),
+            std::time::Duration::ZERO
+        );
+        // without a window the limits apply all day
+        let night = Utc.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap();
+        let noon = Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap();
+        assert_eq!(limits.at(night), limits);
+        limits.window = Some(TimeRange::new(9, 0, 17, 0));
+        assert_eq!(limits.at(noon), limits);
+        assert!(limits.at(night).is_empty());
+    }
+
     #[test]
     fn test_hourly() {
         let sched = Schedule::Hourly;
@@ -286,6 +399,17 @@ mod tests {
         assert!(sched.within_range(then));
     }
 
+    #[test]
+    fn test_past_due_at() {
+        let sched = Schedule::Daily(Some(TimeRange::new(22, 0, 6, 0)));
+        let then = Utc.with_ymd_and_hms(2024, 3, 1, 23, 30, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 3, 2, 10, 0, 0).unwrap();
+        assert!(!sched.past_due_at(then, now));
+        let now = Utc.with_ymd_and_hms(2024, 3, 2, 12, 0, 0).unwrap();
+        assert!(sched.past_due_at(then, now));
+        assert!(!sched.past_due_at(now, then));
+    }
+
     #[test]
     fn test_daily() {
         // overdue with no time range
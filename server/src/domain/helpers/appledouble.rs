@@ -0,0 +1,276 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `appledouble` module reads and writes the AppleDouble files in which
+//! macOS keeps the Finder information and resource fork of a file on those
+//! file systems that cannot hold them, named for the file with a `._` prefix.
+//! On macOS these are available as extended attributes of the file itself.
+
+use anyhow::{anyhow, Error};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Name of the extended attribute holding the Finder information on macOS.
+pub const FINDER_INFO: &str = "com.apple.FinderInfo";
+
+/// Name of the extended attribute holding the resource fork on macOS.
+pub const RESOURCE_FORK: &str = "com.apple.ResourceFork";
+
+/// Name of the extended attribute that marks a downloaded file as needing to
+/// be checked by Gatekeeper before it is first opened.
+pub const QUARANTINE: &str = "com.apple.quarantine";
+
+// Signature and version of the AppleDouble format.
+const MAGIC: u32 = 0x0005_1607;
+const VERSION: u32 = 0x0002_0000;
+
+// Identifiers of the entries in the AppleDouble file.
+const RESOURCE_FORK_ID: u32 = 2;
+const FINDER_INFO_ID: u32 = 9;
+
+// Finder information is always this many bytes.
+const FINDER_INFO_LEN: usize = 32;
+
+// Magic, version, filler, and the number of entries.
+const HEADER_LEN: usize = 26;
+
+// Identifier, offset, and length of each entry.
+const ENTRY_LEN: usize = 12;
+
+///
+/// Return `true` if the named extended attribute is the Finder information or
+/// resource fork, which are kept in an AppleDouble file on other platforms.
+///
+pub fn is_fork(name: &str) -> bool {
+    name == FINDER_INFO || name == RESOURCE_FORK
+}
+
+///
+/// Return `true` if the named extended attribute is one that only macOS
+/// understands, and which other platforms would refuse to set.
+///
+pub fn is_apple(name: &str) -> bool {
+    name.starts_with("com.apple.")
+}
+
+///
+/// Return the path of the AppleDouble file for the given file, or `None` if
+/// the path has no file name, or is itself an AppleDouble file.
+///
+pub fn sidecar_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    if name.as_encoded_bytes().starts_with(b"._") {
+        return None;
+    }
+    let mut sidecar = OsString::from("._");
+    sidecar.push(name);
+    Some(path.with_file_name(sidecar))
+}
+
+///
+/// The Finder information and resource fork of a file.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AppleDouble {
+    /// Finder information, such as the type, creator, and label of the file.
+    pub finder_info: Option<Vec<u8>>,
+    /// Resource fork of the file.
+    pub resource_fork: Option<Vec<u8>>,
+}
+
+impl AppleDouble {
+    /// Return `true` if neither the Finder information nor resource fork is
+    /// present.
+    pub fn is_empty(&self) -> bool {
+        self.finder_info.is_none() && self.resource_fork.is_none()
+    }
+
+    /// Set the value of the named extended attribute, ignoring any attribute
+    /// other than the Finder information and resource fork.
+    pub fn set(&mut self, name: &str, value: Vec<u8>) {
+        if name == FINDER_INFO {
+            self.finder_info = Some(value);
+        } else if name == RESOURCE_FORK {
+            self.resource_fork = Some(value);
+        }
+    }
+
+    /// Return the Finder information and resource fork as extended attribute
+    /// names and values.
+    pub fn attributes(&self) -> Vec<(&'static str, &[u8])> {
+        let mut results: Vec<(&'static str, &[u8])> = Vec::new();
+        if let Some(info) = self.finder_info.as_ref() {
+            results.push((FINDER_INFO, info));
+        }
+        if let Some(fork) = self.resource_fork.as_ref() {
+            results.push((RESOURCE_FORK, fork));
+        }
+        results
+    }
+
+    /// Encode in the AppleDouble format, with the Finder information first,
+    /// as macOS itself does.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut entries: Vec<(u32, &[u8])> = Vec::new();
+        let mut info = [0u8; FINDER_INFO_LEN];
+        if let Some(value) = self.finder_info.as_ref() {
+            let len = value.len().min(FINDER_INFO_LEN);
+            info[..len].copy_from_slice(&value[..len]);
+            entries.push((FINDER_INFO_ID, &info));
+        }
+        if let Some(fork) = self.resource_fork.as_ref() {
+            entries.push((RESOURCE_FORK_ID, fork));
+        }
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer.extend_from_slice(&MAGIC.to_be_bytes());
+        buffer.extend_from_slice(&VERSION.to_be_bytes());
+        buffer.extend_from_slice(b"Mac OS X        ");
+        buffer.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        let mut offset = HEADER_LEN + ENTRY_LEN * entries.len();
+        for (id, data) in entries.iter() {
+            buffer.extend_from_slice(&id.to_be_bytes());
+            buffer.extend_from_slice(&(offset as u32).to_be_bytes());
+            buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in entries.iter() {
+            buffer.extend_from_slice(data);
+        }
+        buffer
+    }
+
+    /// Decode the AppleDouble file content, ignoring any entries other than
+    /// the Finder information and resource fork.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let read_u32 = |at: usize| -> Option<u32> {
+            let bytes = data.get(at..at + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?))
+        };
+        if data.len() < HEADER_LEN || read_u32(0) != Some(MAGIC) {
+            return Err(anyhow!("not an AppleDouble file"));
+        }
+        let count = u16::from_be_bytes([data[24], data[25]]) as usize;
+        let mut result = AppleDouble::default();
+        for index in 0..count {
+            let at = HEADER_LEN + index * ENTRY_LEN;
+            let (Some(id), Some(offset), Some(length)) =
+                (read_u32(at), read_u32(at + 4), read_u32(at + 8))
+            else {
+                return Err(anyhow!("AppleDouble entries are truncated"));
+            };
+            let (offset, length) = (offset as usize, length as usize);
+            let value = data
+                .get(offset..offset + length)
+                .ok_or_else(|| anyhow!("AppleDouble entry {} is truncated", id))?;
+            match id {
+                // the Finder information may be followed by extended
+                // attributes, which are not supported here
+                FINDER_INFO_ID if length >= FINDER_INFO_LEN => {
+                    result.finder_info = Some(value[..FINDER_INFO_LEN].to_vec())
+                }
+                RESOURCE_FORK_ID => result.resource_fork = Some(value.to_vec()),
+                _ => (),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Read the AppleDouble file for the given file, if there is one.
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        let Some(sidecar) = sidecar_path(path) else {
+            return Ok(None);
+        };
+        match std::fs::read(sidecar) {
+            Ok(data) => Ok(Some(Self::decode(&data)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Write the AppleDouble file for the given file, unless the file already
+    /// exists, as it may hold entries that are not supported here. Returns
+    /// `true` if the file was written.
+    pub fn write(&self, path: &Path) -> Result<bool, Error> {
+        let sidecar =
+            sidecar_path(path).ok_or_else(|| anyhow!("no AppleDouble file for {:?}", path))?;
+        match std::fs::symlink_metadata(&sidecar) {
+            Ok(_) => return Ok(false),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(Error::from(err)),
+        }
+        std::fs::write(&sidecar, self.encode())?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            sidecar_path(Path::new("/Users/planet/notes.rtf")),
+            Some(PathBuf::from("/Users/planet/._notes.rtf"))
+        );
+        assert_eq!(
+            sidecar_path(Path::new("notes.rtf")),
+            Some(PathBuf::from("._notes.rtf"))
+        );
+        assert!(sidecar_path(Path::new("/Users/planet/._notes.rtf")).is_none());
+        assert!(sidecar_path(Path::new("/")).is_none());
+        assert!(is_fork(RESOURCE_FORK));
+        assert!(!is_fork(QUARANTINE));
+        assert!(is_apple(QUARANTINE));
+        assert!(!is_apple("user.comment"));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut double = AppleDouble::default();
+        assert!(double.is_empty());
+        double.set("user.comment", vec![1, 2, 3]);
+        assert!(double.is_empty());
+        // short Finder information is padded to the full length
+        double.set(FINDER_INFO, b"TEXTttxt".to_vec());
+        double.set(RESOURCE_FORK, vec![0xca, 0xfe, 0xba, 0xbe]);
+        let encoded = double.encode();
+        assert_eq!(&encoded[0..4], &[0x00, 0x05, 0x16, 0x07]);
+        assert_eq!(encoded.len(), HEADER_LEN + 2 * ENTRY_LEN + 32 + 4);
+        let actual = AppleDouble::decode(&encoded).unwrap();
+        let info = actual.finder_info.as_ref().unwrap();
+        assert_eq!(info.len(), 32);
+        assert_eq!(&info[..8], b"TEXTttxt");
+        assert_eq!(actual.resource_fork, Some(vec![0xca, 0xfe, 0xba, 0xbe]));
+        assert_eq!(actual.attributes().len(), 2);
+
+        assert!(AppleDouble::decode(b"not an AppleDouble file at all").is_err());
+        let truncated = &encoded[..encoded.len() - 2];
+        assert!(AppleDouble::decode(truncated).is_err());
+    }
+
+    #[test]
+    fn test_read_write() -> Result<(), Error> {
+        let tmpdir = tempfile::tempdir()?;
+        let path = tmpdir.path().join("notes.rtf");
+        assert!(AppleDouble::read(&path)?.is_none());
+        let double = AppleDouble {
+            finder_info: None,
+            resource_fork: Some(vec![1, 2, 3, 4, 5]),
+        };
+        assert!(double.write(&path)?);
+        assert!(tmpdir.path().join("._notes.rtf").exists());
+        // nothing to write the second time
+        assert!(!double.write(&path)?);
+        assert_eq!(AppleDouble::read(&path)?, Some(double.clone()));
+        // an existing file is never overwritten
+        let other = AppleDouble {
+            finder_info: None,
+            resource_fork: Some(vec![6, 7, 8]),
+        };
+        assert!(!other.write(&path)?);
+        assert_eq!(AppleDouble::read(&path)?, Some(double));
+        Ok(())
+    }
+}
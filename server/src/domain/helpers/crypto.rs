@@ -2,9 +2,260 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 
-/// Retrieve the user-defined passphrase.
+//! Retrieves the passphrase that encrypts the pack files and the database
+//! snapshots from the secrets provider named by `PASSPHRASE_PROVIDER`.
+//!
+//! * `env` (the default) reads the `PASSPHRASE` environment variable.
+//! * `keyring` reads the password kept in the credential store of the
+//!   operating system (macOS Keychain, Secret Service, Windows Credential
+//!   Manager) for the service named by `KEYRING_SERVICE` and the account named
+//!   by `KEYRING_ACCOUNT`. Requires the `keyring` feature.
+//! * `command` runs `PASSPHRASE_COMMAND` with the shell and takes the first
+//!   line of its output, for use with password managers and hardware tokens.
+//!
+//! The passphrase from the keyring or a command is kept in memory after the
+//! first retrieval, rather than prompting or unlocking again for every backup,
+//! until the provider settings are changed.
+
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use std::env;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use store_core::Secret;
+
+// Passphrase used when `PASSPHRASE` is not set.
+const DEFAULT_PASSPHRASE: &str = "keyboard cat";
+
+// Service and account of the keyring entry when not otherwise specified.
+const DEFAULT_KEYRING_SERVICE: &str = "zorigami";
+const DEFAULT_KEYRING_ACCOUNT: &str = "passphrase";
+
+lazy_static! {
+    // Passphrase retrieved from an external provider, and the key that
+    // identifies the provider settings that produced it.
+    static ref CACHED: Mutex<Option<(String, Secret)>> = Mutex::new(None);
+}
+
+///
+/// Source of the passphrase.
+///
+pub trait SecretsProvider: Send + Sync {
+    /// Retrieve the passphrase.
+    fn passphrase(&self) -> Result<Secret, Error>;
+
+    /// Return a key identifying the settings of this provider if its
+    /// passphrase may be kept in memory after retrieval.
+    fn cache_key(&self) -> Option<String> {
+        None
+    }
+}
+
+///
+/// Provides the passphrase given by the `PASSPHRASE` environment variable.
+///
+pub struct EnvironmentProvider {
+    passphrase: Option<String>,
+}
+
+impl SecretsProvider for EnvironmentProvider {
+    fn passphrase(&self) -> Result<Secret, Error> {
+        let value = self.passphrase.as_deref().unwrap_or(DEFAULT_PASSPHRASE);
+        Ok(Secret::from(value))
+    }
+}
+
+///
+/// Provides the passphrase kept in the credential store of the operating
+/// system.
+///
+pub struct KeyringProvider {
+    service: String,
+    account: String,
+}
+
+impl SecretsProvider for KeyringProvider {
+    #[cfg(feature = "keyring")]
+    fn passphrase(&self) -> Result<Secret, Error> {
+        let entry = keyring::Entry::new(&self.service, &self.account)?;
+        let password = entry.get_password().map_err(|err| {
+            anyhow!(format!(
+                "keyring entry {}/{}: {}",
+                self.service, self.account, err
+            ))
+        })?;
+        Ok(Secret::new(password))
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    fn passphrase(&self) -> Result<Secret, Error> {
+        Err(anyhow!("keyring support was not enabled in this build"))
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        Some(format!("keyring:{}/{}", self.service, self.account))
+    }
+}
+
+///
+/// Provides the passphrase written by a command to its standard output.
+///
+pub struct CommandProvider {
+    command: String,
+}
+
+impl SecretsProvider for CommandProvider {
+    fn passphrase(&self) -> Result<Secret, Error> {
+        #[cfg(unix)]
+        let mut cmd = {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(&self.command);
+            cmd
+        };
+        #[cfg(windows)]
+        let mut cmd = {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(&self.command);
+            cmd
+        };
+        let output = cmd
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| anyhow!(format!("could not run passphrase command: {}", err)))?;
+        if !output.status.success() {
+            return Err(anyhow!(format!(
+                "passphrase command failed: {}",
+                output.status
+            )));
+        }
+        let stdout = String::from_utf8(output.stdout)
+            .map_err(|_| anyhow!("passphrase command output is not UTF-8"))?;
+        let passphrase = stdout.lines().next().unwrap_or_default();
+        if passphrase.is_empty() {
+            return Err(anyhow!("passphrase command produced no output"));
+        }
+        Ok(Secret::from(passphrase))
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        Some(format!("command:{}", self.command))
+    }
+}
+
 ///
-/// Returns a default if one has not been defined.
-pub fn get_passphrase() -> String {
-    std::env::var("PASSPHRASE").unwrap_or_else(|_| "keyboard cat".to_owned())
+/// Return the secrets provider selected by `PASSPHRASE_PROVIDER`.
+///
+pub fn provider() -> Result<Box<dyn SecretsProvider>, Error> {
+    build_provider(|name| env::var(name).ok())
+}
+
+///
+/// Retrieve the passphrase from the configured secrets provider.
+///
+pub fn get_passphrase() -> Result<Secret, Error> {
+    let provider = provider()?;
+    let Some(key) = provider.cache_key() else {
+        return provider.passphrase();
+    };
+    let mut cached = CACHED.lock().unwrap();
+    if let Some((cached_key, secret)) = cached.as_ref() {
+        if cached_key == &key {
+            return Ok(secret.clone());
+        }
+    }
+    let secret = provider.passphrase()?;
+    *cached = Some((key, secret.clone()));
+    Ok(secret)
+}
+
+// Build the provider named by the `PASSPHRASE_PROVIDER` setting, with the
+// settings given by the lookup function.
+fn build_provider<F>(lookup: F) -> Result<Box<dyn SecretsProvider>, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let name = lookup("PASSPHRASE_PROVIDER").unwrap_or_default();
+    match name.trim().to_lowercase().as_str() {
+        "" | "env" => Ok(Box::new(EnvironmentProvider {
+            passphrase: lookup("PASSPHRASE"),
+        })),
+        "keyring" => Ok(Box::new(KeyringProvider {
+            service: lookup("KEYRING_SERVICE")
+                .unwrap_or_else(|| DEFAULT_KEYRING_SERVICE.to_owned()),
+            account: lookup("KEYRING_ACCOUNT")
+                .unwrap_or_else(|| DEFAULT_KEYRING_ACCOUNT.to_owned()),
+        })),
+        "command" => {
+            let command = lookup("PASSPHRASE_COMMAND")
+                .filter(|c| !c.trim().is_empty())
+                .ok_or_else(|| anyhow!("PASSPHRASE_COMMAND must be set"))?;
+            Ok(Box::new(CommandProvider { command }))
+        }
+        other => Err(anyhow!(format!("unknown passphrase provider: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn build(settings: &[(&str, &str)]) -> Result<Box<dyn SecretsProvider>, Error> {
+        let settings: HashMap<String, String> = settings
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        build_provider(|name| settings.get(name).cloned())
+    }
+
+    #[test]
+    fn test_environment_provider() -> Result<(), Error> {
+        let provider = build(&[])?;
+        assert_eq!(provider.passphrase()?.expose(), "keyboard cat");
+        assert!(provider.cache_key().is_none());
+        let provider = build(&[("PASSPHRASE_PROVIDER", "env"), ("PASSPHRASE", "tiger")])?;
+        assert_eq!(provider.passphrase()?.expose(), "tiger");
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyring_provider() -> Result<(), Error> {
+        let provider = build(&[("PASSPHRASE_PROVIDER", "keyring")])?;
+        assert_eq!(provider.cache_key().unwrap(), "keyring:zorigami/passphrase");
+        let provider = build(&[
+            ("PASSPHRASE_PROVIDER", "Keyring"),
+            ("KEYRING_SERVICE", "backups"),
+            ("KEYRING_ACCOUNT", "home"),
+        ])?;
+        assert_eq!(provider.cache_key().unwrap(), "keyring:backups/home");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider() -> Result<(), Error> {
+        let provider = build(&[
+            ("PASSPHRASE_PROVIDER", "command"),
+            ("PASSPHRASE_COMMAND", "printf 'tiger\\nstripes\\n'"),
+        ])?;
+        assert_eq!(provider.passphrase()?.expose(), "tiger");
+        let provider = build(&[
+            ("PASSPHRASE_PROVIDER", "command"),
+            ("PASSPHRASE_COMMAND", "exit 3"),
+        ])?;
+        assert!(provider.passphrase().is_err());
+        let provider = build(&[
+            ("PASSPHRASE_PROVIDER", "command"),
+            ("PASSPHRASE_COMMAND", "true"),
+        ])?;
+        assert!(provider.passphrase().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_provider_errors() {
+        assert!(build(&[("PASSPHRASE_PROVIDER", "command")]).is_err());
+        assert!(build(&[("PASSPHRASE_PROVIDER", "vault")]).is_err());
+    }
 }
@@ -0,0 +1,251 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `metadata` module extracts lightweight application metadata from the
+//! content of certain kinds of files, such as the date a photo was taken, so
+//! that the file records can be searched by more than just their path.
+
+use base64::{engine::general_purpose, Engine as _};
+use chrono::prelude::*;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+// Extensions of the image files that may contain EXIF data.
+const EXIF_EXTENSIONS: &[&str] = &["jpg", "jpeg", "tif", "tiff", "heic", "heif", "png", "webp"];
+
+// Maximum number of header lines to read from an email message.
+const MAX_HEADER_LINES: usize = 1_000;
+
+///
+/// Application metadata extracted from the content of a file.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Date/time the photo was taken, from the EXIF data.
+    pub taken: Option<DateTime<Utc>>,
+    /// Subject of an email message.
+    pub subject: Option<String>,
+}
+
+impl Metadata {
+    /// Return `true` if nothing was extracted.
+    pub fn is_empty(&self) -> bool {
+        self.taken.is_none() && self.subject.is_none()
+    }
+}
+
+///
+/// Extract the application metadata from the given file, as determined by the
+/// file extension, returning `None` if there is nothing of interest. Errors
+/// are ignored, as the metadata is merely a convenience.
+///
+pub fn extract(path: &Path) -> Option<Metadata> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let metadata = if EXIF_EXTENSIONS.contains(&extension.as_str()) {
+        Metadata {
+            taken: read_exif_date(path),
+            subject: None,
+        }
+    } else if extension == "eml" {
+        Metadata {
+            taken: None,
+            subject: read_mail_subject(path),
+        }
+    } else {
+        return None;
+    };
+    if metadata.is_empty() {
+        None
+    } else {
+        Some(metadata)
+    }
+}
+
+// Read the date/time when the photo was taken, falling back to the date/time
+// of the image itself. Without an offset in the EXIF data, the time is taken
+// to be UTC, since the time zone of the camera is not known.
+fn read_exif_date(path: &Path) -> Option<DateTime<Utc>> {
+    let file = fs::File::open(path).ok()?;
+    let mut reader = io::BufReader::new(file);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let (field, offset_tag) = match exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY) {
+        Some(field) => (field, exif::Tag::OffsetTimeOriginal),
+        None => (
+            exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY)?,
+            exif::Tag::OffsetTime,
+        ),
+    };
+    let mut datetime = match field.value {
+        exif::Value::Ascii(ref values) => exif::DateTime::from_ascii(values.first()?).ok()?,
+        _ => return None,
+    };
+    if let Some(field) = exif.get_field(offset_tag, exif::In::PRIMARY) {
+        if let exif::Value::Ascii(ref values) = field.value {
+            if let Some(value) = values.first() {
+                let _ = datetime.parse_offset(value);
+            }
+        }
+    }
+    let naive = NaiveDate::from_ymd_opt(
+        datetime.year as i32,
+        datetime.month as u32,
+        datetime.day as u32,
+    )?
+    .and_hms_opt(
+        datetime.hour as u32,
+        datetime.minute as u32,
+        datetime.second as u32,
+    )?;
+    let offset_secs = datetime.offset.map_or(0, |m| m as i32 * 60);
+    let offset = FixedOffset::east_opt(offset_secs)?;
+    let local = offset.from_local_datetime(&naive).single()?;
+    Some(local.with_timezone(&Utc))
+}
+
+// Read the Subject header of an email message, unfolding continuation lines
+// and decoding any encoded words.
+fn read_mail_subject(path: &Path) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let reader = io::BufReader::new(file);
+    let mut subject: Option<String> = None;
+    for line in reader.lines().take(MAX_HEADER_LINES) {
+        let line = line.ok()?;
+        if line.is_empty() || line == "\r" {
+            // the headers end at the first blank line
+            break;
+        }
+        let line = line.trim_end_matches('\r');
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = subject.as_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if subject.is_some() {
+            break;
+        } else if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("subject") {
+                subject = Some(value.trim().to_owned());
+            }
+        }
+    }
+    subject.map(|s| decode_encoded_words(&s))
+}
+
+// Decode the RFC 2047 encoded words in a header value, such as
+// `=?UTF-8?B?...?=`, leaving as-is any that are not UTF-8 (or ASCII).
+fn decode_encoded_words(value: &str) -> String {
+    let mut result = String::new();
+    let mut previous_encoded = false;
+    for (index, word) in value.split(' ').enumerate() {
+        match decode_word(word) {
+            Some(decoded) => {
+                // whitespace between adjacent encoded words is dropped
+                if index > 0 && !previous_encoded {
+                    result.push(' ');
+                }
+                result.push_str(&decoded);
+                previous_encoded = true;
+            }
+            None => {
+                if index > 0 {
+                    result.push(' ');
+                }
+                result.push_str(word);
+                previous_encoded = false;
+            }
+        }
+    }
+    result
+}
+
+// Decode a single encoded word, returning `None` if it is not one.
+fn decode_word(word: &str) -> Option<String> {
+    let inner = word.strip_prefix("=?")?.strip_suffix("?=")?;
+    let mut parts = inner.splitn(3, '?');
+    let charset = parts.next()?.to_lowercase();
+    let encoding = parts.next()?.to_lowercase();
+    let text = parts.next()?;
+    if charset != "utf-8" && charset != "us-ascii" {
+        return None;
+    }
+    let bytes = match encoding.as_str() {
+        "b" => general_purpose::STANDARD.decode(text).ok()?,
+        "q" => decode_quoted(text)?,
+        _ => return None,
+    };
+    String::from_utf8(bytes).ok()
+}
+
+// Decode the "Q" encoding, in which underscore represents a space.
+fn decode_quoted(text: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [iter.next()?, iter.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_extract_mail_subject() {
+        let outdir = tempfile::tempdir().unwrap();
+        let path = outdir.path().join("message.eml");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(
+            b"From: planet@example.com\r\nSubject: Trip photos\r\n from 2019\r\nTo: me@example.com\r\n\r\nSubject: not this one\r\n",
+        )
+        .unwrap();
+        drop(file);
+        let metadata = extract(&path).unwrap();
+        assert_eq!(metadata.subject.unwrap(), "Trip photos from 2019");
+        assert!(metadata.taken.is_none());
+    }
+
+    #[test]
+    fn test_extract_exif_date() {
+        let metadata = extract(Path::new("../test/fixtures/SekienAkashita.jpg")).unwrap();
+        let expected = Utc.with_ymd_and_hms(2006, 10, 10, 19, 0, 24).unwrap();
+        assert_eq!(metadata.taken.unwrap(), expected);
+        assert!(metadata.subject.is_none());
+        // images without a date have nothing to offer
+        assert!(extract(Path::new("../test/fixtures/baby-birth.jpg")).is_none());
+    }
+
+    #[test]
+    fn test_extract_unsupported() {
+        assert!(extract(Path::new("../test/fixtures/lorem-ipsum.txt")).is_none());
+        assert!(extract(Path::new("../test/fixtures/C++98-tutorial.pdf")).is_none());
+    }
+
+    #[test]
+    fn test_decode_encoded_words() {
+        assert_eq!(decode_encoded_words("plain subject"), "plain subject");
+        assert_eq!(
+            decode_encoded_words("=?UTF-8?B?Q2Fmw6k=?= =?utf-8?Q?_photos?="),
+            "Café photos"
+        );
+        assert_eq!(
+            decode_encoded_words("Re: =?utf-8?q?caf=C3=A9?= time"),
+            "Re: café time"
+        );
+        assert_eq!(
+            decode_encoded_words("=?iso-8859-1?q?caf=E9?="),
+            "=?iso-8859-1?q?caf=E9?="
+        );
+    }
+}
@@ -1,16 +1,82 @@
 //
 // Copyright (c) 2023 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, Chunk};
-use fastcdc::v2020::FastCDC;
+use crate::domain::entities::{Checksum, Chunk, ChunkerParams};
+use fastcdc::v2020::{self, FastCDC, Normalization};
+use log::debug;
 use memmap2::Mmap;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
+pub mod appledouble;
 pub mod crypto;
+pub mod metadata;
 pub mod pack;
+pub mod paths;
 pub mod thread_pool;
+pub mod throttle;
+
+// Number of attempts to open a file that is locked by another process.
+const LOCK_RETRY_COUNT: u32 = 5;
+
+// Initial delay in milliseconds between attempts, doubled after each attempt.
+const LOCK_RETRY_DELAY: u64 = 250;
+
+///
+/// Open the file for reading, retrying with an increasing delay if the file is
+/// locked by another process. After the final attempt the error is returned,
+/// which can be recognized using `is_locked_error()`.
+///
+/// On Windows the file is opened allowing other processes to continue reading,
+/// writing, or deleting the file. If the `BACKUP_SEMANTICS` environment
+/// variable is set, the file is opened with backup semantics, which allows
+/// reading files regardless of their security settings, provided the process
+/// holds the `SeBackupPrivilege` privilege (e.g. running as a service).
+///
+pub fn open_for_read(infile: &Path) -> io::Result<fs::File> {
+    let mut delay = Duration::from_millis(LOCK_RETRY_DELAY);
+    let mut attempt = 1;
+    loop {
+        match open_shared(infile) {
+            Err(err) if is_locked_error(&err) && attempt < LOCK_RETRY_COUNT => {
+                debug!("file {:?} is locked, attempt {}", infile, attempt);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+///
+/// Return `true` if the error indicates the file is locked by another process.
+///
+pub fn is_locked_error(err: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION (32) and ERROR_LOCK_VIOLATION (33)
+    cfg!(target_family = "windows") && matches!(err.raw_os_error(), Some(32) | Some(33))
+}
+
+#[cfg(target_family = "windows")]
+fn open_shared(infile: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    let mut options = fs::OpenOptions::new();
+    // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+    options.read(true).share_mode(0x7);
+    if std::env::var("BACKUP_SEMANTICS").is_ok() {
+        // FILE_FLAG_BACKUP_SEMANTICS
+        options.custom_flags(0x0200_0000);
+    }
+    options.open(paths::long_path(infile))
+}
+
+#[cfg(not(target_family = "windows"))]
+fn open_shared(infile: &Path) -> io::Result<fs::File> {
+    fs::File::open(infile)
+}
 
 ///
 /// Find the chunk boundaries within the given file, using the FastCDC
@@ -18,11 +84,25 @@ pub mod thread_pool;
 /// chunks, however the min/max sizes will be 0.25/4 times that size.
 ///
 pub fn find_file_chunks(infile: &Path, avg_size: u32) -> io::Result<Vec<Chunk>> {
-    let file = fs::File::open(infile)?;
+    find_file_chunks_with(infile, &ChunkerParams::new(avg_size))
+}
+
+///
+/// Find the chunk boundaries within the given file, using the FastCDC
+/// algorithm with the given parameters. Sizes outside of the range supported
+/// by FastCDC are brought within it.
+///
+pub fn find_file_chunks_with(infile: &Path, params: &ChunkerParams) -> io::Result<Vec<Chunk>> {
+    let file = open_for_read(infile)?;
     let mmap = unsafe { Mmap::map(&file).expect("cannot create memmap?") };
-    let min_size = avg_size / 4;
-    let max_size = avg_size * 4;
-    let chunker = FastCDC::new(&mmap[..], min_size, avg_size, max_size);
+    let (min_size, avg_size, max_size) = bounded_sizes(params);
+    let level = match params.normalization {
+        0 => Normalization::Level0,
+        1 => Normalization::Level1,
+        2 => Normalization::Level2,
+        _ => Normalization::Level3,
+    };
+    let chunker = FastCDC::with_level(&mmap[..], min_size, avg_size, max_size, level);
     let mut results = Vec::new();
     for entry in chunker {
         let end = entry.offset + entry.length;
@@ -34,10 +114,40 @@ pub fn find_file_chunks(infile: &Path, avg_size: u32) -> io::Result<Vec<Chunk>>
     Ok(results)
 }
 
+// Bring the chunk sizes within the limits of FastCDC, which panics otherwise,
+// while keeping the minimum and maximum on either side of the average.
+fn bounded_sizes(params: &ChunkerParams) -> (u32, u32, u32) {
+    let avg_size = params
+        .avg_size
+        .clamp(v2020::AVERAGE_MIN, v2020::AVERAGE_MAX);
+    let min_size = params
+        .min_size
+        .clamp(v2020::MINIMUM_MIN, v2020::MINIMUM_MAX)
+        .min(avg_size);
+    let max_size = params
+        .max_size
+        .clamp(v2020::MAXIMUM_MIN, v2020::MAXIMUM_MAX)
+        .max(avg_size);
+    (min_size, avg_size, max_size)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_open_for_read() {
+        let infile = Path::new("../test/fixtures/lorem-ipsum.txt");
+        let result = open_for_read(infile);
+        assert!(result.is_ok());
+        let infile = Path::new("../test/fixtures/does-not-exist.txt");
+        let result = open_for_read(infile);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!is_locked_error(&err));
+    }
+
     #[test]
     fn test_file_chunking_16k() -> io::Result<()> {
         let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
@@ -96,6 +206,45 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_file_chunking_params() -> io::Result<()> {
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        // the default parameters produce the same chunks as before
+        let params = ChunkerParams::new(16384);
+        let results = find_file_chunks_with(&infile, &params)?;
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].length, 21325);
+        // the chunks may be no larger than the maximum size
+        let params = ChunkerParams {
+            min_size: 4096,
+            avg_size: 16384,
+            max_size: 20000,
+            normalization: 3,
+        };
+        let results = find_file_chunks_with(&infile, &params)?;
+        assert!(results.len() > 5);
+        assert!(results.iter().all(|c| c.length <= 20000));
+        let total: usize = results.iter().map(|c| c.length).sum();
+        assert_eq!(total, 109466);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounded_sizes() {
+        let params = ChunkerParams::new(16384);
+        assert_eq!(bounded_sizes(&params), (4096, 16384, 65536));
+        let params = ChunkerParams {
+            min_size: 1,
+            avg_size: 1 << 30,
+            max_size: 2,
+            normalization: 1,
+        };
+        assert_eq!(
+            bounded_sizes(&params),
+            (v2020::MINIMUM_MIN, v2020::AVERAGE_MAX, v2020::AVERAGE_MAX)
+        );
+    }
+
     #[test]
     fn test_file_chunking_64k() -> io::Result<()> {
         let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
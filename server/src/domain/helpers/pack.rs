@@ -1,11 +1,87 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::Chunk;
+use crate::domain::entities::{Chunk, Compression, FileCategory};
+use crate::domain::helpers::open_for_read;
 use anyhow::{anyhow, Context, Error};
 use exaf_rs::writer::{Options, Writer};
+use log::debug;
+use std::collections::HashMap;
 use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// Weight given to the most recent pack when revising a compression ratio.
+const RATIO_SMOOTHING: f64 = 0.5;
+
+// Bounds for the compression ratio, which can exceed 1.0 slightly for data
+// that is already compressed, due to the encryption and archive overhead.
+const MIN_RATIO: f64 = 0.01;
+const MAX_RATIO: f64 = 1.1;
+
+///
+/// Compression ratios (compressed size divided by original size) realized for
+/// each category of content, used to predict the size of a pack file before
+/// the archive writer has committed its content to disk.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CompressionRatios {
+    ratios: HashMap<FileCategory, f64>,
+}
+
+impl CompressionRatios {
+    /// Construct with initial guesses based on the category of content.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Return the expected ratio for the given category.
+    pub fn ratio(&self, category: FileCategory) -> f64 {
+        if let Some(ratio) = self.ratios.get(&category) {
+            *ratio
+        } else {
+            match category {
+                // media and archives are typically compressed already
+                FileCategory::Archive
+                | FileCategory::Audio
+                | FileCategory::Image
+                | FileCategory::Video => 1.0,
+                FileCategory::Code | FileCategory::Document => 0.4,
+                FileCategory::Other => 0.7,
+            }
+        }
+    }
+
+    /// Estimate the compressed size of the given number of bytes.
+    pub fn estimate(&self, category: FileCategory, length: u64) -> u64 {
+        (length as f64 * self.ratio(category)).ceil() as u64
+    }
+
+    /// Revise the ratios given the original bytes by category that went into a
+    /// pack file and the resulting size of that pack file.
+    pub fn observe(&mut self, original: &HashMap<FileCategory, u64>, compressed: u64) {
+        let total: u64 = original.values().sum();
+        let predicted: f64 = original
+            .iter()
+            .map(|(category, length)| *length as f64 * self.ratio(*category))
+            .sum();
+        if total == 0 || predicted <= 0.0 {
+            return;
+        }
+        // The archive does not reveal the compressed size of each chunk, so
+        // the error in the prediction is shared among the categories in
+        // proportion to their contribution to the pack.
+        let scale = compressed as f64 / predicted;
+        for (category, length) in original.iter() {
+            let current = self.ratio(*category);
+            let weight = *length as f64 / total as f64;
+            let revised = current + RATIO_SMOOTHING * weight * (current * scale - current);
+            self.ratios
+                .insert(*category, revised.clamp(MIN_RATIO, MAX_RATIO));
+        }
+    }
+}
 
 /// Builds a compressed archive one chunk at a time.
 pub struct PackBuilder {
@@ -21,6 +97,16 @@ pub struct PackBuilder {
     filepath: Option<PathBuf>,
     /// Number of chunks added to the pack.
     chunks_packed: u32,
+    /// Compression ratios realized by previous packs from this builder.
+    ratios: CompressionRatios,
+    /// Original bytes added to the pack so far, by category of content.
+    original: HashMap<FileCategory, u64>,
+    /// Predicted size of the pack based on the compression ratios.
+    estimated: u64,
+    /// Compression applied to the chunks.
+    compression: Compression,
+    /// Holds the individually compressed chunks until the pack is finalized.
+    staging: Option<TempDir>,
 }
 
 impl PackBuilder {
@@ -34,9 +120,20 @@ impl PackBuilder {
             builder: None,
             filepath: None,
             chunks_packed: 0,
+            ratios: CompressionRatios::new(),
+            original: HashMap::new(),
+            estimated: 0,
+            compression: Compression::Archive,
+            staging: None,
         }
     }
 
+    /// Set the compression to be applied to the chunks.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
     /// Set the password which will enable encryption of the archive.
     pub fn password<S: Into<String>>(mut self, password: S) -> Self {
         self.password = Some(password.into());
@@ -64,7 +161,15 @@ impl PackBuilder {
         self.filepath = Some(outfile.to_path_buf());
         let file = File::create(outfile)?;
         // some use cases will need the pack sizes in the archive
-        let options = Options::new().file_size(true);
+        let mut options = Options::new().file_size(true);
+        if self.compression != Compression::Archive {
+            // the chunks are compressed here, if at all, not by the archive
+            options = options.compression(exaf_rs::Compression::None);
+        }
+        if self.compression.per_chunk() {
+            let parent = outfile.parent().unwrap_or_else(|| Path::new("."));
+            self.staging = Some(TempDir::new_in(parent)?);
+        }
         let mut builder = Writer::with_options(file, options)?;
         if let Some(ref passwd) = self.password {
             builder.enable_encryption(
@@ -92,24 +197,44 @@ impl PackBuilder {
             .as_mut()
             .ok_or_else(|| anyhow!("must call initialize() first"))?;
         let filename = chunk.digest.to_string();
-        builder.add_file_slice(
-            filepath,
-            filename,
-            None,
-            chunk.offset as u64,
-            chunk.length as u32,
-        )?;
+        let length = chunk.length as u64;
+        if let Some(staging) = self.staging.as_ref() {
+            // the archive reads the file content when it commits a block, so
+            // the compressed chunk is kept until the pack is finalized
+            let data = read_slice(filepath, chunk.offset as u64, chunk.length)?;
+            let encoded = encode(self.compression, &data)?;
+            let staged = staging.path().join(&filename);
+            fs::write(&staged, &encoded)?;
+            builder.add_file_slice(&staged, filename, None, 0, encoded.len() as u32)?;
+            self.estimated += encoded.len() as u64;
+        } else {
+            builder.add_file_slice(
+                filepath,
+                filename,
+                None,
+                chunk.offset as u64,
+                chunk.length as u32,
+            )?;
+            if self.compression == Compression::None {
+                self.estimated += length;
+            } else {
+                let category = FileCategory::from_path(filepath);
+                *self.original.entry(category).or_insert(0) += length;
+                self.estimated += self.ratios.estimate(category, length);
+            }
+        }
         // Note that bytes_written() is only updated when a manifest/content
         // pair are committed to the exaf archive, as such this will be wrong by
-        // a wide margin (~16mb). Suitable for realistic pack sizes that are a
-        // multiple of 16mb, but terrible for unit tests with small files.
+        // a wide margin (~16mb). The compression ratios realized by earlier
+        // packs fill in the difference until the writer catches up.
         //
-        // As such, hack the pack size limit to be based on the available data,
-        // which is close enough for the purpose of testing the pack behavior.
+        // For unit tests with small files, base the pack size limit on the
+        // available data, which is close enough for the purpose of testing the
+        // pack behavior.
         if cfg!(test) {
-            self.bytes_packed += chunk.length as u64;
+            self.bytes_packed += length;
         } else {
-            self.bytes_packed = builder.bytes_written();
+            self.bytes_packed = builder.bytes_written().max(self.estimated);
         }
         self.chunks_packed += 1;
         Ok(self.bytes_packed >= self.target_size)
@@ -121,10 +246,20 @@ impl PackBuilder {
             .take()
             .ok_or_else(|| anyhow!("must call initialize() first"))?
             .finish()?;
+        self.staging = None;
         let filepath = self
             .filepath
             .take()
             .ok_or_else(|| anyhow!("must call initialize() first"))?;
+        // feed the realized size back into the estimates for the next pack
+        let compressed = fs::metadata(&filepath)?.len();
+        debug!(
+            "pack estimated at {} bytes, actual size {} bytes",
+            self.estimated, compressed
+        );
+        self.ratios.observe(&self.original, compressed);
+        self.original.clear();
+        self.estimated = 0;
         self.bytes_packed = 0;
         self.chunks_packed = 0;
         Ok(filepath)
@@ -163,12 +298,93 @@ pub fn extract_pack(
     Ok(results)
 }
 
+///
+/// Restore the original content of the named chunks that were extracted to the
+/// output directory, if the pack compressed each chunk individually.
+///
+pub fn decode_chunks(
+    outdir: &Path,
+    names: &[String],
+    compression: Compression,
+) -> Result<(), Error> {
+    if !compression.per_chunk() {
+        return Ok(());
+    }
+    for name in names {
+        let path = outdir.join(name);
+        let data = fs::read(&path)?;
+        let decoded = decode(compression, &data)
+            .with_context(|| format!("decode_chunks {} with {}", name, compression))?;
+        fs::write(&path, decoded)?;
+    }
+    Ok(())
+}
+
+// Read the given portion of the file, retrying if it is locked.
+fn read_slice(path: &Path, offset: u64, length: usize) -> Result<Vec<u8>, Error> {
+    let mut file = open_for_read(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; length];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// Compress the chunk data with the given algorithm.
+fn encode(compression: Compression, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::Zstd(level) => Ok(zstd::bulk::compress(data, level)?),
+        Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        Compression::Archive | Compression::None => Ok(data.to_vec()),
+    }
+}
+
+// Decompress the chunk data that was compressed with the given algorithm.
+fn decode(compression: Compression, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::Zstd(_) => Ok(zstd::stream::decode_all(data)?),
+        Compression::Lz4 => {
+            lz4_flex::decompress_size_prepended(data).map_err(|err| anyhow!(err.to_string()))
+        }
+        Compression::Archive | Compression::None => Ok(data.to_vec()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::domain::entities::Checksum;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_compression_ratios() {
+        let mut ratios = CompressionRatios::new();
+        assert_eq!(ratios.ratio(FileCategory::Image), 1.0);
+        assert_eq!(ratios.estimate(FileCategory::Document, 1000), 400);
+        // text compressed better than expected
+        let mut original: HashMap<FileCategory, u64> = HashMap::new();
+        original.insert(FileCategory::Document, 1000);
+        ratios.observe(&original, 200);
+        let revised = ratios.ratio(FileCategory::Document);
+        assert!(revised < 0.4 && revised > 0.2);
+        // images compressed worse than expected, documents untouched
+        let mut original: HashMap<FileCategory, u64> = HashMap::new();
+        original.insert(FileCategory::Image, 1000);
+        ratios.observe(&original, 5000);
+        assert_eq!(ratios.ratio(FileCategory::Image), MAX_RATIO);
+        assert_eq!(ratios.ratio(FileCategory::Document), revised);
+        // mixed content shares the error by contribution
+        let mut original: HashMap<FileCategory, u64> = HashMap::new();
+        original.insert(FileCategory::Code, 3000);
+        original.insert(FileCategory::Other, 1000);
+        ratios.observe(&original, 0);
+        assert!(ratios.ratio(FileCategory::Code) < ratios.ratio(FileCategory::Other));
+        assert!(ratios.ratio(FileCategory::Code) >= MIN_RATIO);
+        // nothing to learn from an empty pack
+        let before = ratios.ratio(FileCategory::Audio);
+        ratios.observe(&HashMap::new(), 100);
+        assert_eq!(ratios.ratio(FileCategory::Audio), before);
+    }
+
     #[test]
     fn test_pack_builder_single() -> Result<(), Error> {
         // build a small pack file with small files
@@ -284,6 +500,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_pack_builder_compression() -> Result<(), Error> {
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        let chunks = super::super::find_file_chunks(&infile, 16384)?;
+        for compression in [Compression::None, Compression::Zstd(19), Compression::Lz4] {
+            let mut builder = PackBuilder::new(4194304)
+                .password("keyboard cat")
+                .compression(compression);
+            let outdir = tempdir()?;
+            let packfile = outdir.path().join("archive.pack");
+            builder.initialize(&packfile)?;
+            for chunk in chunks.iter() {
+                builder.add_chunk(chunk)?;
+            }
+            builder.finalize()?;
+            let extracted = outdir.path().join("chunks");
+            let entries = extract_pack(&packfile, &extracted, Some("keyboard cat"))?;
+            assert_eq!(entries.len(), chunks.len());
+            decode_chunks(&extracted, &entries, compression)?;
+            for chunk in chunks.iter() {
+                let name = chunk.digest.to_string();
+                let actual = Checksum::blake3_from_file(&extracted.join(&name))?;
+                assert_eq!(actual, chunk.digest);
+            }
+            // the staged chunks are removed along with the builder
+            assert_eq!(fs::read_dir(outdir.path())?.count(), 2);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_pack_builder_jpg() -> Result<(), Error> {
         // build a pack file with a jpeg image
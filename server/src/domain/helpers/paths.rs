@@ -0,0 +1,229 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Functions for safely combining user-provided relative paths with a base
+//! path, such that the result can never refer to anything outside of the base,
+//! as well as for accessing paths that exceed the usual length limits.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+///
+/// Raised when a relative path is not acceptable for restoring files.
+///
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PathError {
+    /// The path is empty or refers only to the current directory.
+    Empty,
+    /// The path has a root or prefix component.
+    Absolute(PathBuf),
+    /// The path has a parent directory (`..`) component.
+    Traversal(PathBuf),
+    /// The path resolves outside of the base path via a symbolic link.
+    SymlinkEscape(PathBuf),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "path is empty"),
+            PathError::Absolute(p) => write!(f, "path must be relative: {}", p.display()),
+            PathError::Traversal(p) => {
+                write!(f, "path must not refer to a parent: {}", p.display())
+            }
+            PathError::SymlinkEscape(p) => {
+                write!(f, "path leads outside of base via link: {}", p.display())
+            }
+        }
+    }
+}
+
+///
+/// Ensure the path is relative and consists only of normal components
+/// (ignoring any `.` components).
+///
+pub fn validate_relative(path: &Path) -> Result<(), PathError> {
+    let mut normal = false;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => normal = true,
+            Component::CurDir => (),
+            Component::ParentDir => return Err(PathError::Traversal(path.to_path_buf())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(PathError::Absolute(path.to_path_buf()))
+            }
+        }
+    }
+    if normal {
+        Ok(())
+    } else {
+        Err(PathError::Empty)
+    }
+}
+
+///
+/// Join the relative path to the base path after validating it, and ensure
+/// that the existing directories along the way do not resolve to a location
+/// outside of the base path by way of symbolic links.
+///
+/// The final component is not resolved, as it is expected to be replaced.
+///
+pub fn safe_join(basepath: &Path, relative: &Path) -> Result<PathBuf, PathError> {
+    validate_relative(relative)?;
+    let joined = basepath.join(relative);
+    // if the base path does not exist, then neither does anything below it
+    if let Ok(base) = basepath.canonicalize() {
+        let mut ancestor = joined.parent();
+        while let Some(dir) = ancestor {
+            // find the nearest existing directory and make sure it resides
+            // within the base path once all links have been resolved
+            if let Ok(actual) = dir.canonicalize() {
+                if !actual.starts_with(&base) {
+                    return Err(PathError::SymlinkEscape(relative.to_path_buf()));
+                }
+                break;
+            }
+            ancestor = dir.parent();
+        }
+    }
+    Ok(joined)
+}
+
+// Paths at least this long are given the extended-length prefix on Windows;
+// directories are limited to 248 characters, files to 260.
+#[cfg(target_family = "windows")]
+const LONG_PATH_THRESHOLD: usize = 248;
+
+///
+/// Return a form of the absolute path that can be opened even if it exceeds
+/// the `MAX_PATH` limit on Windows, by way of the `\\?\` prefix. Other paths,
+/// and all paths on other systems, are returned unchanged.
+///
+#[cfg(target_family = "windows")]
+pub fn long_path(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::path::Prefix;
+    if !path.is_absolute() || path.as_os_str().len() < LONG_PATH_THRESHOLD {
+        return path.to_path_buf();
+    }
+    // the extended-length form is passed through to the file system as-is,
+    // so it must be built from the components using only backslashes
+    let mut result = OsString::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(letter) => {
+                    result.push(format!(r"\\?\{}:", letter as char));
+                }
+                Prefix::UNC(server, share) => {
+                    result.push(r"\\?\UNC\");
+                    result.push(server);
+                    result.push(r"\");
+                    result.push(share);
+                }
+                // already in the extended form, or a device path
+                _ => return path.to_path_buf(),
+            },
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => return path.to_path_buf(),
+            Component::Normal(name) => {
+                result.push(r"\");
+                result.push(name);
+            }
+        }
+    }
+    if result.is_empty() {
+        path.to_path_buf()
+    } else {
+        PathBuf::from(result)
+    }
+}
+
+///
+/// Return a form of the absolute path that can be opened even if it exceeds
+/// the `MAX_PATH` limit on Windows. Other systems have no such limitation.
+///
+#[cfg(not(target_family = "windows"))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_relative() {
+        assert!(validate_relative(Path::new("foo/bar.txt")).is_ok());
+        assert!(validate_relative(Path::new("./foo/bar.txt")).is_ok());
+        assert_eq!(validate_relative(Path::new("")), Err(PathError::Empty));
+        assert_eq!(validate_relative(Path::new(".")), Err(PathError::Empty));
+        assert_eq!(
+            validate_relative(Path::new("foo/../../etc/passwd")),
+            Err(PathError::Traversal(PathBuf::from("foo/../../etc/passwd")))
+        );
+        assert_eq!(
+            validate_relative(Path::new("/etc/passwd")),
+            Err(PathError::Absolute(PathBuf::from("/etc/passwd")))
+        );
+    }
+
+    #[test]
+    fn test_safe_join() {
+        let outdir = tempfile::tempdir().unwrap();
+        let result = safe_join(outdir.path(), Path::new("foo/bar/baz.txt"));
+        assert_eq!(result.unwrap(), outdir.path().join("foo/bar/baz.txt"));
+        let result = safe_join(outdir.path(), Path::new("../baz.txt"));
+        assert!(result.is_err());
+        // nothing is checked if the base path does not yet exist
+        let basepath = outdir.path().join("missing");
+        let result = safe_join(&basepath, Path::new("foo/baz.txt"));
+        assert!(result.is_ok());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_safe_join_symlink_escape() {
+        let outdir = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let link = outdir.path().join("link");
+        std::os::unix::fs::symlink(elsewhere.path(), &link).unwrap();
+        let result = safe_join(outdir.path(), Path::new("link/baz.txt"));
+        assert_eq!(
+            result,
+            Err(PathError::SymlinkEscape(PathBuf::from("link/baz.txt")))
+        );
+        // links that stay within the base path are fine
+        std::fs::create_dir(outdir.path().join("real")).unwrap();
+        let inside = outdir.path().join("inside");
+        std::os::unix::fs::symlink(outdir.path().join("real"), &inside).unwrap();
+        let result = safe_join(outdir.path(), Path::new("inside/baz.txt"));
+        assert!(result.is_ok());
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn test_long_path() {
+        let short = Path::new(r"C:\Users\planet\file.txt");
+        assert_eq!(long_path(short), short);
+        let name = "a".repeat(250);
+        let long = Path::new(r"C:\Users\.").join(&name).join("file.txt");
+        let expected = format!(r"\\?\C:\Users\{}\file.txt", name);
+        assert_eq!(long_path(&long), PathBuf::from(expected));
+        let long = Path::new(r"\\server\share\").join(&name);
+        let expected = format!(r"\\?\UNC\server\share\{}", name);
+        assert_eq!(long_path(&long), PathBuf::from(expected));
+        // relative paths cannot be made into the extended form
+        let relative = Path::new("..").join(&name);
+        assert_eq!(long_path(&relative), relative);
+    }
+
+    #[cfg(not(target_family = "windows"))]
+    #[test]
+    fn test_long_path() {
+        let name = "a".repeat(250);
+        let long = Path::new("/home/planet").join(name);
+        assert_eq!(long_path(&long), long);
+    }
+}
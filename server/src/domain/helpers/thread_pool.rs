@@ -8,6 +8,7 @@
 use log::{debug, error, warn, trace};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use tracing::Span;
 
 ///
 /// Use `new()` to create a thread pool and `execute()` to send functions
@@ -48,7 +49,10 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         if let Some(worker) = self.sender.as_ref() {
-            let job = Box::new(f);
+            // carry the span of the caller, such as the dataset being backed
+            // up, over to the worker thread
+            let span = Span::current();
+            let job = Box::new(move || span.in_scope(f));
             if let Err(err) = worker.send(job) {
                 error!("failed to send job: {err}");
             }
@@ -0,0 +1,122 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Limit the number of operations that are in progress at any one time, such
+//! as the files being examined and read from a network file system that cannot
+//! cope with many concurrent requests. This is separate from the number of
+//! threads, which may continue to do other work while waiting their turn.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+///
+/// Use `new()` to create a throttle and `acquire()` to wait for a permit that
+/// allows an operation to proceed until the permit is dropped.
+///
+#[derive(Clone, Default)]
+pub struct Throttle {
+    // Number of permits currently held and the means of waiting for one to
+    // become available, or `None` if there is no limit.
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    limit: usize,
+    held: Mutex<usize>,
+    cvar: Condvar,
+}
+
+impl Throttle {
+    /// Create a throttle that allows at most `limit` operations at once, or
+    /// any number of them if `limit` is `None` or zero.
+    pub fn new(limit: Option<usize>) -> Self {
+        let inner = limit.filter(|l| *l > 0).map(|limit| {
+            Arc::new(Inner {
+                limit,
+                held: Mutex::new(0),
+                cvar: Condvar::new(),
+            })
+        });
+        Self { inner }
+    }
+
+    /// Return the maximum number of concurrent operations, if any.
+    pub fn limit(&self) -> Option<usize> {
+        self.inner.as_ref().map(|i| i.limit)
+    }
+
+    /// Wait until fewer than the limit of operations are in progress and
+    /// return a permit that holds a place until it is dropped.
+    pub fn acquire(&self) -> Permit {
+        if let Some(inner) = self.inner.as_ref() {
+            let mut held = inner.held.lock().unwrap();
+            while *held >= inner.limit {
+                held = inner.cvar.wait(held).unwrap();
+            }
+            *held += 1;
+        }
+        Permit {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+///
+/// Permission to perform an operation, released when dropped.
+///
+pub struct Permit {
+    inner: Option<Arc<Inner>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            let mut held = inner.held.lock().unwrap();
+            *held -= 1;
+            inner.cvar.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_throttle_limit() {
+        let throttle = Throttle::new(Some(2));
+        assert_eq!(throttle.limit(), Some(2));
+        let current = Arc::new(AtomicUsize::new(0));
+        let highest = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for _ in 0..8 {
+            let throttle = throttle.clone();
+            let current = current.clone();
+            let highest = highest.clone();
+            handles.push(thread::spawn(move || {
+                let _permit = throttle.acquire();
+                let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                highest.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                current.fetch_sub(1, Ordering::SeqCst);
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(highest.load(Ordering::SeqCst) <= 2);
+        assert_eq!(current.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_throttle_unlimited() {
+        for throttle in [Throttle::default(), Throttle::new(Some(0))] {
+            assert!(throttle.limit().is_none());
+            let permits: Vec<Permit> = (0..100).map(|_| throttle.acquire()).collect();
+            assert_eq!(permits.len(), 100);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `copies` module copies the files that are modified all the while, such
+//! as the databases of a running browser, into the workspace before reading
+//! them. The snapshot and the pack files are then built from the same content,
+//! rather than each reading whatever the file happens to contain at the time.
+//!
+//! Each copy is named after the digest of its content, which allows the backup
+//! driver to find it again, even when resuming an interrupted backup.
+
+use crate::domain::entities::{Checksum, Dataset};
+use crate::domain::helpers::open_for_read;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, warn};
+use std::fs;
+use std::io::{self, Seek};
+use std::path::{Path, PathBuf};
+
+// Name of the directory within the workspace that holds the copies.
+const COPIES_DIR: &str = "copies";
+
+///
+/// Copies files matching the dataset `copy_patterns` into the workspace.
+///
+pub struct SnapshotCopies {
+    patterns: GlobSet,
+    dir: PathBuf,
+}
+
+impl SnapshotCopies {
+    /// Construct an instance for the dataset, or `None` if the dataset does
+    /// not copy any files.
+    pub fn new(dataset: &Dataset) -> Option<Self> {
+        let patterns = build_patterns(&dataset.copy_patterns());
+        if patterns.is_empty() {
+            None
+        } else {
+            Some(Self {
+                patterns,
+                dir: copies_dir(&dataset.workspace),
+            })
+        }
+    }
+
+    /// Return `true` if the file should be copied before being read.
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.patterns.is_match(path)
+    }
+
+    /// Copy the file into the workspace and compute the digest of the copy,
+    /// which is kept until the backup is finished. Returns the digest and the
+    /// length of the copy.
+    pub fn copy_and_hash(&self, path: &Path) -> io::Result<(Checksum, u64)> {
+        fs::create_dir_all(&self.dir)?;
+        let mut infile = open_for_read(path)?;
+        let mut copy = tempfile::NamedTempFile::new_in(&self.dir)?;
+        let length = io::copy(&mut infile, copy.as_file_mut())?;
+        copy.as_file_mut().rewind()?;
+        let digest = Checksum::blake3_from_reader(copy.as_file_mut())?;
+        let outfile = self.dir.join(digest.to_string());
+        debug!("copied {} to {}", path.display(), outfile.display());
+        copy.persist(outfile).map_err(|e| e.error)?;
+        Ok((digest, length))
+    }
+}
+
+///
+/// Return the path of the copy of the file with the given digest, if any.
+///
+pub fn copied_path(workspace: &Path, digest: &Checksum) -> Option<PathBuf> {
+    let path = copies_dir(workspace).join(digest.to_string());
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+///
+/// Remove all of the copies made within the workspace.
+///
+pub fn remove_copies(workspace: &Path) {
+    let dir = copies_dir(workspace);
+    if dir.exists() {
+        if let Err(err) = fs::remove_dir_all(&dir) {
+            warn!("could not remove copies in {}: {}", dir.display(), err);
+        }
+    }
+}
+
+fn copies_dir(workspace: &Path) -> PathBuf {
+    workspace.join(COPIES_DIR)
+}
+
+// Build the glob set for the copy patterns, in which a pattern that does not
+// start with a wildcard or separator may match a file in any directory.
+fn build_patterns(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = if pattern.starts_with('*') || pattern.starts_with('/') {
+            pattern.to_owned()
+        } else {
+            format!("**/{}", pattern)
+        };
+        match Glob::new(&pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(err) => warn!("could not build glob for {:?}: {}", pattern, err),
+        }
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+
+    #[test]
+    fn test_copy_patterns() {
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(SnapshotCopies::new(&dataset).is_none());
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "default".to_owned());
+        let copies = SnapshotCopies::new(&dataset).unwrap();
+        let profile = "/home/planet/.mozilla/firefox/abc123.default";
+        assert!(copies.is_match(&Path::new(profile).join("places.sqlite")));
+        assert!(copies.is_match(Path::new(
+            "/home/planet/.config/google/Chrome/Default/History"
+        )));
+        assert!(!copies.is_match(Path::new("/home/planet/History")));
+        assert!(!copies.is_match(Path::new("/home/planet/notes.txt")));
+
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "notes.txt".to_owned());
+        let copies = SnapshotCopies::new(&dataset).unwrap();
+        assert!(copies.is_match(Path::new("/home/planet/notes.txt")));
+        assert!(copies.is_match(Path::new("/home/planet/docs/notes.txt")));
+        assert!(!copies.is_match(Path::new("/home/planet/places.sqlite")));
+
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "".to_owned());
+        assert!(SnapshotCopies::new(&dataset).is_none());
+    }
+
+    #[test]
+    fn test_copy_and_hash() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.workspace = workspace.path().to_path_buf();
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "*.txt".to_owned());
+        let copies = SnapshotCopies::new(&dataset).unwrap();
+        let infile = Path::new("../test/fixtures/lorem-ipsum.txt");
+        let (digest, length) = copies.copy_and_hash(infile)?;
+        let mut file = fs::File::open(infile)?;
+        assert_eq!(digest, Checksum::blake3_from_reader(&mut file)?);
+        assert_eq!(length, fs::metadata(infile)?.len());
+        let copied = copied_path(workspace.path(), &digest).unwrap();
+        assert_eq!(fs::read(copied)?, fs::read(infile)?);
+        // copying the same content again is not a problem
+        copies.copy_and_hash(infile)?;
+        remove_copies(workspace.path());
+        assert!(copied_path(workspace.path(), &digest).is_none());
+        Ok(())
+    }
+}
@@ -15,17 +15,23 @@
 //! records in the database that track which chunks belong to which files, and
 //! where those chunks are located.
 
-use crate::domain::entities;
-use crate::domain::helpers::{self, pack};
+use crate::domain::entities::{self, Event, EventKind, Message, MessageCode};
+use crate::domain::helpers::{self, metadata, pack};
+use crate::domain::managers::progress::{Progress, Reporter};
 use crate::domain::managers::state::{BackupAction, StateStore};
+use crate::domain::managers::{catalog, emergency, events};
 use crate::domain::repositories::{PackRepository, RecordRepository};
 use anyhow::{anyhow, Error};
 use chrono::{DateTime, Utc};
-use log::{error, info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use store_core::Secret;
 
 ///
 /// Receives changed files, placing them in packs and uploading to the pack
@@ -35,11 +41,15 @@ pub struct BackupDriver<'a> {
     dataset: &'a entities::Dataset,
     dbase: &'a Arc<dyn RecordRepository>,
     state: &'a Arc<dyn StateStore>,
-    passphrase: String,
+    /// Digest of the snapshot being backed up.
+    snapshot: entities::Checksum,
+    /// Reports the files and bytes uploaded.
+    progress: Reporter,
+    passphrase: Secret,
     stores: Box<dyn PackRepository>,
     stop_time: Option<DateTime<Utc>>,
-    /// Preferred size of chunks in bytes.
-    chunk_size: u32,
+    /// Parameters for splitting files into chunks.
+    chunker: entities::ChunkerParams,
     /// Builds a pack file comprised of compressed chunks.
     builder: pack::PackBuilder,
     /// Tracks files and chunks in the current pack.
@@ -51,6 +61,15 @@ pub struct BackupDriver<'a> {
     packed_chunks: HashSet<entities::Checksum>,
     /// Those chunks that have been uploaded previously.
     done_chunks: HashSet<entities::Checksum>,
+    /// Application metadata of the new files, held until their records are
+    /// saved to the database.
+    file_metadata: HashMap<entities::Checksum, metadata::Metadata>,
+    /// Size and modification time of the files when their chunks were found,
+    /// saved in the upload plan to detect files that change in the meantime.
+    file_stamps: HashMap<entities::Checksum, FileStamp>,
+    /// Files that are left for the next run since the uploads were stopped,
+    /// or `None` while uploading.
+    planned: Option<Vec<entities::PlannedFile>>,
 }
 
 impl<'a> BackupDriver<'a> {
@@ -59,41 +78,61 @@ impl<'a> BackupDriver<'a> {
         dataset: &'a entities::Dataset,
         dbase: &'a Arc<dyn RecordRepository>,
         state: &'a Arc<dyn StateStore>,
+        snapshot: &entities::Checksum,
         passphrase: &str,
         stop_time: Option<DateTime<Utc>>,
     ) -> Result<Self, Error> {
         let stores = dbase.load_dataset_stores(dataset)?;
-        let chunk_size = calc_chunk_size(dataset.pack_size);
+        let pack_size = clamp_pack_size(dataset, dbase)?;
+        let chunker = dataset.chunker_params(calc_chunk_size(pack_size));
         // Because EXAF combines content into 16mb blocks, it is possible that
         // it will produce something that is just under the desired pack size,
         // and subsequently more chunks will be added, pushing it well past the
         // desired pack size.
-        let target_size = (dataset.pack_size / 10) * 9;
+        let target_size = (pack_size / 10) * 9;
         Ok(Self {
             dataset,
             dbase,
             state,
-            passphrase: passphrase.to_owned(),
+            snapshot: snapshot.to_owned(),
+            progress: Reporter::backup(state.clone(), &dataset.id),
+            passphrase: Secret::from(passphrase),
             stores,
             stop_time,
-            chunk_size,
-            builder: pack::PackBuilder::new(target_size).password(passphrase),
+            chunker,
+            builder: pack::PackBuilder::new(target_size)
+                .password(passphrase)
+                .compression(dataset.compression()),
             record: Default::default(),
             file_chunks: BTreeMap::new(),
             packed_chunks: HashSet::new(),
             done_chunks: HashSet::new(),
+            file_metadata: HashMap::new(),
+            file_stamps: HashMap::new(),
+            planned: None,
         })
     }
 
+    /// Return the reporter for the progress of this backup.
+    pub fn progress(&self) -> &Reporter {
+        &self.progress
+    }
+
     /// Process a single changed file, adding it to the pack, and possibly
     /// uploading one or more pack files as needed.
     pub fn add_file(&mut self, changed: super::ChangedFile) -> Result<(), Error> {
         // ignore files which already have records
         if self.dbase.get_file(&changed.digest)?.is_none() {
-            if self
-                .split_file(&changed.path, changed.digest.clone())
-                .is_err()
-            {
+            // read from the copy made during the snapshot, if there is one, as
+            // the original may have changed since then
+            let path = super::copies::copied_path(&self.dataset.workspace, &changed.digest)
+                .unwrap_or_else(|| changed.path.clone());
+            if self.dataset.extract_metadata() {
+                if let Some(found) = metadata::extract(&path) {
+                    self.file_metadata.insert(changed.digest.clone(), found);
+                }
+            }
+            if self.split_file(&path, changed.digest.clone()).is_err() {
                 // file disappeared out from under us, record it as
                 // having zero length; file restore will handle it
                 // without any problem
@@ -109,6 +148,40 @@ impl<'a> BackupDriver<'a> {
         Ok(())
     }
 
+    /// Process a file from the upload plan of an earlier run, whose chunks have
+    /// already been found, adding it to the pack, and possibly uploading one
+    /// or more pack files as needed. A file that has changed since the plan
+    /// was made is split into chunks again.
+    pub fn add_planned(&mut self, planned: entities::PlannedFile) -> Result<(), Error> {
+        if self.dbase.get_file(&planned.digest)?.is_some() {
+            self.record.file_already_uploaded();
+        } else if !self.file_chunks.contains_key(&planned.digest) {
+            let Some(stamp) = file_stamp(&planned.path) else {
+                error!("file {} went missing during backup", planned.path.display());
+                let file = entities::File::new(planned.digest, 0, vec![]);
+                self.dbase.insert_file(&file)?;
+                return Ok(());
+            };
+            if stamp.1.is_none() || stamp != (planned.length, planned.modified) {
+                warn!(
+                    "file {} changed since the upload plan was made",
+                    planned.path.display()
+                );
+                self.split_file(&planned.path, planned.digest)?;
+            } else {
+                for chunk in planned.chunks.iter() {
+                    if self.dbase.get_chunk(&chunk.digest)?.is_some() {
+                        self.done_chunks.insert(chunk.digest.clone());
+                    }
+                }
+                self.file_stamps.insert(planned.digest.clone(), stamp);
+                self.file_chunks.insert(planned.digest, planned.chunks);
+            }
+            self.process_queue()?;
+        }
+        Ok(())
+    }
+
     /// Split the given file into chunks as necessary, using the database to
     /// eliminate duplicate chunks.
     fn split_file(&mut self, path: &Path, file_digest: entities::Checksum) -> Result<(), Error> {
@@ -121,9 +194,12 @@ impl<'a> BackupDriver<'a> {
         trace!("split_file '{}' digest {}", path.display(), file_digest);
         let attr = fs::metadata(path)?;
         let file_size = attr.len();
-        let chunks = if file_size > self.chunk_size as u64 {
+        let modified = attr.modified().ok().map(DateTime::<Utc>::from);
+        self.file_stamps
+            .insert(file_digest.clone(), (file_size, modified));
+        let chunks = if file_size > self.chunker.avg_size as u64 {
             // split large files into chunks, add chunks to the list
-            helpers::find_file_chunks(path, self.chunk_size)?
+            helpers::find_file_chunks_with(path, &self.chunker)?
         } else {
             let mut chunk = entities::Chunk::new(file_digest.clone(), 0, file_size as usize);
             chunk = chunk.filepath(path);
@@ -156,8 +232,19 @@ impl<'a> BackupDriver<'a> {
 
     /// Add file chunks to packs and upload until there is nothing left. Ignores
     /// files and chunks that have already been processed. Raises an error if
-    /// time runs out.
+    /// time runs out, after uploading the pack that was being built.
     fn process_queue(&mut self) -> Result<(), Error> {
+        if self.planned.is_some() {
+            self.plan_queue();
+            // stopping altogether abandons the plan, the changes will be found
+            // again by comparing the snapshots
+            if let Some(backup) = self.state.get_state().backups(&self.dataset.id) {
+                if backup.should_stop() {
+                    return Err(Error::from(super::OutOfTimeFailure {}));
+                }
+            }
+            return Ok(());
+        }
         while let Some((filesum, chunks)) = self.file_chunks.pop_first() {
             // this may run for a long time if the file is very large
             self.process_file(filesum, chunks)?;
@@ -165,16 +252,79 @@ impl<'a> BackupDriver<'a> {
             if let Some(stop_time) = self.stop_time {
                 let now = Utc::now();
                 if now > stop_time {
+                    self.checkpoint()?;
                     return Err(Error::from(super::OutOfTimeFailure {}));
                 }
             }
             // check if the user requested that the backup stop
             if let Some(backup) = self.state.get_state().backups(&self.dataset.id) {
                 if backup.should_stop() {
+                    self.checkpoint()?;
                     return Err(Error::from(super::OutOfTimeFailure {}));
                 }
+                if backup.uploads_stopped() {
+                    self.stop_uploads()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop uploading, setting aside the pack being built and planning its
+    /// files, along with those waiting to be packed, for the next run.
+    fn stop_uploads(&mut self) -> Result<(), Error> {
+        info!("backup: uploads stopped, planning the remaining changes");
+        if self.builder.is_ready() {
+            let pack_path = self.builder.finalize()?;
+            fs::remove_file(pack_path)?;
+        }
+        let record = std::mem::take(&mut self.record);
+        let stamps = &self.file_stamps;
+        let planned: Vec<entities::PlannedFile> = record
+            .files
+            .into_iter()
+            .map(|(digest, chunks)| planned_file(digest, chunks, stamps))
+            .collect();
+        self.planned = Some(planned);
+        self.plan_queue();
+        Ok(())
+    }
+
+    /// Move the files waiting to be packed into the upload plan.
+    fn plan_queue(&mut self) {
+        if let Some(planned) = self.planned.as_mut() {
+            while let Some((filesum, chunks)) = self.file_chunks.pop_first() {
+                planned.push(planned_file(filesum, chunks, &self.file_stamps));
             }
         }
+    }
+
+    /// If the uploads were stopped, save the upload plan for the snapshot to
+    /// the workspace and return `true`.
+    pub fn save_plan(&mut self, snapshot: &entities::Checksum) -> Result<bool, Error> {
+        match self.planned.take() {
+            Some(files) => {
+                let mut plan = entities::UploadPlan::new(snapshot.to_owned());
+                plan.files = files;
+                info!(
+                    "backup: saving plan of {} files to upload later",
+                    plan.files.len()
+                );
+                super::plan::save(&self.dataset.workspace, &plan)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Upload the pack being built, if any, so that the files processed thus
+    /// far need not be processed again when the backup resumes.
+    fn checkpoint(&mut self) -> Result<(), Error> {
+        if !self.builder.is_empty() {
+            info!("backup: uploading partial pack before stopping");
+            let pack_path = self.builder.finalize()?;
+            self.upload_record_reset(&pack_path)?;
+        }
         Ok(())
     }
 
@@ -227,68 +377,157 @@ impl<'a> BackupDriver<'a> {
         Ok(())
     }
 
-    /// If the pack builder has content, finalize the pack and upload.
+    /// If the pack builder has content, finalize the pack and upload, unless
+    /// the uploads have been stopped.
     pub fn finish_remainder(&mut self) -> Result<(), Error> {
         self.process_queue()?;
-        if !self.builder.is_empty() {
-            let pack_path = self.builder.finalize()?;
-            self.upload_record_reset(&pack_path)?;
+        if self.planned.is_some() {
+            return Ok(());
         }
-        Ok(())
+        self.checkpoint()
     }
 
     /// Upload a single pack to the pack store and record the results.
     fn upload_record_reset(&mut self, pack_path: &Path) -> Result<(), Error> {
         trace!("upload_record_reset {}", pack_path.display());
+        let workspace = &self.dataset.workspace;
+        // An earlier run may have been interrupted while uploading a pack of
+        // the very same chunks, in which case that file is uploaded instead
+        // of the new one, allowing the stores to resume where they left off.
+        let key = self.record.upload_key(&self.dataset.id, &self.snapshot);
+        let (mut pack_path, names) = match super::plan::load_pending(workspace, &key) {
+            Some((pending, path)) => {
+                info!("backup: resuming upload of pack {}", pending.digest);
+                fs::remove_file(pack_path)?;
+                (path, Some((pending.bucket, pending.object)))
+            }
+            None => (pack_path.to_path_buf(), None),
+        };
         // verify that the pack contents match the record; this is not perfect
         // since the record itself could also be wrong, but it's quick and easy
-        if !self.record.verify_pack(pack_path, &self.passphrase)? {
+        let passphrase = self.passphrase.expose();
+        if !self.record.verify_pack(&pack_path, passphrase)? {
+            super::plan::remove_pending(workspace, &key);
             return Err(anyhow!(
                 "missing chunks from pack file {}",
                 pack_path.display()
             ));
         }
-        let pack_digest = entities::Checksum::blake3_from_file(pack_path)?;
+        let pack_digest = entities::Checksum::blake3_from_file(&pack_path)?;
         // basically impossible to produce the same pack twice because the EXAF
         // encryption involves a random nonce per archive content block
         if self.dbase.get_pack(&pack_digest)?.is_none() {
             // new pack file, need to upload this and record to database
-            let computer_id = self.dbase.get_computer_id(&self.dataset.id)?.unwrap();
-            let bucket_name = self.stores.get_bucket_name(&computer_id);
-            let object_name = format!("{}", pack_digest);
+            let (bucket_name, object_name) = match names {
+                Some(names) => names,
+                None => {
+                    let computer_id = self.dbase.get_computer_id(&self.dataset.id)?.unwrap();
+                    let bucket_name = self.stores.get_bucket_name(&computer_id);
+                    (bucket_name, format!("{}", pack_digest))
+                }
+            };
+            // keep the pack and the names until the upload succeeds, such that
+            // the next run can resume an upload that fails
+            let pending = entities::PendingUpload {
+                snapshot: self.snapshot.clone(),
+                digest: pack_digest.clone(),
+                bucket: bucket_name.clone(),
+                object: object_name.clone(),
+            };
+            pack_path = super::plan::save_pending(workspace, &key, &pack_path, &pending)?;
             // capture and record the remote object name, in case it differs from
             // the name we generated ourselves; either value is expected to be
             // sufficiently unique for our purposes
+            let started = Instant::now();
             let locations = self
                 .stores
                 .store_pack(&pack_path, &bucket_name, &object_name)?;
-            self.record
-                .record_completed_pack(self.dbase, &pack_digest, locations)?;
+            self.pace_upload(&pack_path, locations.len(), started)?;
+            let stores: Vec<String> = locations.iter().map(|l| l.store.clone()).collect();
+            // the MD5 allows for checking the stored packs before a restore
+            let md5 = store_core::md5sum_file(&pack_path)?;
+            let pack = entities::Pack::new(pack_digest.clone(), locations)
+                .md5(md5)
+                .compression(self.dataset.compression());
+            self.record.record_completed_pack(self.dbase, pack)?;
+            events::record(
+                Event::new(EventKind::PackUploaded, &self.dataset.id)
+                    .detail("pack", pack_digest.to_string())
+                    .detail("stores", stores.join(",")),
+            );
             self.state
                 .backup_event(BackupAction::UploadPack(self.dataset.id.clone()));
         } else {
             info!("pack record already exists for {}", pack_digest);
         }
-        fs::remove_file(pack_path)?;
+        fs::remove_file(&pack_path)?;
+        super::plan::remove_pending(workspace, &key);
+        let metadata = &mut self.file_metadata;
         let count = self
             .record
-            .record_completed_files(self.dbase, &pack_digest)? as u64;
-        self.state.backup_event(BackupAction::UploadBytes(
-            self.dataset.id.clone(),
-            self.record.bytes_packed as u64,
-        ));
-        self.state
-            .backup_event(BackupAction::UploadFiles(self.dataset.id.clone(), count));
+            .record_completed_files(self.dbase, &pack_digest, metadata)? as u64;
+        for filesum in self.record.files.keys() {
+            self.file_stamps.remove(filesum);
+        }
+        self.progress
+            .advance(count, self.record.bytes_packed as u64);
         self.record = Default::default();
         Ok(())
     }
 
+    /// Abandon the interrupted pack uploads of any snapshot other than the one
+    /// given, or all of them if `None`, removing the pack files and discarding
+    /// the partial uploads held by the stores. Partial uploads whose checkpoint
+    /// is too old to be resumed are discarded as well.
+    pub fn abandon_uploads(&self, keep: Option<&entities::Checksum>) -> Result<(), Error> {
+        let workspace = &self.dataset.workspace;
+        let mut objects: HashSet<String> = HashSet::new();
+        for (key, pending) in super::plan::list_pending(workspace) {
+            if keep != Some(&pending.snapshot) {
+                info!("backup: abandoning upload of pack {}", pending.digest);
+                super::plan::remove_pending(workspace, &key);
+                objects.insert(pending.object);
+            }
+        }
+        for (location, checkpoint) in self.dbase.get_checkpoints()? {
+            // the uploads to other stores are left to the datasets using them
+            if !self.dataset.stores.contains(&location.store) {
+                continue;
+            }
+            if checkpoint.is_stale() || objects.contains(&location.object) {
+                if let Err(err) = self.stores.abort_upload(&location, &checkpoint) {
+                    warn!(
+                        "backup: could not abort upload of {}/{}: {}",
+                        location.bucket, location.object, err
+                    );
+                }
+                self.dbase.delete_checkpoint(&location)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Wait long enough after uploading the pack to each of the stores that the
+    // average rate is within the upload limit in effect at the moment, if any.
+    fn pace_upload(&self, pack_path: &Path, copies: usize, started: Instant) -> Result<(), Error> {
+        let limits = self.dataset.resource_limits().at(Utc::now());
+        if limits.upload_rate.is_some() {
+            let bytes = fs::metadata(pack_path)?.len() * copies as u64;
+            let delay = limits.upload_delay(bytes, started.elapsed());
+            if !delay.is_zero() {
+                debug!("pausing {:?} to limit the upload rate", delay);
+                thread::sleep(delay);
+            }
+        }
+        Ok(())
+    }
+
     /// Update the current snapshot with the end time set to the current time.
     pub fn update_snapshot(&self, snap_sha1: &entities::Checksum) -> Result<(), Error> {
         let mut snapshot = self
             .dbase
             .get_snapshot(snap_sha1)?
-            .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", snap_sha1)))?;
+            .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &snap_sha1))?;
         snapshot.set_end_time(Utc::now());
         self.dbase.put_snapshot(&snapshot)?;
         self.state
@@ -296,26 +535,126 @@ impl<'a> BackupDriver<'a> {
         Ok(())
     }
 
-    /// Upload an archive of the database files to the pack stores.
+    /// Upload the chunks of the database files that have changed since the
+    /// previous backup, along with a catalog of the files, to the pack stores.
     pub fn backup_database(&self) -> Result<(), Error> {
-        // Create a stable snapshot of the database as a single file, upload it
-        // to a special place in the pack store, then record the pseudo-pack to
-        // enable accurate pack pruning.
-        let backup_path = self.dbase.create_backup(&self.passphrase)?;
+        // The packs and the catalog are recorded as pseudo-packs to enable
+        // accurate pack pruning.
+        let computer_id = self.dbase.get_computer_id(&self.dataset.id)?.unwrap();
+        catalog::save(
+            self.dbase.as_ref(),
+            &self.dataset.stores,
+            &computer_id,
+            self.passphrase.expose(),
+            &self.dataset.workspace,
+        )?;
+        Ok(())
+    }
+
+    /// Upload the emergency index of the snapshot to the pack stores, such
+    /// that its most important files can be recovered without the database.
+    pub fn save_emergency_index(&self, snapshot: &entities::Checksum) -> Result<(), Error> {
+        let computer_id = self.dbase.get_computer_id(&self.dataset.id)?.unwrap();
+        emergency::save(
+            self.dbase.as_ref(),
+            self.stores.as_ref(),
+            &computer_id,
+            self.dataset,
+            snapshot,
+            self.passphrase.expose(),
+        )?;
+        Ok(())
+    }
+
+    /// Append the snapshot to the log of snapshots kept in the database for
+    /// each store, then upload the entire log to that store, replacing the
+    /// previous copy. Each entry includes the digest of the one before it,
+    /// such that a log that has been rolled back or altered can be detected.
+    pub fn append_chain(&self, snapshot: &entities::Checksum) -> Result<(), Error> {
         let computer_id = self.dbase.get_computer_id(&self.dataset.id)?.unwrap();
-        let coords = self.stores.store_database(&computer_id, &backup_path)?;
-        let digest = entities::Checksum::blake3_from_file(&backup_path)?;
-        let pack = entities::Pack::new(digest.clone(), coords);
-        self.dbase.insert_database(&pack)?;
+        for store_id in self.dataset.stores.iter() {
+            let mut entries = self.dbase.get_chain_entries(store_id)?;
+            let entry = entities::ChainEntry::new(store_id, entries.last(), snapshot.clone());
+            self.dbase.put_chain_entry(&entry)?;
+            entries.push(entry);
+            let mut outfile = tempfile::Builder::new()
+                .suffix(".log")
+                .tempfile_in(&self.dataset.workspace)?;
+            for entry in entries.iter() {
+                writeln!(outfile, "{}", entry)?;
+            }
+            outfile.flush()?;
+            let location = self
+                .stores
+                .store_chain(store_id, &computer_id, outfile.path())?;
+            self.dbase.put_chain_location(&location)?;
+        }
         Ok(())
     }
 }
 
+/// Size and modification time of a file, the latter of which may not be
+/// available on every platform.
+type FileStamp = (u64, Option<DateTime<Utc>>);
+
+/// Return the size and modification time of the file, or `None` if it is
+/// missing.
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let attr = fs::metadata(path).ok()?;
+    let modified = attr.modified().ok().map(DateTime::<Utc>::from);
+    Some((attr.len(), modified))
+}
+
+/// Build the planned file from the chunks found for the file.
+fn planned_file(
+    digest: entities::Checksum,
+    chunks: Vec<entities::Chunk>,
+    stamps: &HashMap<entities::Checksum, FileStamp>,
+) -> entities::PlannedFile {
+    let path = chunks
+        .first()
+        .and_then(|c| c.filepath.clone())
+        .unwrap_or_default();
+    let (length, modified) = stamps.get(&digest).cloned().unwrap_or((0, None));
+    entities::PlannedFile {
+        digest,
+        path,
+        length,
+        modified,
+        chunks,
+    }
+}
+
 // The default desired chunk size should be a little larger than the typical
 // image file, and small enough that packs do not end up with a wide range
 // of sizes due to large chunks.
 const DEFAULT_CHUNK_SIZE: u64 = 4_194_304;
 
+/// Adjust the pack size of the dataset to suit the limits of its stores, in
+/// case the stores have changed since the dataset was last saved.
+fn clamp_pack_size(
+    dataset: &entities::Dataset,
+    dbase: &Arc<dyn RecordRepository>,
+) -> Result<u64, Error> {
+    let mut stores: Vec<entities::Store> = Vec::new();
+    for store_id in dataset.stores.iter() {
+        if let Some(store) = dbase.get_store(store_id)? {
+            stores.push(store);
+        }
+    }
+    let limits = entities::ObjectSizeLimits::for_stores(&stores);
+    let pack_size = limits.clamp(dataset.pack_size);
+    if pack_size != dataset.pack_size {
+        warn!(
+            "dataset {}: {}, using {} instead",
+            dataset.id,
+            limits.check(dataset.pack_size).unwrap_or_default(),
+            pack_size
+        );
+    }
+    Ok(pack_size)
+}
+
 /// Compute the desired size for the chunks based on the pack size.
 fn calc_chunk_size(pack_size: u64) -> u32 {
     // Use our default chunk size unless the desired pack size is so small that
@@ -361,6 +700,21 @@ impl PackRecord {
         self.chunks.push(chunk);
     }
 
+    /// Return the key that identifies the pack of these chunks for the given
+    /// dataset and snapshot, which stays the same for every pack built of the
+    /// same chunks even though the encryption makes each pack file unique.
+    fn upload_key(&self, dataset: &str, snapshot: &entities::Checksum) -> String {
+        let mut digests: Vec<String> = self.chunks.iter().map(|c| c.digest.to_string()).collect();
+        digests.sort();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(dataset.as_bytes());
+        hasher.update(snapshot.to_string().as_bytes());
+        for digest in digests.iter() {
+            hasher.update(digest.as_bytes());
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
     /// Return true if the given (unencrypted) pack file contains everything
     /// this record expects to be in the pack file, false otherwise.
     fn verify_pack(&self, pack_path: &Path, password: &str) -> Result<bool, Error> {
@@ -400,8 +754,7 @@ impl PackRecord {
     fn record_completed_pack(
         &mut self,
         dbase: &Arc<dyn RecordRepository>,
-        digest: &entities::Checksum,
-        coords: Vec<entities::PackLocation>,
+        pack: entities::Pack,
     ) -> Result<(), Error> {
         // record the uploaded chunks to the database
         for chunk in self.chunks.iter_mut() {
@@ -410,13 +763,12 @@ impl PackRecord {
             // instead the file record will point directly to a pack record.
             if !self.files.contains_key(&chunk.digest) {
                 // set the pack digest for each chunk record
-                chunk.packfile = Some(digest.to_owned());
+                chunk.packfile = Some(pack.digest.clone());
                 dbase.insert_chunk(chunk)?;
             }
         }
         self.chunks.clear();
         // record the pack in the database
-        let pack = entities::Pack::new(digest.to_owned(), coords);
         dbase.insert_pack(&pack)?;
         Ok(())
     }
@@ -427,6 +779,7 @@ impl PackRecord {
         &mut self,
         dbase: &Arc<dyn RecordRepository>,
         digest: &entities::Checksum,
+        file_metadata: &mut HashMap<entities::Checksum, metadata::Metadata>,
     ) -> Result<usize, Error> {
         // massage the file/chunk data into database records for those files
         // that have been completely uploaded
@@ -449,7 +802,11 @@ impl PackRecord {
                     chunks.push((chunk.offset as u64, chunk.digest.clone()));
                 }
             }
-            let file = entities::File::new(filesum.clone(), length, chunks);
+            let mut file = entities::File::new(filesum.clone(), length, chunks);
+            if let Some(found) = file_metadata.remove(filesum) {
+                file.taken = found.taken;
+                file.subject = found.subject;
+            }
             dbase.insert_file(&file)?;
         }
         Ok(self.files.len() + self.completed_files)
@@ -530,7 +887,7 @@ mod tests {
         let mut archive = PathBuf::new();
         archive.push(workspace);
         archive.push(pack_rec.digest.to_string());
-        stores.retrieve_pack(&pack_rec.locations, &archive)?;
+        stores.retrieve_pack(&pack_rec.locations, &pack_rec.digest, &archive)?;
         // unpack the contents
         let mut reader = exaf_rs::reader::Entries::new(&archive)?;
         reader.enable_encryption(passphrase)?;
@@ -611,7 +968,8 @@ mod tests {
         // recording the chunk and file records.
         //
         let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
-        let mut driver = BackupDriver::new(&dataset, &dbase, &state, "secret123", None)?;
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let mut driver = BackupDriver::new(&dataset, &dbase, &state, &snapshot, "secret123", None)?;
         let file1_digest = Checksum::BLAKE3(
             "dba425aa7292ef1209841ab3855a93d4dfa6855658a347f85c502f2c2208cf0f".to_owned(),
         );
@@ -694,10 +1052,7 @@ mod tests {
         let maybe_file = dbase.get_file(&file3_digest)?;
         assert!(maybe_file.is_some());
         let file_rec = maybe_file.unwrap();
-        #[cfg(target_family = "unix")]
         assert_eq!(file_rec.length, 3375);
-        #[cfg(target_family = "windows")]
-        assert_eq!(file_rec.length, 3428);
         assert_eq!(file_rec.chunks.len(), 1);
         let maybe_pack = dbase.get_pack(&file_rec.chunks[0].1)?;
         assert!(maybe_pack.is_some());
@@ -763,7 +1118,8 @@ mod tests {
         // two pack files and seven chunks being generated.
         //
         let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
-        let mut driver = BackupDriver::new(&dataset, &dbase, &state, "secret123", None)?;
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let mut driver = BackupDriver::new(&dataset, &dbase, &state, &snapshot, "secret123", None)?;
         let file1_digest = Checksum::BLAKE3(
             "b740be03e10f454b6f45acdc821822b455aa4ab3721bbe8e3f03923f5cd688b8".to_owned(),
         );
@@ -848,4 +1204,108 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_backup_driver_stop_uploads() -> Result<(), Error> {
+        let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+        fs::create_dir_all(&db_base)?;
+        let db_path = tempfile::tempdir_in(&db_base)?;
+        let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+        let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+        let dbase: Arc<dyn RecordRepository> = Arc::new(repo);
+
+        // set up local pack store
+        let pack_base: PathBuf = ["tmp", "test", "packs"].iter().collect();
+        fs::create_dir_all(&pack_base)?;
+        let pack_path = tempfile::tempdir_in(&pack_base)?;
+        let mut local_props: HashMap<String, String> = HashMap::new();
+        local_props.insert(
+            "basepath".to_owned(),
+            pack_path.into_path().to_string_lossy().into(),
+        );
+        let store = entities::Store {
+            id: "local123".to_owned(),
+            store_type: entities::StoreType::LOCAL,
+            label: "my local".to_owned(),
+            properties: local_props,
+        };
+        dbase.put_store(&store)?;
+
+        // create a dataset with a workspace of its own to hold the plan
+        let fixture_base: PathBuf = ["test", "fixtures"].iter().collect();
+        let mut dataset = entities::Dataset::new(&fixture_base);
+        dataset.add_store("local123");
+        dataset.pack_size = 131072 as u64;
+        let workspace = tempdir()?;
+        dataset.workspace = workspace.path().to_path_buf();
+        let computer_id = entities::Configuration::generate_unique_id("mr.ed", "stable");
+        dbase.put_computer_id(&dataset.id, &computer_id)?;
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+
+        // stop the uploads before any files are added, such that every file
+        // is planned and none are uploaded
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        state.backup_event(BackupAction::Start(dataset.id.clone()));
+        state.backup_event(BackupAction::StopUploads(dataset.id.clone()));
+        let mut driver = BackupDriver::new(&dataset, &dbase, &state, &snapshot, "secret123", None)?;
+        let files = [
+            (
+                "../test/fixtures/SekienAkashita.jpg",
+                "dba425aa7292ef1209841ab3855a93d4dfa6855658a347f85c502f2c2208cf0f",
+            ),
+            (
+                "../test/fixtures/baby-birth.jpg",
+                "2cd10c8eafa9bb6562eae34758bd7bcffd840afb1c503b42d0659b0718cafe99",
+            ),
+            (
+                "../test/fixtures/washington-journal.txt",
+                "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60",
+            ),
+        ];
+        for (path, digest) in files.iter() {
+            let digest = Checksum::BLAKE3(digest.to_string());
+            driver.add_file(ChangedFile::new(Path::new(path), digest))?;
+        }
+        driver.finish_remainder()?;
+        assert!(driver.save_plan(&snapshot)?);
+        let backup = state.get_state().backups(&dataset.id).unwrap();
+        assert_eq!(backup.packs_uploaded(), 0);
+        for (_, digest) in files.iter() {
+            let digest = Checksum::BLAKE3(digest.to_string());
+            assert!(dbase.get_file(&digest)?.is_none());
+        }
+
+        // the next run uploads the planned files
+        let mut plan = super::super::plan::load(&dataset.workspace, &snapshot)?.unwrap();
+        assert_eq!(plan.files.len(), 3);
+        for planned in plan.files.iter() {
+            assert!(planned.length > 0);
+            assert!(planned.modified.is_some());
+        }
+        // a file that appears to have changed is read again
+        let first = Checksum::BLAKE3(files[0].1.to_owned());
+        let changed = plan.files.iter_mut().find(|f| f.digest == first).unwrap();
+        changed.length += 1;
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        state.backup_event(BackupAction::Start(dataset.id.clone()));
+        let mut driver = BackupDriver::new(&dataset, &dbase, &state, &snapshot, "secret123", None)?;
+        for planned in plan.files {
+            driver.add_planned(planned)?;
+        }
+        driver.finish_remainder()?;
+        assert!(!driver.save_plan(&snapshot)?);
+        let backup = state.get_state().backups(&dataset.id).unwrap();
+        assert!(backup.packs_uploaded() > 0);
+        let file_rec = dbase
+            .get_file(&Checksum::BLAKE3(files[0].1.to_owned()))?
+            .unwrap();
+        assert_eq!(file_rec.length, 109466);
+        assert_eq!(file_rec.chunks.len(), 2);
+        for (_, digest) in files.iter() {
+            let digest = Checksum::BLAKE3(digest.to_string());
+            assert!(dbase.get_file(&digest)?.is_some());
+        }
+
+        Ok(())
+    }
 }
@@ -8,8 +8,13 @@
 //! them to the store.
 
 use crate::domain::entities;
+use crate::domain::entities::{Message, MessageCode};
 use crate::domain::helpers::thread_pool::ThreadPool;
+use crate::domain::helpers::throttle::Throttle;
+use crate::domain::helpers::{is_locked_error, open_for_read, paths};
+use crate::domain::managers::progress::Progress;
 use crate::domain::managers::state::{BackupAction, StateStore};
+use crate::domain::managers::{critical, events, sentinel};
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Context, Error};
 use chrono::{DateTime, Utc};
@@ -20,12 +25,19 @@ use mockall::{automock, predicate::*};
 use std::collections::VecDeque;
 use std::fmt;
 use std::fs;
+use std::io::{self, Read, Seek};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 use std::time::SystemTime;
+use store_core::Secret;
+use tracing::{info_span, Span};
 
+mod copies;
 mod driver;
+mod plan;
 pub mod scheduler;
+pub mod trigger;
 pub use scheduler::{Scheduler, SchedulerImpl};
 
 ///
@@ -40,14 +52,14 @@ pub struct Request {
     /// Reference to the shared application state.
     state: Arc<dyn StateStore>,
     /// Passphrase used to generate a secret key to encrypt pack files.
-    passphrase: String,
+    passphrase: Secret,
     /// Optional time at which to stop the backup, albeit temporarily.
     stop_time: Option<DateTime<Utc>>,
 }
 
 impl Request {
     /// Construct a new instance of Request.
-    pub fn new<T: Into<String>>(
+    pub fn new<T: Into<Secret>>(
         dataset: entities::Dataset,
         repo: Arc<dyn RecordRepository>,
         state: Arc<dyn StateStore>,
@@ -77,95 +89,167 @@ pub trait Performer: Send + Sync {
 ///
 /// A simple concrete implementation of Performer that processes backups linearly on
 /// the current thread, allowing for easier management of the database locks
-/// when performing a full database restore.
+/// when performing a full database restore. A backup with a niceness runs on
+/// a thread of its own, which is joined, such that the lowered priority ends
+/// with the backup.
 ///
 #[derive(Default)]
 pub struct PerformerImpl();
 
 impl Performer for PerformerImpl {
     fn backup(&self, request: Request) -> Result<Option<entities::Checksum>, Error> {
-        if let Some(time) = &request.stop_time {
-            debug!("backup: starting for {} until {}", request.dataset, time);
-        } else {
-            debug!("backup: starting for {} until completion", request.dataset);
-        }
-        fs::create_dir_all(&request.dataset.workspace).with_context(|| {
-            format!(
-                "backup fs::create_dir_all({})",
-                request.dataset.workspace.display()
-            )
-        })?;
-        // Check if latest snapshot exists and lacks an end time, which indicates
-        // that the previous backup did not complete successfully.
-        let latest_snapshot = request.repo.get_latest_snapshot(&request.dataset.id)?;
-        if let Some(latest) = latest_snapshot.as_ref() {
-            if let Some(snapshot) = request.repo.get_snapshot(latest)? {
-                if snapshot.end_time.is_none() {
-                    // continue from the previous incomplete backup
-                    let parent_sha1 = snapshot.parent;
-                    let current_sha1 = latest.to_owned();
-                    debug!("backup: continuing previous snapshot {}", &current_sha1);
-                    return continue_backup(
-                        &request.dataset,
-                        &request.repo,
-                        &request.state,
-                        &request.passphrase,
-                        parent_sha1,
-                        current_sha1,
-                        request.stop_time,
-                    );
-                }
+        // the limits in effect at the start remain for the entire backup,
+        // except for the upload rate, which is checked for each pack
+        let limits = request.dataset.resource_limits().at(Utc::now());
+        let hash_threads = limits.hash_threads;
+        match limits.niceness {
+            // The priority of a thread cannot be raised again once lowered,
+            // and the calling thread goes on to run other backups, so a niced
+            // backup runs on a short-lived thread of its own.
+            Some(niceness) => {
+                let span = Span::current();
+                thread::scope(|scope| {
+                    scope
+                        .spawn(move || {
+                            span.in_scope(|| {
+                                lower_priority(niceness);
+                                perform_backup(request, hash_threads)
+                            })
+                        })
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("backup thread panicked")))
+                })
             }
+            None => perform_backup(request, hash_threads),
         }
-        // The start time of a new backup is at the moment that a snapshot is to be
-        // taken. The snapshot can take a long time to build, and another thread may
-        // spawn in the mean time and start taking another snapshot, and again, and
-        // again until the system runs out of resources.
-        request
-            .state
-            .backup_event(BackupAction::Start(request.dataset.id.clone()));
-        // In addition to the exclusions defined in the dataset, we exclude the
-        // temporary workspace and repository database files.
-        let mut excludes = request.repo.get_excludes();
-        excludes.push(request.dataset.workspace.clone());
-        for exclusion in request.dataset.excludes.iter() {
-            excludes.push(PathBuf::from(exclusion));
-        }
-        debug!("backup: dataset exclusions: {:?}", excludes);
-        // Take a snapshot and record it as the new most recent snapshot for this
-        // dataset, to allow detecting a running backup, and thus recover from a
-        // crash or forced shutdown.
-        let snap_opt = take_snapshot(
-            &request.dataset.basepath,
-            latest_snapshot.clone(),
-            &request.repo,
-            excludes,
-        )?;
-        match snap_opt {
-            None => {
-                // indicate that the backup has finished (doing nothing)
-                request
-                    .state
-                    .backup_event(BackupAction::Finish(request.dataset.id.clone()));
-                Ok(None)
-            }
-            Some(current_sha1) => {
-                request
-                    .repo
-                    .put_latest_snapshot(&request.dataset.id, &current_sha1)?;
-                debug!("backup: starting new snapshot {}", &current_sha1);
-                continue_backup(
+    }
+}
+
+///
+/// Perform the backup on the current thread, computing the file digests with
+/// at most `hash_threads` threads, if given.
+///
+fn perform_backup(
+    request: Request,
+    hash_threads: Option<usize>,
+) -> Result<Option<entities::Checksum>, Error> {
+    if let Some(time) = &request.stop_time {
+        debug!("backup: starting for {} until {}", request.dataset, time);
+    } else {
+        debug!("backup: starting for {} until completion", request.dataset);
+    }
+    fs::create_dir_all(&request.dataset.workspace).with_context(|| {
+        format!(
+            "backup fs::create_dir_all({})",
+            request.dataset.workspace.display()
+        )
+    })?;
+    // Check if latest snapshot exists and lacks an end time, which indicates
+    // that the previous backup did not complete successfully.
+    let latest_snapshot = request.repo.get_latest_snapshot(&request.dataset.id)?;
+    if let Some(latest) = latest_snapshot.as_ref() {
+        if let Some(snapshot) = request.repo.get_snapshot(latest)? {
+            if snapshot.end_time.is_none() {
+                // continue from the previous incomplete backup
+                let parent_sha1 = snapshot.parent;
+                let current_sha1 = latest.to_owned();
+                debug!("backup: continuing previous snapshot {}", &current_sha1);
+                let result = continue_backup(
                     &request.dataset,
                     &request.repo,
                     &request.state,
-                    &request.passphrase,
-                    latest_snapshot,
+                    request.passphrase.expose(),
+                    parent_sha1,
                     current_sha1,
                     request.stop_time,
-                )
+                );
+                return discard_copies_on_error(&request.dataset.workspace, result);
             }
         }
     }
+    // The start time of a new backup is at the moment that a snapshot is to be
+    // taken. The snapshot can take a long time to build, and another thread may
+    // spawn in the mean time and start taking another snapshot, and again, and
+    // again until the system runs out of resources.
+    request
+        .state
+        .backup_event(BackupAction::Start(request.dataset.id.clone()));
+    // In addition to the exclusions defined in the dataset, we exclude the
+    // temporary workspace and repository database files.
+    let mut excludes = request.repo.get_excludes();
+    excludes.push(request.dataset.workspace.clone());
+    for exclusion in request.dataset.excludes.iter() {
+        excludes.push(PathBuf::from(exclusion));
+    }
+    debug!("backup: dataset exclusions: {:?}", excludes);
+    // a sentinel that cannot be written is missed but not fatal
+    if let Err(err) = sentinel::plant(&request.dataset) {
+        warn!(
+            "could not plant sentinel for {}: {}",
+            request.dataset.id, err
+        );
+    }
+    // Take a snapshot and record it as the new most recent snapshot for this
+    // dataset, to allow detecting a running backup, and thus recover from a
+    // crash or forced shutdown.
+    let mut locked: Vec<PathBuf> = Vec::new();
+    let copies = copies::SnapshotCopies::new(&request.dataset).map(Arc::new);
+    let throttle = Throttle::new(request.dataset.file_concurrency());
+    let result = take_snapshot(
+        &request.dataset.basepath,
+        latest_snapshot.clone(),
+        &request.repo,
+        excludes,
+        copies,
+        &throttle,
+        hash_threads,
+        &mut locked,
+    );
+    let snap_opt = discard_copies_on_error(&request.dataset.workspace, result)?;
+    for path in locked.into_iter() {
+        request.state.backup_event(BackupAction::FileLocked(
+            request.dataset.id.clone(),
+            path.to_string_lossy().into_owned(),
+        ));
+    }
+    match snap_opt {
+        None => {
+            copies::remove_copies(&request.dataset.workspace);
+            // indicate that the backup has finished (doing nothing)
+            request
+                .state
+                .backup_event(BackupAction::Finish(request.dataset.id.clone()));
+            Ok(None)
+        }
+        Some(current_sha1) => {
+            request
+                .repo
+                .put_latest_snapshot(&request.dataset.id, &current_sha1)?;
+            debug!("backup: starting new snapshot {}", &current_sha1);
+            let result = continue_backup(
+                &request.dataset,
+                &request.repo,
+                &request.state,
+                request.passphrase.expose(),
+                latest_snapshot,
+                current_sha1,
+                request.stop_time,
+            );
+            discard_copies_on_error(&request.dataset.workspace, result)
+        }
+    }
+}
+
+// Remove the copies made while taking the snapshot if the backup failed, as
+// they may be as large as the files themselves. A backup that ran out of time
+// keeps them for when it resumes, as its plan refers to their digests.
+fn discard_copies_on_error<T>(workspace: &Path, result: Result<T, Error>) -> Result<T, Error> {
+    if let Err(err) = &result {
+        if !err.is::<OutOfTimeFailure>() {
+            copies::remove_copies(workspace);
+        }
+    }
+    result
 }
 
 ///
@@ -181,55 +265,138 @@ fn continue_backup(
     current_sha1: entities::Checksum,
     stop_time: Option<DateTime<Utc>>,
 ) -> Result<Option<entities::Checksum>, Error> {
-    let mut driver = driver::BackupDriver::new(dataset, repo, state, passphrase, stop_time)?;
-    // if no previous snapshot, visit every file in the new snapshot, otherwise
-    // find those files that changed from the previous snapshot
-    match parent_sha1 {
-        None => {
-            let snapshot = repo
-                .get_snapshot(&current_sha1)?
-                .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", current_sha1)))?;
-            let tree = snapshot.tree;
-            // count the changed files and emit an event
-            let iter = TreeWalker::new(repo, &dataset.basepath, tree.clone());
-            let count: u64 = iter.count() as u64;
-            state.backup_event(BackupAction::BeginUpload(dataset.id.clone(), count));
-            // perform the backup
-            let iter = TreeWalker::new(repo, &dataset.basepath, tree);
-            for result in iter {
-                driver.add_file(result?)?;
-            }
+    let span = info_span!("snapshot", digest = %current_sha1);
+    let _entered = span.enter();
+    let mut driver =
+        driver::BackupDriver::new(dataset, repo, state, &current_sha1, passphrase, stop_time)?;
+    // uploads interrupted while backing up an earlier snapshot will not resume
+    if let Err(err) = driver.abandon_uploads(Some(&current_sha1)) {
+        warn!(
+            "could not abandon earlier uploads of {}: {}",
+            dataset.id, err
+        );
+    }
+    if let Some(plan) = plan::load(&dataset.workspace, &current_sha1)? {
+        // an earlier run stopped uploading and saved the remaining changes
+        debug!("backup: uploading {} planned files", plan.files.len());
+        driver.progress().begin(Some(plan.files.len() as u64));
+        for planned in plan.files {
+            driver.add_planned(planned)?;
         }
-        Some(ref parent) => {
-            // count the changed files and emit an event
-            let iter = find_changed_files(
-                repo,
-                dataset.basepath.clone(),
-                parent.clone(),
-                current_sha1.clone(),
-            )?;
-            let count: u64 = iter.count() as u64;
-            state.backup_event(BackupAction::BeginUpload(dataset.id.clone(), count));
-            // perform the backup
-            let iter = find_changed_files(
-                repo,
-                dataset.basepath.clone(),
-                parent.clone(),
-                current_sha1.clone(),
-            )?;
-            for result in iter {
-                driver.add_file(result?)?;
+    } else {
+        // if no previous snapshot, visit every file in the new snapshot,
+        // otherwise find those files that changed from the previous snapshot
+        match parent_sha1 {
+            None => {
+                let snapshot = repo.get_snapshot(&current_sha1)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingSnapshot).with("digest", &current_sha1)
+                })?;
+                let tree = snapshot.tree;
+                // count the changed files and emit an event
+                let iter = TreeWalker::new(repo, &dataset.basepath, tree.clone());
+                let count: u64 = iter.count() as u64;
+                driver.progress().begin(Some(count));
+                // perform the backup
+                let iter = TreeWalker::new(repo, &dataset.basepath, tree);
+                for result in iter {
+                    driver.add_file(result?)?;
+                }
+            }
+            Some(ref parent) => {
+                // count the changed files and emit an event
+                let iter = find_changed_files(
+                    repo,
+                    dataset.basepath.clone(),
+                    parent.clone(),
+                    current_sha1.clone(),
+                )?;
+                let count: u64 = iter.count() as u64;
+                driver.progress().begin(Some(count));
+                // perform the backup
+                let iter = find_changed_files(
+                    repo,
+                    dataset.basepath.clone(),
+                    parent.clone(),
+                    current_sha1.clone(),
+                )?;
+                for result in iter {
+                    driver.add_file(result?)?;
+                }
             }
         }
     }
     // finish packing and uploading the changed files
     driver.finish_remainder()?;
+    // if the uploads were stopped, the rest must wait for the next run
+    if driver.save_plan(&current_sha1)? {
+        return Err(Error::from(OutOfTimeFailure {}));
+    }
     // commit everything to the database
     driver.update_snapshot(&current_sha1)?;
+    copies::remove_copies(&dataset.workspace);
+    plan::remove(&dataset.workspace);
+    if let Err(err) = driver.abandon_uploads(None) {
+        warn!(
+            "could not abandon leftover uploads of {}: {}",
+            dataset.id, err
+        );
+    }
+    // the backup itself succeeded even if the critical packs were not copied
+    if let Err(err) = critical::warm_critical_packs(dataset, repo.as_ref(), &current_sha1) {
+        error!("could not copy critical packs of {}: {}", dataset.id, err);
+    }
+    // the backup itself succeeded even if the sentinel was corrupted, which
+    // is recorded as an event to be noticed and investigated
+    if dataset.sentinel() {
+        if let Err(err) = sentinel::verify(repo.as_ref(), dataset, &current_sha1, passphrase) {
+            error!("sentinel of {} failed verification: {}", dataset.id, err);
+            events::record(
+                entities::Event::new(entities::EventKind::SentinelFailed, &dataset.id)
+                    .detail("snapshot", current_sha1.to_string())
+                    .detail("error", err.to_string()),
+            );
+        }
+    }
+    // the backup itself succeeded even if the emergency index was not saved,
+    // the previous index remains and the next backup will try again
+    if let Err(err) = driver.save_emergency_index(&current_sha1) {
+        error!("could not save emergency index of {}: {}", dataset.id, err);
+    }
     driver.backup_database()?;
+    // the backup itself succeeded even if the snapshot logs were not updated,
+    // which will show up when the logs are next verified
+    if let Err(err) = driver.append_chain(&current_sha1) {
+        error!("could not update snapshot logs of {}: {}", dataset.id, err);
+    }
+    driver.progress().finish(None);
     Ok(Some(current_sha1))
 }
 
+// Lower the scheduling priority of the current thread, which is inherited by
+// the threads that it starts, such as those computing the file digests. The
+// priority remains for the life of the thread.
+#[cfg(target_os = "linux")]
+fn lower_priority(niceness: i32) {
+    // on Linux, a `who` of zero refers to the calling thread rather than the
+    // entire process
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } != 0 {
+        warn!(
+            "backup: could not set niceness to {}: {}",
+            niceness,
+            io::Error::last_os_error()
+        );
+    } else {
+        debug!("backup: running with niceness {}", niceness);
+    }
+}
+
+// Elsewhere the priority applies to the entire process, and once lowered it
+// cannot be raised again, so the niceness is ignored.
+#[cfg(not(target_os = "linux"))]
+fn lower_priority(niceness: i32) {
+    warn!("backup: niceness {} is supported only on Linux", niceness);
+}
+
 ///
 /// Raised when the backup has run out of time and must stop temporarily,
 /// resuming at a later time.
@@ -248,25 +415,71 @@ impl fmt::Display for OutOfTimeFailure {
 /// `Some`, specifies the snapshot that will be recorded as the parent of this
 /// new snapshot. If there have been no changes, then None is returned.
 ///
+/// Any files that could not be read because they remained locked by another
+/// process are added to `locked`; the entries of such files are carried over
+/// from the parent snapshot, if they were there, and are otherwise left out.
+///
+/// Files that match the `copies` patterns are copied to the workspace and read
+/// from there, both now and when building the pack files.
+///
+/// The `throttle` limits the number of file system operations in progress at
+/// once, independent of the number of threads computing the file digests,
+/// which is one for each processor unless limited by `threads`.
+///
+#[allow(clippy::too_many_arguments)]
 fn take_snapshot(
     basepath: &Path,
     parent: Option<entities::Checksum>,
     dbase: &Arc<dyn RecordRepository>,
     excludes: Vec<PathBuf>,
+    copies: Option<Arc<copies::SnapshotCopies>>,
+    throttle: &Throttle,
+    threads: Option<usize>,
+    locked: &mut Vec<PathBuf>,
 ) -> Result<Option<entities::Checksum>, Error> {
     let start_time = SystemTime::now();
     let actual_start_time = Utc::now();
     let exclusions = build_exclusions(basepath, &excludes);
     let mut file_counts: entities::FileCounts = Default::default();
-    let cpu_count = std::thread::available_parallelism()?.get();
+    let mut cpu_count = std::thread::available_parallelism()?.get();
+    if let Some(limit) = threads.filter(|t| *t > 0) {
+        cpu_count = cpu_count.min(limit);
+    }
     let pool = ThreadPool::new(cpu_count);
     debug!("take_snapshot: creating pool of {cpu_count} threads");
-    let tree = scan_tree(basepath, dbase, &exclusions, &mut file_counts, &pool)?;
-    if let Some(ref parent_sha1) = parent {
-        let parent_doc = dbase
-            .get_snapshot(parent_sha1)?
-            .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", parent_sha1)))?;
-        if parent_doc.tree == tree.digest {
+    if let Some(limit) = throttle.limit() {
+        debug!("take_snapshot: limiting to {limit} concurrent file operations");
+    }
+    let parent_tree = match parent {
+        Some(ref parent_sha1) => {
+            let parent_doc = dbase.get_snapshot(parent_sha1)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &parent_sha1)
+            })?;
+            Some(PreviousTree {
+                basepath,
+                tree: parent_doc.tree,
+            })
+        }
+        None => None,
+    };
+    let locked_files: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
+    let tree = scan_tree(
+        basepath,
+        dbase,
+        &exclusions,
+        &copies,
+        &mut file_counts,
+        &pool,
+        throttle,
+        &locked_files,
+        parent_tree.as_ref(),
+    )?;
+    locked.append(&mut locked_files.lock().unwrap());
+    if !locked.is_empty() {
+        warn!("take_snapshot: skipped {} locked files", locked.len());
+    }
+    if let Some(previous) = parent_tree.as_ref() {
+        if previous.tree == tree.digest {
             // nothing new at all with this snapshot
             return Ok(None);
         }
@@ -389,7 +602,7 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                         if right_entry.reference.is_tree() {
                             // a new tree: add every file contained therein
                             let mut path = PathBuf::from(base);
-                            path.push(&right_entry.name);
+                            path.push(right_entry.file_name());
                             let sum = right_entry.reference.checksum().unwrap();
                             self.walker = Some(TreeWalker::new(self.dbase, &path, sum));
                             // return to the main loop
@@ -398,7 +611,7 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                             // return the file
                             let sum = right_entry.reference.checksum().unwrap();
                             let mut path = PathBuf::from(base);
-                            path.push(&right_entry.name);
+                            path.push(right_entry.file_name());
                             let changed = ChangedFile::new(&path, sum);
                             return Some(Ok(changed));
                         }
@@ -416,19 +629,19 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                             let left_sum = left_entry.reference.checksum().unwrap();
                             let right_sum = right_entry.reference.checksum().unwrap();
                             let mut path = PathBuf::from(base);
-                            path.push(&left_entry.name);
+                            path.push(left_entry.file_name());
                             self.queue.push_back((path, left_sum, right_sum));
                         } else if (left_is_file || left_is_dir || left_is_link) && right_is_file {
                             // new file or a changed file
                             let sum = right_entry.reference.checksum().unwrap();
                             let mut path = PathBuf::from(base);
-                            path.push(&right_entry.name);
+                            path.push(right_entry.file_name());
                             let changed = ChangedFile::new(&path, sum);
                             return Some(Ok(changed));
                         } else if (left_is_file || left_is_link) && right_is_dir {
                             // now a directory, add everything under it
                             let mut path = PathBuf::from(base);
-                            path.push(&right_entry.name);
+                            path.push(right_entry.file_name());
                             let sum = right_entry.reference.checksum().unwrap();
                             self.walker = Some(TreeWalker::new(self.dbase, &path, sum));
                             // return to the main loop
@@ -450,14 +663,14 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                     if right_entry.reference.is_tree() {
                         // a new tree: add every file contained therein
                         let mut path = PathBuf::from(base);
-                        path.push(&right_entry.name);
+                        path.push(right_entry.file_name());
                         let sum = right_entry.reference.checksum().unwrap();
                         self.walker = Some(TreeWalker::new(self.dbase, &path, sum));
                     } else if right_entry.reference.is_file() {
                         // return the file
                         let sum = right_entry.reference.checksum().unwrap();
                         let mut path = PathBuf::from(base);
-                        path.push(&right_entry.name);
+                        path.push(right_entry.file_name());
                         let changed = ChangedFile::new(&path, sum);
                         return Some(Ok(changed));
                     }
@@ -477,7 +690,9 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                 }
                 let opt = result.unwrap();
                 if opt.is_none() {
-                    return Some(Err(anyhow!(format!("missing tree: {:?}", left_sum))));
+                    return Some(Err(Message::new(MessageCode::MissingTree)
+                        .with("digest", &left_sum)
+                        .into()));
                 }
                 self.left_tree = opt;
                 self.left_idx = 0;
@@ -487,7 +702,9 @@ impl<'a> Iterator for ChangedFilesIter<'a> {
                 }
                 let opt = result.unwrap();
                 if opt.is_none() {
-                    return Some(Err(anyhow!(format!("missing tree: {:?}", right_sum))));
+                    return Some(Err(Message::new(MessageCode::MissingTree)
+                        .with("digest", &right_sum)
+                        .into()));
                 }
                 self.right_tree = opt;
                 self.right_idx = 0;
@@ -516,10 +733,10 @@ fn find_changed_files(
 ) -> Result<ChangedFilesIter, Error> {
     let snap1doc = dbase
         .get_snapshot(&snapshot1)?
-        .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", snapshot1)))?;
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &snapshot1))?;
     let snap2doc = dbase
         .get_snapshot(&snapshot2)?
-        .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", snapshot2)))?;
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &snapshot2))?;
     Ok(ChangedFilesIter::new(
         dbase,
         basepath,
@@ -576,13 +793,13 @@ impl<'a> Iterator for TreeWalker<'a> {
                         // enqueue the tree
                         let sum = entry.reference.checksum().unwrap();
                         let mut path = PathBuf::from(base);
-                        path.push(&entry.name);
+                        path.push(entry.file_name());
                         self.queue.push_back((path, sum));
                     } else if entry.reference.is_file() {
                         // return the file
                         let sum = entry.reference.checksum().unwrap();
                         let mut path = PathBuf::from(base);
-                        path.push(&entry.name);
+                        path.push(entry.file_name());
                         let changed = ChangedFile::new(&path, sum);
                         return Some(Ok(changed));
                     }
@@ -597,7 +814,9 @@ impl<'a> Iterator for TreeWalker<'a> {
                 }
                 let opt = result.unwrap();
                 if opt.is_none() {
-                    return Some(Err(anyhow!(format!("missing tree: {:?}", sum))));
+                    return Some(Err(Message::new(MessageCode::MissingTree)
+                        .with("digest", &sum)
+                        .into()));
                 }
                 // update the tree, index, and path fields
                 self.tree = opt;
@@ -663,11 +882,22 @@ fn build_exclusions(basepath: &Path, excludes: &[PathBuf]) -> GlobSet {
 ///
 /// Read the symbolic link value and convert to raw bytes.
 ///
+/// On Windows the path separators are converted to forward slashes so that
+/// the same link yields the same tree entry on every platform.
+///
 fn read_link(path: &Path) -> Result<Vec<u8>, Error> {
     // convert whatever value returned by the OS into raw bytes without string conversion
     use os_str_bytes::OsStringBytes;
     let value = fs::read_link(path)?;
-    Ok(value.into_os_string().into_raw_vec())
+    #[allow(unused_mut)]
+    let mut raw = value.into_os_string().into_raw_vec();
+    #[cfg(target_family = "windows")]
+    for byte in raw.iter_mut() {
+        if *byte == b'\\' {
+            *byte = b'/';
+        }
+    }
+    Ok(raw)
 }
 
 ///
@@ -675,39 +905,70 @@ fn read_link(path: &Path) -> Result<Vec<u8>, Error> {
 /// directories. The returned tree entity will have already been added to the
 /// database, along with all of the nested trees.
 ///
+#[allow(clippy::too_many_arguments)]
 fn scan_tree(
     basepath: &Path,
     dbase: &Arc<dyn RecordRepository>,
     excludes: &GlobSet,
+    copies: &Option<Arc<copies::SnapshotCopies>>,
     file_counts: &mut entities::FileCounts,
     pool: &ThreadPool,
+    throttle: &Throttle,
+    locked: &Arc<Mutex<Vec<PathBuf>>>,
+    previous: Option<&PreviousTree>,
 ) -> Result<entities::Tree, Error> {
     let mut entries: Vec<entities::TreeEntry> = Vec::new();
     let mut file_count = 0;
     let mut pending_files: Vec<PathBuf> = Vec::new();
-    match fs::read_dir(basepath) {
+    let locked_before = locked.lock().unwrap().len();
+    // Read the whole directory while holding a permit, which is released
+    // before descending into the subdirectories so that the throttle cannot
+    // be exhausted by the directories being scanned. Deeply nested directories
+    // may exceed the path length limit on Windows, but the entries are named
+    // relative to the original path.
+    let listing = {
+        let _permit = throttle.acquire();
+        fs::read_dir(paths::long_path(basepath)).map(|readdir| readdir.collect::<Vec<_>>())
+    };
+    match listing {
         Ok(readdir) => {
             for entry_result in readdir {
                 match entry_result {
                     Ok(entry) => {
-                        let path = entry.path();
+                        let path = basepath.join(entry.file_name());
                         if excludes.is_match(&path) {
                             continue;
                         }
                         // DirEntry.metadata() does not follow symlinks
-                        match entry.metadata() {
+                        let result = {
+                            let _permit = throttle.acquire();
+                            entry.metadata()
+                        };
+                        match result {
                             Ok(metadata) => {
-                                count_files(&metadata, file_counts);
                                 if metadata.is_dir() {
-                                    let scan =
-                                        scan_tree(&path, dbase, excludes, file_counts, pool)?;
+                                    file_counts.directories += 1;
+                                    let scan = scan_tree(
+                                        &path,
+                                        dbase,
+                                        excludes,
+                                        copies,
+                                        file_counts,
+                                        pool,
+                                        throttle,
+                                        locked,
+                                        previous,
+                                    )?;
                                     file_count += scan.file_count;
                                     let digest = scan.digest.clone();
                                     let tref = entities::TreeReference::TREE(digest);
+                                    let _permit = throttle.acquire();
                                     entries.push(process_path(&path, tref, dbase));
                                 } else if metadata.is_symlink() {
+                                    let _permit = throttle.acquire();
                                     match read_link(&path) {
                                         Ok(contents) => {
+                                            file_counts.symlinks += 1;
                                             let tref = entities::TreeReference::LINK(contents);
                                             entries.push(process_path(&path, tref, dbase));
                                         }
@@ -717,14 +978,15 @@ fn scan_tree(
                                     }
                                 } else if metadata.is_file() {
                                     if metadata.len() <= entities::FILE_SIZE_SMALL {
-                                        match fs::read(&path) {
+                                        let _permit = throttle.acquire();
+                                        match read_small_file(&path) {
                                             Ok(contents) => {
+                                                let length = contents.len() as u64;
+                                                count_file(&path, length, file_counts);
                                                 let tref = entities::TreeReference::SMALL(contents);
                                                 entries.push(process_path(&path, tref, dbase));
                                             }
-                                            Err(err) => {
-                                                error!("could not read file: {:?}: {}", path, err)
-                                            }
+                                            Err(err) => report_read_error(path, err, locked),
                                         }
                                     } else {
                                         pending_files.push(path);
@@ -741,42 +1003,129 @@ fn scan_tree(
         Err(err) => error!("read_dir error for {:?}: {}", basepath, err),
     }
     // Process all of the files found in this directory.
-    let mut file_entries = process_files(pending_files, dbase, pool);
+    let mut file_entries = process_files(pending_files, dbase, copies, pool, throttle, locked);
     file_count += file_entries.len() as u32;
-    for entry in file_entries.drain(..) {
+    for (entry, length) in file_entries.drain(..) {
+        count_file(Path::new(&entry.name), length, file_counts);
         entries.push(entry);
     }
+    // files in this directory that remained locked keep their previous entry,
+    // otherwise the next backup would consider them to have been deleted
+    if let Some(previous) = previous {
+        let skipped: Vec<PathBuf> = locked.lock().unwrap()[locked_before..]
+            .iter()
+            .filter(|p| p.parent() == Some(basepath))
+            .cloned()
+            .collect();
+        for path in skipped {
+            if let Some((entry, length)) = previous_entry(dbase, previous, &path)? {
+                debug!("scan_tree: keeping previous entry for {:?}", path);
+                count_file(&path, length, file_counts);
+                file_count += 1;
+                entries.push(entry);
+            }
+        }
+    }
     let tree = entities::Tree::new(entries, file_count);
     dbase.insert_tree(&tree)?;
     Ok(tree)
 }
 
-// Process the given set of files, returning the TreeEntry for each. Uses the
-// thread pool to compute the checksums of the files in parallel.
+// Tree of the previous snapshot of the dataset, from which the entries of the
+// files that could not be read are carried over.
+struct PreviousTree<'a> {
+    basepath: &'a Path,
+    tree: entities::Checksum,
+}
+
+// Find the entry of the previous snapshot for the file at the given path,
+// returning it along with the length of the file, if it was a file then.
+fn previous_entry(
+    dbase: &Arc<dyn RecordRepository>,
+    previous: &PreviousTree,
+    path: &Path,
+) -> Result<Option<(entities::TreeEntry, u64)>, Error> {
+    let Ok(relative) = path.strip_prefix(previous.basepath) else {
+        return Ok(None);
+    };
+    let mut digest = previous.tree.clone();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        let Some(tree) = dbase.get_tree(&digest)? else {
+            return Ok(None);
+        };
+        let name = component.as_os_str();
+        let Some(entry) = tree
+            .entries
+            .into_iter()
+            .find(|e| e.file_name().as_os_str() == name)
+        else {
+            return Ok(None);
+        };
+        if components.peek().is_some() {
+            match &entry.reference {
+                entities::TreeReference::TREE(subtree) => digest = subtree.clone(),
+                _ => return Ok(None),
+            }
+            continue;
+        }
+        let length = match &entry.reference {
+            entities::TreeReference::SMALL(contents) => contents.len() as u64,
+            entities::TreeReference::FILE(file_digest) => match dbase.get_file(file_digest)? {
+                Some(file) => file.length,
+                // the file of an incomplete snapshot may not have a record yet
+                None => 0,
+            },
+            _ => return Ok(None),
+        };
+        return Ok(Some((entry, length)));
+    }
+    Ok(None)
+}
+
+// Process the given set of files, returning the TreeEntry for each, along with
+// the number of bytes that were read. Uses the thread pool to compute the
+// checksums of the files in parallel, as many at a time as the throttle allows.
 fn process_files(
     paths: Vec<PathBuf>,
     dbase: &Arc<dyn RecordRepository>,
+    copies: &Option<Arc<copies::SnapshotCopies>>,
     pool: &ThreadPool,
-) -> Vec<entities::TreeEntry> {
+    throttle: &Throttle,
+    locked: &Arc<Mutex<Vec<PathBuf>>>,
+) -> Vec<(entities::TreeEntry, u64)> {
     // list of results that are either successful (Some(TreeEntry)) or resulted
     // in an error (None), paired with a condvar so the main thread can wait
-    let entries: Arc<(Mutex<Vec<Option<entities::TreeEntry>>>, Condvar)> =
+    let entries: Arc<(Mutex<Vec<Option<(entities::TreeEntry, u64)>>>, Condvar)> =
         Arc::new((Mutex::new(Vec::new()), Condvar::new()));
     for path in paths.iter() {
         let path = path.to_owned();
         let dbase = dbase.clone();
         let entries = entries.clone();
+        let locked = locked.clone();
+        let copies = copies.clone();
+        let throttle = throttle.clone();
         pool.execute(move || {
-            let entry = match entities::Checksum::blake3_from_file(&path) {
-                Ok(digest) => {
+            let permit = throttle.acquire();
+            let result = match copies.filter(|c| c.is_match(&path)) {
+                // read the file just once, then use the copy from now on
+                Some(copies) => copies.copy_and_hash(&path),
+                None => open_for_read(&path).and_then(|mut file| {
+                    let digest = entities::Checksum::blake3_from_reader(&mut file)?;
+                    Ok((digest, file.stream_position()?))
+                }),
+            };
+            let entry = match result {
+                Ok((digest, length)) => {
                     let tref = entities::TreeReference::FILE(digest);
-                    Some(process_path(&path, tref, &dbase))
+                    Some((process_path(&path, tref, &dbase), length))
                 }
                 Err(err) => {
-                    error!("could not read file: {:?}: {}", path, err);
+                    report_read_error(path, err, &locked);
                     None
                 }
             };
+            drop(permit);
             let (lock, cvar) = &*entries;
             let mut actual = lock.lock().unwrap();
             actual.push(entry);
@@ -793,6 +1142,24 @@ fn process_files(
     actual.drain(..).flatten().collect()
 }
 
+// Read the entire contents of the (small) file, retrying if it is locked.
+fn read_small_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = open_for_read(path)?;
+    let mut contents: Vec<u8> = Vec::new();
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Log the error in reading the file, collecting those that remained locked.
+fn report_read_error(path: PathBuf, err: io::Error, locked: &Mutex<Vec<PathBuf>>) {
+    if is_locked_error(&err) {
+        warn!("file remained locked: {:?}: {}", path, err);
+        locked.lock().unwrap().push(path);
+    } else {
+        error!("could not read file: {:?}: {}", path, err);
+    }
+}
+
 ///
 /// Create a `TreeEntry` record for this path, which may include storing
 /// extended attributes in the database.
@@ -834,6 +1201,37 @@ fn process_xattrs(
             }
         }
     }
+    if !cfg!(target_os = "macos") {
+        process_apple_double(fullpath, entry, dbase);
+    }
+}
+
+// Record the Finder information and resource fork held in the AppleDouble
+// file alongside the given file, as written by macOS to file systems that
+// lack extended attributes, such that they are applied natively when the
+// file is restored on macOS. The AppleDouble file itself is saved as usual.
+#[cfg(target_family = "unix")]
+fn process_apple_double(
+    fullpath: &Path,
+    entry: &mut entities::TreeEntry,
+    dbase: &Arc<dyn RecordRepository>,
+) {
+    use crate::domain::helpers::appledouble::{self, AppleDouble};
+    if entry.xattrs.keys().any(|name| appledouble::is_fork(name)) {
+        return;
+    }
+    match AppleDouble::read(fullpath) {
+        Ok(Some(double)) => {
+            for (name, value) in double.attributes() {
+                let digest = entities::Checksum::sha1_from_bytes(value);
+                if dbase.insert_xattr(&digest, value).is_ok() {
+                    entry.xattrs.insert(name.to_owned(), digest);
+                }
+            }
+        }
+        Ok(None) => (),
+        Err(err) => debug!("ignoring AppleDouble file for {:?}: {}", fullpath, err),
+    }
 }
 
 #[cfg(target_family = "windows")]
@@ -845,15 +1243,12 @@ fn process_xattrs(
     // nothing do to be done on Windows
 }
 
-/// Update the file_counts record to reflect this tree entry.
-fn count_files(metadata: &fs::Metadata, file_counts: &mut entities::FileCounts) {
-    if metadata.is_dir() {
-        file_counts.directories += 1;
-    } else if metadata.is_symlink() {
-        file_counts.symlinks += 1;
-    } else if metadata.is_file() {
-        file_counts.register_file(metadata.len());
-    }
+/// Update the file_counts record to reflect a file that was read, using the
+/// length that was read rather than the size reported by the directory scan,
+/// such that the counts agree with the file records.
+fn count_file(path: &Path, length: u64, file_counts: &mut entities::FileCounts) {
+    file_counts.register_file(length);
+    file_counts.register_category(path, length);
 }
 
 #[cfg(test)]
@@ -870,6 +1265,49 @@ mod tests {
     use std::sync::Arc;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_previous_entry() -> Result<(), Error> {
+        // arrange
+        let file_digest = Checksum::BLAKE3("cafebabe".into());
+        let mut file_entry = entities::TreeEntry::new(
+            Path::new("../test/fixtures/lorem-ipsum.txt"),
+            entities::TreeReference::FILE(file_digest.clone()),
+        );
+        file_entry.name = "notes.txt".into();
+        let subtree = entities::Tree::new(vec![file_entry], 1);
+        let mut dir_entry = entities::TreeEntry::new(
+            Path::new("../test/fixtures"),
+            entities::TreeReference::TREE(subtree.digest.clone()),
+        );
+        dir_entry.name = "docs".into();
+        let root = entities::Tree::new(vec![dir_entry], 0);
+        let previous = PreviousTree {
+            basepath: Path::new("/home/planet"),
+            tree: root.digest.clone(),
+        };
+        let trees = vec![root, subtree];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(entities::File::new(digest.clone(), 3129, vec![]))));
+        let dbase: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let path = Path::new("/home/planet/docs/notes.txt");
+        let result = previous_entry(&dbase, &previous, path)?;
+        // assert
+        let (entry, length) = result.unwrap();
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(length, 3129);
+        // neither a directory nor a missing file has an entry to carry over
+        assert!(previous_entry(&dbase, &previous, Path::new("/home/planet/docs"))?.is_none());
+        let path = Path::new("/home/planet/docs/other.txt");
+        assert!(previous_entry(&dbase, &previous, path)?.is_none());
+        let path = Path::new("/elsewhere/docs/notes.txt");
+        assert!(previous_entry(&dbase, &previous, path)?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_process_path() {
         // arrange
@@ -884,15 +1322,40 @@ mod tests {
         let entry = process_path(&path, tref, &dbase);
         // assert
         assert_eq!(entry.name, "washington-journal.txt");
-        #[cfg(target_family = "unix")]
         let expected_hash = "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60";
-        #[cfg(target_family = "windows")]
-        let expected_hash = "183d52ff928be3e77cccf1b78b12b31910d5079195a637a9a2b499059f99b781";
         let expected =
             entities::TreeReference::FILE(entities::Checksum::BLAKE3(expected_hash.into()));
         assert_eq!(entry.reference, expected);
     }
 
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_process_apple_double() -> Result<(), Error> {
+        use crate::domain::helpers::appledouble::{AppleDouble, RESOURCE_FORK};
+        // arrange
+        let tmpdir = tempdir()?;
+        let path = tmpdir.path().join("notes.rtf");
+        fs::write(&path, b"{\\rtf1}")?;
+        let double = AppleDouble {
+            finder_info: None,
+            resource_fork: Some(vec![1, 2, 3, 4]),
+        };
+        double.write(&path)?;
+        let mut mock = MockRecordRepository::new();
+        mock.expect_insert_xattr()
+            .withf(|_, value| value == [1, 2, 3, 4])
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let dbase: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let mut entry = entities::TreeEntry::new(&path, entities::TreeReference::SMALL(vec![]));
+        process_apple_double(&path, &mut entry, &dbase);
+        // assert
+        let expected = Checksum::sha1_from_bytes(&[1, 2, 3, 4]);
+        assert_eq!(entry.xattrs.get(RESOURCE_FORK), Some(&expected));
+        Ok(())
+    }
+
     #[test]
     fn test_process_files() {
         // arrange
@@ -908,7 +1371,9 @@ mod tests {
         let dbase: Arc<(dyn crate::domain::repositories::RecordRepository + 'static)> =
             Arc::new(mock);
         let pool = ThreadPool::new(1);
-        let entries = process_files(paths, &dbase, &pool);
+        let locked: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
+        let throttle = Throttle::new(Some(1));
+        let entries = process_files(paths, &dbase, &None, &pool, &throttle, &locked);
         // assert
         assert_eq!(entries.len(), 4);
         assert!(entries.iter().any(|e| e.name == "lorem-ipsum.txt"));
@@ -1022,7 +1487,17 @@ mod tests {
         // take a snapshot of the dataset
         let dest: PathBuf = fixture_path.path().join("lorem-ipsum.txt");
         assert!(fs::copy("../test/fixtures/lorem-ipsum.txt", dest).is_ok());
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert!(snapshot1.parent.is_none());
         assert_eq!(snapshot1.file_counts.total_files(), 1);
@@ -1030,8 +1505,17 @@ mod tests {
         // take another snapshot
         let dest: PathBuf = fixture_path.path().join("SekienAkashita.jpg");
         assert!(fs::copy("../test/fixtures/SekienAkashita.jpg", &dest).is_ok());
-        let snap2_sha =
-            take_snapshot(fixture_path.path(), Some(snap1_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap2_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap1_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot2 = dbase.get_snapshot(&snap2_sha)?.unwrap();
         assert!(snapshot2.parent.is_some());
         assert_eq!(snapshot2.parent.unwrap(), snap1_sha);
@@ -1059,7 +1543,16 @@ mod tests {
         );
 
         // take yet another snapshot, should find no changes
-        let snap3_opt = take_snapshot(fixture_path.path(), Some(snap2_sha), &dbase, vec![])?;
+        let snap3_opt = take_snapshot(
+            fixture_path.path(),
+            Some(snap2_sha),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?;
         assert!(snap3_opt.is_none());
         Ok(())
     }
@@ -1078,7 +1571,17 @@ mod tests {
         workspace.push(".tmp");
         let excludes = vec![workspace];
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(&basepath, None, &dbase, excludes)?.unwrap();
+        let snap1_sha = take_snapshot(
+            &basepath,
+            None,
+            &dbase,
+            excludes,
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert!(snapshot1.parent.is_none());
         assert_eq!(snapshot1.file_counts.total_files(), 6);
@@ -1132,7 +1635,17 @@ mod tests {
         excludes.push(PathBuf::from("workspace"));
         let basepath: PathBuf = ["..", "test", "fixtures", "dataset_1"].iter().collect();
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(&basepath, None, &dbase, excludes)?.unwrap();
+        let snap1_sha = take_snapshot(
+            &basepath,
+            None,
+            &dbase,
+            excludes,
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert!(snapshot1.parent.is_none());
         assert_eq!(snapshot1.file_counts.total_files(), 3);
@@ -1149,6 +1662,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_snapshot_copies() -> Result<(), Error> {
+        let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+        fs::create_dir_all(&db_base)?;
+        let db_path = tempfile::tempdir_in(&db_base)?;
+        let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+        let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+        let dbase: Arc<dyn RecordRepository> = Arc::new(repo);
+
+        // set up dataset base directory with a workspace elsewhere
+        let fixture_base: PathBuf = ["tmp", "test", "fixtures"].iter().collect();
+        fs::create_dir_all(&fixture_base)?;
+        let fixture_path = tempfile::tempdir_in(&fixture_base)?;
+        let workspace = tempfile::tempdir_in(&fixture_base)?;
+        let dest: PathBuf = fixture_path.path().join("places.sqlite");
+        assert!(fs::copy("../test/fixtures/lorem-ipsum.txt", dest).is_ok());
+        let mut dataset = entities::Dataset::new(fixture_path.path());
+        dataset.workspace = workspace.path().to_path_buf();
+        dataset
+            .properties
+            .insert("copy_patterns".to_owned(), "default".to_owned());
+        let copies = copies::SnapshotCopies::new(&dataset).map(Arc::new);
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            copies,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
+        let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
+        assert_eq!(snapshot1.file_counts.total_files(), 1);
+        // the file entry refers to the copy in the workspace
+        let iter = TreeWalker::new(&dbase, fixture_path.path(), snapshot1.tree);
+        let changed: Vec<ChangedFile> = iter.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(changed.len(), 1);
+        let copied = copies::copied_path(workspace.path(), &changed[0].digest);
+        assert!(copied.is_some());
+        copies::remove_copies(workspace.path());
+        assert!(copies::copied_path(workspace.path(), &changed[0].digest).is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_snapshots_xattrs() -> Result<(), Error> {
         let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
@@ -1172,7 +1731,17 @@ mod tests {
                 && xattr::set(&dest, "me.fiedlers.test", b"foobar").is_ok();
         }
 
-        let snapshot_digest = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snapshot_digest = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot = dbase.get_snapshot(&snapshot_digest)?.unwrap();
         assert!(snapshot.parent.is_none());
         assert_eq!(snapshot.file_counts.total_files(), 1);
@@ -1222,7 +1791,17 @@ mod tests {
         }
 
         // take a snapshot
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert!(snapshot1.parent.is_none());
         assert_eq!(snapshot1.file_counts.total_files(), 0);
@@ -1263,7 +1842,17 @@ mod tests {
         fs::write(&mmm, b"morose monkey munching muffins, morose monkey munching muffins, morose monkey munching muffins, morose monkey munching muffins")?;
         fs::write(&yyy, b"yellow yak yodeling, yellow yak yodeling, yellow yak yodeling, yellow yak yodeling, yellow yak yodeling")?;
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert_eq!(snapshot1.file_counts.total_files(), 3);
         // add new files, change one file
@@ -1277,8 +1866,17 @@ mod tests {
         fs::write(&mmm, b"many mumbling mice moonlight, many mumbling mice moonlight, many mumbling mice moonlight, many mumbling mice moonlight")?;
         fs::write(&nnn, b"neat newts gnawing noodles, neat newts gnawing noodles, neat newts gnawing noodles, neat newts gnawing noodles")?;
         fs::write(&zzz, b"zebras riding on a zephyr, zebras riding on a zephyr, zebras riding on a zephyr, zebras riding on a zephyr")?;
-        let snap2_sha =
-            take_snapshot(fixture_path.path(), Some(snap1_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap2_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap1_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         // compute the differences
         let iter = find_changed_files(
             &dbase,
@@ -1299,8 +1897,17 @@ mod tests {
         fs::remove_file(&bbb)?;
         fs::remove_file(&yyy)?;
         fs::write(&zzz, b"zippy zip ties zooming, zippy zip ties zooming, zippy zip ties zooming, zippy zip ties zooming")?;
-        let snap3_sha =
-            take_snapshot(fixture_path.path(), Some(snap2_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap3_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap2_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         // compute the differences
         let iter = find_changed_files(
             &dbase,
@@ -1332,7 +1939,17 @@ mod tests {
         fs::write(&ccc, b"crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs")?;
         fs::write(&mmm, b"morose monkey munching muffins, morose monkey munching muffins, morose monkey munching muffins, morose monkey munching muffins")?;
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert_eq!(snapshot1.file_counts.total_files(), 2);
         // change files to dirs and vice versa
@@ -1343,8 +1960,17 @@ mod tests {
         fs::remove_dir_all(&mmm)?;
         fs::write(&ccc, b"catastrophic catastrophes, catastrophic catastrophes, catastrophic catastrophes, catastrophic catastrophes")?;
         fs::write(&mmm, b"many mumbling mice moonlight, many mumbling mice moonlight, many mumbling mice moonlight, many mumbling mice moonlight")?;
-        let snap2_sha =
-            take_snapshot(fixture_path.path(), Some(snap1_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap2_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap1_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         // compute the differences
         let iter = find_changed_files(
             &dbase,
@@ -1377,7 +2003,17 @@ mod tests {
         fs::write(&bbb, b"bored baby baboons bathing, bored baby baboons bathing, bored baby baboons bathing, bored baby baboons bathing")?;
         fs::write(&ccc, b"crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs")?;
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert_eq!(snapshot1.file_counts.total_files(), 2);
         // replace the files and directories with links
@@ -1401,8 +2037,17 @@ mod tests {
             #[cfg(target_family = "windows")]
             fs::symlink_file("mmm.txt", &ccc)?;
         }
-        let snap2_sha =
-            take_snapshot(fixture_path.path(), Some(snap1_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap2_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap1_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         // compute the differences
         let iter = find_changed_files(
             &dbase,
@@ -1448,7 +2093,17 @@ mod tests {
             fs::symlink_file("mmm.txt", &ccc)?;
         }
         // take a snapshot of the test data
-        let snap1_sha = take_snapshot(fixture_path.path(), None, &dbase, vec![])?.unwrap();
+        let snap1_sha = take_snapshot(
+            fixture_path.path(),
+            None,
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         let snapshot1 = dbase.get_snapshot(&snap1_sha)?.unwrap();
         assert_eq!(snapshot1.file_counts.total_files(), 1);
         // replace the links with files and directories
@@ -1458,8 +2113,17 @@ mod tests {
         let ccc: PathBuf = fixture_path.path().join("ccc").join("ccc.txt");
         fs::create_dir(ccc.parent().unwrap())?;
         fs::write(&ccc, b"crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs, crazy cat clawing chairs")?;
-        let snap2_sha =
-            take_snapshot(fixture_path.path(), Some(snap1_sha.clone()), &dbase, vec![])?.unwrap();
+        let snap2_sha = take_snapshot(
+            fixture_path.path(),
+            Some(snap1_sha.clone()),
+            &dbase,
+            vec![],
+            None,
+            &Throttle::default(),
+            None,
+            &mut vec![],
+        )?
+        .unwrap();
         // compute the differences
         let iter = find_changed_files(
             &dbase,
@@ -0,0 +1,282 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `plan` module saves the changed files that remain to be uploaded when
+//! the uploads of a backup are stopped, such as when leaving a network that
+//! should not carry the traffic. The backup goes on to find and chunk the rest
+//! of the changes, and the next run resumes by uploading the planned files
+//! rather than comparing the snapshots and reading the files all over again.
+//!
+//! The plan is kept in the workspace of the dataset, alongside the copies of
+//! the files that may be named within it. The size and modification time of
+//! each file are saved as well, such that a file that changed in the meantime
+//! is split into chunks again rather than packed from stale offsets.
+//!
+//! Likewise, a pack whose upload was interrupted is kept in the workspace along
+//! with the bucket and object names, under a key that identifies the chunks of
+//! the pack. The next run uploads that very file to the same place rather than
+//! a new pack of the same chunks, which would differ in every byte due to the
+//! encryption, allowing the stores to resume from their upload checkpoints.
+
+use crate::data::models::plan::{decode_pending, decode_plan, encode_pending, encode_plan};
+use crate::domain::entities::{Checksum, PendingUpload, UploadPlan};
+use anyhow::Error;
+use log::{debug, warn};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Name of the file within the workspace that holds the plan.
+const PLAN_FILE: &str = "upload.plan";
+
+// Name of the directory within the workspace that holds the pending uploads.
+const UPLOADS_DIR: &str = "uploads";
+
+///
+/// Save the plan to the workspace, replacing any previous plan.
+///
+pub fn save(workspace: &Path, plan: &UploadPlan) -> Result<(), Error> {
+    fs::create_dir_all(workspace)?;
+    let encoded = encode_plan(plan)?;
+    let mut outfile = tempfile::NamedTempFile::new_in(workspace)?;
+    outfile.write_all(&encoded)?;
+    outfile.persist(plan_path(workspace)).map_err(|e| e.error)?;
+    debug!(
+        "saved upload plan of {} files for {}",
+        plan.files.len(),
+        plan.snapshot
+    );
+    Ok(())
+}
+
+///
+/// Load the plan for the given snapshot from the workspace, if any. A plan
+/// for any other snapshot, or one that cannot be read, is discarded.
+///
+pub fn load(workspace: &Path, snapshot: &Checksum) -> Result<Option<UploadPlan>, Error> {
+    let path = plan_path(workspace);
+    if !path.exists() {
+        return Ok(None);
+    }
+    match decode_plan(&fs::read(&path)?) {
+        Ok(plan) if &plan.snapshot == snapshot => Ok(Some(plan)),
+        Ok(plan) => {
+            warn!(
+                "discarding upload plan for other snapshot {}",
+                plan.snapshot
+            );
+            remove(workspace);
+            Ok(None)
+        }
+        Err(err) => {
+            warn!("discarding unreadable upload plan: {}", err);
+            remove(workspace);
+            Ok(None)
+        }
+    }
+}
+
+///
+/// Remove the plan from the workspace, if any.
+///
+pub fn remove(workspace: &Path) {
+    let path = plan_path(workspace);
+    if path.exists() {
+        if let Err(err) = fs::remove_file(&path) {
+            warn!("could not remove upload plan {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn plan_path(workspace: &Path) -> PathBuf {
+    workspace.join(PLAN_FILE)
+}
+
+///
+/// Move the pack file into the workspace and save the pending upload under the
+/// given key, replacing any previous upload with the same key. Returns the new
+/// path of the pack file.
+///
+pub fn save_pending(
+    workspace: &Path,
+    key: &str,
+    packfile: &Path,
+    pending: &PendingUpload,
+) -> Result<PathBuf, Error> {
+    let dir = workspace.join(UPLOADS_DIR);
+    fs::create_dir_all(&dir)?;
+    let pack_path = pending_pack_path(workspace, key);
+    if packfile != pack_path {
+        fs::rename(packfile, &pack_path)?;
+    }
+    let encoded = encode_pending(pending)?;
+    let mut outfile = tempfile::NamedTempFile::new_in(&dir)?;
+    outfile.write_all(&encoded)?;
+    outfile
+        .persist(pending_path(workspace, key))
+        .map_err(|e| e.error)?;
+    debug!("saved pending upload of pack {}", pending.digest);
+    Ok(pack_path)
+}
+
+///
+/// Load the pending upload saved under the given key, if any, along with the
+/// path of its pack file. A pending upload that cannot be read, or whose pack
+/// file has gone missing, is discarded.
+///
+pub fn load_pending(workspace: &Path, key: &str) -> Option<(PendingUpload, PathBuf)> {
+    let path = pending_path(workspace, key);
+    if !path.exists() {
+        return None;
+    }
+    let pack_path = pending_pack_path(workspace, key);
+    match read_pending(&path) {
+        Ok(pending) if pack_path.exists() => Some((pending, pack_path)),
+        Ok(pending) => {
+            warn!(
+                "discarding pending upload of missing pack {}",
+                pending.digest
+            );
+            remove_pending(workspace, key);
+            None
+        }
+        Err(err) => {
+            warn!("discarding unreadable pending upload: {}", err);
+            remove_pending(workspace, key);
+            None
+        }
+    }
+}
+
+///
+/// Return the keys and records of all the pending uploads in the workspace.
+/// Those that cannot be read are discarded.
+///
+pub fn list_pending(workspace: &Path) -> Vec<(String, PendingUpload)> {
+    let mut results: Vec<(String, PendingUpload)> = Vec::new();
+    let entries = match fs::read_dir(workspace.join(UPLOADS_DIR)) {
+        Ok(entries) => entries,
+        Err(_) => return results,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("upload") {
+            continue;
+        }
+        if let Some(key) = path.file_stem().and_then(|s| s.to_str()) {
+            match read_pending(&path) {
+                Ok(pending) => results.push((key.to_owned(), pending)),
+                Err(err) => {
+                    warn!("discarding unreadable pending upload: {}", err);
+                    remove_pending(workspace, key);
+                }
+            }
+        }
+    }
+    results
+}
+
+///
+/// Remove the pending upload saved under the given key, and its pack file.
+///
+pub fn remove_pending(workspace: &Path, key: &str) {
+    for path in [
+        pending_path(workspace, key),
+        pending_pack_path(workspace, key),
+    ] {
+        if path.exists() {
+            if let Err(err) = fs::remove_file(&path) {
+                warn!(
+                    "could not remove pending upload {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn read_pending(path: &Path) -> Result<PendingUpload, Error> {
+    decode_pending(&fs::read(path)?)
+}
+
+fn pending_path(workspace: &Path, key: &str) -> PathBuf {
+    workspace.join(UPLOADS_DIR).join(format!("{}.upload", key))
+}
+
+fn pending_pack_path(workspace: &Path, key: &str) -> PathBuf {
+    workspace.join(UPLOADS_DIR).join(format!("{}.pack", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Chunk, PlannedFile};
+
+    #[test]
+    fn test_save_load_remove() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let other = Checksum::SHA1("835c497811ea71999665ace06cc7f8a119eeba4b".to_owned());
+        assert!(load(workspace.path(), &snapshot)?.is_none());
+        let digest = Checksum::BLAKE3(
+            "261930e84e14c240210ae8c459acc4bb85dd52f1b91c868f2106dbc1ceb3acca".to_owned(),
+        );
+        let path = Path::new("/home/planet/notes.txt");
+        let mut plan = UploadPlan::new(snapshot.clone());
+        plan.files.push(PlannedFile {
+            digest: digest.clone(),
+            path: path.to_path_buf(),
+            length: 3129,
+            modified: None,
+            chunks: vec![Chunk::new(digest.clone(), 0, 3129).filepath(path)],
+        });
+        save(workspace.path(), &plan)?;
+        let actual = load(workspace.path(), &snapshot)?.unwrap();
+        assert_eq!(actual.files.len(), 1);
+        assert_eq!(actual.files[0].digest, digest);
+        // a plan for another snapshot is discarded
+        assert!(load(workspace.path(), &other)?.is_none());
+        assert!(load(workspace.path(), &snapshot)?.is_none());
+        // as is a plan that cannot be decoded
+        fs::write(plan_path(workspace.path()), b"not a plan")?;
+        assert!(load(workspace.path(), &snapshot)?.is_none());
+        assert!(!plan_path(workspace.path()).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_uploads() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        assert!(load_pending(workspace.path(), "cafebabe").is_none());
+        assert!(list_pending(workspace.path()).is_empty());
+        let packfile = workspace.path().join("built.pack");
+        fs::write(&packfile, b"not really a pack")?;
+        let pending = PendingUpload {
+            snapshot: Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned()),
+            digest: Checksum::BLAKE3("deadbeef".to_owned()),
+            bucket: "bucket1".to_owned(),
+            object: "object1".to_owned(),
+        };
+        let moved = save_pending(workspace.path(), "cafebabe", &packfile, &pending)?;
+        assert!(!packfile.exists());
+        assert!(moved.exists());
+        let (actual, path) = load_pending(workspace.path(), "cafebabe").unwrap();
+        assert_eq!(actual, pending);
+        assert_eq!(path, moved);
+        let listed = list_pending(workspace.path());
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0, "cafebabe");
+        remove_pending(workspace.path(), "cafebabe");
+        assert!(!moved.exists());
+        assert!(load_pending(workspace.path(), "cafebabe").is_none());
+        // a pending upload whose pack has gone missing is discarded
+        fs::write(&packfile, b"not really a pack")?;
+        let moved = save_pending(workspace.path(), "cafebabe", &packfile, &pending)?;
+        fs::remove_file(moved)?;
+        assert!(load_pending(workspace.path(), "cafebabe").is_none());
+        assert!(list_pending(workspace.path()).is_empty());
+        Ok(())
+    }
+}
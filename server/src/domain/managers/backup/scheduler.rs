@@ -6,22 +6,32 @@
 //! are performed for each dataset according to a schedule.
 
 use crate::domain::entities::schedule::Schedule;
-use crate::domain::entities::Dataset;
+use crate::domain::entities::{Dataset, Event, EventKind};
 use crate::domain::helpers::crypto;
-use crate::domain::managers::backup::{OutOfTimeFailure, Performer, Request};
+use crate::domain::managers::backup::{trigger, OutOfTimeFailure, Performer, Request};
+use crate::domain::managers::clock::{self, ClockWatch};
+use crate::domain::managers::email;
+use crate::domain::managers::events;
+use crate::domain::managers::maintenance;
+use crate::domain::managers::notify::{self, Ping};
 use crate::domain::managers::pretty_print_duration;
+use crate::domain::managers::replica;
 use crate::domain::managers::state::{BackupAction, StateStore, SupervisorAction};
+use crate::domain::managers::tiering;
+use crate::domain::managers::trash;
 use crate::domain::repositories::RecordRepository;
 use actix::prelude::*;
 use anyhow::{anyhow, Error};
 use chrono::prelude::*;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, SystemTime};
+use tracing::info_span;
 
 ///
 /// `Scheduler` manages a supervised actor which in turn spawns actors to
@@ -62,6 +72,24 @@ static SUPERVISOR_INTERVAL: u64 = 100;
 #[cfg(not(test))]
 static SUPERVISOR_INTERVAL: u64 = 300_000;
 
+// Interval in milliseconds between applications of the store tiering policies.
+static TIERING_INTERVAL: u64 = 86_400_000;
+
+// Interval in milliseconds between purges of expired datasets from the trash.
+static TRASH_INTERVAL: u64 = 3_600_000;
+
+// Interval in milliseconds between removals of expired events from the log.
+static EVENTS_INTERVAL: u64 = 3_600_000;
+
+// Interval in milliseconds between checks for datasets that have not been
+// backed up within the staleness window of a webhook, and for the daily
+// summary email being due.
+static NOTIFY_INTERVAL: u64 = 3_600_000;
+
+// Set while the datasets are being scanned for changes, to avoid overlapping
+// scans of large datasets.
+static TRIGGER_SCANNING: AtomicBool = AtomicBool::new(false);
+
 impl SchedulerImpl {
     /// Construct a new instance of SchedulerImpl.
     pub fn new(state: Arc<dyn StateStore>, performer: Arc<dyn Performer>) -> Self {
@@ -150,6 +178,8 @@ struct BackupSupervisor {
     interval: u64,
     // Performs the dataset backup.
     performer: Arc<dyn Performer>,
+    // Detects jumps in the wall clock between checks.
+    clock: ClockWatch,
 }
 
 impl BackupSupervisor {
@@ -168,6 +198,7 @@ impl BackupSupervisor {
             runner: Arbiter::new(),
             interval,
             performer,
+            clock: ClockWatch::new(),
         }
     }
 
@@ -221,12 +252,106 @@ impl Actor for BackupSupervisor {
     fn started(&mut self, ctx: &mut Self::Context) {
         debug!("supervisor started");
         self.state.supervisor_event(SupervisorAction::Started);
+        // stores that are already over their cap or quota are reported as
+        // deferred without waiting for each dataset to be backed up
+        let dbase = self.dbase.clone();
+        let state = self.state.clone();
+        thread::spawn(move || {
+            if let Err(err) = record_deferrals(&dbase, &state) {
+                error!("could not check store transfer caps and quotas: {}", err);
+            }
+        });
         ctx.run_interval(Duration::from_millis(self.interval), |this, _ctx| {
             trace!("supervisor interval fired");
+            // the interval timer is driven by the monotonic clock
+            if let Some(adjustment) = this.clock.check() {
+                clock::record(adjustment);
+            }
             if let Err(err) = this.start_due_datasets() {
                 error!("failed to check datasets: {}", err);
             }
         });
+        ctx.run_interval(Duration::from_millis(self.interval), |this, ctx| {
+            trace!("trigger interval fired");
+            // scanning for changes can take a while, do not block the supervisor
+            let dbase = this.dbase.clone();
+            let state = this.state.clone();
+            let addr = ctx.address();
+            thread::spawn(move || {
+                if TRIGGER_SCANNING.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                if let Err(err) = start_triggered_datasets(&dbase, &state, &addr) {
+                    error!("failed to check dataset changes: {}", err);
+                }
+                TRIGGER_SCANNING.store(false, Ordering::SeqCst);
+            });
+        });
+        ctx.run_interval(Duration::from_millis(TIERING_INTERVAL), |this, _ctx| {
+            trace!("tiering interval fired");
+            // moving packs can take a long time, do not block the supervisor
+            let dbase = this.dbase.clone();
+            thread::spawn(move || {
+                if let Err(err) = tiering::apply_all_policies(dbase.as_ref()) {
+                    error!("failed to apply tiering policies: {}", err);
+                }
+            });
+        });
+        ctx.run_interval(Duration::from_millis(self.interval), |this, _ctx| {
+            trace!("maintenance interval fired");
+            // maintenance tasks can take a long time, do not block the supervisor
+            let dbase = this.dbase.clone();
+            let state = this.state.clone();
+            thread::spawn(move || {
+                if let Err(err) = maintenance::run_due(&dbase, state.as_ref()) {
+                    error!("failed to run maintenance tasks: {}", err);
+                }
+            });
+        });
+        ctx.run_interval(Duration::from_millis(self.interval), |this, _ctx| {
+            trace!("events interval fired");
+            let dbase = this.dbase.clone();
+            thread::spawn(move || {
+                if let Err(err) = events::flush(dbase.as_ref()) {
+                    error!("failed to save events: {}", err);
+                }
+            });
+        });
+        ctx.run_interval(Duration::from_millis(EVENTS_INTERVAL), |this, _ctx| {
+            trace!("expired events interval fired");
+            let dbase = this.dbase.clone();
+            thread::spawn(move || {
+                if let Err(err) = events::prune_expired(dbase.as_ref()) {
+                    error!("failed to remove expired events: {}", err);
+                }
+            });
+        });
+        ctx.run_interval(Duration::from_millis(TRASH_INTERVAL), |this, _ctx| {
+            trace!("trash interval fired");
+            let dbase = this.dbase.clone();
+            thread::spawn(move || {
+                if let Err(err) = trash::purge_expired(dbase.as_ref()) {
+                    error!("failed to purge expired datasets: {}", err);
+                }
+            });
+        });
+        ctx.run_interval(Duration::from_millis(NOTIFY_INTERVAL), |this, _ctx| {
+            trace!("notify interval fired");
+            let dbase = this.dbase.clone();
+            thread::spawn(move || {
+                if let Err(err) = notify::check_stale(dbase.as_ref()) {
+                    error!("failed to check for stale datasets: {}", err);
+                }
+                notify::send_held();
+                if let Err(err) = notify::send_digests(dbase.as_ref()) {
+                    error!("failed to send webhook digests: {}", err);
+                }
+                email::send_held();
+                if let Err(err) = email::send_summary(dbase.as_ref()) {
+                    error!("failed to send daily summary: {}", err);
+                }
+            });
+        });
     }
 
     fn stopping(&mut self, _ctx: &mut Context<Self>) -> Running {
@@ -307,12 +432,42 @@ impl Handler<StartBackup> for BackupRunner {
     }
 }
 
+///
+/// Start a backup for each dataset whose accumulated changes have exceeded
+/// the thresholds of its change trigger.
+///
+fn start_triggered_datasets(
+    dbase: &Arc<dyn RecordRepository>,
+    state: &Arc<dyn StateStore>,
+    addr: &Addr<BackupSupervisor>,
+) -> Result<(), Error> {
+    for set in dbase.get_datasets()? {
+        if set.change_trigger().is_none() || can_run(state, &set)?.is_none() {
+            continue;
+        }
+        // a problem with one dataset should not hold up the others
+        match trigger::should_trigger(&set, dbase) {
+            Ok(true) => {
+                info!("dataset {} changes exceeded trigger threshold", set.id);
+                addr.do_send(Start { dataset: set });
+            }
+            Ok(false) => (),
+            Err(err) => warn!("could not measure changes of dataset {}: {}", set.id, err),
+        }
+    }
+    Ok(())
+}
+
 ///
 /// Check if dataset can be backed up now (backup not already running).
 ///
 /// Returns Some(Schedule::Hourly) if okay to run, otherwise None.
 ///
 fn can_run(state: &Arc<dyn StateStore>, set: &Dataset) -> Result<Option<Schedule>, Error> {
+    if set.archived.is_some() {
+        debug!("dataset {} is archived", &set.id);
+        return Ok(None);
+    }
     let redux = state.get_state();
     let backup_state = redux.backups(&set.id);
     if let Some(backup) = backup_state {
@@ -339,30 +494,36 @@ fn should_run(
     state: &Arc<dyn StateStore>,
     set: &Dataset,
 ) -> Result<Option<Schedule>, Error> {
-    if !set.schedules.is_empty() {
+    // archived datasets keep their schedules in case they are unarchived
+    if !set.schedules.is_empty() && set.archived.is_none() {
         let latest_snapshot = dbase.get_latest_snapshot(&set.id)?;
+        // a backup that was paused after running out of time resumes when
+        // the schedule next comes due, as if it had finished when paused
         let end_time: Option<DateTime<Utc>> = if let Some(checksum) = latest_snapshot {
             let snapshot = dbase
                 .get_snapshot(&checksum)?
                 .ok_or_else(|| anyhow!(format!("snapshot {} missing from database", &checksum)))?;
-            snapshot.end_time
+            snapshot.end_time.or(snapshot.paused)
         } else {
             None
         };
         let redux = state.get_state();
         let backup_state = redux.backups(&set.id);
+        // an end time in the future means the clock has since moved backward
+        let now = Utc::now();
+        let end_time = end_time.map(|et| clock::trusted_time(et, now));
         for schedule in set.schedules.iter() {
             // consider if backup is overdue based on snapshot
             let mut maybe_run = if let Some(et) = end_time {
                 schedule.is_ready(et)
             } else {
-                schedule.within_range(Utc::now())
+                schedule.within_range(now)
             };
             // consider how the backup state may affect the decision
             if let Some(backup) = backup_state {
                 // ignore the error state, it does not override the schedule
                 if !backup.had_error() {
-                    if let Some(et) = backup.end_time() {
+                    if let Some(et) = backup.end_time().map(|et| clock::trusted_time(et, now)) {
                         // a backup ran but there were no changes found
                         if !schedule.is_ready(et) {
                             maybe_run = false;
@@ -386,9 +547,87 @@ fn should_run(
     Ok(None)
 }
 
+///
+/// Return the identifiers of the stores of the dataset that have reached
+/// their monthly transfer cap or their quota.
+///
+fn capped_stores(dbase: &Arc<dyn RecordRepository>, set: &Dataset) -> Result<Vec<String>, Error> {
+    let mut capped: Vec<String> = Vec::new();
+    for store_id in set.stores.iter() {
+        if let Some(store) = dbase.get_store(store_id)? {
+            if let Some(cap) = store.monthly_cap() {
+                let usage = dbase.get_bandwidth_usage(store_id)?;
+                if usage.total() >= cap {
+                    capped.push(store_id.to_owned());
+                    continue;
+                }
+            }
+            if let Some(quota) = store.quota() {
+                let usage = dbase.get_store_usage(store_id)?;
+                if quota.is_reached(&usage) {
+                    capped.push(store_id.to_owned());
+                }
+            }
+        }
+    }
+    Ok(capped)
+}
+
+///
+/// Record the stores that are left out of the backups of every dataset.
+///
+fn record_deferrals(
+    dbase: &Arc<dyn RecordRepository>,
+    state: &Arc<dyn StateStore>,
+) -> Result<(), Error> {
+    for dataset in dbase.get_datasets()? {
+        let capped = capped_stores(dbase, &dataset)?;
+        state.backup_event(BackupAction::Deferred(dataset.id.clone(), capped));
+    }
+    Ok(())
+}
+
+///
+/// Return the time at which the backup should stop, whichever is earlier of
+/// the end of the time range of the schedule and the maximum runtime of the
+/// dataset, if either is defined.
+///
+pub fn compute_stop_time(
+    dataset: &Dataset,
+    schedule: &Schedule,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let range_stop = schedule.stop_time(now);
+    let runtime_stop = dataset
+        .max_runtime(schedule)
+        .and_then(|d| chrono::TimeDelta::from_std(d).ok())
+        .map(|d| now + d);
+    match (range_stop, runtime_stop) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+///
+/// Record the time at which the incomplete backup of the dataset was paused,
+/// such that it resumes the next time the schedule comes due rather than
+/// right away.
+///
+fn mark_paused(dbase: &dyn RecordRepository, dataset_id: &str) -> Result<(), Error> {
+    if let Some(latest) = dbase.get_latest_snapshot(dataset_id)? {
+        if let Some(mut snapshot) = dbase.get_snapshot(&latest)? {
+            if snapshot.end_time.is_none() {
+                snapshot.paused = Some(Utc::now());
+                dbase.put_snapshot(&snapshot)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 ///
 /// Run the backup procedure for the named dataset. Takes the passphrase from
-/// the environment.
+/// the configured secrets provider, failing the backup if it is unavailable.
 ///
 fn run_dataset(
     dbase: Arc<dyn RecordRepository>,
@@ -397,15 +636,52 @@ fn run_dataset(
     schedule: Schedule,
     performer: Arc<dyn Performer>,
 ) {
-    let passphrase = crypto::get_passphrase();
+    // every log line of the backup will include the dataset identifier
+    let span = info_span!("backup", dataset = %dataset.id);
+    let _entered = span.enter();
     info!("dataset {} to be backed up", &dataset.id);
+    // held for the duration of the backup, whose packs must not be pruned
+    let Some(_guard) = maintenance::share_packs() else {
+        info!("dataset {} deferred while packs are pruned", &dataset.id);
+        return;
+    };
     let start_time = SystemTime::now();
-    let stop_time = schedule.stop_time(Utc::now());
+    let stop_time = compute_stop_time(&dataset, &schedule, Utc::now());
     // reset any error state in the backup
     state.backup_event(BackupAction::Restart(dataset.id.clone()));
+    // leave out those stores that have reached their monthly transfer cap or
+    // their quota, deferring the backup entirely if that includes every store
+    let mut dataset = dataset;
+    let deferred = match capped_stores(&dbase, &dataset) {
+        Ok(capped) => capped,
+        Err(err) => {
+            error!("could not check store transfer caps and quotas: {}", err);
+            vec![]
+        }
+    };
+    state.backup_event(BackupAction::Deferred(dataset.id.clone(), deferred.clone()));
+    if !deferred.is_empty() {
+        info!(
+            "dataset {} deferring stores {:?} due to transfer cap or quota",
+            &dataset.id, deferred
+        );
+        dataset.stores.retain(|s| !deferred.contains(s));
+        if dataset.stores.is_empty() {
+            state.backup_event(BackupAction::Pause(dataset.id.clone()));
+            return;
+        }
+    }
     let dataset_id = dataset.id.clone();
-    let request = Request::new(dataset, dbase, state.clone(), passphrase, stop_time);
-    match performer.backup(request) {
+    let replica_dbase = dbase.clone();
+    let events_dbase = dbase.clone();
+    events::record(Event::new(EventKind::BackupStarted, &dataset_id));
+    let healthcheck = dataset.healthcheck_url();
+    notify::healthcheck(healthcheck.as_deref(), Ping::Start);
+    let result = crypto::get_passphrase().and_then(|passphrase| {
+        let request = Request::new(dataset, dbase, state.clone(), passphrase, stop_time);
+        performer.backup(request)
+    });
+    match result {
         Ok(Some(checksum)) => {
             let end_time = SystemTime::now();
             let time_diff = end_time.duration_since(start_time);
@@ -415,32 +691,65 @@ fn run_dataset(
                 "dataset {} backup complete after {}",
                 &dataset_id, pretty_time
             );
+            events::record(
+                Event::new(EventKind::BackupFinished, &dataset_id)
+                    .detail("snapshot", checksum.to_string()),
+            );
+            notify::backup_finished(events_dbase.as_ref(), &dataset_id, Some(&checksum));
+            notify::healthcheck(healthcheck.as_deref(), Ping::Success);
+            if let Err(err) = replica::replicate(replica_dbase.as_ref(), &dataset_id) {
+                error!("could not replicate dataset {}: {}", &dataset_id, err);
+            }
+        }
+        Ok(None) => {
+            info!("no new snapshot required");
+            events::record(Event::new(EventKind::BackupFinished, &dataset_id));
+            notify::backup_finished(events_dbase.as_ref(), &dataset_id, None);
+            notify::healthcheck(healthcheck.as_deref(), Ping::Success);
         }
-        Ok(None) => info!("no new snapshot required"),
         Err(err) => match err.downcast::<OutOfTimeFailure>() {
             Ok(_) => {
                 info!("backup window has reached its end");
                 // put the backup in the paused state for the time being
                 state.backup_event(BackupAction::Pause(dataset_id.clone()));
+                if let Err(err) = mark_paused(events_dbase.as_ref(), &dataset_id) {
+                    error!("could not mark snapshot as paused: {}", err);
+                }
+                // the backup ran for as long as it was allowed and resumes in
+                // the next window, which is not a failure; a dataset that never
+                // finishes is reported by the stale backup webhooks instead
+                notify::healthcheck(healthcheck.as_deref(), Ping::Success);
             }
             Err(err) => {
                 // here `err` is the original error
                 error!("could not perform backup: {}", err);
+                events::record(
+                    Event::new(EventKind::BackupFailed, &dataset_id)
+                        .detail("error", err.to_string()),
+                );
+                notify::backup_failed(events_dbase.as_ref(), &dataset_id, &err.to_string());
+                notify::healthcheck(healthcheck.as_deref(), Ping::Failure);
                 // put the backup in the error state so we try again
                 state.backup_event(BackupAction::Error(dataset_id.clone(), err.to_string()));
             }
         },
     }
+    if let Err(err) = events::flush(events_dbase.as_ref()) {
+        error!("failed to save events: {}", err);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::domain::entities::schedule::{Schedule, TimeRange};
-    use crate::domain::entities::{Checksum, Snapshot};
+    use crate::domain::entities::{
+        BandwidthUsage, Checksum, Snapshot, Store, StoreType, StoreUsage,
+    };
     use crate::domain::managers::backup::MockPerformer;
     use crate::domain::managers::state::{StateStore, StateStoreImpl};
     use crate::domain::repositories::MockRecordRepository;
+    use std::collections::HashMap;
     use std::io;
     use std::path::Path;
 
@@ -477,6 +786,106 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_capped_stores() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_store("metered");
+        dataset.add_store("unmetered");
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|id| {
+            let mut properties: HashMap<String, String> = HashMap::new();
+            if id == "metered" {
+                properties.insert("monthly_cap".to_owned(), "1000".to_owned());
+            }
+            Ok(Some(Store {
+                id: id.to_owned(),
+                store_type: StoreType::LOCAL,
+                label: id.to_owned(),
+                properties,
+            }))
+        });
+        mock.expect_get_bandwidth_usage()
+            .withf(|id| id == "metered")
+            .returning(|_| {
+                let mut usage = BandwidthUsage::new("2024-05");
+                usage.uploaded = 800;
+                usage.downloaded = 200;
+                Ok(usage)
+            });
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let result = capped_stores(&repo, &dataset);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec!["metered".to_owned()]);
+    }
+
+    #[test]
+    fn test_capped_stores_quota() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_store("usbdisk");
+        dataset.add_store("roomy");
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|id| {
+            let mut properties: HashMap<String, String> = HashMap::new();
+            properties.insert("quota_objects".to_owned(), "100".to_owned());
+            Ok(Some(Store {
+                id: id.to_owned(),
+                store_type: StoreType::LOCAL,
+                label: id.to_owned(),
+                properties,
+            }))
+        });
+        mock.expect_get_store_usage().returning(|id| {
+            let objects = if id == "usbdisk" { 100 } else { 10 };
+            Ok(StoreUsage { objects, bytes: 0 })
+        });
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let result = capped_stores(&repo, &dataset);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec!["usbdisk".to_owned()]);
+    }
+
+    #[test]
+    fn test_record_deferrals() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_store("usbdisk");
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_store().returning(|id| {
+            let mut properties: HashMap<String, String> = HashMap::new();
+            properties.insert("quota_objects".to_owned(), "100".to_owned());
+            Ok(Some(Store {
+                id: id.to_owned(),
+                store_type: StoreType::LOCAL,
+                label: id.to_owned(),
+                properties,
+            }))
+        });
+        mock.expect_get_store_usage().returning(|_| {
+            Ok(StoreUsage {
+                objects: 100,
+                bytes: 0,
+            })
+        });
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        // act
+        let result = record_deferrals(&repo, &state);
+        // assert: the deferral is known before the dataset is backed up
+        assert!(result.is_ok());
+        let redux = state.get_state();
+        assert!(redux.backups(&dataset_id).is_none());
+        assert_eq!(redux.deferred_stores(&dataset_id), &["usbdisk".to_owned()]);
+    }
+
     #[test]
     fn test_can_run_empty_state() {
         // arrange
@@ -537,6 +946,36 @@ mod tests {
         assert!(result.unwrap().is_some());
     }
 
+    #[test]
+    fn test_can_run_archived() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.archived = Some(Utc::now());
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let result = can_run(&state, &dataset);
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_should_run_archived() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_schedule(Schedule::Daily(None));
+        dataset.archived = Some(Utc::now());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_latest_snapshot().never();
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let result = should_run(&repo, &state, &dataset);
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
     #[test]
     fn test_should_run_no_schedule() {
         // arrange
@@ -895,6 +1334,87 @@ mod tests {
         assert!(result.unwrap().is_none());
     }
 
+    #[test]
+    fn test_should_run_paused_runtime_exceeded() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_schedule(Schedule::Hourly);
+        let dataset_clone = dataset.clone();
+        let datasets = vec![dataset];
+        // build a "latest" snapshot that was paused recently, such that it
+        // should not resume until the schedule comes due again
+        let tree_sha = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut snapshot = Snapshot::new(None, tree_sha, Default::default());
+        snapshot.paused = Some(chrono::Utc::now() - chrono::Duration::minutes(10));
+        let snapshot_sha1 = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(snapshot_sha1.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        // act
+        let result = should_run(&repo, &state, &dataset_clone);
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_should_run_paused_now_due() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_schedule(Schedule::Hourly);
+        let dataset_clone = dataset.clone();
+        let datasets = vec![dataset];
+        // build a "latest" snapshot that was paused over an hour ago
+        let tree_sha = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut snapshot = Snapshot::new(None, tree_sha, Default::default());
+        snapshot.paused = Some(chrono::Utc::now() - chrono::Duration::minutes(70));
+        let snapshot_sha1 = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(snapshot_sha1.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        // act
+        let result = should_run(&repo, &state, &dataset_clone);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(Schedule::Hourly));
+    }
+
+    #[test]
+    fn test_compute_stop_time() {
+        let now = chrono::Utc::now();
+        let mut dataset = Dataset::new(Path::new("/some/path"));
+        dataset.add_schedule(Schedule::Hourly);
+        assert!(compute_stop_time(&dataset, &Schedule::Hourly, now).is_none());
+        dataset
+            .properties
+            .insert("max_runtime".to_owned(), "30".to_owned());
+        let actual = compute_stop_time(&dataset, &Schedule::Hourly, now);
+        assert_eq!(actual, Some(now + chrono::Duration::minutes(30)));
+        // the end of the time range comes first
+        let stop = now + chrono::Duration::minutes(10);
+        let range = TimeRange::new(
+            (now.hour() + 23) % 24,
+            now.minute(),
+            stop.hour(),
+            stop.minute(),
+        );
+        let schedule = Schedule::Daily(Some(range));
+        let actual = compute_stop_time(&dataset, &schedule, now).unwrap();
+        assert!(actual < now + chrono::Duration::minutes(11));
+    }
+
     #[test]
     fn test_should_run_time_range_had_error() {
         // arrange
@@ -957,6 +1477,8 @@ mod tests {
         mock.expect_get_latest_snapshot()
             .withf(move |id| id == dataset_id)
             .returning(|_| Ok(None));
+        mock.expect_put_event().returning(|_| Ok(()));
+        mock.expect_get_webhooks().returning(|| Ok(vec![]));
         //
         // The expectations here are being called on another thread, so any
         // failures there will go unnoticed, hence we call checkpoint() to make
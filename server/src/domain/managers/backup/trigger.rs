@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `trigger` module measures the changes made to a dataset since its most
+//! recent snapshot, to decide if a backup should start ahead of its schedule.
+
+use super::build_exclusions;
+use crate::domain::entities::{ChangeTrigger, Dataset};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use globset::GlobSet;
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+///
+/// Summary of the files that have changed within a dataset.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Number of files modified since the reference time.
+    pub files: u64,
+    /// Combined size in bytes of the modified files.
+    pub bytes: u64,
+    /// Modification time of the most recently changed file.
+    pub newest: Option<DateTime<Utc>>,
+}
+
+///
+/// Determine if the changes to the dataset warrant starting a backup now.
+///
+/// Returns `false` if the dataset does not define a change trigger, has not
+/// been backed up yet, has a backup in progress, or finished a backup within
+/// the cooldown period.
+///
+pub fn should_trigger(dataset: &Dataset, repo: &Arc<dyn RecordRepository>) -> Result<bool, Error> {
+    let trigger = match dataset.change_trigger() {
+        Some(trigger) => trigger,
+        None => return Ok(false),
+    };
+    // the first backup is left to the schedule or the user
+    let snapshot = match repo.get_latest_snapshot(&dataset.id)? {
+        Some(digest) => repo.get_snapshot(&digest)?,
+        None => None,
+    };
+    let Some(snapshot) = snapshot else {
+        return Ok(false);
+    };
+    let Some(end_time) = snapshot.end_time else {
+        return Ok(false);
+    };
+    let now = Utc::now();
+    if !cooled_down(&trigger, end_time, now) {
+        return Ok(false);
+    }
+    let changes = measure_changes(dataset, repo, snapshot.start_time.into())?;
+    debug!(
+        "trigger: dataset {} has {} changed files, {} bytes",
+        dataset.id, changes.files, changes.bytes
+    );
+    Ok(is_due(&trigger, &changes, now))
+}
+
+// Return true if the cooldown period since the previous backup has elapsed.
+fn cooled_down(trigger: &ChangeTrigger, end_time: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    let elapsed = (now - end_time).to_std().unwrap_or_default();
+    elapsed >= trigger.cooldown
+}
+
+// Return true if the changes exceed the thresholds and the dataset has been
+// quiet for long enough.
+fn is_due(trigger: &ChangeTrigger, changes: &Changes, now: DateTime<Utc>) -> bool {
+    if !trigger.exceeded(changes.files, changes.bytes) {
+        return false;
+    }
+    match changes.newest {
+        Some(newest) => (now - newest).to_std().unwrap_or_default() >= trigger.quiet,
+        None => true,
+    }
+}
+
+///
+/// Count the files in the dataset that were modified after the given time,
+/// honoring the same exclusions as the backup.
+///
+pub fn measure_changes(
+    dataset: &Dataset,
+    repo: &Arc<dyn RecordRepository>,
+    since: SystemTime,
+) -> Result<Changes, Error> {
+    let mut excludes = repo.get_excludes();
+    excludes.push(dataset.workspace.clone());
+    for exclusion in dataset.excludes.iter() {
+        excludes.push(PathBuf::from(exclusion));
+    }
+    let exclusions = build_exclusions(&dataset.basepath, &excludes);
+    let mut changes: Changes = Default::default();
+    scan_changes(&dataset.basepath, &exclusions, since, &mut changes)?;
+    Ok(changes)
+}
+
+// Walk the directory tree without following symbolic links, tallying the
+// regular files that were modified after the given time.
+fn scan_changes(
+    basepath: &Path,
+    exclusions: &GlobSet,
+    since: SystemTime,
+    changes: &mut Changes,
+) -> Result<(), Error> {
+    let mut pending: Vec<PathBuf> = vec![basepath.to_owned()];
+    while let Some(dirpath) = pending.pop() {
+        let readdir = match fs::read_dir(&dirpath) {
+            Ok(readdir) => readdir,
+            Err(err) => {
+                // the backup will report any problems with this directory
+                warn!("trigger: cannot read {}: {}", dirpath.display(), err);
+                continue;
+            }
+        };
+        for entry_result in readdir {
+            // files come and go while the scan is underway, and the backup
+            // will report any that it cannot read
+            let entry = match entry_result {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("trigger: cannot list {}: {}", dirpath.display(), err);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if exclusions.is_match(&path) {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("trigger: cannot stat {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            let file_type = metadata.file_type();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                let modified = match metadata.modified() {
+                    Ok(modified) => modified,
+                    Err(err) => {
+                        warn!("trigger: cannot get mtime of {}: {}", path.display(), err);
+                        continue;
+                    }
+                };
+                if modified > since {
+                    changes.files += 1;
+                    changes.bytes += metadata.len();
+                    let mtime = DateTime::<Utc>::from(modified);
+                    if changes.newest.map(|n| mtime > n).unwrap_or(true) {
+                        changes.newest = Some(mtime);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::TimeDelta;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn make_trigger(files: Option<u64>, bytes: Option<u64>) -> ChangeTrigger {
+        ChangeTrigger {
+            files,
+            bytes,
+            cooldown: Duration::from_secs(3_600),
+            quiet: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn test_cooled_down() {
+        let trigger = make_trigger(Some(1), None);
+        let now = Utc::now();
+        assert!(!cooled_down(&trigger, now - TimeDelta::minutes(59), now));
+        assert!(cooled_down(&trigger, now - TimeDelta::minutes(60), now));
+        // clock went backward, treat as not cooled down
+        assert!(!cooled_down(&trigger, now + TimeDelta::minutes(5), now));
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+        let trigger = make_trigger(Some(10), Some(1_000));
+        // below both thresholds
+        let changes = Changes {
+            files: 9,
+            bytes: 999,
+            newest: Some(now - TimeDelta::hours(1)),
+        };
+        assert!(!is_due(&trigger, &changes, now));
+        // file threshold met and quiet
+        let changes = Changes {
+            files: 10,
+            bytes: 0,
+            newest: Some(now - TimeDelta::minutes(5)),
+        };
+        assert!(is_due(&trigger, &changes, now));
+        // byte threshold met but still changing
+        let changes = Changes {
+            files: 1,
+            bytes: 1_000,
+            newest: Some(now - TimeDelta::minutes(4)),
+        };
+        assert!(!is_due(&trigger, &changes, now));
+    }
+
+    #[test]
+    fn test_measure_changes() -> Result<(), Error> {
+        // arrange
+        let outdir = tempdir()?;
+        let basepath = outdir.path();
+        fs::create_dir_all(basepath.join("subdir"))?;
+        fs::create_dir_all(basepath.join("ignored"))?;
+        fs::write(basepath.join("old.txt"), b"old")?;
+        let since = SystemTime::now() - Duration::from_secs(60);
+        let before = SystemTime::now() - Duration::from_secs(3_600);
+        fs::File::options()
+            .write(true)
+            .open(basepath.join("old.txt"))?
+            .set_modified(before)?;
+        fs::write(basepath.join("new.txt"), b"hello")?;
+        fs::write(basepath.join("subdir").join("nested.txt"), b"hello world")?;
+        fs::write(basepath.join("ignored").join("skip.txt"), b"skipped")?;
+        let mut dataset = Dataset::new(basepath);
+        dataset.excludes.push("ignored".into());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_excludes().returning(Vec::new);
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let changes = measure_changes(&dataset, &repo, since)?;
+        // assert
+        assert_eq!(changes.files, 2);
+        assert_eq!(changes.bytes, 16);
+        assert!(changes.newest.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_trigger_no_trigger() -> Result<(), Error> {
+        // arrange
+        let dataset = Dataset::new(Path::new("/some/path"));
+        let mock = MockRecordRepository::new();
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let result = should_trigger(&dataset, &repo)?;
+        // assert
+        assert!(!result);
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `catalog` module backs up the database by splitting the backup files
+//! into chunks and uploading them in packs, much like the files of a dataset,
+//! such that only the chunks that changed since the previous backup need to be
+//! uploaded. The catalog describes how to put the files back together, and is
+//! itself uploaded as a small encrypted manifest.
+//!
+//! Each pack store has a catalog of its own, since the datasets may send their
+//! packs to different stores, and the catalog of a store must only refer to
+//! packs that the store holds.
+
+use crate::domain::entities::{
+    Catalog, CatalogFile, CatalogPack, Checksum, Chunk, Pack, PackLocation,
+};
+use crate::domain::helpers::{self, pack::PackBuilder};
+use crate::domain::repositories::{PackRepository, RecordRepository};
+use anyhow::{anyhow, Error};
+use log::info;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Desired average size of the chunks of the database files, which are mostly
+// large and never modified once written.
+const CHUNK_SIZE: u32 = 1_048_576;
+
+// Preferred size of the packs of database chunks.
+const PACK_SIZE: u64 = 67_108_864;
+
+///
+/// Back up the database to the catalog bucket of each of the given pack
+/// stores, uploading only those chunks that are not already held by the packs
+/// of the previous catalog of that store. Returns the new catalogs, which are
+/// also saved to the database.
+///
+pub fn save(
+    dbase: &dyn RecordRepository,
+    store_ids: &[String],
+    computer_id: &str,
+    passphrase: &str,
+    workspace: &Path,
+) -> Result<Vec<Catalog>, Error> {
+    let backup_path = dbase.create_backup_dir()?;
+    // the files are split into chunks once, regardless of the number of stores
+    let mut files: Vec<(String, Vec<Chunk>)> = Vec::new();
+    for (path, name) in find_files(&backup_path)? {
+        let chunks = if fs::metadata(&path)?.len() == 0 {
+            // cannot memory map an empty file
+            vec![]
+        } else {
+            helpers::find_file_chunks(&path, CHUNK_SIZE)?
+        };
+        files.push((name, chunks));
+    }
+    let mut catalogs: Vec<Catalog> = Vec::new();
+    for store_id in store_ids.iter() {
+        // stores that have since been removed are passed over, as they are
+        // when loading the stores of a dataset
+        let Some(store) = dbase.get_store(store_id)? else {
+            continue;
+        };
+        let stores = dbase.build_pack_repo(&store)?;
+        let catalog = save_to_store(
+            dbase,
+            stores.as_ref(),
+            &store.id,
+            &files,
+            computer_id,
+            passphrase,
+            workspace,
+        )?;
+        catalogs.push(catalog);
+    }
+    Ok(catalogs)
+}
+
+// Upload the chunks that the given store does not already hold, along with a
+// new catalog, which is saved to the database as the catalog of that store.
+fn save_to_store(
+    dbase: &dyn RecordRepository,
+    stores: &dyn PackRepository,
+    store_id: &str,
+    files: &[(String, Vec<Chunk>)],
+    computer_id: &str,
+    passphrase: &str,
+    workspace: &Path,
+) -> Result<Catalog, Error> {
+    let previous = dbase.get_catalog(store_id)?.unwrap_or_default();
+    let mut chunk_packs = previous.chunk_packs();
+    let mut catalog = Catalog::new();
+    let mut builder = PackBuilder::new(PACK_SIZE).password(passphrase);
+    let mut pending: Vec<Checksum> = Vec::new();
+    let mut uploaded: Vec<CatalogPack> = Vec::new();
+    for (name, chunks) in files.iter() {
+        let mut file = CatalogFile {
+            path: name.to_owned(),
+            chunks: vec![],
+        };
+        for chunk in chunks.iter() {
+            file.chunks.push(chunk.digest.clone());
+            if chunk_packs.contains_key(&chunk.digest) || pending.contains(&chunk.digest) {
+                continue;
+            }
+            if !builder.is_ready() {
+                let (_outfile, outpath) = tempfile::Builder::new()
+                    .suffix(".pack")
+                    .tempfile_in(workspace)?
+                    .keep()?;
+                builder.initialize(&outpath)?;
+            }
+            pending.push(chunk.digest.clone());
+            if builder.add_chunk(chunk)? {
+                let pack = upload_pack(dbase, stores, computer_id, &mut builder, &mut pending)?;
+                for chunk in pack.chunks.iter() {
+                    chunk_packs.insert(chunk.clone(), pack.digest.clone());
+                }
+                uploaded.push(pack);
+            }
+        }
+        catalog.files.push(file);
+    }
+    if builder.is_ready() {
+        let pack = upload_pack(dbase, stores, computer_id, &mut builder, &mut pending)?;
+        uploaded.push(pack);
+    }
+    // carry over the earlier packs that still hold chunks of the database
+    let referenced: HashSet<&Checksum> = catalog
+        .files
+        .iter()
+        .flat_map(|f| f.chunks.iter())
+        .filter_map(|c| chunk_packs.get(c))
+        .collect();
+    for pack in previous.packs.into_iter() {
+        if referenced.contains(&pack.digest) {
+            catalog.packs.push(pack);
+        }
+    }
+    info!(
+        "database backup to store {} reused {} packs and uploaded {} new packs",
+        store_id,
+        catalog.packs.len(),
+        uploaded.len()
+    );
+    catalog.packs.append(&mut uploaded);
+    let manifest = dbase.pack_catalog(&catalog, passphrase)?;
+    let coords = stores.store_catalog(computer_id, &manifest)?;
+    let digest = Checksum::blake3_from_file(&manifest)?;
+    dbase.insert_database(&Pack::new(digest, coords))?;
+    dbase.put_catalog(store_id, &catalog)?;
+    Ok(catalog)
+}
+
+// Finish the pack being built and upload it to the catalog bucket, recording
+// it along with the database archives so that pruning will leave it be.
+fn upload_pack(
+    dbase: &dyn RecordRepository,
+    stores: &dyn PackRepository,
+    computer_id: &str,
+    builder: &mut PackBuilder,
+    pending: &mut Vec<Checksum>,
+) -> Result<CatalogPack, Error> {
+    let packfile = builder.finalize()?;
+    let digest = Checksum::blake3_from_file(&packfile)?;
+    let locations = stores.store_catalog_pack(computer_id, &packfile, &digest.to_string())?;
+    fs::remove_file(&packfile)?;
+    dbase.insert_database(&Pack::new(digest.clone(), locations.clone()))?;
+    Ok(CatalogPack {
+        digest,
+        locations,
+        chunks: std::mem::take(pending),
+    })
+}
+
+// Find all of the regular files within the given directory, returning each
+// path along with its name relative to the directory, sorted by name.
+fn find_files(basepath: &Path) -> Result<Vec<(PathBuf, String)>, Error> {
+    let mut results: Vec<(PathBuf, String)> = Vec::new();
+    let mut dirs: Vec<PathBuf> = vec![basepath.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry_result in fs::read_dir(&dir)? {
+            let entry = entry_result?;
+            let path = entry.path();
+            // DirEntry.metadata() does not follow symlinks and that is good
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else if metadata.is_file() {
+                let relative = path.strip_prefix(basepath)?;
+                let names: Vec<String> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                results.push((path, names.join("/")));
+            }
+        }
+    }
+    results.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(results)
+}
+
+///
+/// Restore the database from the most recent catalog found in the given pack
+/// store, which must be the only store in the pack repository. Returns `false`
+/// if the store does not have any catalog, in which case the database must be
+/// restored from one of the database archives instead.
+///
+pub fn restore(
+    dbase: &dyn RecordRepository,
+    stores: &dyn PackRepository,
+    store_id: &str,
+    computer_id: &str,
+    passphrase: &str,
+) -> Result<bool, Error> {
+    let manifest = tempfile::NamedTempFile::new()?.into_temp_path();
+    if !stores.retrieve_latest_catalog(computer_id, &manifest)? {
+        return Ok(false);
+    }
+    let catalog = dbase.unpack_catalog(&manifest, passphrase)?;
+    info!(
+        "retrieving {} packs of the database catalog from {}",
+        catalog.packs.len(),
+        catalog.created
+    );
+    let workdir = tempfile::tempdir()?;
+    let chunks_path = workdir.path().join("chunks");
+    let packfile = workdir.path().join("catalog.pack");
+    for pack in catalog.packs.iter() {
+        // the store may have been defined anew since the catalog was saved
+        let locations: Vec<PackLocation> = pack
+            .locations
+            .iter()
+            .map(|l| PackLocation::new(store_id, &l.bucket, &l.object))
+            .collect();
+        stores.retrieve_pack(&locations, &pack.digest, &packfile)?;
+        helpers::pack::extract_pack(&packfile, &chunks_path, Some(passphrase))?;
+        fs::remove_file(&packfile)?;
+    }
+    let backup_path = workdir.path().join("backup");
+    assemble_files(&catalog, &chunks_path, &backup_path)?;
+    dbase.restore_from_dir(&backup_path)?;
+    // the restored database has its own, older, catalog
+    dbase.put_catalog(store_id, &catalog)?;
+    Ok(true)
+}
+
+// Put the files of the database backup back together from the chunks found in
+// the given directory, which are named by their digest.
+fn assemble_files(catalog: &Catalog, chunks_path: &Path, outdir: &Path) -> Result<(), Error> {
+    for file in catalog.files.iter() {
+        let mut outpath = outdir.to_path_buf();
+        for name in file.path.split('/') {
+            outpath.push(name);
+        }
+        if let Some(parent) = outpath.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut output = fs::File::create(&outpath)?;
+        for chunk in file.chunks.iter() {
+            let chunk_path = chunks_path.join(chunk.to_string());
+            let content = fs::read(&chunk_path)
+                .map_err(|err| anyhow!("missing chunk {} for {}: {}", chunk, file.path, err))?;
+            output.write_all(&content)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Store, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    // Populate the directory with files that resemble a database backup, with
+    // one large file made of several chunks.
+    fn make_backup(basepath: &Path) -> Result<(), Error> {
+        fs::create_dir_all(basepath.join("shared"))?;
+        fs::create_dir_all(basepath.join("meta"))?;
+        let mut content: Vec<u8> = Vec::new();
+        let mut state: u32 = 0x1234_5678;
+        for _ in 0..3_000_000 {
+            // simple xorshift to produce content that chunks well
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            content.push((state & 0xff) as u8);
+        }
+        fs::write(basepath.join("shared").join("000009.sst"), content)?;
+        fs::write(basepath.join("meta").join("1"), "backup metadata")?;
+        fs::write(basepath.join("LOCK"), "")?;
+        Ok(())
+    }
+
+    // Build a mock pack repository that keeps the uploaded packs and manifest
+    // in the given directory.
+    fn make_stores(remote: PathBuf, store_id: &str) -> MockPackRepository {
+        let mut stores = MockPackRepository::new();
+        let remote1 = remote.clone();
+        let store_id1 = store_id.to_owned();
+        stores
+            .expect_store_catalog_pack()
+            .returning(move |_, packfile, object| {
+                fs::copy(packfile, remote1.join(object))?;
+                Ok(vec![PackLocation::new(
+                    &store_id1,
+                    "cafebabecatalog",
+                    object,
+                )])
+            });
+        let remote2 = remote.clone();
+        let store_id2 = store_id.to_owned();
+        stores.expect_store_catalog().returning(move |_, infile| {
+            fs::copy(infile, remote2.join("manifest"))?;
+            Ok(vec![PackLocation::new(
+                &store_id2,
+                "cafebabecatalog",
+                "manifest",
+            )])
+        });
+        let remote3 = remote.clone();
+        stores
+            .expect_retrieve_latest_catalog()
+            .returning(move |_, outfile| {
+                fs::copy(remote3.join("manifest"), outfile)?;
+                Ok(true)
+            });
+        stores
+            .expect_retrieve_pack()
+            .returning(move |locations, _, outfile| {
+                assert_eq!(locations[0].store, "store2");
+                fs::copy(remote.join(&locations[0].object), outfile)?;
+                Ok(())
+            });
+        stores
+    }
+
+    #[test]
+    fn test_find_files() -> Result<(), Error> {
+        let outdir = tempdir()?;
+        make_backup(outdir.path())?;
+        let files = find_files(outdir.path())?;
+        let names: Vec<&str> = files.iter().map(|f| f.1.as_str()).collect();
+        assert_eq!(names, vec!["LOCK", "meta/1", "shared/000009.sst"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_restore() -> Result<(), Error> {
+        // arrange
+        let backup_dir = tempdir()?;
+        make_backup(backup_dir.path())?;
+        let remote_dir = tempdir()?;
+        let workspace = tempdir()?;
+        let restored_dir = tempdir()?;
+        let saved: Arc<Mutex<HashMap<String, Catalog>>> = Arc::new(Mutex::new(HashMap::new()));
+        let mut dbase = MockRecordRepository::new();
+        let backup_path = backup_dir.path().to_path_buf();
+        dbase
+            .expect_create_backup_dir()
+            .returning(move || Ok(backup_path.clone()));
+        dbase.expect_get_store().returning(|id| {
+            Ok(Some(Store {
+                id: id.to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "temporary".to_owned(),
+                properties: HashMap::new(),
+            }))
+        });
+        let remote_path = remote_dir.path().to_path_buf();
+        dbase
+            .expect_build_pack_repo()
+            .returning(move |store| Ok(Box::new(make_stores(remote_path.clone(), &store.id))));
+        let saved1 = saved.clone();
+        dbase
+            .expect_get_catalog()
+            .returning(move |id| Ok(saved1.lock().unwrap().get(id).cloned()));
+        let saved2 = saved.clone();
+        dbase.expect_put_catalog().returning(move |id, catalog| {
+            saved2
+                .lock()
+                .unwrap()
+                .insert(id.to_owned(), catalog.clone());
+            Ok(())
+        });
+        // the catalog need not be encrypted for the purpose of testing
+        let manifest = Arc::new(Mutex::new(None));
+        let manifest1 = manifest.clone();
+        dbase.expect_pack_catalog().returning(move |catalog, _| {
+            *manifest1.lock().unwrap() = Some(catalog.clone());
+            let outfile = tempfile::NamedTempFile::new()?;
+            fs::write(outfile.path(), catalog.created.to_rfc3339())?;
+            Ok(outfile.into_temp_path())
+        });
+        dbase
+            .expect_unpack_catalog()
+            .returning(move |_, _| Ok(manifest.lock().unwrap().clone().unwrap()));
+        let uploads = Arc::new(Mutex::new(0));
+        let uploads1 = uploads.clone();
+        dbase.expect_insert_database().returning(move |_| {
+            *uploads1.lock().unwrap() += 1;
+            Ok(())
+        });
+        let restored_path = restored_dir.path().join("backup");
+        let restored_path1 = restored_path.clone();
+        dbase.expect_restore_from_dir().returning(move |path| {
+            for (file, name) in find_files(path)? {
+                let outpath = restored_path1.join(name);
+                fs::create_dir_all(outpath.parent().unwrap())?;
+                fs::copy(file, outpath)?;
+            }
+            Ok(())
+        });
+        let stores = make_stores(remote_dir.path().to_path_buf(), "store1");
+        let store1 = vec!["store1".to_owned()];
+
+        // act (first backup uploads every chunk)
+        let first = save(
+            &dbase,
+            &store1,
+            "cafebabe",
+            "keyboard cat",
+            workspace.path(),
+        )?;
+        // assert
+        assert_eq!(first.len(), 1);
+        let first = &first[0];
+        assert_eq!(first.files.len(), 3);
+        assert!(first.files[0].chunks.is_empty());
+        assert!(first.files[2].chunks.len() > 1);
+        assert_eq!(first.packs.len(), 1);
+        // one pack and one manifest
+        assert_eq!(*uploads.lock().unwrap(), 2);
+
+        // act (second backup has only one new small file)
+        fs::write(backup_dir.path().join("meta").join("2"), "more metadata")?;
+        let second = save(
+            &dbase,
+            &store1,
+            "cafebabe",
+            "keyboard cat",
+            workspace.path(),
+        )?;
+        // assert
+        let second = &second[0];
+        assert_eq!(second.files.len(), 4);
+        assert_eq!(second.packs.len(), 2);
+        assert_eq!(second.packs[0], first.packs[0]);
+        assert_eq!(second.packs[1].chunks.len(), 1);
+        assert_eq!(*uploads.lock().unwrap(), 4);
+
+        // act (another store has none of the chunks)
+        let both = vec!["store1".to_owned(), "store2".to_owned()];
+        let third = save(&dbase, &both, "cafebabe", "keyboard cat", workspace.path())?;
+        // assert
+        assert_eq!(third.len(), 2);
+        assert_eq!(third[0].packs, second.packs);
+        assert_eq!(third[1].packs.len(), 1);
+        assert_eq!(third[1].packs[0].locations[0].store, "store2");
+        // one manifest for the first store, one pack and manifest for the other
+        assert_eq!(*uploads.lock().unwrap(), 7);
+
+        // act (restore from the latest catalog)
+        let result = restore(&dbase, &stores, "store2", "cafebabe", "keyboard cat")?;
+        // assert
+        assert!(result);
+        for name in ["LOCK", "meta/1", "meta/2", "shared/000009.sst"] {
+            let expected = fs::read(backup_dir.path().join(name))?;
+            let actual = fs::read(restored_path.join(name))?;
+            assert_eq!(expected, actual, "{} differs", name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_no_catalog() -> Result<(), Error> {
+        // arrange
+        let dbase = MockRecordRepository::new();
+        let mut stores = MockPackRepository::new();
+        stores
+            .expect_retrieve_latest_catalog()
+            .returning(|_, _| Ok(false));
+        // act
+        let result = restore(&dbase, &stores, "store1", "cafebabe", "keyboard cat")?;
+        // assert
+        assert!(!result);
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `checkpoint` module records the progress of pack uploads in the
+//! database, such that a store can resume an upload that was interrupted by a
+//! crash or network outage rather than sending the entire pack again.
+//!
+//! Each store decides what constitutes its progress: the multipart upload ID
+//! and entity tags of the parts for S3, the list of uncommitted blocks for
+//! Azure, and the resumable session URI for Google.
+//!
+//! A checkpoint that is too old to resume is still offered to the store, which
+//! decides whether it must abort the earlier upload before starting another.
+//! Those left behind by abandoned uploads are removed by the backup driver.
+
+use crate::domain::entities::PackLocation;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use std::fmt;
+use std::sync::Arc;
+use store_core::{Checkpoint, CheckpointStore, Coordinates};
+
+///
+/// Saves the upload checkpoints of the pack stores in the database.
+///
+pub struct TransferCheckpoints {
+    dbase: Arc<dyn RecordRepository>,
+}
+
+impl TransferCheckpoints {
+    pub fn new(dbase: Arc<dyn RecordRepository>) -> Self {
+        Self { dbase }
+    }
+}
+
+impl fmt::Debug for TransferCheckpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TransferCheckpoints")
+    }
+}
+
+impl CheckpointStore for TransferCheckpoints {
+    fn get_checkpoint(&self, location: &Coordinates) -> Result<Option<Checkpoint>, Error> {
+        let location = PackLocation::from(location.to_owned());
+        self.dbase.get_checkpoint(&location)
+    }
+
+    fn put_checkpoint(&self, location: &Coordinates, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let location = PackLocation::from(location.to_owned());
+        self.dbase.put_checkpoint(&location, checkpoint)
+    }
+
+    fn delete_checkpoint(&self, location: &Coordinates) -> Result<(), Error> {
+        let location = PackLocation::from(location.to_owned());
+        self.dbase.delete_checkpoint(&location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::repositories::MockRecordRepository;
+    use store_core::CHECKPOINT_MAX_AGE_SECS;
+
+    #[test]
+    fn test_get_checkpoint_fresh() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_checkpoint()
+            .returning(|_| Ok(Some(Checkpoint::new("3129-cafebabe", "upload-1"))));
+        mock.expect_delete_checkpoint().never();
+        // act
+        let checkpoints = TransferCheckpoints::new(Arc::new(mock));
+        let coords = Coordinates::new("store1", "bucket1", "object1");
+        let result = checkpoints.get_checkpoint(&coords);
+        // assert
+        assert!(result.is_ok());
+        let option = result.unwrap();
+        assert!(option.is_some());
+        assert_eq!(option.unwrap().session, "upload-1");
+    }
+
+    #[test]
+    fn test_get_checkpoint_stale() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_checkpoint().returning(|_| {
+            let mut checkpoint = Checkpoint::new("3129-cafebabe", "upload-1");
+            checkpoint.updated -= CHECKPOINT_MAX_AGE_SECS + 60;
+            Ok(Some(checkpoint))
+        });
+        mock.expect_delete_checkpoint().never();
+        // act
+        let checkpoints = TransferCheckpoints::new(Arc::new(mock));
+        let coords = Coordinates::new("store1", "bucket1", "object1");
+        let result = checkpoints.get_checkpoint(&coords);
+        // assert
+        assert!(result.is_ok());
+        let option = result.unwrap();
+        // the store must see the stale checkpoint in order to abort the upload
+        assert!(option.is_some());
+        assert!(option.unwrap().is_stale());
+    }
+}
@@ -0,0 +1,213 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `clock` module detects jumps in the wall clock, such as those caused by
+//! NTP corrections, manual changes, or the system resuming from sleep, by
+//! comparing the elapsed wall-clock time with that of the monotonic clock.
+//!
+//! Schedules are evaluated in UTC, so daylight saving transitions do not move
+//! the clock as far as the scheduler is concerned.
+
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Number of adjustments retained for later retrieval.
+const MAX_ADJUSTMENTS: usize = 32;
+
+// Difference in seconds between the wall and monotonic clocks that is
+// considered to be a jump rather than ordinary drift or timer latency.
+const TOLERANCE_SECS: i64 = 60;
+
+lazy_static! {
+    // Most recent clock adjustments, oldest first.
+    static ref ADJUSTMENTS: Mutex<VecDeque<ClockAdjustment>> = Mutex::new(VecDeque::new());
+}
+
+///
+/// Describes a jump in the wall clock, or a correction the scheduler applied
+/// to a recorded time that could not be trusted.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClockAdjustment {
+    /// Wall-clock time at which the adjustment was made.
+    pub detected: DateTime<Utc>,
+    /// Number of seconds the clock moved, negative if it moved backward.
+    pub offset: i64,
+    /// Explanation of what was observed and how it was handled.
+    pub description: String,
+    // Recorded time that was corrected, if this adjustment is a correction.
+    reference: Option<DateTime<Utc>>,
+}
+
+impl ClockAdjustment {
+    fn new(detected: DateTime<Utc>, offset: i64, description: String) -> Self {
+        Self {
+            detected,
+            offset,
+            description,
+            reference: None,
+        }
+    }
+}
+
+///
+/// Retain the given adjustment and describe it in the log.
+///
+pub fn record(adjustment: ClockAdjustment) {
+    warn!("clock: {}", adjustment.description);
+    let mut adjustments = ADJUSTMENTS.lock().unwrap();
+    if adjustments.len() >= MAX_ADJUSTMENTS {
+        adjustments.pop_front();
+    }
+    adjustments.push_back(adjustment);
+}
+
+///
+/// Return the adjustments made since the application started, oldest first.
+///
+pub fn recent() -> Vec<ClockAdjustment> {
+    let adjustments = ADJUSTMENTS.lock().unwrap();
+    adjustments.iter().cloned().collect()
+}
+
+///
+/// Return the given time if it is not in the future, otherwise return `now`.
+///
+/// A recorded time that lies in the future means the clock has since moved
+/// backward. Taking it at face value would delay the next backup until the
+/// clock caught up again, so the current time is used instead.
+///
+pub fn trusted_time(then: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+    let ahead = (then - now).num_seconds();
+    if ahead > TOLERANCE_SECS {
+        let description = format!(
+            "recorded time {} is {} seconds in the future, using current time",
+            then.to_rfc3339(),
+            ahead
+        );
+        // avoid recording the same correction every time the schedule is
+        // checked; the offset changes with each check but the time does not
+        let exists = ADJUSTMENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|a| a.reference == Some(then));
+        if !exists {
+            let mut adjustment = ClockAdjustment::new(now, -ahead, description);
+            adjustment.reference = Some(then);
+            record(adjustment);
+        }
+        now
+    } else {
+        then
+    }
+}
+
+///
+/// Watches for the wall clock moving at a different rate than the monotonic
+/// clock between successive checks.
+///
+#[derive(Default)]
+pub struct ClockWatch {
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl ClockWatch {
+    /// Construct a new watch that will take its baseline at the first check.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Compare the clocks with the previous check, returning a description of
+    /// the jump if the wall clock moved unexpectedly.
+    pub fn check(&mut self) -> Option<ClockAdjustment> {
+        self.check_at(Instant::now(), Utc::now())
+    }
+
+    fn check_at(&mut self, mono: Instant, wall: DateTime<Utc>) -> Option<ClockAdjustment> {
+        let previous = self.last.replace((mono, wall));
+        let (last_mono, last_wall) = previous?;
+        let mono_secs = mono.saturating_duration_since(last_mono).as_secs() as i64;
+        let wall_secs = (wall - last_wall).num_seconds();
+        let offset = wall_secs - mono_secs;
+        if offset > TOLERANCE_SECS {
+            let description = format!(
+                "wall clock advanced {} seconds more than expected (clock change or resume from sleep)",
+                offset
+            );
+            Some(ClockAdjustment::new(wall, offset, description))
+        } else if offset < -TOLERANCE_SECS {
+            let description = format!(
+                "wall clock moved backward by {} seconds, schedules use the current time",
+                -offset
+            );
+            Some(ClockAdjustment::new(wall, offset, description))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clock_watch_steady() {
+        let mut watch = ClockWatch::new();
+        let mono = Instant::now();
+        let wall = Utc::now();
+        assert!(watch.check_at(mono, wall).is_none());
+        let mono = mono + Duration::from_secs(300);
+        let wall = wall + TimeDelta::seconds(310);
+        assert!(watch.check_at(mono, wall).is_none());
+    }
+
+    #[test]
+    fn test_clock_watch_jumps() {
+        let mut watch = ClockWatch::new();
+        let mono = Instant::now();
+        let wall = Utc::now();
+        assert!(watch.check_at(mono, wall).is_none());
+        // forward jump, as after resuming from sleep
+        let mono = mono + Duration::from_secs(300);
+        let wall = wall + TimeDelta::hours(8);
+        let adjustment = watch.check_at(mono, wall).unwrap();
+        assert_eq!(adjustment.offset, 28_500);
+        // backward jump, as after an NTP correction
+        let mono = mono + Duration::from_secs(300);
+        let wall = wall - TimeDelta::hours(1) + TimeDelta::seconds(300);
+        let adjustment = watch.check_at(mono, wall).unwrap();
+        assert_eq!(adjustment.offset, -3_600);
+    }
+
+    #[test]
+    fn test_trusted_time() {
+        let now = Utc::now();
+        let past = now - TimeDelta::hours(2);
+        assert_eq!(trusted_time(past, now), past);
+        let slight = now + TimeDelta::seconds(5);
+        assert_eq!(trusted_time(slight, now), slight);
+        let future = now + TimeDelta::days(3);
+        assert_eq!(trusted_time(future, now), now);
+        let recorded = recent();
+        let matching: Vec<&ClockAdjustment> =
+            recorded.iter().filter(|a| a.offset == -259_200).collect();
+        assert!(!matching.is_empty());
+        // checking the same time again later is not recorded again
+        let later = now + TimeDelta::minutes(10);
+        assert_eq!(trusted_time(future, later), later);
+        let count = recent()
+            .iter()
+            .filter(|a| a.reference == Some(future))
+            .count();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,305 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `critical` module keeps a warm copy of the packs needed to restore the
+//! critical paths of a dataset, as given by the `critical_paths` property, in
+//! a fast store, such that those files can be restored quickly even if the
+//! other stores are slow or unreachable.
+//!
+//! The fast store is the one named by the `critical_store` property of the
+//! dataset, or else the first local store. The copies are recorded as another
+//! location of each pack, and since retrieval prefers local stores, restores
+//! will use them automatically.
+
+use crate::domain::entities::{
+    Checksum, Dataset, Message, MessageCode, Pack, Store, StoreType, TreeReference,
+};
+use crate::domain::managers::progress::{OperationKind, Progress, Reporter};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{info, warn};
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+
+///
+/// Copy the packs needed to restore the critical paths of the dataset, as of
+/// the given snapshot, to the fast store, if they are not there already.
+/// Returns the number of packs that were copied.
+///
+pub fn warm_critical_packs(
+    dataset: &Dataset,
+    repo: &dyn RecordRepository,
+    snapshot: &Checksum,
+) -> Result<usize, Error> {
+    let patterns = dataset.critical_paths();
+    if patterns.is_empty() {
+        return Ok(0);
+    }
+    let Some(target) = find_target_store(dataset, repo)? else {
+        warn!(
+            "dataset {} has critical paths but no local or critical store",
+            dataset.id
+        );
+        return Ok(0);
+    };
+    let matcher = build_matcher(&patterns)?;
+    let files = find_critical_files(repo, snapshot, &matcher)?;
+    let mut needed: Vec<Pack> = Vec::new();
+    for digest in find_packs(repo, &files)? {
+        match repo.get_pack(&digest)? {
+            Some(pack) if !pack.locations.iter().any(|l| l.store == target.id) => needed.push(pack),
+            Some(_) => (),
+            None => warn!("missing pack record {} for critical paths", digest),
+        }
+    }
+    if needed.is_empty() {
+        return Ok(0);
+    }
+    let progress = Reporter::new(OperationKind::Maintenance, "critical packs");
+    progress.begin(Some(needed.len() as u64));
+    let result = copy_packs(dataset, repo, &target, needed, &progress);
+    progress.finish(result.as_ref().err().map(|e| e.to_string()));
+    let count = result?;
+    info!(
+        "copied {} critical packs of dataset {} to store {}",
+        count, dataset.id, target.id
+    );
+    Ok(count)
+}
+
+// Find the store named by the dataset, or else the first local store.
+fn find_target_store(
+    dataset: &Dataset,
+    repo: &dyn RecordRepository,
+) -> Result<Option<Store>, Error> {
+    if let Some(store_id) = dataset.properties.get("critical_store") {
+        let store = repo
+            .get_store(store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &store_id))?;
+        return Ok(Some(store));
+    }
+    let stores = repo.get_stores()?;
+    Ok(stores
+        .into_iter()
+        .find(|s| s.store_type == StoreType::LOCAL))
+}
+
+///
+/// Build the matcher for the critical path patterns.
+///
+pub fn build_matcher(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        builder.add(Glob::new(pattern.trim_matches('/'))?);
+    }
+    Ok(builder.build()?)
+}
+
+// Walk the tree of the snapshot, collecting the digests of the files that
+// match the critical paths, including everything within a matching directory.
+fn find_critical_files(
+    repo: &dyn RecordRepository,
+    snapshot: &Checksum,
+    matcher: &GlobSet,
+) -> Result<HashSet<Checksum>, Error> {
+    let snapshot = repo
+        .get_snapshot(snapshot)?
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &snapshot))?;
+    let mut files: HashSet<Checksum> = HashSet::new();
+    let mut pending: VecDeque<(Checksum, PathBuf, bool)> = VecDeque::new();
+    pending.push_back((snapshot.tree, PathBuf::new(), false));
+    while let Some((digest, prefix, included)) = pending.pop_front() {
+        let tree = repo
+            .get_tree(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        for entry in tree.entries.into_iter() {
+            let path = prefix.join(&entry.name);
+            let matched = included || matcher.is_match(&path);
+            match entry.reference {
+                TreeReference::TREE(child) => pending.push_back((child, path, matched)),
+                TreeReference::FILE(file) if matched => {
+                    files.insert(file);
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Find the digests of the packs that hold the content of the given files.
+fn find_packs(
+    repo: &dyn RecordRepository,
+    files: &HashSet<Checksum>,
+) -> Result<HashSet<Checksum>, Error> {
+    let mut packs: HashSet<Checksum> = HashSet::new();
+    for digest in files.iter() {
+        let Some(file) = repo.get_file(digest)? else {
+            // not yet backed up, or went missing during the backup
+            continue;
+        };
+        if file.chunks.len() == 1 {
+            // a single chunk is the digest of the pack itself
+            packs.insert(file.chunks[0].1.clone());
+        } else {
+            for (_, chunk_digest) in file.chunks.iter() {
+                let chunk = repo.get_chunk(chunk_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingChunk).with("digest", &chunk_digest)
+                })?;
+                if let Some(packfile) = chunk.packfile {
+                    packs.insert(packfile);
+                }
+            }
+        }
+    }
+    Ok(packs)
+}
+
+// Copy the packs from the stores of the dataset to the target store, adding
+// the new location to each pack record. Returns the number of packs copied.
+fn copy_packs(
+    dataset: &Dataset,
+    repo: &dyn RecordRepository,
+    target: &Store,
+    packs: Vec<Pack>,
+    progress: &dyn Progress,
+) -> Result<usize, Error> {
+    let source_repo = repo.load_dataset_stores(dataset)?;
+    let target_repo = repo.build_pack_repo(target)?;
+    let mut count = 0;
+    for mut pack in packs.into_iter() {
+        let pack_file = tempfile::Builder::new().suffix(".pack").tempfile()?;
+        let result = source_repo.retrieve_pack(&pack.locations, &pack.digest, pack_file.path());
+        if let Err(err) = result {
+            // keep going, the other packs may still be available
+            warn!("unable to retrieve critical pack {}: {}", pack.digest, err);
+            continue;
+        }
+        let length = pack_file.as_file().metadata().map(|m| m.len()).unwrap_or(0);
+        // use the same bucket and object as the other stores, if possible
+        let existing = &pack.locations[0];
+        let locations =
+            target_repo.store_pack(pack_file.path(), &existing.bucket, &existing.object)?;
+        pack.locations
+            .extend(locations.into_iter().filter(|l| l.store == target.id));
+        repo.put_pack(&pack)?;
+        progress.advance(1, length);
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{File, FileCounts, PackLocation, Snapshot, Tree, TreeEntry};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_critical_paths_none() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mock = MockRecordRepository::new();
+        let snapshot = Checksum::SHA1("cafebabe".into());
+        // act
+        let result = warm_critical_packs(&dataset, &mock, &snapshot);
+        // assert
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_warm_critical_packs() {
+        // arrange
+        let subtree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(Checksum::BLAKE3("file1".into())),
+            )],
+            1,
+        );
+        let root = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("file2".into())),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures"),
+                    TreeReference::TREE(subtree.digest.clone()),
+                ),
+            ],
+            2,
+        );
+        let snapshot = Snapshot::new(None, root.digest.clone(), FileCounts::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let trees: HashMap<Checksum, Tree> = vec![root, subtree]
+            .into_iter()
+            .map(|t| (t.digest.clone(), t))
+            .collect();
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.add_store("cloud1");
+        dataset
+            .properties
+            .insert("critical_paths".to_owned(), "fixtures".to_owned());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_stores().returning(|| {
+            Ok(vec![Store {
+                id: "local1".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "external disk".to_owned(),
+                properties: HashMap::new(),
+            }])
+        });
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        // only the file within the critical directory is considered
+        mock.expect_get_file()
+            .withf(|digest| digest == &Checksum::BLAKE3("file1".into()))
+            .times(1)
+            .returning(|digest| {
+                let pack = Checksum::BLAKE3("pack1".into());
+                Ok(Some(File::new(digest.clone(), 3129, vec![(0, pack)])))
+            });
+        mock.expect_get_pack().times(1).returning(|digest| {
+            let location = PackLocation::new("cloud1", "bucket1", "object1");
+            Ok(Some(Pack::new(digest.clone(), vec![location])))
+        });
+        mock.expect_load_dataset_stores().returning(|_| {
+            let mut source = MockPackRepository::new();
+            source
+                .expect_retrieve_pack()
+                .times(1)
+                .returning(|_, _, _| Ok(()));
+            Ok(Box::new(source))
+        });
+        mock.expect_build_pack_repo()
+            .withf(|store| store.id == "local1")
+            .returning(|_| {
+                let mut target = MockPackRepository::new();
+                target
+                    .expect_store_pack()
+                    .times(1)
+                    .returning(|_, bucket, object| {
+                        Ok(vec![PackLocation::new("local1", bucket, object)])
+                    });
+                Ok(Box::new(target))
+            });
+        mock.expect_put_pack()
+            .withf(|pack| {
+                pack.locations.len() == 2
+                    && pack.locations[1] == PackLocation::new("local1", "bucket1", "object1")
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let result = warm_critical_packs(&dataset, &mock, &snapshot_digest);
+        // assert
+        assert_eq!(result.unwrap(), 1);
+    }
+}
@@ -0,0 +1,475 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `email` module sends notifications about backups by way of an SMTP
+//! server, as configured by the `SMTP_*` and `EMAIL_*` settings.
+//!
+//! Depending on `EMAIL_NOTIFY`, a message is sent when a backup fails, when a
+//! backup finishes, and once a day with a summary of every dataset. Each kind
+//! of message is produced from a template, either built in or read from the
+//! directory named by `EMAIL_TEMPLATES`, in which the first line is the subject
+//! and the remainder is the body, with `{{name}}` replaced by the named value.
+//!
+//! A dataset may route its messages elsewhere with the `email_to` property,
+//! and choose which of `failure` and `success` are sent with `email_notify`,
+//! where an empty value leaves that dataset to the daily summary alone.
+//! Messages that would be sent during `EMAIL_QUIET_HOURS` are held until those
+//! hours have passed, for as long as the server is running.
+
+use crate::domain::entities::{Checksum, Dataset, Event, EventKind};
+use crate::domain::managers::settings;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use lazy_static::lazy_static;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{SmtpTransport, Transport};
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Time allowed for the SMTP server to respond before giving up.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Local hour of the day after which the summary is sent if
+// `EMAIL_SUMMARY_HOUR` is not set.
+const DEFAULT_SUMMARY_HOUR: u32 = 7;
+
+const FAILURE_TEMPLATE: &str = "Backup of {{basepath}} failed on {{hostname}}
+The backup of dataset {{dataset}} ({{basepath}}) failed at {{time}}.
+
+Error: {{error}}
+";
+
+const SUCCESS_TEMPLATE: &str = "Backup of {{basepath}} finished on {{hostname}}
+The backup of dataset {{dataset}} ({{basepath}}) finished at {{time}}.
+
+Snapshot: {{snapshot}}
+";
+
+const SUMMARY_TEMPLATE: &str = "Backup summary for {{hostname}}
+Backups of the datasets on {{hostname}} since {{since}}:
+
+{{summary}}";
+
+lazy_static! {
+    // Local date on which the summary was last sent.
+    static ref SUMMARY_SENT: Mutex<Option<NaiveDate>> = Mutex::new(None);
+    // Messages held during the quiet hours, with their recipients.
+    static ref HELD: Mutex<Vec<(Kind, HashMap<&'static str, String>, String)>> =
+        Mutex::new(Vec::new());
+}
+
+/// Kinds of messages that may be sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Failure,
+    Success,
+    Summary,
+}
+
+impl Kind {
+    fn template_name(&self) -> &'static str {
+        match self {
+            Kind::Failure => "failure.txt",
+            Kind::Success => "success.txt",
+            Kind::Summary => "summary.txt",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            Kind::Failure => FAILURE_TEMPLATE,
+            Kind::Success => SUCCESS_TEMPLATE,
+            Kind::Summary => SUMMARY_TEMPLATE,
+        }
+    }
+}
+
+///
+/// Send a message about the finished backup, if `EMAIL_NOTIFY` includes
+/// `success`.
+///
+pub fn backup_finished(
+    dbase: &dyn RecordRepository,
+    dataset_id: &str,
+    snapshot: Option<&Checksum>,
+) {
+    let dataset = dbase.get_dataset(dataset_id).ok().flatten();
+    if !enabled_for(Kind::Success, dataset.as_ref()) {
+        return;
+    }
+    let mut values = dataset_values(dataset.as_ref(), dataset_id);
+    let snapshot = snapshot.map_or(String::from("(no changes)"), |s| s.to_string());
+    values.insert("snapshot", snapshot);
+    send_or_hold(Kind::Success, values, recipients(dataset.as_ref()));
+}
+
+///
+/// Send a message about the failed backup, if `EMAIL_NOTIFY` includes
+/// `failure`, which it does by default.
+///
+pub fn backup_failed(dbase: &dyn RecordRepository, dataset_id: &str, error: &str) {
+    let dataset = dbase.get_dataset(dataset_id).ok().flatten();
+    if !enabled_for(Kind::Failure, dataset.as_ref()) {
+        return;
+    }
+    let mut values = dataset_values(dataset.as_ref(), dataset_id);
+    values.insert("error", error.to_owned());
+    send_or_hold(Kind::Failure, values, recipients(dataset.as_ref()));
+}
+
+///
+/// Send the messages that were held during the quiet hours, if those hours
+/// have passed, returning the number sent.
+///
+pub fn send_held() -> usize {
+    if is_quiet(Local::now().hour()) {
+        return 0;
+    }
+    let held: Vec<(Kind, HashMap<&'static str, String>, String)> =
+        HELD.lock().unwrap().drain(..).collect();
+    let mut count: usize = 0;
+    for (kind, values, recipients) in held.iter() {
+        match send(*kind, values, recipients) {
+            Ok(()) => count += 1,
+            Err(err) => error!("email: could not send message: {}", err),
+        }
+    }
+    count
+}
+
+///
+/// Send the daily summary of all datasets if `EMAIL_NOTIFY` includes `summary`
+/// and it has not yet been sent today, once the local time has passed
+/// `EMAIL_SUMMARY_HOUR`. Returns `true` if the summary was sent.
+///
+pub fn send_summary(dbase: &dyn RecordRepository) -> Result<bool, Error> {
+    if !enabled_for(Kind::Summary, None) {
+        return Ok(false);
+    }
+    let now = Local::now();
+    let hour = settings::var("EMAIL_SUMMARY_HOUR")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v < 24)
+        .unwrap_or(DEFAULT_SUMMARY_HOUR);
+    let today = now.date_naive();
+    if now.hour() < hour || is_quiet(now.hour()) || *SUMMARY_SENT.lock().unwrap() == Some(today) {
+        return Ok(false);
+    }
+    let since = Utc::now() - TimeDelta::days(1);
+    let mut values = common_values();
+    values.insert("since", since.to_rfc3339());
+    values.insert("summary", summarize(dbase, since)?);
+    let recipients = settings::var("EMAIL_TO").unwrap_or_default();
+    send(Kind::Summary, &values, &recipients)?;
+    *SUMMARY_SENT.lock().unwrap() = Some(today);
+    info!("email: sent daily summary");
+    Ok(true)
+}
+
+// Describe the state of each dataset and its backups since the given time.
+fn summarize(dbase: &dyn RecordRepository, since: DateTime<Utc>) -> Result<String, Error> {
+    let events: Vec<Event> = dbase
+        .get_events()?
+        .into_iter()
+        .filter(|e| e.time > since)
+        .collect();
+    let mut datasets = dbase.get_datasets()?;
+    datasets.sort_by(|a, b| a.basepath.cmp(&b.basepath));
+    let mut summary = String::new();
+    for dataset in datasets.iter() {
+        summary.push_str(&format!(
+            "{} ({})\n",
+            dataset.basepath.display(),
+            dataset.id
+        ));
+        let last_backup = match dbase.get_latest_snapshot(&dataset.id)? {
+            Some(digest) => dbase.get_snapshot(&digest)?.and_then(|s| s.end_time),
+            None => None,
+        };
+        let last_backup = last_backup.map_or(String::from("never"), |t| t.to_rfc3339());
+        summary.push_str(&format!("  last backup: {}\n", last_backup));
+        let mine = events.iter().filter(|e| e.subject == dataset.id);
+        let finished = mine
+            .clone()
+            .filter(|e| e.kind == EventKind::BackupFinished)
+            .count();
+        let failed: Vec<&Event> = mine.filter(|e| e.kind == EventKind::BackupFailed).collect();
+        summary.push_str(&format!(
+            "  finished: {}, failed: {}\n",
+            finished,
+            failed.len()
+        ));
+        if let Some(error) = failed.last().and_then(|e| e.details.get("error")) {
+            summary.push_str(&format!("  last error: {}\n", error));
+        }
+        summary.push('\n');
+    }
+    if datasets.is_empty() {
+        summary.push_str("No datasets have been defined.\n");
+    }
+    Ok(summary)
+}
+
+// Return `true` if messages of the given kind are to be sent about the dataset,
+// whose `email_notify` property takes the place of the `EMAIL_NOTIFY` setting.
+fn enabled_for(kind: Kind, dataset: Option<&Dataset>) -> bool {
+    if settings::var("SMTP_HOST").map_or(true, |v| v.is_empty()) {
+        return false;
+    }
+    let modes = match dataset.and_then(|d| d.properties.get("email_notify")) {
+        Some(value) => value.to_owned(),
+        None => settings::var("EMAIL_NOTIFY").unwrap_or_else(|_| String::from("failure")),
+    };
+    parse_modes(&modes).contains(&kind)
+}
+
+// Return the recipients of messages about the dataset, as given by its
+// `email_to` property or the `EMAIL_TO` setting.
+fn recipients(dataset: Option<&Dataset>) -> String {
+    match dataset.and_then(|d| d.properties.get("email_to")) {
+        Some(value) if !value.trim().is_empty() => value.to_owned(),
+        _ => settings::var("EMAIL_TO").unwrap_or_default(),
+    }
+}
+
+// Parse the quiet hours, given as a range of local hours such as `22-7`,
+// where the start hour is included and the stop hour is not.
+fn parse_quiet_hours(value: &str) -> Option<(u32, u32)> {
+    let (start, stop) = value.split_once('-')?;
+    let start = start.trim().parse::<u32>().ok().filter(|h| *h < 24)?;
+    let stop = stop.trim().parse::<u32>().ok().filter(|h| *h < 24)?;
+    Some((start, stop))
+}
+
+// Return `true` if the local hour falls within `EMAIL_QUIET_HOURS`.
+fn is_quiet(hour: u32) -> bool {
+    match settings::var("EMAIL_QUIET_HOURS")
+        .ok()
+        .and_then(|v| parse_quiet_hours(&v))
+    {
+        Some((start, stop)) => within_hours(hour, start, stop),
+        None => false,
+    }
+}
+
+// Return `true` if the hour falls within the range, which may span midnight.
+fn within_hours(hour: u32, start: u32, stop: u32) -> bool {
+    if stop < start {
+        start <= hour || hour < stop
+    } else {
+        start <= hour && hour < stop
+    }
+}
+
+// Send the message now, or hold it until the quiet hours have passed.
+fn send_or_hold(kind: Kind, values: HashMap<&'static str, String>, recipients: String) {
+    if is_quiet(Local::now().hour()) {
+        HELD.lock().unwrap().push((kind, values, recipients));
+    } else if let Err(err) = send(kind, &values, &recipients) {
+        error!("email: could not send message: {}", err);
+    }
+}
+
+// Parse the comma-separated kinds of messages to be sent.
+fn parse_modes(value: &str) -> Vec<Kind> {
+    let mut modes: Vec<Kind> = Vec::new();
+    for mode in value.split(',').map(|m| m.trim().to_lowercase()) {
+        match mode.as_str() {
+            "failure" => modes.push(Kind::Failure),
+            "success" => modes.push(Kind::Success),
+            "summary" => modes.push(Kind::Summary),
+            "" => (),
+            _ => error!("email: unknown EMAIL_NOTIFY value {}", mode),
+        }
+    }
+    modes
+}
+
+// Values available to every template.
+fn common_values() -> HashMap<&'static str, String> {
+    let mut values: HashMap<&'static str, String> = HashMap::new();
+    let hostname = whoami::fallible::hostname().unwrap_or("none".into());
+    values.insert("hostname", hostname);
+    values.insert("time", Utc::now().to_rfc3339());
+    values
+}
+
+// Values describing the dataset, in addition to the common values.
+fn dataset_values(dataset: Option<&Dataset>, dataset_id: &str) -> HashMap<&'static str, String> {
+    let mut values = common_values();
+    values.insert("dataset", dataset_id.to_owned());
+    let basepath = match dataset {
+        Some(dataset) => dataset.basepath.display().to_string(),
+        None => String::from("(unknown)"),
+    };
+    values.insert("basepath", basepath);
+    values
+}
+
+// Replace each `{{name}}` in the template with the named value.
+fn render(template: &str, values: &HashMap<&'static str, String>) -> String {
+    let mut result = template.to_owned();
+    for (name, value) in values.iter() {
+        result = result.replace(&format!("{{{{{}}}}}", name), value);
+    }
+    result
+}
+
+// Read the template from `EMAIL_TEMPLATES`, if available, or use the default.
+fn load_template(kind: Kind) -> String {
+    if let Ok(dir) = settings::var("EMAIL_TEMPLATES") {
+        let path: PathBuf = [dir.as_str(), kind.template_name()].iter().collect();
+        if let Ok(template) = fs::read_to_string(path) {
+            return template;
+        }
+    }
+    kind.default_template().to_owned()
+}
+
+// Render the message of the given kind and send it to the comma-separated
+// recipients.
+fn send(kind: Kind, values: &HashMap<&'static str, String>, recipients: &str) -> Result<(), Error> {
+    let rendered = render(&load_template(kind), values);
+    let (subject, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+    let from = settings::var("EMAIL_FROM").map_err(|_| anyhow!("EMAIL_FROM is not set"))?;
+    let mut builder = Message::builder()
+        .from(from.parse::<Mailbox>()?)
+        .subject(subject.trim())
+        .header(ContentType::TEXT_PLAIN);
+    let mut count = 0;
+    for recipient in recipients.split(',').filter(|r| !r.trim().is_empty()) {
+        builder = builder.to(recipient.trim().parse::<Mailbox>()?);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(anyhow!("EMAIL_TO is not set"));
+    }
+    let message = builder.body(body.trim_start().to_owned())?;
+    build_transport()?.send(&message)?;
+    Ok(())
+}
+
+// Connect to the SMTP server as configured by the `SMTP_*` settings, using
+// STARTTLS unless `SMTP_SECURITY` is set to `tls` or `none`.
+fn build_transport() -> Result<SmtpTransport, Error> {
+    let host = settings::var("SMTP_HOST")?;
+    let security = settings::var("SMTP_SECURITY").unwrap_or_default();
+    let mut builder = match security.to_lowercase().as_str() {
+        "tls" => SmtpTransport::relay(&host)?,
+        "none" => SmtpTransport::builder_dangerous(&host),
+        _ => SmtpTransport::starttls_relay(&host)?,
+    };
+    if let Some(port) = settings::var("SMTP_PORT")
+        .ok()
+        .and_then(|v| v.parse::<u16>().ok())
+    {
+        builder = builder.port(port);
+    }
+    if let Ok(username) = settings::var("SMTP_USERNAME") {
+        let password = settings::var("SMTP_PASSWORD").unwrap_or_default();
+        builder = builder.credentials(Credentials::new(username, password));
+    }
+    Ok(builder.timeout(Some(SMTP_TIMEOUT)).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::Snapshot;
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_modes() {
+        assert_eq!(parse_modes("failure"), vec![Kind::Failure]);
+        assert_eq!(
+            parse_modes("Summary, failure,"),
+            vec![Kind::Summary, Kind::Failure]
+        );
+        assert!(parse_modes("").is_empty());
+        assert_eq!(parse_modes("nonsense,success"), vec![Kind::Success]);
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        assert_eq!(parse_quiet_hours("22-7"), Some((22, 7)));
+        assert_eq!(parse_quiet_hours(" 1 - 5 "), Some((1, 5)));
+        assert!(parse_quiet_hours("22").is_none());
+        assert!(parse_quiet_hours("22-24").is_none());
+        assert!(within_hours(23, 22, 7));
+        assert!(within_hours(3, 22, 7));
+        assert!(!within_hours(7, 22, 7));
+        assert!(within_hours(1, 1, 5));
+        assert!(!within_hours(5, 1, 5));
+    }
+
+    #[test]
+    fn test_recipients() {
+        let mut dataset = Dataset::new(Path::new("/home/work"));
+        dataset
+            .properties
+            .insert("email_to".to_owned(), "ops@example.com".to_owned());
+        assert_eq!(recipients(Some(&dataset)), "ops@example.com");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut values: HashMap<&'static str, String> = HashMap::new();
+        values.insert("hostname", "server1".into());
+        values.insert("basepath", "/home/user".into());
+        values.insert("error", "out of space".into());
+        let actual = render(FAILURE_TEMPLATE, &values);
+        assert!(actual.starts_with("Backup of /home/user failed on server1\n"));
+        assert!(actual.contains("Error: out of space"));
+        // names without values are left as-is
+        assert!(actual.contains("{{dataset}}"));
+    }
+
+    #[test]
+    fn test_summarize() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/user"));
+        let dataset_id = dataset.id.clone();
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut snapshot = Snapshot::new(None, tree, Default::default());
+        let end_time = Utc::now() - TimeDelta::hours(2);
+        snapshot.set_end_time(end_time);
+        let snapshot_sha1 = snapshot.digest.clone();
+        let mut old = Event::new(EventKind::BackupFailed, &dataset_id).detail("error", "old");
+        old.time = Utc::now() - TimeDelta::days(3);
+        let events = vec![
+            old,
+            Event::new(EventKind::BackupFailed, &dataset_id).detail("error", "disk full"),
+            Event::new(EventKind::BackupFinished, &dataset_id),
+            Event::new(EventKind::BackupFinished, "other"),
+        ];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_events()
+            .returning(move || Ok(events.clone()));
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(snapshot_sha1.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        // act
+        let result = summarize(&mock, Utc::now() - TimeDelta::days(1));
+        // assert
+        assert!(result.is_ok());
+        let summary = result.unwrap();
+        assert!(summary.starts_with(&format!("/home/user ({})\n", dataset_id)));
+        assert!(summary.contains(&format!("last backup: {}", end_time.to_rfc3339())));
+        assert!(summary.contains("finished: 1, failed: 1"));
+        assert!(summary.contains("last error: disk full"));
+    }
+}
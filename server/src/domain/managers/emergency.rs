@@ -0,0 +1,472 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `emergency` module writes a small, self-describing index of the most
+//! important files of each snapshot, which is uploaded to the rescue bucket
+//! of every pack store. The index names the pack and chunk of every part of
+//! those files, along with the locations of the packs, such that the files can
+//! be recovered using nothing more than the store credentials and passphrase,
+//! long before the database itself has been restored.
+//!
+//! The files in the index are those at the top level of the dataset, along
+//! with everything within the critical paths, as given by the `critical_paths`
+//! property of the dataset.
+
+use crate::domain::entities::{
+    Checksum, Dataset, EmergencyChunk, EmergencyFile, EmergencyIndex, EmergencyPack, Message,
+    MessageCode, Pack, PackLocation, TreeReference,
+};
+use crate::domain::helpers::{pack, paths};
+use crate::domain::managers::critical;
+use crate::domain::repositories::{PackRepository, RecordRepository};
+use anyhow::{anyhow, Error};
+use globset::GlobSet;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+///
+/// Build the emergency index of the given snapshot and upload it to the pack
+/// stores, recording it along with the database archives so that pruning will
+/// leave it be. Returns the number of files in the index.
+///
+pub fn save(
+    dbase: &dyn RecordRepository,
+    stores: &dyn PackRepository,
+    computer_id: &str,
+    dataset: &Dataset,
+    snapshot: &Checksum,
+    passphrase: &str,
+) -> Result<usize, Error> {
+    let index = build_index(dbase, dataset, snapshot)?;
+    let archive = dbase.pack_emergency(&index, passphrase)?;
+    let coords = stores.store_emergency(computer_id, &dataset.id, &archive)?;
+    let digest = Checksum::blake3_from_file(&archive)?;
+    dbase.insert_database(&Pack::new(digest, coords))?;
+    info!(
+        "emergency index of {} lists {} files in {} packs",
+        dataset.id,
+        index.files.len(),
+        index.packs.len()
+    );
+    Ok(index.files.len())
+}
+
+///
+/// Build the emergency index of the given snapshot, listing the files at the
+/// top level of the dataset and those within the critical paths.
+///
+pub fn build_index(
+    dbase: &dyn RecordRepository,
+    dataset: &Dataset,
+    snapshot: &Checksum,
+) -> Result<EmergencyIndex, Error> {
+    let matcher = critical::build_matcher(&dataset.critical_paths())?;
+    let mut index = EmergencyIndex::new(&dataset.id, snapshot.clone());
+    let mut pack_digests: HashSet<Checksum> = HashSet::new();
+    for (path, reference) in find_files(dbase, snapshot, &matcher)? {
+        let path = path_to_string(&path);
+        match reference {
+            TreeReference::SMALL(content) => index.files.push(EmergencyFile {
+                path,
+                length: content.len() as u64,
+                content: Some(content),
+                chunks: vec![],
+            }),
+            TreeReference::FILE(digest) => {
+                let Some(file) = dbase.get_file(&digest)? else {
+                    // not yet backed up, or went missing during the backup
+                    warn!("emergency index skipping {} without file record", path);
+                    continue;
+                };
+                let mut chunks: Vec<EmergencyChunk> = Vec::new();
+                if file.chunks.len() == 1 {
+                    // the chunk of a single-chunk file is named by the file
+                    // digest and the checksum is that of the pack
+                    chunks.push(EmergencyChunk {
+                        offset: 0,
+                        digest: file.digest.clone(),
+                        pack: file.chunks[0].1.clone(),
+                    });
+                } else {
+                    for (offset, chunk_digest) in file.chunks.iter() {
+                        let chunk = dbase.get_chunk(chunk_digest)?.ok_or_else(|| {
+                            Message::new(MessageCode::MissingChunk).with("digest", chunk_digest)
+                        })?;
+                        let pack = chunk.packfile.ok_or_else(|| {
+                            anyhow!(format!("chunk without pack: {}", chunk_digest))
+                        })?;
+                        chunks.push(EmergencyChunk {
+                            offset: *offset,
+                            digest: chunk_digest.clone(),
+                            pack,
+                        });
+                    }
+                }
+                for chunk in chunks.iter() {
+                    pack_digests.insert(chunk.pack.clone());
+                }
+                index.files.push(EmergencyFile {
+                    path,
+                    length: file.length,
+                    content: None,
+                    chunks,
+                });
+            }
+            _ => (),
+        }
+    }
+    for digest in pack_digests.into_iter() {
+        let pack = dbase
+            .get_pack(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingPack).with("digest", &digest))?;
+        index.packs.push(EmergencyPack {
+            digest: pack.digest,
+            locations: pack.locations,
+            compression: pack.compression,
+        });
+    }
+    Ok(index)
+}
+
+///
+/// Recover the files of the emergency index that match the selected paths,
+/// or every file if no paths are given, into the output directory. A path
+/// selects the file of that name, or every file within the directory of that
+/// name. Packs are fetched into the workspace and removed once extracted.
+/// Returns the number of files recovered.
+///
+pub fn recover(
+    index: &EmergencyIndex,
+    stores: &dyn PackRepository,
+    selection: &[String],
+    outdir: &Path,
+    workspace: &Path,
+    passphrase: &str,
+) -> Result<usize, Error> {
+    let files: Vec<&EmergencyFile> = index
+        .files
+        .iter()
+        .filter(|f| is_selected(&f.path, selection))
+        .collect();
+    let packs: HashMap<&Checksum, &EmergencyPack> =
+        index.packs.iter().map(|p| (&p.digest, p)).collect();
+    let mut fetched: HashSet<&Checksum> = HashSet::new();
+    for file in files.iter() {
+        // refuse paths that would escape the output directory, including by
+        // way of links that were restored by earlier entries
+        let relative = Path::new(&file.path);
+        paths::validate_relative(relative)?;
+        let outfile = paths::safe_join(outdir, relative)?;
+        if let Some(parent) = outfile.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Some(content) = file.content.as_ref() {
+            fs::write(&outfile, content)?;
+            continue;
+        }
+        let mut chunks: Vec<&EmergencyChunk> = file.chunks.iter().collect();
+        chunks.sort_unstable_by_key(|c| c.offset);
+        let mut output = fs::File::create(&outfile)?;
+        for chunk in chunks.into_iter() {
+            if fetched.insert(&chunk.pack) {
+                let pack = packs.get(&chunk.pack).ok_or_else(|| {
+                    anyhow!(format!("pack {} not in emergency index", chunk.pack))
+                })?;
+                fetch_pack(stores, pack, workspace, passphrase)?;
+            }
+            let path = workspace.join(chunk.digest.to_string());
+            let mut input = fs::File::open(&path)
+                .map_err(|err| anyhow!(format!("chunk {} not in pack: {}", chunk.digest, err)))?;
+            std::io::copy(&mut input, &mut output)?;
+        }
+        debug!("recovered {}", file.path);
+    }
+    Ok(files.len())
+}
+
+///
+/// Add a location in the given store for every pack of the index, using the
+/// same bucket and object as the recorded locations. This allows the packs to
+/// be retrieved from a store that has been defined anew, whose identifier is
+/// not the same as the one that was used during the backup.
+///
+pub fn relocate(index: &mut EmergencyIndex, store_id: &str) {
+    for pack in index.packs.iter_mut() {
+        if pack.locations.iter().any(|l| l.store == store_id) {
+            continue;
+        }
+        if let Some(existing) = pack.locations.first() {
+            let location = PackLocation::new(store_id, &existing.bucket, &existing.object);
+            pack.locations.push(location);
+        }
+    }
+}
+
+// Walk the tree of the snapshot, collecting the files at the top level and
+// those that match the critical paths, including everything within a
+// matching directory.
+fn find_files(
+    dbase: &dyn RecordRepository,
+    snapshot: &Checksum,
+    matcher: &GlobSet,
+) -> Result<Vec<(PathBuf, TreeReference)>, Error> {
+    let snapshot = dbase
+        .get_snapshot(snapshot)?
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &snapshot))?;
+    let mut files: Vec<(PathBuf, TreeReference)> = Vec::new();
+    let mut pending: VecDeque<(Checksum, PathBuf, bool)> = VecDeque::new();
+    pending.push_back((snapshot.tree, PathBuf::new(), false));
+    while let Some((digest, prefix, included)) = pending.pop_front() {
+        let top_level = prefix.as_os_str().is_empty();
+        let tree = dbase
+            .get_tree(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        for entry in tree.entries.into_iter() {
+            let path = prefix.join(&entry.name);
+            let matched = included || matcher.is_match(&path);
+            match entry.reference {
+                TreeReference::TREE(child) => pending.push_back((child, path, matched)),
+                TreeReference::FILE(_) | TreeReference::SMALL(_) if matched || top_level => {
+                    files.push((path, entry.reference));
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Fetch the pack from the stores and extract its chunks into the workspace.
+fn fetch_pack(
+    stores: &dyn PackRepository,
+    pack: &EmergencyPack,
+    workspace: &Path,
+    passphrase: &str,
+) -> Result<(), Error> {
+    let archive = workspace.join(pack.digest.to_string());
+    debug!("fetching pack {}", pack.digest);
+    // the pack repository verifies the digest of the retrieved file
+    stores.retrieve_pack(&pack.locations, &pack.digest, &archive)?;
+    let names = pack::extract_pack(&archive, workspace, Some(passphrase))?;
+    pack::decode_chunks(workspace, &names, pack.compression)?;
+    fs::remove_file(archive)?;
+    Ok(())
+}
+
+// Determine if the path is among those selected, or within one of them.
+fn is_selected(path: &str, selection: &[String]) -> bool {
+    selection.is_empty()
+        || selection.iter().any(|s| {
+            let s = s.trim_matches('/');
+            path == s || path.starts_with(&format!("{}/", s))
+        })
+}
+
+// Convert the relative path to a string with `/` as the separator.
+fn path_to_string(path: &Path) -> String {
+    let parts: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{
+        Chunk, Compression, File, FileCounts, Snapshot, Tree, TreeEntry,
+    };
+    use crate::domain::helpers::pack::PackBuilder;
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+
+    #[test]
+    fn test_is_selected() {
+        let selection = vec!["etc/".to_owned(), "notes.txt".to_owned()];
+        assert!(is_selected("etc/passwd", &selection));
+        assert!(is_selected("etc/ssh/config", &selection));
+        assert!(is_selected("notes.txt", &selection));
+        assert!(!is_selected("etcetera", &selection));
+        assert!(!is_selected("notes.txt.bak", &selection));
+        assert!(is_selected("anything", &[]));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_recover_escape() -> Result<(), Error> {
+        let outdir = tempfile::tempdir()?;
+        let workspace = tempfile::tempdir()?;
+        let elsewhere = tempfile::tempdir()?;
+        std::os::unix::fs::symlink(elsewhere.path(), outdir.path().join("etc"))?;
+        let stores = MockPackRepository::new();
+        for path in ["etc/passwd", "../passwd", "/etc/passwd"] {
+            let mut index = EmergencyIndex::new("dataset1", Checksum::SHA1("cafebabe".into()));
+            index.files.push(EmergencyFile {
+                path: path.to_owned(),
+                length: 4,
+                content: Some(b"root".to_vec()),
+                chunks: vec![],
+            });
+            let result = recover(
+                &index,
+                &stores,
+                &[],
+                outdir.path(),
+                workspace.path(),
+                "secret123",
+            );
+            assert!(result.is_err());
+        }
+        assert!(!elsewhere.path().join("passwd").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocate() {
+        let snapshot = Checksum::SHA1("65ace06cc7f835c497811ea7199968a119eeba4b".to_owned());
+        let mut index = EmergencyIndex::new("cafebabe", snapshot.clone());
+        index.packs.push(EmergencyPack {
+            digest: snapshot,
+            locations: vec![PackLocation::new("store1", "bucket1", "object1")],
+            compression: Compression::Archive,
+        });
+        relocate(&mut index, "rescue");
+        relocate(&mut index, "rescue");
+        let locations = &index.packs[0].locations;
+        assert_eq!(locations.len(), 2);
+        assert_eq!(
+            locations[1],
+            PackLocation::new("rescue", "bucket1", "object1")
+        );
+    }
+
+    #[test]
+    fn test_build_and_recover() -> Result<(), Error> {
+        // arrange a pack holding the chunks of a single file
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        let file_digest = Checksum::blake3_from_file(infile)?;
+        let chunks = crate::domain::helpers::find_file_chunks(infile, 16384)?;
+        assert!(chunks.len() > 1);
+        let outdir = tempfile::tempdir()?;
+        let packfile = outdir.path().join("archive.pack");
+        let mut builder = PackBuilder::new(4194304)
+            .password("keyboard cat")
+            .compression(Compression::Lz4);
+        builder.initialize(&packfile)?;
+        for chunk in chunks.iter() {
+            builder.add_chunk(chunk)?;
+        }
+        builder.finalize()?;
+        let pack_digest = Checksum::blake3_from_file(&packfile)?;
+        let file = File::new(
+            file_digest.clone(),
+            fs::metadata(infile)?.len(),
+            chunks
+                .iter()
+                .map(|c| (c.offset as u64, c.digest.clone()))
+                .collect(),
+        );
+        // a top-level file, a critical file, and a file that is neither
+        let critical_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("image.jpg"),
+                TreeReference::FILE(file_digest.clone()),
+            )],
+            1,
+        );
+        let other_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("other.txt"),
+                TreeReference::SMALL(b"other".to_vec()),
+            )],
+            1,
+        );
+        let root_tree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("notes.txt"),
+                    TreeReference::SMALL(b"hello".to_vec()),
+                ),
+                TreeEntry::new(
+                    Path::new("photos"),
+                    TreeReference::TREE(critical_tree.digest.clone()),
+                ),
+                TreeEntry::new(
+                    Path::new("stuff"),
+                    TreeReference::TREE(other_tree.digest.clone()),
+                ),
+            ],
+            3,
+        );
+        let snapshot = Snapshot::new(None, root_tree.digest.clone(), FileCounts::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let trees = vec![root_tree, critical_tree, other_tree];
+        let chunk_packs = chunks.clone();
+        let pack_clone = pack_digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .returning(move |_| Ok(Some(file.clone())));
+        mock.expect_get_chunk().returning(move |digest| {
+            Ok(chunk_packs.iter().find(|c| &c.digest == digest).map(|c| {
+                Chunk::new(c.digest.clone(), c.offset, c.length).packfile(pack_clone.clone())
+            }))
+        });
+        let pack_clone = pack_digest.clone();
+        mock.expect_get_pack().returning(move |_| {
+            let location = PackLocation::new("store1", "bucket1", "object1");
+            Ok(Some(
+                Pack::new(pack_clone.clone(), vec![location]).compression(Compression::Lz4),
+            ))
+        });
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".into();
+        dataset
+            .properties
+            .insert("critical_paths".into(), "photos".into());
+        // act
+        let index = build_index(&mock, &dataset, &snapshot_digest)?;
+        // assert
+        assert_eq!(index.dataset, "cafebabe");
+        assert_eq!(index.files.len(), 2);
+        assert_eq!(index.files[0].path, "notes.txt");
+        assert_eq!(index.files[1].path, "photos/image.jpg");
+        assert_eq!(index.files[1].chunks.len(), chunks.len());
+        assert_eq!(index.packs.len(), 1);
+        assert_eq!(index.packs[0].compression, Compression::Lz4);
+
+        // arrange
+        let mut stores = MockPackRepository::new();
+        stores
+            .expect_retrieve_pack()
+            .times(1)
+            .returning(move |_, _, outfile| {
+                fs::copy(&packfile, outfile)?;
+                Ok(())
+            });
+        let restored = tempfile::tempdir()?;
+        let workspace = tempfile::tempdir()?;
+        // act
+        let selection = vec!["photos".to_owned()];
+        let count = recover(
+            &index,
+            &stores,
+            &selection,
+            restored.path(),
+            workspace.path(),
+            "keyboard cat",
+        )?;
+        // assert
+        assert_eq!(count, 1);
+        let actual = Checksum::blake3_from_file(&restored.path().join("photos/image.jpg"))?;
+        assert_eq!(actual, file_digest);
+        assert!(!restored.path().join("notes.txt").exists());
+        Ok(())
+    }
+}
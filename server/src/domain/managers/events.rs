@@ -0,0 +1,128 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `events` module keeps an audit trail of the actions taken by the
+//! application, such as backups, pack uploads, pruning, restore requests, and
+//! changes to the pack stores.
+//!
+//! Events are recorded without needing a database connection, held in memory
+//! until the next time they are written to the database, which happens after
+//! each backup, periodically while the supervisor is running, and before the
+//! event log is queried. Events older than `EVENT_RETENTION_DAYS` are removed
+//! from the database by the supervisor.
+
+use crate::domain::entities::Event;
+use crate::domain::managers::settings;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use lazy_static::lazy_static;
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// Number of days to retain events if `EVENT_RETENTION_DAYS` is not set.
+const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+// Number of events held in memory before the oldest are dropped, in case the
+// database has been unavailable for a long time.
+const MAX_PENDING: usize = 10_000;
+
+lazy_static! {
+    // Events that have not yet been written to the database, oldest first.
+    static ref PENDING: Mutex<VecDeque<Event>> = Mutex::new(VecDeque::new());
+}
+
+///
+/// Add the event to the event log.
+///
+pub fn record(event: Event) {
+    debug!("event: {} {}", event.kind, event.subject);
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() >= MAX_PENDING {
+        pending.pop_front();
+    }
+    pending.push_back(event);
+}
+
+///
+/// Write the recorded events to the database, returning the number written.
+/// Events that could not be written are kept for the next attempt.
+///
+pub fn flush(repo: &dyn RecordRepository) -> Result<usize, Error> {
+    // release the lock while writing so that recording is never held up
+    let events: Vec<Event> = PENDING.lock().unwrap().drain(..).collect();
+    for (count, event) in events.iter().enumerate() {
+        if let Err(err) = repo.put_event(event) {
+            let mut pending = PENDING.lock().unwrap();
+            for unsaved in events[count..].iter().rev() {
+                pending.push_front(unsaved.clone());
+            }
+            return Err(err);
+        }
+    }
+    Ok(events.len())
+}
+
+///
+/// Return the length of time for which events are retained, as given by the
+/// `EVENT_RETENTION_DAYS` setting.
+///
+pub fn retention() -> TimeDelta {
+    let days = settings::var("EVENT_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v >= 0)
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    TimeDelta::days(days)
+}
+
+///
+/// Remove the events that are older than the retention period, returning the
+/// number of events removed.
+///
+pub fn prune_expired(repo: &dyn RecordRepository) -> Result<usize, Error> {
+    let cutoff = Utc::now() - retention();
+    let mut count: usize = 0;
+    for event in repo.get_events()? {
+        if event.time < cutoff {
+            repo.delete_event(&event.id)?;
+            count += 1;
+        }
+    }
+    if count > 0 {
+        info!("events: removed {} expired events", count);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::EventKind;
+    use crate::domain::repositories::MockRecordRepository;
+    use mockall::predicate::*;
+
+    #[test]
+    fn test_prune_expired() {
+        // arrange
+        let mut old = Event::new(EventKind::BackupStarted, "dataset1");
+        old.time = Utc::now() - TimeDelta::days(DEFAULT_RETENTION_DAYS + 1);
+        let old_id = old.id.clone();
+        let new = Event::new(EventKind::BackupFinished, "dataset1");
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_events()
+            .returning(move || Ok(vec![old.clone(), new.clone()]));
+        mock.expect_delete_event()
+            .with(eq(old_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let result = prune_expired(&mock);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+}
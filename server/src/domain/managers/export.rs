@@ -0,0 +1,873 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `export` module presents a file from a snapshot, such as the disk image
+//! of a virtual machine, as a read-only block device using the Network Block
+//! Device (NBD) protocol. Content is fetched from the pack stores as the client
+//! reads it, so the image can be mounted without restoring it first.
+//!
+//! Only the fixed newstyle handshake is supported, which is what `nbd-client`,
+//! `qemu-nbd`, and `nbdfuse` use by default.
+//!
+//! The protocol has no authentication of its own, short of TLS, so the name of
+//! each export is a random secret that the client must give, and exports are
+//! only served on the loopback interface unless `EXPORT_ALLOW_REMOTE` is set.
+
+use crate::domain::entities::{
+    Checksum, Dataset, Message, MessageCode, RestoreVerify, TreeReference,
+};
+use crate::domain::managers::{pairing, restore, settings};
+use crate::domain::repositories::{PackRepository, RecordRepository};
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use store_core::Secret;
+
+// Handshake and option haggling values from the NBD protocol specification.
+const NBD_MAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const FLAG_FIXED_NEWSTYLE: u16 = 1;
+const FLAG_NO_ZEROES: u16 = 2;
+const FLAG_C_NO_ZEROES: u32 = 2;
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = 0x8000_0001;
+const REP_ERR_POLICY: u32 = 0x8000_0002;
+const REP_ERR_INVALID: u32 = 0x8000_0003;
+const REP_ERR_UNKNOWN: u32 = 0x8000_0006;
+const INFO_EXPORT: u16 = 0;
+const TRANS_HAS_FLAGS: u16 = 1;
+const TRANS_READ_ONLY: u16 = 2;
+
+// Transmission phase values from the NBD protocol specification.
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+// Largest option payload and read request that will be honored.
+const MAX_OPTION_LENGTH: u32 = 4096;
+const MAX_READ_LENGTH: u32 = 32 * 1024 * 1024;
+
+lazy_static! {
+    // Exports currently being served, keyed by export identifier.
+    static ref EXPORTS: Mutex<HashMap<String, Running>> = Mutex::new(HashMap::new());
+}
+
+///
+/// Describes a file from a snapshot that is being served as a block device.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Export {
+    /// Unique identifier of the export.
+    pub id: String,
+    /// Name of the NBD export, which the client must give to connect.
+    pub name: String,
+    /// Identifier of the dataset containing the file.
+    pub dataset: String,
+    /// Digest of the tree containing the file.
+    pub tree: Checksum,
+    /// Name of the file within the tree.
+    pub entry: String,
+    /// Address on which the export is listening for connections.
+    pub address: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Time at which the export was started.
+    pub started: DateTime<Utc>,
+}
+
+// An export along with the means to bring it to an end.
+struct Running {
+    export: Export,
+    stop: Arc<AtomicBool>,
+    address: SocketAddr,
+    client: Arc<Mutex<Option<TcpStream>>>,
+}
+
+///
+/// Return `true` if `EXPORT_ALLOW_REMOTE` is set, in which case exports may
+/// listen on addresses other than the loopback interface.
+///
+pub fn remote_allowed() -> bool {
+    settings::var("EXPORT_ALLOW_REMOTE")
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+///
+/// Start serving the named file entry of the given tree on the address, which
+/// may specify port zero to have one chosen automatically.
+///
+pub fn start(
+    dbase: Arc<dyn RecordRepository>,
+    dataset_id: &str,
+    tree: Checksum,
+    entry: &str,
+    address: &str,
+    passphrase: Secret,
+) -> Result<Export, Error> {
+    let image = LazyImage::new(dbase, dataset_id, &tree, entry, passphrase)?;
+    let listener = TcpListener::bind(address)?;
+    let local_addr = listener.local_addr()?;
+    if !local_addr.ip().is_loopback() && !remote_allowed() {
+        return Err(anyhow!(format!(
+            "export address {} is not a loopback address and EXPORT_ALLOW_REMOTE is not set",
+            local_addr
+        )));
+    }
+    let export = Export {
+        id: xid::new().to_string(),
+        name: pairing::generate_token(),
+        dataset: dataset_id.to_owned(),
+        tree,
+        entry: entry.to_owned(),
+        address: local_addr.to_string(),
+        size: image.size(),
+        started: Utc::now(),
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let client: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+    let running = Running {
+        export: export.clone(),
+        stop: stop.clone(),
+        address: local_addr,
+        client: client.clone(),
+    };
+    EXPORTS.lock().unwrap().insert(export.id.clone(), running);
+    let name = export.name.clone();
+    thread::spawn(move || serve(listener, &name, image, &stop, &client));
+    info!(
+        "exporting {} ({} bytes) as {} on {}",
+        export.entry, export.size, export.id, export.address
+    );
+    Ok(export)
+}
+
+///
+/// Stop the export with the given identifier, disconnecting any client.
+///
+/// Returns `true` if the export was found.
+///
+pub fn stop(id: &str) -> bool {
+    let running = EXPORTS.lock().unwrap().remove(id);
+    if let Some(running) = running {
+        info!("stopping export {}", id);
+        running.stop.store(true, Ordering::SeqCst);
+        if let Some(stream) = running.client.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        // wake the listener so that it notices the stop flag
+        let _ = TcpStream::connect(running.address);
+        true
+    } else {
+        false
+    }
+}
+
+///
+/// Return all of the exports currently being served.
+///
+pub fn exports() -> Vec<Export> {
+    let exports = EXPORTS.lock().unwrap();
+    let mut results: Vec<Export> = exports.values().map(|r| r.export.clone()).collect();
+    results.sort_by(|a, b| a.started.cmp(&b.started));
+    results
+}
+
+///
+/// Source of the content for a block device.
+///
+pub trait BlockSource {
+    /// Size of the content in bytes.
+    fn size(&self) -> u64;
+
+    /// Fill the buffer with the content starting at the given offset.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Error>;
+}
+
+// How the content of the file was recorded in the database.
+enum Content {
+    // content stored directly within the tree entry
+    Small(Vec<u8>),
+    // a single chunk whose digest is that of the pack containing it
+    Single(Checksum, Checksum),
+    // chunks sorted by their offset within the file
+    Chunks(Vec<(u64, Checksum)>),
+}
+
+///
+/// Content of a file from a snapshot, which is read from the packs held in a
+/// `PackCache`, fetching them from the pack stores as needed.
+///
+pub struct FileContent {
+    length: u64,
+    content: Content,
+}
+
+impl FileContent {
+    /// Prepare to read the file referenced by the named tree entry.
+    pub fn new(
+        dbase: &dyn RecordRepository,
+        reference: &TreeReference,
+        name: &str,
+    ) -> Result<Self, Error> {
+        let (length, content) = match reference {
+            TreeReference::SMALL(contents) => {
+                (contents.len() as u64, Content::Small(contents.clone()))
+            }
+            TreeReference::FILE(digest) => {
+                let file = dbase.get_file(digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingFile).with("digest", &digest)
+                })?;
+                if file.chunks.len() == 1 {
+                    let pack_digest = file.chunks[0].1.clone();
+                    (file.length, Content::Single(pack_digest, file.digest))
+                } else {
+                    let mut chunks = file.chunks;
+                    chunks.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                    (file.length, Content::Chunks(chunks))
+                }
+            }
+            _ => return Err(anyhow!(format!("entry {} is not a file", name))),
+        };
+        Ok(Self { length, content })
+    }
+
+    /// Length of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Return `true` if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Fill the buffer with the content starting at the given offset.
+    pub fn read_at(&self, packs: &mut PackCache, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        if offset + buf.len() as u64 > self.length {
+            return Err(anyhow!("read beyond end of file"));
+        }
+        match &self.content {
+            Content::Small(contents) => {
+                let start = offset as usize;
+                buf.copy_from_slice(&contents[start..start + buf.len()]);
+            }
+            Content::Single(pack_digest, file_digest) => {
+                packs.read_chunk(pack_digest, file_digest, offset, buf)?;
+            }
+            Content::Chunks(chunks) => {
+                // find the chunk containing the offset, then read the chunks
+                // that follow until the buffer has been filled
+                let mut index = chunks.partition_point(|c| c.0 <= offset) - 1;
+                let mut position = offset;
+                let mut filled = 0;
+                while filled < buf.len() {
+                    let (chunk_offset, chunk_digest) = &chunks[index];
+                    let chunk_end = chunks.get(index + 1).map(|c| c.0).unwrap_or(self.length);
+                    let wanted = (buf.len() - filled).min((chunk_end - position) as usize);
+                    let pack_digest = packs.find_pack(chunk_digest)?;
+                    let target = &mut buf[filled..filled + wanted];
+                    packs.read_chunk(
+                        &pack_digest,
+                        chunk_digest,
+                        position - chunk_offset,
+                        target,
+                    )?;
+                    filled += wanted;
+                    position += wanted as u64;
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// A file from a snapshot whose content is retrieved from the pack stores
+/// only as it is read.
+///
+pub struct LazyImage {
+    packs: PackCache,
+    file: FileContent,
+}
+
+impl LazyImage {
+    /// Prepare to read the named file entry in the given tree.
+    pub fn new(
+        dbase: Arc<dyn RecordRepository>,
+        dataset_id: &str,
+        tree: &Checksum,
+        entry: &str,
+        passphrase: Secret,
+    ) -> Result<Self, Error> {
+        let dataset = dbase
+            .get_dataset(dataset_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &dataset_id))?;
+        let tree_rec = dbase
+            .get_tree(tree)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &tree))?;
+        let tree_entry = tree_rec
+            .entries
+            .iter()
+            .find(|e| e.name == entry)
+            .ok_or_else(|| anyhow!(format!("no entry {} in tree {}", entry, tree)))?;
+        let file = FileContent::new(dbase.as_ref(), &tree_entry.reference, entry)?;
+        // the image may be read from start to finish, keep every pack
+        let packs = PackCache::new(dbase, &dataset, passphrase, usize::MAX)?;
+        Ok(Self { packs, file })
+    }
+}
+
+impl BlockSource for LazyImage {
+    fn size(&self) -> u64 {
+        self.file.len()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.file.read_at(&mut self.packs, offset, buf)
+    }
+}
+
+///
+/// Downloads packs as needed, keeping the extracted chunks of the most
+/// recently used packs for later reads.
+///
+pub struct PackCache {
+    dbase: Arc<dyn RecordRepository>,
+    stores: Box<dyn PackRepository>,
+    passphrase: Secret,
+    // Location where packs are downloaded and their chunks extracted.
+    workspace: tempfile::TempDir,
+    // Number of packs whose chunks are kept in the workspace.
+    capacity: usize,
+    // Those packs that have been fetched, least recently used first.
+    downloaded: VecDeque<Checksum>,
+}
+
+impl PackCache {
+    /// Prepare to fetch packs for the dataset, keeping the chunks of at most
+    /// `capacity` packs within a temporary directory in the dataset workspace.
+    pub fn new(
+        dbase: Arc<dyn RecordRepository>,
+        dataset: &Dataset,
+        passphrase: Secret,
+        capacity: usize,
+    ) -> Result<Self, Error> {
+        let stores = dbase.load_dataset_stores(dataset)?;
+        fs::create_dir_all(&dataset.workspace)?;
+        let workspace = tempfile::TempDir::new_in(&dataset.workspace)?;
+        Ok(Self {
+            dbase,
+            stores,
+            passphrase,
+            workspace,
+            capacity: capacity.max(1),
+            downloaded: VecDeque::new(),
+        })
+    }
+
+    // Find the digest of the pack containing the chunk.
+    fn find_pack(&self, chunk_digest: &Checksum) -> Result<Checksum, Error> {
+        let chunk = self
+            .dbase
+            .get_chunk(chunk_digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingChunk).with("digest", &chunk_digest))?;
+        chunk
+            .packfile
+            .ok_or_else(|| anyhow!(format!("chunk without pack: {:?}", chunk_digest)))
+    }
+
+    // Directory into which the chunks of the pack are extracted.
+    fn pack_dir(&self, pack_digest: &Checksum) -> PathBuf {
+        self.workspace.path().join(pack_digest.to_string())
+    }
+
+    // Ensure the pack has been downloaded and its chunks extracted, evicting
+    // the least recently used pack if the cache is full.
+    fn fetch_pack(&mut self, pack_digest: &Checksum) -> Result<(), Error> {
+        if let Some(index) = self.downloaded.iter().position(|d| d == pack_digest) {
+            let digest = self.downloaded.remove(index).unwrap();
+            self.downloaded.push_back(digest);
+            return Ok(());
+        }
+        if self.downloaded.len() >= self.capacity {
+            if let Some(oldest) = self.downloaded.pop_front() {
+                fs::remove_dir_all(self.pack_dir(&oldest))?;
+            }
+        }
+        let pack_dir = self.pack_dir(pack_digest);
+        fs::create_dir_all(&pack_dir)?;
+        restore::fetch_pack(
+            self.dbase.as_ref(),
+            self.stores.as_ref(),
+            pack_digest,
+            &pack_dir,
+            self.passphrase.expose(),
+            RestoreVerify::default(),
+        )?;
+        self.downloaded.push_back(pack_digest.to_owned());
+        Ok(())
+    }
+
+    // Read from the extracted chunk file at the given offset within the chunk.
+    fn read_chunk(
+        &mut self,
+        pack_digest: &Checksum,
+        chunk: &Checksum,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), Error> {
+        self.fetch_pack(pack_digest)?;
+        let path = self.pack_dir(pack_digest).join(chunk.to_string());
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
+}
+
+// Accept connections one at a time until the export is stopped. The name is
+// the secret of the export and is never logged.
+fn serve<S: BlockSource>(
+    listener: TcpListener,
+    name: &str,
+    mut source: S,
+    stop: &AtomicBool,
+    client: &Mutex<Option<TcpStream>>,
+) {
+    let local_addr = listener
+        .local_addr()
+        .map_or_else(|_| String::from("unknown"), |a| a.to_string());
+    for incoming in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        match incoming {
+            Ok(mut stream) => {
+                *client.lock().unwrap() = stream.try_clone().ok();
+                if let Err(err) = serve_client(&mut stream, name, &mut source, stop) {
+                    warn!("export {}: client error: {}", local_addr, err);
+                }
+                *client.lock().unwrap() = None;
+            }
+            Err(err) => error!("export {}: accept error: {}", local_addr, err),
+        }
+    }
+    info!("export {} stopped", local_addr);
+}
+
+///
+/// Conduct the handshake with the client, then answer its requests until it
+/// disconnects or the export is stopped.
+///
+pub fn serve_client<T: Read + Write, S: BlockSource>(
+    stream: &mut T,
+    name: &str,
+    source: &mut S,
+    stop: &AtomicBool,
+) -> Result<(), Error> {
+    if negotiate(stream, name, source.size())? {
+        transmit(stream, source, stop)?;
+    }
+    Ok(())
+}
+
+// Perform the fixed newstyle handshake and option haggling, returning true if
+// the client selected the export and is ready to send requests.
+fn negotiate<T: Read + Write>(stream: &mut T, name: &str, size: u64) -> Result<bool, Error> {
+    stream.write_all(&NBD_MAGIC.to_be_bytes())?;
+    stream.write_all(&IHAVEOPT.to_be_bytes())?;
+    stream.write_all(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes())?;
+    stream.flush()?;
+    let client_flags = read_u32(stream)?;
+    let no_zeroes = client_flags & FLAG_C_NO_ZEROES != 0;
+    let flags = TRANS_HAS_FLAGS | TRANS_READ_ONLY;
+    loop {
+        if read_u64(stream)? != IHAVEOPT {
+            return Err(anyhow!("invalid option magic"));
+        }
+        let option = read_u32(stream)?;
+        let length = read_u32(stream)?;
+        if length > MAX_OPTION_LENGTH {
+            return Err(anyhow!("option data too long"));
+        }
+        let mut data = vec![0; length as usize];
+        stream.read_exact(&mut data)?;
+        match option {
+            OPT_EXPORT_NAME => {
+                // this option has no means of reporting an error other than
+                // closing the connection
+                if !name_matches(&data, name) {
+                    return Err(anyhow!("client requested an unknown export"));
+                }
+                stream.write_all(&size.to_be_bytes())?;
+                stream.write_all(&flags.to_be_bytes())?;
+                if !no_zeroes {
+                    stream.write_all(&[0; 124])?;
+                }
+                stream.flush()?;
+                return Ok(true);
+            }
+            OPT_ABORT => {
+                send_option_reply(stream, option, REP_ACK, &[])?;
+                return Ok(false);
+            }
+            OPT_LIST => {
+                // listing the exports would give away the secret name
+                send_option_reply(stream, option, REP_ERR_POLICY, &[])?;
+            }
+            OPT_INFO | OPT_GO => {
+                if data.len() < 6 {
+                    send_option_reply(stream, option, REP_ERR_INVALID, &[])?;
+                    continue;
+                }
+                let name_len = u32::from_be_bytes(data[0..4].try_into()?) as usize;
+                if data.len() < 4 + name_len + 2 {
+                    send_option_reply(stream, option, REP_ERR_INVALID, &[])?;
+                    continue;
+                }
+                // there is no default export, the name must be given
+                let requested = &data[4..4 + name_len];
+                if !name_matches(requested, name) {
+                    send_option_reply(stream, option, REP_ERR_UNKNOWN, &[])?;
+                    continue;
+                }
+                let mut reply: Vec<u8> = Vec::new();
+                reply.extend_from_slice(&INFO_EXPORT.to_be_bytes());
+                reply.extend_from_slice(&size.to_be_bytes());
+                reply.extend_from_slice(&flags.to_be_bytes());
+                send_option_reply(stream, option, REP_INFO, &reply)?;
+                send_option_reply(stream, option, REP_ACK, &[])?;
+                if option == OPT_GO {
+                    return Ok(true);
+                }
+            }
+            _ => send_option_reply(stream, option, REP_ERR_UNSUP, &[])?,
+        }
+    }
+}
+
+// Compare the requested export name with the secret name in constant time.
+fn name_matches(requested: &[u8], name: &str) -> bool {
+    let name = name.as_bytes();
+    requested.len() == name.len()
+        && requested
+            .iter()
+            .zip(name.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// Answer requests from the client until it disconnects.
+fn transmit<T: Read + Write, S: BlockSource>(
+    stream: &mut T,
+    source: &mut S,
+    stop: &AtomicBool,
+) -> Result<(), Error> {
+    let mut header = [0u8; 28];
+    while !stop.load(Ordering::SeqCst) {
+        if let Err(err) = stream.read_exact(&mut header) {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(Error::from(err));
+        }
+        if u32::from_be_bytes(header[0..4].try_into()?) != REQUEST_MAGIC {
+            return Err(anyhow!("invalid request magic"));
+        }
+        let command = u16::from_be_bytes(header[6..8].try_into()?);
+        let handle = &header[8..16];
+        let offset = u64::from_be_bytes(header[16..24].try_into()?);
+        let length = u32::from_be_bytes(header[24..28].try_into()?);
+        match command {
+            CMD_READ => {
+                let end = offset.checked_add(length as u64);
+                if length > MAX_READ_LENGTH || end.map(|e| e > source.size()).unwrap_or(true) {
+                    send_simple_reply(stream, EINVAL, handle, &[])?;
+                    continue;
+                }
+                let mut buf = vec![0; length as usize];
+                match source.read_at(offset, &mut buf) {
+                    Ok(()) => send_simple_reply(stream, 0, handle, &buf)?,
+                    Err(err) => {
+                        error!("export: error reading at {}: {}", offset, err);
+                        send_simple_reply(stream, EIO, handle, &[])?;
+                    }
+                }
+            }
+            CMD_WRITE => {
+                // discard the payload, the export is read-only
+                io::copy(&mut stream.by_ref().take(length as u64), &mut io::sink())?;
+                send_simple_reply(stream, EPERM, handle, &[])?;
+            }
+            CMD_DISC => return Ok(()),
+            CMD_FLUSH => send_simple_reply(stream, 0, handle, &[])?,
+            _ => send_simple_reply(stream, EINVAL, handle, &[])?,
+        }
+    }
+    Ok(())
+}
+
+fn send_option_reply<T: Write>(
+    stream: &mut T,
+    option: u32,
+    reply_type: u32,
+    data: &[u8],
+) -> Result<(), Error> {
+    stream.write_all(&REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&option.to_be_bytes())?;
+    stream.write_all(&reply_type.to_be_bytes())?;
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn send_simple_reply<T: Write>(
+    stream: &mut T,
+    error: u32,
+    handle: &[u8],
+    data: &[u8],
+) -> Result<(), Error> {
+    stream.write_all(&SIMPLE_REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&error.to_be_bytes())?;
+    stream.write_all(handle)?;
+    stream.write_all(data)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_u32<T: Read>(stream: &mut T) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<T: Read>(stream: &mut T) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Content held in memory, for testing the protocol.
+    struct MemorySource(Vec<u8>);
+
+    impl BlockSource for MemorySource {
+        fn size(&self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+            let start = offset as usize;
+            buf.copy_from_slice(&self.0[start..start + buf.len()]);
+            Ok(())
+        }
+    }
+
+    // Scripted client input paired with a buffer for the server output.
+    struct Conversation {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Conversation {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Conversation {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(command: u16, handle: u64, offset: u64, length: u32) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(&REQUEST_MAGIC.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&command.to_be_bytes());
+        buf.extend_from_slice(&handle.to_be_bytes());
+        buf.extend_from_slice(&offset.to_be_bytes());
+        buf.extend_from_slice(&length.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_serve_client_go_and_read() -> Result<(), Error> {
+        // arrange
+        let content: Vec<u8> = (0..=255).collect();
+        let mut source = MemorySource(content);
+        let mut input: Vec<u8> = Vec::new();
+        input.extend_from_slice(&FLAG_C_NO_ZEROES.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_GO.to_be_bytes());
+        input.extend_from_slice(&9u32.to_be_bytes());
+        input.extend_from_slice(&3u32.to_be_bytes());
+        input.extend_from_slice(b"img");
+        input.extend_from_slice(&0u16.to_be_bytes());
+        input.extend(request(CMD_READ, 7, 16, 4));
+        input.extend(request(CMD_WRITE, 8, 0, 2));
+        input.extend_from_slice(&[1, 2]);
+        input.extend(request(CMD_READ, 9, 250, 10));
+        input.extend(request(CMD_DISC, 10, 0, 0));
+        let mut conversation = Conversation {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+        let stop = AtomicBool::new(false);
+        // act
+        serve_client(&mut conversation, "img", &mut source, &stop)?;
+        // assert
+        let output = conversation.output;
+        assert_eq!(&output[0..8], &NBD_MAGIC.to_be_bytes());
+        assert_eq!(&output[8..16], &IHAVEOPT.to_be_bytes());
+        // info reply with export size and flags
+        let info = &output[18..];
+        assert_eq!(&info[0..8], &REPLY_MAGIC.to_be_bytes());
+        assert_eq!(&info[12..16], &REP_INFO.to_be_bytes());
+        assert_eq!(&info[16..20], &12u32.to_be_bytes());
+        assert_eq!(&info[22..30], &256u64.to_be_bytes());
+        assert_eq!(&info[30..32], &3u16.to_be_bytes());
+        // acknowledgement
+        let ack = &info[32..];
+        assert_eq!(&ack[12..16], &REP_ACK.to_be_bytes());
+        // successful read
+        let read = &ack[20..];
+        assert_eq!(&read[0..4], &SIMPLE_REPLY_MAGIC.to_be_bytes());
+        assert_eq!(&read[4..8], &0u32.to_be_bytes());
+        assert_eq!(&read[8..16], &7u64.to_be_bytes());
+        assert_eq!(&read[16..20], &[16, 17, 18, 19]);
+        // rejected write
+        let write = &read[20..];
+        assert_eq!(&write[4..8], &EPERM.to_be_bytes());
+        assert_eq!(&write[8..16], &8u64.to_be_bytes());
+        // read beyond the end
+        let invalid = &write[16..];
+        assert_eq!(&invalid[4..8], &EINVAL.to_be_bytes());
+        assert_eq!(&invalid[8..16], &9u64.to_be_bytes());
+        assert_eq!(invalid.len(), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_client_unknown_export() -> Result<(), Error> {
+        // arrange
+        let mut source = MemorySource(vec![0; 512]);
+        let mut input: Vec<u8> = Vec::new();
+        input.extend_from_slice(&0u32.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_EXPORT_NAME.to_be_bytes());
+        input.extend_from_slice(&5u32.to_be_bytes());
+        input.extend_from_slice(b"other");
+        let mut conversation = Conversation {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+        let stop = AtomicBool::new(false);
+        // act
+        let result = serve_client(&mut conversation, "img", &mut source, &stop);
+        // assert
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_client_secret_name() -> Result<(), Error> {
+        // arrange
+        let mut source = MemorySource(vec![0; 512]);
+        let mut input: Vec<u8> = Vec::new();
+        input.extend_from_slice(&FLAG_C_NO_ZEROES.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_LIST.to_be_bytes());
+        input.extend_from_slice(&0u32.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_GO.to_be_bytes());
+        input.extend_from_slice(&6u32.to_be_bytes());
+        input.extend_from_slice(&0u32.to_be_bytes());
+        input.extend_from_slice(&0u16.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_EXPORT_NAME.to_be_bytes());
+        input.extend_from_slice(&0u32.to_be_bytes());
+        let mut conversation = Conversation {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+        let stop = AtomicBool::new(false);
+        // act
+        let result = serve_client(&mut conversation, "img", &mut source, &stop);
+        // assert
+        assert!(result.is_err());
+        // the listing is refused
+        let list = &conversation.output[18..];
+        assert_eq!(&list[12..16], &REP_ERR_POLICY.to_be_bytes());
+        assert_eq!(&list[16..20], &0u32.to_be_bytes());
+        // the default export is unknown
+        let go = &list[20..];
+        assert_eq!(&go[12..16], &REP_ERR_UNKNOWN.to_be_bytes());
+        assert_eq!(go.len(), 20);
+        assert!(name_matches(b"img", "img"));
+        assert!(!name_matches(b"im", "img"));
+        assert!(!name_matches(b"imx", "img"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_client_export_name() -> Result<(), Error> {
+        // arrange
+        let mut source = MemorySource(vec![0; 512]);
+        let mut input: Vec<u8> = Vec::new();
+        input.extend_from_slice(&0u32.to_be_bytes());
+        input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        input.extend_from_slice(&OPT_EXPORT_NAME.to_be_bytes());
+        input.extend_from_slice(&3u32.to_be_bytes());
+        input.extend_from_slice(b"img");
+        let mut conversation = Conversation {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        };
+        let stop = AtomicBool::new(false);
+        // act
+        serve_client(&mut conversation, "img", &mut source, &stop)?;
+        // assert
+        let output = &conversation.output[18..];
+        assert_eq!(&output[0..8], &512u64.to_be_bytes());
+        assert_eq!(&output[8..10], &3u16.to_be_bytes());
+        // without the no-zeroes flag, the reply is padded
+        assert_eq!(output.len(), 10 + 124);
+        Ok(())
+    }
+}
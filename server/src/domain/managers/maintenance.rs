@@ -0,0 +1,886 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `maintenance` module runs housekeeping tasks, such as verifying a
+//! sample of the packs and compacting the database, without any user
+//! interaction during a daily maintenance window. Verification also checks
+//! the sentinel files of those datasets that have them.
+//!
+//! The window is defined by the `MAINTENANCE_WINDOW` setting in the form
+//! `HH:MM-HH:MM` (UTC), and the tasks queued at the start of each window by
+//! `MAINTENANCE_TASKS`, a comma-separated list of task names. Tasks may also be
+//! queued at any time to run in the next window. No task is started while a
+//! backup is running, or while the time range of a backup schedule is in
+//! effect, and packs are not retrieved from stores that have reached their
+//! monthly transfer cap.
+//!
+//! Some tasks are instead scheduled for each dataset, by a dataset property
+//! such as `prune_schedule`, whose value is one of `hourly`, `daily`,
+//! `weekly`, or `monthly`. Such a task is due when it has never run, or when
+//! the schedule has elapsed since it last finished, regardless of the window.
+//! The outcome of the most recent run of each is saved in the database.
+//!
+//! Every backup holds a shared lock on the packs while it runs, which those
+//! operations that remove packs from the stores, pruning and compaction, must
+//! acquire exclusively. A backup does not start while the packs are locked,
+//! and the packs cannot be locked while any backup is running, as its packs
+//! are not yet recorded in the database.
+
+use crate::domain::entities::schedule::TimeRange;
+use crate::domain::entities::{
+    Checksum, Dataset, MaintenanceResult, MaintenanceTask, Message, MessageCode, Pack, Store,
+    VerifyDepth,
+};
+use crate::domain::helpers::crypto;
+use crate::domain::managers::catalog;
+use crate::domain::managers::notify;
+use crate::domain::managers::progress::{OperationKind, Progress, Reporter};
+use crate::domain::managers::restore::FileRestorerImpl;
+use crate::domain::managers::sentinel;
+use crate::domain::managers::settings;
+use crate::domain::managers::state::StateStore;
+use crate::domain::repositories::RecordRepository;
+use crate::domain::usecases::prune_extra::expected_packs;
+use crate::domain::usecases::prune_snapshots::prune_snapshots;
+use crate::domain::usecases::verify_snapshot::{self, verify_snapshot};
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+// Tasks queued at the start of each window if `MAINTENANCE_TASKS` is not set.
+// Pruning deletes objects from the stores and thus must be chosen explicitly.
+const DEFAULT_TASKS: &[MaintenanceTask] = &[
+    MaintenanceTask::Verify,
+    MaintenanceTask::Compact,
+    MaintenanceTask::Health,
+];
+
+// Number of packs to verify if `MAINTENANCE_SAMPLE` is not set.
+const DEFAULT_SAMPLE_SIZE: usize = 4;
+
+// Number of task outcomes to retain.
+const MAX_RESULTS: usize = 50;
+
+lazy_static! {
+    // Tasks waiting for the maintenance window.
+    static ref QUEUE: Mutex<VecDeque<MaintenanceTask>> = Mutex::new(VecDeque::new());
+    // Outcomes of the most recent tasks, oldest first.
+    static ref RESULTS: Mutex<VecDeque<MaintenanceResult>> = Mutex::new(VecDeque::new());
+    // Date on which the configured tasks were most recently queued.
+    static ref LAST_QUEUED: Mutex<Option<NaiveDate>> = Mutex::new(None);
+    // Held while tasks are running to prevent overlapping runs.
+    static ref RUNNING: Mutex<()> = Mutex::new(());
+    // Shared by the running backups, held exclusively while removing packs.
+    static ref PACKS: RwLock<()> = RwLock::new(());
+    // Held while removing packs, such that pruning and compaction take turns
+    // rather than failing for want of the exclusive lock.
+    static ref REMOVING: Mutex<()> = Mutex::new(());
+}
+
+///
+/// Exclusive lock on the packs, released when dropped.
+///
+pub struct PacksGuard {
+    _packs: RwLockWriteGuard<'static, ()>,
+    _removing: MutexGuard<'static, ()>,
+}
+
+///
+/// Acquire a shared lock on the packs for the duration of a backup, or `None`
+/// if the packs are being pruned or compacted.
+///
+pub fn share_packs() -> Option<RwLockReadGuard<'static, ()>> {
+    match PACKS.try_read() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+///
+/// Acquire the exclusive lock on the packs for removing packs from the
+/// stores, or `None` if any backup is running. Waits for any other operation
+/// that is removing packs to finish first.
+///
+pub fn lock_packs() -> Option<PacksGuard> {
+    let removing = REMOVING.lock().unwrap_or_else(|err| err.into_inner());
+    let packs = match PACKS.try_write() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        Err(TryLockError::WouldBlock) => return None,
+    };
+    Some(PacksGuard {
+        _packs: packs,
+        _removing: removing,
+    })
+}
+
+///
+/// Return the maintenance window as defined by `MAINTENANCE_WINDOW`, if any.
+///
+pub fn window() -> Option<TimeRange> {
+    let value = settings::var("MAINTENANCE_WINDOW").ok()?;
+    match value.parse::<TimeRange>() {
+        Ok(range) => Some(range),
+        Err(err) => {
+            warn!("maintenance: ignoring MAINTENANCE_WINDOW: {}", err);
+            None
+        }
+    }
+}
+
+///
+/// Return the maintenance window if pruning is among the tasks that will run
+/// in it, during which the packs may be locked and backups kept from starting.
+///
+pub fn pruning_window() -> Option<TimeRange> {
+    let pruning = configured_tasks()
+        .into_iter()
+        .chain(queued())
+        .any(|t| t == MaintenanceTask::Prune);
+    if pruning {
+        window()
+    } else {
+        None
+    }
+}
+
+///
+/// Return the tasks to be queued at the start of each maintenance window.
+///
+pub fn configured_tasks() -> Vec<MaintenanceTask> {
+    match settings::var("MAINTENANCE_TASKS") {
+        Ok(value) => parse_tasks(&value),
+        Err(_) => DEFAULT_TASKS.to_vec(),
+    }
+}
+
+///
+/// Add the task to the queue, unless it is already waiting to run.
+///
+pub fn queue(task: MaintenanceTask) {
+    let mut queue = QUEUE.lock().unwrap();
+    if !queue.contains(&task) {
+        queue.push_back(task);
+    }
+}
+
+///
+/// Return the tasks waiting for the maintenance window.
+///
+pub fn queued() -> Vec<MaintenanceTask> {
+    let queue = QUEUE.lock().unwrap();
+    queue.iter().copied().collect()
+}
+
+///
+/// Return the outcomes of the most recent tasks, newest first.
+///
+pub fn last_results() -> Vec<MaintenanceResult> {
+    let results = RESULTS.lock().unwrap();
+    results.iter().rev().cloned().collect()
+}
+
+///
+/// Run the tasks of each dataset whose schedules are due, then the queued
+/// tasks if the maintenance window is open, stopping early if the window
+/// closes or a backup begins. The configured tasks are queued the first time
+/// this is called in each window.
+///
+pub fn run_due(
+    repo: &Arc<dyn RecordRepository>,
+    state: &dyn StateStore,
+) -> Result<Vec<MaintenanceResult>, Error> {
+    let mut results: Vec<MaintenanceResult> = Vec::new();
+    let _guard = match RUNNING.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => return Ok(results),
+    };
+    run_dataset_tasks(repo, state, &mut results)?;
+    let range = match window() {
+        Some(range) => range,
+        None => return Ok(results),
+    };
+    if !range.is_within(Utc::now()) {
+        return Ok(results);
+    }
+    queue_daily(Utc::now().date_naive());
+    loop {
+        let now = Utc::now();
+        if !range.is_within(now) || blacked_out(repo.as_ref(), state, now)? {
+            break;
+        }
+        let task = match QUEUE.lock().unwrap().pop_front() {
+            Some(task) => task,
+            None => break,
+        };
+        results.push(run_task(repo.as_ref(), task));
+    }
+    Ok(results)
+}
+
+///
+/// Perform the given system-wide task immediately, recording the outcome.
+///
+pub fn run_task(repo: &dyn RecordRepository, task: MaintenanceTask) -> MaintenanceResult {
+    let kind = match task {
+        MaintenanceTask::Verify => OperationKind::Verify,
+        MaintenanceTask::Prune => OperationKind::Prune,
+        _ => OperationKind::Maintenance,
+    };
+    let progress = Reporter::new(kind, &task.to_string());
+    let outcome = match task {
+        MaintenanceTask::Verify => verify_all(repo, &progress),
+        MaintenanceTask::Prune => prune_stores(repo, &progress),
+        MaintenanceTask::Compact => repo
+            .compact_database()
+            .map(|_| String::from("database compacted")),
+        MaintenanceTask::Health => check_stores(repo, &progress),
+        _ => Err(anyhow!(format!("task {} requires a dataset", task))),
+    };
+    let result = record(MaintenanceResult::new(task), outcome);
+    progress.finish(result.error.clone());
+    notify::maintenance_finished(repo, &result);
+    result
+}
+
+///
+/// Perform the given task for the dataset immediately, recording the outcome.
+///
+pub fn run_dataset_task(
+    repo: &Arc<dyn RecordRepository>,
+    state: &dyn StateStore,
+    dataset: &Dataset,
+    task: MaintenanceTask,
+) -> MaintenanceResult {
+    let outcome = match task {
+        MaintenanceTask::PruneSnapshots => {
+            prune_snapshots(repo.as_ref(), state, &dataset.id, false)
+                .map(|removed| format!("removed {} snapshots", removed.len()))
+        }
+        MaintenanceTask::VerifySnapshot => verify_latest(repo, dataset),
+        MaintenanceTask::UploadDatabase => upload_database(repo.as_ref(), dataset),
+        _ => Err(anyhow!(format!("task {} is not run for a dataset", task))),
+    };
+    let result = record(MaintenanceResult::new(task).dataset(&dataset.id), outcome);
+    notify::maintenance_finished(repo.as_ref(), &result);
+    result
+}
+
+// Fill in the result from the outcome of the task, logging it and retaining
+// it for `last_results()`.
+fn record(mut result: MaintenanceResult, outcome: Result<String, Error>) -> MaintenanceResult {
+    let subject = match result.dataset_id.as_ref() {
+        Some(dataset_id) => format!("{} of {}", result.task, dataset_id),
+        None => result.task.to_string(),
+    };
+    match outcome {
+        Ok(summary) => {
+            info!("maintenance: {}: {}", subject, summary);
+            result.summary = summary;
+        }
+        Err(err) => {
+            error!("maintenance: {} failed: {}", subject, err);
+            result.error = Some(err.to_string());
+        }
+    }
+    result.finished = Utc::now();
+    let mut results = RESULTS.lock().unwrap();
+    results.push_back(result.clone());
+    while results.len() > MAX_RESULTS {
+        results.pop_front();
+    }
+    result
+}
+
+// Run those tasks of each dataset whose schedules are due, saving the outcome
+// of each in the database such that a restart does not cause every task to
+// run again.
+fn run_dataset_tasks(
+    repo: &Arc<dyn RecordRepository>,
+    state: &dyn StateStore,
+    results: &mut Vec<MaintenanceResult>,
+) -> Result<(), Error> {
+    for dataset in repo.get_datasets()? {
+        if dataset.archived.is_some() {
+            continue;
+        }
+        let previous = repo.get_task_runs(&dataset.id)?;
+        for task in MaintenanceTask::DATASET_TASKS {
+            let now = Utc::now();
+            if !is_due(&dataset, task, &previous, now) || blacked_out(repo.as_ref(), state, now)? {
+                continue;
+            }
+            let result = run_dataset_task(repo, state, &dataset, task);
+            repo.put_task_run(&result)?;
+            results.push(result);
+        }
+    }
+    Ok(())
+}
+
+// Return true if the task has a schedule, and either has never run or the
+// schedule has elapsed since it last finished.
+fn is_due(
+    dataset: &Dataset,
+    task: MaintenanceTask,
+    previous: &[MaintenanceResult],
+    now: DateTime<Utc>,
+) -> bool {
+    let Some(schedule) = dataset.task_schedule(task) else {
+        return false;
+    };
+    let elapsed = match previous.iter().find(|r| r.task == task) {
+        Some(last) => schedule.past_due_at(last.finished, now),
+        None => true,
+    };
+    elapsed && schedule.within_range(now)
+}
+
+// Queue the configured tasks if that has not yet happened on the given date.
+fn queue_daily(today: NaiveDate) {
+    let mut last = LAST_QUEUED.lock().unwrap();
+    if *last != Some(today) {
+        *last = Some(today);
+        for task in configured_tasks() {
+            queue(task);
+        }
+    }
+}
+
+// Return true if a backup is running, or the time range of a backup schedule
+// is in effect, in which case the time is reserved for backups.
+fn blacked_out(
+    repo: &dyn RecordRepository,
+    state: &dyn StateStore,
+    now: DateTime<Utc>,
+) -> Result<bool, Error> {
+    let redux = state.get_state();
+    for dataset in repo.get_datasets()? {
+        if let Some(backup) = redux.backups(&dataset.id) {
+            if backup.end_time().is_none() && !backup.is_paused() {
+                return Ok(true);
+            }
+        }
+        if dataset.archived.is_some() {
+            continue;
+        }
+        for schedule in dataset.schedules.iter() {
+            if schedule.stop_time(now).is_some() && schedule.within_range(now) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Parse the comma-separated list of task names, ignoring unknown names and
+// those of the tasks that are scheduled for each dataset.
+fn parse_tasks(value: &str) -> Vec<MaintenanceTask> {
+    let mut tasks: Vec<MaintenanceTask> = Vec::new();
+    for name in value.split(',').filter(|n| !n.trim().is_empty()) {
+        match MaintenanceTask::from_str(name) {
+            Ok(task) if task.schedule_property().is_some() => {
+                warn!("maintenance: ignoring dataset task: {}", task)
+            }
+            Ok(task) if !tasks.contains(&task) => tasks.push(task),
+            Ok(_) => (),
+            Err(err) => warn!("maintenance: ignoring task: {}", err),
+        }
+    }
+    tasks
+}
+
+// Return the number of packs to verify, as given by `MAINTENANCE_SAMPLE`.
+fn sample_size() -> usize {
+    settings::var("MAINTENANCE_SAMPLE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_SAMPLE_SIZE)
+}
+
+// Return the identifiers of the stores that have reached their monthly cap.
+fn capped_stores(repo: &dyn RecordRepository, stores: &[Store]) -> Result<HashSet<String>, Error> {
+    let mut capped: HashSet<String> = HashSet::new();
+    for store in stores {
+        if let Some(cap) = store.monthly_cap() {
+            if repo.get_bandwidth_usage(&store.id)?.total() >= cap {
+                capped.insert(store.id.clone());
+            }
+        }
+    }
+    Ok(capped)
+}
+
+///
+/// Chooses up to count items at random from any number of items, such as
+/// packs, without holding more than the chosen items in memory (reservoir
+/// sampling).
+///
+pub struct Reservoir<T> {
+    count: usize,
+    seen: usize,
+    sample: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    /// Construct a reservoir that will choose up to count items.
+    pub fn new(count: usize) -> Self {
+        Self {
+            count,
+            seen: 0,
+            sample: Vec::new(),
+        }
+    }
+
+    /// Consider the item for inclusion in the sample.
+    pub fn offer(&mut self, item: T) {
+        self.seen += 1;
+        if self.sample.len() < self.count {
+            self.sample.push(item);
+        } else {
+            let index = (uuid::Uuid::new_v4().as_u128() % self.seen as u128) as usize;
+            if index < self.count {
+                self.sample[index] = item;
+            }
+        }
+    }
+
+    /// Return the chosen items, in no particular order.
+    pub fn into_sample(self) -> Vec<T> {
+        self.sample
+    }
+}
+
+// Verify a sample of the packs, as well as the sentinel files of the datasets
+// that have them, reporting the failures of either.
+fn verify_all(repo: &dyn RecordRepository, progress: &dyn Progress) -> Result<String, Error> {
+    let packs = verify_sample(repo, sample_size(), progress);
+    let sentinels = crypto::get_passphrase()
+        .and_then(|passphrase| sentinel::verify_datasets(repo, passphrase.expose()));
+    match (packs, sentinels) {
+        (Ok(summary), Ok(0)) => Ok(summary),
+        (Ok(summary), Ok(count)) => Ok(format!("{}, {} sentinel files", summary, count)),
+        (Err(err), Ok(_)) | (Ok(_), Err(err)) => Err(err),
+        (Err(first), Err(second)) => Err(anyhow!(format!("{}; {}", first, second))),
+    }
+}
+
+// Retrieve a random sample of packs and compare their checksums with the
+// database records.
+fn verify_sample(
+    repo: &dyn RecordRepository,
+    count: usize,
+    progress: &dyn Progress,
+) -> Result<String, Error> {
+    let stores = repo.get_stores()?;
+    let capped = capped_stores(repo, &stores)?;
+    let mut reservoir = Reservoir::new(count);
+    repo.visit_packs(&mut |pack| {
+        if pack.digest.is_blake3() && pack.locations.iter().any(|l| !capped.contains(&l.store)) {
+            reservoir.offer(pack);
+        }
+        Ok(true)
+    })?;
+    let sample = reservoir.into_sample();
+    progress.begin(Some(sample.len() as u64));
+    let mut failed: Vec<String> = Vec::new();
+    for pack in sample.iter() {
+        match verify_pack(repo, &stores, &capped, pack) {
+            Ok(size) => progress.advance(1, size),
+            Err(err) => {
+                error!(
+                    "maintenance: pack {} failed verification: {}",
+                    pack.digest, err
+                );
+                failed.push(pack.digest.to_string());
+                progress.advance(1, 0);
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(format!("verified {} packs", sample.len()))
+    } else {
+        Err(anyhow!(format!(
+            "{} of {} packs failed verification: {}",
+            failed.len(),
+            sample.len(),
+            failed.join(", ")
+        )))
+    }
+}
+
+// Retrieve the pack from one of the stores that is not capped and verify the
+// checksum of the retrieved file, returning the size of the pack.
+fn verify_pack(
+    repo: &dyn RecordRepository,
+    stores: &[Store],
+    capped: &HashSet<String>,
+    pack: &Pack,
+) -> Result<u64, Error> {
+    let location = pack
+        .locations
+        .iter()
+        .find(|l| !capped.contains(&l.store))
+        .ok_or_else(|| anyhow!("all stores have reached their transfer cap"))?;
+    let store = stores
+        .iter()
+        .find(|s| s.id == location.store)
+        .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &location.store))?;
+    let pack_repo = repo.build_pack_repo(store)?;
+    let outfile = tempfile::NamedTempFile::new()?.into_temp_path();
+    pack_repo.retrieve_pack(&[location.to_owned()], &pack.digest, &outfile)?;
+    let actual = Checksum::blake3_from_file(&outfile)?;
+    if actual != pack.digest {
+        return Err(anyhow!(format!(
+            "pack digest does not match: {} != {}",
+            actual, pack.digest
+        )));
+    }
+    Ok(std::fs::metadata(&outfile)?.len())
+}
+
+// Verify the latest snapshot of the dataset to the depth given by the dataset
+// properties, treating any problems found as a failure of the task.
+fn verify_latest(repo: &Arc<dyn RecordRepository>, dataset: &Dataset) -> Result<String, Error> {
+    let Some(digest) = repo.get_latest_snapshot(&dataset.id)? else {
+        return Ok(String::from("no snapshots to verify"));
+    };
+    let depth = dataset.verify_depth();
+    let passphrase = if depth == VerifyDepth::Full {
+        Some(crypto::get_passphrase()?)
+    } else {
+        None
+    };
+    let params = verify_snapshot::Params::new(
+        dataset.id.clone(),
+        digest,
+        depth,
+        dataset.verify_sample(),
+        passphrase,
+    );
+    let mut fetcher = FileRestorerImpl::new(repo.clone());
+    let verification = verify_snapshot(repo.as_ref(), &mut fetcher, params)?;
+    if verification.passed() {
+        Ok(format!(
+            "verified {} files and {} packs of {}",
+            verification.files, verification.packs, verification.snapshot
+        ))
+    } else {
+        Err(anyhow!(format!(
+            "found {} issues in {}",
+            verification.issues.len(),
+            verification.snapshot
+        )))
+    }
+}
+
+// Upload the changes to the database to the stores of the dataset, as is done
+// at the end of each backup.
+fn upload_database(repo: &dyn RecordRepository, dataset: &Dataset) -> Result<String, Error> {
+    let computer_id = repo
+        .get_computer_id(&dataset.id)?
+        .ok_or_else(|| anyhow!("dataset has not been backed up"))?;
+    let passphrase = crypto::get_passphrase()?;
+    std::fs::create_dir_all(&dataset.workspace)?;
+    let catalogs = catalog::save(
+        repo,
+        &dataset.stores,
+        &computer_id,
+        passphrase.expose(),
+        &dataset.workspace,
+    )?;
+    let files = catalogs.first().map(|c| c.files.len()).unwrap_or(0);
+    Ok(format!(
+        "uploaded {} database files to {} stores",
+        files,
+        catalogs.len()
+    ))
+}
+
+// Remove the objects in each store that are not referenced by any pack.
+fn prune_stores(repo: &dyn RecordRepository, progress: &dyn Progress) -> Result<String, Error> {
+    // the packs of a running backup are not yet recorded in the database
+    let _guard =
+        lock_packs().ok_or_else(|| anyhow!("cannot prune stores while backup is running"))?;
+    let mut total: u32 = 0;
+    let stores = repo.get_stores()?;
+    progress.begin(Some(stores.len() as u64));
+    for store in stores {
+        // include the database snapshots, lest they be removed
+        let all_packs = expected_packs(repo, &store.id)?;
+        let pack_repo = repo.build_pack_repo(&store)?;
+        total += pack_repo.prune_extra(&store.id, &all_packs)?;
+        progress.advance(1, 0);
+    }
+    Ok(format!("removed {} extraneous packs", total))
+}
+
+// Test the basic connectivity of each store.
+fn check_stores(repo: &dyn RecordRepository, progress: &dyn Progress) -> Result<String, Error> {
+    let stores = repo.get_stores()?;
+    progress.begin(Some(stores.len() as u64));
+    let mut failed: Vec<String> = Vec::new();
+    for store in stores.iter() {
+        let outcome = repo
+            .build_pack_repo(store)
+            .and_then(|pack_repo| pack_repo.test_store(&store.id));
+        if let Err(err) = outcome {
+            error!(
+                "maintenance: store {} failed health check: {}",
+                store.id, err
+            );
+            failed.push(format!("{} ({})", store.label, err));
+        }
+        progress.advance(1, 0);
+    }
+    if failed.is_empty() {
+        Ok(format!("{} stores are reachable", stores.len()))
+    } else {
+        Err(anyhow!(format!(
+            "{} of {} stores failed: {}",
+            failed.len(),
+            stores.len(),
+            failed.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{BandwidthUsage, PackLocation, StoreType};
+    use crate::domain::managers::state::{MockStateStore, State};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use chrono::Duration;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn make_store(id: &str, cap: Option<&str>) -> Store {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        if let Some(cap) = cap {
+            properties.insert("monthly_cap".to_owned(), cap.to_owned());
+        }
+        Store {
+            id: id.to_owned(),
+            store_type: StoreType::LOCAL,
+            label: id.to_owned(),
+            properties,
+        }
+    }
+
+    #[test]
+    fn test_parse_tasks() {
+        let tasks = parse_tasks("verify, health,verify,,defrag,prune-snapshots");
+        assert_eq!(
+            tasks,
+            vec![MaintenanceTask::Verify, MaintenanceTask::Health]
+        );
+        assert!(parse_tasks("").is_empty());
+    }
+
+    #[test]
+    fn test_reservoir() {
+        let packs: Vec<Pack> = (0..10)
+            .map(|n| Pack::new(Checksum::BLAKE3(format!("{:04}", n)), vec![]))
+            .collect();
+        let mut reservoir = Reservoir::new(4);
+        for pack in packs.iter() {
+            reservoir.offer(pack.clone());
+        }
+        assert_eq!(reservoir.seen, 10);
+        assert_eq!(reservoir.sample.len(), 4);
+        let unique: HashSet<String> = reservoir
+            .sample
+            .iter()
+            .map(|p| p.digest.to_string())
+            .collect();
+        assert_eq!(unique.len(), 4);
+        let mut reservoir = Reservoir::new(20);
+        for pack in packs {
+            reservoir.offer(pack);
+        }
+        assert_eq!(reservoir.sample.len(), 10);
+    }
+
+    #[test]
+    fn test_capped_stores() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_bandwidth_usage()
+            .withf(|id| id == "metered")
+            .returning(|_| {
+                let mut usage = BandwidthUsage::new("2024-03");
+                usage.uploaded = 2048;
+                Ok(usage)
+            });
+        let stores = vec![
+            make_store("metered", Some("1024")),
+            make_store("free", None),
+        ];
+        // act
+        let capped = capped_stores(&mock, &stores).unwrap();
+        // assert
+        assert_eq!(capped.len(), 1);
+        assert!(capped.contains("metered"));
+    }
+
+    #[test]
+    fn test_verify_sample_mismatch() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_stores()
+            .returning(|| Ok(vec![make_store("local1", None)]));
+        mock.expect_visit_packs().returning(|visitor| {
+            let location = PackLocation::new("local1", "bucket1", "object1");
+            let digest = Checksum::BLAKE3("cafebabe".into());
+            visitor(Pack::new(digest, vec![location]))?;
+            Ok(())
+        });
+        mock.expect_build_pack_repo().returning(|_| {
+            let mut pack_repo = MockPackRepository::new();
+            pack_repo.expect_retrieve_pack().returning(|_, _, outfile| {
+                std::fs::write(outfile, b"not the pack you are looking for")?;
+                Ok(())
+            });
+            Ok(Box::new(pack_repo))
+        });
+        // act
+        let result = verify_sample(&mock, 4, &Reporter::new(OperationKind::Verify, "test"));
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("1 of 1 packs failed"));
+    }
+
+    #[test]
+    fn test_verify_sample_capped() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_stores()
+            .returning(|| Ok(vec![make_store("metered", Some("1024"))]));
+        mock.expect_get_bandwidth_usage().returning(|_| {
+            let mut usage = BandwidthUsage::new("2024-03");
+            usage.downloaded = 4096;
+            Ok(usage)
+        });
+        mock.expect_visit_packs().returning(|visitor| {
+            let location = PackLocation::new("metered", "bucket1", "object1");
+            let digest = Checksum::BLAKE3("cafebabe".into());
+            visitor(Pack::new(digest, vec![location]))?;
+            Ok(())
+        });
+        mock.expect_build_pack_repo().never();
+        // act
+        let result = verify_sample(&mock, 4, &Reporter::new(OperationKind::Verify, "test"));
+        // assert
+        assert_eq!(result.unwrap(), "verified 0 packs");
+    }
+
+    #[test]
+    fn test_run_task_records_result() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_compact_database().returning(|| Ok(()));
+        mock.expect_get_webhooks().times(1).returning(|| Ok(vec![]));
+        // act
+        let result = run_task(&mock, MaintenanceTask::Compact);
+        // assert
+        assert!(result.error.is_none());
+        assert_eq!(result.summary, "database compacted");
+        assert!(last_results()
+            .iter()
+            .any(|r| r.task == MaintenanceTask::Compact && r.error.is_none()));
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+        let task = MaintenanceTask::PruneSnapshots;
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        assert!(!is_due(&dataset, task, &[], now));
+        dataset
+            .properties
+            .insert("prune_schedule".into(), "daily".into());
+        // never run before
+        assert!(is_due(&dataset, task, &[], now));
+        let mut run = MaintenanceResult::new(task).dataset(&dataset.id);
+        run.finished = now - Duration::hours(2);
+        assert!(!is_due(&dataset, task, &[run.clone()], now));
+        run.finished = now - Duration::hours(25);
+        assert!(is_due(&dataset, task, &[run.clone()], now));
+        // runs of other tasks do not matter
+        run.task = MaintenanceTask::VerifySnapshot;
+        run.finished = now;
+        assert!(is_due(&dataset, task, &[run], now));
+    }
+
+    #[test]
+    fn test_run_due_prune_snapshots() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset
+            .properties
+            .insert("prune_schedule".into(), "weekly".into());
+        let mut archived = Dataset::new(Path::new("/home/archive"));
+        archived
+            .properties
+            .insert("prune_schedule".into(), "hourly".into());
+        archived.archived = Some(Utc::now());
+        let datasets = vec![dataset.clone(), archived];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        mock.expect_get_task_runs().returning(|_| Ok(vec![]));
+        let planet = dataset.clone();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(planet.clone())));
+        // the dataset has no snapshots, so there is nothing to prune
+        mock.expect_get_latest_snapshot().returning(|_| Ok(None));
+        let dataset_id = dataset.id.clone();
+        mock.expect_put_task_run()
+            .withf(move |r| {
+                r.dataset_id.as_ref() == Some(&dataset_id)
+                    && r.task == MaintenanceTask::PruneSnapshots
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_get_webhooks().returning(|| Ok(vec![]));
+        let mut state = MockStateStore::new();
+        state.expect_get_state().returning(State::default);
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let result = run_due(&repo, &state);
+        // assert
+        assert!(result.is_ok());
+        let results = result.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].task, MaintenanceTask::PruneSnapshots);
+        assert_eq!(results[0].summary, "removed 0 snapshots");
+        assert!(results[0].error.is_none());
+    }
+
+    #[test]
+    fn test_run_dataset_task_never_backed_up() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_computer_id().returning(|_| Ok(None));
+        mock.expect_get_webhooks().returning(|| Ok(vec![]));
+        let state = MockStateStore::new();
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        // act
+        let task = MaintenanceTask::UploadDatabase;
+        let result = run_dataset_task(&repo, &state, &dataset, task);
+        // assert
+        assert_eq!(result.task, MaintenanceTask::UploadDatabase);
+        assert_eq!(result.dataset_id, Some(dataset.id.clone()));
+        assert!(result.summary.is_empty());
+        assert_eq!(result.error.unwrap(), "dataset has not been backed up");
+    }
+}
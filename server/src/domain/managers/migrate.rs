@@ -0,0 +1,200 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `migrate` module brings the records in the database up to date with
+//! the current version of the application. Each migration is applied once, in
+//! order of version, after which the schema version saved in the database is
+//! advanced and a record of the migration is saved for the `migrations` query.
+//!
+//! A database with a schema version newer than this build understands is
+//! refused, as the application could otherwise corrupt records it does not
+//! know how to read.
+
+use crate::domain::entities::Migration;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use log::info;
+use std::fmt;
+
+// A change to the database records, to be applied exactly once.
+struct Step {
+    // Schema version produced by applying this step.
+    version: u32,
+    // Brief description of the change.
+    name: &'static str,
+    // Function that makes the change.
+    apply: fn(&dyn RecordRepository) -> Result<(), Error>,
+}
+
+// All of the migrations, in order of version. Migrations must not be removed
+// or renumbered once released, new ones are added to the end.
+const STEPS: &[Step] = &[
+    Step {
+        version: 1,
+        name: "record the schema version",
+        apply: baseline,
+    },
+    Step {
+        version: 2,
+        name: "rewrite dataset records with their current fields",
+        apply: rewrite_datasets,
+    },
+];
+
+///
+/// The version of the database schema produced by this build.
+///
+pub const SCHEMA_VERSION: u32 = STEPS[STEPS.len() - 1].version;
+
+///
+/// Raised when the database was written by a newer build of the application.
+///
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub struct SchemaTooNewError {
+    /// Schema version found in the database.
+    pub found: u32,
+    /// Newest schema version that this build supports.
+    pub supported: u32,
+}
+
+impl fmt::Display for SchemaTooNewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "database schema version {} is newer than the supported version {}",
+            self.found, self.supported
+        )
+    }
+}
+
+///
+/// Check that the database can be read by this build and apply any pending
+/// migrations, returning those that were applied. Called at startup, and after
+/// the database has been restored from a backup.
+///
+pub fn self_check(repo: &dyn RecordRepository) -> Result<Vec<Migration>, Error> {
+    // the configuration record is needed for nearly everything
+    let config = repo.get_configuration()?;
+    info!("database opened for computer {}", config.computer_id);
+    migrate(repo)
+}
+
+///
+/// Apply the migrations that have not yet been applied to the database, in
+/// order, returning those that were applied. The schema version is advanced
+/// after each migration, such that a failure leaves the earlier ones in place.
+///
+pub fn migrate(repo: &dyn RecordRepository) -> Result<Vec<Migration>, Error> {
+    let current = repo.get_schema_version()?.unwrap_or(0);
+    check_version(current)?;
+    let mut applied: Vec<Migration> = Vec::new();
+    for step in STEPS.iter().filter(|s| s.version > current) {
+        info!("applying migration {}: {}", step.version, step.name);
+        (step.apply)(repo)?;
+        let migration = Migration::new(step.version, step.name);
+        repo.put_migration(&migration)?;
+        repo.put_schema_version(step.version)?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+///
+/// Raise `SchemaTooNewError` if a database with the given schema version was
+/// written by a newer build than this one.
+///
+pub fn check_version(found: u32) -> Result<(), Error> {
+    if found > SCHEMA_VERSION {
+        return Err(Error::from(SchemaTooNewError {
+            found,
+            supported: SCHEMA_VERSION,
+        }));
+    }
+    Ok(())
+}
+
+// The first version merely records that the schema is now versioned.
+fn baseline(_repo: &dyn RecordRepository) -> Result<(), Error> {
+    Ok(())
+}
+
+// Save each dataset again such that the fields added since it was created are
+// written with their default values.
+fn rewrite_datasets(repo: &dyn RecordRepository) -> Result<(), Error> {
+    for dataset in repo.get_datasets()? {
+        repo.put_dataset(&dataset)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Configuration, Dataset};
+    use crate::domain::repositories::MockRecordRepository;
+    use mockall::Sequence;
+    use std::path::Path;
+
+    #[test]
+    fn test_migrate_new_database() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let mut seq = Sequence::new();
+        mock.expect_get_schema_version().returning(|| Ok(None));
+        mock.expect_get_datasets()
+            .returning(|| Ok(vec![Dataset::new(Path::new("/home/planet"))]));
+        mock.expect_put_dataset().times(1).returning(|_| Ok(()));
+        for version in 1..=SCHEMA_VERSION {
+            mock.expect_put_migration()
+                .withf(move |m| m.version == version)
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_| Ok(()));
+            mock.expect_put_schema_version()
+                .withf(move |v| *v == version)
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_| Ok(()));
+        }
+        // act
+        let result = migrate(&mock);
+        // assert
+        let applied = result.unwrap();
+        assert_eq!(applied.len(), STEPS.len());
+        assert_eq!(applied[0].version, 1);
+        assert_eq!(applied[0].name, "record the schema version");
+    }
+
+    #[test]
+    fn test_migrate_up_to_date() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_configuration()
+            .returning(|| Ok(Configuration::default()));
+        mock.expect_get_schema_version()
+            .returning(|| Ok(Some(SCHEMA_VERSION)));
+        mock.expect_put_migration().never();
+        mock.expect_put_schema_version().never();
+        // act
+        let result = self_check(&mock);
+        // assert
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_too_new() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_schema_version()
+            .returning(|| Ok(Some(SCHEMA_VERSION + 1)));
+        mock.expect_put_migration().never();
+        // act
+        let result = migrate(&mock);
+        // assert
+        let err = result.unwrap_err();
+        let err = err.downcast::<SchemaTooNewError>().unwrap();
+        assert_eq!(err.found, SCHEMA_VERSION + 1);
+        assert_eq!(err.supported, SCHEMA_VERSION);
+    }
+}
@@ -4,8 +4,28 @@
 use std::time::{Duration, SystemTimeError};
 
 pub mod backup;
+pub mod catalog;
+pub mod checkpoint;
+pub mod clock;
+pub mod critical;
+pub mod email;
+pub mod emergency;
+pub mod events;
+pub mod export;
+pub mod maintenance;
+pub mod migrate;
+#[cfg(all(unix, feature = "fuse"))]
+pub mod mount;
+pub mod notify;
+pub mod pairing;
+pub mod progress;
+pub mod replica;
 pub mod restore;
+pub mod sentinel;
+pub mod settings;
 pub mod state;
+pub mod tiering;
+pub mod trash;
 
 // Return a clear and accurate description of the duration.
 pub fn pretty_print_duration(duration: Result<Duration, SystemTimeError>) -> String {
@@ -0,0 +1,527 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `mount` module presents the snapshots of a dataset as a read-only file
+//! system using FUSE. Each completed snapshot appears as a directory at the
+//! top level, named for the time at which the snapshot was started (in UTC),
+//! containing the tree of that snapshot. File content is fetched from the
+//! pack stores only as it is read, with the chunks of the most recently used
+//! packs kept in a temporary directory within the dataset workspace.
+
+use crate::domain::entities::{Checksum, Message, MessageCode, TreeEntry, TreeReference};
+use crate::domain::managers::export::{FileContent, PackCache};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, Request, FUSE_ROOT_ID,
+};
+use log::{error, info};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use store_core::Secret;
+
+// The snapshots never change, the kernel may cache attributes for a while.
+const TTL: Duration = Duration::from_secs(3600);
+
+// Format of the names of the snapshot directories.
+const SNAPSHOT_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+///
+/// Mount the snapshots of the dataset at the given location, returning once
+/// the file system has been unmounted (e.g. with `umount` or `fusermount -u`).
+///
+/// At most `cache_packs` packs will have their chunks kept on disk at once.
+///
+pub fn mount(
+    dbase: Arc<dyn RecordRepository>,
+    dataset_id: &str,
+    mountpoint: &Path,
+    passphrase: Secret,
+    cache_packs: usize,
+) -> Result<(), Error> {
+    let fs = SnapshotFs::new(dbase, dataset_id, passphrase, cache_packs)?;
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("zorigami".into()),
+        MountOption::Subtype(dataset_id.into()),
+    ];
+    info!(
+        "mounting {} snapshots of dataset {} at {}",
+        fs.children.get(&FUSE_ROOT_ID).map(|c| c.len()).unwrap_or(0),
+        dataset_id,
+        mountpoint.display()
+    );
+    fuser::mount2(fs, mountpoint, &options)?;
+    info!("unmounted {}", mountpoint.display());
+    Ok(())
+}
+
+// What a node in the file system represents.
+enum NodeKind {
+    // the top-level directory containing the snapshots
+    Root,
+    // the root tree of a snapshot
+    Snapshot(Checksum, DateTime<Utc>),
+    // an entry within a tree
+    Entry(TreeEntry),
+}
+
+// A file, directory, or link that has been looked up by the kernel. The
+// inode number is the position of the node within the list plus one.
+struct Node {
+    parent: u64,
+    name: OsString,
+    kind: NodeKind,
+    // length of the file content, if the node is a file
+    size: u64,
+}
+
+///
+/// Read-only file system whose top-level directories are the snapshots of a
+/// dataset.
+///
+pub struct SnapshotFs {
+    dbase: Arc<dyn RecordRepository>,
+    packs: PackCache,
+    nodes: Vec<Node>,
+    // inode numbers of the entries of each directory that has been listed
+    children: HashMap<u64, Vec<u64>>,
+    // content of the files that are currently open, keyed by file handle
+    handles: HashMap<u64, FileContent>,
+    next_handle: u64,
+    mounted: SystemTime,
+    uid: u32,
+    gid: u32,
+}
+
+impl SnapshotFs {
+    /// Prepare the file system for the completed snapshots of the dataset.
+    pub fn new(
+        dbase: Arc<dyn RecordRepository>,
+        dataset_id: &str,
+        passphrase: Secret,
+        cache_packs: usize,
+    ) -> Result<Self, Error> {
+        let dataset = dbase
+            .get_dataset(dataset_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &dataset_id))?;
+        let packs = PackCache::new(dbase.clone(), &dataset, passphrase, cache_packs)?;
+        let root = Node {
+            parent: FUSE_ROOT_ID,
+            name: OsString::new(),
+            kind: NodeKind::Root,
+            size: 0,
+        };
+        // SAFETY: these functions are always successful
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut fs = Self {
+            dbase,
+            packs,
+            nodes: vec![root],
+            children: HashMap::new(),
+            handles: HashMap::new(),
+            next_handle: 1,
+            mounted: SystemTime::now(),
+            uid,
+            gid,
+        };
+        // walk the snapshots from newest to oldest, then list them in order
+        let mut snapshots: Vec<(Checksum, DateTime<Utc>)> = Vec::new();
+        let mut next = fs.dbase.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let snapshot = fs.dbase.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            if snapshot.end_time.is_some() {
+                snapshots.push((snapshot.tree, snapshot.start_time));
+            }
+            next = snapshot.parent;
+        }
+        snapshots.reverse();
+        let mut inodes: Vec<u64> = Vec::with_capacity(snapshots.len());
+        let mut previous = String::new();
+        let mut repeats = 0;
+        for (tree, time) in snapshots.into_iter() {
+            // snapshots started within the same second get a suffix
+            let mut name = time.format(SNAPSHOT_FORMAT).to_string();
+            if name == previous {
+                repeats += 1;
+                name = format!("{}.{}", previous, repeats);
+            } else {
+                previous = name.clone();
+                repeats = 0;
+            }
+            let kind = NodeKind::Snapshot(tree, time);
+            inodes.push(fs.add_node(FUSE_ROOT_ID, name.into(), kind, 0));
+        }
+        fs.children.insert(FUSE_ROOT_ID, inodes);
+        Ok(fs)
+    }
+
+    fn add_node(&mut self, parent: u64, name: OsString, kind: NodeKind, size: u64) -> u64 {
+        self.nodes.push(Node {
+            parent,
+            name,
+            kind,
+            size,
+        });
+        self.nodes.len() as u64
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        if ino == 0 {
+            None
+        } else {
+            self.nodes.get((ino - 1) as usize)
+        }
+    }
+
+    // Return the digest of the tree if the node is a directory.
+    fn tree_of(&self, ino: u64) -> Option<Checksum> {
+        match self.node(ino).map(|n| &n.kind) {
+            Some(NodeKind::Snapshot(tree, _)) => Some(tree.clone()),
+            Some(NodeKind::Entry(entry)) => match &entry.reference {
+                TreeReference::TREE(digest) => Some(digest.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Return the inode numbers of the entries in the directory, loading the
+    // tree from the database the first time.
+    fn load_children(&mut self, ino: u64) -> Result<Vec<u64>, i32> {
+        if let Some(inodes) = self.children.get(&ino) {
+            return Ok(inodes.clone());
+        }
+        let digest = self.tree_of(ino).ok_or(libc::ENOTDIR)?;
+        let entries = self.read_tree(&digest).map_err(|err| {
+            error!("mount: error reading tree {}: {}", digest, err);
+            libc::EIO
+        })?;
+        let mut inodes: Vec<u64> = Vec::with_capacity(entries.len());
+        for (entry, size) in entries.into_iter() {
+            let name = entry.file_name();
+            inodes.push(self.add_node(ino, name, NodeKind::Entry(entry), size));
+        }
+        self.children.insert(ino, inodes.clone());
+        Ok(inodes)
+    }
+
+    // Retrieve the entries of the tree along with the length of each file.
+    fn read_tree(&self, digest: &Checksum) -> Result<Vec<(TreeEntry, u64)>, Error> {
+        let tree = self
+            .dbase
+            .get_tree(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        let mut entries: Vec<(TreeEntry, u64)> = Vec::with_capacity(tree.entries.len());
+        for entry in tree.entries.into_iter() {
+            let size = match &entry.reference {
+                TreeReference::FILE(file_digest) => {
+                    let file = self.dbase.get_file(file_digest)?.ok_or_else(|| {
+                        Message::new(MessageCode::MissingFile).with("digest", &file_digest)
+                    })?;
+                    file.length
+                }
+                TreeReference::SMALL(contents) => contents.len() as u64,
+                TreeReference::LINK(value) => value.len() as u64,
+                TreeReference::TREE(_) => 0,
+            };
+            entries.push((entry, size));
+        }
+        Ok(entries)
+    }
+
+    // Find the named entry within the directory.
+    fn find_child(&mut self, parent: u64, name: &OsStr) -> Result<u64, i32> {
+        let inodes = self.load_children(parent)?;
+        inodes
+            .into_iter()
+            .find(|ino| self.nodes[(*ino - 1) as usize].name == name)
+            .ok_or(libc::ENOENT)
+    }
+
+    fn file_type(&self, ino: u64) -> FileType {
+        match self.node(ino).map(|n| &n.kind) {
+            Some(NodeKind::Entry(entry)) => match entry.reference {
+                TreeReference::TREE(_) => FileType::Directory,
+                TreeReference::LINK(_) => FileType::Symlink,
+                _ => FileType::RegularFile,
+            },
+            _ => FileType::Directory,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let node = self.node(ino)?;
+        let kind = self.file_type(ino);
+        let default_perm = if kind == FileType::Directory {
+            0o555
+        } else {
+            0o444
+        };
+        let (perm, mtime, ctime, uid, gid) = match &node.kind {
+            NodeKind::Root => (default_perm, self.mounted, self.mounted, self.uid, self.gid),
+            NodeKind::Snapshot(_, time) => {
+                let time = SystemTime::from(*time);
+                (default_perm, time, time, self.uid, self.gid)
+            }
+            NodeKind::Entry(entry) => (
+                entry
+                    .mode
+                    .map(|m| (m & 0o7777) as u16)
+                    .unwrap_or(default_perm),
+                SystemTime::from(entry.mtime),
+                SystemTime::from(entry.ctime),
+                entry.uid.unwrap_or(self.uid),
+                entry.gid.unwrap_or(self.gid),
+            ),
+        };
+        Some(FileAttr {
+            ino,
+            size: node.size,
+            blocks: node.size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime,
+            crtime: ctime,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid,
+            gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+}
+
+impl Filesystem for SnapshotFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.find_child(parent, name) {
+            Ok(ino) => match self.attr(ino) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(libc::ENOENT),
+            },
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.node(ino).map(|n| &n.kind) {
+            Some(NodeKind::Entry(entry)) => match &entry.reference {
+                TreeReference::LINK(value) => reply.data(value),
+                _ => reply.error(libc::EINVAL),
+            },
+            Some(_) => reply.error(libc::EINVAL),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let entry = match self.node(ino).map(|n| &n.kind) {
+            Some(NodeKind::Entry(entry)) => entry,
+            Some(_) => {
+                reply.error(libc::EISDIR);
+                return;
+            }
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        if entry.reference.is_tree() {
+            reply.error(libc::EISDIR);
+            return;
+        }
+        match FileContent::new(self.dbase.as_ref(), &entry.reference, &entry.name) {
+            Ok(content) => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.handles.insert(handle, content);
+                reply.opened(handle, 0);
+            }
+            Err(err) => {
+                error!("mount: error opening {}: {}", entry.name, err);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let content = match self.handles.get(&fh) {
+            Some(content) => content,
+            None => {
+                reply.error(libc::EBADF);
+                return;
+            }
+        };
+        let offset = offset.max(0) as u64;
+        if offset >= content.len() {
+            reply.data(&[]);
+            return;
+        }
+        let length = (size as u64).min(content.len() - offset) as usize;
+        let mut buf = vec![0; length];
+        match content.read_at(&mut self.packs, offset, &mut buf) {
+            Ok(()) => reply.data(&buf),
+            Err(err) => {
+                error!("mount: error reading at {}: {}", offset, err);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let inodes = match self.load_children(ino) {
+            Ok(inodes) => inodes,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let parent = self.nodes[(ino - 1) as usize].parent;
+        let mut listing: Vec<(u64, FileType, OsString)> = vec![
+            (ino, FileType::Directory, ".".into()),
+            (parent, FileType::Directory, "..".into()),
+        ];
+        for child in inodes.into_iter() {
+            let name = self.nodes[(child - 1) as usize].name.clone();
+            listing.push((child, self.file_type(child), name));
+        }
+        for (index, (child, kind, name)) in listing.iter().enumerate().skip(offset as usize) {
+            // the offset given is that of the next entry to be returned
+            if reply.add(*child, (index + 1) as i64, *kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Dataset, File, FileCounts, Snapshot, Tree};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+
+    #[test]
+    fn test_snapshot_fs_nodes() -> Result<(), Error> {
+        // arrange
+        let workspace = tempfile::tempdir()?;
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.workspace = workspace.path().to_path_buf();
+        let content = Checksum::BLAKE3("1111".into());
+        let mut notes = TreeEntry::new(Path::new("notes.txt"), TreeReference::FILE(content));
+        notes.mode = Some(0o100640);
+        let link = TreeEntry::new(
+            Path::new("link"),
+            TreeReference::LINK(b"notes.txt".to_vec()),
+        );
+        let tree = Tree::new(vec![notes, link], 1);
+        let tree_digest = tree.digest.clone();
+        let mut older = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        older.start_time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        older.set_end_time(older.start_time);
+        older.digest = Checksum::BLAKE3("older".into());
+        let mut newer = Snapshot::new(
+            Some(older.digest.clone()),
+            tree.digest.clone(),
+            FileCounts::default(),
+        );
+        newer.start_time = Utc.with_ymd_and_hms(2024, 3, 2, 12, 0, 0).unwrap();
+        newer.digest = Checksum::BLAKE3("newer".into());
+        // the latest snapshot has not finished and will be left out
+        let latest = newer.digest.clone();
+        let snapshots: HashMap<Checksum, Snapshot> = vec![older, newer]
+            .into_iter()
+            .map(|s| (s.digest.clone(), s))
+            .collect();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_load_dataset_stores()
+            .returning(|_| Ok(Box::new(MockPackRepository::new())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        mock.expect_get_tree()
+            .withf(move |digest| digest == &tree_digest)
+            .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 3129, vec![]))));
+        // act
+        let mut fs = SnapshotFs::new(Arc::new(mock), "cafebabe", Secret::from("keyboard cat"), 4)?;
+        // assert
+        let roots = fs.load_children(FUSE_ROOT_ID).unwrap();
+        assert_eq!(roots.len(), 1);
+        let snapshot = fs.find_child(FUSE_ROOT_ID, OsStr::new("2024-03-01_12-00-00"));
+        assert_eq!(snapshot, Ok(roots[0]));
+        let attr = fs.attr(roots[0]).unwrap();
+        assert_eq!(attr.kind, FileType::Directory);
+        assert_eq!(attr.perm, 0o555);
+        let file = fs.find_child(roots[0], OsStr::new("notes.txt")).unwrap();
+        let attr = fs.attr(file).unwrap();
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.size, 3129);
+        assert_eq!(attr.perm, 0o640);
+        let link = fs.find_child(roots[0], OsStr::new("link")).unwrap();
+        assert_eq!(fs.file_type(link), FileType::Symlink);
+        assert_eq!(
+            fs.find_child(roots[0], OsStr::new("nonesuch")),
+            Err(libc::ENOENT)
+        );
+        assert_eq!(fs.find_child(file, OsStr::new("any")), Err(libc::ENOTDIR));
+        Ok(())
+    }
+}
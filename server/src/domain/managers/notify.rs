@@ -0,0 +1,531 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `notify` module posts a JSON payload to each of the webhooks defined
+//! by the user when a backup finishes or fails, and when a dataset has gone
+//! without a completed backup for longer than the webhook allows.
+//!
+//! A stale dataset is reported only once per webhook until it completes a
+//! backup again, as remembered for as long as the server is running.
+//!
+//! Finished and failed backups are also reported by email, as configured by
+//! the settings described in the `email` module.
+//!
+//! The outcome of each maintenance task is posted as well, to the webhooks of
+//! the dataset for which it was performed, or to those that apply to every
+//! dataset in the case of a system-wide task.
+//!
+//! A webhook with quiet hours holds its notifications until those hours have
+//! passed, and a webhook in digest mode receives a single summary each day of
+//! the backups that finished or failed, rather than one post per backup. Held
+//! notifications are kept only for as long as the server is running.
+//!
+//! A dataset may also name a healthcheck URL that is pinged when each backup
+//! starts, finishes, and fails, such that a monitoring service in the style
+//! of healthchecks.io or Uptime Kuma can report backups that never ran.
+
+use crate::domain::entities::{Checksum, Event, EventKind, MaintenanceResult, Webhook};
+use crate::domain::managers::{email, settings};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+// Time allowed for a webhook to respond before giving up.
+const POST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Time allowed for a healthcheck ping to respond before giving up.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Local hour of the day after which the digest is sent if
+// `WEBHOOK_DIGEST_HOUR` is not set.
+const DEFAULT_DIGEST_HOUR: u32 = 7;
+
+lazy_static! {
+    // Pairs of webhook and dataset identifiers for which a stale dataset has
+    // already been reported.
+    static ref STALE_SENT: Mutex<HashSet<(String, String)>> = Mutex::new(HashSet::new());
+    // Notifications held during the quiet hours of their webhook.
+    static ref HELD: Mutex<Vec<(Webhook, serde_json::Value)>> = Mutex::new(Vec::new());
+    // Local date on which the digest was last sent to each webhook.
+    static ref DIGEST_SENT: Mutex<HashMap<String, NaiveDate>> = Mutex::new(HashMap::new());
+}
+
+///
+/// Notify the webhooks that the backup of the dataset has finished, with the
+/// digest of the new snapshot, if one was needed.
+///
+pub fn backup_finished(
+    dbase: &dyn RecordRepository,
+    dataset_id: &str,
+    snapshot: Option<&Checksum>,
+) {
+    STALE_SENT
+        .lock()
+        .unwrap()
+        .retain(|(_, dataset)| dataset != dataset_id);
+    let payload = json!({
+        "event": "backup_finished",
+        "dataset": dataset_id,
+        "snapshot": snapshot.map(|s| s.to_string()),
+        "time": Utc::now().to_rfc3339(),
+    });
+    send_all(dbase, Some(dataset_id), &payload);
+    email::backup_finished(dbase, dataset_id, snapshot);
+}
+
+///
+/// Notify the webhooks that the backup of the dataset has failed.
+///
+pub fn backup_failed(dbase: &dyn RecordRepository, dataset_id: &str, error: &str) {
+    let payload = json!({
+        "event": "backup_failed",
+        "dataset": dataset_id,
+        "error": error,
+        "time": Utc::now().to_rfc3339(),
+    });
+    send_all(dbase, Some(dataset_id), &payload);
+    email::backup_failed(dbase, dataset_id, error);
+}
+
+///
+/// Notify the webhooks of the outcome of a maintenance task, whether it
+/// finished or failed.
+///
+pub fn maintenance_finished(dbase: &dyn RecordRepository, result: &MaintenanceResult) {
+    let payload = maintenance_payload(result);
+    send_all(dbase, result.dataset_id.as_deref(), &payload);
+}
+
+// Build the payload describing the outcome of a maintenance task.
+fn maintenance_payload(result: &MaintenanceResult) -> serde_json::Value {
+    let event = if result.error.is_some() {
+        "maintenance_failed"
+    } else {
+        "maintenance_finished"
+    };
+    json!({
+        "event": event,
+        "task": result.task.to_string(),
+        "dataset": result.dataset_id,
+        "summary": result.summary,
+        "error": result.error,
+        "time": result.finished.to_rfc3339(),
+    })
+}
+
+///
+/// Notify the webhooks that have a staleness window of any datasets whose
+/// latest completed backup is older than that window, returning the number
+/// of notifications sent.
+///
+pub fn check_stale(dbase: &dyn RecordRepository) -> Result<usize, Error> {
+    let webhooks: Vec<Webhook> = dbase
+        .get_webhooks()?
+        .into_iter()
+        .filter(|w| w.stale_hours.is_some())
+        .collect();
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+    let now = Utc::now();
+    let mut count: usize = 0;
+    for dataset in dbase.get_datasets()? {
+        // archived datasets are not expected to be backed up any more
+        if dataset.archived.is_some() {
+            continue;
+        }
+        let last_backup = match last_completed(dbase, &dataset.id)? {
+            Some(time) => time,
+            // a dataset that has never been backed up has nothing to compare
+            None => continue,
+        };
+        for webhook in webhooks.iter().filter(|w| w.applies_to(&dataset.id)) {
+            // quiet webhooks will be checked again in the next round
+            if webhook.is_quiet(now) {
+                continue;
+            }
+            let hours = webhook.stale_hours.unwrap_or_default();
+            if now - last_backup < TimeDelta::hours(hours as i64) {
+                continue;
+            }
+            let key = (webhook.id.clone(), dataset.id.clone());
+            if STALE_SENT.lock().unwrap().contains(&key) {
+                continue;
+            }
+            let payload = json!({
+                "event": "backup_stale",
+                "dataset": dataset.id,
+                "last_backup": last_backup.to_rfc3339(),
+                "stale_hours": hours,
+                "time": now.to_rfc3339(),
+            });
+            match post(&webhook.url, &payload) {
+                Ok(()) => {
+                    STALE_SENT.lock().unwrap().insert(key);
+                    count += 1;
+                }
+                Err(err) => error!("notify: webhook {} failed: {}", webhook.url, err),
+            }
+        }
+    }
+    if count > 0 {
+        info!("notify: reported {} stale dataset(s)", count);
+    }
+    Ok(count)
+}
+
+/// Stage of a backup that is reported to the healthcheck URL.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ping {
+    Start,
+    Success,
+    Failure,
+}
+
+///
+/// Ping the healthcheck URL of a dataset, if it has one, to report the stage
+/// of the backup. Failures are logged and otherwise ignored.
+///
+pub fn healthcheck(url: Option<&str>, ping: Ping) {
+    if let Some(url) = url {
+        let url = match ping_url(url, ping) {
+            Ok(url) => url,
+            Err(err) => {
+                error!("notify: healthcheck {} is not usable: {}", url, err);
+                return;
+            }
+        };
+        let agent = ureq::AgentBuilder::new().timeout(PING_TIMEOUT).build();
+        if let Err(err) = agent.get(&url).call() {
+            error!("notify: healthcheck {} failed: {}", url, err);
+        }
+    }
+}
+
+// Build the URL for the ping: a `{status}` placeholder is replaced with `start`,
+// `up`, or `down` as in Uptime Kuma, otherwise `start` or `fail` is appended
+// to the path for those stages as in healthchecks.io, leaving any query string
+// where it is.
+fn ping_url(url: &str, ping: Ping) -> Result<String, Error> {
+    if url.contains("{status}") {
+        let status = match ping {
+            Ping::Start => "start",
+            Ping::Success => "up",
+            Ping::Failure => "down",
+        };
+        return Ok(url.replace("{status}", status));
+    }
+    let mut parsed = Url::parse(url)?;
+    {
+        let mut segments = parsed
+            .path_segments_mut()
+            .map_err(|_| anyhow!("URL cannot have a path"))?;
+        segments.pop_if_empty();
+        match ping {
+            Ping::Start => {
+                segments.push("start");
+            }
+            Ping::Success => (),
+            Ping::Failure => {
+                segments.push("fail");
+            }
+        }
+    }
+    Ok(parsed.into())
+}
+
+///
+/// Post the notifications that were held during the quiet hours of webhooks
+/// whose quiet hours have since passed, returning the number sent.
+///
+pub fn send_held() -> usize {
+    let now = Utc::now();
+    let ready: Vec<(Webhook, serde_json::Value)> = {
+        let mut held = HELD.lock().unwrap();
+        let (quiet, ready) = held.drain(..).partition(|(w, _)| w.is_quiet(now));
+        *held = quiet;
+        ready
+    };
+    let mut count: usize = 0;
+    for (webhook, payload) in ready.iter() {
+        match post(&webhook.url, payload) {
+            Ok(()) => count += 1,
+            Err(err) => error!("notify: webhook {} failed: {}", webhook.url, err),
+        }
+    }
+    if count > 0 {
+        info!("notify: sent {} held notification(s)", count);
+    }
+    count
+}
+
+///
+/// Post the daily digest of finished and failed backups to each webhook in
+/// digest mode that has not yet received one today, once the local time has
+/// passed `WEBHOOK_DIGEST_HOUR`. Returns the number of digests sent.
+///
+pub fn send_digests(dbase: &dyn RecordRepository) -> Result<usize, Error> {
+    let webhooks: Vec<Webhook> = dbase
+        .get_webhooks()?
+        .into_iter()
+        .filter(|w| w.digest)
+        .collect();
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+    let local = Local::now();
+    let hour = settings::var("WEBHOOK_DIGEST_HOUR")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v < 24)
+        .unwrap_or(DEFAULT_DIGEST_HOUR);
+    if local.hour() < hour {
+        return Ok(0);
+    }
+    let today = local.date_naive();
+    let now = Utc::now();
+    let since = now - TimeDelta::days(1);
+    let events: Vec<Event> = dbase
+        .get_events()?
+        .into_iter()
+        .filter(|e| e.time > since)
+        .filter(|e| e.kind == EventKind::BackupFinished || e.kind == EventKind::BackupFailed)
+        .collect();
+    let mut count: usize = 0;
+    for webhook in webhooks.iter() {
+        if webhook.is_quiet(now) || DIGEST_SENT.lock().unwrap().get(&webhook.id) == Some(&today) {
+            continue;
+        }
+        let payload = build_digest(webhook, &events, since);
+        match post(&webhook.url, &payload) {
+            Ok(()) => {
+                DIGEST_SENT
+                    .lock()
+                    .unwrap()
+                    .insert(webhook.id.clone(), today);
+                count += 1;
+            }
+            Err(err) => error!("notify: webhook {} failed: {}", webhook.url, err),
+        }
+    }
+    if count > 0 {
+        info!("notify: sent {} digest(s)", count);
+    }
+    Ok(count)
+}
+
+// Build the digest payload of the events that apply to the webhook.
+fn build_digest(webhook: &Webhook, events: &[Event], since: DateTime<Utc>) -> serde_json::Value {
+    let backups: Vec<serde_json::Value> = events
+        .iter()
+        .filter(|e| webhook.applies_to(&e.subject))
+        .map(|e| {
+            let event = if e.kind == EventKind::BackupFailed {
+                "backup_failed"
+            } else {
+                "backup_finished"
+            };
+            json!({
+                "event": event,
+                "dataset": e.subject,
+                "snapshot": e.details.get("snapshot"),
+                "error": e.details.get("error"),
+                "time": e.time.to_rfc3339(),
+            })
+        })
+        .collect();
+    json!({
+        "event": "backup_digest",
+        "since": since.to_rfc3339(),
+        "backups": backups,
+        "time": Utc::now().to_rfc3339(),
+    })
+}
+
+// Find the end time of the most recent completed backup of the dataset.
+fn last_completed(
+    dbase: &dyn RecordRepository,
+    dataset_id: &str,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let mut next = dbase.get_latest_snapshot(dataset_id)?;
+    while let Some(digest) = next {
+        match dbase.get_snapshot(&digest)? {
+            Some(snapshot) => {
+                if snapshot.end_time.is_some() {
+                    return Ok(snapshot.end_time);
+                }
+                next = snapshot.parent;
+            }
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+// Post the payload to each webhook that applies to the dataset, or to every
+// dataset if none is given, logging any failures, since notifications never
+// interrupt the work being reported.
+fn send_all(dbase: &dyn RecordRepository, dataset_id: Option<&str>, payload: &serde_json::Value) {
+    let webhooks = match dbase.get_webhooks() {
+        Ok(webhooks) => webhooks,
+        Err(err) => {
+            error!("notify: could not retrieve webhooks: {}", err);
+            return;
+        }
+    };
+    let now = Utc::now();
+    // webhooks in digest mode learn of each backup from the daily digest
+    for webhook in webhooks.iter().filter(|w| {
+        let applies = match dataset_id {
+            Some(dataset_id) => w.applies_to(dataset_id),
+            None => w.dataset.is_none(),
+        };
+        applies && !w.digest
+    }) {
+        if webhook.is_quiet(now) {
+            HELD.lock()
+                .unwrap()
+                .push((webhook.clone(), payload.clone()));
+            continue;
+        }
+        if let Err(err) = post(&webhook.url, payload) {
+            error!("notify: webhook {} failed: {}", webhook.url, err);
+        }
+    }
+}
+
+// Send the payload to the URL as JSON.
+fn post(url: &str, payload: &serde_json::Value) -> Result<(), Error> {
+    let agent = ureq::AgentBuilder::new().timeout(POST_TIMEOUT).build();
+    agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&payload.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Dataset, MaintenanceTask, Snapshot};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_check_stale_no_webhooks() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_webhooks().returning(|| Ok(vec![]));
+        mock.expect_get_datasets().never();
+        // act
+        let result = check_stale(&mock);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_maintenance_payload() {
+        let mut result = MaintenanceResult::new(MaintenanceTask::Compact);
+        result.summary = String::from("database compacted");
+        let payload = maintenance_payload(&result);
+        assert_eq!(payload["event"], "maintenance_finished");
+        assert_eq!(payload["task"], MaintenanceTask::Compact.to_string());
+        assert!(payload["dataset"].is_null());
+        assert_eq!(payload["summary"], "database compacted");
+        let mut result =
+            MaintenanceResult::new(MaintenanceTask::PruneSnapshots).dataset("dataset1");
+        result.error = Some(String::from("disk full"));
+        let payload = maintenance_payload(&result);
+        assert_eq!(payload["event"], "maintenance_failed");
+        assert_eq!(payload["dataset"], "dataset1");
+        assert_eq!(payload["error"], "disk full");
+    }
+
+    #[test]
+    fn test_ping_url() {
+        let url = "https://hc-ping.com/abc123/";
+        assert_eq!(
+            ping_url(url, Ping::Start).unwrap(),
+            "https://hc-ping.com/abc123/start"
+        );
+        assert_eq!(
+            ping_url(url, Ping::Success).unwrap(),
+            "https://hc-ping.com/abc123"
+        );
+        assert_eq!(
+            ping_url(url, Ping::Failure).unwrap(),
+            "https://hc-ping.com/abc123/fail"
+        );
+        let url = "https://hc-ping.com/abc123?create=1";
+        assert_eq!(
+            ping_url(url, Ping::Start).unwrap(),
+            "https://hc-ping.com/abc123/start?create=1"
+        );
+        let url = "https://kuma.local/api/push/xyz?status={status}&msg=backup";
+        assert_eq!(
+            ping_url(url, Ping::Failure).unwrap(),
+            "https://kuma.local/api/push/xyz?status=down&msg=backup"
+        );
+        assert!(ping_url("not a url", Ping::Start).is_err());
+    }
+
+    #[test]
+    fn test_build_digest() {
+        // arrange
+        let mut webhook = Webhook::new("https://example.com/hook");
+        webhook.dataset = Some("dataset1".to_owned());
+        webhook.digest = true;
+        let events = vec![
+            Event::new(EventKind::BackupFinished, "dataset1").detail("snapshot", "sha1-cafebabe"),
+            Event::new(EventKind::BackupFailed, "dataset2").detail("error", "disk full"),
+            Event::new(EventKind::BackupFailed, "dataset1").detail("error", "no network"),
+        ];
+        // act
+        let payload = build_digest(&webhook, &events, Utc::now() - TimeDelta::days(1));
+        // assert
+        assert_eq!(payload["event"], "backup_digest");
+        let backups = payload["backups"].as_array().unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0]["event"], "backup_finished");
+        assert_eq!(backups[0]["snapshot"], "sha1-cafebabe");
+        assert_eq!(backups[1]["event"], "backup_failed");
+        assert_eq!(backups[1]["error"], "no network");
+    }
+
+    #[test]
+    fn test_last_completed() {
+        // arrange
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut finished = Snapshot::new(None, tree.clone(), Default::default());
+        let end_time = Utc::now() - TimeDelta::hours(30);
+        finished.set_end_time(end_time);
+        let running = Snapshot::new(Some(finished.digest.clone()), tree, Default::default());
+        let running_sha1 = running.digest.clone();
+        let dataset = Dataset::new(Path::new("/some/path"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(running_sha1.clone())));
+        mock.expect_get_snapshot().returning(move |digest| {
+            if *digest == running.digest {
+                Ok(Some(running.clone()))
+            } else {
+                Ok(Some(finished.clone()))
+            }
+        });
+        // act
+        let result = last_completed(&mock, &dataset.id);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(end_time));
+    }
+}
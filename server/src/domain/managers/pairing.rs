@@ -0,0 +1,311 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `pairing` module grants access to other devices, such as the mobile
+//! client, by way of short-lived pairing codes that a device exchanges for a
+//! long-lived access token.
+//!
+//! The pairing codes are held in memory only, while the devices are saved in
+//! the database along with the hash digest of their token.
+
+use crate::domain::entities::{Device, DeviceScope};
+use crate::domain::managers::settings;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// Number of seconds for which a pairing code may be used.
+const CODE_LIFETIME_SECS: i64 = 300;
+
+// Characters of a pairing code, leaving out those easily mistaken for others.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Number of characters in a pairing code.
+const CODE_LENGTH: usize = 8;
+
+// Minimum number of seconds between updates to the last-used time of a device.
+const LAST_USED_INTERVAL_SECS: i64 = 3600;
+
+lazy_static! {
+    // Pairing codes that have not yet been used.
+    static ref CODES: Mutex<Vec<PairingCode>> = Mutex::new(Vec::new());
+}
+
+///
+/// Code that a device may exchange for an access token until it expires.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PairingCode {
+    /// Characters to be entered on the device.
+    pub code: String,
+    /// Access that will be granted to the device.
+    pub scope: DeviceScope,
+    /// Date/time after which the code is no longer valid.
+    pub expires: DateTime<Utc>,
+}
+
+///
+/// Generate a pairing code for a device that will have the given access. The
+/// code is also written to the log, for those who can see the server output.
+///
+pub fn start(scope: DeviceScope) -> PairingCode {
+    let bytes = Uuid::new_v4().into_bytes();
+    // bytes 6 and 8 hold the version and variant bits of the UUID, leaving the
+    // other 14 bytes random; the alphabet size divides 256 evenly so that the
+    // random bytes map to the characters without bias
+    let code: String = bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 6 && *i != 8)
+        .take(CODE_LENGTH)
+        .map(|(_, b)| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+        .collect();
+    let pairing = PairingCode {
+        code,
+        scope,
+        expires: Utc::now() + chrono::Duration::seconds(CODE_LIFETIME_SECS),
+    };
+    let mut codes = CODES.lock().unwrap();
+    codes.retain(|c| c.expires > Utc::now());
+    codes.push(pairing.clone());
+    info!(
+        "pairing code {} for {} access, valid until {}",
+        pairing.code, scope, pairing.expires
+    );
+    pairing
+}
+
+///
+/// Consume the pairing code, returning the access it grants, or `None` if the
+/// code is not valid. Letter case, spaces, and dashes are ignored.
+///
+pub fn redeem(code: &str) -> Option<DeviceScope> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let mut codes = CODES.lock().unwrap();
+    codes.retain(|c| c.expires > Utc::now());
+    let index = codes.iter().position(|c| c.code == code)?;
+    Some(codes.remove(index).scope)
+}
+
+///
+/// Generate a new access token for a device.
+///
+pub fn generate_token() -> String {
+    let mut bytes: Vec<u8> = Vec::with_capacity(32);
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+///
+/// Compute the hash digest of the access token, as saved in the database.
+///
+pub fn hash_token(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+///
+/// Return `true` if `REQUIRE_TOKEN` is set, in which case requests must present
+/// the token of a paired device.
+///
+pub fn token_required() -> bool {
+    env_flag("REQUIRE_TOKEN")
+}
+
+///
+/// Return `true` if `TRUST_LOCAL_HOST` is set, in which case requests from the
+/// local host are admitted without a token even when one is required. This is
+/// not safe behind a reverse proxy on the same host, through which every
+/// request appears to come from the local host.
+///
+pub fn local_host_trusted() -> bool {
+    env_flag("TRUST_LOCAL_HOST")
+}
+
+// Return `true` if the named environment variable is set to a true value.
+fn env_flag(name: &str) -> bool {
+    settings::var(name)
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+// Return `true` if a request without a token is to be given full access.
+fn admit_anonymous(local: bool, required: bool, trusted: bool) -> bool {
+    !required || (local && trusted)
+}
+
+///
+/// Determine the access granted to a request, given the value of its
+/// `Authorization` header, if any, and whether it came from the local host.
+/// Returns `None` if the request is not authorized at all.
+///
+/// A request without a token has full access, unless a token is required, in
+/// which case only requests from the local host are admitted without a token,
+/// and only if the local host is trusted. A request with a token has the
+/// access granted to the device with that token, if there is one.
+///
+pub fn authenticate(
+    repo: &dyn RecordRepository,
+    header: Option<&str>,
+    local: bool,
+) -> Result<Option<DeviceScope>, Error> {
+    let token = match header.and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(token) => token.trim(),
+        None if admit_anonymous(local, token_required(), local_host_trusted()) => {
+            return Ok(Some(DeviceScope::Full))
+        }
+        None => return Ok(None),
+    };
+    let hash = hash_token(token);
+    let device = repo
+        .get_devices()?
+        .into_iter()
+        .find(|d| d.token_hash == hash);
+    match device {
+        Some(mut device) => {
+            let scope = device.scope;
+            let now = Utc::now();
+            let stale = device.last_used.map_or(true, |t| {
+                now - t > chrono::Duration::seconds(LAST_USED_INTERVAL_SECS)
+            });
+            if stale {
+                device.last_used = Some(now);
+                if let Err(err) = repo.put_device(&device) {
+                    warn!("could not update device {}: {}", device.id, err);
+                }
+            }
+            Ok(Some(scope))
+        }
+        None => {
+            warn!("request presented an unknown device token");
+            Ok(None)
+        }
+    }
+}
+
+///
+/// Pair a device by consuming the pairing code and saving a new device record.
+/// Returns the device and its access token, which is not saved anywhere.
+///
+pub fn pair(
+    repo: &dyn RecordRepository,
+    code: &str,
+    name: &str,
+) -> Result<Option<(Device, String)>, Error> {
+    let Some(scope) = redeem(code) else {
+        return Ok(None);
+    };
+    let token = generate_token();
+    let device = Device::new(name, scope, &hash_token(&token));
+    repo.put_device(&device)?;
+    info!(
+        "paired device {} ({}) with {} access",
+        device.name, device.id, scope
+    );
+    Ok(Some((device, token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::repositories::MockRecordRepository;
+
+    #[test]
+    fn test_start_and_redeem() {
+        let pairing = start(DeviceScope::Restore);
+        assert_eq!(pairing.code.len(), CODE_LENGTH);
+        assert!(pairing.code.bytes().all(|b| CODE_ALPHABET.contains(&b)));
+        assert!(pairing.expires > Utc::now());
+        // codes are not case sensitive and may be entered in groups
+        let entered = format!(
+            "{}-{}",
+            &pairing.code[..4].to_lowercase(),
+            &pairing.code[4..]
+        );
+        assert_eq!(redeem(&entered), Some(DeviceScope::Restore));
+        // each code can be used only once
+        assert_eq!(redeem(&pairing.code), None);
+        assert_eq!(redeem("NOTACODE"), None);
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token1 = generate_token();
+        let token2 = generate_token();
+        assert_eq!(token1.len(), 43);
+        assert_ne!(token1, token2);
+        assert_eq!(hash_token(&token1), hash_token(&token1));
+        assert_ne!(hash_token(&token1), hash_token(&token2));
+    }
+
+    #[test]
+    fn test_pair_device() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_put_device()
+            .withf(|device| device.name == "phone" && device.scope == DeviceScope::Full)
+            .times(1)
+            .returning(|_| Ok(()));
+        let pairing = start(DeviceScope::Full);
+        // act
+        let result = pair(&mock, &pairing.code, "phone");
+        // assert
+        assert!(result.is_ok());
+        let (device, token) = result.unwrap().unwrap();
+        assert_eq!(device.token_hash, hash_token(&token));
+        // the code has been used up
+        let result = pair(&mock, &pairing.code, "phone");
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_authenticate_token() {
+        // arrange
+        let token = generate_token();
+        let device = Device::new("phone", DeviceScope::Restore, &hash_token(&token));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_devices()
+            .returning(move || Ok(vec![device.clone()]));
+        mock.expect_put_device()
+            .withf(|device| device.last_used.is_some())
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let header = format!("Bearer {}", token);
+        let result = authenticate(&mock, Some(&header), false);
+        // assert
+        assert_eq!(result.unwrap(), Some(DeviceScope::Restore));
+        let result = authenticate(&mock, Some("Bearer not-a-token"), true);
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    fn test_authenticate_local() {
+        let mock = MockRecordRepository::new();
+        let result = authenticate(&mock, None, true);
+        assert_eq!(result.unwrap(), Some(DeviceScope::Full));
+    }
+
+    #[test]
+    fn test_admit_anonymous() {
+        // without a required token, everyone is admitted
+        assert!(admit_anonymous(false, false, false));
+        assert!(admit_anonymous(true, false, false));
+        // the local host is not trusted by default
+        assert!(!admit_anonymous(true, true, false));
+        assert!(!admit_anonymous(false, true, false));
+        // trusting the local host does not admit others
+        assert!(admit_anonymous(true, true, true));
+        assert!(!admit_anonymous(false, true, true));
+    }
+}
@@ -0,0 +1,366 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `progress` module provides a common means of reporting the progress of
+//! the long running operations, such as backups, restores, verification, and
+//! pruning. Each operation is logged as it starts and finishes, and is held in
+//! memory, along with the most recently finished operations, for the
+//! `operations` query.
+//!
+//! The reporter for a backup also feeds the counters of the backup state in
+//! the state store, which the dataset status is built from, while that of a
+//! restore marks each change in the state store, such that the subscribers to
+//! the restore progress are informed.
+
+use crate::domain::managers::state::{BackupAction, RestorerAction, StateStore};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Number of finished operations to retain.
+const MAX_FINISHED: usize = 32;
+
+lazy_static! {
+    // Operations that are running or have recently finished, oldest first.
+    static ref OPERATIONS: Mutex<VecDeque<Operation>> = Mutex::new(VecDeque::new());
+}
+
+///
+/// The kinds of long running operations.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperationKind {
+    /// Backup of a dataset, with the dataset identifier as the subject.
+    Backup,
+    /// Restore request, with the destination path as the subject.
+    Restore,
+    /// Verification of packs or snapshots.
+    Verify,
+    /// Removal of extraneous objects from the pack stores.
+    Prune,
+    /// Any other maintenance task, with the task name as the subject.
+    Maintenance,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationKind::Backup => write!(f, "backup"),
+            OperationKind::Restore => write!(f, "restore"),
+            OperationKind::Verify => write!(f, "verify"),
+            OperationKind::Prune => write!(f, "prune"),
+            OperationKind::Maintenance => write!(f, "maintenance"),
+        }
+    }
+}
+
+///
+/// Receives the progress of a long running operation.
+///
+pub trait Progress: Send + Sync {
+    /// Set the number of items the operation expects to process, if known.
+    fn begin(&self, total: Option<u64>);
+
+    /// Record that the given numbers of items and bytes have been processed.
+    fn advance(&self, items: u64, bytes: u64);
+
+    /// Record the item that the operation is now processing.
+    fn working_on(&self, item: &str);
+
+    /// Record the end of the operation, with a message if it failed.
+    fn finish(&self, error: Option<String>);
+}
+
+///
+/// The progress of an operation, as reported so far.
+///
+#[derive(Clone, Debug)]
+pub struct Operation {
+    /// Unique identifier of the operation.
+    pub id: String,
+    /// What sort of operation this is.
+    pub kind: OperationKind,
+    /// What the operation is acting upon, such as a dataset identifier.
+    pub subject: String,
+    /// Number of items expected to be processed, if known.
+    pub total: Option<u64>,
+    /// Number of items processed so far.
+    pub items: u64,
+    /// Number of bytes processed so far.
+    pub bytes: u64,
+    /// Item currently being processed, if reported.
+    pub current: Option<String>,
+    /// Date/time when the operation started.
+    pub started: DateTime<Utc>,
+    /// Date/time when the operation finished, if it has.
+    pub finished: Option<DateTime<Utc>>,
+    /// Error message if the operation failed.
+    pub error: Option<String>,
+}
+
+impl Operation {
+    // Return the percentage complete, if the total is known.
+    fn percent(&self) -> Option<u64> {
+        match self.total {
+            Some(0) | None => None,
+            Some(total) => Some(self.items.min(total) * 100 / total),
+        }
+    }
+}
+
+///
+/// Return the operations that are running or have recently finished, newest
+/// first.
+///
+pub fn operations() -> Vec<Operation> {
+    let operations = OPERATIONS.lock().unwrap();
+    operations.iter().rev().cloned().collect()
+}
+
+///
+/// Implementation of `Progress` that records the operation in memory, writes
+/// to the log, and for backups and restores, updates the state store. If the
+/// reporter is dropped without `finish()` having been called, the operation is
+/// recorded as having ended early.
+///
+pub struct Reporter {
+    id: String,
+    kind: OperationKind,
+    subject: String,
+    // state store to be updated for a backup or restore
+    state: Option<Arc<dyn StateStore>>,
+    finished: AtomicBool,
+}
+
+impl Reporter {
+    /// Start reporting on a new operation.
+    pub fn new(kind: OperationKind, subject: &str) -> Self {
+        let id = xid::new().to_string();
+        let operation = Operation {
+            id: id.clone(),
+            kind,
+            subject: subject.to_owned(),
+            total: None,
+            items: 0,
+            bytes: 0,
+            current: None,
+            started: Utc::now(),
+            finished: None,
+            error: None,
+        };
+        OPERATIONS.lock().unwrap().push_back(operation);
+        info!("{} {} started", kind, subject);
+        Self {
+            id,
+            kind,
+            subject: subject.to_owned(),
+            state: None,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Start reporting on the backup of the given dataset, which also updates
+    /// the backup counters in the state store.
+    pub fn backup(state: Arc<dyn StateStore>, dataset_id: &str) -> Self {
+        let mut reporter = Self::new(OperationKind::Backup, dataset_id);
+        reporter.state = Some(state);
+        reporter
+    }
+
+    /// Start reporting on the restore to the given path, marking each change
+    /// in the state store for the benefit of the subscribers.
+    pub fn restore(state: Arc<dyn StateStore>, filepath: &str) -> Self {
+        let mut reporter = Self::new(OperationKind::Restore, filepath);
+        reporter.state = Some(state);
+        reporter
+    }
+
+    // Mark the change in the progress of a restore in the state store.
+    fn restore_changed(&self) {
+        if self.kind == OperationKind::Restore {
+            if let Some(state) = self.state.as_ref() {
+                state.restorer_event(RestorerAction::Progress);
+            }
+        }
+    }
+
+    // Apply the change to the recorded operation, returning a copy of the
+    // result, or `None` if the operation is no longer retained.
+    fn update<F>(&self, change: F) -> Option<Operation>
+    where
+        F: FnOnce(&mut Operation),
+    {
+        let mut operations = OPERATIONS.lock().unwrap();
+        let operation = operations.iter_mut().find(|o| o.id == self.id)?;
+        change(operation);
+        Some(operation.clone())
+    }
+}
+
+impl Progress for Reporter {
+    fn begin(&self, total: Option<u64>) {
+        self.update(|o| o.total = total);
+        if let Some(count) = total {
+            debug!("{} {} expects {} items", self.kind, self.subject, count);
+            if let Some(state) = self.state.as_ref() {
+                if self.kind == OperationKind::Backup {
+                    state.backup_event(BackupAction::BeginUpload(self.subject.clone(), count));
+                }
+            }
+        }
+        self.restore_changed();
+    }
+
+    fn advance(&self, items: u64, bytes: u64) {
+        let mut before: Option<u64> = None;
+        let after = self.update(|o| {
+            before = o.percent();
+            o.items += items;
+            o.bytes += bytes;
+        });
+        if let Some(percent) = after.and_then(|o| o.percent()) {
+            if before.map_or(true, |b| b / 10 != percent / 10) {
+                debug!("{} {} is {}% complete", self.kind, self.subject, percent);
+            }
+        }
+        if let Some(state) = self.state.as_ref() {
+            if self.kind == OperationKind::Backup {
+                state.backup_event(BackupAction::UploadBytes(self.subject.clone(), bytes));
+                state.backup_event(BackupAction::UploadFiles(self.subject.clone(), items));
+            }
+        }
+        self.restore_changed();
+    }
+
+    fn working_on(&self, item: &str) {
+        self.update(|o| o.current = Some(item.to_owned()));
+        self.restore_changed();
+    }
+
+    fn finish(&self, error: Option<String>) {
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        match error.as_ref() {
+            Some(msg) => error!("{} {} failed: {}", self.kind, self.subject, msg),
+            None => info!("{} {} finished", self.kind, self.subject),
+        }
+        self.update(|o| {
+            o.finished = Some(Utc::now());
+            o.current = None;
+            o.error = error;
+        });
+        // discard the oldest of the finished operations
+        let mut operations = OPERATIONS.lock().unwrap();
+        let mut excess = operations
+            .iter()
+            .filter(|o| o.finished.is_some())
+            .count()
+            .saturating_sub(MAX_FINISHED);
+        operations.retain(|o| {
+            if excess > 0 && o.finished.is_some() {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+        drop(operations);
+        self.restore_changed();
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        self.finish(Some(String::from("ended before completion")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::managers::state::StateStoreImpl;
+
+    fn find_operation(id: &str) -> Option<Operation> {
+        operations().into_iter().find(|o| o.id == id)
+    }
+
+    #[test]
+    fn test_reporter_lifecycle() {
+        let reporter = Reporter::new(OperationKind::Verify, "snapshot1");
+        let id = reporter.id.clone();
+        reporter.begin(Some(4));
+        reporter.advance(1, 100);
+        reporter.advance(2, 200);
+        let operation = find_operation(&id).unwrap();
+        assert_eq!(operation.kind, OperationKind::Verify);
+        assert_eq!(operation.subject, "snapshot1");
+        assert_eq!(operation.total, Some(4));
+        assert_eq!(operation.items, 3);
+        assert_eq!(operation.bytes, 300);
+        assert_eq!(operation.percent(), Some(75));
+        assert!(operation.finished.is_none());
+        reporter.finish(None);
+        let operation = find_operation(&id).unwrap();
+        assert!(operation.finished.is_some());
+        assert!(operation.error.is_none());
+        // dropping a finished reporter changes nothing
+        drop(reporter);
+        let operation = find_operation(&id).unwrap();
+        assert!(operation.error.is_none());
+    }
+
+    #[test]
+    fn test_reporter_dropped() {
+        let reporter = Reporter::new(OperationKind::Restore, "/home/planet");
+        let id = reporter.id.clone();
+        drop(reporter);
+        let operation = find_operation(&id).unwrap();
+        assert!(operation.finished.is_some());
+        assert_eq!(operation.error.unwrap(), "ended before completion");
+    }
+
+    #[test]
+    fn test_reporter_backup_state() {
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        state.backup_event(BackupAction::Start("dataset1".to_owned()));
+        let reporter = Reporter::backup(state.clone(), "dataset1");
+        reporter.begin(Some(10));
+        reporter.advance(3, 1024);
+        reporter.finish(None);
+        let current = state.get_state();
+        let backup = current.backups("dataset1").unwrap();
+        assert_eq!(backup.changed_files(), 10);
+        assert_eq!(backup.files_uploaded(), 3);
+        assert_eq!(backup.bytes_uploaded(), 1024);
+    }
+
+    #[test]
+    fn test_reporter_restore_state() {
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let reporter = Reporter::restore(state.clone(), "/home/planet");
+        let id = reporter.id.clone();
+        reporter.begin(None);
+        reporter.working_on("/home/planet/file.txt");
+        reporter.advance(1, 512);
+        let operation = find_operation(&id).unwrap();
+        assert_eq!(operation.current.as_deref(), Some("/home/planet/file.txt"));
+        reporter.finish(None);
+        let operation = find_operation(&id).unwrap();
+        assert!(operation.current.is_none());
+        // every change was marked for the subscribers
+        assert_eq!(state.get_state().restore_revision(), 4);
+    }
+
+    #[test]
+    fn test_operation_kind_display() {
+        assert_eq!(OperationKind::Backup.to_string(), "backup");
+        assert_eq!(OperationKind::Prune.to_string(), "prune");
+        assert_eq!(OperationKind::Maintenance.to_string(), "maintenance");
+    }
+}
@@ -0,0 +1,436 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `replica` module pushes the catalog of each new snapshot to a secondary
+//! zorigami server, providing off-site redundancy of the database beyond the
+//! copy that is saved to the pack stores.
+//!
+//! The primary sends the snapshot along with any new trees, files, chunks, and
+//! pack records, while the packs themselves remain in the stores of the
+//! primary. The secondary can optionally copy the packs into stores of its own
+//! so that it does not depend on the stores of the primary.
+
+use crate::data::models::replica::encode_batch;
+use crate::domain::entities::{
+    Checksum, Dataset, File, Message, MessageCode, Pack, ReplicaBatch, Snapshot, TreeReference,
+};
+use crate::domain::managers::settings;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use log::{debug, error, info};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+///
+/// Send any snapshots of the dataset that the secondary server does not yet
+/// have, oldest first, returning the number of snapshots sent.
+///
+/// Does nothing if `REPLICA_URL` is not set.
+///
+pub fn replicate(dbase: &dyn RecordRepository, dataset_id: &str) -> Result<usize, Error> {
+    let url = match settings::var("REPLICA_URL") {
+        Ok(value) if !value.is_empty() => value.trim_end_matches('/').to_owned(),
+        _ => return Ok(0),
+    };
+    let token = settings::var("REPLICA_TOKEN").unwrap_or_default();
+    let dataset = dbase
+        .get_dataset(dataset_id)?
+        .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &dataset_id))?;
+    let remote_latest = fetch_remote_latest(&url, &token, dataset_id)?;
+    // collect the completed snapshots the secondary is lacking, newest first
+    let mut pending: Vec<Snapshot> = Vec::new();
+    let mut next = dbase.get_latest_snapshot(dataset_id)?;
+    while let Some(digest) = next {
+        if remote_latest.as_ref() == Some(&digest) {
+            break;
+        }
+        let snapshot = dbase
+            .get_snapshot(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &digest))?;
+        next = snapshot.parent.clone();
+        if snapshot.end_time.is_some() {
+            pending.push(snapshot);
+        }
+    }
+    let mut stores = Vec::new();
+    for store_id in dataset.stores.iter() {
+        if let Some(store) = dbase.get_store(store_id)? {
+            stores.push(store);
+        }
+    }
+    let count = pending.len();
+    for snapshot in pending.into_iter().rev() {
+        let parent = match snapshot.parent.as_ref() {
+            Some(digest) => dbase.get_snapshot(digest)?,
+            None => None,
+        };
+        let mut batch = collect_batch(dbase, &dataset, parent.as_ref(), &snapshot)?;
+        batch.stores = stores.clone();
+        debug!(
+            "replica: sending snapshot {} with {} trees, {} files",
+            snapshot.digest,
+            batch.trees.len(),
+            batch.files.len()
+        );
+        let encoded = encode_batch(&batch)?;
+        ureq::post(&format!("{}/replica", url))
+            .set("Authorization", &format!("Bearer {}", token))
+            .set("Content-Type", "application/cbor")
+            .send_bytes(&encoded)?;
+    }
+    if count > 0 {
+        info!("replicated {} snapshot(s) of dataset {}", count, dataset_id);
+    }
+    Ok(count)
+}
+
+// Retrieve the digest of the latest snapshot of the dataset on the secondary.
+fn fetch_remote_latest(
+    url: &str,
+    token: &str,
+    dataset_id: &str,
+) -> Result<Option<Checksum>, Error> {
+    let body = ureq::get(&format!("{}/replica/{}", url, dataset_id))
+        .set("Authorization", &format!("Bearer {}", token))
+        .call()?
+        .into_string()?;
+    let body = body.trim();
+    if body.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Checksum::from_str(body)?))
+    }
+}
+
+///
+/// Gather the records that are new with the given snapshot, as compared to its
+/// parent, into a batch for replication. The stores are left empty.
+///
+pub fn collect_batch(
+    dbase: &dyn RecordRepository,
+    dataset: &Dataset,
+    parent: Option<&Snapshot>,
+    snapshot: &Snapshot,
+) -> Result<ReplicaBatch, Error> {
+    let mut batch = ReplicaBatch {
+        dataset: dataset.clone(),
+        stores: vec![],
+        snapshot: snapshot.clone(),
+        trees: vec![],
+        files: vec![],
+        chunks: vec![],
+        packs: vec![],
+        xattrs: vec![],
+    };
+    let mut seen: HashSet<Checksum> = HashSet::new();
+    let parent_tree = parent.map(|p| p.tree.clone());
+    collect_tree(
+        dbase,
+        &snapshot.tree,
+        parent_tree.as_ref(),
+        &mut seen,
+        &mut batch,
+    )?;
+    // gather the chunk and pack records for the new files
+    let mut pack_digests: Vec<Checksum> = Vec::new();
+    for file in batch.files.iter() {
+        if file.chunks.len() == 1 {
+            // the single chunk digest is actually that of the pack
+            pack_digests.push(file.chunks[0].1.clone());
+        } else {
+            for (_, digest) in file.chunks.iter() {
+                if seen.insert(digest.clone()) {
+                    let chunk = dbase.get_chunk(digest)?.ok_or_else(|| {
+                        Message::new(MessageCode::MissingChunk).with("digest", &digest)
+                    })?;
+                    if let Some(packfile) = chunk.packfile.as_ref() {
+                        pack_digests.push(packfile.clone());
+                    }
+                    batch.chunks.push(chunk);
+                }
+            }
+        }
+    }
+    for digest in pack_digests.into_iter() {
+        if seen.insert(digest.clone()) {
+            let pack = dbase
+                .get_pack(&digest)?
+                .ok_or_else(|| Message::new(MessageCode::MissingPack).with("digest", &digest))?;
+            batch.packs.push(pack);
+        }
+    }
+    Ok(batch)
+}
+
+// Add the tree and the entries that differ from those of the parent tree to
+// the batch, descending into those subtrees that have changed.
+fn collect_tree(
+    dbase: &dyn RecordRepository,
+    digest: &Checksum,
+    parent: Option<&Checksum>,
+    seen: &mut HashSet<Checksum>,
+    batch: &mut ReplicaBatch,
+) -> Result<(), Error> {
+    if parent == Some(digest) || !seen.insert(digest.clone()) {
+        return Ok(());
+    }
+    let tree = dbase
+        .get_tree(digest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+    let old_entries: HashMap<String, TreeReference> = match parent {
+        Some(parent) => match dbase.get_tree(parent)? {
+            Some(old) => old
+                .entries
+                .into_iter()
+                .map(|e| (e.name, e.reference))
+                .collect(),
+            None => HashMap::new(),
+        },
+        None => HashMap::new(),
+    };
+    for entry in tree.entries.iter() {
+        for xattr in entry.xattrs.values() {
+            if seen.insert(xattr.clone()) {
+                if let Some(value) = dbase.get_xattr(xattr)? {
+                    batch.xattrs.push((xattr.clone(), value));
+                }
+            }
+        }
+        let old_reference = old_entries.get(&entry.name);
+        match &entry.reference {
+            TreeReference::TREE(subtree) => {
+                let old_subtree = match old_reference {
+                    Some(TreeReference::TREE(old)) => Some(old),
+                    _ => None,
+                };
+                collect_tree(dbase, subtree, old_subtree, seen, batch)?;
+            }
+            TreeReference::FILE(file_digest) => {
+                let unchanged =
+                    matches!(old_reference, Some(TreeReference::FILE(old)) if old == file_digest);
+                if !unchanged && seen.insert(file_digest.clone()) {
+                    let file: File = dbase.get_file(file_digest)?.ok_or_else(|| {
+                        Message::new(MessageCode::MissingFile).with("digest", &file_digest)
+                    })?;
+                    batch.files.push(file);
+                }
+            }
+            _ => (),
+        }
+    }
+    batch.trees.push(tree);
+    Ok(())
+}
+
+///
+/// Save the records of the batch received from the primary server, then
+/// record the snapshot as the latest for the dataset.
+///
+/// The dataset and stores are added only if they do not already exist, and
+/// the dataset schedules are cleared so that this server does not attempt to
+/// back up the paths of the primary. If `REPLICA_STORES` is set, the packs are
+/// copied to those stores in the background.
+///
+pub fn apply_batch(dbase: Arc<dyn RecordRepository>, batch: ReplicaBatch) -> Result<(), Error> {
+    for store in batch.stores.iter() {
+        if dbase.get_store(&store.id)?.is_none() {
+            dbase.put_store(store)?;
+        }
+    }
+    if dbase.get_dataset(&batch.dataset.id)?.is_none() {
+        let mut dataset = batch.dataset.clone();
+        dataset.schedules = vec![];
+        dbase.put_dataset(&dataset)?;
+    }
+    for (digest, value) in batch.xattrs.iter() {
+        dbase.insert_xattr(digest, value)?;
+    }
+    for pack in batch.packs.iter() {
+        dbase.insert_pack(pack)?;
+    }
+    for chunk in batch.chunks.iter() {
+        dbase.insert_chunk(chunk)?;
+    }
+    for file in batch.files.iter() {
+        dbase.insert_file(file)?;
+    }
+    for tree in batch.trees.iter() {
+        dbase.insert_tree(tree)?;
+    }
+    dbase.put_snapshot(&batch.snapshot)?;
+    dbase.put_latest_snapshot(&batch.dataset.id, &batch.snapshot.digest)?;
+    info!(
+        "received snapshot {} of dataset {}",
+        batch.snapshot.digest, batch.dataset.id
+    );
+    let targets = pull_stores();
+    if !targets.is_empty() && !batch.packs.is_empty() {
+        let sources: Vec<String> = batch.stores.iter().map(|s| s.id.clone()).collect();
+        thread::spawn(move || {
+            if let Err(err) = pull_packs(dbase.as_ref(), &sources, &targets, batch.packs) {
+                error!("replica: could not copy packs: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Return the identifiers of the stores into which packs are to be copied.
+fn pull_stores() -> Vec<String> {
+    settings::var("REPLICA_STORES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Copy each of the packs from the stores of the primary into the stores of
+// this server, adding the new locations to the pack records.
+fn pull_packs(
+    dbase: &dyn RecordRepository,
+    sources: &[String],
+    targets: &[String],
+    packs: Vec<Pack>,
+) -> Result<(), Error> {
+    // use throw-away datasets to load the two sets of stores
+    let mut source_set = Dataset::new(Path::new("."));
+    source_set.stores = sources.to_vec();
+    let source_repo = dbase.load_dataset_stores(&source_set)?;
+    let mut target_set = Dataset::new(Path::new("."));
+    target_set.stores = targets.to_vec();
+    let target_repo = dbase.load_dataset_stores(&target_set)?;
+    let workspace = tempfile::tempdir()?;
+    for pack in packs.into_iter() {
+        // the pack record may have been updated since the batch arrived
+        let mut pack = dbase.get_pack(&pack.digest)?.unwrap_or(pack);
+        if pack.locations.iter().any(|l| targets.contains(&l.store)) {
+            continue;
+        }
+        let (bucket, object) = match pack.locations.first() {
+            Some(loc) => (loc.bucket.clone(), loc.object.clone()),
+            None => continue,
+        };
+        let packfile = workspace.path().join(pack.digest.to_string());
+        source_repo.retrieve_pack(&pack.locations, &pack.digest, &packfile)?;
+        let locations = target_repo.store_pack(&packfile, &bucket, &object)?;
+        std::fs::remove_file(&packfile)?;
+        pack.locations.extend(locations);
+        dbase.put_pack(&pack)?;
+    }
+    Ok(())
+}
+
+///
+/// Determine if the value of the `Authorization` header presents the bearer
+/// token given by `REPLICA_TOKEN`. If the token is not set, replication to
+/// this server is disabled and nothing is authorized.
+///
+pub fn authorized(header: Option<&str>) -> bool {
+    let token = match settings::var("REPLICA_TOKEN") {
+        Ok(value) if !value.is_empty() => value,
+        _ => return false,
+    };
+    match header.and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(given) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+// Compare the two values in time that depends only on their length.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{Chunk, FileCounts, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"keyboard cat", b"keyboard cat"));
+        assert!(!constant_time_eq(b"keyboard cat", b"keyboard dog"));
+        assert!(!constant_time_eq(b"keyboard", b"keyboard cat"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_collect_batch_skips_unchanged() {
+        // arrange
+        let same_file = Checksum::BLAKE3("1111".into());
+        let new_file = Checksum::BLAKE3("2222".into());
+        let old_entry = TreeEntry::new(
+            Path::new("same.txt"),
+            TreeReference::FILE(same_file.clone()),
+        );
+        let unchanged_tree = Tree::new(vec![old_entry], 1);
+        let old_root = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("docs"),
+                TreeReference::TREE(unchanged_tree.digest.clone()),
+            )],
+            1,
+        );
+        let new_root = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("docs"),
+                    TreeReference::TREE(unchanged_tree.digest.clone()),
+                ),
+                TreeEntry::new(Path::new("new.txt"), TreeReference::FILE(new_file.clone())),
+            ],
+            2,
+        );
+        let parent = Snapshot::new(None, old_root.digest.clone(), FileCounts::default());
+        let snapshot = Snapshot::new(
+            Some(parent.digest.clone()),
+            new_root.digest.clone(),
+            FileCounts::default(),
+        );
+        let trees: HashMap<Checksum, Tree> = vec![unchanged_tree, old_root, new_root.clone()]
+            .into_iter()
+            .map(|t| (t.digest.clone(), t))
+            .collect();
+        let pack_digest = Checksum::SHA1("3333".into());
+        let chunk_one = Checksum::BLAKE3("4444".into());
+        let chunk_two = Checksum::BLAKE3("5555".into());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        let chunks = vec![(0, chunk_one.clone()), (100, chunk_two.clone())];
+        mock.expect_get_file()
+            .withf(move |digest| digest == &new_file)
+            .returning(move |digest| Ok(Some(File::new(digest.clone(), 200, chunks.clone()))));
+        let packfile = pack_digest.clone();
+        mock.expect_get_chunk().times(2).returning(move |digest| {
+            let mut chunk = Chunk::new(digest.clone(), 0, 100);
+            chunk.packfile = Some(packfile.clone());
+            Ok(Some(chunk))
+        });
+        mock.expect_get_pack()
+            .times(1)
+            .returning(|digest| Ok(Some(Pack::new(digest.clone(), vec![]))));
+        // act
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let result = collect_batch(&mock, &dataset, Some(&parent), &snapshot);
+        // assert
+        assert!(result.is_ok());
+        let batch = result.unwrap();
+        assert_eq!(batch.trees.len(), 1);
+        assert_eq!(batch.trees[0].digest, new_root.digest);
+        assert_eq!(batch.files.len(), 1);
+        assert_eq!(batch.chunks.len(), 2);
+        assert_eq!(batch.packs.len(), 1);
+        assert_eq!(batch.packs[0].digest, pack_digest);
+        assert!(batch.xattrs.is_empty());
+    }
+}
@@ -1,8 +1,13 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, TreeReference};
-use crate::domain::helpers::pack;
+use crate::domain::entities::{
+    Checksum, File, Message, MessageCode, RestoreVerify, Tree, TreeEntry, TreeReference,
+};
+use crate::domain::helpers::appledouble;
+use crate::domain::helpers::{pack, paths};
+use crate::domain::managers::progress::{Progress, Reporter};
+use crate::domain::managers::settings;
 use crate::domain::managers::state::{RestorerAction, StateStore};
 use crate::domain::repositories::{PackRepository, RecordRepository};
 use actix::prelude::*;
@@ -12,31 +17,75 @@ use log::{debug, error, info, warn};
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 use std::cmp;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use store_core::Secret;
+use tracing::{info_span, Span};
+
+// Number of packs to retrieve at once if `RESTORE_PARALLELISM` is not set.
+const DEFAULT_PARALLELISM: usize = 4;
+
+///
+/// Return the number of packs to be retrieved concurrently during a restore,
+/// as given by the `RESTORE_PARALLELISM` setting.
+///
+pub fn parallelism() -> usize {
+    settings::var("RESTORE_PARALLELISM")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_PARALLELISM)
+}
 
 /// Request to restore a single file or a tree of files.
 #[derive(Clone, Debug)]
 pub struct Request {
     /// Digest of the tree containing the entry to restore.
     pub tree: Checksum,
-    /// Name of the entry within the tree to be restored.
+    /// Name of the entry within the tree to be restored, or empty to restore
+    /// the contents of the tree itself.
     pub entry: String,
     /// Relative path where file/tree will be restored.
     pub filepath: PathBuf,
     /// Identifier of the dataset containing the data.
     pub dataset: String,
+    /// Directory to which the files are restored in place of the base path
+    /// of the dataset, if any.
+    pub target: Option<PathBuf>,
     /// Password text for decrypting the pack files.
-    pub passphrase: String,
+    pub passphrase: Secret,
     /// The datetime when the request was completed.
     pub finished: Option<DateTime<Utc>>,
     /// Number of files restored so far during the restoration.
     pub files_restored: u64,
+    /// Total size in bytes of the files to be restored, once known.
+    pub bytes_total: u64,
+    /// Number of bytes restored so far during the restoration.
+    pub bytes_restored: u64,
+    /// Path of the file currently being restored, if any.
+    pub current_file: Option<PathBuf>,
     /// Error message if request processing failed.
     pub error_msg: Option<String>,
+    /// If true, set the modification time of restored directories.
+    pub restore_times: bool,
+    /// If true, apply only the ownership, mode, and extended attributes to
+    /// the files that already exist, without fetching any packs.
+    pub metadata_only: bool,
+    /// If true, apply the metadata and modification time of every restored
+    /// entry, and skip the files that already match, such that an interrupted
+    /// request can be resumed by enqueuing it again.
+    pub resumable: bool,
+    /// How thoroughly the retrieved data is checked while restoring.
+    pub verify: RestoreVerify,
+    /// Requests that are satisfied by this one, and will be completed along
+    /// with it, rather than being processed separately.
+    pub merged: Vec<Request>,
+    /// Destination of the request that satisfies this one, if any.
+    pub merged_into: Option<PathBuf>,
 }
 
 impl Request {
@@ -45,18 +94,39 @@ impl Request {
         entry: String,
         filepath: PathBuf,
         dataset: String,
-        passphrase: String,
+        passphrase: Secret,
     ) -> Self {
         Self {
             tree,
             entry,
             filepath,
             dataset,
+            target: None,
             passphrase,
             finished: None,
             files_restored: 0,
+            bytes_total: 0,
+            bytes_restored: 0,
+            current_file: None,
             error_msg: None,
+            restore_times: true,
+            metadata_only: false,
+            resumable: false,
+            verify: RestoreVerify::default(),
+            merged: Vec::new(),
+            merged_into: None,
+        }
+    }
+
+    // Mark this request as merged into the other, along with any requests
+    // that had been merged into this one.
+    fn merge_into(mut self, other: &mut Request) {
+        for mut request in self.merged.drain(..) {
+            request.merged_into = Some(other.filepath.clone());
+            other.merged.push(request);
         }
+        self.merged_into = Some(other.filepath.clone());
+        other.merged.push(self);
     }
 }
 
@@ -86,7 +156,8 @@ pub trait Restorer: Send + Sync {
     /// Add the given request to the queue to be processed.
     fn enqueue(&self, request: Request) -> Result<(), Error>;
 
-    /// Return all pending and recently completed requests.
+    /// Return the request being processed, if any, along with all pending
+    /// and recently completed requests.
     fn requests(&self) -> Vec<Request>;
 
     /// Cancel the pending request.
@@ -117,6 +188,8 @@ pub struct RestorerImpl {
     super_addr: Mutex<Option<Addr<RestoreSupervisor>>>,
     // Queue of incoming requests to be processed.
     pending: Arc<Mutex<VecDeque<Request>>>,
+    // Request currently being processed, if any.
+    active: Arc<Mutex<Option<Request>>>,
     // Limited number of recently completed requests.
     completed: Arc<(Mutex<VecDeque<Request>>, Condvar)>,
     // Factory method for the FileRestorer implementation.
@@ -132,6 +205,7 @@ impl RestorerImpl {
             state: state.clone(),
             super_addr: Mutex::new(None),
             pending: Arc::new(Mutex::new(VecDeque::new())),
+            active: Arc::new(Mutex::new(None)),
             completed: Arc::new((Mutex::new(VecDeque::new()), Condvar::new())),
             fetcher,
         }
@@ -177,10 +251,11 @@ impl Restorer for RestorerImpl {
             // start supervisor within the arbiter created earlier
             let state = self.state.clone();
             let pending = self.pending.clone();
+            let active = self.active.clone();
             let completed = self.completed.clone();
             let fetcher = self.fetcher;
             let addr = actix::Supervisor::start_in_arbiter(&self.runner.handle(), move |_| {
-                RestoreSupervisor::new(repo, state, pending, completed, fetcher)
+                RestoreSupervisor::new(repo, state, pending, active, completed, fetcher)
             });
             *su_addr = Some(addr);
         }
@@ -209,10 +284,18 @@ impl Restorer for RestorerImpl {
 
     fn requests(&self) -> Vec<Request> {
         let mut requests: Vec<Request> = Vec::new();
+        // lock the active request before the completed list, as is done when
+        // the request is moved from one to the other
+        let active = self.active.lock().unwrap();
+        if let Some(request) = active.as_ref() {
+            requests.push(request.clone());
+            requests.extend_from_slice(&request.merged);
+        }
         let queue = self.pending.lock().unwrap();
-        let slices = queue.as_slices();
-        requests.extend_from_slice(slices.0);
-        requests.extend_from_slice(slices.1);
+        for request in queue.iter() {
+            requests.push(request.clone());
+            requests.extend_from_slice(&request.merged);
+        }
         let pair = self.completed.clone();
         let (lock, _cvar) = &*pair;
         let completed = lock.lock().unwrap();
@@ -230,6 +313,14 @@ impl Restorer for RestorerImpl {
             queue.remove(idx);
             return true;
         }
+        // the request may have been merged into another pending request
+        for pending in queue.iter_mut() {
+            let position = pending.merged.iter().position(|r| r == &request);
+            if let Some(idx) = position {
+                pending.merged.remove(idx);
+                return true;
+            }
+        }
         false
     }
 
@@ -253,10 +344,14 @@ struct RestoreSupervisor {
     state: Arc<dyn StateStore>,
     // Queue of incoming requests to be processed.
     pending: Arc<Mutex<VecDeque<Request>>>,
+    // Request currently being processed, with its progress so far.
+    active: Arc<Mutex<Option<Request>>>,
     // List to which completed tasks are added (in the front).
     completed: Arc<(Mutex<VecDeque<Request>>, Condvar)>,
     // Factory method for the FileRestorer implementation.
     fetcher: FileRestorerFactory,
+    // Reports the progress of the request being processed.
+    progress: Option<Reporter>,
 }
 
 impl RestoreSupervisor {
@@ -264,6 +359,7 @@ impl RestoreSupervisor {
         repo: Arc<dyn RecordRepository>,
         state: Arc<dyn StateStore>,
         pending: Arc<Mutex<VecDeque<Request>>>,
+        active: Arc<Mutex<Option<Request>>>,
         completed: Arc<(Mutex<VecDeque<Request>>, Condvar)>,
         fetcher: FileRestorerFactory,
     ) -> Self {
@@ -271,8 +367,10 @@ impl RestoreSupervisor {
             dbase: repo,
             state,
             pending,
+            active,
             completed,
             fetcher,
+            progress: None,
         }
     }
 
@@ -283,17 +381,245 @@ impl RestoreSupervisor {
         // Process all of the requests in the queue using the one fetcher, in
         // the hopes that there may be some overlap of the pack files.
         while let Some(request) = self.pop_incoming() {
-            info!("processing request {}/{}", request.tree, request.entry);
-            let mut req = request.clone();
-            if let Err(error) = fetcher.load_dataset(&request.dataset) {
+            // a pending request that restores a parent directory will take care
+            // of this request, and any that it covers, so let that one do it
+            let mut req = match self.merge_into_pending(request) {
+                Some(req) => req,
+                None => continue,
+            };
+            self.merge_from_pending(&mut req);
+            let span = info_span!("restore", dataset = %req.dataset, tree = %req.tree);
+            let _entered = span.enter();
+            info!("processing request {}/{}", req.tree, req.entry);
+            let filepath = req.filepath.to_string_lossy();
+            let progress = Reporter::restore(self.state.clone(), &filepath);
+            *self.active.lock().unwrap() = Some(req.clone());
+            progress.begin(None);
+            self.progress = Some(progress);
+            fetcher.set_verify(req.verify);
+            if let Err(error) = fetcher.load_dataset(&req.dataset, req.target.clone()) {
                 error!("process_queue: error loading dataset: {}", error);
                 self.set_error(error, &mut req);
-            } else if let Err(error) = self.process_entry(&mut req, &mut fetcher) {
-                error!("process_queue: error processing entry: {}", error);
+            } else if let Err(error) = self.verify_request(&req, &mut fetcher) {
+                error!("process_queue: error verifying packs: {}", error);
                 self.set_error(error, &mut req);
+            } else {
+                if !req.metadata_only {
+                    self.measure_request(&mut req);
+                }
+                if let Err(error) = self.process_entry(&mut req, &mut fetcher) {
+                    error!("process_queue: error processing entry: {}", error);
+                    self.set_error(error, &mut req);
+                }
             }
-            info!("completed request {}/{}", request.tree, request.entry);
+            req.current_file = None;
+            info!("completed request {}/{}", req.tree, req.entry);
+            // the request is completed before the reporter marks the change
+            let error = req.error_msg.clone();
             self.push_completed(req);
+            if let Some(progress) = self.progress.take() {
+                progress.finish(error);
+            }
+        }
+        Ok(())
+    }
+
+    // Merge the request into a pending request that covers it, returning the
+    // request if no such request was found.
+    fn merge_into_pending(&self, request: Request) -> Option<Request> {
+        let mut queue = self.pending.lock().unwrap();
+        for pending in queue.iter_mut() {
+            if self.covers(pending, &request) {
+                info!(
+                    "merged request {} into pending request for {}",
+                    request.filepath.display(),
+                    pending.filepath.display()
+                );
+                request.merge_into(pending);
+                return None;
+            }
+        }
+        Some(request)
+    }
+
+    // Remove the pending requests covered by the given request, merging them
+    // into that request.
+    fn merge_from_pending(&self, request: &mut Request) {
+        let mut queue = self.pending.lock().unwrap();
+        let mut remaining: VecDeque<Request> = VecDeque::new();
+        while let Some(pending) = queue.pop_front() {
+            if self.covers(request, &pending) {
+                info!(
+                    "merged pending request {} into request for {}",
+                    pending.filepath.display(),
+                    request.filepath.display()
+                );
+                pending.merge_into(request);
+            } else {
+                remaining.push_back(pending);
+            }
+        }
+        *queue = remaining;
+    }
+
+    // Return true if processing the outer request will restore the very same
+    // content at the same location as the inner request.
+    fn covers(&self, outer: &Request, inner: &Request) -> bool {
+        if outer.dataset != inner.dataset
+            || outer.target != inner.target
+            || outer.metadata_only != inner.metadata_only
+            || outer.verify != inner.verify
+        {
+            return false;
+        }
+        let Ok(relative) = inner.filepath.strip_prefix(&outer.filepath) else {
+            return false;
+        };
+        let mut names: Vec<String> = vec![outer.entry.clone()];
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_string_lossy().into_owned()),
+                _ => return false,
+            }
+        }
+        // the final name is the entry of the inner request, the names leading
+        // up to it are the directories to be traversed from the outer request
+        let entry = names.pop().unwrap();
+        let mut digest = outer.tree.clone();
+        for name in names.iter() {
+            match self.find_subtree(&digest, name) {
+                Ok(Some(subtree)) => digest = subtree,
+                Ok(None) => return false,
+                Err(error) => {
+                    debug!("covers: error reading tree {}: {}", digest, error);
+                    return false;
+                }
+            }
+        }
+        // trees are content addressed, so matching digests means that the
+        // entry will be restored exactly as the inner request would
+        digest == inner.tree && entry == inner.entry
+    }
+
+    // Find the named entry in the tree, returning its digest if the entry is
+    // itself a tree.
+    fn find_subtree(&self, digest: &Checksum, name: &str) -> Result<Option<Checksum>, Error> {
+        let tree = self
+            .dbase
+            .get_tree(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        for entry in tree.entries.iter() {
+            if entry.name == name {
+                if let TreeReference::TREE(subtree) = &entry.reference {
+                    return Ok(Some(subtree.to_owned()));
+                }
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+
+    // Determine the total size of the files to be restored by the request,
+    // such that the progress can be shown as a proportion of the whole.
+    fn measure_request(&self, request: &mut Request) {
+        let reference = match self.find_reference(request) {
+            Ok(Some(reference)) => reference,
+            Ok(None) => return,
+            Err(error) => {
+                warn!("measure_request: error reading tree: {}", error);
+                return;
+            }
+        };
+        match self.measure_entry(&reference) {
+            Ok(total) => {
+                request.bytes_total = total;
+                if let Some(active) = self.active.lock().unwrap().as_mut() {
+                    active.bytes_total = total;
+                }
+            }
+            Err(error) => warn!("measure_request: error measuring entry: {}", error),
+        }
+    }
+
+    // Find the reference of the entry to be restored by the request, which
+    // is the tree itself when the entry name is empty.
+    fn find_reference(&self, request: &Request) -> Result<Option<TreeReference>, Error> {
+        if request.entry.is_empty() {
+            return Ok(Some(TreeReference::TREE(request.tree.clone())));
+        }
+        let tree = self
+            .dbase
+            .get_tree(&request.tree)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &request.tree))?;
+        Ok(tree
+            .entries
+            .iter()
+            .find(|e| e.name == request.entry)
+            .map(|e| e.reference.clone()))
+    }
+
+    // Return the total size of the files within the entry.
+    fn measure_entry(&self, reference: &TreeReference) -> Result<u64, Error> {
+        match reference {
+            TreeReference::LINK(_) => Ok(0),
+            TreeReference::SMALL(contents) => Ok(contents.len() as u64),
+            TreeReference::FILE(digest) => {
+                // a missing file record will fail later when restoring
+                Ok(self.dbase.get_file(digest)?.map_or(0, |f| f.length))
+            }
+            TreeReference::TREE(digest) => {
+                let tree = self.dbase.get_tree(digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingTree).with("digest", &digest)
+                })?;
+                let mut total: u64 = 0;
+                for entry in tree.entries.iter() {
+                    total += self.measure_entry(&entry.reference)?;
+                }
+                Ok(total)
+            }
+        }
+    }
+
+    // Have the stores check the packs needed for the files of the request,
+    // before any of them are downloaded, failing early if any are corrupted.
+    fn verify_request(
+        &self,
+        request: &Request,
+        fetcher: &mut Box<dyn FileRestorer>,
+    ) -> Result<(), Error> {
+        if request.metadata_only {
+            return Ok(());
+        }
+        let mut files: Vec<(Checksum, PathBuf)> = Vec::new();
+        if let Some(reference) = self.find_reference(request)? {
+            self.list_files(&reference, &request.filepath, &mut files)?;
+        }
+        if files.is_empty() {
+            return Ok(());
+        }
+        fetcher.verify(&files)
+    }
+
+    // Collect the digest and path of every file within the entry.
+    fn list_files(
+        &self,
+        reference: &TreeReference,
+        path: &Path,
+        files: &mut Vec<(Checksum, PathBuf)>,
+    ) -> Result<(), Error> {
+        match reference {
+            TreeReference::FILE(digest) => files.push((digest.to_owned(), path.to_path_buf())),
+            TreeReference::TREE(digest) => {
+                let tree = self.dbase.get_tree(digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingTree).with("digest", &digest)
+                })?;
+                for entry in tree.entries.iter() {
+                    let mut filepath = path.to_path_buf();
+                    filepath.push(entry.file_name());
+                    self.list_files(&entry.reference, &filepath, files)?;
+                }
+            }
+            _ => (),
         }
         Ok(())
     }
@@ -303,47 +629,166 @@ impl RestoreSupervisor {
         request: &mut Request,
         fetcher: &mut Box<dyn FileRestorer>,
     ) -> Result<(), Error> {
+        if request.entry.is_empty() {
+            // the contents of the tree are restored to the destination itself
+            let filepath = request.filepath.clone();
+            let digest = request.tree.clone();
+            return self.process_tree(request, digest, &filepath, fetcher);
+        }
         let tree = self
             .dbase
             .get_tree(&request.tree)?
-            .ok_or_else(|| anyhow!(format!("missing tree: {:?}", request.tree)))?;
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &request.tree))?;
         for entry in tree.entries.iter() {
             if entry.name == request.entry {
+                let stripped = strip_sidecar_forks(entry, &tree);
+                let entry = stripped.as_ref().unwrap_or(entry);
                 let filepath = request.filepath.clone();
+                if request.metadata_only {
+                    self.process_metadata(request, entry, &filepath, fetcher)?;
+                    break;
+                }
                 match &entry.reference {
                     TreeReference::LINK(contents) => {
                         fetcher.restore_link(contents, &filepath)?;
                     }
                     TreeReference::TREE(digest) => {
                         self.process_tree(request, digest.to_owned(), &filepath, fetcher)?;
+                        if request.restore_times {
+                            // as for the directories within, the contents
+                            // have been restored, so failing here is no reason
+                            // to fail the request
+                            if let Err(error) = fetcher.set_mtime(&filepath, entry.mtime) {
+                                warn!(
+                                    "process_entry: error setting time of {}: {}",
+                                    filepath.display(),
+                                    error
+                                );
+                            }
+                        }
                     }
                     TreeReference::FILE(digest) => {
-                        self.process_file(request, digest.to_owned(), &filepath, fetcher)?;
+                        self.process_file(request, entry, digest.to_owned(), &filepath, fetcher)?;
                     }
                     TreeReference::SMALL(contents) => {
                         fetcher.restore_small(contents, &filepath)?;
+                        self.count_restored(request, 0, contents.len() as u64);
                     }
                 }
+                self.apply_metadata(request, entry, &filepath, fetcher)?;
                 break;
             }
         }
         Ok(())
     }
 
+    // Apply the metadata of the entry, and of everything within it, to the
+    // files that already exist at the destination.
+    fn process_metadata(
+        &self,
+        request: &mut Request,
+        entry: &TreeEntry,
+        filepath: &Path,
+        fetcher: &mut Box<dyn FileRestorer>,
+    ) -> Result<(), Error> {
+        if let TreeReference::TREE(digest) = &entry.reference {
+            let tree = self
+                .dbase
+                .get_tree(digest)?
+                .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+            for child in tree.entries.iter() {
+                let stripped = strip_sidecar_forks(child, &tree);
+                let child = stripped.as_ref().unwrap_or(child);
+                let mut childpath = filepath.to_path_buf();
+                childpath.push(child.file_name());
+                if let Err(error) = self.process_metadata(request, child, &childpath, fetcher) {
+                    error!(
+                        "process_metadata: error updating {}: {}",
+                        childpath.display(),
+                        error
+                    );
+                }
+            }
+        }
+        // the directory is changed last, in case its new mode prevents
+        // changing its contents
+        if fetcher.restore_metadata(entry, filepath)? {
+            self.count_restored(request, 1, 0);
+        }
+        if request.restore_times && matches!(entry.reference, TreeReference::TREE(_)) {
+            fetcher.set_mtime(filepath, entry.mtime)?;
+        }
+        Ok(())
+    }
+
+    // Apply the metadata and modification time of the restored entry, if the
+    // request is resumable, as those indicate that the entry is complete.
+    fn apply_metadata(
+        &self,
+        request: &Request,
+        entry: &TreeEntry,
+        filepath: &Path,
+        fetcher: &mut Box<dyn FileRestorer>,
+    ) -> Result<(), Error> {
+        if request.resumable {
+            fetcher.restore_metadata(entry, filepath)?;
+            // setting the time of a link would change the file it refers to,
+            // and directory times are set once their contents are restored
+            if matches!(
+                entry.reference,
+                TreeReference::FILE(_) | TreeReference::SMALL(_)
+            ) {
+                fetcher.set_mtime(filepath, entry.mtime)?;
+            }
+        } else if entry.xattrs.keys().any(|name| appledouble::is_fork(name)) {
+            // to macOS the resource fork is as much a part of the file as its
+            // content, and so is restored even when the metadata is not
+            fetcher.restore_forks(entry, filepath)?;
+        }
+        Ok(())
+    }
+
     fn process_file(
         &self,
         request: &mut Request,
+        entry: &TreeEntry,
         digest: Checksum,
         filepath: &Path,
         fetcher: &mut Box<dyn FileRestorer>,
     ) -> Result<(), Error> {
+        if request.resumable && fetcher.is_restored(entry, filepath)? {
+            // restored by an earlier attempt of the same request
+            debug!("process_file: skipping restored {}", filepath.display());
+            let length = self.dbase.get_file(&digest)?.map_or(0, |f| f.length);
+            self.count_restored(request, 1, length);
+            return Ok(());
+        }
+        request.current_file = Some(filepath.to_path_buf());
+        if let Some(active) = self.active.lock().unwrap().as_mut() {
+            active.current_file = request.current_file.clone();
+        }
+        if let Some(progress) = self.progress.as_ref() {
+            progress.working_on(&filepath.to_string_lossy());
+        }
         // fetch the packs for the file and assemble the chunks
-        fetcher.fetch_file(&digest, filepath, &request.passphrase)?;
-        // update the count of files restored so far
-        request.files_restored += 1;
+        let length = fetcher.fetch_file(&digest, filepath, request.passphrase.expose())?;
+        self.count_restored(request, 1, length);
         Ok(())
     }
 
+    // Update the counts of files and bytes restored so far.
+    fn count_restored(&self, request: &mut Request, files: u64, bytes: u64) {
+        request.files_restored += files;
+        request.bytes_restored += bytes;
+        if let Some(active) = self.active.lock().unwrap().as_mut() {
+            active.files_restored = request.files_restored;
+            active.bytes_restored = request.bytes_restored;
+        }
+        if let Some(progress) = self.progress.as_ref() {
+            progress.advance(files, bytes);
+        }
+    }
+
     fn process_tree(
         &self,
         request: &mut Request,
@@ -354,11 +799,39 @@ impl RestoreSupervisor {
         let tree = self
             .dbase
             .get_tree(&digest)?
-            .ok_or_else(|| anyhow!(format!("missing tree: {:?}", digest)))?;
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        // create the directory even if the tree is empty
+        fetcher.restore_dir(path)?;
+        // retrieve the packs for all of the files in this directory at once,
+        // rather than one file after another
+        let mut files: Vec<Checksum> = Vec::new();
+        for entry in tree.entries.iter() {
+            if let TreeReference::FILE(digest) = &entry.reference {
+                if request.resumable {
+                    let filepath = path.join(entry.file_name());
+                    if fetcher.is_restored(entry, &filepath).unwrap_or(false) {
+                        continue;
+                    }
+                }
+                files.push(digest.to_owned());
+            }
+        }
+        if !files.is_empty() {
+            if let Err(error) = fetcher.prefetch(&files, request.passphrase.expose()) {
+                // the files will be fetched individually and report the error
+                warn!(
+                    "process_tree: error prefetching {}: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
         for entry in tree.entries.iter() {
+            let stripped = strip_sidecar_forks(entry, &tree);
+            let entry = stripped.as_ref().unwrap_or(entry);
             let mut filepath = path.to_path_buf();
-            filepath.push(&entry.name);
-            match &entry.reference {
+            filepath.push(entry.file_name());
+            let restored = match &entry.reference {
                 TreeReference::LINK(contents) => {
                     if let Err(error) = fetcher.restore_link(contents, &filepath) {
                         error!(
@@ -366,6 +839,9 @@ impl RestoreSupervisor {
                             filepath.display(),
                             error
                         );
+                        false
+                    } else {
+                        true
                     }
                 }
                 TreeReference::TREE(digest) => {
@@ -377,17 +853,34 @@ impl RestoreSupervisor {
                             filepath.display(),
                             error
                         );
+                        false
+                    } else {
+                        if request.restore_times {
+                            // the directory contents are complete, otherwise the
+                            // time would be changed again by adding more entries
+                            if let Err(error) = fetcher.set_mtime(&filepath, entry.mtime) {
+                                warn!(
+                                    "process_tree: error setting time of {}: {}",
+                                    filepath.display(),
+                                    error
+                                );
+                            }
+                        }
+                        true
                     }
                 }
                 TreeReference::FILE(digest) => {
                     if let Err(error) =
-                        self.process_file(request, digest.to_owned(), &filepath, fetcher)
+                        self.process_file(request, entry, digest.to_owned(), &filepath, fetcher)
                     {
                         error!(
                             "process_tree: error processing file {}: {}",
                             filepath.display(),
                             error
                         );
+                        false
+                    } else {
+                        true
                     }
                 }
                 TreeReference::SMALL(contents) => {
@@ -397,8 +890,21 @@ impl RestoreSupervisor {
                             filepath.display(),
                             error
                         );
+                        false
+                    } else {
+                        self.count_restored(request, 0, contents.len() as u64);
+                        true
                     }
                 }
+            };
+            if restored {
+                if let Err(error) = self.apply_metadata(request, entry, &filepath, fetcher) {
+                    warn!(
+                        "process_tree: error applying metadata to {}: {}",
+                        filepath.display(),
+                        error
+                    );
+                }
             }
         }
         Ok(())
@@ -412,9 +918,19 @@ impl RestoreSupervisor {
     fn push_completed(&self, request: Request) {
         let mut req = request;
         req.finished = Some(Utc::now());
+        // move the request from active to completed in one step
+        let mut active = self.active.lock().unwrap();
         let pair = self.completed.clone();
         let (lock, cvar) = &*pair;
         let mut completed = lock.lock().unwrap();
+        *active = None;
+        // merged requests share the outcome of the request that satisfied them
+        for merged in req.merged.iter() {
+            let mut merged = merged.clone();
+            merged.finished = req.finished;
+            merged.error_msg = req.error_msg.clone();
+            completed.push_front(merged);
+        }
         // Push the completed request to the front of the list and truncate the
         // older items to keep the list from growing indefinitely.
         completed.push_front(req);
@@ -500,26 +1016,62 @@ impl Handler<Restore> for RestoreSupervisor {
 ///
 /// Restores individual files and symbolic links. Maintains a list of the pack
 /// files that have been downloaded so far and retains chunks to avoid fetching
-/// the same pack file multiple times.
+/// the same pack file multiple times. Packs are retrieved several at a time.
 ///
 #[cfg_attr(test, automock)]
 pub trait FileRestorer: Send + Sync {
-    /// Prepare for restoring files by loading the given dataset.
-    fn load_dataset(&mut self, dataset_id: &str) -> Result<(), Error>;
-
-    /// Fetch the necessary packs and restore the given file.
+    /// Prepare for restoring files by loading the given dataset. If a target
+    /// directory is given, files are restored there rather than to the base
+    /// path of the dataset.
+    fn load_dataset(&mut self, dataset_id: &str, target: Option<PathBuf>) -> Result<(), Error>;
+
+    /// Set how thoroughly the retrieved data is checked while restoring.
+    fn set_verify(&mut self, level: RestoreVerify);
+
+    /// Fetch the packs needed to restore the given files, without restoring
+    /// them yet, retrieving several packs at once.
+    fn prefetch(&mut self, checksums: &[Checksum], passphrase: &str) -> Result<(), Error>;
+
+    /// Ask the stores to compare their digests of the packs needed to restore
+    /// the given files (digest and path) with the recorded digests, without
+    /// retrieving the packs. Returns an error naming the corrupted packs and
+    /// the files that need them, if any.
+    fn verify(&mut self, files: &[(Checksum, PathBuf)]) -> Result<(), Error>;
+
+    /// Fetch the necessary packs and restore the given file, returning the
+    /// number of bytes written.
     fn fetch_file(
         &mut self,
         checksum: &Checksum,
         filepath: &Path,
         passphrase: &str,
-    ) -> Result<(), Error>;
+    ) -> Result<u64, Error>;
+
+    /// Return `true` if the file for the entry already exists with the length
+    /// and modification time that it would have once restored.
+    fn is_restored(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error>;
 
     /// Restore the named symbolic link given its contents.
     fn restore_link(&self, contents: &[u8], filepath: &Path) -> Result<(), Error>;
 
     /// Restore the named small file given its contents.
     fn restore_small(&self, contents: &[u8], filepath: &Path) -> Result<(), Error>;
+
+    /// Create the named directory, if it does not already exist.
+    fn restore_dir(&self, filepath: &Path) -> Result<(), Error>;
+
+    /// Set the modification time of the named file or directory.
+    fn set_mtime(&self, filepath: &Path, mtime: DateTime<Utc>) -> Result<(), Error>;
+
+    /// Apply the ownership, mode, and extended attributes of the entry to the
+    /// existing file, changing only those that differ. Returns `true` if any
+    /// changes were made.
+    fn restore_metadata(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error>;
+
+    /// Apply the Finder information and resource fork of the entry, if any, to
+    /// the existing file, as extended attributes on macOS, or in an AppleDouble
+    /// file elsewhere. Returns `true` if any changes were made.
+    fn restore_forks(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error>;
 }
 
 pub struct FileRestorerImpl {
@@ -531,10 +1083,17 @@ pub struct FileRestorerImpl {
     stores: Option<Arc<dyn PackRepository>>,
     // Base path to which files will be restored.
     basepath: Option<PathBuf>,
+    // Directory to which files will be restored instead of the base path.
+    target: Option<PathBuf>,
     // Temporary location where packs and chunks are downloaded.
     packpath: Option<tempfile::TempDir>,
-    // Those pack files that have already been fetched.
-    downloaded: HashSet<Checksum>,
+    // Those pack files that have already been fetched, and how thoroughly
+    // they were checked at the time.
+    downloaded: HashMap<Checksum, RestoreVerify>,
+    // Number of packs to retrieve concurrently.
+    parallelism: usize,
+    // How thoroughly the retrieved data is checked.
+    verify: RestoreVerify,
 }
 
 impl FileRestorerImpl {
@@ -545,45 +1104,222 @@ impl FileRestorerImpl {
             dataset: None,
             stores: None,
             basepath: None,
+            target: None,
             packpath: None,
-            downloaded: HashSet::new(),
+            downloaded: HashMap::new(),
+            parallelism: parallelism(),
+            verify: RestoreVerify::default(),
+        }
+    }
+
+    /// Set the number of packs to retrieve concurrently.
+    pub fn set_parallelism(&mut self, parallelism: usize) {
+        self.parallelism = parallelism.max(1);
+    }
+
+    // Return `true` if the pack has yet to be fetched, or was fetched with fewer
+    // checks than the current level calls for. The digests of the restored
+    // files are checked apart from the packs, and need not fetch them again.
+    fn needs_fetch(&self, digest: &Checksum) -> bool {
+        let wanted = self.verify.min(RestoreVerify::Chunk);
+        self.downloaded
+            .get(digest)
+            .map_or(true, |level| *level < wanted)
+    }
+
+    // Return the digests of the packs that hold the chunks of the file.
+    fn find_packs(&self, file: &File) -> Result<Vec<Checksum>, Error> {
+        if file.chunks.len() == 1 {
+            // If the file record contains a single chunk entry then its digest
+            // is actually that of the pack record rather than a chunk record.
+            return Ok(vec![file.chunks[0].1.clone()]);
+        }
+        let mut packs: Vec<Checksum> = Vec::new();
+        for (_offset, chunk) in &file.chunks {
+            let chunk_rec = self
+                .dbase
+                .get_chunk(chunk)?
+                .ok_or_else(|| Message::new(MessageCode::MissingChunk).with("digest", &chunk))?;
+            let pack_digest = chunk_rec
+                .packfile
+                .ok_or_else(|| anyhow!(format!("chunk without pack: {:?}", chunk)))?;
+            packs.push(pack_digest);
         }
+        Ok(packs)
     }
 
-    // Fetch a pack file.
-    fn fetch_pack(
+    // Fetch those pack files that have not already been fetched, several at a
+    // time, returning the first error encountered, if any. The chunks of each
+    // pack are extracted to the workspace, where they remain for any other
+    // files that need them.
+    fn fetch_packs(
         &mut self,
-        pack_digest: &Checksum,
+        pack_digests: Vec<Checksum>,
         workspace: &Path,
         passphrase: &str,
     ) -> Result<(), Error> {
-        if !self.downloaded.contains(pack_digest) {
-            let stores = self.stores.as_ref().unwrap();
-            let saved_pack = self
-                .dbase
-                .get_pack(pack_digest)?
-                .ok_or_else(|| anyhow!(format!("missing pack record: {:?}", pack_digest)))?;
-            // retrieve the pack file
-            let mut archive = PathBuf::new();
-            archive.push(workspace);
-            archive.push(pack_digest.to_string());
-            debug!("fetching pack {}", pack_digest);
-            stores.retrieve_pack(&saved_pack.locations, &archive)?;
-            // unpack the contents
-            verify_pack_digest(pack_digest, &archive)?;
-            pack::extract_pack(&archive, workspace, Some(passphrase))?;
-            debug!("pack extracted");
-            fs::remove_file(archive)?;
-            // remember this pack as being downloaded
-            self.downloaded.insert(pack_digest.to_owned());
+        let mut seen: HashSet<Checksum> = HashSet::new();
+        let pending: Vec<Checksum> = pack_digests
+            .into_iter()
+            .filter(|d| self.needs_fetch(d) && seen.insert(d.to_owned()))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
         }
-        Ok(())
+        let stores = self
+            .stores
+            .clone()
+            .ok_or_else(|| anyhow!("no dataset loaded"))?;
+        let workers = self.parallelism.min(pending.len());
+        let queue: Mutex<Vec<Checksum>> = Mutex::new(pending);
+        let fetched: Mutex<Vec<Checksum>> = Mutex::new(Vec::new());
+        let failure: Mutex<Option<Error>> = Mutex::new(None);
+        let dbase = self.dbase.as_ref();
+        let verify = self.verify;
+        let span = Span::current();
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let _entered = span.enter();
+                    let Some(digest) = queue.lock().unwrap().pop() else {
+                        break;
+                    };
+                    let result = fetch_pack(
+                        dbase,
+                        stores.as_ref(),
+                        &digest,
+                        workspace,
+                        passphrase,
+                        verify,
+                    );
+                    match result {
+                        Ok(()) => fetched.lock().unwrap().push(digest),
+                        Err(error) => {
+                            // let the other workers finish what they started
+                            queue.lock().unwrap().clear();
+                            failure.lock().unwrap().get_or_insert(error);
+                        }
+                    }
+                });
+            }
+        });
+        // remember these packs as being downloaded, and how they were checked
+        let fetched = fetched.into_inner().unwrap();
+        self.downloaded
+            .extend(fetched.into_iter().map(|digest| (digest, verify)));
+        match failure.into_inner().unwrap() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    // Return the path of the workspace to which packs are extracted.
+    fn workspace(&self) -> Result<PathBuf, Error> {
+        use anyhow::Context;
+        let workspace = self
+            .packpath
+            .as_ref()
+            .ok_or_else(|| anyhow!("no dataset loaded"))?
+            .path()
+            .to_path_buf();
+        fs::create_dir_all(&workspace)
+            .with_context(|| format!("fs::create_dir_all({})", workspace.display()))?;
+        Ok(workspace)
+    }
+
+    // Retrieve the value of the extended attribute with the given digest.
+    fn get_xattr(&self, digest: &Checksum) -> Result<Vec<u8>, Error> {
+        self.dbase
+            .get_xattr(digest)?
+            .ok_or_else(|| anyhow!(format!("missing xattr: {:?}", digest)))
+    }
+
+    // Set the named extended attribute of the file, unless it already has the
+    // value with the given digest. Returns true if the attribute was set.
+    #[cfg(target_family = "unix")]
+    fn set_xattr(&self, outfile: &Path, name: &str, digest: &Checksum) -> Result<bool, Error> {
+        let current = xattr::get(outfile, name).ok().flatten();
+        let matches = current
+            .map(|value| Checksum::sha1_from_bytes(&value) == *digest)
+            .unwrap_or(false);
+        if matches {
+            return Ok(false);
+        }
+        let value = self.get_xattr(digest)?;
+        xattr::set(outfile, name, &value)?;
+        Ok(true)
+    }
+
+    // Apply the Finder information and resource fork of the entry to the file
+    // as extended attributes.
+    #[cfg(target_os = "macos")]
+    fn apply_forks(&self, entry: &TreeEntry, outfile: &Path) -> Result<bool, Error> {
+        let mut changed = false;
+        for (name, digest) in entry.xattrs.iter() {
+            if appledouble::is_fork(name) && self.set_xattr(outfile, name, digest)? {
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    // Write the Finder information and resource fork of the entry to the
+    // AppleDouble file alongside the file, as macOS itself does on those file
+    // systems that cannot hold them, such that they are not lost in copying
+    // the file back to a Mac.
+    #[cfg(not(target_os = "macos"))]
+    fn apply_forks(&self, entry: &TreeEntry, outfile: &Path) -> Result<bool, Error> {
+        let mut double = appledouble::AppleDouble::default();
+        for (name, digest) in entry.xattrs.iter() {
+            if appledouble::is_fork(name) {
+                double.set(name, self.get_xattr(digest)?);
+            }
+        }
+        if double.is_empty() {
+            return Ok(false);
+        }
+        double.write(outfile)
+    }
+
+    // Produce the path within the dataset for the entry, rejecting anything
+    // that would lead outside of the base path.
+    fn dataset_path(&self, filepath: &Path) -> Result<PathBuf, Error> {
+        let basepath = self
+            .target
+            .as_ref()
+            .or(self.basepath.as_ref())
+            .ok_or_else(|| anyhow!("no dataset loaded"))?;
+        if filepath.as_os_str().is_empty() {
+            // restoring the contents of a tree to the base path itself
+            return Ok(paths::long_path(basepath));
+        }
+        let outfile = paths::safe_join(basepath, filepath)?;
+        // deeply nested entries may exceed the path length limit on Windows
+        Ok(paths::long_path(&outfile))
+    }
+
+    // Produce the path within the dataset to which the entry is restored,
+    // rejecting anything that would lead outside of the base path.
+    fn target_path(&self, filepath: &Path) -> Result<PathBuf, Error> {
+        let outfile = self.dataset_path(filepath)?;
+        // an existing link would be followed when writing the file, and the
+        // entry is about to be replaced anyway; the base path is left alone
+        if filepath.as_os_str().is_empty() {
+            return Ok(outfile);
+        }
+        if let Ok(attr) = fs::symlink_metadata(&outfile) {
+            if attr.file_type().is_symlink() {
+                fs::remove_file(&outfile)?;
+            }
+        }
+        Ok(outfile)
     }
 }
 
 impl FileRestorer for FileRestorerImpl {
-    fn load_dataset(&mut self, dataset_id: &str) -> Result<(), Error> {
+    fn load_dataset(&mut self, dataset_id: &str, target: Option<PathBuf>) -> Result<(), Error> {
         use anyhow::Context;
+        self.target = target;
         if let Some(id) = self.dataset.as_ref() {
             if id == dataset_id {
                 return Ok(());
@@ -592,7 +1328,7 @@ impl FileRestorer for FileRestorerImpl {
         let dataset = self
             .dbase
             .get_dataset(dataset_id)?
-            .ok_or_else(|| anyhow!(format!("missing dataset: {:?}", dataset_id)))?;
+            .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &dataset_id))?;
         self.dataset = Some(dataset_id.to_owned());
         self.stores = Some(Arc::from(self.dbase.load_dataset_stores(&dataset)?));
         fs::create_dir_all(&dataset.workspace).with_context(|| {
@@ -606,32 +1342,120 @@ impl FileRestorer for FileRestorerImpl {
         Ok(())
     }
 
+    fn set_verify(&mut self, level: RestoreVerify) {
+        self.verify = level;
+    }
+
+    fn prefetch(&mut self, checksums: &[Checksum], passphrase: &str) -> Result<(), Error> {
+        let workspace = self.workspace()?;
+        let mut packs: Vec<Checksum> = Vec::new();
+        for checksum in checksums {
+            let saved_file = self
+                .dbase
+                .get_file(checksum)?
+                .ok_or_else(|| Message::new(MessageCode::MissingFile).with("digest", &checksum))?;
+            packs.extend(self.find_packs(&saved_file)?);
+        }
+        debug!(
+            "prefetching {} packs for {} files",
+            packs.len(),
+            checksums.len()
+        );
+        self.fetch_packs(packs, &workspace, passphrase)
+    }
+
+    fn verify(&mut self, files: &[(Checksum, PathBuf)]) -> Result<(), Error> {
+        let stores = self
+            .stores
+            .clone()
+            .ok_or_else(|| anyhow!("no dataset loaded"))?;
+        // map each pack to the paths of the files that need it; missing
+        // records are left for the restore itself to report
+        let mut pack_files: BTreeMap<Checksum, Vec<&Path>> = BTreeMap::new();
+        for (checksum, filepath) in files.iter() {
+            if let Some(saved_file) = self.dbase.get_file(checksum)? {
+                for digest in self.find_packs(&saved_file)? {
+                    if self.needs_fetch(&digest) {
+                        pack_files.entry(digest).or_default().push(filepath);
+                    }
+                }
+            }
+        }
+        debug!(
+            "verifying {} packs for {} files",
+            pack_files.len(),
+            files.len()
+        );
+        let mut corrupted: Vec<String> = Vec::new();
+        let mut affected: BTreeSet<&Path> = BTreeSet::new();
+        let workspace = self.workspace()?;
+        for (digest, paths) in pack_files.iter() {
+            let Some(pack) = self.dbase.get_pack(digest)? else {
+                continue;
+            };
+            // packs uploaded before digests were recorded cannot be checked
+            let Some(md5) = pack.md5.as_ref() else {
+                continue;
+            };
+            if stores.check_pack(&pack.locations, md5)? == Some(false) {
+                // the digest kept by the store is not always the MD5 of the
+                // content, depending on how the object was encrypted, so only
+                // the digest of the retrieved pack is conclusive
+                warn!(
+                    "pack {} does not match its MD5, retrieving to check",
+                    digest
+                );
+                let outfile = tempfile::NamedTempFile::new_in(&workspace)?.into_temp_path();
+                if let Err(err) = stores.retrieve_pack(&pack.locations, digest, &outfile) {
+                    warn!("pack {} failed verification: {}", digest, err);
+                    corrupted.push(digest.to_string());
+                    affected.extend(paths.iter());
+                }
+            }
+        }
+        if corrupted.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<String> = affected
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        Err(Message::new(MessageCode::CorruptPacks)
+            .with("packs", &corrupted.join(", "))
+            .with("files", &names.join(", "))
+            .into())
+    }
+
     fn fetch_file(
         &mut self,
         checksum: &Checksum,
         filepath: &Path,
         passphrase: &str,
-    ) -> Result<(), Error> {
-        use anyhow::Context;
+    ) -> Result<u64, Error> {
         info!("restoring file from {} to {}", checksum, filepath.display());
-        let workspace = self.packpath.as_ref().unwrap().path().to_path_buf();
-        fs::create_dir_all(&workspace)
-            .with_context(|| format!("fetch_file fs::create_dir_all({})", workspace.display()))?;
+        let workspace = self.workspace()?;
         // look up the file record to get chunks
         let saved_file = self
             .dbase
             .get_file(checksum)?
-            .ok_or_else(|| anyhow!(format!("missing file: {:?}", checksum)))?;
+            .ok_or_else(|| Message::new(MessageCode::MissingFile).with("digest", &checksum))?;
+        if saved_file.chunks.len() > 120 {
+            // For very large files, give some indication that we will be
+            // busy for a while downloading all of the pack files.
+            warn!(
+                "retrieving packs for large file {} with {} chunks",
+                filepath.display(),
+                saved_file.chunks.len()
+            );
+        }
+        // look up chunk records to get pack record(s)
+        let packs = self.find_packs(&saved_file)?;
+        self.fetch_packs(packs, &workspace, passphrase)?;
         if saved_file.chunks.len() == 1 {
-            // If the file record contains a single chunk entry then its digest
-            // is actually that of the pack record rather than a chunk record.
-            let pack_digest = &saved_file.chunks[0].1;
-            self.fetch_pack(pack_digest, &workspace, passphrase)?;
             let mut cpath = PathBuf::from(&workspace);
             let filename = &saved_file.digest.to_string();
             cpath.push(filename);
-            let mut outfile = self.basepath.clone().unwrap();
-            outfile.push(filepath);
+            let outfile = self.target_path(filepath)?;
             let chunk_paths: Vec<&Path> = vec![&cpath];
             debug!(
                 "assembling 1-chunk file {} from {:?}",
@@ -640,24 +1464,6 @@ impl FileRestorer for FileRestorerImpl {
             );
             assemble_chunks(&chunk_paths, &outfile)?;
         } else {
-            if saved_file.chunks.len() > 120 {
-                // For very large files, give some indication that we will be
-                // busy for a while downloading all of the pack files.
-                warn!(
-                    "retrieving packs for large file {} with {} chunks",
-                    filepath.display(),
-                    saved_file.chunks.len()
-                );
-            }
-            // look up chunk records to get pack record(s)
-            for (_offset, chunk) in &saved_file.chunks {
-                let chunk_rec = self
-                    .dbase
-                    .get_chunk(chunk)?
-                    .ok_or_else(|| anyhow!(format!("missing chunk: {:?}", chunk)))?;
-                let pack_digest = chunk_rec.packfile.as_ref().unwrap();
-                self.fetch_pack(pack_digest, &workspace, passphrase)?;
-            }
             // sort the chunks by offset to produce the ordered file list
             let mut chunks = saved_file.chunks;
             chunks.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
@@ -670,22 +1476,60 @@ impl FileRestorer for FileRestorerImpl {
                 })
                 .collect();
             let chunk_paths: Vec<&Path> = chunk_bufs.iter().map(|b| b.as_path()).collect();
-            let mut outfile = self.basepath.clone().unwrap();
-            outfile.push(filepath);
+            let outfile = self.target_path(filepath)?;
             debug!("assembling N-chunk file {}", outfile.display());
             assemble_chunks(&chunk_paths, &outfile)?;
         }
-        Ok(())
+        // files recorded with older digests cannot be checked
+        if self.verify >= RestoreVerify::File && saved_file.digest.is_blake3() {
+            let outfile = self.target_path(filepath)?;
+            if Checksum::blake3_from_file(&outfile)? != saved_file.digest {
+                return Err(Message::new(MessageCode::CorruptFile)
+                    .with("path", &outfile.display())
+                    .with("digest", &saved_file.digest)
+                    .into());
+            }
+        }
+        Ok(saved_file.length)
+    }
+
+    fn is_restored(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error> {
+        let TreeReference::FILE(digest) = &entry.reference else {
+            return Ok(false);
+        };
+        let outfile = self.dataset_path(filepath)?;
+        let Ok(attr) = fs::symlink_metadata(&outfile) else {
+            return Ok(false);
+        };
+        if !attr.is_file() {
+            return Ok(false);
+        }
+        let Some(saved_file) = self.dbase.get_file(digest)? else {
+            return Ok(false);
+        };
+        // the time is set only after the content is complete; compare whole
+        // seconds as some file systems are not as precise as others
+        let mtime = DateTime::<Utc>::from(attr.modified()?);
+        Ok(attr.len() == saved_file.length && mtime.timestamp() == entry.mtime.timestamp())
     }
 
     fn restore_link(&self, contents: &[u8], filepath: &Path) -> Result<(), Error> {
         use anyhow::Context;
         info!("restoring symbolic link: {}", filepath.display());
         use os_str_bytes::OsStringBytes;
+        // link values are saved with forward slashes, convert them back to the
+        // native separator for this platform
+        #[allow(unused_mut)]
+        let mut contents = contents.to_owned();
+        #[cfg(target_family = "windows")]
+        for byte in contents.iter_mut() {
+            if *byte == b'/' {
+                *byte = b'\\';
+            }
+        }
         // this may panic if the bytes are not valid for this platform
-        let target = std::ffi::OsString::assert_from_raw_vec(contents.to_owned());
-        let mut outfile = self.basepath.clone().unwrap();
-        outfile.push(filepath);
+        let target = std::ffi::OsString::assert_from_raw_vec(contents);
+        let outfile = self.target_path(filepath)?;
         if let Some(parent) = outfile.parent() {
             fs::create_dir_all(parent).with_context(|| {
                 format!("restore_link fs::create_dir_all({})", parent.display())
@@ -713,8 +1557,7 @@ impl FileRestorer for FileRestorerImpl {
     fn restore_small(&self, contents: &[u8], filepath: &Path) -> Result<(), Error> {
         use anyhow::Context;
         info!("restoring small file: {}", filepath.display());
-        let mut outfile = self.basepath.clone().unwrap();
-        outfile.push(filepath);
+        let outfile = self.target_path(filepath)?;
         if let Some(parent) = outfile.parent() {
             fs::create_dir_all(parent).with_context(|| {
                 format!("restore_small fs::create_dir_all({})", parent.display())
@@ -724,6 +1567,125 @@ impl FileRestorer for FileRestorerImpl {
         }
         Err(anyhow!(format!("no parent for: {:?}", outfile)))
     }
+
+    fn restore_dir(&self, filepath: &Path) -> Result<(), Error> {
+        use anyhow::Context;
+        let outfile = self.target_path(filepath)?;
+        fs::create_dir_all(&outfile)
+            .with_context(|| format!("restore_dir fs::create_dir_all({})", outfile.display()))?;
+        Ok(())
+    }
+
+    fn set_mtime(&self, filepath: &Path, mtime: DateTime<Utc>) -> Result<(), Error> {
+        let outfile = self.target_path(filepath)?;
+        debug!("setting modified time of {}", outfile.display());
+        // directories can only be opened for changing attributes on Windows
+        // by using the backup semantics flag
+        #[cfg(target_family = "windows")]
+        let file = {
+            use std::os::windows::fs::OpenOptionsExt;
+            fs::OpenOptions::new()
+                // FILE_WRITE_ATTRIBUTES
+                .access_mode(0x100)
+                // FILE_FLAG_BACKUP_SEMANTICS
+                .custom_flags(0x0200_0000)
+                .open(&outfile)?
+        };
+        #[cfg(not(target_family = "windows"))]
+        let file = fs::File::open(&outfile)?;
+        file.set_modified(mtime.into())?;
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn restore_metadata(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        // links are not replaced here, they are not followed either
+        let outfile = self.dataset_path(filepath)?;
+        let mut attr = fs::symlink_metadata(&outfile)?;
+        let is_link = attr.file_type().is_symlink();
+        let mut changed = false;
+        let uid = entry.uid.filter(|uid| *uid != attr.uid());
+        let gid = entry.gid.filter(|gid| *gid != attr.gid());
+        // the owner is changed before the mode, as changing the owner clears
+        // the setuid and setgid bits
+        if uid.is_some() || gid.is_some() {
+            // changing the owner requires privileges the server may not have,
+            // which should not prevent the other changes
+            match std::os::unix::fs::lchown(&outfile, uid, gid) {
+                Ok(()) => {
+                    changed = true;
+                    attr = fs::symlink_metadata(&outfile)?;
+                }
+                Err(error) => warn!("cannot change owner of {}: {}", outfile.display(), error),
+            }
+        }
+        if let Some(mode) = entry.mode.filter(|_| !is_link) {
+            if attr.mode() & 0o7777 != mode & 0o7777 {
+                debug!("setting mode of {} to {:o}", outfile.display(), mode);
+                fs::set_permissions(&outfile, fs::Permissions::from_mode(mode & 0o7777))?;
+                changed = true;
+            }
+        }
+        if xattr::SUPPORTED_PLATFORM && !is_link {
+            for (name, digest) in entry.xattrs.iter() {
+                // macOS insists on checking a quarantined application when it
+                // is next opened, and reports a restored one as damaged
+                if name == appledouble::QUARANTINE || appledouble::is_fork(name) {
+                    continue;
+                }
+                // other platforms refuse the attributes that only macOS knows
+                if appledouble::is_apple(name) && !cfg!(target_os = "macos") {
+                    continue;
+                }
+                if self.set_xattr(&outfile, name, digest)? {
+                    changed = true;
+                }
+            }
+            // the snapshot recorded every attribute that the file had, so any
+            // others were added since then
+            if let Ok(names) = xattr::list(&outfile) {
+                for name in names {
+                    let nm = name.to_string_lossy();
+                    if !entry.xattrs.contains_key(nm.as_ref()) {
+                        match xattr::remove(&outfile, &name) {
+                            Ok(()) => changed = true,
+                            Err(error) => warn!(
+                                "cannot remove xattr {} of {}: {}",
+                                nm,
+                                outfile.display(),
+                                error
+                            ),
+                        }
+                    }
+                }
+            }
+        }
+        if !is_link && self.apply_forks(entry, &outfile)? {
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    #[cfg(target_family = "windows")]
+    fn restore_metadata(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error> {
+        // ownership and mode are not recorded on this platform
+        let outfile = self.dataset_path(filepath)?;
+        let attr = fs::symlink_metadata(&outfile)?;
+        if attr.file_type().is_symlink() {
+            return Ok(false);
+        }
+        self.apply_forks(entry, &outfile)
+    }
+
+    fn restore_forks(&self, entry: &TreeEntry, filepath: &Path) -> Result<bool, Error> {
+        let outfile = self.dataset_path(filepath)?;
+        let attr = fs::symlink_metadata(&outfile)?;
+        if attr.file_type().is_symlink() {
+            return Ok(false);
+        }
+        self.apply_forks(entry, &outfile)
+    }
 }
 
 impl Drop for FileRestorerImpl {
@@ -736,27 +1698,94 @@ impl Drop for FileRestorerImpl {
     }
 }
 
-// Verify the retrieved pack file digest matches the database record.
-fn verify_pack_digest(digest: &Checksum, path: &Path) -> Result<(), Error> {
-    let actual = Checksum::blake3_from_file(path)?;
-    if &actual != digest {
-        Err(anyhow!(format!(
-            "pack digest does not match: {} != {}",
-            &actual, digest
-        )))
+///
+/// Retrieve the pack file from the pack stores and extract its chunks into the
+/// workspace, where each chunk is named by its digest. The digests of the pack
+/// and chunks are checked according to the given level.
+///
+pub fn fetch_pack(
+    dbase: &dyn RecordRepository,
+    stores: &dyn PackRepository,
+    pack_digest: &Checksum,
+    workspace: &Path,
+    passphrase: &str,
+    verify: RestoreVerify,
+) -> Result<(), Error> {
+    let saved_pack = dbase
+        .get_pack(pack_digest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingPack).with("digest", &pack_digest))?;
+    // retrieve the pack file
+    let mut archive = PathBuf::new();
+    archive.push(workspace);
+    archive.push(pack_digest.to_string());
+    debug!("fetching pack {}", pack_digest);
+    if verify >= RestoreVerify::Pack {
+        // the pack repository verifies the digest of the retrieved file
+        stores.retrieve_pack(&saved_pack.locations, pack_digest, &archive)?;
     } else {
-        Ok(())
+        stores.retrieve_pack_unverified(&saved_pack.locations, &archive)?;
+    }
+    // unpack the contents
+    let names = pack::extract_pack(&archive, workspace, Some(passphrase))?;
+    pack::decode_chunks(workspace, &names, saved_pack.compression)?;
+    debug!("pack extracted");
+    fs::remove_file(archive)?;
+    if verify >= RestoreVerify::Chunk {
+        verify_chunks(workspace, &names, pack_digest)?;
     }
+    Ok(())
 }
 
-// Copy the chunk files to the given output location. The chunk files are left
-// in place and must be removed by the caller.
-fn assemble_chunks(chunks: &[&Path], outfile: &Path) -> Result<(), Error> {
-    use anyhow::Context;
-    if let Some(parent) = outfile.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("assemble_chunks fs::create_dir_all({})", parent.display()))?;
-        let mut file = fs::File::create(outfile)
+// Return the entry without its Finder information and resource fork if its
+// AppleDouble file is also an entry of the tree, as that file is restored as it
+// was, rather than being written anew from the attributes. On macOS, where
+// these are applied as extended attributes instead, the entry is unchanged.
+#[cfg(not(target_os = "macos"))]
+fn strip_sidecar_forks(entry: &TreeEntry, tree: &Tree) -> Option<TreeEntry> {
+    if !entry.xattrs.keys().any(|name| appledouble::is_fork(name)) {
+        return None;
+    }
+    let sidecar = format!("._{}", entry.name);
+    if !tree.entries.iter().any(|e| e.name == sidecar) {
+        return None;
+    }
+    let mut stripped = entry.clone();
+    stripped
+        .xattrs
+        .retain(|name, _| !appledouble::is_fork(name));
+    Some(stripped)
+}
+
+#[cfg(target_os = "macos")]
+fn strip_sidecar_forks(_entry: &TreeEntry, _tree: &Tree) -> Option<TreeEntry> {
+    None
+}
+
+// Compare the digest of each extracted chunk with its name, skipping those
+// chunks whose older digests cannot be checked.
+fn verify_chunks(workspace: &Path, names: &[String], pack_digest: &Checksum) -> Result<(), Error> {
+    for name in names.iter() {
+        let Ok(expected) = name.parse::<Checksum>() else {
+            continue;
+        };
+        if expected.is_blake3() && Checksum::blake3_from_file(&workspace.join(name))? != expected {
+            return Err(Message::new(MessageCode::CorruptChunk)
+                .with("digest", &expected)
+                .with("pack", pack_digest)
+                .into());
+        }
+    }
+    Ok(())
+}
+
+// Copy the chunk files to the given output location. The chunk files are left
+// in place and must be removed by the caller.
+fn assemble_chunks(chunks: &[&Path], outfile: &Path) -> Result<(), Error> {
+    use anyhow::Context;
+    if let Some(parent) = outfile.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("assemble_chunks fs::create_dir_all({})", parent.display()))?;
+        let mut file = fs::File::create(outfile)
             .with_context(|| format!("assemble_chunks File::create({})", outfile.display()))?;
         for infile in chunks {
             let mut cfile = fs::File::open(infile)
@@ -801,9 +1830,10 @@ mod tests {
         let repo = Arc::new(mock);
         fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
             let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
             restorer
                 .expect_load_dataset()
-                .returning(|_| Err(anyhow!("oh no!")));
+                .returning(|_, _| Err(anyhow!("oh no!")));
             Box::new(restorer)
         }
         let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
@@ -848,8 +1878,10 @@ mod tests {
         mock.expect_get_tree()
             .withf(|digest| digest.to_string() == "sha1-cafebabe")
             .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 3129, vec![]))));
 
-        let passphrase = crypto::get_passphrase();
+        let passphrase = crypto::get_passphrase().unwrap();
 
         //
         // Debugging the mocks can be tricky with the restorer running on a
@@ -860,9 +1892,10 @@ mod tests {
         //
         fn factory_fail(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
             let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
             restorer
                 .expect_load_dataset()
-                .returning(|_| Err(anyhow!("oh no!")));
+                .returning(|_, _| Err(anyhow!("oh no!")));
             Box::new(restorer)
         }
 
@@ -891,8 +1924,10 @@ mod tests {
         // act with successful request
         fn factory_pass(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
             let mut restorer = MockFileRestorer::new();
-            restorer.expect_load_dataset().returning(|_| Ok(()));
-            restorer.expect_fetch_file().returning(|_, _, _| Ok(()));
+            restorer.expect_set_verify().return_const(());
+            restorer.expect_load_dataset().returning(|_, _| Ok(()));
+            restorer.expect_verify().returning(|_| Ok(()));
+            restorer.expect_fetch_file().returning(|_, _, _| Ok(3129));
             Box::new(restorer)
         }
         sut.reset_completed();
@@ -912,6 +1947,64 @@ mod tests {
         assert_eq!(requests.len(), 1);
         let request = &requests[0];
         assert!(request.error_msg.is_none());
+        assert_eq!(request.files_restored, 1);
+        assert_eq!(request.bytes_total, 3129);
+        assert_eq!(request.bytes_restored, 3129);
+        assert!(request.current_file.is_none());
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    #[serial_test::serial]
+    async fn test_restorer_corrupt_packs() -> io::Result<()> {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(Checksum::BLAKE3(String::from(
+                    "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
+                ))),
+            )],
+            1,
+        );
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
+            let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
+            restorer.expect_load_dataset().returning(|_, _| Ok(()));
+            restorer.expect_verify().returning(|files| {
+                Err(Message::new(MessageCode::CorruptPacks)
+                    .with("packs", "blake3-cafebabe")
+                    .with("files", &files[0].1.display())
+                    .into())
+            });
+            restorer.expect_fetch_file().never();
+            Box::new(restorer)
+        }
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let sut = RestorerImpl::new(state, factory);
+        // act
+        let result = sut.start(Arc::new(mock));
+        assert!(result.is_ok());
+        let result = sut.enqueue(managers::restore::Request::new(
+            Checksum::SHA1("cafebabe".into()),
+            String::from("lorem-ipsum.txt"),
+            PathBuf::from("lorem-ipsum.txt"),
+            "dataset1".into(),
+            "password".into(),
+        ));
+        // assert
+        assert!(result.is_ok());
+        sut.wait_for_completed();
+        let requests = sut.requests();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.files_restored, 0);
+        let error_msg = request.error_msg.as_ref().unwrap();
+        assert!(error_msg.contains("blake3-cafebabe"));
+        assert!(error_msg.contains("lorem-ipsum.txt"));
         Ok(())
     }
 
@@ -966,6 +2059,9 @@ mod tests {
         mock.expect_get_tree()
             .withf(move |digest| digest.to_string() == roottree_str)
             .returning(move |_| Ok(Some(roottree.clone())));
+        mock.expect_get_file()
+            .times(3)
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 1024, vec![]))));
 
         //
         // Debugging the mocks can be tricky with the restorer running on a
@@ -976,8 +2072,31 @@ mod tests {
         //
         fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
             let mut restorer = MockFileRestorer::new();
-            restorer.expect_load_dataset().returning(|_| Ok(()));
-            restorer.expect_fetch_file().returning(|_, _, _| Ok(()));
+            restorer.expect_set_verify().return_const(());
+            restorer.expect_load_dataset().returning(|_, _| Ok(()));
+            restorer
+                .expect_verify()
+                .withf(|files| {
+                    files.len() == 3
+                        && files
+                            .iter()
+                            .any(|f| f.1 == Path::new("/home/town/lorem-ipsum.txt"))
+                })
+                .times(1)
+                .returning(|_| Ok(()));
+            restorer.expect_prefetch().returning(|_, _| Ok(()));
+            restorer.expect_fetch_file().returning(|_, _, _| Ok(1024));
+            restorer
+                .expect_restore_dir()
+                .withf(|path| path == Path::new("/home/town"))
+                .times(1)
+                .returning(|_| Ok(()));
+            // failing to set the time of the directory is not fatal
+            restorer
+                .expect_set_mtime()
+                .withf(|path, _| path == Path::new("/home/town"))
+                .times(1)
+                .returning(|_, _| Err(anyhow!("operation not permitted")));
             Box::new(restorer)
         }
 
@@ -987,7 +2106,7 @@ mod tests {
         let sut = RestorerImpl::new(state, factory);
         let result = sut.start(repo.clone());
         assert!(result.is_ok());
-        let passphrase = crypto::get_passphrase();
+        let passphrase = crypto::get_passphrase().unwrap();
         let result = sut.enqueue(managers::restore::Request::new(
             roottree_sha1,
             String::from("fixtures"),
@@ -1003,9 +2122,730 @@ mod tests {
         assert_eq!(requests.len(), 1);
         let request = &requests[0];
         assert!(request.error_msg.is_none());
+        assert_eq!(request.files_restored, 3);
+        assert_eq!(request.bytes_total, 3072);
+        assert_eq!(request.bytes_restored, 3072);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    #[serial_test::serial]
+    async fn test_restorer_restore_metadata_only() -> io::Result<()> {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/town"));
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        let subtree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
+                    ))),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60",
+                    ))),
+                ),
+            ],
+            2,
+        );
+        let subtree_str = subtree.digest.to_string();
+        let subtree_str_clone = subtree_str.clone();
+        mock.expect_get_tree()
+            .withf(move |digest| digest.to_string() == subtree_str_clone)
+            .returning(move |_| Ok(Some(subtree.clone())));
+        let subtree_digest: Checksum = FromStr::from_str(&subtree_str).unwrap();
+        let roottree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures"),
+                TreeReference::TREE(subtree_digest),
+            )],
+            1,
+        );
+        let roottree_sha1 = roottree.digest.clone();
+        let roottree_str = roottree_sha1.to_string();
+        mock.expect_get_tree()
+            .withf(move |digest| digest.to_string() == roottree_str)
+            .returning(move |_| Ok(Some(roottree.clone())));
+
+        fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
+            let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
+            restorer.expect_load_dataset().returning(|_, _| Ok(()));
+            restorer.expect_verify().never();
+            restorer.expect_fetch_file().never();
+            restorer.expect_restore_dir().never();
+            // only one of the files had any changes
+            restorer
+                .expect_restore_metadata()
+                .withf(|_, path| path == Path::new("town/lorem-ipsum.txt"))
+                .returning(|_, _| Ok(true));
+            restorer
+                .expect_restore_metadata()
+                .times(2)
+                .returning(|_, _| Ok(false));
+            restorer
+                .expect_set_mtime()
+                .withf(|path, _| path == Path::new("town"))
+                .times(1)
+                .returning(|_, _| Ok(()));
+            Box::new(restorer)
+        }
+
+        // act
+        let repo = Arc::new(mock);
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let sut = RestorerImpl::new(state, factory);
+        let result = sut.start(repo.clone());
+        assert!(result.is_ok());
+        let mut request = managers::restore::Request::new(
+            roottree_sha1,
+            String::from("fixtures"),
+            PathBuf::from("town"),
+            dataset_id.clone(),
+            crypto::get_passphrase().unwrap(),
+        );
+        request.metadata_only = true;
+        let result = sut.enqueue(request);
+        assert!(result.is_ok());
+
+        // assert
+        sut.wait_for_completed();
+        let requests = sut.requests();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert!(request.error_msg.is_none());
+        assert_eq!(request.files_restored, 1);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_file_restorer_restore_metadata() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        // arrange
+        let tmpdir = tempfile::tempdir()?;
+        let infile = tmpdir.path().join("lorem-ipsum.txt");
+        fs::copy("../test/fixtures/lorem-ipsum.txt", &infile)?;
+        fs::set_permissions(&infile, fs::Permissions::from_mode(0o644))?;
+        let mut entry = TreeEntry::new(&infile, TreeReference::SMALL(vec![]));
+        entry.mode = Some(0o100600);
+        entry.xattrs.clear();
+        let mock = MockRecordRepository::new();
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.basepath = Some(tmpdir.path().to_path_buf());
+        // act
+        let result = sut.restore_metadata(&entry, Path::new("lorem-ipsum.txt"));
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap());
+        let attr = fs::metadata(&infile)?;
+        assert_eq!(attr.permissions().mode() & 0o7777, 0o600);
+        // nothing left to change the second time
+        let result = sut.restore_metadata(&entry, Path::new("lorem-ipsum.txt"));
+        assert!(!result.unwrap());
+        // missing files are an error
+        let result = sut.restore_metadata(&entry, Path::new("missing.txt"));
+        assert!(result.is_err());
         Ok(())
     }
 
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_file_restorer_restore_forks() -> Result<(), Error> {
+        use crate::domain::helpers::appledouble::{AppleDouble, RESOURCE_FORK};
+        // arrange
+        let tmpdir = tempfile::tempdir()?;
+        let infile = tmpdir.path().join("lorem-ipsum.txt");
+        fs::copy("../test/fixtures/lorem-ipsum.txt", &infile)?;
+        let fork: Vec<u8> = vec![0xca, 0xfe, 0xba, 0xbe];
+        let digest = Checksum::sha1_from_bytes(&fork);
+        let mut entry = TreeEntry::new(&infile, TreeReference::SMALL(vec![]));
+        entry.xattrs.clear();
+        entry.xattrs.insert(RESOURCE_FORK.to_owned(), digest);
+        let mut mock = MockRecordRepository::new();
+        let value = fork.clone();
+        mock.expect_get_xattr()
+            .returning(move |_| Ok(Some(value.clone())));
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.basepath = Some(tmpdir.path().to_path_buf());
+        // act
+        let result = sut.restore_forks(&entry, Path::new("lorem-ipsum.txt"));
+        // assert
+        assert!(result.unwrap());
+        let double = AppleDouble::read(&infile)?.unwrap();
+        assert_eq!(double.resource_fork, Some(fork));
+        assert!(double.finder_info.is_none());
+        // nothing left to change the second time
+        let result = sut.restore_forks(&entry, Path::new("lorem-ipsum.txt"));
+        assert!(!result.unwrap());
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_strip_sidecar_forks() {
+        use crate::domain::helpers::appledouble::RESOURCE_FORK;
+        let infile = Path::new("../test/fixtures/lorem-ipsum.txt");
+        let mut entry = TreeEntry::new(infile, TreeReference::SMALL(vec![]));
+        entry.xattrs.clear();
+        entry
+            .xattrs
+            .insert(RESOURCE_FORK.to_owned(), Checksum::SHA1("cafebabe".into()));
+        entry
+            .xattrs
+            .insert("user.comment".to_owned(), Checksum::SHA1("deadbeef".into()));
+        let tree = Tree::new(vec![entry.clone()], 1);
+        assert!(strip_sidecar_forks(&entry, &tree).is_none());
+        // the AppleDouble file of the entry is also in the snapshot
+        let mut sidecar = TreeEntry::new(infile, TreeReference::SMALL(vec![]));
+        sidecar.name = "._lorem-ipsum.txt".into();
+        let tree = Tree::new(vec![sidecar, entry.clone()], 2);
+        let stripped = strip_sidecar_forks(&entry, &tree).unwrap();
+        assert_eq!(stripped.xattrs.len(), 1);
+        assert!(stripped.xattrs.contains_key("user.comment"));
+    }
+
+    #[test]
+    fn test_file_restorer_is_restored() -> Result<(), Error> {
+        // arrange
+        let tmpdir = tempfile::tempdir()?;
+        let target = tmpdir.path().join("elsewhere");
+        fs::create_dir(&target)?;
+        let infile = target.join("lorem-ipsum.txt");
+        fs::copy("../test/fixtures/lorem-ipsum.txt", &infile)?;
+        let digest = Checksum::BLAKE3("cafebabe".into());
+        let entry = TreeEntry::new(&infile, TreeReference::FILE(digest));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 3129, vec![]))));
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.basepath = Some(tmpdir.path().join("dataset"));
+        sut.target = Some(target);
+        // act and assert
+        assert!(sut.is_restored(&entry, Path::new("lorem-ipsum.txt"))?);
+        assert!(!sut.is_restored(&entry, Path::new("missing.txt"))?);
+        // a file whose time was not yet set is incomplete
+        let mut earlier = entry.clone();
+        earlier.mtime = entry.mtime - chrono::TimeDelta::hours(1);
+        assert!(!sut.is_restored(&earlier, Path::new("lorem-ipsum.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_restorer_parallel_fetch() -> Result<(), Error> {
+        use crate::domain::entities::{Chunk, Pack, PackLocation};
+        use crate::domain::helpers;
+        use crate::domain::repositories::MockPackRepository;
+        // arrange: one pack for each chunk of the file
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        let chunks = helpers::find_file_chunks(infile, 16384)?;
+        assert_eq!(chunks.len(), 5);
+        let packdir = tempfile::tempdir()?;
+        let mut file_chunks: Vec<(u64, Checksum)> = Vec::new();
+        let mut chunk_records: Vec<Chunk> = Vec::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let pack_digest = Checksum::BLAKE3(format!("pack{}", index));
+            let packfile = packdir.path().join(pack_digest.to_string());
+            let mut builder = pack::PackBuilder::new(1048576).password("keyboard cat");
+            builder.initialize(&packfile)?;
+            builder.add_chunk(chunk)?;
+            builder.finalize()?;
+            file_chunks.push((chunk.offset as u64, chunk.digest.clone()));
+            chunk_records.push(chunk.clone().packfile(pack_digest));
+        }
+        let file_digest = Checksum::blake3_from_file(infile)?;
+        let file = File::new(file_digest.clone(), 109466, file_chunks);
+        let basedir = tempfile::tempdir()?;
+        let dataset = Dataset::new(basedir.path());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        let packpath = packdir.path().to_path_buf();
+        mock.expect_load_dataset_stores().returning(move |_| {
+            let packpath = packpath.clone();
+            let mut stores = MockPackRepository::new();
+            // each pack is retrieved only once, even for two files
+            stores
+                .expect_retrieve_pack()
+                .times(5)
+                .returning(move |_, digest, outfile| {
+                    fs::copy(packpath.join(digest.to_string()), outfile)?;
+                    Ok(())
+                });
+            Ok(Box::new(stores))
+        });
+        mock.expect_get_file()
+            .returning(move |_| Ok(Some(file.clone())));
+        mock.expect_get_chunk().returning(move |digest| {
+            Ok(chunk_records.iter().find(|c| &c.digest == digest).cloned())
+        });
+        mock.expect_get_pack().returning(|digest| {
+            let location = PackLocation::new("store1", "bucket1", &digest.to_string());
+            Ok(Some(Pack::new(digest.clone(), vec![location])))
+        });
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.set_parallelism(3);
+        sut.load_dataset("dataset1", None)?;
+        // act
+        sut.prefetch(&[file_digest.clone()], "keyboard cat")?;
+        sut.fetch_file(&file_digest, Path::new("first.jpg"), "keyboard cat")?;
+        sut.fetch_file(&file_digest, Path::new("second.jpg"), "keyboard cat")?;
+        // assert
+        for name in ["first.jpg", "second.jpg"] {
+            let outfile = basedir.path().join(name);
+            let digest = Checksum::blake3_from_file(&outfile)?;
+            assert_eq!(digest, file_digest);
+        }
+        Ok(())
+    }
+
+    // Build a restorer whose packs are copied from the given directory, and
+    // which expects them to be retrieved with or without their digest being
+    // checked, according to the verify level.
+    fn make_level_restorer(
+        level: RestoreVerify,
+        packdir: &Path,
+        basepath: &Path,
+        files: Vec<File>,
+        chunks: Vec<crate::domain::entities::Chunk>,
+    ) -> FileRestorerImpl {
+        use crate::domain::entities::{Pack, PackLocation};
+        use crate::domain::repositories::MockPackRepository;
+        let dataset = Dataset::new(basepath);
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        let packpath = packdir.to_path_buf();
+        mock.expect_load_dataset_stores().returning(move |_| {
+            let packpath = packpath.clone();
+            let mut stores = MockPackRepository::new();
+            if level >= RestoreVerify::Pack {
+                stores
+                    .expect_retrieve_pack()
+                    .returning(move |_, digest, outfile| {
+                        fs::copy(packpath.join(digest.to_string()), outfile)?;
+                        Ok(())
+                    });
+                stores.expect_retrieve_pack_unverified().never();
+            } else {
+                stores.expect_retrieve_pack().never();
+                stores
+                    .expect_retrieve_pack_unverified()
+                    .returning(move |locations, outfile| {
+                        fs::copy(packpath.join(&locations[0].object), outfile)?;
+                        Ok(())
+                    });
+            }
+            Ok(Box::new(stores))
+        });
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        mock.expect_get_chunk()
+            .returning(move |digest| Ok(chunks.iter().find(|c| &c.digest == digest).cloned()));
+        mock.expect_get_pack().returning(|digest| {
+            let location = PackLocation::new("store1", "bucket1", &digest.to_string());
+            Ok(Some(Pack::new(digest.clone(), vec![location])))
+        });
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.set_verify(level);
+        sut
+    }
+
+    #[test]
+    fn test_file_restorer_verify_levels() -> Result<(), Error> {
+        use crate::domain::entities::Chunk;
+        use crate::domain::helpers;
+        // arrange: one pack holding the chunks of the file, and another that
+        // holds a chunk under the wrong digest
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        let chunks = helpers::find_file_chunks(infile, 16384)?;
+        let packdir = tempfile::tempdir()?;
+        let good_pack = Checksum::BLAKE3("pack1".into());
+        let mut builder = pack::PackBuilder::new(1048576).password("keyboard cat");
+        builder.initialize(&packdir.path().join(good_pack.to_string()))?;
+        for chunk in chunks.iter() {
+            builder.add_chunk(chunk)?;
+        }
+        builder.finalize()?;
+        let bad_pack = Checksum::BLAKE3("pack2".into());
+        let mut mislabeled = chunks[0].clone();
+        mislabeled.digest = Checksum::BLAKE3("deadbeef".into());
+        let mut builder = pack::PackBuilder::new(1048576).password("keyboard cat");
+        builder.initialize(&packdir.path().join(bad_pack.to_string()))?;
+        builder.add_chunk(&mislabeled)?;
+        builder.finalize()?;
+        let mut chunk_records: Vec<Chunk> = chunks
+            .iter()
+            .map(|c| c.clone().packfile(good_pack.clone()))
+            .collect();
+        chunk_records.push(mislabeled.clone().packfile(bad_pack));
+        let file_chunks: Vec<(u64, Checksum)> = chunks
+            .iter()
+            .map(|c| (c.offset as u64, c.digest.clone()))
+            .collect();
+        // a file whose recorded digest does not match its content
+        let wrong_digest = File::new(
+            Checksum::BLAKE3("cafebabe".into()),
+            109466,
+            file_chunks.clone(),
+        );
+        // a file that needs the mislabeled chunk
+        let mut bad_chunks = file_chunks;
+        bad_chunks[0].1 = mislabeled.digest.clone();
+        let needs_bad = File::new(Checksum::BLAKE3("c0ffee".into()), 109466, bad_chunks);
+        let files = vec![wrong_digest.clone(), needs_bad.clone()];
+        let basedir = tempfile::tempdir()?;
+        let restore = |level: RestoreVerify, file: &File| -> Result<u64, Error> {
+            let mut sut = make_level_restorer(
+                level,
+                packdir.path(),
+                basedir.path(),
+                files.clone(),
+                chunk_records.clone(),
+            );
+            sut.load_dataset("dataset1", None)?;
+            sut.fetch_file(&file.digest, Path::new("restored.jpg"), "keyboard cat")
+        };
+        // act and assert: checking nothing lets everything through
+        assert!(restore(RestoreVerify::None, &needs_bad).is_ok());
+        assert!(restore(RestoreVerify::Pack, &needs_bad).is_ok());
+        let err = restore(RestoreVerify::Chunk, &needs_bad).unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::CorruptChunk);
+        assert_eq!(message.params["digest"], "blake3-deadbeef");
+        assert!(restore(RestoreVerify::Chunk, &wrong_digest).is_ok());
+        let err = restore(RestoreVerify::File, &wrong_digest).unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::CorruptFile);
+        assert_eq!(message.params["digest"], "blake3-cafebabe");
+
+        // a pack fetched with fewer checks is fetched again when more are wanted
+        let mut sut = make_level_restorer(
+            RestoreVerify::Pack,
+            packdir.path(),
+            basedir.path(),
+            files.clone(),
+            chunk_records.clone(),
+        );
+        sut.load_dataset("dataset1", None)?;
+        let outfile = Path::new("restored.jpg");
+        sut.fetch_file(&needs_bad.digest, outfile, "keyboard cat")?;
+        sut.set_verify(RestoreVerify::Chunk);
+        let err = sut
+            .fetch_file(&needs_bad.digest, outfile, "keyboard cat")
+            .unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::CorruptChunk);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_restorer_verify() -> Result<(), Error> {
+        use crate::domain::entities::{Chunk, Pack, PackLocation};
+        use crate::domain::repositories::MockPackRepository;
+        // arrange: one good pack, one corrupted pack, one without a digest,
+        // and one whose MD5 differs while the content is intact
+        let chunk1 = Checksum::BLAKE3("chunk1".into());
+        let chunk2 = Checksum::BLAKE3("chunk2".into());
+        let chunk3 = Checksum::BLAKE3("chunk3".into());
+        let first = File::new(
+            Checksum::BLAKE3("file1".into()),
+            3072,
+            vec![(0, chunk1.clone()), (1024, chunk2.clone())],
+        );
+        let second = File::new(
+            Checksum::BLAKE3("file2".into()),
+            2048,
+            vec![(0, chunk2.clone()), (1024, chunk3.clone())],
+        );
+        let third = File::new(
+            Checksum::BLAKE3("file3".into()),
+            1024,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let fourth = File::new(
+            Checksum::BLAKE3("file4".into()),
+            1024,
+            vec![(0, Checksum::BLAKE3("pack4".into()))],
+        );
+        let files = vec![first.clone(), second.clone(), third.clone(), fourth.clone()];
+        let dataset = Dataset::new(Path::new("/home/town"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_load_dataset_stores().returning(|_| {
+            let mut stores = MockPackRepository::new();
+            stores
+                .expect_check_pack()
+                .times(3)
+                .returning(|locations, _| Ok(Some(locations[0].object == "pack1")));
+            stores
+                .expect_retrieve_pack()
+                .times(2)
+                .returning(|locations, _, _| {
+                    if locations[0].object == "pack2" {
+                        Err(anyhow!("pack digest does not match"))
+                    } else {
+                        Ok(())
+                    }
+                });
+            Ok(Box::new(stores))
+        });
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        mock.expect_get_chunk().returning(|digest| {
+            let pack = if digest.to_string().ends_with("chunk1") {
+                "pack1"
+            } else if digest.to_string().ends_with("chunk2") {
+                "pack2"
+            } else {
+                "pack3"
+            };
+            let chunk = Chunk::new(digest.clone(), 0, 1024);
+            Ok(Some(chunk.packfile(Checksum::BLAKE3(pack.into()))))
+        });
+        mock.expect_get_pack().returning(|digest| {
+            let name = digest.to_string().replace("blake3-", "");
+            let location = PackLocation::new("store1", "bucket1", &name);
+            let pack = Pack::new(digest.clone(), vec![location]);
+            if name == "pack3" {
+                Ok(Some(pack))
+            } else {
+                Ok(Some(pack.md5("40756e6058736e2485119410c2014380".into())))
+            }
+        });
+        let mut sut = FileRestorerImpl::new(Arc::new(mock));
+        sut.load_dataset("dataset1", None)?;
+        // act
+        let result = sut.verify(&[
+            (first.digest, PathBuf::from("town/first.txt")),
+            (second.digest, PathBuf::from("town/second.txt")),
+            (third.digest, PathBuf::from("town/third.txt")),
+            (fourth.digest, PathBuf::from("town/fourth.txt")),
+        ]);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::CorruptPacks);
+        assert_eq!(message.params["packs"], "blake3-pack2");
+        assert_eq!(message.params["files"], "town/first.txt, town/second.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_restorer_merge_requests() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let subtree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
+                    ))),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60",
+                    ))),
+                ),
+            ],
+            2,
+        );
+        let subtree_sha1 = subtree.digest.clone();
+        let subtree_str = subtree_sha1.to_string();
+        let subtree_str_clone = subtree_str.clone();
+        mock.expect_get_tree()
+            .withf(move |digest| digest.to_string() == subtree_str_clone)
+            .returning(move |_| Ok(Some(subtree.clone())));
+        let roottree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures"),
+                TreeReference::TREE(subtree_sha1.clone()),
+            )],
+            1,
+        );
+        let roottree_sha1 = roottree.digest.clone();
+        let roottree_str = roottree_sha1.to_string();
+        mock.expect_get_tree()
+            .withf(move |digest| digest.to_string() == roottree_str)
+            .returning(move |_| Ok(Some(roottree.clone())));
+        // the directory and one other file are restored, the file within the
+        // directory is not restored separately
+        fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
+            let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
+            restorer.expect_load_dataset().returning(|_, _| Ok(()));
+            restorer.expect_verify().returning(|_| Ok(()));
+            restorer
+                .expect_fetch_file()
+                .times(3)
+                .returning(|_, _, _| Ok(1024));
+            restorer
+                .expect_prefetch()
+                .withf(|files, _| files.len() == 2)
+                .times(1)
+                .returning(|_, _| Ok(()));
+            restorer.expect_restore_dir().returning(|_| Ok(()));
+            restorer.expect_set_mtime().returning(|_, _| Ok(()));
+            Box::new(restorer)
+        }
+        let passphrase = crypto::get_passphrase().unwrap();
+        let file_request = managers::restore::Request::new(
+            subtree_sha1.clone(),
+            String::from("lorem-ipsum.txt"),
+            PathBuf::from("/home/town/lorem-ipsum.txt"),
+            "dataset1".into(),
+            passphrase.clone(),
+        );
+        let tree_request = managers::restore::Request::new(
+            roottree_sha1,
+            String::from("fixtures"),
+            PathBuf::from("/home/town"),
+            "dataset1".into(),
+            passphrase.clone(),
+        );
+        let other_request = managers::restore::Request::new(
+            subtree_sha1,
+            String::from("lorem-ipsum.txt"),
+            PathBuf::from("/home/town/lorem-ipsum.txt"),
+            "dataset2".into(),
+            passphrase.clone(),
+        );
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 1024, vec![]))));
+        let mut queue: VecDeque<Request> = VecDeque::new();
+        queue.push_back(file_request);
+        queue.push_back(tree_request);
+        queue.push_back(other_request);
+        let pending = Arc::new(Mutex::new(queue));
+        let active = Arc::new(Mutex::new(None));
+        let completed = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        let mut sut =
+            RestoreSupervisor::new(repo, state, pending, active, completed.clone(), factory);
+        // act
+        let result = sut.process_queue();
+        // assert
+        assert!(result.is_ok());
+        let (lock, _cvar) = &*completed;
+        let requests = lock.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        let tree = requests.iter().find(|r| r.entry == "fixtures").unwrap();
+        assert_eq!(tree.merged.len(), 1);
+        assert!(tree.merged_into.is_none());
+        let file = requests
+            .iter()
+            .find(|r| r.dataset == "dataset1" && r.entry == "lorem-ipsum.txt")
+            .unwrap();
+        assert_eq!(file.merged_into, Some(PathBuf::from("/home/town")));
+        assert!(file.finished.is_some());
+        assert!(file.error_msg.is_none());
+        let other = requests.iter().find(|r| r.dataset == "dataset2").unwrap();
+        assert!(other.merged_into.is_none());
+        assert_eq!(other.files_restored, 1);
+    }
+
+    #[test]
+    fn test_restorer_resume_snapshot() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let tree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
+                    ))),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3(String::from(
+                        "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60",
+                    ))),
+                ),
+            ],
+            2,
+        );
+        let tree_digest = tree.digest.clone();
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 1024, vec![]))));
+        // the first file was restored before the interruption
+        fn factory(_dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
+            let mut restorer = MockFileRestorer::new();
+            restorer.expect_set_verify().return_const(());
+            restorer
+                .expect_load_dataset()
+                .withf(|_, target| target.as_deref() == Some(Path::new("/mnt/elsewhere")))
+                .returning(|_, _| Ok(()));
+            restorer.expect_verify().returning(|_| Ok(()));
+            restorer
+                .expect_is_restored()
+                .returning(|entry, _| Ok(entry.name == "lorem-ipsum.txt"));
+            restorer
+                .expect_prefetch()
+                .withf(|files, _| files.len() == 1)
+                .returning(|_, _| Ok(()));
+            restorer
+                .expect_fetch_file()
+                .withf(|_, path, _| path == Path::new("washington-journal.txt"))
+                .times(1)
+                .returning(|_, _, _| Ok(1024));
+            restorer
+                .expect_restore_dir()
+                .withf(|path| path.as_os_str().is_empty())
+                .returning(|_| Ok(()));
+            restorer
+                .expect_restore_metadata()
+                .returning(|_, _| Ok(false));
+            restorer
+                .expect_set_mtime()
+                .times(2)
+                .returning(|_, _| Ok(()));
+            Box::new(restorer)
+        }
+        let mut request = Request::new(
+            tree_digest,
+            String::new(),
+            PathBuf::new(),
+            "dataset1".into(),
+            crypto::get_passphrase().unwrap(),
+        );
+        request.target = Some(PathBuf::from("/mnt/elsewhere"));
+        request.resumable = true;
+        let mut queue: VecDeque<Request> = VecDeque::new();
+        queue.push_back(request);
+        let pending = Arc::new(Mutex::new(queue));
+        let active = Arc::new(Mutex::new(None));
+        let completed = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let repo: Arc<dyn RecordRepository> = Arc::new(mock);
+        let mut sut =
+            RestoreSupervisor::new(repo, state, pending, active, completed.clone(), factory);
+        // act
+        let result = sut.process_queue();
+        // assert
+        assert!(result.is_ok());
+        let (lock, _cvar) = &*completed;
+        let requests = lock.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].error_msg.is_none());
+        assert_eq!(requests[0].files_restored, 2);
+        assert_eq!(requests[0].bytes_total, 2048);
+        assert_eq!(requests[0].bytes_restored, 2048);
+    }
+
     #[actix_rt::test]
     #[serial_test::serial]
     async fn test_restorer_start_stop_restart() -> io::Result<()> {
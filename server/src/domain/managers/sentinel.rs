@@ -0,0 +1,334 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `sentinel` module keeps a small file of known content in the base path
+//! of those datasets that have the `sentinel` property set, and verifies after
+//! each backup, and during maintenance, that the file survives the round trip
+//! through the backup pipeline: chunking, encryption, upload, download, and
+//! decryption. A mismatch indicates corruption somewhere along the way, long
+//! before a restore would otherwise reveal it.
+//!
+//! The content is derived entirely from a short header naming the dataset and
+//! the time the file was written, such that any copy of the file can be checked
+//! without keeping the original. The file is rewritten once a day so that new
+//! content passes through the pipeline regularly.
+
+use crate::domain::entities::{
+    Checksum, Dataset, Message, MessageCode, RestoreVerify, TreeReference,
+};
+use crate::domain::managers::restore;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Name of the sentinel file within the base path of the dataset.
+pub const SENTINEL_NAME: &str = ".zorigami-sentinel";
+
+// First line of every sentinel file.
+const MAGIC: &str = "zorigami sentinel 1";
+
+// Number of lines of filler following the header.
+const FILLER_LINES: u32 = 64;
+
+// Age at which the sentinel file is written anew.
+const REFRESH_HOURS: i64 = 24;
+
+///
+/// Write the sentinel file into the base path of the dataset if the dataset
+/// has the `sentinel` property and the file is missing, invalid, or due to be
+/// refreshed. Returns `true` if the file was written.
+///
+pub fn plant(dataset: &Dataset) -> Result<bool, Error> {
+    if !dataset.sentinel() {
+        return Ok(false);
+    }
+    let path = dataset.basepath.join(SENTINEL_NAME);
+    if let Ok(content) = fs::read(&path) {
+        if let Some((id, written)) = check(&content) {
+            if id == dataset.id && Utc::now() - written < TimeDelta::hours(REFRESH_HOURS) {
+                return Ok(false);
+            }
+        }
+    }
+    fs::write(&path, generate(&dataset.id, Utc::now()))?;
+    Ok(true)
+}
+
+///
+/// Retrieve the sentinel file of the given snapshot from the pack stores and
+/// check that its content is intact. Returns `false` if the snapshot does not
+/// contain a sentinel file, and an error if the content does not match.
+///
+pub fn verify(
+    repo: &dyn RecordRepository,
+    dataset: &Dataset,
+    snapshot: &Checksum,
+    passphrase: &str,
+) -> Result<bool, Error> {
+    let snap = repo
+        .get_snapshot(snapshot)?
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", snapshot))?;
+    let tree = repo
+        .get_tree(&snap.tree)?
+        .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &snap.tree))?;
+    let entry = match tree.entries.iter().find(|e| e.name == SENTINEL_NAME) {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+    let content = match &entry.reference {
+        TreeReference::FILE(digest) => retrieve(repo, dataset, digest, passphrase)?,
+        _ => return Err(anyhow!(format!("{} is not a file", SENTINEL_NAME))),
+    };
+    match check(&content) {
+        Some((id, _)) if id == dataset.id => Ok(true),
+        _ => Err(Message::new(MessageCode::SentinelMismatch)
+            .with("path", &dataset.basepath.display())
+            .with("digest", snapshot)
+            .into()),
+    }
+}
+
+///
+/// Verify the sentinel files in the most recent completed snapshot of each
+/// dataset that has the `sentinel` property, returning the number of files
+/// that were verified, or an error naming the datasets that failed.
+///
+pub fn verify_datasets(repo: &dyn RecordRepository, passphrase: &str) -> Result<usize, Error> {
+    let mut count: usize = 0;
+    let mut failed: Vec<String> = Vec::new();
+    for dataset in repo.get_datasets()? {
+        if !dataset.sentinel() {
+            continue;
+        }
+        let Some(snapshot) = latest_completed(repo, &dataset.id)? else {
+            continue;
+        };
+        match verify(repo, &dataset, &snapshot, passphrase) {
+            Ok(true) => count += 1,
+            Ok(false) => warn!("sentinel: dataset {} has no sentinel file", dataset.id),
+            Err(err) => failed.push(format!("{} ({})", dataset.id, err)),
+        }
+    }
+    if failed.is_empty() {
+        info!("sentinel: verified {} sentinel files", count);
+        Ok(count)
+    } else {
+        Err(anyhow!(format!(
+            "sentinel files failed verification: {}",
+            failed.join(", ")
+        )))
+    }
+}
+
+// Find the most recent snapshot of the dataset that has finished, skipping
+// over one that is still in progress.
+fn latest_completed(
+    repo: &dyn RecordRepository,
+    dataset_id: &str,
+) -> Result<Option<Checksum>, Error> {
+    let Some(latest) = repo.get_latest_snapshot(dataset_id)? else {
+        return Ok(None);
+    };
+    let snapshot = repo
+        .get_snapshot(&latest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &latest))?;
+    if snapshot.end_time.is_some() {
+        Ok(Some(latest))
+    } else {
+        Ok(snapshot.parent)
+    }
+}
+
+// Fetch the packs holding the chunks of the file and assemble its content.
+fn retrieve(
+    repo: &dyn RecordRepository,
+    dataset: &Dataset,
+    digest: &Checksum,
+    passphrase: &str,
+) -> Result<Vec<u8>, Error> {
+    let file = repo
+        .get_file(digest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingFile).with("digest", digest))?;
+    let stores = repo.load_dataset_stores(dataset)?;
+    fs::create_dir_all(&dataset.workspace)?;
+    let workspace = tempfile::TempDir::new_in(&dataset.workspace)?;
+    let mut content: Vec<u8> = Vec::with_capacity(file.length as usize);
+    if file.chunks.len() == 1 {
+        // the chunk of a single-chunk file is named by the file digest
+        let pack_digest = &file.chunks[0].1;
+        restore::fetch_pack(
+            repo,
+            stores.as_ref(),
+            pack_digest,
+            workspace.path(),
+            passphrase,
+            RestoreVerify::default(),
+        )?;
+        content.extend(read_chunk(workspace.path(), &file.digest)?);
+    } else {
+        let mut chunks = file.chunks;
+        chunks.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let mut fetched: HashSet<Checksum> = HashSet::new();
+        for (_, chunk_digest) in chunks.iter() {
+            let chunk = repo.get_chunk(chunk_digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingChunk).with("digest", chunk_digest)
+            })?;
+            let pack_digest = chunk
+                .packfile
+                .ok_or_else(|| anyhow!(format!("chunk without pack: {}", chunk_digest)))?;
+            if fetched.insert(pack_digest.clone()) {
+                restore::fetch_pack(
+                    repo,
+                    stores.as_ref(),
+                    &pack_digest,
+                    workspace.path(),
+                    passphrase,
+                    RestoreVerify::default(),
+                )?;
+            }
+            content.extend(read_chunk(workspace.path(), chunk_digest)?);
+        }
+    }
+    Ok(content)
+}
+
+// Read the extracted chunk file from the workspace.
+fn read_chunk(workspace: &Path, digest: &Checksum) -> Result<Vec<u8>, Error> {
+    let path = workspace.join(digest.to_string());
+    fs::read(&path).map_err(|err| anyhow!(format!("chunk {} not in pack: {}", digest, err)))
+}
+
+// Produce the content of the sentinel file for the dataset as written at the
+// given time.
+fn generate(dataset_id: &str, written: DateTime<Utc>) -> Vec<u8> {
+    let header = format!(
+        "{}\ndataset {}\nwritten {}\n",
+        MAGIC,
+        dataset_id,
+        written.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    let mut content = header.clone().into_bytes();
+    for line in 0..FILLER_LINES {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(header.as_bytes());
+        hasher.update(&line.to_le_bytes());
+        content.extend(hasher.finalize().to_hex().as_bytes());
+        content.push(b'\n');
+    }
+    content
+}
+
+// Check that the content is exactly what would be generated from its header,
+// returning the dataset identifier and time of writing if so.
+fn check(content: &[u8]) -> Option<(String, DateTime<Utc>)> {
+    let text = std::str::from_utf8(content).ok()?;
+    let mut lines = text.lines();
+    if lines.next()? != MAGIC {
+        return None;
+    }
+    let dataset_id = lines.next()?.strip_prefix("dataset ")?;
+    let written = lines.next()?.strip_prefix("written ")?;
+    let written = DateTime::parse_from_rfc3339(written)
+        .ok()?
+        .with_timezone(&Utc);
+    if generate(dataset_id, written) == content {
+        Some((dataset_id.to_owned(), written))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{FileCounts, Snapshot, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+
+    #[test]
+    fn test_generate_check() {
+        let written = Utc.with_ymd_and_hms(2024, 5, 1, 8, 30, 0).unwrap();
+        let content = generate("cafebabe", written);
+        assert!(content.len() > 80 * 4);
+        let (id, time) = check(&content).unwrap();
+        assert_eq!(id, "cafebabe");
+        assert_eq!(time, written);
+        // any change to the content is detected
+        let mut flipped = content.clone();
+        let last = flipped.len() - 2;
+        flipped[last] ^= 0x01;
+        assert!(check(&flipped).is_none());
+        assert!(check(&content[..content.len() - 1]).is_none());
+        let mut renamed = content.clone();
+        renamed[28] = b'd';
+        assert!(check(&renamed).is_none());
+        assert!(check(b"hello world").is_none());
+    }
+
+    #[test]
+    fn test_plant() -> Result<(), Error> {
+        // arrange
+        let basepath = tempfile::tempdir()?;
+        let mut dataset = Dataset::new(basepath.path());
+        dataset.id = "cafebabe".into();
+        let path = basepath.path().join(SENTINEL_NAME);
+        // act & assert
+        assert!(!plant(&dataset)?);
+        assert!(!path.exists());
+        dataset.properties.insert("sentinel".into(), "true".into());
+        assert!(plant(&dataset)?);
+        let (id, _) = check(&fs::read(&path)?).unwrap();
+        assert_eq!(id, "cafebabe");
+        // a recent and valid file is left alone
+        assert!(!plant(&dataset)?);
+        // stale or damaged files are replaced
+        let stale = Utc::now() - TimeDelta::hours(REFRESH_HOURS + 1);
+        fs::write(&path, generate("cafebabe", stale))?;
+        assert!(plant(&dataset)?);
+        fs::write(&path, b"not a sentinel")?;
+        assert!(plant(&dataset)?);
+        assert!(check(&fs::read(&path)?).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_without_sentinel() {
+        // arrange
+        let entry = TreeEntry::new(
+            Path::new("notes.txt"),
+            TreeReference::SMALL(b"hello".to_vec()),
+        );
+        let tree = Tree::new(vec![entry], 1);
+        let snapshot = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        let digest = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file().never();
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        // act
+        let result = verify(&mock, &dataset, &digest, "keyboard cat");
+        // assert
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn test_verify_datasets_disabled() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(|| Ok(vec![Dataset::new(Path::new("/home/planet"))]));
+        mock.expect_get_latest_snapshot().never();
+        // act
+        let result = verify_datasets(&mock, "keyboard cat");
+        // assert
+        assert_eq!(result.unwrap(), 0);
+    }
+}
@@ -0,0 +1,327 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `settings` module re-reads the environment, including the `.env` file,
+//! and applies those settings that can be changed while the server is running.
+//!
+//! The reloaded values are kept here, rather than written back to the process
+//! environment, which is not safe to modify while other threads are running.
+//! The live settings are read through [`var`] to see the reloaded values.
+//!
+//! Backup schedules and pack store properties live in the database and are
+//! read anew for each backup, so they never require a reload.
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io;
+use std::sync::{OnceLock, RwLock};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+// Settings that take effect only after a restart. The passphrase unlocks the
+// keys saved in the database and must not change underneath a running backup,
+// while the others are read from the environment by code outside of the
+// server, or only once at startup.
+const RESTART_SETTINGS: &[&str] = &[
+    "BACKUP_SEMANTICS",
+    "DB_PATH",
+    "HOST",
+    "KEYRING_ACCOUNT",
+    "KEYRING_SERVICE",
+    "LOG_FORMAT",
+    "PASSPHRASE",
+    "PASSPHRASE_COMMAND",
+    "PASSPHRASE_PROVIDER",
+    "PORT",
+    "RCLONE_PROGRAM",
+    "STATIC_FILES",
+];
+
+// Filter applied when `RUST_LOG` is not set, the same as env_logger.
+const DEFAULT_FILTER: &str = "error";
+
+// Settings that are either read every time they are used, or are applied to
+// the running server when the configuration is reloaded.
+const LIVE_SETTINGS: &[&str] = &[
+    "EMAIL_FROM",
+    "EMAIL_NOTIFY",
+    "EMAIL_QUIET_HOURS",
+    "EMAIL_SUMMARY_HOUR",
+    "EMAIL_TEMPLATES",
+    "EMAIL_TO",
+    "EVENT_RETENTION_DAYS",
+    "EXPORT_ALLOW_REMOTE",
+    "MAINTENANCE_SAMPLE",
+    "MAINTENANCE_TASKS",
+    "MAINTENANCE_WINDOW",
+    "REPLICA_STORES",
+    "REPLICA_TOKEN",
+    "REPLICA_URL",
+    "REQUIRE_TOKEN",
+    "RESTORE_PARALLELISM",
+    "RUST_LOG",
+    "SMTP_HOST",
+    "SMTP_PASSWORD",
+    "SMTP_PORT",
+    "SMTP_SECURITY",
+    "SMTP_USERNAME",
+    "STATUS_PAGE",
+    "STATUS_TOKEN",
+    "TRASH_RETENTION_DAYS",
+    "TRUST_LOCAL_HOST",
+    "WEBHOOK_DIGEST_HOUR",
+];
+
+lazy_static! {
+    // Values of the known settings as of startup or the most recent reload.
+    static ref LOADED: RwLock<BTreeMap<&'static str, Option<String>>> =
+        RwLock::new(read_settings(|name| env::var(name).ok()));
+}
+
+// Means of replacing the log filter of the running server.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+///
+/// Outcome of reloading the configuration, naming the settings that changed.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Settings whose new values are now in effect.
+    pub applied: Vec<String>,
+    /// Settings whose new values will not take effect until a restart.
+    pub restart_required: Vec<String>,
+}
+
+///
+/// Load the `.env` file and initialize the logger.
+///
+/// Log lines are written to standard error, either as text or, if
+/// `LOG_FORMAT` is `json`, as one JSON object per line. Records from the `log`
+/// macros are included, along with the fields of any enclosing spans, such as
+/// the dataset being backed up. The `RUST_LOG` filter, including directives
+/// for individual modules, can be replaced by a later reload.
+///
+pub fn init_logging() {
+    dotenv::dotenv().ok();
+    let filter = match parse_filter(env::var("RUST_LOG").ok().as_deref()) {
+        Ok(filter) => filter,
+        Err(err) => {
+            eprintln!("invalid RUST_LOG value: {}", err);
+            EnvFilter::new(DEFAULT_FILTER)
+        }
+    };
+    let max_level = log_max_level(&filter);
+    let (filter, handle) = reload::Layer::new(filter);
+    let json = env::var("LOG_FORMAT")
+        .map(|v| v.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let result = tracing_subscriber::registry()
+        .with(filter)
+        .with(json.then(|| fmt::layer().json().with_writer(io::stderr)))
+        .with((!json).then(|| fmt::layer().with_writer(io::stderr)))
+        .try_init();
+    if result.is_ok() {
+        let _ = FILTER.set(handle);
+        log::set_max_level(max_level);
+    }
+    // capture the settings as they were at startup
+    lazy_static::initialize(&LOADED);
+}
+
+///
+/// Return the value of the named setting. The live settings are returned as of
+/// startup or the most recent reload, while any other name is looked up in the
+/// environment.
+///
+pub fn var(name: &str) -> Result<String, env::VarError> {
+    if LIVE_SETTINGS.contains(&name) {
+        let loaded = LOADED.read().unwrap();
+        loaded
+            .get(name)
+            .cloned()
+            .flatten()
+            .ok_or(env::VarError::NotPresent)
+    } else {
+        env::var(name)
+    }
+}
+
+///
+/// Re-read the `.env` file, whose values take precedence over those in the
+/// environment, then apply the settings that have changed since startup or the
+/// previous reload.
+///
+pub fn reload() -> Result<ReloadReport, Error> {
+    let mut dotenv: HashMap<String, String> = HashMap::new();
+    match dotenv::dotenv_iter() {
+        Ok(iter) => {
+            for item in iter {
+                let (name, value) = item?;
+                dotenv.insert(name, value);
+            }
+        }
+        Err(err) if err.not_found() => (),
+        Err(err) => return Err(Error::from(err)),
+    }
+    let mut loaded = LOADED.write().unwrap();
+    let report = compare_settings(&mut loaded, |name| {
+        dotenv.get(name).cloned().or_else(|| env::var(name).ok())
+    });
+    for name in report.applied.iter() {
+        info!("settings: applied new value for {}", name);
+    }
+    for name in report.restart_required.iter() {
+        warn!("settings: {} has changed, restart required", name);
+    }
+    Ok(report)
+}
+
+// Read the values of all known settings using the given lookup function.
+fn read_settings<F>(lookup: F) -> BTreeMap<&'static str, Option<String>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut settings = BTreeMap::new();
+    for name in RESTART_SETTINGS.iter().chain(LIVE_SETTINGS.iter()) {
+        settings.insert(*name, lookup(name));
+    }
+    settings
+}
+
+// Compare the loaded settings with the current values, applying the changes
+// that can be applied, and recording those as the new loaded values.
+fn compare_settings<F>(
+    loaded: &mut BTreeMap<&'static str, Option<String>>,
+    lookup: F,
+) -> ReloadReport
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut report: ReloadReport = Default::default();
+    for (name, value) in read_settings(lookup) {
+        if loaded.get(name) == Some(&value) {
+            continue;
+        }
+        if RESTART_SETTINGS.contains(&name) {
+            // keep reporting the change until the server is restarted
+            report.restart_required.push(name.to_owned());
+            continue;
+        }
+        if name == "RUST_LOG" {
+            match parse_filter(value.as_deref()) {
+                Ok(filter) => apply_filter(filter),
+                Err(err) => {
+                    // keep the current filter until the value is corrected
+                    warn!("settings: ignoring invalid RUST_LOG: {}", err);
+                    continue;
+                }
+            }
+        }
+        // all other live settings are read each time they are needed
+        report.applied.push(name.to_owned());
+        loaded.insert(name, value);
+    }
+    report
+}
+
+// Interpret the value of RUST_LOG as a log filter, which may be a single
+// level or a list of directives for individual modules.
+fn parse_filter(value: Option<&str>) -> Result<EnvFilter, Error> {
+    let value = value.map(|v| v.trim()).unwrap_or_default();
+    if value.is_empty() {
+        Ok(EnvFilter::new(DEFAULT_FILTER))
+    } else {
+        Ok(EnvFilter::try_new(value)?)
+    }
+}
+
+// Replace the filter of the running logger, if it has been initialized.
+fn apply_filter(filter: EnvFilter) {
+    if let Some(handle) = FILTER.get() {
+        let max_level = log_max_level(&filter);
+        if let Err(err) = handle.reload(filter) {
+            warn!("settings: could not replace log filter: {}", err);
+        } else {
+            log::set_max_level(max_level);
+        }
+    }
+}
+
+// Find the most verbose level that the filter may enable, such that records
+// from the `log` macros that would be discarded are skipped cheaply.
+fn log_max_level(filter: &EnvFilter) -> log::LevelFilter {
+    match filter.max_level_hint() {
+        Some(hint) if hint == LevelFilter::OFF => log::LevelFilter::Off,
+        Some(hint) if hint == LevelFilter::ERROR => log::LevelFilter::Error,
+        Some(hint) if hint == LevelFilter::WARN => log::LevelFilter::Warn,
+        Some(hint) if hint == LevelFilter::INFO => log::LevelFilter::Info,
+        Some(hint) if hint == LevelFilter::DEBUG => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_filter() {
+        let level = |value| log_max_level(&parse_filter(value).unwrap());
+        assert_eq!(level(None), log::LevelFilter::Error);
+        assert_eq!(level(Some("")), log::LevelFilter::Error);
+        assert_eq!(level(Some("debug")), log::LevelFilter::Debug);
+        assert_eq!(level(Some("INFO")), log::LevelFilter::Info);
+        assert_eq!(level(Some("off")), log::LevelFilter::Off);
+        assert_eq!(
+            level(Some("warn,server::domain::managers::backup=trace")),
+            log::LevelFilter::Trace
+        );
+        assert!(parse_filter(Some("server=loud")).is_err());
+    }
+
+    #[test]
+    fn test_compare_settings() {
+        // arrange
+        let mut startup: HashMap<&str, &str> = HashMap::new();
+        startup.insert("PORT", "8080");
+        startup.insert("PASSPHRASE", "keyboard cat");
+        startup.insert("RUST_LOG", "info");
+        let mut loaded = read_settings(|name| startup.get(name).map(|v| v.to_string()));
+        // act
+        let report = compare_settings(&mut loaded, |name| startup.get(name).map(|v| v.to_string()));
+        // assert
+        assert!(report.applied.is_empty());
+        assert!(report.restart_required.is_empty());
+
+        // arrange
+        let mut changed = startup.clone();
+        changed.insert("PORT", "8081");
+        changed.insert("MAINTENANCE_SAMPLE", "5");
+        changed.insert("RUST_LOG", "server=debug");
+        // act
+        let report = compare_settings(&mut loaded, |name| changed.get(name).map(|v| v.to_string()));
+        // assert
+        assert_eq!(report.applied, vec!["MAINTENANCE_SAMPLE", "RUST_LOG"]);
+        assert_eq!(report.restart_required, vec!["PORT"]);
+
+        // arrange
+        changed.insert("RUST_LOG", "server=loud");
+        changed.insert("LOG_FORMAT", "json");
+        changed.insert("PASSPHRASE", "tiger stripes");
+        // act
+        let report = compare_settings(&mut loaded, |name| changed.get(name).map(|v| v.to_string()));
+        // assert
+        assert!(report.applied.is_empty());
+        assert_eq!(
+            report.restart_required,
+            vec!["LOG_FORMAT", "PASSPHRASE", "PORT"]
+        );
+    }
+}
@@ -185,6 +185,10 @@ impl StateStore for StateStoreImpl {
         let mut store = self.store.lock().unwrap();
         let _ = store.dispatch(action.clone());
         drop(store);
+        if action == RestorerAction::Progress {
+            // waiting for the supervisor must not miss its start or stop
+            return;
+        }
         let pair = self.restore_var.clone();
         let (lock, cvar) = &*pair;
         let mut actual = lock.lock().unwrap();
@@ -237,6 +241,9 @@ pub enum BackupAction {
     Start(String),
     /// Signal the backup to be stopped if it is running.
     Stop(String),
+    /// Signal the backup to stop uploading, while finding and chunking the
+    /// remaining changed files such that the next run can upload them.
+    StopUploads(String),
     /// Set the number of changed files when the backup starts.
     BeginUpload(String, u64),
     /// Increment the pack upload count for a dataset.
@@ -253,6 +260,12 @@ pub enum BackupAction {
     Pause(String),
     /// Clear the error state and end time to indicate a restart.
     Restart(String),
+    /// Record a file that could not be read because it remained locked by
+    /// another process (dataset key and file path).
+    FileLocked(String, String),
+    /// Set the stores that were left out of the backup because they reached
+    /// their monthly transfer cap (dataset key and store identifiers).
+    Deferred(String, Vec<String>),
 }
 
 ///
@@ -285,6 +298,9 @@ pub enum RestorerAction {
     Stop,
     /// Indicates that the supervisor has in fact stopped.
     Stopped,
+    /// The progress of the file restores has changed, which is not an action
+    /// that anyone waits for.
+    Progress,
 }
 
 ///
@@ -306,6 +322,13 @@ pub struct BackupState {
     error_msg: Option<String>,
     paused: bool,
     stop_requested: bool,
+    /// Uploads were stopped while the changes are still being planned.
+    uploads_stopped: bool,
+    /// Files that were skipped because they remained locked by another
+    /// process despite repeated attempts to read them.
+    locked_files: Vec<String>,
+    /// Stores left out of this backup due to reaching their transfer cap.
+    deferred_stores: Vec<String>,
 }
 
 impl Default for BackupState {
@@ -320,6 +343,9 @@ impl Default for BackupState {
             error_msg: None,
             paused: false,
             stop_requested: false,
+            uploads_stopped: false,
+            locked_files: vec![],
+            deferred_stores: vec![],
         }
     }
 }
@@ -374,6 +400,22 @@ impl BackupState {
     pub fn should_stop(&self) -> bool {
         self.stop_requested
     }
+
+    /// Return true if the backup should stop uploading.
+    pub fn uploads_stopped(&self) -> bool {
+        self.uploads_stopped
+    }
+
+    /// Return the paths of the files that were skipped due to being locked.
+    pub fn locked_files(&self) -> &[String] {
+        &self.locked_files
+    }
+
+    /// Return the identifiers of the stores that were left out of the backup
+    /// due to reaching their monthly transfer cap.
+    pub fn deferred_stores(&self) -> &[String] {
+        &self.deferred_stores
+    }
 }
 
 ///
@@ -416,6 +458,11 @@ pub struct State {
     pub supervisor: SupervisorState,
     /// Requested state of the restore supervisor process.
     pub restorer: RestorerState,
+    /// Number of times the progress of the file restores has changed.
+    restore_revision: u64,
+    /// Stores left out of the backups of each dataset, which are known before
+    /// the first backup of the dataset has started.
+    deferrals: HashMap<String, Vec<String>>,
     /// Collection of subscribers to the application state.
     subscribers: HashMap<String, Subscription<State>>,
 }
@@ -436,6 +483,8 @@ impl Default for State {
             backups: HashMap::new(),
             supervisor: SupervisorState::Stopped,
             restorer: RestorerState::Stopped,
+            restore_revision: 0,
+            deferrals: HashMap::new(),
             subscribers: HashMap::new(),
         }
     }
@@ -447,6 +496,8 @@ impl Clone for State {
             backups: self.backups.clone(),
             supervisor: self.supervisor.clone(),
             restorer: self.restorer.clone(),
+            restore_revision: self.restore_revision,
+            deferrals: self.deferrals.clone(),
             subscribers: self.subscribers.clone(),
         }
     }
@@ -469,13 +520,26 @@ impl Reducer<BackupAction> for State {
     fn reduce(&mut self, action: BackupAction) {
         match action {
             BackupAction::Start(key) => {
-                self.backups.insert(key, BackupState::default());
+                // the deferred stores are decided before the backup starts
+                let deferred_stores = self.deferrals.get(&key).cloned().unwrap_or_default();
+                self.backups.insert(
+                    key,
+                    BackupState {
+                        deferred_stores,
+                        ..Default::default()
+                    },
+                );
             }
             BackupAction::Stop(key) => {
                 if let Some(record) = self.backups.get_mut(&key) {
                     record.stop_requested = true;
                 }
             }
+            BackupAction::StopUploads(key) => {
+                if let Some(record) = self.backups.get_mut(&key) {
+                    record.uploads_stopped = true;
+                }
+            }
             BackupAction::BeginUpload(key, files) => {
                 if let Some(record) = self.backups.get_mut(&key) {
                     record.changed_files = files;
@@ -519,9 +583,21 @@ impl Reducer<BackupAction> for State {
                     record.error_msg = None;
                     record.paused = false;
                     record.stop_requested = false;
+                    record.uploads_stopped = false;
                     record.end_time = None;
                 }
             }
+            BackupAction::FileLocked(key, path) => {
+                if let Some(record) = self.backups.get_mut(&key) {
+                    record.locked_files.push(path);
+                }
+            }
+            BackupAction::Deferred(key, stores) => {
+                if let Some(record) = self.backups.get_mut(&key) {
+                    record.deferred_stores = stores.clone();
+                }
+                self.deferrals.insert(key, stores);
+            }
         }
     }
 }
@@ -560,6 +636,9 @@ impl Reducer<RestorerAction> for State {
             RestorerAction::Stopped => {
                 self.restorer = RestorerState::Stopped;
             }
+            RestorerAction::Progress => {
+                self.restore_revision += 1;
+            }
         }
     }
 }
@@ -578,6 +657,18 @@ impl State {
             None
         }
     }
+
+    /// Return the identifiers of the stores that are left out of the backups of
+    /// the named dataset due to their monthly transfer cap or quota.
+    pub fn deferred_stores(&self, dataset: &str) -> &[String] {
+        self.deferrals.get(dataset).map_or(&[], |d| d.as_slice())
+    }
+
+    /// Return a number that changes whenever the progress of the file
+    /// restores has changed.
+    pub fn restore_revision(&self) -> u64 {
+        self.restore_revision
+    }
 }
 
 ///
@@ -626,6 +717,77 @@ mod tests {
         assert!(sut.get_state().backups("foobar").is_none());
     }
 
+    #[test]
+    fn test_locked_files_backup() {
+        let key = "dataset1";
+        let sut = StateStoreImpl::new();
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        assert!(sut
+            .get_state()
+            .backups(key)
+            .unwrap()
+            .locked_files()
+            .is_empty());
+        sut.backup_event(BackupAction::FileLocked(
+            key.to_owned(),
+            String::from("C:\\Users\\me\\outlook.pst"),
+        ));
+        let state = sut.get_state();
+        let backup = state.backups(key).unwrap();
+        assert_eq!(backup.locked_files().len(), 1);
+        assert_eq!(backup.locked_files()[0], "C:\\Users\\me\\outlook.pst");
+        // starting a new backup clears the list
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        assert!(sut
+            .get_state()
+            .backups(key)
+            .unwrap()
+            .locked_files()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_deferred_stores_backup() {
+        let key = "dataset1";
+        let sut = StateStoreImpl::new();
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        sut.backup_event(BackupAction::Deferred(
+            key.to_owned(),
+            vec![String::from("metered")],
+        ));
+        // starting the backup retains the deferred stores
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        let state = sut.get_state();
+        let backup = state.backups(key).unwrap();
+        assert_eq!(backup.deferred_stores(), &["metered".to_owned()]);
+        sut.backup_event(BackupAction::Deferred(key.to_owned(), vec![]));
+        assert!(sut
+            .get_state()
+            .backups(key)
+            .unwrap()
+            .deferred_stores()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_deferred_stores_before_backup() {
+        let key = "dataset1";
+        let sut = StateStoreImpl::new();
+        // the deferral is known before any backup has started
+        sut.backup_event(BackupAction::Deferred(
+            key.to_owned(),
+            vec![String::from("metered")],
+        ));
+        let state = sut.get_state();
+        assert!(state.backups(key).is_none());
+        assert_eq!(state.deferred_stores(key), &["metered".to_owned()]);
+        assert!(state.deferred_stores("dataset2").is_empty());
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        let state = sut.get_state();
+        let backup = state.backups(key).unwrap();
+        assert_eq!(backup.deferred_stores(), &["metered".to_owned()]);
+    }
+
     #[test]
     fn test_errored_backup() {
         let key = "dataset1";
@@ -651,6 +813,22 @@ mod tests {
         assert_eq!(backup.is_paused(), true);
     }
 
+    #[test]
+    fn test_stop_uploads_backup() {
+        let key = "dataset5";
+        let sut = StateStoreImpl::new();
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        assert!(!sut.get_state().backups(key).unwrap().uploads_stopped());
+        sut.backup_event(BackupAction::StopUploads(key.to_owned()));
+        let state = sut.get_state();
+        let backup = state.backups(key).unwrap();
+        assert!(backup.uploads_stopped());
+        assert!(!backup.should_stop());
+        // the next backup uploads as usual
+        sut.backup_event(BackupAction::Start(key.to_owned()));
+        assert!(!sut.get_state().backups(key).unwrap().uploads_stopped());
+    }
+
     #[test]
     fn test_finished_backup() {
         let key = "dataset3";
@@ -821,4 +999,18 @@ mod tests {
         let state = sut.get_state();
         assert_eq!(state.restorer, RestorerState::Stopped);
     }
+
+    #[test]
+    fn test_restorer_progress() {
+        let sut = StateStoreImpl::new();
+        sut.restorer_event(RestorerAction::Started);
+        assert_eq!(sut.get_state().restore_revision(), 0);
+        sut.restorer_event(RestorerAction::Progress);
+        sut.restorer_event(RestorerAction::Progress);
+        let state = sut.get_state();
+        assert_eq!(state.restore_revision(), 2);
+        assert_eq!(state.restorer, RestorerState::Started);
+        // progress does not disturb those waiting for the supervisor
+        sut.wait_for_restorer(RestorerAction::Started);
+    }
 }
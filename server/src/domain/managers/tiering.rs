@@ -0,0 +1,302 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `tiering` module moves packs that are no longer referenced by recent
+//! snapshots to a colder storage class, according to the tiering policy of
+//! each store (see `Store::tiering_policy()`).
+
+use crate::domain::entities::{
+    Checksum, Message, MessageCode, Store, TieringResult, TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+lazy_static! {
+    // Results of the most recent application of the policy for each store.
+    static ref RESULTS: Mutex<HashMap<String, TieringResult>> = Mutex::new(HashMap::new());
+    // Held while the policies are being applied to prevent overlapping runs.
+    static ref RUNNING: Mutex<()> = Mutex::new(());
+}
+
+///
+/// Return the results of the most recent application of each tiering policy
+/// since the application started.
+///
+pub fn last_results() -> Vec<TieringResult> {
+    let results = RESULTS.lock().unwrap();
+    let mut values: Vec<TieringResult> = results.values().cloned().collect();
+    values.sort_by(|a, b| a.store_id.cmp(&b.store_id));
+    values
+}
+
+///
+/// Apply the tiering policy of every store that has one.
+///
+pub fn apply_all_policies(repo: &dyn RecordRepository) -> Result<Vec<TieringResult>, Error> {
+    let _guard = RUNNING
+        .try_lock()
+        .map_err(|_| anyhow!("tiering policies are already being applied"))?;
+    let mut results: Vec<TieringResult> = Vec::new();
+    for store in repo.get_stores()? {
+        if store.tiering_policy().is_some() {
+            results.push(apply_policy(repo, &store));
+        }
+    }
+    Ok(results)
+}
+
+///
+/// Apply the tiering policy of the given store, unless the policies are
+/// already being applied.
+///
+pub fn apply_store_policy(
+    repo: &dyn RecordRepository,
+    store: &Store,
+) -> Result<TieringResult, Error> {
+    let _guard = RUNNING
+        .try_lock()
+        .map_err(|_| anyhow!("tiering policies are already being applied"))?;
+    Ok(apply_policy(repo, store))
+}
+
+// Apply the tiering policy of the given store, moving any packs whose most
+// recent referencing snapshot is older than the policy allows. The outcome is
+// retained for later retrieval via `last_results()`. The caller is expected to
+// hold the `RUNNING` lock.
+fn apply_policy(repo: &dyn RecordRepository, store: &Store) -> TieringResult {
+    let mut result = TieringResult::new(&store.id);
+    if let Err(err) = transition_packs(repo, store, &mut result) {
+        error!(
+            "tiering: error applying policy of store {}: {}",
+            store.id, err
+        );
+        result.error = Some(err.to_string());
+    }
+    result.finished = Utc::now();
+    info!(
+        "tiering: store {} transitioned {} packs, {} unchanged, {} retained",
+        store.id, result.transitioned, result.unchanged, result.retained
+    );
+    let mut results = RESULTS.lock().unwrap();
+    results.insert(store.id.clone(), result.clone());
+    result
+}
+
+// Move the eligible packs of the store, updating the counts in the result.
+fn transition_packs(
+    repo: &dyn RecordRepository,
+    store: &Store,
+    result: &mut TieringResult,
+) -> Result<(), Error> {
+    let policy = store
+        .tiering_policy()
+        .ok_or_else(|| anyhow!(format!("store {} has no tiering policy", store.id)))?;
+    let cutoff = Utc::now() - chrono::Duration::days(policy.days as i64);
+    let referenced = find_pack_references(repo, &store.id)?;
+    let stores = repo.build_pack_repo(store)?;
+    for pack in repo.get_packs(&store.id)? {
+        // packs not referenced by any snapshot are left alone, they may belong
+        // to a backup that is still in progress
+        match referenced.get(&pack.digest) {
+            Some(latest) if *latest < cutoff => {
+                if stores.set_storage_class(&store.id, &pack, &policy.storage_class)? {
+                    result.transitioned += 1;
+                } else {
+                    result.unchanged += 1;
+                }
+            }
+            _ => result.retained += 1,
+        }
+    }
+    Ok(())
+}
+
+// Find the start time of the most recent snapshot that references each pack,
+// considering every dataset that uses the given store.
+fn find_pack_references(
+    repo: &dyn RecordRepository,
+    store_id: &str,
+) -> Result<HashMap<Checksum, DateTime<Utc>>, Error> {
+    let mut referenced: HashMap<Checksum, DateTime<Utc>> = HashMap::new();
+    for dataset in repo.get_datasets()? {
+        if !dataset.stores.iter().any(|s| s == store_id) {
+            continue;
+        }
+        // Walk the snapshots from newest to oldest, such that the first
+        // snapshot to reach a tree or file is the most recent one to reference
+        // anything within it, and hence nothing needs to be visited twice.
+        let mut visited: HashSet<Checksum> = HashSet::new();
+        let mut next = repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let snapshot = repo.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            let mut pending_trees: VecDeque<Checksum> = VecDeque::new();
+            pending_trees.push_back(snapshot.tree.clone());
+            while let Some(tree_digest) = pending_trees.pop_front() {
+                if !visited.insert(tree_digest.clone()) {
+                    continue;
+                }
+                let tree = repo.get_tree(&tree_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingTree).with("digest", &tree_digest)
+                })?;
+                for entry in tree.entries.iter() {
+                    match &entry.reference {
+                        TreeReference::TREE(child) => pending_trees.push_back(child.to_owned()),
+                        TreeReference::FILE(file_digest) => {
+                            if visited.insert(file_digest.clone()) {
+                                for pack in file_packs(repo, file_digest)? {
+                                    let latest =
+                                        referenced.entry(pack).or_insert(snapshot.start_time);
+                                    if *latest < snapshot.start_time {
+                                        *latest = snapshot.start_time;
+                                    }
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            next = snapshot.parent;
+        }
+    }
+    Ok(referenced)
+}
+
+// Return the digests of the packs that contain the content of the file.
+fn file_packs(repo: &dyn RecordRepository, digest: &Checksum) -> Result<Vec<Checksum>, Error> {
+    let mut packs: Vec<Checksum> = Vec::new();
+    // the file record will be missing if the backup has not yet finished
+    if let Some(file) = repo.get_file(digest)? {
+        if file.chunks.len() == 1 {
+            // single-chunk files refer to the pack directly
+            packs.push(file.chunks[0].1.clone());
+        } else {
+            for (_, chunk_digest) in file.chunks.iter() {
+                if let Some(chunk) = repo.get_chunk(chunk_digest)? {
+                    if let Some(pack) = chunk.packfile {
+                        packs.push(pack);
+                    }
+                }
+            }
+        }
+    }
+    Ok(packs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{
+        Dataset, File, FileCounts, Pack, PackLocation, Snapshot, StoreType, Tree, TreeEntry,
+    };
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::path::Path;
+
+    #[test]
+    fn test_apply_policy() {
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("tiering_days".to_owned(), "30".to_owned());
+        properties.insert("tiering_class".to_owned(), "GLACIER_IR".to_owned());
+        let store = Store {
+            id: "coldstore".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "cold storage".to_owned(),
+            properties,
+        };
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.add_store("coldstore");
+        // old snapshot references only the old pack, the latest references
+        // only the new pack
+        let old_pack = Checksum::SHA1("0000000000000000000000000000000000000001".into());
+        let new_pack = Checksum::SHA1("0000000000000000000000000000000000000002".into());
+        let old_file = Checksum::BLAKE3("1111".into());
+        let new_file = Checksum::BLAKE3("2222".into());
+        let old_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(old_file.clone()),
+            )],
+            1,
+        );
+        let new_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/washington-journal.txt"),
+                TreeReference::FILE(new_file.clone()),
+            )],
+            1,
+        );
+        let mut old_snapshot = Snapshot::new(None, old_tree.digest.clone(), FileCounts::default());
+        old_snapshot.set_start_time(Utc::now() - chrono::Duration::days(60));
+        let old_snapshot = old_snapshot.with_parent(None);
+        let mut new_snapshot = Snapshot::new(
+            Some(old_snapshot.digest.clone()),
+            new_tree.digest.clone(),
+            FileCounts::default(),
+        );
+        new_snapshot.set_start_time(Utc::now() - chrono::Duration::days(1));
+        let new_snapshot = new_snapshot.with_parent(Some(old_snapshot.digest.clone()));
+        let latest = new_snapshot.digest.clone();
+        let snapshots: HashMap<Checksum, Snapshot> = vec![old_snapshot, new_snapshot]
+            .into_iter()
+            .map(|s| (s.digest.clone(), s))
+            .collect();
+        let trees: HashMap<Checksum, Tree> = vec![old_tree, new_tree]
+            .into_iter()
+            .map(|t| (t.digest.clone(), t))
+            .collect();
+        let files: HashMap<Checksum, File> = vec![
+            File::new(old_file, 3129, vec![(0, old_pack.clone())]),
+            File::new(new_file, 3129, vec![(0, new_pack.clone())]),
+        ]
+        .into_iter()
+        .map(|f| (f.digest.clone(), f))
+        .collect();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.get(digest).cloned()));
+        let old_clone = old_pack.clone();
+        mock.expect_build_pack_repo().returning(move |_| {
+            let expected = old_clone.clone();
+            let mut stores = MockPackRepository::new();
+            stores
+                .expect_set_storage_class()
+                .withf(move |_, pack, class| pack.digest == expected && class == "GLACIER_IR")
+                .times(1)
+                .returning(|_, _, _| Ok(true));
+            Ok(Box::new(stores))
+        });
+        mock.expect_get_packs().returning(move |_| {
+            let coords = vec![PackLocation::new("coldstore", "bucket1", "object1")];
+            Ok(vec![
+                Pack::new(old_pack.clone(), coords.clone()),
+                Pack::new(new_pack.clone(), coords),
+            ])
+        });
+        // act
+        let result = apply_policy(&mock, &store);
+        // assert
+        assert!(result.error.is_none());
+        assert_eq!(result.transitioned, 1);
+        assert_eq!(result.unchanged, 0);
+        assert_eq!(result.retained, 1);
+        let results = last_results();
+        assert!(results.iter().any(|r| r.store_id == "coldstore"));
+    }
+}
@@ -0,0 +1,106 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `trash` module permanently removes datasets that were deleted long
+//! enough ago that they can no longer be restored via `undeleteDataset`.
+
+use crate::domain::entities::TrashedDataset;
+use crate::domain::managers::settings;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use log::{error, info};
+
+// Number of days to retain deleted datasets if `TRASH_RETENTION_DAYS` is not set.
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+///
+/// Return the length of time for which deleted datasets are retained, as
+/// given by the `TRASH_RETENTION_DAYS` setting.
+///
+pub fn retention() -> TimeDelta {
+    let days = settings::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v >= 0)
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    TimeDelta::days(days)
+}
+
+///
+/// Return the date/time after which the trashed dataset will be purged.
+///
+pub fn purge_after(trashed: &TrashedDataset) -> DateTime<Utc> {
+    trashed.deleted + retention()
+}
+
+///
+/// Permanently remove the dataset and the records that refer to it, whether
+/// the dataset is in the trash or not.
+///
+pub fn purge_dataset(repo: &dyn RecordRepository, dataset_id: &str) -> Result<(), Error> {
+    repo.delete_dataset(dataset_id)?;
+    repo.delete_trashed_dataset(dataset_id)?;
+    // ignore any errors when deleting records that may or may not be
+    // present in the data source
+    let _ = repo.delete_computer_id(dataset_id);
+    let _ = repo.delete_latest_snapshot(dataset_id);
+    info!("purged dataset {}", dataset_id);
+    Ok(())
+}
+
+///
+/// Purge those datasets that have been in the trash longer than the retention
+/// period, returning their identifiers.
+///
+pub fn purge_expired(repo: &dyn RecordRepository) -> Result<Vec<String>, Error> {
+    let now = Utc::now();
+    let mut purged: Vec<String> = Vec::new();
+    for trashed in repo.get_trashed_datasets()? {
+        if purge_after(&trashed) <= now {
+            let id = &trashed.dataset.id;
+            match purge_dataset(repo, id) {
+                Ok(()) => purged.push(id.to_owned()),
+                Err(err) => error!("failed to purge dataset {}: {}", id, err),
+            }
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::Dataset;
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_purge_expired() {
+        // arrange
+        let mut old = TrashedDataset::new(Dataset::new(Path::new("/home/planet")));
+        old.deleted = Utc::now() - TimeDelta::days(DEFAULT_RETENTION_DAYS + 1);
+        let old_id = old.dataset.id.clone();
+        let new = TrashedDataset::new(Dataset::new(Path::new("/home/earth")));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_trashed_datasets()
+            .returning(move || Ok(vec![old.clone(), new.clone()]));
+        let expected = old_id.clone();
+        mock.expect_delete_dataset()
+            .withf(move |id| id == expected)
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_trashed_dataset()
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_computer_id().returning(|_| Ok(()));
+        mock.expect_delete_latest_snapshot().returning(|_| Ok(()));
+        // act
+        let result = purge_expired(&mock);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![old_id]);
+    }
+}
@@ -2,13 +2,17 @@
 // Copyright (c) 2020 Nathan Fiedler
 //
 use crate::domain::entities::{
-    Checksum, Chunk, Configuration, Dataset, File, Pack, PackLocation, RecordCounts, Snapshot,
-    Store, Tree,
+    BandwidthUsage, Catalog, ChainEntry, Checksum, Chunk, Configuration, Dataset, DedupStats,
+    Device, EmergencyIndex, Event, File, MaintenanceResult, Migration, Pack, PackLocation,
+    RecordCounts, Snapshot, Store, StoreTestStep, StoreUsage, TrashedDataset, Tree, Verification,
+    Webhook,
 };
 use anyhow::Error;
 #[cfg(test)]
 use mockall::{automock, predicate::*};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use store_core::Checkpoint;
 
 ///
 /// Repository for entity records.
@@ -18,6 +22,19 @@ pub trait RecordRepository: Send + Sync {
     /// Retrieve the configuration, or build a new one using default values.
     fn get_configuration(&self) -> Result<Configuration, Error>;
 
+    /// Retrieve the version of the schema of the database records, or `None`
+    /// if the database predates the versioning of the schema.
+    fn get_schema_version(&self) -> Result<Option<u32>, Error>;
+
+    /// Save the version of the schema of the database records.
+    fn put_schema_version(&self, version: u32) -> Result<(), Error>;
+
+    /// Save the record of a migration that was applied to the database.
+    fn put_migration(&self, migration: &Migration) -> Result<(), Error>;
+
+    /// Retrieve the migrations applied to the database, oldest first.
+    fn get_migrations(&self) -> Result<Vec<Migration>, Error>;
+
     /// Provide the set of paths that should be excluded from backup, if any.
     fn get_excludes(&self) -> Vec<PathBuf>;
 
@@ -46,6 +63,19 @@ pub trait RecordRepository: Send + Sync {
     /// Retrieve the chunk by the given digest, returning `None` if not found.
     fn get_chunk(&self, digest: &Checksum) -> Result<Option<Chunk>, Error>;
 
+    /// Save the given chunk to the repository, overwriting any existing entry.
+    fn put_chunk(&self, chunk: &Chunk) -> Result<(), Error>;
+
+    /// Remove the chunk record with the given digest.
+    fn delete_chunk(&self, digest: &Checksum) -> Result<(), Error>;
+
+    /// Pass each chunk record to the visitor, one at a time, until the visitor
+    /// returns `false`. The visitor must not access the repository.
+    fn visit_chunks(
+        &self,
+        visitor: &mut dyn FnMut(Chunk) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given pack into the repository, if one with the same digest
     /// does not already exist. Packs with the same digest are assumed to be
     /// identical.
@@ -57,12 +87,23 @@ pub trait RecordRepository: Send + Sync {
     /// Retrieve the pack by the given digest, returning `None` if not found.
     fn get_pack(&self, digest: &Checksum) -> Result<Option<Pack>, Error>;
 
+    /// Remove the pack record with the given digest.
+    fn delete_pack(&self, digest: &Checksum) -> Result<(), Error>;
+
     /// Retrieve all pack records that should be in the given store.
     fn get_packs(&self, store_id: &str) -> Result<Vec<Pack>, Error>;
 
     /// Retrieve all pack records in the system regardless of store.
     fn get_all_packs(&self) -> Result<Vec<Pack>, Error>;
 
+    /// Pass each pack record in the system to the visitor, one at a time,
+    /// until the visitor returns `false`, without holding all of the records
+    /// in memory. The visitor must not make use of the repository.
+    fn visit_packs(
+        &self,
+        visitor: &mut dyn FnMut(Pack) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given psedo-pack for the database snapshot, if one with the
     /// same digest does not already exist. Packs with the same digest are
     /// assumed to be identical.
@@ -92,6 +133,19 @@ pub trait RecordRepository: Send + Sync {
     /// Retrieve the file by the given digest, returning `None` if not found.
     fn get_file(&self, digest: &Checksum) -> Result<Option<File>, Error>;
 
+    /// Save the given file to the repository, overwriting any existing entry.
+    fn put_file(&self, file: &File) -> Result<(), Error>;
+
+    /// Remove the file record with the given digest.
+    fn delete_file(&self, digest: &Checksum) -> Result<(), Error>;
+
+    /// Pass each file record to the visitor, one at a time, until the visitor
+    /// returns `false`. The visitor must not access the repository.
+    fn visit_files(
+        &self,
+        visitor: &mut dyn FnMut(File) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
     /// Insert the given tree into the repository, if one with the same digest
     /// does not already exist. Trees with the same digest are assumed to be
     /// identical.
@@ -112,6 +166,27 @@ pub trait RecordRepository: Send + Sync {
     /// Remove the store by the given identifier.
     fn delete_store(&self, id: &str) -> Result<(), Error>;
 
+    /// Retrieve the number of bytes transferred to and from the store during
+    /// the current calendar month.
+    fn get_bandwidth_usage(&self, store_id: &str) -> Result<BandwidthUsage, Error>;
+
+    /// Retrieve the number and total size of the objects that have been added
+    /// to the store, less those that have been removed.
+    fn get_store_usage(&self, store_id: &str) -> Result<StoreUsage, Error>;
+
+    /// Save the checkpoint of a partially completed upload to the location.
+    fn put_checkpoint(&self, location: &PackLocation, checkpoint: &Checkpoint)
+        -> Result<(), Error>;
+
+    /// Retrieve the checkpoint of an upload to the location, if any.
+    fn get_checkpoint(&self, location: &PackLocation) -> Result<Option<Checkpoint>, Error>;
+
+    /// Remove the checkpoint of an upload to the location.
+    fn delete_checkpoint(&self, location: &PackLocation) -> Result<(), Error>;
+
+    /// Retrieve all of the upload checkpoints along with their locations.
+    fn get_checkpoints(&self) -> Result<Vec<(PackLocation, Checkpoint)>, Error>;
+
     /// Construct a pack repository for the given dataset.
     ///
     /// If the dataset does not have any valid stores defined, an error is
@@ -133,20 +208,134 @@ pub trait RecordRepository: Send + Sync {
     /// Remove the dataset by the given identifier.
     fn delete_dataset(&self, id: &str) -> Result<(), Error>;
 
+    /// Save the deleted dataset to the trash.
+    fn put_trashed_dataset(&self, trashed: &TrashedDataset) -> Result<(), Error>;
+
+    /// Retrieve all of the datasets in the trash.
+    fn get_trashed_datasets(&self) -> Result<Vec<TrashedDataset>, Error>;
+
+    /// Remove the dataset with the given identifier from the trash.
+    fn delete_trashed_dataset(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given paired device to the repository.
+    fn put_device(&self, device: &Device) -> Result<(), Error>;
+
+    /// Retrieve all of the paired devices.
+    fn get_devices(&self) -> Result<Vec<Device>, Error>;
+
+    /// Remove the paired device with the given identifier.
+    fn delete_device(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given webhook, replacing any with the same identifier.
+    fn put_webhook(&self, webhook: &Webhook) -> Result<(), Error>;
+
+    /// Retrieve all of the defined webhooks.
+    fn get_webhooks(&self) -> Result<Vec<Webhook>, Error>;
+
+    /// Remove the webhook with the given identifier.
+    fn delete_webhook(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the given event to the event log.
+    fn put_event(&self, event: &Event) -> Result<(), Error>;
+
+    /// Retrieve all of the events in the event log, oldest first.
+    fn get_events(&self) -> Result<Vec<Event>, Error>;
+
+    /// Remove the event with the given identifier from the event log.
+    fn delete_event(&self, id: &str) -> Result<(), Error>;
+
+    /// Save the outcome of verifying a snapshot of a dataset.
+    fn put_verification(&self, verification: &Verification) -> Result<(), Error>;
+
+    /// Retrieve the verifications of the snapshots of the dataset, oldest first.
+    fn get_verifications(&self, dataset: &str) -> Result<Vec<Verification>, Error>;
+
+    /// Remove the verification with the given identifier.
+    fn delete_verification(&self, dataset: &str, id: &str) -> Result<(), Error>;
+
+    /// Save the outcome of running a scheduled task for a dataset, replacing
+    /// the previous outcome of the same task.
+    fn put_task_run(&self, run: &MaintenanceResult) -> Result<(), Error>;
+
+    /// Retrieve the most recent run of each scheduled task of the dataset,
+    /// ordered by task name.
+    fn get_task_runs(&self, dataset: &str) -> Result<Vec<MaintenanceResult>, Error>;
+
+    /// Save the given entry of the snapshot log of a store.
+    fn put_chain_entry(&self, entry: &ChainEntry) -> Result<(), Error>;
+
+    /// Retrieve the entries of the snapshot log of the given store, in order
+    /// of their sequence.
+    fn get_chain_entries(&self, store_id: &str) -> Result<Vec<ChainEntry>, Error>;
+
+    /// Save the location of the snapshot log within its store, replacing any
+    /// previous location for that store.
+    fn put_chain_location(&self, location: &PackLocation) -> Result<(), Error>;
+
+    /// Retrieve the locations of the snapshot logs of every store.
+    fn get_chain_locations(&self) -> Result<Vec<PackLocation>, Error>;
+
+    /// Save the catalog of the most recent chunked database backup to the
+    /// given store.
+    fn put_catalog(&self, store_id: &str, catalog: &Catalog) -> Result<(), Error>;
+
+    /// Retrieve the catalog of the most recent chunked database backup to the
+    /// given store. Each store has a catalog of its own, as the datasets may
+    /// save their packs to different stores.
+    fn get_catalog(&self, store_id: &str) -> Result<Option<Catalog>, Error>;
+
+    /// Save the deduplication statistics of a dataset.
+    fn put_dedup_stats(&self, stats: &DedupStats) -> Result<(), Error>;
+
+    /// Retrieve the deduplication statistics of the dataset, if any.
+    fn get_dedup_stats(&self, dataset: &str) -> Result<Option<DedupStats>, Error>;
+
     /// Save the given snapshot to the repository.
     fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Error>;
 
     /// Retrieve a snapshot by its digest, returning `None` if not found.
     fn get_snapshot(&self, digest: &Checksum) -> Result<Option<Snapshot>, Error>;
 
+    /// Remove the snapshot with the given digest.
+    fn delete_snapshot(&self, digest: &Checksum) -> Result<(), Error>;
+
     /// Create a backup of the database, returning the path of the archive file.
     fn create_backup(&self, password: &str) -> Result<tempfile::TempPath, Error>;
 
     /// Restore the database from the provided archive file.
     fn restore_from_backup(&self, path: &Path, password: &str) -> Result<(), Error>;
 
+    /// Create a backup of the database, returning the path of the directory
+    /// that holds the backup files, which are neither compressed nor
+    /// encrypted. Files that have not changed since the previous backup are
+    /// left as they were.
+    fn create_backup_dir(&self) -> Result<PathBuf, Error>;
+
+    /// Restore the database from the backup files in the given directory.
+    fn restore_from_dir(&self, path: &Path) -> Result<(), Error>;
+
+    /// Write the catalog to an encrypted archive, returning its path.
+    fn pack_catalog(&self, catalog: &Catalog, password: &str) -> Result<tempfile::TempPath, Error>;
+
+    /// Read the catalog from the encrypted archive produced by `pack_catalog()`.
+    fn unpack_catalog(&self, path: &Path, password: &str) -> Result<Catalog, Error>;
+
+    /// Write the emergency index to an encrypted archive, returning its path.
+    fn pack_emergency(
+        &self,
+        index: &EmergencyIndex,
+        password: &str,
+    ) -> Result<tempfile::TempPath, Error>;
+
+    /// Read the emergency index from the encrypted archive produced by
+    /// `pack_emergency()`.
+    fn unpack_emergency(&self, path: &Path, password: &str) -> Result<EmergencyIndex, Error>;
+
     /// Retrieve the counts of the various record types in the data source.
     fn get_entity_counts(&self) -> Result<RecordCounts, Error>;
+
+    /// Reclaim the space occupied by deleted and overwritten records.
+    fn compact_database(&self) -> Result<(), Error>;
 }
 
 ///
@@ -176,8 +365,44 @@ pub trait PackRepository: Send + Sync {
     /// Retrieve the pack from one of the stores provided in the constructor.
     ///
     /// The most suitable store will be utilized, preferring a local store over
-    /// a remote one, and fast one over a slow one.
-    fn retrieve_pack(&self, locations: &[PackLocation], outfile: &Path) -> Result<(), Error>;
+    /// a remote one, and fast one over a slow one. The retrieved file must
+    /// match the given digest, otherwise the next store is tried. If no store
+    /// provides a matching file, the error is an `IntegrityError`.
+    fn retrieve_pack(
+        &self,
+        locations: &[PackLocation],
+        digest: &Checksum,
+        outfile: &Path,
+    ) -> Result<(), Error>;
+
+    /// Retrieve the pack in the same manner as `retrieve_pack()`, but without
+    /// computing its digest, for when the caller has chosen speed over safety.
+    fn retrieve_pack_unverified(
+        &self,
+        locations: &[PackLocation],
+        outfile: &Path,
+    ) -> Result<(), Error>;
+
+    /// Retrieve the object from the given location exactly as it was stored,
+    /// without verifying its content, which may or may not be a pack file.
+    fn retrieve_object(&self, location: &PackLocation, outfile: &Path) -> Result<(), Error>;
+
+    /// Compare the MD5 digest that each store keeps for the pack against the
+    /// one recorded when the pack was uploaded, without retrieving the pack.
+    ///
+    /// Returns `Some(true)` if any store reports a matching digest,
+    /// `Some(false)` if every store that reported a digest disagrees, and
+    /// `None` if none of the stores were able to report a digest.
+    fn check_pack(&self, locations: &[PackLocation], md5: &str) -> Result<Option<bool>, Error>;
+
+    /// Return the size in bytes of the object at the given location, without
+    /// retrieving the object, or `None` if the store cannot report the size.
+    fn object_size(&self, location: &PackLocation) -> Result<Option<u64>, Error>;
+
+    /// Discard the partial upload to the location recorded in the checkpoint,
+    /// such that the store no longer retains (and charges for) the parts that
+    /// were sent before the upload was interrupted.
+    fn abort_upload(&self, location: &PackLocation, checkpoint: &Checkpoint) -> Result<(), Error>;
 
     /// Test the connection to the store with the given identifier.
     ///
@@ -185,6 +410,14 @@ pub trait PackRepository: Send + Sync {
     /// raised by the data source are returned as-is.
     fn test_store(&self, store: &str) -> Result<(), Error>;
 
+    /// Exercise the store with the given identifier by writing, reading, and
+    /// deleting a small probe pack in a new bucket.
+    ///
+    /// Returns the outcome of each step, stopping at the first failure other
+    /// than the clean up steps, which are attempted whenever the probe was
+    /// successfully uploaded.
+    fn test_store_deep(&self, store: &str) -> Result<Vec<StoreTestStep>, Error>;
+
     /// Store the compressed database snapshot in the pack stores.
     ///
     /// This archive should be stored in such a manner that it can be retrieved
@@ -199,6 +432,76 @@ pub trait PackRepository: Send + Sync {
     /// most suitable pack store in order to retrieve the database.
     fn retrieve_latest_database(&self, computer_id: &str, outfile: &Path) -> Result<(), Error>;
 
+    /// Save the log of snapshots uploaded to the store with the given
+    /// identifier, replacing the log previously saved there.
+    ///
+    /// The log is stored in a bucket named for the computer, apart from the
+    /// packs and database snapshots. Returns the location of the log.
+    fn store_chain(
+        &self,
+        store_id: &str,
+        computer_id: &str,
+        infile: &Path,
+    ) -> Result<PackLocation, Error>;
+
+    /// Return the name of the bucket that holds the packs and manifests of
+    /// the chunked database backups for the given computer.
+    fn get_catalog_bucket(&self, computer_id: &str) -> String;
+
+    /// Store the encrypted catalog manifest in the catalog bucket of each pack
+    /// store. The pack locations are returned to support accurate pruning.
+    fn store_catalog(&self, computer_id: &str, infile: &Path) -> Result<Vec<PackLocation>, Error>;
+
+    /// Store a pack of database chunks in the catalog bucket of each pack
+    /// store. Like the manifests, and unlike the packs of the datasets, these
+    /// are never given a lifecycle rule, as restoring the database must not
+    /// wait for them to be thawed.
+    fn store_catalog_pack(
+        &self,
+        computer_id: &str,
+        infile: &Path,
+        object: &str,
+    ) -> Result<Vec<PackLocation>, Error>;
+
+    /// Retrieve the most recent catalog manifest for the given computer,
+    /// returning `false` if there is none.
+    ///
+    /// As with `retrieve_latest_database()`, uses a random pack store.
+    fn retrieve_latest_catalog(&self, computer_id: &str, outfile: &Path) -> Result<bool, Error>;
+
+    /// Store the encrypted emergency index of the given dataset in the rescue
+    /// bucket of each pack store, which like the catalog bucket is never given
+    /// a lifecycle rule. The pack locations are returned to support accurate
+    /// pruning.
+    fn store_emergency(
+        &self,
+        computer_id: &str,
+        dataset_id: &str,
+        infile: &Path,
+    ) -> Result<Vec<PackLocation>, Error>;
+
+    /// Retrieve the most recent emergency index of the given dataset,
+    /// returning `false` if there is none.
+    ///
+    /// As with `retrieve_latest_database()`, uses a random pack store.
+    fn retrieve_latest_emergency(
+        &self,
+        computer_id: &str,
+        dataset_id: &str,
+        outfile: &Path,
+    ) -> Result<bool, Error>;
+
+    /// List the names of the emergency indices of every dataset of the given
+    /// computer, which begin with `emergency-` and the dataset identifier, and
+    /// end with a ULID, such that they sort by dataset and then by time.
+    ///
+    /// As with `retrieve_latest_database()`, uses a random pack store.
+    fn list_emergency(&self, computer_id: &str) -> Result<Vec<String>, Error>;
+
+    /// List the location of every object in every bucket of the given pack
+    /// store, using the cached listings if available.
+    fn list_locations(&self, store_id: &str) -> Result<Vec<PackLocation>, Error>;
+
     /// Find any packs that are missing from the given pack store.
     ///
     /// Returns a new list of the pack digests for those packs that were not
@@ -209,4 +512,61 @@ pub trait PackRepository: Send + Sync {
     ///
     /// Returns the number of objects removed by this operation.
     fn prune_extra(&self, store_id: &str, packs: &[Pack]) -> Result<u32, Error>;
+
+    /// Remove the objects of the pack from each of its locations that reside
+    /// in the stores of this repository.
+    ///
+    /// Returns the number of objects removed by this operation.
+    fn delete_pack(&self, pack: &Pack) -> Result<u32, Error>;
+
+    /// Discard any cached bucket and object listings for the given store.
+    ///
+    /// Listings are cached for a short time (per the `listing_ttl` store
+    /// property) so that consecutive maintenance operations do not need to
+    /// list the entire store each time.
+    fn invalidate_listings(&self, store_id: &str);
+
+    /// Move the objects of the pack that reside in the given store to the
+    /// named storage class.
+    ///
+    /// Returns `true` if any object was moved, or `false` if all of them were
+    /// already in that storage class.
+    fn set_storage_class(&self, store_id: &str, pack: &Pack, class: &str) -> Result<bool, Error>;
+
+    /// Apply the pack lifecycle rule defined by the properties of the given
+    /// store to the named pack buckets, and clear any rule from the database
+    /// bucket for the computer, whose archives are expired by the server. The
+    /// catalog bucket and any buckets not named are left alone.
+    ///
+    /// Returns the number of buckets that were configured.
+    fn configure_lifecycle(
+        &self,
+        store_id: &str,
+        computer_id: &str,
+        pack_buckets: &[String],
+    ) -> Result<u32, Error>;
+}
+
+///
+/// Raised when a pack file retrieved from a store does not match the digest in
+/// the pack record, meaning the stored object is damaged or incomplete.
+///
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub struct IntegrityError {
+    /// Identifier of the store that provided the file.
+    pub store: String,
+    /// Digest recorded in the database.
+    pub expected: Checksum,
+    /// Digest of the retrieved file.
+    pub actual: Checksum,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pack digest does not match: {} != {} (store {})",
+            self.actual, self.expected, self.store
+        )
+    }
 }
@@ -0,0 +1,154 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Dataset, Message, MessageCode};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use std::cmp;
+use std::fmt;
+
+///
+/// Archive a dataset such that it is no longer backed up, while its snapshots
+/// remain available for browsing and restoring, or return an archived dataset
+/// to service.
+///
+pub struct ArchiveDataset {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl ArchiveDataset {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Dataset, Params> for ArchiveDataset {
+    fn call(&self, params: Params) -> Result<Dataset, Error> {
+        let mut dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        if params.archive {
+            // keep the original time if already archived
+            dataset.archived = dataset.archived.or_else(|| Some(Utc::now()));
+        } else {
+            dataset.archived = None;
+        }
+        self.repo.put_dataset(&dataset)?;
+        Ok(dataset)
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the dataset.
+    dataset_id: String,
+    /// True to archive the dataset, false to return it to service.
+    archive: bool,
+}
+
+impl Params {
+    pub fn new(dataset_id: String, archive: bool) -> Self {
+        Self {
+            dataset_id,
+            archive,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.dataset_id, self.archive)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id && self.archive == other.archive
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_archive_dataset_ok() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".to_owned();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_put_dataset()
+            .withf(|dataset| dataset.archived.is_some())
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = ArchiveDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let dataset = result.unwrap();
+        assert!(dataset.archived.is_some());
+    }
+
+    #[test]
+    fn test_archive_dataset_twice() {
+        // arrange
+        let archived = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.archived = Some(archived);
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_put_dataset().returning(|_| Ok(()));
+        // act
+        let usecase = ArchiveDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().archived, Some(archived));
+    }
+
+    #[test]
+    fn test_unarchive_dataset() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.archived = Some(Utc::now());
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_put_dataset()
+            .withf(|dataset| dataset.archived.is_none())
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = ArchiveDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().archived.is_none());
+    }
+
+    #[test]
+    fn test_archive_dataset_missing() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        mock.expect_put_dataset().never();
+        // act
+        let usecase = ArchiveDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use super::file_history::split_path;
+use crate::domain::entities::{
+    Checksum, DirectoryListing, ListingEntry, Message, MessageCode, TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use std::cmp;
+use std::fmt;
+use std::path::PathBuf;
+
+///
+/// Resolve a path within a snapshot and list the entries of that directory,
+/// such that a client can present the snapshot as a file explorer without
+/// walking the trees itself.
+///
+/// Returns `None` if the dataset has no snapshots, or if the path does not
+/// name a directory within the snapshot.
+///
+pub struct BrowseSnapshot {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl BrowseSnapshot {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Option<DirectoryListing>, Params> for BrowseSnapshot {
+    fn call(&self, params: Params) -> Result<Option<DirectoryListing>, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        let path = split_path(&params.path, &dataset.basepath)?;
+        let digest = match params.snapshot {
+            Some(digest) => digest,
+            None => match self.repo.get_latest_snapshot(&dataset.id)? {
+                Some(digest) => digest,
+                None => return Ok(None),
+            },
+        };
+        let snapshot = self
+            .repo
+            .get_snapshot(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &digest))?;
+        let mut tree_digest = snapshot.tree.clone();
+        for name in path.iter() {
+            let tree = self.repo.get_tree(&tree_digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingTree).with("digest", &tree_digest)
+            })?;
+            let subtree = tree.entries.iter().find_map(|e| match &e.reference {
+                TreeReference::TREE(digest) if &e.name == name => Some(digest.clone()),
+                _ => None,
+            });
+            match subtree {
+                Some(digest) => tree_digest = digest,
+                None => return Ok(None),
+            }
+        }
+        let tree = self
+            .repo
+            .get_tree(&tree_digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &tree_digest))?;
+        let mut entries: Vec<ListingEntry> = Vec::with_capacity(tree.entries.len());
+        for entry in tree.entries.iter() {
+            let mut listed = ListingEntry::from(entry);
+            if let TreeReference::FILE(digest) = &entry.reference {
+                listed.size = self.repo.get_file(digest)?.map(|f| f.length);
+            }
+            entries.push(listed);
+        }
+        Ok(Some(DirectoryListing {
+            snapshot: snapshot.digest,
+            tree: tree_digest,
+            path: path.iter().collect(),
+            entries,
+        }))
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset.
+    dataset_id: String,
+    /// Digest of the snapshot, or `None` for the latest snapshot.
+    snapshot: Option<Checksum>,
+    /// Path of the directory, either absolute or relative to the dataset
+    /// base path; an empty path is the base path itself.
+    path: PathBuf,
+}
+
+impl Params {
+    pub fn new<P: Into<PathBuf>>(dataset_id: String, snapshot: Option<Checksum>, path: P) -> Self {
+        Self {
+            dataset_id,
+            snapshot,
+            path: path.into(),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.dataset_id, self.path.display())
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+            && self.snapshot == other.snapshot
+            && self.path == other.path
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Dataset, File, FileCounts, Snapshot, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // Build a mock repository for a dataset whose only snapshot contains the
+    // `docs` directory, which holds `notes.txt` and `tiny.txt`.
+    fn make_repo() -> (MockRecordRepository, Snapshot) {
+        let content = Checksum::BLAKE3("1111".into());
+        let notes = TreeEntry::new(Path::new("notes.txt"), TreeReference::FILE(content));
+        let tiny = TreeEntry::new(
+            Path::new("tiny.txt"),
+            TreeReference::SMALL(b"hello".to_vec()),
+        );
+        let subtree = Tree::new(vec![notes, tiny], 2);
+        let docs = TreeEntry::new(
+            Path::new("docs"),
+            TreeReference::TREE(subtree.digest.clone()),
+        );
+        let root = Tree::new(vec![docs], 2);
+        let snapshot = Snapshot::new(None, root.digest.clone(), FileCounts::default());
+        let mut trees: HashMap<Checksum, Tree> = HashMap::new();
+        trees.insert(subtree.digest.clone(), subtree);
+        trees.insert(root.digest.clone(), root);
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".into();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        let latest = snapshot.digest.clone();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        let snapshot_clone = snapshot.clone();
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot_clone.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        mock.expect_get_file()
+            .returning(|digest| Ok(Some(File::new(digest.clone(), 3129, vec![]))));
+        (mock, snapshot)
+    }
+
+    #[test]
+    fn test_browse_snapshot_root() {
+        // arrange
+        let (mock, snapshot) = make_repo();
+        // act
+        let usecase = BrowseSnapshot::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), None, "");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let listing = result.unwrap().unwrap();
+        assert_eq!(listing.snapshot, snapshot.digest);
+        assert_eq!(listing.tree, snapshot.tree);
+        assert_eq!(listing.path, PathBuf::new());
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].name, "docs");
+        assert!(listing.entries[0].reference.is_tree());
+        assert!(listing.entries[0].size.is_none());
+    }
+
+    #[test]
+    fn test_browse_snapshot_subdir() {
+        // arrange
+        let (mock, snapshot) = make_repo();
+        // act
+        let usecase = BrowseSnapshot::new(Box::new(mock));
+        let params = Params::new(
+            "cafebabe".into(),
+            Some(snapshot.digest.clone()),
+            "/home/planet/docs",
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let listing = result.unwrap().unwrap();
+        assert_ne!(listing.tree, snapshot.tree);
+        assert_eq!(listing.path, PathBuf::from("docs"));
+        assert_eq!(listing.entries.len(), 2);
+        assert_eq!(listing.entries[0].name, "notes.txt");
+        assert_eq!(listing.entries[0].size, Some(3129));
+        assert_eq!(listing.entries[1].name, "tiny.txt");
+        assert_eq!(listing.entries[1].size, Some(5));
+    }
+
+    #[test]
+    fn test_browse_snapshot_not_directory() {
+        // arrange
+        let (mock, _) = make_repo();
+        let usecase = BrowseSnapshot::new(Box::new(mock));
+        for path in ["docs/notes.txt", "nonesuch"] {
+            // act
+            let params = Params::new("cafebabe".into(), None, path);
+            let result = usecase.call(params);
+            // assert
+            assert!(result.is_ok());
+            assert!(result.unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_browse_snapshot_outside_basepath() {
+        // arrange
+        let (mock, _) = make_repo();
+        // act
+        let usecase = BrowseSnapshot::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), None, "/home/moon/docs");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+}
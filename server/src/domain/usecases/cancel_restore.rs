@@ -8,6 +8,7 @@ use std::cmp;
 use std::fmt;
 use std::path::PathBuf;
 use std::sync::Arc;
+use store_core::Secret;
 
 pub struct CancelRestore {
     restorer: Arc<dyn Restorer>,
@@ -61,7 +62,7 @@ impl From<Params> for Request {
             val.entry,
             val.filepath,
             val.dataset,
-            String::new(),
+            Secret::from(String::new()),
         )
     }
 }
@@ -0,0 +1,672 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Chunk, CompactionReport, Dataset, Message, MessageCode, Pack, TreeReference,
+};
+use crate::domain::helpers::pack::{self, PackBuilder};
+use crate::domain::managers::state::StateStore;
+use crate::domain::managers::{emergency, maintenance};
+use crate::domain::repositories::{PackRepository, RecordRepository};
+use anyhow::{anyhow, Error};
+use log::{debug, error, info};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use store_core::Secret;
+
+///
+/// Rewrite the packs of a dataset whose live data has fallen below the given
+/// fraction of their content, such as after pruning snapshots, moving the live
+/// chunks into new packs and removing the old packs from the stores.
+///
+/// A chunk is live if any snapshot of any dataset refers to it, since chunks
+/// are shared across datasets. The records of the dead chunks and files are
+/// removed along with the old packs, otherwise a later backup would find them
+/// and assume the content was still available. The emergency index of each
+/// dataset sharing those stores is then rebuilt, as it names the old packs.
+///
+pub struct CompactPacks {
+    repo: Box<dyn RecordRepository>,
+    state: Arc<dyn StateStore>,
+}
+
+impl CompactPacks {
+    pub fn new(repo: Box<dyn RecordRepository>, state: Arc<dyn StateStore>) -> Self {
+        Self { repo, state }
+    }
+
+    // Ensure that no backup is running or waiting to resume, as its chunks
+    // would not yet be referenced by a completed snapshot.
+    fn check_backups(&self, datasets: &[Dataset]) -> Result<(), Error> {
+        let redux = self.state.get_state();
+        for dataset in datasets.iter() {
+            if let Some(backup) = redux.backups(&dataset.id) {
+                if backup.end_time().is_none() {
+                    return Err(anyhow!("cannot compact packs while backup is running"));
+                }
+            }
+            if let Some(latest) = self.repo.get_latest_snapshot(&dataset.id)? {
+                let snapshot = self.repo.get_snapshot(&latest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingSnapshot).with("digest", &latest)
+                })?;
+                if snapshot.end_time.is_none() {
+                    return Err(anyhow!("cannot compact packs while backup is incomplete"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Upload a new emergency index for the latest snapshot of the dataset,
+    // replacing the one that names the packs that were just removed.
+    fn refresh_emergency(&self, dataset: &Dataset, passphrase: &Secret) -> Result<(), Error> {
+        if let Some(latest) = self.repo.get_latest_snapshot(&dataset.id)? {
+            let computer_id = self
+                .repo
+                .get_computer_id(&dataset.id)?
+                .ok_or_else(|| anyhow!(format!("missing computer id for {}", dataset.id)))?;
+            let stores = self.repo.load_dataset_stores(dataset)?;
+            emergency::save(
+                self.repo.as_ref(),
+                stores.as_ref(),
+                &computer_id,
+                dataset,
+                &latest,
+                passphrase.expose(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl super::UseCase<CompactionReport, Params> for CompactPacks {
+    fn call(&self, params: Params) -> Result<CompactionReport, Error> {
+        if params.threshold <= 0.0 || params.threshold > 1.0 {
+            return Err(anyhow!("threshold must be greater than 0 and at most 1"));
+        }
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        // held until the old packs are removed, keeping backups from starting
+        let _guard = maintenance::lock_packs()
+            .ok_or_else(|| anyhow!("cannot compact packs while backup is running"))?;
+        let mut datasets = self.repo.get_datasets()?;
+        let active = datasets.len();
+        for trashed in self.repo.get_trashed_datasets()? {
+            datasets.push(trashed.dataset);
+        }
+        self.check_backups(&datasets)?;
+        let live = find_live_entries(self.repo.as_ref(), &datasets)?;
+        let recorded = find_recorded_entries(self.repo.as_ref(), &live)?;
+        let mut report = CompactionReport {
+            dataset_id: dataset.id.clone(),
+            ..Default::default()
+        };
+        // consider only those packs that reside entirely within the stores of
+        // this dataset, as the replacements are uploaded to those stores
+        let mut candidates: Vec<Pack> = Vec::new();
+        for pack in self.repo.get_all_packs()? {
+            if pack.locations.is_empty()
+                || !pack
+                    .locations
+                    .iter()
+                    .all(|l| dataset.stores.contains(&l.store))
+            {
+                continue;
+            }
+            report.packs_examined += 1;
+            let entries = recorded.entries.get(&pack.digest);
+            let total: u64 = entries.map_or(0, |e| e.iter().map(|e| e.length).sum());
+            if total == 0 {
+                // without any records of its content, there is no telling
+                // what the pack holds, so leave it be
+                debug!("pack {} has no recorded entries", pack.digest);
+                continue;
+            }
+            let alive: u64 = entries.map_or(0, |e| {
+                e.iter()
+                    .filter(|e| live.is_live(&pack.digest, &e.digest))
+                    .map(|e| e.length)
+                    .sum()
+            });
+            let ratio = alive as f64 / total as f64;
+            if ratio < params.threshold {
+                debug!("pack {} has {} of {} bytes live", pack.digest, alive, total);
+                report.bytes_reclaimed += total - alive;
+                candidates.push(pack);
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(report);
+        }
+        let stores = self.repo.load_dataset_stores(&dataset)?;
+        let computer_id = self
+            .repo
+            .get_computer_id(&dataset.id)?
+            .ok_or_else(|| anyhow!(format!("missing computer id for {}", dataset.id)))?;
+        fs::create_dir_all(&dataset.workspace)?;
+        let workspace = tempfile::tempdir_in(&dataset.workspace)?;
+        let mut compactor = Compactor {
+            repo: self.repo.as_ref(),
+            stores: stores.as_ref(),
+            dataset: &dataset,
+            bucket: stores.get_bucket_name(&computer_id),
+            workspace: workspace.path().to_path_buf(),
+            builder: PackBuilder::new(dataset.pack_size)
+                .compression(dataset.compression())
+                .password(params.passphrase.expose()),
+            passphrase: &params.passphrase,
+            packed: Vec::new(),
+            pending: Vec::new(),
+            report: &mut report,
+            recorded: &recorded,
+        };
+        for pack in candidates.into_iter() {
+            compactor.compact(pack, &live)?;
+        }
+        compactor.flush()?;
+        // the packs are shared with any dataset that uploads to the same
+        // stores, so each of their emergency indices may name the old packs
+        for other in datasets[..active]
+            .iter()
+            .filter(|d| d.stores.iter().any(|s| dataset.stores.contains(s)))
+        {
+            if let Err(err) = self.refresh_emergency(other, &params.passphrase) {
+                error!("could not refresh emergency index of {}: {}", other.id, err);
+            }
+        }
+        info!(
+            "CompactPacks rewrote {} and removed {} packs of dataset {}",
+            report.packs_rewritten, report.packs_removed, report.dataset_id
+        );
+        Ok(report)
+    }
+}
+
+// Entry within a pack, either a chunk or an entire file, as recorded in the
+// database.
+struct PackEntry {
+    digest: Checksum,
+    length: u64,
+    is_file: bool,
+}
+
+// Names of the entries within each pack that are referenced by a snapshot.
+struct LiveEntries {
+    packs: HashMap<Checksum, HashSet<Checksum>>,
+    files: HashSet<Checksum>,
+}
+
+impl LiveEntries {
+    fn is_live(&self, pack: &Checksum, entry: &Checksum) -> bool {
+        self.packs.get(pack).map_or(false, |e| e.contains(entry))
+    }
+}
+
+// Walk every snapshot of the given datasets to find the pack entries that
+// hold the content of the files they contain.
+fn find_live_entries(
+    repo: &dyn RecordRepository,
+    datasets: &[Dataset],
+) -> Result<LiveEntries, Error> {
+    let mut live = LiveEntries {
+        packs: HashMap::new(),
+        files: HashSet::new(),
+    };
+    let mut seen_trees: HashSet<Checksum> = HashSet::new();
+    for dataset in datasets.iter() {
+        let mut next = repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let snapshot = repo.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            walk_tree(repo, &snapshot.tree, &mut seen_trees, &mut live)?;
+            next = snapshot.parent;
+        }
+    }
+    Ok(live)
+}
+
+fn walk_tree(
+    repo: &dyn RecordRepository,
+    digest: &Checksum,
+    seen_trees: &mut HashSet<Checksum>,
+    live: &mut LiveEntries,
+) -> Result<(), Error> {
+    if !seen_trees.insert(digest.to_owned()) {
+        return Ok(());
+    }
+    let tree = repo
+        .get_tree(digest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+    for entry in tree.entries.iter() {
+        match &entry.reference {
+            TreeReference::TREE(subtree) => walk_tree(repo, subtree, seen_trees, live)?,
+            TreeReference::FILE(file_digest) => {
+                if !live.files.insert(file_digest.to_owned()) {
+                    continue;
+                }
+                let file = repo.get_file(file_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingFile).with("digest", &file_digest)
+                })?;
+                if file.chunks.len() == 1 {
+                    // the "chunk" of a single-chunk file is the pack digest
+                    let pack = file.chunks[0].1.clone();
+                    live.packs.entry(pack).or_default().insert(file.digest);
+                    continue;
+                }
+                for (_, chunk_digest) in file.chunks.iter() {
+                    let pack = find_chunk_pack(repo, chunk_digest)?;
+                    live.packs
+                        .entry(pack)
+                        .or_default()
+                        .insert(chunk_digest.to_owned());
+                }
+            }
+            TreeReference::LINK(_) | TreeReference::SMALL(_) => (),
+        }
+    }
+    Ok(())
+}
+
+// Find the pack holding the chunk, which is named by the file record rather
+// than a chunk record when the chunk was also an entire file in the same pack.
+fn find_chunk_pack(repo: &dyn RecordRepository, digest: &Checksum) -> Result<Checksum, Error> {
+    if let Some(pack) = repo.get_chunk(digest)?.and_then(|c| c.packfile) {
+        return Ok(pack);
+    }
+    if let Some(file) = repo.get_file(digest)? {
+        if file.chunks.len() == 1 {
+            return Ok(file.chunks[0].1.clone());
+        }
+    }
+    Err(Message::new(MessageCode::MissingChunk)
+        .with("digest", digest)
+        .into())
+}
+
+// Entries recorded for each pack, and the dead files that are made up of more
+// than one chunk, by the digests of those chunks.
+struct RecordedEntries {
+    entries: HashMap<Checksum, Vec<PackEntry>>,
+    dead_files: HashMap<Checksum, Vec<Checksum>>,
+}
+
+fn find_recorded_entries(
+    repo: &dyn RecordRepository,
+    live: &LiveEntries,
+) -> Result<RecordedEntries, Error> {
+    let mut recorded = RecordedEntries {
+        entries: HashMap::new(),
+        dead_files: HashMap::new(),
+    };
+    repo.visit_chunks(&mut |chunk| {
+        if let Some(pack) = chunk.packfile {
+            recorded.entries.entry(pack).or_default().push(PackEntry {
+                digest: chunk.digest,
+                length: chunk.length as u64,
+                is_file: false,
+            });
+        }
+        Ok(true)
+    })?;
+    repo.visit_files(&mut |file| {
+        if file.chunks.len() == 1 {
+            let pack = file.chunks[0].1.clone();
+            recorded.entries.entry(pack).or_default().push(PackEntry {
+                digest: file.digest,
+                length: file.length,
+                is_file: true,
+            });
+        } else if !live.files.contains(&file.digest) {
+            for (_, chunk_digest) in file.chunks.iter() {
+                recorded
+                    .dead_files
+                    .entry(chunk_digest.to_owned())
+                    .or_default()
+                    .push(file.digest.clone());
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(recorded)
+}
+
+// Moves the live entries of the old packs into new packs, uploading each new
+// pack when it is full, after which the old packs whose entries have all been
+// uploaded are removed.
+struct Compactor<'a> {
+    repo: &'a dyn RecordRepository,
+    stores: &'a dyn PackRepository,
+    dataset: &'a Dataset,
+    bucket: String,
+    workspace: PathBuf,
+    builder: PackBuilder,
+    passphrase: &'a Secret,
+    // Entries added to the pack being built.
+    packed: Vec<&'a PackEntry>,
+    // Old packs whose live entries have all been added to the builder, along
+    // with the directory holding their extracted content.
+    pending: Vec<(Pack, PathBuf)>,
+    report: &'a mut CompactionReport,
+    recorded: &'a RecordedEntries,
+}
+
+impl<'a> Compactor<'a> {
+    // Add the live entries of the pack to the pack being built, retrieving the
+    // old pack from the stores, or simply remove the pack if nothing is live.
+    fn compact(&mut self, pack: Pack, live: &LiveEntries) -> Result<(), Error> {
+        let recorded: &'a RecordedEntries = self.recorded;
+        let entries: Vec<&'a PackEntry> = recorded
+            .entries
+            .get(&pack.digest)
+            .map_or(vec![], |e| e.iter().collect());
+        let survivors: Vec<&'a PackEntry> = entries
+            .iter()
+            .filter(|e| live.is_live(&pack.digest, &e.digest))
+            .copied()
+            .collect();
+        if survivors.is_empty() {
+            self.retire(&pack)?;
+            self.report.packs_removed += 1;
+            return Ok(());
+        }
+        let outdir = self.workspace.join(pack.digest.to_string());
+        let archive = self.workspace.join(format!("{}.pack", pack.digest));
+        self.stores
+            .retrieve_pack(&pack.locations, &pack.digest, &archive)?;
+        let names = pack::extract_pack(&archive, &outdir, Some(self.passphrase.expose()))?;
+        pack::decode_chunks(&outdir, &names, pack.compression)?;
+        fs::remove_file(archive)?;
+        for entry in survivors.into_iter() {
+            if !self.builder.is_ready() {
+                let (_outfile, outpath) = tempfile::Builder::new()
+                    .suffix(".pack")
+                    .tempfile_in(&self.workspace)?
+                    .keep()?;
+                self.builder.initialize(&outpath)?;
+            }
+            let filepath = outdir.join(entry.digest.to_string());
+            let chunk =
+                Chunk::new(entry.digest.clone(), 0, entry.length as usize).filepath(&filepath);
+            self.packed.push(entry);
+            if self.builder.add_chunk(&chunk)? {
+                self.upload()?;
+            }
+        }
+        self.pending.push((pack, outdir));
+        self.report.packs_rewritten += 1;
+        Ok(())
+    }
+
+    // Upload the pack being built, if any, and remove the old packs.
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.builder.is_ready() {
+            self.upload()?;
+        }
+        Ok(())
+    }
+
+    // Upload the pack being built, point the records of its entries to the
+    // new pack, then remove the old packs whose entries are all accounted for.
+    fn upload(&mut self) -> Result<(), Error> {
+        let pack_path = self.builder.finalize()?;
+        let digest = Checksum::blake3_from_file(&pack_path)?;
+        let object = format!("{}", digest);
+        let locations = self.stores.store_pack(&pack_path, &self.bucket, &object)?;
+        let md5 = store_core::md5sum_file(&pack_path)?;
+        let pack = Pack::new(digest.clone(), locations)
+            .md5(md5)
+            .compression(self.dataset.compression());
+        self.repo.insert_pack(&pack)?;
+        fs::remove_file(pack_path)?;
+        for entry in self.packed.drain(..) {
+            if entry.is_file {
+                if let Some(mut file) = self.repo.get_file(&entry.digest)? {
+                    file.chunks = vec![(0, digest.clone())];
+                    self.repo.put_file(&file)?;
+                }
+            } else if let Some(mut chunk) = self.repo.get_chunk(&entry.digest)? {
+                chunk.packfile = Some(digest.clone());
+                self.repo.put_chunk(&chunk)?;
+            }
+        }
+        self.report.packs_created += 1;
+        for (old, outdir) in std::mem::take(&mut self.pending) {
+            self.retire(&old)?;
+            fs::remove_dir_all(outdir)?;
+        }
+        Ok(())
+    }
+
+    // Remove the records of the dead entries of the pack, then the pack itself
+    // from the stores and the database.
+    fn retire(&mut self, pack: &Pack) -> Result<(), Error> {
+        if let Some(entries) = self.recorded.entries.get(&pack.digest) {
+            for entry in entries.iter() {
+                // the records of live entries now point to the new pack
+                let still_here = if entry.is_file {
+                    self.repo.get_file(&entry.digest)?.map_or(false, |f| {
+                        f.chunks.len() == 1 && f.chunks[0].1 == pack.digest
+                    })
+                } else {
+                    self.repo
+                        .get_chunk(&entry.digest)?
+                        .map_or(false, |c| c.packfile.as_ref() == Some(&pack.digest))
+                };
+                if !still_here {
+                    continue;
+                }
+                if entry.is_file {
+                    self.repo.delete_file(&entry.digest)?;
+                } else {
+                    self.repo.delete_chunk(&entry.digest)?;
+                }
+                // files made up of the discarded entry are gone as well
+                if let Some(files) = self.recorded.dead_files.get(&entry.digest) {
+                    for file in files.iter() {
+                        self.repo.delete_file(file)?;
+                    }
+                }
+            }
+        }
+        self.stores.delete_pack(pack)?;
+        self.repo.delete_pack(&pack.digest)?;
+        debug!("removed pack {}", pack.digest);
+        Ok(())
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset whose packs are to be compacted.
+    dataset_id: String,
+    /// Packs whose fraction of live data is below this value are rewritten.
+    threshold: f64,
+    /// Pass phrase for decrypting and encrypting the pack files.
+    passphrase: Secret,
+}
+
+impl Params {
+    pub fn new<S: Into<Secret>>(dataset_id: String, threshold: f64, passphrase: S) -> Self {
+        Self {
+            dataset_id,
+            threshold,
+            passphrase: passphrase.into(),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.dataset_id, self.threshold)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id && self.threshold == other.threshold
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::data::repositories::RecordRepositoryImpl;
+    use crate::data::sources::EntityDataSourceImpl;
+    use crate::domain::entities::{
+        Configuration, File, FileCounts, Snapshot, Store, StoreType, Tree, TreeEntry,
+    };
+    use crate::domain::managers::state::StateStoreImpl;
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::prelude::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_compact_packs_ok() -> Result<(), Error> {
+        // arrange
+        let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+        fs::create_dir_all(&db_base)?;
+        let db_path = tempfile::tempdir_in(&db_base)?;
+        let datasource = Arc::new(EntityDataSourceImpl::new(&db_path)?);
+        let repo = RecordRepositoryImpl::new(datasource.clone());
+        let pack_base: PathBuf = ["tmp", "test", "packs"].iter().collect();
+        fs::create_dir_all(&pack_base)?;
+        let pack_path = tempfile::tempdir_in(&pack_base)?;
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert(
+            "basepath".to_owned(),
+            pack_path.path().to_string_lossy().into(),
+        );
+        let store = Store {
+            id: "local123".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "my local".to_owned(),
+            properties,
+        };
+        repo.put_store(&store)?;
+        let mut dataset = Dataset::new(Path::new("../test/fixtures"));
+        dataset.add_store("local123");
+        let workspace = tempfile::tempdir()?;
+        dataset.workspace = workspace.path().to_path_buf();
+        repo.put_dataset(&dataset)?;
+        let computer_id = Configuration::generate_unique_id("mr.ed", "stable");
+        repo.put_computer_id(&dataset.id, &computer_id)?;
+        let stores = repo.load_dataset_stores(&dataset)?;
+
+        // build a pack of three whole files, only one of which is live
+        let fixtures = [
+            "lorem-ipsum.txt",
+            "washington-journal.txt",
+            "SekienAkashita.jpg",
+        ];
+        let mut builder = PackBuilder::new(1048576).password("secret123");
+        let outfile = workspace.path().join("old.pack");
+        builder.initialize(&outfile)?;
+        let mut files: Vec<File> = Vec::new();
+        for name in fixtures.iter() {
+            let path = Path::new("../test/fixtures").join(name);
+            let digest = Checksum::blake3_from_file(&path)?;
+            let length = fs::metadata(&path)?.len();
+            let chunk = Chunk::new(digest.clone(), 0, length as usize).filepath(&path);
+            builder.add_chunk(&chunk)?;
+            files.push(File::new(digest, length, vec![]));
+        }
+        let outfile = builder.finalize()?;
+        let old_digest = Checksum::blake3_from_file(&outfile)?;
+        let bucket = stores.get_bucket_name(&computer_id);
+        let locations = stores.store_pack(&outfile, &bucket, &old_digest.to_string())?;
+        repo.insert_pack(&Pack::new(old_digest.clone(), locations))?;
+        for file in files.iter_mut() {
+            file.chunks = vec![(0, old_digest.clone())];
+            repo.insert_file(file)?;
+        }
+        let tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(files[0].digest.clone()),
+            )],
+            1,
+        );
+        repo.insert_tree(&tree)?;
+        let mut snapshot = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        snapshot.set_end_time(Utc::now());
+        repo.put_snapshot(&snapshot)?;
+        repo.put_latest_snapshot(&dataset.id, &snapshot.digest)?;
+
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = CompactPacks::new(Box::new(repo), state);
+        let params = Params::new(dataset.id.clone(), 0.5, "secret123");
+        let report = usecase.call(params)?;
+
+        // assert
+        assert_eq!(report.packs_examined, 1);
+        assert_eq!(report.packs_rewritten, 1);
+        assert_eq!(report.packs_removed, 0);
+        assert_eq!(report.packs_created, 1);
+        assert_eq!(report.bytes_reclaimed, 109466 + 3375);
+        let repo = RecordRepositoryImpl::new(datasource);
+        assert!(repo.get_pack(&old_digest)?.is_none());
+        assert!(repo.get_file(&files[1].digest)?.is_none());
+        assert!(repo.get_file(&files[2].digest)?.is_none());
+        let file = repo.get_file(&files[0].digest)?.unwrap();
+        let new_pack = repo.get_pack(&file.chunks[0].1)?.unwrap();
+        assert_ne!(new_pack.digest, old_digest);
+        let archive = workspace.path().join("new.pack");
+        stores.retrieve_pack(&new_pack.locations, &new_pack.digest, &archive)?;
+        let outdir = workspace.path().join("extracted");
+        let names = pack::extract_pack(&archive, &outdir, Some("secret123"))?;
+        assert_eq!(names, vec![files[0].digest.to_string()]);
+        let restored = Checksum::blake3_from_file(&outdir.join(&names[0]))?;
+        assert_eq!(restored, files[0].digest);
+        // the emergency index was replaced to name the new pack
+        let index = repo.get_databases()?;
+        assert_eq!(index.len(), 1);
+        let computer_id = repo.get_computer_id(&dataset.id)?.unwrap();
+        let archive = workspace.path().join("emergency.pack");
+        assert!(stores.retrieve_latest_emergency(&computer_id, &dataset.id, &archive)?);
+        let index = repo.unpack_emergency(&archive, "secret123")?;
+        assert_eq!(index.packs.len(), 1);
+        assert_eq!(index.packs[0].digest, new_pack.digest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_packs_bad_threshold() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().never();
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        // act
+        let usecase = CompactPacks::new(Box::new(mock), state);
+        let params = Params::new("cafebabe".to_owned(), 1.5, "secret123");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("threshold must be"));
+    }
+
+    #[test]
+    fn test_compact_packs_no_dataset() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        // act
+        let usecase = CompactPacks::new(Box::new(mock), state);
+        let params = Params::new("nosuchdataset".to_owned(), 0.5, "secret123");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such dataset"));
+    }
+}
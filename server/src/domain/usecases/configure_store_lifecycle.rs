@@ -0,0 +1,230 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Message, MessageCode, Store};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use log::info;
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+use store_core::LifecycleRule;
+
+///
+/// Save the lifecycle rules in the store properties and apply the pack rule to
+/// the buckets that hold the packs of this computer. Buckets created later are
+/// given the rule by the store itself. The database archives are expired by the
+/// server after each upload, always keeping the newest.
+///
+pub struct ConfigureStoreLifecycle {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl ConfigureStoreLifecycle {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Store, Params> for ConfigureStoreLifecycle {
+    fn call(&self, params: Params) -> Result<Store, Error> {
+        let mut store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        set_or_remove(&mut store, "lifecycle_days", params.transition_days);
+        set_or_remove(&mut store, "lifecycle_class", params.transition_class);
+        set_or_remove(&mut store, "lifecycle_database_days", params.database_days);
+        // reject invalid combinations before saving anything
+        LifecycleRule::for_packs(&store.properties)?;
+        LifecycleRule::for_databases(&store.properties)?;
+        self.repo.put_store(&store)?;
+        let config = self.repo.get_configuration()?;
+        // only the buckets holding packs recorded by this computer are changed
+        let mut buckets: HashSet<String> = HashSet::new();
+        self.repo.visit_packs(&mut |pack| {
+            for location in pack.locations.into_iter() {
+                if location.store == store.id {
+                    buckets.insert(location.bucket);
+                }
+            }
+            Ok(true)
+        })?;
+        let buckets: Vec<String> = buckets.into_iter().collect();
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        let count = pack_repo.configure_lifecycle(&store.id, &config.computer_id, &buckets)?;
+        info!(
+            "ConfigureStoreLifecycle updated {} buckets in store {}",
+            count, store.id
+        );
+        Ok(store)
+    }
+}
+
+// Set the store property to the given value, or remove it if there is none.
+fn set_or_remove<T: ToString>(store: &mut Store, name: &str, value: Option<T>) {
+    match value {
+        Some(value) => {
+            store.properties.insert(name.to_owned(), value.to_string());
+        }
+        None => {
+            store.properties.remove(name);
+        }
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// Days after which pack objects move to the transition storage class.
+    transition_days: Option<u32>,
+    /// Storage class to which pack objects are moved.
+    transition_class: Option<String>,
+    /// Days after which database snapshots are deleted.
+    database_days: Option<u32>,
+}
+
+impl Params {
+    pub fn new(
+        store_id: String,
+        transition_days: Option<u32>,
+        transition_class: Option<String>,
+        database_days: Option<u32>,
+    ) -> Self {
+        Self {
+            store_id,
+            transition_days,
+            transition_class,
+            database_days,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.store_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+            && self.transition_days == other.transition_days
+            && self.transition_class == other.transition_class
+            && self.database_days == other.database_days
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Checksum, Configuration, Pack, PackLocation, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use mockall::predicate::*;
+    use std::collections::HashMap;
+
+    fn make_store() -> Store {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".to_owned());
+        properties.insert("lifecycle_database_days".to_owned(), "90".to_owned());
+        Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::AMAZON,
+            label: "s3store".to_owned(),
+            properties,
+        }
+    }
+
+    #[test]
+    fn test_configure_lifecycle_ok() {
+        // arrange
+        let store = make_store();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .with(eq("cafebabe"))
+            .returning(move |_| Ok(Some(store.clone())));
+        mock.expect_put_store()
+            .withf(|store| {
+                store.properties.get("lifecycle_days") == Some(&"30".to_owned())
+                    && store.properties.get("lifecycle_class") == Some(&"GLACIER".to_owned())
+                    && !store.properties.contains_key("lifecycle_database_days")
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        let config = Configuration {
+            computer_id: "uUeOJWUbR4KTTa9nKyxmQr".to_owned(),
+            ..Default::default()
+        };
+        mock.expect_get_configuration()
+            .returning(move || Ok(config.clone()));
+        mock.expect_visit_packs().returning(|visitor| {
+            let digest = Checksum::SHA1("bc1a3198db79036e56b30f0ab307cee55e845907".to_owned());
+            let coords = vec![
+                PackLocation::new("cafebabe", "bucket1", "object1"),
+                PackLocation::new("deadbeef", "bucket2", "object2"),
+            ];
+            visitor(Pack::new(digest, coords))?;
+            Ok(())
+        });
+        mock.expect_build_pack_repo().returning(move |_| {
+            let mut mock_store = MockPackRepository::new();
+            mock_store
+                .expect_configure_lifecycle()
+                .withf(|store_id, computer_id, buckets| {
+                    store_id == "cafebabe"
+                        && computer_id == "uUeOJWUbR4KTTa9nKyxmQr"
+                        && buckets == ["bucket1".to_owned()]
+                })
+                .returning(|_, _, _| Ok(3));
+            Ok(Box::new(mock_store))
+        });
+        // act
+        let usecase = ConfigureStoreLifecycle::new(Box::new(mock));
+        let params = Params::new(
+            "cafebabe".to_owned(),
+            Some(30),
+            Some("GLACIER".to_owned()),
+            None,
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let store = result.unwrap();
+        assert_eq!(store.properties.get("region").unwrap(), "us-west-2");
+        assert_eq!(store.properties.get("lifecycle_days").unwrap(), "30");
+    }
+
+    #[test]
+    fn test_configure_lifecycle_missing_class() {
+        // arrange
+        let store = make_store();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(store.clone())));
+        mock.expect_put_store().never();
+        // act
+        let usecase = ConfigureStoreLifecycle::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), Some(30), None, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_configure_lifecycle_no_store() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        // act
+        let usecase = ConfigureStoreLifecycle::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), None, None, Some(30));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such store: cafebabe"));
+    }
+}
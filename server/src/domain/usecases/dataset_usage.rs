@@ -0,0 +1,378 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, DatasetUsage, File, Message, MessageCode, SnapshotGrowth, StoreBytes, TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Number of snapshots for which growth is reported if not specified.
+const DEFAULT_GROWTH_SNAPSHOTS: usize = 10;
+
+///
+/// Report the storage consumed by a dataset.
+///
+/// Pack records do not track their size, so the stored bytes are the combined
+/// size of the distinct chunks referenced by the snapshots of the dataset,
+/// before compression. Content that is shared with other datasets is counted
+/// for each of them. Small files held within the tree records are included in
+/// the logical size, but not in the stored bytes.
+///
+pub struct GetDatasetUsage {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl GetDatasetUsage {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<DatasetUsage, Params> for GetDatasetUsage {
+    fn call(&self, params: Params) -> Result<DatasetUsage, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        // collect the snapshots from newest to oldest, then visit them from
+        // oldest to newest such that the growth of each can be determined
+        let mut snapshots = Vec::new();
+        let mut next = self.repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let snapshot = self.repo.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            next = snapshot.parent.clone();
+            snapshots.push(snapshot);
+        }
+        let mut walker = UsageWalker::new(self.repo.as_ref());
+        let mut growth: Vec<SnapshotGrowth> = Vec::new();
+        for snapshot in snapshots.iter().rev() {
+            let before = walker.stored_bytes;
+            let logical_size = walker.tree_size(&snapshot.tree)?;
+            growth.push(SnapshotGrowth {
+                digest: snapshot.digest.clone(),
+                start_time: snapshot.start_time,
+                logical_size,
+                added_bytes: walker.stored_bytes - before,
+            });
+        }
+        growth.reverse();
+        let logical_size = growth.first().map_or(0, |g| g.logical_size);
+        growth.truncate(params.snapshots);
+        let mut stores: Vec<StoreBytes> = walker
+            .store_bytes
+            .into_iter()
+            .map(|(store_id, bytes)| StoreBytes { store_id, bytes })
+            .collect();
+        stores.sort_by(|a, b| a.store_id.cmp(&b.store_id));
+        Ok(DatasetUsage {
+            dataset_id: dataset.id,
+            logical_size,
+            stored_bytes: walker.stored_bytes,
+            growth,
+            stores,
+        })
+    }
+}
+
+// Walks the trees of the snapshots, visiting each tree, file, and chunk only
+// once no matter how many snapshots refer to it.
+struct UsageWalker<'a> {
+    repo: &'a dyn RecordRepository,
+    // Size of the trees visited so far.
+    tree_sizes: HashMap<Checksum, u64>,
+    // Files whose content has been counted.
+    files: HashSet<Checksum>,
+    // Chunks whose size has been counted.
+    chunks: HashSet<Checksum>,
+    // Identifiers of the stores holding each pack.
+    pack_stores: HashMap<Checksum, Vec<String>>,
+    // Combined size of the distinct chunks.
+    stored_bytes: u64,
+    // Combined size of the chunks in each store.
+    store_bytes: HashMap<String, u64>,
+}
+
+impl<'a> UsageWalker<'a> {
+    fn new(repo: &'a dyn RecordRepository) -> Self {
+        Self {
+            repo,
+            tree_sizes: HashMap::new(),
+            files: HashSet::new(),
+            chunks: HashSet::new(),
+            pack_stores: HashMap::new(),
+            stored_bytes: 0,
+            store_bytes: HashMap::new(),
+        }
+    }
+
+    // Return the combined size of the files within the tree, counting the
+    // content of any files not seen before.
+    fn tree_size(&mut self, digest: &Checksum) -> Result<u64, Error> {
+        if let Some(size) = self.tree_sizes.get(digest) {
+            return Ok(*size);
+        }
+        let tree = self
+            .repo
+            .get_tree(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        let mut size: u64 = 0;
+        for entry in tree.entries.iter() {
+            match &entry.reference {
+                TreeReference::TREE(subtree) => size += self.tree_size(subtree)?,
+                TreeReference::FILE(file_digest) => {
+                    let file = self.repo.get_file(file_digest)?.ok_or_else(|| {
+                        Message::new(MessageCode::MissingFile).with("digest", &file_digest)
+                    })?;
+                    size += file.length;
+                    if self.files.insert(file_digest.to_owned()) {
+                        self.count_file(&file)?;
+                    }
+                }
+                TreeReference::SMALL(contents) => size += contents.len() as u64,
+                TreeReference::LINK(_) => (),
+            }
+        }
+        self.tree_sizes.insert(digest.to_owned(), size);
+        Ok(size)
+    }
+
+    // Count the chunks of the file that have not been seen before.
+    fn count_file(&mut self, file: &File) -> Result<(), Error> {
+        if file.chunks.len() == 1 {
+            // a single chunk is the digest of the pack itself
+            let pack_digest = file.chunks[0].1.clone();
+            self.count_chunk(&file.digest, file.length, &pack_digest)?;
+        } else {
+            for (_, chunk_digest) in file.chunks.iter() {
+                if self.chunks.contains(chunk_digest) {
+                    continue;
+                }
+                let chunk = self.repo.get_chunk(chunk_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingChunk).with("digest", &chunk_digest)
+                })?;
+                if let Some(pack_digest) = chunk.packfile.as_ref() {
+                    self.count_chunk(chunk_digest, chunk.length as u64, pack_digest)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Add the size of the chunk to the total and to each store holding it.
+    fn count_chunk(
+        &mut self,
+        digest: &Checksum,
+        length: u64,
+        pack: &Checksum,
+    ) -> Result<(), Error> {
+        if !self.chunks.insert(digest.to_owned()) {
+            return Ok(());
+        }
+        self.stored_bytes += length;
+        if !self.pack_stores.contains_key(pack) {
+            let mut stores: Vec<String> = match self.repo.get_pack(pack)? {
+                Some(record) => record.locations.into_iter().map(|l| l.store).collect(),
+                None => vec![],
+            };
+            stores.sort();
+            stores.dedup();
+            self.pack_stores.insert(pack.to_owned(), stores);
+        }
+        for store in self.pack_stores[pack].iter() {
+            *self.store_bytes.entry(store.to_owned()).or_default() += length;
+        }
+        Ok(())
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset for which to report usage.
+    dataset_id: String,
+    /// Number of recent snapshots for which to report growth.
+    snapshots: usize,
+}
+
+impl Params {
+    pub fn new(dataset_id: String, snapshots: Option<usize>) -> Self {
+        Self {
+            dataset_id,
+            snapshots: snapshots.unwrap_or(DEFAULT_GROWTH_SNAPSHOTS),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.dataset_id, self.snapshots)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id && self.snapshots == other.snapshots
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{
+        Chunk, Dataset, FileCounts, Pack, PackLocation, Snapshot, Tree, TreeEntry,
+    };
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_dataset_usage_ok() {
+        // arrange: the first snapshot has one file, the second snapshot adds
+        // a file of two chunks, one of which is shared with the first file
+        let file1 = File::new(
+            Checksum::BLAKE3("file1".into()),
+            1000,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let file2 = File::new(
+            Checksum::BLAKE3("file2".into()),
+            3000,
+            vec![
+                (0, Checksum::BLAKE3("file1".into())),
+                (1000, Checksum::BLAKE3("chunk2".into())),
+            ],
+        );
+        let files = vec![file1, file2];
+        let chunks = vec![
+            Chunk::new(Checksum::BLAKE3("file1".into()), 0, 1000)
+                .packfile(Checksum::BLAKE3("pack1".into())),
+            Chunk::new(Checksum::BLAKE3("chunk2".into()), 1000, 2000)
+                .packfile(Checksum::BLAKE3("pack2".into())),
+        ];
+        let tree1 = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(Checksum::BLAKE3("file1".into())),
+            )],
+            1,
+        );
+        let tree2 = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("file1".into())),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("file2".into())),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/zero-length.txt"),
+                    TreeReference::SMALL(vec![0; 50]),
+                ),
+            ],
+            3,
+        );
+        let snapshot1 = Snapshot::new(None, tree1.digest.clone(), FileCounts::default());
+        let snapshot2 = Snapshot::new(
+            Some(snapshot1.digest.clone()),
+            tree2.digest.clone(),
+            FileCounts::default(),
+        );
+        let latest = snapshot2.digest.clone();
+        let snapshots = vec![snapshot1, snapshot2];
+        let trees = vec![tree1, tree2];
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.iter().find(|s| &s.digest == digest).cloned()));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        // the shared chunk is never looked up, as it was already counted
+        mock.expect_get_chunk()
+            .times(1)
+            .returning(move |digest| Ok(chunks.iter().find(|c| &c.digest == digest).cloned()));
+        mock.expect_get_pack().times(2).returning(|digest| {
+            let mut locations = vec![PackLocation::new("local1", "bucket1", "object1")];
+            if digest == &Checksum::BLAKE3("pack2".into()) {
+                locations.push(PackLocation::new("cloud1", "bucket1", "object2"));
+            }
+            Ok(Some(Pack::new(digest.clone(), locations)))
+        });
+        // act
+        let usecase = GetDatasetUsage::new(Box::new(mock));
+        let params = Params::new(dataset_id, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let usage = result.unwrap();
+        assert_eq!(usage.logical_size, 4050);
+        assert_eq!(usage.stored_bytes, 3000);
+        assert_eq!(usage.growth.len(), 2);
+        assert_eq!(usage.growth[0].logical_size, 4050);
+        assert_eq!(usage.growth[0].added_bytes, 2000);
+        assert_eq!(usage.growth[1].logical_size, 1000);
+        assert_eq!(usage.growth[1].added_bytes, 1000);
+        assert_eq!(
+            usage.stores,
+            vec![
+                StoreBytes {
+                    store_id: "cloud1".into(),
+                    bytes: 2000
+                },
+                StoreBytes {
+                    store_id: "local1".into(),
+                    bytes: 3000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dataset_usage_no_snapshots() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot().returning(|_| Ok(None));
+        // act
+        let usecase = GetDatasetUsage::new(Box::new(mock));
+        let params = Params::new(dataset_id, Some(5));
+        let result = usecase.call(params);
+        // assert
+        let usage = result.unwrap();
+        assert_eq!(usage.logical_size, 0);
+        assert_eq!(usage.stored_bytes, 0);
+        assert!(usage.growth.is_empty());
+        assert!(usage.stores.is_empty());
+    }
+
+    #[test]
+    fn test_dataset_usage_no_dataset() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        // act
+        let usecase = GetDatasetUsage::new(Box::new(mock));
+        let params = Params::new("nosuchdataset".to_owned(), None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such dataset"));
+    }
+}
@@ -0,0 +1,603 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, DedupStats, File, Message, MessageCode, Snapshot, SnapshotDedup, TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+lazy_static! {
+    // Chunk references of each dataset, kept between requests such that only
+    // the snapshots added or removed since then need to be walked.
+    static ref TALLIES: Mutex<HashMap<String, ChunkTally>> = Mutex::new(HashMap::new());
+}
+
+///
+/// Report the deduplication achieved across the snapshots of a dataset.
+///
+/// The results are saved in the database and returned again until the
+/// snapshots of the dataset change, as when a backup completes or old snapshots
+/// are pruned. The references to each chunk are kept in memory, along with a
+/// summary of each tree, such that only the snapshots added or removed since
+/// then are walked, and only their new trees are read. As with the dataset
+/// usage, the physical bytes are measured before compression.
+///
+pub struct GetDedupStats {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl GetDedupStats {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<DedupStats, Params> for GetDedupStats {
+    fn call(&self, params: Params) -> Result<DedupStats, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        // collect the snapshots from newest to oldest
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        let mut next = self.repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let snapshot = self.repo.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            next = snapshot.parent.clone();
+            snapshots.push(snapshot);
+        }
+        if let Some(saved) = self.repo.get_dedup_stats(&dataset.id)? {
+            let unchanged = saved.snapshots.len() == snapshots.len()
+                && saved
+                    .snapshots
+                    .iter()
+                    .zip(snapshots.iter())
+                    .all(|(a, b)| a.digest == b.digest);
+            if unchanged {
+                return Ok(saved);
+            }
+        }
+        let stats = update_stats(self.repo.as_ref(), &dataset.id, &snapshots)?;
+        self.repo.put_dedup_stats(&stats)?;
+        Ok(stats)
+    }
+}
+
+// Compute the statistics of the given snapshots anew, replacing any tally that
+// was kept for the dataset.
+pub(crate) fn compute_stats(
+    repo: &dyn RecordRepository,
+    dataset_id: &str,
+    snapshots: &[Snapshot],
+) -> Result<DedupStats, Error> {
+    let mut tally = ChunkTally::default();
+    let stats = tally.update(repo, dataset_id, snapshots)?;
+    TALLIES.lock().unwrap().insert(dataset_id.to_owned(), tally);
+    Ok(stats)
+}
+
+// Update the tally kept for the dataset to reflect the given snapshots, which
+// walks only those snapshots that were added or removed since the last time.
+fn update_stats(
+    repo: &dyn RecordRepository,
+    dataset_id: &str,
+    snapshots: &[Snapshot],
+) -> Result<DedupStats, Error> {
+    // the tally is taken out while it is updated, and dropped if that fails
+    // part way through, as it would no longer be consistent
+    let taken = TALLIES.lock().unwrap().remove(dataset_id);
+    let mut tally = taken.unwrap_or_default();
+    let stats = tally.update(repo, dataset_id, snapshots)?;
+    TALLIES.lock().unwrap().insert(dataset_id.to_owned(), tally);
+    Ok(stats)
+}
+
+// Logical size of a tree and everything within it, along with the chunks of
+// the files directly within the tree, and the subtrees.
+#[derive(Default)]
+struct TreeSummary {
+    size: u64,
+    chunks: Vec<Checksum>,
+    subtrees: Vec<Checksum>,
+}
+
+// Logical size of a snapshot and the size of the chunks that only it
+// references, along with its root tree.
+struct SnapshotTally {
+    tree: Checksum,
+    logical_size: u64,
+    unique_bytes: u64,
+}
+
+// Number of snapshots referencing each chunk, maintained as snapshots are
+// added and removed, such that the totals are updated without walking every
+// snapshot again. Trees and files are immutable, so their summaries remain
+// valid for as long as the tally is kept.
+#[derive(Default)]
+struct ChunkTally {
+    // Summary of every tree seen so far.
+    trees: HashMap<Checksum, TreeSummary>,
+    // Length and chunk digests of each file.
+    files: HashMap<Checksum, (u64, Vec<Checksum>)>,
+    // Size of every chunk seen so far.
+    chunk_sizes: HashMap<Checksum, u64>,
+    // Number of snapshots referencing each chunk, and the exclusive-or of
+    // their identifiers, which is that of the snapshot when there is one.
+    references: HashMap<Checksum, (u32, u32)>,
+    // Identifier assigned to each snapshot that has been tallied.
+    ids: HashMap<Checksum, u32>,
+    // Sizes of each snapshot, by identifier.
+    tallied: HashMap<u32, SnapshotTally>,
+    next_id: u32,
+    physical_bytes: u64,
+}
+
+impl ChunkTally {
+    // Add and remove snapshots such that the tally reflects those given,
+    // returning the statistics for them.
+    fn update(
+        &mut self,
+        repo: &dyn RecordRepository,
+        dataset_id: &str,
+        snapshots: &[Snapshot],
+    ) -> Result<DedupStats, Error> {
+        let current: HashSet<&Checksum> = snapshots.iter().map(|s| &s.digest).collect();
+        let removed: Vec<Checksum> = self
+            .ids
+            .keys()
+            .filter(|d| !current.contains(d))
+            .cloned()
+            .collect();
+        for digest in removed.iter() {
+            if let Some(id) = self.ids.remove(digest) {
+                if let Some(snapshot) = self.tallied.remove(&id) {
+                    for chunk in self.collect_chunks(&snapshot.tree) {
+                        self.release(chunk, id);
+                    }
+                }
+            }
+        }
+        for snapshot in snapshots.iter() {
+            if !self.ids.contains_key(&snapshot.digest) {
+                self.add_snapshot(repo, snapshot)?;
+            }
+        }
+        let sizes: Vec<&SnapshotTally> = snapshots
+            .iter()
+            .map(|s| &self.tallied[&self.ids[&s.digest]])
+            .collect();
+        Ok(DedupStats {
+            dataset_id: dataset_id.to_owned(),
+            computed: Utc::now(),
+            logical_bytes: sizes.iter().map(|t| t.logical_size).sum(),
+            physical_bytes: self.physical_bytes,
+            snapshots: snapshots
+                .iter()
+                .zip(sizes.iter())
+                .map(|(snapshot, tally)| SnapshotDedup {
+                    digest: snapshot.digest.clone(),
+                    start_time: snapshot.start_time,
+                    logical_size: tally.logical_size,
+                    unique_bytes: tally.unique_bytes,
+                })
+                .collect(),
+        })
+    }
+
+    // Count the references of the snapshot to each of its distinct chunks.
+    fn add_snapshot(
+        &mut self,
+        repo: &dyn RecordRepository,
+        snapshot: &Snapshot,
+    ) -> Result<(), Error> {
+        let logical_size = self.summarize(repo, &snapshot.tree)?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.ids.insert(snapshot.digest.clone(), id);
+        self.tallied.insert(
+            id,
+            SnapshotTally {
+                tree: snapshot.tree.clone(),
+                logical_size,
+                unique_bytes: 0,
+            },
+        );
+        for chunk in self.collect_chunks(&snapshot.tree) {
+            self.acquire(chunk, id);
+        }
+        Ok(())
+    }
+
+    // Record a reference to the chunk by the snapshot, which no longer has
+    // the only reference, if another snapshot had one.
+    fn acquire(&mut self, chunk: Checksum, id: u32) {
+        let length = self.chunk_sizes[&chunk];
+        let entry = self.references.entry(chunk).or_insert((0, 0));
+        let owner = match entry.0 {
+            0 => {
+                self.physical_bytes += length;
+                Some((id, true))
+            }
+            1 => Some((entry.1, false)),
+            _ => None,
+        };
+        entry.0 += 1;
+        entry.1 ^= id;
+        if let Some((owner, unique)) = owner {
+            if let Some(tally) = self.tallied.get_mut(&owner) {
+                if unique {
+                    tally.unique_bytes += length;
+                } else {
+                    tally.unique_bytes -= length;
+                }
+            }
+        }
+    }
+
+    // Remove a reference to the chunk by the snapshot, leaving the remaining
+    // snapshot with the only reference, if there is just one.
+    fn release(&mut self, chunk: Checksum, id: u32) {
+        let length = self.chunk_sizes[&chunk];
+        let Some(entry) = self.references.get_mut(&chunk) else {
+            return;
+        };
+        entry.0 -= 1;
+        entry.1 ^= id;
+        match entry.0 {
+            0 => {
+                self.physical_bytes -= length;
+                self.references.remove(&chunk);
+            }
+            1 => {
+                let owner = entry.1;
+                if let Some(tally) = self.tallied.get_mut(&owner) {
+                    tally.unique_bytes += length;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Collect the distinct chunks within the tree, which has been summarized.
+    fn collect_chunks(&self, digest: &Checksum) -> HashSet<Checksum> {
+        let mut chunks: HashSet<Checksum> = HashSet::new();
+        let mut seen_trees: HashSet<&Checksum> = HashSet::new();
+        let mut pending: Vec<&Checksum> = vec![digest];
+        while let Some(digest) = pending.pop() {
+            if !seen_trees.insert(digest) {
+                continue;
+            }
+            if let Some(summary) = self.trees.get(digest) {
+                chunks.extend(summary.chunks.iter().cloned());
+                pending.extend(summary.subtrees.iter());
+            }
+        }
+        chunks
+    }
+
+    // Return the combined size of the files within the tree, summarizing the
+    // tree and its subtrees the first time each one is seen. Trees that
+    // appear more than once within the same snapshot are counted again.
+    fn summarize(&mut self, repo: &dyn RecordRepository, digest: &Checksum) -> Result<u64, Error> {
+        if let Some(summary) = self.trees.get(digest) {
+            return Ok(summary.size);
+        }
+        let tree = repo
+            .get_tree(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &digest))?;
+        let mut summary = TreeSummary::default();
+        for entry in tree.entries.iter() {
+            match &entry.reference {
+                TreeReference::TREE(subtree) => {
+                    summary.size += self.summarize(repo, subtree)?;
+                    summary.subtrees.push(subtree.to_owned());
+                }
+                TreeReference::FILE(file_digest) => {
+                    let (length, chunks) = self.file_chunks(repo, file_digest)?;
+                    summary.size += length;
+                    summary.chunks.extend(chunks);
+                }
+                TreeReference::SMALL(contents) => summary.size += contents.len() as u64,
+                TreeReference::LINK(_) => (),
+            }
+        }
+        let size = summary.size;
+        self.trees.insert(digest.to_owned(), summary);
+        Ok(size)
+    }
+
+    // Return the length and chunk digests of the file, reading the file and
+    // chunk records the first time the file is seen.
+    fn file_chunks(
+        &mut self,
+        repo: &dyn RecordRepository,
+        digest: &Checksum,
+    ) -> Result<(u64, Vec<Checksum>), Error> {
+        if let Some(found) = self.files.get(digest) {
+            return Ok(found.clone());
+        }
+        let file: File = repo
+            .get_file(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingFile).with("digest", &digest))?;
+        let mut chunks: Vec<Checksum> = Vec::new();
+        if file.chunks.len() == 1 {
+            // the chunk of a single-chunk file is named by the file digest
+            self.chunk_sizes.insert(file.digest.clone(), file.length);
+            chunks.push(file.digest.clone());
+        } else {
+            for (_, chunk_digest) in file.chunks.iter() {
+                if !self.chunk_sizes.contains_key(chunk_digest) {
+                    let chunk = repo.get_chunk(chunk_digest)?.ok_or_else(|| {
+                        Message::new(MessageCode::MissingChunk).with("digest", &chunk_digest)
+                    })?;
+                    self.chunk_sizes
+                        .insert(chunk_digest.clone(), chunk.length as u64);
+                }
+                chunks.push(chunk_digest.clone());
+            }
+        }
+        self.files
+            .insert(digest.to_owned(), (file.length, chunks.clone()));
+        Ok((file.length, chunks))
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset for which to report deduplication.
+    dataset_id: String,
+}
+
+impl Params {
+    pub fn new(dataset_id: String) -> Self {
+        Self { dataset_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.dataset_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Chunk, Dataset, FileCounts, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_dedup_stats_ok() {
+        // arrange: the first snapshot has one file, the second snapshot adds
+        // a file of two chunks, one of which is shared with the first file
+        let file1 = File::new(
+            Checksum::BLAKE3("file1".into()),
+            1000,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let file2 = File::new(
+            Checksum::BLAKE3("file2".into()),
+            3000,
+            vec![
+                (0, Checksum::BLAKE3("file1".into())),
+                (1000, Checksum::BLAKE3("chunk2".into())),
+            ],
+        );
+        let files = vec![file1, file2];
+        let chunks = vec![
+            Chunk::new(Checksum::BLAKE3("file1".into()), 0, 1000)
+                .packfile(Checksum::BLAKE3("pack1".into())),
+            Chunk::new(Checksum::BLAKE3("chunk2".into()), 1000, 2000)
+                .packfile(Checksum::BLAKE3("pack2".into())),
+        ];
+        let tree1 = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::FILE(Checksum::BLAKE3("file1".into())),
+            )],
+            1,
+        );
+        let tree2 = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("file1".into())),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("file2".into())),
+                ),
+            ],
+            2,
+        );
+        let snapshot1 = Snapshot::new(None, tree1.digest.clone(), FileCounts::default());
+        let snapshot2 = Snapshot::new(
+            Some(snapshot1.digest.clone()),
+            tree2.digest.clone(),
+            FileCounts::default(),
+        );
+        let latest = snapshot2.digest.clone();
+        let snapshots = vec![snapshot1, snapshot2];
+        let trees = vec![tree1, tree2];
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.iter().find(|s| &s.digest == digest).cloned()));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        // the shared chunk is never looked up, as its size is already known
+        mock.expect_get_chunk()
+            .times(1)
+            .returning(move |digest| Ok(chunks.iter().find(|c| &c.digest == digest).cloned()));
+        mock.expect_get_dedup_stats().returning(|_| Ok(None));
+        mock.expect_put_dedup_stats().times(1).returning(|_| Ok(()));
+        // act
+        let usecase = GetDedupStats::new(Box::new(mock));
+        let params = Params::new(dataset_id);
+        let result = usecase.call(params);
+        // assert
+        let stats = result.unwrap();
+        assert_eq!(stats.logical_bytes, 5000);
+        assert_eq!(stats.physical_bytes, 3000);
+        assert!((stats.ratio() - 5000.0 / 3000.0).abs() < 0.001);
+        assert_eq!(stats.snapshots.len(), 2);
+        assert_eq!(stats.snapshots[0].logical_size, 4000);
+        assert_eq!(stats.snapshots[0].unique_bytes, 2000);
+        assert_eq!(stats.snapshots[1].logical_size, 1000);
+        assert_eq!(stats.snapshots[1].unique_bytes, 0);
+    }
+
+    #[test]
+    fn test_chunk_tally_update() -> Result<(), Error> {
+        // arrange: two snapshots that share one file, and each have another
+        let file1 = File::new(
+            Checksum::BLAKE3("file1".into()),
+            1000,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let file2 = File::new(
+            Checksum::BLAKE3("file2".into()),
+            2000,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let file3 = File::new(
+            Checksum::BLAKE3("file3".into()),
+            4000,
+            vec![(0, Checksum::BLAKE3("pack2".into()))],
+        );
+        let make_tree = |files: &[&File]| {
+            let entries: Vec<TreeEntry> = files
+                .iter()
+                .enumerate()
+                .map(|(index, file)| {
+                    let mut entry = TreeEntry::new(
+                        Path::new("../test/fixtures/lorem-ipsum.txt"),
+                        TreeReference::FILE(file.digest.clone()),
+                    );
+                    entry.name = format!("file{}", index);
+                    entry
+                })
+                .collect();
+            Tree::new(entries, files.len() as u32)
+        };
+        let tree1 = make_tree(&[&file1, &file2]);
+        let tree2 = make_tree(&[&file1, &file3]);
+        let snapshot1 = Snapshot::new(None, tree1.digest.clone(), FileCounts::default());
+        let snapshot2 = Snapshot::new(
+            Some(snapshot1.digest.clone()),
+            tree2.digest.clone(),
+            FileCounts::default(),
+        );
+        let files = vec![file1, file2, file3];
+        let trees = vec![tree1, tree2];
+        let mut mock = MockRecordRepository::new();
+        // each tree and file is read only once
+        mock.expect_get_tree()
+            .times(2)
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .times(3)
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        mock.expect_get_chunk().never();
+        let mut tally = ChunkTally::default();
+        // act
+        let stats = tally.update(&mock, "cafebabe", &[snapshot1.clone()])?;
+        // assert
+        assert_eq!(stats.physical_bytes, 3000);
+        assert_eq!(stats.snapshots[0].unique_bytes, 3000);
+        // act: a new snapshot shares the first file
+        let stats = tally.update(&mock, "cafebabe", &[snapshot2.clone(), snapshot1])?;
+        // assert
+        assert_eq!(stats.logical_bytes, 8000);
+        assert_eq!(stats.physical_bytes, 7000);
+        assert_eq!(stats.snapshots[0].unique_bytes, 4000);
+        assert_eq!(stats.snapshots[1].unique_bytes, 2000);
+        // act: the older snapshot is pruned
+        let stats = tally.update(&mock, "cafebabe", &[snapshot2])?;
+        // assert
+        assert_eq!(stats.logical_bytes, 5000);
+        assert_eq!(stats.physical_bytes, 5000);
+        assert_eq!(stats.snapshots[0].unique_bytes, 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_stats_saved() {
+        // arrange
+        let tree = Tree::new(vec![], 0);
+        let snapshot = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        let latest = snapshot.digest.clone();
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let saved = DedupStats {
+            dataset_id: dataset_id.clone(),
+            computed: Utc::now(),
+            logical_bytes: 100,
+            physical_bytes: 50,
+            snapshots: vec![SnapshotDedup {
+                digest: snapshot.digest.clone(),
+                start_time: snapshot.start_time,
+                logical_size: 100,
+                unique_bytes: 50,
+            }],
+        };
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_dedup_stats()
+            .returning(move |_| Ok(Some(saved.clone())));
+        mock.expect_get_tree().never();
+        mock.expect_put_dedup_stats().never();
+        // act
+        let usecase = GetDedupStats::new(Box::new(mock));
+        let params = Params::new(dataset_id);
+        let result = usecase.call(params);
+        // assert
+        let stats = result.unwrap();
+        assert_eq!(stats.logical_bytes, 100);
+        assert!((stats.ratio() - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_dedup_stats_no_dataset() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        // act
+        let usecase = GetDedupStats::new(Box::new(mock));
+        let params = Params::new("nosuchdataset".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such dataset"));
+    }
+}
@@ -1,6 +1,8 @@
 //
 // Copyright (c) 2020 Nathan Fiedler
 //
+use crate::domain::entities::{Message, MessageCode, TrashedDataset};
+use crate::domain::managers::trash;
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
@@ -18,35 +20,42 @@ impl DeleteDataset {
 
 impl super::UseCase<(), Params> for DeleteDataset {
     fn call(&self, params: Params) -> Result<(), Error> {
-        self.repo.delete_dataset(&params.dataset_id)?;
-        // ignore any errors when deleting records that may or may not be
-        // present in the data source
-        let _ = self.repo.delete_computer_id(&params.dataset_id);
-        let _ = self.repo.delete_latest_snapshot(&params.dataset_id);
-        Ok(())
+        if params.force {
+            return trash::purge_dataset(self.repo.as_ref(), &params.dataset_id);
+        }
+        // move the dataset to the trash, leaving the snapshots in place so
+        // that the dataset can be restored in its entirety
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        self.repo
+            .put_trashed_dataset(&TrashedDataset::new(dataset))?;
+        self.repo.delete_dataset(&params.dataset_id)
     }
 }
 
 pub struct Params {
     /// Unique identifier of the dataset.
     dataset_id: String,
+    /// If true, remove the dataset immediately rather than moving it to the trash.
+    force: bool,
 }
 
 impl Params {
-    pub fn new(dataset_id: String) -> Self {
-        Self { dataset_id }
+    pub fn new(dataset_id: String, force: bool) -> Self {
+        Self { dataset_id, force }
     }
 }
 
 impl fmt::Display for Params {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Params({})", self.dataset_id)
+        write!(f, "Params({}, {})", self.dataset_id, self.force)
     }
 }
 
 impl cmp::PartialEq for Params {
     fn eq(&self, other: &Self) -> bool {
-        self.dataset_id == other.dataset_id
+        self.dataset_id == other.dataset_id && self.force == other.force
     }
 }
 
@@ -56,21 +65,49 @@ impl cmp::Eq for Params {}
 mod tests {
     use super::super::UseCase;
     use super::*;
+    use crate::domain::entities::Dataset;
     use crate::domain::repositories::MockRecordRepository;
-    use anyhow::anyhow;
+    use std::path::Path;
 
     #[test]
     fn test_delete_dataset_ok() {
         // arrange
         let mut mock = MockRecordRepository::new();
-        mock.expect_delete_dataset().returning(|_| Ok(()));
+        mock.expect_get_dataset().returning(|id| {
+            let mut dataset = Dataset::new(Path::new("/home/planet"));
+            dataset.id = id.to_owned();
+            Ok(Some(dataset))
+        });
+        mock.expect_put_trashed_dataset()
+            .withf(|trashed| trashed.dataset.id == "cafebabe")
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_dataset().times(1).returning(|_| Ok(()));
+        // snapshots are retained while the dataset is in the trash
+        mock.expect_delete_computer_id().never();
+        mock.expect_delete_latest_snapshot().never();
+        // act
+        let usecase = DeleteDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_delete_dataset_force() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_put_trashed_dataset().never();
+        mock.expect_delete_dataset().times(1).returning(|_| Ok(()));
+        mock.expect_delete_trashed_dataset()
+            .times(1)
+            .returning(|_| Ok(()));
         mock.expect_delete_computer_id().returning(|_| Ok(()));
         mock.expect_delete_latest_snapshot().returning(|_| Ok(()));
         // act
         let usecase = DeleteDataset::new(Box::new(mock));
-        let params = Params {
-            dataset_id: "cafebabe".to_owned(),
-        };
+        let params = Params::new("cafebabe".to_owned(), true);
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
@@ -80,13 +117,10 @@ mod tests {
     fn test_delete_dataset_err() {
         // arrange
         let mut mock = MockRecordRepository::new();
-        mock.expect_delete_dataset()
-            .returning(|_| Err(anyhow!("oh no")));
+        mock.expect_get_dataset().returning(|_| Ok(None));
         // act
         let usecase = DeleteDataset::new(Box::new(mock));
-        let params = Params {
-            dataset_id: "cafebabe".to_owned(),
-        };
+        let params = Params::new("cafebabe".to_owned(), false);
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
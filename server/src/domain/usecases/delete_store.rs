@@ -1,11 +1,21 @@
 //
 // Copyright (c) 2020 Nathan Fiedler
 //
+use crate::domain::entities::{Event, EventKind};
+use crate::domain::managers::events;
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
+use log::{info, warn};
 use std::cmp;
 use std::fmt;
 
+///
+/// Delete a store, provided no dataset or pack record refers to it, including
+/// the datasets in the trash, which may yet be restored. With the `force`
+/// flag, the store is first removed from the datasets and from the locations
+/// of the pack records, unless that would leave a pack without any location at
+/// all.
+///
 pub struct DeleteStore {
     repo: Box<dyn RecordRepository>,
 }
@@ -18,18 +28,105 @@ impl DeleteStore {
 
 impl super::UseCase<(), Params> for DeleteStore {
     fn call(&self, params: Params) -> Result<(), Error> {
-        self.repo.delete_store(&params.store_id)
+        let datasets: Vec<_> = self
+            .repo
+            .get_datasets()?
+            .into_iter()
+            .filter(|d| d.stores.contains(&params.store_id))
+            .collect();
+        let trashed: Vec<_> = self
+            .repo
+            .get_trashed_datasets()?
+            .into_iter()
+            .filter(|t| t.dataset.stores.contains(&params.store_id))
+            .collect();
+        let packs = self.repo.get_packs(&params.store_id)?;
+        if !datasets.is_empty() || !trashed.is_empty() || !packs.is_empty() {
+            let stranded = packs
+                .iter()
+                .filter(|p| p.locations.iter().all(|l| l.store == params.store_id))
+                .count();
+            if !params.force || stranded > 0 {
+                return Err(Error::from(StoreInUseError {
+                    datasets: datasets
+                        .into_iter()
+                        .map(|d| d.id)
+                        .chain(trashed.into_iter().map(|t| t.dataset.id))
+                        .collect(),
+                    packs: packs.len() as u64,
+                    stranded: stranded as u64,
+                }));
+            }
+            for mut dataset in datasets.into_iter() {
+                dataset.stores.retain(|s| s != &params.store_id);
+                if dataset.stores.is_empty() {
+                    warn!("dataset {} no longer has any stores", dataset.id);
+                }
+                self.repo.put_dataset(&dataset)?;
+            }
+            for mut entry in trashed.into_iter() {
+                entry.dataset.stores.retain(|s| s != &params.store_id);
+                self.repo.put_trashed_dataset(&entry)?;
+            }
+            for mut pack in packs.into_iter() {
+                pack.locations.retain(|l| l.store != params.store_id);
+                self.repo.put_pack(&pack)?;
+            }
+            info!("detached store {} from datasets and packs", params.store_id);
+        }
+        self.repo.delete_store(&params.store_id)?;
+        events::record(Event::new(EventKind::StoreDeleted, &params.store_id));
+        Ok(())
+    }
+}
+
+///
+/// Raised when deleting a store that is still referenced by datasets or pack
+/// records. The identifiers of the datasets are included so the caller can
+/// show what depends on the store.
+///
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub struct StoreInUseError {
+    /// Identifiers of the datasets that use the store, including those in the
+    /// trash.
+    pub datasets: Vec<String>,
+    /// Number of pack records with a location in the store.
+    pub packs: u64,
+    /// Number of pack records whose only location is in the store.
+    pub stranded: u64,
+}
+
+impl fmt::Display for StoreInUseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.stranded > 0 {
+            write!(
+                f,
+                "store holds the only copy of {} packs, which must first be copied \
+                 to another store (restorePacks) or reassigned (reassignPacks)",
+                self.stranded
+            )
+        } else {
+            write!(
+                f,
+                "store is used by {} datasets and {} packs, delete with force to \
+                 remove it from them",
+                self.datasets.len(),
+                self.packs
+            )
+        }
     }
 }
 
 pub struct Params {
     /// Unique identifier of the store.
     store_id: String,
+    /// Remove the store from the datasets and pack records that refer to it.
+    force: bool,
 }
 
 impl Params {
-    pub fn new(store_id: String) -> Self {
-        Self { store_id }
+    pub fn new(store_id: String, force: bool) -> Self {
+        Self { store_id, force }
     }
 }
 
@@ -41,7 +138,7 @@ impl fmt::Display for Params {
 
 impl cmp::PartialEq for Params {
     fn eq(&self, other: &Self) -> bool {
-        self.store_id == other.store_id
+        self.store_id == other.store_id && self.force == other.force
     }
 }
 
@@ -51,19 +148,22 @@ impl cmp::Eq for Params {}
 mod tests {
     use super::super::UseCase;
     use super::*;
+    use crate::domain::entities::{Checksum, Dataset, Pack, PackLocation, TrashedDataset};
     use crate::domain::repositories::MockRecordRepository;
     use anyhow::anyhow;
+    use std::path::Path;
 
     #[test]
     fn test_delete_store_ok() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
         mock.expect_delete_store().returning(|_| Ok(()));
         // act
         let usecase = DeleteStore::new(Box::new(mock));
-        let params = Params {
-            store_id: "cafebabe".to_owned(),
-        };
+        let params = Params::new("cafebabe".to_owned(), false);
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
@@ -73,15 +173,133 @@ mod tests {
     fn test_delete_store_err() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
         mock.expect_delete_store()
             .returning(|_| Err(anyhow!("oh no")));
         // act
         let usecase = DeleteStore::new(Box::new(mock));
-        let params = Params {
-            store_id: "cafebabe".to_owned(),
-        };
+        let params = Params::new("cafebabe".to_owned(), false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_delete_store_in_use() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.add_store("cafebabe");
+        dataset.add_store("deadbeef");
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| {
+            let locations = vec![
+                PackLocation::new("cafebabe", "bucket1", "object1"),
+                PackLocation::new("deadbeef", "bucket1", "object1"),
+            ];
+            Ok(vec![Pack::new(Checksum::BLAKE3("pack1".into()), locations)])
+        });
+        mock.expect_delete_store().never();
+        // act
+        let usecase = DeleteStore::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err().downcast::<StoreInUseError>().unwrap();
+        assert_eq!(err.datasets, vec![dataset_id]);
+        assert_eq!(err.packs, 1);
+        assert_eq!(err.stranded, 0);
+    }
+
+    #[test]
+    fn test_delete_store_force() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.add_store("cafebabe");
+        dataset.add_store("deadbeef");
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| {
+            let locations = vec![
+                PackLocation::new("cafebabe", "bucket1", "object1"),
+                PackLocation::new("deadbeef", "bucket1", "object1"),
+            ];
+            Ok(vec![Pack::new(Checksum::BLAKE3("pack1".into()), locations)])
+        });
+        mock.expect_put_dataset()
+            .withf(|dataset| dataset.stores == vec!["deadbeef".to_owned()])
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_put_pack()
+            .withf(|pack| pack.locations.len() == 1 && pack.locations[0].store == "deadbeef")
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_store().times(1).returning(|_| Ok(()));
+        // act
+        let usecase = DeleteStore::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_delete_store_force_trashed() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.add_store("cafebabe");
+        dataset.add_store("deadbeef");
+        let dataset_id = dataset.id.clone();
+        let trashed = TrashedDataset::new(dataset);
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets()
+            .returning(move || Ok(vec![trashed.clone()]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
+        mock.expect_put_trashed_dataset()
+            .withf(move |trashed| {
+                trashed.dataset.id == dataset_id
+                    && trashed.dataset.stores == vec!["deadbeef".to_owned()]
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_store().times(1).returning(|_| Ok(()));
+        // act
+        let usecase = DeleteStore::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_delete_store_force_stranded() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| {
+            let locations = vec![PackLocation::new("cafebabe", "bucket1", "object1")];
+            Ok(vec![Pack::new(Checksum::BLAKE3("pack1".into()), locations)])
+        });
+        mock.expect_put_pack().never();
+        mock.expect_delete_store().never();
+        // act
+        let usecase = DeleteStore::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned(), true);
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
+        let err = result.unwrap_err().downcast::<StoreInUseError>().unwrap();
+        assert_eq!(err.stranded, 1);
+        assert!(err.to_string().contains("only copy of 1 packs"));
     }
 }
@@ -0,0 +1,353 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Chunk, DiscoveryReport, Message, MessageCode, Pack, RemoteObject, NULL_SHA1,
+};
+use crate::domain::managers::maintenance;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use log::info;
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use store_core::Secret;
+
+///
+/// What to do with the objects in a store that are not known to the database.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Only report the unknown objects.
+    Report,
+    /// Make pack records, and any missing chunk records, for the unknown
+    /// objects that are readable pack files.
+    Adopt,
+    /// Remove the unknown objects, and any buckets left empty, from the store.
+    Remove,
+}
+
+///
+/// Find the objects in a store that are not known to the database, such as
+/// after the database was lost or restored from an older archive, and try to
+/// read each one as a pack file.
+///
+/// Every unknown object is downloaded to be examined, which may take some time
+/// and incur transfer fees for remote stores.
+///
+pub struct DiscoverRemote {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl DiscoverRemote {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Record the unknown pack, adding a location if the pack itself is known,
+    // along with any of its chunks that are not yet known.
+    fn adopt(&self, object: &RemoteObject, digest: &Checksum) -> Result<(), Error> {
+        match self.repo.get_pack(digest)? {
+            Some(mut pack) => {
+                pack.locations.push(object.location.clone());
+                self.repo.put_pack(&pack)?;
+            }
+            None => {
+                let pack = Pack::new(digest.clone(), vec![object.location.clone()]);
+                self.repo.insert_pack(&pack)?;
+            }
+        }
+        for (chunk, length) in object.chunks.iter() {
+            if self.repo.get_chunk(chunk)?.is_none() {
+                // the offset of the chunk within its file is not known
+                let record = Chunk::new(chunk.clone(), 0, *length).packfile(digest.clone());
+                self.repo.insert_chunk(&record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl super::UseCase<DiscoveryReport, Params> for DiscoverRemote {
+    fn call(&self, params: Params) -> Result<DiscoveryReport, Error> {
+        let store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        // gather everything the database expects to be in the store, which
+        // includes the database snapshots and the snapshot log
+        let mut known_packs = self.repo.get_packs(&store.id)?;
+        known_packs.append(&mut self.repo.get_databases()?);
+        for location in self.repo.get_chain_locations()? {
+            let digest = Checksum::from_str(NULL_SHA1)?;
+            known_packs.push(Pack::new(digest, vec![location]));
+        }
+        let known: HashSet<(&str, &str)> = known_packs
+            .iter()
+            .flat_map(|p| p.locations.iter())
+            .filter(|l| l.store == store.id)
+            .map(|l| (l.bucket.as_str(), l.object.as_str()))
+            .collect();
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        let mut report = DiscoveryReport::new(&store.id);
+        for location in pack_repo.list_locations(&store.id)? {
+            report.objects += 1;
+            if known.contains(&(location.bucket.as_str(), location.object.as_str())) {
+                report.known += 1;
+                continue;
+            }
+            let mut object = RemoteObject::new(location);
+            let outfile = tempfile::NamedTempFile::new()?.into_temp_path();
+            match pack_repo.retrieve_object(&object.location, &outfile) {
+                Ok(()) => {
+                    let digest = Checksum::blake3_from_file(&outfile)?;
+                    match read_chunks(&outfile, &object.location.object, &digest, &params) {
+                        Ok(chunks) => object.chunks = chunks,
+                        Err(err) => object.error = Some(err.to_string()),
+                    }
+                    object.digest = Some(digest);
+                }
+                Err(err) => object.error = Some(format!("could not be retrieved: {}", err)),
+            }
+            if params.action == Action::Adopt && object.is_pack() {
+                if let Some(digest) = object.digest.as_ref() {
+                    self.adopt(&object, digest)?;
+                    object.adopted = true;
+                }
+            }
+            report.unknown.push(object);
+        }
+        info!(
+            "DiscoverRemote found {} unknown of {} objects in store {}",
+            report.unknown.len(),
+            report.objects,
+            store.id
+        );
+        if params.action == Action::Remove && !report.unknown.is_empty() {
+            // the packs of a running backup are not yet recorded in the database
+            let _guard = maintenance::lock_packs()
+                .ok_or_else(|| anyhow!("cannot remove objects while backup is running"))?;
+            report.removed = pack_repo.prune_extra(&store.id, &known_packs)?;
+            info!(
+                "DiscoverRemote removed {} objects from store {}",
+                report.removed, store.id
+            );
+        }
+        Ok(report)
+    }
+}
+
+// Read the names and sizes of the entries in the pack file, each of which must
+// be the digest of a chunk, and make sure the content matches the object name
+// if that name looks like a digest.
+fn read_chunks(
+    infile: &Path,
+    object: &str,
+    digest: &Checksum,
+    params: &Params,
+) -> Result<Vec<(Checksum, usize)>, Error> {
+    if let Ok(named) = Checksum::from_str(object) {
+        if &named != digest {
+            return Err(anyhow!("content does not match the object name"));
+        }
+    }
+    let mut chunks: Vec<(Checksum, usize)> = Vec::new();
+    let mut reader = exaf_rs::reader::Entries::new(infile)?;
+    reader.enable_encryption(params.passphrase.expose())?;
+    for maybe_entry in reader {
+        let entry = maybe_entry?;
+        let chunk = Checksum::from_str(entry.name())
+            .map_err(|_| anyhow!("entry {} is not a chunk", entry.name()))?;
+        let length = entry.size().unwrap_or(0) as usize;
+        chunks.push((chunk, length));
+    }
+    Ok(chunks)
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// What to do with the unknown objects.
+    action: Action,
+    /// Pass phrase for decrypting the pack files.
+    passphrase: Secret,
+}
+
+impl Params {
+    pub fn new<S: Into<Secret>>(store_id: String, action: Action, passphrase: S) -> Self {
+        Self {
+            store_id,
+            action,
+            passphrase: passphrase.into(),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {:?})", self.store_id, self.action)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id && self.action == other.action
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{PackLocation, Store, StoreType};
+    use crate::domain::helpers::{self, pack};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    // Build a pack file from the chunks of a test fixture, returning its path
+    // and digest; the directory must be kept for as long as the file is needed.
+    fn build_pack(outdir: &Path) -> Result<(PathBuf, Checksum), Error> {
+        let infile = Path::new("../test/fixtures/SekienAkashita.jpg");
+        let chunks = helpers::find_file_chunks(infile, 32768)?;
+        let mut builder = pack::PackBuilder::new(1048576).password("keyboard cat");
+        let packfile = outdir.join("found.pack");
+        builder.initialize(&packfile)?;
+        for chunk in chunks.iter() {
+            builder.add_chunk(chunk)?;
+        }
+        builder.finalize()?;
+        let digest = Checksum::blake3_from_file(&packfile)?;
+        Ok((packfile, digest))
+    }
+
+    fn make_mock(packfile: PathBuf, digest: &Checksum) -> MockRecordRepository {
+        let known = Pack::new(
+            Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned()),
+            vec![PackLocation::new("store1", "bucket1", "known")],
+        );
+        let object_name = digest.to_string();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| {
+            Ok(Some(Store {
+                id: "store1".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "local".to_owned(),
+                properties: HashMap::new(),
+            }))
+        });
+        mock.expect_get_packs()
+            .returning(move |_| Ok(vec![known.clone()]));
+        mock.expect_get_databases().returning(|| Ok(vec![]));
+        mock.expect_get_chain_locations().returning(|| Ok(vec![]));
+        mock.expect_build_pack_repo().returning(move |_| {
+            let packfile = packfile.clone();
+            let object_name = object_name.clone();
+            let mut stores = MockPackRepository::new();
+            stores.expect_list_locations().returning(move |_| {
+                Ok(vec![
+                    PackLocation::new("store1", "bucket1", "known"),
+                    PackLocation::new("store1", "bucket2", &object_name),
+                    PackLocation::new("store1", "bucket2", "garbage"),
+                ])
+            });
+            stores
+                .expect_retrieve_object()
+                .returning(move |location, outfile| {
+                    if location.object == "garbage" {
+                        fs::write(outfile, "not a pack file")?;
+                    } else {
+                        fs::copy(&packfile, outfile)?;
+                    }
+                    Ok(())
+                });
+            stores.expect_prune_extra().returning(|_, packs| {
+                assert_eq!(packs.len(), 1);
+                Ok(2)
+            });
+            Ok(Box::new(stores))
+        });
+        mock
+    }
+
+    #[test]
+    fn test_discover_remote_report() -> Result<(), Error> {
+        // arrange
+        let outdir = tempdir()?;
+        let (packfile, digest) = build_pack(outdir.path())?;
+        let mut mock = make_mock(packfile, &digest);
+        mock.expect_insert_pack().never();
+        // act
+        let usecase = DiscoverRemote::new(Box::new(mock));
+        let params = Params::new("store1".to_owned(), Action::Report, "keyboard cat");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert_eq!(report.objects, 3);
+        assert_eq!(report.known, 1);
+        assert_eq!(report.unknown.len(), 2);
+        assert!(report.unknown[0].is_pack());
+        assert_eq!(report.unknown[0].digest, Some(digest));
+        assert_eq!(report.unknown[0].chunks.len(), 2);
+        let length: usize = report.unknown[0].chunks.iter().map(|(_, l)| l).sum();
+        assert_eq!(length, 109_466);
+        assert!(!report.unknown[0].adopted);
+        assert!(!report.unknown[1].is_pack());
+        assert_eq!(report.removed, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_remote_adopt() -> Result<(), Error> {
+        // arrange
+        let outdir = tempdir()?;
+        let (packfile, digest) = build_pack(outdir.path())?;
+        let mut mock = make_mock(packfile, &digest);
+        mock.expect_get_pack().returning(|_| Ok(None));
+        let expected = digest.clone();
+        mock.expect_insert_pack()
+            .withf(move |pack| pack.digest == expected && pack.locations[0].bucket == "bucket2")
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_get_chunk().returning(|_| Ok(None));
+        mock.expect_insert_chunk()
+            .withf(|chunk| chunk.length > 0)
+            .times(2)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = DiscoverRemote::new(Box::new(mock));
+        let params = Params::new("store1".to_owned(), Action::Adopt, "keyboard cat");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert!(report.unknown[0].adopted);
+        assert!(!report.unknown[1].adopted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_remote_remove() -> Result<(), Error> {
+        // arrange
+        let outdir = tempdir()?;
+        let (packfile, digest) = build_pack(outdir.path())?;
+        let mock = make_mock(packfile, &digest);
+        // act
+        let usecase = DiscoverRemote::new(Box::new(mock));
+        let params = Params::new("store1".to_owned(), Action::Remove, "keyboard cat");
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert_eq!(report.unknown.len(), 2);
+        assert_eq!(report.removed, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Message, MessageCode, PackLocation};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use log::info;
+use std::cmp;
+use std::fmt;
+use tempfile::TempPath;
+
+///
+/// Retrieve any object from a store, exactly as it was stored, for those
+/// occasions when a pack must be inspected or moved by hand.
+///
+pub struct DownloadObject {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl DownloadObject {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<TempPath, Params> for DownloadObject {
+    fn call(&self, params: Params) -> Result<TempPath, Error> {
+        check_name(&params.bucket)?;
+        check_name(&params.object)?;
+        let store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        let location = PackLocation::new(&store.id, &params.bucket, &params.object);
+        let outfile = tempfile::NamedTempFile::new()?.into_temp_path();
+        info!(
+            "DownloadObject: retrieving {}/{} from store {}",
+            params.bucket, params.object, store.id
+        );
+        pack_repo.retrieve_object(&location, &outfile)?;
+        Ok(outfile)
+    }
+}
+
+///
+/// Ensure the bucket or object name refers to a single entry, lest a store
+/// that keeps its objects in a file system be led outside of its base path.
+///
+pub fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(anyhow!(format!("invalid bucket or object name: {}", name)));
+    }
+    Ok(())
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// Name of the bucket containing the object.
+    bucket: String,
+    /// Name of the object to be retrieved.
+    object: String,
+}
+
+impl Params {
+    pub fn new(store_id: String, bucket: String, object: String) -> Self {
+        Self {
+            store_id,
+            bucket,
+            object,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Params({}, {}, {})",
+            self.store_id, self.bucket, self.object
+        )
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+            && self.bucket == other.bucket
+            && self.object == other.object
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Store, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn test_download_object_ok() {
+        // arrange
+        let store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "mylocalstore".to_owned(),
+            properties: HashMap::new(),
+        };
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(store.clone())));
+        mock.expect_build_pack_repo().returning(|_| {
+            let mut pack_repo = MockPackRepository::new();
+            pack_repo
+                .expect_retrieve_object()
+                .withf(|location, _| location.bucket == "bucket1" && location.object == "object1")
+                .returning(|_, outfile| {
+                    fs::write(outfile, b"some pack content")?;
+                    Ok(())
+                });
+            Ok(Box::new(pack_repo))
+        });
+        // act
+        let usecase = DownloadObject::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), "bucket1".into(), "object1".into());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let outfile = result.unwrap();
+        assert_eq!(fs::read(&outfile).unwrap(), b"some pack content");
+    }
+
+    #[test]
+    fn test_download_object_no_store() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        // act
+        let usecase = DownloadObject::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), "bucket1".into(), "object1".into());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("no such store"));
+    }
+
+    #[test]
+    fn test_download_object_bad_name() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().never();
+        let usecase = DownloadObject::new(Box::new(mock));
+        for (bucket, object) in [
+            ("..", "object1"),
+            ("bucket1", "../etc"),
+            ("bucket1", "a\\b"),
+        ] {
+            // act
+            let params = Params::new("cafebabe".into(), bucket.into(), object.into());
+            let result = usecase.call(params);
+            // assert
+            assert!(result.is_err());
+            let err_string = result.unwrap_err().to_string();
+            assert!(err_string.contains("invalid bucket or object name"));
+        }
+        assert!(check_name("bucket1").is_ok());
+        assert!(check_name("manifest-01H0..").is_ok());
+        assert!(check_name("").is_err());
+    }
+}
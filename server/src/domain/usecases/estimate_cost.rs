@@ -0,0 +1,423 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use super::prune_snapshots::{get_snapshots, select_retained};
+use crate::domain::entities::{
+    Checksum, CostEstimate, Dataset, Message, MessageCode, ObjectSizeLimits, Snapshot, Store,
+    TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Number of bytes in a gigabyte, as used by the storage providers for billing.
+const GIGABYTE: f64 = 1_073_741_824.0;
+
+// Average number of days in a month.
+const DAYS_PER_MONTH: f64 = 30.44;
+
+///
+/// Project the monthly cost of each store used by a dataset.
+///
+/// The pricing comes from the store properties: `price_storage` (per GB per
+/// month), `price_put` and `price_get` (per 1,000 requests), and
+/// `price_egress` (per GB downloaded). Missing values are treated as free.
+///
+/// Pack records do not track their size, so the stored bytes are estimated
+/// from the dataset pack size. Only the packs referenced by the snapshots that
+/// the retention policy of the dataset keeps are counted, as those of other
+/// datasets or of snapshots that pruning will remove are not the cost of
+/// keeping this dataset, and the upload rate is averaged over the age of the
+/// oldest of the retained snapshots.
+///
+/// Packs are expected to be removed once they are as old as the oldest of the
+/// retained snapshots. For storage classes with a minimum duration, packs that
+/// are removed sooner are billed for the remainder, and that early deletion
+/// charge is included in the storage cost.
+///
+pub struct EstimateCost {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl EstimateCost {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Collect the snapshots of the dataset, newest first, that would remain
+    // once the retention policy has been applied.
+    fn retained_snapshots(&self, dataset: &Dataset) -> Result<Vec<Snapshot>, Error> {
+        let snapshots = get_snapshots(self.repo.as_ref(), &dataset.id)?;
+        let keep = select_retained(&snapshots, &dataset.retention_policy(), Utc::now());
+        Ok(snapshots
+            .into_iter()
+            .filter(|s| keep.contains(&s.digest))
+            .collect())
+    }
+
+    // Walk the given snapshots to find the packs that hold the content of
+    // their files.
+    fn find_packs(&self, snapshots: &[Snapshot]) -> Result<HashSet<Checksum>, Error> {
+        let mut packs: HashSet<Checksum> = HashSet::new();
+        let mut seen_trees: HashSet<Checksum> = HashSet::new();
+        let mut seen_files: HashSet<Checksum> = HashSet::new();
+        for snapshot in snapshots.iter() {
+            let mut pending = vec![snapshot.tree.clone()];
+            while let Some(tree_digest) = pending.pop() {
+                if !seen_trees.insert(tree_digest.clone()) {
+                    continue;
+                }
+                let tree = self.repo.get_tree(&tree_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingTree).with("digest", &tree_digest)
+                })?;
+                for entry in tree.entries.into_iter() {
+                    match entry.reference {
+                        TreeReference::TREE(subtree) => pending.push(subtree),
+                        TreeReference::FILE(file_digest) => {
+                            if seen_files.insert(file_digest.clone()) {
+                                self.add_file_packs(&file_digest, &mut packs)?;
+                            }
+                        }
+                        TreeReference::LINK(_) | TreeReference::SMALL(_) => (),
+                    }
+                }
+            }
+        }
+        Ok(packs)
+    }
+
+    // Add the packs holding the chunks of the file to the set.
+    fn add_file_packs(
+        &self,
+        digest: &Checksum,
+        packs: &mut HashSet<Checksum>,
+    ) -> Result<(), Error> {
+        let file = self
+            .repo
+            .get_file(digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingFile).with("digest", &digest))?;
+        if file.chunks.len() == 1 {
+            // the "chunk" of a single-chunk file is the pack digest
+            packs.insert(file.chunks[0].1.clone());
+        } else {
+            for (_, chunk_digest) in file.chunks.iter() {
+                let chunk = self.repo.get_chunk(chunk_digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingChunk).with("digest", &chunk_digest)
+                })?;
+                if let Some(pack) = chunk.packfile {
+                    packs.insert(pack);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl super::UseCase<Vec<CostEstimate>, Params> for EstimateCost {
+    fn call(&self, params: Params) -> Result<Vec<CostEstimate>, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        let snapshots = self.retained_snapshots(&dataset)?;
+        let months = history_months(&snapshots);
+        let mut store_packs: HashMap<String, u64> = HashMap::new();
+        for digest in self.find_packs(&snapshots)?.iter() {
+            if let Some(pack) = self.repo.get_pack(digest)? {
+                for location in pack.locations.iter() {
+                    *store_packs.entry(location.store.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut estimates: Vec<CostEstimate> = Vec::new();
+        for store_id in dataset.stores.iter() {
+            if let Some(store) = self.repo.get_store(store_id)? {
+                let pack_count = store_packs.get(&store.id).copied().unwrap_or(0);
+                let stored_bytes = pack_count * dataset.pack_size;
+                let gigabytes = stored_bytes as f64 / GIGABYTE;
+                let monthly_uploads = pack_count as f64 / months;
+                let limits = ObjectSizeLimits::for_stores(&[store.clone()]);
+                let early_months = early_deletion_months(months, limits.min_days);
+                let monthly_gigabytes = monthly_uploads * dataset.pack_size as f64 / GIGABYTE;
+                let storage_cost = (gigabytes + monthly_gigabytes * early_months)
+                    * get_price(&store, "price_storage");
+                let upload_cost = monthly_uploads * get_price(&store, "price_put") / 1000.0;
+                let restore_cost = gigabytes * get_price(&store, "price_egress")
+                    + pack_count as f64 * get_price(&store, "price_get") / 1000.0;
+                estimates.push(CostEstimate {
+                    store_id: store.id,
+                    label: store.label,
+                    pack_count,
+                    stored_bytes,
+                    monthly_uploads,
+                    storage_cost,
+                    upload_cost,
+                    restore_cost,
+                });
+            }
+        }
+        Ok(estimates)
+    }
+}
+
+// Return the number of months (at least one) since the oldest of the
+// snapshots, which are ordered newest first.
+fn history_months(snapshots: &[Snapshot]) -> f64 {
+    let days = snapshots
+        .last()
+        .map(|s| (Utc::now() - s.start_time).num_days() as f64)
+        .unwrap_or(0.0);
+    (days / DAYS_PER_MONTH).max(1.0)
+}
+
+// Return the number of months for which a pack that is kept for the given
+// number of months is billed beyond that, due to the minimum storage duration.
+fn early_deletion_months(months: f64, min_days: u32) -> f64 {
+    (min_days as f64 / DAYS_PER_MONTH - months).max(0.0)
+}
+
+// Retrieve the named price from the store properties, defaulting to zero.
+fn get_price(store: &Store, name: &str) -> f64 {
+    store
+        .properties
+        .get(name)
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(0.0)
+}
+
+pub struct Params {
+    /// Identifier of the dataset for which to estimate costs.
+    dataset_id: String,
+}
+
+impl Params {
+    pub fn new(dataset_id: String) -> Self {
+        Self { dataset_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.dataset_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{
+        Chunk, Dataset, File, FileCounts, Pack, PackLocation, Snapshot, StoreType, Tree, TreeEntry,
+    };
+    use crate::domain::repositories::MockRecordRepository;
+    use mockall::predicate::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_estimate_cost_ok() {
+        // arrange
+        let mut dataset = Dataset::with_pack_size(Path::new("/home/planet"), 1_073_741_824);
+        dataset.add_store("cafebabe");
+        let dataset_id = dataset.id.clone();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("price_storage".to_owned(), "0.02".to_owned());
+        properties.insert("price_put".to_owned(), "0.005".to_owned());
+        properties.insert("price_get".to_owned(), "0.0004".to_owned());
+        properties.insert("price_egress".to_owned(), "0.09".to_owned());
+        let store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::MINIO,
+            label: "pretend S3".to_owned(),
+            properties,
+        };
+        // one file in a pack of its own, another in chunks that share a pack
+        let pack1 = Checksum::SHA1("cafebabe1".into());
+        let pack2 = Checksum::SHA1("cafebabe2".into());
+        let file1 = File::new(Checksum::SHA1("f1".into()), 10, vec![(0, pack1.clone())]);
+        let chunks = vec![
+            (0, Checksum::SHA1("c1".into())),
+            (5, Checksum::SHA1("c2".into())),
+        ];
+        let file2 = File::new(Checksum::SHA1("f2".into()), 10, chunks);
+        let tree = Tree::new(
+            vec![
+                TreeEntry::new(Path::new("one"), TreeReference::FILE(file1.digest.clone())),
+                TreeEntry::new(Path::new("two"), TreeReference::FILE(file2.digest.clone())),
+            ],
+            2,
+        );
+        let snapshot = Snapshot::new(None, tree.digest.clone(), FileCounts::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .with(eq(dataset_id.clone()))
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(snapshot_digest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file().returning(move |digest| {
+            if digest == &file1.digest {
+                Ok(Some(file1.clone()))
+            } else {
+                Ok(Some(file2.clone()))
+            }
+        });
+        let chunk_pack = pack2.clone();
+        mock.expect_get_chunk().returning(move |digest| {
+            Ok(Some(
+                Chunk::new(digest.clone(), 0, 5).packfile(chunk_pack.clone()),
+            ))
+        });
+        mock.expect_get_store()
+            .with(eq("cafebabe"))
+            .returning(move |_| Ok(Some(store.clone())));
+        // packs of other datasets are never looked up
+        mock.expect_get_pack().times(2).returning(|digest| {
+            let coords = vec![
+                PackLocation::new("cafebabe", "bucket1", "object1"),
+                PackLocation::new("deadbeef", "bucket1", "object1"),
+            ];
+            Ok(Some(Pack::new(digest.clone(), coords)))
+        });
+        // act
+        let usecase = EstimateCost::new(Box::new(mock));
+        let params = Params::new(dataset_id);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let estimates = result.unwrap();
+        assert_eq!(estimates.len(), 1);
+        let estimate = &estimates[0];
+        assert_eq!(estimate.pack_count, 2);
+        assert_eq!(estimate.stored_bytes, 2_147_483_648);
+        assert!((estimate.storage_cost - 0.04).abs() < 0.0001);
+        assert!((estimate.upload_cost - 0.00001).abs() < 0.0001);
+        assert!((estimate.restore_cost - 0.1800008).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_estimate_cost_retention() {
+        // arrange
+        let mut dataset = Dataset::with_pack_size(Path::new("/home/planet"), 1_073_741_824);
+        dataset.add_store("cafebabe");
+        dataset
+            .properties
+            .insert("retain_count".to_owned(), "1".to_owned());
+        let dataset_id = dataset.id.clone();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("price_storage".to_owned(), "0.02".to_owned());
+        let store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::MINIO,
+            label: "pretend S3".to_owned(),
+            properties,
+        };
+        // the older snapshot will be pruned, and its pack along with it
+        let old_pack = Checksum::SHA1("cafebabe1".into());
+        let new_pack = Checksum::SHA1("cafebabe2".into());
+        let old_file = File::new(Checksum::SHA1("f1".into()), 10, vec![(0, old_pack)]);
+        let new_file = File::new(Checksum::SHA1("f2".into()), 10, vec![(0, new_pack.clone())]);
+        let old_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("one"),
+                TreeReference::FILE(old_file.digest.clone()),
+            )],
+            1,
+        );
+        let new_tree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("two"),
+                TreeReference::FILE(new_file.digest.clone()),
+            )],
+            1,
+        );
+        let mut old_snapshot = Snapshot::new(None, old_tree.digest.clone(), Default::default());
+        old_snapshot.set_start_time(Utc::now() - chrono::TimeDelta::days(90));
+        let new_snapshot = Snapshot::new(
+            Some(old_snapshot.digest.clone()),
+            new_tree.digest.clone(),
+            Default::default(),
+        );
+        let latest = new_snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .with(eq(dataset_id.clone()))
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot().returning(move |digest| {
+            if digest == &old_snapshot.digest {
+                Ok(Some(old_snapshot.clone()))
+            } else {
+                Ok(Some(new_snapshot.clone()))
+            }
+        });
+        mock.expect_get_tree()
+            .with(eq(new_tree.digest.clone()))
+            .returning(move |_| Ok(Some(new_tree.clone())));
+        mock.expect_get_file()
+            .with(eq(new_file.digest.clone()))
+            .returning(move |_| Ok(Some(new_file.clone())));
+        mock.expect_get_store()
+            .with(eq("cafebabe"))
+            .returning(move |_| Ok(Some(store.clone())));
+        mock.expect_get_pack()
+            .with(eq(new_pack))
+            .times(1)
+            .returning(|digest| {
+                let coords = vec![PackLocation::new("cafebabe", "bucket1", "object1")];
+                Ok(Some(Pack::new(digest.clone(), coords)))
+            });
+        // act
+        let usecase = EstimateCost::new(Box::new(mock));
+        let params = Params::new(dataset_id);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let estimates = result.unwrap();
+        assert_eq!(estimates.len(), 1);
+        let estimate = &estimates[0];
+        assert_eq!(estimate.pack_count, 1);
+        assert_eq!(estimate.stored_bytes, 1_073_741_824);
+        assert!((estimate.storage_cost - 0.02).abs() < 0.0001);
+        // the upload rate is based on the age of the retained snapshot
+        assert!((estimate.monthly_uploads - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_early_deletion_months() {
+        assert_eq!(early_deletion_months(1.0, 0), 0.0);
+        assert_eq!(early_deletion_months(12.0, 180), 0.0);
+        let months = early_deletion_months(1.0, 180);
+        assert!((months - 4.9133).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_estimate_cost_no_dataset() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        // act
+        let usecase = EstimateCost::new(Box::new(mock));
+        let params = Params::new("nosuchdataset".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such dataset"));
+    }
+}
@@ -0,0 +1,318 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, FileVersion, Message, MessageCode, Snapshot, TreeReference,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum number of versions to return when no limit is given.
+const DEFAULT_LIMIT: usize = 100;
+
+///
+/// Walk the snapshots of a dataset and collect each distinct version of the
+/// file at the given path, newest first.
+///
+/// A version is distinct if either its content or its modification time
+/// differs from that of the version in the preceding snapshot. Snapshots in
+/// which the path does not exist, or is not a file, are skipped.
+///
+pub struct FileHistory {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl FileHistory {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Collect the snapshots of the dataset, oldest first.
+    fn load_snapshots(&self, dataset_id: &str) -> Result<Vec<Snapshot>, Error> {
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        let mut next = self.repo.get_latest_snapshot(dataset_id)?;
+        while let Some(digest) = next {
+            let snapshot = self.repo.get_snapshot(&digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingSnapshot).with("digest", &digest)
+            })?;
+            next = snapshot.parent.clone();
+            snapshots.push(snapshot);
+        }
+        snapshots.reverse();
+        Ok(snapshots)
+    }
+
+    // Find the file at the given path within the snapshot, returning the
+    // version with the snapshot-specific values filled in.
+    fn find_version(
+        &self,
+        snapshot: &Snapshot,
+        path: &[String],
+    ) -> Result<Option<FileVersion>, Error> {
+        let (name, parents) = match path.split_last() {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut tree_digest = snapshot.tree.clone();
+        for parent in parents.iter() {
+            let tree = self.repo.get_tree(&tree_digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingTree).with("digest", &tree_digest)
+            })?;
+            let subtree = tree.entries.iter().find_map(|e| match &e.reference {
+                TreeReference::TREE(digest) if &e.name == parent => Some(digest.clone()),
+                _ => None,
+            });
+            match subtree {
+                Some(digest) => tree_digest = digest,
+                None => return Ok(None),
+            }
+        }
+        let tree = self
+            .repo
+            .get_tree(&tree_digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingTree).with("digest", &tree_digest))?;
+        let entry = match tree.entries.iter().find(|e| &e.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let (size, digest) = match &entry.reference {
+            TreeReference::FILE(digest) => {
+                let file = self.repo.get_file(digest)?.ok_or_else(|| {
+                    Message::new(MessageCode::MissingFile).with("digest", &digest)
+                })?;
+                (file.length, Some(digest.clone()))
+            }
+            TreeReference::SMALL(contents) => (contents.len() as u64, None),
+            _ => return Ok(None),
+        };
+        Ok(Some(FileVersion {
+            snapshot: snapshot.digest.clone(),
+            snapshot_time: snapshot.start_time,
+            tree: tree_digest,
+            entry: entry.name.clone(),
+            modified: entry.mtime,
+            size,
+            digest,
+            changed: true,
+        }))
+    }
+}
+
+impl super::UseCase<Vec<FileVersion>, Params> for FileHistory {
+    fn call(&self, params: Params) -> Result<Vec<FileVersion>, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        let path = split_path(&params.path, &dataset.basepath)?;
+        if path.is_empty() {
+            return Err(anyhow!("path must name a file"));
+        }
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut versions: Vec<FileVersion> = Vec::new();
+        // track the content of the previous version, including small files,
+        // to determine if the content has changed
+        let mut previous: Option<(Option<Checksum>, u64)> = None;
+        for snapshot in self.load_snapshots(&params.dataset_id)? {
+            if let Some(mut version) = self.find_version(&snapshot, &path)? {
+                let content = (version.digest.clone(), version.size);
+                if let Some(last) = versions.last() {
+                    let same_content = previous.as_ref() == Some(&content);
+                    if same_content && last.modified == version.modified {
+                        continue;
+                    }
+                    version.changed = !same_content;
+                }
+                previous = Some(content);
+                versions.push(version);
+            }
+        }
+        versions.reverse();
+        versions.truncate(limit);
+        Ok(versions)
+    }
+}
+
+// Split the path into its components, relative to the base path of the
+// dataset if the path is absolute.
+pub(super) fn split_path(path: &Path, basepath: &Path) -> Result<Vec<String>, Error> {
+    let relative = if path.is_absolute() {
+        path.strip_prefix(basepath).map_err(|_| {
+            anyhow!(format!(
+                "path {} is not within {}",
+                path.display(),
+                basepath.display()
+            ))
+        })?
+    } else {
+        path
+    };
+    let mut names: Vec<String> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => names.push(name.to_string_lossy().into_owned()),
+            Component::CurDir => (),
+            _ => return Err(anyhow!(format!("invalid path: {}", path.display()))),
+        }
+    }
+    Ok(names)
+}
+
+pub struct Params {
+    /// Identifier of the dataset.
+    dataset_id: String,
+    /// Path of the file, either absolute or relative to the dataset base path.
+    path: PathBuf,
+    /// Maximum number of versions to return.
+    limit: Option<usize>,
+}
+
+impl Params {
+    pub fn new<P: Into<PathBuf>>(dataset_id: String, path: P, limit: Option<usize>) -> Self {
+        Self {
+            dataset_id,
+            path: path.into(),
+            limit,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.dataset_id, self.path.display())
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id && self.path == other.path
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Dataset, File, FileCounts, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::{TimeZone, Utc};
+    use std::collections::HashMap;
+
+    // Build a snapshot whose tree contains `docs/notes.txt` with the given
+    // reference and modification time (in seconds).
+    fn make_snapshot(
+        parent: Option<Checksum>,
+        reference: TreeReference,
+        mtime: i64,
+        trees: &mut HashMap<Checksum, Tree>,
+    ) -> Snapshot {
+        let mut file_entry = TreeEntry::new(Path::new("notes.txt"), reference);
+        file_entry.mtime = Utc.timestamp_opt(mtime, 0).unwrap();
+        let subtree = Tree::new(vec![file_entry], 1);
+        let dir_entry = TreeEntry::new(
+            Path::new("docs"),
+            TreeReference::TREE(subtree.digest.clone()),
+        );
+        let root = Tree::new(vec![dir_entry], 1);
+        let mut snapshot = Snapshot::new(parent, root.digest.clone(), FileCounts::default());
+        // ensure each snapshot is unique regardless of the clock resolution
+        snapshot.start_time = Utc.timestamp_opt(mtime + 60, 0).unwrap();
+        snapshot.digest = Checksum::BLAKE3(format!("{:040}", mtime));
+        trees.insert(subtree.digest.clone(), subtree);
+        trees.insert(root.digest.clone(), root);
+        snapshot
+    }
+
+    #[test]
+    fn test_file_history_ok() {
+        // arrange
+        let one = Checksum::BLAKE3("1111".into());
+        let two = Checksum::BLAKE3("2222".into());
+        let mut trees: HashMap<Checksum, Tree> = HashMap::new();
+        let s1 = make_snapshot(None, TreeReference::FILE(one.clone()), 1000, &mut trees);
+        // identical to the first, will be skipped
+        let s2 = make_snapshot(
+            Some(s1.digest.clone()),
+            TreeReference::FILE(one.clone()),
+            1000,
+            &mut trees,
+        );
+        // touched but not changed
+        let s3 = make_snapshot(
+            Some(s2.digest.clone()),
+            TreeReference::FILE(one.clone()),
+            2000,
+            &mut trees,
+        );
+        // content changed
+        let s4 = make_snapshot(
+            Some(s3.digest.clone()),
+            TreeReference::FILE(two.clone()),
+            3000,
+            &mut trees,
+        );
+        let latest = s4.digest.clone();
+        let snapshots: HashMap<Checksum, Snapshot> = vec![s1.clone(), s2, s3.clone(), s4.clone()]
+            .into_iter()
+            .map(|s| (s.digest.clone(), s))
+            .collect();
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".into();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        mock.expect_get_file().returning(|digest| {
+            let length = if digest.to_string().ends_with("1111") {
+                100
+            } else {
+                200
+            };
+            Ok(Some(File::new(digest.clone(), length, vec![])))
+        });
+        // act
+        let usecase = FileHistory::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), "/home/planet/docs/notes.txt", None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let versions = result.unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0].snapshot, s4.digest);
+        assert_eq!(versions[0].digest, Some(two));
+        assert_eq!(versions[0].size, 200);
+        assert!(versions[0].changed);
+        assert_eq!(versions[1].snapshot, s3.digest);
+        assert_eq!(versions[1].digest, Some(one.clone()));
+        assert!(!versions[1].changed);
+        assert_eq!(versions[2].snapshot, s1.digest);
+        assert_eq!(versions[2].size, 100);
+        assert_eq!(versions[2].entry, "notes.txt");
+        assert!(versions[2].changed);
+    }
+
+    #[test]
+    fn test_file_history_outside_basepath() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
+        // act
+        let usecase = FileHistory::new(Box::new(mock));
+        let params = Params::new("cafebabe".into(), "/etc/passwd", Some(10));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("is not within"));
+    }
+}
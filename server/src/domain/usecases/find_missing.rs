@@ -1,9 +1,9 @@
 //
 // Copyright (c) 2021 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, Pack};
+use crate::domain::entities::{Checksum, Message, MessageCode, Pack};
 use crate::domain::repositories::RecordRepository;
-use anyhow::{anyhow, Error};
+use anyhow::Error;
 use log::info;
 use std::cmp;
 use std::collections::HashSet;
@@ -50,7 +50,9 @@ impl super::UseCase<Vec<Pack>, Params> for FindMissingPacks {
             );
             Ok(missing_packs)
         } else {
-            Err(anyhow!(format!("no such store: {}", params.store_id)))
+            Err(Message::new(MessageCode::NoSuchStore)
+                .with("id", &params.store_id)
+                .into())
         }
     }
 }
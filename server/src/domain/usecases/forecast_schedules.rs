@@ -0,0 +1,572 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::schedule::{Schedule, TimeRange};
+use crate::domain::entities::{Dataset, ForecastRun, ScheduleConflict, ScheduleForecast};
+use crate::domain::managers::backup::scheduler::compute_stop_time;
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::{DateTime, Datelike, NaiveDate, TimeDelta, Utc};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Number of days forecast when the caller does not specify.
+pub const DEFAULT_DAYS: u32 = 7;
+
+/// Largest number of days that will be forecast at one time.
+pub const MAX_DAYS: u32 = 31;
+
+// Number of recent backups whose durations are averaged.
+const HISTORY_SIZE: usize = 5;
+
+// Duration in seconds assumed for a dataset without any finished backups.
+const DEFAULT_DURATION: i64 = 3_600;
+
+// The scheduler checks for datasets that are due every five minutes.
+const STEP_SECS: i64 = 300;
+
+///
+/// Simulate the scheduler over the coming days, producing the backups that
+/// are expected to run for each dataset and the times at which the backups
+/// of different datasets overlap.
+///
+/// The duration of each backup is the average of the recent backups of that
+/// dataset that finished without being paused. A backup is cut short at the
+/// end of the time range of its schedule, or its maximum runtime, as the
+/// scheduler would pause it. Since the scheduler starts every dataset that is
+/// due without waiting for the others, any overlap is a conflict, and more so
+/// when the datasets upload to the same stores.
+///
+/// Stores that have reached their monthly transfer cap are left out of the
+/// backups until the start of the next month, and those that have reached
+/// their quota for the rest of the forecast; a backup with no other stores is
+/// deferred. Likewise, a backup does not start during the maintenance window
+/// if pruning may have locked the packs.
+///
+pub struct ForecastSchedules {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl ForecastSchedules {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Return the average duration of the recent backups of the dataset that
+    // ran to completion without pausing, along with their number.
+    fn estimate_duration(&self, dataset: &Dataset) -> Result<(TimeDelta, usize), Error> {
+        let mut durations: Vec<TimeDelta> = Vec::new();
+        let mut visited = 0;
+        let mut next = self.repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            // paused backups do not count, but do not search forever
+            if durations.len() >= HISTORY_SIZE || visited >= HISTORY_SIZE * 4 {
+                break;
+            }
+            visited += 1;
+            let snapshot = match self.repo.get_snapshot(&digest)? {
+                Some(snapshot) => snapshot,
+                None => break,
+            };
+            if let (Some(end_time), None) = (snapshot.end_time, snapshot.paused) {
+                if end_time > snapshot.start_time {
+                    durations.push(end_time - snapshot.start_time);
+                }
+            }
+            next = snapshot.parent;
+        }
+        if durations.is_empty() {
+            return Ok((TimeDelta::seconds(DEFAULT_DURATION), 0));
+        }
+        let total: TimeDelta = durations.iter().sum();
+        Ok((total / durations.len() as i32, durations.len()))
+    }
+
+    // Return the stores of the datasets that have reached their monthly
+    // transfer cap or their quota, with the time at which each is expected to
+    // be available again, if ever.
+    fn capped_stores(
+        &self,
+        datasets: &[Dataset],
+        start: DateTime<Utc>,
+    ) -> Result<HashMap<String, Option<DateTime<Utc>>>, Error> {
+        let mut capped: HashMap<String, Option<DateTime<Utc>>> = HashMap::new();
+        let store_ids: HashSet<&String> = datasets.iter().flat_map(|d| d.stores.iter()).collect();
+        for store_id in store_ids {
+            let Some(store) = self.repo.get_store(store_id)? else {
+                continue;
+            };
+            if let Some(quota) = store.quota() {
+                if quota.is_reached(&self.repo.get_store_usage(store_id)?) {
+                    capped.insert(store_id.to_owned(), None);
+                    continue;
+                }
+            }
+            if let Some(cap) = store.monthly_cap() {
+                if self.repo.get_bandwidth_usage(store_id)?.total() >= cap {
+                    capped.insert(store_id.to_owned(), Some(next_month(start)));
+                }
+            }
+        }
+        Ok(capped)
+    }
+
+    // Return the time from which the schedules of the dataset are due, the
+    // same as the scheduler does.
+    fn last_end_time(&self, dataset: &Dataset) -> Result<Option<DateTime<Utc>>, Error> {
+        if let Some(digest) = self.repo.get_latest_snapshot(&dataset.id)? {
+            if let Some(snapshot) = self.repo.get_snapshot(&digest)? {
+                return Ok(snapshot.end_time.or(snapshot.paused));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl super::UseCase<ScheduleForecast, Params> for ForecastSchedules {
+    fn call(&self, params: Params) -> Result<ScheduleForecast, Error> {
+        let start = params.start;
+        let end = start + TimeDelta::days(params.days.clamp(1, MAX_DAYS) as i64);
+        let datasets: Vec<Dataset> = self
+            .repo
+            .get_datasets()?
+            .into_iter()
+            .filter(|d| d.archived.is_none() && !d.schedules.is_empty())
+            .collect();
+        let holdups = Holdups {
+            capped: self.capped_stores(&datasets, start)?,
+            maintenance: params.maintenance,
+            schedules: datasets.iter().flat_map(|d| d.schedules.clone()).collect(),
+        };
+        let mut runs: Vec<ForecastRun> = Vec::new();
+        for dataset in datasets.iter() {
+            let (duration, history) = self.estimate_duration(dataset)?;
+            let last_end = self.last_end_time(dataset)?;
+            runs.extend(simulate(
+                dataset, last_end, duration, history, start, end, &holdups,
+            ));
+        }
+        runs.sort_by(|a, b| a.start.cmp(&b.start).then(a.dataset_id.cmp(&b.dataset_id)));
+        let conflicts = find_conflicts(&runs, &datasets);
+        Ok(ScheduleForecast {
+            start,
+            end,
+            runs,
+            conflicts,
+        })
+    }
+}
+
+// Conditions apart from the schedules of a dataset that keep its backups from
+// starting, or from uploading to all of its stores.
+struct Holdups {
+    // Stores that have reached their monthly transfer cap or quota, and the
+    // time at which each is available again, if ever.
+    capped: HashMap<String, Option<DateTime<Utc>>>,
+    // Maintenance window in which pruning may lock the packs.
+    maintenance: Option<TimeRange>,
+    // Schedules of every dataset, whose time ranges keep maintenance tasks
+    // from starting.
+    schedules: Vec<Schedule>,
+}
+
+impl Holdups {
+    // Return the stores of the dataset that are left out of a backup started
+    // at the given time.
+    fn deferred_stores(&self, dataset: &Dataset, now: DateTime<Utc>) -> Vec<String> {
+        dataset
+            .stores
+            .iter()
+            .filter(|s| match self.capped.get(*s) {
+                Some(until) => until.map_or(true, |u| now < u),
+                None => false,
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Return true if pruning may be holding the packs at the given time, as
+    // the maintenance window is open and no time range of a backup schedule
+    // is in effect.
+    fn packs_locked(&self, now: DateTime<Utc>) -> bool {
+        match self.maintenance.as_ref() {
+            Some(window) if window.is_within(now) => !self
+                .schedules
+                .iter()
+                .any(|s| s.stop_time(now).is_some() && s.within_range(now)),
+            _ => false,
+        }
+    }
+}
+
+// Return the start of the calendar month after the one in which the given time
+// falls, when the transfer caps of the stores are reset.
+fn next_month(time: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = if time.month() == 12 {
+        (time.year() + 1, 1)
+    } else {
+        (time.year(), time.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|d| d.and_utc())
+        .unwrap_or(time)
+}
+
+// Step through the forecast period as the scheduler would, starting a backup
+// whenever one of the schedules of the dataset comes due.
+fn simulate(
+    dataset: &Dataset,
+    last_end: Option<DateTime<Utc>>,
+    duration: TimeDelta,
+    history: usize,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    holdups: &Holdups,
+) -> Vec<ForecastRun> {
+    let step = TimeDelta::seconds(STEP_SECS);
+    let mut runs: Vec<ForecastRun> = Vec::new();
+    let mut last_end = last_end;
+    let mut now = start;
+    while now < end {
+        let due = dataset.schedules.iter().find(|s| match last_end {
+            Some(et) => s.past_due_at(et, now) && s.within_range(now),
+            None => s.within_range(now),
+        });
+        let schedule = match due {
+            Some(schedule) => schedule,
+            None => {
+                now += step;
+                continue;
+            }
+        };
+        // the backup is deferred until the next check, and the one after that
+        let deferred_stores = holdups.deferred_stores(dataset, now);
+        let all_deferred =
+            !dataset.stores.is_empty() && deferred_stores.len() == dataset.stores.len();
+        if all_deferred || holdups.packs_locked(now) {
+            now += step;
+            continue;
+        }
+        let finish = now + duration;
+        let (run_end, overrun) = match compute_stop_time(dataset, schedule, now) {
+            Some(stop) if stop < finish => (stop, true),
+            _ => (finish, false),
+        };
+        runs.push(ForecastRun {
+            dataset_id: dataset.id.clone(),
+            start: now,
+            end: run_end,
+            history,
+            overrun,
+            deferred_stores,
+        });
+        last_end = Some(run_end);
+        // resume checking at the first interval after the backup ends
+        let steps = ((run_end - now).num_seconds() + STEP_SECS - 1) / STEP_SECS;
+        now += step * steps.max(1) as i32;
+    }
+    runs
+}
+
+// Find the pairs of backups of different datasets that overlap, given the
+// backups ordered by start time. The stores left out of either backup are not
+// counted among those they share.
+fn find_conflicts(runs: &[ForecastRun], datasets: &[Dataset]) -> Vec<ScheduleConflict> {
+    let stores: HashMap<&str, &Vec<String>> = datasets
+        .iter()
+        .map(|d| (d.id.as_str(), &d.stores))
+        .collect();
+    let mut conflicts: Vec<ScheduleConflict> = Vec::new();
+    for (index, first) in runs.iter().enumerate() {
+        let overlapping = runs[index + 1..]
+            .iter()
+            .take_while(|second| second.start < first.end);
+        for second in overlapping {
+            if second.dataset_id == first.dataset_id {
+                continue;
+            }
+            let shared_stores: Vec<String> = match (
+                stores.get(first.dataset_id.as_str()),
+                stores.get(second.dataset_id.as_str()),
+            ) {
+                (Some(these), Some(those)) => these
+                    .iter()
+                    .filter(|s| those.contains(s))
+                    .filter(|s| {
+                        !first.deferred_stores.contains(s) && !second.deferred_stores.contains(s)
+                    })
+                    .cloned()
+                    .collect(),
+                _ => vec![],
+            };
+            conflicts.push(ScheduleConflict {
+                first: first.dataset_id.clone(),
+                second: second.dataset_id.clone(),
+                start: second.start,
+                end: first.end.min(second.end),
+                shared_stores,
+            });
+        }
+    }
+    conflicts.sort_by(|a, b| a.start.cmp(&b.start));
+    conflicts
+}
+
+pub struct Params {
+    /// Number of days to forecast.
+    days: u32,
+    /// Time from which to forecast, typically the current time.
+    start: DateTime<Utc>,
+    /// Maintenance window in which pruning may lock the packs, if any.
+    maintenance: Option<TimeRange>,
+}
+
+impl Params {
+    pub fn new(days: Option<u32>, start: DateTime<Utc>) -> Self {
+        Self {
+            days: days.unwrap_or(DEFAULT_DAYS),
+            start,
+            maintenance: None,
+        }
+    }
+
+    pub fn maintenance(mut self, maintenance: Option<TimeRange>) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.days, self.start)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.days == other.days && self.start == other.start
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{
+        BandwidthUsage, Checksum, FileCounts, Snapshot, Store, StoreType,
+    };
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::TimeZone;
+    use std::path::Path;
+
+    fn make_snapshot(parent: Option<Checksum>, start_time: DateTime<Utc>, hours: i64) -> Snapshot {
+        let tree = Checksum::SHA1("811ea7199968a119eeba4b65ace06cc7f835c497".to_owned());
+        let mut snapshot = Snapshot::new(parent, tree, FileCounts::default());
+        snapshot.start_time = start_time;
+        snapshot.end_time = Some(start_time + TimeDelta::hours(hours));
+        snapshot
+    }
+
+    #[test]
+    fn test_forecast_schedules() {
+        // arrange
+        let mut nightly = Dataset::new(Path::new("/home/planet"));
+        nightly.id = "nightly".to_owned();
+        nightly.schedules = vec![Schedule::Daily(Some(TimeRange::new(22, 0, 6, 0)))];
+        nightly.stores = vec!["store1".to_owned(), "store2".to_owned()];
+        let mut late = Dataset::new(Path::new("/home/music"));
+        late.id = "late".to_owned();
+        late.schedules = vec![Schedule::Daily(Some(TimeRange::new(23, 0, 1, 0)))];
+        late.stores = vec!["store2".to_owned()];
+        late.properties
+            .insert("max_runtime".to_owned(), "30".to_owned());
+        let mut archived = Dataset::new(Path::new("/home/photos"));
+        archived.schedules = vec![Schedule::Hourly];
+        archived.archived = Some(Utc::now());
+        let older = make_snapshot(None, Utc.with_ymd_and_hms(2024, 3, 2, 22, 0, 0).unwrap(), 4);
+        let newer = make_snapshot(
+            Some(older.digest.clone()),
+            Utc.with_ymd_and_hms(2024, 3, 3, 22, 0, 0).unwrap(),
+            2,
+        );
+        let latest = newer.digest.clone();
+        let snapshots = vec![older, newer];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![nightly.clone(), late.clone(), archived.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |id| match id {
+                "nightly" => Ok(Some(latest.clone())),
+                _ => Ok(None),
+            });
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.iter().find(|s| &s.digest == digest).cloned()));
+        mock.expect_get_store().returning(|_| Ok(None));
+        // act
+        let usecase = ForecastSchedules::new(Box::new(mock));
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap();
+        let params = Params::new(Some(3), start);
+        let result = usecase.call(params);
+        // assert
+        let forecast = result.unwrap();
+        assert_eq!(forecast.end, start + TimeDelta::days(3));
+        assert_eq!(forecast.runs.len(), 6);
+        let first = &forecast.runs[0];
+        assert_eq!(first.dataset_id, "nightly");
+        assert_eq!(
+            first.start,
+            Utc.with_ymd_and_hms(2024, 3, 4, 22, 0, 0).unwrap()
+        );
+        assert_eq!(
+            first.end,
+            Utc.with_ymd_and_hms(2024, 3, 5, 1, 0, 0).unwrap()
+        );
+        assert_eq!(first.history, 2);
+        assert!(!first.overrun);
+        let second = &forecast.runs[1];
+        assert_eq!(second.dataset_id, "late");
+        assert_eq!(
+            second.start,
+            Utc.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap()
+        );
+        assert_eq!(
+            second.end,
+            Utc.with_ymd_and_hms(2024, 3, 4, 23, 30, 0).unwrap()
+        );
+        assert_eq!(second.history, 0);
+        assert!(second.overrun);
+        assert_eq!(forecast.conflicts.len(), 3);
+        for (day, conflict) in forecast.conflicts.iter().enumerate() {
+            let day = day as i64;
+            assert_eq!(conflict.first, "nightly");
+            assert_eq!(conflict.second, "late");
+            assert_eq!(conflict.start, second.start + TimeDelta::days(day));
+            assert_eq!(conflict.end, second.end + TimeDelta::days(day));
+            assert_eq!(conflict.shared_stores, vec!["store2".to_owned()]);
+        }
+    }
+
+    #[test]
+    fn test_forecast_schedules_no_conflicts() {
+        // arrange
+        let mut hourly = Dataset::new(Path::new("/home/planet"));
+        hourly.id = "hourly".to_owned();
+        hourly.schedules = vec![Schedule::Hourly];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![hourly.clone()]));
+        mock.expect_get_latest_snapshot().returning(|_| Ok(None));
+        // act
+        let usecase = ForecastSchedules::new(Box::new(mock));
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap();
+        let params = Params::new(Some(1_000), start);
+        let result = usecase.call(params);
+        // assert
+        let forecast = result.unwrap();
+        assert_eq!(forecast.end, start + TimeDelta::days(MAX_DAYS as i64));
+        // a run of an hour, then due again an hour after that
+        assert_eq!(
+            forecast.runs[1].start - forecast.runs[0].start,
+            TimeDelta::minutes(125)
+        );
+        assert!(forecast.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_forecast_schedules_capped() {
+        // arrange
+        let mut metered = Dataset::new(Path::new("/home/planet"));
+        metered.id = "metered".to_owned();
+        metered.schedules = vec![Schedule::Daily(None)];
+        metered.stores = vec!["capped".to_owned()];
+        let mut partial = Dataset::new(Path::new("/home/music"));
+        partial.id = "partial".to_owned();
+        partial.schedules = vec![Schedule::Daily(None)];
+        partial.stores = vec!["capped".to_owned(), "open".to_owned()];
+        let mut other = partial.clone();
+        other.id = "other".to_owned();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![metered.clone(), partial.clone(), other.clone()]));
+        mock.expect_get_latest_snapshot().returning(|_| Ok(None));
+        mock.expect_get_store().returning(|id| {
+            let mut properties: HashMap<String, String> = HashMap::new();
+            properties.insert("monthly_cap".to_owned(), "1000".to_owned());
+            Ok(Some(Store {
+                id: id.to_owned(),
+                store_type: StoreType::LOCAL,
+                label: id.to_owned(),
+                properties,
+            }))
+        });
+        mock.expect_get_bandwidth_usage().returning(|id| {
+            let mut usage = BandwidthUsage::new("2024-03");
+            usage.uploaded = if id == "capped" { 1000 } else { 10 };
+            Ok(usage)
+        });
+        // act
+        let usecase = ForecastSchedules::new(Box::new(mock));
+        let start = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+        let params = Params::new(Some(1), start);
+        let result = usecase.call(params);
+        // assert
+        let forecast = result.unwrap();
+        assert_eq!(forecast.runs.len(), 3);
+        // datasets with other stores run without the capped store
+        assert_eq!(forecast.runs[0].dataset_id, "other");
+        assert_eq!(forecast.runs[1].dataset_id, "partial");
+        assert_eq!(forecast.runs[1].start, start);
+        assert_eq!(forecast.runs[1].deferred_stores, vec!["capped".to_owned()]);
+        // one without any waits for the cap to be reset
+        assert_eq!(forecast.runs[2].dataset_id, "metered");
+        assert_eq!(
+            forecast.runs[2].start,
+            Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap()
+        );
+        assert!(forecast.runs[2].deferred_stores.is_empty());
+        // the capped store is not counted as shared
+        assert_eq!(forecast.conflicts.len(), 1);
+        assert_eq!(forecast.conflicts[0].shared_stores, vec!["open".to_owned()]);
+    }
+
+    #[test]
+    fn test_forecast_schedules_maintenance() {
+        // arrange
+        let mut hourly = Dataset::new(Path::new("/home/planet"));
+        hourly.id = "hourly".to_owned();
+        hourly.schedules = vec![Schedule::Hourly];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![hourly.clone()]));
+        mock.expect_get_latest_snapshot().returning(|_| Ok(None));
+        // act
+        let usecase = ForecastSchedules::new(Box::new(mock));
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap();
+        let window = TimeRange::new(2, 0, 4, 0);
+        let params = Params::new(Some(1), start).maintenance(Some(window.clone()));
+        let result = usecase.call(params);
+        // assert
+        let forecast = result.unwrap();
+        assert_eq!(forecast.runs[0].start, start);
+        // due again at 02:05, but the packs may be locked until 04:00
+        assert_eq!(
+            forecast.runs[1].start,
+            Utc.with_ymd_and_hms(2024, 3, 4, 4, 0, 0).unwrap()
+        );
+        assert!(forecast.runs.iter().all(|r| !window.is_within(r.start)));
+    }
+
+    #[test]
+    fn test_next_month() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+        let expected = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+        assert_eq!(next_month(time), expected);
+        let time = Utc.with_ymd_and_hms(2024, 12, 1, 0, 0, 0).unwrap();
+        let expected = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(next_month(time), expected);
+    }
+}
@@ -0,0 +1,151 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Event, EventKind};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use std::cmp;
+use std::fmt;
+
+// Number of events returned if no limit is given.
+const DEFAULT_LIMIT: usize = 100;
+
+///
+/// Retrieve the events from the event log, oldest first, that occurred after
+/// the given time and are of the given kinds.
+///
+pub struct GetEvents {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl GetEvents {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Vec<Event>, Params> for GetEvents {
+    fn call(&self, params: Params) -> Result<Vec<Event>, Error> {
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        let events: Vec<Event> = self
+            .repo
+            .get_events()?
+            .into_iter()
+            .filter(|e| params.after.map_or(true, |after| e.time > after))
+            .filter(|e| params.kinds.is_empty() || params.kinds.contains(&e.kind))
+            .take(limit)
+            .collect();
+        Ok(events)
+    }
+}
+
+pub struct Params {
+    /// Only events that occurred after this time are returned.
+    after: Option<DateTime<Utc>>,
+    /// Only events of these kinds are returned, unless empty.
+    kinds: Vec<EventKind>,
+    /// Maximum number of events to return.
+    limit: Option<usize>,
+}
+
+impl Params {
+    pub fn new(after: Option<DateTime<Utc>>, kinds: Vec<EventKind>, limit: Option<usize>) -> Self {
+        Self {
+            after,
+            kinds,
+            limit,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({:?}, {:?})", self.after, self.kinds)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.after == other.after && self.kinds == other.kinds && self.limit == other.limit
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::repositories::MockRecordRepository;
+    use anyhow::anyhow;
+    use chrono::TimeDelta;
+
+    fn make_events() -> Vec<Event> {
+        let now = Utc::now();
+        let mut events: Vec<Event> = Vec::new();
+        for (hours, kind) in [
+            (4, EventKind::BackupStarted),
+            (3, EventKind::PackUploaded),
+            (2, EventKind::BackupFinished),
+            (1, EventKind::StoreUpdated),
+        ] {
+            let mut event = Event::new(kind, "cafebabe");
+            event.time = now - TimeDelta::hours(hours);
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_get_events_filtered() {
+        // arrange
+        let events = make_events();
+        let after = events[0].time;
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_events()
+            .returning(move || Ok(events.clone()));
+        // act
+        let usecase = GetEvents::new(Box::new(mock));
+        let kinds = vec![EventKind::BackupStarted, EventKind::BackupFinished];
+        let params = Params::new(Some(after), kinds, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let actual = result.unwrap();
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[0].kind, EventKind::BackupFinished);
+    }
+
+    #[test]
+    fn test_get_events_limit() {
+        // arrange
+        let events = make_events();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_events()
+            .returning(move || Ok(events.clone()));
+        // act
+        let usecase = GetEvents::new(Box::new(mock));
+        let params = Params::new(None, vec![], Some(2));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let actual = result.unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].kind, EventKind::BackupStarted);
+        assert_eq!(actual[1].kind, EventKind::PackUploaded);
+    }
+
+    #[test]
+    fn test_get_events_err() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_events().returning(|| Err(anyhow!("oh no")));
+        // act
+        let usecase = GetEvents::new(Box::new(mock));
+        let params = Params::new(None, vec![], None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+}
@@ -1,14 +1,15 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, PackEntry, PackFile};
+use crate::domain::entities::{Checksum, Message, MessageCode, PackEntry, PackFile};
 use crate::domain::repositories::RecordRepository;
-use anyhow::{anyhow, Context, Error};
+use anyhow::{Context, Error};
 use log::debug;
 use std::borrow::Cow;
 use std::cmp;
 use std::fmt;
 use std::fs;
+use store_core::Secret;
 
 pub struct GetPack {
     repo: Box<dyn RecordRepository>,
@@ -23,10 +24,9 @@ impl GetPack {
 impl<'a> super::UseCase<PackFile, Params<'a>> for GetPack {
     fn call(&self, params: Params) -> Result<PackFile, Error> {
         let pack_digest = &params.digest;
-        let dataset = self
-            .repo
-            .get_dataset(&params.dataset_id)?
-            .ok_or_else(|| anyhow!(format!("missing dataset: {:?}", params.dataset_id)))?;
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
         let stores = self.repo.load_dataset_stores(&dataset)?;
         fs::create_dir_all(&dataset.workspace).context("creating workspace")?;
         let archive = tempfile::Builder::new()
@@ -35,16 +35,16 @@ impl<'a> super::UseCase<PackFile, Params<'a>> for GetPack {
         let pack_record = self
             .repo
             .get_pack(pack_digest)?
-            .ok_or_else(|| anyhow!(format!("missing pack record: {:?}", pack_digest)))?;
+            .ok_or_else(|| Message::new(MessageCode::MissingPack).with("digest", &pack_digest))?;
         // retrieve the pack file
         debug!("get-pack: fetching pack {}", pack_digest);
-        stores.retrieve_pack(&pack_record.locations, archive.path())?;
+        stores.retrieve_pack(&pack_record.locations, pack_digest, archive.path())?;
         // read the archive file entries
         let mut entries: Vec<PackEntry> = Vec::new();
         let attr = fs::metadata(&archive)?;
         let file_size = attr.len();
         let mut reader = exaf_rs::reader::Entries::new(&archive)?;
-        reader.enable_encryption(&params.passphrase)?;
+        reader.enable_encryption(params.passphrase.expose())?;
         for maybe_entry in reader {
             let entry = maybe_entry?;
             let path = entry.name().to_string();
@@ -60,15 +60,19 @@ pub struct Params<'a> {
     /// Hash digest of the pack to retrieve.
     digest: Checksum,
     /// Pass phrase for decrypting the pack.
-    passphrase: Cow<'a, str>,
+    passphrase: Secret,
 }
 
 impl<'a> Params<'a> {
-    pub fn new<T: Into<String>>(dataset_id: T, digest: Checksum, passphrase: T) -> Self {
+    pub fn new<T: Into<String>, S: Into<Secret>>(
+        dataset_id: T,
+        digest: Checksum,
+        passphrase: S,
+    ) -> Self {
         Self {
             dataset_id: Cow::from(dataset_id.into()),
             digest,
-            passphrase: Cow::from(passphrase.into()),
+            passphrase: passphrase.into(),
         }
     }
 }
@@ -154,7 +158,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     // rename on Windows fails with permission denied, so do
                     // what the local pack store would do and just copy
                     std::fs::copy(packfile_path.clone(), outfile).unwrap();
@@ -208,7 +212,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     std::fs::copy(packfile_path.clone(), outfile).unwrap();
                     Ok(())
                 });
@@ -0,0 +1,322 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Dataset, MaintenanceResult, MaintenanceTask, Message, MessageCode, Priority,
+    Recommendation,
+};
+use crate::domain::repositories::RecordRepository;
+use crate::domain::usecases::NoParams;
+use anyhow::Error;
+use chrono::prelude::*;
+
+// Days after which a dataset without a completed backup deserves attention.
+const STALE_BACKUP_DAYS: i64 = 7;
+
+// Number of snapshots in a dataset beyond which pruning is worthwhile.
+const SNAPSHOT_THRESHOLD: usize = 100;
+
+// Number of packs beyond which pruning snapshots is likely to pay off.
+const PACK_THRESHOLD: usize = 10_000;
+
+///
+/// Inspect the datasets and the outcomes of the maintenance tasks, suggesting
+/// actions that would keep the backups in good order, most important first.
+///
+/// The maintenance outcomes are retained only while the server is running, so
+/// a task that has not run since the server started is considered never run.
+///
+pub struct GetRecommendations {
+    repo: Box<dyn RecordRepository>,
+    results: Vec<MaintenanceResult>,
+}
+
+impl GetRecommendations {
+    pub fn new(repo: Box<dyn RecordRepository>, results: Vec<MaintenanceResult>) -> Self {
+        Self { repo, results }
+    }
+
+    // Return true if the task has completed successfully at least once.
+    fn has_succeeded(&self, task: MaintenanceTask) -> bool {
+        self.results
+            .iter()
+            .any(|r| r.task == task && r.error.is_none())
+    }
+
+    // Find the end time of the most recent completed snapshot, and count the
+    // snapshots in the dataset, up to the pruning threshold.
+    fn inspect_snapshots(
+        &self,
+        latest: Option<Checksum>,
+    ) -> Result<(Option<DateTime<Utc>>, usize), Error> {
+        let mut finished: Option<DateTime<Utc>> = None;
+        let mut count: usize = 0;
+        let mut next = latest;
+        while let Some(digest) = next {
+            let Some(snapshot) = self.repo.get_snapshot(&digest)? else {
+                break;
+            };
+            count += 1;
+            if finished.is_none() {
+                finished = snapshot.end_time;
+            }
+            if finished.is_some() && count >= SNAPSHOT_THRESHOLD {
+                break;
+            }
+            next = snapshot.parent;
+        }
+        Ok((finished, count))
+    }
+
+    fn check_dataset(
+        &self,
+        dataset: &Dataset,
+        many_packs: bool,
+        found: &mut Vec<Recommendation>,
+    ) -> Result<(), Error> {
+        let latest = self.repo.get_latest_snapshot(&dataset.id)?;
+        let (finished, count) = self.inspect_snapshots(latest)?;
+        let path = dataset.basepath.display();
+        match finished {
+            // archived datasets are not expected to be backed up any more
+            _ if dataset.archived.is_some() => (),
+            None => found.push(
+                Recommendation::new(
+                    Priority::High,
+                    Message::new(MessageCode::NeverBackedUp).with("path", &path),
+                    "startBackup",
+                )
+                .subject(&dataset.id),
+            ),
+            Some(time) if Utc::now() - time > chrono::Duration::days(STALE_BACKUP_DAYS) => found
+                .push(
+                    Recommendation::new(
+                        Priority::High,
+                        Message::new(MessageCode::BackupOverdue)
+                            .with("path", &path)
+                            .with("date", &time.format("%Y-%m-%d")),
+                        "startBackup",
+                    )
+                    .subject(&dataset.id),
+                ),
+            _ => (),
+        }
+        if count >= SNAPSHOT_THRESHOLD && many_packs {
+            found.push(
+                Recommendation::new(
+                    Priority::Medium,
+                    Message::new(MessageCode::ManySnapshots)
+                        .with("path", &path)
+                        .with("count", &count),
+                    "pruneSnapshots",
+                )
+                .subject(&dataset.id),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl super::UseCase<Vec<Recommendation>, NoParams> for GetRecommendations {
+    fn call(&self, _params: NoParams) -> Result<Vec<Recommendation>, Error> {
+        let mut found: Vec<Recommendation> = Vec::new();
+        let counts = self.repo.get_entity_counts()?;
+        let many_packs = counts.pack >= PACK_THRESHOLD;
+        for dataset in self.repo.get_datasets()?.iter() {
+            self.check_dataset(dataset, many_packs, &mut found)?;
+        }
+        if counts.pack > 0 && !self.has_succeeded(MaintenanceTask::Verify) {
+            found.push(Recommendation::new(
+                Priority::Medium,
+                Message::new(MessageCode::PacksNotVerified),
+                "queueMaintenance",
+            ));
+        }
+        if !self.has_succeeded(MaintenanceTask::Compact) {
+            found.push(Recommendation::new(
+                Priority::Low,
+                Message::new(MessageCode::DatabaseNotCompacted),
+                "queueMaintenance",
+            ));
+        }
+        // the sort is stable, leaving the datasets in their original order
+        found.sort_by_key(|r| r.priority);
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{FileCounts, RecordCounts, Snapshot};
+    use crate::domain::repositories::MockRecordRepository;
+    use mockall::predicate::*;
+    use std::path::Path;
+
+    fn make_snapshot(end_time: Option<DateTime<Utc>>) -> Snapshot {
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let mut snapshot = Snapshot::new(None, tree, FileCounts::default());
+        if let Some(time) = end_time {
+            snapshot.set_end_time(time);
+        }
+        snapshot
+    }
+
+    fn make_results() -> Vec<MaintenanceResult> {
+        vec![
+            MaintenanceResult::new(MaintenanceTask::Verify),
+            MaintenanceResult::new(MaintenanceTask::Compact),
+        ]
+    }
+
+    #[test]
+    fn test_get_recommendations_none() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let snapshot = make_snapshot(Some(Utc::now()));
+        let digest = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_entity_counts().returning(|| {
+            Ok(RecordCounts {
+                pack: 10,
+                ..Default::default()
+            })
+        });
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(digest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        // act
+        let usecase = GetRecommendations::new(Box::new(mock), make_results());
+        let result = usecase.call(NoParams {});
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_recommendations_archived() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.archived = Some(Utc::now());
+        let old = make_snapshot(Some(Utc::now() - chrono::Duration::days(365)));
+        let digest = old.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_entity_counts()
+            .returning(|| Ok(RecordCounts::default()));
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(digest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(old.clone())));
+        // act
+        let usecase = GetRecommendations::new(Box::new(mock), make_results());
+        let result = usecase.call(NoParams {});
+        // assert
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_recommendations_stale() {
+        // arrange
+        let fresh = Dataset::new(Path::new("/home/planet"));
+        let stale = Dataset::new(Path::new("/home/moon"));
+        let stale_id = stale.id.clone();
+        let never = Dataset::new(Path::new("/home/comet"));
+        let never_id = never.id.clone();
+        let datasets = vec![fresh.clone(), stale, never];
+        let recent = make_snapshot(Some(Utc::now()));
+        let recent_digest = recent.digest.clone();
+        let old = make_snapshot(Some(Utc::now() - chrono::Duration::days(30)));
+        // the latest snapshot of the stale dataset is still in progress
+        let pending = make_snapshot(None).with_parent(Some(old.digest.clone()));
+        let pending_digest = pending.digest.clone();
+        let old_digest = old.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_entity_counts()
+            .returning(|| Ok(RecordCounts::default()));
+        mock.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        let fresh_id = fresh.id.clone();
+        let latest_pending = pending_digest.clone();
+        mock.expect_get_latest_snapshot().returning(move |id| {
+            if id == fresh_id {
+                Ok(Some(recent_digest.clone()))
+            } else if id == stale_id {
+                Ok(Some(latest_pending.clone()))
+            } else {
+                Ok(None)
+            }
+        });
+        mock.expect_get_snapshot()
+            .with(eq(pending_digest))
+            .returning(move |_| Ok(Some(pending.clone())));
+        mock.expect_get_snapshot()
+            .with(eq(old_digest))
+            .returning(move |_| Ok(Some(old.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(recent.clone())));
+        // act
+        let usecase = GetRecommendations::new(Box::new(mock), vec![]);
+        let result = usecase.call(NoParams {});
+        // assert
+        assert!(result.is_ok());
+        let actual = result.unwrap();
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual[0].priority, Priority::High);
+        assert_eq!(actual[0].message.code, MessageCode::BackupOverdue);
+        assert!(actual[0].message.to_string().contains("/home/moon"));
+        assert!(actual[0].message.to_string().contains("since"));
+        assert_eq!(actual[0].mutation, "startBackup");
+        assert_eq!(actual[1].priority, Priority::High);
+        assert_eq!(actual[1].subject.as_deref(), Some(never_id.as_str()));
+        assert!(actual[1].message.to_string().contains("never completed"));
+        // without any packs there is nothing to verify
+        assert_eq!(actual[2].priority, Priority::Low);
+        assert!(actual[2].message.to_string().contains("compacted"));
+        assert_eq!(actual[2].mutation, "queueMaintenance");
+    }
+
+    #[test]
+    fn test_get_recommendations_prune() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let snapshot = make_snapshot(Some(Utc::now()));
+        // every snapshot refers to the same parent, which is an endless chain
+        let snapshot = snapshot.with_parent(Some(snapshot.digest.clone()));
+        let digest = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_entity_counts().returning(|| {
+            Ok(RecordCounts {
+                pack: 20_000,
+                ..Default::default()
+            })
+        });
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(digest.clone())));
+        mock.expect_get_snapshot()
+            .times(SNAPSHOT_THRESHOLD)
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        // act
+        let results = vec![MaintenanceResult::new(MaintenanceTask::Compact)];
+        let usecase = GetRecommendations::new(Box::new(mock), results);
+        let result = usecase.call(NoParams {});
+        // assert
+        assert!(result.is_ok());
+        let actual = result.unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].priority, Priority::Medium);
+        assert_eq!(actual[0].mutation, "pruneSnapshots");
+        assert_eq!(actual[0].subject.as_deref(), Some(dataset_id.as_str()));
+        assert_eq!(actual[1].priority, Priority::Medium);
+        assert!(actual[1].message.to_string().contains("verified"));
+    }
+}
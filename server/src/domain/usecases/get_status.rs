@@ -0,0 +1,241 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Dataset, DatasetStatus, MaintenanceResult, MaintenanceTask, RetrievalFailures,
+    StatusReport, StoreStatus,
+};
+use crate::domain::managers::restore::Restorer;
+use crate::domain::managers::state::StateStore;
+use crate::domain::repositories::RecordRepository;
+use crate::domain::usecases::NoParams;
+use anyhow::Error;
+use chrono::prelude::*;
+use std::sync::Arc;
+
+///
+/// Summarize the state of the backups of each dataset, the health of each
+/// store, and the restore requests, for the status page.
+///
+/// The retrieval failures and maintenance outcomes are retained only while
+/// the server is running, so a store that has not been used since the server
+/// started is considered healthy.
+///
+pub struct GetStatus {
+    repo: Box<dyn RecordRepository>,
+    state: Arc<dyn StateStore>,
+    restorer: Arc<dyn Restorer>,
+    failures: Vec<RetrievalFailures>,
+    results: Vec<MaintenanceResult>,
+}
+
+impl GetStatus {
+    pub fn new(
+        repo: Box<dyn RecordRepository>,
+        state: Arc<dyn StateStore>,
+        restorer: Arc<dyn Restorer>,
+        failures: Vec<RetrievalFailures>,
+        results: Vec<MaintenanceResult>,
+    ) -> Self {
+        Self {
+            repo,
+            state,
+            restorer,
+            failures,
+            results,
+        }
+    }
+
+    // Find the end time of the most recent completed snapshot, passing over
+    // any snapshot that is still in progress.
+    fn last_success(&self, latest: Option<Checksum>) -> Result<Option<DateTime<Utc>>, Error> {
+        let mut next = latest;
+        while let Some(digest) = next {
+            let Some(snapshot) = self.repo.get_snapshot(&digest)? else {
+                break;
+            };
+            if snapshot.end_time.is_some() {
+                return Ok(snapshot.end_time);
+            }
+            next = snapshot.parent;
+        }
+        Ok(None)
+    }
+
+    fn dataset_status(&self, dataset: &Dataset) -> Result<DatasetStatus, Error> {
+        let latest = self.repo.get_latest_snapshot(&dataset.id)?;
+        let redux = self.state.get_state();
+        let backup = redux.backups(&dataset.id);
+        let state = match backup {
+            Some(backup) if backup.is_paused() => "paused",
+            Some(backup) if backup.had_error() => "failed",
+            Some(backup) if backup.end_time().is_none() => "running",
+            Some(_) => "finished",
+            None => "none",
+        };
+        Ok(DatasetStatus {
+            id: dataset.id.clone(),
+            basepath: dataset.basepath.clone(),
+            backup: state.to_owned(),
+            last_success: self.last_success(latest)?,
+            error: backup.and_then(|b| b.error_message()),
+        })
+    }
+}
+
+impl super::UseCase<StatusReport, NoParams> for GetStatus {
+    fn call(&self, _params: NoParams) -> Result<StatusReport, Error> {
+        let mut datasets: Vec<DatasetStatus> = Vec::new();
+        for dataset in self.repo.get_datasets()? {
+            if dataset.archived.is_none() {
+                datasets.push(self.dataset_status(&dataset)?);
+            }
+        }
+        let mut stores: Vec<StoreStatus> = Vec::new();
+        for store in self.repo.get_stores()? {
+            let failures = self.failures.iter().find(|f| f.store == store.id);
+            stores.push(StoreStatus {
+                healthy: failures.map(|f| f.consecutive == 0).unwrap_or(true),
+                last_error: failures.and_then(|f| f.last_error.clone()),
+                id: store.id,
+                label: store.label,
+                store_type: store.store_type,
+            });
+        }
+        let requests = self.restorer.requests();
+        let pending_restores = requests.iter().filter(|r| r.finished.is_none()).count();
+        let failed_restores = requests.iter().filter(|r| r.error_msg.is_some()).count();
+        // the outcomes are ordered newest first
+        let health_check = self
+            .results
+            .iter()
+            .find(|r| r.task == MaintenanceTask::Health)
+            .cloned();
+        Ok(StatusReport {
+            generated: Utc::now(),
+            datasets,
+            stores,
+            pending_restores: pending_restores as u64,
+            failed_restores: failed_restores as u64,
+            health_check,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Snapshot, Store, StoreType};
+    use crate::domain::managers::restore::{MockRestorer, Request};
+    use crate::domain::managers::state::{MockStateStore, State};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use store_core::Secret;
+
+    #[test]
+    fn test_get_status() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mut archived = Dataset::new(Path::new("/home/archive"));
+        archived.archived = Some(Utc::now());
+        let datasets = vec![dataset.clone(), archived];
+        // the latest snapshot is still in progress
+        let mut older = Snapshot::new(None, Checksum::SHA1("cafebabe".into()), Default::default());
+        older.set_end_time(Utc::now());
+        let older_digest = older.digest.clone();
+        let newer = Snapshot::new(
+            Some(older_digest.clone()),
+            Checksum::SHA1("deadbeef".into()),
+            Default::default(),
+        );
+        let newer_digest = newer.digest.clone();
+        let expected_end = older.end_time;
+        let stores = vec![
+            Store {
+                id: "local123".into(),
+                store_type: StoreType::LOCAL,
+                label: "usb disk".into(),
+                properties: HashMap::new(),
+            },
+            Store {
+                id: "sftp456".into(),
+                store_type: StoreType::SFTP,
+                label: "nas".into(),
+                properties: HashMap::new(),
+            },
+        ];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(newer_digest.clone())));
+        mock.expect_get_snapshot().returning(move |digest| {
+            if digest == &older_digest {
+                Ok(Some(older.clone()))
+            } else {
+                Ok(Some(newer.clone()))
+            }
+        });
+        mock.expect_get_stores()
+            .returning(move || Ok(stores.clone()));
+        let mut state = MockStateStore::new();
+        state.expect_get_state().returning(State::default);
+        let mut restorer = MockRestorer::new();
+        restorer.expect_requests().returning(|| {
+            let mut failed = Request::new(
+                Checksum::SHA1("cafebabe".into()),
+                "lorem.txt".into(),
+                "lorem.txt".into(),
+                "dataset1".into(),
+                Secret::new("secret123".into()),
+            );
+            failed.finished = Some(Utc::now());
+            failed.error_msg = Some("oh no".into());
+            let pending = Request::new(
+                Checksum::SHA1("cafebabe".into()),
+                "ipsum.txt".into(),
+                "ipsum.txt".into(),
+                "dataset1".into(),
+                Secret::new("secret123".into()),
+            );
+            vec![failed, pending]
+        });
+        let mut failures = RetrievalFailures::new("sftp456");
+        failures.failures = 2;
+        failures.consecutive = 1;
+        failures.last_error = Some("connection refused".into());
+        let mut health = MaintenanceResult::new(MaintenanceTask::Health);
+        health.summary = "2 stores are reachable".into();
+        let results = vec![MaintenanceResult::new(MaintenanceTask::Verify), health];
+        // act
+        let usecase = GetStatus::new(
+            Box::new(mock),
+            Arc::new(state),
+            Arc::new(restorer),
+            vec![failures],
+            results,
+        );
+        let result = usecase.call(NoParams {});
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert_eq!(report.datasets.len(), 1);
+        assert_eq!(report.datasets[0].id, dataset.id);
+        assert_eq!(report.datasets[0].backup, "none");
+        assert_eq!(report.datasets[0].last_success, expected_end);
+        assert!(report.datasets[0].error.is_none());
+        assert_eq!(report.stores.len(), 2);
+        assert!(report.stores[0].healthy);
+        assert!(!report.stores[1].healthy);
+        assert_eq!(
+            report.stores[1].last_error.as_deref(),
+            Some("connection refused")
+        );
+        assert_eq!(report.pending_restores, 1);
+        assert_eq!(report.failed_restores, 1);
+        let health = report.health_check.unwrap();
+        assert_eq!(health.summary, "2 stores are reachable");
+    }
+}
@@ -0,0 +1,325 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Checksum, File, Message, MessageCode, SearchResult, TreeReference};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use chrono::prelude::*;
+use globset::{GlobBuilder, GlobMatcher};
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Maximum number of results to return when no limit is given.
+const DEFAULT_LIMIT: usize = 1_000;
+
+///
+/// Search the latest snapshot of every dataset for entries whose path matches
+/// the given glob pattern, ignoring case, and optionally whose file metadata
+/// matches the given filter.
+///
+pub struct GlobalSearch {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl GlobalSearch {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Search the latest snapshot of a single dataset, stopping once the
+    // results reach the given limit.
+    fn search_dataset(
+        &self,
+        dataset_id: &str,
+        matcher: &GlobMatcher,
+        filter: &MetadataFilter,
+        limit: usize,
+        results: &mut Vec<SearchResult>,
+    ) -> Result<(), Error> {
+        let snapshot_digest = match self.repo.get_latest_snapshot(dataset_id)? {
+            Some(digest) => digest,
+            None => return Ok(()),
+        };
+        let snapshot = self.repo.get_snapshot(&snapshot_digest)?.ok_or_else(|| {
+            Message::new(MessageCode::MissingSnapshot).with("digest", &snapshot_digest)
+        })?;
+        let mut pending_trees: VecDeque<(Checksum, PathBuf)> = VecDeque::new();
+        pending_trees.push_back((snapshot.tree, PathBuf::new()));
+        while let Some((tree_digest, prefix)) = pending_trees.pop_front() {
+            let tree = self.repo.get_tree(&tree_digest)?.ok_or_else(|| {
+                Message::new(MessageCode::MissingTree).with("digest", &tree_digest)
+            })?;
+            for entry in tree.entries.iter() {
+                let filepath = prefix.join(&entry.name);
+                if matcher.is_match(&filepath) {
+                    // only file records carry the metadata
+                    let file = match &entry.reference {
+                        TreeReference::FILE(digest) => self.repo.get_file(digest)?,
+                        _ => None,
+                    };
+                    if filter.matches(file.as_ref()) {
+                        results.push(SearchResult {
+                            dataset_id: dataset_id.to_owned(),
+                            snapshot: snapshot_digest.clone(),
+                            tree: tree_digest.clone(),
+                            entry: entry.name.clone(),
+                            filepath: filepath.clone(),
+                            modified: entry.mtime,
+                            directory: entry.reference.is_tree(),
+                            taken: file.as_ref().and_then(|f| f.taken),
+                            subject: file.and_then(|f| f.subject),
+                        });
+                        if results.len() >= limit {
+                            return Ok(());
+                        }
+                    }
+                }
+                if let TreeReference::TREE(child) = &entry.reference {
+                    pending_trees.push_back((child.to_owned(), filepath));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl super::UseCase<Vec<SearchResult>, Params> for GlobalSearch {
+    fn call(&self, params: Params) -> Result<Vec<SearchResult>, Error> {
+        let matcher = GlobBuilder::new(&params.pattern)
+            .case_insensitive(true)
+            .build()?
+            .compile_matcher();
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut results: Vec<SearchResult> = Vec::new();
+        for dataset in self.repo.get_datasets()? {
+            if results.len() >= limit {
+                break;
+            }
+            self.search_dataset(&dataset.id, &matcher, &params.filter, limit, &mut results)?;
+        }
+        Ok(results)
+    }
+}
+
+///
+/// Criteria for the metadata of the files found by a search. An entry that
+/// lacks a field being filtered on, such as a directory, does not match.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataFilter {
+    /// Photo must have been taken at or after this date/time.
+    pub taken_after: Option<DateTime<Utc>>,
+    /// Photo must have been taken before this date/time.
+    pub taken_before: Option<DateTime<Utc>>,
+    /// Message subject must contain this text, ignoring case.
+    pub subject: Option<String>,
+}
+
+impl MetadataFilter {
+    // Return `true` if the filter is empty or the file matches every part.
+    fn matches(&self, file: Option<&File>) -> bool {
+        if self.taken_after.is_some() || self.taken_before.is_some() {
+            let Some(taken) = file.and_then(|f| f.taken) else {
+                return false;
+            };
+            if self.taken_after.map_or(false, |after| taken < after) {
+                return false;
+            }
+            if self.taken_before.map_or(false, |before| taken >= before) {
+                return false;
+            }
+        }
+        if let Some(text) = self.subject.as_ref() {
+            let Some(subject) = file.and_then(|f| f.subject.as_ref()) else {
+                return false;
+            };
+            if !subject.to_lowercase().contains(&text.to_lowercase()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub struct Params {
+    /// Glob pattern to match against the relative path of each entry.
+    pattern: String,
+    /// Maximum number of results to return.
+    limit: Option<usize>,
+    /// Criteria for the metadata of matching files.
+    filter: MetadataFilter,
+}
+
+impl Params {
+    pub fn new(pattern: String, limit: Option<usize>, filter: MetadataFilter) -> Self {
+        Self {
+            pattern,
+            limit,
+            filter,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.pattern)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern && self.limit == other.limit && self.filter == other.filter
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Dataset, FileCounts, Snapshot, Tree, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_global_search_ok() {
+        // arrange
+        let file_ref = TreeReference::FILE(Checksum::BLAKE3("1111".into()));
+        let subtree = Tree::new(
+            vec![TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                file_ref.clone(),
+            )],
+            1,
+        );
+        let root = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    file_ref,
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures"),
+                    TreeReference::TREE(subtree.digest.clone()),
+                ),
+            ],
+            2,
+        );
+        let root_digest = root.digest.clone();
+        let subtree_digest = subtree.digest.clone();
+        let snapshot = Snapshot::new(None, root.digest.clone(), FileCounts::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let trees: HashMap<Checksum, Tree> = vec![root, subtree]
+            .into_iter()
+            .map(|t| (t.digest.clone(), t))
+            .collect();
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        let latest = snapshot_digest.clone();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.get(digest).cloned()));
+        mock.expect_get_file().returning(|_| Ok(None));
+        // act
+        let usecase = GlobalSearch::new(Box::new(mock));
+        let params = Params::new("*LOREM*".into(), None, MetadataFilter::default());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let results = result.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].snapshot, snapshot_digest);
+        assert_eq!(results[0].tree, subtree_digest);
+        assert_eq!(results[0].entry, "lorem-ipsum.txt");
+        assert_eq!(
+            results[0].filepath,
+            PathBuf::from("fixtures/lorem-ipsum.txt")
+        );
+        assert!(!results[0].directory);
+        assert_ne!(results[0].tree, root_digest);
+    }
+
+    #[test]
+    fn test_global_search_metadata() {
+        // arrange
+        let digest_2019 = Checksum::BLAKE3("2019".into());
+        let digest_2020 = Checksum::BLAKE3("2020".into());
+        let root = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/SekienAkashita.jpg"),
+                    TreeReference::FILE(digest_2019.clone()),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/baby-birth.jpg"),
+                    TreeReference::FILE(digest_2020.clone()),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(Checksum::BLAKE3("1111".into())),
+                ),
+            ],
+            3,
+        );
+        let snapshot = Snapshot::new(None, root.digest.clone(), FileCounts::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_datasets()
+            .returning(move || Ok(vec![dataset.clone()]));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(snapshot_digest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(root.clone())));
+        mock.expect_get_file().returning(|digest| {
+            let mut file = File::new(digest.clone(), 1024, vec![]);
+            file.taken = match digest.to_string().as_str() {
+                "blake3-2019" => Some(Utc.with_ymd_and_hms(2019, 6, 1, 8, 0, 0).unwrap()),
+                "blake3-2020" => Some(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()),
+                _ => None,
+            };
+            Ok(Some(file))
+        });
+        // act
+        let usecase = GlobalSearch::new(Box::new(mock));
+        let filter = MetadataFilter {
+            taken_after: Some(Utc.with_ymd_and_hms(2019, 1, 1, 0, 0, 0).unwrap()),
+            taken_before: Some(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()),
+            subject: None,
+        };
+        let params = Params::new("*".into(), None, filter);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let results = result.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].entry, "SekienAkashita.jpg");
+        assert_eq!(
+            results[0].taken,
+            Some(Utc.with_ymd_and_hms(2019, 6, 1, 8, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_global_search_bad_pattern() {
+        // arrange
+        let mock = MockRecordRepository::new();
+        // act
+        let usecase = GlobalSearch::new(Box::new(mock));
+        let params = Params::new("foo[".into(), None, MetadataFilter::default());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+}
@@ -1,7 +1,8 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, File};
+use crate::domain::entities::{Checksum, File, Message, MessageCode};
+use crate::domain::helpers::pack;
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Context, Error};
 use log::info;
@@ -10,6 +11,7 @@ use std::cmp;
 use std::fmt;
 use std::fs;
 use std::str::FromStr;
+use store_core::Secret;
 
 ///
 /// Insert a new file record with the given checksum and pack digest.
@@ -40,25 +42,23 @@ impl<'a> super::UseCase<bool, Params<'a>> for InsertFile {
             )));
         }
         // get the dataset and its associated pack stores
-        let dataset = self
-            .repo
-            .get_dataset(&params.dataset_id)?
-            .ok_or_else(|| anyhow!(format!("missing dataset: {:?}", params.dataset_id)))?;
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
         let stores = self.repo.load_dataset_stores(&dataset)?;
         fs::create_dir_all(&dataset.workspace).context("creating workspace")?;
         info!("InsertFile: retrieving pack {}", &params.pack_digest);
-        let pack = self
-            .repo
-            .get_pack(&params.pack_digest)?
-            .ok_or_else(|| anyhow!(format!("missing pack: {:?}", params.pack_digest)))?;
+        let pack = self.repo.get_pack(&params.pack_digest)?.ok_or_else(|| {
+            Message::new(MessageCode::MissingPack).with("digest", &params.pack_digest)
+        })?;
         // retrieve and decrypt the pack file
         let archive = tempfile::Builder::new()
             .suffix(".pack")
             .tempfile_in(&dataset.workspace)?;
-        stores.retrieve_pack(&pack.locations, archive.path())?;
+        stores.retrieve_pack(&pack.locations, &pack.digest, archive.path())?;
         // scan the contents of the tar file to verify chunk exists
         let mut reader = exaf_rs::reader::Entries::new(&archive)?;
-        reader.enable_encryption(&params.passphrase)?;
+        reader.enable_encryption(params.passphrase.expose())?;
         let mut file_size: u64 = 0;
         for maybe_entry in reader {
             let entry = maybe_entry?;
@@ -69,6 +69,16 @@ impl<'a> super::UseCase<bool, Params<'a>> for InsertFile {
                 break;
             }
         }
+        if file_size > 0 && pack.compression.per_chunk() {
+            // the entry holds the compressed chunk, whose original size is
+            // only known once it has been decoded
+            let outdir = tempfile::tempdir_in(&dataset.workspace)?;
+            let passphrase = Some(params.passphrase.expose());
+            let names = pack::extract_pack(archive.path(), outdir.path(), passphrase)?;
+            pack::decode_chunks(outdir.path(), &names, pack.compression)?;
+            let chunk_path = outdir.path().join(params.chunk_digest.to_string());
+            file_size = fs::metadata(chunk_path)?.len();
+        }
         if file_size == 0 {
             Err(anyhow!(format!("pack did not contain chunk")))
         } else {
@@ -91,21 +101,21 @@ pub struct Params<'a> {
     /// Digest of the pack that is expected to contain the chunk.
     pack_digest: Checksum,
     /// Pass phrase for decrypting the pack.
-    passphrase: Cow<'a, str>,
+    passphrase: Secret,
 }
 
 impl<'a> Params<'a> {
-    pub fn new<T: Into<String>>(
+    pub fn new<T: Into<String>, S: Into<Secret>>(
         dataset_id: T,
         chunk_digest: Checksum,
         pack_digest: Checksum,
-        passphrase: T,
+        passphrase: S,
     ) -> Self {
         Self {
             dataset_id: Cow::from(dataset_id.into()),
             chunk_digest,
             pack_digest,
-            passphrase: Cow::from(passphrase.into()),
+            passphrase: passphrase.into(),
         }
     }
 }
@@ -184,7 +194,7 @@ mod tests {
         // assert
         assert!(result.is_err());
         let err_string = result.err().unwrap().to_string();
-        assert!(err_string.contains("missing dataset"));
+        assert!(err_string.contains("no such dataset"));
     }
 
     #[test]
@@ -241,7 +251,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     std::fs::copy(packfile_path.clone(), outfile).unwrap();
                     Ok(())
                 });
@@ -297,7 +307,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     // rename on Windows fails with permission denied, so do
                     // what the local pack store would do and just copy
                     std::fs::copy(packfile_path.clone(), outfile).unwrap();
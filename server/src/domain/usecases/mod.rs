@@ -5,38 +5,80 @@ use anyhow::Error;
 use std::cmp;
 use std::fmt;
 
+pub mod archive_dataset;
+pub mod browse_snapshot;
 pub mod cancel_restore;
+pub mod compact_packs;
+pub mod configure_store_lifecycle;
+pub mod dataset_usage;
+pub mod dedup_stats;
 pub mod delete_dataset;
 pub mod delete_store;
+pub mod discover_remote;
+pub mod download_object;
+pub mod estimate_cost;
+pub mod file_history;
 pub mod find_missing;
+pub mod forecast_schedules;
 pub mod get_counts;
 pub mod get_datasets;
+pub mod get_events;
 pub mod get_pack;
+pub mod get_recommendations;
 pub mod get_snapshot;
+pub mod get_status;
 pub mod get_stores;
 pub mod get_tree;
+pub mod global_search;
 pub mod insert_file;
 pub mod new_dataset;
 pub mod new_store;
 pub mod prune_extra;
+pub mod prune_snapshots;
 pub mod query_restores;
 pub mod reassign_packs;
+pub mod recompute_statistics;
+pub mod reconcile_store;
 pub mod restore_database;
 pub mod restore_files;
 pub mod restore_missing;
+pub mod restore_snapshot;
 pub mod scan_packs;
 pub mod start_backup;
 pub mod stop_backup;
 pub mod test_store;
+pub mod tier_packs;
+pub mod undelete_dataset;
 pub mod update_dataset;
 pub mod update_store;
+pub mod upload_object;
+pub mod verify_chain;
 pub mod verify_snapshot;
+pub mod walk_snapshot;
 
 /// `UseCase` is the interface by which all use cases are invoked.
 pub trait UseCase<Type, Params> {
     fn call(&self, params: Params) -> Result<Type, Error>;
 }
 
+///
+/// Raised when an update is based on an outdated version of the record, as
+/// indicated by the entity tag, meaning someone else changed it in the mean
+/// time. The current entity tag is included so the caller can merge the
+/// changes and try again.
+///
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub struct ConflictError {
+    /// Entity tag of the record as it is now in the database.
+    pub current: String,
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "record was modified by another client")
+    }
+}
+
 /// `NoParams` is the type for use cases that do not take arguments.
 pub struct NoParams {}
 
@@ -2,10 +2,11 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 use crate::domain::entities::schedule::Schedule;
-use crate::domain::entities::Dataset;
+use crate::domain::entities::{Compression, Dataset};
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 
@@ -21,6 +22,10 @@ impl NewDataset {
 
 impl super::UseCase<Dataset, Params> for NewDataset {
     fn call(&self, params: Params) -> Result<Dataset, Error> {
+        // an unrecognized compression would otherwise be quietly ignored
+        if let Some(value) = params.properties.get("compression") {
+            value.parse::<Compression>()?;
+        }
         // use the constructor to generate a new identifier and then copy
         // everything over
         let mut dataset = Dataset::with_pack_size(&params.basepath, params.pack_size);
@@ -36,6 +41,7 @@ impl super::UseCase<Dataset, Params> for NewDataset {
         for store in params.stores.iter() {
             dataset.add_store(store);
         }
+        dataset.properties = params.properties;
         self.repo.put_dataset(&dataset)?;
         // for new datasets we need to save the computer id
         let config = self.repo.get_configuration()?;
@@ -56,6 +62,8 @@ pub struct Params {
     stores: Vec<String>,
     /// List of file/directory exclusion patterns.
     excludes: Vec<String>,
+    /// Name/value pairs for optional dataset settings.
+    properties: HashMap<String, String>,
 }
 
 impl Params {
@@ -72,8 +80,15 @@ impl Params {
             pack_size,
             stores,
             excludes,
+            properties: HashMap::new(),
         }
     }
+
+    /// Set the name/value pairs for optional dataset settings.
+    pub fn properties(mut self, properties: HashMap<String, String>) -> Self {
+        self.properties = properties;
+        self
+    }
 }
 
 impl fmt::Display for Params {
@@ -122,6 +137,7 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: HashMap::new(),
         };
         let result = usecase.call(params);
         // assert
@@ -162,6 +178,7 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec!["".to_owned()],
+            properties: HashMap::new(),
         };
         let result = usecase.call(params);
         // assert
@@ -179,6 +196,30 @@ mod tests {
         assert_eq!(actual.excludes.len(), 0);
     }
 
+    #[test]
+    fn test_new_dataset_bad_compression() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_put_dataset().never();
+        // act
+        let usecase = NewDataset::new(Box::new(mock));
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("compression".into(), "zstd:99".into());
+        let params = Params {
+            basepath: PathBuf::from("/home/planet"),
+            schedules: vec![],
+            pack_size: 33_554_432,
+            stores: vec!["cafebabe".to_owned()],
+            excludes: vec![],
+            properties,
+        };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("not a recognized compression"));
+    }
+
     #[test]
     fn test_new_dataset_err() {
         // arrange
@@ -199,6 +240,7 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: HashMap::new(),
         };
         let result = usecase.call(params);
         // assert
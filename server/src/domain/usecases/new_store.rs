@@ -1,7 +1,8 @@
 //
 // Copyright (c) 2022 Nathan Fiedler
 //
-use crate::domain::entities::{Store, StoreType};
+use crate::domain::entities::{Event, EventKind, Store, StoreType};
+use crate::domain::managers::events;
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
@@ -30,6 +31,11 @@ impl super::UseCase<Store, Params> for NewStore {
             properties: params.properties,
         };
         self.repo.put_store(&store)?;
+        events::record(
+            Event::new(EventKind::StoreCreated, &store.id)
+                .detail("type", store.store_type.to_string())
+                .detail("label", store.label.clone()),
+        );
         Ok(store)
     }
 }
@@ -1,11 +1,15 @@
 //
 // Copyright (c) 2021 Nathan Fiedler
 //
+use crate::domain::entities::{Checksum, Message, MessageCode, Pack, PackLocation, NULL_SHA1};
+use crate::domain::managers::maintenance;
+use crate::domain::managers::progress::{OperationKind, Progress, Reporter};
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Error};
 use log::info;
 use std::cmp;
 use std::fmt;
+use std::str::FromStr;
 
 pub struct PruneExtraPacks {
     repo: Box<dyn RecordRepository>,
@@ -20,12 +24,11 @@ impl PruneExtraPacks {
 impl super::UseCase<u32, Params> for PruneExtraPacks {
     fn call(&self, params: Params) -> Result<u32, Error> {
         if let Some(store) = self.repo.get_store(&params.store_id)? {
-            // Find all packs and database snapshot packs. Pruning the database
-            // packs prematurely leads to possible data loss and may also incur
-            // early deletion fees.
-            let mut all_packs = self.repo.get_packs(&store.id)?;
-            let mut databases = self.repo.get_databases()?;
-            all_packs.append(&mut databases);
+            // the packs of a running backup are not yet recorded in the database
+            let _guard = maintenance::lock_packs()
+                .ok_or_else(|| anyhow!("cannot prune packs while backup is running"))?;
+            let progress = Reporter::new(OperationKind::Prune, &store.id);
+            let all_packs = expected_packs(self.repo.as_ref(), &store.id)?;
             info!(
                 "PruneExtra expecting {} packs in store {}",
                 all_packs.len(),
@@ -34,13 +37,49 @@ impl super::UseCase<u32, Params> for PruneExtraPacks {
             let pack_repo = self.repo.build_pack_repo(&store)?;
             let count = pack_repo.prune_extra(&store.id, &all_packs)?;
             info!("PruneExtra removed {} packs from store {}", count, store.id);
+            progress.finish(None);
             Ok(count)
         } else {
-            Err(anyhow!(format!("no such store: {}", params.store_id)))
+            Err(Message::new(MessageCode::NoSuchStore)
+                .with("id", &params.store_id)
+                .into())
         }
     }
 }
 
+///
+/// Collect the packs that are expected to be found in the given store, along
+/// with the database snapshot packs and the snapshot logs. Pruning the database
+/// packs prematurely leads to possible data loss and may also incur early
+/// deletion fees.
+///
+/// The pack records are streamed from the database and only the locations
+/// within the store are retained, which keeps memory bounded by the size of
+/// the store rather than that of the entire system.
+///
+pub fn expected_packs(repo: &dyn RecordRepository, store_id: &str) -> Result<Vec<Pack>, Error> {
+    let mut all_packs: Vec<Pack> = Vec::new();
+    repo.visit_packs(&mut |pack| {
+        let locations: Vec<PackLocation> = pack
+            .locations
+            .into_iter()
+            .filter(|l| l.store == store_id)
+            .collect();
+        if !locations.is_empty() {
+            all_packs.push(Pack::new(pack.digest, locations));
+        }
+        Ok(true)
+    })?;
+    let mut databases = repo.get_databases()?;
+    all_packs.append(&mut databases);
+    // likewise the snapshot logs, which are not packs at all
+    for location in repo.get_chain_locations()? {
+        let digest = Checksum::from_str(NULL_SHA1)?;
+        all_packs.push(Pack::new(digest, vec![location]));
+    }
+    Ok(all_packs)
+}
+
 pub struct Params {
     /// Unique identifier of the store.
     store_id: String,
@@ -90,7 +129,9 @@ mod tests {
         mock.expect_get_store()
             .with(eq("cafebabe"))
             .returning(move |_| Ok(Some(store.clone())));
-        mock.expect_get_packs().returning(|_| Ok(Vec::new()));
+        mock.expect_visit_packs().returning(|_| Ok(()));
+        mock.expect_get_chain_locations()
+            .returning(|| Ok(Vec::new()));
         mock.expect_get_databases().returning(|| Ok(Vec::new()));
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
@@ -123,22 +164,28 @@ mod tests {
         mock.expect_get_store()
             .with(eq("cafebabe"))
             .returning(move |_| Ok(Some(store.clone())));
-        mock.expect_get_packs().returning(|_| {
+        mock.expect_visit_packs().returning(|visitor| {
             let digest = Checksum::SHA1(String::from("bf24db8ccd274daad5fe73a71b95cd00ffa56a37"));
-            let coords = vec![PackLocation::new("store1", "bucket1", "object1")];
-            let pack1 = Pack::new(digest.clone(), coords);
+            let coords = vec![PackLocation::new("cafebabe", "bucket1", "object1")];
+            visitor(Pack::new(digest, coords))?;
             let digest = Checksum::SHA1(String::from("4a285c30855fde0a195f3bdbd5e2663338f7510a"));
-            let coords = vec![PackLocation::new("store1", "bucket1", "object2")];
-            let pack2 = Pack::new(digest.clone(), coords);
+            let coords = vec![
+                PackLocation::new("store1", "bucket1", "object2"),
+                PackLocation::new("cafebabe", "bucket1", "object2"),
+            ];
+            visitor(Pack::new(digest, coords))?;
+            // pack that lives in another store entirely
             let digest = Checksum::SHA1(String::from("ed841695851abdcfe6a50ce3d01d770eb053356b"));
             let coords = vec![PackLocation::new("store1", "bucket1", "object3")];
-            let pack3 = Pack::new(digest.clone(), coords);
-            Ok(vec![pack1, pack2, pack3])
+            visitor(Pack::new(digest, coords))?;
+            Ok(())
         });
+        mock.expect_get_chain_locations()
+            .returning(|| Ok(Vec::new()));
         mock.expect_get_databases().returning(|| {
             let digest = Checksum::SHA1(String::from("e449af1b9c5561b424b8c199be502bbe06b84af9"));
             let coords = vec![PackLocation::new(
-                "store1",
+                "cafebabe",
                 "9819f08f363c5d58ac4a5b54f7a0cc25",
                 "01EE40MSWC12YYVG67GN9XSQEA",
             )];
@@ -176,22 +223,28 @@ mod tests {
         mock.expect_get_store()
             .with(eq("cafebabe"))
             .returning(move |_| Ok(Some(store.clone())));
-        mock.expect_get_packs().returning(|_| {
+        mock.expect_visit_packs().returning(|visitor| {
             let digest = Checksum::SHA1(String::from("bf24db8ccd274daad5fe73a71b95cd00ffa56a37"));
-            let coords = vec![PackLocation::new("store1", "bucket1", "object1")];
-            let pack1 = Pack::new(digest.clone(), coords);
+            let coords = vec![PackLocation::new("cafebabe", "bucket1", "object1")];
+            visitor(Pack::new(digest, coords))?;
             let digest = Checksum::SHA1(String::from("4a285c30855fde0a195f3bdbd5e2663338f7510a"));
-            let coords = vec![PackLocation::new("store1", "bucket1", "object2")];
-            let pack2 = Pack::new(digest.clone(), coords);
+            let coords = vec![
+                PackLocation::new("store1", "bucket1", "object2"),
+                PackLocation::new("cafebabe", "bucket1", "object2"),
+            ];
+            visitor(Pack::new(digest, coords))?;
+            // pack that lives in another store entirely
             let digest = Checksum::SHA1(String::from("ed841695851abdcfe6a50ce3d01d770eb053356b"));
             let coords = vec![PackLocation::new("store1", "bucket1", "object3")];
-            let pack3 = Pack::new(digest.clone(), coords);
-            Ok(vec![pack1, pack2, pack3])
+            visitor(Pack::new(digest, coords))?;
+            Ok(())
         });
+        mock.expect_get_chain_locations()
+            .returning(|| Ok(Vec::new()));
         mock.expect_get_databases().returning(|| {
             let digest = Checksum::SHA1(String::from("e449af1b9c5561b424b8c199be502bbe06b84af9"));
             let coords = vec![PackLocation::new(
-                "store1",
+                "cafebabe",
                 "9819f08f363c5d58ac4a5b54f7a0cc25",
                 "01EE40MSWC12YYVG67GN9XSQEA",
             )];
@@ -200,7 +253,17 @@ mod tests {
         });
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
-            mock_store.expect_prune_extra().returning(|_, _| Ok(42));
+            mock_store
+                .expect_prune_extra()
+                .withf(|_, packs| {
+                    // two packs in this store, plus the database snapshot
+                    packs.len() == 3
+                        && packs
+                            .iter()
+                            .flat_map(|p| p.locations.iter())
+                            .all(|l| l.store == "cafebabe")
+                })
+                .returning(|_, _| Ok(42));
             Ok(Box::new(mock_store))
         });
         // act
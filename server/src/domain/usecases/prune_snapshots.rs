@@ -0,0 +1,482 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Event, EventKind, Message, MessageCode, RetentionPolicy, Snapshot,
+};
+use crate::domain::managers::events;
+use crate::domain::managers::state::StateStore;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use log::info;
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+const HOUR: i64 = 3_600;
+const DAY: i64 = 86_400;
+const WEEK: i64 = 604_800;
+
+// Number of weekly snapshots retained in the absence of a retention policy.
+const MAX_WEEKLY: usize = 52;
+
+// Identifies the calendar period (day, week, month, or year) of a date/time.
+type Period = fn(&DateTime<Utc>) -> (i32, u32);
+
+///
+/// Thin out the snapshots of a dataset according to its retention policy (see
+/// `Dataset::retention_policy()`). Without a policy, the snapshots are spaced
+/// further apart as they age: all snapshots from the last 24 hours, one per
+/// hour for the last week, one per day for the last month, and one per week
+/// beyond that, for up to 52 weeks.
+///
+/// The snapshots that are retained are re-linked to form a new chain, keeping
+/// their digests, and the others are removed from the database. Returns the
+/// digests of the snapshots that were (or, for a dry run, would be) removed.
+///
+pub struct PruneSnapshots {
+    repo: Box<dyn RecordRepository>,
+    state: Arc<dyn StateStore>,
+}
+
+impl PruneSnapshots {
+    pub fn new(repo: Box<dyn RecordRepository>, state: Arc<dyn StateStore>) -> Self {
+        Self { repo, state }
+    }
+}
+
+impl super::UseCase<Vec<Checksum>, Params> for PruneSnapshots {
+    fn call(&self, params: Params) -> Result<Vec<Checksum>, Error> {
+        prune_snapshots(
+            self.repo.as_ref(),
+            self.state.as_ref(),
+            &params.dataset_id,
+            params.dry_run,
+        )
+    }
+}
+
+// Thin out the snapshots of the dataset, as described for `PruneSnapshots`,
+// such that the scheduler can do the same for those datasets that have a
+// prune schedule.
+pub(crate) fn prune_snapshots(
+    repo: &dyn RecordRepository,
+    state: &dyn StateStore,
+    dataset_id: &str,
+    dry_run: bool,
+) -> Result<Vec<Checksum>, Error> {
+    if !dry_run {
+        let redux = state.get_state();
+        if let Some(backup) = redux.backups(dataset_id) {
+            if backup.end_time().is_none() {
+                return Err(anyhow!("cannot prune snapshots while backup is running"));
+            }
+        }
+    }
+    let policy = match repo.get_dataset(dataset_id)? {
+        Some(dataset) => dataset.retention_policy(),
+        None => RetentionPolicy::default(),
+    };
+    let snapshots = get_snapshots(repo, dataset_id)?;
+    let keep = select_retained(&snapshots, &policy, Utc::now());
+    let removed: Vec<Checksum> = snapshots
+        .iter()
+        .filter(|s| !keep.contains(&s.digest))
+        .map(|s| s.digest.clone())
+        .collect();
+    if dry_run || removed.is_empty() {
+        return Ok(removed);
+    }
+    // Rebuild the chain from oldest to newest, updating the parent of those
+    // snapshots whose parent has been removed. The digest of a snapshot is its
+    // identifier, referenced from elsewhere in the database and in the stores,
+    // so it does not change. The removed records are deleted only once the new
+    // chain is complete.
+    let mut parent: Option<Checksum> = None;
+    for snapshot in snapshots.iter().rev() {
+        if keep.contains(&snapshot.digest) {
+            if snapshot.parent != parent {
+                let mut relinked = snapshot.clone();
+                relinked.parent = parent;
+                repo.put_snapshot(&relinked)?;
+            }
+            parent = Some(snapshot.digest.clone());
+        }
+    }
+    for digest in removed.iter() {
+        repo.delete_snapshot(digest)?;
+    }
+    info!(
+        "PruneSnapshots removed {} snapshots from dataset {}",
+        removed.len(),
+        dataset_id
+    );
+    events::record(
+        Event::new(EventKind::SnapshotsPruned, dataset_id)
+            .detail("count", removed.len().to_string()),
+    );
+    Ok(removed)
+}
+
+// Collect the snapshots for the dataset, from newest to oldest.
+pub(crate) fn get_snapshots(
+    repo: &dyn RecordRepository,
+    dataset_id: &str,
+) -> Result<Vec<Snapshot>, Error> {
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    let mut next = repo.get_latest_snapshot(dataset_id)?;
+    while let Some(digest) = next {
+        let snapshot = repo
+            .get_snapshot(&digest)?
+            .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &digest))?;
+        next = snapshot.parent.clone();
+        snapshots.push(snapshot);
+    }
+    Ok(snapshots)
+}
+
+// Determine which of the snapshots (newest first) are kept by the retention
+// policy, or by the default spacing if the policy is empty.
+pub(crate) fn select_retained(
+    snapshots: &[Snapshot],
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> HashSet<Checksum> {
+    if policy.is_empty() {
+        select_snapshots(snapshots, now)
+    } else {
+        select_by_policy(snapshots, policy, now)
+    }
+}
+
+// Determine which of the snapshots (newest first) are to be retained.
+//
+// Within each interval, the oldest snapshot is the one that is kept, so that
+// the selection remains stable as the snapshots continue to age. The buckets
+// are based on the absolute time for the same reason. Of the weekly snapshots,
+// only the newest `MAX_WEEKLY` are kept.
+fn select_snapshots(snapshots: &[Snapshot], now: DateTime<Utc>) -> HashSet<Checksum> {
+    let mut keep: HashSet<Checksum> = HashSet::new();
+    if let Some(latest) = snapshots.first() {
+        keep.insert(latest.digest.clone());
+    }
+    let mut buckets: HashSet<(i64, i64)> = HashSet::new();
+    let mut weekly: Vec<&Checksum> = Vec::new();
+    for snapshot in snapshots.iter().rev() {
+        let age = (now - snapshot.start_time).num_seconds();
+        let spacing = if age < DAY {
+            keep.insert(snapshot.digest.clone());
+            continue;
+        } else if age < WEEK {
+            HOUR
+        } else if age < 30 * DAY {
+            DAY
+        } else {
+            WEEK
+        };
+        let bucket = snapshot.start_time.timestamp().div_euclid(spacing);
+        if buckets.insert((spacing, bucket)) {
+            if spacing == WEEK {
+                weekly.push(&snapshot.digest);
+            } else {
+                keep.insert(snapshot.digest.clone());
+            }
+        }
+    }
+    // the weekly snapshots were collected from oldest to newest
+    let skip = weekly.len().saturating_sub(MAX_WEEKLY);
+    for digest in weekly.into_iter().skip(skip) {
+        keep.insert(digest.clone());
+    }
+    keep
+}
+
+// Determine which of the snapshots (newest first) are retained by the policy.
+//
+// For the calendar rules, the newest snapshot in each period is kept, and only
+// those periods that have snapshots are counted, such that gaps in the backups
+// do not cause older snapshots to be removed sooner.
+fn select_by_policy(
+    snapshots: &[Snapshot],
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> HashSet<Checksum> {
+    let mut keep: HashSet<Checksum> = HashSet::new();
+    if let Some(latest) = snapshots.first() {
+        keep.insert(latest.digest.clone());
+    }
+    if let Some(count) = policy.count {
+        for snapshot in snapshots.iter().take(count as usize) {
+            keep.insert(snapshot.digest.clone());
+        }
+    }
+    if let Some(days) = policy.days {
+        let cutoff = now - TimeDelta::days(days as i64);
+        for snapshot in snapshots.iter().filter(|s| s.start_time >= cutoff) {
+            keep.insert(snapshot.digest.clone());
+        }
+    }
+    let periods: [(Option<u32>, Period); 4] = [
+        (policy.daily, |t| (t.year(), t.ordinal())),
+        (policy.weekly, |t| {
+            (t.iso_week().year(), t.iso_week().week())
+        }),
+        (policy.monthly, |t| (t.year(), t.month())),
+        (policy.yearly, |t| (t.year(), 0)),
+    ];
+    for (limit, period) in periods {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let mut seen: HashSet<(i32, u32)> = HashSet::new();
+        for snapshot in snapshots.iter() {
+            if seen.len() >= limit as usize {
+                break;
+            }
+            if seen.insert(period(&snapshot.start_time)) {
+                keep.insert(snapshot.digest.clone());
+            }
+        }
+    }
+    keep
+}
+
+pub struct Params {
+    /// Identifier of the dataset whose snapshots are to be pruned.
+    dataset_id: String,
+    /// If true, only report which snapshots would be removed.
+    dry_run: bool,
+}
+
+impl Params {
+    pub fn new(dataset_id: String, dry_run: bool) -> Self {
+        Self {
+            dataset_id,
+            dry_run,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.dataset_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Dataset, FileCounts};
+    use crate::domain::managers::state::{MockStateStore, State};
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::Duration;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // Build a chain of snapshots with the given ages in hours, newest first.
+    fn make_chain(hours: &[i64]) -> Vec<Snapshot> {
+        make_chain_at(Utc::now(), hours)
+    }
+
+    // Build a chain of snapshots with ages in hours relative to the given time.
+    fn make_chain_at(now: DateTime<Utc>, hours: &[i64]) -> Vec<Snapshot> {
+        let mut chain: Vec<Snapshot> = Vec::new();
+        let mut parent: Option<Checksum> = None;
+        for age in hours.iter().rev() {
+            let tree = Checksum::SHA1(format!("cafebabe{}", age));
+            let mut snapshot = Snapshot::new(parent, tree, FileCounts::default());
+            snapshot.set_start_time(now - Duration::hours(*age));
+            let snapshot = snapshot.with_parent(snapshot.parent.clone());
+            parent = Some(snapshot.digest.clone());
+            chain.insert(0, snapshot);
+        }
+        chain
+    }
+
+    #[test]
+    fn test_select_snapshots() {
+        // two recent snapshots, several within the same hour from two days
+        // ago, and several within the same week from months ago
+        let hours: Vec<i64> = vec![1, 2, 48, 48, 48, 2000, 2001, 2002];
+        let chain = make_chain(&hours);
+        let keep = select_snapshots(&chain, Utc::now());
+        assert!(keep.contains(&chain[0].digest));
+        assert!(keep.contains(&chain[1].digest));
+        // only the oldest in each interval is kept
+        assert!(!keep.contains(&chain[2].digest));
+        assert!(!keep.contains(&chain[3].digest));
+        assert!(keep.contains(&chain[4].digest));
+        assert!(keep.len() < chain.len());
+        assert!(keep.contains(&chain[7].digest));
+    }
+
+    #[test]
+    fn test_select_snapshots_weekly_limit() {
+        // one snapshot every week for two years, beyond the first month
+        let hours: Vec<i64> = (0..104).map(|w| 800 + w * 168).collect();
+        let chain = make_chain(&hours);
+        let keep = select_snapshots(&chain, Utc::now());
+        assert_eq!(keep.len(), MAX_WEEKLY);
+        // the newest are kept and the oldest are removed
+        assert!(keep.contains(&chain[0].digest));
+        assert!(keep.contains(&chain[MAX_WEEKLY - 1].digest));
+        assert!(!keep.contains(&chain[MAX_WEEKLY].digest));
+        assert!(!keep.contains(&chain[103].digest));
+    }
+
+    #[test]
+    fn test_select_by_policy() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        // two today, two yesterday, one the day before, two in May, and one
+        // from the previous year
+        let hours: Vec<i64> = vec![1, 3, 26, 30, 50, 960, 1080, 9600];
+        let chain = make_chain_at(now, &hours);
+        let selected = |policy: RetentionPolicy| -> Vec<usize> {
+            let keep = select_by_policy(&chain, &policy, now);
+            let mut indices: Vec<usize> = (0..chain.len())
+                .filter(|i| keep.contains(&chain[*i].digest))
+                .collect();
+            indices.sort();
+            indices
+        };
+        let policy = RetentionPolicy {
+            count: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(selected(policy), vec![0, 1, 2]);
+        let policy = RetentionPolicy {
+            days: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(selected(policy), vec![0, 1]);
+        let policy = RetentionPolicy {
+            daily: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(selected(policy), vec![0, 2]);
+        let policy = RetentionPolicy {
+            monthly: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(selected(policy), vec![0, 5]);
+        let policy = RetentionPolicy {
+            daily: Some(2),
+            monthly: Some(2),
+            yearly: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(selected(policy), vec![0, 2, 5, 7]);
+    }
+
+    #[test]
+    fn test_prune_snapshots_policy() {
+        // arrange
+        let chain = make_chain(&[1, 2, 3]);
+        let latest = chain[0].digest.clone();
+        let mut snapshots: HashMap<Checksum, Snapshot> = HashMap::new();
+        for snapshot in chain.iter() {
+            snapshots.insert(snapshot.digest.clone(), snapshot.clone());
+        }
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| {
+            let mut dataset = Dataset::new(Path::new("/home/planet"));
+            dataset.properties.insert("retain_count".into(), "2".into());
+            Ok(Some(dataset))
+        });
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        let state = MockStateStore::new();
+        // act
+        let usecase = PruneSnapshots::new(Box::new(mock), Arc::new(state));
+        let params = Params::new("cafebabe".into(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let removed = result.unwrap();
+        // without the policy, all of these recent snapshots would be kept
+        assert_eq!(removed, vec![chain[2].digest.clone()]);
+    }
+
+    #[test]
+    fn test_prune_snapshots_dry_run() {
+        // arrange
+        let chain = make_chain(&[1, 48, 48]);
+        let latest = chain[0].digest.clone();
+        let mut snapshots: HashMap<Checksum, Snapshot> = HashMap::new();
+        for snapshot in chain.iter() {
+            snapshots.insert(snapshot.digest.clone(), snapshot.clone());
+        }
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        let state = MockStateStore::new();
+        // act
+        let usecase = PruneSnapshots::new(Box::new(mock), Arc::new(state));
+        let params = Params::new("cafebabe".into(), true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let removed = result.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0], chain[1].digest);
+    }
+
+    #[test]
+    fn test_prune_snapshots_relink() {
+        // arrange
+        let chain = make_chain(&[1, 48, 48]);
+        let latest = chain[0].digest.clone();
+        let removed_digest = chain[1].digest.clone();
+        let oldest_digest = chain[2].digest.clone();
+        let mut snapshots: HashMap<Checksum, Snapshot> = HashMap::new();
+        for snapshot in chain.iter() {
+            snapshots.insert(snapshot.digest.clone(), snapshot.clone());
+        }
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.get(digest).cloned()));
+        // the newest snapshot is re-linked to the oldest one, keeping its digest
+        let latest_digest = chain[0].digest.clone();
+        mock.expect_put_snapshot()
+            .withf(move |s| s.digest == latest_digest && s.parent.as_ref() == Some(&oldest_digest))
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_put_latest_snapshot().never();
+        let deleted_digest = removed_digest.clone();
+        mock.expect_delete_snapshot()
+            .withf(move |d| d == &deleted_digest)
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut state = MockStateStore::new();
+        state.expect_get_state().returning(State::default);
+        // act
+        let usecase = PruneSnapshots::new(Box::new(mock), Arc::new(state));
+        let params = Params::new("cafebabe".into(), false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let removed = result.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0], removed_digest);
+    }
+}
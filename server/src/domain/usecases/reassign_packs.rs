@@ -1,9 +1,9 @@
 //
 // Copyright (c) 2023 Nathan Fiedler
 //
-use crate::domain::entities::Pack;
+use crate::domain::entities::{Message, MessageCode, Pack};
 use crate::domain::repositories::RecordRepository;
-use anyhow::{anyhow, Error};
+use anyhow::Error;
 use log::info;
 use std::cmp;
 use std::fmt;
@@ -11,7 +11,7 @@ use std::fmt;
 ///
 /// Change the store identifier from `source` to `target`, updating all pack
 /// records that have a matching location entry.
-/// 
+///
 /// Note that this action may result in pack records that refer to locations
 /// that do not really exist. For simple pack stores like the local or sftp
 /// stores, this should be safe. However, pack stores that rely on cloud storage
@@ -31,10 +31,9 @@ impl ReassignPacks {
 impl super::UseCase<u64, Params> for ReassignPacks {
     fn call(&self, params: Params) -> Result<u64, Error> {
         // purely for error-checking, ensure new store exists
-        let _ = self
-            .repo
-            .get_store(&params.new_store_id)?
-            .ok_or_else(|| anyhow!(format!("no such store: {}", params.new_store_id)))?;
+        let _ = self.repo.get_store(&params.new_store_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchStore).with("id", &params.new_store_id)
+        })?;
         let matching_packs = self.repo.get_packs(&params.old_store_id)?;
         info!(
             "ReassignPacks: will update {} packs with store {}",
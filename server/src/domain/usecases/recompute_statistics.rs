@@ -0,0 +1,445 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, FileCounts, Message, MessageCode, Snapshot, StatisticsReport, TreeReference,
+};
+use crate::domain::managers::state::StateStore;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+///
+/// Rebuild the file counts of every snapshot of a dataset by walking the trees
+/// and files that were recorded, correcting any that have drifted, such as
+/// after manual repairs to the database or a prune that failed part way.
+///
+/// The deduplication statistics of the dataset are computed anew as well,
+/// while the record counts are reported as they are found in the database.
+///
+/// Snapshots that are not yet complete are left alone, as are those whose
+/// trees or files are missing records, which are reported as problems. The
+/// statistics cannot be recomputed while a backup of the dataset is running.
+///
+pub struct RecomputeStatistics {
+    repo: Box<dyn RecordRepository>,
+    state: Arc<dyn StateStore>,
+}
+
+impl RecomputeStatistics {
+    pub fn new(repo: Box<dyn RecordRepository>, state: Arc<dyn StateStore>) -> Self {
+        Self { repo, state }
+    }
+}
+
+impl super::UseCase<StatisticsReport, Params> for RecomputeStatistics {
+    fn call(&self, params: Params) -> Result<StatisticsReport, Error> {
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
+        if let Some(backup) = self.state.get_state().backups(&dataset.id) {
+            if backup.end_time().is_none() {
+                return Err(anyhow!(
+                    "cannot recompute statistics while backup is running"
+                ));
+            }
+        }
+        let mut report = StatisticsReport {
+            dataset_id: dataset.id.clone(),
+            ..Default::default()
+        };
+        // collect the completed snapshots from newest to oldest
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        let mut next = self.repo.get_latest_snapshot(&dataset.id)?;
+        while let Some(digest) = next {
+            let Some(snapshot) = self.repo.get_snapshot(&digest)? else {
+                report
+                    .problems
+                    .push(format!("snapshot {} is missing", digest));
+                break;
+            };
+            next = snapshot.parent.clone();
+            if snapshot.end_time.is_some() {
+                snapshots.push(snapshot);
+            }
+        }
+        let mut counter = CountWalker::new(self.repo.as_ref());
+        let mut intact: Vec<Snapshot> = Vec::new();
+        for mut snapshot in snapshots.into_iter() {
+            let mut counts = FileCounts::default();
+            counter.missing.clear();
+            counter.walk_tree(&snapshot.tree, &mut counts)?;
+            report.snapshots += 1;
+            if !counter.missing.is_empty() {
+                // the counts cannot be trusted without all of the records
+                for missing in counter.missing.iter() {
+                    report
+                        .problems
+                        .push(format!("snapshot {}: {}", snapshot.digest, missing));
+                }
+                continue;
+            }
+            if intact.is_empty() {
+                report.files = counts.total_files();
+                report.bytes = counts.categories.values().map(|c| c.bytes).sum();
+            }
+            if counts != snapshot.file_counts {
+                report.problems.extend(describe_drift(
+                    &snapshot.digest,
+                    &snapshot.file_counts,
+                    &counts,
+                ));
+                snapshot.file_counts = counts;
+                self.repo.put_snapshot(&snapshot)?;
+                report.repaired += 1;
+            }
+            intact.push(snapshot);
+        }
+        let stats = super::dedup_stats::compute_stats(self.repo.as_ref(), &dataset.id, &intact)?;
+        self.repo.put_dedup_stats(&stats)?;
+        report.records = self.repo.get_entity_counts()?;
+        Ok(report)
+    }
+}
+
+// Describe the ways in which the recorded file counts differ from the counts
+// that were found by walking the snapshot.
+fn describe_drift(digest: &Checksum, stored: &FileCounts, found: &FileCounts) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+    if stored.directories != found.directories {
+        problems.push(format!(
+            "snapshot {}: {} directories recorded, {} found",
+            digest, stored.directories, found.directories
+        ));
+    }
+    if stored.symlinks != found.symlinks {
+        problems.push(format!(
+            "snapshot {}: {} symlinks recorded, {} found",
+            digest, stored.symlinks, found.symlinks
+        ));
+    }
+    if stored.total_files() != found.total_files() {
+        problems.push(format!(
+            "snapshot {}: {} files recorded, {} found",
+            digest,
+            stored.total_files(),
+            found.total_files()
+        ));
+    }
+    if problems.is_empty() {
+        problems.push(format!(
+            "snapshot {}: file sizes or categories differ",
+            digest
+        ));
+    }
+    problems
+}
+
+// Walks the trees of the snapshots, remembering the length of each file such
+// that the file records are read only once, and collecting descriptions of the
+// records that were found to be missing.
+struct CountWalker<'a> {
+    repo: &'a dyn RecordRepository,
+    file_lengths: HashMap<Checksum, u64>,
+    missing: Vec<String>,
+}
+
+impl<'a> CountWalker<'a> {
+    fn new(repo: &'a dyn RecordRepository) -> Self {
+        Self {
+            repo,
+            file_lengths: HashMap::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    // Add the entries of the tree and its subtrees to the file counts, in the
+    // same manner as the backup while scanning the dataset.
+    fn walk_tree(&mut self, digest: &Checksum, counts: &mut FileCounts) -> Result<(), Error> {
+        let Some(tree) = self.repo.get_tree(digest)? else {
+            self.missing.push(format!("tree {} is missing", digest));
+            return Ok(());
+        };
+        for entry in tree.entries.iter() {
+            match &entry.reference {
+                TreeReference::TREE(subtree) => {
+                    counts.directories += 1;
+                    self.walk_tree(subtree, counts)?;
+                }
+                TreeReference::LINK(_) => counts.symlinks += 1,
+                TreeReference::FILE(file_digest) => {
+                    if let Some(length) = self.file_length(file_digest)? {
+                        counts.register_file(length);
+                        counts.register_category(Path::new(&entry.name), length);
+                    } else {
+                        self.missing
+                            .push(format!("file {} is missing", file_digest));
+                    }
+                }
+                TreeReference::SMALL(contents) => {
+                    let length = contents.len() as u64;
+                    counts.register_file(length);
+                    counts.register_category(Path::new(&entry.name), length);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn file_length(&mut self, digest: &Checksum) -> Result<Option<u64>, Error> {
+        if let Some(length) = self.file_lengths.get(digest) {
+            return Ok(Some(*length));
+        }
+        let Some(file) = self.repo.get_file(digest)? else {
+            return Ok(None);
+        };
+        self.file_lengths.insert(digest.to_owned(), file.length);
+        Ok(Some(file.length))
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset whose statistics are to be recomputed.
+    dataset_id: String,
+}
+
+impl Params {
+    pub fn new(dataset_id: String) -> Self {
+        Self { dataset_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.dataset_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Dataset, File, RecordCounts, Tree, TreeEntry};
+    use crate::domain::managers::state::{BackupAction, StateStoreImpl};
+    use crate::domain::repositories::MockRecordRepository;
+    use chrono::prelude::*;
+
+    fn make_trees() -> (Tree, Tree, File) {
+        let file = File::new(
+            Checksum::BLAKE3("file1".into()),
+            3129,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let mut link = TreeEntry::new(
+            Path::new("../test/fixtures/lorem-ipsum.txt"),
+            TreeReference::LINK(b"lorem-ipsum.txt".to_vec()),
+        );
+        link.name = "link".into();
+        let subtree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(file.digest.clone()),
+                ),
+                link,
+            ],
+            1,
+        );
+        let mut subdir = TreeEntry::new(
+            Path::new("../test/fixtures"),
+            TreeReference::TREE(subtree.digest.clone()),
+        );
+        subdir.name = "docs".into();
+        let root = Tree::new(
+            vec![
+                subdir,
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::SMALL(b"hello".to_vec()),
+                ),
+            ],
+            2,
+        );
+        (root, subtree, file)
+    }
+
+    fn expected_counts() -> FileCounts {
+        let mut counts = FileCounts {
+            directories: 1,
+            symlinks: 1,
+            ..Default::default()
+        };
+        counts.register_file(3129);
+        counts.register_category(Path::new("lorem-ipsum.txt"), 3129);
+        counts.register_file(5);
+        counts.register_category(Path::new("washington-journal.txt"), 5);
+        counts
+    }
+
+    fn mock_repo(snapshot: Snapshot, file_found: bool) -> MockRecordRepository {
+        let (root, subtree, file) = make_trees();
+        let trees = vec![root, subtree];
+        let latest = snapshot.digest.clone();
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        mock.expect_get_file()
+            .returning(move |_| Ok(Some(file.clone()).filter(|_| file_found)));
+        mock.expect_get_chunk().never();
+        mock.expect_put_dedup_stats().times(1).returning(|_| Ok(()));
+        mock.expect_get_entity_counts().returning(|| {
+            Ok(RecordCounts {
+                snapshot: 1,
+                tree: 2,
+                file: 1,
+                ..Default::default()
+            })
+        });
+        mock
+    }
+
+    #[test]
+    fn test_recompute_statistics_repaired() {
+        // arrange: the recorded counts are missing everything
+        let (root, _, _) = make_trees();
+        let mut snapshot = Snapshot::new(None, root.digest, FileCounts::default());
+        snapshot.set_end_time(Utc::now());
+        let mut mock = mock_repo(snapshot, true);
+        mock.expect_put_snapshot()
+            .withf(|s| s.file_counts == expected_counts())
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        let report = result.unwrap();
+        assert_eq!(report.snapshots, 1);
+        assert_eq!(report.repaired, 1);
+        assert_eq!(report.files, 2);
+        assert_eq!(report.bytes, 3134);
+        assert_eq!(report.records.tree, 2);
+        assert_eq!(report.problems.len(), 3);
+        assert!(report.problems[0].contains("0 directories recorded, 1 found"));
+        assert!(report.problems[2].contains("0 files recorded, 2 found"));
+    }
+
+    #[test]
+    fn test_recompute_statistics_intact() {
+        // arrange
+        let (root, _, _) = make_trees();
+        let mut snapshot = Snapshot::new(None, root.digest, expected_counts());
+        snapshot.set_end_time(Utc::now());
+        let mut mock = mock_repo(snapshot, true);
+        mock.expect_put_snapshot().never();
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        let report = result.unwrap();
+        assert_eq!(report.snapshots, 1);
+        assert_eq!(report.repaired, 0);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn test_recompute_statistics_incomplete() {
+        // arrange: the snapshot of a backup that has not yet finished
+        let (root, _, _) = make_trees();
+        let snapshot = Snapshot::new(None, root.digest, FileCounts::default());
+        let mut mock = mock_repo(snapshot, false);
+        mock.expect_put_snapshot().never();
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        let report = result.unwrap();
+        assert_eq!(report.snapshots, 0);
+        assert_eq!(report.repaired, 0);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn test_recompute_statistics_missing() {
+        // arrange: the file record has gone missing
+        let (root, _, _) = make_trees();
+        let mut snapshot = Snapshot::new(None, root.digest, FileCounts::default());
+        snapshot.set_end_time(Utc::now());
+        let mut mock = mock_repo(snapshot, false);
+        mock.expect_put_snapshot().never();
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        let report = result.unwrap();
+        assert_eq!(report.snapshots, 1);
+        assert_eq!(report.repaired, 0);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].contains("is missing"));
+    }
+
+    #[test]
+    fn test_recompute_statistics_running() {
+        // arrange
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let dataset_id = dataset.id.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(dataset.clone())));
+        mock.expect_get_latest_snapshot().never();
+        mock.expect_put_snapshot().never();
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        state.backup_event(BackupAction::Start(dataset_id.clone()));
+        // act
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new(dataset_id);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("backup is running"));
+    }
+
+    #[test]
+    fn test_recompute_statistics_no_dataset() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| Ok(None));
+        // act
+        let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+        let usecase = RecomputeStatistics::new(Box::new(mock), state);
+        let params = Params::new("nosuchdataset".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such dataset"));
+    }
+}
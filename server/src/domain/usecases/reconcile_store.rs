@@ -0,0 +1,244 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Message, MessageCode, MissingObject, OrphanedObject, Pack, ReconciliationReport,
+    RecordKind, NULL_SHA1,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use log::{info, warn};
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// Compare the objects in a store with the pack, database, and snapshot log
+/// records that refer to that store, reporting the objects that no record
+/// references and the records whose object is not in the store.
+///
+/// Nothing is changed or retrieved, making this a safe way to see what
+/// `PruneExtraPacks` would remove, and what `RestoreMissing` would replace,
+/// before doing either. The size of each orphaned object is included if the
+/// store can report it without retrieving the object.
+///
+pub struct ReconcileStore {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl ReconcileStore {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    // Gather every record that expects an object to be in the store, the same
+    // as those that prune_extra takes into consideration.
+    fn expected_objects(&self, store_id: &str) -> Result<Vec<(Pack, RecordKind)>, Error> {
+        let mut records: Vec<(Pack, RecordKind)> = Vec::new();
+        for pack in self.repo.get_packs(store_id)? {
+            records.push((pack, RecordKind::Pack));
+        }
+        for pack in self.repo.get_databases()? {
+            records.push((pack, RecordKind::Database));
+        }
+        for location in self.repo.get_chain_locations()? {
+            let digest = Checksum::from_str(NULL_SHA1)?;
+            records.push((Pack::new(digest, vec![location]), RecordKind::Chain));
+        }
+        Ok(records)
+    }
+}
+
+impl super::UseCase<ReconciliationReport, Params> for ReconcileStore {
+    fn call(&self, params: Params) -> Result<ReconciliationReport, Error> {
+        let store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        let records = self.expected_objects(&store.id)?;
+        let expected: HashSet<(&str, &str)> = records
+            .iter()
+            .flat_map(|(p, _)| p.locations.iter())
+            .filter(|l| l.store == store.id)
+            .map(|l| (l.bucket.as_str(), l.object.as_str()))
+            .collect();
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        let locations = pack_repo.list_locations(&store.id)?;
+        let mut report = ReconciliationReport::new(&store.id);
+        for location in locations.iter() {
+            report.objects += 1;
+            if expected.contains(&(location.bucket.as_str(), location.object.as_str())) {
+                report.matched += 1;
+                continue;
+            }
+            let size = match pack_repo.object_size(location) {
+                Ok(size) => size,
+                Err(err) => {
+                    warn!(
+                        "could not get size of {}/{} in store {}: {:?}",
+                        location.bucket, location.object, store.id, err
+                    );
+                    None
+                }
+            };
+            report.orphaned.push(OrphanedObject {
+                location: location.clone(),
+                size,
+            });
+        }
+        let present: HashSet<(&str, &str)> = locations
+            .iter()
+            .map(|l| (l.bucket.as_str(), l.object.as_str()))
+            .collect();
+        for (pack, kind) in records.iter() {
+            for location in pack.locations.iter().filter(|l| l.store == store.id) {
+                if !present.contains(&(location.bucket.as_str(), location.object.as_str())) {
+                    report.missing.push(MissingObject {
+                        digest: pack.digest.clone(),
+                        kind: *kind,
+                        location: location.clone(),
+                    });
+                }
+            }
+        }
+        info!(
+            "ReconcileStore found {} orphaned and {} missing of {} objects in store {}",
+            report.orphaned.len(),
+            report.missing.len(),
+            report.objects,
+            store.id
+        );
+        Ok(report)
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+}
+
+impl Params {
+    pub fn new(store_id: String) -> Self {
+        Self { store_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.store_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{PackLocation, Store, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_reconcile_store() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| {
+            Ok(Some(Store {
+                id: "store1".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "local".to_owned(),
+                properties: HashMap::new(),
+            }))
+        });
+        mock.expect_get_packs().returning(|_| {
+            let pack1 = Pack::new(
+                Checksum::SHA1("bf24db8ccd274daad5fe73a71b95cd00ffa56a37".to_owned()),
+                vec![
+                    PackLocation::new("store1", "bucket1", "object1"),
+                    PackLocation::new("store2", "bucket1", "object1"),
+                ],
+            );
+            let pack2 = Pack::new(
+                Checksum::SHA1("4a285c30855fde0a195f3bdbd5e2663338f7510a".to_owned()),
+                vec![PackLocation::new("store1", "bucket1", "object2")],
+            );
+            Ok(vec![pack1, pack2])
+        });
+        mock.expect_get_databases().returning(|| {
+            let pack = Pack::new(
+                Checksum::SHA1("e449af1b9c5561b424b8c199be502bbe06b84af9".to_owned()),
+                vec![PackLocation::new("store1", "database", "archive1")],
+            );
+            Ok(vec![pack])
+        });
+        mock.expect_get_chain_locations()
+            .returning(|| Ok(vec![PackLocation::new("store1", "database", "chain")]));
+        mock.expect_build_pack_repo().returning(|_| {
+            let mut stores = MockPackRepository::new();
+            stores.expect_list_locations().returning(|_| {
+                Ok(vec![
+                    PackLocation::new("store1", "bucket1", "object1"),
+                    PackLocation::new("store1", "bucket1", "stray1"),
+                    PackLocation::new("store1", "bucket2", "stray2"),
+                    PackLocation::new("store1", "bucket2", "stray3"),
+                    PackLocation::new("store1", "database", "archive1"),
+                ])
+            });
+            stores
+                .expect_object_size()
+                .returning(|location| match location.object.as_str() {
+                    "stray1" => Ok(Some(1024)),
+                    "stray2" => Ok(None),
+                    _ => Err(anyhow!("oh no")),
+                });
+            stores.expect_prune_extra().never();
+            stores.expect_delete_pack().never();
+            stores.expect_retrieve_object().never();
+            Ok(Box::new(stores))
+        });
+        // act
+        let usecase = ReconcileStore::new(Box::new(mock));
+        let params = Params::new("store1".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert_eq!(report.objects, 5);
+        assert_eq!(report.matched, 2);
+        assert_eq!(report.orphaned.len(), 3);
+        assert_eq!(report.orphaned[0].location.object, "stray1");
+        assert_eq!(report.orphaned[0].size, Some(1024));
+        assert_eq!(report.orphaned[1].size, None);
+        assert_eq!(report.orphaned[2].size, None);
+        assert_eq!(report.orphaned_bytes(), 1024);
+        assert_eq!(report.missing.len(), 2);
+        assert_eq!(report.missing[0].kind, RecordKind::Pack);
+        assert_eq!(report.missing[0].location.object, "object2");
+        assert_eq!(report.missing[1].kind, RecordKind::Chain);
+        assert_eq!(report.missing[1].location.object, "chain");
+    }
+
+    #[test]
+    fn test_reconcile_store_no_store() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        // act
+        let usecase = ReconcileStore::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such store"));
+    }
+}
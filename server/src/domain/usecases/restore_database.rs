@@ -2,13 +2,14 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 use crate::domain::managers::state::{RestorerAction, StateStore, SupervisorAction};
+use crate::domain::managers::{catalog, migrate};
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Error};
 use log::{debug, error, info, log_enabled, Level};
-use std::borrow::Cow;
 use std::cmp;
 use std::fmt;
 use std::sync::Arc;
+use store_core::Secret;
 
 pub struct RestoreDatabase {
     repo: Box<dyn RecordRepository>,
@@ -20,7 +21,7 @@ impl RestoreDatabase {
     }
 }
 
-impl<'a> super::UseCase<String, Params<'a>> for RestoreDatabase {
+impl super::UseCase<String, Params> for RestoreDatabase {
     fn call(&self, params: Params) -> Result<String, Error> {
         if log_enabled!(Level::Debug) {
             if let Ok(datasets) = self.repo.get_datasets() {
@@ -36,10 +37,6 @@ impl<'a> super::UseCase<String, Params<'a>> for RestoreDatabase {
             info!("found store {}", store.id);
             let pack_repo = self.repo.build_pack_repo(&store)?;
             let config = self.repo.get_configuration()?;
-            let archive_file = tempfile::NamedTempFile::new()?;
-            let archive_path = archive_file.into_temp_path();
-            info!("retrieving latest database snapshot...");
-            pack_repo.retrieve_latest_database(&config.computer_id, &archive_path)?;
             // By this point we can safely assume that the backup supervisor has
             // completely shut down and released its reference to the database.
             //
@@ -47,11 +44,31 @@ impl<'a> super::UseCase<String, Params<'a>> for RestoreDatabase {
             // in progress, in which case the user will need to either wait or
             // stop the backup before trying again. Of course, a running backup
             // would be unlikely given the use case scenario.
-            info!("restoring database from backup...");
-            self.repo.restore_from_backup(&archive_path, &params.passphrase)
+            info!("restoring database from latest catalog...");
+            let restored = catalog::restore(
+                self.repo.as_ref(),
+                pack_repo.as_ref(),
+                &store.id,
+                &config.computer_id,
+                params.passphrase.expose(),
+            )?;
+            if restored {
+                Ok(())
+            } else {
+                // databases saved by older versions were uploaded whole
+                let archive_file = tempfile::NamedTempFile::new()?;
+                let archive_path = archive_file.into_temp_path();
+                info!("retrieving latest database snapshot...");
+                pack_repo.retrieve_latest_database(&config.computer_id, &archive_path)?;
+                info!("restoring database from backup...");
+                self.repo
+                    .restore_from_backup(&archive_path, params.passphrase.expose())
+            }
         } else {
             Err(anyhow!("no pack stores defined"))
         };
+        // the backup may have been made by an older version
+        let result = result.and_then(|_| migrate::migrate(self.repo.as_ref()).map(|_| ()));
         if let Err(err) = result {
             error!("database restore failed: {}", err);
             Err(err)
@@ -72,38 +89,42 @@ impl<'a> super::UseCase<String, Params<'a>> for RestoreDatabase {
     }
 }
 
-pub struct Params<'a> {
+pub struct Params {
     /// Identifier of the pack store from which to retrieve the database.
     store_id: String,
     /// Reference to the application state store.
     state: Arc<dyn StateStore>,
     /// Pass phrase for decrypting the pack.
-    passphrase: Cow<'a, str>,
+    passphrase: Secret,
 }
 
-impl<'a> Params<'a> {
-    pub fn new<T: Into<String>>(store_id: T, state: Arc<dyn StateStore>, passphrase: T) -> Self {
+impl Params {
+    pub fn new<T: Into<String>, S: Into<Secret>>(
+        store_id: T,
+        state: Arc<dyn StateStore>,
+        passphrase: S,
+    ) -> Self {
         Self {
             store_id: store_id.into(),
             state,
-            passphrase: Cow::from(passphrase.into()),
+            passphrase: passphrase.into(),
         }
     }
 }
 
-impl<'a> fmt::Display for Params<'a> {
+impl fmt::Display for Params {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Params({})", self.store_id)
     }
 }
 
-impl<'a> cmp::PartialEq for Params<'a> {
+impl cmp::PartialEq for Params {
     fn eq(&self, other: &Self) -> bool {
         self.store_id == other.store_id
     }
 }
 
-impl<'a> cmp::Eq for Params<'a> {}
+impl cmp::Eq for Params {}
 
 #[cfg(test)]
 mod tests {
@@ -132,6 +153,9 @@ mod tests {
             .returning(move |_| Ok(Some(store.clone())));
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
+            mock_store
+                .expect_retrieve_latest_catalog()
+                .returning(move |_, _| Ok(false));
             mock_store
                 .expect_retrieve_latest_database()
                 .returning(move |_, _| Ok(()));
@@ -141,6 +165,8 @@ mod tests {
         mock.expect_get_configuration()
             .returning(move || Ok(config.clone()));
         mock.expect_restore_from_backup().returning(|_, _| Ok(()));
+        mock.expect_get_schema_version()
+            .returning(|| Ok(Some(migrate::SCHEMA_VERSION)));
         let mut stater = MockStateStore::new();
         stater
             .expect_stop_supervisor()
@@ -184,6 +210,9 @@ mod tests {
             .returning(move |_| Ok(Some(store.clone())));
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
+            mock_store
+                .expect_retrieve_latest_catalog()
+                .returning(move |_, _| Ok(false));
             mock_store
                 .expect_retrieve_latest_database()
                 .returning(move |_, _| Ok(()));
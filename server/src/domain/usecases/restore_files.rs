@@ -1,14 +1,16 @@
 //
 // Copyright (c) 2023 Nathan Fiedler
 //
-use crate::domain::entities::Checksum;
-use crate::domain::helpers::crypto;
+use crate::domain::entities::{Checksum, Event, EventKind, RestoreVerify};
+use crate::domain::helpers::{crypto, paths};
+use crate::domain::managers::events;
 use crate::domain::managers::restore::{Request, Restorer};
 use anyhow::Error;
 use std::cmp;
 use std::fmt;
 use std::path::PathBuf;
 use std::sync::Arc;
+use store_core::Secret;
 
 pub struct RestoreFiles {
     restorer: Arc<dyn Restorer>,
@@ -22,9 +24,18 @@ impl RestoreFiles {
 
 impl super::UseCase<(), Params> for RestoreFiles {
     fn call(&self, params: Params) -> Result<(), Error> {
+        // the path is joined with the dataset base path, it must not be
+        // allowed to lead anywhere else
+        paths::validate_relative(&params.filepath)?;
         let mut request: Request = params.into();
-        request.passphrase = crypto::get_passphrase();
-        self.restorer.enqueue(request)
+        request.passphrase = crypto::get_passphrase()?;
+        let event = Event::new(EventKind::RestoreRequested, &request.dataset)
+            .detail("tree", request.tree.to_string())
+            .detail("entry", request.entry.clone())
+            .detail("filepath", request.filepath.to_string_lossy());
+        self.restorer.enqueue(request)?;
+        events::record(event);
+        Ok(())
     }
 }
 
@@ -37,6 +48,12 @@ pub struct Params {
     filepath: PathBuf,
     /// Identifier of the dataset containing the snapshot.
     dataset: String,
+    /// If true, the modification times of directories will not be restored.
+    skip_times: bool,
+    /// If true, only the metadata of the existing files will be restored.
+    metadata_only: bool,
+    /// How thoroughly the retrieved data is checked.
+    verify: RestoreVerify,
 }
 
 impl Params {
@@ -46,8 +63,30 @@ impl Params {
             entry,
             filepath,
             dataset,
+            skip_times: false,
+            metadata_only: false,
+            verify: RestoreVerify::default(),
         }
     }
+
+    /// Leave the modification times of the restored directories as-is.
+    pub fn skip_times(mut self, skip: bool) -> Self {
+        self.skip_times = skip;
+        self
+    }
+
+    /// Apply only the ownership, mode, and extended attributes to the files
+    /// that already exist, rather than restoring their content.
+    pub fn metadata_only(mut self, metadata_only: bool) -> Self {
+        self.metadata_only = metadata_only;
+        self
+    }
+
+    /// Check the retrieved data to the given level, rather than the default.
+    pub fn verify(mut self, level: RestoreVerify) -> Self {
+        self.verify = level;
+        self
+    }
 }
 
 impl fmt::Display for Params {
@@ -58,13 +97,17 @@ impl fmt::Display for Params {
 
 impl From<Params> for Request {
     fn from(val: Params) -> Self {
-        Request::new(
+        let mut request = Request::new(
             val.tree,
             val.entry,
             val.filepath,
             val.dataset,
-            String::new(),
-        )
+            Secret::from(String::new()),
+        );
+        request.restore_times = !val.skip_times;
+        request.metadata_only = val.metadata_only;
+        request.verify = val.verify;
+        request
     }
 }
 
@@ -98,4 +141,80 @@ mod tests {
         // assert
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_restore_files_skip_times() {
+        // arrange
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue()
+            .withf(|request| !request.restore_times)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = RestoreFiles::new(Arc::new(mock));
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let entry = String::from("somedir");
+        let filepath = PathBuf::from("restored");
+        let dataset = String::from("dataset1");
+        let params = Params::new(tree, entry, filepath, dataset).skip_times(true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_restore_files_metadata_only() {
+        // arrange
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue()
+            .withf(|request| request.metadata_only && request.restore_times)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = RestoreFiles::new(Arc::new(mock));
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let entry = String::from("somedir");
+        let filepath = PathBuf::from("restored");
+        let dataset = String::from("dataset1");
+        let params = Params::new(tree, entry, filepath, dataset).metadata_only(true);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_restore_files_verify() {
+        // arrange
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue()
+            .withf(|request| request.verify == RestoreVerify::None)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = RestoreFiles::new(Arc::new(mock));
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let entry = String::from("somedir");
+        let filepath = PathBuf::from("restored");
+        let dataset = String::from("dataset1");
+        let params = Params::new(tree, entry, filepath, dataset).verify(RestoreVerify::None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_restore_files_bad_path() {
+        // arrange
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue().never();
+        let usecase = RestoreFiles::new(Arc::new(mock));
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        for bad in ["../etc/passwd", "/etc/passwd", "foo/../../bar", ""] {
+            // act
+            let filepath = PathBuf::from(bad);
+            let params = Params::new(tree.clone(), "entry".into(), filepath, "dataset1".into());
+            let result = usecase.call(params);
+            // assert
+            assert!(result.is_err());
+            let err = result.unwrap_err();
+            assert!(err.downcast_ref::<paths::PathError>().is_some());
+        }
+    }
 }
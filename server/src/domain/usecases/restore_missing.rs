@@ -1,9 +1,9 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, Pack, PackLocation};
+use crate::domain::entities::{Checksum, Message, MessageCode, Pack, PackLocation};
 use crate::domain::repositories::RecordRepository;
-use anyhow::{anyhow, Error};
+use anyhow::Error;
 use log::{error, info};
 use std::cmp;
 use std::collections::HashSet;
@@ -24,11 +24,15 @@ impl super::UseCase<Vec<Pack>, Params> for RestoreMissingPacks {
         let source_store = self
             .repo
             .get_store(&params.source_store_id)?
-            .ok_or_else(|| anyhow!(format!("no such store: {}", params.source_store_id)))?;
+            .ok_or_else(|| {
+                Message::new(MessageCode::NoSuchStore).with("id", &params.source_store_id)
+            })?;
         let target_store = self
             .repo
             .get_store(&params.target_store_id)?
-            .ok_or_else(|| anyhow!(format!("no such store: {}", params.target_store_id)))?;
+            .ok_or_else(|| {
+                Message::new(MessageCode::NoSuchStore).with("id", &params.target_store_id)
+            })?;
         // Find all packs and database snapshot packs.
         let mut all_packs = self.repo.get_packs(&target_store.id)?;
         let mut databases = self.repo.get_databases()?;
@@ -64,7 +68,11 @@ impl super::UseCase<Vec<Pack>, Params> for RestoreMissingPacks {
                 "RestoreMissing: retrieving pack {} from source",
                 &missing.digest
             );
-            let result = source_pack_repo.retrieve_pack(&missing.locations, pack_file.path());
+            let result = source_pack_repo.retrieve_pack(
+                &missing.locations,
+                &missing.digest,
+                pack_file.path(),
+            );
             if result.is_err() {
                 error!(
                     "RestoreMissing: unable to retrieve pack {}: {:?}",
@@ -165,6 +173,7 @@ mod tests {
     use super::*;
     use crate::domain::entities::{Pack, PackLocation, Store, StoreType};
     use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use anyhow::anyhow;
     use mockall::predicate::*;
     use std::collections::HashMap;
 
@@ -349,7 +358,7 @@ mod tests {
                 let mut mock_store = MockPackRepository::new();
                 mock_store
                     .expect_retrieve_pack()
-                    .returning(|_, _| Err(anyhow!("oh no")));
+                    .returning(|_, _, _| Err(anyhow!("oh no")));
                 Ok(Box::new(mock_store))
             });
         // act
@@ -433,7 +442,9 @@ mod tests {
             .withf(|s| s.id == "cafebabe")
             .returning(move |_| {
                 let mut mock_store = MockPackRepository::new();
-                mock_store.expect_retrieve_pack().returning(|_, _| Ok(()));
+                mock_store
+                    .expect_retrieve_pack()
+                    .returning(|_, _, _| Ok(()));
                 Ok(Box::new(mock_store))
             });
         // act
@@ -529,7 +540,9 @@ mod tests {
             .withf(|s| s.id == "cafebabe")
             .returning(move |_| {
                 let mut mock_store = MockPackRepository::new();
-                mock_store.expect_retrieve_pack().returning(|_, _| Ok(()));
+                mock_store
+                    .expect_retrieve_pack()
+                    .returning(|_, _, _| Ok(()));
                 Ok(Box::new(mock_store))
             });
         // ensure pack record is updated with new coordinates
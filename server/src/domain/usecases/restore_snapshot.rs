@@ -0,0 +1,188 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Checksum, Event, EventKind, Message, MessageCode, RestoreVerify};
+use crate::domain::helpers::crypto;
+use crate::domain::managers::events;
+use crate::domain::managers::restore::{Request, Restorer};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::fmt;
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+
+///
+/// Restore the entire tree of a snapshot to a directory other than the base
+/// path of the dataset, applying the metadata and modification time of every
+/// entry. Enqueuing the same snapshot and target again after an interruption
+/// will skip those files that were already restored.
+///
+pub struct RestoreSnapshot {
+    repo: Box<dyn RecordRepository>,
+    restorer: Arc<dyn Restorer>,
+}
+
+impl RestoreSnapshot {
+    pub fn new(repo: Box<dyn RecordRepository>, restorer: Arc<dyn Restorer>) -> Self {
+        Self { repo, restorer }
+    }
+}
+
+impl super::UseCase<(), Params> for RestoreSnapshot {
+    fn call(&self, params: Params) -> Result<(), Error> {
+        // the target is not joined with anything, so it must stand on its own
+        if !params.target.is_absolute()
+            || params
+                .target
+                .components()
+                .any(|c| c == Component::ParentDir)
+        {
+            return Err(anyhow!(
+                "target must be an absolute path: {}",
+                params.target.display()
+            ));
+        }
+        let snapshot = self.repo.get_snapshot(&params.snapshot)?.ok_or_else(|| {
+            Message::new(MessageCode::MissingSnapshot).with("digest", &params.snapshot)
+        })?;
+        let mut request = Request::new(
+            snapshot.tree,
+            String::new(),
+            PathBuf::new(),
+            params.dataset,
+            crypto::get_passphrase()?,
+        );
+        request.target = Some(params.target);
+        request.resumable = true;
+        request.verify = params.verify;
+        let event = Event::new(EventKind::RestoreRequested, &request.dataset)
+            .detail("snapshot", params.snapshot.to_string())
+            .detail("target", request.target.as_ref().unwrap().to_string_lossy());
+        self.restorer.enqueue(request)?;
+        events::record(event);
+        Ok(())
+    }
+}
+
+pub struct Params {
+    /// Identifier of the dataset containing the snapshot.
+    dataset: String,
+    /// Digest of the snapshot to be restored.
+    snapshot: Checksum,
+    /// Directory to which the snapshot will be restored.
+    target: PathBuf,
+    /// How thoroughly the retrieved data is checked.
+    verify: RestoreVerify,
+}
+
+impl Params {
+    pub fn new(dataset: String, snapshot: Checksum, target: PathBuf) -> Self {
+        Self {
+            dataset,
+            snapshot,
+            target,
+            verify: RestoreVerify::default(),
+        }
+    }
+
+    /// Check the retrieved data to the given level, rather than the default.
+    pub fn verify(mut self, level: RestoreVerify) -> Self {
+        self.verify = level;
+        self
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({}, {})", self.snapshot, self.target.display())
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.snapshot == other.snapshot && self.target == other.target
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::Snapshot;
+    use crate::domain::managers::restore::MockRestorer;
+    use crate::domain::repositories::MockRecordRepository;
+
+    #[test]
+    fn test_restore_snapshot_ok() {
+        // arrange
+        let tree = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let snapshot = Snapshot::new(None, tree.clone(), Default::default());
+        let snapshot_digest = snapshot.digest.clone();
+        let mut repo = MockRecordRepository::new();
+        repo.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue()
+            .withf(move |request| {
+                request.tree == tree
+                    && request.entry.is_empty()
+                    && request.filepath.as_os_str().is_empty()
+                    && request.target == Some(PathBuf::from("/mnt/elsewhere"))
+                    && request.resumable
+                    && request.verify == RestoreVerify::File
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = RestoreSnapshot::new(Box::new(repo), Arc::new(mock));
+        let params = Params::new(
+            "dataset1".into(),
+            snapshot_digest,
+            PathBuf::from("/mnt/elsewhere"),
+        )
+        .verify(RestoreVerify::File);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_restore_snapshot_missing() {
+        // arrange
+        let mut repo = MockRecordRepository::new();
+        repo.expect_get_snapshot().returning(|_| Ok(None));
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue().never();
+        // act
+        let usecase = RestoreSnapshot::new(Box::new(repo), Arc::new(mock));
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        let params = Params::new("dataset1".into(), digest, PathBuf::from("/mnt/elsewhere"));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::MissingSnapshot);
+    }
+
+    #[test]
+    fn test_restore_snapshot_bad_target() {
+        // arrange
+        let mut repo = MockRecordRepository::new();
+        repo.expect_get_snapshot().never();
+        let mut mock = MockRestorer::new();
+        mock.expect_enqueue().never();
+        let usecase = RestoreSnapshot::new(Box::new(repo), Arc::new(mock));
+        let digest = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".into());
+        for bad in ["relative/path", "", "/mnt/../etc"] {
+            // act
+            let params = Params::new("dataset1".into(), digest.clone(), PathBuf::from(bad));
+            let result = usecase.call(params);
+            // assert
+            assert!(result.is_err());
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::Checksum;
+use crate::domain::entities::{Checksum, Message, MessageCode};
 use crate::domain::helpers::crypto;
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Context, Error};
@@ -11,6 +11,7 @@ use std::cmp;
 use std::fmt;
 use std::fs;
 use std::str::FromStr;
+use store_core::Secret;
 
 // Currently unused, but would be useful if there is a file record for which the
 // pack references are incorrect. In that case, this usecase would scan all pack
@@ -38,10 +39,9 @@ impl ScanPacks {
 impl<'a> super::UseCase<Vec<ChunkLocation>, Params<'a>> for ScanPacks {
     fn call(&self, params: Params) -> Result<Vec<ChunkLocation>, Error> {
         // get the dataset and its associated pack stores
-        let dataset = self
-            .repo
-            .get_dataset(&params.dataset_id)?
-            .ok_or_else(|| anyhow!(format!("missing dataset: {:?}", params.dataset_id)))?;
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
         let stores = self.repo.load_dataset_stores(&dataset)?;
         fs::create_dir_all(&dataset.workspace).context("creating workspace")?;
         // retrieve the file record to get the chunk digests
@@ -65,7 +65,7 @@ impl<'a> super::UseCase<Vec<ChunkLocation>, Params<'a>> for ScanPacks {
             let archive = tempfile::Builder::new()
                 .suffix(".pack")
                 .tempfile_in(&dataset.workspace)?;
-            let result = stores.retrieve_pack(&pack.locations, archive.path());
+            let result = stores.retrieve_pack(&pack.locations, &pack.digest, archive.path());
             if result.is_err() {
                 error!(
                     "ScanPacks: unable to retrieve pack {}: {:?}",
@@ -76,7 +76,7 @@ impl<'a> super::UseCase<Vec<ChunkLocation>, Params<'a>> for ScanPacks {
             // scan the contents of the tar file using an n^2 search, which is
             // acceptable because most pack files have very few chunks
             let reader = exaf_rs::reader::Entries::new(&archive)?;
-            reader.enable_encryption(&params.passphrase)?;
+            reader.enable_encryption(params.passphrase.expose())?;
             for maybe_entry in reader {
                 let entry = maybe_entry?;
                 // we know the names are valid UTF-8, we created them
@@ -130,15 +130,19 @@ pub struct Params<'a> {
     /// Digest of the file whose chunks are to be located.
     file_digest: Checksum,
     /// Pass phrase for decrypting the pack.
-    passphrase: Cow<'a, str>,
+    passphrase: Secret,
 }
 
 impl<'a> Params<'a> {
-    pub fn new<T: Into<String>>(dataset_id: T, file_digest: Checksum, passphrase: T) -> Self {
+    pub fn new<T: Into<String>, S: Into<Secret>>(
+        dataset_id: T,
+        file_digest: Checksum,
+        passphrase: S,
+    ) -> Self {
         Self {
             dataset_id: Cow::from(dataset_id.into()),
             file_digest,
-            passphrase: Cow::from(passphrase.into()),
+            passphrase: passphrase.into(),
         }
     }
 }
@@ -296,7 +300,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     std::fs::rename(packfile_path.clone(), outfile).unwrap();
                     Ok(())
                 });
@@ -371,7 +375,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     std::fs::rename(packfile_path.clone(), outfile).unwrap();
                     Ok(())
                 });
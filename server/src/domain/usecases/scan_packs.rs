@@ -1,15 +1,16 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
-use crate::domain::entities::Checksum;
+use crate::domain::entities::{Checksum, Message, MessageCode};
 use crate::domain::repositories::RecordRepository;
-use anyhow::{anyhow, Context, Error};
+use anyhow::{Context, Error};
 use log::{error, info};
 use std::borrow::Cow;
 use std::cmp;
 use std::fmt;
 use std::fs;
 use std::str::FromStr;
+use store_core::Secret;
 
 ///
 /// Scan all packs in the system to find the desired chunk.
@@ -32,22 +33,31 @@ impl ScanPacks {
 impl<'a> super::UseCase<Option<Checksum>, Params<'a>> for ScanPacks {
     fn call(&self, params: Params) -> Result<Option<Checksum>, Error> {
         // get the dataset and its associated pack stores
-        let dataset = self
-            .repo
-            .get_dataset(&params.dataset_id)?
-            .ok_or_else(|| anyhow!(format!("missing dataset: {:?}", params.dataset_id)))?;
+        let dataset = self.repo.get_dataset(&params.dataset_id)?.ok_or_else(|| {
+            Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset_id)
+        })?;
         let stores = self.repo.load_dataset_stores(&dataset)?;
         fs::create_dir_all(&dataset.workspace).context("creating workspace")?;
-        // get all packs in the entire system
-        let all_packs = self.repo.get_all_packs()?;
-        info!("ScanPacks: will scan {} packs", all_packs.len());
-        for pack in all_packs.iter() {
+        // gather only the pack digests, the records are fetched one at a time
+        // since the database cannot be held while downloading the packs
+        let mut digests: Vec<Checksum> = Vec::new();
+        self.repo.visit_packs(&mut |pack| {
+            digests.push(pack.digest);
+            Ok(true)
+        })?;
+        info!("ScanPacks: will scan {} packs", digests.len());
+        for digest in digests.iter() {
+            let pack = match self.repo.get_pack(digest)? {
+                Some(pack) => pack,
+                // removed since the digests were gathered
+                None => continue,
+            };
             info!("ScanPacks: scanning pack {}", &pack.digest);
             // retrieve and decrypt the pack file
             let archive = tempfile::Builder::new()
                 .suffix(".pack")
                 .tempfile_in(&dataset.workspace)?;
-            let result = stores.retrieve_pack(&pack.locations, archive.path());
+            let result = stores.retrieve_pack(&pack.locations, &pack.digest, archive.path());
             if result.is_err() {
                 error!(
                     "ScanPacks: unable to retrieve pack {}: {:?}",
@@ -57,7 +67,7 @@ impl<'a> super::UseCase<Option<Checksum>, Params<'a>> for ScanPacks {
             }
             // scan the contents of the tar file to find the chunk digest
             let mut reader = exaf_rs::reader::Entries::new(&archive)?;
-            reader.enable_encryption(&params.passphrase)?;
+            reader.enable_encryption(params.passphrase.expose())?;
             for maybe_entry in reader {
                 let entry = maybe_entry?;
                 // we know the names are valid UTF-8, we created them
@@ -79,15 +89,19 @@ pub struct Params<'a> {
     /// Digest of the chunk that is to be located.
     chunk_digest: Checksum,
     /// Pass phrase for decrypting the pack.
-    passphrase: Cow<'a, str>,
+    passphrase: Secret,
 }
 
 impl<'a> Params<'a> {
-    pub fn new<T: Into<String>>(dataset_id: T, chunk_digest: Checksum, passphrase: T) -> Self {
+    pub fn new<T: Into<String>, S: Into<Secret>>(
+        dataset_id: T,
+        chunk_digest: Checksum,
+        passphrase: S,
+    ) -> Self {
         Self {
             dataset_id: Cow::from(dataset_id.into()),
             chunk_digest,
-            passphrase: Cow::from(passphrase.into()),
+            passphrase: passphrase.into(),
         }
     }
 }
@@ -146,7 +160,7 @@ mod tests {
             let mock_store = MockPackRepository::new();
             Ok(Box::new(mock_store))
         });
-        mock.expect_get_all_packs().returning(|| Ok(Vec::new()));
+        mock.expect_visit_packs().returning(|_| Ok(()));
         mock.expect_get_file().returning(|_| {
             Ok(Some(File::new(
                 Checksum::BLAKE3("deadbeef".into()),
@@ -193,7 +207,7 @@ mod tests {
             let mut mock_store = MockPackRepository::new();
             mock_store
                 .expect_retrieve_pack()
-                .returning(move |_, outfile| {
+                .returning(move |_, _, outfile| {
                     // rename on Windows fails with permission denied, so do
                     // what the local pack store would do and just copy
                     std::fs::copy(packfile_path.clone(), outfile).unwrap();
@@ -201,11 +215,16 @@ mod tests {
                 });
             Ok(Box::new(mock_store))
         });
-        mock.expect_get_all_packs().returning(move || {
+        mock.expect_visit_packs().returning(move |visitor| {
             // this pack digest will be captured as the correct ("new") value
             let pack_sum = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
             let locations = vec![PackLocation::new("storeid", "bucketid", "objectid")];
-            Ok(vec![Pack::new(pack_sum, locations)])
+            visitor(Pack::new(pack_sum, locations))?;
+            Ok(())
+        });
+        mock.expect_get_pack().returning(move |digest| {
+            let locations = vec![PackLocation::new("storeid", "bucketid", "objectid")];
+            Ok(Some(Pack::new(digest.clone(), locations)))
         });
 
         // act
@@ -1,6 +1,7 @@
 //
 // Copyright (c) 2022 Nathan Fiedler
 //
+use crate::domain::entities::{Message, MessageCode};
 use crate::domain::managers::backup::Scheduler;
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
@@ -23,6 +24,11 @@ impl super::UseCase<(), Params> for StartBackup {
     fn call(&self, params: Params) -> Result<(), Error> {
         for dataset in self.repo.get_datasets()? {
             if dataset.id == params.dataset_id {
+                if dataset.archived.is_some() {
+                    return Err(Message::new(MessageCode::DatasetArchived)
+                        .with("path", &dataset.basepath.display())
+                        .into());
+                }
                 self.processor.start_backup(dataset)?;
             }
         }
@@ -83,6 +89,29 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_start_backup_archived() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.archived = Some(chrono::Utc::now());
+        let dataset_id = dataset.id.clone();
+        let datasets = vec![dataset];
+        let mut repo = MockRecordRepository::new();
+        repo.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        let mut processor = MockScheduler::new();
+        processor.expect_start_backup().never();
+        // act
+        let usecase = StartBackup::new(Box::new(repo), Arc::new(processor));
+        let params = Params { dataset_id };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        let message = err.downcast_ref::<Message>().unwrap();
+        assert_eq!(message.code, MessageCode::DatasetArchived);
+    }
+
     #[test]
     fn test_start_backup_not_found() {
         // arrange
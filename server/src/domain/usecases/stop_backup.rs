@@ -23,7 +23,12 @@ impl super::UseCase<(), Params> for StopBackup {
     fn call(&self, params: Params) -> Result<(), Error> {
         for dataset in self.repo.get_datasets()? {
             if dataset.id == params.dataset_id {
-                self.state.backup_event(BackupAction::Stop(dataset.id));
+                if params.uploads_only {
+                    self.state
+                        .backup_event(BackupAction::StopUploads(dataset.id));
+                } else {
+                    self.state.backup_event(BackupAction::Stop(dataset.id));
+                }
             }
         }
         Ok(())
@@ -33,23 +38,29 @@ impl super::UseCase<(), Params> for StopBackup {
 pub struct Params {
     /// Unique identifier of the dataset.
     dataset_id: String,
+    /// True to stop only the uploads, letting the backup finish finding the
+    /// changes such that the next run can upload them straight away.
+    uploads_only: bool,
 }
 
 impl Params {
-    pub fn new(dataset_id: String) -> Self {
-        Self { dataset_id }
+    pub fn new(dataset_id: String, uploads_only: bool) -> Self {
+        Self {
+            dataset_id,
+            uploads_only,
+        }
     }
 }
 
 impl fmt::Display for Params {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Params({})", self.dataset_id)
+        write!(f, "Params({}, {})", self.dataset_id, self.uploads_only)
     }
 }
 
 impl cmp::PartialEq for Params {
     fn eq(&self, other: &Self) -> bool {
-        self.dataset_id == other.dataset_id
+        self.dataset_id == other.dataset_id && self.uploads_only == other.uploads_only
     }
 }
 
@@ -77,7 +88,29 @@ mod tests {
         state.expect_backup_event().returning(|_| ());
         // act
         let usecase = StopBackup::new(Box::new(repo), Arc::new(state));
-        let params = Params { dataset_id };
+        let params = Params::new(dataset_id, false);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_stop_backup_uploads_only() {
+        // arrange
+        let datasets = vec![Dataset::new(Path::new("/home/planet"))];
+        let dataset_id = datasets[0].id.clone();
+        let mut repo = MockRecordRepository::new();
+        repo.expect_get_datasets()
+            .returning(move || Ok(datasets.clone()));
+        let mut state = MockStateStore::new();
+        state
+            .expect_backup_event()
+            .withf(|action| matches!(action, BackupAction::StopUploads(_)))
+            .times(1)
+            .returning(|_| ());
+        // act
+        let usecase = StopBackup::new(Box::new(repo), Arc::new(state));
+        let params = Params::new(dataset_id, true);
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
@@ -93,9 +126,7 @@ mod tests {
         let state = MockStateStore::new();
         // act
         let usecase = StopBackup::new(Box::new(repo), Arc::new(state));
-        let params = Params {
-            dataset_id: "nonesuch".to_owned(),
-        };
+        let params = Params::new("nonesuch".to_owned(), false);
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
@@ -110,9 +141,7 @@ mod tests {
         let state = MockStateStore::new();
         // act
         let usecase = StopBackup::new(Box::new(repo), Arc::new(state));
-        let params = Params {
-            dataset_id: "cafebabe".to_owned(),
-        };
+        let params = Params::new("cafebabe".to_owned(), false);
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
@@ -1,13 +1,14 @@
 //
 // Copyright (c) 2021 Nathan Fiedler
 //
-use crate::domain::entities::{Store, StoreType};
+use crate::domain::entities::{Store, StoreTestStep, StoreType};
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
 use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
+use std::time::Instant;
 
 pub struct TestStore {
     repo: Box<dyn RecordRepository>,
@@ -19,17 +20,30 @@ impl TestStore {
     }
 }
 
-impl super::UseCase<(), Params> for TestStore {
-    fn call(&self, params: Params) -> Result<(), Error> {
+impl super::UseCase<Vec<StoreTestStep>, Params> for TestStore {
+    fn call(&self, params: Params) -> Result<Vec<StoreTestStep>, Error> {
         let store_type = StoreType::from_str(&params.type_name)?;
-        let store = Store {
+        let mut store = Store {
             id: params.store_id,
             store_type,
             label: params.label,
             properties: params.properties,
         };
+        // the client may be testing changes to a store whose credentials it
+        // was never given
+        if let Some(saved) = self.repo.get_store(&store.id)? {
+            store.restore_secrets(&saved);
+        }
         let pack_repo = self.repo.build_pack_repo(&store)?;
-        pack_repo.test_store(&store.id)
+        if params.deep {
+            pack_repo.test_store_deep(&store.id)
+        } else {
+            let started = Instant::now();
+            let result = pack_repo.test_store(&store.id);
+            let step = StoreTestStep::new("list", started, &result);
+            result?;
+            Ok(vec![step])
+        }
     }
 }
 
@@ -42,6 +56,8 @@ pub struct Params {
     label: String,
     /// Name/value pairs that make up this store configuration.
     properties: HashMap<String, String>,
+    /// If true, perform a full write/read/delete round trip.
+    deep: bool,
 }
 
 impl Params {
@@ -50,12 +66,14 @@ impl Params {
         type_name: String,
         label: String,
         properties: HashMap<String, String>,
+        deep: bool,
     ) -> Self {
         Self {
             store_id,
             type_name,
             label,
             properties,
+            deep,
         }
     }
 }
@@ -85,6 +103,7 @@ mod tests {
     fn test_test_store_ok() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
             mock_store.expect_test_store().returning(move |_| Ok(()));
@@ -99,16 +118,21 @@ mod tests {
             type_name: "minio".to_owned(),
             label: "pretend S3".to_owned(),
             properties,
+            deep: false,
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
+        let steps = result.unwrap();
+        assert_eq!(steps.len(), 1);
+        assert!(steps[0].error.is_none());
     }
 
     #[test]
     fn test_test_store_err() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
         mock.expect_build_pack_repo().returning(move |_| {
             let mut mock_store = MockPackRepository::new();
             mock_store
@@ -125,9 +149,48 @@ mod tests {
             type_name: "minio".to_owned(),
             label: "pretend S3".to_owned(),
             properties,
+            deep: false,
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_test_store_deep() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        mock.expect_build_pack_repo().returning(move |_| {
+            let mut mock_store = MockPackRepository::new();
+            mock_store.expect_test_store_deep().returning(move |_| {
+                let started = Instant::now();
+                let ok: Result<(), Error> = Ok(());
+                let err: Result<(), Error> = Err(anyhow!("access denied"));
+                Ok(vec![
+                    StoreTestStep::new("list", started, &ok),
+                    StoreTestStep::new("upload", started, &err),
+                ])
+            });
+            Ok(Box::new(mock_store))
+        });
+        // act
+        let usecase = TestStore::new(Box::new(mock));
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("endpoint".to_owned(), "localhost:9000".to_owned());
+        let params = Params {
+            store_id: "cafebabe".to_owned(),
+            type_name: "minio".to_owned(),
+            label: "pretend S3".to_owned(),
+            properties,
+            deep: true,
+        };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let steps = result.unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].name, "upload");
+        assert_eq!(steps[1].error.as_ref().unwrap(), "access denied");
+    }
 }
@@ -0,0 +1,114 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Message, MessageCode, TieringResult};
+use crate::domain::managers::tiering;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::fmt;
+
+///
+/// Apply the tiering policy of one or all stores immediately, rather than
+/// waiting for the periodic job to run.
+///
+pub struct TierPacks {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl TierPacks {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Vec<TieringResult>, Params> for TierPacks {
+    fn call(&self, params: Params) -> Result<Vec<TieringResult>, Error> {
+        if let Some(store_id) = params.store_id {
+            let store = self
+                .repo
+                .get_store(&store_id)?
+                .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &store_id))?;
+            if store.tiering_policy().is_none() {
+                return Err(anyhow!(format!("store {} has no tiering policy", store_id)));
+            }
+            let result = tiering::apply_store_policy(self.repo.as_ref(), &store)?;
+            Ok(vec![result])
+        } else {
+            tiering::apply_all_policies(self.repo.as_ref())
+        }
+    }
+}
+
+pub struct Params {
+    /// Identifier of the store whose policy is to be applied, or `None` to
+    /// apply the policies of all stores.
+    store_id: Option<String>,
+}
+
+impl Params {
+    pub fn new(store_id: Option<String>) -> Self {
+        Self { store_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({:?})", self.store_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Store, StoreType};
+    use crate::domain::repositories::MockRecordRepository;
+    use mockall::predicate::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_tier_packs_no_policy() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().with(eq("cafebabe")).returning(|_| {
+            Ok(Some(Store {
+                id: "cafebabe".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "mylocalstore".to_owned(),
+                properties: HashMap::new(),
+            }))
+        });
+        // act
+        let usecase = TierPacks::new(Box::new(mock));
+        let params = Params::new(Some("cafebabe".to_owned()));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no tiering policy"));
+    }
+
+    #[test]
+    fn test_tier_packs_no_store() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        // act
+        let usecase = TierPacks::new(Box::new(mock));
+        let params = Params::new(Some("nosuchstore".to_owned()));
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("no such store"));
+    }
+}
@@ -0,0 +1,106 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::Dataset;
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::fmt;
+
+pub struct UndeleteDataset {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl UndeleteDataset {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<Dataset, Params> for UndeleteDataset {
+    fn call(&self, params: Params) -> Result<Dataset, Error> {
+        let trashed = self
+            .repo
+            .get_trashed_datasets()?
+            .into_iter()
+            .find(|t| t.dataset.id == params.dataset_id)
+            .ok_or_else(|| anyhow!(format!("no such deleted dataset: {}", params.dataset_id)))?;
+        self.repo.put_dataset(&trashed.dataset)?;
+        self.repo.delete_trashed_dataset(&params.dataset_id)?;
+        Ok(trashed.dataset)
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the deleted dataset.
+    dataset_id: String,
+}
+
+impl Params {
+    pub fn new(dataset_id: String) -> Self {
+        Self { dataset_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.dataset_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.dataset_id == other.dataset_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::TrashedDataset;
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    #[test]
+    fn test_undelete_dataset_ok() {
+        // arrange
+        let mut dataset = Dataset::new(Path::new("/home/planet"));
+        dataset.id = "cafebabe".to_owned();
+        let trashed = TrashedDataset::new(dataset);
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_trashed_datasets()
+            .returning(move || Ok(vec![trashed.clone()]));
+        mock.expect_put_dataset()
+            .withf(|dataset| dataset.id == "cafebabe")
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_delete_trashed_dataset()
+            .withf(|id| id == "cafebabe")
+            .times(1)
+            .returning(|_| Ok(()));
+        // act
+        let usecase = UndeleteDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().basepath, Path::new("/home/planet"));
+    }
+
+    #[test]
+    fn test_undelete_dataset_missing() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_put_dataset().never();
+        // act
+        let usecase = UndeleteDataset::new(Box::new(mock));
+        let params = Params::new("cafebabe".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+}
@@ -1,13 +1,19 @@
 //
 // Copyright (c) 2024 Nathan Fiedler
 //
+use super::ConflictError;
 use crate::domain::entities::schedule::Schedule;
-use crate::domain::entities::Dataset;
+use crate::domain::entities::{Compression, Dataset, Message, MessageCode};
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Ensures that comparing the entity tag and saving the dataset happen together.
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
 
 pub struct UpdateDataset {
     repo: Box<dyn RecordRepository>,
@@ -21,6 +27,10 @@ impl UpdateDataset {
 
 impl super::UseCase<Dataset, Params> for UpdateDataset {
     fn call(&self, params: Params) -> Result<Dataset, Error> {
+        // an unrecognized compression would otherwise be quietly ignored
+        if let Some(value) = params.properties.get("compression") {
+            value.parse::<Compression>()?;
+        }
         // use the constructor to leverage some of the default behavior in case
         // not everything has been defined in the params
         let mut dataset = Dataset::with_pack_size(&params.basepath, params.pack_size);
@@ -37,9 +47,21 @@ impl super::UseCase<Dataset, Params> for UpdateDataset {
         for store in params.stores.iter() {
             dataset.add_store(store);
         }
+        dataset.properties = params.properties;
         if let Some(workspace) = params.workspace {
             dataset.workspace = workspace;
         }
+        let _guard = UPDATE_LOCK.lock().unwrap();
+        let current = self
+            .repo
+            .get_dataset(&dataset.id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &dataset.id))?;
+        let etag = current.etag();
+        if etag != params.etag {
+            return Err(Error::from(ConflictError { current: etag }));
+        }
+        // archiving is not a matter of configuration
+        dataset.archived = current.archived;
         self.repo.put_dataset(&dataset)?;
         Ok(dataset)
     }
@@ -60,6 +82,10 @@ pub struct Params {
     stores: Vec<String>,
     /// List of file/directory exclusion patterns.
     excludes: Vec<String>,
+    /// Name/value pairs for optional dataset settings.
+    properties: HashMap<String, String>,
+    /// Entity tag of the dataset on which the changes are based.
+    etag: String,
 }
 
 impl Params {
@@ -71,6 +97,7 @@ impl Params {
         pack_size: u64,
         stores: Vec<String>,
         excludes: Vec<String>,
+        etag: String,
     ) -> Self {
         Self {
             id,
@@ -80,8 +107,16 @@ impl Params {
             pack_size,
             stores,
             excludes,
+            properties: HashMap::new(),
+            etag,
         }
     }
+
+    /// Set the name/value pairs for optional dataset settings.
+    pub fn properties(mut self, properties: HashMap<String, String>) -> Self {
+        self.properties = properties;
+        self
+    }
 }
 
 impl fmt::Display for Params {
@@ -105,15 +140,23 @@ mod tests {
     use crate::domain::repositories::MockRecordRepository;
     use anyhow::anyhow;
 
+    fn existing_dataset() -> Dataset {
+        let mut dataset = Dataset::new(&PathBuf::from("/home/planet"));
+        dataset.id = "cafebabe".to_owned();
+        dataset
+    }
+
     #[test]
     fn test_update_dataset_ok() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(existing_dataset())));
         mock.expect_put_dataset().returning(|_| Ok(()));
         // act
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let basepath = "/home/planet";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let basepath = "\\home\\planet";
         let usecase = UpdateDataset::new(Box::new(mock));
         let params = Params {
@@ -124,15 +167,17 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: HashMap::new(),
+            etag: existing_dataset().etag(),
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
         let actual = result.unwrap();
         assert_eq!(actual.basepath.to_string_lossy(), basepath);
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let expected_workspace = "/home/planet/.tmp";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let expected_workspace = "\\home\\planet\\.tmp";
         assert_eq!(actual.workspace.to_string_lossy(), expected_workspace);
     }
@@ -141,15 +186,17 @@ mod tests {
     fn test_update_dataset_workspace() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(existing_dataset())));
         mock.expect_put_dataset().returning(|_| Ok(()));
         // act
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let basepath = "/home/planet";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let basepath = "\\home\\planet";
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let workspace = "/home/planet/tmpdir";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let workspace = "\\home\\planet\\tmpdir";
         let usecase = UpdateDataset::new(Box::new(mock));
         let params = Params {
@@ -160,6 +207,8 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: HashMap::new(),
+            etag: existing_dataset().etag(),
         };
         let result = usecase.call(params);
         // assert
@@ -173,11 +222,13 @@ mod tests {
     fn test_update_dataset_empty_excludes() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(existing_dataset())));
         mock.expect_put_dataset().returning(|_| Ok(()));
         // act
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let basepath = "/home/planet";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let basepath = "\\home\\planet";
         let usecase = UpdateDataset::new(Box::new(mock));
         let params = Params {
@@ -188,15 +239,17 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec!["".to_owned()],
+            properties: HashMap::new(),
+            etag: existing_dataset().etag(),
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
         let actual = result.unwrap();
         assert_eq!(actual.basepath.to_string_lossy(), basepath);
-        #[cfg(target_family="unix")]
+        #[cfg(target_family = "unix")]
         let expected_workspace = "/home/planet/.tmp";
-        #[cfg(target_family="windows")]
+        #[cfg(target_family = "windows")]
         let expected_workspace = "\\home\\planet\\.tmp";
         assert_eq!(actual.workspace.to_string_lossy(), expected_workspace);
         assert_eq!(actual.pack_size, 33_554_432);
@@ -209,6 +262,8 @@ mod tests {
     fn test_update_dataset_err() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(existing_dataset())));
         mock.expect_put_dataset()
             .returning(|_| Err(anyhow!("oh no")));
         // act
@@ -221,9 +276,71 @@ mod tests {
             pack_size: 33_554_432,
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: HashMap::new(),
+            etag: existing_dataset().etag(),
+        };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_update_dataset_bad_compression() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(existing_dataset())));
+        mock.expect_put_dataset().never();
+        // act
+        let usecase = UpdateDataset::new(Box::new(mock));
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("compression".into(), "brotli".into());
+        let params = Params {
+            id: "cafebabe".to_owned(),
+            basepath: PathBuf::from("/home/planet"),
+            schedules: vec![],
+            workspace: None,
+            pack_size: 33_554_432,
+            stores: vec!["cafebabe".to_owned()],
+            excludes: vec![],
+            properties,
+            etag: existing_dataset().etag(),
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("not a recognized compression"));
+    }
+
+    #[test]
+    fn test_update_dataset_conflict() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset().returning(|_| {
+            let mut dataset = existing_dataset();
+            dataset.add_store("deadbeef");
+            Ok(Some(dataset))
+        });
+        mock.expect_put_dataset().never();
+        // act
+        let usecase = UpdateDataset::new(Box::new(mock));
+        let params = Params::new(
+            "cafebabe".to_owned(),
+            PathBuf::from("/home/planet"),
+            vec![],
+            None,
+            33_554_432,
+            vec!["cafebabe".to_owned()],
+            vec![],
+            existing_dataset().etag(),
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err().downcast::<ConflictError>().unwrap();
+        let mut expected = existing_dataset();
+        expected.add_store("deadbeef");
+        assert_eq!(err.current, expected.etag());
     }
 }
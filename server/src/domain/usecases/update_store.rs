@@ -1,13 +1,19 @@
 //
 // Copyright (c) 2020 Nathan Fiedler
 //
-use crate::domain::entities::{Store, StoreType};
+use super::ConflictError;
+use crate::domain::entities::{Event, EventKind, Message, MessageCode, Store, StoreType};
+use crate::domain::managers::events;
 use crate::domain::repositories::RecordRepository;
 use anyhow::Error;
 use std::cmp;
 use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Mutex;
+
+// Ensures that comparing the entity tag and saving the store happen together.
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
 
 pub struct UpdateStore {
     repo: Box<dyn RecordRepository>,
@@ -22,13 +28,26 @@ impl UpdateStore {
 impl super::UseCase<Store, Params> for UpdateStore {
     fn call(&self, params: Params) -> Result<Store, Error> {
         let store_type = StoreType::from_str(&params.type_name)?;
-        let store = Store {
+        let mut store = Store {
             id: params.store_id,
             store_type,
             label: params.label,
             properties: params.properties,
         };
+        let _guard = UPDATE_LOCK.lock().unwrap();
+        let current = self
+            .repo
+            .get_store(&store.id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &store.id))?;
+        let etag = current.etag();
+        if etag != params.etag {
+            return Err(Error::from(ConflictError { current: etag }));
+        }
+        store.restore_secrets(&current);
         self.repo.put_store(&store)?;
+        events::record(
+            Event::new(EventKind::StoreUpdated, &store.id).detail("label", store.label.clone()),
+        );
         Ok(store)
     }
 }
@@ -42,6 +61,8 @@ pub struct Params {
     label: String,
     /// Name/value pairs that make up this store configuration.
     properties: HashMap<String, String>,
+    /// Entity tag of the store on which the changes are based.
+    etag: String,
 }
 
 impl Params {
@@ -50,12 +71,14 @@ impl Params {
         type_name: String,
         label: String,
         properties: HashMap<String, String>,
+        etag: String,
     ) -> Self {
         Self {
             store_id,
             type_name,
             label,
             properties,
+            etag,
         }
     }
 }
@@ -78,13 +101,25 @@ impl cmp::Eq for Params {}
 mod tests {
     use super::super::UseCase;
     use super::*;
+    use crate::domain::entities::REDACTED_PROPERTY;
     use crate::domain::repositories::MockRecordRepository;
     use anyhow::anyhow;
 
+    fn existing_store() -> Store {
+        Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "my local".to_owned(),
+            properties: HashMap::new(),
+        }
+    }
+
     #[test]
     fn test_update_store_ok() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(|_| Ok(Some(existing_store())));
         mock.expect_put_store().returning(|_| Ok(()));
         // act
         let usecase = UpdateStore::new(Box::new(mock));
@@ -95,6 +130,7 @@ mod tests {
             type_name: "minio".to_owned(),
             label: "pretend S3".to_owned(),
             properties,
+            etag: existing_store().etag(),
         };
         let result = usecase.call(params);
         // assert
@@ -105,10 +141,42 @@ mod tests {
         assert!(actual.properties.contains_key("endpoint"));
     }
 
+    #[test]
+    fn test_update_store_secrets() {
+        // arrange
+        let mut saved = existing_store();
+        saved
+            .properties
+            .insert("secret_key".to_owned(), "wJalrXUtnFEMI".to_owned());
+        let etag = saved.etag();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(saved.clone())));
+        mock.expect_put_store()
+            .withf(|store| store.properties["secret_key"] == "wJalrXUtnFEMI")
+            .returning(|_| Ok(()));
+        // act
+        let usecase = UpdateStore::new(Box::new(mock));
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("secret_key".to_owned(), REDACTED_PROPERTY.to_owned());
+        let params = Params {
+            store_id: "cafebabe".to_owned(),
+            type_name: "minio".to_owned(),
+            label: "pretend S3".to_owned(),
+            properties,
+            etag,
+        };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+    }
+
     #[test]
     fn test_update_store_err() {
         // arrange
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(|_| Ok(Some(existing_store())));
         mock.expect_put_store().returning(|_| Err(anyhow!("oh no")));
         // act
         let usecase = UpdateStore::new(Box::new(mock));
@@ -119,9 +187,59 @@ mod tests {
             type_name: "minio".to_owned(),
             label: "pretend S3".to_owned(),
             properties,
+            etag: existing_store().etag(),
         };
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_update_store_conflict() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| {
+            let mut store = existing_store();
+            store.label = "changed elsewhere".to_owned();
+            Ok(Some(store))
+        });
+        mock.expect_put_store().never();
+        // act
+        let usecase = UpdateStore::new(Box::new(mock));
+        let params = Params {
+            store_id: "cafebabe".to_owned(),
+            type_name: "local".to_owned(),
+            label: "my local".to_owned(),
+            properties: HashMap::new(),
+            etag: existing_store().etag(),
+        };
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err = result.unwrap_err().downcast::<ConflictError>().unwrap();
+        let mut expected = existing_store();
+        expected.label = "changed elsewhere".to_owned();
+        assert_eq!(err.current, expected.etag());
+    }
+
+    #[test]
+    fn test_update_store_missing() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        mock.expect_put_store().never();
+        // act
+        let usecase = UpdateStore::new(Box::new(mock));
+        let params = Params::new(
+            "cafebabe".to_owned(),
+            "local".to_owned(),
+            "my local".to_owned(),
+            HashMap::new(),
+            existing_store().etag(),
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("no such store"));
+    }
 }
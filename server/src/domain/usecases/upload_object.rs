@@ -0,0 +1,135 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{Message, MessageCode, PackLocation};
+use crate::domain::repositories::RecordRepository;
+use crate::domain::usecases::download_object::check_name;
+use anyhow::{anyhow, Error};
+use log::info;
+use std::cmp;
+use std::fmt;
+use std::path::PathBuf;
+
+///
+/// Save the given file to a store under the named bucket and object, such as
+/// when pushing a pack back to a store from which it was lost. The database
+/// records are not changed in any way.
+///
+pub struct UploadObject {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl UploadObject {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<PackLocation, Params> for UploadObject {
+    fn call(&self, params: Params) -> Result<PackLocation, Error> {
+        check_name(&params.bucket)?;
+        check_name(&params.object)?;
+        let store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        info!(
+            "UploadObject: storing {}/{} in store {}",
+            params.bucket, params.object, store.id
+        );
+        let mut locations = pack_repo.store_pack(&params.infile, &params.bucket, &params.object)?;
+        locations
+            .pop()
+            .ok_or_else(|| anyhow!(format!("store {} did not accept the object", store.id)))
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// Name of the bucket to contain the object.
+    bucket: String,
+    /// Name of the object to be stored.
+    object: String,
+    /// Path of the file to be uploaded.
+    infile: PathBuf,
+}
+
+impl Params {
+    pub fn new(store_id: String, bucket: String, object: String, infile: PathBuf) -> Self {
+        Self {
+            store_id,
+            bucket,
+            object,
+            infile,
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Params({}, {}, {})",
+            self.store_id, self.bucket, self.object
+        )
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+            && self.bucket == other.bucket
+            && self.object == other.object
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{Store, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_upload_object_ok() {
+        // arrange
+        let store = Store {
+            id: "cafebabe".to_owned(),
+            store_type: StoreType::LOCAL,
+            label: "mylocalstore".to_owned(),
+            properties: HashMap::new(),
+        };
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(store.clone())));
+        mock.expect_build_pack_repo().returning(|_| {
+            let mut pack_repo = MockPackRepository::new();
+            pack_repo
+                .expect_store_pack()
+                .withf(|_, bucket, object| bucket == "bucket1" && object == "object1")
+                .returning(|_, bucket, object| {
+                    Ok(vec![PackLocation::new("cafebabe", bucket, object)])
+                });
+            Ok(Box::new(pack_repo))
+        });
+        // act
+        let usecase = UploadObject::new(Box::new(mock));
+        let params = Params::new(
+            "cafebabe".into(),
+            "bucket1".into(),
+            "object1".into(),
+            PathBuf::from("../test/fixtures/lorem-ipsum.txt"),
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let location = result.unwrap();
+        assert_eq!(location.store, "cafebabe");
+        assert_eq!(location.object, "object1");
+    }
+}
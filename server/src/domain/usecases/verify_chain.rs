@@ -0,0 +1,299 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{ChainEntry, ChainReport, Checksum, Message, MessageCode, NULL_SHA1};
+use crate::domain::repositories::RecordRepository;
+use anyhow::Error;
+use log::info;
+use std::cmp;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+///
+/// Compare the log of snapshots held by a store with the entries recorded in
+/// the database, to detect history that has been altered, deleted, or rolled
+/// back by someone with access to the store.
+///
+pub struct VerifyChain {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl VerifyChain {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+}
+
+impl super::UseCase<ChainReport, Params> for VerifyChain {
+    fn call(&self, params: Params) -> Result<ChainReport, Error> {
+        let store = self
+            .repo
+            .get_store(&params.store_id)?
+            .ok_or_else(|| Message::new(MessageCode::NoSuchStore).with("id", &params.store_id))?;
+        let mut report = ChainReport::new(&store.id);
+        let expected = self.repo.get_chain_entries(&store.id)?;
+        report.expected = expected.len() as u64;
+        let location = self
+            .repo
+            .get_chain_locations()?
+            .into_iter()
+            .find(|l| l.store == store.id);
+        let location = match location {
+            Some(location) => location,
+            None => {
+                if !expected.is_empty() {
+                    report
+                        .problems
+                        .push(String::from("log has never been saved to the store"));
+                }
+                return Ok(report);
+            }
+        };
+        info!(
+            "VerifyChain: retrieving snapshot log from store {}",
+            store.id
+        );
+        let pack_repo = self.repo.build_pack_repo(&store)?;
+        let outfile = tempfile::NamedTempFile::new()?.into_temp_path();
+        if let Err(err) = pack_repo.retrieve_object(&location, &outfile) {
+            report
+                .problems
+                .push(format!("log could not be retrieved: {}", err));
+            return Ok(report);
+        }
+        let contents = fs::read_to_string(&outfile)?;
+        let found = check_entries(&store.id, &contents, &mut report.problems);
+        report.found = found.len() as u64;
+        compare_entries(&expected, &found, &mut report.problems);
+        Ok(report)
+    }
+}
+
+// Parse the entries of the log and check that each one is intact and follows
+// the one before it, returning the entries that could be parsed.
+fn check_entries(store_id: &str, contents: &str, problems: &mut Vec<String>) -> Vec<ChainEntry> {
+    let mut entries: Vec<ChainEntry> = Vec::new();
+    let mut previous: Option<ChainEntry> = None;
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        let entry = match ChainEntry::parse(store_id, line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                problems.push(err.to_string());
+                continue;
+            }
+        };
+        if !entry.is_intact() {
+            problems.push(format!("entry {} has been altered", entry.sequence));
+        }
+        match previous.as_ref() {
+            Some(prior) => {
+                if entry.sequence != prior.sequence + 1 {
+                    problems.push(format!(
+                        "entries missing between {} and {}",
+                        prior.sequence, entry.sequence
+                    ));
+                } else if entry.previous != prior.digest {
+                    problems.push(format!(
+                        "entry {} does not follow entry {}",
+                        entry.sequence, prior.sequence
+                    ));
+                }
+            }
+            None => {
+                let null_sha1 = Checksum::from_str(NULL_SHA1).ok();
+                let genesis = entry.sequence == 1 && Some(&entry.previous) == null_sha1.as_ref();
+                if !genesis {
+                    problems.push(format!("log begins at entry {}", entry.sequence));
+                }
+            }
+        }
+        previous = Some(entry.clone());
+        entries.push(entry);
+    }
+    entries
+}
+
+// Compare the entries found in the store with those recorded in the database.
+fn compare_entries(expected: &[ChainEntry], found: &[ChainEntry], problems: &mut Vec<String>) {
+    for entry in expected.iter() {
+        match found.iter().find(|e| e.sequence == entry.sequence) {
+            Some(other) => {
+                if other.digest != entry.digest || other.snapshot != entry.snapshot {
+                    problems.push(format!("entry {} differs from database", entry.sequence));
+                }
+            }
+            None => problems.push(format!("entry {} is missing", entry.sequence)),
+        }
+    }
+    let expected_last = expected.last().map(|e| e.sequence).unwrap_or(0);
+    let found_last = found.last().map(|e| e.sequence).unwrap_or(0);
+    if found_last < expected_last {
+        problems.push(format!(
+            "log ends at entry {} rather than {}, history was rolled back or deleted",
+            found_last, expected_last
+        ));
+    } else if found_last > expected_last {
+        problems.push(format!(
+            "log has entries after {} that are not in the database",
+            expected_last
+        ));
+    }
+}
+
+pub struct Params {
+    /// Unique identifier of the store.
+    store_id: String,
+}
+
+impl Params {
+    pub fn new(store_id: String) -> Self {
+        Self { store_id }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Params({})", self.store_id)
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.store_id == other.store_id
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{PackLocation, Store, StoreType};
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::collections::HashMap;
+
+    fn make_entries(count: usize) -> Vec<ChainEntry> {
+        let snapshot = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+        let mut entries: Vec<ChainEntry> = Vec::new();
+        for _ in 0..count {
+            let entry = ChainEntry::new("store1", entries.last(), snapshot.clone());
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn make_mock(expected: Vec<ChainEntry>, stored: Vec<ChainEntry>) -> MockRecordRepository {
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_store().returning(|_| {
+            Ok(Some(Store {
+                id: "store1".to_owned(),
+                store_type: StoreType::LOCAL,
+                label: "local".to_owned(),
+                properties: HashMap::new(),
+            }))
+        });
+        mock.expect_get_chain_entries()
+            .returning(move |_| Ok(expected.clone()));
+        mock.expect_get_chain_locations().returning(|| {
+            Ok(vec![PackLocation::new(
+                "store1",
+                "bucket1",
+                "snapshots.log",
+            )])
+        });
+        mock.expect_build_pack_repo().returning(move |_| {
+            let stored = stored.clone();
+            let mut stores = MockPackRepository::new();
+            stores
+                .expect_retrieve_object()
+                .returning(move |_, outfile| {
+                    let lines: Vec<String> = stored.iter().map(|e| e.to_string()).collect();
+                    fs::write(outfile, lines.join("\n"))?;
+                    Ok(())
+                });
+            Ok(Box::new(stores))
+        });
+        mock
+    }
+
+    #[test]
+    fn test_verify_chain_intact() {
+        // arrange
+        let entries = make_entries(3);
+        let mock = make_mock(entries.clone(), entries);
+        // act
+        let usecase = VerifyChain::new(Box::new(mock));
+        let params = Params::new("store1".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert!(report.is_intact(), "{:?}", report.problems);
+        assert_eq!(report.expected, 3);
+        assert_eq!(report.found, 3);
+    }
+
+    #[test]
+    fn test_verify_chain_rolled_back() {
+        // arrange
+        let entries = make_entries(3);
+        let stored = entries[0..2].to_vec();
+        let mock = make_mock(entries, stored);
+        // act
+        let usecase = VerifyChain::new(Box::new(mock));
+        let params = Params::new("store1".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.found, 2);
+        assert!(report.problems.iter().any(|p| p.contains("rolled back")));
+    }
+
+    #[test]
+    fn test_verify_chain_deleted_entry() {
+        // arrange
+        let entries = make_entries(3);
+        let stored = vec![entries[0].clone(), entries[2].clone()];
+        let mock = make_mock(entries, stored);
+        // act
+        let usecase = VerifyChain::new(Box::new(mock));
+        let params = Params::new("store1".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p == "entries missing between 1 and 3"));
+        assert!(report.problems.iter().any(|p| p == "entry 2 is missing"));
+    }
+
+    #[test]
+    fn test_verify_chain_altered_entry() {
+        // arrange
+        let entries = make_entries(2);
+        let mut stored = entries.clone();
+        stored[1].snapshot = Checksum::SHA1("ed841695851abdcfe6a50ce3d01d770eb053356b".to_owned());
+        let mock = make_mock(entries, stored);
+        // act
+        let usecase = VerifyChain::new(Box::new(mock));
+        let params = Params::new("store1".to_owned());
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let report = result.unwrap();
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p == "entry 2 has been altered"));
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p == "entry 2 differs from database"));
+    }
+}
@@ -1,84 +1,315 @@
 //
 // Copyright (c) 2023 Nathan Fiedler
 //
-use crate::domain::entities::{Checksum, TreeReference};
+use crate::domain::entities::{
+    Checksum, Dataset, Message, MessageCode, TreeReference, Verification, VerifyDepth,
+};
+use crate::domain::managers::maintenance::Reservoir;
+use crate::domain::managers::progress::{OperationKind, Progress, Reporter};
+use crate::domain::managers::restore::FileRestorer;
 use crate::domain::repositories::RecordRepository;
 use anyhow::{anyhow, Error};
 use bloomfilter::Bloom;
+use chrono::prelude::*;
+use log::{info, warn};
 use std::cmp;
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, VecDeque};
 use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use store_core::Secret;
 
+// Number of verifications to retain for each dataset.
+const MAX_VERIFICATIONS: usize = 50;
+
+///
+/// Verify a snapshot to the given depth, saving the outcome such that it can
+/// be reviewed later, and pruning the oldest verifications of the dataset.
+///
+/// Every depth ensures the records referenced by the snapshot exist. The
+/// `Packs` depth also retrieves each referenced pack and compares its digest,
+/// while `Full` goes on to restore a sample of the files (or all of them) to a
+/// temporary location and compare their digests with the file records.
+///
 pub struct VerifySnapshot {
     repo: Box<dyn RecordRepository>,
+    fetcher: Mutex<Box<dyn FileRestorer>>,
 }
 
 impl VerifySnapshot {
-    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
-        Self { repo }
-    }
-}
-
-impl super::UseCase<Vec<DataError>, Params> for VerifySnapshot {
-    fn call(&self, params: Params) -> Result<Vec<DataError>, Error> {
-        let mut issues: Vec<DataError> = Vec::new();
-        let snapshot = self
-            .repo
-            .get_snapshot(&params.digest)?
-            .ok_or_else(|| anyhow!(format!("missing snapshot: {:?}", params.digest)))?;
-        let mut pending_trees: VecDeque<Checksum> = VecDeque::new();
-        pending_trees.push_back(snapshot.tree);
-        // roughly 1mb of space for visited tree filter
-        let mut visited_trees: Bloom<Checksum> = Bloom::new_for_fp_rate(250000, 0.0000001);
-        // roughly 2mb of space for visited file filter
-        let mut visited_files: Bloom<Checksum> = Bloom::new_for_fp_rate(500000, 0.0000001);
-        while let Some(tree_digest) = pending_trees.pop_front() {
-            if !visited_trees.check(&tree_digest) {
-                if let Some(tree) = self.repo.get_tree(&tree_digest)? {
-                    for entry in tree.entries.iter() {
-                        match &entry.reference {
-                            TreeReference::LINK(_) => (),
-                            TreeReference::SMALL(_) => (),
-                            TreeReference::TREE(checksum) => {
-                                pending_trees.push_back(checksum.to_owned())
-                            }
-                            TreeReference::FILE(file_digest) => {
-                                if !visited_files.check(file_digest) {
-                                    if let Some(code) = check_file(&self.repo, file_digest)? {
-                                        issues.push(code);
-                                    }
-                                    visited_files.set(file_digest);
+    pub fn new(repo: Box<dyn RecordRepository>, fetcher: Box<dyn FileRestorer>) -> Self {
+        Self {
+            repo,
+            fetcher: Mutex::new(fetcher),
+        }
+    }
+}
+
+impl super::UseCase<Verification, Params> for VerifySnapshot {
+    fn call(&self, params: Params) -> Result<Verification, Error> {
+        let mut fetcher = self.fetcher.lock().unwrap();
+        verify_snapshot(self.repo.as_ref(), fetcher.as_mut(), params)
+    }
+}
+
+// Return true if the snapshot is one of those of the dataset, following the
+// parents from the latest snapshot, as the verification is saved with the
+// dataset and the restored files are fetched using its stores.
+fn in_dataset(
+    repo: &dyn RecordRepository,
+    dataset_id: &str,
+    digest: &Checksum,
+) -> Result<bool, Error> {
+    let mut next = repo.get_latest_snapshot(dataset_id)?;
+    while let Some(current) = next {
+        if &current == digest {
+            return Ok(true);
+        }
+        next = repo.get_snapshot(&current)?.and_then(|s| s.parent);
+    }
+    Ok(false)
+}
+
+// Verify the snapshot as described for `VerifySnapshot`, borrowing the
+// repository and restorer such that other managers may do the same.
+pub(crate) fn verify_snapshot(
+    repo: &dyn RecordRepository,
+    fetcher: &mut dyn FileRestorer,
+    params: Params,
+) -> Result<Verification, Error> {
+    let dataset = repo
+        .get_dataset(&params.dataset)?
+        .ok_or_else(|| Message::new(MessageCode::NoSuchDataset).with("id", &params.dataset))?;
+    let snapshot = repo
+        .get_snapshot(&params.digest)?
+        .ok_or_else(|| Message::new(MessageCode::MissingSnapshot).with("digest", &params.digest))?;
+    if !in_dataset(repo, &dataset.id, &snapshot.digest)? {
+        return Err(anyhow!(format!(
+            "snapshot {} does not belong to dataset {}",
+            snapshot.digest, dataset.id
+        )));
+    }
+    if params.depth == VerifyDepth::Full && params.passphrase.is_none() {
+        return Err(anyhow!("passphrase is required to restore files"));
+    }
+    let mut verification = Verification::new(&dataset.id, snapshot.digest, params.depth);
+    let progress = Reporter::new(OperationKind::Verify, &verification.snapshot.to_string());
+    let mut issues: Vec<DataError> = Vec::new();
+    let mut packs: BTreeSet<Checksum> = BTreeSet::new();
+    let mut files: Reservoir<Checksum> = Reservoir::new(match params.depth {
+        VerifyDepth::Full => params.sample.unwrap_or(usize::MAX),
+        _ => 0,
+    });
+    check_records(
+        repo,
+        snapshot.tree,
+        &mut verification,
+        &mut packs,
+        &mut files,
+        &mut issues,
+        &progress,
+    )?;
+    if params.depth != VerifyDepth::Metadata {
+        check_packs(
+            repo,
+            &dataset,
+            &packs,
+            &mut verification,
+            &mut issues,
+            &progress,
+        )?;
+    }
+    if let Some(passphrase) = params.passphrase.as_ref() {
+        if params.depth == VerifyDepth::Full {
+            let files = files.into_sample();
+            check_files(
+                fetcher,
+                &dataset,
+                files,
+                passphrase,
+                &mut verification,
+                &mut issues,
+                &progress,
+            )?;
+        }
+    }
+    verification.issues = issues.iter().map(|i| i.to_string()).collect();
+    verification.finished = Utc::now();
+    info!(
+        "VerifySnapshot: {} of {} found {} issues",
+        verification.depth,
+        verification.snapshot,
+        verification.issues.len()
+    );
+    progress.finish(None);
+    save(repo, &verification)?;
+    Ok(verification)
+}
+
+// Walk the trees of the snapshot, ensuring every record exists, and
+// collecting the digests of the packs and a sample of the files.
+fn check_records(
+    repo: &dyn RecordRepository,
+    tree: Checksum,
+    verification: &mut Verification,
+    packs: &mut BTreeSet<Checksum>,
+    files: &mut Reservoir<Checksum>,
+    issues: &mut Vec<DataError>,
+    progress: &dyn Progress,
+) -> Result<(), Error> {
+    let mut pending_trees: VecDeque<Checksum> = VecDeque::new();
+    pending_trees.push_back(tree);
+    // roughly 1mb of space for visited tree filter
+    let mut visited_trees: Bloom<Checksum> = Bloom::new_for_fp_rate(250000, 0.0000001);
+    // roughly 2mb of space for visited file filter
+    let mut visited_files: Bloom<Checksum> = Bloom::new_for_fp_rate(500000, 0.0000001);
+    while let Some(tree_digest) = pending_trees.pop_front() {
+        if !visited_trees.check(&tree_digest) {
+            if let Some(tree) = repo.get_tree(&tree_digest)? {
+                for entry in tree.entries.iter() {
+                    match &entry.reference {
+                        TreeReference::LINK(_) => (),
+                        TreeReference::SMALL(_) => (),
+                        TreeReference::TREE(checksum) => {
+                            pending_trees.push_back(checksum.to_owned())
+                        }
+                        TreeReference::FILE(file_digest) => {
+                            if !visited_files.check(file_digest) {
+                                match check_file(repo, file_digest, packs)? {
+                                    Some(code) => issues.push(code),
+                                    None => files.offer(file_digest.to_owned()),
                                 }
+                                visited_files.set(file_digest);
+                                verification.files += 1;
+                                progress.advance(1, 0);
                             }
                         }
-                        for (_, xattr_digest) in entry.xattrs.iter() {
-                            if self.repo.get_xattr(xattr_digest)?.is_none() {
-                                issues.push(DataError::MissingXattrs(xattr_digest.to_owned()));
-                            }
+                    }
+                    for (_, xattr_digest) in entry.xattrs.iter() {
+                        if repo.get_xattr(xattr_digest)?.is_none() {
+                            issues.push(DataError::MissingXattrs(xattr_digest.to_owned()));
                         }
                     }
-                    visited_trees.set(&tree_digest);
-                } else {
-                    issues.push(DataError::MissingTree(tree_digest.to_owned()));
                 }
+                visited_trees.set(&tree_digest);
+            } else {
+                issues.push(DataError::MissingTree(tree_digest.to_owned()));
             }
         }
-        // TODO: devise an efficient means of verifying all pack entries, or
-        //       consider putting this into a separate use case due to cost
-        Ok(issues)
     }
+    Ok(())
+}
+
+// Retrieve each of the packs, whose digests are compared with the pack
+// records by the pack repository as they are retrieved.
+fn check_packs(
+    repo: &dyn RecordRepository,
+    dataset: &Dataset,
+    packs: &BTreeSet<Checksum>,
+    verification: &mut Verification,
+    issues: &mut Vec<DataError>,
+    progress: &dyn Progress,
+) -> Result<(), Error> {
+    let pack_repo = repo.load_dataset_stores(dataset)?;
+    for digest in packs.iter() {
+        // missing pack records have already been reported
+        let Some(pack) = repo.get_pack(digest)? else {
+            continue;
+        };
+        let outfile = tempfile::NamedTempFile::new()?.into_temp_path();
+        match pack_repo.retrieve_pack(&pack.locations, &pack.digest, &outfile) {
+            Ok(()) => {
+                let size = std::fs::metadata(&outfile)?.len();
+                progress.advance(1, size);
+            }
+            Err(err) => {
+                warn!("VerifySnapshot: pack {} failed: {}", digest, err);
+                issues.push(DataError::CorruptPack(digest.to_owned(), err.to_string()));
+                progress.advance(1, 0);
+            }
+        }
+        verification.packs += 1;
+    }
+    Ok(())
+}
+
+// Restore each of the files to a temporary directory and compare the
+// digest of the restored file with that of the file record.
+fn check_files(
+    fetcher: &mut dyn FileRestorer,
+    dataset: &Dataset,
+    files: Vec<Checksum>,
+    passphrase: &Secret,
+    verification: &mut Verification,
+    issues: &mut Vec<DataError>,
+    progress: &dyn Progress,
+) -> Result<(), Error> {
+    let target = tempfile::TempDir::new()?;
+    fetcher.load_dataset(&dataset.id, Some(target.path().to_path_buf()))?;
+    for digest in files.iter() {
+        let name = digest.to_string();
+        let outfile = target.path().join(&name);
+        match fetcher.fetch_file(digest, Path::new(&name), passphrase.expose()) {
+            Ok(size) => {
+                // only the BLAKE3 digests are computed from the contents
+                if digest.is_blake3() {
+                    let actual = Checksum::blake3_from_file(&outfile)?;
+                    if &actual != digest {
+                        issues.push(DataError::CorruptFile(digest.to_owned(), actual));
+                    }
+                }
+                progress.advance(1, size);
+            }
+            Err(err) => {
+                warn!("VerifySnapshot: file {} failed: {}", digest, err);
+                issues.push(DataError::UnrestorableFile(
+                    digest.to_owned(),
+                    err.to_string(),
+                ));
+                progress.advance(1, 0);
+            }
+        }
+        let _ = std::fs::remove_file(&outfile);
+        verification.restored += 1;
+    }
+    Ok(())
+}
+
+// Save the verification and remove the oldest of those for the dataset.
+fn save(repo: &dyn RecordRepository, verification: &Verification) -> Result<(), Error> {
+    repo.put_verification(verification)?;
+    let saved = repo.get_verifications(&verification.dataset_id)?;
+    if saved.len() > MAX_VERIFICATIONS {
+        for old in saved.iter().take(saved.len() - MAX_VERIFICATIONS) {
+            repo.delete_verification(&old.dataset_id, &old.id)?;
+        }
+    }
+    Ok(())
 }
 
 fn check_file(
-    repo: &Box<dyn RecordRepository>,
+    repo: &dyn RecordRepository,
     file_digest: &Checksum,
+    packs: &mut BTreeSet<Checksum>,
 ) -> Result<Option<DataError>, Error> {
     if let Some(file) = repo.get_file(file_digest)? {
+        if file.chunks.len() == 1 {
+            // a single chunk is the digest of the pack itself
+            let pack_digest = &file.chunks[0].1;
+            if repo.get_pack(pack_digest)?.is_none() {
+                return Ok(Some(DataError::MissingPack(pack_digest.to_owned())));
+            }
+            packs.insert(pack_digest.to_owned());
+            return Ok(None);
+        }
         for (_, chunk_digest) in file.chunks.iter() {
             if let Some(chunk) = repo.get_chunk(chunk_digest)? {
                 if let Some(pack_digest) = chunk.packfile {
-                    if repo.get_pack(&pack_digest)?.is_none() {
-                        return Ok(Some(DataError::MissingPack(pack_digest.to_owned())));
+                    if !packs.contains(&pack_digest) {
+                        if repo.get_pack(&pack_digest)?.is_none() {
+                            return Ok(Some(DataError::MissingPack(pack_digest.to_owned())));
+                        }
+                        packs.insert(pack_digest);
                     }
                 } else {
                     return Ok(Some(DataError::UnpackedChunk(chunk_digest.to_owned())));
@@ -94,25 +325,52 @@ fn check_file(
 }
 
 pub struct Params {
+    /// Identifier of the dataset to which the snapshot belongs.
+    dataset: String,
     /// Hash digest of the snapshot to verify.
     digest: Checksum,
+    /// How thoroughly to verify the snapshot.
+    depth: VerifyDepth,
+    /// Number of files to restore at the full depth, or all of them if `None`.
+    sample: Option<usize>,
+    /// Pass phrase for decrypting the packs, required at the full depth.
+    passphrase: Option<Secret>,
 }
 
 impl Params {
-    pub fn new(digest: Checksum) -> Self {
-        Self { digest }
+    pub fn new(
+        dataset: String,
+        digest: Checksum,
+        depth: VerifyDepth,
+        sample: Option<usize>,
+        passphrase: Option<Secret>,
+    ) -> Self {
+        Self {
+            dataset,
+            digest,
+            depth,
+            sample,
+            passphrase,
+        }
     }
 }
 
 impl fmt::Display for Params {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Params({})", self.digest)
+        write!(
+            f,
+            "Params({}, {}, {})",
+            self.dataset, self.digest, self.depth
+        )
     }
 }
 
 impl cmp::PartialEq for Params {
     fn eq(&self, other: &Self) -> bool {
-        self.digest == other.digest
+        self.dataset == other.dataset
+            && self.digest == other.digest
+            && self.depth == other.depth
+            && self.sample == other.sample
     }
 }
 
@@ -133,14 +391,43 @@ pub enum DataError {
     MissingPack(Checksum),
     /// Missing extended attributes record.
     MissingXattrs(Checksum),
+    /// Pack could not be retrieved or its digest did not match.
+    CorruptPack(Checksum, String),
+    /// File could not be restored.
+    UnrestorableFile(Checksum, String),
+    /// Restored file has a different digest.
+    CorruptFile(Checksum, Checksum),
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::MissingTree(digest) => write!(f, "missing tree {}", digest),
+            DataError::MissingFile(digest) => write!(f, "missing file {}", digest),
+            DataError::MissingChunk(digest) => write!(f, "missing chunk {}", digest),
+            DataError::UnpackedChunk(digest) => write!(f, "chunk {} has no pack", digest),
+            DataError::MissingPack(digest) => write!(f, "missing pack {}", digest),
+            DataError::MissingXattrs(digest) => write!(f, "missing xattrs {}", digest),
+            DataError::CorruptPack(digest, err) => write!(f, "pack {} failed: {}", digest, err),
+            DataError::UnrestorableFile(digest, err) => {
+                write!(f, "file {} could not be restored: {}", digest, err)
+            }
+            DataError::CorruptFile(digest, actual) => {
+                write!(f, "file {} was restored as {}", digest, actual)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::UseCase;
     use super::*;
-    use crate::domain::entities::Snapshot;
-    use crate::domain::repositories::MockRecordRepository;
+    use crate::domain::entities::{Chunk, File, Pack, PackLocation, Snapshot, Tree, TreeEntry};
+    use crate::domain::managers::restore::MockFileRestorer;
+    use crate::domain::repositories::{MockPackRepository, MockRecordRepository};
+    use std::path::PathBuf;
+    use std::sync::Arc;
 
     #[test]
     fn test_verify_snapshot_missing_snapshot() {
@@ -148,12 +435,21 @@ mod tests {
         let snapshot_sha1 = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
         let snapshot_cleon = snapshot_sha1.clone();
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
         mock.expect_get_snapshot()
             .withf(move |d| d == &snapshot_sha1)
             .returning(move |_| Ok(None));
+        mock.expect_put_verification().never();
         // act
-        let usecase = VerifySnapshot::new(Box::new(mock));
-        let params = Params::new(snapshot_cleon);
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(MockFileRestorer::new()));
+        let params = Params::new(
+            "cafebabe".into(),
+            snapshot_cleon,
+            VerifyDepth::Metadata,
+            None,
+            None,
+        );
         let result = usecase.call(params);
         // assert
         assert!(result.is_err());
@@ -170,26 +466,245 @@ mod tests {
         let snapshot_sha1 = snapshot.digest.clone();
         let snapshot_sha2 = snapshot.digest.clone();
         let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
+        let latest = snapshot.digest.clone();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
         mock.expect_get_snapshot()
             .withf(move |d| d == &snapshot_sha1)
             .returning(move |_| Ok(Some(snapshot.clone())));
         mock.expect_get_tree()
             .withf(move |d| d == &tree_sha2)
             .returning(|_| Ok(None));
+        mock.expect_load_dataset_stores().never();
+        mock.expect_put_verification()
+            .times(1)
+            .returning(|_| Ok(()));
+        mock.expect_get_verifications().returning(|_| Ok(vec![]));
+        // act
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(MockFileRestorer::new()));
+        let params = Params::new(
+            "cafebabe".into(),
+            snapshot_sha2,
+            VerifyDepth::Metadata,
+            None,
+            None,
+        );
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let verification = result.unwrap();
+        assert!(!verification.passed());
+        assert_eq!(verification.issues.len(), 1);
+        assert!(verification.issues[0].starts_with("missing tree"));
+    }
+
+    // Produce a snapshot of two files, one of a single chunk and another of
+    // two chunks, the latter in a pack that cannot be retrieved.
+    fn make_snapshot(mock: &mut MockRecordRepository) -> Checksum {
+        let content = b"lorem ipsum dolor sit amet";
+        let file1 = File::new(
+            Checksum::blake3_from_bytes(content),
+            26,
+            vec![(0, Checksum::BLAKE3("pack1".into()))],
+        );
+        let file2 = File::new(
+            Checksum::BLAKE3("file2".into()),
+            3000,
+            vec![
+                (0, Checksum::BLAKE3("chunk1".into())),
+                (1000, Checksum::BLAKE3("chunk2".into())),
+            ],
+        );
+        let chunks = vec![
+            Chunk::new(Checksum::BLAKE3("chunk1".into()), 0, 1000)
+                .packfile(Checksum::BLAKE3("pack2".into())),
+            Chunk::new(Checksum::BLAKE3("chunk2".into()), 1000, 2000)
+                .packfile(Checksum::BLAKE3("pack2".into())),
+        ];
+        let tree = Tree::new(
+            vec![
+                TreeEntry::new(
+                    Path::new("../test/fixtures/lorem-ipsum.txt"),
+                    TreeReference::FILE(file1.digest.clone()),
+                ),
+                TreeEntry::new(
+                    Path::new("../test/fixtures/washington-journal.txt"),
+                    TreeReference::FILE(file2.digest.clone()),
+                ),
+            ],
+            2,
+        );
+        let snapshot = Snapshot::new(None, tree.digest.clone(), Default::default());
+        let digest = snapshot.digest.clone();
+        let latest = snapshot.digest.clone();
+        let files = vec![file1, file2];
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        mock.expect_get_file()
+            .returning(move |digest| Ok(files.iter().find(|f| &f.digest == digest).cloned()));
+        mock.expect_get_chunk()
+            .returning(move |digest| Ok(chunks.iter().find(|c| &c.digest == digest).cloned()));
+        mock.expect_get_pack().returning(|digest| {
+            let locations = vec![PackLocation::new("local1", "bucket1", "object1")];
+            Ok(Some(Pack::new(digest.clone(), locations)))
+        });
+        mock.expect_load_dataset_stores().returning(|_| {
+            let mut stores = MockPackRepository::new();
+            stores
+                .expect_retrieve_pack()
+                .times(2)
+                .returning(|_, digest, outfile| {
+                    if digest == &Checksum::BLAKE3("pack2".into()) {
+                        Err(anyhow!("pack digest does not match"))
+                    } else {
+                        std::fs::write(outfile, b"pack")?;
+                        Ok(())
+                    }
+                });
+            Ok(Box::new(stores))
+        });
+        mock.expect_put_verification()
+            .times(1)
+            .returning(|_| Ok(()));
+        digest
+    }
+
+    #[test]
+    fn test_verify_snapshot_packs() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let digest = make_snapshot(&mut mock);
+        mock.expect_get_verifications().returning(|dataset| {
+            let mut saved: Vec<Verification> = Vec::new();
+            for _ in 0..MAX_VERIFICATIONS + 2 {
+                let digest = Checksum::BLAKE3("snapshot".into());
+                saved.push(Verification::new(dataset, digest, VerifyDepth::Metadata));
+            }
+            Ok(saved)
+        });
+        mock.expect_delete_verification()
+            .times(2)
+            .returning(|_, _| Ok(()));
+        let mut fetcher = MockFileRestorer::new();
+        fetcher.expect_fetch_file().never();
+        // act
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(fetcher));
+        let params = Params::new("cafebabe".into(), digest, VerifyDepth::Packs, None, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_ok());
+        let verification = result.unwrap();
+        assert_eq!(verification.depth, VerifyDepth::Packs);
+        assert_eq!(verification.files, 2);
+        assert_eq!(verification.packs, 2);
+        assert_eq!(verification.restored, 0);
+        assert_eq!(verification.issues.len(), 1);
+        assert!(verification.issues[0].contains("pack digest does not match"));
+    }
+
+    #[test]
+    fn test_verify_snapshot_full() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        let digest = make_snapshot(&mut mock);
+        mock.expect_get_verifications().returning(|_| Ok(vec![]));
+        let target: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
+        let target_clone = target.clone();
+        let mut fetcher = MockFileRestorer::new();
+        fetcher.expect_load_dataset().returning(move |_, path| {
+            *target_clone.lock().unwrap() = path;
+            Ok(())
+        });
+        // the first file is restored intact, while the second cannot be
+        fetcher
+            .expect_fetch_file()
+            .times(2)
+            .returning(move |digest, filepath, _| {
+                assert_eq!(filepath, Path::new(&digest.to_string()));
+                if digest == &Checksum::BLAKE3("file2".into()) {
+                    return Err(anyhow!("oh no"));
+                }
+                let basepath = target.lock().unwrap().clone().unwrap();
+                std::fs::write(basepath.join(filepath), b"lorem ipsum dolor sit amet")?;
+                Ok(26)
+            });
         // act
-        let usecase = VerifySnapshot::new(Box::new(mock));
-        let params = Params::new(snapshot_sha2);
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(fetcher));
+        let params = Params::new(
+            "cafebabe".into(),
+            digest,
+            VerifyDepth::Full,
+            Some(5),
+            Some(Secret::from("keyboard cat")),
+        );
         let result = usecase.call(params);
         // assert
         assert!(result.is_ok());
-        let errors = result.unwrap();
-        assert_eq!(errors.len(), 1);
-        matches!(&errors[0], DataError::MissingTree(_));
+        let verification = result.unwrap();
+        assert_eq!(verification.packs, 2);
+        assert_eq!(verification.restored, 2);
+        assert_eq!(verification.issues.len(), 2);
+        assert!(verification.issues[1].contains("could not be restored: oh no"));
     }
 
-    // TODO: missing file
-    // TODO: missing pack
-    // TODO: missing chunk
-    // TODO: missing xattr
-    // TODO: unpacked chunk
+    #[test]
+    fn test_verify_snapshot_full_no_passphrase() {
+        // arrange
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
+        let snapshot = Snapshot::new(None, Checksum::BLAKE3("tree".into()), Default::default());
+        let digest = snapshot.digest.clone();
+        let latest = snapshot.digest.clone();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree().never();
+        // act
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(MockFileRestorer::new()));
+        let params = Params::new("cafebabe".into(), digest, VerifyDepth::Full, None, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("passphrase is required"));
+    }
+
+    #[test]
+    fn test_verify_snapshot_other_dataset() {
+        // arrange
+        let tree = Checksum::BLAKE3("tree".into());
+        let older = Snapshot::new(None, tree.clone(), Default::default());
+        let newer = Snapshot::new(Some(older.digest.clone()), tree, Default::default());
+        let foreign = Snapshot::new(None, Checksum::BLAKE3("other".into()), Default::default());
+        let digest = foreign.digest.clone();
+        let latest = newer.digest.clone();
+        let snapshots = vec![older, newer, foreign];
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_dataset()
+            .returning(|_| Ok(Some(Dataset::new(Path::new("/home/planet")))));
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |digest| Ok(snapshots.iter().find(|s| &s.digest == digest).cloned()));
+        mock.expect_get_tree().never();
+        mock.expect_put_verification().never();
+        // act
+        let usecase = VerifySnapshot::new(Box::new(mock), Box::new(MockFileRestorer::new()));
+        let params = Params::new("cafebabe".into(), digest, VerifyDepth::Metadata, None, None);
+        let result = usecase.call(params);
+        // assert
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("does not belong to dataset"));
+    }
 }
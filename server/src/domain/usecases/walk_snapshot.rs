@@ -0,0 +1,315 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::domain::entities::{
+    Checksum, Message, MessageCode, SnapshotWalk, Tree, TreeReference, WalkedEntry,
+};
+use crate::domain::repositories::RecordRepository;
+use anyhow::{anyhow, Error};
+use std::cmp;
+use std::fmt;
+
+/// Number of entries returned when the caller does not specify a limit.
+pub const DEFAULT_LIMIT: usize = 1_000;
+
+/// Largest number of entries that will be returned at one time.
+pub const MAX_LIMIT: usize = 10_000;
+
+///
+/// Walk the trees of a snapshot in depth-first order, returning a limited
+/// number of entries at a time along with a cursor from which to continue.
+///
+/// The cursor records the position within each tree along the path from the
+/// root to the next entry, so the walk holds only those trees in memory, and
+/// resuming a walk reads only those trees again. Since the trees of a snapshot
+/// never change, the cursor remains valid indefinitely.
+///
+pub struct WalkSnapshot {
+    repo: Box<dyn RecordRepository>,
+}
+
+impl WalkSnapshot {
+    pub fn new(repo: Box<dyn RecordRepository>) -> Self {
+        Self { repo }
+    }
+
+    fn get_tree(&self, digest: &Checksum) -> Result<Tree, Error> {
+        self.repo.get_tree(digest)?.ok_or_else(|| {
+            Message::new(MessageCode::MissingTree)
+                .with("digest", digest)
+                .into()
+        })
+    }
+
+    // Rebuild the stack of trees described by the cursor, which is the index
+    // of the next entry within each tree, separated by slashes.
+    fn resume(&self, root: Tree, cursor: Option<&str>) -> Result<Vec<Frame>, Error> {
+        let mut stack = vec![Frame {
+            tree: root,
+            index: 0,
+            path: String::new(),
+        }];
+        let cursor = match cursor {
+            Some(value) if !value.is_empty() => value,
+            _ => return Ok(stack),
+        };
+        let mut indices = Vec::new();
+        for part in cursor.split('/') {
+            let index: usize = part
+                .parse()
+                .map_err(|_| anyhow!(format!("invalid cursor: {}", cursor)))?;
+            indices.push(index);
+        }
+        for (depth, index) in indices.iter().enumerate() {
+            if depth > 0 {
+                // the entry before the parent's next entry is this subtree
+                let parent = stack.last().unwrap();
+                let entry = parent
+                    .index
+                    .checked_sub(1)
+                    .and_then(|i| parent.tree.entries.get(i))
+                    .ok_or_else(|| anyhow!(format!("invalid cursor: {}", cursor)))?;
+                let subtree = match &entry.reference {
+                    TreeReference::TREE(digest) => self.get_tree(digest)?,
+                    _ => return Err(anyhow!(format!("invalid cursor: {}", cursor))),
+                };
+                let path = join_path(&parent.path, &entry.name);
+                stack.push(Frame {
+                    tree: subtree,
+                    index: 0,
+                    path,
+                });
+            }
+            let frame = stack.last_mut().unwrap();
+            if *index > frame.tree.entries.len() {
+                return Err(anyhow!(format!("invalid cursor: {}", cursor)));
+            }
+            frame.index = *index;
+        }
+        Ok(stack)
+    }
+}
+
+impl super::UseCase<SnapshotWalk, Params> for WalkSnapshot {
+    fn call(&self, params: Params) -> Result<SnapshotWalk, Error> {
+        let snapshot = self.repo.get_snapshot(&params.digest)?.ok_or_else(|| {
+            Message::new(MessageCode::MissingSnapshot).with("digest", &params.digest)
+        })?;
+        let root = self.get_tree(&snapshot.tree)?;
+        let mut stack = self.resume(root, params.cursor.as_deref())?;
+        let limit = params.limit.clamp(1, MAX_LIMIT);
+        let mut walk = SnapshotWalk::default();
+        while walk.entries.len() < limit {
+            let frame = match stack.last_mut() {
+                Some(frame) => frame,
+                None => break,
+            };
+            if frame.index >= frame.tree.entries.len() {
+                stack.pop();
+                continue;
+            }
+            let entry = frame.tree.entries[frame.index].clone();
+            frame.index += 1;
+            let path = join_path(&frame.path, &entry.name);
+            if let TreeReference::TREE(digest) = &entry.reference {
+                let subtree = self.get_tree(digest)?;
+                stack.push(Frame {
+                    tree: subtree,
+                    index: 0,
+                    path: path.clone(),
+                });
+            }
+            walk.entries.push(WalkedEntry { path, entry });
+        }
+        // drop the exhausted trees so the cursor is absent at the very end
+        while stack
+            .last()
+            .map_or(false, |f| f.index >= f.tree.entries.len())
+        {
+            stack.pop();
+        }
+        if !stack.is_empty() {
+            let indices: Vec<String> = stack.iter().map(|f| f.index.to_string()).collect();
+            walk.cursor = Some(indices.join("/"));
+        }
+        Ok(walk)
+    }
+}
+
+// Position within one of the trees along the path to the next entry.
+struct Frame {
+    tree: Tree,
+    // Index of the next entry to be returned.
+    index: usize,
+    // Path of the tree relative to the root of the snapshot.
+    path: String,
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+pub struct Params {
+    /// Digest of the snapshot to be walked.
+    digest: Checksum,
+    /// Position from which to continue a previous walk, if any.
+    cursor: Option<String>,
+    /// Maximum number of entries to return.
+    limit: usize,
+}
+
+impl Params {
+    pub fn new(digest: Checksum, cursor: Option<String>, limit: Option<usize>) -> Self {
+        Self {
+            digest,
+            cursor,
+            limit: limit.unwrap_or(DEFAULT_LIMIT),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Params({}, {:?}, {})",
+            self.digest, self.cursor, self.limit
+        )
+    }
+}
+
+impl cmp::PartialEq for Params {
+    fn eq(&self, other: &Self) -> bool {
+        self.digest == other.digest && self.cursor == other.cursor && self.limit == other.limit
+    }
+}
+
+impl cmp::Eq for Params {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UseCase;
+    use super::*;
+    use crate::domain::entities::{FileCounts, Snapshot, TreeEntry};
+    use crate::domain::repositories::MockRecordRepository;
+    use std::path::Path;
+
+    // Build a snapshot of the form: a.txt, dir/{b.txt, sub/{c.txt}}, z.txt
+    fn make_snapshot() -> (Snapshot, Vec<Tree>) {
+        let file_entry = |name: &str| {
+            let mut entry = TreeEntry::new(
+                Path::new("../test/fixtures/lorem-ipsum.txt"),
+                TreeReference::SMALL(name.as_bytes().to_vec()),
+            );
+            entry.name = name.to_owned();
+            entry
+        };
+        let tree_entry = |name: &str, tree: &Tree| {
+            let mut entry = TreeEntry::new(
+                Path::new("../test/fixtures"),
+                TreeReference::TREE(tree.digest.clone()),
+            );
+            entry.name = name.to_owned();
+            entry
+        };
+        let sub = Tree::new(vec![file_entry("c.txt")], 1);
+        let dir = Tree::new(vec![file_entry("b.txt"), tree_entry("sub", &sub)], 2);
+        let root = Tree::new(
+            vec![
+                file_entry("a.txt"),
+                tree_entry("dir", &dir),
+                file_entry("z.txt"),
+            ],
+            4,
+        );
+        let snapshot = Snapshot::new(None, root.digest.clone(), FileCounts::default());
+        (snapshot, vec![root, dir, sub])
+    }
+
+    fn make_usecase() -> (WalkSnapshot, Checksum) {
+        let (snapshot, trees) = make_snapshot();
+        let digest = snapshot.digest.clone();
+        let mut mock = MockRecordRepository::new();
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |digest| Ok(trees.iter().find(|t| &t.digest == digest).cloned()));
+        (WalkSnapshot::new(Box::new(mock)), digest)
+    }
+
+    #[test]
+    fn test_walk_snapshot_all() {
+        // arrange
+        let (usecase, digest) = make_usecase();
+        // act
+        let params = Params::new(digest, None, None);
+        let result = usecase.call(params);
+        // assert
+        let walk = result.unwrap();
+        let paths: Vec<&str> = walk.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "a.txt",
+                "dir",
+                "dir/b.txt",
+                "dir/sub",
+                "dir/sub/c.txt",
+                "z.txt"
+            ]
+        );
+        assert!(walk.cursor.is_none());
+    }
+
+    #[test]
+    fn test_walk_snapshot_pages() {
+        // arrange
+        let (usecase, digest) = make_usecase();
+        let mut paths: Vec<String> = Vec::new();
+        let mut cursor: Option<String> = None;
+        let mut pages = 0;
+        // act
+        loop {
+            let params = Params::new(digest.clone(), cursor, Some(2));
+            let walk = usecase.call(params).unwrap();
+            pages += 1;
+            paths.extend(walk.entries.into_iter().map(|e| e.path));
+            cursor = walk.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        // assert
+        assert_eq!(pages, 3);
+        assert_eq!(
+            paths,
+            vec![
+                "a.txt",
+                "dir",
+                "dir/b.txt",
+                "dir/sub",
+                "dir/sub/c.txt",
+                "z.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_snapshot_bad_cursor() {
+        // arrange
+        let (usecase, digest) = make_usecase();
+        for cursor in ["x", "9", "1/0", "2/5"] {
+            // act
+            let params = Params::new(digest.clone(), Some(cursor.to_owned()), None);
+            let result = usecase.call(params);
+            // assert
+            assert!(result.is_err(), "cursor {}", cursor);
+            let err_string = result.err().unwrap().to_string();
+            assert!(err_string.contains("invalid cursor"));
+        }
+    }
+}
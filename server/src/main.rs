@@ -7,24 +7,46 @@
 
 use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
 use actix_web::{
     error::InternalError, http, middleware, web, App, HttpRequest, HttpResponse, HttpServer, Result,
 };
+use futures::StreamExt;
 use juniper::http::graphiql::graphiql_source;
 use juniper::http::GraphQLRequest;
+use juniper_actix::subscriptions;
+use juniper_graphql_ws::ConnectionConfig;
 use lazy_static::lazy_static;
 use log::{error, info};
-use server::data::repositories::RecordRepositoryImpl;
+use serde::{Deserialize, Serialize};
+use server::data::models::replica::decode_batch;
+use server::data::repositories::{self, RecordRepositoryImpl};
 use server::data::sources::{EntityDataSource, EntityDataSourceImpl};
+use server::domain::entities::{Checksum, DeviceScope};
 use server::domain::managers::backup::{Performer, PerformerImpl, Scheduler, SchedulerImpl};
+use server::domain::managers::maintenance;
+use server::domain::managers::migrate;
+use server::domain::managers::pairing;
+use server::domain::managers::replica;
 use server::domain::managers::restore::{FileRestorer, FileRestorerImpl, Restorer, RestorerImpl};
+use server::domain::managers::settings;
 use server::domain::managers::state::{self, StateStore, StateStoreImpl};
 use server::domain::repositories::RecordRepository;
+use server::domain::usecases::download_object::{self, DownloadObject};
+use server::domain::usecases::file_history::{self, FileHistory};
+use server::domain::usecases::get_status::GetStatus;
+use server::domain::usecases::restore_files::{self, RestoreFiles};
+use server::domain::usecases::upload_object::{self, UploadObject};
+use server::domain::usecases::{NoParams, UseCase};
 use server::preso::graphql;
+use server::preso::recover::{self, RecoverForm, RecoverQuery};
+use server::preso::status;
 use std::env;
-use std::io;
+use std::io::{self, Write};
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 // When running in test mode, the cwd is the server directory.
 #[cfg(test)]
@@ -42,6 +64,12 @@ static DEFAULT_WEB_PATH: &str = "../web/";
 #[cfg(not(test))]
 static DEFAULT_WEB_PATH: &str = "./web/";
 
+// Largest replica batch that will be accepted from the primary server.
+const MAX_REPLICA_BATCH: usize = 256 * 1024 * 1024;
+
+// Largest object that may be uploaded to a store by way of the object endpoint.
+const MAX_OBJECT_UPLOAD: usize = 1024 * 1024 * 1024;
+
 fn file_restorer_factory(dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
     Box::new(FileRestorerImpl::new(dbase))
 }
@@ -79,25 +107,32 @@ lazy_static! {
 }
 
 async fn graphiql() -> Result<HttpResponse> {
-    let html = graphiql_source("/graphql", None);
+    let html = graphiql_source("/graphql", Some("/subscriptions"));
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(html))
 }
 
 async fn graphql(
+    req: HttpRequest,
     st: web::Data<Arc<graphql::Schema>>,
     data: web::Json<GraphQLRequest>,
 ) -> Result<HttpResponse> {
     let source = EntityDataSourceImpl::new(DB_PATH.as_path())
         .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
     let datasource: Arc<dyn EntityDataSource> = Arc::new(source);
+    let repo = RecordRepositoryImpl::new(datasource.clone());
+    let access = request_access(&req, &repo)
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let Some(access) = access else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
     let state = STATE_STORE.clone();
     let processor = SCHEDULER.clone();
     let restorer = FILE_RESTORER.clone();
-    let ctx = Arc::new(graphql::GraphContext::new(
-        datasource, state, processor, restorer,
-    ));
+    let ctx = Arc::new(
+        graphql::GraphContext::new(datasource, state, processor, restorer).with_access(access),
+    );
     let res = data.execute(&st, &ctx).await;
     let body = serde_json::to_string(&res)?;
     Ok(HttpResponse::Ok()
@@ -105,6 +140,342 @@ async fn graphql(
         .body(body))
 }
 
+// Serve the GraphQL subscriptions over a websocket using either the
+// graphql-ws or graphql-transport-ws protocol.
+async fn subscriptions(
+    req: HttpRequest,
+    stream: web::Payload,
+    st: web::Data<Arc<graphql::Schema>>,
+) -> Result<HttpResponse> {
+    let source = EntityDataSourceImpl::new(DB_PATH.as_path())
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let datasource: Arc<dyn EntityDataSource> = Arc::new(source);
+    let repo = RecordRepositoryImpl::new(datasource.clone());
+    let access = request_access(&req, &repo)
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let Some(access) = access else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let state = STATE_STORE.clone();
+    let processor = SCHEDULER.clone();
+    let restorer = FILE_RESTORER.clone();
+    let ctx =
+        graphql::GraphContext::new(datasource, state, processor, restorer).with_access(access);
+    let config = ConnectionConfig::new(ctx).with_keep_alive_interval(Duration::from_secs(15));
+    let schema: Arc<graphql::Schema> = st.get_ref().clone();
+    subscriptions::ws_handler(req, stream, schema, config).await
+}
+
+// Determine the access granted to the request by way of its bearer token, if
+// any, and whether it came from the local host.
+fn request_access(
+    req: &HttpRequest,
+    repo: &dyn RecordRepository,
+) -> Result<Option<DeviceScope>, anyhow::Error> {
+    let header = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    let local = req.peer_addr().map_or(false, |a| a.ip().is_loopback());
+    pairing::authenticate(repo, header, local)
+}
+
+// Return an error response unless the request presents the token of a device
+// with full access. The raw objects bypass the database entirely, so unlike
+// the other endpoints, a token is required even of the local host.
+fn require_full_access(req: &HttpRequest) -> Result<Option<HttpResponse>> {
+    let bearer = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |h| h.starts_with("Bearer "));
+    if !bearer {
+        return Ok(Some(HttpResponse::Unauthorized().finish()));
+    }
+    let repo = open_repository()
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let access = request_access(req, repo.as_ref())
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    match access {
+        Some(DeviceScope::Full) => Ok(None),
+        Some(_) => Ok(Some(HttpResponse::Forbidden().finish())),
+        None => Ok(Some(HttpResponse::Unauthorized().finish())),
+    }
+}
+
+// Send an object from a store exactly as it was stored, for support purposes.
+async fn download_object(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse> {
+    if let Some(refused) = require_full_access(&req)? {
+        return Ok(refused);
+    }
+    let (store_id, bucket, object) = path.into_inner();
+    let filename = object.clone();
+    let temp_path = web::block(move || {
+        let repo = open_record_repository()?;
+        let usecase = DownloadObject::new(repo);
+        usecase.call(download_object::Params::new(store_id, bucket, object))
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::BAD_GATEWAY))?;
+    // the open file remains readable after the temporary path is removed
+    let file = NamedFile::open(&temp_path)?.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    });
+    Ok(file.into_response(&req))
+}
+
+// Save the request body to a store as the named object, for support purposes.
+// The body is written to a temporary file as it arrives rather than being
+// held in memory.
+async fn upload_object(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    mut body: web::Payload,
+) -> Result<HttpResponse> {
+    if let Some(refused) = require_full_access(&req)? {
+        return Ok(refused);
+    }
+    let (store_id, bucket, object) = path.into_inner();
+    let mut infile = web::block(tempfile::NamedTempFile::new).await??;
+    let mut received: usize = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        received += chunk.len();
+        if received > MAX_OBJECT_UPLOAD {
+            return Ok(HttpResponse::PayloadTooLarge().finish());
+        }
+        infile = web::block(move || infile.write_all(&chunk).map(|_| infile)).await??;
+    }
+    let location = web::block(move || {
+        infile.flush()?;
+        let repo = open_record_repository()?;
+        let usecase = UploadObject::new(repo);
+        let params =
+            upload_object::Params::new(store_id, bucket, object, infile.path().to_path_buf());
+        usecase.call(params)
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::BAD_GATEWAY))?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "store": location.store,
+        "bucket": location.bucket,
+        "object": location.object,
+    })))
+}
+
+// Open the database as a record repository for the use cases.
+fn open_record_repository() -> Result<Box<dyn RecordRepository>, anyhow::Error> {
+    let datasource = EntityDataSourceImpl::new(DB_PATH.as_path())?;
+    Ok(Box::new(RecordRepositoryImpl::new(Arc::new(datasource))))
+}
+
+// Open the database as a record repository for the replica requests.
+fn open_repository() -> Result<Arc<dyn RecordRepository>, anyhow::Error> {
+    let datasource = EntityDataSourceImpl::new(DB_PATH.as_path())?;
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+    Ok(Arc::new(repo))
+}
+
+// Determine if the request carries the replica token.
+fn replica_authorized(req: &HttpRequest) -> bool {
+    let header = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    replica::authorized(header)
+}
+
+// Report the digest of the latest snapshot of the dataset, if any, to the
+// primary server that is replicating to this server.
+async fn replica_latest(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse> {
+    if !replica_authorized(&req) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let dataset_id = path.into_inner();
+    let latest = web::block(move || open_repository()?.get_latest_snapshot(&dataset_id))
+        .await?
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let body = latest.map(|d| d.to_string()).unwrap_or_default();
+    Ok(HttpResponse::Ok().content_type("text/plain").body(body))
+}
+
+// Save a snapshot and its records sent by the primary server.
+async fn replica_apply(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse> {
+    if !replica_authorized(&req) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    web::block(move || {
+        let batch = decode_batch(&body)?;
+        replica::apply_batch(open_repository()?, batch)
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::BAD_REQUEST))?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    format: Option<String>,
+}
+
+// Summarize the backups, stores, and restores for a dashboard, as HTML or as
+// JSON, if the status page has been enabled.
+async fn status_page(req: HttpRequest, query: web::Query<StatusQuery>) -> Result<HttpResponse> {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let authorization = header(http::header::AUTHORIZATION.as_str());
+    match status::access(authorization, header(status::TOKEN_HEADER)) {
+        status::Access::Disabled => return Ok(HttpResponse::NotFound().finish()),
+        status::Access::Denied => return Ok(HttpResponse::Unauthorized().finish()),
+        status::Access::Granted => (),
+    }
+    let report = web::block(move || {
+        let usecase = GetStatus::new(
+            open_record_repository()?,
+            STATE_STORE.clone(),
+            FILE_RESTORER.clone(),
+            repositories::retrieval_failures(),
+            maintenance::last_results(),
+        );
+        usecase.call(NoParams {})
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let accepts_json = req
+        .headers()
+        .get(http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("application/json"))
+        .unwrap_or(false);
+    if query.format.as_deref() == Some("json") || accepts_json {
+        Ok(HttpResponse::Ok().json(status::to_json(&report)))
+    } else {
+        Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(status::to_html(&report)))
+    }
+}
+
+// Render the "get my file back" page, with the versions of the file that was
+// named in the query, if any.
+async fn recover_page(req: HttpRequest, query: web::Query<RecoverQuery>) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    recover_response(&req, query, None).await
+}
+
+// Enqueue the restore of the chosen version of a file, then show the versions
+// of the file again, along with a notice of what happened.
+async fn recover_file(req: HttpRequest, form: web::Form<RecoverForm>) -> Result<HttpResponse> {
+    let origin = req
+        .headers()
+        .get(http::header::ORIGIN)
+        .and_then(|v| v.to_str().ok());
+    let host = req
+        .headers()
+        .get(http::header::HOST)
+        .and_then(|v| v.to_str().ok());
+    if !recover::same_origin(origin, host) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let form = form.into_inner();
+    let query = RecoverQuery {
+        dataset: Some(form.dataset.clone()),
+        path: Some(form.path.clone()),
+    };
+    let filepath = form.filepath.clone();
+    let requested = web::block(move || {
+        let tree = Checksum::from_str(&form.tree)?;
+        let usecase = RestoreFiles::new(FILE_RESTORER.clone());
+        let params = restore_files::Params::new(
+            tree,
+            form.entry,
+            PathBuf::from(form.filepath),
+            form.dataset,
+        );
+        usecase.call(params)
+    })
+    .await?;
+    let notice = match requested {
+        Ok(()) => format!("Restore of {} has been requested.", filepath),
+        Err(err) => format!("Restore of {} could not be requested: {}", filepath, err),
+    };
+    recover_response(&req, query, Some(notice)).await
+}
+
+// Produce the "get my file back" page if the request is authorized.
+async fn recover_response(
+    req: &HttpRequest,
+    query: RecoverQuery,
+    notice: Option<String>,
+) -> Result<HttpResponse> {
+    let repo = open_record_repository()
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    let access = request_access(req, repo.as_ref())
+        .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    if access.is_none() {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let html = web::block(move || {
+        let datasets = repo.get_datasets()?;
+        let versions = match (query.dataset.clone(), query.path.clone()) {
+            (Some(dataset), Some(path)) if !path.is_empty() => {
+                let usecase = FileHistory::new(repo);
+                let params = file_history::Params::new(dataset, path, None);
+                Some(usecase.call(params).map_err(|e| e.to_string()))
+            }
+            _ => None,
+        };
+        Ok::<String, anyhow::Error>(recover::to_html(
+            &datasets,
+            &query,
+            versions,
+            notice.as_deref(),
+        ))
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html))
+}
+
+#[derive(Deserialize)]
+struct PairRequest {
+    code: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct PairResponse {
+    id: String,
+    name: String,
+    scope: String,
+    token: String,
+}
+
+// Exchange a pairing code for the access token of a newly paired device.
+async fn pair_device(data: web::Json<PairRequest>) -> Result<HttpResponse> {
+    let request = data.into_inner();
+    let paired = web::block(move || {
+        let repo = open_repository()?;
+        pairing::pair(repo.as_ref(), &request.code, &request.name)
+    })
+    .await?
+    .map_err(|e| InternalError::new(e, http::StatusCode::INTERNAL_SERVER_ERROR))?;
+    match paired {
+        Some((device, token)) => Ok(HttpResponse::Ok().json(PairResponse {
+            id: device.id,
+            name: device.name,
+            scope: device.scope.to_string(),
+            token,
+        })),
+        None => Ok(HttpResponse::Forbidden().finish()),
+    }
+}
+
 // Start and stop the supervisor(s) based on application state changes.
 fn manage_supervisors(state: &state::State, _previous: Option<&state::State>) {
     if state.supervisor == state::SupervisorState::Stopping {
@@ -178,9 +549,41 @@ async fn default_index(_req: HttpRequest) -> Result<NamedFile> {
     Ok(file.use_last_modified(true))
 }
 
+// Reload the configuration whenever the process receives a hangup signal.
+#[cfg(unix)]
+async fn reload_on_hangup() {
+    use actix_rt::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::hangup()) {
+        Ok(mut stream) => {
+            while stream.recv().await.is_some() {
+                info!("received SIGHUP, reloading configuration");
+                if let Err(err) = settings::reload() {
+                    error!("error reloading configuration: {}", err);
+                }
+            }
+        }
+        Err(err) => error!("error installing SIGHUP handler: {}", err),
+    }
+}
+
+// Open the database and bring its records up to date, refusing to go on if
+// the database was written by a newer version of the application.
+fn check_database() -> Result<(), anyhow::Error> {
+    let source = EntityDataSourceImpl::new(DB_PATH.as_path())?;
+    let repo = RecordRepositoryImpl::new(Arc::new(source));
+    migrate::self_check(&repo)?;
+    Ok(())
+}
+
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
-    env_logger::init();
+    settings::init_logging();
+    if let Err(err) = check_database() {
+        error!("database self-check failed: {}", err);
+        return Err(io::Error::other(err.to_string()));
+    }
+    #[cfg(unix)]
+    actix_rt::spawn(reload_on_hangup());
     STATE_STORE.subscribe("super-manager", manage_supervisors);
     STATE_STORE.subscribe("backup-logger", log_state_changes);
     STATE_STORE.supervisor_event(state::SupervisorAction::Start);
@@ -205,7 +608,26 @@ async fn main() -> io::Result<()> {
                     .max_age(3600),
             )
             .service(web::resource("/graphql").route(web::post().to(graphql)))
+            .service(web::resource("/subscriptions").route(web::get().to(subscriptions)))
             .service(web::resource("/graphiql").route(web::get().to(graphiql)))
+            .service(web::resource("/pair").route(web::post().to(pair_device)))
+            .service(web::resource("/status").route(web::get().to(status_page)))
+            .service(
+                web::resource("/recover")
+                    .route(web::get().to(recover_page))
+                    .route(web::post().to(recover_file)),
+            )
+            .service(
+                web::resource("/object/{store}/{bucket}/{object}")
+                    .route(web::get().to(download_object))
+                    .route(web::put().to(upload_object)),
+            )
+            .service(web::resource("/replica/{dataset}").route(web::get().to(replica_latest)))
+            .service(
+                web::resource("/replica")
+                    .app_data(web::PayloadConfig::new(MAX_REPLICA_BATCH))
+                    .route(web::post().to(replica_apply)),
+            )
             .service(Files::new("/", STATIC_PATH.clone()).index_file("index.html"))
             .default_service(web::get().to(default_index))
     })
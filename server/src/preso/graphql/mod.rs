@@ -6,22 +6,47 @@
 
 use crate::data::repositories::RecordRepositoryImpl;
 use crate::data::sources::EntityDataSource;
-use crate::domain::entities::{self, Checksum, TreeReference};
+use crate::domain::entities::{self, Checksum, DeviceScope, TreeReference};
 use crate::domain::helpers;
 use crate::domain::managers::backup::Scheduler;
+use crate::domain::managers::clock;
+use crate::domain::managers::export;
+use crate::domain::managers::pairing;
+use crate::domain::managers::progress;
 use crate::domain::managers::restore::{self, Restorer};
+use crate::domain::managers::settings;
 use crate::domain::managers::state::{self, StateStore};
+use crate::domain::managers::trash;
 use crate::domain::repositories::RecordRepository;
+use crate::domain::usecases::discover_remote;
 use chrono::prelude::*;
+use futures::channel::mpsc;
+use futures::stream::{self, Stream, StreamExt};
 use juniper::{
-    EmptySubscription, FieldError, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject,
+    graphql_value, FieldError, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject,
     GraphQLScalar, InputValue, ParseScalarResult, ParseScalarValue, RootNode, ScalarToken,
     ScalarValue, Value,
 };
+use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    // Subscriptions that are waiting for state changes.
+    static ref STATE_WATCHERS: Mutex<Vec<StateWatcher>> = Mutex::new(Vec::new());
+}
+
+// Most recent state not yet sent to a subscription, and the channel by which
+// the subscription is woken to send it. Changes that arrive faster than the
+// client receives them replace one another, such that a slow client sees only
+// the latest state rather than an ever longer queue.
+struct StateWatcher {
+    latest: Arc<Mutex<Option<state::State>>>,
+    wakeup: mpsc::Sender<()>,
+}
 
 // Context for the GraphQL schema.
 pub struct GraphContext {
@@ -29,6 +54,7 @@ pub struct GraphContext {
     appstate: Arc<dyn StateStore>,
     processor: Arc<dyn Scheduler>,
     restorer: Arc<dyn Restorer>,
+    access: DeviceScope,
 }
 
 impl GraphContext {
@@ -43,6 +69,26 @@ impl GraphContext {
             appstate,
             processor,
             restorer,
+            access: DeviceScope::Full,
+        }
+    }
+
+    /// Limit the operations permitted to those allowed by the scope.
+    pub fn with_access(mut self, access: DeviceScope) -> Self {
+        self.access = access;
+        self
+    }
+
+    // Return an error if the request lacks full access, such as when it comes
+    // from a device that was paired for restoring files only.
+    fn require_full(&self) -> FieldResult<()> {
+        if self.access == DeviceScope::Full {
+            Ok(())
+        } else {
+            Err(FieldError::new(
+                "operation not permitted for this device",
+                graphql_value!({ "code": "FORBIDDEN" }),
+            ))
         }
     }
 }
@@ -172,6 +218,32 @@ impl entities::Tree {
     }
 }
 
+#[juniper::graphql_object(description = "An entry found while walking a snapshot.")]
+impl entities::WalkedEntry {
+    /// Path of the entry relative to the root of the snapshot.
+    fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// The tree entry itself.
+    fn entry(&self) -> entities::TreeEntry {
+        self.entry.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Portion of the entries of a snapshot.")]
+impl entities::SnapshotWalk {
+    /// Entries in depth-first order.
+    fn entries(&self) -> Vec<entities::WalkedEntry> {
+        self.entries.clone()
+    }
+
+    /// Cursor from which to continue the walk, or null if it is complete.
+    fn cursor(&self) -> Option<String> {
+        self.cursor.clone()
+    }
+}
+
 #[derive(GraphQLObject)]
 /// Number of files whose size is close to the given power of 2.
 struct FileSize {
@@ -181,6 +253,17 @@ struct FileSize {
     count: BigInt,
 }
 
+#[derive(GraphQLObject)]
+/// Number of files whose name indicates the given kind of content.
+struct FileCategoryCount {
+    /// Content category (archive, audio, code, document, image, video, other).
+    category: String,
+    /// Number of files in this category.
+    count: BigInt,
+    /// Combined size of the files in bytes.
+    bytes: BigInt,
+}
+
 #[juniper::graphql_object(description = "Number of files and directories in a snapshot.")]
 impl entities::FileCounts {
     fn directories(&self) -> BigInt {
@@ -209,6 +292,20 @@ impl entities::FileCounts {
     fn very_large_files(&self) -> BigInt {
         BigInt(self.very_large_files as i64)
     }
+
+    /// Number and size of files by broad content category, sorted by name.
+    fn file_categories(&self) -> Vec<FileCategoryCount> {
+        let mut result: Vec<FileCategoryCount> = Vec::new();
+        for (category, tally) in self.categories.iter() {
+            result.push(FileCategoryCount {
+                category: category.to_string(),
+                count: BigInt(tally.files as i64),
+                bytes: BigInt(tally.bytes as i64),
+            });
+        }
+        result.sort_by(|a, b| a.category.cmp(&b.category));
+        result
+    }
 }
 
 #[juniper::graphql_object(description = "A single backup, either in progress or completed.")]
@@ -233,6 +330,12 @@ impl entities::Snapshot {
         self.end_time
     }
 
+    /// Time when the backup last paused before completing, having run out of
+    /// time, to resume when its schedule next comes due.
+    fn paused(&self) -> Option<DateTime<Utc>> {
+        self.paused
+    }
+
     /// Total number of files contained in this snapshot.
     fn file_count(&self) -> BigInt {
         BigInt(self.file_counts.total_files() as i64)
@@ -264,6 +367,80 @@ pub enum Status {
     FAILED,
 }
 
+#[juniper::graphql_object(description = "Jump in the system clock detected by the scheduler.")]
+impl clock::ClockAdjustment {
+    /// Time at which the adjustment was made.
+    fn detected(&self) -> DateTime<Utc> {
+        self.detected
+    }
+
+    /// Number of seconds the clock moved, negative if backward.
+    fn offset(&self) -> BigInt {
+        BigInt(self.offset)
+    }
+
+    /// Explanation of what was observed and how it was handled.
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Settings changed by reloading the configuration.")]
+impl settings::ReloadReport {
+    /// Names of the settings whose new values are now in effect.
+    fn applied(&self) -> Vec<String> {
+        self.applied.clone()
+    }
+
+    /// Names of the settings that will not take effect until a restart.
+    fn restart_required(&self) -> Vec<String> {
+        self.restart_required.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "File from a snapshot served as a network block device.")]
+impl export::Export {
+    /// Unique identifier of the export.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Name of the NBD export, which the client must give to connect.
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Identifier of the dataset containing the file.
+    fn dataset(&self) -> String {
+        self.dataset.clone()
+    }
+
+    /// Digest of the tree containing the file.
+    fn tree(&self) -> ChecksumGQL {
+        ChecksumGQL(self.tree.clone())
+    }
+
+    /// Name of the file within the tree.
+    fn entry(&self) -> String {
+        self.entry.clone()
+    }
+
+    /// Address on which the export is listening for connections.
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    /// Size of the file in bytes.
+    fn size(&self) -> BigInt {
+        BigInt(self.size as i64)
+    }
+
+    /// Time at which the export was started.
+    fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+}
+
 #[juniper::graphql_object(description = "Detailed information of the state of the backup.")]
 impl state::BackupState {
     /// True if the running backup has been paused.
@@ -299,6 +476,20 @@ impl state::BackupState {
     fn uploaded_bytes(&self) -> BigInt {
         BigInt(self.bytes_uploaded() as i64)
     }
+
+    /// Paths of files that were skipped because they remained locked by
+    /// another process.
+    #[graphql(name = "lockedFiles")]
+    fn files_locked(&self) -> Vec<String> {
+        self.locked_files().to_vec()
+    }
+
+    /// Identifiers of the stores that were left out of the backup because
+    /// they reached their monthly transfer cap.
+    #[graphql(name = "deferredStores")]
+    fn stores_deferred(&self) -> Vec<String> {
+        self.deferred_stores().to_vec()
+    }
 }
 
 #[juniper::graphql_object(
@@ -311,6 +502,11 @@ impl entities::Dataset {
         self.id.clone()
     }
 
+    /// Entity tag of this dataset, required when updating the dataset.
+    fn etag(&self) -> String {
+        self.etag()
+    }
+
     /// Unique computer identifier.
     fn computer_id(&self, #[graphql(ctx)] ctx: &GraphContext) -> Option<String> {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
@@ -360,6 +556,13 @@ impl entities::Dataset {
         }
     }
 
+    /// Identifiers of the stores that are left out of the backups of this
+    /// dataset because they reached their monthly transfer cap or quota.
+    fn deferred_stores(&self, #[graphql(ctx)] ctx: &GraphContext) -> Vec<String> {
+        let redux = ctx.appstate.get_state();
+        redux.deferred_stores(&self.id).to_vec()
+    }
+
     /// Detailed state of the backup for this dataset.
     fn backup_state(&self, #[graphql(ctx)] ctx: &GraphContext) -> Option<state::BackupState> {
         let redux = ctx.appstate.get_state();
@@ -397,6 +600,84 @@ impl entities::Dataset {
     fn excludes(&self) -> Vec<String> {
         self.excludes.clone()
     }
+
+    /// Name/value pairs for optional dataset settings.
+    fn properties(&self) -> Vec<Property> {
+        let mut properties: Vec<Property> = Vec::new();
+        for (key, val) in self.properties.iter() {
+            properties.push(Property {
+                name: key.to_owned(),
+                value: val.to_owned(),
+            });
+        }
+        properties
+    }
+
+    /// When the dataset was archived, if it has been. Archived datasets are
+    /// not backed up, although their snapshots remain available.
+    fn archived(&self) -> Option<DateTime<Utc>> {
+        self.archived
+    }
+
+    /// Rules for which snapshots are retained when pruning, as given by the
+    /// `retain_*` properties.
+    fn retention(&self) -> entities::RetentionPolicy {
+        self.retention_policy()
+    }
+}
+
+#[juniper::graphql_object(description = "Rules for which snapshots are retained when pruning.")]
+impl entities::RetentionPolicy {
+    /// Number of the most recent snapshots to keep.
+    fn count(&self) -> Option<i32> {
+        self.count.map(|v| v as i32)
+    }
+
+    /// Snapshots that started within this many days are kept.
+    fn days(&self) -> Option<i32> {
+        self.days.map(|v| v as i32)
+    }
+
+    /// Number of days for which the newest snapshot is kept.
+    fn daily(&self) -> Option<i32> {
+        self.daily.map(|v| v as i32)
+    }
+
+    /// Number of weeks for which the newest snapshot is kept.
+    fn weekly(&self) -> Option<i32> {
+        self.weekly.map(|v| v as i32)
+    }
+
+    /// Number of months for which the newest snapshot is kept.
+    fn monthly(&self) -> Option<i32> {
+        self.monthly.map(|v| v as i32)
+    }
+
+    /// Number of years for which the newest snapshot is kept.
+    fn yearly(&self) -> Option<i32> {
+        self.yearly.map(|v| v as i32)
+    }
+}
+
+#[juniper::graphql_object(
+    Context = GraphContext,
+    description = "Deleted dataset that may be restored until it is purged.")
+]
+impl entities::TrashedDataset {
+    /// The dataset as it was when it was deleted.
+    fn dataset(&self) -> &entities::Dataset {
+        &self.dataset
+    }
+
+    /// Date/time when the dataset was deleted.
+    fn deleted(&self) -> DateTime<Utc> {
+        self.deleted
+    }
+
+    /// Date/time after which the dataset will be permanently removed.
+    fn purge_after(&self) -> DateTime<Utc> {
+        trash::purge_after(self)
+    }
 }
 
 #[juniper::graphql_object(name = "TimeRange", desc = "Range of time in which to run backup. If stopTime is less than startTime, the times span the midnight hour.")]
@@ -444,6 +725,27 @@ impl From<InputTimeRange> for entities::schedule::TimeRange {
     }
 }
 
+/// What to do with the objects in a store that are not known to the database.
+#[derive(Copy, Clone, GraphQLEnum)]
+pub enum DiscoveryAction {
+    /// Only report the unknown objects.
+    Report,
+    /// Make pack records for the unknown objects that are pack files.
+    Adopt,
+    /// Remove the unknown objects from the store.
+    Remove,
+}
+
+impl From<DiscoveryAction> for discover_remote::Action {
+    fn from(action: DiscoveryAction) -> Self {
+        match action {
+            DiscoveryAction::Report => discover_remote::Action::Report,
+            DiscoveryAction::Adopt => discover_remote::Action::Adopt,
+            DiscoveryAction::Remove => discover_remote::Action::Remove,
+        }
+    }
+}
+
 #[derive(Copy, Clone, GraphQLEnum)]
 pub enum DayOfWeek {
     Sun,
@@ -626,14 +928,70 @@ struct Store {
     store_type: String,
     /// User-defined label for this store.
     label: String,
-    /// Name/value pairs that make up this store configuration.
+    /// Name/value pairs that make up this store configuration, with the
+    /// credentials replaced by "[REDACTED]", which may be given back as is
+    /// when updating the store to keep the saved credentials.
     properties: Vec<Property>,
+    /// Policy for moving older packs to a colder storage class, as defined by
+    /// the `tiering_days` and `tiering_class` properties.
+    tiering_policy: Option<TieringPolicy>,
+    /// Entity tag of this store, required when updating the store.
+    etag: String,
+}
+
+/// Packs not referenced by a snapshot in the last N days are moved to a
+/// colder storage class.
+#[derive(GraphQLObject)]
+struct TieringPolicy {
+    /// Number of days since the pack was last referenced by a snapshot.
+    days: i32,
+    /// Name of the storage class (or access tier) for older packs.
+    storage_class: String,
+}
+
+/// Bytes transferred to and from a store during the current month.
+#[derive(GraphQLObject)]
+struct StoreBandwidth {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// Calendar month in the form YYYY-MM (UTC).
+    month: String,
+    /// Number of bytes uploaded to the store.
+    uploaded: BigInt,
+    /// Number of bytes downloaded from the store.
+    downloaded: BigInt,
+    /// Monthly transfer cap as defined by the `monthly_cap` property.
+    monthly_cap: Option<BigInt>,
+    /// True if the cap has been reached, and backups to the store deferred.
+    capped: bool,
+}
+
+/// Number and size of the objects in a store, along with its quota.
+#[derive(GraphQLObject)]
+struct StoreUsage {
+    /// Unique identifier of the store.
+    store_id: String,
+    /// Number of objects added to the store, less those removed.
+    objects: BigInt,
+    /// Total size in bytes of the objects in the store.
+    bytes: BigInt,
+    /// Maximum number of objects as defined by the `quota_objects` property.
+    max_objects: Option<BigInt>,
+    /// Maximum total size as defined by the `quota_bytes` property.
+    max_bytes: Option<BigInt>,
+    /// True if the quota has been reached, and no more packs will be added.
+    exceeded: bool,
 }
 
 impl From<entities::Store> for Store {
     fn from(store: entities::Store) -> Self {
+        let tiering_policy = store.tiering_policy().map(|p| TieringPolicy {
+            days: p.days as i32,
+            storage_class: p.storage_class,
+        });
+        let etag = store.etag();
         let mut properties: Vec<Property> = Vec::new();
-        for (key, val) in store.properties.iter() {
+        for (key, val) in store.redacted_properties().iter() {
             properties.push(Property {
                 name: key.to_owned(),
                 value: val.to_owned(),
@@ -644,133 +1002,1059 @@ impl From<entities::Store> for Store {
             store_type: store.store_type.to_string(),
             label: store.label,
             properties,
+            tiering_policy,
+            etag,
         }
     }
 }
 
-#[juniper::graphql_object(description = "Configuration of the application.")]
-impl entities::Configuration {
-    /// Name of the computer on which this application is running.
-    fn hostname(&self) -> String {
-        self.hostname.clone()
+#[juniper::graphql_object(description = "Outcome of a single step of testing a store.")]
+impl entities::StoreTestStep {
+    /// Name of the operation (e.g. "upload", "download").
+    fn name(&self) -> String {
+        self.name.clone()
     }
-    /// Name of the user running this application.
-    fn username(&self) -> String {
-        self.username.clone()
+    /// Time in milliseconds that the operation took to complete.
+    fn elapsed(&self) -> BigInt {
+        BigInt(self.elapsed as i64)
     }
-    /// Computer UUID for generating bucket names.
-    fn computer_id(&self) -> String {
-        self.computer_id.clone()
+    /// Error message if the operation failed.
+    fn error(&self) -> Option<String> {
+        self.error.clone()
     }
 }
 
-#[juniper::graphql_object(description = "Entry within a saved pack file.")]
-impl entities::PackEntry {
-    /// File name of the entry in the pack file.
-    fn name(&self) -> String {
-        self.name.clone()
-    }
+/// Results of testing the connection and permissions of a store.
+#[derive(GraphQLObject)]
+struct StoreTest {
+    /// Either "ok" or the first error encountered.
+    status: String,
+    /// Outcome of each step of the test.
+    steps: Vec<entities::StoreTestStep>,
+}
 
-    /// Length of the content of the entry.
-    fn size(&self) -> BigInt {
-        BigInt(self.size as i64)
+#[juniper::graphql_object(description = "Projected cost of a dataset within a store.")]
+impl entities::CostEstimate {
+    /// Identifier of the store.
+    fn store_id(&self) -> String {
+        self.store_id.clone()
+    }
+    /// User-defined label of the store.
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+    /// Number of packs saved to the store.
+    fn pack_count(&self) -> BigInt {
+        BigInt(self.pack_count as i64)
+    }
+    /// Approximate number of bytes occupied by the packs.
+    fn stored_bytes(&self) -> BigInt {
+        BigInt(self.stored_bytes as i64)
+    }
+    /// Average number of packs uploaded each month.
+    fn monthly_uploads(&self) -> f64 {
+        self.monthly_uploads
+    }
+    /// Cost of storing the packs for one month.
+    fn storage_cost(&self) -> f64 {
+        self.storage_cost
+    }
+    /// Cost of the requests to upload a month's worth of packs.
+    fn upload_cost(&self) -> f64 {
+        self.upload_cost
+    }
+    /// Total of the recurring monthly costs.
+    fn monthly_cost(&self) -> f64 {
+        self.storage_cost + self.upload_cost
+    }
+    /// One-time cost of downloading every pack, as for a full restore.
+    fn restore_cost(&self) -> f64 {
+        self.restore_cost
     }
 }
 
-#[juniper::graphql_object(description = "Details about a pack file.")]
-impl entities::PackFile {
-    /// Number of entries in the pack file.
-    fn count(&self) -> i32 {
-        self.entries.len() as i32
+#[juniper::graphql_object(description = "Storage consumed by a dataset.")]
+impl entities::DatasetUsage {
+    /// Identifier of the dataset.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
     }
-
-    /// All entries in the pack file.
-    fn entries(&self) -> Vec<entities::PackEntry> {
-        self.entries.clone()
+    /// Combined size of the files in the latest snapshot.
+    fn logical_size(&self) -> BigInt {
+        BigInt(self.logical_size as i64)
     }
-
-    /// Length of the pack file in bytes.
-    fn length(&self) -> BigInt {
-        BigInt(self.length as i64)
+    /// Combined size of the distinct file content across all snapshots,
+    /// before compression.
+    fn stored_bytes(&self) -> BigInt {
+        BigInt(self.stored_bytes as i64)
     }
-
-    /// Total size of all pack entries.
-    fn content_length(&self) -> BigInt {
-        BigInt(self.content_length as i64)
+    /// Growth of the most recent snapshots, newest first.
+    fn growth(&self) -> Vec<entities::SnapshotGrowth> {
+        self.growth.clone()
     }
-
-    /// Size of the smallest entry in the pack file.
-    fn smallest(&self) -> BigInt {
-        BigInt(self.smallest as i64)
+    /// Portion of the stored bytes held by each store.
+    fn stores(&self) -> Vec<entities::StoreBytes> {
+        self.stores.clone()
     }
+}
 
-    /// Size of the largest entry in the pack file.
-    fn largest(&self) -> BigInt {
-        BigInt(self.largest as i64)
+#[juniper::graphql_object(description = "Deduplication achieved by the snapshots of a dataset.")]
+impl entities::DedupStats {
+    /// Identifier of the dataset.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
     }
-
-    /// Average size of all entries in the pack file.
-    fn average(&self) -> BigInt {
-        BigInt(self.average as i64)
+    /// Time when the statistics were computed.
+    fn computed(&self) -> DateTime<Utc> {
+        self.computed
+    }
+    /// Combined size of the files of every snapshot, as if each snapshot were
+    /// stored in full.
+    fn logical_bytes(&self) -> BigInt {
+        BigInt(self.logical_bytes as i64)
+    }
+    /// Combined size of the distinct chunks referenced by any snapshot,
+    /// before compression.
+    fn physical_bytes(&self) -> BigInt {
+        BigInt(self.physical_bytes as i64)
+    }
+    /// Ratio of the logical bytes to the physical bytes.
+    fn ratio(&self) -> f64 {
+        self.ratio()
+    }
+    /// Statistics of each snapshot, newest first.
+    fn snapshots(&self) -> Vec<entities::SnapshotDedup> {
+        self.snapshots.clone()
     }
 }
 
-#[juniper::graphql_object(description = "Location within a store of a saved pack.")]
-impl entities::PackLocation {
-    /// ULID of the pack store.
-    fn store(&self) -> String {
-        self.store.clone()
+#[juniper::graphql_object(description = "Deduplication statistics of a single snapshot.")]
+impl entities::SnapshotDedup {
+    /// Digest of the snapshot.
+    fn digest(&self) -> ChecksumGQL {
+        ChecksumGQL(self.digest.clone())
     }
-
-    /// Remote bucket name.
-    fn bucket(&self) -> String {
-        self.bucket.clone()
+    /// Time when the snapshot was started.
+    fn start_time(&self) -> DateTime<Utc> {
+        self.start_time
     }
-
-    /// Remote object name.
-    fn object(&self) -> String {
-        self.object.clone()
+    /// Combined size of the files in the snapshot.
+    fn logical_size(&self) -> BigInt {
+        BigInt(self.logical_size as i64)
+    }
+    /// Size of the content that no other snapshot references.
+    fn unique_bytes(&self) -> BigInt {
+        BigInt(self.unique_bytes as i64)
     }
 }
 
-#[juniper::graphql_object(description = "An archive containing saved files.")]
-impl entities::Pack {
-    /// Unique checksum of the pack contents.
-    fn checksum(&self) -> ChecksumGQL {
+#[juniper::graphql_object(description = "Size of a snapshot and the content it added.")]
+impl entities::SnapshotGrowth {
+    /// Digest of the snapshot.
+    fn digest(&self) -> ChecksumGQL {
         ChecksumGQL(self.digest.clone())
     }
+    /// Time when the snapshot was started.
+    fn start_time(&self) -> DateTime<Utc> {
+        self.start_time
+    }
+    /// Combined size of the files in the snapshot.
+    fn logical_size(&self) -> BigInt {
+        BigInt(self.logical_size as i64)
+    }
+    /// Size of the content that no earlier snapshot contained.
+    fn added_bytes(&self) -> BigInt {
+        BigInt(self.added_bytes as i64)
+    }
+}
 
-    /// List of store-specific coordinates where the pack is saved.
-    fn locations(&self) -> Vec<entities::PackLocation> {
-        self.locations.clone()
+#[juniper::graphql_object(description = "Number of bytes of a dataset held by a store.")]
+impl entities::StoreBytes {
+    /// Identifier of the store.
+    fn store_id(&self) -> String {
+        self.store_id.clone()
+    }
+    /// Combined size of the content in the store, before compression.
+    fn bytes(&self) -> BigInt {
+        BigInt(self.bytes as i64)
     }
 }
 
-#[juniper::graphql_object(description = "A request to restore a file or directory.")]
-impl restore::Request {
-    /// Digest of the tree containing the entry to restore.
+#[juniper::graphql_object(description = "Distinct version of a file within a dataset.")]
+impl entities::FileVersion {
+    /// Digest of the earliest snapshot containing this version.
+    fn snapshot(&self) -> ChecksumGQL {
+        ChecksumGQL(self.snapshot.clone())
+    }
+    /// Start time of the earliest snapshot containing this version.
+    fn snapshot_time(&self) -> DateTime<Utc> {
+        self.snapshot_time
+    }
+    /// Digest of the tree containing the file.
     fn tree(&self) -> ChecksumGQL {
         ChecksumGQL(self.tree.clone())
     }
-
-    /// Name of the entry within the tree to be restored.
+    /// Name of the file within the tree.
     fn entry(&self) -> String {
         self.entry.clone()
     }
-
-    /// Relative path where file/tree will be restored.
-    fn filepath(&self) -> String {
-        self.filepath.to_string_lossy().into()
+    /// Modification time of the file.
+    fn modified(&self) -> DateTime<Utc> {
+        self.modified
     }
-
-    /// Identifier of the dataset containing the data.
-    fn dataset(&self) -> String {
-        self.dataset.clone()
+    /// Length of the file in bytes.
+    fn size(&self) -> BigInt {
+        BigInt(self.size as i64)
     }
-
-    /// The datetime when the request was completed in UTC.
-    fn finished(&self) -> Option<DateTime<Utc>> {
-        self.finished
+    /// Digest of the file content, null for very small files.
+    fn digest(&self) -> Option<ChecksumGQL> {
+        self.digest.clone().map(ChecksumGQL)
+    }
+    /// True if the content differs from the previous version, false if only
+    /// the modification time has changed.
+    fn changed(&self) -> bool {
+        self.changed
+    }
+}
+
+#[juniper::graphql_object(description = "Entries of a directory within a snapshot.")]
+impl entities::DirectoryListing {
+    /// Digest of the snapshot containing the directory.
+    fn snapshot(&self) -> ChecksumGQL {
+        ChecksumGQL(self.snapshot.clone())
+    }
+    /// Digest of the tree of the directory.
+    fn tree(&self) -> ChecksumGQL {
+        ChecksumGQL(self.tree.clone())
+    }
+    /// Path of the directory relative to the dataset base path.
+    fn path(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+    /// Entries of the directory, sorted by name.
+    fn entries(&self) -> Vec<entities::ListingEntry> {
+        self.entries.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Entry within a directory listing.")]
+impl entities::ListingEntry {
+    /// Name of the file, directory, or symbolic link.
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    /// Reference to the entry itself.
+    fn reference(&self) -> TreeReferenceGQL {
+        TreeReferenceGQL(self.reference.clone())
+    }
+    /// True if the entry is a directory.
+    fn directory(&self) -> bool {
+        self.reference.is_tree()
+    }
+    /// Unix file mode of the entry.
+    fn mode(&self) -> Option<i32> {
+        self.mode.map(|m| m as i32)
+    }
+    /// Name of the owning user.
+    fn user(&self) -> Option<String> {
+        self.user.clone()
+    }
+    /// Name of the owning group.
+    fn group(&self) -> Option<String> {
+        self.group.clone()
+    }
+    /// Modification time of the entry.
+    fn modified(&self) -> DateTime<Utc> {
+        self.modified
+    }
+    /// Length of the file in bytes, null for directories and links.
+    fn size(&self) -> Option<BigInt> {
+        self.size.map(|s| BigInt(s as i64))
+    }
+}
+
+#[juniper::graphql_object(description = "Entry found by searching the datasets.")]
+impl entities::SearchResult {
+    /// Identifier of the dataset containing the entry.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
+    }
+    /// Digest of the latest snapshot of the dataset.
+    fn snapshot(&self) -> ChecksumGQL {
+        ChecksumGQL(self.snapshot.clone())
+    }
+    /// Digest of the tree containing the entry.
+    fn tree(&self) -> ChecksumGQL {
+        ChecksumGQL(self.tree.clone())
+    }
+    /// Name of the entry within the tree.
+    fn entry(&self) -> String {
+        self.entry.clone()
+    }
+    /// Path of the entry relative to the dataset base path.
+    fn filepath(&self) -> String {
+        self.filepath.to_string_lossy().into_owned()
+    }
+    /// Modification time of the entry.
+    fn modified(&self) -> DateTime<Utc> {
+        self.modified
+    }
+    /// True if the entry is a directory.
+    fn directory(&self) -> bool {
+        self.directory
+    }
+    /// Date/time the photo was taken, if the dataset extracts metadata.
+    fn taken(&self) -> Option<DateTime<Utc>> {
+        self.taken
+    }
+    /// Subject of the email message, if the dataset extracts metadata.
+    fn subject(&self) -> Option<String> {
+        self.subject.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of applying the tiering policy of a store.")]
+impl entities::TieringResult {
+    /// Identifier of the store.
+    fn store_id(&self) -> String {
+        self.store_id.clone()
+    }
+    /// Date/time when the policy was last applied.
+    fn finished(&self) -> DateTime<Utc> {
+        self.finished
+    }
+    /// Number of packs moved to the colder storage class.
+    fn transitioned(&self) -> BigInt {
+        BigInt(self.transitioned as i64)
+    }
+    /// Number of eligible packs that were already in the storage class.
+    fn unchanged(&self) -> BigInt {
+        BigInt(self.unchanged as i64)
+    }
+    /// Number of packs left as-is due to being recently referenced.
+    fn retained(&self) -> BigInt {
+        BigInt(self.retained as i64)
+    }
+    /// Error message if the policy could not be applied completely.
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Progress of a long running operation.")]
+impl progress::Operation {
+    /// Unique identifier of the operation.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    /// Kind of operation: backup, restore, verify, prune, or maintenance.
+    fn kind(&self) -> String {
+        self.kind.to_string()
+    }
+    /// What the operation is acting upon, such as a dataset identifier.
+    fn subject(&self) -> String {
+        self.subject.clone()
+    }
+    /// Number of items expected to be processed, if known.
+    fn total(&self) -> Option<BigInt> {
+        self.total.map(|v| BigInt(v as i64))
+    }
+    /// Number of items processed so far.
+    fn items(&self) -> BigInt {
+        BigInt(self.items as i64)
+    }
+    /// Number of bytes processed so far.
+    fn bytes(&self) -> BigInt {
+        BigInt(self.bytes as i64)
+    }
+    /// Item currently being processed, if reported.
+    fn current(&self) -> Option<String> {
+        self.current.clone()
+    }
+    /// Date/time when the operation started.
+    fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+    /// Date/time when the operation finished, if it has.
+    fn finished(&self) -> Option<DateTime<Utc>> {
+        self.finished
+    }
+    /// Error message if the operation failed.
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Failed attempts to retrieve packs from a store.")]
+impl entities::RetrievalFailures {
+    /// Identifier of the store.
+    fn store(&self) -> String {
+        self.store.clone()
+    }
+    /// Total number of failed retrievals since the server started.
+    fn failures(&self) -> BigInt {
+        BigInt(self.failures as i64)
+    }
+    /// Number of failed retrievals since the last successful one.
+    fn consecutive(&self) -> BigInt {
+        BigInt(self.consecutive as i64)
+    }
+    /// Message of the most recent failure.
+    fn last_error(&self) -> Option<String> {
+        self.last_error.clone()
+    }
+    /// Date/time of the most recent failure.
+    fn last_failure(&self) -> Option<DateTime<Utc>> {
+        self.last_failure
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of running a maintenance task.")]
+impl entities::MaintenanceResult {
+    /// Name of the task that was performed.
+    fn task(&self) -> String {
+        self.task.to_string()
+    }
+    /// Identifier of the dataset for which the task was performed, if the task
+    /// is scheduled for each dataset.
+    fn dataset_id(&self) -> Option<String> {
+        self.dataset_id.clone()
+    }
+    /// Date/time when the task was started.
+    fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+    /// Date/time when the task finished.
+    fn finished(&self) -> DateTime<Utc> {
+        self.finished
+    }
+    /// Brief description of what the task accomplished.
+    fn summary(&self) -> String {
+        self.summary.clone()
+    }
+    /// Error message if the task failed.
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of verifying a snapshot.")]
+impl entities::Verification {
+    /// Unique identifier of the verification.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    /// Identifier of the dataset to which the snapshot belongs.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
+    }
+    /// Digest of the snapshot that was verified.
+    fn snapshot(&self) -> ChecksumGQL {
+        ChecksumGQL(self.snapshot.clone())
+    }
+    /// How thoroughly the snapshot was verified: metadata, packs, or full.
+    fn depth(&self) -> String {
+        self.depth.to_string()
+    }
+    /// Date/time when the verification started.
+    fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+    /// Date/time when the verification finished.
+    fn finished(&self) -> DateTime<Utc> {
+        self.finished
+    }
+    /// Number of distinct file records that were checked.
+    fn files(&self) -> BigInt {
+        BigInt(self.files as i64)
+    }
+    /// Number of packs that were retrieved and checked.
+    fn packs(&self) -> BigInt {
+        BigInt(self.packs as i64)
+    }
+    /// Number of files that were restored and checked.
+    fn restored(&self) -> BigInt {
+        BigInt(self.restored as i64)
+    }
+    /// Description of each problem that was found.
+    fn issues(&self) -> Vec<String> {
+        self.issues.clone()
+    }
+    /// True if no problems were found.
+    fn passed(&self) -> bool {
+        self.passed()
+    }
+}
+
+#[juniper::graphql_object(description = "Suggested action for keeping the backups in good order.")]
+impl entities::Recommendation {
+    /// Importance of the suggestion: high, medium, or low.
+    fn priority(&self) -> String {
+        self.priority.to_string()
+    }
+    /// Identifier of the dataset to which the suggestion applies, if any.
+    fn subject(&self) -> Option<String> {
+        self.subject.clone()
+    }
+    /// Description of the problem that was found, in English.
+    fn message(&self) -> String {
+        self.message.to_string()
+    }
+    /// Code and parameters of the message, for presenting it in other
+    /// languages.
+    fn detail(&self) -> entities::Message {
+        self.message.clone()
+    }
+    /// Name of the mutation that would address the problem.
+    fn mutation(&self) -> String {
+        self.mutation.clone()
+    }
+}
+
+#[juniper::graphql_object(
+    description = "Message given by a code and parameters, which clients may localize."
+)]
+impl entities::Message {
+    /// Code that identifies the message, such as `NO_SUCH_STORE`.
+    fn code(&self) -> String {
+        self.code.to_string()
+    }
+    /// Values to be inserted into the message, by name.
+    fn params(&self) -> Vec<Property> {
+        self.params
+            .iter()
+            .map(|(name, value)| Property {
+                name: name.to_owned(),
+                value: value.to_owned(),
+            })
+            .collect()
+    }
+    /// Text of the message in English.
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[juniper::graphql_object(description = "A device paired with this server.")]
+impl entities::Device {
+    /// Identifier of the device.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    /// Name given to the device when it was paired.
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    /// Access granted to the device: restore or full.
+    fn scope(&self) -> String {
+        self.scope.to_string()
+    }
+    /// Date/time when the device was paired.
+    fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+    /// Date/time when the device last made a request, approximately.
+    fn last_used(&self) -> Option<DateTime<Utc>> {
+        self.last_used
+    }
+}
+
+#[juniper::graphql_object(description = "Migration that was applied to the database.")]
+impl entities::Migration {
+    /// Schema version that the migration produced.
+    fn version(&self) -> i32 {
+        self.version as i32
+    }
+    /// Brief description of the changes made by the migration.
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    /// Date/time when the migration was applied.
+    fn applied(&self) -> DateTime<Utc> {
+        self.applied
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of verifying the snapshot log of a store.")]
+impl entities::ChainReport {
+    /// Identifier of the store that was checked.
+    fn store_id(&self) -> String {
+        self.store.clone()
+    }
+    /// Number of entries recorded in the database.
+    fn expected(&self) -> BigInt {
+        BigInt(self.expected as i64)
+    }
+    /// Number of entries found in the log held by the store.
+    fn found(&self) -> BigInt {
+        BigInt(self.found as i64)
+    }
+    /// True if the log held by the store matches the database.
+    fn intact(&self) -> bool {
+        self.is_intact()
+    }
+    /// Descriptions of each discrepancy that was found.
+    fn problems(&self) -> Vec<String> {
+        self.problems.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of compacting the packs of a dataset.")]
+impl entities::CompactionReport {
+    /// Identifier of the dataset whose packs were compacted.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
+    }
+    /// Number of packs that were considered for compaction.
+    fn packs_examined(&self) -> BigInt {
+        BigInt(self.packs_examined as i64)
+    }
+    /// Number of packs whose live chunks were moved to new packs.
+    fn packs_rewritten(&self) -> BigInt {
+        BigInt(self.packs_rewritten as i64)
+    }
+    /// Number of packs that were removed for having no live chunks.
+    fn packs_removed(&self) -> BigInt {
+        BigInt(self.packs_removed as i64)
+    }
+    /// Number of new packs that were uploaded.
+    fn packs_created(&self) -> BigInt {
+        BigInt(self.packs_created as i64)
+    }
+    /// Combined size of the dead chunks that were discarded.
+    fn bytes_reclaimed(&self) -> BigInt {
+        BigInt(self.bytes_reclaimed as i64)
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of recomputing the statistics of a dataset.")]
+impl entities::StatisticsReport {
+    /// Identifier of the dataset whose statistics were recomputed.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
+    }
+    /// Number of snapshots that were examined.
+    fn snapshots(&self) -> BigInt {
+        BigInt(self.snapshots as i64)
+    }
+    /// Number of snapshots whose file counts were corrected.
+    fn repaired(&self) -> BigInt {
+        BigInt(self.repaired as i64)
+    }
+    /// Number of files in the latest snapshot.
+    fn files(&self) -> BigInt {
+        BigInt(self.files as i64)
+    }
+    /// Combined size in bytes of the files in the latest snapshot.
+    fn bytes(&self) -> BigInt {
+        BigInt(self.bytes as i64)
+    }
+    /// Number of records of each kind in the database.
+    fn records(&self) -> entities::RecordCounts {
+        self.records.clone()
+    }
+    /// Descriptions of each discrepancy that was found and corrected.
+    fn problems(&self) -> Vec<String> {
+        self.problems.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Object in a store that is not known to the database.")]
+impl entities::RemoteObject {
+    /// Location of the object within the store.
+    fn location(&self) -> entities::PackLocation {
+        self.location.clone()
+    }
+    /// Digest of the object content, if it could be retrieved.
+    fn digest(&self) -> Option<ChecksumGQL> {
+        self.digest.clone().map(ChecksumGQL)
+    }
+    /// Number of chunks within the object, if it is a pack file.
+    fn chunks(&self) -> i32 {
+        self.chunks.len() as i32
+    }
+    /// True if the object was read successfully as a pack file.
+    fn valid_pack(&self) -> bool {
+        self.is_pack()
+    }
+    /// Reason the object is not a usable pack file, if any.
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+    /// True if a pack record was made for the object.
+    fn adopted(&self) -> bool {
+        self.adopted
+    }
+}
+
+#[juniper::graphql_object(description = "Outcome of looking for objects unknown to the database.")]
+impl entities::DiscoveryReport {
+    /// Identifier of the store that was examined.
+    fn store_id(&self) -> String {
+        self.store.clone()
+    }
+    /// Number of objects found in the store.
+    fn objects(&self) -> BigInt {
+        BigInt(self.objects as i64)
+    }
+    /// Number of those objects that are known to the database.
+    fn known(&self) -> BigInt {
+        BigInt(self.known as i64)
+    }
+    /// Objects that are not known to the database.
+    fn unknown(&self) -> Vec<entities::RemoteObject> {
+        self.unknown.clone()
+    }
+    /// Number of objects removed from the store.
+    fn removed(&self) -> i32 {
+        self.removed as i32
+    }
+}
+
+#[juniper::graphql_object(description = "Object in a store that no record references.")]
+impl entities::OrphanedObject {
+    /// Location of the object within the store.
+    fn location(&self) -> entities::PackLocation {
+        self.location.clone()
+    }
+    /// Size of the object in bytes, if the store was able to report it.
+    fn size(&self) -> Option<BigInt> {
+        self.size.map(|s| BigInt(s as i64))
+    }
+}
+
+#[juniper::graphql_object(description = "Record whose object was not found in the store.")]
+impl entities::MissingObject {
+    /// Digest of the pack or database archive.
+    fn digest(&self) -> ChecksumGQL {
+        ChecksumGQL(self.digest.clone())
+    }
+    /// Kind of record: pack, database, or chain (the snapshot log).
+    fn kind(&self) -> String {
+        self.kind.to_string()
+    }
+    /// Location at which the object was expected to be.
+    fn location(&self) -> entities::PackLocation {
+        self.location.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Comparison of the objects in a store with the database records.")]
+impl entities::ReconciliationReport {
+    /// Identifier of the store that was examined.
+    fn store_id(&self) -> String {
+        self.store.clone()
+    }
+    /// Number of objects found in the store.
+    fn objects(&self) -> BigInt {
+        BigInt(self.objects as i64)
+    }
+    /// Number of those objects that are referenced by a record.
+    fn matched(&self) -> BigInt {
+        BigInt(self.matched as i64)
+    }
+    /// Objects in the store that no record references.
+    fn orphaned(&self) -> Vec<entities::OrphanedObject> {
+        self.orphaned.clone()
+    }
+    /// Total size of the orphaned objects whose size is known.
+    fn orphaned_bytes(&self) -> BigInt {
+        BigInt(self.orphaned_bytes() as i64)
+    }
+    /// Records whose object is not in the store.
+    fn missing(&self) -> Vec<entities::MissingObject> {
+        self.missing.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Backup expected to run by the schedules of a dataset.")]
+impl entities::ForecastRun {
+    /// Identifier of the dataset.
+    fn dataset_id(&self) -> String {
+        self.dataset_id.clone()
+    }
+    /// When the backup is expected to start.
+    fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+    /// When the backup is expected to finish, or to be paused.
+    fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+    /// Number of previous backups from which the duration was estimated, zero
+    /// if a duration of one hour was assumed.
+    fn history(&self) -> i32 {
+        self.history as i32
+    }
+    /// True if the backup will be paused before it finishes.
+    fn overrun(&self) -> bool {
+        self.overrun
+    }
+    /// Stores left out of the backup for having reached their monthly
+    /// transfer cap or their quota.
+    fn deferred_stores(&self) -> Vec<String> {
+        self.deferred_stores.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Backups of two datasets expected to overlap.")]
+impl entities::ScheduleConflict {
+    /// Identifier of the dataset whose backup starts first.
+    fn first(&self) -> String {
+        self.first.clone()
+    }
+    /// Identifier of the dataset whose backup starts second.
+    fn second(&self) -> String {
+        self.second.clone()
+    }
+    /// When the backups start running at the same time.
+    fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+    /// When one of the backups is expected to finish.
+    fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+    /// Identifiers of the stores to which both datasets upload.
+    fn shared_stores(&self) -> Vec<String> {
+        self.shared_stores.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Backups expected to run over the coming days.")]
+impl entities::ScheduleForecast {
+    /// Beginning of the forecast period.
+    fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+    /// End of the forecast period.
+    fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+    /// Expected backups, ordered by start time.
+    fn runs(&self) -> Vec<entities::ForecastRun> {
+        self.runs.clone()
+    }
+    /// Overlapping backups of different datasets, ordered by start time.
+    fn conflicts(&self) -> Vec<entities::ScheduleConflict> {
+        self.conflicts.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "URL to which notifications about backups are posted.")]
+impl entities::Webhook {
+    /// Unique identifier of the webhook.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    /// URL to which the JSON payload is posted.
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+    /// Dataset whose backups are reported, or null for every dataset.
+    fn dataset_id(&self) -> Option<String> {
+        self.dataset.clone()
+    }
+    /// Hours without a completed backup after which a dataset is reported as
+    /// being stale, or null to never report stale datasets.
+    fn stale_hours(&self) -> Option<i32> {
+        self.stale_hours.map(|h| h as i32)
+    }
+    /// Time of day, in local time, during which notifications are held until
+    /// later.
+    fn quiet_hours(&self) -> Option<entities::schedule::TimeRange> {
+        self.quiet_hours.clone()
+    }
+    /// If true, finished and failed backups are reported once a day in a
+    /// digest rather than one at a time.
+    fn digest(&self) -> bool {
+        self.digest
+    }
+}
+
+#[juniper::graphql_object(description = "Action taken by the application, from the event log.")]
+impl entities::Event {
+    /// Unique identifier of the event.
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    /// Type of the event, such as `backup_started` or `store_updated`.
+    fn kind(&self) -> String {
+        self.kind.to_string()
+    }
+    /// Date/time when the action took place.
+    fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+    /// What the action concerned, such as a dataset or store identifier.
+    fn subject(&self) -> String {
+        self.subject.clone()
+    }
+    /// Further details of the action, as name/value pairs.
+    fn details(&self) -> Vec<Property> {
+        let mut details: Vec<Property> = self
+            .details
+            .iter()
+            .map(|(name, value)| Property {
+                name: name.to_owned(),
+                value: value.to_owned(),
+            })
+            .collect();
+        details.sort_by(|a, b| a.name.cmp(&b.name));
+        details
+    }
+}
+
+#[juniper::graphql_object(description = "Code for pairing a device with this server.")]
+impl pairing::PairingCode {
+    /// Characters to be entered on the device.
+    fn code(&self) -> String {
+        self.code.clone()
+    }
+    /// Access that will be granted to the device: restore or full.
+    fn scope(&self) -> String {
+        self.scope.to_string()
+    }
+    /// Date/time after which the code is no longer valid.
+    fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+}
+
+#[juniper::graphql_object(description = "Configuration of the application.")]
+impl entities::Configuration {
+    /// Name of the computer on which this application is running.
+    fn hostname(&self) -> String {
+        self.hostname.clone()
+    }
+    /// Name of the user running this application.
+    fn username(&self) -> String {
+        self.username.clone()
+    }
+    /// Computer UUID for generating bucket names.
+    fn computer_id(&self) -> String {
+        self.computer_id.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "Entry within a saved pack file.")]
+impl entities::PackEntry {
+    /// File name of the entry in the pack file.
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Length of the content of the entry.
+    fn size(&self) -> BigInt {
+        BigInt(self.size as i64)
+    }
+}
+
+#[juniper::graphql_object(description = "Details about a pack file.")]
+impl entities::PackFile {
+    /// Number of entries in the pack file.
+    fn count(&self) -> i32 {
+        self.entries.len() as i32
+    }
+
+    /// All entries in the pack file.
+    fn entries(&self) -> Vec<entities::PackEntry> {
+        self.entries.clone()
+    }
+
+    /// Length of the pack file in bytes.
+    fn length(&self) -> BigInt {
+        BigInt(self.length as i64)
+    }
+
+    /// Total size of all pack entries.
+    fn content_length(&self) -> BigInt {
+        BigInt(self.content_length as i64)
+    }
+
+    /// Size of the smallest entry in the pack file.
+    fn smallest(&self) -> BigInt {
+        BigInt(self.smallest as i64)
+    }
+
+    /// Size of the largest entry in the pack file.
+    fn largest(&self) -> BigInt {
+        BigInt(self.largest as i64)
+    }
+
+    /// Average size of all entries in the pack file.
+    fn average(&self) -> BigInt {
+        BigInt(self.average as i64)
+    }
+}
+
+#[juniper::graphql_object(description = "Location within a store of a saved pack.")]
+impl entities::PackLocation {
+    /// ULID of the pack store.
+    fn store(&self) -> String {
+        self.store.clone()
+    }
+
+    /// Remote bucket name.
+    fn bucket(&self) -> String {
+        self.bucket.clone()
+    }
+
+    /// Remote object name.
+    fn object(&self) -> String {
+        self.object.clone()
+    }
+}
+
+#[juniper::graphql_object(description = "An archive containing saved files.")]
+impl entities::Pack {
+    /// Unique checksum of the pack contents.
+    fn checksum(&self) -> ChecksumGQL {
+        ChecksumGQL(self.digest.clone())
+    }
+
+    /// List of store-specific coordinates where the pack is saved.
+    fn locations(&self) -> Vec<entities::PackLocation> {
+        self.locations.clone()
+    }
+
+    /// Compression applied to the chunks in the pack (e.g. `zstd`, `lz4`).
+    fn compression(&self) -> String {
+        self.compression.to_string()
+    }
+}
+
+#[juniper::graphql_object(description = "A request to restore a file or directory.")]
+impl restore::Request {
+    /// Digest of the tree containing the entry to restore.
+    fn tree(&self) -> ChecksumGQL {
+        ChecksumGQL(self.tree.clone())
+    }
+
+    /// Name of the entry within the tree to be restored.
+    fn entry(&self) -> String {
+        self.entry.clone()
+    }
+
+    /// Relative path where file/tree will be restored.
+    fn filepath(&self) -> String {
+        self.filepath.to_string_lossy().into()
+    }
+
+    /// Identifier of the dataset containing the data.
+    fn dataset(&self) -> String {
+        self.dataset.clone()
+    }
+
+    /// Directory to which the files are restored, if other than the base
+    /// path of the dataset.
+    fn target_path(&self) -> Option<String> {
+        self.target.as_ref().map(|p| p.to_string_lossy().into())
+    }
+
+    /// The datetime when the request was completed in UTC.
+    fn finished(&self) -> Option<DateTime<Utc>> {
+        self.finished
     }
 
     /// Number of files restored so far during the restoration.
@@ -778,10 +2062,55 @@ impl restore::Request {
         self.files_restored as i32
     }
 
+    /// Total size in bytes of the files to be restored, once known.
+    fn bytes_total(&self) -> BigInt {
+        BigInt(self.bytes_total as i64)
+    }
+
+    /// Number of bytes restored so far during the restoration.
+    fn bytes_restored(&self) -> BigInt {
+        BigInt(self.bytes_restored as i64)
+    }
+
+    /// Path of the file currently being restored, if any.
+    fn current_file(&self) -> Option<String> {
+        self.current_file
+            .as_ref()
+            .map(|p| p.to_string_lossy().into())
+    }
+
+    /// True if only the metadata of the existing files is being restored.
+    fn metadata_only(&self) -> bool {
+        self.metadata_only
+    }
+
+    /// How thoroughly the retrieved data is checked: `none`, `pack`, `chunk`,
+    /// or `file`.
+    fn verify(&self) -> String {
+        self.verify.to_string()
+    }
+
     /// Error message if request processing failed.
     fn error_message(&self) -> Option<String> {
         self.error_msg.clone()
     }
+
+    /// Destinations of the overlapping requests that were merged into this
+    /// one, and restored along with it.
+    fn merged(&self) -> Vec<String> {
+        self.merged
+            .iter()
+            .map(|r| r.filepath.to_string_lossy().into())
+            .collect()
+    }
+
+    /// Destination of the request that this one was merged into, as that
+    /// request restores everything that this one would have restored.
+    fn merged_into(&self) -> Option<String> {
+        self.merged_into
+            .as_ref()
+            .map(|p| p.to_string_lossy().into())
+    }
 }
 
 #[juniper::graphql_object(description = "Number of database records for each entity type.")]
@@ -831,12 +2160,134 @@ pub struct QueryRoot;
 
 #[juniper::graphql_object(Context = GraphContext)]
 impl QueryRoot {
+    /// Retrieve the number of bytes transferred to and from each store during
+    /// the current calendar month.
+    fn bandwidth_usage(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<StoreBandwidth>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let mut results: Vec<StoreBandwidth> = Vec::new();
+        for store in repo.get_stores()? {
+            let usage = repo.get_bandwidth_usage(&store.id)?;
+            let cap = store.monthly_cap();
+            results.push(StoreBandwidth {
+                capped: cap.map(|c| usage.total() >= c).unwrap_or(false),
+                store_id: store.id,
+                month: usage.month,
+                uploaded: BigInt(usage.uploaded as i64),
+                downloaded: BigInt(usage.downloaded as i64),
+                monthly_cap: cap.map(|c| BigInt(c as i64)),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Retrieve the number and size of the objects in each store, along with
+    /// the quota of the store, if any.
+    fn store_usage(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<StoreUsage>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let mut results: Vec<StoreUsage> = Vec::new();
+        for store in repo.get_stores()? {
+            let usage = repo.get_store_usage(&store.id)?;
+            let quota = store.quota().unwrap_or_default();
+            results.push(StoreUsage {
+                exceeded: quota.is_reached(&usage),
+                store_id: store.id,
+                objects: BigInt(usage.objects as i64),
+                bytes: BigInt(usage.bytes as i64),
+                max_objects: quota.max_objects.map(|m| BigInt(m as i64)),
+                max_bytes: quota.max_bytes.map(|m| BigInt(m as i64)),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Retrieve the jumps in the system clock that the scheduler has detected,
+    /// or corrected for, since the server was started.
+    fn clock_adjustments() -> Vec<clock::ClockAdjustment> {
+        clock::recent()
+    }
+
     /// Retrieve the configuration record.
     fn configuration(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<entities::Configuration> {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         Ok(repo.get_configuration()?)
     }
 
+    /// Project the monthly cost of each store used by the given dataset.
+    ///
+    /// Pricing is taken from the store properties `price_storage`,
+    /// `price_put`, `price_get`, and `price_egress`. Only the snapshots kept
+    /// by the retention policy of the dataset are counted.
+    fn cost_estimate(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+    ) -> FieldResult<Vec<entities::CostEstimate>> {
+        use crate::domain::usecases::estimate_cost::{EstimateCost, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = EstimateCost::new(Box::new(repo));
+        let params: Params = Params::new(dataset_id);
+        let result: Vec<entities::CostEstimate> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Report the storage consumed by the given dataset, with the growth of
+    /// the most recent snapshots (10 by default).
+    fn dataset_usage(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+        snapshots: Option<i32>,
+    ) -> FieldResult<entities::DatasetUsage> {
+        use crate::domain::usecases::dataset_usage::{GetDatasetUsage, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = GetDatasetUsage::new(Box::new(repo));
+        let snapshots = snapshots.map(|n| n.max(0) as usize);
+        let params: Params = Params::new(id, snapshots);
+        let result: entities::DatasetUsage = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Report the deduplication achieved across the snapshots of the given
+    /// dataset, which is computed anew only when its snapshots have changed.
+    fn dedup_stats(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+    ) -> FieldResult<entities::DedupStats> {
+        use crate::domain::usecases::dedup_stats::{GetDedupStats, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = GetDedupStats::new(Box::new(repo));
+        let params: Params = Params::new(dataset);
+        let result: entities::DedupStats = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Retrieve the events from the event log that occurred after the given
+    /// time, oldest first, optionally only those of the given types, up to
+    /// the limit (100 by default).
+    fn events(
+        #[graphql(ctx)] ctx: &GraphContext,
+        after: Option<DateTime<Utc>>,
+        types: Option<Vec<String>>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<entities::Event>> {
+        use crate::domain::managers::events;
+        use crate::domain::usecases::get_events::{GetEvents, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        // include the events that have not yet been saved
+        events::flush(&repo)?;
+        let mut kinds: Vec<entities::EventKind> = Vec::new();
+        for name in types.unwrap_or_default() {
+            kinds.push(entities::EventKind::from_str(&name)?);
+        }
+        let usecase = GetEvents::new(Box::new(repo));
+        let limit = limit.map(|n| n.max(0) as usize);
+        let params: Params = Params::new(after, kinds, limit);
+        let result: Vec<entities::Event> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
     /// Find all dataset configurations.
     fn datasets(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<entities::Dataset>> {
         use crate::domain::usecases::get_datasets::GetDatasets;
@@ -844,10 +2295,106 @@ impl QueryRoot {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetDatasets::new(Box::new(repo));
         let params: NoParams = NoParams {};
-        let datasets = usecase.call(params)?;
+        let datasets = usecase.call(params).map_err(field_error)?;
+        Ok(datasets)
+    }
+
+    /// Find all deleted datasets that have yet to be purged.
+    fn trashed_datasets(
+        #[graphql(ctx)] ctx: &GraphContext,
+    ) -> FieldResult<Vec<entities::TrashedDataset>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let mut datasets = repo.get_trashed_datasets()?;
+        datasets.sort_by(|a, b| b.deleted.cmp(&a.deleted));
         Ok(datasets)
     }
 
+    /// Retrieve the snapshot files that are being served as block devices.
+    fn exports(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<export::Export>> {
+        // the export names are the secrets by which clients connect
+        ctx.require_full()?;
+        Ok(export::exports())
+    }
+
+    /// List the entries of the directory at the given path within a snapshot
+    /// of the dataset, or the latest snapshot if none is given. The path may
+    /// be absolute or relative to the dataset base path, and defaults to the
+    /// base path itself.
+    ///
+    /// Returns null if the path does not name a directory in the snapshot.
+    /// The `tree` of the listing and the `name` of an entry can be given to
+    /// `restoreFiles` to restore that entry.
+    fn browse(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+        snapshot: Option<ChecksumGQL>,
+        path: Option<String>,
+    ) -> FieldResult<Option<entities::DirectoryListing>> {
+        use crate::domain::usecases::browse_snapshot::{BrowseSnapshot, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = BrowseSnapshot::new(Box::new(repo));
+        let params: Params = Params::new(
+            dataset,
+            snapshot.map(|s| s.0),
+            path.unwrap_or_default(),
+        );
+        let result: Option<entities::DirectoryListing> =
+            usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Walk the snapshots of the dataset and return each distinct version of
+    /// the file at the given path, newest first. The path may be absolute or
+    /// relative to the dataset base path.
+    ///
+    /// The `tree` and `entry` of a version can be given to `restoreFiles` to
+    /// restore that version of the file.
+    fn file_history(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+        path: String,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<entities::FileVersion>> {
+        use crate::domain::usecases::file_history::{FileHistory, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = FileHistory::new(Box::new(repo));
+        let params: Params = Params::new(dataset_id, path, limit.map(|l| l.max(1) as usize));
+        let result: Vec<entities::FileVersion> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Search the latest snapshot of every dataset for entries whose path,
+    /// relative to the dataset base path, matches the glob pattern.
+    ///
+    /// The `takenAfter` and `takenBefore` arguments limit the results to
+    /// photos taken within that range, and `subject` to email messages whose
+    /// subject contains the text, for datasets that extract metadata.
+    ///
+    /// The results can be given to `restoreFiles` to restore the entries.
+    fn global_search(
+        #[graphql(ctx)] ctx: &GraphContext,
+        pattern: String,
+        limit: Option<i32>,
+        taken_after: Option<DateTime<Utc>>,
+        taken_before: Option<DateTime<Utc>>,
+        subject: Option<String>,
+    ) -> FieldResult<Vec<entities::SearchResult>> {
+        use crate::domain::usecases::global_search::{GlobalSearch, MetadataFilter, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = GlobalSearch::new(Box::new(repo));
+        let filter = MetadataFilter {
+            taken_after,
+            taken_before,
+            subject,
+        };
+        let params: Params = Params::new(pattern, limit.map(|l| l.max(1) as usize), filter);
+        let result: Vec<entities::SearchResult> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
     /// Find any packs that are missing from the given store.
     fn missing_packs(
         #[graphql(ctx)] ctx: &GraphContext,
@@ -858,7 +2405,23 @@ impl QueryRoot {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = FindMissingPacks::new(Box::new(repo));
         let params: Params = Params::new(store_id);
-        let result: Vec<entities::Pack> = usecase.call(params)?;
+        let result: Vec<entities::Pack> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Compare the objects in the given store with the records in the
+    /// database, without changing anything, listing the objects that no
+    /// record references and the records whose object is not in the store.
+    fn reconcile_store(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+    ) -> FieldResult<entities::ReconciliationReport> {
+        use crate::domain::usecases::reconcile_store::{Params, ReconcileStore};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = ReconcileStore::new(Box::new(repo));
+        let params: Params = Params::new(store_id);
+        let result: entities::ReconciliationReport = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
@@ -872,9 +2435,9 @@ impl QueryRoot {
         use crate::domain::usecases::UseCase;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetPack::new(Box::new(repo));
-        let passphrase = helpers::crypto::get_passphrase();
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
         let params: Params = Params::new(dataset, digest.0, passphrase);
-        let result: entities::PackFile = usecase.call(params)?;
+        let result: entities::PackFile = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
@@ -891,9 +2454,9 @@ impl QueryRoot {
         use crate::domain::usecases::UseCase;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = ScanPacks::new(Box::new(repo));
-        let passphrase = helpers::crypto::get_passphrase();
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
         let params: Params = Params::new(dataset, digest.0, passphrase);
-        let result: Option<Checksum> = usecase.call(params)?;
+        let result: Option<Checksum> = usecase.call(params).map_err(field_error)?;
         Ok(result.map(|c| ChecksumGQL(c)))
     }
 
@@ -904,7 +2467,7 @@ impl QueryRoot {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetCounts::new(Box::new(repo));
         let params: NoParams = NoParams {};
-        let counts = usecase.call(params)?;
+        let counts = usecase.call(params).map_err(field_error)?;
         Ok(counts)
     }
 
@@ -914,10 +2477,24 @@ impl QueryRoot {
         use crate::domain::usecases::{NoParams, UseCase};
         let usecase = QueryRestores::new(ctx.restorer.clone());
         let params: NoParams = NoParams {};
-        let requests: Vec<restore::Request> = usecase.call(params)?;
+        let requests: Vec<restore::Request> = usecase.call(params).map_err(field_error)?;
         Ok(requests)
     }
 
+    /// Preview the snapshots that would be removed by `pruneSnapshots`.
+    fn snapshot_thinning(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+    ) -> FieldResult<Vec<ChecksumGQL>> {
+        use crate::domain::usecases::prune_snapshots::{Params, PruneSnapshots};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = PruneSnapshots::new(Box::new(repo), ctx.appstate.clone());
+        let params: Params = Params::new(dataset_id, true);
+        let result: Vec<Checksum> = usecase.call(params).map_err(field_error)?;
+        Ok(result.into_iter().map(ChecksumGQL).collect())
+    }
+
     /// Retrieve a specific snapshot.
     fn snapshot(
         #[graphql(ctx)] ctx: &GraphContext,
@@ -928,7 +2505,7 @@ impl QueryRoot {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetSnapshot::new(Box::new(repo));
         let params: Params = Params::new(digest.0);
-        let result: Option<entities::Snapshot> = usecase.call(params)?;
+        let result: Option<entities::Snapshot> = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
@@ -936,14 +2513,129 @@ impl QueryRoot {
     fn stores(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<Store>> {
         use crate::domain::usecases::get_stores::GetStores;
         use crate::domain::usecases::{NoParams, UseCase};
+        // the store properties include credentials for the remote services
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetStores::new(Box::new(repo));
         let params: NoParams = NoParams {};
-        let result: Vec<crate::domain::entities::Store> = usecase.call(params)?;
+        let result: Vec<crate::domain::entities::Store> =
+            usecase.call(params).map_err(field_error)?;
         let stores: Vec<Store> = result.into_iter().map(|s| s.into()).collect();
         Ok(stores)
     }
 
+    /// Return the names of the store types supported by this build.
+    fn store_types() -> Vec<String> {
+        let types = crate::data::sources::supported_store_types();
+        types.into_iter().map(|t| t.to_string()).collect()
+    }
+
+    /// Retrieve the outcome of the most recent application of the tiering
+    /// policy for each store, since the server was started.
+    fn tiering_results() -> Vec<entities::TieringResult> {
+        crate::domain::managers::tiering::last_results()
+    }
+
+    /// Retrieve the long running operations that are in progress or have
+    /// recently finished, newest first.
+    fn operations() -> Vec<progress::Operation> {
+        progress::operations()
+    }
+
+    /// Retrieve the counts of failed pack retrievals for each store that has
+    /// had any since the server was started.
+    fn retrieval_failures() -> Vec<entities::RetrievalFailures> {
+        crate::data::repositories::retrieval_failures()
+    }
+
+    /// Retrieve the outcomes of the most recent maintenance tasks, newest
+    /// first, since the server was started.
+    fn maintenance_results() -> Vec<entities::MaintenanceResult> {
+        crate::domain::managers::maintenance::last_results()
+    }
+
+    /// Retrieve the saved verifications of the snapshots of the dataset, newest
+    /// first.
+    fn verifications(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+    ) -> FieldResult<Vec<entities::Verification>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let mut results = repo.get_verifications(&dataset)?;
+        results.reverse();
+        Ok(results)
+    }
+
+    /// Retrieve the most recent run of each of the scheduled tasks of the
+    /// dataset, as set by the `prune_schedule`, `verify_schedule`, and
+    /// `database_schedule` properties.
+    fn dataset_tasks(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+    ) -> FieldResult<Vec<entities::MaintenanceResult>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let results = repo.get_task_runs(&dataset)?;
+        Ok(results)
+    }
+
+    /// Inspect the system and suggest actions that would keep the backups in
+    /// good order, most important first.
+    fn recommendations(
+        #[graphql(ctx)] ctx: &GraphContext,
+    ) -> FieldResult<Vec<entities::Recommendation>> {
+        use crate::domain::managers::maintenance;
+        use crate::domain::usecases::get_recommendations::GetRecommendations;
+        use crate::domain::usecases::{NoParams, UseCase};
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = GetRecommendations::new(Box::new(repo), maintenance::last_results());
+        let params: NoParams = NoParams {};
+        let result: Vec<entities::Recommendation> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Retrieve the devices that have been paired with this server.
+    fn devices(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<entities::Device>> {
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let devices = repo.get_devices()?;
+        Ok(devices)
+    }
+
+    /// Return the path of the endpoint from which the object may be downloaded
+    /// exactly as it was stored, for support purposes. The request to that
+    /// endpoint must carry the same authorization as this query.
+    fn download_object(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+        bucket: String,
+        object: String,
+    ) -> FieldResult<String> {
+        ctx.require_full()?;
+        object_endpoint(ctx, &store_id, &bucket, &object)
+    }
+
+    /// Retrieve the webhooks to which notifications are posted.
+    fn webhooks(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<entities::Webhook>> {
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let webhooks = repo.get_webhooks()?;
+        Ok(webhooks)
+    }
+
+    /// Retrieve the migrations that have been applied to the database, oldest
+    /// first, the last of which gives the current schema version.
+    fn migrations(#[graphql(ctx)] ctx: &GraphContext) -> FieldResult<Vec<entities::Migration>> {
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let migrations = repo.get_migrations()?;
+        Ok(migrations)
+    }
+
+    /// Retrieve the names of the tasks waiting for the maintenance window.
+    fn maintenance_queue() -> Vec<String> {
+        let queued = crate::domain::managers::maintenance::queued();
+        queued.into_iter().map(|t| t.to_string()).collect()
+    }
+
     /// Retrieve a specific tree.
     fn tree(
         #[graphql(ctx)] ctx: &GraphContext,
@@ -954,7 +2646,45 @@ impl QueryRoot {
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = GetTree::new(Box::new(repo));
         let params: Params = Params::new(digest.0);
-        let result: Option<entities::Tree> = usecase.call(params)?;
+        let result: Option<entities::Tree> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Walk the entire snapshot in depth-first order, returning up to `limit`
+    /// entries (default 1,000, at most 10,000) and a cursor which is given to
+    /// the next call to continue the walk.
+    fn walk_snapshot(
+        #[graphql(ctx)] ctx: &GraphContext,
+        digest: ChecksumGQL,
+        cursor: Option<String>,
+        limit: Option<i32>,
+    ) -> FieldResult<entities::SnapshotWalk> {
+        use crate::domain::usecases::walk_snapshot::{Params, WalkSnapshot};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = WalkSnapshot::new(Box::new(repo));
+        let limit = limit.map(|l| l.max(0) as usize);
+        let params: Params = Params::new(digest.0, cursor, limit);
+        let result: entities::SnapshotWalk = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Simulate the scheduler over the given number of days (7 by default, at
+    /// most 31), returning the backups expected to run, based on the duration
+    /// of recent backups, and those backups that will overlap.
+    fn schedule_forecast(
+        #[graphql(ctx)] ctx: &GraphContext,
+        days: Option<i32>,
+    ) -> FieldResult<entities::ScheduleForecast> {
+        use crate::domain::managers::maintenance;
+        use crate::domain::usecases::forecast_schedules::{ForecastSchedules, Params};
+        use crate::domain::usecases::UseCase;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = ForecastSchedules::new(Box::new(repo));
+        let days = days.map(|d| d.max(0) as u32);
+        let params: Params =
+            Params::new(days, Utc::now()).maintenance(maintenance::pruning_window());
+        let result: entities::ScheduleForecast = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 }
@@ -977,6 +2707,8 @@ struct StoreInput {
     label: String,
     /// Name/value pairs that make up this store configuration.
     properties: Vec<PropertyInput>,
+    /// Entity tag of the store being updated, as returned by the server.
+    etag: Option<String>,
 }
 
 impl From<StoreInput> for crate::domain::usecases::new_store::Params {
@@ -989,17 +2721,19 @@ impl From<StoreInput> for crate::domain::usecases::new_store::Params {
     }
 }
 
-impl From<StoreInput> for crate::domain::usecases::test_store::Params {
-    fn from(val: StoreInput) -> Self {
+impl StoreInput {
+    /// Convert to parameters for testing the store, optionally in depth.
+    fn into_test_params(self, deep: bool) -> crate::domain::usecases::test_store::Params {
         let mut properties: HashMap<String, String> = HashMap::new();
-        for prop in val.properties.iter() {
+        for prop in self.properties.iter() {
             properties.insert(prop.name.to_owned(), prop.value.to_owned());
         }
         crate::domain::usecases::test_store::Params::new(
-            val.id.unwrap_or(String::from("default")),
-            val.store_type,
-            val.label,
+            self.id.unwrap_or(String::from("default")),
+            self.store_type,
+            self.label,
             properties,
+            deep,
         )
     }
 }
@@ -1015,6 +2749,7 @@ impl From<StoreInput> for crate::domain::usecases::update_store::Params {
             val.store_type,
             val.label,
             properties,
+            val.etag.unwrap_or_default(),
         )
     }
 }
@@ -1035,10 +2770,36 @@ pub struct DatasetInput {
     pub stores: Vec<String>,
     /// List of paths to be excluded from backups. Can include * and ** wildcards.
     pub excludes: Vec<String>,
+    /// Name/value pairs for optional dataset settings.
+    pub properties: Option<Vec<PropertyInput>>,
+    /// Rules for retaining snapshots when pruning, which take the place of
+    /// any `retain_*` properties.
+    pub retention: Option<RetentionInput>,
+    /// Entity tag of the dataset being updated, as returned by the server.
+    pub etag: Option<String>,
+}
+
+/// Rules for which snapshots of a dataset are retained when pruning. Rules
+/// that are null or not positive are removed.
+#[derive(GraphQLInputObject)]
+pub struct RetentionInput {
+    /// Number of the most recent snapshots to keep.
+    pub count: Option<i32>,
+    /// Keep every snapshot that started within this many days.
+    pub days: Option<i32>,
+    /// Keep the newest snapshot of each of this many days.
+    pub daily: Option<i32>,
+    /// Keep the newest snapshot of each of this many weeks.
+    pub weekly: Option<i32>,
+    /// Keep the newest snapshot of each of this many months.
+    pub monthly: Option<i32>,
+    /// Keep the newest snapshot of each of this many years.
+    pub yearly: Option<i32>,
 }
 
 impl From<DatasetInput> for crate::domain::usecases::new_dataset::Params {
     fn from(val: DatasetInput) -> Self {
+        let properties = val.property_map();
         crate::domain::usecases::new_dataset::Params::new(
             PathBuf::from(val.basepath),
             val.schedules.into_iter().map(|s| s.into()).collect(),
@@ -1046,11 +2807,13 @@ impl From<DatasetInput> for crate::domain::usecases::new_dataset::Params {
             val.stores,
             val.excludes,
         )
+        .properties(properties)
     }
 }
 
 impl From<DatasetInput> for crate::domain::usecases::update_dataset::Params {
     fn from(val: DatasetInput) -> Self {
+        let properties = val.property_map();
         crate::domain::usecases::update_dataset::Params::new(
             val.id.unwrap_or(String::from("default")),
             PathBuf::from(val.basepath),
@@ -1059,11 +2822,42 @@ impl From<DatasetInput> for crate::domain::usecases::update_dataset::Params {
             val.pack_size.into(),
             val.stores,
             val.excludes,
+            val.etag.unwrap_or_default(),
         )
+        .properties(properties)
     }
 }
 
 impl DatasetInput {
+    /// Collect the optional properties into a map.
+    fn property_map(&self) -> HashMap<String, String> {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        for prop in self.properties.iter().flatten() {
+            properties.insert(prop.name.to_owned(), prop.value.to_owned());
+        }
+        if let Some(retention) = self.retention.as_ref() {
+            let rules = [
+                retention.count,
+                retention.days,
+                retention.daily,
+                retention.weekly,
+                retention.monthly,
+                retention.yearly,
+            ];
+            for (name, rule) in entities::RetentionPolicy::PROPERTIES.iter().zip(rules) {
+                match rule {
+                    Some(value) if value > 0 => {
+                        properties.insert(name.to_string(), value.to_string());
+                    }
+                    _ => {
+                        properties.remove(*name);
+                    }
+                }
+            }
+        }
+        properties
+    }
+
     /// Perform basic validation on the input dataset.
     fn validate(&self, datasource: Arc<dyn EntityDataSource>) -> FieldResult<()> {
         // not convinced this is necessary
@@ -1074,15 +2868,23 @@ impl DatasetInput {
         //     ));
         // }
         // verify the stores exist in the database
+        let mut stores: Vec<entities::Store> = Vec::new();
         for store in self.stores.iter() {
-            let opt = datasource.get_store(store)?;
-            if opt.is_none() {
-                return Err(FieldError::new(
-                    format!("Named store does not exist: {}", &store),
-                    Value::null(),
-                ));
+            match datasource.get_store(store)? {
+                Some(value) => stores.push(value),
+                None => {
+                    return Err(FieldError::new(
+                        format!("Named store does not exist: {}", &store),
+                        Value::null(),
+                    ))
+                }
             }
         }
+        // ensure the stores will accept packs of the desired size
+        let limits = entities::ObjectSizeLimits::for_stores(&stores);
+        if let Some(msg) = limits.check(self.pack_size.into()) {
+            return Err(FieldError::new(msg, Value::null()));
+        }
         // ensure the basepath actually exists
         let bpath = Path::new(&self.basepath);
         if !bpath.exists() {
@@ -1099,6 +2901,57 @@ impl DatasetInput {
     }
 }
 
+/// Webhook defines a URL to which notifications about backups are posted.
+#[derive(GraphQLInputObject)]
+pub struct WebhookInput {
+    /// Webhook identifier, only used when updating a webhook.
+    pub id: Option<String>,
+    /// URL to which the JSON payload is posted, either http or https.
+    pub url: String,
+    /// Dataset whose backups are reported, or null for every dataset.
+    pub dataset_id: Option<String>,
+    /// Hours without a completed backup after which a dataset is reported as
+    /// being stale, or null to never report stale datasets.
+    pub stale_hours: Option<i32>,
+    /// Time of day, in local time, during which notifications are held until
+    /// later.
+    pub quiet_hours: Option<InputTimeRange>,
+    /// If true, finished and failed backups are reported once a day in a
+    /// digest rather than one at a time.
+    pub digest: Option<bool>,
+}
+
+impl WebhookInput {
+    fn validate(&self, datasource: Arc<dyn EntityDataSource>) -> FieldResult<()> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(FieldError::new(
+                "Webhook URL must use http or https",
+                Value::null(),
+            ));
+        }
+        if let Some(hours) = self.stale_hours {
+            if hours < 1 {
+                return Err(FieldError::new(
+                    "Stale hours must be at least 1",
+                    Value::null(),
+                ));
+            }
+        }
+        if let Some(range) = self.quiet_hours.as_ref() {
+            range.validate()?;
+        }
+        if let Some(dataset_id) = self.dataset_id.as_ref() {
+            if datasource.get_dataset(dataset_id)?.is_none() {
+                return Err(FieldError::new(
+                    format!("Named dataset does not exist: {}", dataset_id),
+                    Value::null(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// New schedule for the dataset. Combine elements to get backups to run on a
 /// certain day of the week, month, and/or within a given time range.
 #[derive(GraphQLInputObject)]
@@ -1236,6 +3089,99 @@ impl From<ScheduleInput> for entities::schedule::Schedule {
     }
 }
 
+// Return the path of the endpoint for transferring the object, after ensuring
+// that the store exists.
+fn object_endpoint(
+    ctx: &GraphContext,
+    store_id: &str,
+    bucket: &str,
+    object: &str,
+) -> FieldResult<String> {
+    use crate::domain::usecases::download_object::check_name;
+    check_name(bucket).map_err(field_error)?;
+    check_name(object).map_err(field_error)?;
+    let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+    if repo.get_store(store_id)?.is_none() {
+        let err = entities::Message::new(entities::MessageCode::NoSuchStore).with("id", store_id);
+        return Err(field_error(err.into()));
+    }
+    Ok(format!(
+        "/object/{}/{}/{}",
+        encode_segment(store_id),
+        encode_segment(bucket),
+        encode_segment(object)
+    ))
+}
+
+// Percent-encode everything but the unreserved characters of the value so
+// that it may serve as a single segment of a URL path.
+fn encode_segment(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Convert the error into a field error, with the message code and parameters
+// set in the extensions if the error is a user-facing message, such that the
+// client can present the message in the language of the user.
+fn field_error(err: anyhow::Error) -> FieldError {
+    match err.downcast::<entities::Message>() {
+        Ok(message) => {
+            let mut params = juniper::Object::with_capacity(message.params.len());
+            for (name, value) in message.params.iter() {
+                params.add_field(name.as_str(), Value::scalar(value.to_owned()));
+            }
+            let mut extensions = juniper::Object::with_capacity(2);
+            extensions.add_field("code", Value::scalar(message.code.to_string()));
+            extensions.add_field("params", Value::object(params));
+            FieldError::new(message.to_string(), Value::object(extensions))
+        }
+        Err(err) => FieldError::new(err.to_string(), Value::null()),
+    }
+}
+
+// Convert the error from an update into a field error, with the conflict code
+// and current entity tag set in the extensions if the record had been modified
+// by someone else, such that the client can merge the changes and try again.
+fn conflict_error(err: anyhow::Error) -> FieldError {
+    use crate::domain::usecases::ConflictError;
+    match err.downcast::<ConflictError>() {
+        Ok(conflict) => FieldError::new(
+            conflict.to_string(),
+            graphql_value!({ "code": "CONFLICT", "etag": (conflict.current) }),
+        ),
+        Err(err) => field_error(err),
+    }
+}
+
+// Convert the error from deleting a store into a field error, with the code
+// and dependent datasets set in the extensions if the store is still in use.
+fn in_use_error(err: anyhow::Error) -> FieldError {
+    use crate::domain::usecases::delete_store::StoreInUseError;
+    match err.downcast::<StoreInUseError>() {
+        Ok(in_use) => {
+            let datasets: Vec<Value> = in_use
+                .datasets
+                .iter()
+                .map(|id| Value::scalar(id.to_owned()))
+                .collect();
+            let mut extensions = juniper::Object::with_capacity(4);
+            extensions.add_field("code", Value::scalar("IN_USE".to_owned()));
+            extensions.add_field("datasets", Value::list(datasets));
+            extensions.add_field("packs", Value::scalar(in_use.packs as i32));
+            extensions.add_field("stranded", Value::scalar(in_use.stranded as i32));
+            FieldError::new(in_use.to_string(), Value::object(extensions))
+        }
+        Err(err) => field_error(err),
+    }
+}
+
 pub struct MutationRoot;
 
 #[juniper::graphql_object(Context = GraphContext)]
@@ -1244,55 +3190,88 @@ impl MutationRoot {
     fn define_store(#[graphql(ctx)] ctx: &GraphContext, input: StoreInput) -> FieldResult<Store> {
         use crate::domain::usecases::new_store::{NewStore, Params};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = NewStore::new(Box::new(repo));
         let params: Params = input.into();
-        let result: crate::domain::entities::Store = usecase.call(params)?;
+        let result: crate::domain::entities::Store = usecase.call(params).map_err(field_error)?;
         Ok(result.into())
     }
 
     /// Update the saved store configuration.
     fn update_store(#[graphql(ctx)] ctx: &GraphContext, input: StoreInput) -> FieldResult<Store> {
+        ctx.require_full()?;
         if input.id.is_none() {
             return Err(FieldError::new(
                 "Cannot update store without id field",
                 Value::null(),
             ));
         }
+        if input.etag.is_none() {
+            return Err(FieldError::new(
+                "Cannot update store without etag field",
+                Value::null(),
+            ));
+        }
         use crate::domain::usecases::update_store::{Params, UpdateStore};
         use crate::domain::usecases::UseCase;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = UpdateStore::new(Box::new(repo));
         let params: Params = input.into();
-        let result: crate::domain::entities::Store = usecase.call(params)?;
+        let result: crate::domain::entities::Store =
+            usecase.call(params).map_err(conflict_error)?;
         Ok(result.into())
     }
 
     /// Test the given pack store definition for basic connectivity.
     ///
-    /// Returns an error message, or 'ok' if there were no errors.
-    fn test_store(#[graphql(ctx)] ctx: &GraphContext, input: StoreInput) -> FieldResult<String> {
+    /// If `deep` is true, a small probe pack is uploaded, retrieved, verified,
+    /// and deleted, with the outcome and latency of each step reported. The
+    /// status is either the first error message, or 'ok' if there were none.
+    fn test_store(
+        #[graphql(ctx)] ctx: &GraphContext,
+        input: StoreInput,
+        deep: Option<bool>,
+    ) -> FieldResult<StoreTest> {
         use crate::domain::usecases::test_store::{Params, TestStore};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = TestStore::new(Box::new(repo));
-        let params: Params = input.into();
-        let result = usecase.call(params);
-        if result.is_err() {
-            Ok(format!("{:?}", result.unwrap_err()))
-        } else {
-            Ok(String::from("ok"))
+        let params: Params = input.into_test_params(deep.unwrap_or(false));
+        match usecase.call(params) {
+            Ok(steps) => {
+                let status = match steps.iter().find_map(|s| s.error.as_ref()) {
+                    Some(err) => err.to_owned(),
+                    None => String::from("ok"),
+                };
+                Ok(StoreTest { status, steps })
+            }
+            Err(err) => Ok(StoreTest {
+                status: format!("{:?}", err),
+                steps: vec![],
+            }),
         }
     }
 
     /// Delete the named store, returning the identifier.
-    fn delete_store(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<String> {
+    ///
+    /// A store that is used by any dataset or pack record is not deleted,
+    /// and the error has the code `IN_USE` along with the identifiers of the
+    /// datasets. With `force`, the store is first removed from the datasets
+    /// and pack records, unless it holds the only copy of any pack.
+    fn delete_store(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+        force: Option<bool>,
+    ) -> FieldResult<String> {
         use crate::domain::usecases::delete_store::{DeleteStore, Params};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = DeleteStore::new(Box::new(repo));
-        let params: Params = Params::new(id.clone());
-        usecase.call(params)?;
+        let params: Params = Params::new(id.clone(), force.unwrap_or(false));
+        usecase.call(params).map_err(in_use_error)?;
         Ok(id)
     }
 
@@ -1303,12 +3282,13 @@ impl MutationRoot {
     ) -> FieldResult<entities::Dataset> {
         use crate::domain::usecases::new_dataset::{NewDataset, Params};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let datasource = ctx.datasource.clone();
         input.validate(datasource.clone())?;
         let repo = RecordRepositoryImpl::new(datasource);
         let usecase = NewDataset::new(Box::new(repo));
         let params: Params = input.into();
-        let dataset = usecase.call(params)?;
+        let dataset = usecase.call(params).map_err(field_error)?;
         Ok(dataset)
     }
 
@@ -1317,12 +3297,19 @@ impl MutationRoot {
         #[graphql(ctx)] ctx: &GraphContext,
         input: DatasetInput,
     ) -> FieldResult<entities::Dataset> {
+        ctx.require_full()?;
         if input.id.is_none() {
             return Err(FieldError::new(
                 "Cannot update dataset without id field",
                 Value::null(),
             ));
         }
+        if input.etag.is_none() {
+            return Err(FieldError::new(
+                "Cannot update dataset without etag field",
+                Value::null(),
+            ));
+        }
         use crate::domain::usecases::update_dataset::{Params, UpdateDataset};
         use crate::domain::usecases::UseCase;
         let datasource = ctx.datasource.clone();
@@ -1330,40 +3317,107 @@ impl MutationRoot {
         let repo = RecordRepositoryImpl::new(datasource);
         let usecase = UpdateDataset::new(Box::new(repo));
         let params: Params = input.into();
-        let result = usecase.call(params)?;
+        let result = usecase.call(params).map_err(conflict_error)?;
         Ok(result)
     }
 
     /// Delete the dataset with the given identifier, returning the identifier.
-    fn delete_dataset(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<String> {
+    ///
+    /// The dataset is moved to the trash, from which it can be restored using
+    /// `undeleteDataset` until the retention period has passed. If `force` is
+    /// true, the dataset is removed immediately.
+    fn delete_dataset(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+        force: Option<bool>,
+    ) -> FieldResult<String> {
         use crate::domain::usecases::delete_dataset::{DeleteDataset, Params};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = DeleteDataset::new(Box::new(repo));
-        let params: Params = Params::new(id.clone());
-        usecase.call(params)?;
+        let params: Params = Params::new(id.clone(), force.unwrap_or(false));
+        usecase.call(params).map_err(field_error)?;
         Ok(id)
     }
 
+    /// Restore the deleted dataset with the given identifier from the trash.
+    fn undelete_dataset(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+    ) -> FieldResult<entities::Dataset> {
+        use crate::domain::usecases::undelete_dataset::{Params, UndeleteDataset};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = UndeleteDataset::new(Box::new(repo));
+        let params: Params = Params::new(id);
+        let dataset = usecase.call(params).map_err(field_error)?;
+        Ok(dataset)
+    }
+
+    /// Archive the dataset with the given identifier.
+    ///
+    /// An archived dataset is no longer backed up, while its snapshots remain
+    /// available for browsing and restoring.
+    fn archive_dataset(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+    ) -> FieldResult<entities::Dataset> {
+        use crate::domain::usecases::archive_dataset::{ArchiveDataset, Params};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = ArchiveDataset::new(Box::new(repo));
+        let params: Params = Params::new(id, true);
+        let dataset = usecase.call(params).map_err(field_error)?;
+        Ok(dataset)
+    }
+
+    /// Return the archived dataset with the given identifier to service.
+    fn unarchive_dataset(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+    ) -> FieldResult<entities::Dataset> {
+        use crate::domain::usecases::archive_dataset::{ArchiveDataset, Params};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = ArchiveDataset::new(Box::new(repo));
+        let params: Params = Params::new(id, false);
+        let dataset = usecase.call(params).map_err(field_error)?;
+        Ok(dataset)
+    }
+
     /// Begin the backup procedure for the dataset with the given identifier.
     fn start_backup(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<bool> {
         use crate::domain::usecases::start_backup::{Params, StartBackup};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = StartBackup::new(Box::new(repo), ctx.processor.clone());
         let params: Params = Params::new(id);
-        usecase.call(params)?;
+        usecase.call(params).map_err(field_error)?;
         Ok(true)
     }
 
     /// Signal the running backup for the given dataset to stop prematurely.
-    fn stop_backup(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<bool> {
+    ///
+    /// With `uploadsOnly`, the backup stops uploading but continues to find
+    /// and chunk the changed files, saving them such that the next run goes
+    /// straight to uploading.
+    fn stop_backup(
+        #[graphql(ctx)] ctx: &GraphContext,
+        id: String,
+        uploads_only: Option<bool>,
+    ) -> FieldResult<bool> {
         use crate::domain::usecases::stop_backup::{Params, StopBackup};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = StopBackup::new(Box::new(repo), ctx.appstate.clone());
-        let params: Params = Params::new(id);
-        usecase.call(params)?;
+        let params: Params = Params::new(id, uploads_only.unwrap_or(false));
+        usecase.call(params).map_err(field_error)?;
         Ok(true)
     }
 
@@ -1374,31 +3428,111 @@ impl MutationRoot {
     ) -> FieldResult<String> {
         use crate::domain::usecases::restore_database::{Params, RestoreDatabase};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
-        let passphrase = helpers::crypto::get_passphrase();
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
         let usecase = RestoreDatabase::new(Box::new(repo));
         let params: Params = Params::new(store_id, ctx.appstate.clone(), passphrase);
-        let result = usecase.call(params)?;
+        let result = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
     /// Enqueue a request to restore the given file or directory tree.
+    ///
+    /// Directories, including empty ones, are created as needed and their
+    /// modification times restored, unless `skipTimes` is true.
+    ///
+    /// If `metadataOnly` is true, the files are expected to exist already, and
+    /// only their ownership, mode, and extended attributes are restored, which
+    /// does not involve downloading any packs.
+    ///
+    /// The `verify` level is one of `none`, `pack` (the default), `chunk`, or
+    /// `file`, each checking the digests of the retrieved packs, extracted
+    /// chunks, and restored files, respectively, along with those before it.
+    #[allow(clippy::too_many_arguments)]
     fn restore_files(
         #[graphql(ctx)] ctx: &GraphContext,
         tree: ChecksumGQL,
         entry: String,
         filepath: String,
         dataset: String,
+        skip_times: Option<bool>,
+        metadata_only: Option<bool>,
+        verify: Option<String>,
     ) -> FieldResult<bool> {
         use crate::domain::usecases::restore_files::{Params, RestoreFiles};
         use crate::domain::usecases::UseCase;
+        let verify = match verify {
+            Some(name) => entities::RestoreVerify::from_str(&name)?,
+            None => entities::RestoreVerify::default(),
+        };
         let usecase = RestoreFiles::new(ctx.restorer.clone());
         let fpath = PathBuf::from(filepath);
-        let params: Params = Params::new(tree.0.clone(), entry.clone(), fpath, dataset);
-        usecase.call(params)?;
+        let params: Params = Params::new(tree.0.clone(), entry.clone(), fpath, dataset)
+            .skip_times(skip_times.unwrap_or(false))
+            .metadata_only(metadata_only.unwrap_or(false))
+            .verify(verify);
+        usecase.call(params).map_err(field_error)?;
+        Ok(true)
+    }
+
+    /// Enqueue a request to restore the entire tree of the given snapshot to
+    /// the target directory, which must be an absolute path on the server.
+    ///
+    /// The ownership, mode, extended attributes, and modification time of
+    /// every entry are restored. If the restore is interrupted, enqueuing the
+    /// same snapshot and target again will skip the files already restored.
+    /// The `verify` level is the same as for `restoreFiles`.
+    fn restore_snapshot(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+        snapshot: ChecksumGQL,
+        target_path: String,
+        verify: Option<String>,
+    ) -> FieldResult<bool> {
+        use crate::domain::usecases::restore_snapshot::{Params, RestoreSnapshot};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let verify = match verify {
+            Some(name) => entities::RestoreVerify::from_str(&name)?,
+            None => entities::RestoreVerify::default(),
+        };
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = RestoreSnapshot::new(Box::new(repo), ctx.restorer.clone());
+        let params = Params::new(dataset, snapshot.0, PathBuf::from(target_path)).verify(verify);
+        usecase.call(params).map_err(field_error)?;
         Ok(true)
     }
 
+    /// Serve a file from a snapshot, such as a virtual machine disk image, as a
+    /// read-only network block device (NBD) that can be mounted directly.
+    ///
+    /// The address defaults to `127.0.0.1:0`, in which case a port is chosen
+    /// automatically; other than loopback addresses are refused unless
+    /// `EXPORT_ALLOW_REMOTE` is set. The client must give the randomly
+    /// generated `name` of the export, which is not listed to clients.
+    fn start_export(
+        #[graphql(ctx)] ctx: &GraphContext,
+        tree: ChecksumGQL,
+        entry: String,
+        dataset: String,
+        address: Option<String>,
+    ) -> FieldResult<export::Export> {
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let dbase: Arc<dyn RecordRepository> = Arc::new(repo);
+        let address = address.unwrap_or_else(|| "127.0.0.1:0".to_owned());
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
+        let result = export::start(dbase, &dataset, tree.0, &entry, &address, passphrase)?;
+        Ok(result)
+    }
+
+    /// Stop serving the export with the given identifier.
+    fn stop_export(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<bool> {
+        ctx.require_full()?;
+        Ok(export::stop(&id))
+    }
+
     /// Cancel the pending restore request that matches the given values.
     fn cancel_restore(
         #[graphql(ctx)] ctx: &GraphContext,
@@ -1412,7 +3546,7 @@ impl MutationRoot {
         let usecase = CancelRestore::new(ctx.restorer.clone());
         let fpath = PathBuf::from(filepath);
         let params: Params = Params::new(tree.0.clone(), entry.clone(), fpath, dataset);
-        let result = usecase.call(params)?;
+        let result = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
@@ -1426,10 +3560,11 @@ impl MutationRoot {
     ) -> FieldResult<i32> {
         use crate::domain::usecases::reassign_packs::{Params, ReassignPacks};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = ReassignPacks::new(Box::new(repo));
         let params: Params = Params::new(source_id, target_id);
-        let result: u64 = usecase.call(params)?;
+        let result: u64 = usecase.call(params).map_err(field_error)?;
         // let's hope we never update more than 2 billion pack records
         let result_i32: i32 = result
             .try_into()
@@ -1445,10 +3580,11 @@ impl MutationRoot {
     ) -> FieldResult<Vec<entities::Pack>> {
         use crate::domain::usecases::restore_missing::{Params, RestoreMissingPacks};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = RestoreMissingPacks::new(Box::new(repo));
         let params: Params = Params::new(source_id, target_id);
-        let result: Vec<entities::Pack> = usecase.call(params)?;
+        let result: Vec<entities::Pack> = usecase.call(params).map_err(field_error)?;
         Ok(result)
     }
 
@@ -1456,13 +3592,239 @@ impl MutationRoot {
     fn prune_extra(#[graphql(ctx)] ctx: &GraphContext, store_id: String) -> FieldResult<i32> {
         use crate::domain::usecases::prune_extra::{Params, PruneExtraPacks};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
         let usecase = PruneExtraPacks::new(Box::new(repo));
         let params: Params = Params::new(store_id);
-        let result: u32 = usecase.call(params)?;
+        let result: u32 = usecase.call(params).map_err(field_error)?;
         Ok(result as i32)
     }
 
+    /// Look for objects in the given store that are not known to the
+    /// database, such as after the database was lost, and report them, or
+    /// adopt those that are pack files, or remove them, per the action.
+    ///
+    /// Every unknown object is downloaded to be examined.
+    fn discover_remote_data(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+        action: Option<DiscoveryAction>,
+    ) -> FieldResult<entities::DiscoveryReport> {
+        use crate::domain::usecases::discover_remote::{DiscoverRemote, Params};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = DiscoverRemote::new(Box::new(repo));
+        let action = action.unwrap_or(DiscoveryAction::Report);
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
+        let params: Params = Params::new(store_id, action.into(), passphrase);
+        let result: entities::DiscoveryReport = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Thin out the snapshots of the dataset according to its retention
+    /// policy or, without one, keeping fewer as they age.
+    ///
+    /// Returns the digests of the snapshots that were removed.
+    fn prune_snapshots(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+    ) -> FieldResult<Vec<ChecksumGQL>> {
+        use crate::domain::usecases::prune_snapshots::{Params, PruneSnapshots};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = PruneSnapshots::new(Box::new(repo), ctx.appstate.clone());
+        let params: Params = Params::new(dataset_id, false);
+        let result: Vec<Checksum> = usecase.call(params).map_err(field_error)?;
+        Ok(result.into_iter().map(ChecksumGQL).collect())
+    }
+
+    /// Return the path of the endpoint to which the content of the object may
+    /// be sent with a PUT request, replacing the object in the store, for
+    /// support purposes. That request must carry the same authorization as
+    /// this mutation.
+    fn upload_object(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+        bucket: String,
+        object: String,
+    ) -> FieldResult<String> {
+        ctx.require_full()?;
+        object_endpoint(ctx, &store_id, &bucket, &object)
+    }
+
+    /// Rewrite the packs of the dataset whose fraction of live data is below
+    /// the threshold (between 0 and 1), moving the live chunks to new packs
+    /// and removing the old packs from the stores.
+    fn compact_packs(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+        threshold: f64,
+    ) -> FieldResult<entities::CompactionReport> {
+        use crate::domain::usecases::compact_packs::{CompactPacks, Params};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = CompactPacks::new(Box::new(repo), ctx.appstate.clone());
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
+        let params: Params = Params::new(dataset_id, threshold, passphrase);
+        let result: entities::CompactionReport = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Verify the snapshot of the dataset, saving the outcome for the
+    /// `verifications` query. The depth is one of `metadata` (the default),
+    /// which ensures the records of the snapshot exist; `packs`, which also
+    /// retrieves and checks every pack; or `full`, which also restores the
+    /// given number of files chosen at random (all of them if not given) to
+    /// a temporary location and compares their digests.
+    fn verify_snapshot(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset: String,
+        snapshot: ChecksumGQL,
+        depth: Option<String>,
+        sample: Option<i32>,
+    ) -> FieldResult<entities::Verification> {
+        use crate::domain::usecases::verify_snapshot::{Params, VerifySnapshot};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let depth = match depth {
+            Some(name) => entities::VerifyDepth::from_str(&name)?,
+            None => entities::VerifyDepth::Metadata,
+        };
+        let passphrase = if depth == entities::VerifyDepth::Full {
+            Some(helpers::crypto::get_passphrase().map_err(field_error)?)
+        } else {
+            None
+        };
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let dbase = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let fetcher = restore::FileRestorerImpl::new(Arc::new(dbase));
+        let usecase = VerifySnapshot::new(Box::new(repo), Box::new(fetcher));
+        let sample = sample.map(|n| n.max(0) as usize);
+        let params: Params = Params::new(dataset, snapshot.0, depth, sample, passphrase);
+        let result: entities::Verification = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Compare the log of snapshots held by the given store with the database,
+    /// to detect history that was altered, deleted, or rolled back.
+    fn verify_chain(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+    ) -> FieldResult<entities::ChainReport> {
+        use crate::domain::usecases::verify_chain::{Params, VerifyChain};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = VerifyChain::new(Box::new(repo));
+        let params: Params = Params::new(store_id);
+        let result: entities::ChainReport = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Rebuild the file counts of every snapshot of the dataset from the
+    /// recorded trees and files, correcting those that have drifted, and
+    /// compute the deduplication statistics anew.
+    fn recompute_statistics(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+    ) -> FieldResult<entities::StatisticsReport> {
+        use crate::domain::usecases::recompute_statistics::{Params, RecomputeStatistics};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = RecomputeStatistics::new(Box::new(repo), ctx.appstate.clone());
+        let params: Params = Params::new(dataset_id);
+        let result: entities::StatisticsReport = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Apply the tiering policy of the given store, or of all stores if none
+    /// is given, rather than waiting for the daily job to run.
+    fn apply_tiering(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: Option<String>,
+    ) -> FieldResult<Vec<entities::TieringResult>> {
+        use crate::domain::usecases::tier_packs::{Params, TierPacks};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = TierPacks::new(Box::new(repo));
+        let params: Params = Params::new(store_id);
+        let result: Vec<entities::TieringResult> = usecase.call(params).map_err(field_error)?;
+        Ok(result)
+    }
+
+    /// Configure the lifecycle rules of the given store, saving them in the
+    /// store properties and applying them to the existing pack buckets.
+    ///
+    /// Pack objects move to `transitionClass` after `transitionDays` days, and
+    /// database snapshots other than the newest are deleted after
+    /// `databaseDays` days. Omitting a value removes that part of the rules.
+    fn configure_store_lifecycle(
+        #[graphql(ctx)] ctx: &GraphContext,
+        store_id: String,
+        transition_days: Option<i32>,
+        transition_class: Option<String>,
+        database_days: Option<i32>,
+    ) -> FieldResult<Store> {
+        use crate::domain::usecases::configure_store_lifecycle::{ConfigureStoreLifecycle, Params};
+        use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
+        let to_days = |value: Option<i32>| -> Result<Option<u32>, FieldError> {
+            match value {
+                Some(days) if days < 0 => Err(FieldError::new(
+                    "Lifecycle days must not be negative",
+                    Value::null(),
+                )),
+                Some(days) => Ok(Some(days as u32)),
+                None => Ok(None),
+            }
+        };
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let usecase = ConfigureStoreLifecycle::new(Box::new(repo));
+        let params: Params = Params::new(
+            store_id,
+            to_days(transition_days)?,
+            transition_class,
+            to_days(database_days)?,
+        );
+        let result: crate::domain::entities::Store = usecase.call(params).map_err(field_error)?;
+        Ok(result.into())
+    }
+
+    /// Queue a maintenance task (verify, prune, compact, or health) to run
+    /// during the next maintenance window, returning the queued tasks.
+    fn queue_maintenance(
+        #[graphql(ctx)] ctx: &GraphContext,
+        task: String,
+    ) -> FieldResult<Vec<String>> {
+        use crate::domain::managers::maintenance;
+        ctx.require_full()?;
+        let task = entities::MaintenanceTask::from_str(&task)?;
+        if task.schedule_property().is_some() {
+            return Err(FieldError::new(
+                format!("task {} runs on the schedule of each dataset", task),
+                Value::null(),
+            ));
+        }
+        maintenance::queue(task);
+        let queued = maintenance::queued();
+        Ok(queued.into_iter().map(|t| t.to_string()).collect())
+    }
+
+    /// Re-read the configuration file and environment, applying any changes
+    /// that do not require restarting the server.
+    fn reload_configuration(
+        #[graphql(ctx)] ctx: &GraphContext,
+    ) -> FieldResult<settings::ReloadReport> {
+        ctx.require_full()?;
+        let report = settings::reload()?;
+        Ok(report)
+    }
+
     /// Create a missing file record from the given information.
     ///
     /// This will fetch the given pack file to verify the chunk is contained
@@ -1476,20 +3838,176 @@ impl MutationRoot {
     ) -> FieldResult<bool> {
         use crate::domain::usecases::insert_file::{InsertFile, Params};
         use crate::domain::usecases::UseCase;
+        ctx.require_full()?;
         let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
-        let passphrase = helpers::crypto::get_passphrase();
+        let passphrase = helpers::crypto::get_passphrase().map_err(field_error)?;
         let usecase = InsertFile::new(Box::new(repo));
         let params: Params = Params::new(dataset, chunk_digest.0, pack_digest.0, passphrase);
-        usecase.call(params)?;
+        usecase.call(params).map_err(field_error)?;
+        Ok(true)
+    }
+
+    /// Generate a short-lived code with which a device, such as the mobile
+    /// client, may obtain an access token by way of the `/pair` endpoint. The
+    /// scope is either restore (the default) or full.
+    fn start_pairing(
+        #[graphql(ctx)] ctx: &GraphContext,
+        scope: Option<String>,
+    ) -> FieldResult<pairing::PairingCode> {
+        ctx.require_full()?;
+        let scope = match scope {
+            Some(value) => DeviceScope::from_str(&value)?,
+            None => DeviceScope::Restore,
+        };
+        Ok(pairing::start(scope))
+    }
+
+    /// Revoke the access token of the paired device.
+    fn revoke_device(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<bool> {
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        if !repo.get_devices()?.iter().any(|d| d.id == id) {
+            return Err(FieldError::new(
+                "no such device",
+                graphql_value!({ "code": "NOT_FOUND" }),
+            ));
+        }
+        repo.delete_device(&id)?;
+        Ok(true)
+    }
+
+    /// Define a new webhook, or update an existing one if an identifier is
+    /// given, to which notifications about backups will be posted.
+    fn define_webhook(
+        #[graphql(ctx)] ctx: &GraphContext,
+        input: WebhookInput,
+    ) -> FieldResult<entities::Webhook> {
+        ctx.require_full()?;
+        input.validate(ctx.datasource.clone())?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        let mut webhook = entities::Webhook::new(&input.url);
+        if let Some(id) = input.id {
+            if !repo.get_webhooks()?.iter().any(|w| w.id == id) {
+                return Err(FieldError::new(
+                    "no such webhook",
+                    graphql_value!({ "code": "NOT_FOUND" }),
+                ));
+            }
+            webhook.id = id;
+        }
+        webhook.dataset = input.dataset_id;
+        webhook.stale_hours = input.stale_hours.map(|h| h as u32);
+        webhook.quiet_hours = input.quiet_hours.map(|r| r.into());
+        webhook.digest = input.digest.unwrap_or(false);
+        repo.put_webhook(&webhook)?;
+        Ok(webhook)
+    }
+
+    /// Remove the webhook so that it no longer receives notifications.
+    fn delete_webhook(#[graphql(ctx)] ctx: &GraphContext, id: String) -> FieldResult<bool> {
+        ctx.require_full()?;
+        let repo = RecordRepositoryImpl::new(ctx.datasource.clone());
+        if !repo.get_webhooks()?.iter().any(|w| w.id == id) {
+            return Err(FieldError::new(
+                "no such webhook",
+                graphql_value!({ "code": "NOT_FOUND" }),
+            ));
+        }
+        repo.delete_webhook(&id)?;
         Ok(true)
     }
 }
 
-pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<GraphContext>>;
+// Send the changed state to each subscription, forgetting those that have
+// since been closed by the client.
+fn broadcast_state(state: &state::State, _previous: Option<&state::State>) {
+    let mut watchers = STATE_WATCHERS.lock().unwrap();
+    watchers.retain_mut(|watcher| {
+        *watcher.latest.lock().unwrap() = Some(state.clone());
+        // a wakeup that is still pending will send this state instead
+        match watcher.wakeup.try_send(()) {
+            Ok(()) => true,
+            Err(err) => err.is_full(),
+        }
+    });
+}
+
+// Produce a stream of the application state, starting with the current state
+// and followed by the latest state whenever it changes.
+fn watch_state(appstate: &dyn StateStore) -> impl Stream<Item = state::State> + Send {
+    let latest: Arc<Mutex<Option<state::State>>> = Arc::new(Mutex::new(None));
+    let (wakeup, rx) = mpsc::channel::<()>(0);
+    STATE_WATCHERS.lock().unwrap().push(StateWatcher {
+        latest: latest.clone(),
+        wakeup,
+    });
+    // adding the same subscriber again is harmless
+    appstate.subscribe("graphql-subscriptions", broadcast_state);
+    let current = appstate.get_state();
+    let changes = rx.filter_map(move |_| {
+        let state = latest.lock().unwrap().take();
+        async move { state }
+    });
+    stream::once(async move { current }).chain(changes)
+}
+
+type BackupStream = Pin<Box<dyn Stream<Item = Option<state::BackupState>> + Send>>;
+
+type RestoreStream = Pin<Box<dyn Stream<Item = Vec<restore::Request>> + Send>>;
+
+pub struct SubscriptionRoot;
+
+#[juniper::graphql_subscription(Context = GraphContext)]
+impl SubscriptionRoot {
+    /// Progress of the backup of the given dataset, sent whenever it changes,
+    /// or null if the dataset has not been backed up since the server started.
+    async fn backup_progress(
+        #[graphql(ctx)] ctx: &GraphContext,
+        dataset_id: String,
+    ) -> BackupStream {
+        let mut previous: Option<String> = None;
+        let changes = watch_state(ctx.appstate.as_ref()).filter_map(move |redux| {
+            let backup = redux.backups(&dataset_id).cloned();
+            // many state changes concern other datasets or the supervisor
+            let current = format!("{:?}", backup);
+            let changed = previous.as_ref() != Some(&current);
+            previous = Some(current);
+            async move {
+                if changed {
+                    Some(backup)
+                } else {
+                    None
+                }
+            }
+        });
+        Box::pin(changes)
+    }
+
+    /// Pending and recently completed file restore requests, sent whenever
+    /// the progress of any of them changes.
+    async fn restore_progress(#[graphql(ctx)] ctx: &GraphContext) -> RestoreStream {
+        let restorer = ctx.restorer.clone();
+        let mut previous: Option<u64> = None;
+        let changes = watch_state(ctx.appstate.as_ref()).filter_map(move |redux| {
+            let revision = redux.restore_revision();
+            let changed = previous != Some(revision);
+            previous = Some(revision);
+            let requests = if changed {
+                Some(restorer.requests())
+            } else {
+                None
+            };
+            async move { requests }
+        });
+        Box::pin(changes)
+    }
+}
+
+pub type Schema = RootNode<'static, QueryRoot, MutationRoot, SubscriptionRoot>;
 
 /// Create the GraphQL schema.
 pub fn create_schema() -> Schema {
-    Schema::new(QueryRoot {}, MutationRoot {}, EmptySubscription::new())
+    Schema::new(QueryRoot {}, MutationRoot {}, SubscriptionRoot {})
 }
 
 #[cfg(test)]
@@ -1601,19 +4119,60 @@ mod tests {
         let properties: HashMap<String, String> = HashMap::new();
         let stores = vec![crate::domain::entities::Store {
             id: "cafebabe".to_owned(),
-            store_type: crate::domain::entities::StoreType::LOCAL,
+            store_type: crate::domain::entities::StoreType::LOCAL,
+            label: "mylocalstore".to_owned(),
+            properties,
+        }];
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_stores()
+            .returning(move || Ok(stores.clone()));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"query {
+                stores { storeType label }
+            }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("stores").unwrap();
+        let list = res.as_list_value().unwrap();
+        assert_eq!(list.len(), 1);
+        let object = list[0].as_object_value().unwrap();
+        let field = object.get_field_value("storeType").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "local");
+        let field = object.get_field_value("label").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "mylocalstore");
+    }
+
+    #[test]
+    fn test_query_download_object_ok() {
+        // arrange
+        let store = entities::Store {
+            id: "cafebabe".to_owned(),
+            store_type: entities::StoreType::LOCAL,
             label: "mylocalstore".to_owned(),
-            properties,
-        }];
+            properties: HashMap::new(),
+        };
         let mut mock = MockEntityDataSource::new();
-        mock.expect_get_stores()
-            .returning(move || Ok(stores.clone()));
+        mock.expect_get_store()
+            .withf(|id| id == "cafebabe")
+            .returning(move |_| Ok(Some(store.clone())));
         let ctx = make_context(mock);
         // act
         let schema = create_schema();
         let (res, errors) = juniper::execute_sync(
             r#"query {
-                stores { storeType label }
+                downloadObject(storeId: "cafebabe", bucket: "b1", object: "a+b c")
             }"#,
             None,
             &schema,
@@ -1624,16 +4183,38 @@ mod tests {
         // assert
         assert_eq!(errors.len(), 0);
         let res = res.as_object_value().unwrap();
-        let res = res.get_field_value("stores").unwrap();
-        let list = res.as_list_value().unwrap();
-        assert_eq!(list.len(), 1);
-        let object = list[0].as_object_value().unwrap();
-        let field = object.get_field_value("storeType").unwrap();
+        let field = res.get_field_value("downloadObject").unwrap();
         let value = field.as_scalar_value::<String>().unwrap();
-        assert_eq!(value, "local");
-        let field = object.get_field_value("label").unwrap();
-        let value = field.as_scalar_value::<String>().unwrap();
-        assert_eq!(value, "mylocalstore");
+        assert_eq!(value, "/object/cafebabe/b1/a%2Bb%20c");
+    }
+
+    #[test]
+    fn test_mutation_upload_object_forbidden() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_store().never();
+        let datasource: Arc<dyn EntityDataSource> = Arc::new(mock);
+        let appstate = Arc::new(MockStateStore::new());
+        let processor = Arc::new(MockScheduler::new());
+        let restorer = Arc::new(MockRestorer::new());
+        let ctx = GraphContext::new(datasource, appstate, processor, restorer)
+            .with_access(DeviceScope::Restore);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation {
+                uploadObject(storeId: "cafebabe", bucket: "b1", object: "o1")
+            }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].error().message().contains("not permitted"));
     }
 
     #[test]
@@ -1687,6 +4268,33 @@ mod tests {
         assert!(errors[0].error().message().contains("oh no"));
     }
 
+    #[test]
+    fn test_query_store_types() {
+        // arrange
+        let mock = MockEntityDataSource::new();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"query { storeTypes }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("storeTypes").unwrap();
+        let list = res.as_list_value().unwrap();
+        let types: Vec<String> = list
+            .iter()
+            .map(|v| v.as_scalar_value::<String>().unwrap().to_owned())
+            .collect();
+        assert_eq!(types.contains(&"local".to_owned()), cfg!(feature = "local"));
+    }
+
     #[test]
     fn test_query_datasets_ok() {
         use crate::domain::managers::state;
@@ -2095,6 +4703,59 @@ mod tests {
         assert_eq!(value, "lorem-ipsum.txt");
     }
 
+    #[test]
+    fn test_query_browse() {
+        // arrange
+        let reference = TreeReference::SMALL(b"hello".to_vec());
+        let entry = entities::TreeEntry::new(Path::new("tiny.txt"), reference);
+        let tree = entities::Tree::new(vec![entry], 1);
+        let snapshot = entities::Snapshot::new(None, tree.digest.clone(), Default::default());
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset().returning(|_| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = "abc123".to_owned();
+            Ok(Some(dataset))
+        });
+        let latest = snapshot.digest.clone();
+        mock.expect_get_latest_snapshot()
+            .returning(move |_| Ok(Some(latest.clone())));
+        mock.expect_get_snapshot()
+            .returning(move |_| Ok(Some(snapshot.clone())));
+        mock.expect_get_tree()
+            .returning(move |_| Ok(Some(tree.clone())));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"query {
+                browse(dataset: "abc123") { path entries { name directory size } }
+            }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("browse").unwrap();
+        let res = res.as_object_value().unwrap();
+        let field = res.get_field_value("path").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "");
+        let res = res.get_field_value("entries").unwrap();
+        let list = res.as_list_value().unwrap();
+        assert_eq!(list.len(), 1);
+        let object = list[0].as_object_value().unwrap();
+        let field = object.get_field_value("name").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "tiny.txt");
+        let field = object.get_field_value("directory").unwrap();
+        let value = field.as_scalar_value::<bool>().unwrap();
+        assert!(!value);
+    }
+
     #[test]
     fn test_query_tree_none() {
         // arrange
@@ -2176,6 +4837,7 @@ mod tests {
             store_type: "local".to_owned(),
             label: "my local".to_owned(),
             properties,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2230,6 +4892,7 @@ mod tests {
             store_type: "local".to_owned(),
             label: "my local".to_owned(),
             properties,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2253,7 +4916,16 @@ mod tests {
     #[test]
     fn test_mutation_update_store_ok() {
         // arrange
+        let existing = entities::Store {
+            id: "cafebabe".to_owned(),
+            store_type: entities::StoreType::LOCAL,
+            label: "old local".to_owned(),
+            properties: HashMap::new(),
+        };
+        let etag = existing.etag();
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(existing.clone())));
         mock.expect_put_store().returning(|_| Ok(()));
         let ctx = make_context(mock);
         // act
@@ -2268,6 +4940,7 @@ mod tests {
             store_type: "local".to_owned(),
             label: "my local".to_owned(),
             properties,
+            etag: Some(etag),
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2295,6 +4968,136 @@ mod tests {
         assert_eq!(value, "my local");
     }
 
+    #[test]
+    fn test_mutation_update_store_conflict() {
+        // arrange
+        let existing = entities::Store {
+            id: "cafebabe".to_owned(),
+            store_type: entities::StoreType::LOCAL,
+            label: "changed elsewhere".to_owned(),
+            properties: HashMap::new(),
+        };
+        let current = existing.etag();
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_store()
+            .returning(move |_| Ok(Some(existing.clone())));
+        mock.expect_put_store().never();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        let input = StoreInput {
+            id: Some("cafebabe".to_owned()),
+            store_type: "local".to_owned(),
+            label: "my local".to_owned(),
+            properties: vec![],
+            etag: Some("0123456789abcdef".to_owned()),
+        };
+        vars.insert("input".to_owned(), input.to_input_value());
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Update($input: StoreInput!) {
+                updateStore(input: $input) { id }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        let error = errors[0].error();
+        assert!(error.message().contains("modified by another client"));
+        let extensions = error.extensions().as_object_value().unwrap();
+        let field = extensions.get_field_value("code").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "CONFLICT");
+        let field = extensions.get_field_value("etag").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, &current);
+    }
+
+    #[test]
+    fn test_mutation_update_store_missing() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_store().returning(|_| Ok(None));
+        mock.expect_put_store().never();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        let input = StoreInput {
+            id: Some("cafebabe".to_owned()),
+            store_type: "local".to_owned(),
+            label: "my local".to_owned(),
+            properties: vec![],
+            etag: Some("0123456789abcdef".to_owned()),
+        };
+        vars.insert("input".to_owned(), input.to_input_value());
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Update($input: StoreInput!) {
+                updateStore(input: $input) { id }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        let error = errors[0].error();
+        assert_eq!(error.message(), "no such store: cafebabe");
+        let extensions = error.extensions().as_object_value().unwrap();
+        let field = extensions.get_field_value("code").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "NO_SUCH_STORE");
+        let params = extensions.get_field_value("params").unwrap();
+        let params = params.as_object_value().unwrap();
+        let field = params.get_field_value("id").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "cafebabe");
+    }
+
+    #[test]
+    fn test_mutation_update_store_etag() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_put_store().never();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        let input = StoreInput {
+            id: Some("cafebabe".to_owned()),
+            store_type: "local".to_owned(),
+            label: "my local".to_owned(),
+            properties: vec![],
+            etag: None,
+        };
+        vars.insert("input".to_owned(), input.to_input_value());
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Update($input: StoreInput!) {
+                updateStore(input: $input) { id }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .error()
+            .message()
+            .contains("store without etag field"));
+    }
+
     #[test]
     fn test_mutation_update_store_id() {
         // arrange
@@ -2310,6 +5113,7 @@ mod tests {
             store_type: "local".to_owned(),
             label: "my local".to_owned(),
             properties,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2349,6 +5153,7 @@ mod tests {
             store_type: "local".to_owned(),
             label: "my local".to_owned(),
             properties,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2371,6 +5176,9 @@ mod tests {
     fn test_mutation_delete_store_ok() {
         // arrange
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
         mock.expect_delete_store().returning(|_| Ok(()));
         let ctx = make_context(mock);
         // act
@@ -2399,6 +5207,9 @@ mod tests {
     fn test_mutation_delete_store_err() {
         // arrange
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
         mock.expect_delete_store()
             .returning(|_| Err(anyhow!("oh no")));
         let ctx = make_context(mock);
@@ -2422,6 +5233,85 @@ mod tests {
         assert!(errors[0].error().message().contains("oh no"));
     }
 
+    #[test]
+    fn test_mutation_delete_store_in_use() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_datasets().returning(|| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = "dataset1".to_owned();
+            dataset.add_store("abc123");
+            Ok(vec![dataset])
+        });
+        mock.expect_get_trashed_datasets().returning(|| Ok(vec![]));
+        mock.expect_get_packs().returning(|_| Ok(vec![]));
+        mock.expect_delete_store().never();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("input".to_owned(), InputValue::scalar("abc123"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Delete($input: String!) {
+                deleteStore(id: $input)
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        let error = errors[0].error();
+        assert!(error.message().contains("used by 1 datasets"));
+        let extensions = error.extensions().as_object_value().unwrap();
+        let code = extensions.get_field_value("code").unwrap();
+        assert_eq!(code.as_scalar_value::<String>().unwrap(), "IN_USE");
+        let datasets = extensions.get_field_value("datasets").unwrap();
+        let datasets = datasets.as_list_value().unwrap();
+        assert_eq!(datasets.len(), 1);
+        assert_eq!(datasets[0].as_scalar_value::<String>().unwrap(), "dataset1");
+    }
+
+    #[test]
+    fn test_dataset_input_retention() {
+        let input = DatasetInput {
+            id: None,
+            basepath: "/home/planet".into(),
+            schedules: vec![],
+            workspace: None,
+            pack_size: BigInt(1048576),
+            stores: vec![],
+            excludes: vec![],
+            properties: Some(vec![
+                PropertyInput {
+                    name: "retain_count".into(),
+                    value: "10".into(),
+                },
+                PropertyInput {
+                    name: "retain_daily".into(),
+                    value: "3".into(),
+                },
+            ]),
+            retention: Some(RetentionInput {
+                count: None,
+                days: Some(30),
+                daily: Some(7),
+                weekly: Some(0),
+                monthly: Some(12),
+                yearly: None,
+            }),
+            etag: None,
+        };
+        let properties = input.property_map();
+        assert_eq!(properties.len(), 3);
+        assert_eq!(properties["retain_days"], "30");
+        assert_eq!(properties["retain_daily"], "7");
+        assert_eq!(properties["retain_monthly"], "12");
+    }
+
     #[test]
     fn test_mutation_define_dataset_ok() {
         // arrange
@@ -2448,6 +5338,9 @@ mod tests {
             pack_size: BigInt(1048576),
             stores: vec![],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2480,13 +5373,66 @@ mod tests {
     }
 
     #[test]
-    fn test_mutation_define_dataset_store() {
+    fn test_mutation_define_dataset_store() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        let config: entities::Configuration = Default::default();
+        mock.expect_get_configuration()
+            .returning(move || Ok(Some(config.clone())));
+        mock.expect_get_store().returning(|_| Ok(None));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        let cwd = std::env::current_dir().unwrap();
+        let input = DatasetInput {
+            id: None,
+            basepath: cwd.to_str().unwrap().to_owned(),
+            schedules: vec![],
+            workspace: None,
+            pack_size: BigInt(1048576),
+            stores: vec!["cafebabe".to_owned()],
+            excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: None,
+        };
+        vars.insert("input".to_owned(), input.to_input_value());
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Define($input: DatasetInput!) {
+                defineDataset(input: $input) {
+                    basepath packSize
+                }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].error().message().contains("store does not exist"));
+    }
+
+    #[test]
+    fn test_mutation_define_dataset_pack_size() {
         // arrange
         let mut mock = MockEntityDataSource::new();
         let config: entities::Configuration = Default::default();
         mock.expect_get_configuration()
             .returning(move || Ok(Some(config.clone())));
-        mock.expect_get_store().returning(|_| Ok(None));
+        mock.expect_get_store().returning(|id| {
+            let mut properties: HashMap<String, String> = HashMap::new();
+            properties.insert("storage".to_owned(), "STANDARD_IA".to_owned());
+            Ok(Some(entities::Store {
+                id: id.to_owned(),
+                store_type: entities::StoreType::AMAZON,
+                label: "infrequent".to_owned(),
+                properties,
+            }))
+        });
         let ctx = make_context(mock);
         // act
         let schema = create_schema();
@@ -2497,9 +5443,12 @@ mod tests {
             basepath: cwd.to_str().unwrap().to_owned(),
             schedules: vec![],
             workspace: None,
-            pack_size: BigInt(1048576),
+            pack_size: BigInt(65536),
             stores: vec!["cafebabe".to_owned()],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2517,7 +5466,7 @@ mod tests {
         // assert
         assert!(res.is_null());
         assert_eq!(errors.len(), 1);
-        assert!(errors[0].error().message().contains("store does not exist"));
+        assert!(errors[0].error().message().contains("minimum billable"));
     }
 
     #[test]
@@ -2542,6 +5491,9 @@ mod tests {
             pack_size: BigInt(1048576),
             stores: vec![],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2563,13 +5515,18 @@ mod tests {
     #[test]
     fn test_mutation_update_dataset_ok() {
         // arrange
+        let cwd = std::env::current_dir().unwrap();
+        let mut existing = entities::Dataset::new(&cwd);
+        existing.id = "cafebabe".to_owned();
+        let etag = existing.etag();
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(existing.clone())));
         mock.expect_put_dataset().returning(|_| Ok(()));
         let ctx = make_context(mock);
         // act
         let schema = create_schema();
         let mut vars = Variables::new();
-        let cwd = std::env::current_dir().unwrap();
         let mut ws = cwd.clone();
         ws.push(".tmp");
         let input = DatasetInput {
@@ -2580,6 +5537,9 @@ mod tests {
             pack_size: BigInt(1048576),
             stores: vec![],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: Some(etag),
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2628,6 +5588,9 @@ mod tests {
             pack_size: BigInt(1048576),
             stores: vec![],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: None,
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2651,14 +5614,19 @@ mod tests {
     #[test]
     fn test_mutation_update_dataset_err() {
         // arrange
+        let cwd = std::env::current_dir().unwrap();
+        let mut existing = entities::Dataset::new(&cwd);
+        existing.id = "cafebabe".to_owned();
+        let etag = existing.etag();
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset()
+            .returning(move |_| Ok(Some(existing.clone())));
         mock.expect_put_dataset()
             .returning(|_| Err(anyhow!("oh no")));
         let ctx = make_context(mock);
         // act
         let schema = create_schema();
         let mut vars = Variables::new();
-        let cwd = std::env::current_dir().unwrap();
         let input = DatasetInput {
             id: Some("cafebabe".to_owned()),
             basepath: cwd.to_str().unwrap().to_owned(),
@@ -2667,6 +5635,9 @@ mod tests {
             pack_size: BigInt(1048576),
             stores: vec![],
             excludes: vec![],
+            properties: None,
+            retention: None,
+            etag: Some(etag),
         };
         vars.insert("input".to_owned(), input.to_input_value());
         let (res, errors) = juniper::execute_sync(
@@ -2689,9 +5660,13 @@ mod tests {
     fn test_mutation_delete_dataset_ok() {
         // arrange
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset().returning(|id| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = id.to_owned();
+            Ok(Some(dataset))
+        });
+        mock.expect_put_trashed_dataset().returning(|_| Ok(()));
         mock.expect_delete_dataset().returning(|_| Ok(()));
-        mock.expect_delete_computer_id().returning(|_| Ok(()));
-        mock.expect_delete_latest_snapshot().returning(|_| Ok(()));
         let ctx = make_context(mock);
         // act
         let schema = create_schema();
@@ -2719,6 +5694,12 @@ mod tests {
     fn test_mutation_delete_dataset_err() {
         // arrange
         let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset().returning(|id| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = id.to_owned();
+            Ok(Some(dataset))
+        });
+        mock.expect_put_trashed_dataset().returning(|_| Ok(()));
         mock.expect_delete_dataset()
             .returning(|_| Err(anyhow!("oh no")));
         let ctx = make_context(mock);
@@ -2741,4 +5722,351 @@ mod tests {
         assert_eq!(errors.len(), 1);
         assert!(errors[0].error().message().contains("oh no"));
     }
+
+    #[test]
+    fn test_mutation_delete_dataset_force() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_delete_dataset().returning(|_| Ok(()));
+        mock.expect_delete_trashed_dataset().returning(|_| Ok(()));
+        mock.expect_delete_computer_id().returning(|_| Ok(()));
+        mock.expect_delete_latest_snapshot().returning(|_| Ok(()));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("input".to_owned(), InputValue::scalar("abc123"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Delete($input: String!) {
+                deleteDataset(id: $input, force: true)
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let field = res.get_field_value("deleteDataset").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "abc123");
+    }
+
+    #[test]
+    fn test_mutation_undelete_dataset() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_trashed_datasets().returning(|| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = "abc123".to_owned();
+            Ok(vec![entities::TrashedDataset::new(dataset)])
+        });
+        mock.expect_put_dataset().returning(|_| Ok(()));
+        mock.expect_delete_trashed_dataset().returning(|_| Ok(()));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("input".to_owned(), InputValue::scalar("abc123"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Undelete($input: String!) {
+                undeleteDataset(id: $input) { id basepath }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("undeleteDataset").unwrap();
+        let object = res.as_object_value().unwrap();
+        let field = object.get_field_value("id").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "abc123");
+    }
+
+    #[test]
+    fn test_mutation_archive_dataset() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_dataset().returning(|_| {
+            let mut dataset = entities::Dataset::new(Path::new("/home/planet"));
+            dataset.id = "abc123".to_owned();
+            Ok(Some(dataset))
+        });
+        mock.expect_put_dataset()
+            .withf(|dataset| dataset.archived.is_some())
+            .returning(|_| Ok(()));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("input".to_owned(), InputValue::scalar("abc123"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Archive($input: String!) {
+                archiveDataset(id: $input) { id archived }
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("archiveDataset").unwrap();
+        let object = res.as_object_value().unwrap();
+        let field = object.get_field_value("id").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "abc123");
+        let field = object.get_field_value("archived").unwrap();
+        assert!(!field.is_null());
+    }
+
+    #[test]
+    fn test_mutation_queue_maintenance() {
+        // arrange
+        let mock = MockEntityDataSource::new();
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation { queueMaintenance(task: "prune") }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("queueMaintenance").unwrap();
+        let list = res.as_list_value().unwrap();
+        let tasks: Vec<String> = list
+            .iter()
+            .map(|v| v.as_scalar_value::<String>().unwrap().to_owned())
+            .collect();
+        assert!(tasks.contains(&"prune".to_owned()));
+
+        // act
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation { queueMaintenance(task: "defrag") }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .error()
+            .message()
+            .contains("not a recognized maintenance task"));
+
+        // act
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation { queueMaintenance(task: "prune-snapshots") }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .error()
+            .message()
+            .contains("runs on the schedule of each dataset"));
+    }
+
+    #[test]
+    fn test_mutation_restore_device_forbidden() {
+        // arrange
+        let mock = MockEntityDataSource::new();
+        let datasource: Arc<dyn EntityDataSource> = Arc::new(mock);
+        let appstate = Arc::new(MockStateStore::new());
+        let processor = Arc::new(MockScheduler::new());
+        let restorer = Arc::new(MockRestorer::new());
+        let ctx = GraphContext::new(datasource, appstate, processor, restorer)
+            .with_access(DeviceScope::Restore);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("input".to_owned(), InputValue::scalar("abc123"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Delete($input: String!) {
+                deleteStore(id: $input)
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].error().message().contains("not permitted"));
+    }
+
+    #[test]
+    fn test_query_devices_ok() {
+        // arrange
+        let device = entities::Device::new("phone", DeviceScope::Restore, "cafebabe");
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_devices()
+            .returning(move || Ok(vec![device.clone()]));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"query { devices { name scope lastUsed } }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("devices").unwrap();
+        let list_value = res.as_list_value().unwrap();
+        assert_eq!(list_value.len(), 1);
+        let object = list_value[0].as_object_value().unwrap();
+        let field = object.get_field_value("name").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "phone");
+        let field = object.get_field_value("scope").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "restore");
+        let field = object.get_field_value("lastUsed").unwrap();
+        assert!(field.is_null());
+    }
+
+    #[test]
+    fn test_query_migrations() {
+        // arrange
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_migrations().returning(|| {
+            Ok(vec![
+                entities::Migration::new(1, "record the schema version"),
+                entities::Migration::new(2, "rewrite dataset records"),
+            ])
+        });
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let (res, errors) = juniper::execute_sync(
+            r#"query { migrations { version name applied } }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let res = res.get_field_value("migrations").unwrap();
+        let list_value = res.as_list_value().unwrap();
+        assert_eq!(list_value.len(), 2);
+        let object = list_value[1].as_object_value().unwrap();
+        let field = object.get_field_value("version").unwrap();
+        let value = field.as_scalar_value::<i32>().unwrap();
+        assert_eq!(*value, 2);
+        let field = object.get_field_value("name").unwrap();
+        let value = field.as_scalar_value::<String>().unwrap();
+        assert_eq!(value, "rewrite dataset records");
+    }
+
+    #[test]
+    fn test_mutation_revoke_device() {
+        // arrange
+        let device = entities::Device::new("phone", DeviceScope::Restore, "cafebabe");
+        let device_id = device.id.clone();
+        let expected_id = device.id.clone();
+        let mut mock = MockEntityDataSource::new();
+        mock.expect_get_devices()
+            .returning(move || Ok(vec![device.clone()]));
+        mock.expect_delete_device()
+            .withf(move |id| id == expected_id)
+            .times(1)
+            .returning(|_| Ok(()));
+        let ctx = make_context(mock);
+        // act
+        let schema = create_schema();
+        let mut vars = Variables::new();
+        vars.insert("id".to_owned(), InputValue::scalar(device_id));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Revoke($id: String!) {
+                revokeDevice(id: $id)
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        // assert
+        assert_eq!(errors.len(), 0);
+        let res = res.as_object_value().unwrap();
+        let field = res.get_field_value("revokeDevice").unwrap();
+        let value = field.as_scalar_value::<bool>().unwrap();
+        assert!(*value);
+
+        // an unknown device is an error
+        let mut vars = Variables::new();
+        vars.insert("id".to_owned(), InputValue::scalar("nosuchdevice"));
+        let (res, errors) = juniper::execute_sync(
+            r#"mutation Revoke($id: String!) {
+                revokeDevice(id: $id)
+            }"#,
+            None,
+            &schema,
+            &vars,
+            &ctx,
+        )
+        .unwrap();
+        assert!(res.is_null());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].error().message().contains("no such device"));
+    }
+
+    #[actix_rt::test]
+    async fn test_watch_state_latest_only() {
+        // arrange
+        let mut appstate = MockStateStore::new();
+        appstate.expect_subscribe().returning(|_, _| ());
+        appstate.expect_get_state().returning(state::State::default);
+        let mut changes = Box::pin(watch_state(&appstate));
+        // act
+        let mut redux = state::State::default();
+        for restorer in [
+            state::RestorerState::Starting,
+            state::RestorerState::Started,
+            state::RestorerState::Stopping,
+        ] {
+            redux.restorer = restorer;
+            broadcast_state(&redux, None);
+        }
+        // assert
+        let current = changes.next().await.unwrap();
+        assert_eq!(current.restorer, state::RestorerState::Stopped);
+        // the changes that the client did not receive in time were replaced
+        let latest = changes.next().await.unwrap();
+        assert_eq!(latest.restorer, state::RestorerState::Stopping);
+        assert!(futures::FutureExt::now_or_never(changes.next()).is_none());
+    }
 }
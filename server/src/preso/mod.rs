@@ -2,3 +2,5 @@
 // Copyright (c) 2020 Nathan Fiedler
 //
 pub mod graphql;
+pub mod recover;
+pub mod status;
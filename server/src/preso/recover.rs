@@ -0,0 +1,232 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `recover` module renders the "get my file back" page, which finds the
+//! versions of a single file across the snapshots of a dataset and offers to
+//! restore any one of them, for those who would rather not browse the trees
+//! of the snapshots in the web interface.
+//!
+//! The page is rendered by the server, without the need for any script, and
+//! restores the chosen version in place by way of the restorer, just as the
+//! `restoreFiles` mutation would.
+
+use super::status::{escape, format_time};
+use crate::domain::entities::{Dataset, FileVersion};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Parameters of the page, given in the query string.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RecoverQuery {
+    /// Identifier of the dataset containing the file.
+    pub dataset: Option<String>,
+    /// Path of the file, either absolute or relative to the dataset.
+    pub path: Option<String>,
+}
+
+/// Version of a file that is to be restored, as posted by the page.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RecoverForm {
+    /// Identifier of the dataset containing the file.
+    pub dataset: String,
+    /// Path of the file as it was entered, to show the versions again.
+    pub path: String,
+    /// Digest of the tree containing the chosen version.
+    pub tree: String,
+    /// Name of the entry within the tree.
+    pub entry: String,
+    /// Path of the file relative to the base path of the dataset.
+    pub filepath: String,
+}
+
+///
+/// Return `true` if the request came from a page served by this server, as
+/// indicated by the `Origin` and `Host` headers. Browsers send the origin
+/// along with every form post, such that a page elsewhere cannot make use of
+/// the access granted to the local host to have files overwritten.
+///
+pub fn same_origin(origin: Option<&str>, host: Option<&str>) -> bool {
+    match origin {
+        None => true,
+        Some(origin) => {
+            let authority = origin.split_once("://").map(|(_, a)| a);
+            authority.is_some() && authority == host
+        }
+    }
+}
+
+///
+/// Produce the HTML page with the form for finding a file, the versions of
+/// the file that were found (or the reason they could not be), and a notice
+/// regarding a restore that was just requested, if any.
+///
+pub fn to_html(
+    datasets: &[Dataset],
+    query: &RecoverQuery,
+    versions: Option<Result<Vec<FileVersion>, String>>,
+    notice: Option<&str>,
+) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>Get My File Back</title>\n</head>\n<body>\n<h1>Get My File Back</h1>\n"
+    ));
+    if let Some(notice) = notice {
+        html.push_str(&format!("<p><strong>{}</strong></p>\n", escape(notice)));
+    }
+    html.push_str(
+        "<form method=\"get\" action=\"/recover\">\n<p><label>Folder <select name=\"dataset\">",
+    );
+    for dataset in datasets.iter() {
+        let selected = if query.dataset.as_deref() == Some(dataset.id.as_str()) {
+            " selected"
+        } else {
+            ""
+        };
+        html.push_str(&format!(
+            "<option value=\"{}\"{}>{}</option>",
+            escape(&dataset.id),
+            selected,
+            escape(&dataset.basepath.to_string_lossy())
+        ));
+    }
+    html.push_str(&format!(
+        concat!(
+            "</select></label></p>\n<p><label>File <input type=\"text\" name=\"path\" ",
+            "size=\"60\" value=\"{}\"></label></p>\n",
+            "<p><button type=\"submit\">Find</button></p>\n</form>\n"
+        ),
+        escape(query.path.as_deref().unwrap_or_default())
+    ));
+    match versions {
+        None => (),
+        Some(Err(err)) => html.push_str(&format!("<p>{}</p>\n", escape(&err))),
+        Some(Ok(versions)) if versions.is_empty() => {
+            html.push_str("<p>No backups of that file were found.</p>\n")
+        }
+        Some(Ok(versions)) => {
+            let dataset_id = query.dataset.as_deref().unwrap_or_default();
+            let path = query.path.as_deref().unwrap_or_default();
+            let filepath = datasets
+                .iter()
+                .find(|d| d.id == dataset_id)
+                .map(|d| relative_path(path, &d.basepath))
+                .unwrap_or_else(|| path.to_owned());
+            html.push_str("<h2>Versions</h2>\n<table>\n");
+            html.push_str("<tr><th>Backed Up</th><th>Modified</th><th>Size</th><th></th></tr>\n");
+            for (index, version) in versions.iter().enumerate() {
+                let label = if index == 0 {
+                    "Restore latest"
+                } else {
+                    "Restore"
+                };
+                html.push_str(&format!(
+                    concat!(
+                        "<tr><td>{}</td><td>{}</td><td>{} bytes</td><td>",
+                        "<form method=\"post\" action=\"/recover\">",
+                        "<input type=\"hidden\" name=\"dataset\" value=\"{}\">",
+                        "<input type=\"hidden\" name=\"path\" value=\"{}\">",
+                        "<input type=\"hidden\" name=\"tree\" value=\"{}\">",
+                        "<input type=\"hidden\" name=\"entry\" value=\"{}\">",
+                        "<input type=\"hidden\" name=\"filepath\" value=\"{}\">",
+                        "<button type=\"submit\">{}</button></form></td></tr>\n"
+                    ),
+                    format_time(Some(version.snapshot_time)),
+                    format_time(Some(version.modified)),
+                    version.size,
+                    escape(dataset_id),
+                    escape(path),
+                    escape(&version.tree.to_string()),
+                    escape(&version.entry),
+                    escape(&filepath),
+                    label
+                ));
+            }
+            html.push_str("</table>\n");
+            html.push_str("<p>The chosen version replaces the file where it is now.</p>\n");
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// Produce the path of the file relative to the base path of the dataset, which
+// is the form expected by the restorer.
+fn relative_path(path: &str, basepath: &Path) -> String {
+    let path = Path::new(path);
+    path.strip_prefix(basepath)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::Checksum;
+    use chrono::prelude::*;
+
+    #[test]
+    fn test_same_origin() {
+        assert!(same_origin(None, Some("localhost:8080")));
+        assert!(same_origin(
+            Some("http://localhost:8080"),
+            Some("localhost:8080")
+        ));
+        assert!(!same_origin(
+            Some("https://example.com"),
+            Some("localhost:8080")
+        ));
+        assert!(!same_origin(Some("null"), Some("localhost:8080")));
+        assert!(!same_origin(Some("http://localhost:8080"), None));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let basepath = Path::new("/home/planet");
+        assert_eq!(
+            relative_path("/home/planet/docs/taxes.txt", basepath),
+            "docs/taxes.txt"
+        );
+        assert_eq!(relative_path("docs/taxes.txt", basepath), "docs/taxes.txt");
+    }
+
+    #[test]
+    fn test_render_versions() {
+        let dataset = Dataset::new(Path::new("/home/planet"));
+        let query = RecoverQuery {
+            dataset: Some(dataset.id.clone()),
+            path: Some("/home/planet/<notes>.txt".into()),
+        };
+        let tree = Checksum::BLAKE3("cafebabe".into());
+        let version = FileVersion {
+            snapshot: Checksum::BLAKE3("deadbeef".into()),
+            snapshot_time: Utc::now(),
+            tree: tree.clone(),
+            entry: "<notes>.txt".into(),
+            modified: Utc::now(),
+            size: 1024,
+            digest: None,
+            changed: true,
+        };
+        let versions = vec![version.clone(), version];
+        let html = to_html(
+            &[dataset.clone()],
+            &query,
+            Some(Ok(versions)),
+            Some("restore requested"),
+        );
+        assert!(html.contains("<strong>restore requested</strong>"));
+        assert!(html.contains(&format!("<option value=\"{}\" selected>", dataset.id)));
+        assert!(html.contains(&format!("name=\"tree\" value=\"{}\"", tree)));
+        assert!(html.contains("name=\"filepath\" value=\"&lt;notes&gt;.txt\""));
+        assert_eq!(html.matches("Restore latest").count(), 1);
+        let older = "<button type=\"submit\">Restore</button>";
+        assert_eq!(html.matches(older).count(), 1);
+        // nothing to show until a file has been given
+        let html = to_html(&[dataset], &RecoverQuery::default(), None, None);
+        assert!(!html.contains("<h2>Versions</h2>"));
+        let html = to_html(&[], &query, Some(Ok(vec![])), None);
+        assert!(html.contains("No backups of that file were found."));
+    }
+}
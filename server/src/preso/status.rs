@@ -0,0 +1,261 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! The `status` module renders the read-only status page, which summarizes
+//! the backups, stores, and restores for a home-lab dashboard, without the
+//! need to use the GraphQL API.
+//!
+//! The page is disabled unless either `STATUS_TOKEN` is set, in which case
+//! the request must present that token, or `STATUS_PAGE` is set to `public`,
+//! in which case no token is required. The token is accepted only in a header,
+//! never in the query string, which is recorded in access logs.
+
+use crate::domain::entities::StatusReport;
+use crate::domain::managers::replica::constant_time_eq;
+use crate::domain::managers::settings;
+use chrono::prelude::*;
+use serde_json::{json, Value};
+
+/// Header in which a dashboard that cannot send a bearer token may instead
+/// present the status token.
+pub const TOKEN_HEADER: &str = "X-Status-Token";
+
+/// Whether a request may view the status page.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// The status page has not been enabled.
+    Disabled,
+    /// The request did not present the status token.
+    Denied,
+    /// The request may view the status page.
+    Granted,
+}
+
+///
+/// Determine if the request may view the status page, given the values of the
+/// `Authorization` header and the `X-Status-Token` header, if any.
+///
+pub fn access(authorization: Option<&str>, token_header: Option<&str>) -> Access {
+    let token = settings::var("STATUS_TOKEN").ok().filter(|t| !t.is_empty());
+    let public = settings::var("STATUS_PAGE")
+        .map(|v| v.eq_ignore_ascii_case("public"))
+        .unwrap_or(false);
+    check_access(token.as_deref(), public, authorization, token_header)
+}
+
+// Decide access based on the configured token and the credentials, if any,
+// that were presented by the request.
+fn check_access(
+    token: Option<&str>,
+    public: bool,
+    authorization: Option<&str>,
+    token_header: Option<&str>,
+) -> Access {
+    let Some(token) = token else {
+        return if public {
+            Access::Granted
+        } else {
+            Access::Disabled
+        };
+    };
+    let given = authorization
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .or(token_header);
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Access::Granted,
+        _ => Access::Denied,
+    }
+}
+
+///
+/// Produce the JSON form of the status report.
+///
+pub fn to_json(report: &StatusReport) -> Value {
+    let datasets: Vec<Value> = report
+        .datasets
+        .iter()
+        .map(|d| {
+            json!({
+                "id": d.id,
+                "basepath": d.basepath.to_string_lossy(),
+                "backup": d.backup,
+                "lastSuccess": d.last_success.map(|t| t.to_rfc3339()),
+                "error": d.error,
+            })
+        })
+        .collect();
+    let stores: Vec<Value> = report
+        .stores
+        .iter()
+        .map(|s| {
+            json!({
+                "id": s.id,
+                "label": s.label,
+                "storeType": s.store_type.to_string(),
+                "healthy": s.healthy,
+                "lastError": s.last_error,
+            })
+        })
+        .collect();
+    let health_check = report.health_check.as_ref().map(|r| {
+        json!({
+            "finished": r.finished.to_rfc3339(),
+            "summary": r.summary,
+            "error": r.error,
+        })
+    });
+    json!({
+        "generated": report.generated.to_rfc3339(),
+        "datasets": datasets,
+        "stores": stores,
+        "restores": {
+            "pending": report.pending_restores,
+            "failed": report.failed_restores,
+        },
+        "healthCheck": health_check,
+    })
+}
+
+///
+/// Produce a simple HTML page showing the status report.
+///
+pub fn to_html(report: &StatusReport) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>Zorigami Status</title>\n</head>\n<body>\n<h1>Zorigami Status</h1>\n"
+    ));
+    html.push_str(&format!(
+        "<p>Generated {}</p>\n",
+        format_time(Some(report.generated))
+    ));
+    html.push_str("<h2>Datasets</h2>\n<table>\n");
+    html.push_str("<tr><th>Path</th><th>Backup</th><th>Last Success</th><th>Error</th></tr>\n");
+    for dataset in report.datasets.iter() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&dataset.basepath.to_string_lossy()),
+            escape(&dataset.backup),
+            format_time(dataset.last_success),
+            escape(dataset.error.as_deref().unwrap_or_default())
+        ));
+    }
+    html.push_str("</table>\n<h2>Stores</h2>\n<table>\n");
+    html.push_str("<tr><th>Label</th><th>Type</th><th>Health</th><th>Error</th></tr>\n");
+    for store in report.stores.iter() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&store.label),
+            store.store_type.to_string(),
+            if store.healthy { "ok" } else { "failing" },
+            escape(store.last_error.as_deref().unwrap_or_default())
+        ));
+    }
+    html.push_str("</table>\n");
+    if let Some(result) = report.health_check.as_ref() {
+        let outcome = result.error.as_ref().unwrap_or(&result.summary);
+        html.push_str(&format!(
+            "<p>Health check at {}: {}</p>\n",
+            format_time(Some(result.finished)),
+            escape(outcome)
+        ));
+    }
+    html.push_str(&format!(
+        "<h2>Restores</h2>\n<p>{} pending, {} failed</p>\n</body>\n</html>\n",
+        report.pending_restores, report.failed_restores
+    ));
+    html
+}
+
+// Format the date/time in the local time zone, if any.
+pub(crate) fn format_time(value: Option<DateTime<Utc>>) -> String {
+    match value {
+        Some(value) => {
+            let local: DateTime<Local> = value.into();
+            local.format("%Y-%m-%d %H:%M").to_string()
+        }
+        None => String::from("never"),
+    }
+}
+
+// Escape the characters that are significant in HTML.
+pub(crate) fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::entities::{DatasetStatus, StoreStatus, StoreType};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_check_access() {
+        assert_eq!(check_access(None, false, None, None), Access::Disabled);
+        assert_eq!(
+            check_access(None, false, Some("Bearer abc"), None),
+            Access::Disabled
+        );
+        assert_eq!(check_access(None, true, None, None), Access::Granted);
+        assert_eq!(check_access(Some("abc"), true, None, None), Access::Denied);
+        assert_eq!(
+            check_access(Some("abc"), false, Some("Bearer abc"), None),
+            Access::Granted
+        );
+        assert_eq!(
+            check_access(Some("abc"), false, None, Some("abc")),
+            Access::Granted
+        );
+        assert_eq!(
+            check_access(Some("abc"), false, Some("Bearer xyz"), Some("xyz")),
+            Access::Denied
+        );
+    }
+
+    #[test]
+    fn test_render_report() {
+        let report = StatusReport {
+            generated: Utc::now(),
+            datasets: vec![DatasetStatus {
+                id: "dataset1".into(),
+                basepath: PathBuf::from("/home/planet"),
+                backup: "failed".into(),
+                last_success: None,
+                error: Some("<disk full>".into()),
+            }],
+            stores: vec![StoreStatus {
+                id: "local123".into(),
+                label: "usb disk".into(),
+                store_type: StoreType::LOCAL,
+                healthy: true,
+                last_error: None,
+            }],
+            pending_restores: 2,
+            failed_restores: 0,
+            health_check: None,
+        };
+        let value = to_json(&report);
+        assert_eq!(value["datasets"][0]["backup"], "failed");
+        assert!(value["datasets"][0]["lastSuccess"].is_null());
+        assert_eq!(value["stores"][0]["storeType"], "local");
+        assert_eq!(value["stores"][0]["healthy"], true);
+        assert_eq!(value["restores"]["pending"], 2);
+        assert!(value["healthCheck"].is_null());
+        let html = to_html(&report);
+        assert!(html.contains("<td>/home/planet</td>"));
+        assert!(html.contains("&lt;disk full&gt;"));
+        assert!(html.contains("<td>never</td>"));
+        assert!(html.contains("2 pending, 0 failed"));
+    }
+}
@@ -2,11 +2,14 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 use anyhow::Error;
+use server::data::repositories::RecordRepositoryImpl;
 use server::data::sources::{EntityDataSource, EntityDataSourceImpl};
 use server::domain::entities::{self, Checksum};
+use server::domain::repositories::RecordRepository;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[test]
 fn test_insert_get_chunk() -> Result<(), Error> {
@@ -136,6 +139,20 @@ fn test_insert_get_pack() -> Result<(), Error> {
     assert_eq!(packs[0].digest, digest5);
     assert_eq!(packs[1].digest, digest2);
     assert_eq!(packs[2].digest, digest1);
+
+    // test visit_packs(), which goes in key order and may stop early
+    let mut visited: Vec<Checksum> = Vec::new();
+    datasource.visit_packs(&mut |pack| {
+        visited.push(pack.digest);
+        Ok(visited.len() < 2)
+    })?;
+    assert_eq!(visited, vec![digest5, digest2]);
+    let mut count = 0;
+    datasource.visit_packs(&mut |_| {
+        count += 1;
+        Ok(true)
+    })?;
+    assert_eq!(count, 5);
     Ok(())
 }
 
@@ -267,6 +284,63 @@ fn test_put_get_delete_datasets() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_put_get_delete_trashed_datasets() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    // trashed datasets are kept apart from the active datasets
+    let dataset = entities::Dataset::new(Path::new("/home/planet"));
+    let trashed = entities::TrashedDataset::new(dataset);
+    datasource.put_trashed_dataset(&trashed).unwrap();
+    assert!(datasource.get_datasets().unwrap().is_empty());
+
+    let actual = datasource.get_trashed_datasets().unwrap();
+    assert_eq!(actual.len(), 1);
+    assert_eq!(actual[0].dataset.id, trashed.dataset.id);
+    assert_eq!(actual[0].dataset.basepath, trashed.dataset.basepath);
+    assert_eq!(
+        actual[0].deleted.timestamp_millis(),
+        trashed.deleted.timestamp_millis()
+    );
+
+    datasource
+        .delete_trashed_dataset(&trashed.dataset.id)
+        .unwrap();
+    assert!(datasource.get_trashed_datasets().unwrap().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_put_get_delete_devices() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    let phone = entities::Device::new("phone", entities::DeviceScope::Restore, "cafebabe");
+    datasource.put_device(&phone).unwrap();
+    let mut tablet = entities::Device::new("tablet", entities::DeviceScope::Full, "deadbeef");
+    tablet.last_used = Some(chrono::Utc::now());
+    datasource.put_device(&tablet).unwrap();
+
+    let devices = datasource.get_devices().unwrap();
+    assert_eq!(devices.len(), 2);
+    let actual = devices.iter().find(|d| d.id == phone.id).unwrap();
+    assert_eq!(actual, &phone);
+    let actual = devices.iter().find(|d| d.id == tablet.id).unwrap();
+    assert_eq!(actual.scope, entities::DeviceScope::Full);
+    assert!(actual.last_used.is_some());
+
+    datasource.delete_device(&phone.id).unwrap();
+    let devices = datasource.get_devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].id, tablet.id);
+    Ok(())
+}
+
 #[test]
 fn test_put_get_configuration() -> Result<(), Error> {
     let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
@@ -285,6 +359,127 @@ fn test_put_get_configuration() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_schema_migrations() -> Result<(), Error> {
+    use server::domain::managers::migrate;
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+
+    // a new database has no schema version and all migrations are applied
+    assert!(repo.get_schema_version()?.is_none());
+    let applied = migrate::self_check(&repo)?;
+    assert_eq!(applied.len() as u32, migrate::SCHEMA_VERSION);
+    assert_eq!(repo.get_schema_version()?, Some(migrate::SCHEMA_VERSION));
+    let migrations = repo.get_migrations()?;
+    assert_eq!(migrations, applied);
+
+    // nothing more to be done the second time
+    let applied = migrate::self_check(&repo)?;
+    assert!(applied.is_empty());
+
+    // a database from a newer version is refused
+    repo.put_schema_version(migrate::SCHEMA_VERSION + 1)?;
+    assert!(migrate::self_check(&repo).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_put_get_delete_events() -> Result<(), Error> {
+    use chrono::{TimeDelta, Utc};
+    use server::domain::entities::{Event, EventKind};
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+
+    // events are returned oldest first, regardless of insertion order
+    let mut started = Event::new(EventKind::BackupStarted, "dataset1");
+    started.time = Utc::now() - TimeDelta::minutes(5);
+    let finished = Event::new(EventKind::BackupFinished, "dataset1").detail("snapshot", "cafebabe");
+    repo.put_event(&finished)?;
+    repo.put_event(&started)?;
+    let events = repo.get_events()?;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].id, started.id);
+    assert_eq!(events[0].kind, EventKind::BackupStarted);
+    assert_eq!(events[1].id, finished.id);
+    assert_eq!(events[1].details["snapshot"], "cafebabe");
+
+    repo.delete_event(&started.id)?;
+    let events = repo.get_events()?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].id, finished.id);
+    Ok(())
+}
+
+#[test]
+fn test_put_get_chain_entries() -> Result<(), Error> {
+    use server::domain::entities::{ChainEntry, PackLocation};
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+
+    // entries are kept separately for each store, in order
+    let snapshot = Checksum::SHA1("b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned());
+    let mut entries: Vec<ChainEntry> = Vec::new();
+    for _ in 0..12 {
+        let entry = ChainEntry::new("store1", entries.last(), snapshot.clone());
+        repo.put_chain_entry(&entry)?;
+        entries.push(entry);
+    }
+    let other = ChainEntry::new("store2", None, snapshot);
+    repo.put_chain_entry(&other)?;
+    let actual = repo.get_chain_entries("store1")?;
+    assert_eq!(actual, entries);
+    let actual = repo.get_chain_entries("store2")?;
+    assert_eq!(actual, vec![other]);
+    assert!(repo.get_chain_entries("store3")?.is_empty());
+
+    // only the latest location of the log in each store is kept
+    repo.put_chain_location(&PackLocation::new("store1", "bucket1", "snapshots.log"))?;
+    repo.put_chain_location(&PackLocation::new("store1", "bucket2", "snapshots.log"))?;
+    repo.put_chain_location(&PackLocation::new("store2", "bucket1", "snapshots.log"))?;
+    let mut locations = repo.get_chain_locations()?;
+    locations.sort_by(|a, b| a.store.cmp(&b.store));
+    assert_eq!(locations.len(), 2);
+    assert_eq!(locations[0].bucket, "bucket2");
+    assert_eq!(locations[1].store, "store2");
+    Ok(())
+}
+
+#[test]
+fn test_put_get_delete_webhooks() -> Result<(), Error> {
+    use server::domain::entities::schedule::TimeRange;
+    use server::domain::entities::Webhook;
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+
+    assert!(repo.get_webhooks()?.is_empty());
+    let global = Webhook::new("https://example.com/hook");
+    let mut local = Webhook::new("http://localhost:8080/notify");
+    local.dataset = Some("dataset1".into());
+    local.stale_hours = Some(48);
+    local.quiet_hours = Some(TimeRange::new(22, 0, 6, 0));
+    local.digest = true;
+    repo.put_webhook(&global)?;
+    repo.put_webhook(&local)?;
+    let mut webhooks = repo.get_webhooks()?;
+    webhooks.sort_by(|a, b| a.url.cmp(&b.url));
+    assert_eq!(webhooks, vec![local.clone(), global.clone()]);
+    repo.delete_webhook(&local.id)?;
+    assert_eq!(repo.get_webhooks()?, vec![global]);
+    Ok(())
+}
+
 #[test]
 fn test_put_get_computer_id() -> Result<(), Error> {
     let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
@@ -326,6 +521,67 @@ fn test_put_get_latest_snapshot() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_put_get_bandwidth() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    let mut usage = entities::BandwidthUsage::new("2024-05");
+    usage.uploaded = 1_048_576;
+    usage.downloaded = 4_096;
+    datasource.put_bandwidth("cafebabe", &usage).unwrap();
+    let opt = datasource.get_bandwidth("cafebabe", "2024-06").unwrap();
+    assert!(opt.is_none());
+    let opt = datasource.get_bandwidth("deadbeef", "2024-05").unwrap();
+    assert!(opt.is_none());
+    let opt = datasource.get_bandwidth("cafebabe", "2024-05").unwrap();
+    assert_eq!(opt, Some(usage));
+    Ok(())
+}
+
+#[test]
+fn test_put_get_store_usage() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    let opt = datasource.get_store_usage("cafebabe").unwrap();
+    assert!(opt.is_none());
+    let usage = entities::StoreUsage {
+        objects: 12,
+        bytes: 786_432,
+    };
+    datasource.put_store_usage("cafebabe", &usage).unwrap();
+    let opt = datasource.get_store_usage("cafebabe").unwrap();
+    assert_eq!(opt, Some(usage));
+    Ok(())
+}
+
+#[test]
+fn test_put_get_delete_checkpoint() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    let location = entities::PackLocation::new("cafebabe", "bucket1", "object1");
+    let mut checkpoint = store_core::Checkpoint::new("3129-cafebabe", "upload-1");
+    checkpoint.add_part("etag-1", 8_388_608);
+    datasource.put_checkpoint(&location, &checkpoint).unwrap();
+    let other = entities::PackLocation::new("cafebabe", "bucket1", "object2");
+    let opt = datasource.get_checkpoint(&other).unwrap();
+    assert!(opt.is_none());
+    let opt = datasource.get_checkpoint(&location).unwrap();
+    assert_eq!(opt, Some(checkpoint));
+    datasource.delete_checkpoint(&location).unwrap();
+    let opt = datasource.get_checkpoint(&location).unwrap();
+    assert!(opt.is_none());
+    Ok(())
+}
+
 #[test]
 fn test_insert_get_file() -> Result<(), Error> {
     let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
@@ -505,6 +761,26 @@ fn test_record_counts() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn test_compact_database() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+
+    // records that were deleted remain so after compaction
+    let digest = Checksum::SHA1(String::from("bc1a3198db79036e56b30f0ab307cee55e845907"));
+    let coords = vec![entities::PackLocation::new("store1", "bucket1", "object1")];
+    let pack = entities::Pack::new(digest.clone(), coords);
+    datasource.insert_pack(&pack)?;
+    datasource.put_computer_id("cafebabe", "charlietuna")?;
+    datasource.delete_computer_id("cafebabe")?;
+    datasource.compact_database()?;
+    assert!(datasource.get_computer_id("cafebabe")?.is_none());
+    assert!(datasource.get_pack(&digest)?.is_some());
+    Ok(())
+}
+
 #[test]
 fn test_backup_restore() -> Result<(), Error> {
     let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
@@ -536,3 +812,61 @@ fn test_backup_restore() -> Result<(), Error> {
     let _ = std::fs::remove_dir_all(backup_path);
     Ok(())
 }
+
+#[test]
+fn test_restore_newer_schema() -> Result<(), Error> {
+    use server::domain::managers::migrate;
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    datasource.put_schema_version(migrate::SCHEMA_VERSION + 1)?;
+    datasource.put_computer_id("charlie", "localhost")?;
+    let backup_path = tempfile::tempdir_in(&db_base)?;
+    datasource.create_backup(Some(backup_path.path().to_path_buf()))?;
+    datasource.put_schema_version(migrate::SCHEMA_VERSION)?;
+    datasource.put_computer_id("charlie", "remotehost")?;
+
+    // the backup is refused and the current database is left as it was
+    let result = datasource.restore_from_backup(Some(backup_path.path().to_path_buf()));
+    let err = result.unwrap_err();
+    assert!(err.downcast_ref::<migrate::SchemaTooNewError>().is_some());
+    assert_eq!(
+        datasource.get_schema_version()?,
+        Some(migrate::SCHEMA_VERSION)
+    );
+    assert_eq!(
+        datasource.get_computer_id("charlie")?,
+        Some("remotehost".into())
+    );
+    let _ = std::fs::remove_dir_all(backup_path);
+    Ok(())
+}
+
+#[test]
+fn test_open_archive() -> Result<(), Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(&db_path).unwrap();
+    assert!(datasource.put_computer_id("charlie", "localhost").is_ok());
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+    let archive = repo.create_backup("Secret123")?;
+
+    // modify the live database
+    assert!(repo.put_computer_id("charlie", "remotehost").is_ok());
+
+    // the copy reflects the database at the time of the backup
+    let workdir = tempfile::tempdir_in(&db_base)?;
+    let copy = RecordRepositoryImpl::open_archive(&archive, "Secret123", workdir.path())?;
+    let value = copy.get_computer_id("charlie")?;
+    assert_eq!(value, Some("localhost".into()));
+    let value = repo.get_computer_id("charlie")?;
+    assert_eq!(value, Some("remotehost".into()));
+
+    // wrong passphrase is an error
+    let workdir = tempfile::tempdir_in(&db_base)?;
+    let result = RecordRepositoryImpl::open_archive(&archive, "Wrong123", workdir.path());
+    assert!(result.is_err());
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Exercise the backup and restore procedures against the in-memory pack
+//! store, injecting the faults that a remote store would produce. Run with
+//! `cargo test -p server --features memory`.
+
+#![cfg(feature = "memory")]
+
+use anyhow::Error;
+use server::data::repositories::RecordRepositoryImpl;
+use server::data::sources::EntityDataSourceImpl;
+use server::domain::entities::{self, Checksum};
+use server::domain::managers::backup::{self, Performer, PerformerImpl};
+use server::domain::managers::restore::*;
+use server::domain::managers::state::{StateStore, StateStoreImpl};
+use server::domain::repositories::RecordRepository;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use store_core::memory::MemoryStore;
+use tempfile::TempDir;
+
+fn file_restorer_factory(dbase: Arc<dyn RecordRepository>) -> Box<dyn FileRestorer> {
+    Box::new(FileRestorerImpl::new(dbase))
+}
+
+// Database, store, and dataset used by a test, whose directories are removed
+// when it is dropped.
+struct Fixture {
+    dbase: Arc<dyn RecordRepository>,
+    dataset: entities::Dataset,
+    memory: MemoryStore,
+    _dirs: Vec<TempDir>,
+}
+
+// Create a database, a memory store with the given identifier, and a dataset
+// that saves its packs to that store, containing a copy of the lorem ipsum
+// text.
+fn setup(store_id: &str) -> Result<Fixture, Error> {
+    let db_base: PathBuf = ["tmp", "test", "database"].iter().collect();
+    fs::create_dir_all(&db_base)?;
+    let db_path = tempfile::tempdir_in(&db_base)?;
+    let datasource = EntityDataSourceImpl::new(db_path.path())?;
+    let repo = RecordRepositoryImpl::new(Arc::new(datasource));
+    let dbase: Arc<dyn RecordRepository> = Arc::new(repo);
+
+    // start with an empty store, in case the tests are run again
+    let memory = MemoryStore::new(store_id, &HashMap::new())?;
+    memory.clear();
+    let store = entities::Store {
+        id: store_id.to_owned(),
+        store_type: entities::StoreType::MEMORY,
+        label: "in memory".to_owned(),
+        properties: HashMap::new(),
+    };
+    dbase.put_store(&store)?;
+
+    let fixture_base: PathBuf = ["tmp", "test", "fixtures"].iter().collect();
+    fs::create_dir_all(&fixture_base)?;
+    let fixture_path = tempfile::tempdir_in(&fixture_base)?;
+    let mut dataset = entities::Dataset::new(fixture_path.path());
+    dataset.add_store(store_id);
+    dataset.pack_size = 131072 as u64;
+    dbase.put_dataset(&dataset)?;
+    let computer_id = entities::Configuration::generate_unique_id("charlie", "horse");
+    dbase.put_computer_id(&dataset.id, &computer_id)?;
+    let dest: PathBuf = fixture_path.path().join("lorem-ipsum.txt");
+    fs::copy("../test/fixtures/lorem-ipsum.txt", dest)?;
+    Ok(Fixture {
+        dbase,
+        dataset,
+        memory,
+        _dirs: vec![db_path, fixture_path],
+    })
+}
+
+// Restore the lorem ipsum text from the snapshot, returning the request once
+// the restorer has finished with it.
+fn restore_lorem(
+    dbase: Arc<dyn RecordRepository>,
+    dataset: &entities::Dataset,
+    snapshot: &Checksum,
+) -> Result<Request, Error> {
+    let snapshot = dbase.get_snapshot(snapshot)?.unwrap();
+    let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+    let sut = RestorerImpl::new(state, file_restorer_factory);
+    sut.start(dbase)?;
+    sut.enqueue(Request::new(
+        snapshot.tree,
+        String::from("lorem-ipsum.txt"),
+        PathBuf::from("restored.txt"),
+        dataset.id.to_owned(),
+        "keyboard cat".into(),
+    ))?;
+    sut.wait_for_completed();
+    let mut requests = sut.requests();
+    sut.stop()?;
+    assert_eq!(requests.len(), 1);
+    Ok(requests.remove(0))
+}
+
+#[actix_rt::test]
+#[serial_test::serial]
+async fn test_memory_failed_upload() -> Result<(), Error> {
+    let Fixture {
+        dbase,
+        dataset,
+        memory,
+        _dirs,
+    } = setup("memory-retry")?;
+
+    // the first upload fails, and the backup carries on regardless
+    memory.fail_upload(1);
+    let performer = PerformerImpl::default();
+    let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+    let request = backup::Request::new(dataset.clone(), dbase.clone(), state, "keyboard cat", None);
+    let snapshot = performer.backup(request)?.unwrap();
+    let counts = dbase.get_entity_counts()?;
+    assert_eq!(counts.pack, 1);
+    // the failed attempt was followed by at least one more
+    assert!(memory.upload_count() >= 2);
+
+    // the content that was uploaded on the second attempt is intact
+    let request = restore_lorem(dbase, &dataset, &snapshot)?;
+    assert!(request.error_msg.is_none());
+    let outfile = dataset.basepath.join("restored.txt");
+    let digest_expected = Checksum::BLAKE3(String::from(
+        "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
+    ));
+    assert_eq!(Checksum::blake3_from_file(&outfile)?, digest_expected);
+    actix::System::current().stop();
+    Ok(())
+}
+
+#[actix_rt::test]
+#[serial_test::serial]
+async fn test_memory_corrupt_pack() -> Result<(), Error> {
+    let Fixture {
+        dbase,
+        dataset,
+        memory,
+        _dirs,
+    } = setup("memory-corrupt")?;
+    let performer = PerformerImpl::default();
+    let state: Arc<dyn StateStore> = Arc::new(StateStoreImpl::new());
+    let request = backup::Request::new(dataset.clone(), dbase.clone(), state, "keyboard cat", None);
+    let snapshot = performer.backup(request)?.unwrap();
+
+    // damage the one pack in the store, as bit rot might
+    let packs = dbase.get_packs(&dataset.stores[0])?;
+    assert_eq!(packs.len(), 1);
+    let location = &packs[0].locations[0];
+    memory.corrupt_object(&location.bucket, &location.object)?;
+
+    // the restore reports the damaged pack rather than restoring bad data
+    let request = restore_lorem(dbase, &dataset, &snapshot)?;
+    assert!(request.error_msg.is_some());
+    let outfile = dataset.basepath.join("restored.txt");
+    assert!(!outfile.exists());
+    actix::System::current().stop();
+    Ok(())
+}
@@ -79,7 +79,7 @@ async fn test_process_manager_async_store() -> Result<(), Error> {
     let processor = SchedulerImpl::new(state.clone(), performer).interval(100);
     let result = processor.start(dbase.clone());
     assert!(result.is_ok());
-    // n.b. If the tests seem to be hanging here, check that the store_minio
+    // n.b. If the tests seem to be hanging here, check that the store_s3
     // tests are passing, there could be an issue with the access keys; be sure
     // to define new access keys if the minio docker container is rebuilt.
     state.wait_for_backup(BackupAction::Finish(dataset.id.clone()));
@@ -88,14 +88,9 @@ async fn test_process_manager_async_store() -> Result<(), Error> {
     let maybe_snapshot = dbase.get_latest_snapshot(&dataset.id)?;
     assert!(maybe_snapshot.is_some(), "latest snapshot not available");
     let snapshot_sha1 = maybe_snapshot.unwrap();
-    #[cfg(target_family = "unix")]
     let digest_expected = Checksum::BLAKE3(String::from(
         "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
     ));
-    #[cfg(target_family = "windows")]
-    let digest_expected = Checksum::BLAKE3(String::from(
-        "2720a91db93dae2a92ed9f74b0f7a135cfdf4d32dd069477cda457002ffc9e7a",
-    ));
     let snapshot = dbase.get_snapshot(&snapshot_sha1)?.unwrap();
     let restorer = RestorerImpl::new(state, file_restorer_factory);
     let result = restorer.start(dbase.clone());
@@ -119,7 +114,7 @@ async fn test_process_manager_async_store() -> Result<(), Error> {
     assert_eq!(digest_expected, digest_actual);
 
     // Ideally would iterate pack records in database and delete the pack files
-    // from minio, but eventually the store_minio tests will run and clean up
+    // from minio, but eventually the store_s3 tests will run and clean up
     // everything anyway.
 
     // shutdown the restorer supervisor to release the database lock
@@ -131,14 +131,9 @@ async fn test_backup_restore() -> Result<(), Error> {
     assert_eq!(counts.tree, 4);
 
     // restore the file from the first snapshot
-    #[cfg(target_family = "unix")]
     let digest_expected = Checksum::BLAKE3(String::from(
         "deb7853b5150885d2f6bda99b252b97104324fe3ecbf737f89d6cd8c781d1128",
     ));
-    #[cfg(target_family = "windows")]
-    let digest_expected = Checksum::BLAKE3(String::from(
-        "2720a91db93dae2a92ed9f74b0f7a135cfdf4d32dd069477cda457002ffc9e7a",
-    ));
     let snapshot = dbase.get_snapshot(&first_backup)?.unwrap();
     let sut = RestorerImpl::new(state, file_restorer_factory);
     let result = sut.start(dbase.clone());
@@ -184,14 +179,9 @@ async fn test_backup_restore() -> Result<(), Error> {
     assert_eq!(digest_expected, digest_actual);
 
     // restore the file from the third snapshot
-    #[cfg(target_family = "unix")]
     let digest_expected = Checksum::BLAKE3(String::from(
         "540c45803112958ab53e31daee5eec067b1442d579eb1e787cf7684657275b60",
     ));
-    #[cfg(target_family = "windows")]
-    let digest_expected = Checksum::BLAKE3(String::from(
-        "183d52ff928be3e77cccf1b78b12b31910d5079195a637a9a2b499059f99b781",
-    ));
     sut.reset_completed();
     let snapshot = dbase.get_snapshot(&third_backup)?.unwrap();
     let result = sut.enqueue(Request::new(
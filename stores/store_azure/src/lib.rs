@@ -2,17 +2,51 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 use anyhow::{anyhow, Error};
-use azure_core::{RetryOptions, StatusCode};
+use async_trait::async_trait;
+use azure_core::auth::TokenCredential;
+use azure_core::{RetryOptions, StatusCode, TransportOptions};
+use azure_identity::{DefaultAzureCredential, TokenCredentialOptions};
 use azure_storage::{CloudLocation, ErrorKind, StorageCredentials};
 use azure_storage_blobs::prelude::{
-    AccessTier, BlobBlockType, BlockId, BlockList, ClientBuilder, PublicAccess,
+    AccessTier, BlobBlockType, BlobClient, BlobContentMD5, BlockId, BlockList, ClientBuilder,
+    PublicAccess,
 };
 use futures::StreamExt;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use store_core::Coordinates;
+use std::sync::Arc;
+use std::time::Duration;
+use store_core::{
+    AsyncPackDataSource, Checkpoint, CheckpointStore, Coordinates, PackDataSource, Secret,
+    Throttle, TimeoutError, Timeouts,
+};
+
+///
+/// Means by which the store authenticates with the storage account.
+///
+#[derive(Clone)]
+enum Credential {
+    /// Shared key of the storage account.
+    AccessKey(Secret),
+    /// Shared access signature granting access to the account or container.
+    SasToken(Secret),
+    /// Azure AD credential found in the environment, such as the managed
+    /// identity of the virtual machine or the workload identity in AKS.
+    Identity(Arc<dyn TokenCredential>),
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::AccessKey(_) => write!(f, "AccessKey"),
+            Credential::SasToken(_) => write!(f, "SasToken"),
+            Credential::Identity(_) => write!(f, "Identity"),
+        }
+    }
+}
 
 ///
 /// A pack store implementation that uses Azure blob storage.
@@ -21,47 +55,63 @@ use store_core::Coordinates;
 pub struct AzureStore {
     store_id: String,
     account: String,
-    access_key: String,
+    credential: Credential,
     access_tier: Option<AccessTier>,
     custom_uri: Option<String>,
     retry_options: Option<RetryOptions>,
+    checkpoints: Option<Arc<dyn CheckpointStore>>,
+    throttle: Option<Arc<Throttle>>,
+    timeouts: Timeouts,
 }
 
 impl AzureStore {
     /// Validate the given store and construct a azure pack source.
+    ///
+    /// The store authenticates using the `access_key` property if given,
+    /// otherwise the `sas_token` property, otherwise the `credential` property
+    /// must be set to `default` to use the Azure AD credential found in the
+    /// environment (e.g. a managed identity).
+    ///
+    /// The `connect_timeout` applies to establishing each connection, the
+    /// `read_timeout` to waiting for each portion of a download, the
+    /// `write_timeout` to the upload of each block, and the `operation_timeout`
+    /// to each store operation as a whole.
     pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
         let account = props
             .get("account")
             .ok_or_else(|| anyhow!("missing account property"))?;
-        let access_key = props
-            .get("access_key")
-            .ok_or_else(|| anyhow!("missing access_key property"))?;
+        let credential = select_credential(props)?;
         let custom_uri = props.get("custom_uri");
-        let access_tier = props.get("access_tier").and_then(|tier| {
-            if tier.to_lowercase() == "hot" {
-                Some(AccessTier::Hot)
-            } else if tier.to_lowercase() == "cool" {
-                Some(AccessTier::Cool)
-            } else if tier.to_lowercase() == "archive" {
-                Some(AccessTier::Archive)
-            } else {
-                None
-            }
-        });
+        let access_tier = props.get("access_tier").and_then(|t| parse_access_tier(t));
         Ok(Self {
             store_id: store_id.to_owned(),
             account: account.to_owned(),
-            access_key: access_key.to_owned(),
+            credential,
             custom_uri: custom_uri.cloned(),
             access_tier,
             retry_options: None,
+            checkpoints: None,
+            throttle: Throttle::from_properties(store_id, props)?,
+            timeouts: Timeouts::from_properties(props)?,
         })
     }
 
-    fn connect(&self) -> ClientBuilder {
+    /// Record the blocks uploaded so far using the given checkpoint store, such
+    /// that an interrupted upload will be resumed rather than restarted.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<dyn CheckpointStore>) -> Self {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    fn connect(&self) -> Result<ClientBuilder, Error> {
         let account = self.account.clone();
-        let access_key = self.access_key.clone();
-        let credentials = StorageCredentials::access_key(account.clone(), access_key);
+        let credentials = match &self.credential {
+            Credential::AccessKey(key) => {
+                StorageCredentials::access_key(account.clone(), key.expose().to_owned())
+            }
+            Credential::SasToken(token) => StorageCredentials::sas_token(token.expose())?,
+            Credential::Identity(cred) => StorageCredentials::token_credential(cred.clone()),
+        };
         let mut cb = if let Some(uri) = &self.custom_uri {
             let location = CloudLocation::Custom {
                 account,
@@ -74,7 +124,12 @@ impl AzureStore {
         if let Some(ref retry) = self.retry_options {
             cb = cb.retry(retry.to_owned());
         }
-        cb
+        if let Some(limit) = self.timeouts.connect {
+            // the default transport waits as long as the operating system
+            let client = reqwest::Client::builder().connect_timeout(limit).build()?;
+            cb = cb.transport(TransportOptions::new(Arc::new(client)));
+        }
+        Ok(cb)
     }
 
     pub fn store_pack_sync(
@@ -84,7 +139,12 @@ impl AzureStore {
         object: &str,
     ) -> Result<Coordinates, Error> {
         // use and_then(std::convert::identity) until Result.flatten() is stable
-        block_on(self.store_pack(packfile, bucket, object)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "store_pack",
+            self.store_pack(packfile, bucket, object),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn store_pack(
@@ -94,19 +154,54 @@ impl AzureStore {
         object: &str,
     ) -> Result<Coordinates, Error> {
         // the container must exist before receiving blobs
-        let builder = self.connect();
+        let builder = self.connect()?;
         create_container(builder, bucket).await?;
         //
         // Process the pack file by uploading it in 8mb chunks as "blocks", then
         // assembling the "blob" from the list of blocks ("block list"). Blocks
         // larger than 5mb will benefit from the "high throughput" feature of
-        // the Azure storage API.
+        // the Azure storage API. A blob is made of at most 50,000 blocks, so
+        // larger blocks are used for packs that would need more than that.
+        //
+        // Uncommitted blocks are retained by the service for a week, so an
+        // upload that was interrupted can resume after the last block that
+        // was recorded in the checkpoint.
         //
-        let builder = self.connect();
+        let builder = self.connect()?;
         let blob_client = builder.blob_client(bucket, object);
+        let coords = Coordinates::new(&self.store_id, bucket, object);
+        let mut checkpoint = match self.checkpoints.as_ref() {
+            Some(checkpoints) => {
+                let fingerprint = store_core::fingerprint_file(packfile)?;
+                match checkpoints.get_checkpoint(&coords)? {
+                    Some(saved) if saved.fingerprint == fingerprint && !saved.is_stale() => {
+                        Some(saved)
+                    }
+                    _ => Some(Checkpoint::new(&fingerprint, "")),
+                }
+            }
+            None => None,
+        };
         let mut file_handle = File::open(packfile)?;
-        let block_size: usize = 8388608;
+        let (offset, sent_blocks) = checkpoint
+            .as_ref()
+            .map(|saved| (saved.offset, saved.parts.len() as u64))
+            .unwrap_or((0, 0));
+        let remaining = file_handle.metadata()?.len().saturating_sub(offset);
+        let rate = self.throttle.as_ref().map(|t| t.rate());
+        let block_size = choose_block_size(remaining, sent_blocks, rate)?;
         let mut block_list: Vec<BlobBlockType> = Vec::new();
+        let resumed = if let Some(saved) = checkpoint.as_ref() {
+            file_handle.seek(SeekFrom::Start(saved.offset))?;
+            for block_id in saved.parts.iter() {
+                block_list.push(BlobBlockType::Uncommitted(BlockId::new(
+                    block_id.to_owned(),
+                )));
+            }
+            saved.offset > 0
+        } else {
+            false
+        };
         loop {
             // blob API wants to take ownership of the data, so allocate a new
             // buffer for every put_block call (i.e. cannot use BufReader)
@@ -127,52 +222,144 @@ impl AzureStore {
             let data_ref: &[u8] = data.as_ref();
             let md5 = md5sum_blob(data_ref)?;
             let hash = azure_storage_blobs::prelude::Hash::MD5(md5);
-            let response = blob_client
-                .put_block(block_id.clone(), data)
-                .hash(hash)
-                .await?;
+            if let Some(throttle) = self.throttle.as_ref() {
+                tokio::time::sleep(throttle.reserve(read_bytes)).await;
+            }
+            let request = blob_client.put_block(block_id.clone(), data).hash(hash);
+            let response = within(self.timeouts.write, "store_pack", async {
+                Ok(request.await?)
+            })
+            .await?;
             if let Some(content_md5) = response.content_md5 {
                 if content_md5.as_slice() != &md5 {
                     return Err(anyhow!("returned MD5 does not match"));
                 }
             }
+            if let (Some(checkpoints), Some(saved)) = (self.checkpoints.as_ref(), &mut checkpoint) {
+                saved.add_part(&block_id, read_bytes as u64);
+                checkpoints.put_checkpoint(&coords, saved)?;
+            }
             block_list.push(BlobBlockType::Uncommitted(BlockId::new(block_id)));
         }
-        let mut builder = blob_client.put_block_list(BlockList { blocks: block_list });
+        // the service keeps the digest of the whole blob only if it is given,
+        // and that digest is what allows checking the blob without reading it
+        let content_md5 = md5sum_file(packfile)?;
+        let mut builder = blob_client
+            .put_block_list(BlockList { blocks: block_list })
+            .content_md5(BlobContentMD5::from(content_md5));
         if let Some(tier) = &self.access_tier {
             builder = builder.access_tier(*tier);
         }
-        builder.await?;
-        let loc = Coordinates::new(&self.store_id, bucket, object);
-        Ok(loc)
+        let result = builder.await;
+        if let Some(checkpoints) = self.checkpoints.as_ref() {
+            // a resumed upload whose blocks have since expired cannot succeed
+            if result.is_ok() || resumed {
+                checkpoints.delete_checkpoint(&coords)?;
+            }
+        }
+        result?;
+        Ok(coords)
     }
 
     pub fn retrieve_pack_sync(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
-        block_on(self.retrieve_pack(location, outfile)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            self.retrieve_pack(location, outfile),
+        ))
+        .and_then(std::convert::identity)
     }
 
+    /// Download the blob to the given file. A blob in the archive tier cannot
+    /// be read until it has been rehydrated, which is requested on the first
+    /// attempt to retrieve it; the retrieval fails until that has finished,
+    /// which may take several hours.
     pub async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
-        let builder = self.connect();
+        let builder = self.connect()?;
         let client = builder.blob_client(&location.bucket, &location.object);
+        match self.download_blob(&client, outfile).await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.rehydrate_archived(&client, location, err).await),
+        }
+    }
+
+    async fn download_blob(&self, client: &BlobClient, outfile: &Path) -> Result<(), Error> {
         let mut file_handle = File::create(outfile)?;
         // n.b. this uses the default chunk size of 1MB, which enables the
         // pipeline to handle intermittent connection failures with retry,
         // rather than restarting the whole blob on a failure.
         let mut stream = client.get().into_stream();
-        while let Some(value) = stream.next().await {
-            let data = value?.data.collect().await?;
+        loop {
+            let next = async {
+                match stream.next().await {
+                    Some(value) => Ok(Some(value?.data.collect().await?)),
+                    None => Ok(None),
+                }
+            };
+            let data = match within(self.timeouts.read, "retrieve_pack", next).await? {
+                Some(data) => data,
+                None => break,
+            };
+            if let Some(throttle) = self.throttle.as_ref() {
+                tokio::time::sleep(throttle.reserve(data.len())).await;
+            }
             file_handle.write_all(&data)?;
         }
         Ok(())
     }
 
+    // If the blob that could not be retrieved is in the archive tier, request
+    // that it be rehydrated, and explain why it is not available; otherwise
+    // return the original error.
+    async fn rehydrate_archived(
+        &self,
+        client: &BlobClient,
+        location: &Coordinates,
+        err: Error,
+    ) -> Error {
+        let Ok(response) = client.get_properties().await else {
+            return err;
+        };
+        let properties = response.blob.properties;
+        if properties.access_tier != Some(AccessTier::Archive) {
+            return err;
+        }
+        if properties.archive_status.is_some() {
+            return anyhow!(format!(
+                "blob {}/{} is being rehydrated from the archive tier",
+                location.bucket, location.object
+            ));
+        }
+        // rehydrate to the tier of newly uploaded packs, unless that is the
+        // archive tier itself
+        let tier = match self.access_tier {
+            Some(AccessTier::Cool) => AccessTier::Cool,
+            _ => AccessTier::Hot,
+        };
+        match client.set_blob_tier(tier).await {
+            Ok(_) => anyhow!(format!(
+                "blob {}/{} is in the archive tier, rehydration has been requested",
+                location.bucket, location.object
+            )),
+            Err(rehydrate) => anyhow!(format!(
+                "blob {}/{} is in the archive tier and could not be rehydrated: {}",
+                location.bucket, location.object, rehydrate
+            )),
+        }
+    }
+
     pub fn list_buckets_sync(&self) -> Result<Vec<String>, Error> {
-        block_on(self.list_buckets()).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "list_buckets",
+            self.list_buckets(),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn list_buckets(&self) -> Result<Vec<String>, Error> {
         let mut results = Vec::new();
-        let builder = self.connect();
+        let builder = self.connect()?;
         let blob_service = builder.blob_service_client();
         let mut pageable = blob_service.list_containers().into_stream();
         while let Some(result) = pageable.next().await {
@@ -189,12 +376,17 @@ impl AzureStore {
     }
 
     pub fn list_objects_sync(&self, bucket: &str) -> Result<Vec<String>, Error> {
-        block_on(self.list_objects(bucket)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "list_objects",
+            self.list_objects(bucket),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
         use azure_storage_blobs::container::operations::BlobItem::Blob;
-        let builder = self.connect();
+        let builder = self.connect()?;
         let client = builder.container_client(bucket);
         let mut results = Vec::new();
         let mut pageable = client.list_blobs().into_stream();
@@ -214,22 +406,89 @@ impl AzureStore {
     }
 
     pub fn delete_object_sync(&self, bucket: &str, object: &str) -> Result<(), Error> {
-        block_on(self.delete_object(bucket, object)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "delete_object",
+            self.delete_object(bucket, object),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
-        let builder = self.connect();
+        let builder = self.connect()?;
         let client = builder.blob_client(bucket, object);
         client.delete().await?;
         Ok(())
     }
 
+    pub fn set_storage_class_sync(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "set_storage_class",
+            self.set_storage_class(bucket, object, class),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Change the access tier of the blob to the one named by `class`.
+    /// Returns `false` if the blob is already in the given tier.
+    pub async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        let tier = parse_access_tier(class)
+            .ok_or_else(|| anyhow!(format!("unsupported access tier: {}", class)))?;
+        let builder = self.connect()?;
+        let client = builder.blob_client(bucket, object);
+        let response = client.get_properties().await?;
+        if response.blob.properties.access_tier == Some(tier) {
+            return Ok(false);
+        }
+        client.set_blob_tier(tier).await?;
+        Ok(true)
+    }
+
+    pub fn object_md5_sync(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "object_md5",
+            self.object_md5(location),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Return the MD5 digest of the blob from its properties, which is only
+    /// present for blobs whose digest was given when they were uploaded.
+    pub async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        let builder = self.connect()?;
+        let client = builder.blob_client(&location.bucket, &location.object);
+        let response = client.get_properties().await?;
+        Ok(response.blob.properties.content_md5.map(|md5| {
+            md5.as_slice()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        }))
+    }
+
     pub fn delete_bucket_sync(&self, bucket: &str) -> Result<(), Error> {
-        block_on(self.delete_bucket(bucket)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "delete_bucket",
+            self.delete_bucket(bucket),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
-        let builder = self.connect();
+        let builder = self.connect()?;
         let client = builder.container_client(bucket);
         client.delete().await?;
         Ok(())
@@ -266,6 +525,168 @@ impl AzureStore {
     }
 }
 
+impl PackDataSource for AzureStore {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack_sync(location, outfile)
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.list_buckets_sync()
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects_sync(bucket)
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        self.delete_object_sync(bucket, object)
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        self.delete_bucket_sync(bucket)
+    }
+
+    fn set_storage_class(&self, bucket: &str, object: &str, class: &str) -> Result<bool, Error> {
+        self.set_storage_class_sync(bucket, object, class)
+    }
+
+    fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        self.object_md5_sync(location)
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_database_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_database_sync(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_databases_sync(bucket)
+    }
+}
+
+#[async_trait]
+impl AsyncPackDataSource for AzureStore {
+    async fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        within(
+            self.timeouts.operation,
+            "store_pack",
+            AzureStore::store_pack(self, packfile, bucket, object),
+        )
+        .await
+    }
+
+    async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            AzureStore::retrieve_pack(self, location, outfile),
+        )
+        .await
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_buckets",
+            AzureStore::list_buckets(self),
+        )
+        .await
+    }
+
+    async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_objects",
+            AzureStore::list_objects(self, bucket),
+        )
+        .await
+    }
+
+    async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_object",
+            AzureStore::delete_object(self, bucket, object),
+        )
+        .await
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_bucket",
+            AzureStore::delete_bucket(self, bucket),
+        )
+        .await
+    }
+
+    async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        within(
+            self.timeouts.operation,
+            "set_storage_class",
+            AzureStore::set_storage_class(self, bucket, object, class),
+        )
+        .await
+    }
+
+    async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "object_md5",
+            AzureStore::object_md5(self, location),
+        )
+        .await
+    }
+}
+
+// Convert the name of an access tier into the corresponding value.
+fn parse_access_tier(tier: &str) -> Option<AccessTier> {
+    if tier.to_lowercase() == "hot" {
+        Some(AccessTier::Hot)
+    } else if tier.to_lowercase() == "cool" {
+        Some(AccessTier::Cool)
+    } else if tier.to_lowercase() == "archive" {
+        Some(AccessTier::Archive)
+    } else {
+        None
+    }
+}
+
 /// Ensure the named container exists.
 async fn create_container(builder: ClientBuilder, container: &str) -> Result<(), Error> {
     let client = builder.container_client(container);
@@ -286,6 +707,44 @@ async fn create_container(builder: ClientBuilder, container: &str) -> Result<(),
     }
 }
 
+/// Determine the credential from the store properties.
+fn select_credential(props: &HashMap<String, String>) -> Result<Credential, Error> {
+    let given = |name: &str| props.get(name).filter(|v| !v.is_empty());
+    if let Some(access_key) = given("access_key") {
+        Ok(Credential::AccessKey(Secret::from(access_key.as_str())))
+    } else if let Some(sas_token) = given("sas_token") {
+        // the token may be copied from the portal with the leading ?
+        let sas_token = sas_token.trim_start_matches('?');
+        Ok(Credential::SasToken(Secret::from(sas_token)))
+    } else if let Some(kind) = given("credential") {
+        if kind == "default" {
+            let cred = DefaultAzureCredential::create(TokenCredentialOptions::default())?;
+            Ok(Credential::Identity(Arc::new(cred)))
+        } else {
+            Err(anyhow!(format!("unsupported credential: {}", kind)))
+        }
+    } else {
+        Err(anyhow!(
+            "missing access_key, sas_token, or credential property"
+        ))
+    }
+}
+
+/// Wait for the future to complete, raising a `TimeoutError` for the named
+/// operation if it takes longer than the limit, if any.
+async fn within<T, F>(limit: Option<Duration>, operation: &str, future: F) -> Result<T, Error>
+where
+    F: std::future::Future<Output = Result<T, Error>>,
+{
+    match limit {
+        Some(limit) => match tokio::time::timeout(limit, future).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::from(TimeoutError::new(operation, Some(limit)))),
+        },
+        None => future.await,
+    }
+}
+
 /// Run the given future on a newly created single-threaded runtime if possible,
 /// otherwise raise an error if this thread already has a runtime.
 fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, Error> {
@@ -303,6 +762,41 @@ fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, Error> {
     }
 }
 
+// Most blocks that a single blob may be assembled from.
+const MAX_BLOCKS: u64 = 50_000;
+
+// Largest block that may be uploaded in a single request.
+const MAX_BLOCK_SIZE: u64 = 4_194_304_000;
+
+// Choose the size of the blocks for uploading the remaining bytes of a pack,
+// given the number of blocks already uploaded and the throttle rate, if any.
+// When throttled, send about one second's worth of data at a time, otherwise
+// 8mb, but large enough that the blob does not exceed the block limit.
+fn choose_block_size(remaining: u64, sent_blocks: u64, rate: Option<u64>) -> Result<usize, Error> {
+    let preferred = match rate {
+        Some(rate) => rate.clamp(65_536, 8_388_608),
+        None => 8_388_608,
+    };
+    let available = MAX_BLOCKS.saturating_sub(sent_blocks);
+    if available == 0 {
+        return Err(anyhow!("blob already has the maximum number of blocks"));
+    }
+    let required = remaining.div_ceil(available);
+    if required > MAX_BLOCK_SIZE {
+        return Err(anyhow!("pack file is too large for a single blob"));
+    }
+    Ok(preferred.max(required) as usize)
+}
+
+fn md5sum_file(infile: &Path) -> Result<[u8; 16], Error> {
+    use md5::{Digest, Md5};
+    let mut file = File::open(infile)?;
+    let mut hasher = Md5::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    let digest = hasher.finalize();
+    Ok(digest.into())
+}
+
 fn md5sum_blob<T: AsRef<[u8]>>(data: T) -> Result<[u8; 16], Error> {
     use md5::{Digest, Md5};
     let mut hasher = Md5::new();
@@ -328,6 +822,28 @@ mod tests {
         // could check all of the others, I guess?
     }
 
+    #[test]
+    fn test_choose_block_size() {
+        assert_eq!(choose_block_size(67_108_864, 0, None).unwrap(), 8_388_608);
+        assert_eq!(
+            choose_block_size(67_108_864, 0, Some(1_024)).unwrap(),
+            65_536
+        );
+        assert_eq!(
+            choose_block_size(67_108_864, 0, Some(1_048_576)).unwrap(),
+            1_048_576
+        );
+        // a large pack needs larger blocks to stay within the limit
+        let size = choose_block_size(1_000_000_000_000, 0, None).unwrap();
+        assert_eq!(size, 20_000_000);
+        assert!(1_000_000_000_000 / size as u64 <= MAX_BLOCKS);
+        // a resumed upload has fewer blocks to spare
+        let size = choose_block_size(1_000_000_000_000, 40_000, Some(65_536)).unwrap();
+        assert_eq!(size, 100_000_000);
+        assert!(choose_block_size(1_024, MAX_BLOCKS, None).is_err());
+        assert!(choose_block_size(u64::MAX, 0, None).is_err());
+    }
+
     #[test]
     fn test_azure_wrong_account() -> Result<(), Error> {
         // arrange
@@ -345,6 +861,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_new_azure_store_credentials() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("account".to_owned(), "zorigami-test".to_owned());
+        let result = AzureStore::new("azure123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing access_key, sas_token, or credential"));
+
+        properties.insert("sas_token".to_owned(), "?sv=2022-11-02&sig=abc".to_owned());
+        let source = AzureStore::new("azure123", &properties).unwrap();
+        match &source.credential {
+            Credential::SasToken(token) => assert_eq!(token.expose(), "sv=2022-11-02&sig=abc"),
+            other => panic!("expected SAS token, got {:?}", other),
+        }
+        assert!(source.connect().is_ok());
+
+        properties.remove("sas_token");
+        properties.insert("credential".to_owned(), "magic".to_owned());
+        let result = AzureStore::new("azure123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("unsupported credential"));
+
+        properties.insert("credential".to_owned(), "default".to_owned());
+        let source = AzureStore::new("azure123", &properties).unwrap();
+        assert!(matches!(source.credential, Credential::Identity(_)));
+    }
+
     #[test]
     fn test_new_azure_store_ok() {
         let mut properties: HashMap<String, String> = HashMap::new();
@@ -352,6 +897,10 @@ mod tests {
         properties.insert("access_key".to_owned(), "azure-access-key".to_owned());
         let result = AzureStore::new("azure123", &properties);
         assert!(result.is_ok());
+        properties.insert("connect_timeout".to_owned(), "15".to_owned());
+        let source = AzureStore::new("azure123", &properties).unwrap();
+        assert_eq!(source.timeouts.connect, Some(Duration::from_secs(15)));
+        assert!(source.connect().is_ok());
     }
 
     #[test]
@@ -396,6 +945,8 @@ mod tests {
         assert_eq!(location.store, "azure1");
         assert_eq!(location.bucket, bucket);
         assert_eq!(location.object, object);
+        let md5sum = source.object_md5_sync(&location)?;
+        assert_eq!(md5sum, Some(store_core::md5sum_file(packfile)?));
 
         // check for bucket(s) being present
         let buckets = source.list_buckets_sync()?;
@@ -413,10 +964,7 @@ mod tests {
         let result = source.retrieve_pack_sync(&location, &outfile);
         assert!(result.is_ok());
         let md5sum = store_core::md5sum_file(&outfile)?;
-        #[cfg(target_family = "unix")]
         assert_eq!(md5sum, "4b9772cf2c623ad529900f0ffe4e8ded");
-        #[cfg(target_family = "windows")]
-        assert_eq!(md5sum, "f143ccda41d1e2a553ef214e4549cd6e");
 
         // remove all objects from all buckets, and the buckets, too
         for bucket in buckets {
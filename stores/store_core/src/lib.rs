@@ -4,11 +4,24 @@
 
 //! Defines the traits and types for all pack stores.
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "memory")]
+pub mod memory;
+mod renames;
+mod secret;
+mod throttle;
+mod timeout;
+pub use renames::{is_rename_marker, ObjectRenames, RenameTracker};
+pub use secret::Secret;
+pub use throttle::{Throttle, ThrottledReader, ThrottledWriter};
+pub use timeout::{classify_timeout, Deadline, DeadlineReader, TimeoutError, Timeouts};
 
 ///
 /// Return the last part of the path, converting to a String.
@@ -53,6 +66,16 @@ pub fn md5sum_blob<T: AsRef<[u8]>>(data: T) -> Result<String, Error> {
     Ok(result)
 }
 
+///
+/// Compute a fingerprint of the given file from its length and MD5 digest, for
+/// detecting that a file is unchanged since an upload began.
+///
+pub fn fingerprint_file(infile: &Path) -> Result<String, Error> {
+    let length = std::fs::metadata(infile)?.len();
+    let md5sum = md5sum_file(infile)?;
+    Ok(format!("{}-{}", length, md5sum))
+}
+
 ///
 /// Remote coordinates for a pack file, naming the store, bucket, and object by
 /// which the pack file can be retrieved.
@@ -91,6 +114,326 @@ impl fmt::Display for CollisionError {
     }
 }
 
+///
+/// Progress of an upload that can be resumed, recorded by the store after each
+/// part of the file has been uploaded successfully.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Checkpoint {
+    /// Fingerprint of the local file, as computed by `fingerprint_file()`.
+    pub fingerprint: String,
+    /// Identifier of the upload session, such as the multipart upload ID for
+    /// S3 or the resumable session URI for Google.
+    pub session: String,
+    /// Identifiers of the parts uploaded so far, in order, such as the entity
+    /// tags for S3 or the block identifiers for Azure.
+    pub parts: Vec<String>,
+    /// Number of bytes of the file that have been uploaded.
+    pub offset: u64,
+    /// Seconds since the Unix epoch when the checkpoint was last updated.
+    pub updated: u64,
+}
+
+impl Checkpoint {
+    /// Construct a checkpoint for a newly started upload session.
+    pub fn new(fingerprint: &str, session: &str) -> Self {
+        Self {
+            fingerprint: fingerprint.to_owned(),
+            session: session.to_owned(),
+            parts: vec![],
+            offset: 0,
+            updated: now_secs(),
+        }
+    }
+
+    /// Record the successful upload of another part of the file.
+    pub fn add_part(&mut self, part: &str, length: u64) {
+        self.parts.push(part.to_owned());
+        self.offset += length;
+        self.updated = now_secs();
+    }
+
+    /// Return `true` if the checkpoint is too old for the upload to resume.
+    pub fn is_stale(&self) -> bool {
+        now_secs().saturating_sub(self.updated) > CHECKPOINT_MAX_AGE_SECS
+    }
+}
+
+/// Age in seconds beyond which an upload checkpoint cannot be resumed. Azure
+/// retains uncommitted blocks for seven days, and a Google resumable session is
+/// valid for a week, so anything older is of no use.
+pub const CHECKPOINT_MAX_AGE_SECS: u64 = 6 * 24 * 60 * 60;
+
+/// Prefixes of the names given to pack objects, those being the hash digests
+/// of the packs. Lifecycle rules select only the objects with these prefixes,
+/// leaving alone anything else in the bucket, such as the rename marker.
+pub const PACK_PREFIXES: &[&str] = &["blake3-", "sha1-"];
+
+///
+/// Rule by which the service itself moves or removes the objects of a bucket
+/// as they age. A rule with nothing defined means there should be none at all.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LifecycleRule {
+    /// Objects older than this many days are moved to the `storage_class`.
+    pub transition_days: Option<u32>,
+    /// Name of the storage class to which older objects are moved.
+    pub storage_class: Option<String>,
+    /// Objects older than this many days are deleted.
+    pub expiration_days: Option<u32>,
+}
+
+impl LifecycleRule {
+    /// Return the rule for the buckets that hold pack files, as given by the
+    /// `lifecycle_days` and `lifecycle_class` properties of the store. Packs
+    /// are never deleted by the service, only moved to the colder class.
+    pub fn for_packs(props: &HashMap<String, String>) -> Result<Self, Error> {
+        let days = read_days(props, "lifecycle_days")?;
+        let class = props
+            .get("lifecycle_class")
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty());
+        match (days, class) {
+            (None, None) => Ok(Self::default()),
+            (Some(days), Some(class)) => Ok(Self {
+                transition_days: Some(days),
+                storage_class: Some(class.to_owned()),
+                expiration_days: None,
+            }),
+            _ => Err(anyhow!(
+                "lifecycle_days and lifecycle_class must be given together"
+            )),
+        }
+    }
+
+    /// Return the rule for the database archives, as given by the
+    /// `lifecycle_database_days` property of the store. This rule is never
+    /// given to the service, which would delete the newest archive as readily
+    /// as the others; the server removes the older archives itself.
+    pub fn for_databases(props: &HashMap<String, String>) -> Result<Self, Error> {
+        let days = read_days(props, "lifecycle_database_days")?;
+        Ok(Self {
+            transition_days: None,
+            storage_class: None,
+            expiration_days: days,
+        })
+    }
+
+    /// Return `true` if the rule neither moves nor deletes anything.
+    pub fn is_empty(&self) -> bool {
+        self.transition_days.is_none() && self.expiration_days.is_none()
+    }
+}
+
+// Read the named property as a positive number of days, if given.
+fn read_days(props: &HashMap<String, String>, name: &str) -> Result<Option<u32>, Error> {
+    match props.get(name).map(|v| v.trim()) {
+        None | Some("") => Ok(None),
+        Some(value) => match value.parse::<u32>() {
+            Ok(0) => Ok(None),
+            Ok(days) => Ok(Some(days)),
+            Err(_) => Err(anyhow!(format!(
+                "{} must be a number of days: {}",
+                name, value
+            ))),
+        },
+    }
+}
+
+// Return the current time in seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+///
+/// Saves the upload checkpoints on behalf of the pack stores, such that an
+/// upload interrupted by a crash or network outage can be resumed later.
+///
+pub trait CheckpointStore: fmt::Debug + Send + Sync {
+    /// Retrieve the checkpoint for an upload to the given location, if any.
+    fn get_checkpoint(&self, location: &Coordinates) -> Result<Option<Checkpoint>, Error>;
+
+    /// Save the checkpoint for an upload to the given location.
+    fn put_checkpoint(&self, location: &Coordinates, checkpoint: &Checkpoint) -> Result<(), Error>;
+
+    /// Remove the checkpoint for the given location, once the upload has
+    /// completed or can no longer be resumed.
+    fn delete_checkpoint(&self, location: &Coordinates) -> Result<(), Error>;
+}
+
+///
+/// Operations common to all pack stores, in blocking form.
+///
+/// Stores that are built on an asynchronous client will run the operation to
+/// completion on a runtime of their own, and hence these functions must not be
+/// called from a thread that is already running an async runtime.
+///
+pub trait PackDataSource: Send + Sync {
+    /// Return `true` if this store is local to the system.
+    fn is_local(&self) -> bool;
+
+    /// Return `true` if this store is remarkably slow compared to usual.
+    fn is_slow(&self) -> bool;
+
+    /// Store the pack file under the named bucket and referenced by the object
+    /// name. Returns the remote location of the pack, in case it was assigned
+    /// new values by the backing store.
+    fn store_pack(&self, packfile: &Path, bucket: &str, object: &str)
+        -> Result<Coordinates, Error>;
+
+    /// Retrieve a pack from the given location, writing the contents to the
+    /// given path.
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error>;
+
+    /// List the known buckets in the repository.
+    fn list_buckets(&self) -> Result<Vec<String>, Error>;
+
+    /// List of all objects in the named bucket.
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error>;
+
+    /// Delete the named object from the given bucket.
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error>;
+
+    /// Delete the named bucket. It almost certainly needs to be empty first, so
+    /// use `list_objects()` and `delete_object()` to remove the objects.
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error>;
+
+    /// Move the named object to the given storage class (or access tier),
+    /// returning `false` if the object was already in that class. Stores that
+    /// do not have storage classes will return an error.
+    fn set_storage_class(&self, _bucket: &str, _object: &str, _class: &str) -> Result<bool, Error> {
+        Err(anyhow!("storage classes not supported by this store"))
+    }
+
+    /// Replace the lifecycle configuration of the named bucket with the given
+    /// rule, or remove it if the rule is empty. Stores whose service does not
+    /// manage the lifecycle of objects will return an error.
+    fn set_lifecycle(&self, _bucket: &str, _rule: &LifecycleRule) -> Result<(), Error> {
+        Err(anyhow!("lifecycle rules not supported by this store"))
+    }
+
+    /// Discard the partial upload recorded in the checkpoint, such that the
+    /// service no longer retains (and charges for) the parts sent so far.
+    /// Stores whose partial uploads expire on their own do nothing.
+    fn abort_upload(&self, _location: &Coordinates, _checkpoint: &Checkpoint) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Return the MD5 digest (in hexadecimal) that the store keeps for the
+    /// object at the given location, without retrieving the object. Returns
+    /// `None` if the store does not keep such a digest for the object.
+    fn object_md5(&self, _location: &Coordinates) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Return the size in bytes of the object at the given location, without
+    /// retrieving the object. Returns `None` if the store cannot cheaply report
+    /// the size of an object.
+    fn object_size(&self, _location: &Coordinates) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    /// Store the database archive under the named bucket and referenced by the
+    /// object name. Returns the remote location of the pack, in case it was
+    /// assigned new values by the backing store.
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error>;
+
+    /// Retrieve a database archive from the given location, writing the
+    /// contents to the given path.
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error>;
+
+    /// List all database archives in the named bucket.
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error>;
+}
+
+///
+/// Operations common to all pack stores that are built on an asynchronous
+/// client, for use within an existing async runtime. The functions have the
+/// same meaning as those of `PackDataSource`.
+///
+#[async_trait::async_trait]
+pub trait AsyncPackDataSource: Send + Sync {
+    /// Store the pack file under the named bucket and referenced by the object
+    /// name.
+    async fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error>;
+
+    /// Retrieve a pack from the given location, writing the contents to the
+    /// given path.
+    async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error>;
+
+    /// List the known buckets in the repository.
+    async fn list_buckets(&self) -> Result<Vec<String>, Error>;
+
+    /// List of all objects in the named bucket.
+    async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error>;
+
+    /// Delete the named object from the given bucket.
+    async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error>;
+
+    /// Delete the named bucket.
+    async fn delete_bucket(&self, bucket: &str) -> Result<(), Error>;
+
+    /// Move the named object to the given storage class (or access tier).
+    async fn set_storage_class(
+        &self,
+        _bucket: &str,
+        _object: &str,
+        _class: &str,
+    ) -> Result<bool, Error> {
+        Err(anyhow!("storage classes not supported by this store"))
+    }
+
+    /// Replace the lifecycle configuration of the named bucket.
+    async fn set_lifecycle(&self, _bucket: &str, _rule: &LifecycleRule) -> Result<(), Error> {
+        Err(anyhow!("lifecycle rules not supported by this store"))
+    }
+
+    /// Return the MD5 digest that the store keeps for the object, if any.
+    async fn object_md5(&self, _location: &Coordinates) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Return the size in bytes of the object, if the store can report it.
+    async fn object_size(&self, _location: &Coordinates) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+
+    /// Store the database archive under the named bucket and referenced by the
+    /// object name.
+    async fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack(packfile, bucket, object).await
+    }
+
+    /// Retrieve a database archive from the given location, writing the
+    /// contents to the given path.
+    async fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack(location, outfile).await
+    }
+
+    /// List all database archives in the named bucket.
+    async fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects(bucket).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,18 +442,57 @@ mod tests {
     fn test_md5sum_file() {
         let infile = Path::new("../../test/fixtures/lorem-ipsum.txt");
         let md5sum = md5sum_file(&infile).unwrap();
-        #[cfg(target_family = "unix")]
         assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
-        #[cfg(target_family = "windows")]
-        assert_eq!(md5sum, "8aed508af644bc58db20c9b73c5b67ad");
+    }
+
+    #[test]
+    fn test_fingerprint_file() {
+        let infile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let fingerprint = fingerprint_file(infile).unwrap();
+        assert_eq!(fingerprint, "3129-40756e6058736e2485119410c2014380");
+    }
+
+    #[test]
+    fn test_checkpoint_add_part() {
+        let mut checkpoint = Checkpoint::new("3129-cafebabe", "upload-1");
+        assert_eq!(checkpoint.offset, 0);
+        checkpoint.add_part("etag-1", 8388608);
+        checkpoint.add_part("etag-2", 1024);
+        assert_eq!(checkpoint.parts, vec!["etag-1", "etag-2"]);
+        assert_eq!(checkpoint.offset, 8389632);
+        assert!(checkpoint.updated > 0);
+        assert!(!checkpoint.is_stale());
+        checkpoint.updated -= CHECKPOINT_MAX_AGE_SECS + 60;
+        assert!(checkpoint.is_stale());
+    }
+
+    #[test]
+    fn test_lifecycle_rule_properties() {
+        let mut props: HashMap<String, String> = HashMap::new();
+        assert!(LifecycleRule::for_packs(&props).unwrap().is_empty());
+        assert!(LifecycleRule::for_databases(&props).unwrap().is_empty());
+        props.insert("lifecycle_days".to_owned(), "30".to_owned());
+        let result = LifecycleRule::for_packs(&props);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("must be given together"));
+        props.insert("lifecycle_class".to_owned(), "GLACIER_IR".to_owned());
+        let rule = LifecycleRule::for_packs(&props).unwrap();
+        assert_eq!(rule.transition_days, Some(30));
+        assert_eq!(rule.storage_class.as_deref(), Some("GLACIER_IR"));
+        assert!(rule.expiration_days.is_none());
+        props.insert("lifecycle_database_days".to_owned(), "soon".to_owned());
+        assert!(LifecycleRule::for_databases(&props).is_err());
+        props.insert("lifecycle_database_days".to_owned(), "90".to_owned());
+        let rule = LifecycleRule::for_databases(&props).unwrap();
+        assert_eq!(rule.expiration_days, Some(90));
+        assert!(rule.transition_days.is_none());
+        assert!(!rule.is_empty());
     }
 
     #[test]
     fn test_md5sum_blob() {
         let md5sum = md5sum_blob(b"hello world").unwrap();
-        #[cfg(target_family = "unix")]
-        assert_eq!(md5sum, "5eb63bbbe01eeed093cb22bb8f5acdc3");
-        #[cfg(target_family = "windows")]
         assert_eq!(md5sum, "5eb63bbbe01eeed093cb22bb8f5acdc3");
     }
 }
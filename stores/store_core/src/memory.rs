@@ -0,0 +1,392 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! An in-memory pack store for testing the backup and restore procedures
+//! without network access or credentials. Faults can be injected to simulate
+//! failed uploads, corrupted objects, and slow connections.
+//!
+//! Stores constructed with the same identifier share their contents, just as
+//! two connections to the same remote store would see the same objects.
+
+use crate::{Coordinates, PackDataSource, TimeoutError, Timeouts};
+use anyhow::{anyhow, Error};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// Contents and fault settings of a single in-memory store.
+#[derive(Debug, Default)]
+struct State {
+    // bucket name to object name to contents
+    buckets: BTreeMap<String, BTreeMap<String, Vec<u8>>>,
+    // number of upload attempts made so far
+    uploads: usize,
+    // upload attempts (1-based) that are destined to fail
+    failures: BTreeSet<usize>,
+    // delay imposed on every operation
+    latency: Duration,
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+// Registry of all in-memory stores, keyed by store identifier.
+fn registry() -> &'static Mutex<HashMap<String, SharedState>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, SharedState>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+///
+/// A pack store implementation in which pack files are held in memory for the
+/// lifetime of the process.
+///
+/// The optional `latency` property sets a delay, in milliseconds, that is
+/// imposed on every operation. An operation whose delay is longer than the
+/// `operation_timeout` property allows fails with a `TimeoutError` once that
+/// much time has passed, as a stalled connection to a remote store would.
+///
+#[derive(Clone, Debug)]
+pub struct MemoryStore {
+    store_id: String,
+    state: SharedState,
+    timeouts: Timeouts,
+}
+
+impl MemoryStore {
+    /// Construct a memory store, sharing the contents of any existing store
+    /// with the same identifier.
+    pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
+        let state = registry()
+            .lock()
+            .unwrap()
+            .entry(store_id.to_owned())
+            .or_default()
+            .clone();
+        let store = Self {
+            store_id: store_id.to_owned(),
+            state,
+            timeouts: Timeouts::from_properties(props)?,
+        };
+        if let Some(value) = props.get("latency") {
+            let millis: u64 = value
+                .parse()
+                .map_err(|_| anyhow!("latency must be a number of milliseconds"))?;
+            store.set_latency(Duration::from_millis(millis));
+        }
+        Ok(store)
+    }
+
+    /// Cause the `nth` upload from now to fail, where 1 is the next upload.
+    pub fn fail_upload(&self, nth: usize) {
+        let mut state = self.state.lock().unwrap();
+        let attempt = state.uploads + nth;
+        state.failures.insert(attempt);
+    }
+
+    /// Alter the contents of the named object so that it no longer matches
+    /// what was originally uploaded.
+    pub fn corrupt_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let contents = state
+            .buckets
+            .get_mut(bucket)
+            .and_then(|b| b.get_mut(object))
+            .ok_or_else(|| anyhow!(format!("no such object: {}/{}", bucket, object)))?;
+        if contents.is_empty() {
+            contents.push(0xff);
+        } else {
+            let middle = contents.len() / 2;
+            contents[middle] = !contents[middle];
+        }
+        Ok(())
+    }
+
+    /// Set the delay imposed on every operation.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Return the number of upload attempts made, including failed ones.
+    pub fn upload_count(&self) -> usize {
+        self.state.lock().unwrap().uploads
+    }
+
+    /// Remove all buckets and objects, and clear any pending faults.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = Default::default();
+    }
+
+    // Sleep for the configured latency, if any, giving up on the operation
+    // if the latency exceeds the operation timeout.
+    fn delay(&self, operation: &str) -> Result<(), Error> {
+        let latency = self.state.lock().unwrap().latency;
+        if let Some(limit) = self.timeouts.operation {
+            if latency > limit {
+                thread::sleep(limit);
+                return Err(Error::from(TimeoutError::new(operation, Some(limit))));
+            }
+        }
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        Ok(())
+    }
+}
+
+impl PackDataSource for MemoryStore {
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.delay("store_pack")?;
+        let contents = fs::read(packfile)?;
+        let mut state = self.state.lock().unwrap();
+        state.uploads += 1;
+        let attempt = state.uploads;
+        if state.failures.remove(&attempt) {
+            return Err(anyhow!(format!(
+                "injected failure for upload {} of {}/{}",
+                attempt, bucket, object
+            )));
+        }
+        state
+            .buckets
+            .entry(bucket.to_owned())
+            .or_default()
+            .insert(object.to_owned(), contents);
+        let loc = Coordinates::new(&self.store_id, bucket, object);
+        Ok(loc)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.delay("retrieve_pack")?;
+        let state = self.state.lock().unwrap();
+        let contents = state
+            .buckets
+            .get(&location.bucket)
+            .and_then(|b| b.get(&location.object))
+            .ok_or_else(|| {
+                anyhow!(format!(
+                    "no such object: {}/{}",
+                    location.bucket, location.object
+                ))
+            })?;
+        fs::write(outfile, contents)?;
+        Ok(())
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.delay("list_buckets")?;
+        let state = self.state.lock().unwrap();
+        Ok(state.buckets.keys().cloned().collect())
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.delay("list_objects")?;
+        let state = self.state.lock().unwrap();
+        let objects = state
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| anyhow!(format!("no such bucket: {}", bucket)))?;
+        Ok(objects.keys().cloned().collect())
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        self.delay("delete_object")?;
+        let mut state = self.state.lock().unwrap();
+        state
+            .buckets
+            .get_mut(bucket)
+            .and_then(|b| b.remove(object))
+            .ok_or_else(|| anyhow!(format!("no such object: {}/{}", bucket, object)))?;
+        Ok(())
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        self.delay("delete_bucket")?;
+        let mut state = self.state.lock().unwrap();
+        let objects = state
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| anyhow!(format!("no such bucket: {}", bucket)))?;
+        if !objects.is_empty() {
+            return Err(anyhow!(format!("bucket not empty: {}", bucket)));
+        }
+        state.buckets.remove(bucket);
+        Ok(())
+    }
+
+    fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        self.delay("object_md5")?;
+        let state = self.state.lock().unwrap();
+        let contents = state
+            .buckets
+            .get(&location.bucket)
+            .and_then(|b| b.get(&location.object))
+            .ok_or_else(|| {
+                anyhow!(format!(
+                    "no such object: {}/{}",
+                    location.bucket, location.object
+                ))
+            })?;
+        Ok(Some(crate::md5sum_blob(contents)?))
+    }
+
+    fn object_size(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        self.delay("object_size")?;
+        let state = self.state.lock().unwrap();
+        let contents = state
+            .buckets
+            .get(&location.bucket)
+            .and_then(|b| b.get(&location.object))
+            .ok_or_else(|| {
+                anyhow!(format!(
+                    "no such object: {}/{}",
+                    location.bucket, location.object
+                ))
+            })?;
+        Ok(Some(contents.len() as u64))
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects(bucket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Instant;
+
+    #[test]
+    fn test_memory_store_roundtrip() -> Result<(), Error> {
+        // arrange
+        let properties: HashMap<String, String> = HashMap::new();
+        let source = MemoryStore::new("memoryone", &properties)?;
+        assert!(source.is_local());
+        assert!(!source.is_slow());
+        let bucket = "747267d56e7057118a9aa40c24c1730f";
+        let object = "39c6061a56b7711f92c6ccd2047d47fdcc1609c1";
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+
+        // act
+        let location = source.store_pack(packfile, bucket, object)?;
+
+        // assert
+        assert_eq!(location.store, "memoryone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+        // a store with the same identifier sees the same contents
+        let other = MemoryStore::new("memoryone", &properties)?;
+        assert_eq!(other.list_buckets()?, vec![bucket.to_owned()]);
+        assert_eq!(other.list_objects(bucket)?, vec![object.to_owned()]);
+        let outfile = env::temp_dir().join("memory_store_roundtrip.txt");
+        other.retrieve_pack(&location, &outfile)?;
+        let md5sum = crate::md5sum_file(&outfile)?;
+        fs::remove_file(&outfile)?;
+        assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
+        assert!(source.delete_bucket(bucket).is_err());
+        source.delete_object(bucket, object)?;
+        source.delete_bucket(bucket)?;
+        assert!(other.list_buckets()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_store_faults() -> Result<(), Error> {
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("latency".into(), "20".into());
+        let source = MemoryStore::new("memorytwo", &properties)?;
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        source.fail_upload(2);
+
+        // act
+        let start = Instant::now();
+        let first = source.store_pack(packfile, "bucket", "first");
+        let second = source.store_pack(packfile, "bucket", "second");
+        let third = source.store_pack(packfile, "bucket", "third");
+
+        // assert
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert!(first.is_ok());
+        assert!(second.is_err());
+        assert!(third.is_ok());
+        assert_eq!(source.upload_count(), 3);
+        assert_eq!(source.list_objects("bucket")?, vec!["first", "third"]);
+        source.set_latency(Duration::ZERO);
+        let third = third?;
+        let md5sum = source.object_md5(&third)?;
+        assert_eq!(md5sum.as_deref(), Some("40756e6058736e2485119410c2014380"));
+        assert_eq!(source.object_size(&third)?, Some(3129));
+        source.corrupt_object("bucket", "third")?;
+        let md5sum = source.object_md5(&third)?;
+        assert_ne!(md5sum.as_deref(), Some("40756e6058736e2485119410c2014380"));
+        let outfile = env::temp_dir().join("memory_store_faults.txt");
+        source.retrieve_pack(&third, &outfile)?;
+        let md5sum = crate::md5sum_file(&outfile)?;
+        fs::remove_file(&outfile)?;
+        assert_ne!(md5sum, "40756e6058736e2485119410c2014380");
+        assert!(source.corrupt_object("bucket", "second").is_err());
+        source.clear();
+        assert!(source.list_buckets()?.is_empty());
+        assert_eq!(source.upload_count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_store_timeout() -> Result<(), Error> {
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("latency".into(), "1500".into());
+        properties.insert("operation_timeout".into(), "1".into());
+        let source = MemoryStore::new("memorythree", &properties)?;
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+
+        // act
+        let start = Instant::now();
+        let result = source.store_pack(packfile, "bucket", "object");
+
+        // assert
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(1));
+        assert!(elapsed < Duration::from_millis(1500));
+        let err = result.unwrap_err();
+        let timeout = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.operation, "store_pack");
+        assert_eq!(timeout.limit, Some(Duration::from_secs(1)));
+        assert_eq!(source.upload_count(), 0);
+        source.set_latency(Duration::ZERO);
+        source.store_pack(packfile, "bucket", "object")?;
+        assert_eq!(source.upload_count(), 1);
+        Ok(())
+    }
+}
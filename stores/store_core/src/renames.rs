@@ -0,0 +1,203 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Keeps track of the buckets that had to be renamed because the original name
+//! was already taken by another account.
+
+use crate::{AsyncPackDataSource, Coordinates};
+use anyhow::Error;
+use std::io::Write;
+
+// Prefix of the name of the object that records the original bucket name.
+const MARKER_PREFIX: &str = "renamed-from-";
+
+///
+/// Records the new names of buckets whose original name was unavailable, such
+/// that the database archives can be found again after a fresh install.
+///
+#[async_trait::async_trait]
+pub trait RenameTracker: Send + Sync {
+    /// Record that the bucket named `original` is now named `renamed`.
+    async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error>;
+
+    /// Retrieve the new name for the original bucket, if it was renamed.
+    async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error>;
+}
+
+///
+/// Return `true` if the object is the one written by `ObjectRenames`, which
+/// stores will hide from the object listings such that it is not mistaken for
+/// a pack or database archive, nor removed when pruning the bucket.
+///
+pub fn is_rename_marker(object: &str) -> bool {
+    object.starts_with(MARKER_PREFIX)
+}
+
+///
+/// Rename tracker that writes a small object into the renamed bucket itself,
+/// requiring nothing more of the service than the pack store already uses.
+/// Stores write the marker in the standard storage class, without creating
+/// the bucket with the lifecycle rule of the pack buckets, and the name of the
+/// marker falls outside of the `PACK_PREFIXES` to which lifecycle rules apply.
+///
+/// Finding a renamed bucket involves checking each of the buckets that look
+/// like a type 4 UUID (the form given to renamed buckets) for the object that
+/// names the original bucket.
+///
+pub struct ObjectRenames<'a> {
+    source: &'a dyn AsyncPackDataSource,
+}
+
+impl<'a> ObjectRenames<'a> {
+    /// Construct a tracker that records the renames in the given store.
+    pub fn new(source: &'a dyn AsyncPackDataSource) -> Self {
+        Self { source }
+    }
+}
+
+#[async_trait::async_trait]
+impl RenameTracker for ObjectRenames<'_> {
+    async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error> {
+        let mut marker = tempfile::NamedTempFile::new()?;
+        marker.write_all(original.as_bytes())?;
+        marker.flush()?;
+        let object = format!("{}{}", MARKER_PREFIX, original);
+        self.source
+            .store_pack(marker.path(), renamed, &object)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error> {
+        let object = format!("{}{}", MARKER_PREFIX, original);
+        let outdir = tempfile::tempdir()?;
+        let outfile = outdir.path().join("marker");
+        for bucket in self.source.list_buckets().await? {
+            if bucket == original || !looks_like_uuid(&bucket) {
+                continue;
+            }
+            // most of the buckets will not have the marker, which is fine
+            let location = Coordinates::new("", &bucket, &object);
+            if self.source.retrieve_pack(&location, &outfile).await.is_ok() {
+                let recorded = std::fs::read(&outfile)?;
+                if recorded == original.as_bytes() {
+                    return Ok(Some(bucket));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Return `true` if the name has the hyphenated form of a UUID.
+fn looks_like_uuid(name: &str) -> bool {
+    name.len() == 36
+        && name.char_indices().all(|(idx, ch)| match idx {
+            8 | 13 | 18 | 23 => ch == '-',
+            _ => ch.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    // Just enough of a store to hold a few objects.
+    #[derive(Default)]
+    struct FakeStore {
+        buckets: Mutex<HashMap<String, HashMap<String, Vec<u8>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncPackDataSource for FakeStore {
+        async fn store_pack(
+            &self,
+            packfile: &Path,
+            bucket: &str,
+            object: &str,
+        ) -> Result<Coordinates, Error> {
+            let data = std::fs::read(packfile)?;
+            let mut buckets = self.buckets.lock().unwrap();
+            let objects = buckets.entry(bucket.to_owned()).or_default();
+            objects.insert(object.to_owned(), data);
+            Ok(Coordinates::new("fake", bucket, object))
+        }
+
+        async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+            let buckets = self.buckets.lock().unwrap();
+            let data = buckets
+                .get(&location.bucket)
+                .and_then(|objects| objects.get(&location.object))
+                .ok_or_else(|| anyhow!("no such object"))?;
+            std::fs::write(outfile, data)?;
+            Ok(())
+        }
+
+        async fn list_buckets(&self) -> Result<Vec<String>, Error> {
+            let buckets = self.buckets.lock().unwrap();
+            Ok(buckets.keys().cloned().collect())
+        }
+
+        async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+            let buckets = self.buckets.lock().unwrap();
+            Ok(buckets
+                .get(bucket)
+                .map(|objects| objects.keys().cloned().collect())
+                .unwrap_or_default())
+        }
+
+        async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+            let mut buckets = self.buckets.lock().unwrap();
+            if let Some(objects) = buckets.get_mut(bucket) {
+                objects.remove(object);
+            }
+            Ok(())
+        }
+
+        async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+            let mut buckets = self.buckets.lock().unwrap();
+            buckets.remove(bucket);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_looks_like_uuid() {
+        assert!(looks_like_uuid("6f0ae4b6-6d6a-4ff3-9b1c-3b1a8f2c0d9e"));
+        assert!(!looks_like_uuid("6f0ae4b66d6a4ff39b1c3b1a8f2c0d9e"));
+        assert!(!looks_like_uuid("01hq3f6b5v8k2n9x7c4d1a0e3r"));
+        assert!(!looks_like_uuid("6f0ae4b6-6d6a-4ff3-9b1c-3b1a8f2c0d9x"));
+    }
+
+    #[test]
+    fn test_object_renames() {
+        let store = FakeStore::default();
+        let tracker = ObjectRenames::new(&store);
+        let original = "8eac1fb0e3f34f9f9ba3f70ab2c4ecb1";
+        let renamed = "6f0ae4b6-6d6a-4ff3-9b1c-3b1a8f2c0d9e";
+        let other = "0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d";
+        futures::executor::block_on(async {
+            // nothing has been renamed yet
+            assert!(tracker.get_bucket_name(original).await.unwrap().is_none());
+            tracker.save_bucket_name("f00dcafe", other).await.unwrap();
+            tracker.save_bucket_name(original, renamed).await.unwrap();
+            let actual = tracker.get_bucket_name(original).await.unwrap();
+            assert_eq!(actual.unwrap(), renamed);
+            let actual = tracker.get_bucket_name("f00dcafe").await.unwrap();
+            assert_eq!(actual.unwrap(), other);
+            assert!(tracker.get_bucket_name("deadbeef").await.unwrap().is_none());
+        });
+        let objects = futures::executor::block_on(store.list_objects(renamed)).unwrap();
+        assert_eq!(objects.len(), 1);
+        assert!(is_rename_marker(&objects[0]));
+        assert!(!crate::PACK_PREFIXES
+            .iter()
+            .any(|p| objects[0].starts_with(p)));
+        assert!(!is_rename_marker("01hq3f6b5v8k2n9x7c4d1a0e3r"));
+    }
+}
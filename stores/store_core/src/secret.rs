@@ -0,0 +1,196 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Defines the `Secret` type for holding sensitive values in memory.
+
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(unix)]
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+// Number of secrets occupying each locked page, by the address of the page.
+#[cfg(unix)]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+///
+/// Holds a sensitive value, such as a passphrase or access key, for as long as
+/// it is needed, and no longer.
+///
+/// The memory is overwritten with zeros when the value is dropped, and on Unix
+/// systems is locked to keep it from being written to swap, if permitted. The
+/// latter is only a best effort: the value may have been copied elsewhere
+/// before it was given to the `Secret`. The lock applies to whole pages that
+/// may be shared with other secrets, so the secrets on each page are counted,
+/// and the page is unlocked when the last of them is dropped. The `Debug` and
+/// `Display` implementations never reveal the value.
+///
+pub struct Secret {
+    value: String,
+}
+
+impl Secret {
+    /// Take ownership of the given value.
+    pub fn new(value: String) -> Self {
+        lock_memory(value.as_ptr(), value.capacity());
+        Self { value }
+    }
+
+    /// Borrow the sensitive value, which should not be copied elsewhere.
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    /// Return `true` if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // zeroing the value leaves the allocation in place until it is freed
+        let (ptr, len) = (self.value.as_ptr(), self.value.capacity());
+        self.value.zeroize();
+        unlock_memory(ptr, len);
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        Secret::new(self.value.clone())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl From<&String> for Secret {
+    fn from(value: &String) -> Self {
+        Secret::new(value.clone())
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret::new(value.to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+// Return the size of a page and the addresses of the pages containing the
+// given range of memory, which must not be empty.
+#[cfg(unix)]
+fn page_range(ptr: *const u8, len: usize) -> (usize, impl Iterator<Item = usize>) {
+    let size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    };
+    let first = ptr as usize / size * size;
+    let last = (ptr as usize + len - 1) / size * size;
+    (size, (first..=last).step_by(size))
+}
+
+// Lock the pages containing the value into memory, unless another secret
+// already holds them. Failure is expected when the process lacks the privilege
+// or has reached its limit, in which case the value is simply not locked;
+// the page is counted all the same, as unlocking it later does no harm.
+#[cfg(unix)]
+fn lock_memory(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let (size, pages) = page_range(ptr, len);
+    for page in pages {
+        let count = locked.entry(page).or_insert(0);
+        if *count == 0 {
+            unsafe {
+                libc::mlock(page as *const libc::c_void, size);
+            }
+        }
+        *count += 1;
+    }
+}
+
+// Unlock the pages containing the value, unless another secret still holds
+// them.
+#[cfg(unix)]
+fn unlock_memory(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let (size, pages) = page_range(ptr, len);
+    for page in pages {
+        if let Some(count) = locked.get_mut(&page) {
+            *count -= 1;
+            if *count == 0 {
+                locked.remove(&page);
+                unsafe {
+                    libc::munlock(page as *const libc::c_void, size);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_memory(_ptr: *const u8, _len: usize) {}
+
+#[cfg(not(unix))]
+fn unlock_memory(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_redacted() {
+        let secret = Secret::from("keyboard cat");
+        assert_eq!(secret.expose(), "keyboard cat");
+        assert!(!secret.is_empty());
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        let copy = secret.clone();
+        drop(secret);
+        assert_eq!(copy.expose(), "keyboard cat");
+        let empty = Secret::from(String::new());
+        assert!(empty.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_locked_pages_counted() {
+        // an address range that no allocation will occupy, spanning two pages
+        let (size, _) = page_range(std::ptr::null(), 1);
+        let base = usize::MAX / 2 / size * size;
+        let ptr = (base + size - 8) as *const u8;
+        let count = |page: usize| LOCKED_PAGES.lock().unwrap().get(&page).copied();
+        lock_memory(ptr, 16);
+        lock_memory(ptr, 4);
+        assert_eq!(count(base), Some(2));
+        assert_eq!(count(base + size), Some(1));
+        unlock_memory(ptr, 16);
+        assert_eq!(count(base), Some(1));
+        assert_eq!(count(base + size), None);
+        unlock_memory(ptr, 4);
+        assert_eq!(count(base), None);
+    }
+}
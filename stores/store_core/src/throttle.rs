@@ -0,0 +1,250 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Limits the rate at which data is transferred to and from a pack store.
+
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Largest amount of data that is read or written in one step, such that the
+// transfer proceeds smoothly rather than in large bursts.
+const CHUNK_SIZE: usize = 65_536;
+
+// Throttles shared by all transfers to and from a given store.
+static SHARED: OnceLock<Mutex<HashMap<String, Arc<Throttle>>>> = OnceLock::new();
+
+///
+/// Paces the transfer of data so that the average throughput does not exceed
+/// the given number of bytes per second. A single throttle is meant to be
+/// shared by all of the transfers to and from a store, both uploads and
+/// downloads, whether they are blocking or asynchronous.
+///
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    // Time at which the next transfer may begin.
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Construct a throttle that allows the given number of bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Return the throttle for the store as given by the `bandwidth_limit`
+    /// property (in bytes per second), if any. Pack sources built for the same
+    /// store share the throttle, such that the limit applies to all of them.
+    pub fn from_properties(
+        store_id: &str,
+        props: &HashMap<String, String>,
+    ) -> Result<Option<Arc<Throttle>>, Error> {
+        let rate = match props.get("bandwidth_limit") {
+            Some(value) if !value.trim().is_empty() => {
+                value.trim().parse::<u64>().map_err(|_| {
+                    anyhow!(format!(
+                        "bandwidth_limit must be a number of bytes per second: {}",
+                        value
+                    ))
+                })?
+            }
+            _ => return Ok(None),
+        };
+        if rate == 0 {
+            return Ok(None);
+        }
+        let mut shared = SHARED.get_or_init(Default::default).lock().unwrap();
+        if let Some(throttle) = shared.get(store_id) {
+            if throttle.rate == rate {
+                return Ok(Some(throttle.clone()));
+            }
+        }
+        let throttle = Arc::new(Throttle::new(rate));
+        shared.insert(store_id.to_owned(), throttle.clone());
+        Ok(Some(throttle))
+    }
+
+    /// Return the number of bytes per second allowed by this throttle.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Reserve the transfer of the given number of bytes, returning the length
+    /// of time that the caller must wait before making the transfer.
+    ///
+    /// Asynchronous code should sleep for the returned duration using the
+    /// facilities of its runtime, rather than calling `pause()`.
+    pub fn reserve(&self, amount: usize) -> Duration {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        // time spent idle cannot be saved up for a later burst
+        let start = if *next < now { now } else { *next };
+        *next = start + Duration::from_secs_f64(amount as f64 / self.rate as f64);
+        start - now
+    }
+
+    /// Block the current thread until the given number of bytes may be
+    /// transferred.
+    pub fn pause(&self, amount: usize) {
+        let delay = self.reserve(amount);
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+///
+/// Reader that paces the data read from the inner reader according to the
+/// throttle, if any.
+///
+pub struct ThrottledReader<R> {
+    inner: R,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl<R> ThrottledReader<R> {
+    /// Wrap the given reader, which is read at full speed if there is no
+    /// throttle.
+    pub fn new(inner: R, throttle: Option<Arc<Throttle>>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.throttle.as_ref() {
+            Some(throttle) => {
+                let len = buf.len().min(CHUNK_SIZE);
+                let count = self.inner.read(&mut buf[..len])?;
+                throttle.pause(count);
+                Ok(count)
+            }
+            None => self.inner.read(buf),
+        }
+    }
+}
+
+impl<R: Seek> Seek for ThrottledReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+///
+/// Writer that paces the data written to the inner writer according to the
+/// throttle, if any.
+///
+pub struct ThrottledWriter<W> {
+    inner: W,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl<W> ThrottledWriter<W> {
+    /// Wrap the given writer, which is written at full speed if there is no
+    /// throttle.
+    pub fn new(inner: W, throttle: Option<Arc<Throttle>>) -> Self {
+        Self { inner, throttle }
+    }
+
+    /// Return the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.throttle.as_ref() {
+            Some(throttle) => {
+                let len = buf.len().min(CHUNK_SIZE);
+                throttle.pause(len);
+                self.inner.write(&buf[..len])
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_reserve() {
+        let throttle = Throttle::new(1000);
+        // the first transfer may proceed immediately
+        assert!(throttle.reserve(500).is_zero());
+        // the next must wait for the first to have taken its share of time
+        let delay = throttle.reserve(500);
+        assert!(delay > Duration::from_millis(400));
+        assert!(delay <= Duration::from_millis(500));
+        let delay = throttle.reserve(1);
+        assert!(delay > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_throttle_from_properties() {
+        let mut props: HashMap<String, String> = HashMap::new();
+        assert!(Throttle::from_properties("store1", &props)
+            .unwrap()
+            .is_none());
+        props.insert("bandwidth_limit".to_owned(), "0".to_owned());
+        assert!(Throttle::from_properties("store1", &props)
+            .unwrap()
+            .is_none());
+        props.insert("bandwidth_limit".to_owned(), "fast".to_owned());
+        assert!(Throttle::from_properties("store1", &props).is_err());
+        props.insert("bandwidth_limit".to_owned(), "1048576".to_owned());
+        let first = Throttle::from_properties("store1", &props)
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.rate(), 1048576);
+        // sources for the same store share the throttle
+        let second = Throttle::from_properties("store1", &props)
+            .unwrap()
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let other = Throttle::from_properties("store2", &props)
+            .unwrap()
+            .unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+        // a change to the limit replaces the throttle
+        props.insert("bandwidth_limit".to_owned(), "2097152".to_owned());
+        let changed = Throttle::from_properties("store1", &props)
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed.rate(), 2097152);
+    }
+
+    #[test]
+    fn test_throttled_reader_writer() {
+        let data: Vec<u8> = vec![7; 300_000];
+        let throttle = Arc::new(Throttle::new(1_000_000));
+        let started = Instant::now();
+        let mut reader = ThrottledReader::new(&data[..], Some(throttle.clone()));
+        let mut writer = ThrottledWriter::new(Vec::new(), None);
+        let copied = io::copy(&mut reader, &mut writer).unwrap();
+        assert_eq!(copied, 300_000);
+        assert_eq!(writer.into_inner(), data);
+        // all but the first chunk had to wait their turn
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        let mut reader = ThrottledReader::new(&data[..], None);
+        let mut writer = ThrottledWriter::new(Vec::new(), Some(throttle));
+        let copied = io::copy(&mut reader, &mut writer).unwrap();
+        assert_eq!(copied, 300_000);
+        assert_eq!(writer.into_inner(), data);
+    }
+}
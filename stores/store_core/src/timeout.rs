@@ -0,0 +1,285 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+//! Limits on how long the operations of a pack store may take.
+
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+///
+/// Time limits for the operations of a pack store, as given by the store
+/// properties. Any limit that is not given leaves the store to wait for as
+/// long as its client library would by default, which may be forever.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Timeouts {
+    /// Limit on establishing a connection to the service.
+    pub connect: Option<Duration>,
+    /// Longest wait for the service to respond or to send more data.
+    pub read: Option<Duration>,
+    /// Longest wait for the service to accept a request or more data.
+    pub write: Option<Duration>,
+    /// Limit on an entire operation, such as uploading a pack, regardless of
+    /// whether it is making progress.
+    pub operation: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Return the timeouts given by the `connect_timeout`, `read_timeout`,
+    /// `write_timeout`, and `operation_timeout` properties, each a number of
+    /// seconds, where zero is the same as not giving the property at all.
+    pub fn from_properties(props: &HashMap<String, String>) -> Result<Self, Error> {
+        Ok(Self {
+            connect: read_seconds(props, "connect_timeout")?,
+            read: read_seconds(props, "read_timeout")?,
+            write: read_seconds(props, "write_timeout")?,
+            operation: read_seconds(props, "operation_timeout")?,
+        })
+    }
+
+    /// Return `true` if none of the limits are defined.
+    pub fn is_empty(&self) -> bool {
+        self.connect.is_none()
+            && self.read.is_none()
+            && self.write.is_none()
+            && self.operation.is_none()
+    }
+
+    /// Return the longer of the read and write timeouts, for those clients
+    /// that have a single limit on waiting for the connection.
+    pub fn io(&self) -> Option<Duration> {
+        match (self.read, self.write) {
+            (Some(read), Some(write)) => Some(read.max(write)),
+            (read, write) => read.or(write),
+        }
+    }
+
+    /// Start the clock on the named operation, for those stores that check on
+    /// the operation timeout as the operation makes progress.
+    pub fn deadline(&self, operation: &str) -> Deadline {
+        Deadline::new(operation, self.operation)
+    }
+}
+
+// Read the named property as a positive number of seconds, if given.
+fn read_seconds(props: &HashMap<String, String>, name: &str) -> Result<Option<Duration>, Error> {
+    match props.get(name).map(|v| v.trim()) {
+        None | Some("") => Ok(None),
+        Some(value) => match value.parse::<u64>() {
+            Ok(0) => Ok(None),
+            Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+            Err(_) => Err(anyhow!(format!(
+                "{} must be a number of seconds: {}",
+                name, value
+            ))),
+        },
+    }
+}
+
+///
+/// Raised when an operation of a pack store did not complete within the time
+/// allowed by the store properties. Stores raise this error in place of the
+/// various timeout errors of their client libraries, such that the caller can
+/// treat every timeout in the same manner.
+///
+#[derive(thiserror::Error, Clone, Debug, Eq, PartialEq)]
+pub struct TimeoutError {
+    /// Name of the operation that timed out, such as `store_pack`.
+    pub operation: String,
+    /// The limit that was exceeded, if known.
+    pub limit: Option<Duration>,
+}
+
+impl TimeoutError {
+    /// Construct an error for the named operation.
+    pub fn new(operation: &str, limit: Option<Duration>) -> Self {
+        Self {
+            operation: operation.to_owned(),
+            limit,
+        }
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            Some(limit) => write!(f, "{} timed out after {:?}", self.operation, limit),
+            None => write!(f, "{} timed out", self.operation),
+        }
+    }
+}
+
+///
+/// Replace the error with a `TimeoutError` if it was caused by a timeout,
+/// such as a socket read that timed out within the client library, otherwise
+/// return the error unchanged.
+///
+pub fn classify_timeout(err: Error, operation: &str, limit: Option<Duration>) -> Error {
+    if err.is::<TimeoutError>() {
+        return err;
+    }
+    let mut timeout: Option<TimeoutError> = None;
+    for cause in err.chain() {
+        if let Some(found) = cause.downcast_ref::<TimeoutError>() {
+            timeout = Some(found.clone());
+            break;
+        }
+        if let Some(ioerr) = cause.downcast_ref::<io::Error>() {
+            if let Some(found) = ioerr
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<TimeoutError>())
+            {
+                timeout = Some(found.clone());
+                break;
+            }
+            // would-block only means the operation could not finish yet,
+            // which is left as an ordinary error for the caller to retry
+            if ioerr.kind() == io::ErrorKind::TimedOut {
+                timeout = Some(TimeoutError::new(operation, limit));
+                break;
+            }
+        }
+    }
+    match timeout {
+        Some(timeout) => Error::from(timeout),
+        None => err,
+    }
+}
+
+///
+/// The time by which an operation must be finished, if there is a limit.
+///
+#[derive(Clone, Debug)]
+pub struct Deadline {
+    operation: String,
+    limit: Option<Duration>,
+    started: Instant,
+}
+
+impl Deadline {
+    /// Start the clock on the named operation with the given limit, if any.
+    pub fn new(operation: &str, limit: Option<Duration>) -> Self {
+        Self {
+            operation: operation.to_owned(),
+            limit,
+            started: Instant::now(),
+        }
+    }
+
+    /// Return the time remaining before the deadline, if there is a limit.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.limit
+            .map(|limit| limit.saturating_sub(self.started.elapsed()))
+    }
+
+    /// Return an error if the deadline has passed.
+    pub fn check(&self) -> Result<(), TimeoutError> {
+        match self.remaining() {
+            Some(remaining) if remaining.is_zero() => {
+                Err(TimeoutError::new(&self.operation, self.limit))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+///
+/// Reader that fails with a `TimeoutError` (within an `io::Error`) once the
+/// deadline has passed, for stores that copy data using blocking I/O.
+///
+pub struct DeadlineReader<R> {
+    inner: R,
+    deadline: Deadline,
+}
+
+impl<R> DeadlineReader<R> {
+    /// Wrap the given reader, which is read as usual until the deadline.
+    pub fn new(inner: R, deadline: Deadline) -> Self {
+        Self { inner, deadline }
+    }
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.deadline
+            .check()
+            .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))?;
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_timeouts_from_properties() {
+        let mut props: HashMap<String, String> = HashMap::new();
+        let timeouts = Timeouts::from_properties(&props).unwrap();
+        assert!(timeouts.is_empty());
+        props.insert("connect_timeout".to_owned(), "10".to_owned());
+        props.insert("read_timeout".to_owned(), "30".to_owned());
+        props.insert("write_timeout".to_owned(), "60".to_owned());
+        props.insert("operation_timeout".to_owned(), "0".to_owned());
+        let timeouts = Timeouts::from_properties(&props).unwrap();
+        assert_eq!(timeouts.connect, Some(Duration::from_secs(10)));
+        assert_eq!(timeouts.read, Some(Duration::from_secs(30)));
+        assert_eq!(timeouts.write, Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.operation, None);
+        assert_eq!(timeouts.io(), Some(Duration::from_secs(60)));
+        props.insert("operation_timeout".to_owned(), "forever".to_owned());
+        let result = Timeouts::from_properties(&props);
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("operation_timeout must be a number of seconds"));
+    }
+
+    #[test]
+    fn test_classify_timeout() {
+        let limit = Some(Duration::from_secs(5));
+        // errors other than timeouts are left alone
+        let err = classify_timeout(anyhow!("access denied"), "list_buckets", limit);
+        assert!(!err.is::<TimeoutError>());
+        // socket timeouts become timeout errors
+        let ioerr = io::Error::new(io::ErrorKind::TimedOut, "connection timed out");
+        let err = classify_timeout(Error::from(ioerr).context("oh no"), "list_buckets", limit);
+        let timeout = err.downcast::<TimeoutError>().unwrap();
+        assert_eq!(timeout, TimeoutError::new("list_buckets", limit));
+        // would-block is transient but is not a timeout
+        let ioerr = io::Error::new(io::ErrorKind::WouldBlock, "resource unavailable");
+        let err = classify_timeout(Error::from(ioerr), "list_buckets", limit);
+        assert!(!err.is::<TimeoutError>());
+        assert!(err.is::<io::Error>());
+        // timeout errors within an io::Error are recovered as-is
+        let inner = TimeoutError::new("store_pack", None);
+        let ioerr = io::Error::new(io::ErrorKind::TimedOut, inner.clone());
+        let err = classify_timeout(Error::from(ioerr), "list_buckets", limit);
+        assert_eq!(err.downcast::<TimeoutError>().unwrap(), inner);
+        assert_eq!(inner.to_string(), "store_pack timed out");
+    }
+
+    #[test]
+    fn test_deadline_reader() {
+        let data: Vec<u8> = vec![7; 1000];
+        let deadline = Deadline::new("retrieve_pack", None);
+        let mut reader = DeadlineReader::new(&data[..], deadline);
+        let mut copied: Vec<u8> = Vec::new();
+        assert_eq!(io::copy(&mut reader, &mut copied).unwrap(), 1000);
+
+        let deadline = Deadline::new("retrieve_pack", Some(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(deadline.remaining(), Some(Duration::ZERO));
+        let mut reader = DeadlineReader::new(&data[..], deadline);
+        let result = io::copy(&mut reader, &mut copied);
+        assert!(result.is_err());
+        let err = classify_timeout(Error::from(result.unwrap_err()), "other", None);
+        let timeout = err.downcast::<TimeoutError>().unwrap();
+        assert_eq!(timeout.operation, "retrieve_pack");
+        assert_eq!(timeout.limit, Some(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,580 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use store_core::{
+    classify_timeout, Coordinates, DeadlineReader, PackDataSource, Secret, Throttle,
+    ThrottledReader, TimeoutError, Timeouts,
+};
+
+// Endpoint for the calls whose arguments and results are JSON.
+const API_URL: &str = "https://api.dropboxapi.com/2/";
+
+// Endpoint for the calls that upload or download file content.
+const CONTENT_URL: &str = "https://content.dropboxapi.com/2/";
+
+// Endpoint for exchanging a refresh token for an access token.
+const TOKEN_URL: &str = "https://api.dropboxapi.com/oauth2/token";
+
+// Largest file that may be uploaded in a single request.
+const SINGLE_UPLOAD_LIMIT: u64 = 150 * 1_048_576;
+
+// Size of each part of a file uploaded via an upload session, which must be a
+// multiple of 4 MiB and no larger than the single upload limit.
+const CHUNK_SIZE: u64 = 64 * 1_048_576;
+
+// Limits on connecting and reading when not given by the store properties.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Access tokens are refreshed this long before they actually expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+
+// Number of times to retry a call that was rejected due to rate limiting.
+const MAX_RETRIES: u32 = 3;
+
+// Means by which the store obtains an access token.
+#[derive(Debug)]
+enum Credentials {
+    // long-lived token generated in the app console
+    Token(Secret),
+    // refresh token obtained via the OAuth flow, with the app key and secret
+    Refresh {
+        app_key: String,
+        app_secret: Option<Secret>,
+        refresh_token: Secret,
+    },
+}
+
+// Access token obtained with a refresh token, and when it expires.
+#[derive(Debug)]
+struct AccessToken {
+    value: Secret,
+    expires: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct FolderEntry {
+    #[serde(rename = ".tag")]
+    tag: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ListFolderResult {
+    entries: Vec<FolderEntry>,
+    cursor: String,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct UploadSessionStart {
+    session_id: String,
+}
+
+///
+/// A `PackDataSource` implementation that stores pack files in Dropbox, with
+/// each bucket being a folder within the `basepath` folder.
+///
+#[derive(Debug)]
+pub struct DropboxStore {
+    store_id: String,
+    // either empty for the root folder, or a path with a leading slash
+    basepath: String,
+    credentials: Credentials,
+    // access token obtained with the refresh token, if any
+    token: Mutex<Option<AccessToken>>,
+    agent: ureq::Agent,
+    throttle: Option<Arc<Throttle>>,
+    timeouts: Timeouts,
+}
+
+impl DropboxStore {
+    /// Validate the given store and construct a Dropbox pack source.
+    pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
+        let non_empty = |name: &str| props.get(name).filter(|s| !s.is_empty());
+        let credentials = if let Some(token) = non_empty("access_token") {
+            Credentials::Token(Secret::from(token.as_str()))
+        } else if let Some(refresh_token) = non_empty("refresh_token") {
+            let app_key =
+                non_empty("app_key").ok_or_else(|| anyhow!("missing app_key property"))?;
+            Credentials::Refresh {
+                app_key: app_key.to_owned(),
+                app_secret: non_empty("app_secret").map(|s| Secret::from(s.as_str())),
+                refresh_token: Secret::from(refresh_token.as_str()),
+            }
+        } else {
+            return Err(anyhow!("missing access_token or refresh_token property"));
+        };
+        let basepath = normalize_basepath(props.get("basepath").map_or("", |s| s.as_str()));
+        let timeouts = Timeouts::from_properties(props)?;
+        let mut builder = ureq::AgentBuilder::new()
+            .timeout_connect(timeouts.connect.unwrap_or(DEFAULT_CONNECT_TIMEOUT))
+            .timeout_read(timeouts.read.unwrap_or(DEFAULT_READ_TIMEOUT));
+        if let Some(limit) = timeouts.write {
+            builder = builder.timeout_write(limit);
+        }
+        // applies to each request, including the reading of the response
+        if let Some(limit) = timeouts.operation {
+            builder = builder.timeout(limit);
+        }
+        let agent = builder.build();
+        Ok(Self {
+            store_id: store_id.to_owned(),
+            basepath,
+            credentials,
+            token: Mutex::new(None),
+            agent,
+            throttle: Throttle::from_properties(store_id, props)?,
+            timeouts,
+        })
+    }
+
+    // Build the Dropbox path of the named bucket, or of an object within it.
+    fn remote_path(&self, bucket: &str, object: Option<&str>) -> String {
+        match object {
+            Some(name) => format!("{}/{}/{}", self.basepath, bucket, name),
+            None => format!("{}/{}", self.basepath, bucket),
+        }
+    }
+
+    // Return the value for the authorization header, refreshing the access
+    // token if it is missing or about to expire.
+    fn authorization(&self) -> Result<String, Error> {
+        let (app_key, app_secret, refresh_token) = match &self.credentials {
+            Credentials::Token(token) => return Ok(format!("Bearer {}", token.expose())),
+            Credentials::Refresh {
+                app_key,
+                app_secret,
+                refresh_token,
+            } => (app_key, app_secret, refresh_token),
+        };
+        let mut guard = self.token.lock().unwrap();
+        if let Some(token) = guard.as_ref() {
+            if token.expires > Instant::now() + EXPIRY_MARGIN {
+                return Ok(format!("Bearer {}", token.value.expose()));
+            }
+        }
+        let mut form: Vec<(&str, &str)> = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.expose()),
+            ("client_id", app_key),
+        ];
+        if let Some(secret) = app_secret.as_ref() {
+            form.push(("client_secret", secret.expose()));
+        }
+        let response: TokenResponse = self
+            .agent
+            .post(TOKEN_URL)
+            .send_form(&form)
+            .map_err(|err| call_error("oauth2/token", err))?
+            .into_json()?;
+        let token = AccessToken {
+            value: Secret::from(response.access_token),
+            expires: Instant::now() + Duration::from_secs(response.expires_in),
+        };
+        let header = format!("Bearer {}", token.value.expose());
+        *guard = Some(token);
+        Ok(header)
+    }
+
+    // Invoke an endpoint whose arguments and results are JSON, retrying if the
+    // request was rejected due to rate limiting.
+    fn rpc(&self, endpoint: &str, args: Value) -> Result<Value, Error> {
+        let url = format!("{}{}", API_URL, endpoint);
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .agent
+                .post(&url)
+                .set("Authorization", &self.authorization()?)
+                .send_json(args.clone());
+            match result {
+                Ok(response) => return Ok(response.into_json()?),
+                Err(ureq::Error::Status(429, response)) if attempt < MAX_RETRIES => {
+                    attempt += 1;
+                    std::thread::sleep(retry_after(&response));
+                }
+                Err(err) => return Err(call_error(endpoint, err)),
+            }
+        }
+    }
+
+    // Invoke an endpoint that transfers file content, with the arguments given
+    // in the header and the content (if any) in the body.
+    fn content<R: Read>(
+        &self,
+        endpoint: &str,
+        args: Value,
+        body: Option<R>,
+    ) -> Result<ureq::Response, Error> {
+        let url = format!("{}{}", CONTENT_URL, endpoint);
+        let request = self
+            .agent
+            .post(&url)
+            .set("Authorization", &self.authorization()?)
+            .set("Dropbox-API-Arg", &header_json(&args));
+        let result = match body {
+            Some(reader) => request
+                .set("Content-Type", "application/octet-stream")
+                .send(ThrottledReader::new(reader, self.throttle.clone())),
+            None => request.call(),
+        };
+        result.map_err(|err| call_error(endpoint, err))
+    }
+
+    // Upload a large file in parts via an upload session, with the operation
+    // timeout applying to the upload as a whole rather than each part.
+    fn upload_session(&self, packfile: &Path, path: &str, length: u64) -> Result<(), Error> {
+        let file = File::open(packfile)?;
+        let mut file = DeadlineReader::new(file, self.timeouts.deadline("store_pack"));
+        let response = self.content(
+            "files/upload_session/start",
+            json!({ "close": false }),
+            Some((&mut file).take(CHUNK_SIZE)),
+        )?;
+        let session: UploadSessionStart = response.into_json()?;
+        let mut offset = CHUNK_SIZE.min(length);
+        while length - offset > CHUNK_SIZE {
+            self.content(
+                "files/upload_session/append_v2",
+                json!({
+                    "cursor": { "session_id": session.session_id, "offset": offset },
+                    "close": false
+                }),
+                Some((&mut file).take(CHUNK_SIZE)),
+            )?;
+            offset += CHUNK_SIZE;
+        }
+        // the finish call carries whatever remains of the file
+        self.content(
+            "files/upload_session/finish",
+            json!({
+                "cursor": { "session_id": session.session_id, "offset": offset },
+                "commit": { "path": path, "mode": "overwrite", "mute": true }
+            }),
+            Some(&mut file),
+        )?;
+        Ok(())
+    }
+
+    // List the names of the entries in the folder with the given tag.
+    fn list_folder(&self, path: &str, tag: &str) -> Result<Vec<String>, Error> {
+        let mut results = Vec::new();
+        let mut listing = self.rpc("files/list_folder", json!({ "path": path }))?;
+        loop {
+            let page: ListFolderResult = serde_json::from_value(listing)?;
+            for entry in page.entries {
+                // ignore entries that are definitely not ours
+                if entry.tag == tag && !entry.name.starts_with('.') {
+                    results.push(entry.name);
+                }
+            }
+            if !page.has_more {
+                break;
+            }
+            listing = self.rpc(
+                "files/list_folder/continue",
+                json!({ "cursor": page.cursor }),
+            )?;
+        }
+        Ok(results)
+    }
+}
+
+impl PackDataSource for DropboxStore {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        // folders are created implicitly by uploading files into them
+        let path = self.remote_path(bucket, Some(object));
+        let length = fs::metadata(packfile)?.len();
+        if length > SINGLE_UPLOAD_LIMIT {
+            self.upload_session(packfile, &path, length)?;
+        } else {
+            let file = File::open(packfile)?;
+            self.content(
+                "files/upload",
+                json!({ "path": path, "mode": "overwrite", "mute": true }),
+                Some(file),
+            )?;
+        }
+        let loc = Coordinates::new(&self.store_id, bucket, object);
+        Ok(loc)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        let path = self.remote_path(&location.bucket, Some(&location.object));
+        let response = self.content("files/download", json!({ "path": path }), None::<File>)?;
+        let mut remote = ThrottledReader::new(response.into_reader(), self.throttle.clone());
+        let mut local = File::create(outfile)?;
+        io::copy(&mut remote, &mut local)
+            .map_err(|e| classify_timeout(Error::from(e), "retrieve_pack", self.timeouts.read))?;
+        Ok(())
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        match self.list_folder(&self.basepath, "folder") {
+            Ok(names) => Ok(names),
+            // the base folder does not exist until something is stored
+            Err(err) if err.to_string().contains("path/not_found") => Ok(vec![]),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        let path = self.remote_path(bucket, None);
+        self.list_folder(&path, "file")
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        let path = self.remote_path(bucket, Some(object));
+        self.rpc("files/delete_v2", json!({ "path": path }))?;
+        Ok(())
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        let path = self.remote_path(bucket, None);
+        self.rpc("files/delete_v2", json!({ "path": path }))?;
+        Ok(())
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects(bucket)
+    }
+}
+
+// Convert the base path to the form Dropbox expects, in which the root folder
+// is the empty string and every other path starts with a slash.
+fn normalize_basepath(basepath: &str) -> String {
+    let trimmed = basepath.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+// Serialize the arguments for the Dropbox-API-Arg header, which must be plain
+// ASCII, hence any other characters are escaped.
+fn header_json(args: &Value) -> String {
+    let mut result = String::new();
+    for ch in args.to_string().chars() {
+        if ch.is_ascii() {
+            result.push(ch);
+        } else {
+            let mut buf = [0u16; 2];
+            for unit in ch.encode_utf16(&mut buf) {
+                result.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    result
+}
+
+// Return the time to wait before retrying a rate limited request.
+fn retry_after(response: &ureq::Response) -> Duration {
+    let seconds = response
+        .header("Retry-After")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(1);
+    Duration::from_secs(seconds)
+}
+
+// Convert the error from a call into one that includes the error summary from
+// the response, if any, such as "path/not_found/". Transport errors caused by
+// a timeout are raised as a `TimeoutError` for the endpoint.
+fn call_error(endpoint: &str, err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let summary = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|v| v["error_summary"].as_str().map(|s| s.to_owned()))
+                .unwrap_or(body);
+            anyhow!(format!(
+                "dropbox {} failed ({}): {}",
+                endpoint,
+                code,
+                summary.trim()
+            ))
+        }
+        ureq::Error::Transport(transport) => {
+            let message = format!("dropbox {} failed: {}", endpoint, transport);
+            let err = classify_timeout(Error::from(transport), endpoint, None);
+            if err.is::<TimeoutError>() {
+                err
+            } else {
+                anyhow!(message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_new_dropbox_store_credentials() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        let result = DropboxStore::new("dropbox123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing access_token or refresh_token"));
+
+        properties.insert("refresh_token".to_owned(), "refreshing".to_owned());
+        let result = DropboxStore::new("dropbox123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing app_key property"));
+
+        properties.insert("app_key".to_owned(), "k3y".to_owned());
+        let result = DropboxStore::new("dropbox123", &properties);
+        assert!(result.is_ok());
+
+        properties.insert("read_timeout".to_owned(), "soon".to_owned());
+        let result = DropboxStore::new("dropbox123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("read_timeout must be a number of seconds"));
+    }
+
+    #[test]
+    fn test_new_dropbox_store_ok() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("access_token".to_owned(), "sl.t0k3n".to_owned());
+        properties.insert("basepath".to_owned(), "backups/".to_owned());
+        let result = DropboxStore::new("dropbox123", &properties);
+        assert!(result.is_ok());
+        let source = result.unwrap();
+        assert!(!source.is_local());
+        assert!(!source.is_slow());
+        assert_eq!(source.authorization().unwrap(), "Bearer sl.t0k3n");
+        assert_eq!(
+            source.remote_path("bucket", Some("object")),
+            "/backups/bucket/object"
+        );
+        assert_eq!(source.remote_path("bucket", None), "/backups/bucket");
+    }
+
+    #[test]
+    fn test_normalize_basepath() {
+        assert_eq!(normalize_basepath(""), "");
+        assert_eq!(normalize_basepath("/"), "");
+        assert_eq!(normalize_basepath("zorigami"), "/zorigami");
+        assert_eq!(normalize_basepath("/zorigami/"), "/zorigami");
+        assert_eq!(normalize_basepath("/apps/zorigami"), "/apps/zorigami");
+    }
+
+    #[test]
+    fn test_header_json() {
+        let args = json!({ "path": "/backups/bucket" });
+        assert_eq!(header_json(&args), r#"{"path":"/backups/bucket"}"#);
+        let args = json!({ "path": "/bänd/🎸" });
+        assert_eq!(header_json(&args), r#"{"path":"/b\u00e4nd/\ud83c\udfb8"}"#);
+    }
+
+    #[test]
+    fn test_list_folder_result() {
+        let body = r#"{
+            "entries": [
+                {".tag": "folder", "name": "bucket1", "id": "id:a4ayc_80_OEAAAAAAAAAXw"},
+                {".tag": "file", "name": "object1", "size": 7212},
+                {".tag": "folder", "name": ".hidden"}
+            ],
+            "cursor": "ZtkX9_EHj3x7PMkVuFIhwKYXEpwpLwyxp9vMKomUhllil9q7eWiAu",
+            "has_more": false
+        }"#;
+        let result: ListFolderResult = serde_json::from_str(body).unwrap();
+        assert_eq!(result.entries.len(), 3);
+        assert_eq!(result.entries[0].tag, "folder");
+        assert_eq!(result.entries[1].name, "object1");
+        assert!(!result.has_more);
+    }
+
+    #[test]
+    fn test_dropbox_roundtrip() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let token_var = env::var("DROPBOX_TOKEN");
+        if token_var.is_err() {
+            // bail out silently if dropbox is not configured
+            return Ok(());
+        }
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("access_token".to_owned(), token_var.unwrap());
+        properties.insert("basepath".to_owned(), "/zorigami-test".to_owned());
+        let source = DropboxStore::new("dropboxone", &properties)?;
+
+        // store an object
+        let bucket = "0b7bbcbc3a0a5cd7a5a6f33b4d3d1b39".to_owned();
+        let object = "39c6061a56b7711f92c6ccd2047d47fdcc1609c1".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_pack(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "dropboxone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // check for bucket and object being present
+        let buckets = source.list_buckets()?;
+        assert!(buckets.contains(&bucket));
+        let listing = source.list_objects(&bucket)?;
+        assert!(listing.contains(&object));
+
+        // retrieve the file and verify by checksum
+        let outdir = tempdir()?;
+        let outfile = outdir.path().join("restored.txt");
+        source.retrieve_pack(&location, &outfile)?;
+        let md5sum = store_core::md5sum_file(&outfile)?;
+        assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
+
+        // remove the object and the bucket
+        source.delete_object(&bucket, &object)?;
+        source.delete_bucket(&bucket)?;
+        let buckets = source.list_buckets()?;
+        assert!(!buckets.contains(&bucket));
+        Ok(())
+    }
+}
@@ -4,13 +4,38 @@
 extern crate google_firestore1 as firestore1;
 extern crate google_storage1 as storage1;
 use anyhow::{anyhow, Error};
+use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
 use std::collections::HashMap;
 use std::default::Default;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use storage1::hyper::client::HttpConnector;
 use storage1::hyper_rustls::HttpsConnector;
-use store_core::{CollisionError, Coordinates};
+use store_core::{
+    is_rename_marker, AsyncPackDataSource, Checkpoint, CheckpointStore, CollisionError,
+    Coordinates, LifecycleRule, ObjectRenames, PackDataSource, RenameTracker, Throttle,
+    ThrottledReader, TimeoutError, Timeouts, PACK_PREFIXES,
+};
+
+// Storage class of objects that must remain readable at all times.
+const STANDARD_CLASS: &str = "STANDARD";
+
+// Rule for buckets that are to have no lifecycle rule at all.
+const NO_LIFECYCLE: LifecycleRule = LifecycleRule {
+    transition_days: None,
+    storage_class: None,
+    expiration_days: None,
+};
+
+// Where the new names of renamed buckets are recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Renames {
+    None,
+    Firestore,
+    Object,
+}
 
 #[derive(Clone, Debug)]
 pub struct GoogleStore {
@@ -19,10 +44,25 @@ pub struct GoogleStore {
     project: String,
     region: Option<String>,
     storage: Option<String>,
+    kms_key: Option<String>,
+    renames: Renames,
+    pack_lifecycle: LifecycleRule,
+    checkpoints: Option<Arc<dyn CheckpointStore>>,
+    throttle: Option<Arc<Throttle>>,
+    timeouts: Timeouts,
 }
 
 impl GoogleStore {
     /// Validate the given store and construct a google pack source.
+    ///
+    /// Pack buckets created by the store are given the lifecycle rule defined
+    /// by the `lifecycle_days` and `lifecycle_class` properties (see
+    /// `LifecycleRule`), while the database bucket is given none at all.
+    ///
+    /// The `connect_timeout` applies to establishing each connection, the
+    /// `read_timeout` to waiting for each response or portion of a download,
+    /// the `write_timeout` to the upload of each pack, and the
+    /// `operation_timeout` to each store operation as a whole.
     pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
         let credentials = props
             .get("credentials")
@@ -32,22 +72,59 @@ impl GoogleStore {
             .ok_or_else(|| anyhow!("missing project property"))?;
         let region = props.get("region").map(|s| s.to_owned());
         let storage = props.get("storage").map(|s| s.to_owned());
+        let kms_key = match props.get("kms_key").map(|s| s.trim()) {
+            Some(key) if !key.is_empty() => Some(validate_kms_key(key)?),
+            _ => None,
+        };
+        let renames = match props.get("renames").map(|v| v.trim()) {
+            None | Some("") | Some("firestore") => Renames::Firestore,
+            Some("object") => Renames::Object,
+            Some("none") => Renames::None,
+            Some(other) => {
+                return Err(anyhow!(format!(
+                    "renames must be one of firestore, object, or none: {}",
+                    other
+                )))
+            }
+        };
         Ok(Self {
             store_id: store_id.to_owned(),
             credentials: credentials.to_owned(),
             project: project.to_owned(),
             region,
             storage,
+            kms_key,
+            renames,
+            pack_lifecycle: LifecycleRule::for_packs(props)?,
+            checkpoints: None,
+            throttle: Throttle::from_properties(store_id, props)?,
+            timeouts: Timeouts::from_properties(props)?,
         })
     }
 
+    /// Record the resumable upload session using the given checkpoint store,
+    /// such that an interrupted upload will be resumed rather than restarted.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<dyn CheckpointStore>) -> Self {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    // Build the connector that gives up on connecting after the connect
+    // timeout, if any, which is otherwise left to the operating system.
+    fn http_connector(&self) -> HttpConnector {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(self.timeouts.connect);
+        http
+    }
+
     async fn connect(&self) -> Result<storage1::Storage<HttpsConnector<HttpConnector>>, Error> {
         let conn = storage1::hyper_rustls::HttpsConnectorBuilder::new()
             .with_native_roots()
             .https_or_http()
             .enable_http1()
             .enable_http2()
-            .build();
+            .wrap_connector(self.http_connector());
         let https_client = storage1::hyper::Client::builder().build(conn);
         let account_key = storage1::oauth2::read_service_account_key(&self.credentials).await?;
         let authenticator = storage1::oauth2::ServiceAccountAuthenticator::builder(account_key)
@@ -65,7 +142,7 @@ impl GoogleStore {
             .https_or_http()
             .enable_http1()
             .enable_http2()
-            .build();
+            .wrap_connector(self.http_connector());
         let https_client = firestore1::hyper::Client::builder().build(conn);
         let account_key = firestore1::oauth2::read_service_account_key(&self.credentials).await?;
         let authenticator = firestore1::oauth2::ServiceAccountAuthenticator::builder(account_key)
@@ -82,7 +159,12 @@ impl GoogleStore {
         object: &str,
     ) -> Result<Coordinates, Error> {
         // use and_then(std::convert::identity) until Result.flatten() is stable
-        block_on(self.store_pack(packfile, bucket, object)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "store_pack",
+            self.store_pack(packfile, bucket, object),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn store_pack(
@@ -90,24 +172,73 @@ impl GoogleStore {
         packfile: &Path,
         bucket: &str,
         object: &str,
+    ) -> Result<Coordinates, Error> {
+        // the bucket rename marker must remain readable at all times
+        let lifecycle = if is_rename_marker(object) {
+            &NO_LIFECYCLE
+        } else {
+            &self.pack_lifecycle
+        };
+        self.upload(packfile, bucket, object, lifecycle).await
+    }
+
+    // Upload the file to the bucket, creating the bucket with the given
+    // lifecycle rule if it does not yet exist.
+    async fn upload(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+        lifecycle: &LifecycleRule,
     ) -> Result<Coordinates, Error> {
         let hub = self.connect().await?;
         // the bucket must exist before receiving objects
-        create_bucket(&hub, &self.project, bucket, &self.region, &self.storage).await?;
-        let req = storage1::api::Object::default();
+        create_bucket(
+            &hub,
+            &self.project,
+            bucket,
+            &self.region,
+            &self.storage,
+            &self.kms_key,
+            lifecycle,
+        )
+        .await?;
+        let req = storage1::api::Object {
+            storage_class: is_rename_marker(object).then(|| STANDARD_CLASS.to_owned()),
+            ..Default::default()
+        };
+        // the client reads the file synchronously, so a throttled reader will
+        // simply block this thread while waiting
         let infile = std::fs::File::open(packfile)?;
+        let infile = ThrottledReader::new(infile, self.throttle.clone());
         let mimetype = "application/octet-stream"
             .parse()
             .map_err(|e| anyhow!(format!("{:?}", e)))?;
+        let coords = Coordinates::new(&self.store_id, bucket, object);
+        let mut delegate = match self.checkpoints.as_ref() {
+            Some(checkpoints) => Some(SessionDelegate {
+                checkpoints: checkpoints.clone(),
+                coords: coords.clone(),
+                fingerprint: store_core::fingerprint_file(packfile)?,
+            }),
+            None => None,
+        };
+        let mut call = hub.objects().insert(req, bucket).name(object);
+        if let Some(key) = self.kms_key.as_ref() {
+            // the key is needed only for writing, the service decrypts the
+            // object transparently when it is read
+            call = call.kms_key_name(key);
+        }
+        if let Some(dlg) = delegate.as_mut() {
+            call = call.delegate(dlg);
+        }
         // storing the same object twice is not treated as an error
-        match hub
-            .objects()
-            .insert(req, bucket)
-            .name(object)
-            .upload_resumable(infile, mimetype)
-            .await
-        {
+        let upload = async { Ok(call.upload_resumable(infile, mimetype).await) };
+        match within(self.timeouts.write, "store_pack", upload).await? {
             Ok((_response, objdata)) => {
+                if let Some(checkpoints) = self.checkpoints.as_ref() {
+                    checkpoints.delete_checkpoint(&coords)?;
+                }
                 // ensure uploaded file matches local contents
                 if let Some(hash) = objdata.md5_hash.as_ref() {
                     let returned = general_purpose::STANDARD.decode(hash)?;
@@ -116,7 +247,7 @@ impl GoogleStore {
                         return Err(anyhow!("returned md5_hash does not match MD5 of pack file"));
                     }
                 }
-                Ok(Coordinates::new(&self.store_id, bucket, object))
+                Ok(coords)
             }
             Err(error) => match &error {
                 // detect the case of a bucket that exists but belongs to
@@ -142,27 +273,54 @@ impl GoogleStore {
     }
 
     pub fn retrieve_pack_sync(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
-        block_on(self.retrieve_pack(location, outfile)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            self.retrieve_pack(location, outfile),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
         let hub = self.connect().await?;
-        let (response, _object) = hub
+        let call = hub
             .objects()
             .get(&location.bucket, &location.object)
-            .param("alt", "media")
-            .doit()
-            .await?;
-        let buf = storage1::hyper::body::aggregate(response).await?;
-        use storage1::hyper::body::Buf;
-        let mut remote = buf.reader();
+            .param("alt", "media");
+        let request = async { Ok(call.doit().await?) };
+        let (response, _object) = within(self.timeouts.read, "retrieve_pack", request).await?;
         let mut local = std::fs::File::create(outfile)?;
-        std::io::copy(&mut remote, &mut local)?;
+        if self.throttle.is_none() && self.timeouts.read.is_none() {
+            let buf = storage1::hyper::body::aggregate(response).await?;
+            use storage1::hyper::body::Buf;
+            let mut remote = buf.reader();
+            std::io::copy(&mut remote, &mut local)?;
+        } else {
+            use std::io::Write;
+            use storage1::hyper::body::HttpBody;
+            let mut body = response.into_body();
+            loop {
+                let next = async { body.data().await.transpose().map_err(Error::from) };
+                let chunk = match within(self.timeouts.read, "retrieve_pack", next).await? {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                if let Some(throttle) = self.throttle.as_ref() {
+                    tokio::time::sleep(throttle.reserve(chunk.len())).await;
+                }
+                local.write_all(&chunk)?;
+            }
+        }
         Ok(())
     }
 
     pub fn list_buckets_sync(&self) -> Result<Vec<String>, Error> {
-        block_on(self.list_buckets()).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "list_buckets",
+            self.list_buckets(),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn list_buckets(&self) -> Result<Vec<String>, Error> {
@@ -205,7 +363,12 @@ impl GoogleStore {
     }
 
     pub fn list_objects_sync(&self, bucket: &str) -> Result<Vec<String>, Error> {
-        block_on(self.list_objects(bucket)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "list_objects",
+            self.list_objects(bucket),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
@@ -227,7 +390,10 @@ impl GoogleStore {
                         // names be provided when uploading them.
                         for object in objs.iter() {
                             if let Some(name) = object.name.as_ref() {
-                                results.push(name.to_owned());
+                                // hide the bucket rename marker from the server
+                                if !is_rename_marker(name) {
+                                    results.push(name.to_owned());
+                                }
                             }
                         }
                     }
@@ -243,7 +409,12 @@ impl GoogleStore {
     }
 
     pub fn delete_object_sync(&self, bucket: &str, object: &str) -> Result<(), Error> {
-        block_on(self.delete_object(bucket, object)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "delete_object",
+            self.delete_object(bucket, object),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
@@ -252,8 +423,115 @@ impl GoogleStore {
         Ok(())
     }
 
+    pub fn set_storage_class_sync(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "set_storage_class",
+            self.set_storage_class(bucket, object, class),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Change the storage class of the object by rewriting it in place.
+    /// Returns `false` if the object already has the given storage class.
+    pub async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        let hub = self.connect().await?;
+        let (_, current) = hub.objects().get(bucket, object).doit().await?;
+        if current.storage_class.as_deref() == Some(class) {
+            return Ok(false);
+        }
+        let req = storage1::api::Object {
+            storage_class: Some(class.to_owned()),
+            ..Default::default()
+        };
+        // large objects may require multiple calls to complete the rewrite
+        let mut token: Option<String> = None;
+        loop {
+            let mut call = hub
+                .objects()
+                .rewrite(req.clone(), bucket, object, bucket, object);
+            if let Some(ref value) = token {
+                call = call.rewrite_token(value);
+            }
+            if let Some(ref key) = self.kms_key {
+                // otherwise the rewritten object would use the default key
+                call = call.destination_kms_key_name(key);
+            }
+            let (_, response) = call.doit().await?;
+            if response.done.unwrap_or(false) || response.rewrite_token.is_none() {
+                break;
+            }
+            token = response.rewrite_token;
+        }
+        Ok(true)
+    }
+
+    pub fn object_md5_sync(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "object_md5",
+            self.object_md5(location),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Return the MD5 digest of the object from its metadata, which composite
+    /// objects do not have.
+    pub async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        let hub = self.connect().await?;
+        let (_, objdata) = hub
+            .objects()
+            .get(&location.bucket, &location.object)
+            .doit()
+            .await?;
+        match objdata.md5_hash.as_ref() {
+            Some(hash) => {
+                let decoded = general_purpose::STANDARD.decode(hash)?;
+                let hex: Vec<String> = decoded.iter().map(|b| format!("{:02x}", b)).collect();
+                Ok(Some(hex.concat()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_lifecycle_sync(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "set_lifecycle",
+            self.set_lifecycle(bucket, rule),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Replace the lifecycle rules of the bucket with the given rule, or remove
+    /// the rules if the given rule is empty.
+    pub async fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        let hub = self.connect().await?;
+        let req = storage1::api::Bucket {
+            lifecycle: Some(bucket_lifecycle(rule)),
+            ..Default::default()
+        };
+        hub.buckets().patch(req, bucket).doit().await?;
+        Ok(())
+    }
+
     pub fn delete_bucket_sync(&self, bucket: &str) -> Result<(), Error> {
-        block_on(self.delete_bucket(bucket)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "delete_bucket",
+            self.delete_bucket(bucket),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
@@ -262,51 +540,29 @@ impl GoogleStore {
         Ok(())
     }
 
+    // The means of recording bucket renames, if any.
+    fn rename_tracker(&self) -> Option<Box<dyn RenameTracker + '_>> {
+        match self.renames {
+            Renames::Firestore => Some(Box::new(FirestoreRenames { store: self })),
+            Renames::Object => Some(Box::new(ObjectRenames::new(self))),
+            Renames::None => None,
+        }
+    }
+
     /// Record the new name of the bucket.
     async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error> {
-        let hub = self.connect_fire().await?;
-        let mut values: HashMap<String, firestore1::api::Value> = HashMap::new();
-        let value = firestore1::api::Value {
-            string_value: Some(renamed.to_owned()),
-            ..Default::default()
-        };
-        values.insert("renamed".into(), value);
-        let name = format!(
-            "projects/{}/databases/{}/documents/renames/{}",
-            &self.project, "(default)", original
-        );
-        let document = firestore1::api::Document {
-            fields: Some(values),
-            ..Default::default()
-        };
-        // databases_documents_patch() will either insert or update
-        let (_response, _document) = hub
-            .projects()
-            .databases_documents_patch(document, &name)
-            .doit()
-            .await?;
-        Ok(())
+        match self.rename_tracker() {
+            Some(tracker) => tracker.save_bucket_name(original, renamed).await,
+            None => Err(anyhow!("bucket renames are not recorded by this store")),
+        }
     }
 
     /// Retrieve the renamed value for the original bucket.
     async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error> {
-        let hub = self.connect_fire().await?;
-        let name = format!(
-            "projects/{}/databases/{}/documents/renames/{}",
-            &self.project, "(default)", original
-        );
-        // If the document is missing a 404 error is returned, and if the
-        // document is returned and has missing values, still return none.
-        if let Ok((_response, document)) =
-            hub.projects().databases_documents_get(&name).doit().await
-        {
-            if let Some(fields) = document.fields {
-                if let Some(renamed_field) = fields.get("renamed") {
-                    return Ok(renamed_field.string_value.to_owned());
-                }
-            }
+        match self.rename_tracker() {
+            Some(tracker) => tracker.get_bucket_name(original).await,
+            None => Ok(None),
         }
-        Ok(None)
     }
 
     // async fn get_renamed_buckets(&self) -> Result<Vec<String>, Error> {
@@ -349,7 +605,12 @@ impl GoogleStore {
         bucket: &str,
         object: &str,
     ) -> Result<Coordinates, Error> {
-        block_on(self.store_database(packfile, bucket, object)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "store_database",
+            self.store_database(packfile, bucket, object),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn store_database(
@@ -361,7 +622,8 @@ impl GoogleStore {
         if let Some(renamed) = self.get_bucket_name(bucket).await? {
             // If the renamed bucket fails for some reason, then report it
             // immediately, do not attempt to generate a new name again.
-            self.store_pack(packfile, &renamed, object).await
+            self.upload(packfile, &renamed, object, &LifecycleRule::default())
+                .await
         } else {
             // Store the database in the same manner as any pack file, using the
             // given bucket and object names. If there is a collision with an
@@ -370,10 +632,16 @@ impl GoogleStore {
             // or fails in some other manner.
             let mut bucket_name = bucket.to_owned();
             loop {
-                match self.store_pack(packfile, &bucket_name, object).await {
+                let lifecycle = &LifecycleRule::default();
+                match self.upload(packfile, &bucket_name, object, lifecycle).await {
                     Ok(coords) => return Ok(coords),
                     Err(err) => {
                         match err.downcast::<CollisionError>() {
+                            Ok(err) if self.renames == Renames::None => {
+                                // without a place to record the new name, the
+                                // database would be lost after a rename
+                                return Err(Error::from(err));
+                            }
                             Ok(_) => {
                                 // There was a collision, simply generate a new
                                 // name and hope that it will work. Type 4 UUID
@@ -395,7 +663,12 @@ impl GoogleStore {
         location: &Coordinates,
         outfile: &Path,
     ) -> Result<(), Error> {
-        block_on(self.retrieve_database(location, outfile)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "retrieve_database",
+            self.retrieve_database(location, outfile),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn retrieve_database(
@@ -413,7 +686,12 @@ impl GoogleStore {
     }
 
     pub fn list_databases_sync(&self, bucket: &str) -> Result<Vec<String>, Error> {
-        block_on(self.list_databases(bucket)).and_then(std::convert::identity)
+        block_on(within(
+            self.timeouts.operation,
+            "list_databases",
+            self.list_databases(bucket),
+        ))
+        .and_then(std::convert::identity)
     }
 
     pub async fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
@@ -425,6 +703,200 @@ impl GoogleStore {
     }
 }
 
+impl PackDataSource for GoogleStore {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack_sync(location, outfile)
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.list_buckets_sync()
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects_sync(bucket)
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        self.delete_object_sync(bucket, object)
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        self.delete_bucket_sync(bucket)
+    }
+
+    fn set_storage_class(&self, bucket: &str, object: &str, class: &str) -> Result<bool, Error> {
+        self.set_storage_class_sync(bucket, object, class)
+    }
+
+    fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        self.set_lifecycle_sync(bucket, rule)
+    }
+
+    fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        self.object_md5_sync(location)
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_database_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_database_sync(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_databases_sync(bucket)
+    }
+}
+
+#[async_trait]
+impl AsyncPackDataSource for GoogleStore {
+    async fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        within(
+            self.timeouts.operation,
+            "store_pack",
+            GoogleStore::store_pack(self, packfile, bucket, object),
+        )
+        .await
+    }
+
+    async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            GoogleStore::retrieve_pack(self, location, outfile),
+        )
+        .await
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_buckets",
+            GoogleStore::list_buckets(self),
+        )
+        .await
+    }
+
+    async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_objects",
+            GoogleStore::list_objects(self, bucket),
+        )
+        .await
+    }
+
+    async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_object",
+            GoogleStore::delete_object(self, bucket, object),
+        )
+        .await
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_bucket",
+            GoogleStore::delete_bucket(self, bucket),
+        )
+        .await
+    }
+
+    async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        within(
+            self.timeouts.operation,
+            "set_storage_class",
+            GoogleStore::set_storage_class(self, bucket, object, class),
+        )
+        .await
+    }
+
+    async fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "set_lifecycle",
+            GoogleStore::set_lifecycle(self, bucket, rule),
+        )
+        .await
+    }
+
+    async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "object_md5",
+            GoogleStore::object_md5(self, location),
+        )
+        .await
+    }
+
+    async fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        within(
+            self.timeouts.operation,
+            "store_database",
+            GoogleStore::store_database(self, packfile, bucket, object),
+        )
+        .await
+    }
+
+    async fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "retrieve_database",
+            GoogleStore::retrieve_database(self, location, outfile),
+        )
+        .await
+    }
+
+    async fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_databases",
+            GoogleStore::list_databases(self, bucket),
+        )
+        .await
+    }
+}
+
 /// Ensure the named bucket exists.
 async fn create_bucket(
     hub: &storage1::Storage<HttpsConnector<HttpConnector>>,
@@ -432,11 +904,23 @@ async fn create_bucket(
     name: &str,
     region: &Option<String>,
     storage_class: &Option<String>,
+    kms_key: &Option<String>,
+    lifecycle: &LifecycleRule,
 ) -> Result<(), Error> {
+    let encryption = kms_key.as_ref().map(|key| storage1::api::BucketEncryption {
+        default_kms_key_name: Some(key.to_owned()),
+    });
+    let lifecycle = if lifecycle.is_empty() {
+        None
+    } else {
+        Some(bucket_lifecycle(lifecycle))
+    };
     let req = storage1::api::Bucket {
         location: region.to_owned(),
         name: Some(name.to_owned()),
         storage_class: storage_class.to_owned(),
+        encryption,
+        lifecycle,
         ..Default::default()
     };
     // If bucket creation results in a 409, it means the bucket already exists,
@@ -463,6 +947,119 @@ async fn create_bucket(
     Ok(())
 }
 
+/// Convert the lifecycle rule into the bucket lifecycle configuration.
+fn bucket_lifecycle(rule: &LifecycleRule) -> storage1::api::BucketLifecycle {
+    use storage1::api::{
+        BucketLifecycleRule, BucketLifecycleRuleAction, BucketLifecycleRuleCondition,
+    };
+    let mut rules: Vec<BucketLifecycleRule> = Vec::new();
+    // only the pack objects are moved or removed
+    let pack_prefixes: Vec<String> = PACK_PREFIXES.iter().map(|p| p.to_string()).collect();
+    if let Some(days) = rule.transition_days {
+        rules.push(BucketLifecycleRule {
+            action: Some(BucketLifecycleRuleAction {
+                type_: Some("SetStorageClass".to_owned()),
+                storage_class: rule.storage_class.clone(),
+            }),
+            condition: Some(BucketLifecycleRuleCondition {
+                age: Some(days as i32),
+                matches_prefix: Some(pack_prefixes.clone()),
+                ..Default::default()
+            }),
+        });
+    }
+    if let Some(days) = rule.expiration_days {
+        rules.push(BucketLifecycleRule {
+            action: Some(BucketLifecycleRuleAction {
+                type_: Some("Delete".to_owned()),
+                storage_class: None,
+            }),
+            condition: Some(BucketLifecycleRuleCondition {
+                age: Some(days as i32),
+                matches_prefix: Some(pack_prefixes),
+                ..Default::default()
+            }),
+        });
+    }
+    // an empty list of rules removes any existing rules
+    storage1::api::BucketLifecycle { rule: Some(rules) }
+}
+
+///
+/// Records the bucket renames as documents in Firestore.
+///
+struct FirestoreRenames<'a> {
+    store: &'a GoogleStore,
+}
+
+#[async_trait]
+impl RenameTracker for FirestoreRenames<'_> {
+    async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error> {
+        let hub = self.store.connect_fire().await?;
+        let mut values: HashMap<String, firestore1::api::Value> = HashMap::new();
+        let value = firestore1::api::Value {
+            string_value: Some(renamed.to_owned()),
+            ..Default::default()
+        };
+        values.insert("renamed".into(), value);
+        let name = format!(
+            "projects/{}/databases/{}/documents/renames/{}",
+            &self.store.project, "(default)", original
+        );
+        let document = firestore1::api::Document {
+            fields: Some(values),
+            ..Default::default()
+        };
+        // databases_documents_patch() will either insert or update
+        let (_response, _document) = hub
+            .projects()
+            .databases_documents_patch(document, &name)
+            .doit()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error> {
+        let hub = self.store.connect_fire().await?;
+        let name = format!(
+            "projects/{}/databases/{}/documents/renames/{}",
+            &self.store.project, "(default)", original
+        );
+        // If the document is missing a 404 error is returned, and if the
+        // document is returned and has missing values, still return none.
+        if let Ok((_response, document)) =
+            hub.projects().databases_documents_get(&name).doit().await
+        {
+            if let Some(fields) = document.fields {
+                if let Some(renamed_field) = fields.get("renamed") {
+                    return Ok(renamed_field.string_value.to_owned());
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Ensure the key name is that of a Cloud KMS key, which takes the form
+/// `projects/P/locations/L/keyRings/R/cryptoKeys/K`.
+fn validate_kms_key(key: &str) -> Result<String, Error> {
+    let parts: Vec<&str> = key.split('/').collect();
+    let valid = parts.len() == 8
+        && parts[0] == "projects"
+        && parts[2] == "locations"
+        && parts[4] == "keyRings"
+        && parts[6] == "cryptoKeys"
+        && parts.iter().all(|p| !p.is_empty());
+    if valid {
+        Ok(key.to_owned())
+    } else {
+        Err(anyhow!(format!(
+            "kms_key must be of the form projects/P/locations/L/keyRings/R/cryptoKeys/K: {}",
+            key
+        )))
+    }
+}
+
 /// Compute the MD5 digest of the given file.
 fn md5sum_file(infile: &Path) -> Result<Vec<u8>, Error> {
     use md5::{Digest, Md5};
@@ -473,6 +1070,55 @@ fn md5sum_file(infile: &Path) -> Result<Vec<u8>, Error> {
     Ok(digest)
 }
 
+//
+// Saves the session URI of a resumable upload, and offers it back to the client
+// when the same file is uploaded again, in which case the client will ask the
+// service how much was received and continue from there.
+//
+struct SessionDelegate {
+    checkpoints: Arc<dyn CheckpointStore>,
+    coords: Coordinates,
+    fingerprint: String,
+}
+
+impl storage1::client::Delegate for SessionDelegate {
+    fn upload_url(&mut self) -> Option<String> {
+        match self.checkpoints.get_checkpoint(&self.coords) {
+            Ok(Some(saved)) if saved.fingerprint == self.fingerprint && !saved.is_stale() => {
+                Some(saved.session)
+            }
+            _ => None,
+        }
+    }
+
+    fn store_upload_url(&mut self, url: Option<&str>) {
+        // errors cannot be returned from here, the upload will simply not be
+        // resumable should it be interrupted
+        let _ = match url {
+            Some(url) => {
+                let checkpoint = Checkpoint::new(&self.fingerprint, url);
+                self.checkpoints.put_checkpoint(&self.coords, &checkpoint)
+            }
+            None => self.checkpoints.delete_checkpoint(&self.coords),
+        };
+    }
+}
+
+/// Wait for the future to complete, raising a `TimeoutError` for the named
+/// operation if it takes longer than the limit, if any.
+async fn within<T, F>(limit: Option<Duration>, operation: &str, future: F) -> Result<T, Error>
+where
+    F: core::future::Future<Output = Result<T, Error>>,
+{
+    match limit {
+        Some(limit) => match tokio::time::timeout(limit, future).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::from(TimeoutError::new(operation, Some(limit)))),
+        },
+        None => future.await,
+    }
+}
+
 /// Run the given future on a newly created single-threaded runtime if possible,
 /// otherwise raise an error if this thread already has a runtime.
 fn block_on<F: core::future::Future>(future: F) -> Result<F::Output, Error> {
@@ -515,6 +1161,78 @@ mod tests {
         properties.insert("storage".to_owned(), "nearline".to_owned());
         let result = GoogleStore::new("google123", &properties);
         assert!(result.is_ok());
+        properties.insert("connect_timeout".to_owned(), "30".to_owned());
+        properties.insert("operation_timeout".to_owned(), "600".to_owned());
+        let store = GoogleStore::new("google123", &properties).unwrap();
+        assert_eq!(store.timeouts.connect, Some(Duration::from_secs(30)));
+        assert_eq!(store.timeouts.operation, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn test_new_google_store_kms_key() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("credentials".to_owned(), "/path/to/file".to_owned());
+        properties.insert("project".to_owned(), "shinkansen".to_owned());
+        properties.insert("kms_key".to_owned(), "".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert!(result.unwrap().kms_key.is_none());
+        properties.insert("kms_key".to_owned(), "my-key".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("kms_key must be of the form"));
+        let key = "projects/shinkansen/locations/us/keyRings/ring/cryptoKeys/packs";
+        properties.insert("kms_key".to_owned(), key.to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert_eq!(result.unwrap().kms_key.as_deref(), Some(key));
+    }
+
+    #[test]
+    fn test_bucket_lifecycle() {
+        let rule = LifecycleRule::default();
+        let actual = bucket_lifecycle(&rule);
+        assert!(actual.rule.unwrap().is_empty());
+        let rule = LifecycleRule {
+            transition_days: Some(90),
+            storage_class: Some("ARCHIVE".into()),
+            expiration_days: Some(30),
+        };
+        let actual = bucket_lifecycle(&rule).rule.unwrap();
+        assert_eq!(actual.len(), 2);
+        let action = actual[0].action.as_ref().unwrap();
+        assert_eq!(action.type_.as_deref(), Some("SetStorageClass"));
+        assert_eq!(action.storage_class.as_deref(), Some("ARCHIVE"));
+        assert_eq!(actual[0].condition.as_ref().unwrap().age, Some(90));
+        let action = actual[1].action.as_ref().unwrap();
+        assert_eq!(action.type_.as_deref(), Some("Delete"));
+        assert_eq!(actual[1].condition.as_ref().unwrap().age, Some(30));
+        // the rename marker is not selected by the rules
+        let prefixes = actual[0].condition.as_ref().unwrap().matches_prefix.clone();
+        let prefixes = prefixes.unwrap();
+        assert!(prefixes.iter().any(|p| "blake3-cafebabe".starts_with(p)));
+        assert!(!prefixes
+            .iter()
+            .any(|p| "renamed-from-cafebabe".starts_with(p)));
+    }
+
+    #[test]
+    fn test_new_google_store_renames() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("credentials".to_owned(), "/path/to/file".to_owned());
+        properties.insert("project".to_owned(), "shinkansen".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert_eq!(result.unwrap().renames, Renames::Firestore);
+        properties.insert("renames".to_owned(), "object".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert_eq!(result.unwrap().renames, Renames::Object);
+        properties.insert("renames".to_owned(), "none".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert_eq!(result.unwrap().renames, Renames::None);
+        properties.insert("renames".to_owned(), "dynamodb".to_owned());
+        let result = GoogleStore::new("google123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("renames must be one of"));
     }
 
     #[test]
@@ -572,10 +1290,7 @@ mod tests {
         let result = source.retrieve_pack_sync(&location, &outfile);
         assert!(result.is_ok());
         let md5sum = store_core::md5sum_file(&outfile)?;
-        #[cfg(target_family = "unix")]
         assert_eq!(md5sum, "4b9772cf2c623ad529900f0ffe4e8ded");
-        #[cfg(target_family = "windows")]
-        assert_eq!(md5sum, "f143ccda41d1e2a553ef214e4549cd6e");
 
         // remove all objects from all buckets, and the buckets, too
         for bucket in buckets {
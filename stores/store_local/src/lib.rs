@@ -4,17 +4,23 @@
 use anyhow::{anyhow, Context, Error};
 use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use store_core::Coordinates;
+use store_core::{Coordinates, Deadline, DeadlineReader, PackDataSource, Timeouts};
 
 ///
 /// A pack store implementation in which pack files are stored on a locally
 /// accessible file system.
 ///
+/// Of the timeout properties, only `operation_timeout` has any meaning for a
+/// file system, which limits the time spent copying a pack file, such as when
+/// the base path is on a network file system that has stopped responding.
+///
 #[derive(Debug)]
 pub struct LocalStore {
     store_id: String,
     basepath: String,
+    timeouts: Timeouts,
 }
 
 impl LocalStore {
@@ -26,41 +32,137 @@ impl LocalStore {
         Ok(Self {
             store_id: store_id.to_owned(),
             basepath: basepath.to_owned(),
+            timeouts: Timeouts::from_properties(props)?,
         })
     }
+}
+
+// Prefix of the names of the temporary files used while copying objects.
+const TEMP_PREFIX: &str = ".tmp";
+
+// Copy the file into the directory by way of a temporary file, which is then
+// flushed to disk and renamed to the given name, such that the object is
+// either complete or absent, even if the system crashes during the copy. The
+// temporary file has a leading dot so that it is not listed as an object.
+//
+// If the copy fails, the temporary file is removed before returning the error,
+// rather than leaving it to be dropped, which ignores any failure to remove it.
+fn write_atomically(
+    infile: &Path,
+    dir: &Path,
+    name: &str,
+    deadline: Deadline,
+) -> Result<(), Error> {
+    let mut input = DeadlineReader::new(fs::File::open(infile)?, deadline);
+    let mut output = tempfile::Builder::new()
+        .prefix(TEMP_PREFIX)
+        .tempfile_in(dir)?;
+    let copied =
+        io::copy(&mut input, output.as_file_mut()).and_then(|_| output.as_file().sync_all());
+    if let Err(err) = copied {
+        return Err(discard_temporary(output, err));
+    }
+    if let Err(err) = output.persist(dir.join(name)) {
+        return Err(discard_temporary(err.file, err.error));
+    }
+    sync_directory(dir)
+}
+
+// Remove the temporary file after the given error, noting in the error if the
+// file could not be removed.
+fn discard_temporary(file: tempfile::NamedTempFile, err: io::Error) -> Error {
+    let path = file.path().to_owned();
+    match file.close() {
+        Ok(()) => Error::from(err),
+        Err(close_err) => Error::from(err).context(format!(
+            "cannot remove temporary file {}: {}",
+            path.display(),
+            close_err
+        )),
+    }
+}
+
+// Remove any temporary files left in the directory by a copy that was
+// interrupted by a crash, since those cannot be removed when the copy fails.
+fn remove_temporaries(dir: &Path) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with(TEMP_PREFIX) && entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Flush the directory entries to disk, making the rename durable.
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> Result<(), Error> {
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
 
-    pub fn is_local(&self) -> bool {
+// Directories cannot be opened as files on Windows, where the rename is
+// durable once the file itself has been flushed.
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+impl PackDataSource for LocalStore {
+    fn is_local(&self) -> bool {
         true
     }
 
-    pub fn is_slow(&self) -> bool {
+    fn is_slow(&self) -> bool {
         false
     }
 
-    pub fn store_pack(
+    fn store_pack(
         &self,
         packfile: &Path,
         bucket: &str,
         object: &str,
     ) -> Result<Coordinates, Error> {
-        let mut path: PathBuf = [&self.basepath, bucket].iter().collect();
+        let path: PathBuf = [&self.basepath, bucket].iter().collect();
         fs::create_dir_all(&path)
             .with_context(|| format!("store_pack fs::create_dir_all({})", path.display()))?;
-        path.push(object);
-        fs::copy(packfile, &path)?;
+        let deadline = self.timeouts.deadline("store_pack");
+        write_atomically(packfile, &path, object, deadline)
+            .map_err(|e| store_core::classify_timeout(e, "store_pack", self.timeouts.operation))
+            .with_context(|| format!("store_pack write_atomically({})", path.display()))?;
         let loc = Coordinates::new(&self.store_id, bucket, object);
         Ok(loc)
     }
 
-    pub fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
         let path: PathBuf = [&self.basepath, &location.bucket, &location.object]
             .iter()
             .collect();
-        fs::copy(path, outfile)?;
+        let deadline = self.timeouts.deadline("retrieve_pack");
+        let mut input = DeadlineReader::new(fs::File::open(path)?, deadline);
+        let mut output = fs::File::create(outfile)?;
+        io::copy(&mut input, &mut output).map_err(|e| {
+            store_core::classify_timeout(Error::from(e), "retrieve_pack", self.timeouts.operation)
+        })?;
         Ok(())
     }
 
-    pub fn list_buckets(&self) -> Result<Vec<String>, Error> {
+    fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        let path: PathBuf = [&self.basepath, &location.bucket, &location.object]
+            .iter()
+            .collect();
+        Ok(Some(store_core::md5sum_file(&path)?))
+    }
+
+    fn object_size(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        let path: PathBuf = [&self.basepath, &location.bucket, &location.object]
+            .iter()
+            .collect();
+        Ok(Some(fs::metadata(path)?.len()))
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
         let mut results = Vec::new();
         for entry in fs::read_dir(&self.basepath)? {
             let entry = entry?;
@@ -77,7 +179,7 @@ impl LocalStore {
         Ok(results)
     }
 
-    pub fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
         let path: PathBuf = [&self.basepath, bucket].iter().collect();
         let mut results = Vec::new();
         for entry in fs::read_dir(path)? {
@@ -95,19 +197,21 @@ impl LocalStore {
         Ok(results)
     }
 
-    pub fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
         let path: PathBuf = [&self.basepath, bucket, object].iter().collect();
         fs::remove_file(path)?;
         Ok(())
     }
 
-    pub fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
         let path: PathBuf = [&self.basepath, bucket].iter().collect();
+        // the bucket cannot be removed while any temporary files remain
+        remove_temporaries(&path)?;
         fs::remove_dir(path)?;
         Ok(())
     }
 
-    pub fn store_database(
+    fn store_database(
         &self,
         packfile: &Path,
         bucket: &str,
@@ -116,11 +220,11 @@ impl LocalStore {
         self.store_pack(packfile, bucket, object)
     }
 
-    pub fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
         self.retrieve_pack(location, outfile)
     }
 
-    pub fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
         self.list_objects(bucket)
     }
 }
@@ -151,6 +255,17 @@ mod tests {
         assert!(!source.is_slow());
     }
 
+    #[test]
+    fn test_new_local_store_timeouts() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("basepath".to_owned(), "/tmp".to_owned());
+        properties.insert("operation_timeout".to_owned(), "soon".to_owned());
+        let result = LocalStore::new("local123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("operation_timeout must be a number of seconds"));
+    }
+
     #[test]
     fn test_local_store_roundtrip() {
         // arrange
@@ -192,10 +307,18 @@ mod tests {
         let result = source.retrieve_pack(&location, &outfile);
         assert!(result.is_ok());
         let md5sum = store_core::md5sum_file(&outfile).unwrap();
-        #[cfg(target_family = "unix")]
         assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
-        #[cfg(target_family = "windows")]
-        assert_eq!(md5sum, "8aed508af644bc58db20c9b73c5b67ad");
+
+        // the store reports the same digest without retrieving the file
+        let result = source.object_md5(&location);
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap().as_deref(),
+            Some("40756e6058736e2485119410c2014380")
+        );
+        let result = source.object_size(&location);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), Some(3129));
 
         // remove all objects from all buckets, and the buckets, too
         for bucket in buckets {
@@ -208,4 +331,66 @@ mod tests {
             source.delete_bucket(&bucket).unwrap();
         }
     }
+
+    #[test]
+    fn test_local_store_overwrite() {
+        // arrange
+        let basedir = tempdir().unwrap();
+        let basepath = basedir.path().to_string_lossy().into_owned();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("basepath".to_owned(), basepath);
+        let source = LocalStore::new("localone", &properties).unwrap();
+        let bucket = "747267d56e7057118a9aa40c24c1730f";
+        let object = "39c6061a56b7711f92c6ccd2047d47fdcc1609c1";
+
+        // act
+        let packfile = Path::new("../../test/fixtures/SekienAkashita.jpg");
+        source.store_database(packfile, bucket, object).unwrap();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_pack(packfile, bucket, object).unwrap();
+
+        // assert
+        let outfile = basedir.path().join("restored.txt");
+        source.retrieve_pack(&location, &outfile).unwrap();
+        let md5sum = store_core::md5sum_file(&outfile).unwrap();
+        assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
+        // nothing is left behind other than the object itself
+        let entries = fs::read_dir(basedir.path().join(bucket)).unwrap();
+        assert_eq!(entries.count(), 1);
+    }
+
+    #[test]
+    fn test_write_atomically_failure() {
+        // arrange
+        let basedir = tempdir().unwrap();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        // act
+        let deadline = Deadline::new("store_pack", Some(std::time::Duration::ZERO));
+        let result = write_atomically(packfile, basedir.path(), "object", deadline);
+        // assert
+        assert!(result.is_err());
+        let entries = fs::read_dir(basedir.path()).unwrap();
+        assert_eq!(entries.count(), 0);
+    }
+
+    #[test]
+    fn test_delete_bucket_temporaries() {
+        // arrange
+        let basedir = tempdir().unwrap();
+        let basepath = basedir.path().to_string_lossy().into_owned();
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("basepath".to_owned(), basepath);
+        let source = LocalStore::new("localone", &properties).unwrap();
+        let bucket = "747267d56e7057118a9aa40c24c1730f";
+        // as if a copy into the bucket was interrupted by a crash
+        let path = basedir.path().join(bucket);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join(".tmpAbc123"), b"partial").unwrap();
+        assert!(source.list_objects(bucket).unwrap().is_empty());
+        // act
+        let result = source.delete_bucket(bucket);
+        // assert
+        assert!(result.is_ok());
+        assert!(!path.exists());
+    }
 }
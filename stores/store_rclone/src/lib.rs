@@ -0,0 +1,425 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use store_core::{Coordinates, PackDataSource, TimeoutError, Timeouts};
+
+// How often to check whether rclone has finished when there is a time limit.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+///
+/// A `PackDataSource` implementation that runs the rclone program to transfer
+/// pack files, such that any of the providers supported by rclone may serve as
+/// a pack store. The remote is configured with rclone itself, by `rclone config`,
+/// and the buckets are directories within the remote path.
+///
+/// The rclone program is found on the `PATH` unless the `RCLONE_PROGRAM`
+/// environment variable names it, which is a setting of the server rather than
+/// a store property so that clients cannot choose which program is run.
+///
+#[derive(Debug)]
+pub struct RcloneStore {
+    store_id: String,
+    // name of the rclone remote, optionally followed by a path
+    remote: String,
+    // path to the rclone executable
+    program: PathBuf,
+    // path to a configuration file other than the rclone default
+    config_file: Option<PathBuf>,
+    // bytes per second, enforced by rclone rather than our throttle
+    bandwidth_limit: Option<u64>,
+    // connect and read/write limits are enforced by rclone, while the
+    // operation limit is enforced by stopping rclone
+    timeouts: Timeouts,
+}
+
+impl RcloneStore {
+    /// Validate the given store and construct an rclone pack source.
+    pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
+        let remote = props
+            .get("remote")
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| anyhow!("missing remote property"))?;
+        // a leading dash would be taken as an option by rclone
+        if remote.starts_with('-') {
+            return Err(anyhow!(format!(
+                "remote must not start with a dash: {}",
+                remote
+            )));
+        }
+        if props.contains_key("rclone_path") {
+            return Err(anyhow!(
+                "rclone_path property is not supported, set RCLONE_PROGRAM instead"
+            ));
+        }
+        let program = env::var_os("RCLONE_PROGRAM")
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("rclone"));
+        let config_file = props
+            .get("config_file")
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        let bandwidth_limit = match props.get("bandwidth_limit").map(|s| s.trim()) {
+            None | Some("") => None,
+            Some(value) => match value.parse::<u64>() {
+                Ok(0) => None,
+                Ok(limit) => Some(limit),
+                Err(_) => {
+                    return Err(anyhow!(format!(
+                        "bandwidth_limit must be a number of bytes per second: {}",
+                        value
+                    )))
+                }
+            },
+        };
+        Ok(Self {
+            store_id: store_id.to_owned(),
+            remote: remote.to_owned(),
+            program,
+            config_file,
+            bandwidth_limit,
+            timeouts: Timeouts::from_properties(props)?,
+        })
+    }
+
+    // Build the rclone path of the named bucket, or of an object within it.
+    fn remote_path(&self, bucket: &str, object: Option<&str>) -> String {
+        let mut path = self.remote.clone();
+        if !path.ends_with(':') && !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(bucket);
+        if let Some(name) = object {
+            path.push('/');
+            path.push_str(name);
+        }
+        path
+    }
+
+    // Run rclone with the given command and arguments, returning the standard
+    // output if the command succeeded.
+    fn run<S: AsRef<OsStr>>(&self, command: &str, args: &[S]) -> Result<String, Error> {
+        let mut cmd = Command::new(&self.program);
+        cmd.arg(command);
+        if let Some(config) = self.config_file.as_ref() {
+            cmd.arg("--config").arg(config);
+        }
+        if let Some(limit) = self.bandwidth_limit {
+            cmd.arg("--bwlimit").arg(format!("{}B", limit));
+        }
+        if let Some(limit) = self.timeouts.connect {
+            cmd.arg("--contimeout").arg(format!("{}s", limit.as_secs()));
+        }
+        if let Some(limit) = self.timeouts.io() {
+            cmd.arg("--timeout").arg(format!("{}s", limit.as_secs()));
+        }
+        cmd.args(args);
+        let output = self.output(cmd, command).map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                anyhow!(format!(
+                    "rclone program not found: {}",
+                    self.program.display()
+                ))
+            } else if err.kind() == io::ErrorKind::TimedOut {
+                Error::from(TimeoutError::new(command, self.timeouts.operation))
+            } else {
+                Error::from(err)
+            }
+        })?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(anyhow!(format!(
+                "rclone {} failed ({}): {}",
+                command,
+                output.status,
+                stderr.trim()
+            )))
+        }
+    }
+
+    // Run the command to completion and collect its output, stopping it if it
+    // runs longer than the operation timeout allows.
+    fn output(&self, mut cmd: Command, command: &str) -> io::Result<std::process::Output> {
+        let limit = match self.timeouts.operation {
+            Some(limit) => limit,
+            None => return cmd.output(),
+        };
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // drain the pipes as rclone runs so it never blocks on writing
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= limit {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("rclone {} timed out", command),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        Ok(std::process::Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    // List the entries of the given rclone path, as selected by the flag,
+    // ignoring any that are definitely not ours.
+    fn list_names(&self, path: &str, flag: &str) -> Result<Vec<String>, Error> {
+        let output = self.run("lsf", &[flag, path])?;
+        let results = output
+            .lines()
+            .map(|line| line.trim_end_matches('/'))
+            .filter(|name| !name.is_empty() && !name.starts_with('.'))
+            .map(|name| name.to_owned())
+            .collect();
+        Ok(results)
+    }
+}
+
+// Read everything from the pipe in the background, if there is one.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer: Vec<u8> = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+impl PackDataSource for RcloneStore {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        let path = self.remote_path(bucket, Some(object));
+        self.run("copyto", &[packfile.as_os_str(), OsStr::new(&path)])?;
+        let loc = Coordinates::new(&self.store_id, bucket, object);
+        Ok(loc)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        let path = self.remote_path(&location.bucket, Some(&location.object));
+        self.run("copyto", &[OsStr::new(&path), outfile.as_os_str()])?;
+        Ok(())
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.list_names(&self.remote, "--dirs-only")
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        let path = self.remote_path(bucket, None);
+        self.list_names(&path, "--files-only")
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        let path = self.remote_path(bucket, Some(object));
+        self.run("deletefile", &[path])?;
+        Ok(())
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        let path = self.remote_path(bucket, None);
+        self.run("rmdir", &[path])?;
+        Ok(())
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects(bucket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotenv::dotenv;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_new_rclone_store_remote() {
+        let props = HashMap::new();
+        let result = RcloneStore::new("rclone123", &props);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing remote property"));
+
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "--config=/tmp/evil".to_owned());
+        let result = RcloneStore::new("rclone123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("must not start with a dash"));
+
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        properties.insert("rclone_path".to_owned(), "/bin/sh".to_owned());
+        let result = RcloneStore::new("rclone123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("set RCLONE_PROGRAM instead"));
+    }
+
+    #[test]
+    fn test_new_rclone_store_ok() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        properties.insert("bandwidth_limit".to_owned(), "1048576".to_owned());
+        let result = RcloneStore::new("rclone123", &properties);
+        assert!(result.is_ok());
+        let source = result.unwrap();
+        assert!(!source.is_local());
+        assert!(!source.is_slow());
+        assert_eq!(source.program, PathBuf::from("rclone"));
+        assert_eq!(source.bandwidth_limit, Some(1048576));
+
+        properties.insert("bandwidth_limit".to_owned(), "fast".to_owned());
+        let result = RcloneStore::new("rclone123", &properties);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_remote_path() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        let source = RcloneStore::new("rclone123", &properties).unwrap();
+        assert_eq!(source.remote_path("bucket", None), "b2:bucket");
+        assert_eq!(
+            source.remote_path("bucket", Some("object")),
+            "b2:bucket/object"
+        );
+        properties.insert("remote".to_owned(), "gdrive:backups".to_owned());
+        let source = RcloneStore::new("rclone123", &properties).unwrap();
+        assert_eq!(
+            source.remote_path("bucket", Some("object")),
+            "gdrive:backups/bucket/object"
+        );
+        properties.insert("remote".to_owned(), "gdrive:backups/".to_owned());
+        let source = RcloneStore::new("rclone123", &properties).unwrap();
+        assert_eq!(source.remote_path("bucket", None), "gdrive:backups/bucket");
+    }
+
+    #[test]
+    fn test_rclone_missing_program() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        let mut source = RcloneStore::new("rclone123", &properties).unwrap();
+        source.program = PathBuf::from("/nonexistent/bin/rclone");
+        let result = source.list_buckets();
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("rclone program not found"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rclone_operation_timeout() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        // stand-in for rclone that never finishes in time
+        let outdir = tempdir()?;
+        let program = outdir.path().join("rclone");
+        std::fs::write(&program, "#!/bin/sh\nsleep 30\n")?;
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755))?;
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), "b2:".to_owned());
+        properties.insert("operation_timeout".to_owned(), "1".to_owned());
+        let mut source = RcloneStore::new("rclone123", &properties)?;
+        source.program = program;
+        let started = Instant::now();
+        let result = source.list_buckets();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let err = result.unwrap_err();
+        let timeout = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.operation, "lsf");
+        assert_eq!(timeout.limit, Some(Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rclone_roundtrip() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let remote_var = env::var("RCLONE_REMOTE");
+        if remote_var.is_err() {
+            // bail out silently if rclone is not configured
+            return Ok(());
+        }
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote".to_owned(), remote_var.unwrap());
+        let source = RcloneStore::new("rcloneone", &properties)?;
+
+        // store an object
+        let bucket = "0b7bbcbc3a0a5cd7a5a6f33b4d3d1b39".to_owned();
+        let object = "39c6061a56b7711f92c6ccd2047d47fdcc1609c1".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_pack(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "rcloneone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // check for bucket and object being present
+        let buckets = source.list_buckets()?;
+        assert!(buckets.contains(&bucket));
+        let listing = source.list_objects(&bucket)?;
+        assert!(listing.contains(&object));
+
+        // retrieve the file and verify by checksum
+        let outdir = tempdir()?;
+        let outfile = outdir.path().join("restored.txt");
+        source.retrieve_pack(&location, &outfile)?;
+        let md5sum = store_core::md5sum_file(&outfile)?;
+        assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
+
+        // remove the object and the bucket
+        source.delete_object(&bucket, &object)?;
+        source.delete_bucket(&bucket)?;
+        let buckets = source.list_buckets()?;
+        assert!(!buckets.contains(&bucket));
+        Ok(())
+    }
+}
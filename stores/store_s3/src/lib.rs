@@ -0,0 +1,1950 @@
+//
+// Copyright (c) 2023 Nathan Fiedler
+//
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{FutureExt, StreamExt, TryFutureExt, TryStreamExt};
+use lazy_static::lazy_static;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeDefinition, AttributeValue, CreateTableError, CreateTableInput, DeleteItemInput,
+    DescribeTableInput, DynamoDb, DynamoDbClient, GetItemError, GetItemInput, KeySchemaElement,
+    ProvisionedThroughput, PutItemInput,
+};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, BucketLifecycleConfiguration, CompleteMultipartUploadRequest,
+    CompletedMultipartUpload, CompletedPart, CopyObjectRequest, CreateBucketConfiguration,
+    CreateBucketError, CreateBucketRequest, CreateMultipartUploadRequest,
+    DeleteBucketLifecycleRequest, DeleteBucketRequest, DeleteObjectRequest, GetObjectRequest,
+    HeadObjectRequest, LifecycleExpiration, LifecycleRuleFilter, ListObjectsV2Request,
+    PutBucketLifecycleConfigurationRequest, PutObjectRequest, S3Client, StreamingBody, Transition,
+    UploadPartRequest, S3,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store_core::{
+    is_rename_marker, AsyncPackDataSource, Checkpoint, CheckpointStore, CollisionError,
+    Coordinates, LifecycleRule, ObjectRenames, PackDataSource, RenameTracker, Secret, Throttle,
+    TimeoutError, Timeouts, PACK_PREFIXES,
+};
+use tokio::io::AsyncWriteExt;
+
+lazy_static! {
+    // Names of all existing S3 buckets. Populated and used only when too many
+    // buckets have been created.
+    static ref BUCKET_NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+// Name of the table in DynomaDB for tracking bucket renames.
+const RENAMES_TABLE: &str = "zori_renames";
+
+// Identifier of the lifecycle rules managed by this store.
+const LIFECYCLE_RULE_ID: &str = "zorigami";
+
+// Storage class of objects that must remain readable at all times.
+const STANDARD_CLASS: &str = "STANDARD";
+
+// Rule for buckets that are to have no lifecycle rule at all.
+const NO_LIFECYCLE: LifecycleRule = LifecycleRule {
+    transition_days: None,
+    storage_class: None,
+    expiration_days: None,
+};
+
+// Size of each part of a multipart upload; S3 requires at least 5mb for all
+// but the last part.
+const PART_SIZE: u64 = 8388608;
+
+// Size of the pieces in which a throttled upload is sent.
+const THROTTLE_CHUNK: usize = 65_536;
+
+///
+/// Raised when S3 indicates the account has too many buckets.
+///
+#[derive(thiserror::Error, Debug)]
+pub struct TooManyBucketsError;
+
+impl fmt::Display for TooManyBucketsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "too many buckets")
+    }
+}
+
+// Where the new names of renamed buckets are recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Renames {
+    None,
+    DynamoDB,
+    Object,
+}
+
+///
+/// A pack store implementation for Amazon S3/Glacier and any service that
+/// speaks the S3 protocol, such as Minio, Wasabi, Backblaze B2, or Ceph.
+///
+#[derive(Clone, Debug)]
+pub struct S3Store {
+    store_id: String,
+    region: String,
+    endpoint: Option<String>,
+    storage: Option<String>,
+    access_key: String,
+    secret_key: Secret,
+    renames: Renames,
+    sse: Option<String>,
+    sse_kms_key_id: Option<String>,
+    pack_lifecycle: LifecycleRule,
+    checkpoints: Option<Arc<dyn CheckpointStore>>,
+    throttle: Option<Arc<Throttle>>,
+    timeouts: Timeouts,
+}
+
+impl S3Store {
+    /// Validate the given store and construct an s3 pack source.
+    ///
+    /// Without an `endpoint` property the store connects to Amazon in the
+    /// given region, otherwise the region is simply passed along to the
+    /// service at that endpoint. The optional `renames` property selects where
+    /// renamed database buckets are recorded: `dynamodb` (the default for
+    /// Amazon), `object` for a marker object in the renamed bucket (the default
+    /// for all other services), or `none` to report collisions as errors.
+    ///
+    /// Objects are encrypted by the service if the `sse` property is either
+    /// `AES256` or `aws:kms`, the latter using the key named by the optional
+    /// `sse_kms_key_id` property.
+    ///
+    /// Pack buckets created by the store are given the lifecycle rule defined
+    /// by the `lifecycle_days` and `lifecycle_class` properties (see
+    /// `LifecycleRule`), while the database bucket is given none at all.
+    ///
+    /// The `connect_timeout` applies to establishing each connection, the
+    /// `read_timeout` to waiting for each response or portion of a download,
+    /// the `write_timeout` to each request that sends pack data (the entire
+    /// pack, or one part of a multipart upload), and the `operation_timeout`
+    /// to each store operation as a whole.
+    pub fn new(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
+        let region = props
+            .get("region")
+            .ok_or_else(|| anyhow!("missing region property"))?;
+        let endpoint = props
+            .get("endpoint")
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| v.trim().to_owned());
+        let storage = props
+            .get("storage")
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| v.trim().to_owned());
+        let access_key = props
+            .get("access_key")
+            .ok_or_else(|| anyhow!("missing access_key property"))?;
+        let secret_key = props
+            .get("secret_key")
+            .ok_or_else(|| anyhow!("missing secret_key property"))?;
+        let renames = match props.get("renames").map(|v| v.trim()) {
+            None | Some("") if endpoint.is_none() => Renames::DynamoDB,
+            None | Some("") => Renames::Object,
+            Some("dynamodb") => Renames::DynamoDB,
+            Some("object") => Renames::Object,
+            Some("none") => Renames::None,
+            Some(other) => {
+                return Err(anyhow!(format!(
+                    "renames must be one of dynamodb, object, or none: {}",
+                    other
+                )))
+            }
+        };
+        let sse_kms_key_id = props
+            .get("sse_kms_key_id")
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| v.trim().to_owned());
+        let sse = validate_sse(props.get("sse"), sse_kms_key_id.as_ref())?;
+        Ok(Self {
+            store_id: store_id.to_owned(),
+            region: region.to_owned(),
+            endpoint,
+            storage,
+            access_key: access_key.to_owned(),
+            secret_key: Secret::from(secret_key.as_str()),
+            renames,
+            sse,
+            sse_kms_key_id,
+            pack_lifecycle: LifecycleRule::for_packs(props)?,
+            checkpoints: None,
+            throttle: Throttle::from_properties(store_id, props)?,
+            timeouts: Timeouts::from_properties(props)?,
+        })
+    }
+
+    /// Validate the given store and construct a pack source for a service
+    /// other than Amazon, for which the `endpoint` property is required.
+    pub fn new_compatible(store_id: &str, props: &HashMap<String, String>) -> Result<Self, Error> {
+        let store = Self::new(store_id, props)?;
+        if store.endpoint.is_none() {
+            return Err(anyhow!("missing endpoint property"));
+        }
+        Ok(store)
+    }
+
+    /// Record the progress of large uploads using the given checkpoint store,
+    /// such that an interrupted upload will be resumed rather than restarted.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<dyn CheckpointStore>) -> Self {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    // Either the named Amazon region or the custom endpoint.
+    fn region(&self) -> Region {
+        match self.endpoint.as_ref() {
+            Some(endpoint) => Region::Custom {
+                name: self.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => Region::from_str(&self.region).unwrap_or_default(),
+        }
+    }
+
+    fn connect(&self) -> S3Client {
+        //
+        // Credentials are picked up in a variety of ways, see the rusoto docs:
+        // https://github.com/rusoto/rusoto/blob/master/AWS-CREDENTIALS.md
+        //
+        let client = self.http_client();
+        let creds = rusoto_credential::StaticProvider::new(
+            self.access_key.clone(),
+            self.secret_key.expose().to_owned(),
+            None,
+            None,
+        );
+        S3Client::new_with(client, creds, self.region())
+    }
+
+    fn connect_dynamo(&self) -> DynamoDbClient {
+        // DynamoDB is only ever found at Amazon, never at a custom endpoint
+        let region = Region::from_str(&self.region).unwrap_or_default();
+        let client = self.http_client();
+        let creds = rusoto_credential::StaticProvider::new(
+            self.access_key.clone(),
+            self.secret_key.expose().to_owned(),
+            None,
+            None,
+        );
+        DynamoDbClient::new_with(client, creds, region)
+    }
+
+    // Build the HTTP client that gives up on connecting after the connect
+    // timeout, if any, which is otherwise left to the operating system.
+    fn http_client(&self) -> rusoto_core::request::HttpClient {
+        let mut http = hyper::client::HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(self.timeouts.connect);
+        let https = hyper_tls::HttpsConnector::new_with_connector(http);
+        rusoto_core::request::HttpClient::from_connector(https)
+    }
+
+    pub fn store_pack_sync(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        // use and_then(std::convert::identity) until Result.flatten() is stable
+        block_on(within(
+            self.timeouts.operation,
+            "store_pack",
+            self.store_pack(packfile, bucket, object),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    // Try to create the named bucket.
+    //
+    // If that fails due to too many buckets, select one of the existing buckets
+    // at random and use that instead.
+    //
+    // A newly created bucket is given the lifecycle rule, if any.
+    //
+    // Returns the name of the bucket that was created or selected.
+    async fn try_create_bucket(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        lifecycle: &LifecycleRule,
+    ) -> Result<String, Error> {
+        // Amazon wants to know the region in which to create the bucket,
+        // while other services have a region of their own choosing
+        let location = match self.endpoint {
+            Some(_) => None,
+            None => Some(self.region.as_str()),
+        };
+        match create_bucket(client, bucket, location).await {
+            Err(err) => match err.downcast::<TooManyBucketsError>() {
+                Ok(_) => {
+                    let mut names = BUCKET_NAMES.lock().unwrap();
+                    if names.is_empty() {
+                        // The mutex is held during the time of requesting the
+                        // list of buckets, which is acceptable. Note also that
+                        // the list of buckets is never updated again, as that
+                        // is assumed to be acceptable as well (normally never
+                        // remove any buckets so the list remains static).
+                        *names = self.list_buckets().await?;
+                    }
+                    use rand::{thread_rng, Rng};
+                    let mut rng = thread_rng();
+                    let idx = rng.gen_range(0..names.len());
+                    Ok(names[idx].to_owned())
+                }
+                Err(err) => Err(err),
+            },
+            Ok(created) => {
+                if created && !lifecycle.is_empty() {
+                    self.set_lifecycle(bucket, lifecycle).await?;
+                }
+                Ok(bucket.to_owned())
+            }
+        }
+    }
+
+    pub async fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        // the bucket rename marker must remain readable at all times
+        let lifecycle = if is_rename_marker(object) {
+            &NO_LIFECYCLE
+        } else {
+            &self.pack_lifecycle
+        };
+        self.upload(packfile, bucket, object, lifecycle).await
+    }
+
+    // Upload the file to the bucket, creating the bucket with the given
+    // lifecycle rule if it does not yet exist.
+    async fn upload(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+        lifecycle: &LifecycleRule,
+    ) -> Result<Coordinates, Error> {
+        let client = self.connect();
+        // a bucket must exist before receiving objects; note that the bucket
+        // may be renamed if there are too many buckets already
+        let bucket_name = self.try_create_bucket(&client, bucket, lifecycle).await?;
+        let meta = std::fs::metadata(packfile)?;
+        if let Some(checkpoints) = self.checkpoints.as_ref() {
+            if meta.len() > PART_SIZE {
+                let coords = Coordinates::new(&self.store_id, &bucket_name, object);
+                self.upload_multipart(&client, checkpoints.as_ref(), packfile, &coords)
+                    .await?;
+                return Ok(coords);
+            }
+        }
+        let body = match self.throttle.as_ref() {
+            Some(throttle) => {
+                let data = Bytes::from(tokio::fs::read(packfile).await?);
+                throttled_body(data, throttle.clone())
+            }
+            None => {
+                let read_stream = tokio::fs::read(packfile.to_owned())
+                    .into_stream()
+                    .map_ok(Bytes::from);
+                StreamingBody::new(read_stream)
+            }
+        };
+        let storage_class = if is_rename_marker(object) {
+            Some(STANDARD_CLASS.to_owned())
+        } else {
+            self.storage.clone()
+        };
+        let req = PutObjectRequest {
+            bucket: bucket_name.clone(),
+            storage_class,
+            key: object.to_owned(),
+            content_length: Some(meta.len() as i64),
+            body: Some(body),
+            server_side_encryption: self.sse.clone(),
+            ssekms_key_id: self.sse_kms_key_id.clone(),
+            ..Default::default()
+        };
+        // wait for the future(s) to complete
+        let result = within(
+            self.timeouts.write,
+            "store_pack",
+            client.put_object(req).map_err(Error::from),
+        )
+        .await?;
+        let etag_md5 = etag_is_md5(
+            result.server_side_encryption.as_deref(),
+            result.sse_customer_algorithm.as_deref(),
+        );
+        match result.e_tag {
+            Some(ref etag) if etag_md5 => {
+                // compute MD5 of file and compare to returned e_tag
+                let md5 = store_core::md5sum_file(packfile)?;
+                check_etag(etag, &md5, "pack file")?;
+            }
+            _ => (),
+        }
+        Ok(Coordinates::new(&self.store_id, &bucket_name, object))
+    }
+
+    // Upload the file in parts, saving a checkpoint after each part so that
+    // an interrupted upload can continue where it left off.
+    async fn upload_multipart(
+        &self,
+        client: &S3Client,
+        checkpoints: &dyn CheckpointStore,
+        packfile: &Path,
+        coords: &Coordinates,
+    ) -> Result<(), Error> {
+        let fingerprint = store_core::fingerprint_file(packfile)?;
+        let mut checkpoint = match checkpoints.get_checkpoint(coords)? {
+            Some(saved) if saved.fingerprint == fingerprint && !saved.is_stale() => saved,
+            saved => {
+                if let Some(stale) = saved {
+                    // the file has changed since, or the upload is too old to
+                    // resume, discard the earlier upload
+                    let _ = self.abort_multipart(client, coords, &stale).await;
+                }
+                let req = CreateMultipartUploadRequest {
+                    bucket: coords.bucket.clone(),
+                    key: coords.object.clone(),
+                    storage_class: self.storage.clone(),
+                    server_side_encryption: self.sse.clone(),
+                    ssekms_key_id: self.sse_kms_key_id.clone(),
+                    ..Default::default()
+                };
+                let result = client.create_multipart_upload(req).await?;
+                let upload_id = result
+                    .upload_id
+                    .ok_or_else(|| anyhow!("missing multipart upload ID"))?;
+                let checkpoint = Checkpoint::new(&fingerprint, &upload_id);
+                checkpoints.put_checkpoint(coords, &checkpoint)?;
+                checkpoint
+            }
+        };
+        let mut infile = File::open(packfile)?;
+        infile.seek(SeekFrom::Start(checkpoint.offset))?;
+        loop {
+            let mut data: Vec<u8> = Vec::with_capacity(PART_SIZE as usize);
+            let read_bytes = (&mut infile).take(PART_SIZE).read_to_end(&mut data)?;
+            if read_bytes == 0 {
+                break;
+            }
+            let md5 = store_core::md5sum_blob(&data)?;
+            let body = match self.throttle.as_ref() {
+                Some(throttle) => throttled_body(Bytes::from(data), throttle.clone()),
+                None => StreamingBody::from(data),
+            };
+            let req = UploadPartRequest {
+                bucket: coords.bucket.clone(),
+                key: coords.object.clone(),
+                upload_id: checkpoint.session.clone(),
+                part_number: checkpoint.parts.len() as i64 + 1,
+                content_length: Some(read_bytes as i64),
+                body: Some(body),
+                ..Default::default()
+            };
+            let request = client.upload_part(req).map(Ok);
+            let result = match within(self.timeouts.write, "store_pack", request).await? {
+                Ok(result) => result,
+                Err(RusotoError::Unknown(ref res)) if res.status.as_u16() == 404 => {
+                    // the upload was aborted or expired, start over next time
+                    checkpoints.delete_checkpoint(coords)?;
+                    return Err(anyhow!("multipart upload no longer exists"));
+                }
+                Err(err) => return Err(Error::from(err)),
+            };
+            let etag_md5 = etag_is_md5(
+                result.server_side_encryption.as_deref(),
+                result.sse_customer_algorithm.as_deref(),
+            );
+            let etag = result.e_tag.unwrap_or_default();
+            if etag_md5 {
+                check_etag(&etag, &md5, "part")?;
+            }
+            checkpoint.add_part(&etag, read_bytes as u64);
+            checkpoints.put_checkpoint(coords, &checkpoint)?;
+        }
+        let parts: Vec<CompletedPart> = checkpoint
+            .parts
+            .iter()
+            .enumerate()
+            .map(|(idx, etag)| CompletedPart {
+                e_tag: Some(etag.to_owned()),
+                part_number: Some(idx as i64 + 1),
+            })
+            .collect();
+        let req = CompleteMultipartUploadRequest {
+            bucket: coords.bucket.clone(),
+            key: coords.object.clone(),
+            upload_id: checkpoint.session.clone(),
+            multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+            ..Default::default()
+        };
+        client.complete_multipart_upload(req).await?;
+        checkpoints.delete_checkpoint(coords)
+    }
+
+    pub fn abort_upload_sync(
+        &self,
+        location: &Coordinates,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), Error> {
+        let client = self.connect();
+        block_on(within(
+            self.timeouts.operation,
+            "abort_upload",
+            self.abort_multipart(&client, location, checkpoint),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    // Abort the multipart upload recorded in the checkpoint.
+    async fn abort_multipart(
+        &self,
+        client: &S3Client,
+        coords: &Coordinates,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), Error> {
+        let req = AbortMultipartUploadRequest {
+            bucket: coords.bucket.clone(),
+            key: coords.object.clone(),
+            upload_id: checkpoint.session.clone(),
+            ..Default::default()
+        };
+        client.abort_multipart_upload(req).await?;
+        Ok(())
+    }
+
+    pub fn retrieve_pack_sync(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            self.retrieve_pack(location, outfile),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        let client = self.connect();
+        let request = GetObjectRequest {
+            bucket: location.bucket.clone(),
+            key: location.object.clone(),
+            ..Default::default()
+        };
+        // wait for the future(s) to complete
+        let result = within(
+            self.timeouts.read,
+            "retrieve_pack",
+            client.get_object(request).map_err(Error::from),
+        )
+        .await?;
+        let mut stream = result.body.ok_or_else(|| {
+            anyhow!(format!(
+                "failed to retrieve object {} from bucket {}",
+                location.object.clone(),
+                location.bucket.clone()
+            ))
+        })?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(outfile)
+            .await?;
+        if self.throttle.is_none() && self.timeouts.read.is_none() {
+            let mut body = stream.into_async_read();
+            tokio::io::copy(&mut body, &mut file).await?;
+        } else {
+            loop {
+                let next = stream.try_next().map_err(Error::from);
+                let chunk = match within(self.timeouts.read, "retrieve_pack", next).await? {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                if let Some(throttle) = self.throttle.as_ref() {
+                    tokio::time::sleep(throttle.reserve(chunk.len())).await;
+                }
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub fn list_buckets_sync(&self) -> Result<Vec<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "list_buckets",
+            self.list_buckets(),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        let client = self.connect();
+        // wait for the future(s) to complete
+        let result = client.list_buckets().await?;
+        let mut results = Vec::new();
+        if let Some(buckets) = result.buckets {
+            for bucket in buckets {
+                if let Some(name) = bucket.name {
+                    results.push(name);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    pub fn list_objects_sync(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "list_objects",
+            self.list_objects(bucket),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        let client = self.connect();
+        // default AWS S3 max-keys is 1,000
+        let mut request = ListObjectsV2Request {
+            bucket: bucket.to_owned(),
+            ..Default::default()
+        };
+        let mut results = Vec::new();
+        loop {
+            // we will be re-using the request, so clone it each time
+            // wait for the future(s) to complete
+            let result = client.list_objects_v2(request.clone()).await?;
+            if let Some(contents) = result.contents {
+                for entry in contents {
+                    if let Some(key) = entry.key {
+                        // hide the bucket rename marker from the server
+                        if !is_rename_marker(&key) {
+                            results.push(key);
+                        }
+                    }
+                }
+            }
+            // check if there are more results to be fetched
+            if result.next_continuation_token.is_none() {
+                break;
+            }
+            request.continuation_token = result.next_continuation_token;
+        }
+        Ok(results)
+    }
+
+    pub fn delete_object_sync(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "delete_object",
+            self.delete_object(bucket, object),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        let client = self.connect();
+        let request = DeleteObjectRequest {
+            bucket: bucket.to_owned(),
+            key: object.to_owned(),
+            ..Default::default()
+        };
+        // wait for the future(s) to complete
+        client.delete_object(request).await?;
+        Ok(())
+    }
+
+    pub fn set_storage_class_sync(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "set_storage_class",
+            self.set_storage_class(bucket, object, class),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Change the storage class of the object by copying it onto itself.
+    /// Returns `false` if the object already has the given storage class.
+    pub async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        let client = self.connect();
+        let request = HeadObjectRequest {
+            bucket: bucket.to_owned(),
+            key: object.to_owned(),
+            ..Default::default()
+        };
+        let result = client.head_object(request).await?;
+        // S3 omits the storage class for objects in the standard class
+        let current = result
+            .storage_class
+            .unwrap_or_else(|| "STANDARD".to_owned());
+        if current == class {
+            return Ok(false);
+        }
+        let request = CopyObjectRequest {
+            bucket: bucket.to_owned(),
+            key: object.to_owned(),
+            copy_source: format!("{}/{}", bucket, object),
+            storage_class: Some(class.to_owned()),
+            metadata_directive: Some("COPY".to_owned()),
+            // the copy is a new object that must be encrypted all over again
+            server_side_encryption: self.sse.clone(),
+            ssekms_key_id: self.sse_kms_key_id.clone(),
+            ..Default::default()
+        };
+        // wait for the future(s) to complete
+        client.copy_object(request).await?;
+        Ok(true)
+    }
+
+    pub fn object_md5_sync(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "object_md5",
+            self.object_md5(location),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Return the MD5 digest of the object from its entity tag, which is only
+    /// the case for objects uploaded in one part and either not encrypted by
+    /// the service or encrypted with keys managed by S3. The encryption is
+    /// taken from the object itself, as it may have been uploaded before the
+    /// `sse` property of the store was changed.
+    pub async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        let client = self.connect();
+        let request = HeadObjectRequest {
+            bucket: location.bucket.clone(),
+            key: location.object.clone(),
+            ..Default::default()
+        };
+        let result = client.head_object(request).await?;
+        if !etag_is_md5(
+            result.server_side_encryption.as_deref(),
+            result.sse_customer_algorithm.as_deref(),
+        ) {
+            return Ok(None);
+        }
+        // AWS S3 quotes the etag values for some reason
+        let etag = result.e_tag.map(|e| e.trim_matches('"').to_owned());
+        Ok(etag.filter(|e| !e.contains('-')))
+    }
+
+    pub fn object_size_sync(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "object_size",
+            self.object_size(location),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Return the size of the object as reported by its metadata.
+    pub async fn object_size(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        let client = self.connect();
+        let request = HeadObjectRequest {
+            bucket: location.bucket.clone(),
+            key: location.object.clone(),
+            ..Default::default()
+        };
+        let result = client.head_object(request).await?;
+        Ok(result.content_length.map(|l| l as u64))
+    }
+
+    pub fn set_lifecycle_sync(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "set_lifecycle",
+            self.set_lifecycle(bucket, rule),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    /// Replace the lifecycle configuration of the bucket with the given rule,
+    /// or remove the configuration if the rule is empty.
+    pub async fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        let client = self.connect();
+        if rule.is_empty() {
+            let request = DeleteBucketLifecycleRequest {
+                bucket: bucket.to_owned(),
+                expected_bucket_owner: None,
+            };
+            client.delete_bucket_lifecycle(request).await?;
+            return Ok(());
+        }
+        let transitions = rule.transition_days.map(|days| {
+            vec![Transition {
+                date: None,
+                days: Some(days as i64),
+                storage_class: rule.storage_class.clone(),
+            }]
+        });
+        let expiration = rule.expiration_days.map(|days| LifecycleExpiration {
+            days: Some(days as i64),
+            ..Default::default()
+        });
+        // a filter selects objects by a single prefix, so there is one rule
+        // for each of the prefixes of the pack objects
+        let rules: Vec<rusoto_s3::LifecycleRule> = PACK_PREFIXES
+            .iter()
+            .map(|prefix| rusoto_s3::LifecycleRule {
+                id: Some(format!(
+                    "{}-{}",
+                    LIFECYCLE_RULE_ID,
+                    prefix.trim_end_matches('-')
+                )),
+                status: "Enabled".to_owned(),
+                filter: Some(LifecycleRuleFilter {
+                    prefix: Some((*prefix).to_owned()),
+                    ..Default::default()
+                }),
+                transitions: transitions.clone(),
+                expiration: expiration.clone(),
+                ..Default::default()
+            })
+            .collect();
+        let request = PutBucketLifecycleConfigurationRequest {
+            bucket: bucket.to_owned(),
+            lifecycle_configuration: Some(BucketLifecycleConfiguration { rules }),
+            ..Default::default()
+        };
+        // wait for the future(s) to complete
+        client.put_bucket_lifecycle_configuration(request).await?;
+        Ok(())
+    }
+
+    pub fn delete_bucket_sync(&self, bucket: &str) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "delete_bucket",
+            self.delete_bucket(bucket),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        let client = self.connect();
+        let request = DeleteBucketRequest {
+            bucket: bucket.to_owned(),
+            expected_bucket_owner: None,
+        };
+        // wait for the future(s) to complete
+        let result = client.delete_bucket(request).await;
+        // certain error conditions are okay
+        match result {
+            Err(e) => match e {
+                RusotoError::Unknown(_) => Ok(()),
+                _ => Err(anyhow!(format!("{}", e))),
+            },
+            Ok(_) => Ok(()),
+        }
+    }
+
+    // The means of recording bucket renames, if any.
+    fn rename_tracker(&self) -> Option<Box<dyn RenameTracker + '_>> {
+        match self.renames {
+            Renames::DynamoDB => Some(Box::new(DynamoRenames { store: self })),
+            Renames::Object => Some(Box::new(ObjectRenames::new(self))),
+            Renames::None => None,
+        }
+    }
+
+    /// Record the new name of the bucket.
+    async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error> {
+        match self.rename_tracker() {
+            Some(tracker) => tracker.save_bucket_name(original, renamed).await,
+            None => Err(anyhow!("bucket renames are not recorded by this store")),
+        }
+    }
+
+    /// Retrieve the renamed value for the original bucket.
+    async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error> {
+        match self.rename_tracker() {
+            Some(tracker) => tracker.get_bucket_name(original).await,
+            None => Ok(None),
+        }
+    }
+
+    // for testing purposes only
+    #[allow(dead_code)]
+    fn delete_bucket_name(&self, original: &str) -> Result<(), Error> {
+        block_on(async {
+            let client = self.connect_dynamo();
+            let mut delete_input = DeleteItemInput::default();
+            delete_input.table_name = RENAMES_TABLE.into();
+            let mut key: HashMap<String, AttributeValue> = HashMap::new();
+            let mut value = AttributeValue::default();
+            value.s = Some(original.into());
+            key.insert("original".into(), value);
+            delete_input.key = key;
+            client.delete_item(delete_input).await?;
+            Ok(())
+        })
+        .and_then(std::convert::identity)
+    }
+
+    pub fn store_database_sync(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "store_database",
+            self.store_database(packfile, bucket, object),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        if let Some(renamed) = self.get_bucket_name(bucket).await? {
+            // If the renamed bucket fails for some reason, then report it
+            // immediately, do not attempt to generate a new name again.
+            self.upload(packfile, &renamed, object, &LifecycleRule::default())
+                .await
+        } else {
+            // Store the database in the same manner as any pack file, using the
+            // given bucket and object names. If there is a collision with an
+            // existing bucket that belongs to a different project, generate a
+            // new random bucket name and try that instead. Loop until it works
+            // or fails in some other manner.
+            let mut bucket_name = bucket.to_owned();
+            loop {
+                let lifecycle = &LifecycleRule::default();
+                match self.upload(packfile, &bucket_name, object, lifecycle).await {
+                    Ok(coords) => return Ok(coords),
+                    Err(err) => {
+                        match err.downcast::<CollisionError>() {
+                            Ok(err) if self.renames == Renames::None => {
+                                // without a place to record the new name, the
+                                // database would be lost after a rename
+                                return Err(Error::from(err));
+                            }
+                            Ok(_) => {
+                                // There was a collision, simply generate a new
+                                // name and hope that it will work. Type 4 UUID
+                                // works well since it conforms to Amazon's
+                                // bucket naming conventions.
+                                bucket_name = uuid::Uuid::new_v4().to_string();
+                                self.save_bucket_name(bucket, &bucket_name).await?;
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn retrieve_database_sync(
+        &self,
+        location: &Coordinates,
+        outfile: &Path,
+    ) -> Result<(), Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "retrieve_database",
+            self.retrieve_database(location, outfile),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn retrieve_database(
+        &self,
+        location: &Coordinates,
+        outfile: &Path,
+    ) -> Result<(), Error> {
+        if let Some(renamed) = self.get_bucket_name(&location.bucket).await? {
+            let mut adjusted = location.clone();
+            adjusted.bucket = renamed;
+            self.retrieve_pack(&adjusted, outfile).await
+        } else {
+            self.retrieve_pack(location, outfile).await
+        }
+    }
+
+    pub fn list_databases_sync(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        block_on(within(
+            self.timeouts.operation,
+            "list_databases",
+            self.list_databases(bucket),
+        ))
+        .and_then(std::convert::identity)
+    }
+
+    pub async fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        if let Some(renamed) = self.get_bucket_name(bucket).await? {
+            self.list_objects(&renamed).await
+        } else {
+            self.list_objects(bucket).await
+        }
+    }
+}
+
+impl PackDataSource for S3Store {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_pack_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_pack_sync(location, outfile)
+    }
+
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        self.list_buckets_sync()
+    }
+
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_objects_sync(bucket)
+    }
+
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        self.delete_object_sync(bucket, object)
+    }
+
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        self.delete_bucket_sync(bucket)
+    }
+
+    fn set_storage_class(&self, bucket: &str, object: &str, class: &str) -> Result<bool, Error> {
+        self.set_storage_class_sync(bucket, object, class)
+    }
+
+    fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        self.set_lifecycle_sync(bucket, rule)
+    }
+
+    fn abort_upload(&self, location: &Coordinates, checkpoint: &Checkpoint) -> Result<(), Error> {
+        self.abort_upload_sync(location, checkpoint)
+    }
+
+    fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        self.object_md5_sync(location)
+    }
+
+    fn object_size(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        self.object_size_sync(location)
+    }
+
+    fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        self.store_database_sync(packfile, bucket, object)
+    }
+
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        self.retrieve_database_sync(location, outfile)
+    }
+
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        self.list_databases_sync(bucket)
+    }
+}
+
+#[async_trait]
+impl AsyncPackDataSource for S3Store {
+    async fn store_pack(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        within(
+            self.timeouts.operation,
+            "store_pack",
+            S3Store::store_pack(self, packfile, bucket, object),
+        )
+        .await
+    }
+
+    async fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "retrieve_pack",
+            S3Store::retrieve_pack(self, location, outfile),
+        )
+        .await
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_buckets",
+            S3Store::list_buckets(self),
+        )
+        .await
+    }
+
+    async fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_objects",
+            S3Store::list_objects(self, bucket),
+        )
+        .await
+    }
+
+    async fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_object",
+            S3Store::delete_object(self, bucket, object),
+        )
+        .await
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "delete_bucket",
+            S3Store::delete_bucket(self, bucket),
+        )
+        .await
+    }
+
+    async fn set_storage_class(
+        &self,
+        bucket: &str,
+        object: &str,
+        class: &str,
+    ) -> Result<bool, Error> {
+        within(
+            self.timeouts.operation,
+            "set_storage_class",
+            S3Store::set_storage_class(self, bucket, object, class),
+        )
+        .await
+    }
+
+    async fn set_lifecycle(&self, bucket: &str, rule: &LifecycleRule) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "set_lifecycle",
+            S3Store::set_lifecycle(self, bucket, rule),
+        )
+        .await
+    }
+
+    async fn object_md5(&self, location: &Coordinates) -> Result<Option<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "object_md5",
+            S3Store::object_md5(self, location),
+        )
+        .await
+    }
+
+    async fn object_size(&self, location: &Coordinates) -> Result<Option<u64>, Error> {
+        within(
+            self.timeouts.operation,
+            "object_size",
+            S3Store::object_size(self, location),
+        )
+        .await
+    }
+
+    async fn store_database(
+        &self,
+        packfile: &Path,
+        bucket: &str,
+        object: &str,
+    ) -> Result<Coordinates, Error> {
+        within(
+            self.timeouts.operation,
+            "store_database",
+            S3Store::store_database(self, packfile, bucket, object),
+        )
+        .await
+    }
+
+    async fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+        within(
+            self.timeouts.operation,
+            "retrieve_database",
+            S3Store::retrieve_database(self, location, outfile),
+        )
+        .await
+    }
+
+    async fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+        within(
+            self.timeouts.operation,
+            "list_databases",
+            S3Store::list_databases(self, bucket),
+        )
+        .await
+    }
+}
+
+///
+/// Records the bucket renames in a DynamoDB table, which is only available when
+/// the store is connected to Amazon.
+///
+struct DynamoRenames<'a> {
+    store: &'a S3Store,
+}
+
+#[async_trait]
+impl RenameTracker for DynamoRenames<'_> {
+    async fn save_bucket_name(&self, original: &str, renamed: &str) -> Result<(), Error> {
+        let client = self.store.connect_dynamo();
+        // ensure the renames table exists
+        let mut create_input = CreateTableInput::default();
+        create_input.table_name = RENAMES_TABLE.into();
+        create_input.attribute_definitions = vec![AttributeDefinition {
+            attribute_name: "original".into(),
+            attribute_type: "S".into(),
+        }];
+        create_input.key_schema = vec![KeySchemaElement {
+            attribute_name: "original".into(),
+            key_type: "HASH".into(),
+        }];
+        create_input.provisioned_throughput = Some(ProvisionedThroughput {
+            read_capacity_units: 5,
+            write_capacity_units: 5,
+        });
+        let result = client.create_table(create_input).await;
+        let created_result: Result<bool, Error> = if let Err(err) = result {
+            match err {
+                RusotoError::Service(CreateTableError::ResourceInUse(_)) => Ok(false),
+                _ => Err(err.into()),
+            }
+        } else {
+            Ok(true)
+        };
+
+        // Wait for the new table to become ACTIVE, allowing for errors as the
+        // describe table request may fail initially while the table is not yet
+        // ready to be queried.
+        if created_result? {
+            let mut retries = 10;
+            let delay = std::time::Duration::from_millis(1000);
+            loop {
+                let mut describe_input = DescribeTableInput::default();
+                describe_input.table_name = RENAMES_TABLE.into();
+                match client.describe_table(describe_input).await {
+                    Ok(output) => {
+                        if let Some(table) = output.table {
+                            if let Some(status) = table.table_status {
+                                if status == "ACTIVE" {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        retries -= 1;
+                        if retries == 0 {
+                            return Err(err.into());
+                        }
+                        std::thread::sleep(delay);
+                    }
+                }
+            }
+        }
+
+        // insert table entry that maps `original` to `renamed`
+        let mut put_input = PutItemInput::default();
+        put_input.table_name = RENAMES_TABLE.into();
+        let mut item: HashMap<String, AttributeValue> = HashMap::new();
+        let mut value = AttributeValue::default();
+        value.s = Some(original.into());
+        item.insert("original".into(), value);
+        let mut value = AttributeValue::default();
+        value.s = Some(renamed.into());
+        item.insert("renamed".into(), value);
+        put_input.item = item;
+        client.put_item(put_input).await?;
+        Ok(())
+    }
+
+    async fn get_bucket_name(&self, original: &str) -> Result<Option<String>, Error> {
+        let client = self.store.connect_dynamo();
+        let mut get_input = GetItemInput::default();
+        get_input.table_name = RENAMES_TABLE.into();
+        let mut key: HashMap<String, AttributeValue> = HashMap::new();
+        let mut value = AttributeValue::default();
+        value.s = Some(original.into());
+        key.insert("original".into(), value);
+        get_input.key = key;
+        match client.get_item(get_input).await {
+            Ok(output) => {
+                if let Some(items) = output.item {
+                    if let Some(value) = items.get("renamed") {
+                        return Ok(value.s.to_owned());
+                    }
+                }
+                Ok(None)
+            }
+            Err(err) => match err {
+                RusotoError::Service(GetItemError::ResourceNotFound(_)) => Ok(None),
+                _ => Err(err.into()),
+            },
+        }
+    }
+}
+
+// Return `true` if the entity tag of an object with the given server-side
+// encryption is the MD5 digest of its content, which is not so for objects
+// encrypted with KMS or with keys provided by the client.
+fn etag_is_md5(sse: Option<&str>, sse_customer_algorithm: Option<&str>) -> bool {
+    sse_customer_algorithm.is_none() && matches!(sse, None | Some("AES256"))
+}
+
+/// Validate the server-side encryption settings, returning the algorithm to be
+/// requested of the service, if any. A KMS key implies the `aws:kms` algorithm.
+fn validate_sse(
+    sse: Option<&String>,
+    kms_key_id: Option<&String>,
+) -> Result<Option<String>, Error> {
+    let sse = sse.map(|v| v.trim()).filter(|v| !v.is_empty());
+    match (sse, kms_key_id) {
+        (None, None) => Ok(None),
+        (None, Some(_)) | (Some("aws:kms"), _) => Ok(Some("aws:kms".to_owned())),
+        (Some("AES256"), None) => Ok(Some("AES256".to_owned())),
+        (Some("AES256"), Some(_)) => Err(anyhow!("sse_kms_key_id requires sse of aws:kms")),
+        (Some(other), _) => Err(anyhow!(format!(
+            "sse must be one of AES256 or aws:kms: {}",
+            other
+        ))),
+    }
+}
+
+// Ensure the entity tag returned for the uploaded content matches its MD5.
+fn check_etag(etag: &str, md5: &str, what: &str) -> Result<(), Error> {
+    // AWS S3 quotes the etag values for some reason
+    if etag.trim_matches('"') != md5 {
+        return Err(anyhow!(format!(
+            "returned e_tag does not match MD5 of {}",
+            what
+        )));
+    }
+    Ok(())
+}
+
+/// Ensure the named bucket exists, optionally in the given region. Returns
+/// `true` if the bucket was created, `false` if it already existed.
+async fn create_bucket(
+    client: &S3Client,
+    bucket: &str,
+    region: Option<&str>,
+) -> Result<bool, Error> {
+    let config = region.map(|region| CreateBucketConfiguration {
+        location_constraint: Some(region.to_owned()),
+    });
+    let request = CreateBucketRequest {
+        bucket: bucket.to_owned(),
+        create_bucket_configuration: config,
+        ..Default::default()
+    };
+    // wait for the future(s) to complete
+    let result = client.create_bucket(request).await;
+    // certain error conditions are okay while others need to be detected and
+    // converted to discrete error types
+    match result {
+        Err(e) => match e {
+            RusotoError::Service(ref se) => match se {
+                CreateBucketError::BucketAlreadyExists(_) => Err(Error::from(CollisionError {})),
+                CreateBucketError::BucketAlreadyOwnedByYou(_) => Ok(false),
+            },
+            RusotoError::Unknown(ref bhr) => {
+                // rusoto_s3 does not recognize many errors very well
+                if bhr.status.as_u16() == 400 && bhr.body_as_str().contains("TooManyBuckets") {
+                    Err(Error::from(TooManyBucketsError {}))
+                } else {
+                    Err(e.into())
+                }
+            }
+            _ => Err(e.into()),
+        },
+        Ok(_) => Ok(true),
+    }
+}
+
+/// Divide the data into pieces that are released no faster than the throttle
+/// allows.
+fn throttled_body(data: Bytes, throttle: Arc<Throttle>) -> StreamingBody {
+    let chunks: Vec<Bytes> = (0..data.len())
+        .step_by(THROTTLE_CHUNK)
+        .map(|start| data.slice(start..(start + THROTTLE_CHUNK).min(data.len())))
+        .collect();
+    let stream = futures::stream::iter(chunks).then(move |chunk| {
+        let delay = throttle.reserve(chunk.len());
+        async move {
+            tokio::time::sleep(delay).await;
+            Ok(chunk)
+        }
+    });
+    StreamingBody::new(stream)
+}
+
+// Wait for the future to complete, raising a `TimeoutError` for the named
+// operation if it takes longer than the limit, if any.
+async fn within<T, F>(limit: Option<Duration>, operation: &str, future: F) -> Result<T, Error>
+where
+    F: std::future::Future<Output = Result<T, Error>>,
+{
+    match limit {
+        Some(limit) => match tokio::time::timeout(limit, future).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::from(TimeoutError::new(operation, Some(limit)))),
+        },
+        None => future.await,
+    }
+}
+
+/// Run the given future on a newly created single-threaded runtime if possible,
+/// otherwise raise an error if this thread already has a runtime.
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, Error> {
+    if let Ok(_handle) = tokio::runtime::Handle::try_current() {
+        Err(anyhow!("cannot call block_on inside a runtime"))
+    } else {
+        // Build the simplest and lightest runtime we can, while still enabling
+        // us to wait for this future (and everything it spawns) to complete
+        // synchronously. Must enable the io and time features otherwise the
+        // runtime does not really start.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(runtime.block_on(future))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_new_amazon_store_region() {
+        let props = HashMap::new();
+        let result = S3Store::new("amazon123", &props);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing region property"));
+        // could check all of the others, I guess?
+    }
+
+    #[test]
+    fn test_new_amazon_store_ok() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west2".to_owned());
+        properties.insert("storage".to_owned(), "STANDARD_IA".to_owned());
+        properties.insert("access_key".to_owned(), "amazon".to_owned());
+        properties.insert("secret_key".to_owned(), "shamazon".to_owned());
+        let result = S3Store::new("amazon123", &properties);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_new_s3_store_endpoint() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".to_owned());
+        properties.insert("access_key".to_owned(), "amazon".to_owned());
+        properties.insert("secret_key".to_owned(), "shamazon".to_owned());
+        // storage class is optional and renames are tracked for amazon
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert!(store.storage.is_none());
+        assert_eq!(store.renames, Renames::DynamoDB);
+        assert!(matches!(store.region(), Region::UsWest2));
+        let result = S3Store::new_compatible("amazon123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("missing endpoint property"));
+
+        // custom endpoints record renames within the store itself
+        properties.insert("endpoint".to_owned(), "https://s3.wasabisys.com".to_owned());
+        let store = S3Store::new_compatible("wasabi123", &properties).unwrap();
+        assert_eq!(store.renames, Renames::Object);
+        match store.region() {
+            Region::Custom { name, endpoint } => {
+                assert_eq!(name, "us-west-2");
+                assert_eq!(endpoint, "https://s3.wasabisys.com");
+            }
+            _ => panic!("expected a custom region"),
+        }
+        properties.insert("renames".to_owned(), "dynamodb".to_owned());
+        let store = S3Store::new("wasabi123", &properties).unwrap();
+        assert_eq!(store.renames, Renames::DynamoDB);
+        properties.insert("renames".to_owned(), "none".to_owned());
+        let store = S3Store::new("wasabi123", &properties).unwrap();
+        assert_eq!(store.renames, Renames::None);
+        properties.insert("renames".to_owned(), "etcd".to_owned());
+        let result = S3Store::new("wasabi123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("renames must be one of"));
+    }
+
+    #[test]
+    fn test_new_s3_store_sse() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".to_owned());
+        properties.insert("access_key".to_owned(), "amazon".to_owned());
+        properties.insert("secret_key".to_owned(), "shamazon".to_owned());
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert!(store.sse.is_none());
+        assert!(store.sse_kms_key_id.is_none());
+
+        properties.insert("sse".to_owned(), "AES256".to_owned());
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert_eq!(store.sse.unwrap(), "AES256");
+
+        // a key with the wrong algorithm is an error
+        let key_id = "arn:aws:kms:us-west-2:111122223333:key/1234abcd";
+        properties.insert("sse_kms_key_id".to_owned(), key_id.to_owned());
+        let result = S3Store::new("amazon123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("requires sse of aws:kms"));
+
+        // a key by itself implies aws:kms
+        properties.remove("sse");
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert_eq!(store.sse.unwrap(), "aws:kms");
+        assert_eq!(store.sse_kms_key_id.unwrap(), key_id);
+
+        properties.insert("sse".to_owned(), "rot13".to_owned());
+        let result = S3Store::new("amazon123", &properties);
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("sse must be one of"));
+    }
+
+    #[test]
+    fn test_etag_kms() {
+        assert!(etag_is_md5(None, None));
+        assert!(etag_is_md5(Some("AES256"), None));
+        // objects encrypted with KMS or client keys do not have an MD5 entity tag
+        assert!(!etag_is_md5(Some("aws:kms"), None));
+        assert!(!etag_is_md5(Some("aws:kms:dsse"), None));
+        assert!(!etag_is_md5(None, Some("AES256")));
+        let md5 = "d41d8cd98f00b204e9800998ecf8427e";
+        assert!(check_etag("\"d41d8cd98f00b204e9800998ecf8427e\"", md5, "part").is_ok());
+        let result = check_etag("\"a6f1c4f3e2b0\"", md5, "pack file");
+        assert!(result.is_err());
+        let err_string = result.unwrap_err().to_string();
+        assert!(err_string.contains("does not match MD5 of pack file"));
+    }
+
+    #[test]
+    fn test_new_s3_store_lifecycle() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".to_owned());
+        properties.insert("access_key".to_owned(), "amazon".to_owned());
+        properties.insert("secret_key".to_owned(), "shamazon".to_owned());
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert!(store.pack_lifecycle.is_empty());
+        properties.insert("lifecycle_days".to_owned(), "60".to_owned());
+        properties.insert("lifecycle_class".to_owned(), "DEEP_ARCHIVE".to_owned());
+        let store = S3Store::new("amazon123", &properties).unwrap();
+        assert_eq!(store.pack_lifecycle.transition_days, Some(60));
+        properties.remove("lifecycle_class");
+        assert!(S3Store::new("amazon123", &properties).is_err());
+    }
+
+    #[test]
+    fn test_within_timeout() {
+        let limit = Some(Duration::from_millis(10));
+        let result = block_on(within(limit, "list_buckets", async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }))
+        .unwrap();
+        let err = result.unwrap_err();
+        let timeout = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.operation, "list_buckets");
+        assert_eq!(timeout.limit, limit);
+        let result = block_on(within(limit, "list_buckets", async { Ok(42) })).unwrap();
+        assert_eq!(result.unwrap(), 42);
+        let result = block_on(within(None, "list_buckets", async { Ok(42) })).unwrap();
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_new_minio_store_ok() {
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west2".to_owned());
+        properties.insert("endpoint".to_owned(), "localhost:9000".to_owned());
+        properties.insert("access_key".to_owned(), "minio".to_owned());
+        properties.insert("secret_key".to_owned(), "shminio".to_owned());
+        let result = S3Store::new_compatible("minio123", &properties);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_amazon_wrong_account() -> Result<(), Error> {
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".into());
+        properties.insert("storage".to_owned(), "STANDARD_IA".into());
+        properties.insert("access_key".to_owned(), "not_access_key".into());
+        properties.insert("secret_key".to_owned(), "not_secret_key".into());
+        let source = S3Store::new("amazon2", &properties)?;
+        // act
+        let result = source.list_buckets_sync();
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("InvalidAccessKeyId"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_amazon_bucket_collision() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let region_var = env::var("AWS_REGION");
+        if region_var.is_err() {
+            // bail out silently if amazon is not configured
+            return Ok(());
+        }
+        let region = region_var?;
+        let access_key = env::var("AWS_ACCESS_KEY")?;
+        let secret_key = env::var("AWS_SECRET_KEY")?;
+
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), region);
+        properties.insert("storage".to_owned(), "STANDARD_IA".into());
+        properties.insert("access_key".to_owned(), access_key);
+        properties.insert("secret_key".to_owned(), secret_key);
+        let source = S3Store::new("amazonone", &properties)?;
+
+        // store an object in a bucket that already exists and belongs to
+        // another AWS account (surprise, mybucketname is already taken)
+        let bucket = "mybucketname".to_owned();
+        let object = "b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let result = source.store_pack_sync(packfile, &bucket, &object);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert!(err.downcast::<CollisionError>().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_amazon_store_roundtrip() -> Result<(), Error> {
+        //
+        // N.B. the rusoto crate will pick up environment variables, such as
+        // AWS_REGION, which can lead to false successes when running the tests
+        //
+        // set up the environment and remote connection
+        dotenv().ok();
+        let region_var = env::var("AWS_REGION");
+        if region_var.is_err() {
+            // bail out silently if amazon is not configured
+            return Ok(());
+        }
+        let region = region_var?;
+        let access_key = env::var("AWS_ACCESS_KEY")?;
+        let secret_key = env::var("AWS_SECRET_KEY")?;
+
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), region);
+        properties.insert("storage".to_owned(), "STANDARD_IA".into());
+        properties.insert("access_key".to_owned(), access_key);
+        properties.insert("secret_key".to_owned(), secret_key);
+        let source = S3Store::new("amazonone", &properties)?;
+
+        // store an object
+        let bucket = xid::new().to_string();
+        let object = "b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "amazonone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // store another object to ensure create bucket works repeatedly
+        let object = "489492a49220c814f49487efb12adfbc372aa3f8".to_owned();
+        let packfile = Path::new("../../test/fixtures/washington-journal.txt");
+        let location = source.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "amazonone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // check for bucket(s) being present
+        let buckets = source.list_buckets_sync()?;
+        assert!(!buckets.is_empty());
+        assert!(buckets.contains(&bucket));
+
+        // check for object(s) being present
+        let listing = source.list_objects_sync(&bucket)?;
+        assert!(!listing.is_empty());
+        assert!(listing.contains(&object));
+
+        // retrieve the file and verify by checksum
+        let outdir = tempdir()?;
+        let outfile = outdir.path().join("restored.txt");
+        let result = source.retrieve_pack_sync(&location, &outfile);
+        assert!(result.is_ok());
+        let md5sum = store_core::md5sum_file(&outfile)?;
+        assert_eq!(md5sum, "4b9772cf2c623ad529900f0ffe4e8ded");
+
+        // remove all objects from all buckets, and the buckets, too
+        for bucket in buckets {
+            let objects = source.list_objects_sync(&bucket)?;
+            for obj in objects {
+                source.delete_object_sync(&bucket, &obj)?;
+            }
+            source.delete_bucket_sync(&bucket)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_amazon_database_bucket_collision() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let region_var = env::var("AWS_REGION");
+        if region_var.is_err() {
+            // bail out silently if amazon is not configured
+            return Ok(());
+        }
+        let region = region_var?;
+        let access_key = env::var("AWS_ACCESS_KEY")?;
+        let secret_key = env::var("AWS_SECRET_KEY")?;
+
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), region);
+        properties.insert("storage".to_owned(), "STANDARD_IA".into());
+        properties.insert("access_key".to_owned(), access_key);
+        properties.insert("secret_key".to_owned(), secret_key);
+        let source = S3Store::new("amazon1", &properties)?;
+
+        // store an object in a bucket that already exists and belongs to
+        // another AWS account (surprise, mybucketname is already taken)
+        let bucket = "mybucketname".to_owned();
+        let object = "b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_database_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "amazon1");
+        assert_ne!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // retrieve the database file using the original coordinates
+        let location = Coordinates::new("amazon1", &bucket, &object);
+        let outdir = tempdir()?;
+        let outfile = outdir.path().join("restored.txt");
+        source.retrieve_database_sync(&location, &outfile)?;
+
+        // list available databases
+        let mut retries = 10;
+        let delay = std::time::Duration::from_millis(1000);
+        loop {
+            // DynamoDB is eventually consistent, so try a few times
+            let databases = source.list_databases_sync(&bucket)?;
+            if databases.is_empty() {
+                retries -= 1;
+                if retries == 0 {
+                    panic!("list_databases test failed after several tries");
+                }
+                std::thread::sleep(delay);
+            } else {
+                assert_eq!(databases.len(), 1);
+                assert_eq!(&databases[0], &object);
+                break;
+            }
+        }
+
+        // remove database mapping for test reproduction
+        source.delete_bucket_name(&bucket)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_minio_wrong_account() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let endp_var = env::var("MINIO_ENDPOINT");
+        if endp_var.is_err() {
+            // bail out silently if minio is not available
+            return Ok(());
+        }
+        let endpoint = endp_var?;
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), "us-west-2".into());
+        properties.insert("endpoint".to_owned(), endpoint);
+        properties.insert("access_key".to_owned(), "not_access_key".into());
+        properties.insert("secret_key".to_owned(), "not_secret_key".into());
+        let source = S3Store::new_compatible("minio2", &properties)?;
+        // act
+        let result = source.list_buckets_sync();
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("InvalidAccessKeyId"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_minio_collision_error() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let endp_var = env::var("MINIO_ENDPOINT");
+        if endp_var.is_err() {
+            // bail out silently if minio is not available
+            return Ok(());
+        }
+        let endpoint = endp_var?;
+        let region = env::var("MINIO_REGION")?;
+        let access_key_1 = env::var("MINIO_ACCESS_KEY_1")?;
+        let secret_key_1 = env::var("MINIO_SECRET_KEY_1")?;
+
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), region);
+        properties.insert("endpoint".to_owned(), endpoint);
+        properties.insert("access_key".to_owned(), access_key_1);
+        properties.insert("secret_key".to_owned(), secret_key_1);
+        let source1 = S3Store::new_compatible("minioone", &properties)?;
+
+        // store an object
+        let bucket = xid::new().to_string();
+        let object = "b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source1.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "minioone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // store another object to the same bucket but as a different user
+        //
+        // apparently this is not an issue out of the box, as minio allows
+        // different access keys to modify any buckets and objects; the
+        // bucket create operation returns "already owned by you" error
+        let access_key_2 = env::var("MINIO_ACCESS_KEY_2")?;
+        let secret_key_2 = env::var("MINIO_SECRET_KEY_2")?;
+        properties.insert("access_key".to_owned(), access_key_2);
+        properties.insert("secret_key".to_owned(), secret_key_2);
+        let source2 = S3Store::new_compatible("miniotwo", &properties)?;
+        let object = "489492a49220c814f49487efb12adfbc372aa3f8".to_owned();
+        let packfile = Path::new("../../test/fixtures/washington-journal.txt");
+        let location = source2.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "miniotwo");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // do _NOT_ remove all buckets and objects, other test needs them;
+        // tests are run concurrently, only one can clean up everything
+        Ok(())
+    }
+
+    #[test]
+    fn test_minio_store_roundtrip() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let endp_var = env::var("MINIO_ENDPOINT");
+        if endp_var.is_err() {
+            // bail out silently if minio is not available
+            return Ok(());
+        }
+        let endpoint = endp_var?;
+        let region = env::var("MINIO_REGION")?;
+        let access_key = env::var("MINIO_ACCESS_KEY_1")?;
+        let secret_key = env::var("MINIO_SECRET_KEY_1")?;
+
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("region".to_owned(), region);
+        properties.insert("endpoint".to_owned(), endpoint);
+        properties.insert("access_key".to_owned(), access_key);
+        properties.insert("secret_key".to_owned(), secret_key);
+        let source = S3Store::new_compatible("minioone", &properties)?;
+
+        // store an object
+        let bucket = xid::new().to_string();
+        let object = "b14c4909c3fce2483cd54b328ada88f5ef5e8f96".to_owned();
+        let packfile = Path::new("../../test/fixtures/lorem-ipsum.txt");
+        let location = source.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "minioone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // store another object to ensure create bucket works repeatedly
+        let object = "489492a49220c814f49487efb12adfbc372aa3f8".to_owned();
+        let packfile = Path::new("../../test/fixtures/washington-journal.txt");
+        let location = source.store_pack_sync(packfile, &bucket, &object)?;
+        assert_eq!(location.store, "minioone");
+        assert_eq!(location.bucket, bucket);
+        assert_eq!(location.object, object);
+
+        // check for bucket(s) being present
+        let buckets = source.list_buckets_sync()?;
+        assert!(!buckets.is_empty());
+        assert!(buckets.contains(&bucket));
+
+        // check for object(s) being present
+        let listing = source.list_objects_sync(&bucket)?;
+        assert!(!listing.is_empty());
+        assert!(listing.contains(&object));
+
+        // retrieve the file and verify by checksum
+        let outdir = tempdir()?;
+        let outfile = outdir.path().join("restored.txt");
+        let result = source.retrieve_pack_sync(&location, &outfile);
+        assert!(result.is_ok());
+        let md5sum = store_core::md5sum_file(&outfile)?;
+        assert_eq!(md5sum, "4b9772cf2c623ad529900f0ffe4e8ded");
+
+        // remove all objects from all buckets, and the buckets, too
+        for bucket in buckets {
+            let objects = source.list_objects_sync(&bucket)?;
+            for obj in objects {
+                source.delete_object_sync(&bucket, &obj)?;
+            }
+            source.delete_bucket_sync(&bucket)?;
+        }
+        Ok(())
+    }
+}
@@ -2,13 +2,46 @@
 // Copyright (c) 2020 Nathan Fiedler
 //
 use anyhow::{anyhow, Error};
-use ssh2::{FileStat, Session};
+use ssh2::{CheckResult, ErrorCode, FileStat, KnownHostFileKind, Session, Sftp};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io;
-use std::net::TcpStream;
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
 use std::path::{Path, PathBuf};
-use store_core::Coordinates;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use store_core::{
+    Coordinates, DeadlineReader, PackDataSource, Secret, Throttle, ThrottledReader, TimeoutError,
+    Timeouts,
+};
+
+// Number of idle connections to keep for later use.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+// Idle connections older than this are discarded, as the server has likely
+// closed them already (e.g. OpenSSH with ClientAliveInterval).
+const MAX_IDLE_TIME: Duration = Duration::from_secs(300);
+
+// Idle connections older than this are probed before being used again.
+const PROBE_IDLE_TIME: Duration = Duration::from_secs(15);
+
+// Error code of libssh2 when a blocking call exceeds the session timeout.
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+
+// An authenticated session and its SFTP channel.
+struct Connection {
+    sftp: Sftp,
+    // the channel does not work without the session
+    _sess: Session,
+    last_used: Instant,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Connection(idle {:?})", self.last_used.elapsed())
+    }
+}
 
 ///
 /// A `PackDataSource` implementation that operates over SSH2/SFTP to store pack
@@ -19,10 +52,16 @@ pub struct SftpStore {
     store_id: String,
     remote_addr: String,
     username: String,
-    password: Option<String>,
+    password: Option<Secret>,
     basepath: Option<String>,
-    // private_key: Option<String>,
-    // passphrase: Option<String>,
+    // either the path to a private key file, or the key itself in PEM format
+    private_key: Option<Secret>,
+    passphrase: Option<Secret>,
+    known_hosts: Option<PathBuf>,
+    // connections that may be reused by subsequent operations
+    pool: Mutex<Vec<Connection>>,
+    throttle: Option<Arc<Throttle>>,
+    timeouts: Timeouts,
 }
 
 impl SftpStore {
@@ -34,70 +73,315 @@ impl SftpStore {
         let username = props
             .get("username")
             .ok_or_else(|| anyhow!("missing username property"))?;
-        let password = props.get("password").map(|s| s.to_owned());
+        let password = props.get("password").map(|s| Secret::from(s.as_str()));
         let basepath = props.get("basepath").map(|s| s.to_owned());
+        let private_key = props
+            .get("private_key")
+            .filter(|s| !s.is_empty())
+            .map(|s| Secret::from(s.as_str()));
+        let passphrase = props
+            .get("passphrase")
+            .filter(|s| !s.is_empty())
+            .map(|s| Secret::from(s.as_str()));
+        let known_hosts = props
+            .get("known_hosts")
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
         Ok(Self {
             store_id: store_id.to_owned(),
             remote_addr: remote_addr.to_owned(),
             username: username.to_owned(),
             password,
             basepath,
+            private_key,
+            passphrase,
+            known_hosts,
+            pool: Mutex::new(Vec::new()),
+            throttle: Throttle::from_properties(store_id, props)?,
+            timeouts: Timeouts::from_properties(props)?,
+        })
+    }
+
+    /// Invoke the function with an SFTP channel, reusing an idle connection
+    /// if one is available. The connection is kept for reuse unless the
+    /// operation failed for reasons other than an SFTP error status.
+    ///
+    /// Errors that indicate a timeout are raised as a `TimeoutError` for the
+    /// named operation.
+    fn with_sftp<T, F>(&self, operation: &str, op: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Sftp) -> Result<T, Error>,
+    {
+        let conn = match self.checkout() {
+            Some(conn) => conn,
+            None => self
+                .open()
+                .map_err(|e| classify_timeout(e, operation, self.timeouts.connect))?,
+        };
+        let result = op(&conn.sftp);
+        match result {
+            Ok(_) => self.checkin(conn),
+            Err(ref err) if is_status_error(err) => self.checkin(conn),
+            Err(_) => (),
+        }
+        result.map_err(|e| classify_timeout(e, operation, self.session_timeout()))
+    }
+
+    // Limit on each blocking call of the session once connected, which is
+    // never longer than the operation as a whole is allowed to take.
+    fn session_timeout(&self) -> Option<Duration> {
+        match (self.timeouts.io(), self.timeouts.operation) {
+            (Some(io), Some(operation)) => Some(io.min(operation)),
+            (io, operation) => io.or(operation),
+        }
+    }
+
+    // Take a healthy connection from the pool, discarding any that have been
+    // idle for too long or fail to respond.
+    fn checkout(&self) -> Option<Connection> {
+        loop {
+            let conn = self.pool.lock().unwrap().pop()?;
+            let idle = conn.last_used.elapsed();
+            if idle > MAX_IDLE_TIME {
+                continue;
+            }
+            if idle > PROBE_IDLE_TIME && conn.sftp.stat(Path::new(".")).is_err() {
+                continue;
+            }
+            return Some(conn);
+        }
+    }
+
+    // Return the connection to the pool, unless the pool is already full.
+    fn checkin(&self, mut conn: Connection) {
+        let mut pool = self.pool.lock().unwrap();
+        if pool.len() < MAX_IDLE_CONNECTIONS {
+            conn.last_used = Instant::now();
+            pool.push(conn);
+        }
+    }
+
+    // Establish a new session and open the SFTP channel.
+    fn open(&self) -> Result<Connection, Error> {
+        let sess = self.connect()?;
+        let sftp = sess.sftp()?;
+        Ok(Connection {
+            sftp,
+            _sess: sess,
+            last_used: Instant::now(),
         })
     }
 
     /// Connect to the SFTP server using an SSH connection. The caller must
     /// instantiate the Sftp instance using the Session in connection.
     fn connect(&self) -> Result<Session, Error> {
-        // Simply build a new session and connection every time. Trying to reuse
-        // the session though a combination of Rc and RefCell does not improve
-        // the run time in the slightest.
-        let tcp = TcpStream::connect(&self.remote_addr)?;
+        let tcp = match self.timeouts.connect {
+            Some(limit) => connect_timeout(&self.remote_addr, limit)?,
+            None => TcpStream::connect(&self.remote_addr)?,
+        };
         let mut sess = Session::new()?;
         sess.set_tcp_stream(tcp);
+        // the handshake and authentication are part of connecting
+        sess.set_timeout(timeout_millis(self.timeouts.connect));
         sess.handshake()?;
-        sess.userauth_password(&self.username, self.password.as_ref().unwrap())?;
+        self.verify_host(&sess)?;
+        let passphrase = self.passphrase.as_ref().map(|p| p.expose());
+        if let Some(private_key) = self.private_key.as_ref() {
+            let key = private_key.expose();
+            if key.trim_start().starts_with("-----BEGIN") {
+                userauth_pubkey_pem(&sess, &self.username, key, passphrase)?;
+            } else {
+                sess.userauth_pubkey_file(&self.username, None, Path::new(key), passphrase)?;
+            }
+        } else if let Some(password) = self.password.as_ref() {
+            sess.userauth_password(&self.username, password.expose())?;
+        } else {
+            return Err(anyhow!("missing password or private_key property"));
+        }
+        sess.set_timeout(timeout_millis(self.session_timeout()));
         Ok(sess)
     }
 
-    pub fn store_pack(
+    /// Verify the host key of the server against the known hosts file, if
+    /// one was given in the store properties.
+    fn verify_host(&self, sess: &Session) -> Result<(), Error> {
+        if let Some(known_path) = self.known_hosts.as_ref() {
+            let mut known_hosts = sess.known_hosts()?;
+            known_hosts.read_file(known_path, KnownHostFileKind::OpenSSH)?;
+            let (key, _) = sess
+                .host_key()
+                .ok_or_else(|| anyhow!("server did not provide a host key"))?;
+            let (host, port) = split_address(&self.remote_addr)?;
+            match known_hosts.check_port(host, port, key) {
+                CheckResult::Match => (),
+                CheckResult::NotFound => {
+                    return Err(anyhow!(format!("host key for {} not found", host)))
+                }
+                CheckResult::Mismatch => {
+                    return Err(anyhow!(format!("host key for {} does not match", host)))
+                }
+                CheckResult::Failure => return Err(anyhow!("could not check host key")),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Authenticate using a private key given in PEM format.
+#[cfg(unix)]
+fn userauth_pubkey_pem(
+    sess: &Session,
+    username: &str,
+    key: &str,
+    passphrase: Option<&str>,
+) -> Result<(), Error> {
+    sess.userauth_pubkey_memory(username, None, key, passphrase)?;
+    Ok(())
+}
+
+// Authenticate using a private key given in PEM format.
+//
+// The in-memory variant is not available with the Windows crypto backend of
+// libssh2, so write the key to a temporary file that only we can read.
+#[cfg(not(unix))]
+fn userauth_pubkey_pem(
+    sess: &Session,
+    username: &str,
+    key: &str,
+    passphrase: Option<&str>,
+) -> Result<(), Error> {
+    use std::io::Write;
+    let mut keyfile = tempfile::NamedTempFile::new()?;
+    keyfile.write_all(key.as_bytes())?;
+    keyfile.flush()?;
+    sess.userauth_pubkey_file(username, None, keyfile.path(), passphrase)?;
+    Ok(())
+}
+
+// Connect to the remote address, trying each of the addresses to which the
+// host name resolves, giving up on each after the given time.
+fn connect_timeout(addr: &str, limit: Duration) -> Result<TcpStream, Error> {
+    let (host, port) = split_address(addr)?;
+    let mut last_err: Option<io::Error> = None;
+    for sockaddr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sockaddr, limit) {
+            Ok(tcp) => return Ok(tcp),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    match last_err {
+        Some(err) => Err(Error::from(err)),
+        None => Err(anyhow!(format!("could not resolve address {}", addr))),
+    }
+}
+
+// Convert the limit to milliseconds for libssh2, where zero means no limit.
+fn timeout_millis(limit: Option<Duration>) -> u32 {
+    limit.map_or(0, |l| l.as_millis().clamp(1, u32::MAX as u128) as u32)
+}
+
+// Replace the error with a `TimeoutError` if libssh2 or the socket gave up
+// waiting, otherwise return the error unchanged.
+fn classify_timeout(err: Error, operation: &str, limit: Option<Duration>) -> Error {
+    let timed_out = err
+        .downcast_ref::<ssh2::Error>()
+        .is_some_and(|e| e.code() == ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT));
+    if timed_out {
+        Error::from(TimeoutError::new(operation, limit))
+    } else {
+        store_core::classify_timeout(err, operation, limit)
+    }
+}
+
+// Return true if the error is an SFTP status (e.g. no such file), which means
+// the connection itself is still usable.
+fn is_status_error(err: &Error) -> bool {
+    match err.downcast_ref::<ssh2::Error>() {
+        Some(e) => matches!(e.code(), ErrorCode::SFTP(_)),
+        None => false,
+    }
+}
+
+// Split the remote address into the host and port, with the port defaulting
+// to 22 if not given. An IPv6 address must be enclosed in brackets when a port
+// is given, otherwise the address is taken as it is.
+fn split_address(addr: &str) -> Result<(&str, u16), Error> {
+    if addr.parse::<IpAddr>().is_ok() {
+        return Ok((addr, 22));
+    }
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| anyhow!(format!("invalid port in remote_addr: {}", addr)))
+    };
+    if let Some(rest) = addr.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, "")) => Ok((host, 22)),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => Ok((host, parse_port(port)?)),
+                None => Err(anyhow!(format!("invalid remote_addr: {}", addr))),
+            },
+            None => Err(anyhow!(format!("invalid remote_addr: {}", addr))),
+        };
+    }
+    match addr.split_once(':') {
+        Some((host, port)) => Ok((host, parse_port(port)?)),
+        None => Ok((addr, 22)),
+    }
+}
+
+impl PackDataSource for SftpStore {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn is_slow(&self) -> bool {
+        false
+    }
+
+    fn store_pack(
         &self,
         packfile: &Path,
         bucket: &str,
         object: &str,
     ) -> Result<Coordinates, Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
         let mut path: PathBuf = match &self.basepath {
             Some(bp) => [bp, bucket].iter().collect(),
             None => PathBuf::from(bucket),
         };
-        // mkdir will fail if directory already exists, let's just ignore all
-        // errors for mkdir and hope that it was not a real issue
-        let _ = sftp.mkdir(&path, 0o755);
-        path.push(object);
-        let mut remote = sftp.create(&path)?;
-        let mut local = File::open(packfile)?;
-        io::copy(&mut local, &mut remote)?;
+        let deadline = self.timeouts.deadline("store_pack");
+        self.with_sftp("store_pack", |sftp| {
+            // mkdir will fail if directory already exists, let's just ignore
+            // all errors for mkdir and hope that it was not a real issue
+            let _ = sftp.mkdir(&path, 0o755);
+            path.push(object);
+            let mut remote = sftp.create(&path)?;
+            let local = File::open(packfile)?;
+            let local = ThrottledReader::new(local, self.throttle.clone());
+            let mut local = DeadlineReader::new(local, deadline);
+            io::copy(&mut local, &mut remote)?;
+            Ok(())
+        })?;
         let loc = Coordinates::new(&self.store_id, bucket, object);
         Ok(loc)
     }
 
-    pub fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
+    fn retrieve_pack(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
         let object_path: PathBuf = match &self.basepath {
             Some(bp) => [bp, &location.bucket, &location.object].iter().collect(),
             None => [&location.bucket, &location.object].iter().collect(),
         };
-        let mut remote = sftp.open(&object_path)?;
-        let mut local = File::create(outfile)?;
-        io::copy(&mut remote, &mut local)?;
-        Ok(())
+        let deadline = self.timeouts.deadline("retrieve_pack");
+        self.with_sftp("retrieve_pack", |sftp| {
+            let remote = sftp.open(&object_path)?;
+            let remote = ThrottledReader::new(remote, self.throttle.clone());
+            let mut remote = DeadlineReader::new(remote, deadline);
+            let mut local = File::create(outfile)?;
+            io::copy(&mut remote, &mut local)?;
+            Ok(())
+        })
     }
 
-    pub fn list_buckets(&self) -> Result<Vec<String>, Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
+    fn list_buckets(&self) -> Result<Vec<String>, Error> {
         // Default the directory to something, it cannot be blank or ~ as that
         // results in a "no such file" error. Regardless, it is discarded when
         // we produce the results so it matters not.
@@ -105,7 +389,8 @@ impl SftpStore {
             Some(bp) => Path::new(bp),
             None => Path::new("."),
         };
-        let listing: Vec<(PathBuf, FileStat)> = sftp.readdir(dirname)?;
+        let listing: Vec<(PathBuf, FileStat)> =
+            self.with_sftp("list_buckets", |sftp| Ok(sftp.readdir(dirname)?))?;
         let mut results = Vec::new();
         for (path, stat) in listing {
             if stat.is_dir() {
@@ -117,14 +402,13 @@ impl SftpStore {
         Ok(results)
     }
 
-    pub fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
+    fn list_objects(&self, bucket: &str) -> Result<Vec<String>, Error> {
         let bucket_path: PathBuf = match &self.basepath {
             Some(bp) => [bp, bucket].iter().collect(),
             None => PathBuf::from(bucket),
         };
-        let listing: Vec<(PathBuf, FileStat)> = sftp.readdir(&bucket_path)?;
+        let listing: Vec<(PathBuf, FileStat)> =
+            self.with_sftp("list_objects", |sftp| Ok(sftp.readdir(&bucket_path)?))?;
         let mut results = Vec::new();
         for (path, stat) in listing {
             if stat.is_file() {
@@ -136,29 +420,23 @@ impl SftpStore {
         Ok(results)
     }
 
-    pub fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
+    fn delete_object(&self, bucket: &str, object: &str) -> Result<(), Error> {
         let object_path: PathBuf = match &self.basepath {
             Some(bp) => [bp, bucket, object].iter().collect(),
             None => [bucket, object].iter().collect(),
         };
-        sftp.unlink(&object_path)?;
-        Ok(())
+        self.with_sftp("delete_object", |sftp| Ok(sftp.unlink(&object_path)?))
     }
 
-    pub fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
-        let sess = self.connect()?;
-        let sftp = sess.sftp()?;
+    fn delete_bucket(&self, bucket: &str) -> Result<(), Error> {
         let bucket_path: PathBuf = match &self.basepath {
             Some(bp) => [bp, bucket].iter().collect(),
             None => PathBuf::from(bucket),
         };
-        sftp.rmdir(&bucket_path)?;
-        Ok(())
+        self.with_sftp("delete_bucket", |sftp| Ok(sftp.rmdir(&bucket_path)?))
     }
 
-    pub fn store_database(
+    fn store_database(
         &self,
         packfile: &Path,
         bucket: &str,
@@ -167,11 +445,11 @@ impl SftpStore {
         self.store_pack(packfile, bucket, object)
     }
 
-    pub fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
+    fn retrieve_database(&self, location: &Coordinates, outfile: &Path) -> Result<(), Error> {
         self.retrieve_pack(location, outfile)
     }
 
-    pub fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
+    fn list_databases(&self, bucket: &str) -> Result<Vec<String>, Error> {
         self.list_objects(bucket)
     }
 }
@@ -202,6 +480,71 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_split_address() {
+        assert_eq!(split_address("localhost:22").unwrap(), ("localhost", 22));
+        assert_eq!(split_address("10.0.0.5:2222").unwrap(), ("10.0.0.5", 2222));
+        assert_eq!(split_address("[::1]:2222").unwrap(), ("::1", 2222));
+        assert_eq!(split_address("[::1]").unwrap(), ("::1", 22));
+        assert_eq!(
+            split_address("2001:db8:1:2:3:4:5:6").unwrap(),
+            ("2001:db8:1:2:3:4:5:6", 22)
+        );
+        assert_eq!(split_address("fe80::1").unwrap(), ("fe80::1", 22));
+        assert!(split_address("[::1]2222").is_err());
+        assert!(split_address("[::1").is_err());
+        assert_eq!(split_address("nas.local").unwrap(), ("nas.local", 22));
+        assert!(split_address("nas.local:ssh").is_err());
+    }
+
+    #[test]
+    fn test_classify_timeout() {
+        let err = Error::from(ssh2::Error::new(ErrorCode::Session(-9), "timed out"));
+        let err = classify_timeout(err, "list_objects", Some(Duration::from_secs(30)));
+        let timeout = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.operation, "list_objects");
+        assert_eq!(timeout.limit, Some(Duration::from_secs(30)));
+        let err = Error::from(ssh2::Error::new(ErrorCode::SFTP(2), "no such file"));
+        let err = classify_timeout(err, "list_objects", None);
+        assert!(!err.is::<TimeoutError>());
+        assert_eq!(timeout_millis(None), 0);
+        assert_eq!(timeout_millis(Some(Duration::from_secs(30))), 30_000);
+    }
+
+    #[test]
+    fn test_is_status_error() {
+        let err = Error::from(ssh2::Error::new(ErrorCode::SFTP(2), "no such file"));
+        assert!(is_status_error(&err));
+        let err = Error::from(ssh2::Error::new(ErrorCode::Session(-43), "socket recv"));
+        assert!(!is_status_error(&err));
+        let err = anyhow!("oh no");
+        assert!(!is_status_error(&err));
+    }
+
+    #[test]
+    fn test_sftp_missing_credentials() -> Result<(), Error> {
+        // set up the environment and remote connection
+        dotenv().ok();
+        let addr_var = env::var("SFTP_ADDR");
+        if addr_var.is_err() {
+            // bail out silently if sftp is not configured
+            return Ok(());
+        }
+        let address = addr_var.unwrap();
+        // arrange
+        let mut properties: HashMap<String, String> = HashMap::new();
+        properties.insert("remote_addr".to_owned(), address);
+        properties.insert("username".to_owned(), "charlie".into());
+        let source = SftpStore::new("sftpone", &properties)?;
+        // act
+        let result = source.list_buckets();
+        // assert
+        assert!(result.is_err());
+        let err_string = result.err().unwrap().to_string();
+        assert!(err_string.contains("missing password or private_key"));
+        Ok(())
+    }
+
     #[test]
     fn test_sftp_wrong_account() -> Result<(), Error> {
         // set up the environment and remote connection
@@ -282,10 +625,10 @@ mod tests {
         let result = source.retrieve_pack(&location, &outfile);
         assert!(result.is_ok());
         let md5sum = store_core::md5sum_file(&outfile).unwrap();
-        #[cfg(target_family = "unix")]
         assert_eq!(md5sum, "40756e6058736e2485119410c2014380");
-        #[cfg(target_family = "windows")]
-        assert_eq!(md5sum, "8aed508af644bc58db20c9b73c5b67ad");
+
+        // all of the above should have used the same connection
+        assert_eq!(source.pool.lock().unwrap().len(), 1);
 
         // remove all objects from all buckets, and the buckets, too
         for bucket in buckets {
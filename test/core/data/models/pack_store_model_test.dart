@@ -2,6 +2,7 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 import 'dart:convert';
+import 'package:oxidized/oxidized.dart';
 import 'package:zorigami/core/data/models/pack_store_model.dart';
 import 'package:zorigami/core/domain/entities/pack_store.dart';
 import 'package:flutter_test/flutter_test.dart';
@@ -71,6 +72,21 @@ void main() {
         expect(result, expectedMap);
       },
     );
+
+    test(
+      'should include the entity tag when present',
+      () async {
+        // arrange
+        final model = PackStoreModel.fromStore(
+          tPackStoreModel.withEtag(const Some('0123456789abcdef')),
+        );
+        // act
+        final result = model.toJson();
+        // assert
+        expect(result['etag'], equals('0123456789abcdef'));
+        expect(PackStoreModel.fromJson(result).etag, equals(model.etag));
+      },
+    );
   });
 
   group('toJson and then fromJson', () {
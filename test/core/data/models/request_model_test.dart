@@ -16,6 +16,7 @@ void main() {
       finished: None(),
       filesRestored: 123,
       errorMessage: None(),
+      mergedInto: None(),
     );
     test(
       'should be a subclass of Request entity',
@@ -40,6 +41,7 @@ void main() {
           finished: Some(DateTime.now()),
           filesRestored: 1234567890,
           errorMessage: Some('oh noes'),
+          mergedInto: Some('dir'),
         );
         final encoded = actual.toJson();
         final decoded = RequestModel.fromJson(encoded);
@@ -49,6 +51,7 @@ void main() {
         expect(decoded.finished, equals(actual.finished));
         expect(decoded.filesRestored, equals(actual.filesRestored));
         expect(decoded.errorMessage, equals(actual.errorMessage));
+        expect(decoded.mergedInto, equals(actual.mergedInto));
       },
     );
   });
@@ -44,6 +44,7 @@ void main() {
     finished: None(),
     filesRestored: 13,
     errorMessage: None(),
+    mergedInto: None(),
   );
   final tRequestModelList = [tRequestModel];
   final List<Request> tRestores = [tRequestModel];
@@ -94,6 +94,7 @@ void main() {
         operation: {
           '__typename': 'Dataset',
           'id': 'setkey1',
+          'etag': 'setkey1-etag',
           'computerId': 'cray-11',
           'basepath': '/home/planet',
           'schedules': [
@@ -216,6 +217,7 @@ void main() {
               {
                 '__typename': 'Dataset',
                 'id': 'a1',
+                'etag': 'a1-etag',
                 'computerId': 's1',
                 'basepath': '/home/planet',
                 'schedules': [],
@@ -265,6 +267,7 @@ void main() {
               {
                 '__typename': 'Dataset',
                 'id': 'a1',
+                'etag': 'a1-etag',
                 'computerId': 's1',
                 'basepath': '/home/planet',
                 'schedules': [],
@@ -276,6 +279,7 @@ void main() {
               {
                 '__typename': 'Dataset',
                 'id': 'a2',
+                'etag': 'a2-etag',
                 'computerId': 's2',
                 'basepath': '/home/town',
                 'schedules': [],
@@ -287,6 +291,7 @@ void main() {
               {
                 '__typename': 'Dataset',
                 'id': 'a3',
+                'etag': 'a3-etag',
                 'computerId': 's3',
                 'basepath': '/home/sweet/home',
                 'schedules': [],
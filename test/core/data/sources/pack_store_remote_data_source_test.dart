@@ -60,7 +60,10 @@ void main() {
 
   void setUpMockTestGraphQLResponse() {
     final response = {
-      'data': {'__typename': 'Store', 'testStore': 'ok'}
+      'data': {
+        '__typename': 'Store',
+        'testStore': {'__typename': 'StoreTest', 'status': 'ok'}
+      }
     };
     // graphql client uses the 'send' method
     when(() => mockHttpClient.send(any())).thenAnswer((_) async {
@@ -80,6 +83,7 @@ void main() {
         operation: {
           '__typename': 'Store',
           'id': 'abc123',
+          'etag': 'abc123-etag',
           'label': 'lstore',
           'storeType': 'local',
           'properties': options,
@@ -153,6 +157,7 @@ void main() {
             'stores': [
               {
                 'id': 'a1',
+                'etag': 'a1-etag',
                 'label': 's1',
                 'storeType': 'minio',
                 'properties': []
@@ -190,18 +195,21 @@ void main() {
             'stores': [
               {
                 'id': 'a1',
+                'etag': 'a1-etag',
                 'label': 's1',
                 'storeType': 'minio',
                 'properties': []
               },
               {
                 'id': 'b2',
+                'etag': 'b2-etag',
                 'label': 's2',
                 'storeType': 'local',
                 'properties': []
               },
               {
                 'id': 'c3',
+                'etag': 'c3-etag',
                 'label': 's3',
                 'storeType': 'sftp',
                 'properties': []
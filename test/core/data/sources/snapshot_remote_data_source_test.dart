@@ -366,6 +366,7 @@ void main() {
           finished: None(),
           filesRestored: 123,
           errorMessage: None(),
+          mergedInto: None(),
         );
         expect(result, contains(store));
       },
@@ -427,6 +428,7 @@ void main() {
           finished: None(),
           filesRestored: 13,
           errorMessage: None(),
+          mergedInto: None(),
         );
         expect(result, contains(store));
       },
@@ -28,6 +28,7 @@ void main() {
     finished: None(),
     filesRestored: 123,
     errorMessage: None(),
+    mergedInto: None(),
   );
   final List<Request> tRequests = [tRequestModel];
 